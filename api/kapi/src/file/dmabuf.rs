@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! File descriptors of shared DMA buffers.
+
+use alloc::{borrow::Cow, sync::Arc};
+use core::task::Context;
+
+use dmabuf::{DmaBuf, DmaDirection};
+use kerrno::{KError, KResult};
+use khal::paging::PageSize;
+use kpoll::{IoEvents, Pollable};
+use memaddr::{PAGE_SIZE_4K, VirtAddr};
+use memspace::backend::{Backend, SharedPages};
+use osvm::VirtPtr;
+
+use crate::file::{FileLike, Kstat};
+
+/// `DMA_BUF_IOCTL_SYNC`, `_IOW('b', 0, struct dma_buf_sync)`.
+const DMA_BUF_IOCTL_SYNC: u32 = 0x4008_6200;
+
+const DMA_BUF_SYNC_READ: u64 = 1 << 0;
+const DMA_BUF_SYNC_WRITE: u64 = 1 << 1;
+const DMA_BUF_SYNC_RW: u64 = DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE;
+const DMA_BUF_SYNC_END: u64 = 1 << 2;
+
+/// A file descriptor of a [`DmaBuf`], which keeps the buffer alive.
+///
+/// Mapping it maps the pages of the buffer. User space brackets its
+/// accesses with `DMA_BUF_IOCTL_SYNC`, and polls it for `POLLIN` to wait for
+/// the fence of the buffer.
+pub struct DmaBufFile(Arc<DmaBuf>);
+
+impl DmaBufFile {
+    /// Creates a file descriptor of `buf`.
+    pub fn new(buf: Arc<DmaBuf>) -> Self {
+        Self(buf)
+    }
+
+    /// Returns the buffer.
+    pub fn buf(&self) -> &Arc<DmaBuf> {
+        &self.0
+    }
+
+    /// Returns the backend mapping `length` bytes at `offset` of the buffer
+    /// at `start`.
+    pub fn mmap_backend(&self, start: VirtAddr, offset: usize, length: usize) -> KResult<Backend> {
+        let end = offset.checked_add(length).ok_or(KError::InvalidInput)?;
+        if end > self.0.size() {
+            return Err(KError::InvalidInput);
+        }
+        let pages = self
+            .0
+            .pages()
+            .skip(offset / PAGE_SIZE_4K)
+            .take(length / PAGE_SIZE_4K)
+            .collect();
+        let pages = SharedPages::from_owned(pages, self.0.clone());
+        debug_assert_eq!(pages.size, PageSize::Size4K);
+        Ok(Backend::new_shared(start, Arc::new(pages)))
+    }
+}
+
+impl FileLike for DmaBufFile {
+    fn stat(&self) -> KResult<Kstat> {
+        Ok(Kstat {
+            size: self.0.size() as u64,
+            blocks: self.0.size() as u64 / 512,
+            ..Default::default()
+        })
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "anon_inode:dmabuf".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> KResult<usize> {
+        match cmd {
+            DMA_BUF_IOCTL_SYNC => {
+                let flags = (arg as *const u64).read_vm()?;
+                if flags & !(DMA_BUF_SYNC_RW | DMA_BUF_SYNC_END) != 0 {
+                    return Err(KError::InvalidInput);
+                }
+                let dir = match flags & DMA_BUF_SYNC_RW {
+                    DMA_BUF_SYNC_READ => DmaDirection::FromDevice,
+                    DMA_BUF_SYNC_WRITE => DmaDirection::ToDevice,
+                    DMA_BUF_SYNC_RW => DmaDirection::Bidirectional,
+                    _ => return Err(KError::InvalidInput),
+                };
+                if flags & DMA_BUF_SYNC_END != 0 {
+                    self.0.end_cpu_access(dir);
+                } else {
+                    self.0.begin_cpu_access(dir);
+                }
+                Ok(0)
+            }
+            _ => Err(KError::NotATty),
+        }
+    }
+}
+
+impl Pollable for DmaBufFile {
+    fn poll(&self) -> IoEvents {
+        self.0.fence().poll()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        self.0.fence().register(context, events);
+    }
+}
@@ -3,15 +3,27 @@
 // See LICENSES for license details.
 
 //! Epoll instance and interest management.
+//!
+//! An interest is *armed* by registering a waker with its file; the file
+//! wakes it when its readiness changes, which puts the interest on the ready
+//! queue of its epoll. Level-triggered interests stay on the queue while the
+//! file is ready, edge-triggered ones leave it once reported and only come
+//! back on the next wakeup, so a burst of writes is reported once.
+//!
+//! Every arming registers a fresh waker and makes those of earlier armings
+//! stale, as wakers left behind in the poll sets of the file would otherwise
+//! report edges that were already reported. An interest is always armed
+//! before its file is polled, so an edge between the two is never lost.
 use alloc::{
     borrow::Cow,
-    collections::vec_deque::VecDeque,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
     sync::{Arc, Weak},
     task::Wake,
+    vec::Vec,
 };
 use core::{
     hash::{Hash, Hasher},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     task::{Context, Waker},
 };
 
@@ -19,8 +31,9 @@ use bitflags::bitflags;
 use hashbrown::HashMap;
 use kerrno::{KError, KResult};
 use kpoll::{IoEvents, PollSet, Pollable};
+use kprocess::{ExitRegistration, Process, Reaped};
 use kspin::SpinNoPreempt;
-use linux_raw_sys::general::{EPOLLET, EPOLLONESHOT, epoll_event};
+use linux_raw_sys::general::{EPOLLET, EPOLLEXCLUSIVE, EPOLLONESHOT, epoll_event};
 
 use crate::file::{FileLike, get_file_like};
 
@@ -37,6 +50,7 @@ bitflags! {
     pub struct EpollFlags: u32 {
         const EDGE_TRIGGER = EPOLLET;
         const ONESHOT = EPOLLONESHOT;
+        const EXCLUSIVE = EPOLLEXCLUSIVE;
     }
 }
 
@@ -72,7 +86,7 @@ impl TriggerMode {
             // if we could wake, we need notify
             TriggerMode::Edge => (true, TriggerMode::Edge),
             TriggerMode::OneShot { fired } => {
-                // ONESHOT: 只触发一次
+                // ONESHOT: only once until re-armed by EPOLL_CTL_MOD
                 if *fired {
                     (false, *self)
                 } else {
@@ -91,11 +105,11 @@ impl TriggerMode {
 }
 
 enum ConsumeResult {
-    // success and should keep in ready list
+    // reported, and the caller should put it back on the ready list
     EventAndKeep(EpollEvent),
-    // success and hould remove ready list
+    // reported, and the caller should not put it back on the ready list
     EventAndRemove(EpollEvent),
-    // no event and should remove ready list
+    // nothing to report, off the ready list
     NoEvent,
 }
 
@@ -106,11 +120,14 @@ struct EntryKey {
 }
 impl EntryKey {
     fn new(fd: i32) -> KResult<Self> {
-        let file = get_file_like(fd)?;
-        Ok(Self {
+        Ok(Self::with_file(fd, &get_file_like(fd)?))
+    }
+
+    fn with_file(fd: i32, file: &Arc<dyn FileLike>) -> Self {
+        Self {
             fd,
-            file: Arc::downgrade(&file),
-        })
+            file: Arc::downgrade(file),
+        }
     }
 
     #[inline]
@@ -132,20 +149,115 @@ impl PartialEq for EntryKey {
 
 impl Eq for EntryKey {}
 
+/// The `EPOLLEXCLUSIVE` interests of all epoll instances in one file.
+///
+/// A wakeup of the file is claimed by the first of them to be woken; the
+/// others stay quiet until the claimant has been consumed, which arms them
+/// again.
+struct ExclusiveGroup {
+    file: Weak<dyn FileLike>,
+    members: SpinNoPreempt<Vec<Weak<EpollInterest>>>,
+    claimed: AtomicBool,
+}
+
+/// Exclusive groups by the address of their file.
+static EXCLUSIVE_GROUPS: SpinNoPreempt<BTreeMap<usize, Weak<ExclusiveGroup>>> =
+    SpinNoPreempt::new(BTreeMap::new());
+
+impl ExclusiveGroup {
+    /// Adds `interest` to the group of `file`, creating it if needed.
+    fn join(file: &Arc<dyn FileLike>, interest: Weak<EpollInterest>) -> Arc<Self> {
+        let key = Arc::as_ptr(file) as *const () as usize;
+        let mut groups = EXCLUSIVE_GROUPS.lock();
+        let group = match groups.get(&key).and_then(Weak::upgrade) {
+            Some(group) if Weak::ptr_eq(&group.file, &Arc::downgrade(file)) => group,
+            _ => {
+                let group = Arc::new(Self {
+                    file: Arc::downgrade(file),
+                    members: SpinNoPreempt::new(Vec::new()),
+                    claimed: AtomicBool::new(false),
+                });
+                groups.insert(key, Arc::downgrade(&group));
+                group
+            }
+        };
+        drop(groups);
+        group.members.lock().push(interest);
+        group
+    }
+
+    /// Claims the current wakeup for `interest`, returning `false` if another
+    /// member already has it.
+    fn claim(&self, interest: &EpollInterest) -> bool {
+        let claimed = self
+            .claimed
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if claimed {
+            interest.holds_claim.store(true, Ordering::Release);
+        }
+        claimed
+    }
+
+    /// Gives up the claim and arms the members that are not on a ready queue,
+    /// as their wakers went with the claimed wakeup.
+    fn release(&self, file: &dyn FileLike) {
+        self.claimed.store(false, Ordering::Release);
+        let members: Vec<_> = self
+            .members
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for member in members {
+            if !member.is_in_queue() && member.is_enabled() {
+                member.arm(file);
+            }
+        }
+    }
+}
+
+impl Drop for ExclusiveGroup {
+    fn drop(&mut self) {
+        let key = self.file.as_ptr() as *const () as usize;
+        let mut groups = EXCLUSIVE_GROUPS.lock();
+        if groups.get(&key).is_some_and(|it| it.strong_count() == 0) {
+            groups.remove(&key);
+        }
+    }
+}
+
 struct EpollInterest {
     key: EntryKey,
     event: EpollEvent,
+    epoll: Weak<EpollInner>,
     mode: SpinNoPreempt<TriggerMode>,
+    /// Bumped by every arming and disarming; wakers of an older generation
+    /// are stale.
+    generation: AtomicU64,
     in_ready_queue: AtomicBool,
+    exclusive: Option<Arc<ExclusiveGroup>>,
+    /// Whether this interest claimed the last wakeup of its exclusive group.
+    holds_claim: AtomicBool,
 }
 
 impl EpollInterest {
-    fn new(key: EntryKey, event: EpollEvent, flags: EpollFlags) -> Self {
+    fn new(
+        key: EntryKey,
+        event: EpollEvent,
+        flags: EpollFlags,
+        epoll: Weak<EpollInner>,
+        exclusive: Option<Arc<ExclusiveGroup>>,
+    ) -> Self {
         Self {
             key,
             event,
+            epoll,
             mode: SpinNoPreempt::new(TriggerMode::from_flags(flags)),
+            generation: AtomicU64::new(0),
             in_ready_queue: AtomicBool::new(false),
+            exclusive,
+            holds_claim: AtomicBool::new(false),
         }
     }
 
@@ -171,44 +283,122 @@ impl EpollInterest {
         self.in_ready_queue.store(false, Ordering::Release);
     }
 
-    fn consume(&self, file: &dyn FileLike) -> ConsumeResult {
-        let current_events = file.poll();
-        let matched = current_events & self.event.events;
+    /// Registers a fresh waker with `file`, making earlier ones stale.
+    fn arm(self: &Arc<Self>, file: &dyn FileLike) {
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let waker = Waker::from(Arc::new(InterestWaker {
+            interest: Arc::downgrade(self),
+            generation,
+        }));
+        file.register(&mut Context::from_waker(&waker), self.event.events);
+    }
+
+    /// Makes all registered wakers stale.
+    fn disarm(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
 
-        // not ready
-        if matched.is_empty() {
+    /// Arms the interest and queues it if `file` is already ready.
+    fn start(self: &Arc<Self>, file: &dyn FileLike) {
+        self.arm(file);
+        if self.ready_events(file).is_some() {
+            self.enqueue();
+        }
+    }
+
+    /// Puts the interest on the ready queue of its epoll, unless it is there.
+    fn enqueue(self: &Arc<Self>) {
+        let Some(epoll) = self.epoll.upgrade() else {
+            return;
+        };
+        if self.try_mark_in_queue() {
+            epoll.ready_queue.lock().push_back(Arc::downgrade(self));
+            trace!(
+                "Epoll: fd={} added to ready queue, events={:?} wake up poller",
+                self.key.fd, self.event.events
+            );
+            epoll.poll_ready.wake();
+        }
+    }
+
+    fn ready_events(&self, file: &dyn FileLike) -> Option<EpollEvent> {
+        let matched = file.poll() & self.event.events;
+        (!matched.is_empty()).then(|| EpollEvent {
+            events: matched,
+            user_data: self.event.user_data,
+        })
+    }
+
+    /// Takes the interest off the ready queue, reporting its events if any.
+    fn consume(self: &Arc<Self>, file: &dyn FileLike) -> ConsumeResult {
+        if self.holds_claim.swap(false, Ordering::AcqRel)
+            && let Some(group) = &self.exclusive
+        {
+            group.release(file);
+        }
+
+        let mode = *self.mode.lock();
+        if !mode.is_enabled() {
+            self.mark_not_in_queue();
             return ConsumeResult::NoEvent;
         }
+        if let TriggerMode::Level = mode
+            && let Some(event) = self.ready_events(file)
+        {
+            return ConsumeResult::EventAndKeep(event);
+        }
+
+        // Off the queue, and armed before polling so that an edge in between
+        // queues it again.
+        self.mark_not_in_queue();
+        self.arm(file);
+        let Some(event) = self.ready_events(file) else {
+            return ConsumeResult::NoEvent;
+        };
 
         let mut mode = self.mode.lock();
         let (should_notify, new_mode) = mode.should_notify();
         *mode = new_mode;
+        drop(mode);
         trace!(
             "consume fd: {} matches {:?} should notify: {} ",
-            self.key.fd, matched, should_notify
+            self.key.fd, event.events, should_notify
         );
-
         if !should_notify {
             return ConsumeResult::NoEvent;
         }
 
-        // create event
-        let event = EpollEvent {
-            events: matched,
-            user_data: self.event.user_data,
-        };
+        match new_mode {
+            // The waker may have queued it already.
+            TriggerMode::Level if self.try_mark_in_queue() => ConsumeResult::EventAndKeep(event),
+            TriggerMode::Level | TriggerMode::Edge => ConsumeResult::EventAndRemove(event),
+            TriggerMode::OneShot { .. } => {
+                self.disarm();
+                ConsumeResult::EventAndRemove(event)
+            }
+        }
+    }
+}
 
-        // shoud still keep in ready?
-        match *mode {
-            TriggerMode::Level => ConsumeResult::EventAndKeep(event),
-            TriggerMode::Edge | TriggerMode::OneShot { .. } => ConsumeResult::EventAndRemove(event),
+impl Drop for EpollInterest {
+    fn drop(&mut self) {
+        let Some(group) = &self.exclusive else {
+            return;
+        };
+        let this = self as *const Self;
+        group.members.lock().retain(|it| it.as_ptr() != this);
+        // Pass on a claimed wakeup that is not going to be consumed.
+        if *self.holds_claim.get_mut()
+            && let Some(file) = group.file.upgrade()
+        {
+            group.release(file.as_ref());
         }
     }
 }
 
 struct InterestWaker {
-    epoll: Weak<EpollInner>,
     interest: Weak<EpollInterest>,
+    generation: u64,
 }
 
 impl Wake for InterestWaker {
@@ -217,25 +407,21 @@ impl Wake for InterestWaker {
     }
 
     fn wake_by_ref(self: &Arc<Self>) {
-        let Some(epoll) = self.epoll.upgrade() else {
-            return;
-        };
-
         let Some(interest) = self.interest.upgrade() else {
             return;
         };
-
-        if interest.try_mark_in_queue() {
-            epoll
-                .ready_queue
-                .lock()
-                .push_back(Arc::downgrade(&interest));
-            trace!(
-                "Epoll: fd={} added to ready queue, events={:?} wake up poller",
-                interest.key.fd, interest.event.events
-            );
-            epoll.poll_ready.wake();
+        if interest.generation.load(Ordering::Acquire) != self.generation {
+            return;
+        }
+        if interest.epoll.strong_count() == 0 {
+            return;
+        }
+        if let Some(group) = &interest.exclusive
+            && !group.claim(&interest)
+        {
+            return;
         }
+        interest.enqueue();
     }
 }
 
@@ -245,6 +431,26 @@ struct EpollInner {
     poll_ready: PollSet,
 }
 
+impl EpollInner {
+    /// Wakes the waiters, whose tasks went with the process that created the
+    /// instance, and drops the interests in files closed since.
+    fn reap(&self) {
+        self.poll_ready.wake();
+        let mut stale = Vec::new();
+        self.interests.lock().retain(|key, interest| {
+            let open = key.file.strong_count() > 0;
+            if !open {
+                stale.push(interest.clone());
+            }
+            open
+        });
+        // Dropped out of the lock.
+        for interest in stale {
+            interest.disarm();
+        }
+    }
+}
+
 impl Default for EpollInner {
     fn default() -> Self {
         Self {
@@ -258,6 +464,8 @@ impl Default for EpollInner {
 #[derive(Default)]
 pub struct Epoll {
     inner: Arc<EpollInner>,
+    /// Reaps the instance when the process that created it exits.
+    _exit_hook: Option<ExitRegistration>,
 }
 
 impl Epoll {
@@ -266,123 +474,131 @@ impl Epoll {
         Self::default()
     }
 
-    // only register waker, not add to ready queue
-    fn register_waker_only(&self, interest: &Arc<EpollInterest>) {
-        let Some(file) = interest.key.get_file() else {
-            return;
-        };
-
-        if !interest.is_enabled() {
-            return;
+    /// Creates a new epoll instance reaped when `process` exits. It lives on
+    /// in the other processes it was shared with.
+    pub fn new_owned(process: &Process) -> Self {
+        let inner = Arc::new(EpollInner::default());
+        let exit_hook = process
+            .exit_hooks()
+            .register_object("epoll", &inner, |inner| {
+                inner.reap();
+                Reaped::Orphaned
+            });
+        Self {
+            inner,
+            _exit_hook: Some(exit_hook),
         }
-
-        let waker = Waker::from(Arc::new(InterestWaker {
-            epoll: Arc::downgrade(&self.inner),
-            interest: Arc::downgrade(interest),
-        }));
-
-        let mut context = Context::from_waker(&waker);
-        file.register(&mut context, interest.event.events);
     }
 
-    // for add/modify
-    fn check_and_register_waker(&self, interest: &Arc<EpollInterest>) {
-        let Some(file) = interest.key.get_file() else {
-            return;
-        };
-
-        if !interest.is_enabled() {
-            return;
-        }
-
-        let waker = Waker::from(Arc::new(InterestWaker {
-            epoll: Arc::downgrade(&self.inner),
-            interest: Arc::downgrade(interest),
-        }));
-
-        let current = file.poll() & interest.event.events;
-
-        if !current.is_empty() {
-            waker.wake_by_ref();
-        } else {
-            let mut context = Context::from_waker(&waker);
-            file.register(&mut context, interest.event.events);
-
-            let current = file.poll() & interest.event.events;
-            if !current.is_empty() {
-                waker.wake_by_ref();
-            }
-        }
+    fn new_interest(
+        &self,
+        key: EntryKey,
+        file: &Arc<dyn FileLike>,
+        event: EpollEvent,
+        flags: EpollFlags,
+    ) -> Arc<EpollInterest> {
+        Arc::new_cyclic(|weak| {
+            let exclusive = flags
+                .contains(EpollFlags::EXCLUSIVE)
+                .then(|| ExclusiveGroup::join(file, weak.clone()));
+            EpollInterest::new(key, event, flags, Arc::downgrade(&self.inner), exclusive)
+        })
     }
 
     /// Adds a file descriptor interest to the epoll instance.
     pub fn add(&self, fd: i32, event: EpollEvent, flags: EpollFlags) -> KResult<()> {
-        let key = EntryKey::new(fd)?;
-        let interest = Arc::new(EpollInterest::new(key.clone(), event, flags));
+        self.add_file(fd, get_file_like(fd)?, event, flags)
+    }
+
+    fn add_file(
+        &self,
+        fd: i32,
+        file: Arc<dyn FileLike>,
+        event: EpollEvent,
+        flags: EpollFlags,
+    ) -> KResult<()> {
+        if flags.contains(EpollFlags::EXCLUSIVE | EpollFlags::ONESHOT) {
+            return Err(KError::InvalidInput);
+        }
+        let key = EntryKey::with_file(fd, &file);
         let mut guard = self.inner.interests.lock();
         if guard.contains_key(&key) {
             return Err(KError::AlreadyExists);
         }
-        guard.insert(key.clone(), Arc::clone(&interest));
+        let interest = self.new_interest(key.clone(), &file, event, flags);
+        guard.insert(key, Arc::clone(&interest));
         drop(guard);
         trace!("Epoll add fd: {} interest {:?} ", fd, interest.event.events);
-        self.check_and_register_waker(&interest);
+        interest.start(file.as_ref());
         Ok(())
     }
 
     /// Modifies an existing interest for the given file descriptor.
+    ///
+    /// This also re-arms an `EPOLLONESHOT` interest that has fired.
     pub fn modify(&self, fd: i32, event: EpollEvent, flags: EpollFlags) -> KResult<()> {
-        let key = EntryKey::new(fd)?;
-        let interest = Arc::new(EpollInterest::new(key.clone(), event, flags));
-
+        self.modify_file(fd, get_file_like(fd)?, event, flags)
+    }
+
+    fn modify_file(
+        &self,
+        fd: i32,
+        file: Arc<dyn FileLike>,
+        event: EpollEvent,
+        flags: EpollFlags,
+    ) -> KResult<()> {
+        if flags.contains(EpollFlags::EXCLUSIVE) {
+            return Err(KError::InvalidInput);
+        }
+        let key = EntryKey::with_file(fd, &file);
         let mut guard = self.inner.interests.lock();
         let old = guard.get_mut(&key).ok_or(KError::NotFound)?;
-
-        // update new interest if old already in ready queue
-        if old.is_in_queue() {
-            interest.in_ready_queue.store(true, Ordering::Release);
+        if old.exclusive.is_some() {
+            return Err(KError::InvalidInput);
         }
-        *old = Arc::clone(&interest);
+        // The queue refers to the old interest, which is gone with it, so
+        // the new one starts off the queue.
+        let interest = self.new_interest(key, &file, event, flags);
+        let old = core::mem::replace(old, Arc::clone(&interest));
         drop(guard);
+        old.disarm();
         trace!(
             "Epoll: modify fd={}, events={:?}",
             fd, interest.event.events
         );
-        // reset waker
-        self.check_and_register_waker(&interest);
+        interest.start(file.as_ref());
         Ok(())
     }
 
     /// Removes an existing interest for the given file descriptor.
     pub fn delete(&self, fd: i32) -> KResult<()> {
         let key = EntryKey::new(fd)?;
-        self.inner
+        let interest = self
+            .inner
             .interests
             .lock()
             .remove(&key)
             .ok_or(KError::NotFound)?;
+        interest.disarm();
         trace!("Epoll: delete fd={fd}");
         Ok(())
     }
 
     /// Polls for ready events and writes them into `out`.
+    ///
+    /// Each queued interest is looked at once, so a level-triggered one is
+    /// reported at most once per call.
     pub fn poll_events(&self, out: &mut [epoll_event]) -> KResult<usize> {
         trace!("Epoll: poll_events called, out.len()={}", out.len());
         let mut count = 0;
-        loop {
-            let weak_interest = {
-                let mut queue = self.inner.ready_queue.lock();
-                queue.pop_front()
-            };
-
-            let Some(weak_interest) = weak_interest else {
-                break;
-            };
-
+        let pending = self.inner.ready_queue.lock().len();
+        for _ in 0..pending {
             if count >= out.len() {
-                self.inner.ready_queue.lock().push_front(weak_interest);
                 break;
             }
+            let Some(weak_interest) = self.inner.ready_queue.lock().pop_front() else {
+                break;
+            };
 
             let Some(interest) = weak_interest.upgrade() else {
                 continue; // interest already removed
@@ -418,13 +634,8 @@ impl Epoll {
                         data: event.user_data,
                     };
                     count += 1;
-                    interest.mark_not_in_queue();
-                    self.register_waker_only(&interest);
-                }
-                ConsumeResult::NoEvent => {
-                    interest.mark_not_in_queue();
-                    self.register_waker_only(&interest);
                 }
+                ConsumeResult::NoEvent => {}
             }
         }
 
@@ -464,6 +675,7 @@ mod epoll_tests {
     use unittest::def_test;
 
     use super::*;
+    use crate::file::pipe::Pipe;
 
     /// Test basic Epoll creation
     #[def_test]
@@ -485,6 +697,7 @@ mod epoll_tests {
 
         flags.insert(EpollFlags::ONESHOT);
         assert!(flags.contains(EpollFlags::EDGE_TRIGGER | EpollFlags::ONESHOT));
+        assert_eq!(EpollFlags::EXCLUSIVE.bits(), EPOLLEXCLUSIVE);
     }
 
     /// Test TriggerMode creation from flags
@@ -571,6 +784,141 @@ mod epoll_tests {
         assert_eq!(event.user_data, 0x12345678);
     }
 
+    fn pipe() -> (Arc<dyn FileLike>, Arc<Pipe>) {
+        let (read_end, write_end) = Pipe::new();
+        (Arc::new(read_end), Arc::new(write_end))
+    }
+
+    fn write(pipe: &Pipe, data: &[u8]) {
+        let mut src = data;
+        assert_eq!(pipe.write(&mut src), Ok(data.len()));
+    }
+
+    fn drain(pipe: &Arc<dyn FileLike>) {
+        let mut buf = [0u8; 64];
+        let mut dst: &mut [u8] = &mut buf;
+        assert!(pipe.read(&mut dst).is_ok());
+    }
+
+    fn watch(epoll: &Epoll, file: &Arc<dyn FileLike>, flags: EpollFlags) {
+        let event = EpollEvent {
+            events: IoEvents::IN,
+            user_data: 7,
+        };
+        epoll.add_file(3, file.clone(), event, flags).unwrap();
+    }
+
+    /// Returns how many events a call of `epoll_wait` would report.
+    fn wait(epoll: &Epoll) -> usize {
+        let mut out = [epoll_event { events: 0, data: 0 }; 4];
+        epoll.poll_events(&mut out).unwrap_or(0)
+    }
+
+    #[def_test]
+    fn test_edge_triggered_once_per_burst() {
+        let epoll = Epoll::new();
+        let (read_end, write_end) = pipe();
+        watch(&epoll, &read_end, EpollFlags::EDGE_TRIGGER);
+        assert_eq!(wait(&epoll), 0);
+
+        write(&write_end, b"a");
+        write(&write_end, b"b");
+        write(&write_end, b"c");
+        assert_eq!(wait(&epoll), 1);
+        // Still readable, but nothing new.
+        assert_eq!(wait(&epoll), 0);
+
+        // New data is an edge even if some was left.
+        write(&write_end, b"d");
+        assert_eq!(wait(&epoll), 1);
+        assert_eq!(wait(&epoll), 0);
+
+        // Drained then refilled.
+        drain(&read_end);
+        assert_eq!(wait(&epoll), 0);
+        write(&write_end, b"e");
+        assert_eq!(wait(&epoll), 1);
+        assert_eq!(wait(&epoll), 0);
+    }
+
+    #[def_test]
+    fn test_edge_triggered_ready_when_added() {
+        let epoll = Epoll::new();
+        let (read_end, write_end) = pipe();
+        write(&write_end, b"a");
+        watch(&epoll, &read_end, EpollFlags::EDGE_TRIGGER);
+        assert_eq!(wait(&epoll), 1);
+        assert_eq!(wait(&epoll), 0);
+    }
+
+    #[def_test]
+    fn test_level_triggered_once_per_call() {
+        let epoll = Epoll::new();
+        let (read_end, write_end) = pipe();
+        watch(&epoll, &read_end, EpollFlags::empty());
+        write(&write_end, b"a");
+        assert_eq!(wait(&epoll), 1);
+        assert_eq!(wait(&epoll), 1);
+        drain(&read_end);
+        assert_eq!(wait(&epoll), 0);
+    }
+
+    #[def_test]
+    fn test_oneshot_rearmed_by_modify() {
+        let epoll = Epoll::new();
+        let (read_end, write_end) = pipe();
+        watch(&epoll, &read_end, EpollFlags::ONESHOT);
+        write(&write_end, b"a");
+        assert_eq!(wait(&epoll), 1);
+        write(&write_end, b"b");
+        assert_eq!(wait(&epoll), 0);
+
+        let event = EpollEvent {
+            events: IoEvents::IN,
+            user_data: 7,
+        };
+        epoll
+            .modify_file(3, read_end.clone(), event, EpollFlags::ONESHOT)
+            .unwrap();
+        assert_eq!(wait(&epoll), 1);
+        assert_eq!(wait(&epoll), 0);
+    }
+
+    #[def_test]
+    fn test_exclusive_wakes_one() {
+        let (first, second) = (Epoll::new(), Epoll::new());
+        let (read_end, write_end) = pipe();
+        let flags = EpollFlags::EDGE_TRIGGER | EpollFlags::EXCLUSIVE;
+        watch(&first, &read_end, flags);
+        watch(&second, &read_end, flags);
+
+        write(&write_end, b"a");
+        assert_eq!(wait(&first) + wait(&second), 1);
+        write(&write_end, b"b");
+        assert_eq!(wait(&first) + wait(&second), 1);
+
+        let event = EpollEvent {
+            events: IoEvents::IN,
+            user_data: 7,
+        };
+        assert_eq!(
+            first.modify_file(3, read_end.clone(), event, EpollFlags::empty()),
+            Err(KError::InvalidInput)
+        );
+        assert_eq!(
+            Epoll::new().add_file(
+                3,
+                read_end,
+                EpollEvent {
+                    events: IoEvents::IN,
+                    user_data: 7,
+                },
+                EpollFlags::EXCLUSIVE | EpollFlags::ONESHOT
+            ),
+            Err(KError::InvalidInput)
+        );
+    }
+
     /// Test poll_events with zero-length buffer
     #[def_test]
     fn test_poll_events_zero_buffer() {
@@ -12,7 +12,7 @@ use core::{
     task::Context,
 };
 
-use fs_ng_vfs::{Location, Metadata, NodeFlags};
+use fs_ng_vfs::{DirEntry, Location, LockOwner, Metadata, MetadataUpdate, NodeFlags};
 use kerrno::{KError, KResult};
 use kfs::{FS_CONTEXT, FsContext};
 use kpoll::{IoEvents, Pollable};
@@ -96,6 +96,86 @@ pub fn resolve_at(dirfd: c_int, path: Option<&str>, flags: u32) -> KResult<Resol
     }
 }
 
+/// Changes the metadata of the inode behind the file descriptor `fd`.
+///
+/// `f` computes the update from the current metadata, and may refuse it. The
+/// update goes to the inode the descriptor was opened on rather than to
+/// whatever its path names now, so it is unaffected by renames and works on
+/// unlinked files. `O_PATH` descriptors and descriptors that are not backed by
+/// a filesystem are refused with `EBADF`.
+pub fn update_fd_metadata(
+    fd: c_int,
+    f: impl FnOnce(&Metadata) -> KResult<MetadataUpdate>,
+) -> KResult {
+    let file_like = get_file_like(fd)?;
+    if let Some(file) = file_like.downcast_ref::<File>() {
+        let file = file.inner();
+        if file.is_path() {
+            return Err(KError::BadFileDescriptor);
+        }
+        let update = f(&file.metadata()?)?;
+        file.update_metadata(update)?;
+    } else if let Some(dir) = file_like.downcast_ref::<Directory>() {
+        if dir.is_path() {
+            return Err(KError::BadFileDescriptor);
+        }
+        let update = f(&dir.inner.metadata()?)?;
+        dir.inner.update_metadata(update)?;
+    } else {
+        return Err(KError::BadFileDescriptor);
+    }
+    Ok(())
+}
+
+/// Returns the location of the inode behind the file descriptor `fd`.
+///
+/// As with [`update_fd_metadata`], this is the inode the descriptor was
+/// opened on. `O_PATH` descriptors are refused with `EBADF`, and descriptors
+/// that are not backed by a filesystem with `EOPNOTSUPP`.
+pub fn fd_location(fd: c_int) -> KResult<Location> {
+    let file_like = get_file_like(fd)?;
+    if let Some(file) = file_like.downcast_ref::<File>() {
+        let file = file.inner();
+        if file.is_path() {
+            return Err(KError::BadFileDescriptor);
+        }
+        Ok(file.location().clone())
+    } else if let Some(dir) = file_like.downcast_ref::<Directory>() {
+        if dir.is_path() {
+            return Err(KError::BadFileDescriptor);
+        }
+        Ok(dir.inner.clone())
+    } else {
+        Err(KError::OperationNotSupported)
+    }
+}
+
+/// Returns the entry that advisory locks taken through `f` apply to, or
+/// `None` if `f` is not backed by a filesystem.
+pub fn lock_entry(f: &dyn FileLike) -> Option<&DirEntry> {
+    if let Some(file) = f.downcast_ref::<File>() {
+        Some(file.inner().location().entry())
+    } else if let Some(dir) = f.downcast_ref::<Directory>() {
+        Some(dir.inner().entry())
+    } else {
+        None
+    }
+}
+
+/// Returns the lock owner that stands for the open file `f`, as used by
+/// `flock` and open file description locks.
+pub fn open_file_owner(f: &dyn FileLike) -> LockOwner {
+    f as *const dyn FileLike as *const () as LockOwner
+}
+
+/// Drops the locks owned by an open file once its last descriptor is gone.
+fn release_open_file_locks(f: &dyn FileLike, entry: &DirEntry) {
+    let owner = open_file_owner(f);
+    // Unlocking never fails.
+    let _ = entry.flock(owner, None, None);
+    entry.release_record_locks(owner);
+}
+
 /// Converts filesystem metadata to kernel stat structure.
 pub fn metadata_to_kstat(metadata: &Metadata) -> Kstat {
     let ty = metadata.node_type as u8;
@@ -147,6 +227,12 @@ impl File {
     }
 }
 
+impl Drop for File {
+    fn drop(&mut self) {
+        release_open_file_locks(&*self, self.inner.location().entry());
+    }
+}
+
 /// Gets the absolute path of a location, or `<error>` if unavailable.
 fn path_for(loc: &Location) -> Cow<'static, str> {
     loc.absolute_path()
@@ -237,6 +323,8 @@ pub struct Directory {
     inner: Location,
     /// Current offset for directory iteration
     pub offset: Mutex<u64>,
+    /// Whether the directory was opened with `O_PATH`
+    path_only: bool,
 }
 
 impl Directory {
@@ -245,15 +333,34 @@ impl Directory {
         Self {
             inner,
             offset: Mutex::new(0),
+            path_only: false,
         }
     }
 
+    /// Creates a directory wrapper for an `O_PATH` descriptor.
+    pub fn new_path(inner: Location) -> Self {
+        let mut dir = Self::new(inner);
+        dir.path_only = true;
+        dir
+    }
+
+    /// Returns whether the directory was opened with `O_PATH`.
+    pub fn is_path(&self) -> bool {
+        self.path_only
+    }
+
     /// Get the inner node of the directory.
     pub fn inner(&self) -> &Location {
         &self.inner
     }
 }
 
+impl Drop for Directory {
+    fn drop(&mut self) {
+        release_open_file_locks(&*self, self.inner.entry());
+    }
+}
+
 impl FileLike for Directory {
     /// Read is not supported on directories.
     fn read(&self, _dst: &mut IoDst) -> KResult<usize> {
@@ -294,9 +401,12 @@ impl Pollable for Directory {
 
 #[cfg(unittest)]
 mod fs_tests {
+    use fs_ng_vfs::{Mountpoint, NodePermission, NodeType};
+    use kfs::OpenResult;
     use unittest::def_test;
 
     use super::*;
+    use crate::vfs::MemoryFs;
 
     /// Test AT_ constants have correct Linux values
     #[def_test]
@@ -305,4 +415,84 @@ mod fs_tests {
         assert_eq!(AT_EMPTY_PATH, 0x1000);
         assert_eq!(AT_SYMLINK_NOFOLLOW, 0x100);
     }
+
+    fn tmp_root() -> Location {
+        Mountpoint::new_root(&MemoryFs::new()).root_location()
+    }
+
+    fn create(dir: &Location, name: &str) -> Location {
+        dir.create(
+            name,
+            NodeType::RegularFile,
+            NodePermission::from_bits_truncate(0o644),
+        )
+        .unwrap()
+    }
+
+    fn open(loc: &Location, path_only: bool) -> kfs::File {
+        kfs::OpenOptions::new()
+            .read(true)
+            .path(path_only)
+            .open_loc(loc.clone())
+            .and_then(OpenResult::into_file)
+            .unwrap()
+    }
+
+    fn chmod(mode: u16) -> MetadataUpdate {
+        MetadataUpdate {
+            mode: Some(NodePermission::from_bits_truncate(mode)),
+            ..Default::default()
+        }
+    }
+
+    fn mode_of(dir: &Location, name: &str) -> u16 {
+        let loc = dir.lookup_no_follow(name).unwrap();
+        loc.metadata().unwrap().mode.bits()
+    }
+
+    /// Metadata changes through an open file follow the inode, not the name.
+    #[def_test]
+    fn test_update_metadata_after_rename_swap() {
+        let root = tmp_root();
+        let a = create(&root, "a");
+        create(&root, "b");
+        let file = open(&a, false);
+
+        root.rename("a", &root, "tmp").unwrap();
+        root.rename("b", &root, "a").unwrap();
+        root.rename("tmp", &root, "b").unwrap();
+
+        file.update_metadata(chmod(0o600)).unwrap();
+        assert_eq!(mode_of(&root, "b"), 0o600);
+        assert_eq!(mode_of(&root, "a"), 0o644);
+    }
+
+    #[def_test]
+    fn test_update_metadata_unlinked() {
+        let root = tmp_root();
+        let file = open(&create(&root, "c"), false);
+        root.unlink("c", false).unwrap();
+
+        file.update_metadata(MetadataUpdate {
+            owner: Some((1000, 100)),
+            ..Default::default()
+        })
+        .unwrap();
+        let meta = file.metadata().unwrap();
+        assert_eq!((meta.uid, meta.gid), (1000, 100));
+    }
+
+    #[def_test]
+    fn test_update_metadata_o_path() {
+        let root = tmp_root();
+        let file = open(&create(&root, "d"), true);
+        assert_eq!(
+            file.update_metadata(chmod(0o600)).unwrap_err(),
+            KError::BadFileDescriptor
+        );
+        assert_eq!(mode_of(&root, "d"), 0o644);
+
+        assert!(Directory::new_path(root.clone()).is_path());
+        assert!(!Directory::new(root).is_path());
+    }
 }
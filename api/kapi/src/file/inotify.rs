@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Inotify instances: queues of filesystem change events.
+
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Context,
+};
+
+use fs_ng_vfs::{FsEvents, FsWatch, Location};
+use kerrno::{KError, KResult, LinuxError};
+use kpoll::{IoEvents, PollSet, Pollable};
+use ksync::Mutex;
+use ktask::future::{block_on, poll_io};
+use linux_raw_sys::{
+    general::{
+        IN_ALL_EVENTS, IN_IGNORED, IN_ISDIR, IN_MASK_ADD, IN_MASK_CREATE, IN_ONESHOT, IN_Q_OVERFLOW,
+    },
+    ioctl::FIONREAD,
+};
+use osvm::VirtMutPtr;
+use zerocopy::{Immutable, IntoBytes};
+
+use crate::file::{FileLike, IoDst};
+
+/// Events queued at most per instance, the default of
+/// `/proc/sys/fs/inotify/max_queued_events`.
+pub const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// Header of `struct inotify_event`, followed by `len` bytes of name.
+#[repr(C)]
+#[derive(Immutable, IntoBytes)]
+struct EventHeader {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    len: u32,
+}
+
+const EVENT_HEADER_SIZE: usize = size_of::<EventHeader>();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Event {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    name: Option<String>,
+}
+
+impl Event {
+    fn overflow() -> Self {
+        Self {
+            wd: -1,
+            mask: IN_Q_OVERFLOW,
+            cookie: 0,
+            name: None,
+        }
+    }
+
+    /// Returns the length of the name with its NUL terminator, padded to a
+    /// multiple of the header size as Linux does.
+    fn name_len(&self) -> usize {
+        self.name.as_ref().map_or(0, |name| {
+            (name.len() + 1).next_multiple_of(EVENT_HEADER_SIZE)
+        })
+    }
+
+    fn size(&self) -> usize {
+        EVENT_HEADER_SIZE + self.name_len()
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let len = self.name_len();
+        let header = EventHeader {
+            wd: self.wd,
+            mask: self.mask,
+            cookie: self.cookie,
+            len: len as u32,
+        };
+        out.extend_from_slice(header.as_bytes());
+        if let Some(name) = &self.name {
+            out.extend_from_slice(name.as_bytes());
+            out.resize(out.len() + len - name.len(), 0);
+        }
+    }
+}
+
+/// A watch of an inotify instance on an inode.
+struct Watch {
+    wd: i32,
+    /// Events to report, with `IN_ONESHOT`.
+    mask: AtomicU32,
+    location: Location,
+    inotify: Weak<Inotify>,
+}
+
+impl FsWatch for Watch {
+    fn notify(&self, events: FsEvents, name: Option<&str>, cookie: u32) {
+        let Some(inotify) = self.inotify.upgrade() else {
+            return;
+        };
+        let mask = self.mask.load(Ordering::Acquire);
+        let matched = events.bits() & mask & IN_ALL_EVENTS;
+        if matched != 0 {
+            inotify.push(Event {
+                wd: self.wd,
+                mask: matched | (events.bits() & IN_ISDIR),
+                cookie,
+                name: name.map(ToString::to_string),
+            });
+        }
+        if (matched != 0 && mask & IN_ONESHOT != 0) || events.contains(FsEvents::DELETE_SELF) {
+            let _ = inotify.remove_watch(self.wd);
+        }
+    }
+}
+
+struct InotifyState {
+    queue: VecDeque<Event>,
+    watches: BTreeMap<i32, Arc<Watch>>,
+    next_wd: i32,
+}
+
+/// An inotify instance, reporting the changes of the inodes it watches.
+pub struct Inotify {
+    this: Weak<Inotify>,
+    state: Mutex<InotifyState>,
+    non_blocking: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl Inotify {
+    /// Creates an instance without watches.
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            state: Mutex::new(InotifyState {
+                queue: VecDeque::new(),
+                watches: BTreeMap::new(),
+                next_wd: 1,
+            }),
+            non_blocking: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        })
+    }
+
+    /// Queues `event`, unless it repeats the last queued one.
+    ///
+    /// A full queue takes a single `IN_Q_OVERFLOW` event in place of the
+    /// events that do not fit.
+    fn push(&self, event: Event) {
+        {
+            let mut state = self.state.lock();
+            let queue = &mut state.queue;
+            if queue.back() == Some(&event) {
+                return;
+            }
+            if queue.len() < MAX_QUEUED_EVENTS {
+                queue.push_back(event);
+            } else if queue.back() != Some(&Event::overflow()) {
+                queue.push_back(Event::overflow());
+            } else {
+                return;
+            }
+        }
+        self.poll_rx.wake();
+    }
+
+    /// Watches the inode at `location` for the events of `mask`, returning
+    /// the watch descriptor.
+    ///
+    /// An inode is watched at most once per instance: watching it again
+    /// replaces the mask, or adds to it with `IN_MASK_ADD`, or fails with
+    /// `EEXIST` with `IN_MASK_CREATE`.
+    pub fn add_watch(&self, location: Location, mask: u32) -> KResult<i32> {
+        let kept = mask & (IN_ALL_EVENTS | IN_ONESHOT);
+        let mut state = self.state.lock();
+        let existing = state.watches.values().find(|watch| {
+            watch.location.inode() == location.inode()
+                && core::ptr::addr_eq(watch.location.filesystem(), location.filesystem())
+        });
+        if let Some(watch) = existing {
+            if mask & IN_MASK_CREATE != 0 {
+                return Err(KError::AlreadyExists);
+            }
+            if mask & IN_MASK_ADD != 0 {
+                watch.mask.fetch_or(kept, Ordering::AcqRel);
+            } else {
+                watch.mask.store(kept, Ordering::Release);
+            }
+            return Ok(watch.wd);
+        }
+
+        let wd = state.next_wd;
+        state.next_wd = wd.checked_add(1).ok_or(KError::from(LinuxError::ENOSPC))?;
+        let watch = Arc::new(Watch {
+            wd,
+            mask: AtomicU32::new(kept),
+            location,
+            inotify: self.this.clone(),
+        });
+        watch
+            .location
+            .entry()
+            .add_watch(&(watch.clone() as Arc<dyn FsWatch>));
+        state.watches.insert(wd, watch);
+        Ok(wd)
+    }
+
+    /// Removes the watch `wd`, queueing an `IN_IGNORED` event for it.
+    pub fn remove_watch(&self, wd: i32) -> KResult<()> {
+        let watch = self
+            .state
+            .lock()
+            .watches
+            .remove(&wd)
+            .ok_or(KError::InvalidInput)?;
+        watch
+            .location
+            .entry()
+            .remove_watch(&(watch.clone() as Arc<dyn FsWatch>));
+        self.push(Event {
+            wd,
+            mask: IN_IGNORED,
+            cookie: 0,
+            name: None,
+        });
+        Ok(())
+    }
+
+    fn queued_bytes(&self) -> usize {
+        self.state.lock().queue.iter().map(Event::size).sum()
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        for watch in self.state.get_mut().watches.values() {
+            watch
+                .location
+                .entry()
+                .remove_watch(&(watch.clone() as Arc<dyn FsWatch>));
+        }
+    }
+}
+
+impl FileLike for Inotify {
+    /// Reads as many whole events as fit, failing with `EINVAL` if not even
+    /// the first one does.
+    fn read(&self, dst: &mut IoDst) -> KResult<usize> {
+        block_on(poll_io(self, IoEvents::IN, self.nonblocking(), || {
+            let mut state = self.state.lock();
+            if state.queue.is_empty() {
+                return Err(KError::WouldBlock);
+            }
+            let room = dst.remaining_mut();
+            let mut out = Vec::new();
+            while let Some(event) = state.queue.front()
+                && out.len() + event.size() <= room
+            {
+                event.encode(&mut out);
+                state.queue.pop_front();
+            }
+            if out.is_empty() {
+                return Err(KError::InvalidInput);
+            }
+            dst.write(&out)?;
+            Ok(out.len())
+        }))
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "anon_inode:inotify".into()
+    }
+
+    /// Performs I/O control operations (supports FIONREAD).
+    fn ioctl(&self, cmd: u32, arg: usize) -> KResult<usize> {
+        match cmd {
+            FIONREAD => {
+                (arg as *mut u32).write_vm(self.queued_bytes() as u32)?;
+                Ok(0)
+            }
+            _ => Err(KError::NotATty),
+        }
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> KResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Pollable for Inotify {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, !self.state.lock().queue.is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod tests_inotify {
+    use alloc::{string::ToString, vec::Vec};
+
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    fn event(wd: i32, mask: u32, name: Option<&str>) -> Event {
+        Event {
+            wd,
+            mask,
+            cookie: 0,
+            name: name.map(ToString::to_string),
+        }
+    }
+
+    #[def_test]
+    fn test_event_layout() {
+        let mut out = Vec::new();
+        event(1, 0x100, None).encode(&mut out);
+        assert_eq!(out.len(), 16);
+        assert_eq!(&out[12..16], &0u32.to_ne_bytes());
+
+        // The name is NUL-terminated and padded to 16 bytes, as on Linux.
+        for (name, len) in [("a", 16), ("fifteen_chars__", 16), ("sixteen_chars___", 32)] {
+            let mut out = Vec::new();
+            let ev = event(2, 0x200, Some(name));
+            ev.encode(&mut out);
+            assert_eq!(out.len(), 16 + len);
+            assert_eq!(ev.size(), out.len());
+            assert_eq!(&out[12..16], &(len as u32).to_ne_bytes());
+            assert_eq!(&out[16..16 + name.len()], name.as_bytes());
+            assert!(out[16 + name.len()..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[def_test]
+    fn test_coalesce_and_overflow() {
+        let inotify = Inotify::new();
+        inotify.push(event(1, 0x2, Some("f")));
+        inotify.push(event(1, 0x2, Some("f")));
+        inotify.push(event(1, 0x2, Some("g")));
+        assert_eq!(inotify.state.lock().queue.len(), 2);
+        assert!(inotify.poll().contains(IoEvents::IN));
+
+        for i in 0..MAX_QUEUED_EVENTS + 10 {
+            inotify.push(event(1, 0x100, Some(&i.to_string())));
+        }
+        let state = inotify.state.lock();
+        assert_eq!(state.queue.len(), MAX_QUEUED_EVENTS + 1);
+        assert_eq!(state.queue.back(), Some(&Event::overflow()));
+    }
+
+    #[def_test]
+    fn test_remove_unknown_watch() {
+        let inotify = Inotify::new();
+        assert_eq!(inotify.remove_watch(1), Err(KError::InvalidInput));
+        assert!(!inotify.poll().contains(IoEvents::IN));
+    }
+}
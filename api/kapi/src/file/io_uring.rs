@@ -0,0 +1,630 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! io_uring instances: submission and completion rings shared with user
+//! space.
+//!
+//! The rings have the layout of Linux, so programs written against liburing
+//! run unchanged. Both rings live in one mapping (`IORING_FEAT_SINGLE_MMAP`),
+//! the submission queue entries in another. User space owns the submission
+//! tail and the completion head, the kernel the other two; each side
+//! publishes its index with release ordering and reads the other's with
+//! acquire ordering.
+//!
+//! Operations are issued once their file is ready, so they never block. One
+//! that is not ready is kept pending and reissued as its file becomes ready.
+//! Completions that do not fit into the completion ring are kept back until
+//! user space makes room (`IORING_FEAT_NODROP`).
+
+use alloc::{borrow::Cow, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    task::Context,
+};
+
+use bytemuck::AnyBitPattern;
+use kerrno::{KError, KResult};
+use khal::{mem::p2v, paging::PageSize};
+use kpoll::{IoEvents, PollSet, Pollable};
+use ksync::Mutex;
+use memaddr::{PAGE_SIZE_4K, VirtAddr};
+use memspace::backend::{Backend, SharedPages};
+
+use crate::file::{FileLike, Kstat};
+
+/// The mmap offset of the submission ring.
+pub const IORING_OFF_SQ_RING: usize = 0;
+/// The mmap offset of the completion ring, the same mapping here.
+pub const IORING_OFF_CQ_RING: usize = 0x800_0000;
+/// The mmap offset of the submission queue entries.
+pub const IORING_OFF_SQES: usize = 0x1000_0000;
+
+/// `cq_entries` of the setup parameters gives the size of the completion
+/// ring.
+pub const IORING_SETUP_CQSIZE: u32 = 1 << 3;
+/// Ring sizes beyond the limits are clamped instead of rejected.
+pub const IORING_SETUP_CLAMP: u32 = 1 << 4;
+
+/// The features of the rings, as reported by `io_uring_setup`.
+pub const IORING_FEATURES: u32 =
+    IORING_FEAT_SINGLE_MMAP | IORING_FEAT_NODROP | IORING_FEAT_SUBMIT_STABLE;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_FEAT_NODROP: u32 = 1 << 1;
+const IORING_FEAT_SUBMIT_STABLE: u32 = 1 << 2;
+
+/// `sq_flags`: completions are kept back as the completion ring is full.
+const IORING_SQ_CQ_OVERFLOW: u32 = 1 << 1;
+
+/// The most submission queue entries of a ring.
+pub const IORING_MAX_ENTRIES: u32 = 4096;
+/// The most completion queue entries of a ring.
+pub const IORING_MAX_CQ_ENTRIES: u32 = 2 * IORING_MAX_ENTRIES;
+
+// The layout of the ring mapping. The indices of the two rings are on
+// cache lines of their own; the completion queue entries follow, then the
+// submission ring. No entry straddles a page.
+const SQ_HEAD: usize = 0;
+const SQ_TAIL: usize = 4;
+const SQ_RING_MASK: usize = 8;
+const SQ_RING_ENTRIES: usize = 12;
+const SQ_FLAGS: usize = 16;
+const SQ_DROPPED: usize = 20;
+const CQ_HEAD: usize = 64;
+const CQ_TAIL: usize = 68;
+const CQ_RING_MASK: usize = 72;
+const CQ_RING_ENTRIES: usize = 76;
+const CQ_OVERFLOW: usize = 80;
+const CQ_FLAGS: usize = 84;
+const CQES: usize = 128;
+
+/// `struct io_sqring_offsets`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoSqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+/// `struct io_cqring_offsets`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoCqringOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub user_addr: u64,
+}
+
+/// `struct io_uring_params`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: IoSqringOffsets,
+    pub cq_off: IoCqringOffsets,
+}
+
+/// `struct io_uring_sqe`, with the unions of Linux under the names of the
+/// members used here.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct IoUringSqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    /// The file offset, or the address of the address length for accept.
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    /// `rw_flags`, `poll32_events` or `accept_flags`.
+    pub op_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub addr3: u64,
+    pub _pad: u64,
+}
+
+/// `struct io_uring_cqe`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AnyBitPattern)]
+pub struct IoUringCqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+/// What became of an issued operation.
+pub enum Issued {
+    /// It completed with the result, a negative errno on failure.
+    Done(i32),
+    /// It waits for `events` on `file`.
+    Pending(Arc<dyn FileLike>, IoEvents),
+}
+
+/// Pages shared with user space, accessed by the kernel through the linear
+/// mapping.
+struct Region(Arc<SharedPages>);
+
+impl Region {
+    fn new(size: usize) -> KResult<Self> {
+        let size = size.next_multiple_of(PAGE_SIZE_4K);
+        Ok(Self(Arc::new(SharedPages::new(size, PageSize::Size4K)?)))
+    }
+
+    fn size(&self) -> usize {
+        self.0.len() * PAGE_SIZE_4K
+    }
+
+    /// Returns the `T` at `offset`, which must not straddle a page.
+    fn at<T>(&self, offset: usize) -> *mut T {
+        debug_assert!(offset % PAGE_SIZE_4K + size_of::<T>() <= PAGE_SIZE_4K);
+        let page = p2v(self.0[offset / PAGE_SIZE_4K]);
+        (page + offset % PAGE_SIZE_4K).as_mut_ptr_of()
+    }
+
+    fn atomic(&self, offset: usize) -> &AtomicU32 {
+        // SAFETY: the pages live as long as `self` and are aligned for it.
+        unsafe { AtomicU32::from_ptr(self.at(offset)) }
+    }
+
+    fn mmap_backend(&self, start: VirtAddr, length: usize) -> KResult<Backend> {
+        if length > self.size() {
+            return Err(KError::InvalidInput);
+        }
+        let pages = self.0.iter().copied().take(length / PAGE_SIZE_4K).collect();
+        let pages = SharedPages::from_owned(pages, self.0.clone());
+        Ok(Backend::new_shared(start, Arc::new(pages)))
+    }
+}
+
+/// An operation waiting for its file.
+struct Pending {
+    sqe: IoUringSqe,
+    file: Arc<dyn FileLike>,
+    events: IoEvents,
+}
+
+#[derive(Default)]
+struct RingState {
+    pending: VecDeque<Pending>,
+    /// Completions kept back as the completion ring was full.
+    overflow: VecDeque<IoUringCqe>,
+}
+
+/// An io_uring instance.
+///
+/// Closing the last file descriptor of it drops the pending operations and
+/// the references to their files; mappings of the rings stay valid until they
+/// are unmapped.
+pub struct IoUring {
+    rings: Region,
+    sqes: Region,
+    sq_entries: u32,
+    cq_entries: u32,
+    state: Mutex<RingState>,
+    cq_ready: PollSet,
+}
+
+impl IoUring {
+    /// Creates rings of `sq_entries` and `cq_entries` entries, both powers of
+    /// two within the limits.
+    pub fn new(sq_entries: u32, cq_entries: u32) -> KResult<Self> {
+        debug_assert!(sq_entries.is_power_of_two() && sq_entries <= IORING_MAX_ENTRIES);
+        debug_assert!(cq_entries.is_power_of_two() && cq_entries <= IORING_MAX_CQ_ENTRIES);
+        let ring = Self {
+            rings: Region::new(Self::sq_array(cq_entries) + sq_entries as usize * 4)?,
+            sqes: Region::new(sq_entries as usize * size_of::<IoUringSqe>())?,
+            sq_entries,
+            cq_entries,
+            state: Mutex::new(RingState::default()),
+            cq_ready: PollSet::new(),
+        };
+        let store = |offset, value| ring.rings.atomic(offset).store(value, Ordering::Relaxed);
+        store(SQ_RING_MASK, sq_entries - 1);
+        store(SQ_RING_ENTRIES, sq_entries);
+        store(CQ_RING_MASK, cq_entries - 1);
+        store(CQ_RING_ENTRIES, cq_entries);
+        Ok(ring)
+    }
+
+    /// Returns the offset of the submission ring in the ring mapping.
+    fn sq_array(cq_entries: u32) -> usize {
+        CQES + cq_entries as usize * size_of::<IoUringCqe>()
+    }
+
+    /// Fills in the sizes, offsets and features of `params`.
+    pub fn fill_params(&self, params: &mut IoUringParams) {
+        params.sq_entries = self.sq_entries;
+        params.cq_entries = self.cq_entries;
+        params.features = IORING_FEATURES;
+        params.sq_off = IoSqringOffsets {
+            head: SQ_HEAD as _,
+            tail: SQ_TAIL as _,
+            ring_mask: SQ_RING_MASK as _,
+            ring_entries: SQ_RING_ENTRIES as _,
+            flags: SQ_FLAGS as _,
+            dropped: SQ_DROPPED as _,
+            array: Self::sq_array(self.cq_entries) as _,
+            ..Default::default()
+        };
+        params.cq_off = IoCqringOffsets {
+            head: CQ_HEAD as _,
+            tail: CQ_TAIL as _,
+            ring_mask: CQ_RING_MASK as _,
+            ring_entries: CQ_RING_ENTRIES as _,
+            overflow: CQ_OVERFLOW as _,
+            cqes: CQES as _,
+            flags: CQ_FLAGS as _,
+            ..Default::default()
+        };
+    }
+
+    /// Returns the backend mapping `length` bytes of the region at the mmap
+    /// `offset` at `start`.
+    pub fn mmap_backend(&self, start: VirtAddr, offset: usize, length: usize) -> KResult<Backend> {
+        match offset {
+            IORING_OFF_SQ_RING | IORING_OFF_CQ_RING => self.rings.mmap_backend(start, length),
+            IORING_OFF_SQES => self.sqes.mmap_backend(start, length),
+            _ => Err(KError::InvalidInput),
+        }
+    }
+
+    /// Returns the size of the completion ring.
+    pub fn cq_entries(&self) -> u32 {
+        self.cq_entries
+    }
+
+    /// Returns how many completions user space has yet to consume.
+    pub fn completions(&self) -> u32 {
+        let head = self.rings.atomic(CQ_HEAD).load(Ordering::Acquire);
+        let tail = self.rings.atomic(CQ_TAIL).load(Ordering::Relaxed);
+        tail.wrapping_sub(head)
+    }
+
+    /// Returns `true` if operations wait for their files.
+    pub fn has_pending(&self) -> bool {
+        !self.state.lock().pending.is_empty()
+    }
+
+    /// Takes the next submission queue entry, skipping invalid indices.
+    fn pop_sqe(&self) -> Option<IoUringSqe> {
+        let head = self.rings.atomic(SQ_HEAD);
+        let tail = self.rings.atomic(SQ_TAIL).load(Ordering::Acquire);
+        loop {
+            let index = head.load(Ordering::Relaxed);
+            if index == tail {
+                return None;
+            }
+            let array =
+                Self::sq_array(self.cq_entries) + (index & (self.sq_entries - 1)) as usize * 4;
+            // SAFETY: the entry is within the ring and any bits are valid.
+            let entry = unsafe { ptr::read_volatile(self.rings.at::<u32>(array)) };
+            let sqe = (entry < self.sq_entries).then(|| {
+                // SAFETY: as above. User space may change the entry
+                // meanwhile, this copy is what is issued.
+                unsafe {
+                    ptr::read_volatile(
+                        self.sqes
+                            .at::<IoUringSqe>(entry as usize * size_of::<IoUringSqe>()),
+                    )
+                }
+            });
+            head.store(index.wrapping_add(1), Ordering::Release);
+            match sqe {
+                Some(sqe) => return Some(sqe),
+                None => {
+                    self.rings
+                        .atomic(SQ_DROPPED)
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Moves completions kept back into the completion ring, as far as
+    /// there is room, returning `true` if none are left.
+    fn flush_overflow(&self, state: &mut RingState) -> bool {
+        let mut posted = false;
+        while let Some(cqe) = state.overflow.front() {
+            if !self.push_cqe(*cqe) {
+                break;
+            }
+            state.overflow.pop_front();
+            posted = true;
+        }
+        if state.overflow.is_empty() {
+            self.rings
+                .atomic(SQ_FLAGS)
+                .fetch_and(!IORING_SQ_CQ_OVERFLOW, Ordering::Relaxed);
+        }
+        if posted {
+            self.cq_ready.wake();
+        }
+        state.overflow.is_empty()
+    }
+
+    /// Writes `cqe` into the completion ring, returning `false` if it is
+    /// full.
+    fn push_cqe(&self, cqe: IoUringCqe) -> bool {
+        if self.completions() >= self.cq_entries {
+            return false;
+        }
+        let tail = self.rings.atomic(CQ_TAIL);
+        let index = tail.load(Ordering::Relaxed);
+        let offset = CQES + (index & (self.cq_entries - 1)) as usize * size_of::<IoUringCqe>();
+        // SAFETY: the entry is within the ring, which user space does not
+        // read until the tail moves past it.
+        unsafe { ptr::write_volatile(self.rings.at(offset), cqe) };
+        tail.store(index.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Posts the completion of the operation of `user_data`.
+    fn post(&self, state: &mut RingState, user_data: u64, res: i32) {
+        let cqe = IoUringCqe {
+            user_data,
+            res,
+            flags: 0,
+        };
+        if state.overflow.is_empty() && self.push_cqe(cqe) {
+            self.cq_ready.wake();
+            return;
+        }
+        state.overflow.push_back(cqe);
+        self.rings
+            .atomic(SQ_FLAGS)
+            .fetch_or(IORING_SQ_CQ_OVERFLOW, Ordering::Relaxed);
+    }
+
+    fn complete(&self, state: &mut RingState, sqe: IoUringSqe, issued: Issued) {
+        match issued {
+            Issued::Done(res) => self.post(state, sqe.user_data, res),
+            Issued::Pending(file, events) => state.pending.push_back(Pending { sqe, file, events }),
+        }
+    }
+
+    /// Issues up to `to_submit` submitted operations with `issue`, returning
+    /// how many were consumed.
+    ///
+    /// Fails with `ResourceBusy` while completions are kept back, so their
+    /// number stays bounded.
+    pub fn submit(
+        &self,
+        to_submit: u32,
+        mut issue: impl FnMut(&IoUringSqe) -> Issued,
+    ) -> KResult<u32> {
+        let mut state = self.state.lock();
+        if !self.flush_overflow(&mut state) {
+            return Err(KError::ResourceBusy);
+        }
+        let mut submitted = 0;
+        while submitted < to_submit
+            && let Some(sqe) = self.pop_sqe()
+        {
+            let issued = issue(&sqe);
+            self.complete(&mut state, sqe, issued);
+            submitted += 1;
+        }
+        Ok(submitted)
+    }
+
+    /// Reissues with `issue` the pending operations whose files are ready,
+    /// and moves completions kept back into the completion ring.
+    pub fn reap(&self, mut issue: impl FnMut(&IoUringSqe) -> Issued) {
+        let mut state = self.state.lock();
+        self.flush_overflow(&mut state);
+        for _ in 0..state.pending.len() {
+            let Some(pending) = state.pending.pop_front() else {
+                break;
+            };
+            if pending
+                .file
+                .poll()
+                .intersects(pending.events | IoEvents::ALWAYS_POLL)
+            {
+                let issued = issue(&pending.sqe);
+                self.complete(&mut state, pending.sqe, issued);
+            } else {
+                state.pending.push_back(pending);
+            }
+        }
+    }
+}
+
+impl FileLike for IoUring {
+    fn stat(&self) -> KResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "anon_inode:[io_uring]".into()
+    }
+}
+
+impl Pollable for IoUring {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, self.completions() > 0);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.cq_ready.register(context.waker());
+            // Pending operations complete as their files become ready.
+            let pending: Vec<_> = self
+                .state
+                .lock()
+                .pending
+                .iter()
+                .map(|it| (it.file.clone(), it.events))
+                .collect();
+            for (file, events) in pending {
+                file.register(context, events);
+            }
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod io_uring_tests {
+    use bytemuck::Zeroable;
+    use unittest::def_test;
+
+    use super::*;
+
+    const IORING_OP_NOP: u8 = 0;
+
+    fn push_sqe(ring: &IoUring, sqe: IoUringSqe) {
+        let tail = ring.rings.atomic(SQ_TAIL);
+        let index = tail.load(Ordering::Relaxed);
+        let slot = index & (ring.sq_entries - 1);
+        let array = IoUring::sq_array(ring.cq_entries) + slot as usize * 4;
+        unsafe {
+            ring.sqes
+                .at::<IoUringSqe>(slot as usize * size_of::<IoUringSqe>())
+                .write(sqe);
+            ring.rings.at::<u32>(array).write(slot);
+        }
+        tail.store(index.wrapping_add(1), Ordering::Release);
+    }
+
+    fn pop_cqe(ring: &IoUring) -> Option<IoUringCqe> {
+        let head = ring.rings.atomic(CQ_HEAD);
+        let index = head.load(Ordering::Relaxed);
+        if index == ring.rings.atomic(CQ_TAIL).load(Ordering::Acquire) {
+            return None;
+        }
+        let offset = CQES + (index & (ring.cq_entries - 1)) as usize * size_of::<IoUringCqe>();
+        let cqe = unsafe { ring.rings.at::<IoUringCqe>(offset).read() };
+        head.store(index.wrapping_add(1), Ordering::Release);
+        Some(cqe)
+    }
+
+    fn nop(user_data: u64) -> IoUringSqe {
+        IoUringSqe {
+            opcode: IORING_OP_NOP,
+            user_data,
+            ..Zeroable::zeroed()
+        }
+    }
+
+    #[def_test]
+    fn test_abi_layout() {
+        assert_eq!(size_of::<IoUringParams>(), 120);
+        assert_eq!(size_of::<IoUringSqe>(), 64);
+        assert_eq!(size_of::<IoUringCqe>(), 16);
+        assert_eq!(core::mem::offset_of!(IoUringSqe, user_data), 32);
+        assert_eq!(core::mem::offset_of!(IoUringParams, sq_off), 40);
+        assert_eq!(core::mem::offset_of!(IoUringParams, cq_off), 80);
+    }
+
+    #[def_test]
+    fn test_params_describe_rings() {
+        let ring = IoUring::new(8, 16).unwrap();
+        let mut params = IoUringParams::default();
+        ring.fill_params(&mut params);
+        assert_eq!((params.sq_entries, params.cq_entries), (8, 16));
+        assert_eq!(
+            params.features & IORING_FEAT_SINGLE_MMAP,
+            IORING_FEAT_SINGLE_MMAP
+        );
+        assert_eq!(ring.rings.atomic(SQ_RING_MASK).load(Ordering::Relaxed), 7);
+        assert_eq!(
+            ring.rings.atomic(CQ_RING_ENTRIES).load(Ordering::Relaxed),
+            16
+        );
+        // Both rings fit into the one mapping.
+        assert!(params.sq_off.array as usize + 8 * 4 <= ring.rings.size());
+        assert!(params.cq_off.cqes as usize + 16 * 16 <= params.sq_off.array as usize);
+    }
+
+    #[def_test]
+    fn test_user_data_round_trips() {
+        let ring = IoUring::new(4, 8).unwrap();
+        for user_data in [1, u64::MAX, 0xdead_beef] {
+            push_sqe(&ring, nop(user_data));
+        }
+        let submitted = ring.submit(u32::MAX, |_| Issued::Done(0)).unwrap();
+        assert_eq!(submitted, 3);
+        assert_eq!(ring.completions(), 3);
+        assert!(ring.poll().contains(IoEvents::IN));
+        for user_data in [1, u64::MAX, 0xdead_beef] {
+            assert_eq!(
+                pop_cqe(&ring),
+                Some(IoUringCqe {
+                    user_data,
+                    res: 0,
+                    flags: 0
+                })
+            );
+        }
+        assert_eq!(pop_cqe(&ring), None);
+    }
+
+    #[def_test]
+    fn test_invalid_index_dropped() {
+        let ring = IoUring::new(4, 8).unwrap();
+        push_sqe(&ring, nop(1));
+        let array = IoUring::sq_array(ring.cq_entries);
+        unsafe { ring.rings.at::<u32>(array).write(99) };
+        assert_eq!(ring.submit(1, |_| Issued::Done(0)).unwrap(), 0);
+        assert_eq!(ring.rings.atomic(SQ_DROPPED).load(Ordering::Relaxed), 1);
+        assert_eq!(ring.completions(), 0);
+    }
+
+    #[def_test]
+    fn test_completions_kept_back_when_full() {
+        let ring = IoUring::new(4, 4).unwrap();
+        for user_data in 0..4 {
+            push_sqe(&ring, nop(user_data));
+        }
+        assert_eq!(ring.submit(4, |_| Issued::Done(0)).unwrap(), 4);
+        for user_data in 4..6 {
+            push_sqe(&ring, nop(user_data));
+        }
+        assert_eq!(ring.submit(4, |_| Issued::Done(0)).unwrap(), 2);
+        assert_ne!(
+            ring.rings.atomic(SQ_FLAGS).load(Ordering::Relaxed) & IORING_SQ_CQ_OVERFLOW,
+            0
+        );
+        push_sqe(&ring, nop(6));
+        assert_eq!(
+            ring.submit(1, |_| Issued::Done(0)),
+            Err(KError::ResourceBusy)
+        );
+        // Nothing is lost and the order is kept.
+        let mut seen = Vec::new();
+        while let Some(cqe) = pop_cqe(&ring) {
+            seen.push(cqe.user_data);
+            ring.reap(|_| Issued::Done(0));
+        }
+        assert_eq!(seen, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(ring.rings.atomic(SQ_FLAGS).load(Ordering::Relaxed), 0);
+        assert_eq!(ring.submit(1, |_| Issued::Done(0)).unwrap(), 1);
+    }
+}
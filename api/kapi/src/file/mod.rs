@@ -4,21 +4,25 @@
 
 //! File descriptor abstractions and file-like traits.
 
+mod dmabuf;
 pub mod epoll;
 pub mod event;
 mod fs;
+pub mod inotify;
+pub mod io_uring;
 mod net;
+pub mod perf;
 mod pidfd;
 mod pipe;
 pub mod signalfd;
+mod table;
 
-use alloc::{borrow::Cow, sync::Arc};
+use alloc::{borrow::Cow, sync::Arc, vec::Vec};
 use core::{ffi::c_int, time::Duration};
 
 use downcast_rs::{DowncastSync, impl_downcast};
-use flatten_objects::FlattenObjects;
 use fs_ng_vfs::DeviceId;
-use kcore::{resources::FILE_LIMIT, task::AsThread};
+use kcore::task::AsThread;
 use kerrno::{KError, KResult};
 use kfs::{FS_CONTEXT, OpenOptions};
 use kio::prelude::*;
@@ -28,10 +32,15 @@ use ktask::current;
 use linux_raw_sys::general::{RLIMIT_NOFILE, stat, statx, statx_timestamp};
 
 pub use self::{
-    fs::{Directory, File, ResolveAtResult, metadata_to_kstat, resolve_at, with_fs},
+    dmabuf::DmaBufFile,
+    fs::{
+        Directory, File, ResolveAtResult, fd_location, lock_entry, metadata_to_kstat,
+        open_file_owner, resolve_at, update_fd_metadata, with_fs,
+    },
     net::Socket,
     pidfd::PidFd,
     pipe::Pipe,
+    table::FdTable,
 };
 
 /// Kernel stat structure containing file metadata.
@@ -231,7 +240,7 @@ pub struct FileDescriptor {
 
 scope_local::scope_local! {
     /// The current file descriptor table.
-    pub static FD_TABLE: Arc<RwLock<FlattenObjects<FileDescriptor, { FILE_LIMIT }>>> = Arc::default();
+    pub static FD_TABLE: Arc<RwLock<FdTable<FileDescriptor>>> = Arc::default();
 }
 
 /// Retrieves a file-like object from the file descriptor table.
@@ -258,13 +267,26 @@ pub fn get_file_like(fd: c_int) -> KResult<Arc<dyn FileLike>> {
 /// # Returns
 /// The new file descriptor number, or an error if the table is full.
 pub fn add_file_like(f: Arc<dyn FileLike>, cloexec: bool) -> KResult<c_int> {
+    add_file_like_from(f, 0, cloexec)
+}
+
+/// Adds a file-like object to the current process's file descriptor table,
+/// at the lowest free descriptor not below `min_fd`, as `F_DUPFD` does.
+///
+/// Fails with `EINVAL` if `min_fd` is not below `RLIMIT_NOFILE`.
+pub fn add_file_like_from(f: Arc<dyn FileLike>, min_fd: usize, cloexec: bool) -> KResult<c_int> {
     let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    if min_fd as u64 >= max_nofile {
+        return Err(KError::InvalidInput);
+    }
     let mut table = FD_TABLE.write();
     if table.count() as u64 >= max_nofile {
         return Err(KError::TooManyOpenFiles);
     }
     let fd = FileDescriptor { inner: f, cloexec };
-    Ok(table.add(fd).map_err(|_| KError::TooManyOpenFiles)? as c_int)
+    Ok(table
+        .add_from(min_fd, fd)
+        .map_err(|_| KError::TooManyOpenFiles)? as c_int)
 }
 
 /// Closes a file descriptor and removes it from the file descriptor table.
@@ -276,11 +298,51 @@ pub fn close_file_like(fd: c_int) -> KResult {
         .write()
         .remove(fd as usize)
         .ok_or(KError::BadFileDescriptor)?;
+    release_record_locks(f.inner.as_ref());
     debug!("close_file_like <= count: {}", Arc::strong_count(&f.inner));
     Ok(())
 }
 
-pub fn add_stdio(fd_table: &mut FlattenObjects<FileDescriptor, { FILE_LIMIT }>) -> KResult<()> {
+/// Gives the current process a table of its own if it shares its file
+/// descriptor table with others (`CLONE_FILES`), so that closing descriptors
+/// does not close theirs.
+pub fn unshare_fd_table() {
+    let curr = current();
+    let mut scope = curr.as_thread().proc_data.scope.write();
+    let mut table = FD_TABLE.scope_mut(&mut scope);
+    if Arc::strong_count(&table) > 1 {
+        let copy = table.read().clone();
+        *table = Arc::new(RwLock::new(copy));
+    }
+}
+
+/// Closes the descriptors of `table` flagged close-on-exec, as `execve`
+/// does before running the new program.
+pub fn close_on_exec(table: &mut FdTable<FileDescriptor>) {
+    let cloexec = table
+        .iter()
+        .filter(|(_, f)| f.cloexec)
+        .map(|(fd, _)| fd)
+        .collect::<Vec<_>>();
+    for fd in cloexec {
+        if let Some(f) = table.remove(fd) {
+            release_record_locks(f.inner.as_ref());
+        }
+    }
+}
+
+/// Drops the record locks the current process holds on the file behind `f`.
+///
+/// POSIX locks go away when their process closes any descriptor of the file,
+/// not only the one they were taken through.
+pub fn release_record_locks(f: &dyn FileLike) {
+    if let Some(entry) = lock_entry(f) {
+        let pid = current().as_thread().proc_data.proc.pid();
+        entry.release_record_locks(pid as _);
+    }
+}
+
+pub fn add_stdio(fd_table: &mut FdTable<FileDescriptor>) -> KResult<()> {
     assert_eq!(fd_table.count(), 0);
     let cx = FS_CONTEXT.lock();
     let open = |options: &mut OpenOptions| {
@@ -312,3 +374,32 @@ pub fn add_stdio(fd_table: &mut FlattenObjects<FileDescriptor, { FILE_LIMIT }>)
 
     Ok(())
 }
+
+#[cfg(unittest)]
+mod tests_cloexec {
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    fn descriptor(f: impl FileLike, cloexec: bool) -> FileDescriptor {
+        FileDescriptor {
+            inner: Arc::new(f),
+            cloexec,
+        }
+    }
+
+    #[def_test]
+    fn test_close_on_exec() {
+        let (read_end, write_end) = Pipe::new();
+        let mut table = FdTable::new();
+        table.add_at(3, descriptor(read_end, false)).ok().unwrap();
+        table.add_at(4, descriptor(write_end, true)).ok().unwrap();
+
+        close_on_exec(&mut table);
+        // The write end is gone, the read end is kept and sees it closed.
+        assert!(table.get(4).is_none());
+        let read_end = table.get(3).unwrap().inner.clone();
+        assert!(read_end.downcast_arc::<Pipe>().ok().unwrap().closed());
+        assert_eq!(table.count(), 1);
+    }
+}
@@ -7,17 +7,125 @@
 use alloc::{borrow::Cow, format, sync::Arc};
 use core::{ffi::c_int, ops::Deref, task::Context};
 
+use bytemuck::{Pod, Zeroable};
 use kerrno::{KError, KResult};
 use knet::{
     SocketOps,
+    moderation::{Coalesce, ModerationConfig},
     options::{Configurable, GetSocketOption, SetSocketOption},
 };
 use kpoll::{IoEvents, Pollable};
 use linux_raw_sys::general::S_IFSOCK;
+use osvm::{VirtMutPtr, VirtPtr};
 
 use super::{FileLike, Kstat};
 use crate::file::{IoDst, IoSrc, get_file_like};
 
+/// `ioctl` on a socket to query or configure a network interface.
+const SIOCETHTOOL: u32 = 0x8946;
+/// Length of `ifr_name` in `struct ifreq`.
+const IFNAMSIZ: usize = 16;
+/// `ethtool` commands.
+const ETHTOOL_GCOALESCE: u32 = 0x0e;
+const ETHTOOL_SCOALESCE: u32 = 0x0f;
+
+/// Linux's `struct ethtool_coalesce`.
+///
+/// Only the fields up to `use_adaptive_rx_coalesce` are supported; the
+/// others must be left zero when set.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+struct EthtoolCoalesce {
+    cmd: u32,
+    rx_coalesce_usecs: u32,
+    rx_max_coalesced_frames: u32,
+    rx_coalesce_usecs_irq: u32,
+    rx_max_coalesced_frames_irq: u32,
+    tx_coalesce_usecs: u32,
+    tx_max_coalesced_frames: u32,
+    tx_coalesce_usecs_irq: u32,
+    tx_max_coalesced_frames_irq: u32,
+    stats_block_coalesce_usecs: u32,
+    use_adaptive_rx_coalesce: u32,
+    use_adaptive_tx_coalesce: u32,
+    pkt_rate_low: u32,
+    rx_coalesce_usecs_low: u32,
+    rx_max_coalesced_frames_low: u32,
+    tx_coalesce_usecs_low: u32,
+    tx_max_coalesced_frames_low: u32,
+    pkt_rate_high: u32,
+    rx_coalesce_usecs_high: u32,
+    rx_max_coalesced_frames_high: u32,
+    tx_coalesce_usecs_high: u32,
+    tx_max_coalesced_frames_high: u32,
+    rate_sample_interval: u32,
+}
+
+impl EthtoolCoalesce {
+    fn new(config: ModerationConfig) -> Self {
+        let Coalesce {
+            rx_usecs,
+            rx_max_frames,
+            tx_usecs,
+            tx_max_frames,
+        } = config.coalesce;
+        Self {
+            cmd: ETHTOOL_GCOALESCE,
+            rx_coalesce_usecs: rx_usecs,
+            rx_max_coalesced_frames: rx_max_frames,
+            tx_coalesce_usecs: tx_usecs,
+            tx_max_coalesced_frames: tx_max_frames,
+            use_adaptive_rx_coalesce: config.adaptive_rx as u32,
+            ..Default::default()
+        }
+    }
+
+    fn config(&self) -> KResult<ModerationConfig> {
+        let supported = Self {
+            cmd: self.cmd,
+            rx_coalesce_usecs: self.rx_coalesce_usecs,
+            rx_max_coalesced_frames: self.rx_max_coalesced_frames,
+            tx_coalesce_usecs: self.tx_coalesce_usecs,
+            tx_max_coalesced_frames: self.tx_max_coalesced_frames,
+            use_adaptive_rx_coalesce: self.use_adaptive_rx_coalesce,
+            ..Default::default()
+        };
+        if bytemuck::bytes_of(self) != bytemuck::bytes_of(&supported) {
+            return Err(KError::OperationNotSupported);
+        }
+        Ok(ModerationConfig {
+            coalesce: Coalesce {
+                rx_usecs: self.rx_coalesce_usecs,
+                rx_max_frames: self.rx_max_coalesced_frames,
+                tx_usecs: self.tx_coalesce_usecs,
+                tx_max_frames: self.tx_max_coalesced_frames,
+            },
+            adaptive_rx: self.use_adaptive_rx_coalesce != 0,
+        })
+    }
+}
+
+/// Handles `SIOCETHTOOL` with the `struct ifreq` at `arg`.
+fn ethtool(arg: usize) -> KResult<usize> {
+    let name = (arg as *const [u8; IFNAMSIZ]).read_vm()?;
+    let len = name.iter().position(|&b| b == 0).unwrap_or(IFNAMSIZ);
+    let name = str::from_utf8(&name[..len]).map_err(|_| KError::NoSuchDevice)?;
+    let data = ((arg + IFNAMSIZ) as *const usize).read_vm()?;
+    let cmd = (data as *const u32).read_vm()?;
+    match cmd {
+        ETHTOOL_GCOALESCE => {
+            let coalesce = EthtoolCoalesce::new(knet::moderation(name)?);
+            (data as *mut EthtoolCoalesce).write_vm(coalesce)?;
+        }
+        ETHTOOL_SCOALESCE => {
+            let coalesce = (data as *const EthtoolCoalesce).read_vm()?;
+            knet::set_moderation(name, coalesce.config()?)?;
+        }
+        _ => return Err(KError::OperationNotSupported),
+    }
+    Ok(0)
+}
+
 /// Socket wrapper providing file-like interface for network sockets.
 ///
 /// This struct wraps the underlying kernel network socket and implements
@@ -69,6 +177,14 @@ impl FileLike for Socket {
             .set_option(SetSocketOption::NonBlocking(&nonblocking))
     }
 
+    /// Handles the network interface ioctls.
+    fn ioctl(&self, cmd: u32, arg: usize) -> KResult<usize> {
+        match cmd {
+            SIOCETHTOOL => ethtool(arg),
+            _ => Err(KError::NotATty),
+        }
+    }
+
     /// Returns a string representation of the socket address.
     fn path(&self) -> Cow<'_, str> {
         format!("socket:[{}]", self as *const _ as usize).into()
@@ -107,4 +223,22 @@ mod socket_tests {
     fn test_socket_mode_constant() {
         assert_eq!(S_IFSOCK, 0o140000);
     }
+
+    /// `struct ethtool_coalesce` round-trips the supported fields and refuses
+    /// the others.
+    #[def_test]
+    fn test_ethtool_coalesce() {
+        assert_eq!(size_of::<EthtoolCoalesce>(), 92);
+        let config = ModerationConfig {
+            coalesce: Coalesce {
+                rx_usecs: 64,
+                ..Default::default()
+            },
+            adaptive_rx: false,
+        };
+        let mut coalesce = EthtoolCoalesce::new(config);
+        assert_eq!(coalesce.config(), Ok(config));
+        coalesce.pkt_rate_high = 1;
+        assert_eq!(coalesce.config(), Err(KError::OperationNotSupported));
+    }
 }
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! File descriptors of hardware performance events.
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::task::Context;
+
+use kcore::perf::PerfEvent;
+use kerrno::{KError, KResult};
+use kpoll::{IoEvents, Pollable};
+use osvm::VirtMutPtr;
+
+use crate::file::{FileLike, IoDst};
+
+const PERF_EVENT_IOC_ENABLE: u32 = 0x2400;
+const PERF_EVENT_IOC_DISABLE: u32 = 0x2401;
+const PERF_EVENT_IOC_RESET: u32 = 0x2403;
+/// `_IOR('$', 7, u64)`.
+const PERF_EVENT_IOC_ID: u32 = 0x8008_2407;
+
+const PERF_IOC_FLAG_GROUP: usize = 1 << 0;
+
+/// Read how long the event was enabled.
+pub const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+/// Read how long the event was counting.
+pub const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+/// Read the IDs of events.
+pub const PERF_FORMAT_ID: u64 = 1 << 2;
+/// Read the whole group of a leader at once.
+pub const PERF_FORMAT_GROUP: u64 = 1 << 3;
+
+/// A file descriptor of a [`PerfEvent`], which closes the event when the
+/// last reference is gone.
+///
+/// Reading it returns the count laid out as `read_format` asks.
+pub struct PerfEventFile {
+    event: PerfEvent,
+    read_format: u64,
+}
+
+impl PerfEventFile {
+    /// Wraps `event`, read as `read_format`.
+    pub fn new(event: PerfEvent, read_format: u64) -> Self {
+        Self { event, read_format }
+    }
+
+    /// Returns the event.
+    pub fn event(&self) -> &PerfEvent {
+        &self.event
+    }
+
+    /// Lays out the counts of the event, or of its group with
+    /// `PERF_FORMAT_GROUP`.
+    fn values(&self) -> Vec<u64> {
+        let format = self.read_format;
+        let counts = if format & PERF_FORMAT_GROUP != 0 {
+            self.event.read_group().unwrap_or_default()
+        } else {
+            alloc::vec![(self.event.id(), self.event.read())]
+        };
+        let Some((_, first)) = counts.first() else {
+            return Vec::new();
+        };
+
+        let mut values = Vec::new();
+        if format & PERF_FORMAT_GROUP != 0 {
+            values.push(counts.len() as u64);
+        } else {
+            values.push(first.value);
+        }
+        if format & PERF_FORMAT_TOTAL_TIME_ENABLED != 0 {
+            values.push(first.time_enabled);
+        }
+        if format & PERF_FORMAT_TOTAL_TIME_RUNNING != 0 {
+            values.push(first.time_running);
+        }
+        if format & PERF_FORMAT_GROUP != 0 {
+            for (id, count) in &counts {
+                values.push(count.value);
+                if format & PERF_FORMAT_ID != 0 {
+                    values.push(*id);
+                }
+            }
+        } else if format & PERF_FORMAT_ID != 0 {
+            values.push(self.event.id());
+        }
+        values
+    }
+}
+
+impl FileLike for PerfEventFile {
+    /// Reads the count, failing with `StorageFull` if `dst` cannot hold it
+    /// all.
+    fn read(&self, dst: &mut IoDst) -> KResult<usize> {
+        let values = self.values();
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        if dst.remaining_mut() < bytes.len() {
+            return Err(KError::StorageFull);
+        }
+        dst.write(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "anon_inode:[perf_event]".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> KResult<usize> {
+        let group = arg & PERF_IOC_FLAG_GROUP != 0;
+        match cmd {
+            PERF_EVENT_IOC_ENABLE => self.event.enable(group),
+            PERF_EVENT_IOC_DISABLE => self.event.disable(group),
+            PERF_EVENT_IOC_RESET => self.event.reset(group),
+            PERF_EVENT_IOC_ID => (arg as *mut u64).write_vm(self.event.id())?,
+            _ => return Err(KError::NotATty),
+        }
+        Ok(0)
+    }
+}
+
+impl Pollable for PerfEventFile {
+    /// Counting events are always readable.
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
@@ -10,7 +10,8 @@ use core::task::Context;
 
 use kcore::task::ProcessData;
 use kerrno::{KError, KResult};
-use kpoll::{IoEvents, PollSet, Pollable};
+use kpoll::{IoEvents, Pollable};
+use kprocess::Process;
 
 use crate::file::FileLike;
 
@@ -21,15 +22,15 @@ use crate::file::FileLike;
 pub struct PidFd {
     /// Weak reference to the process data to avoid keeping the process alive
     proc_data: Weak<ProcessData>,
-    /// Event notification set for process exit events
-    exit_event: Arc<PollSet>,
+    /// The process itself, kept to watch for its exit
+    proc: Arc<Process>,
 }
 impl PidFd {
     /// Creates a new process file descriptor for the given process.
     pub fn new(proc_data: &Arc<ProcessData>) -> Self {
         Self {
             proc_data: Arc::downgrade(proc_data),
-            exit_event: proc_data.exit_event.clone(),
+            proc: proc_data.proc.clone(),
         }
     }
 
@@ -39,6 +40,11 @@ impl PidFd {
     pub fn process_data(&self) -> KResult<Arc<ProcessData>> {
         self.proc_data.upgrade().ok_or(KError::NoSuchProcess)
     }
+
+    /// Returns the process, which outlives its exit.
+    pub fn process(&self) -> &Arc<Process> {
+        &self.proc
+    }
 }
 impl FileLike for PidFd {
     /// Returns the path representation of this pidfd.
@@ -48,17 +54,17 @@ impl FileLike for PidFd {
 }
 
 impl Pollable for PidFd {
-    /// Polls for readable events (set to true when process is still alive).
+    /// Polls for readable events (set once the process has exited).
     fn poll(&self) -> IoEvents {
         let mut events = IoEvents::empty();
-        events.set(IoEvents::IN, self.proc_data.strong_count() > 0);
+        events.set(IoEvents::IN, self.proc.is_zombie());
         events
     }
 
     /// Registers the pidfd for polling process exit events.
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
         if events.contains(IoEvents::IN) {
-            self.exit_event.register(context.waker());
+            self.proc.register_exit_waiter(context.waker());
         }
     }
 }
@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! The file descriptor table.
+//!
+//! Descriptors are kept in fixed-size chunks that are only allocated once one
+//! of their descriptors is used, so a process that uses a few huge descriptor
+//! numbers, e.g. after `dup2(fd, 1000000)`, does not pay for all the numbers
+//! below.
+
+use alloc::{boxed::Box, collections::BTreeMap};
+
+use kcore::resources::NR_OPEN;
+
+/// Number of descriptors per chunk, one bit of [`Chunk::used`] each.
+const CHUNK_SIZE: usize = u64::BITS as usize;
+
+#[derive(Clone)]
+struct Chunk<T> {
+    used: u64,
+    slots: [Option<T>; CHUNK_SIZE],
+}
+
+impl<T> Chunk<T> {
+    fn new() -> Box<Self> {
+        Box::new(Self {
+            used: 0,
+            slots: core::array::from_fn(|_| None),
+        })
+    }
+}
+
+/// A sparse table of objects indexed by file descriptor numbers below
+/// [`NR_OPEN`].
+#[derive(Clone)]
+pub struct FdTable<T> {
+    chunks: BTreeMap<usize, Box<Chunk<T>>>,
+    count: usize,
+    /// No descriptor below this one is free.
+    next_free: usize,
+}
+
+impl<T> FdTable<T> {
+    /// Creates an empty table.
+    pub const fn new() -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            count: 0,
+            next_free: 0,
+        }
+    }
+
+    /// Returns the number of descriptors in use.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the object at `fd`.
+    pub fn get(&self, fd: usize) -> Option<&T> {
+        self.chunks.get(&(fd / CHUNK_SIZE))?.slots[fd % CHUNK_SIZE].as_ref()
+    }
+
+    /// Returns the object at `fd` mutably.
+    pub fn get_mut(&mut self, fd: usize) -> Option<&mut T> {
+        self.chunks.get_mut(&(fd / CHUNK_SIZE))?.slots[fd % CHUNK_SIZE].as_mut()
+    }
+
+    /// Adds `value` at the lowest free descriptor and returns it.
+    ///
+    /// Gives `value` back if the table is full.
+    pub fn add(&mut self, value: T) -> Result<usize, T> {
+        self.add_from(0, value)
+    }
+
+    /// Adds `value` at the lowest free descriptor not below `from` and
+    /// returns it, as `F_DUPFD` does.
+    ///
+    /// Gives `value` back if no descriptor from `from` on is free.
+    pub fn add_from(&mut self, from: usize, value: T) -> Result<usize, T> {
+        let fd = self.lowest_free_from(from.max(self.next_free));
+        self.add_at(fd, value)?;
+        if from <= self.next_free {
+            self.next_free = fd + 1;
+        }
+        Ok(fd)
+    }
+
+    /// Adds `value` at `fd`.
+    ///
+    /// Gives `value` back if `fd` is in use or not below [`NR_OPEN`].
+    pub fn add_at(&mut self, fd: usize, value: T) -> Result<usize, T> {
+        if fd >= NR_OPEN {
+            return Err(value);
+        }
+        let chunk = self
+            .chunks
+            .entry(fd / CHUNK_SIZE)
+            .or_insert_with(Chunk::new);
+        let bit = 1 << (fd % CHUNK_SIZE);
+        if chunk.used & bit != 0 {
+            return Err(value);
+        }
+        chunk.used |= bit;
+        chunk.slots[fd % CHUNK_SIZE] = Some(value);
+        self.count += 1;
+        Ok(fd)
+    }
+
+    /// Removes and returns the object at `fd`.
+    pub fn remove(&mut self, fd: usize) -> Option<T> {
+        let index = fd / CHUNK_SIZE;
+        let chunk = self.chunks.get_mut(&index)?;
+        let value = chunk.slots[fd % CHUNK_SIZE].take()?;
+        chunk.used &= !(1 << (fd % CHUNK_SIZE));
+        if chunk.used == 0 {
+            self.chunks.remove(&index);
+        }
+        self.count -= 1;
+        self.next_free = self.next_free.min(fd);
+        Some(value)
+    }
+
+    /// Returns the descriptors in use, in ascending order.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.chunks.iter().flat_map(|(&index, chunk)| {
+            let used = chunk.used;
+            (0..CHUNK_SIZE)
+                .filter(move |bit| used & (1 << bit) != 0)
+                .map(move |bit| index * CHUNK_SIZE + bit)
+        })
+    }
+
+    /// Returns the descriptors in use with their objects, in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (usize, &T)> + '_ {
+        self.chunks.iter().flat_map(|(&index, chunk)| {
+            chunk
+                .slots
+                .iter()
+                .enumerate()
+                .filter_map(move |(bit, slot)| Some((index * CHUNK_SIZE + bit, slot.as_ref()?)))
+        })
+    }
+
+    /// Returns the lowest free descriptor not below `from`.
+    ///
+    /// Full chunks are skipped one bitmap at a time.
+    fn lowest_free_from(&self, from: usize) -> usize {
+        let mut fd = from;
+        for (&index, chunk) in self.chunks.range(from / CHUNK_SIZE..) {
+            if index * CHUNK_SIZE > fd {
+                // `fd` lies in a chunk that was never allocated.
+                break;
+            }
+            let free = !chunk.used & (u64::MAX << (fd % CHUNK_SIZE));
+            if free != 0 {
+                return index * CHUNK_SIZE + free.trailing_zeros() as usize;
+            }
+            fd = (index + 1) * CHUNK_SIZE;
+        }
+        fd
+    }
+}
+
+impl<T> Default for FdTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(unittest)]
+mod table_tests {
+    use alloc::vec::Vec;
+
+    use unittest::def_test;
+
+    use super::*;
+
+    #[def_test]
+    fn test_add_uses_lowest_free() {
+        let mut table = FdTable::new();
+        for i in 0..200 {
+            assert_eq!(table.add(i), Ok(i));
+        }
+        assert_eq!(table.remove(70), Some(70));
+        assert_eq!(table.remove(3), Some(3));
+        assert_eq!(table.add(1000), Ok(3));
+        assert_eq!(table.add(1001), Ok(70));
+        assert_eq!(table.add(1002), Ok(200));
+        assert_eq!(table.count(), 201);
+        assert_eq!(table.get(70), Some(&1001));
+    }
+
+    #[def_test]
+    fn test_sparse_descriptors() {
+        let mut table = FdTable::new();
+        assert_eq!(table.add_at(NR_OPEN - 1, 'a'), Ok(NR_OPEN - 1));
+        assert_eq!(table.add_at(NR_OPEN - 1, 'b'), Err('b'));
+        assert_eq!(table.add_at(NR_OPEN, 'c'), Err('c'));
+        // Only the chunk of the descriptor was allocated.
+        assert_eq!(table.chunks.len(), 1);
+
+        assert_eq!(table.add('d'), Ok(0));
+        assert_eq!(table.ids().collect::<Vec<_>>(), [0, NR_OPEN - 1]);
+        assert_eq!(table.ids().next_back(), Some(NR_OPEN - 1));
+
+        assert_eq!(
+            table.iter().collect::<Vec<_>>(),
+            [(0, &'d'), (NR_OPEN - 1, &'a')]
+        );
+        assert_eq!(table.iter().next_back(), Some((NR_OPEN - 1, &'a')));
+
+        assert_eq!(table.remove(NR_OPEN - 1), Some('a'));
+        assert_eq!(table.remove(NR_OPEN - 1), None);
+        assert_eq!(table.chunks.len(), 1);
+        assert_eq!(table.count(), 1);
+    }
+
+    #[def_test]
+    fn test_add_from() {
+        let mut table = FdTable::new();
+        for i in 0..3 {
+            assert_eq!(table.add(i), Ok(i));
+        }
+        assert_eq!(table.add_from(10, 10), Ok(10));
+        assert_eq!(table.add_from(10, 11), Ok(11));
+        // Asked from below the first free descriptor: that one is taken.
+        assert_eq!(table.add_from(1, 3), Ok(3));
+        assert_eq!(table.add(4), Ok(4));
+        assert_eq!(table.remove(1), Some(1));
+        assert_eq!(table.add_from(0, 1), Ok(1));
+        assert_eq!(table.add_from(NR_OPEN - 1, 5), Ok(NR_OPEN - 1));
+        assert_eq!(table.add_from(NR_OPEN - 1, 6), Err(6));
+        assert_eq!(table.add(5), Ok(5));
+    }
+}
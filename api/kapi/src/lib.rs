@@ -27,18 +27,20 @@ pub mod task;
 pub mod tee;
 pub mod terminal;
 pub mod time;
+pub mod trace;
 pub mod vfs;
 
-/// Initializes VFS, /proc/interrupts accounting, and alarm task.
+/// Initializes VFS, the alarm task, the flight recorder and the filesystem
+/// scrubber.
 pub fn init() {
     info!("Initialize VFS...");
     vfs::mount_all().expect("Failed to mount vfs");
 
-    info!("Initialize /proc/interrupts...");
-    ktask::register_timer_callback(|_| {
-        time::inc_irq_cnt();
-    });
-
     info!("Initialize alarm...");
     kcore::time::spawn_alarm_task();
+
+    trace::start_flight_recorder();
+
+    #[cfg(feature = "ext4-scrub")]
+    kfs::scrub::start_scrubber();
 }
@@ -13,6 +13,7 @@ use core::{
     ptr, slice, str,
 };
 
+use fs_ng_vfs::path::{MAX_PATH_LEN, verify_path};
 use kcore::{mm::access_user_memory, task::AsThread};
 use kerrno::{KError, KResult};
 use khal::{
@@ -22,7 +23,7 @@ use khal::{
 use kio::prelude::*;
 use ktask::current;
 use memaddr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
-use osvm::{load_vec, load_vec_until_null, read_vm_mem, write_vm_mem};
+use osvm::{load_cstr, load_vec, load_vec_until_null, read_vm_mem, write_vm_mem};
 
 /// Validate a user memory region and populate pages if needed.
 fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) -> KResult<()> {
@@ -282,12 +283,42 @@ pub fn vm_load_string(ptr: *const c_char) -> KResult<String> {
 }
 
 /// Load a string with specified length from user virtual memory
+///
+/// The string may not hold a NUL, which would cut it short for whatever
+/// takes it as a C string.
 pub fn vm_load_string_with_len(ptr: *const c_char, len: usize) -> KResult<String> {
     #[allow(clippy::unnecessary_cast)]
     let bytes = load_vec(ptr as *const u8, len)?;
+    if bytes.contains(&0) {
+        return Err(KError::InvalidInput);
+    }
     String::from_utf8(bytes).map_err(|_| KError::IllegalBytes)
 }
 
+/// Load a path from user virtual memory, checked as every path taken by a
+/// system call is before it is resolved.
+///
+/// A path or a name in it that is too long fails with `ENAMETOOLONG` (see
+/// [`verify_path`]), and an empty path with `ENOENT`. To user space names are
+/// bytes, but the VFS names entries with `str`: a path that is not UTF-8
+/// fails here with `EILSEQ`, rather than reaching a filesystem mangled.
+pub fn vm_load_path(ptr: *const c_char) -> KResult<String> {
+    let path = vm_load_path_allow_empty(ptr)?;
+    if path.is_empty() {
+        return Err(KError::NotFound);
+    }
+    Ok(path)
+}
+
+/// Load a path like [`vm_load_path`], but let it be empty, for system calls
+/// taking `AT_EMPTY_PATH`.
+pub fn vm_load_path_allow_empty(ptr: *const c_char) -> KResult<String> {
+    #[allow(clippy::unnecessary_cast)]
+    let path = load_cstr(ptr as *const u8, MAX_PATH_LEN)?.into_bytes();
+    verify_path(&path)?;
+    String::from_utf8(path).map_err(|_| KError::IllegalBytes)
+}
+
 /// A read-only buffer in the VM's memory.
 ///
 /// It implements the `kio::Read` trait, allowing it to be used with other I/O
@@ -4,13 +4,21 @@
 
 //! Signal checking helpers and blocked set management.
 
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
 
-use kcore::task::{AsThread, Thread};
+use kcore::task::{AsThread, Thread, send_signal_to_process};
 use kerrno::KResult;
 use khal::uspace::UserContext;
-use ksignal::{SignalOSAction, SignalSet};
-use ktask::current;
+use ksignal::{SignalInfo, SignalOSAction, SignalSet, Signo};
+use ktask::{
+    current,
+    future::{block_on, interruptible},
+};
+use linux_raw_sys::general::CLD_STOPPED;
 
 use crate::task::do_exit;
 
@@ -25,6 +33,20 @@ pub fn check_signals(
     };
 
     let signo = sig.signo();
+    let action = match os_action {
+        SignalOSAction::Handler => 0,
+        SignalOSAction::Terminate => 1,
+        SignalOSAction::CoreDump => 2,
+        SignalOSAction::Stop => 3,
+        SignalOSAction::Continue => 4,
+    };
+    flightrec::record(
+        current().id().as_u64(),
+        flightrec::Event::Signal {
+            signo: signo as u32,
+            action,
+        },
+    );
     match os_action {
         SignalOSAction::Terminate => {
             do_exit(signo as i32, true);
@@ -33,12 +55,9 @@ pub fn check_signals(
             // TODO: implement core dump
             do_exit(128 + signo as i32, true);
         }
-        SignalOSAction::Stop => {
-            // TODO: implement stop
-            do_exit(1, true);
-        }
+        SignalOSAction::Stop => stop_current(thr, signo),
         SignalOSAction::Continue => {
-            // TODO: implement continue
+            // The group was resumed when the signal was sent.
         }
         SignalOSAction::Handler => {
             // do nothing
@@ -47,6 +66,38 @@ pub fn check_signals(
     true
 }
 
+/// Stops the process of the current thread `thr` for the stop signal `signo`
+/// until `SIGCONT` resumes it or `SIGKILL` ends it.
+///
+/// The first thread to stop reports the stop to the parent; the others of
+/// the group stop as they check their signals, `SIGSTOP` interrupting them.
+fn stop_current(thr: &Thread, signo: Signo) {
+    let proc = &thr.proc_data.proc;
+    if proc.stop(signo as u32)
+        && let Some(parent) = proc.parent()
+    {
+        let sig = SignalInfo::new_child(CLD_STOPPED as _, proc.pid(), signo as _);
+        let _ = send_signal_to_process(parent.pid(), Some(sig));
+    }
+
+    let curr = current();
+    loop {
+        let resumed = block_on(interruptible(poll_fn(|cx| {
+            proc.register_exit_waiter(cx.waker());
+            if proc.is_stopped() {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })));
+        // Other signals wait for the group to be resumed.
+        if resumed.is_ok() || thr.signal.pending().has(Signo::SIGKILL) || thr.pending_exit() {
+            break;
+        }
+        curr.clear_interrupt();
+    }
+}
+
 static BLOCK_NEXT_SIGNAL_CHECK: AtomicBool = AtomicBool::new(false);
 
 /// Block the next signal check from executing.
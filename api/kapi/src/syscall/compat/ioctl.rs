@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! `ioctl` of 32-bit programs.
+//!
+//! Only the commands known to take the same argument as the native ones, or
+//! converted here, reach the device: an unknown command may take a structure
+//! with pointers or `long`s the device would misread.
+
+use kerrno::{KError, KResult};
+use osvm::{VirtMutPtr, VirtPtr};
+
+use super::Scratch;
+use crate::{
+    syscall::sys_ioctl,
+    vfs::dev::{FixScreenInfo, FixScreenInfo32},
+};
+
+/// `FBIOGET_FSCREENINFO`, whose structure has addresses.
+const FBIOGET_FSCREENINFO: u32 = 0x4602;
+
+/// Returns whether the command `cmd` takes the same argument for 32-bit
+/// programs.
+fn same_layout(cmd: u32) -> bool {
+    match cmd {
+        // The tty commands, `_IO('T', nr)`, take integers, `struct termios`
+        // or `struct winsize`, and the `FIO*` ones integers.
+        0x5401..=0x5460 => true,
+        // TIOCGPTN, TIOCSPTLCK, TIOCGPTPEER
+        0x8004_5430 | 0x4004_5431 | 0x5441 => true,
+        // FBIOGET_VSCREENINFO and FBIOPUT_VSCREENINFO take only `u32`s;
+        // FBIOGETCMAP, FBIOPUTCMAP, FBIOPAN_DISPLAY and FBIOBLANK do not
+        // read their argument.
+        0x4600 | 0x4601 | 0x4604 | 0x4605 | 0x4606 | 0x4611 => true,
+        // FBIO_PRESENT_DMABUF takes a file descriptor, DMA_BUF_IOCTL_SYNC a
+        // `u64` and VCAPTURE_IOC_CAPTURE only 32-bit integers.
+        0x46f0 | 0x4008_6200 | 0xc010_56c0 => true,
+        _ => false,
+    }
+}
+
+/// Runs `ioctl(fd, cmd, arg)` for a 32-bit program.
+pub(super) fn compat_ioctl(scratch: &mut Scratch, fd: i32, cmd: u32, arg: usize) -> KResult<isize> {
+    match cmd {
+        FBIOGET_FSCREENINFO => {
+            let info = scratch.alloc::<FixScreenInfo>()?;
+            sys_ioctl(fd, cmd, info as usize)?;
+            // SAFETY: the structure is made of integers.
+            let info = unsafe { info.read_uninit()?.assume_init() };
+            (arg as *mut FixScreenInfo32).write_vm(FixScreenInfo32::from(info))?;
+            Ok(0)
+        }
+        _ if same_layout(cmd) => sys_ioctl(fd, cmd, arg),
+        _ => {
+            warn!("Unsupported compat ioctl: fd {fd}, cmd {cmd:#x}");
+            Err(KError::NotATty)
+        }
+    }
+}
@@ -0,0 +1,816 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Syscalls of 32-bit ARM (AArch32) programs.
+//!
+//! They follow the ARM EABI: the syscall number is in `r7`, and up to seven
+//! arguments are in `r0`-`r6`, 64-bit ones in an even/odd register pair.
+//! The numbers differ from the native ones, see [`native_sysno`].
+//!
+//! - Syscalls whose arguments have the same layout on both run the native
+//!   handler through the same tracing and fault injection as native calls.
+//! - The others are converted here, see [`handle_compat_syscall`]. The
+//!   native copies of their structures are put in [`Scratch`] space below
+//!   the user stack pointer, and the results converted back.
+//!
+//! Unknown syscalls fail with `ENOSYS` and are logged with their number,
+//! which together with the `SYS_ENTER` tracepoint tells which calls a
+//! program makes.
+
+mod ioctl;
+mod types;
+
+use alloc::vec::Vec;
+use core::ptr::{null, null_mut};
+
+use ksignal::{
+    SignalInfo, SignalSet, SignalStack,
+    arch::{CompatSignalInfo, CompatSignalStack},
+};
+use linux_raw_sys::general::{
+    AT_FDCWD, AT_REMOVEDIR, CLONE_VFORK, CLONE_VM, O_CREAT, O_TRUNC, O_WRONLY, SIGCHLD,
+    kernel_sigaction, pollfd, rlimit64, rusage, siginfo, stat, statfs, sysinfo, timespec, timeval,
+};
+use osvm::{IoVec, VirtMutPtr, VirtPtr, load_vec, load_vec_until_null, write_vm_mem};
+
+use self::{ioctl::compat_ioctl, types::*};
+use super::*;
+use crate::mm::{UserConstPtr, UserPtr};
+
+/// Numbers of the AArch32 syscalls converted by [`handle_compat_syscall`].
+mod nr {
+    pub const FORK: u32 = 2;
+    pub const OPEN: u32 = 5;
+    pub const CREAT: u32 = 8;
+    pub const LINK: u32 = 9;
+    pub const UNLINK: u32 = 10;
+    pub const EXECVE: u32 = 11;
+    pub const MKNOD: u32 = 14;
+    pub const CHMOD: u32 = 15;
+    pub const LSEEK: u32 = 19;
+    pub const ACCESS: u32 = 33;
+    pub const RENAME: u32 = 38;
+    pub const MKDIR: u32 = 39;
+    pub const RMDIR: u32 = 40;
+    pub const PIPE: u32 = 42;
+    pub const IOCTL: u32 = 54;
+    pub const FCNTL: u32 = 55;
+    pub const DUP2: u32 = 63;
+    pub const SETRLIMIT: u32 = 75;
+    pub const GETRUSAGE: u32 = 77;
+    pub const GETTIMEOFDAY: u32 = 78;
+    pub const SYMLINK: u32 = 83;
+    pub const READLINK: u32 = 85;
+    pub const TRUNCATE: u32 = 92;
+    pub const FTRUNCATE: u32 = 93;
+    pub const WAIT4: u32 = 114;
+    pub const SYSINFO: u32 = 116;
+    pub const LLSEEK: u32 = 140;
+    pub const READV: u32 = 145;
+    pub const WRITEV: u32 = 146;
+    pub const NANOSLEEP: u32 = 162;
+    pub const POLL: u32 = 168;
+    pub const RT_SIGACTION: u32 = 174;
+    pub const RT_SIGTIMEDWAIT: u32 = 177;
+    pub const PREAD64: u32 = 180;
+    pub const PWRITE64: u32 = 181;
+    pub const SIGALTSTACK: u32 = 186;
+    pub const VFORK: u32 = 190;
+    pub const UGETRLIMIT: u32 = 191;
+    pub const MMAP2: u32 = 192;
+    pub const TRUNCATE64: u32 = 193;
+    pub const FTRUNCATE64: u32 = 194;
+    pub const STAT64: u32 = 195;
+    pub const LSTAT64: u32 = 196;
+    pub const FSTAT64: u32 = 197;
+    pub const LCHOWN32: u32 = 198;
+    pub const CHOWN32: u32 = 212;
+    pub const FCNTL64: u32 = 221;
+    pub const FUTEX: u32 = 240;
+    pub const EPOLL_CREATE: u32 = 250;
+    pub const EPOLL_WAIT: u32 = 252;
+    pub const CLOCK_SETTIME: u32 = 262;
+    pub const CLOCK_GETTIME: u32 = 263;
+    pub const CLOCK_GETRES: u32 = 264;
+    pub const CLOCK_NANOSLEEP: u32 = 265;
+    pub const STATFS64: u32 = 266;
+    pub const FSTATFS64: u32 = 267;
+    pub const ARM_FADVISE64_64: u32 = 270;
+    pub const WAITID: u32 = 280;
+    pub const FSTATAT64: u32 = 327;
+    pub const FCHMODAT: u32 = 333;
+    pub const FACCESSAT: u32 = 334;
+    pub const PPOLL: u32 = 336;
+    pub const UTIMENSAT: u32 = 348;
+    pub const FALLOCATE: u32 = 352;
+    pub const CLOCK_SETTIME64: u32 = 404;
+    pub const CLOCK_NANOSLEEP_TIME64: u32 = 407;
+    pub const UTIMENSAT_TIME64: u32 = 412;
+    pub const PPOLL_TIME64: u32 = 414;
+    pub const RT_SIGTIMEDWAIT_TIME64: u32 = 421;
+    pub const FUTEX_TIME64: u32 = 422;
+
+    /// The ARM private syscalls.
+    pub const ARM_CACHEFLUSH: u32 = 0xf_0002;
+    pub const ARM_SET_TLS: u32 = 0xf_0005;
+    pub const ARM_GET_TLS: u32 = 0xf_0006;
+}
+
+/// `fcntl64` commands locking with a `struct flock64`.
+const F_GETLK64: u32 = 12;
+const F_SETLKW64: u32 = 14;
+/// `fcntl` commands locking with a `struct flock`, whose offsets are 32-bit.
+const F_GETLK: u32 = 5;
+const F_SETLKW: u32 = 7;
+
+/// The size of the ARM EABI `struct statfs64`.
+const STATFS64_SIZE: usize = size_of::<Statfs64>();
+
+/// Space for native copies of syscall arguments, below the user stack
+/// pointer.
+///
+/// It is in user memory, so the native handlers access it as they do any
+/// user argument. It is not reserved: the program does not use the memory
+/// below its stack pointer, and the space is only used until the syscall
+/// returns.
+pub(super) struct Scratch(usize);
+
+impl Scratch {
+    fn new(uctx: &UserContext) -> Self {
+        Scratch(uctx.sp())
+    }
+
+    /// Takes space for `len` values of type `T`.
+    fn alloc_slice<T>(&mut self, len: usize) -> KResult<*mut T> {
+        let size = size_of::<T>().checked_mul(len).ok_or(KError::BadAddress)?;
+        let align = align_of::<T>().max(16);
+        self.0 = self.0.checked_sub(size).ok_or(KError::BadAddress)? & !(align - 1);
+        Ok(self.0 as *mut T)
+    }
+
+    /// Takes space for a value of type `T`.
+    pub(super) fn alloc<T>(&mut self) -> KResult<*mut T> {
+        self.alloc_slice(1)
+    }
+
+    /// Takes space for a value of type `T`, or returns null if `addr`, the
+    /// 32-bit value the native one is converted to, is null.
+    fn alloc_for<T>(&mut self, addr: usize) -> KResult<*mut T> {
+        if addr == 0 {
+            return Ok(null_mut());
+        }
+        self.alloc()
+    }
+
+    /// Copies `value` to the space.
+    fn put<T>(&mut self, value: T) -> KResult<*mut T> {
+        let ptr = self.alloc()?;
+        ptr.write_vm(value)?;
+        Ok(ptr)
+    }
+
+    /// Converts the 32-bit value of type `C` at `addr` to the space, or
+    /// returns null if `addr` is null.
+    fn convert_in<C, N: From<C>>(&mut self, addr: usize) -> KResult<*mut N> {
+        if addr == 0 {
+            return Ok(null_mut());
+        }
+        self.put(N::from(read(addr)?))
+    }
+}
+
+/// Reads a value from user memory.
+///
+/// Only used for the plain C structures of the syscall ABI.
+fn read<T>(addr: usize) -> KResult<T> {
+    // SAFETY: any bit pattern is valid for the structures of the ABI.
+    Ok(unsafe { (addr as *const T).read_uninit()?.assume_init() })
+}
+
+/// Converts the native value at `native` to the 32-bit one of type `C` at
+/// `addr`, if `addr` is not null.
+fn convert_out<N, C: From<N>>(native: *mut N, addr: usize) -> KResult<()> {
+    if addr != 0 {
+        (addr as *mut C).write_vm(C::from(read(native as usize)?))?;
+    }
+    Ok(())
+}
+
+/// Converts the native `siginfo` at `native` to the 32-bit one at `addr`.
+fn siginfo_out(native: *mut siginfo, addr: usize) -> KResult<()> {
+    if addr != 0 {
+        let info: SignalInfo = read(native as usize)?;
+        (addr as *mut CompatSignalInfo).write_vm((&info).into())?;
+    }
+    Ok(())
+}
+
+/// Joins the halves of a 64-bit argument passed in a register pair.
+fn arg64(lo: usize, hi: usize) -> i64 {
+    ((hi as u64) << 32 | lo as u32 as u64) as i64
+}
+
+/// Returns the native syscall of the AArch32 syscall `nr`, if its arguments
+/// have the same layout.
+fn native_sysno(nr: u32) -> Option<Sysno> {
+    Some(match nr {
+        1 => Sysno::exit,
+        3 => Sysno::read,
+        4 => Sysno::write,
+        6 => Sysno::close,
+        12 => Sysno::chdir,
+        20 => Sysno::getpid,
+        21 => Sysno::mount,
+        36 => Sysno::sync,
+        37 => Sysno::kill,
+        41 => Sysno::dup,
+        45 => Sysno::brk,
+        52 => Sysno::umount2,
+        57 => Sysno::setpgid,
+        60 => Sysno::umask,
+        61 => Sysno::chroot,
+        64 => Sysno::getppid,
+        66 => Sysno::setsid,
+        91 => Sysno::munmap,
+        94 => Sysno::fchmod,
+        96 => Sysno::getpriority,
+        97 => Sysno::setpriority,
+        103 => Sysno::syslog,
+        118 => Sysno::fsync,
+        120 => Sysno::clone,
+        122 => Sysno::uname,
+        125 => Sysno::mprotect,
+        132 => Sysno::getpgid,
+        133 => Sysno::fchdir,
+        143 => Sysno::flock,
+        144 => Sysno::msync,
+        147 => Sysno::getsid,
+        148 => Sysno::fdatasync,
+        150 => Sysno::mlock,
+        154 => Sysno::sched_setparam,
+        155 => Sysno::sched_getparam,
+        156 => Sysno::sched_setscheduler,
+        157 => Sysno::sched_getscheduler,
+        158 => Sysno::sched_yield,
+        159 => Sysno::sched_get_priority_max,
+        160 => Sysno::sched_get_priority_min,
+        163 => Sysno::mremap,
+        172 => Sysno::prctl,
+        173 => Sysno::rt_sigreturn,
+        175 => Sysno::rt_sigprocmask,
+        176 => Sysno::rt_sigpending,
+        179 => Sysno::rt_sigsuspend,
+        183 => Sysno::getcwd,
+        184 => Sysno::capget,
+        185 => Sysno::capset,
+        // The `*32` calls, taking 32-bit ids as the native ones do.
+        199 => Sysno::getuid,
+        200 => Sysno::getgid,
+        201 => Sysno::geteuid,
+        202 => Sysno::getegid,
+        203 => Sysno::setreuid,
+        205 => Sysno::getgroups,
+        206 => Sysno::setgroups,
+        207 => Sysno::fchown,
+        208 => Sysno::setresuid,
+        210 => Sysno::setresgid,
+        213 => Sysno::setuid,
+        214 => Sysno::setgid,
+        217 => Sysno::getdents64,
+        219 => Sysno::mincore,
+        220 => Sysno::madvise,
+        224 => Sysno::gettid,
+        238 => Sysno::tkill,
+        241 => Sysno::sched_setaffinity,
+        242 => Sysno::sched_getaffinity,
+        248 => Sysno::exit_group,
+        251 => Sysno::epoll_ctl,
+        256 => Sysno::set_tid_address,
+        268 => Sysno::tgkill,
+        281 => Sysno::socket,
+        282 => Sysno::bind,
+        283 => Sysno::connect,
+        284 => Sysno::listen,
+        285 => Sysno::accept,
+        286 => Sysno::getsockname,
+        287 => Sysno::getpeername,
+        288 => Sysno::socketpair,
+        290 => Sysno::sendto,
+        292 => Sysno::recvfrom,
+        293 => Sysno::shutdown,
+        294 => Sysno::setsockopt,
+        295 => Sysno::getsockopt,
+        303 => Sysno::msgget,
+        305 => Sysno::shmat,
+        306 => Sysno::shmdt,
+        307 => Sysno::shmget,
+        317 => Sysno::inotify_add_watch,
+        318 => Sysno::inotify_rm_watch,
+        322 => Sysno::openat,
+        323 => Sysno::mkdirat,
+        324 => Sysno::mknodat,
+        325 => Sysno::fchownat,
+        328 => Sysno::unlinkat,
+        329 => Sysno::renameat,
+        330 => Sysno::linkat,
+        331 => Sysno::symlinkat,
+        332 => Sysno::readlinkat,
+        346 => Sysno::epoll_pwait,
+        350 => Sysno::timerfd_create,
+        355 => Sysno::signalfd4,
+        356 => Sysno::eventfd2,
+        357 => Sysno::epoll_create1,
+        358 => Sysno::dup3,
+        359 => Sysno::pipe2,
+        360 => Sysno::inotify_init1,
+        364 => Sysno::perf_event_open,
+        366 => Sysno::accept4,
+        367 => Sysno::fanotify_init,
+        369 => Sysno::prlimit64,
+        373 => Sysno::syncfs,
+        382 => Sysno::renameat2,
+        384 => Sysno::getrandom,
+        385 => Sysno::memfd_create,
+        386 => Sysno::bpf,
+        388 => Sysno::userfaultfd,
+        389 => Sysno::membarrier,
+        390 => Sysno::mlock2,
+        391 => Sysno::copy_file_range,
+        397 => Sysno::statx,
+        // The `*_time64` calls writing a `struct __kernel_timespec`, which
+        // is the native `timespec`.
+        403 => Sysno::clock_gettime,
+        406 => Sysno::clock_getres,
+        425 => Sysno::io_uring_setup,
+        426 => Sysno::io_uring_enter,
+        428 => Sysno::open_tree,
+        430 => Sysno::fsopen,
+        433 => Sysno::fspick,
+        434 => Sysno::pidfd_open,
+        436 => Sysno::close_range,
+        438 => Sysno::pidfd_getfd,
+        439 => Sysno::faccessat2,
+        452 => Sysno::fchmodat2,
+        _ => return None,
+    })
+}
+
+/// Dispatches a syscall of a 32-bit program.
+pub(super) fn dispatch_compat_syscall(uctx: &mut UserContext) {
+    let nr = uctx.sysno() as u32;
+    let (ip, arg0) = (uctx.ip(), uctx.arg0());
+    let result = match native_sysno(nr) {
+        Some(sysno) => run_syscall(sysno, uctx),
+        None => {
+            trace!("Compat syscall {nr}");
+            trace_event!(
+                SYS_ENTER,
+                "compat {nr}({:#x}, {:#x}, {:#x})",
+                uctx.arg0(),
+                uctx.arg1(),
+                uctx.arg2()
+            );
+            let result = handle_compat_syscall(nr, uctx);
+            debug!("Compat syscall {nr} return {result:?}");
+            result
+        }
+    };
+    if matches!(result, Err(KError::Interrupted)) && matches!(nr, nr::WAIT4 | nr::WAITID) {
+        // The Thumb `svc` is 2 bytes long.
+        let insn_len = if uctx.is_thumb() { 2 } else { 4 };
+        current()
+            .as_thread()
+            .signal
+            .set_syscall_restart(SyscallRestart {
+                ip: ip - insn_len,
+                sysno: nr as _,
+                arg0,
+            });
+    }
+    uctx.set_retval(result.unwrap_or_else(|err| -LinuxError::from(err).into_raw() as _) as _);
+}
+
+/// Runs the AArch32 syscall `nr`, converting its arguments.
+fn handle_compat_syscall(nr: u32, uctx: &mut UserContext) -> KResult<isize> {
+    let a = [
+        uctx.arg0(),
+        uctx.arg1(),
+        uctx.arg2(),
+        uctx.arg3(),
+        uctx.arg4(),
+        uctx.arg5(),
+    ];
+    let s = &mut Scratch::new(uctx);
+    match nr {
+        // task management
+        nr::FORK => sys_clone(uctx, SIGCHLD, 0, 0, 0, 0),
+        nr::VFORK => sys_clone(uctx, CLONE_VM | CLONE_VFORK | SIGCHLD, 0, 0, 0, 0),
+        nr::EXECVE => {
+            let argv = widen_ptr_array(s, a[1])?;
+            let envp = widen_ptr_array(s, a[2])?;
+            sys_execve(uctx, a[0] as _, argv as _, envp as _)
+        }
+        nr::WAIT4 => {
+            let usage = s.alloc_for::<rusage>(a[3])?;
+            let ret = sys_wait4(a[0] as _, a[1] as _, a[2] as _, usage)?;
+            convert_out::<_, Rusage32>(usage, a[3])?;
+            Ok(ret)
+        }
+        nr::WAITID => {
+            let info = s.alloc_for::<siginfo>(a[2])?;
+            if !info.is_null() {
+                // Left untouched if no child is waitable with `WNOHANG`.
+                // SAFETY: `siginfo` is plain data.
+                info.write_vm(unsafe { core::mem::zeroed() })?;
+            }
+            let usage = s.alloc_for::<rusage>(a[4])?;
+            let ret = sys_waitid(a[0] as _, a[1] as _, info, a[3] as _, usage)?;
+            siginfo_out(info, a[2])?;
+            convert_out::<_, Rusage32>(usage, a[4])?;
+            Ok(ret)
+        }
+
+        // paths relative to the working directory
+        nr::OPEN => sys_openat(AT_FDCWD, a[0] as _, a[1] as _, a[2] as _),
+        nr::CREAT => sys_openat(
+            AT_FDCWD,
+            a[0] as _,
+            (O_CREAT | O_WRONLY | O_TRUNC) as _,
+            a[1] as _,
+        ),
+        nr::LINK => sys_linkat(AT_FDCWD, a[0] as _, AT_FDCWD, a[1] as _, 0),
+        nr::UNLINK => sys_unlinkat(AT_FDCWD, a[0] as _, 0),
+        nr::RMDIR => sys_unlinkat(AT_FDCWD, a[0] as _, AT_REMOVEDIR as _),
+        nr::MKDIR => sys_mkdirat(AT_FDCWD, a[0] as _, a[1] as _),
+        nr::MKNOD => sys_mknodat(AT_FDCWD, a[0] as _, a[1] as _, a[2] as _),
+        nr::CHMOD => sys_fchmodat(AT_FDCWD, a[0] as _, a[1] as _, 0),
+        nr::ACCESS => sys_faccessat2(AT_FDCWD, a[0] as _, a[1] as _, 0),
+        nr::RENAME => sys_renameat(AT_FDCWD, a[0] as _, AT_FDCWD, a[1] as _),
+        nr::SYMLINK => sys_symlinkat(a[0] as _, AT_FDCWD, a[1] as _),
+        nr::READLINK => sys_readlinkat(AT_FDCWD, a[0] as _, a[1] as _, a[2] as _),
+        nr::CHOWN32 => sys_fchownat(AT_FDCWD, a[0] as _, a[1] as _, a[2] as _, 0),
+        nr::LCHOWN32 => sys_fchownat(
+            AT_FDCWD,
+            a[0] as _,
+            a[1] as _,
+            a[2] as _,
+            linux_raw_sys::general::AT_SYMLINK_NOFOLLOW,
+        ),
+        // Without the `flags` of `fchmodat2` and `faccessat2`.
+        nr::FCHMODAT => sys_fchmodat(a[0] as _, a[1] as _, a[2] as _, 0),
+        nr::FACCESSAT => sys_faccessat2(a[0] as _, a[1] as _, a[2] as _, 0),
+
+        // fd ops
+        nr::PIPE => sys_pipe2(a[0] as _, 0),
+        nr::DUP2 => {
+            if a[0] == a[1] {
+                // `dup3` refuses equal descriptors, `dup2` checks the old one.
+                sys_fcntl(a[0] as _, linux_raw_sys::general::F_GETFD as _, 0)?;
+                return Ok(a[1] as _);
+            }
+            sys_dup3(a[0] as _, a[1] as _, 0)
+        }
+        nr::IOCTL => compat_ioctl(s, a[0] as _, a[1] as _, a[2]),
+        nr::FCNTL => {
+            if (F_GETLK..=F_SETLKW).contains(&(a[1] as u32)) {
+                // `struct flock` has 32-bit offsets here, use `fcntl64`.
+                return Err(KError::InvalidInput);
+            }
+            sys_fcntl(a[0] as _, a[1] as _, a[2])
+        }
+        nr::FCNTL64 => {
+            let mut cmd = a[1] as u32;
+            if (F_GETLK64..=F_SETLKW64).contains(&cmd) {
+                // `struct flock64` is the native `struct flock`.
+                cmd = cmd - F_GETLK64 + F_GETLK;
+            }
+            sys_fcntl(a[0] as _, cmd as _, a[2])
+        }
+
+        // io
+        nr::LSEEK => sys_lseek(a[0] as _, a[1] as i32 as _, a[2] as _),
+        nr::LLSEEK => {
+            let offset = sys_lseek(a[0] as _, arg64(a[2], a[1]), a[4] as _)?;
+            (a[3] as *mut i64).write_vm(offset as _)?;
+            Ok(0)
+        }
+        nr::READV | nr::WRITEV => {
+            let iov = widen_iovec(s, a[1], a[2])?;
+            if nr == nr::READV {
+                sys_readv(a[0] as _, iov, a[2])
+            } else {
+                sys_writev(a[0] as _, iov, a[2])
+            }
+        }
+        // The 64-bit offset is in an even register pair, skipping `r3`.
+        nr::PREAD64 => sys_pread64(a[0] as _, a[1] as _, a[2], arg64(a[4], a[5])),
+        nr::PWRITE64 => sys_pwrite64(a[0] as _, a[1] as _, a[2], arg64(a[4], a[5])),
+        nr::TRUNCATE => sys_truncate(a[0] as _, a[1] as i32 as _),
+        nr::FTRUNCATE => sys_ftruncate(a[0] as _, a[1] as i32 as _),
+        nr::TRUNCATE64 => sys_truncate(a[0] as _, arg64(a[2], a[3])),
+        nr::FTRUNCATE64 => sys_ftruncate(a[0] as _, arg64(a[2], a[3])),
+        nr::FALLOCATE => sys_fallocate(a[0] as _, a[1] as _, arg64(a[2], a[3]), arg64(a[4], a[5])),
+        // The advice comes second to keep the offsets in register pairs.
+        nr::ARM_FADVISE64_64 => {
+            sys_fadvise64(a[0] as _, arg64(a[2], a[3]), arg64(a[4], a[5]), a[1] as _)
+        }
+
+        // fs stat
+        nr::STAT64 | nr::LSTAT64 => {
+            let flags = if nr == nr::LSTAT64 {
+                linux_raw_sys::general::AT_SYMLINK_NOFOLLOW
+            } else {
+                0
+            };
+            let st = s.alloc::<stat>()?;
+            sys_fstatat(AT_FDCWD, a[0] as _, st, flags)?;
+            convert_out::<_, Stat64>(st, a[1])?;
+            Ok(0)
+        }
+        nr::FSTAT64 => {
+            let st = s.alloc::<stat>()?;
+            sys_fstat(a[0] as _, st)?;
+            convert_out::<_, Stat64>(st, a[1])?;
+            Ok(0)
+        }
+        nr::FSTATAT64 => {
+            let st = s.alloc::<stat>()?;
+            sys_fstatat(a[0] as _, a[1] as _, st, a[3] as _)?;
+            convert_out::<_, Stat64>(st, a[2])?;
+            Ok(0)
+        }
+        nr::STATFS64 | nr::FSTATFS64 => {
+            if a[1] != STATFS64_SIZE {
+                return Err(KError::InvalidInput);
+            }
+            let st = s.alloc::<statfs>()?;
+            if nr == nr::STATFS64 {
+                sys_statfs(a[0] as _, st)?;
+            } else {
+                sys_fstatfs(a[0] as _, st)?;
+            }
+            convert_out::<_, Statfs64>(st, a[2])?;
+            Ok(0)
+        }
+
+        // mm
+        // The offset is in pages of 4 KiB, whatever the page size.
+        nr::MMAP2 => sys_mmap(
+            a[0],
+            a[1],
+            a[2] as _,
+            a[3] as _,
+            a[4] as _,
+            (a[5] << 12) as _,
+        ),
+
+        // io mpx
+        nr::POLL => {
+            let timeout = a[2] as i32;
+            let ts = if timeout < 0 {
+                null_mut()
+            } else {
+                s.put(timespec {
+                    tv_sec: (timeout / 1000) as _,
+                    tv_nsec: (timeout % 1000 * 1_000_000) as _,
+                })?
+            };
+            sys_ppoll(
+                a[0].into(),
+                a[1] as _,
+                ts.cast_const().into(),
+                UserConstPtr::default(),
+                0,
+            )
+        }
+        nr::PPOLL | nr::PPOLL_TIME64 => {
+            let ts = if nr == nr::PPOLL {
+                s.convert_in::<Timespec32, timespec>(a[2])?
+            } else {
+                s.convert_in::<Timespec64, timespec>(a[2])?
+            };
+            sys_ppoll(
+                UserPtr::<pollfd>::from(a[0]),
+                a[1] as _,
+                ts.cast_const().into(),
+                UserConstPtr::<SignalSet>::from(a[3]),
+                a[4],
+            )
+        }
+        nr::EPOLL_CREATE => {
+            if a[0] as i32 <= 0 {
+                return Err(KError::InvalidInput);
+            }
+            sys_epoll_create1(0)
+        }
+        nr::EPOLL_WAIT => sys_epoll_pwait(
+            a[0] as _,
+            a[1].into(),
+            a[2] as _,
+            a[3] as _,
+            UserConstPtr::default(),
+            0,
+        ),
+
+        // time
+        nr::GETTIMEOFDAY => {
+            let tv = s.alloc_for::<timeval>(a[0])?;
+            sys_gettimeofday(tv, a[1] as _)?;
+            convert_out::<_, Timeval32>(tv, a[0])?;
+            Ok(0)
+        }
+        nr::CLOCK_GETTIME | nr::CLOCK_GETRES => {
+            let ts = s.alloc_for::<timespec>(a[1])?;
+            if nr == nr::CLOCK_GETTIME {
+                sys_clock_gettime(a[0] as _, ts)?;
+            } else {
+                sys_clock_getres(a[0] as _, ts)?;
+            }
+            convert_out::<_, Timespec32>(ts, a[1])?;
+            Ok(0)
+        }
+        nr::CLOCK_SETTIME => {
+            sys_clock_settime(a[0] as _, s.convert_in::<Timespec32, timespec>(a[1])?)
+        }
+        nr::CLOCK_SETTIME64 => {
+            sys_clock_settime(a[0] as _, s.convert_in::<Timespec64, timespec>(a[1])?)
+        }
+        nr::NANOSLEEP => {
+            let req = s.convert_in::<Timespec32, timespec>(a[0])?;
+            let rem = s.alloc_for::<timespec>(a[1])?;
+            let result = sys_nanosleep(req, rem);
+            if matches!(result, Err(KError::Interrupted)) {
+                convert_out::<_, Timespec32>(rem, a[1])?;
+            }
+            result
+        }
+        nr::CLOCK_NANOSLEEP => {
+            let req = s.convert_in::<Timespec32, timespec>(a[2])?;
+            let rem = s.alloc_for::<timespec>(a[3])?;
+            let result = sys_clock_nanosleep(a[0] as _, a[1] as _, req, rem);
+            if matches!(result, Err(KError::Interrupted)) {
+                convert_out::<_, Timespec32>(rem, a[3])?;
+            }
+            result
+        }
+        nr::CLOCK_NANOSLEEP_TIME64 => {
+            let req = s.convert_in::<Timespec64, timespec>(a[2])?;
+            sys_clock_nanosleep(a[0] as _, a[1] as _, req, a[3] as _)
+        }
+        nr::UTIMENSAT | nr::UTIMENSAT_TIME64 => {
+            let times = if a[2] == 0 {
+                null_mut()
+            } else if nr == nr::UTIMENSAT {
+                s.put(read::<[Timespec32; 2]>(a[2])?.map(timespec::from))?
+            } else {
+                s.put(read::<[Timespec64; 2]>(a[2])?.map(timespec::from))?
+            };
+            sys_utimensat(a[0] as _, a[1] as _, times, a[3] as _)
+        }
+
+        // sync
+        nr::FUTEX | nr::FUTEX_TIME64 => {
+            // Only the waits take a timeout, the other operations an integer.
+            let timeout = match a[1] & 0x7f {
+                0 | 6 | 9 | 11 | 13 if nr == nr::FUTEX => {
+                    s.convert_in::<Timespec32, timespec>(a[3])?
+                }
+                0 | 6 | 9 | 11 | 13 => s.convert_in::<Timespec64, timespec>(a[3])?,
+                _ => a[3] as _,
+            };
+            sys_futex(
+                a[0] as _, a[1] as _, a[2] as _, timeout, a[4] as _, a[5] as _,
+            )
+        }
+
+        // resources
+        nr::GETRUSAGE => {
+            let usage = s.alloc::<rusage>()?;
+            sys_getrusage(a[0] as _, usage)?;
+            convert_out::<_, Rusage32>(usage, a[1])?;
+            Ok(0)
+        }
+        nr::UGETRLIMIT => {
+            let old = s.alloc::<rlimit64>()?;
+            sys_prlimit64(0, a[0] as _, null(), old)?;
+            convert_out::<_, Rlimit32>(old, a[1])?;
+            Ok(0)
+        }
+        nr::SETRLIMIT => {
+            let new = s.put(rlimit64::from(read::<Rlimit32>(a[1])?))?;
+            sys_prlimit64(0, a[0] as _, new, null_mut())
+        }
+        nr::SYSINFO => {
+            let info = s.alloc::<sysinfo>()?;
+            sys_sysinfo(info)?;
+            convert_out::<_, Sysinfo32>(info, a[0])?;
+            Ok(0)
+        }
+
+        // signal
+        nr::RT_SIGACTION => {
+            let act = s.convert_in::<Sigaction32, NativeSigaction>(a[1])?;
+            let oldact = s.alloc_for::<NativeSigaction>(a[2])?;
+            sys_rt_sigaction(
+                a[0] as _,
+                act.cast::<kernel_sigaction>(),
+                oldact.cast::<kernel_sigaction>(),
+                a[3],
+            )?;
+            convert_out::<_, Sigaction32>(oldact, a[2])?;
+            Ok(0)
+        }
+        nr::RT_SIGTIMEDWAIT | nr::RT_SIGTIMEDWAIT_TIME64 => {
+            let info = s.alloc_for::<siginfo>(a[1])?;
+            let timeout = if nr == nr::RT_SIGTIMEDWAIT {
+                s.convert_in::<Timespec32, timespec>(a[2])?
+            } else {
+                s.convert_in::<Timespec64, timespec>(a[2])?
+            };
+            let signo = sys_rt_sigtimedwait(uctx, a[0] as _, info, timeout, a[3])?;
+            siginfo_out(info, a[1])?;
+            Ok(signo)
+        }
+        nr::SIGALTSTACK => {
+            let ss = s.convert_in::<CompatSignalStack, SignalStack>(a[0])?;
+            let old_ss = s.alloc_for::<SignalStack>(a[1])?;
+            sys_sigaltstack(uctx, ss, old_ss)?;
+            convert_out::<_, CompatSignalStack>(old_ss, a[1])?;
+            Ok(0)
+        }
+
+        // ARM private
+        nr::ARM_CACHEFLUSH => {
+            khal::asm::flush_icache_all();
+            Ok(0)
+        }
+        nr::ARM_SET_TLS => {
+            uctx.set_tls(a[0]);
+            Ok(0)
+        }
+        nr::ARM_GET_TLS => Ok(uctx.tls() as _),
+
+        _ => {
+            warn!("Unsupported compat syscall: {nr}");
+            Err(KError::Unsupported)
+        }
+    }
+}
+
+/// Copies the null-terminated array of 32-bit pointers at `addr` to a
+/// native one, or returns null if `addr` is null.
+fn widen_ptr_array(s: &mut Scratch, addr: usize) -> KResult<*mut usize> {
+    if addr == 0 {
+        return Ok(null_mut());
+    }
+    let mut ptrs: Vec<usize> = load_vec_until_null(addr as *const u32)?
+        .into_iter()
+        .map(|ptr| ptr as usize)
+        .collect();
+    ptrs.push(0);
+    let native = s.alloc_slice::<usize>(ptrs.len())?;
+    write_vm_mem(native, &ptrs)?;
+    Ok(native)
+}
+
+/// Copies the `iovcnt` 32-bit `struct iovec`s at `addr` to native ones.
+fn widen_iovec(s: &mut Scratch, addr: usize, iovcnt: usize) -> KResult<*const IoVec> {
+    if iovcnt > osvm::IOV_MAX {
+        return Err(KError::InvalidInput);
+    }
+    let iovs: Vec<IoVec32> = load_vec(addr as *const IoVec32, iovcnt)?;
+    let native: Vec<IoVec> = iovs
+        .into_iter()
+        .map(|iov| IoVec {
+            iov_base: iov.iov_base as usize as *mut u8,
+            iov_len: iov.iov_len as isize,
+        })
+        .collect();
+    let ptr = s.alloc_slice::<IoVec>(native.len())?;
+    write_vm_mem(ptr, &native)?;
+    Ok(ptr)
+}
+
+#[cfg(unittest)]
+mod compat_tests {
+    use unittest::def_test;
+
+    use super::*;
+
+    #[def_test]
+    fn test_arg64_joins_register_pair() {
+        assert_eq!(arg64(0x8765_4321, 0x1), 0x1_8765_4321);
+        assert_eq!(arg64(0xffff_ffff, 0xffff_ffff), -1);
+        // Garbage in the upper half of a register is ignored.
+        assert_eq!(arg64(0xdead_0000_0001, 0), 1);
+    }
+
+    #[def_test]
+    fn test_native_sysno_skips_converted_calls() {
+        assert_eq!(native_sysno(322), Some(Sysno::openat));
+        assert_eq!(native_sysno(403), Some(Sysno::clock_gettime));
+        assert_eq!(native_sysno(nr::STAT64), None);
+        assert_eq!(native_sysno(nr::RT_SIGACTION), None);
+    }
+}
@@ -0,0 +1,343 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Structures of the 32-bit ARM EABI that differ from the native ones.
+//!
+//! Each converts to and from its native counterpart field by field. The
+//! values are not checked here: narrowing truncates, widening sign- or
+//! zero-extends, and the native syscall checks the result.
+//!
+//! `struct termios`, `struct winsize`, `struct pollfd`, `struct
+//! epoll_event`, `struct linux_dirent64`, `struct statx` and `struct
+//! rlimit64` have the same layout on both, and are not converted.
+//!
+//! # Year 2038
+//!
+//! The `time_t` of [`Timespec32`] and [`Timeval32`] is 32-bit, and cannot
+//! tell times past 2038-01-19: as on Linux, they are truncated. Programs
+//! built with a 64-bit `time_t` use the `*_time64` syscalls instead, whose
+//! [`Timespec64`] only differs from the native `timespec` in the padding
+//! next to `tv_nsec`.
+
+use bytemuck::AnyBitPattern;
+use linux_raw_sys::general::{
+    kernel_sigaction, rlimit64, rusage, stat, statfs, sysinfo, timespec, timeval,
+};
+
+/// `struct old_timespec32`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timespec32 {
+    pub tv_sec: i32,
+    pub tv_nsec: i32,
+}
+
+impl From<Timespec32> for timespec {
+    fn from(ts: Timespec32) -> Self {
+        timespec {
+            tv_sec: ts.tv_sec as _,
+            tv_nsec: ts.tv_nsec as _,
+        }
+    }
+}
+
+impl From<timespec> for Timespec32 {
+    fn from(ts: timespec) -> Self {
+        Timespec32 {
+            tv_sec: ts.tv_sec as _,
+            tv_nsec: ts.tv_nsec as _,
+        }
+    }
+}
+
+/// `struct __kernel_timespec` as 32-bit programs pass it.
+///
+/// The upper half of `tv_nsec` is padding user space need not clear, and
+/// is ignored as Linux does.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timespec64 {
+    pub tv_sec: i64,
+    pub tv_nsec: i32,
+    __pad: i32,
+}
+
+impl From<Timespec64> for timespec {
+    fn from(ts: Timespec64) -> Self {
+        timespec {
+            tv_sec: ts.tv_sec as _,
+            tv_nsec: ts.tv_nsec as _,
+        }
+    }
+}
+
+/// `struct old_timeval32`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timeval32 {
+    pub tv_sec: i32,
+    pub tv_usec: i32,
+}
+
+impl From<Timeval32> for timeval {
+    fn from(tv: Timeval32) -> Self {
+        timeval {
+            tv_sec: tv.tv_sec as _,
+            tv_usec: tv.tv_usec as _,
+        }
+    }
+}
+
+impl From<timeval> for Timeval32 {
+    fn from(tv: timeval) -> Self {
+        Timeval32 {
+            tv_sec: tv.tv_sec as _,
+            tv_usec: tv.tv_usec as _,
+        }
+    }
+}
+
+/// `struct stat64` of the ARM EABI, with its padding spelled out.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Stat64 {
+    pub st_dev: u64,
+    __pad0: [u8; 4],
+    /// The inode number truncated to 32 bits.
+    pub __st_ino: u32,
+    pub st_mode: u32,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub st_rdev: u64,
+    __pad3: [u8; 4],
+    __pad4: u32,
+    pub st_size: i64,
+    pub st_blksize: u32,
+    __pad5: u32,
+    pub st_blocks: u64,
+    pub st_atime: u32,
+    pub st_atime_nsec: u32,
+    pub st_mtime: u32,
+    pub st_mtime_nsec: u32,
+    pub st_ctime: u32,
+    pub st_ctime_nsec: u32,
+    pub st_ino: u64,
+}
+
+impl From<stat> for Stat64 {
+    fn from(st: stat) -> Self {
+        Stat64 {
+            st_dev: st.st_dev as _,
+            __pad0: [0; 4],
+            __st_ino: st.st_ino as _,
+            st_mode: st.st_mode as _,
+            st_nlink: st.st_nlink as _,
+            st_uid: st.st_uid as _,
+            st_gid: st.st_gid as _,
+            st_rdev: st.st_rdev as _,
+            __pad3: [0; 4],
+            __pad4: 0,
+            st_size: st.st_size as _,
+            st_blksize: st.st_blksize as _,
+            __pad5: 0,
+            st_blocks: st.st_blocks as _,
+            st_atime: st.st_atime as _,
+            st_atime_nsec: st.st_atime_nsec as _,
+            st_mtime: st.st_mtime as _,
+            st_mtime_nsec: st.st_mtime_nsec as _,
+            st_ctime: st.st_ctime as _,
+            st_ctime_nsec: st.st_ctime_nsec as _,
+            st_ino: st.st_ino as _,
+        }
+    }
+}
+
+/// `struct statfs64` of the ARM EABI, packed to 4 bytes.
+#[repr(C, packed(4))]
+#[derive(Debug, Clone, Copy)]
+pub struct Statfs64 {
+    pub f_type: u32,
+    pub f_bsize: u32,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: u32,
+    pub f_frsize: u32,
+    pub f_flags: u32,
+    pub f_spare: [u32; 4],
+}
+
+impl From<statfs> for Statfs64 {
+    fn from(st: statfs) -> Self {
+        Statfs64 {
+            f_type: st.f_type as _,
+            f_bsize: st.f_bsize as _,
+            f_blocks: st.f_blocks as _,
+            f_bfree: st.f_bfree as _,
+            f_bavail: st.f_bavail as _,
+            f_files: st.f_files as _,
+            f_ffree: st.f_ffree as _,
+            f_fsid: st.f_fsid.val,
+            f_namelen: st.f_namelen as _,
+            f_frsize: st.f_frsize as _,
+            f_flags: st.f_flags as _,
+            f_spare: [0; 4],
+        }
+    }
+}
+
+/// `struct rusage` with 32-bit `long`s: two [`Timeval32`] followed by 14
+/// counters, as the native one is two `timeval`s followed by 14 `long`s.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rusage32([i32; 18]);
+
+impl From<rusage> for Rusage32 {
+    fn from(usage: rusage) -> Self {
+        const _: () = assert!(size_of::<rusage>() == size_of::<[i64; 18]>());
+        // SAFETY: `rusage` is made of 18 `long`s.
+        let longs: [i64; 18] = unsafe { core::mem::transmute(usage) };
+        Rusage32(longs.map(|long| long as i32))
+    }
+}
+
+/// `struct sysinfo` with 32-bit `long`s.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Sysinfo32 {
+    pub uptime: i32,
+    pub loads: [u32; 3],
+    pub totalram: u32,
+    pub freeram: u32,
+    pub sharedram: u32,
+    pub bufferram: u32,
+    pub totalswap: u32,
+    pub freeswap: u32,
+    pub procs: u16,
+    pub pad: u16,
+    pub totalhigh: u32,
+    pub freehigh: u32,
+    pub mem_unit: u32,
+    _f: [u8; 8],
+}
+
+impl From<sysinfo> for Sysinfo32 {
+    fn from(info: sysinfo) -> Self {
+        Sysinfo32 {
+            uptime: info.uptime as _,
+            loads: info.loads.map(|load| load as _),
+            totalram: info.totalram as _,
+            freeram: info.freeram as _,
+            sharedram: info.sharedram as _,
+            bufferram: info.bufferram as _,
+            totalswap: info.totalswap as _,
+            freeswap: info.freeswap as _,
+            procs: info.procs,
+            pad: 0,
+            totalhigh: info.totalhigh as _,
+            freehigh: info.freehigh as _,
+            mem_unit: info.mem_unit,
+            _f: [0; 8],
+        }
+    }
+}
+
+/// `struct rlimit` with 32-bit `long`s, where `RLIM_INFINITY` is `!0`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rlimit32 {
+    pub rlim_cur: u32,
+    pub rlim_max: u32,
+}
+
+impl From<Rlimit32> for rlimit64 {
+    fn from(rlim: Rlimit32) -> Self {
+        let widen = |value: u32| {
+            if value == u32::MAX {
+                u64::MAX
+            } else {
+                value as u64
+            }
+        };
+        rlimit64 {
+            rlim_cur: widen(rlim.rlim_cur),
+            rlim_max: widen(rlim.rlim_max),
+        }
+    }
+}
+
+impl From<rlimit64> for Rlimit32 {
+    fn from(rlim: rlimit64) -> Self {
+        Rlimit32 {
+            rlim_cur: rlim.rlim_cur.min(u32::MAX as u64) as u32,
+            rlim_max: rlim.rlim_max.min(u32::MAX as u64) as u32,
+        }
+    }
+}
+
+/// `struct sigaction` of the kernel ABI with 32-bit pointers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Sigaction32 {
+    pub sa_handler: u32,
+    pub sa_flags: u32,
+    pub sa_restorer: u32,
+    pub sa_mask: [u32; 2],
+}
+
+/// The native `kernel_sigaction`, with its function pointers as integers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NativeSigaction {
+    pub sa_handler: usize,
+    pub sa_flags: usize,
+    pub sa_restorer: usize,
+    pub sa_mask: u64,
+}
+
+const _: () = assert!(size_of::<NativeSigaction>() == size_of::<kernel_sigaction>());
+
+impl From<Sigaction32> for NativeSigaction {
+    fn from(act: Sigaction32) -> Self {
+        NativeSigaction {
+            sa_handler: act.sa_handler as _,
+            sa_flags: act.sa_flags as _,
+            sa_restorer: act.sa_restorer as _,
+            sa_mask: act.sa_mask[0] as u64 | (act.sa_mask[1] as u64) << 32,
+        }
+    }
+}
+
+impl From<NativeSigaction> for Sigaction32 {
+    fn from(act: NativeSigaction) -> Self {
+        Sigaction32 {
+            sa_handler: act.sa_handler as _,
+            sa_flags: act.sa_flags as _,
+            sa_restorer: act.sa_restorer as _,
+            sa_mask: [act.sa_mask as u32, (act.sa_mask >> 32) as u32],
+        }
+    }
+}
+
+/// `struct iovec` with 32-bit pointers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct IoVec32 {
+    pub iov_base: u32,
+    pub iov_len: u32,
+}
+
+const _: () = {
+    assert!(size_of::<Stat64>() == 104);
+    assert!(core::mem::offset_of!(Stat64, st_size) == 48);
+    assert!(core::mem::offset_of!(Stat64, st_ino) == 96);
+    assert!(size_of::<Statfs64>() == 84);
+    assert!(size_of::<Rusage32>() == 72);
+    assert!(size_of::<Sysinfo32>() == 64);
+    assert!(size_of::<Sigaction32>() == 20);
+};
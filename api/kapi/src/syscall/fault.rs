@@ -0,0 +1,605 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Rule-based syscall fault injection, for robustness testing of user space.
+//!
+//! A rule selects calls of one syscall, optionally narrowed to a process, a
+//! command name, the fd passed as first argument or the path argument, and
+//! makes some of them fail with an errno, sleep before running, or report a
+//! shorter result. Rules are managed with [`add_rule`], [`remove_rule`] and
+//! [`clear_rules`], or by writing to `/proc/sys/debug/syscall_faults`:
+//!
+//! ```text
+//! sys=read comm=cat fail=EINTR prob=50 max=10
+//! sys=openat path=/etc/* delay=100
+//! -3
+//! clear
+//! ```
+//!
+//! Only debug builds have this module. While no rule is loaded, the
+//! dispatcher only goes through a patched-out branch.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use kcore::task::AsThread;
+use kerrno::{KError, KResult, LinuxError};
+use khal::uspace::UserContext;
+use kprocess::Pid;
+use kspin::SpinNoIrq;
+use ksync::Mutex;
+use ktask::current;
+use linux_sysno::Sysno;
+use static_keys::{StaticKey, static_branch_unlikely};
+
+use crate::mm::vm_load_string;
+
+/// Maximum number of loaded rules.
+pub const MAX_RULES: usize = 32;
+
+/// What a rule does to the calls it hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Fails the call with the errno, without running the handler.
+    Fail(LinuxError),
+    /// Sleeps before running the handler.
+    Delay(Duration),
+    /// Runs the handler and caps a successful result, e.g. to make a read
+    /// or write look short.
+    Truncate(usize),
+}
+
+/// The matching conditions and the action of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultSpec {
+    pub sysno: Sysno,
+    pub pid: Option<Pid>,
+    /// Command name of the calling task.
+    pub comm: Option<String>,
+    /// First argument of the call, for syscalls taking an fd there.
+    pub fd: Option<i32>,
+    /// Path argument of the call. A trailing `*` matches any suffix.
+    pub path: Option<String>,
+    pub action: FaultAction,
+    /// Chance of a matching call being hit, in percent.
+    pub probability: u8,
+    /// Number of hits after which the rule stops firing, 0 for no limit.
+    pub max_hits: u64,
+}
+
+impl FaultSpec {
+    /// Creates a rule hitting every call of `sysno`.
+    pub fn new(sysno: Sysno, action: FaultAction) -> Self {
+        Self {
+            sysno,
+            pid: None,
+            comm: None,
+            fd: None,
+            path: None,
+            action,
+            probability: 100,
+            max_hits: 0,
+        }
+    }
+}
+
+impl FromStr for FaultSpec {
+    type Err = KError;
+
+    /// Parses the space-separated `key=value` form read back from
+    /// `/proc/sys/debug/syscall_faults`.
+    fn from_str(s: &str) -> KResult<Self> {
+        fn value<T: FromStr>(v: &str) -> KResult<T> {
+            v.parse().map_err(|_| KError::InvalidInput)
+        }
+
+        let mut sysno = None;
+        let mut action = None;
+        let mut spec = Self::new(Sysno::read, FaultAction::Truncate(0));
+        for token in s.split_whitespace() {
+            let (key, v) = token.split_once('=').ok_or(KError::InvalidInput)?;
+            let new_action = match key {
+                "sys" => {
+                    sysno = Some(parse_sysno(v)?);
+                    None
+                }
+                "pid" => {
+                    spec.pid = Some(value(v)?);
+                    None
+                }
+                "comm" => {
+                    spec.comm = Some(v.to_string());
+                    None
+                }
+                "fd" => {
+                    spec.fd = Some(value(v)?);
+                    None
+                }
+                "path" => {
+                    spec.path = Some(v.to_string());
+                    None
+                }
+                "prob" => {
+                    spec.probability = value(v)?;
+                    None
+                }
+                "max" => {
+                    spec.max_hits = value(v)?;
+                    None
+                }
+                "fail" => Some(FaultAction::Fail(parse_errno(v)?)),
+                "delay" => Some(FaultAction::Delay(Duration::from_millis(value(v)?))),
+                "truncate" => Some(FaultAction::Truncate(value(v)?)),
+                _ => return Err(KError::InvalidInput),
+            };
+            if let Some(new_action) = new_action
+                && action.replace(new_action).is_some()
+            {
+                return Err(KError::InvalidInput);
+            }
+        }
+        spec.sysno = sysno.ok_or(KError::InvalidInput)?;
+        spec.action = action.ok_or(KError::InvalidInput)?;
+        if !(1..=100).contains(&spec.probability) {
+            return Err(KError::InvalidInput);
+        }
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for FaultSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sys={}", self.sysno.name())?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid={pid}")?;
+        }
+        if let Some(comm) = &self.comm {
+            write!(f, " comm={comm}")?;
+        }
+        if let Some(fd) = self.fd {
+            write!(f, " fd={fd}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, " path={path}")?;
+        }
+        match self.action {
+            FaultAction::Fail(err) => match err.name() {
+                Some(name) => write!(f, " fail={name}")?,
+                None => write!(f, " fail={}", err.into_raw())?,
+            },
+            FaultAction::Delay(dur) => write!(f, " delay={}", dur.as_millis())?,
+            FaultAction::Truncate(len) => write!(f, " truncate={len}")?,
+        }
+        write!(f, " prob={} max={}", self.probability, self.max_hits)
+    }
+}
+
+fn parse_sysno(s: &str) -> KResult<Sysno> {
+    match s.parse::<usize>() {
+        Ok(num) => Sysno::new(num),
+        Err(_) => s.parse().ok(),
+    }
+    .ok_or(KError::InvalidInput)
+}
+
+/// Parses an errno given by name (`EINTR`) or positive number (`4`).
+fn parse_errno(s: &str) -> KResult<LinuxError> {
+    let err = match s.parse::<i32>() {
+        Ok(num) => LinuxError::new(num),
+        Err(_) => (1..4096)
+            .map(LinuxError::new)
+            .find(|err| err.name() == Some(s))
+            .ok_or(KError::InvalidInput)?,
+    };
+    if err.into_raw() <= 0 || err.name().is_none() {
+        return Err(KError::InvalidInput);
+    }
+    Ok(err)
+}
+
+/// The properties of a call that rules match against.
+struct Call<'a> {
+    sysno: Sysno,
+    pid: Pid,
+    comm: &'a str,
+    arg0: usize,
+    path: Option<&'a str>,
+}
+
+/// A loaded rule.
+pub struct FaultRule {
+    id: u32,
+    spec: FaultSpec,
+    hits: AtomicU64,
+}
+
+impl FaultRule {
+    /// Returns the identifier of the rule, used to remove it.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the conditions and the action of the rule.
+    pub fn spec(&self) -> &FaultSpec {
+        &self.spec
+    }
+
+    /// Returns the number of calls the rule has hit.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn matches(&self, call: &Call) -> bool {
+        let spec = &self.spec;
+        spec.sysno == call.sysno
+            && spec.pid.is_none_or(|pid| pid == call.pid)
+            && spec.comm.as_deref().is_none_or(|comm| comm == call.comm)
+            && spec.fd.is_none_or(|fd| fd == call.arg0 as i32)
+            && spec
+                .path
+                .as_deref()
+                .is_none_or(|pattern| call.path.is_some_and(|path| path_matches(pattern, path)))
+    }
+
+    /// Counts a hit for a matching call that rolled `roll` (0 to 99), unless
+    /// the roll misses or the rule has used up its hits.
+    fn try_hit(&self, roll: u32) -> bool {
+        if roll >= self.spec.probability as u32 {
+            return false;
+        }
+        let max = self.spec.max_hits;
+        self.hits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hits| {
+                (max == 0 || hits < max).then_some(hits + 1)
+            })
+            .is_ok()
+    }
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => path == pattern,
+    }
+}
+
+/// Returns the index of the path argument of `sysno`, if it has one.
+fn path_arg(sysno: Sysno) -> Option<usize> {
+    match sysno {
+        Sysno::openat
+        | Sysno::mkdirat
+        | Sysno::mknodat
+        | Sysno::unlinkat
+        | Sysno::faccessat
+        | Sysno::faccessat2
+        | Sysno::fchmodat
+        | Sysno::fchownat
+        | Sysno::statx
+        | Sysno::readlinkat
+        | Sysno::utimensat
+        | Sysno::renameat
+        | Sysno::renameat2
+        | Sysno::linkat
+        | Sysno::execveat => Some(1),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::newfstatat => Some(1),
+        #[cfg(not(target_arch = "x86_64"))]
+        Sysno::fstatat => Some(1),
+        Sysno::execve
+        | Sysno::chdir
+        | Sysno::chroot
+        | Sysno::truncate
+        | Sysno::statfs
+        | Sysno::getxattr
+        | Sysno::lgetxattr
+        | Sysno::setxattr
+        | Sysno::lsetxattr
+        | Sysno::listxattr
+        | Sysno::llistxattr
+        | Sysno::removexattr
+        | Sysno::lremovexattr => Some(0),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::open
+        | Sysno::creat
+        | Sysno::stat
+        | Sysno::lstat
+        | Sysno::access
+        | Sysno::mkdir
+        | Sysno::rmdir
+        | Sysno::unlink
+        | Sysno::readlink
+        | Sysno::chmod
+        | Sysno::chown
+        | Sysno::lchown
+        | Sysno::rename
+        | Sysno::mknod => Some(0),
+        _ => None,
+    }
+}
+
+/// Enabled while at least one rule is loaded.
+static FAULT_RULES_ACTIVE: StaticKey = StaticKey::new(false);
+/// The loaded rules. Updates replace the whole list, so the dispatcher can
+/// walk a snapshot without holding the lock.
+static RULES: SpinNoIrq<Option<Arc<[Arc<FaultRule>]>>> = SpinNoIrq::new(None);
+/// Serializes updates together with the patching of the key.
+static UPDATE: Mutex<()> = Mutex::new(());
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+static RNG_STATE: AtomicU64 = AtomicU64::new(0x9e37_79b9_7f4a_7c15);
+
+fn snapshot() -> Option<Arc<[Arc<FaultRule>]>> {
+    RULES.lock().clone()
+}
+
+/// Replaces the loaded rules with `f` applied to them.
+fn update<T>(f: impl FnOnce(&mut Vec<Arc<FaultRule>>) -> T) -> T {
+    let _guard = UPDATE.lock();
+    let mut rules = snapshot().map_or_else(Vec::new, |rules| rules.to_vec());
+    let ret = f(&mut rules);
+    let active = !rules.is_empty();
+    *RULES.lock() = active.then(|| rules.into());
+    // Patch the gate outside the list lock: patching may log.
+    FAULT_RULES_ACTIVE.set(active);
+    ret
+}
+
+/// Loads a rule, returning its identifier.
+pub fn add_rule(spec: FaultSpec) -> KResult<u32> {
+    if !(1..=100).contains(&spec.probability) {
+        return Err(KError::InvalidInput);
+    }
+    update(|rules| {
+        if rules.len() >= MAX_RULES {
+            return Err(KError::NoMemory);
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        rules.push(Arc::new(FaultRule {
+            id,
+            spec,
+            hits: AtomicU64::new(0),
+        }));
+        Ok(id)
+    })
+}
+
+/// Unloads the rule `id`. Returns `false` if there is no such rule.
+pub fn remove_rule(id: u32) -> bool {
+    update(|rules| {
+        let len = rules.len();
+        rules.retain(|rule| rule.id != id);
+        rules.len() != len
+    })
+}
+
+/// Unloads all rules.
+pub fn clear_rules() {
+    update(|rules| rules.clear());
+}
+
+/// Returns the loaded rules, in the order they are tried.
+pub fn rules() -> Vec<Arc<FaultRule>> {
+    snapshot().map_or_else(Vec::new, |rules| rules.to_vec())
+}
+
+/// Applies one line written to `/proc/sys/debug/syscall_faults`: a rule to
+/// load, `-<id>` to unload one, or `clear`.
+pub fn apply_command(line: &str) -> KResult {
+    let line = line.trim();
+    if line.is_empty() {
+        Ok(())
+    } else if line == "clear" {
+        clear_rules();
+        Ok(())
+    } else if let Some(id) = line.strip_prefix('-') {
+        let id = id.parse().map_err(|_| KError::InvalidInput)?;
+        remove_rule(id).then_some(()).ok_or(KError::NotFound)
+    } else {
+        add_rule(line.parse()?).map(|_| ())
+    }
+}
+
+/// Formats the loaded rules, one per line with its identifier and hits.
+pub fn format_rules() -> String {
+    let mut out = String::new();
+    for rule in rules() {
+        let _ = writeln!(out, "{} {} hits={}", rule.id, rule.spec, rule.hits());
+    }
+    out
+}
+
+/// Returns a number from 0 to 99 for the probability rolls.
+fn roll() -> u32 {
+    let mut state = RNG_STATE.load(Ordering::Relaxed);
+    loop {
+        let mut next = state;
+        next ^= next << 13;
+        next ^= next >> 7;
+        next ^= next << 17;
+        match RNG_STATE.compare_exchange_weak(state, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return (next % 100) as u32,
+            Err(cur) => state = cur,
+        }
+    }
+}
+
+/// Returns the first rule hitting the current call of `sysno`, if any.
+fn find_hit(sysno: Sysno, uctx: &UserContext) -> Option<Arc<FaultRule>> {
+    let rules = snapshot()?;
+    if !rules.iter().any(|rule| rule.spec.sysno == sysno) {
+        return None;
+    }
+
+    let curr = current();
+    let comm = curr.name();
+    let path = if rules
+        .iter()
+        .any(|rule| rule.spec.sysno == sysno && rule.spec.path.is_some())
+    {
+        path_arg(sysno).and_then(|idx| {
+            let ptr = [uctx.arg0(), uctx.arg1()][idx];
+            vm_load_string(ptr as _).ok()
+        })
+    } else {
+        None
+    };
+    let call = Call {
+        sysno,
+        pid: curr.as_thread().proc_data.proc.pid(),
+        comm: &comm,
+        arg0: uctx.arg0(),
+        path: path.as_deref(),
+    };
+    rules
+        .iter()
+        .find(|rule| rule.matches(&call) && rule.try_hit(roll()))
+        .cloned()
+}
+
+/// Runs `handler` for the call, applying the first rule that hits it.
+#[inline(always)]
+pub(super) fn intercept(
+    sysno: Sysno,
+    uctx: &mut UserContext,
+    handler: fn(Sysno, &mut UserContext) -> KResult<isize>,
+) -> KResult<isize> {
+    if static_branch_unlikely!(FAULT_RULES_ACTIVE) {
+        return intercept_slow(sysno, uctx, handler);
+    }
+    handler(sysno, uctx)
+}
+
+#[cold]
+fn intercept_slow(
+    sysno: Sysno,
+    uctx: &mut UserContext,
+    handler: fn(Sysno, &mut UserContext) -> KResult<isize>,
+) -> KResult<isize> {
+    let Some(rule) = find_hit(sysno, uctx) else {
+        return handler(sysno, uctx);
+    };
+    debug!(
+        "Syscall {sysno}: injecting {:?} (rule {})",
+        rule.spec.action, rule.id
+    );
+    match rule.spec.action {
+        FaultAction::Fail(err) => Err(err.into()),
+        FaultAction::Delay(dur) => {
+            ktask::sleep(dur);
+            handler(sysno, uctx)
+        }
+        FaultAction::Truncate(len) => {
+            handler(sysno, uctx).map(|ret| ret.min(len.min(isize::MAX as usize) as isize))
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod fault_tests {
+    use alloc::format;
+
+    use unittest::def_test;
+
+    use super::*;
+
+    fn rule(spec: &str) -> FaultRule {
+        FaultRule {
+            id: 1,
+            spec: spec.parse().unwrap(),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    fn call(sysno: Sysno, arg0: usize, path: Option<&'static str>) -> Call<'static> {
+        Call {
+            sysno,
+            pid: 42,
+            comm: "cat",
+            arg0,
+            path,
+        }
+    }
+
+    #[def_test]
+    fn test_parse_round_trip() {
+        let spec: FaultSpec = "sys=read comm=cat fd=3 fail=EINTR prob=50 max=10"
+            .parse()
+            .unwrap();
+        assert_eq!(spec.sysno, Sysno::read);
+        assert_eq!(spec.comm.as_deref(), Some("cat"));
+        assert_eq!(spec.fd, Some(3));
+        assert_eq!(spec.action, FaultAction::Fail(LinuxError::EINTR));
+        assert_eq!((spec.probability, spec.max_hits), (50, 10));
+        assert_eq!(spec.to_string().parse::<FaultSpec>().unwrap(), spec);
+
+        // Syscalls and errnos can also be given by number.
+        let spec: FaultSpec = format!("sys={} fail=4", Sysno::read.id()).parse().unwrap();
+        assert_eq!(spec.sysno, Sysno::read);
+        assert_eq!(spec.action, FaultAction::Fail(LinuxError::EINTR));
+        assert_eq!((spec.probability, spec.max_hits), (100, 0));
+    }
+
+    #[def_test]
+    fn test_parse_rejects_bad_rules() {
+        for bad in [
+            "fail=EINTR",
+            "sys=read",
+            "sys=read fail=EINTR delay=10",
+            "sys=no_such_call fail=EINTR",
+            "sys=read fail=ENOTANERRNO",
+            "sys=read fail=0",
+            "sys=read fail=EINTR prob=0",
+            "sys=read fail=EINTR prob=101",
+            "sys=read fail=EINTR bogus=1",
+            "sys=read fail",
+        ] {
+            assert!(bad.parse::<FaultSpec>().is_err(), "{bad}");
+        }
+    }
+
+    #[def_test]
+    fn test_rule_matching() {
+        let r = rule("sys=read comm=cat fd=3 fail=EINTR");
+        assert!(r.matches(&call(Sysno::read, 3, None)));
+        assert!(!r.matches(&call(Sysno::read, 4, None)));
+        assert!(!r.matches(&call(Sysno::write, 3, None)));
+        assert!(!r.matches(&Call {
+            comm: "sh",
+            ..call(Sysno::read, 3, None)
+        }));
+
+        let r = rule("sys=openat path=/etc/* pid=42 delay=5");
+        assert!(r.matches(&call(Sysno::openat, 0, Some("/etc/passwd"))));
+        assert!(!r.matches(&call(Sysno::openat, 0, Some("/tmp/passwd"))));
+        // A path rule never matches a call whose path is unknown.
+        assert!(!r.matches(&call(Sysno::openat, 0, None)));
+
+        let r = rule("sys=openat path=/etc/passwd delay=5");
+        assert!(r.matches(&call(Sysno::openat, 0, Some("/etc/passwd"))));
+        assert!(!r.matches(&call(Sysno::openat, 0, Some("/etc/passwd.bak"))));
+    }
+
+    #[def_test]
+    fn test_rule_hits() {
+        let r = rule("sys=read fail=EINTR prob=30 max=2");
+        assert!(!r.try_hit(30));
+        assert!(r.try_hit(29));
+        assert!(r.try_hit(0));
+        // The rule stops firing once its hits are used up.
+        assert!(!r.try_hit(0));
+        assert_eq!(r.hits(), 2);
+
+        let r = rule("sys=read truncate=1");
+        assert!((0..100).all(|roll| r.try_hit(roll)));
+        assert_eq!(r.hits(), 100);
+    }
+}
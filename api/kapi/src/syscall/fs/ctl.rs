@@ -19,12 +19,13 @@ use core::{
     time::Duration,
 };
 
-use fs_ng_vfs::{MetadataUpdate, NodePermission, NodeType, path::Path};
-use kcore::task::AsThread;
+use fs_ng_vfs::{
+    DeviceId, DirEntrySink, Metadata, MetadataUpdate, NodePermission, NodeType, VfsResult,
+    path::Path,
+};
 use kerrno::{KError, KResult};
-use kfs::{FS_CONTEXT, FsContext};
+use kfs::FS_CONTEXT;
 use khal::time::wall_time;
-use ktask::current;
 use linux_raw_sys::{
     general::*,
     ioctl::{FIONBIO, TIOCGWINSZ},
@@ -32,8 +33,9 @@ use linux_raw_sys::{
 use osvm::{VirtPtr, write_vm_mem};
 
 use crate::{
-    file::{Directory, FileLike, get_file_like, resolve_at, with_fs},
-    mm::vm_load_string,
+    file::{Directory, FileLike, get_file_like, resolve_at, update_fd_metadata, with_fs},
+    mm::{vm_load_path, vm_load_path_allow_empty, vm_load_string},
+    syscall::sys::{sys_getegid, sys_geteuid},
     time::TimeValueLike,
 };
 
@@ -50,6 +52,9 @@ pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> KResult<isize> {
         f.set_nonblocking(val != 0)?;
         return Ok(0);
     }
+    if super::fscrypt::is_fscrypt_ioctl(cmd) {
+        return super::fscrypt::fscrypt_ioctl(fd, cmd, arg);
+    }
     f.ioctl(cmd, arg)
         .map(|result| result as isize)
         .inspect_err(|err| {
@@ -66,7 +71,7 @@ pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> KResult<isize> {
 
 /// Changes the current working directory.
 pub fn sys_chdir(path: *const c_char) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
     debug!("sys_chdir <= path: {path}");
 
     let mut fs = FS_CONTEXT.lock();
@@ -91,24 +96,24 @@ pub fn sys_mkdir(path: *const c_char, mode: u32) -> KResult<isize> {
 
 /// Changes the root directory of the calling process.
 pub fn sys_chroot(path: *const c_char) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
     debug!("sys_chroot <= path: {path}");
 
+    // Only root, which holds `CAP_SYS_CHROOT`, may.
+    if sys_geteuid()? != 0 {
+        return Err(KError::OperationNotPermitted);
+    }
     let mut fs = FS_CONTEXT.lock();
     let loc = fs.resolve(path)?;
-    if loc.node_type() != NodeType::Directory {
-        return Err(KError::NotADirectory);
-    }
-    *fs = FsContext::new(loc);
+    fs.set_root_dir(loc)?;
     Ok(0)
 }
 
 /// Creates a directory relative to a directory file descriptor.
 pub fn sys_mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
     debug!("sys_mkdirat <= dirfd: {dirfd}, path: {path}, mode: {mode}");
 
-    let mode = mode & !current().as_thread().proc_data.umask();
     let mode = NodePermission::from_bits_truncate(mode as u16);
 
     with_fs(dirfd, |fs| {
@@ -117,6 +122,43 @@ pub fn sys_mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> KResult<isize>
     })
 }
 
+#[cfg(target_arch = "x86_64")]
+pub fn sys_mknod(path: *const c_char, mode: u32, dev: u32) -> KResult<isize> {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
+/// Creates a filesystem node (device special file, FIFO, socket or regular
+/// file) relative to a directory file descriptor.
+pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u32) -> KResult<isize> {
+    let path = vm_load_path(path)?;
+    debug!("sys_mknodat <= dirfd: {dirfd}, path: {path}, mode: {mode:#o}, dev: {dev:#x}");
+
+    let node_type = match mode & S_IFMT {
+        0 | S_IFREG => NodeType::RegularFile,
+        S_IFCHR => NodeType::CharacterDevice,
+        S_IFBLK => NodeType::BlockDevice,
+        S_IFIFO => NodeType::Fifo,
+        S_IFSOCK => NodeType::Socket,
+        S_IFDIR => return Err(KError::OperationNotPermitted),
+        _ => return Err(KError::InvalidInput),
+    };
+    // Device nodes give access to hardware, so only root may create them.
+    if matches!(node_type, NodeType::CharacterDevice | NodeType::BlockDevice) && sys_geteuid()? != 0
+    {
+        return Err(KError::OperationNotPermitted);
+    }
+
+    let mode = NodePermission::from_bits_truncate((mode & !S_IFMT) as u16);
+    // The kernel ABI passes `dev` in the 32-bit `new_encode_dev` form, which
+    // matches the low half of `DeviceId`.
+    let rdev = DeviceId(dev as u64);
+
+    with_fs(dirfd, |fs| {
+        fs.mknod(path, node_type, mode, rdev)?;
+        Ok(0)
+    })
+}
+
 // Directory buffer for getdents64 syscall
 struct DirBuffer {
     buf: Vec<u8>,
@@ -145,16 +187,19 @@ impl DirBuffer {
             return false;
         }
 
-        // FIXME: safety
+        // SAFETY: `len` bytes from `self.offset` are in bounds, and the byte
+        // buffer carries no alignment guarantee, hence the unaligned write.
         unsafe {
             let entry_ptr = self.buf.as_mut_ptr().add(self.offset);
-            entry_ptr.cast::<linux_dirent64>().write(linux_dirent64 {
-                d_ino,
-                d_off,
-                d_reclen: len as _,
-                d_type: d_type as _,
-                d_name: Default::default(),
-            });
+            entry_ptr
+                .cast::<linux_dirent64>()
+                .write_unaligned(linux_dirent64 {
+                    d_ino,
+                    d_off,
+                    d_reclen: len as _,
+                    d_type: d_type as _,
+                    d_name: Default::default(),
+                });
 
             let name_ptr = entry_ptr.add(NAME_OFFSET);
             name_ptr.copy_from_nonoverlapping(name.as_ptr(), name.len());
@@ -170,30 +215,48 @@ impl DirBuffer {
 pub fn sys_getdents64(fd: i32, buf: *mut u8, len: usize) -> KResult<isize> {
     debug!("sys_getdents64 <= fd: {fd}, buf: {buf:?}, len: {len}");
 
-    let mut buffer = DirBuffer::new(len);
+    let mut buffer = DirBuffer::new(len.min(MAX_GETDENTS_BUFFER));
 
     let dir = Directory::from_fd(fd)?;
     let mut dir_offset = dir.offset.lock();
+    fill_dirents(
+        |offset, sink| dir.inner().read_dir(offset, sink),
+        &mut dir_offset,
+        &mut buffer,
+    )?;
 
-    let mut has_remaining = false;
+    write_vm_mem(buf, &buffer.buf[..buffer.offset])?;
 
-    dir.inner()
-        .read_dir(*dir_offset, &mut |name: &str, ino, node_type, offset| {
-            has_remaining = true;
-            if !buffer.write_entry(ino, offset as _, node_type, name.as_bytes()) {
-                return false;
-            }
-            *dir_offset = offset;
-            true
-        })?;
+    Ok(buffer.offset as _)
+}
+
+/// Upper bound on the kernel-side buffer of a single `getdents64` call; a
+/// larger user buffer just gets a short read.
+const MAX_GETDENTS_BUFFER: usize = 64 * 1024;
+
+/// Packs entries from the cookie `*cookie` on into `buffer`, advancing
+/// `*cookie` past every entry that fit.
+///
+/// Fails with `EINVAL` if not even the first remaining entry fits.
+fn fill_dirents(
+    read_dir: impl FnOnce(u64, &mut dyn DirEntrySink) -> VfsResult<usize>,
+    cookie: &mut u64,
+    buffer: &mut DirBuffer,
+) -> KResult<()> {
+    let mut has_remaining = false;
+    read_dir(*cookie, &mut |name: &str, ino, node_type, offset| {
+        has_remaining = true;
+        if !buffer.write_entry(ino, offset as _, node_type, name.as_bytes()) {
+            return false;
+        }
+        *cookie = offset;
+        true
+    })?;
 
     if has_remaining && buffer.offset == 0 {
         return Err(KError::InvalidInput);
     }
-
-    write_vm_mem(buf, &buffer.buf)?;
-
-    Ok(buffer.offset as _)
+    Ok(())
 }
 
 /// create a link from new_path to old_path
@@ -209,8 +272,11 @@ pub fn sys_linkat(
     new_path: *const c_char,
     flags: u32,
 ) -> KResult<isize> {
-    let old_path = old_path.check_non_null().map(vm_load_string).transpose()?;
-    let new_path = vm_load_string(new_path)?;
+    let old_path = old_path
+        .check_non_null()
+        .map(vm_load_path_allow_empty)
+        .transpose()?;
+    let new_path = vm_load_path(new_path)?;
     debug!(
         "sys_linkat <= old_dirfd: {old_dirfd}, old_path: {old_path:?}, new_dirfd: {new_dirfd}, \
          new_path: {new_path}, flags: {flags}"
@@ -226,8 +292,7 @@ pub fn sys_linkat(
     if old.is_dir() {
         return Err(KError::OperationNotPermitted);
     }
-    let (new_dir, new_name) =
-        with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    let (new_dir, new_name) = with_fs(new_dirfd, |fs| fs.resolve_new_file(Path::new(&new_path)))?;
 
     new_dir.link(new_name, &old)?;
     Ok(0)
@@ -245,7 +310,7 @@ pub fn sys_link(old_path: *const c_char, new_path: *const c_char) -> KResult<isi
 /// return 0 when success, else return -1
 /// Removes a directory entry (file or directory).
 pub fn sys_unlinkat(dirfd: i32, path: *const c_char, flags: usize) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
 
     debug!("sys_unlinkat <= dirfd: {dirfd}, path: {path:?}, flags: {flags}");
 
@@ -303,7 +368,7 @@ pub fn sys_symlinkat(
     linkpath: *const c_char,
 ) -> KResult<isize> {
     let target = vm_load_string(target)?;
-    let linkpath = vm_load_string(linkpath)?;
+    let linkpath = vm_load_path(linkpath)?;
     debug!("sys_symlinkat <= target: {target:?}, new_dirfd: {new_dirfd}, linkpath: {linkpath:?}");
 
     with_fs(new_dirfd, |fs| {
@@ -324,7 +389,7 @@ pub fn sys_readlinkat(
     buf: *mut u8,
     size: usize,
 ) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
 
     debug!("sys_readlinkat <= dirfd: {dirfd}, path: {path:?}");
 
@@ -352,6 +417,42 @@ pub fn sys_fchown(fd: i32, uid: i32, gid: i32) -> KResult<isize> {
     sys_fchownat(fd, core::ptr::null(), uid, gid, AT_EMPTY_PATH)
 }
 
+/// Changes the metadata of the inode named by `dirfd`, `path` and `flags`.
+///
+/// With `AT_EMPTY_PATH` and no path, the change goes straight to the inode
+/// behind `dirfd` (see [`update_fd_metadata`]); otherwise `path` is resolved
+/// relative to `dirfd`. `f` computes the update from the current metadata.
+fn change_metadata(
+    dirfd: i32,
+    path: *const c_char,
+    flags: u32,
+    f: impl FnOnce(&Metadata) -> KResult<MetadataUpdate>,
+) -> KResult<()> {
+    let path = path
+        .check_non_null()
+        .map(vm_load_path_allow_empty)
+        .transpose()?;
+    if matches!(path.as_deref(), None | Some("")) && flags & AT_EMPTY_PATH != 0 {
+        return update_fd_metadata(dirfd, f);
+    }
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
+        .into_file()
+        .ok_or(KError::BadFileDescriptor)?;
+    let update = f(&loc.metadata()?)?;
+    loc.update_metadata(update)?;
+    Ok(())
+}
+
+/// Checks that the caller owns the inode described by `meta`, or is root.
+fn check_owner(meta: &Metadata) -> KResult<()> {
+    let euid = sys_geteuid()? as u32;
+    if euid == 0 || euid == meta.uid {
+        Ok(())
+    } else {
+        Err(KError::OperationNotPermitted)
+    }
+}
+
 /// Changes file ownership relative to a directory file descriptor.
 pub fn sys_fchownat(
     dirfd: i32,
@@ -360,26 +461,30 @@ pub fn sys_fchownat(
     gid: i32,
     flags: u32,
 ) -> KResult<isize> {
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
-    let loc = resolve_at(dirfd, path.as_deref(), flags)?
-        .into_file()
-        .ok_or(KError::BadFileDescriptor)?;
-    let meta = loc.metadata()?;
-
-    let mut mode = meta.mode;
-    // chown always clears the setuid bits
-    mode.remove(NodePermission::SET_UID);
-    // chown also removes the setgid bits if group-executable
-    if mode.contains(NodePermission::GROUP_EXEC) {
-        mode.remove(NodePermission::SET_GID);
-    }
+    change_metadata(dirfd, path, flags, |meta| {
+        let uid = if uid == -1 { meta.uid } else { uid as _ };
+        let gid = if gid == -1 { meta.gid } else { gid as _ };
+        check_owner(meta)?;
+        // Only root may give a file away; the owner may only move it to its
+        // own group.
+        if sys_geteuid()? != 0
+            && (uid != meta.uid || (gid != meta.gid && gid != sys_getegid()? as u32))
+        {
+            return Err(KError::OperationNotPermitted);
+        }
 
-    let uid = if uid == -1 { meta.uid } else { uid as _ };
-    let gid = if gid == -1 { meta.gid } else { gid as _ };
-    loc.update_metadata(MetadataUpdate {
-        owner: Some((uid, gid)),
-        mode: Some(mode),
-        ..Default::default()
+        let mut mode = meta.mode;
+        // chown always clears the setuid bits
+        mode.remove(NodePermission::SET_UID);
+        // chown also removes the setgid bits if group-executable
+        if mode.contains(NodePermission::GROUP_EXEC) {
+            mode.remove(NodePermission::SET_GID);
+        }
+        Ok(MetadataUpdate {
+            owner: Some((uid, gid)),
+            mode: Some(mode),
+            ..Default::default()
+        })
     })?;
     Ok(0)
 }
@@ -396,14 +501,13 @@ pub fn sys_fchmod(fd: i32, mode: u32) -> KResult<isize> {
 
 /// Changes file permissions relative to a directory file descriptor.
 pub fn sys_fchmodat(dirfd: i32, path: *const c_char, mode: u32, flags: u32) -> KResult<isize> {
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
-        .into_file()
-        .ok_or(KError::BadFileDescriptor)?
-        .update_metadata(MetadataUpdate {
+    change_metadata(dirfd, path, flags, |meta| {
+        check_owner(meta)?;
+        Ok(MetadataUpdate {
             mode: Some(NodePermission::from_bits_truncate(mode as u16)),
             ..Default::default()
-        })?;
+        })
+    })?;
     Ok(0)
 }
 
@@ -414,16 +518,14 @@ fn update_times(
     mtime: Option<Duration>,
     flags: u32,
 ) -> KResult<()> {
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
-        .into_file()
-        .ok_or(KError::BadFileDescriptor)?
-        .update_metadata(MetadataUpdate {
+    change_metadata(dirfd, path, flags, |meta| {
+        check_owner(meta)?;
+        Ok(MetadataUpdate {
             atime,
             mtime,
             ..Default::default()
-        })?;
-    Ok(())
+        })
+    })
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -525,8 +627,8 @@ pub fn sys_renameat2(
     new_path: *const c_char,
     flags: u32,
 ) -> KResult<isize> {
-    let old_path = vm_load_string(old_path)?;
-    let new_path = vm_load_string(new_path)?;
+    let old_path = vm_load_path(old_path)?;
+    let new_path = vm_load_path(new_path)?;
     debug!(
         "sys_renameat2 <= old_dirfd: {old_dirfd}, old_path: {old_path:?}, new_dirfd: {new_dirfd}, \
          new_path: {new_path}, flags: {flags}"
@@ -535,6 +637,10 @@ pub fn sys_renameat2(
     let (old_dir, old_name) = with_fs(old_dirfd, |fs| fs.resolve_parent(Path::new(&old_path)))?;
     let (new_dir, new_name) =
         with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    // With a trailing slash on either path, only a directory may move.
+    if Path::new(&old_path).has_trailing_slash() || Path::new(&new_path).has_trailing_slash() {
+        old_dir.lookup_no_follow(&old_name)?.check_is_dir()?;
+    }
 
     old_dir.rename(&old_name, &new_dir, new_name)?;
     Ok(0)
@@ -549,3 +655,98 @@ pub fn sys_syncfs(_fd: i32) -> KResult<isize> {
     warn!("dummy sys_syncfs");
     Ok(0)
 }
+
+#[cfg(unittest)]
+mod tests_getdents {
+    use alloc::{format, string::String, vec::Vec};
+
+    use fs_ng_vfs::{DirEntrySink, NodeType, VfsResult};
+    use linux_raw_sys::general::linux_dirent64;
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::{DirBuffer, fill_dirents};
+
+    /// Directory whose cookies are sparse, like the byte offsets of ext4.
+    fn read_dir(names: &[String], offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let mut count = 0;
+        for (i, name) in names.iter().enumerate() {
+            let cookie = (i as u64 + 1) * 24;
+            if cookie <= offset {
+                continue;
+            }
+            if !sink.accept(name, i as u64 + 2, NodeType::RegularFile, cookie) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn parse(buffer: &DirBuffer) -> Vec<(String, i64)> {
+        let name_offset = core::mem::offset_of!(linux_dirent64, d_name);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos < buffer.offset {
+            let dirent = unsafe {
+                buffer
+                    .buf
+                    .as_ptr()
+                    .add(pos)
+                    .cast::<linux_dirent64>()
+                    .read_unaligned()
+            };
+            let reclen = dirent.d_reclen as usize;
+            assert_eq!(reclen % align_of::<linux_dirent64>(), 0);
+            let name = &buffer.buf[pos + name_offset..pos + reclen];
+            let len = name.iter().position(|&b| b == 0).unwrap();
+            entries.push((
+                String::from_utf8(name[..len].to_vec()).unwrap(),
+                dirent.d_off,
+            ));
+            pos += reclen;
+        }
+        entries
+    }
+
+    #[def_test]
+    fn test_fill_dirents_resumes_across_calls() {
+        let names: Vec<String> = (0..200).map(|i| format!("file{i}")).collect();
+        let mut cookie = 0;
+        let mut seen = Vec::new();
+        let mut calls = 0;
+        loop {
+            let mut buffer = DirBuffer::new(1024);
+            fill_dirents(
+                |offset, sink| read_dir(&names, offset, sink),
+                &mut cookie,
+                &mut buffer,
+            )
+            .unwrap();
+            if buffer.offset == 0 {
+                break;
+            }
+            calls += 1;
+            let entries = parse(&buffer);
+            assert_eq!(entries.last().unwrap().1 as u64, cookie);
+            seen.extend(entries.into_iter().map(|(name, _)| name));
+        }
+        assert!(calls >= 3);
+        assert_eq!(seen, names);
+    }
+
+    #[def_test]
+    fn test_fill_dirents_buffer_too_small() {
+        let names: Vec<String> = (0..3).map(|i| format!("file{i}")).collect();
+        let mut cookie = 0;
+        let mut buffer = DirBuffer::new(8);
+        assert!(
+            fill_dirents(
+                |offset, sink| read_dir(&names, offset, sink),
+                &mut cookie,
+                &mut buffer
+            )
+            .is_err()
+        );
+        assert_eq!(cookie, 0);
+    }
+}
@@ -13,26 +13,37 @@
 use alloc::{format, string::ToString, sync::Arc};
 use core::{
     ffi::{c_char, c_int},
-    mem,
-    ops::{Deref, DerefMut},
+    future::poll_fn,
+    task::Poll,
 };
 
 use bitflags::bitflags;
-use fs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference};
-use kcore::{task::AsThread, vfs::Device};
-use kerrno::{KError, KResult};
-use kfs::{FS_CONTEXT, FileBackend, OpenOptions, OpenResult};
-use ktask::current;
+use fs_ng_vfs::{
+    DirEntry, FileNode, Location, LockOwner, LockType, NodePermission, NodeType, RecordLock,
+    Reference, cancel_lock_wait,
+};
+use kcore::{
+    task::AsThread,
+    vfs::{DeviceFile, DeviceKind, device_ops, open_device},
+};
+use kerrno::{KError, KResult, LinuxError};
+use kfs::{FS_CONTEXT, FileBackend, FileFlags, OpenOptions, OpenResult};
+use kio::{Seek, SeekFrom};
+use ktask::{
+    current,
+    future::{block_on, interruptible},
+};
 use linux_raw_sys::general::*;
 
 use crate::{
     file::{
-        Directory, FD_TABLE, File, FileLike, Pipe, add_file_like, close_file_like, get_file_like,
-        with_fs,
+        Directory, FD_TABLE, File, FileLike, Pipe, add_file_like, add_file_like_from,
+        close_file_like, get_file_like, lock_entry, open_file_owner, release_record_locks,
+        unshare_fd_table, with_fs,
     },
-    mm::{UserPtr, vm_load_string},
+    mm::{UserPtr, vm_load_path},
     syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    vfs::dev::{procevents::ProcEvents, tty},
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -82,9 +93,26 @@ fn flags_to_options(flags: c_int, mode: __kernel_mode_t, (uid, gid): (u32, u32))
 fn add_to_fd(result: OpenResult, flags: u32) -> KResult<i32> {
     let f: Arc<dyn FileLike> = match result {
         OpenResult::File(mut file) => {
+            // Device nodes outside devfs reach their driver through the
+            // device number registry.
+            let metadata = file.location().metadata()?;
+            if flags & O_PATH == 0
+                && let Some(kind) = DeviceKind::from_node_type(metadata.node_type)
+                && device_ops(file.location().entry()).is_none()
+            {
+                let ops = open_device(kind, metadata.rdev)?;
+                let entry = file.location().entry();
+                let entry = DirEntry::new_file(
+                    FileNode::new(DeviceFile::new(entry.as_file()?.inner().clone(), ops)),
+                    metadata.node_type,
+                    Reference::new(entry.parent(), entry.name().to_string()),
+                );
+                let loc = Location::new(file.location().mountpoint().clone(), entry);
+                file = kfs::File::new(FileBackend::Direct(loc), file.flags());
+            }
             // /dev/xx handling
-            if let Ok(device) = file.location().entry().downcast::<Device>() {
-                let inner = device.inner().as_any();
+            if let Some(ops) = device_ops(file.location().entry()) {
+                let inner = ops.as_any();
                 if let Some(ptmx) = inner.downcast_ref::<tty::Ptmx>() {
                     // Opening /dev/ptmx creates a new pseudo-terminal
                     let (master, pty_number) = ptmx.create_pty()?;
@@ -97,6 +125,16 @@ fn add_to_fd(result: OpenResult, flags: u32) -> KResult<i32> {
                     );
                     let loc = Location::new(file.location().mountpoint().clone(), entry);
                     file = kfs::File::new(FileBackend::Direct(loc), file.flags());
+                } else if let Some(events) = inner.downcast_ref::<ProcEvents>() {
+                    // Every open of /dev/procevents is a listener of its own
+                    let entry = file.location().entry();
+                    let entry = DirEntry::new_file(
+                        FileNode::new(events.open()),
+                        NodeType::CharacterDevice,
+                        Reference::new(entry.parent(), entry.name().to_string()),
+                    );
+                    let loc = Location::new(file.location().mountpoint().clone(), entry);
+                    file = kfs::File::new(FileBackend::Direct(loc), file.flags());
                 } else if inner.is::<tty::CurrentTty>() {
                     let term = current()
                         .as_thread()
@@ -119,6 +157,7 @@ fn add_to_fd(result: OpenResult, flags: u32) -> KResult<i32> {
             }
             Arc::new(File::new(file))
         }
+        OpenResult::Dir(dir) if flags & O_PATH != 0 => Arc::new(Directory::new_path(dir)),
         OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
     };
     if flags & O_NONBLOCK != 0 {
@@ -140,11 +179,9 @@ pub fn sys_openat(
     flags: i32,
     mode: __kernel_mode_t,
 ) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
     debug!("sys_openat <= {dirfd} {path:?} {flags:#o} {mode:#o}");
 
-    let mode = mode & !current().as_thread().proc_data.umask();
-
     let options = flags_to_options(flags, mode, (sys_geteuid()? as _, sys_getegid()? as _));
     with_fs(dirfd, |fs| options.open(fs, path))
         .and_then(|it| add_to_fd(it, flags as _))
@@ -184,12 +221,7 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> KResult<isize> {
     let flags = CloseRangeFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
     debug!("sys_close_range <= fds: [{first}, {last}], flags: {flags:?}");
     if flags.contains(CloseRangeFlags::UNSHARE) {
-        // TODO: optimize
-        let curr = current();
-        let mut scope = curr.as_thread().proc_data.scope.write();
-        let mut guard = FD_TABLE.scope_mut(&mut scope);
-        let old_files = mem::take(guard.deref_mut());
-        old_files.write().clone_from(old_files.read().deref());
+        unshare_fd_table();
     }
 
     let cloexec = flags.contains(CloseRangeFlags::CLOEXEC);
@@ -200,8 +232,8 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> KResult<isize> {
                 if let Some(f) = fd_table.get_mut(fd as _) {
                     f.cloexec = true;
                 }
-            } else {
-                fd_table.remove(fd as _);
+            } else if let Some(f) = fd_table.remove(fd as _) {
+                release_record_locks(f.inner.as_ref());
             }
         }
     }
@@ -209,17 +241,18 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> KResult<isize> {
     Ok(0)
 }
 
-/// Duplicates a file descriptor and optionally sets `CLOEXEC`.
-fn dup_fd(old_fd: c_int, cloexec: bool) -> KResult<isize> {
+/// Duplicates a file descriptor to the lowest free one not below `min_fd`,
+/// and optionally sets `CLOEXEC`.
+fn dup_fd(old_fd: c_int, min_fd: usize, cloexec: bool) -> KResult<isize> {
     let f = get_file_like(old_fd)?;
-    let new_fd = add_file_like(f, cloexec)?;
+    let new_fd = add_file_like_from(f, min_fd, cloexec)?;
     Ok(new_fd as _)
 }
 
 /// Duplicates a file descriptor.
 pub fn sys_dup(old_fd: c_int) -> KResult<isize> {
     debug!("sys_dup <= {old_fd}");
-    dup_fd(old_fd, false)
+    dup_fd(old_fd, 0, false)
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -247,6 +280,10 @@ pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> KResult<isize> {
     if old_fd == new_fd {
         return Err(KError::InvalidInput);
     }
+    let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    if new_fd < 0 || new_fd as u64 >= max_nofile {
+        return Err(KError::BadFileDescriptor);
+    }
 
     let mut fd_table = FD_TABLE.write();
     let mut f = fd_table
@@ -255,7 +292,9 @@ pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> KResult<isize> {
         .ok_or(KError::BadFileDescriptor)?;
     f.cloexec = flags.contains(Dup3Flags::O_CLOEXEC);
 
-    fd_table.remove(new_fd as _);
+    if let Some(old) = fd_table.remove(new_fd as _) {
+        release_record_locks(old.inner.as_ref());
+    }
     fd_table
         .add_at(new_fd as _, f)
         .map_err(|_| KError::BadFileDescriptor)?;
@@ -268,13 +307,20 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> KResult<isize> {
     debug!("sys_fcntl <= fd: {fd} cmd: {cmd} arg: {arg}");
 
     match cmd as u32 {
-        F_DUPFD => dup_fd(fd, false),
-        F_DUPFD_CLOEXEC => dup_fd(fd, true),
-        F_SETLK | F_SETLKW => Ok(0),
-        F_OFD_SETLK | F_OFD_SETLKW => Ok(0),
+        F_DUPFD => dup_fd(fd, arg, false),
+        F_DUPFD_CLOEXEC => dup_fd(fd, arg, true),
+        F_SETLK | F_SETLKW | F_OFD_SETLK | F_OFD_SETLKW => {
+            let ofd = matches!(cmd as u32, F_OFD_SETLK | F_OFD_SETLKW);
+            let wait = matches!(cmd as u32, F_SETLKW | F_OFD_SETLKW);
+            let f = get_file_like(fd)?;
+            let lock = UserPtr::<flock64>::from(arg).get_as_mut()?;
+            set_record_lock(f.as_ref(), lock, ofd, wait)?;
+            Ok(0)
+        }
         F_GETLK | F_OFD_GETLK => {
-            let arg = UserPtr::<flock64>::from(arg);
-            arg.get_as_mut()?.l_type = F_UNLCK as _;
+            let f = get_file_like(fd)?;
+            let lock = UserPtr::<flock64>::from(arg).get_as_mut()?;
+            get_record_lock(f.as_ref(), lock, cmd as u32 == F_OFD_GETLK)?;
             Ok(0)
         }
         F_SETFL => {
@@ -333,9 +379,153 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> KResult<isize> {
     }
 }
 
+/// Returns the owner of the record locks taken through `f`: the open file
+/// for open file description locks, the current process otherwise.
+fn record_lock_owner(f: &dyn FileLike, ofd: bool) -> (LockOwner, Option<u32>) {
+    if ofd {
+        (open_file_owner(f), None)
+    } else {
+        let pid = current().as_thread().proc_data.proc.pid();
+        (pid as LockOwner, Some(pid))
+    }
+}
+
+/// Converts the `flock64` of `fcntl` into a [`RecordLock`], along with
+/// whether it asks for `F_UNLCK`.
+fn to_record_lock(
+    f: &dyn FileLike,
+    entry: &DirEntry,
+    lock: &flock64,
+    ofd: bool,
+) -> KResult<(RecordLock, bool)> {
+    if ofd && lock.l_pid != 0 {
+        return Err(KError::InvalidInput);
+    }
+    let (ty, unlock) = match lock.l_type as u32 {
+        F_RDLCK => (LockType::Read, false),
+        F_WRLCK => (LockType::Write, false),
+        F_UNLCK => (LockType::Read, true),
+        _ => return Err(KError::InvalidInput),
+    };
+    let base = match lock.l_whence as u32 {
+        SEEK_SET => 0,
+        SEEK_CUR => match f.downcast_ref::<File>() {
+            Some(file) => file.inner().seek(SeekFrom::Current(0))?,
+            None => 0,
+        },
+        SEEK_END => entry.len()?,
+        _ => return Err(KError::InvalidInput),
+    };
+    let start = base
+        .checked_add_signed(lock.l_start)
+        .ok_or(KError::InvalidInput)?;
+    // A zero length extends to the end of the file, a negative one covers
+    // the bytes before `start`.
+    let (start, end) = match lock.l_len {
+        0 => (start, u64::MAX),
+        len if len > 0 => {
+            let end = start
+                .checked_add(len as u64 - 1)
+                .ok_or(KError::from(LinuxError::EOVERFLOW))?;
+            (start, end)
+        }
+        len => {
+            let first = start.checked_add_signed(len).ok_or(KError::InvalidInput)?;
+            (first, start - 1)
+        }
+    };
+    let (owner, pid) = record_lock_owner(f, ofd);
+    let record = RecordLock {
+        owner,
+        pid,
+        ty,
+        start,
+        end,
+    };
+    Ok((record, unlock))
+}
+
+/// Handles `F_SETLK` and `F_SETLKW` and their open file description variants.
+fn set_record_lock(f: &dyn FileLike, lock: &flock64, ofd: bool, wait: bool) -> KResult {
+    let entry = lock_entry(f).ok_or(KError::BadFileDescriptor)?;
+    let (record, unlock) = to_record_lock(f, entry, lock, ofd)?;
+    if unlock {
+        return entry.unlock_records(record.owner, record.start, record.end);
+    }
+    if let Some(file) = f.downcast_ref::<File>() {
+        let needed = match record.ty {
+            LockType::Read => FileFlags::READ,
+            LockType::Write => FileFlags::WRITE,
+        };
+        if !file.inner().flags().contains(needed) {
+            return Err(KError::BadFileDescriptor);
+        }
+    }
+
+    if !wait {
+        return entry.set_record_lock(record, None);
+    }
+    let res = block_on(interruptible(poll_fn(|cx| {
+        match entry.set_record_lock(record, Some(cx.waker())) {
+            Err(KError::WouldBlock) => Poll::Pending,
+            res => Poll::Ready(res),
+        }
+    })));
+    if res.is_err() {
+        cancel_lock_wait(record.owner);
+    }
+    res?
+}
+
+/// Handles `F_GETLK` and `F_OFD_GETLK`.
+fn get_record_lock(f: &dyn FileLike, lock: &mut flock64, ofd: bool) -> KResult {
+    let entry = lock_entry(f).ok_or(KError::BadFileDescriptor)?;
+    let (record, unlock) = to_record_lock(f, entry, lock, ofd)?;
+    if unlock {
+        return Err(KError::InvalidInput);
+    }
+    let Some(conflict) = entry.test_record_lock(&record) else {
+        lock.l_type = F_UNLCK as _;
+        return Ok(());
+    };
+    lock.l_type = match conflict.ty {
+        LockType::Read => F_RDLCK,
+        LockType::Write => F_WRLCK,
+    } as _;
+    lock.l_whence = SEEK_SET as _;
+    lock.l_start = conflict.start as _;
+    lock.l_len = if conflict.end == u64::MAX {
+        0
+    } else {
+        (conflict.end - conflict.start + 1) as _
+    };
+    lock.l_pid = conflict.pid.map_or(-1, |pid| pid as _);
+    Ok(())
+}
+
 /// Applies or removes an advisory lock on a file descriptor.
 pub fn sys_flock(fd: c_int, operation: c_int) -> KResult<isize> {
     debug!("flock <= fd: {fd}, operation: {operation}");
-    // TODO: flock
+    let operation = operation as u32;
+    let ty = match operation & !LOCK_NB {
+        LOCK_SH => Some(LockType::Read),
+        LOCK_EX => Some(LockType::Write),
+        LOCK_UN => None,
+        _ => return Err(KError::InvalidInput),
+    };
+    let f = get_file_like(fd)?;
+    let entry = lock_entry(f.as_ref()).ok_or(KError::BadFileDescriptor)?;
+    let owner = open_file_owner(f.as_ref());
+
+    if ty.is_none() || operation & LOCK_NB != 0 {
+        entry.flock(owner, ty, None)?;
+    } else {
+        block_on(interruptible(poll_fn(|cx| {
+            match entry.flock(owner, ty, Some(cx.waker())) {
+                Err(KError::WouldBlock) => Poll::Pending,
+                res => Poll::Ready(res),
+            }
+        })))??;
+    }
     Ok(0)
 }
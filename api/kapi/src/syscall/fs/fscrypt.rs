@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Filesystem encryption ioctls.
+//!
+//! Implements the v2 policy and key management subset of the Linux fscrypt
+//! ioctl interface on top of [`kfs::fscrypt`]. Keys are kept in one global
+//! keyring rather than one per filesystem, and only keys specified by
+//! identifier are supported.
+
+use kerrno::{KError, KResult, LinuxError};
+use kfs::fscrypt::{self, EncryptionPolicy, KEY_IDENTIFIER_SIZE, KeyIdentifier, MAX_KEY_SIZE};
+use osvm::{load_vec, write_vm_mem};
+
+use crate::file::resolve_at;
+
+const FS_IOC_SET_ENCRYPTION_POLICY: u32 = 0x800c_6613;
+const FS_IOC_GET_ENCRYPTION_POLICY_EX: u32 = 0xc009_6616;
+const FS_IOC_ADD_ENCRYPTION_KEY: u32 = 0xc050_6617;
+const FS_IOC_REMOVE_ENCRYPTION_KEY: u32 = 0xc040_6618;
+const FS_IOC_REMOVE_ENCRYPTION_KEY_ALL_USERS: u32 = 0xc040_6619;
+const FS_IOC_GET_ENCRYPTION_KEY_STATUS: u32 = 0xc080_661a;
+
+const FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER: u32 = 2;
+const FSCRYPT_KEY_STATUS_ABSENT: u32 = 1;
+const FSCRYPT_KEY_STATUS_PRESENT: u32 = 2;
+
+/// Size of `struct fscrypt_key_specifier`.
+const KEY_SPEC_SIZE: usize = 40;
+/// Size of `struct fscrypt_add_key_arg`, without the raw key.
+const ADD_KEY_ARG_SIZE: usize = 80;
+/// Size of `struct fscrypt_remove_key_arg`.
+const REMOVE_KEY_ARG_SIZE: usize = 64;
+/// Size of `struct fscrypt_get_key_status_arg`.
+const KEY_STATUS_ARG_SIZE: usize = 128;
+
+/// Returns whether `cmd` is one of the ioctls handled here.
+pub(super) fn is_fscrypt_ioctl(cmd: u32) -> bool {
+    matches!(
+        cmd,
+        FS_IOC_SET_ENCRYPTION_POLICY
+            | FS_IOC_GET_ENCRYPTION_POLICY_EX
+            | FS_IOC_ADD_ENCRYPTION_KEY
+            | FS_IOC_REMOVE_ENCRYPTION_KEY
+            | FS_IOC_REMOVE_ENCRYPTION_KEY_ALL_USERS
+            | FS_IOC_GET_ENCRYPTION_KEY_STATUS
+    )
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+/// Parses a `struct fscrypt_key_specifier` naming a key by identifier.
+fn key_identifier(spec: &[u8]) -> KResult<KeyIdentifier> {
+    if read_u32(spec, 0) != FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER || read_u32(spec, 4) != 0 {
+        return Err(KError::InvalidInput);
+    }
+    Ok(spec[8..8 + KEY_IDENTIFIER_SIZE].try_into().unwrap())
+}
+
+/// Handles the fscrypt ioctl `cmd` on the file descriptor `fd`.
+pub(super) fn fscrypt_ioctl(fd: i32, cmd: u32, arg: usize) -> KResult<isize> {
+    let loc = resolve_at(fd, None, linux_raw_sys::general::AT_EMPTY_PATH)?
+        .into_file()
+        .ok_or(KError::NotATty)?;
+    match cmd {
+        FS_IOC_SET_ENCRYPTION_POLICY => {
+            let version = load_vec(arg as *const u8, 1)?[0];
+            if version != fscrypt::POLICY_V2 {
+                return Err(KError::InvalidInput);
+            }
+            let bytes = load_vec(arg as *const u8, EncryptionPolicy::SIZE)?;
+            let policy = EncryptionPolicy::from_bytes(&bytes)?;
+            fscrypt::set_policy(&loc, &policy)?;
+        }
+        FS_IOC_GET_ENCRYPTION_POLICY_EX => {
+            let size = u64::from_ne_bytes(load_vec(arg as *const u8, 8)?.try_into().unwrap());
+            if size < EncryptionPolicy::SIZE as u64 {
+                return Err(LinuxError::EOVERFLOW.into());
+            }
+            let policy = fscrypt::get_policy(&loc)?;
+            write_vm_mem(
+                arg as *mut u8,
+                &(EncryptionPolicy::SIZE as u64).to_ne_bytes(),
+            )?;
+            write_vm_mem((arg + 8) as *mut u8, &policy.to_bytes())?;
+        }
+        FS_IOC_ADD_ENCRYPTION_KEY => {
+            let header = load_vec(arg as *const u8, ADD_KEY_ARG_SIZE)?;
+            let raw_size = read_u32(&header, KEY_SPEC_SIZE) as usize;
+            let key_id = read_u32(&header, KEY_SPEC_SIZE + 4);
+            // Keys in the kernel keyring and v1 descriptors are not supported.
+            if read_u32(&header, 0) != FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER
+                || key_id != 0
+                || raw_size > MAX_KEY_SIZE
+                || header[KEY_SPEC_SIZE + 8..].iter().any(|&b| b != 0)
+            {
+                return Err(KError::InvalidInput);
+            }
+            let mut raw = load_vec((arg + ADD_KEY_ARG_SIZE) as *const u8, raw_size)?;
+            let identifier = fscrypt::add_key(&raw);
+            kcrypto::zeroize(&mut raw);
+            write_vm_mem((arg + 8) as *mut u8, &identifier?)?;
+        }
+        FS_IOC_REMOVE_ENCRYPTION_KEY | FS_IOC_REMOVE_ENCRYPTION_KEY_ALL_USERS => {
+            let buf = load_vec(arg as *const u8, REMOVE_KEY_ARG_SIZE)?;
+            fscrypt::remove_key(&key_identifier(&buf[..KEY_SPEC_SIZE])?)?;
+            // No files are left busy, so there are no status flags to report.
+            write_vm_mem((arg + KEY_SPEC_SIZE) as *mut u8, &0u32.to_ne_bytes())?;
+        }
+        FS_IOC_GET_ENCRYPTION_KEY_STATUS => {
+            let buf = load_vec(arg as *const u8, KEY_STATUS_ARG_SIZE)?;
+            let identifier = key_identifier(&buf[..KEY_SPEC_SIZE])?;
+            let status = if fscrypt::key_present(&identifier) {
+                FSCRYPT_KEY_STATUS_PRESENT
+            } else {
+                FSCRYPT_KEY_STATUS_ABSENT
+            };
+            // status, status_flags and user_count
+            let mut out = [0u8; 12];
+            out[..4].copy_from_slice(&status.to_ne_bytes());
+            write_vm_mem((arg + 64) as *mut u8, &out)?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(0)
+}
@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Inotify syscalls.
+//!
+//! This module implements filesystem change notification including:
+//! - Instance creation (inotify_init, inotify_init1)
+//! - Watch management (inotify_add_watch, inotify_rm_watch)
+
+use core::ffi::{c_char, c_int};
+
+use bitflags::bitflags;
+use kerrno::{KError, KResult};
+use linux_raw_sys::general::{
+    AT_FDCWD, AT_SYMLINK_NOFOLLOW, IN_ALL_EVENTS, IN_CLOEXEC, IN_DONT_FOLLOW, IN_MASK_ADD,
+    IN_MASK_CREATE, IN_NONBLOCK, IN_ONLYDIR,
+};
+
+use crate::{
+    file::{FileLike, add_file_like, inotify::Inotify, resolve_at},
+    mm::vm_load_path,
+};
+
+bitflags! {
+    /// Flags for the `inotify_init1` syscall.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct InotifyFlags: u32 {
+        /// Create a file descriptor that is closed on `exec`.
+        const CLOEXEC = IN_CLOEXEC;
+        /// Create a non-blocking inotify instance.
+        const NONBLOCK = IN_NONBLOCK;
+    }
+}
+
+/// Creates an inotify instance and returns a new file descriptor.
+pub fn sys_inotify_init1(flags: u32) -> KResult<isize> {
+    debug!("sys_inotify_init1 <= flags: {flags:#x}");
+
+    let flags = InotifyFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
+
+    let inotify = Inotify::new();
+    inotify.set_nonblocking(flags.contains(InotifyFlags::NONBLOCK))?;
+    add_file_like(inotify as _, flags.contains(InotifyFlags::CLOEXEC)).map(|fd| fd as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_inotify_init() -> KResult<isize> {
+    sys_inotify_init1(0)
+}
+
+/// Watches the file at `path` for the events of `mask`, returning the watch
+/// descriptor.
+pub fn sys_inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> KResult<isize> {
+    let path = vm_load_path(path)?;
+    debug!("sys_inotify_add_watch <= fd: {fd}, path: {path}, mask: {mask:#x}");
+
+    let inotify = Inotify::from_fd(fd)?;
+    if mask & IN_ALL_EVENTS == 0
+        || mask & (IN_MASK_ADD | IN_MASK_CREATE) == IN_MASK_ADD | IN_MASK_CREATE
+    {
+        return Err(KError::InvalidInput);
+    }
+    let flags = if mask & IN_DONT_FOLLOW != 0 {
+        AT_SYMLINK_NOFOLLOW
+    } else {
+        0
+    };
+    let location = resolve_at(AT_FDCWD, Some(path.as_str()), flags)?
+        .into_file()
+        .ok_or(KError::NotFound)?;
+    if mask & IN_ONLYDIR != 0 {
+        location.check_is_dir()?;
+    }
+    inotify.add_watch(location, mask).map(|wd| wd as _)
+}
+
+/// Removes the watch `wd` from an inotify instance.
+pub fn sys_inotify_rm_watch(fd: c_int, wd: i32) -> KResult<isize> {
+    debug!("sys_inotify_rm_watch <= fd: {fd}, wd: {wd}");
+
+    Inotify::from_fd(fd)?.remove_watch(wd)?;
+    Ok(0)
+}
@@ -17,19 +17,24 @@ use core::{
     task::Context,
 };
 
-use kerrno::{KError, KResult};
-use kfs::{FS_CONTEXT, FileFlags, OpenOptions};
+use fs_ng_vfs::{FallocateMode, NodeType};
+use kerrno::{KError, KResult, LinuxError};
+use kfs::{FS_CONTEXT, FileAdvice, FileFlags, OpenOptions};
 use kio::{Seek, SeekFrom};
 use kpoll::{IoEvents, Pollable};
 use ktask::current;
-use linux_raw_sys::general::__kernel_off_t;
+use linux_raw_sys::general::{
+    __kernel_off_t, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE,
+    POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE, POSIX_FADV_NORMAL, POSIX_FADV_RANDOM,
+    POSIX_FADV_SEQUENTIAL, POSIX_FADV_WILLNEED,
+};
 use linux_sysno::Sysno;
 use osvm::{VirtMutPtr, VirtPtr};
 
 use crate::{
-    file::{File, FileLike, Pipe, get_file_like},
+    file::{Directory, File, FileLike, Pipe, get_file_like},
     io::{IoVec, IoVectorBuf},
-    mm::{UserConstPtr, VmBytes, VmBytesMut},
+    mm::{VmBytes, VmBytesMut, vm_load_path},
 };
 
 struct DummyFd;
@@ -59,13 +64,31 @@ pub fn sys_dummy_fd(sysno: Sysno) -> KResult<isize> {
     DummyFd.add_to_fd_table(false).map(|fd| fd as isize)
 }
 
+/// Fails with `EINVAL` if `f` was opened with `O_DIRECT` and the user buffer
+/// at `buf` is not aligned as its filesystem requires, usually to the logical
+/// sector size of the device.
+///
+/// Offsets and lengths are checked by the filesystem layer, which never sees
+/// the user address.
+pub fn check_direct_buf(f: &dyn FileLike, buf: usize) -> KResult<()> {
+    if let Some(file) = f.downcast_ref::<File>()
+        && let Some(align) = file.inner().direct_io_mem_align()
+        && !buf.is_multiple_of(align)
+    {
+        return Err(KError::InvalidInput);
+    }
+    Ok(())
+}
+
 /// Read data from the file indicated by `fd`.
 ///
 /// Return the read size if success.
 pub fn sys_read(fd: i32, buf: *mut u8, len: usize) -> KResult<isize> {
     debug!("sys_read <= fd: {fd}, buf: {buf:p}, len: {len}");
     // Get the file object and perform the read operation into the user buffer
-    Ok(get_file_like(fd)?.read(&mut VmBytesMut::new(buf, len))? as _)
+    let f = get_file_like(fd)?;
+    check_direct_buf(&*f, buf as usize)?;
+    Ok(f.read(&mut VmBytesMut::new(buf, len))? as _)
 }
 
 /// Vectored read into multiple buffers.
@@ -82,7 +105,9 @@ pub fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: usize) -> KResult<isize> {
 /// Return the written size if success.
 pub fn sys_write(fd: i32, buf: *mut u8, len: usize) -> KResult<isize> {
     debug!("sys_write <= fd: {fd}, buf: {buf:p}, len: {len}");
-    Ok(get_file_like(fd)?.write(&mut VmBytes::new(buf, len))? as _)
+    let f = get_file_like(fd)?;
+    check_direct_buf(&*f, buf as usize)?;
+    Ok(f.write(&mut VmBytes::new(buf, len))? as _)
 }
 
 /// Vectored write from multiple buffers.
@@ -97,6 +122,19 @@ pub fn sys_writev(fd: i32, iov: *const IoVec, iovcnt: usize) -> KResult<isize> {
 /// Repositions the read/write file offset.
 pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> KResult<isize> {
     debug!("sys_lseek <= {fd} {offset} {whence}");
+    if let Ok(dir) = Directory::from_fd(fd) {
+        // A directory position is a `getdents64` cookie, which `telldir` and
+        // `seekdir` pass back verbatim; there is no meaningful end to seek to.
+        let mut cookie = dir.offset.lock();
+        *cookie = match whence {
+            0 if offset >= 0 => offset as u64,
+            1 => cookie
+                .checked_add_signed(offset)
+                .ok_or(KError::InvalidInput)?,
+            _ => return Err(KError::InvalidInput),
+        };
+        return Ok(*cookie as _);
+    }
     // Change file position - whence: 0=start, 1=current, 2=end
     let pos = match whence {
         0 => SeekFrom::Start(offset as _),
@@ -109,8 +147,8 @@ pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> KResult<is
 }
 
 /// Truncates a file to a specified length by path.
-pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> KResult<isize> {
-    let path = path.get_as_str()?;
+pub fn sys_truncate(path: *const c_char, length: __kernel_off_t) -> KResult<isize> {
+    let path = vm_load_path(path)?;
     debug!("sys_truncate <= {path:?} {length}");
     // Truncate file to specified length - opens file by path
     if length < 0 {
@@ -133,7 +171,13 @@ pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> KResult<isize> {
     Ok(0)
 }
 
-/// Preallocates disk space for a file.
+/// Manipulates the disk space of a file.
+///
+/// Mode 0 allocates the range, growing the file if it ends past the end of
+/// file; `FALLOC_FL_KEEP_SIZE` keeps the size. `FALLOC_FL_PUNCH_HOLE` (which
+/// requires `FALLOC_FL_KEEP_SIZE`) frees the range and `FALLOC_FL_ZERO_RANGE`
+/// zeroes it. Filesystems without support for a mode get `EOPNOTSUPP`,
+/// except that plain allocation falls back to extending the file.
 pub fn sys_fallocate(
     fd: c_int,
     mode: u32,
@@ -141,15 +185,43 @@ pub fn sys_fallocate(
     len: __kernel_off_t,
 ) -> KResult<isize> {
     debug!("sys_fallocate <= fd: {fd}, mode: {mode}, offset: {offset}, len: {len}");
-    // Allocate/deallocate disk space for a file
-    if mode != 0 {
+    if offset < 0 || len <= 0 {
         return Err(KError::InvalidInput);
     }
+    if offset.checked_add(len).is_none() {
+        return Err(KError::from(LinuxError::EFBIG));
+    }
+    if Pipe::from_fd(fd).is_ok() {
+        return Err(KError::from(LinuxError::ESPIPE));
+    }
+    if Directory::from_fd(fd).is_ok() {
+        return Err(KError::IsADirectory);
+    }
     let f = File::from_fd(fd)?;
-    let inner = f.inner();
-    let file = inner.access(FileFlags::WRITE)?;
-    // Ensure file is at least as large as offset + len
-    file.set_len(file.location().len()?.max(offset as u64 + len as u64))?;
+    // Works on the open inode; `O_PATH` and read-only files get `EBADF`.
+    let file = f.inner().access(FileFlags::WRITE)?;
+    match file.location().node_type() {
+        NodeType::RegularFile => {}
+        NodeType::Directory => return Err(KError::IsADirectory),
+        NodeType::Fifo => return Err(KError::from(LinuxError::ESPIPE)),
+        _ => return Err(KError::NoSuchDevice),
+    }
+
+    let keep_size = mode & FALLOC_FL_KEEP_SIZE != 0;
+    let mode = match mode & !FALLOC_FL_KEEP_SIZE {
+        0 => FallocateMode::Allocate { keep_size },
+        FALLOC_FL_PUNCH_HOLE if keep_size => FallocateMode::PunchHole,
+        FALLOC_FL_ZERO_RANGE => FallocateMode::ZeroRange { keep_size },
+        m if m & !(FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE) != 0 => {
+            return Err(KError::OperationNotSupported);
+        }
+        _ => return Err(KError::InvalidInput),
+    };
+    file.fallocate(mode, offset as u64, len as u64)
+        .map_err(|err| match err {
+            KError::Unsupported => KError::OperationNotSupported,
+            err => err,
+        })?;
     Ok(0)
 }
 
@@ -172,6 +244,9 @@ pub fn sys_fdatasync(fd: c_int) -> KResult<isize> {
 }
 
 /// Provides access pattern advice for a file region.
+///
+/// `POSIX_FADV_SEQUENTIAL` and `POSIX_FADV_RANDOM` tune readahead of the
+/// whole open file, the other advice is accepted and ignored.
 pub fn sys_fadvise64(
     fd: c_int,
     offset: __kernel_off_t,
@@ -179,13 +254,20 @@ pub fn sys_fadvise64(
     advice: u32,
 ) -> KResult<isize> {
     debug!("sys_fadvise64 <= fd: {fd}, offset: {offset}, len: {len}, advice: {advice}");
-    // Provide hints to kernel about how file will be accessed
-    // Currently not fully implemented - pipes are not supported
     if Pipe::from_fd(fd).is_ok() {
         return Err(KError::BrokenPipe);
     }
-    if advice > 5 {
-        return Err(KError::InvalidInput);
+    let advice = match advice {
+        POSIX_FADV_NORMAL => Some(FileAdvice::Normal),
+        POSIX_FADV_SEQUENTIAL => Some(FileAdvice::Sequential),
+        POSIX_FADV_RANDOM => Some(FileAdvice::Random),
+        POSIX_FADV_WILLNEED | POSIX_FADV_DONTNEED | POSIX_FADV_NOREUSE => None,
+        _ => return Err(KError::InvalidInput),
+    };
+    if let Some(advice) = advice
+        && let Ok(f) = File::from_fd(fd)
+    {
+        f.inner().advise(advice);
     }
     Ok(0)
 }
@@ -197,6 +279,7 @@ pub fn sys_pread64(fd: c_int, buf: *mut u8, len: usize, offset: __kernel_off_t)
     if offset < 0 {
         return Err(KError::InvalidInput);
     }
+    check_direct_buf(&*f, buf as usize)?;
     let read = f.inner().read_at(VmBytesMut::new(buf, len), offset as _)?;
     Ok(read as _)
 }
@@ -213,6 +296,7 @@ pub fn sys_pwrite64(
         return Ok(0);
     }
     let f = File::from_fd(fd)?;
+    check_direct_buf(&*f, buf as usize)?;
     let write = f.inner().write_at(VmBytes::new(buf, len), offset as _)?;
     Ok(write as _)
 }
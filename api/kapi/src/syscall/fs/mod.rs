@@ -11,10 +11,14 @@
 //! - File metadata and statistics (stat, fstat, etc.)
 //! - File control (ioctl, fcntl, etc.)
 //! - Special files (pipes, fifos, device files, etc.)
+//! - Change notification (inotify)
+//! - Extended attributes (getxattr, setxattr, etc.)
 
 mod ctl;
 mod event;
 mod fd_ops;
+mod fscrypt;
+mod inotify;
 mod io;
 mod memfd;
 mod mount;
@@ -22,7 +26,9 @@ mod pidfd;
 mod pipe;
 mod signalfd;
 mod stat;
+mod xattr;
 
 pub use self::{
-    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*, signalfd::*, stat::*,
+    ctl::*, event::*, fd_ops::*, inotify::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*,
+    signalfd::*, stat::*, xattr::*,
 };
@@ -9,56 +9,114 @@
 //! - Unmount filesystem (umount, umount2, etc.)
 //! - Mount operations and flags
 
+use alloc::string::String;
 use core::ffi::{c_char, c_void};
 
 use kerrno::{KError, KResult};
-use kfs::FS_CONTEXT;
+use kfs::{FS_CONTEXT, MountFlags, UnmountFlags};
 
-use crate::{mm::vm_load_string, vfs::MemoryFs};
+use crate::mm::{vm_load_path, vm_load_string};
+
+/// Magic number that old programs put in the upper half of the mount flags.
+const MS_MGC_VAL: u32 = 0xc0ed_0000;
+const MS_MGC_MSK: u32 = 0xffff_0000;
+
+/// Parses the tmpfs mount options in `data`, returning the size limit.
+///
+/// Only `size=` is supported; `mode=`, `uid=`, `gid=` and `nr_inodes=` are
+/// accepted and ignored.
+fn parse_tmpfs_options(data: &str) -> KResult<Option<u64>> {
+    let mut size = None;
+    for option in data.split(',').filter(|it| !it.is_empty()) {
+        let (key, value) = option.split_once('=').unwrap_or((option, ""));
+        match key {
+            "size" => {
+                let (digits, shift) = match value.as_bytes().last() {
+                    Some(b'k' | b'K') => (&value[..value.len() - 1], 10),
+                    Some(b'm' | b'M') => (&value[..value.len() - 1], 20),
+                    Some(b'g' | b'G') => (&value[..value.len() - 1], 30),
+                    _ => (value, 0),
+                };
+                let bytes = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|it| it.checked_mul(1 << shift))
+                    .ok_or(KError::InvalidInput)?;
+                size = Some(bytes);
+            }
+            "mode" | "uid" | "gid" | "nr_inodes" => {}
+            _ => return Err(KError::InvalidInput),
+        }
+    }
+    Ok(size)
+}
+
+/// Loads a string argument that may be null, as the source and type are for
+/// bind mounts and remounts.
+fn load_optional_string(ptr: *const c_char) -> KResult<String> {
+    if ptr.is_null() {
+        return Ok(String::new());
+    }
+    vm_load_string(ptr)
+}
 
 /// Mount a filesystem at the specified target path
 ///
-/// Currently only supports tmpfs (temporary memory-based filesystem).
-/// The source is loaded from user memory but not validated since tmpfs doesn't use source device names.
+/// Supports tmpfs and block devices registered by the filesystem layer, such
+/// as `/dev/vdb`, formatted with a supported filesystem, as well as bind
+/// mounts and changing whether they are read-only with `MS_REMOUNT`.
 pub fn sys_mount(
     source: *const c_char,
     target: *const c_char,
     fs_type: *const c_char,
-    _flags: i32,
-    _data: *const c_void,
+    flags: i32,
+    data: *const c_void,
 ) -> KResult<isize> {
     // Load filesystem type string from user memory
-    let source = vm_load_string(source)?;
-    let target = vm_load_string(target)?;
-    let fs_type = vm_load_string(fs_type)?;
-    debug!("sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}");
+    let source = load_optional_string(source)?;
+    let target = vm_load_path(target)?;
+    let fs_type = load_optional_string(fs_type)?;
+    debug!(
+        "sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}, flags: \
+         {flags:#x}"
+    );
 
-    // Only tmpfs is supported - reject unsupported filesystem types
-    if fs_type != "tmpfs" {
-        return Err(KError::NoSuchDevice);
+    let mut flags = flags as u32;
+    if flags & MS_MGC_MSK == MS_MGC_VAL {
+        flags &= !MS_MGC_MSK;
     }
+    // Propagation changes are not supported.
+    let flags = MountFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
 
-    // Create a new in-memory filesystem instance
-    let fs = MemoryFs::new();
-
-    // Resolve the target mount point path and attach the filesystem
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.mount(&fs)?;
-
+    if flags.contains(MountFlags::REMOUNT) {
+        kfs::remount(&FS_CONTEXT.lock(), target, flags)?;
+    } else if flags.contains(MountFlags::BIND) {
+        let recursive = flags.contains(MountFlags::REC);
+        kfs::bind_mount(&FS_CONTEXT.lock(), source, target, recursive)?;
+    } else if fs_type == "tmpfs" {
+        let data = if data.is_null() {
+            Default::default()
+        } else {
+            vm_load_string(data as *const c_char)?
+        };
+        let size_limit = parse_tmpfs_options(&data)?;
+        kfs::mount_tmpfs(&FS_CONTEXT.lock(), target, size_limit)?;
+    } else {
+        kfs::mount_blockdev(&FS_CONTEXT.lock(), target, &source, &fs_type, flags)?;
+    }
     Ok(0)
 }
 
 /// Unmount a filesystem at the specified target path
 ///
-/// Removes the filesystem mounted at the target path and detaches it from the directory tree.
-/// The mounted filesystem must be empty or the unmount may fail depending on the filesystem implementation.
-pub fn sys_umount2(target: *const c_char, _flags: i32) -> KResult<isize> {
+/// Fails with `EBUSY` while the filesystem is in use, unless `MNT_DETACH` is
+/// given, in which case it is released once the last user is gone.
+pub fn sys_umount2(target: *const c_char, flags: i32) -> KResult<isize> {
     // Load target path from user memory
-    let target = vm_load_string(target)?;
-    debug!("sys_umount2 <= target: {target:?}");
+    let target = vm_load_path(target)?;
+    debug!("sys_umount2 <= target: {target:?}, flags: {flags:#x}");
 
-    // Resolve the mount point path and detach the filesystem
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.unmount()?;
+    let flags = UnmountFlags::from_bits(flags as u32).ok_or(KError::InvalidInput)?;
+    kfs::umount(&FS_CONTEXT.lock(), target, flags)?;
     Ok(0)
 }
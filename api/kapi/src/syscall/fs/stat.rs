@@ -22,7 +22,7 @@ use osvm::{VirtMutPtr, VirtPtr};
 
 use crate::{
     file::{File, FileLike, resolve_at},
-    mm::vm_load_string,
+    mm::{vm_load_path, vm_load_path_allow_empty},
 };
 
 /// Get the file metadata by `path` and write into `statbuf`.
@@ -59,7 +59,10 @@ pub fn sys_fstatat(
     statbuf: *mut stat,
     flags: u32,
 ) -> KResult<isize> {
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
+    let path = path
+        .check_non_null()
+        .map(vm_load_path_allow_empty)
+        .transpose()?;
 
     debug!("sys_fstatat <= dirfd: {dirfd}, path: {path:?}, flags: {flags}");
 
@@ -104,7 +107,10 @@ pub fn sys_statx(
     //        below), then the target file is the one referred to by the
     //        file descriptor dirfd.
 
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
+    let path = path
+        .check_non_null()
+        .map(vm_load_path_allow_empty)
+        .transpose()?;
     debug!("sys_statx <= dirfd: {dirfd}, path: {path:?}, flags: {flags}");
 
     statxbuf.write_vm(resolve_at(dirfd, path.as_deref(), flags)?.stat()?.into())?;
@@ -121,7 +127,10 @@ pub fn sys_access(path: *const c_char, mode: u32) -> KResult<isize> {
 
 /// Checks file accessibility with additional flags.
 pub fn sys_faccessat2(dirfd: c_int, path: *const c_char, mode: u32, flags: u32) -> KResult<isize> {
-    let path = path.check_non_null().map(vm_load_string).transpose()?;
+    let path = path
+        .check_non_null()
+        .map(vm_load_path_allow_empty)
+        .transpose()?;
     debug!("sys_faccessat2 <= dirfd: {dirfd}, path: {path:?}, mode: {mode}, flags: {flags}");
 
     let file = resolve_at(dirfd, path.as_deref(), flags)?;
@@ -171,7 +180,7 @@ fn statfs(loc: &Location) -> KResult<statfs> {
 
 /// Gets filesystem statistics by path.
 pub fn sys_statfs(path: *const c_char, buf: *mut statfs) -> KResult<isize> {
-    let path = vm_load_string(path)?;
+    let path = vm_load_path(path)?;
     debug!("sys_statfs <= path: {path:?}");
 
     buf.write_vm(statfs(
@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Extended attribute syscalls.
+//!
+//! This module implements the `getxattr`, `setxattr`, `listxattr` and
+//! `removexattr` families. Each comes in three variants: on a path, on a path
+//! without following a final symlink (`l*`) and on a file descriptor (`f*`).
+//! Values are copied as raw bytes; what they mean is up to their consumers.
+
+use alloc::{string::String, vec::Vec};
+use core::ffi::{c_char, c_int};
+
+use fs_ng_vfs::{
+    Location, Metadata, NodeFlags, NodePermission, NodeType, XATTR_LIST_MAX, XATTR_SIZE_MAX,
+    XattrFlags, XattrNamespace, no_xattr,
+};
+use kerrno::{KError, KResult};
+use linux_raw_sys::general::{AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use osvm::{load_vec, write_vm_mem};
+
+use crate::{
+    file::{fd_location, resolve_at},
+    mm::{vm_load_path, vm_load_string},
+    syscall::sys::{sys_getegid, sys_geteuid},
+};
+
+/// The inode an xattr syscall operates on.
+enum Target {
+    Path(*const c_char),
+    /// A path whose final symlink is not followed.
+    Link(*const c_char),
+    Fd(c_int),
+}
+
+impl Target {
+    fn resolve(self) -> KResult<Location> {
+        match self {
+            Self::Path(path) => resolve_path(path, 0),
+            Self::Link(path) => resolve_path(path, AT_SYMLINK_NOFOLLOW),
+            Self::Fd(fd) => fd_location(fd),
+        }
+    }
+}
+
+fn resolve_path(path: *const c_char, flags: u32) -> KResult<Location> {
+    let path = vm_load_path(path)?;
+    resolve_at(AT_FDCWD, Some(&path), flags)?
+        .into_file()
+        .ok_or(KError::OperationNotSupported)
+}
+
+/// Loads an attribute name from user memory and returns it with its
+/// namespace.
+fn load_name(name: *const c_char) -> KResult<(String, XattrNamespace)> {
+    let name = vm_load_string(name)?;
+    let namespace = XattrNamespace::parse(&name)?;
+    Ok((name, namespace))
+}
+
+/// Returns whether the permission bits of `meta` let an unprivileged caller
+/// read or, with `write`, write the inode.
+fn mode_allows(meta: &Metadata, euid: u32, write: bool) -> KResult<bool> {
+    let shift = if euid == meta.uid {
+        6
+    } else if sys_getegid()? as u32 == meta.gid {
+        3
+    } else {
+        0
+    };
+    let bit = if write { 0o2 } else { 0o4 };
+    Ok((meta.mode.bits() >> shift) & bit != 0)
+}
+
+/// Checks that the caller may read or, with `write`, change the attributes of
+/// `namespace` on `loc`.
+///
+/// As on Linux, attributes the caller may not read look absent. `system.*`
+/// is refused since it only holds POSIX ACLs, which are not supported.
+fn check_access(loc: &Location, namespace: XattrNamespace, write: bool) -> KResult<()> {
+    if write && loc.flags().contains(NodeFlags::READ_ONLY) {
+        return Err(KError::ReadOnlyFilesystem);
+    }
+    let denied = || {
+        if write {
+            KError::OperationNotPermitted
+        } else {
+            no_xattr()
+        }
+    };
+    let euid = sys_geteuid()? as u32;
+    let privileged = euid == 0;
+    match namespace {
+        XattrNamespace::System => Err(KError::OperationNotSupported),
+        XattrNamespace::Trusted if !privileged => Err(denied()),
+        XattrNamespace::Trusted => Ok(()),
+        // Anyone may read `security.*`; changing it takes privileges, be it
+        // a file capability or a security label.
+        XattrNamespace::Security if write && !privileged => Err(KError::OperationNotPermitted),
+        XattrNamespace::Security => Ok(()),
+        XattrNamespace::User => {
+            let meta = loc.metadata()?;
+            // Other file types carry `user.*` for no one, and the entries of
+            // a sticky directory belong to their owners.
+            if !matches!(meta.node_type, NodeType::RegularFile | NodeType::Directory) {
+                return Err(denied());
+            }
+            if privileged {
+                return Ok(());
+            }
+            if meta.node_type == NodeType::Directory
+                && meta.mode.contains(NodePermission::STICKY)
+                && euid != meta.uid
+            {
+                return Err(denied());
+            }
+            if !mode_allows(&meta, euid, write)? {
+                return Err(KError::PermissionDenied);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Copies `data` to the user buffer `buf` of `size` bytes, or returns its
+/// length if `size` is zero.
+fn copy_out(data: &[u8], buf: *mut u8, size: usize) -> KResult<isize> {
+    if size == 0 {
+        return Ok(data.len() as _);
+    }
+    if data.len() > size {
+        return Err(KError::OutOfRange);
+    }
+    write_vm_mem(buf, data)?;
+    Ok(data.len() as _)
+}
+
+fn getxattr(target: Target, name: *const c_char, value: *mut u8, size: usize) -> KResult<isize> {
+    let (name, namespace) = load_name(name)?;
+    debug!("getxattr <= name: {name:?}, size: {size}");
+    let loc = target.resolve()?;
+    check_access(&loc, namespace, false)?;
+    let data = loc.get_xattr(&name)?;
+    copy_out(&data, value, size.min(XATTR_SIZE_MAX))
+}
+
+fn setxattr(
+    target: Target,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> KResult<isize> {
+    let flags = XattrFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
+    let (name, namespace) = load_name(name)?;
+    debug!("setxattr <= name: {name:?}, size: {size}, flags: {flags:?}");
+    if size > XATTR_SIZE_MAX {
+        return Err(KError::ArgumentListTooLong);
+    }
+    let value = if size == 0 {
+        Vec::new()
+    } else {
+        load_vec(value, size)?
+    };
+    let loc = target.resolve()?;
+    check_access(&loc, namespace, true)?;
+    loc.set_xattr(&name, &value, flags)?;
+    Ok(0)
+}
+
+fn listxattr(target: Target, list: *mut u8, size: usize) -> KResult<isize> {
+    let loc = target.resolve()?;
+    // Filesystems without extended attributes simply have none.
+    let names = match loc.list_xattr() {
+        Ok(names) => names,
+        Err(KError::OperationNotSupported) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let privileged = sys_geteuid()? == 0;
+    let mut buf = Vec::new();
+    for name in names {
+        if !privileged && XattrNamespace::parse(&name) == Ok(XattrNamespace::Trusted) {
+            continue;
+        }
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+    }
+    if size >= XATTR_LIST_MAX && buf.len() > XATTR_LIST_MAX {
+        return Err(KError::ArgumentListTooLong);
+    }
+    copy_out(&buf, list, size.min(XATTR_LIST_MAX))
+}
+
+fn removexattr(target: Target, name: *const c_char) -> KResult<isize> {
+    let (name, namespace) = load_name(name)?;
+    debug!("removexattr <= name: {name:?}");
+    let loc = target.resolve()?;
+    check_access(&loc, namespace, true)?;
+    loc.remove_xattr(&name)?;
+    Ok(0)
+}
+
+pub fn sys_getxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> KResult<isize> {
+    getxattr(Target::Path(path), name, value, size)
+}
+
+pub fn sys_lgetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> KResult<isize> {
+    getxattr(Target::Link(path), name, value, size)
+}
+
+pub fn sys_fgetxattr(
+    fd: c_int,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> KResult<isize> {
+    getxattr(Target::Fd(fd), name, value, size)
+}
+
+pub fn sys_setxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> KResult<isize> {
+    setxattr(Target::Path(path), name, value, size, flags)
+}
+
+pub fn sys_lsetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> KResult<isize> {
+    setxattr(Target::Link(path), name, value, size, flags)
+}
+
+pub fn sys_fsetxattr(
+    fd: c_int,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> KResult<isize> {
+    setxattr(Target::Fd(fd), name, value, size, flags)
+}
+
+pub fn sys_listxattr(path: *const c_char, list: *mut u8, size: usize) -> KResult<isize> {
+    listxattr(Target::Path(path), list, size)
+}
+
+pub fn sys_llistxattr(path: *const c_char, list: *mut u8, size: usize) -> KResult<isize> {
+    listxattr(Target::Link(path), list, size)
+}
+
+pub fn sys_flistxattr(fd: c_int, list: *mut u8, size: usize) -> KResult<isize> {
+    listxattr(Target::Fd(fd), list, size)
+}
+
+pub fn sys_removexattr(path: *const c_char, name: *const c_char) -> KResult<isize> {
+    removexattr(Target::Path(path), name)
+}
+
+pub fn sys_lremovexattr(path: *const c_char, name: *const c_char) -> KResult<isize> {
+    removexattr(Target::Link(path), name)
+}
+
+pub fn sys_fremovexattr(fd: c_int, name: *const c_char) -> KResult<isize> {
+    removexattr(Target::Fd(fd), name)
+}
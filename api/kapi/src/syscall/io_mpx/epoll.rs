@@ -13,10 +13,14 @@
 use core::time::Duration;
 
 use bitflags::bitflags;
+use kcore::task::AsThread;
 use kerrno::{KError, KResult};
 use kpoll::IoEvents;
 use ksignal::SignalSet;
-use ktask::future::{self, block_on, poll_io};
+use ktask::{
+    current,
+    future::{self, block_on, poll_io},
+};
 use linux_raw_sys::general::{
     EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, epoll_event, timespec,
 };
@@ -44,7 +48,7 @@ bitflags! {
 pub fn sys_epoll_create1(flags: u32) -> KResult<isize> {
     let flags = EpollCreateFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
     debug!("sys_epoll_create1 <= flags: {flags:?}");
-    Epoll::new()
+    Epoll::new_owned(&current().as_thread().proc_data.proc)
         .add_to_fd_table(flags.contains(EpollCreateFlags::CLOEXEC))
         .map(|fd| fd as isize)
 }
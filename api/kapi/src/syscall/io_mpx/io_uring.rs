@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! io_uring syscalls.
+//!
+//! Operations are issued synchronously by `io_uring_enter`, in the context
+//! of the caller, as soon as their files are ready. Supported are
+//! `IORING_OP_NOP`, `IORING_OP_READ`, `IORING_OP_WRITE`, `IORING_OP_ACCEPT`
+//! and one-shot `IORING_OP_POLL_ADD`, without `IOSQE_*` flags.
+
+use kerrno::{KError, KResult, LinuxError};
+use kpoll::{IoEvents, Pollable};
+use ksignal::SignalSet;
+use ktask::future::{block_on, poll_io};
+
+use crate::{
+    file::{
+        File, FileLike, get_file_like,
+        io_uring::{
+            IORING_MAX_CQ_ENTRIES, IORING_MAX_ENTRIES, IORING_SETUP_CLAMP, IORING_SETUP_CQSIZE,
+            IoUring, IoUringParams, IoUringSqe, Issued,
+        },
+    },
+    mm::{UserConstPtr, UserPtr, VmBytes, VmBytesMut, nullable},
+    signal::with_replacen_blocked,
+    syscall::{check_direct_buf, signal::check_sigset_size, sys_accept4},
+};
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_ACCEPT: u8 = 13;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+
+/// Clamps `entries` to `max` if `clamp`, fails if it is out of range
+/// otherwise.
+fn ring_size(entries: u32, max: u32, clamp: bool) -> KResult<u32> {
+    match entries {
+        0 => Err(KError::InvalidInput),
+        n if n <= max => Ok(n.next_power_of_two()),
+        _ if clamp => Ok(max),
+        _ => Err(KError::InvalidInput),
+    }
+}
+
+/// Creates an io_uring instance with rings of at least `entries` entries.
+pub fn sys_io_uring_setup(entries: u32, params: UserPtr<IoUringParams>) -> KResult<isize> {
+    let params = params.get_as_mut()?;
+    debug!(
+        "sys_io_uring_setup <= entries: {entries}, flags: {:#x}",
+        params.flags
+    );
+    if params.flags & !(IORING_SETUP_CQSIZE | IORING_SETUP_CLAMP) != 0 || params.resv != [0; 3] {
+        return Err(KError::InvalidInput);
+    }
+    let clamp = params.flags & IORING_SETUP_CLAMP != 0;
+    let sq_entries = ring_size(entries, IORING_MAX_ENTRIES, clamp)?;
+    let cq_entries = if params.flags & IORING_SETUP_CQSIZE != 0 {
+        let cq_entries = ring_size(params.cq_entries, IORING_MAX_CQ_ENTRIES, clamp)?;
+        if cq_entries < sq_entries {
+            return Err(KError::InvalidInput);
+        }
+        cq_entries
+    } else {
+        2 * sq_entries
+    };
+
+    let ring = IoUring::new(sq_entries, cq_entries)?;
+    ring.fill_params(params);
+    ring.add_to_fd_table(true).map(|fd| fd as isize)
+}
+
+fn read(file: &dyn FileLike, sqe: &IoUringSqe) -> KResult<usize> {
+    check_direct_buf(file, sqe.addr as usize)?;
+    let mut buf = VmBytesMut::new(sqe.addr as *mut u8, sqe.len as usize);
+    match file.downcast_ref::<File>() {
+        // An offset of -1 reads at the file position.
+        Some(f) if sqe.off != u64::MAX => f.inner().read_at(buf, sqe.off as _),
+        _ => file.read(&mut buf),
+    }
+}
+
+fn write(file: &dyn FileLike, sqe: &IoUringSqe) -> KResult<usize> {
+    check_direct_buf(file, sqe.addr as usize)?;
+    let mut buf = VmBytes::new(sqe.addr as *const u8, sqe.len as usize);
+    match file.downcast_ref::<File>() {
+        Some(f) if sqe.off != u64::MAX => f.inner().write_at(buf, sqe.off as _),
+        _ => file.write(&mut buf),
+    }
+}
+
+/// Issues the operation of `sqe` if its file is ready.
+fn issue(sqe: &IoUringSqe) -> Issued {
+    let issued = || -> KResult<Issued> {
+        if sqe.opcode == IORING_OP_NOP {
+            return Ok(Issued::Done(0));
+        }
+        if sqe.flags != 0 {
+            return Err(KError::InvalidInput);
+        }
+        let events = match sqe.opcode {
+            IORING_OP_READ | IORING_OP_ACCEPT => IoEvents::IN,
+            IORING_OP_WRITE => IoEvents::OUT,
+            IORING_OP_POLL_ADD => IoEvents::from_bits_truncate(sqe.op_flags),
+            _ => return Err(KError::InvalidInput),
+        };
+        let file = get_file_like(sqe.fd)?;
+        // A pending operation on a ring would keep it alive.
+        if file.is::<IoUring>() {
+            return Err(KError::InvalidInput);
+        }
+        let ready = file.poll() & (events | IoEvents::ALWAYS_POLL);
+        if ready.is_empty() {
+            return Ok(Issued::Pending(file, events));
+        }
+        let result = match sqe.opcode {
+            IORING_OP_READ => read(&*file, sqe),
+            IORING_OP_WRITE => write(&*file, sqe),
+            IORING_OP_ACCEPT => sys_accept4(
+                sqe.fd,
+                (sqe.addr as usize).into(),
+                (sqe.off as usize).into(),
+                sqe.op_flags,
+            )
+            .map(|fd| fd as usize),
+            IORING_OP_POLL_ADD => Ok(ready.bits() as usize),
+            _ => unreachable!(),
+        };
+        match result {
+            Err(KError::WouldBlock) => Ok(Issued::Pending(file, events)),
+            result => result.map(|res| Issued::Done(res as i32)),
+        }
+    };
+    issued().unwrap_or_else(|err| Issued::Done(-(LinuxError::from(err).into_raw() as i32)))
+}
+
+/// Submits up to `to_submit` operations, and with `IORING_ENTER_GETEVENTS`
+/// waits until `min_complete` of them completed.
+///
+/// The wait ends early when no operation is left pending to complete.
+pub fn sys_io_uring_enter(
+    fd: i32,
+    to_submit: u32,
+    min_complete: u32,
+    flags: u32,
+    sigmask: UserConstPtr<SignalSet>,
+    sigsetsize: usize,
+) -> KResult<isize> {
+    debug!(
+        "sys_io_uring_enter <= fd: {fd}, to_submit: {to_submit}, min_complete: {min_complete}, \
+         flags: {flags:#x}"
+    );
+    if flags & !IORING_ENTER_GETEVENTS != 0 {
+        return Err(KError::InvalidInput);
+    }
+    let ring = IoUring::from_fd(fd)?;
+    let submitted = match to_submit {
+        0 => 0,
+        n => ring.submit(n, issue)?,
+    };
+
+    if flags & IORING_ENTER_GETEVENTS == 0 {
+        ring.reap(issue);
+        return Ok(submitted as isize);
+    }
+    let sigmask = nullable!(sigmask.get_as_ref())?.copied();
+    if sigmask.is_some() {
+        check_sigset_size(sigsetsize)?;
+    }
+    let waited = with_replacen_blocked(sigmask, || {
+        block_on(poll_io(ring.as_ref(), IoEvents::IN, false, || {
+            ring.reap(issue);
+            if ring.completions() >= min_complete.min(ring.cq_entries()) || !ring.has_pending() {
+                Ok(())
+            } else {
+                Err(KError::WouldBlock)
+            }
+        }))
+    });
+    match waited {
+        // What was submitted is reported even if the wait was interrupted.
+        Err(err) if submitted == 0 => Err(err),
+        _ => Ok(submitted as isize),
+    }
+}
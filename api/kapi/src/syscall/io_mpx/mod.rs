@@ -8,10 +8,12 @@
 //! - select: Traditional file descriptor multiplexing
 //! - poll: Enhanced multiplexing with better scalability
 //! - epoll: High-performance event notification mechanism
+//! - io_uring: Completion-based submission of I/O operations
 //!
 //! Allows monitoring multiple file descriptors for I/O events.
 
 mod epoll;
+mod io_uring;
 mod poll;
 mod select;
 
@@ -20,7 +22,7 @@ use core::task::Context;
 
 use kpoll::{IoEvents, Pollable};
 
-pub use self::{epoll::*, poll::*, select::*};
+pub use self::{epoll::*, io_uring::*, poll::*, select::*};
 use crate::file::FileLike;
 
 struct FdPollSet(pub Vec<(Arc<dyn FileLike>, IoEvents)>);
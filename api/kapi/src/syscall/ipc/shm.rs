@@ -113,18 +113,19 @@ pub fn sys_shmat(shmid: i32, addr: usize, shmflg: u32) -> KResult<isize> {
 
     // alloc the virtual address range
     assert!(shm_inner.get_addr_range(pid).is_none());
+    let end = VirtAddr::from(proc_data.user_space_end()).min(aspace.end());
     let start_addr = aspace
         .find_free_area(
             VirtAddr::from(start_aligned),
             length,
-            VirtAddrRange::new(aspace.base(), aspace.end()),
+            VirtAddrRange::new(aspace.base(), end),
             PAGE_SIZE_4K,
         )
         .or_else(|| {
             aspace.find_free_area(
                 aspace.base(),
                 length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
+                VirtAddrRange::new(aspace.base(), end),
                 PAGE_SIZE_4K,
             )
         })
@@ -7,26 +7,51 @@
 //! This module implements virtual memory mapping operations including:
 //! - Memory mapping (mmap, mmap2, etc.)
 //! - Memory unmapping (munmap)
+//! - Memory remapping (mremap)
 //! - Memory protection (mprotect)
 //! - Memory synchronization (msync)
 //! - Memory advice (madvise)
+//!
+//! Shared file mappings map the pages of the page cache of the file, so
+//! every mapping of the file sees the same data. Stores mark the pages dirty,
+//! and `msync(MS_SYNC)` and `munmap` write them back to the file. Accessing a
+//! page past the end of the file raises `SIGBUS`.
+//!
+//! Mappings of a dma-buf map its pages directly, and keep it alive.
+//!
+//! With the `w-xor-x` feature, no mapping may be writable and executable at
+//! once, unless it was created with [`MAP_JIT`]: such requests fail with
+//! `EACCES`.
 
 use alloc::sync::Arc;
 
 use kcore::{
     task::AsThread,
-    vfs::{Device, DeviceMmap},
+    vfs::{DeviceMmap, device_ops},
 };
 use kerrno::{KError, KResult};
 use kfs::FileBackend;
 use khal::paging::{MappingFlags, PageSize};
+use ksync::Mutex;
 use ktask::current;
 use linux_raw_sys::general::*;
-use memaddr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
-use memspace::backend::{Backend, SharedPages};
-use osvm::{load_vec, write_vm_mem};
+use memaddr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange, align_up_4k, is_aligned_4k};
+use memspace::{
+    AddrSpace,
+    backend::{Backend, SharedPages},
+};
+
+use crate::file::{DmaBufFile, File, FileLike, io_uring::IoUring};
+
+/// `mmap` flag allowing the mapping to be writable and executable at once,
+/// as just-in-time compilers need, when W^X is enforced.
+///
+/// This is not a Linux flag; the bit is unused there.
+const MAP_JIT: u32 = 0x0080_0000;
 
-use crate::file::{File, FileLike};
+/// Whether mappings may not be writable and executable at once, unless they
+/// were created with [`MAP_JIT`].
+const ENFORCE_WX: bool = cfg!(feature = "w-xor-x");
 
 bitflags::bitflags! {
     /// `PROT_*` flags for use with [`sys_mmap`].
@@ -94,12 +119,40 @@ bitflags::bitflags! {
         const HUGE_1GB = MAP_HUGETLB | MAP_HUGE_1GB;
         /// Deprecated flag
         const DENYWRITE = MAP_DENYWRITE;
+        /// The mapping may be writable and executable at once.
+        const JIT = MAP_JIT;
 
         /// Mask for type of mapping
         const TYPE = MAP_TYPE;
     }
 }
 
+bitflags::bitflags! {
+    /// flags for sys_mremap
+    #[derive(Debug, Clone, Copy)]
+    struct MremapFlags: u32 {
+        /// The mapping may be moved to another address.
+        const MAYMOVE = MREMAP_MAYMOVE;
+        /// The mapping is moved to the address given.
+        const FIXED = MREMAP_FIXED;
+        /// The old mapping is kept, with no pages.
+        const DONTUNMAP = MREMAP_DONTUNMAP;
+    }
+}
+
+bitflags::bitflags! {
+    /// flags for sys_msync
+    #[derive(Debug, Clone, Copy)]
+    struct MsyncFlags: u32 {
+        /// Schedule the write back, without waiting for it.
+        const ASYNC = MS_ASYNC;
+        /// Ask to invalidate other mappings of the same file.
+        const INVALIDATE = MS_INVALIDATE;
+        /// Write back and wait for it to complete.
+        const SYNC = MS_SYNC;
+    }
+}
+
 pub fn sys_mmap(
     addr: usize,
     length: usize,
@@ -157,6 +210,13 @@ pub fn sys_mmap(
         PageSize::Size4K
     };
 
+    let mut mapping_flags = MappingFlags::from(permission_flags);
+    if map_flags.contains(MmapFlags::JIT) {
+        mapping_flags |= MappingFlags::JIT;
+    } else if ENFORCE_WX && permission_flags.contains(MmapProt::WRITE | MmapProt::EXEC) {
+        return Err(KError::PermissionDenied);
+    }
+
     let start = addr.align_down(page_size);
     let end = (addr + length).align_up(page_size);
     let mut length = end - start;
@@ -169,23 +229,44 @@ pub fn sys_mmap(
         dst_addr
     } else {
         let align = page_size as usize;
+        // A 32-bit process only reaches the low 4 GiB.
+        let end = VirtAddr::from(curr.as_thread().proc_data.user_space_end()).min(aspace.end());
         aspace
             .find_free_area(
                 VirtAddr::from(start),
                 length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
+                VirtAddrRange::new(aspace.base(), end),
                 align,
             )
             .or(aspace.find_free_area(
                 aspace.base(),
                 length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
+                VirtAddrRange::new(aspace.base(), end),
                 align,
             ))
             .ok_or(KError::NoMemory)?
     };
 
     let file = if fd > 0 {
+        if let Ok(dmabuf) = DmaBufFile::from_fd(fd) {
+            // The pages of a dma-buf are only ever shared.
+            if map_type == MmapFlags::PRIVATE || page_size != PageSize::Size4K {
+                return Err(KError::InvalidInput);
+            }
+            let backend = dmabuf.mmap_backend(start, offset, length)?;
+            aspace.map(start, length, mapping_flags, false, backend)?;
+            return Ok(start.as_usize() as _);
+        }
+        if let Ok(ring) = IoUring::from_fd(fd) {
+            // So are the rings of an io_uring, which are selected by the
+            // offset.
+            if map_type == MmapFlags::PRIVATE || page_size != PageSize::Size4K {
+                return Err(KError::InvalidInput);
+            }
+            let backend = ring.mmap_backend(start, offset, length)?;
+            aspace.map(start, length, mapping_flags, false, backend)?;
+            return Ok(start.as_usize() as _);
+        }
         Some(File::from_fd(fd)?)
     } else {
         None
@@ -208,10 +289,7 @@ pub fn sys_mmap(
                         )
                     }
                     FileBackend::Direct(loc) => {
-                        let device = loc
-                            .entry()
-                            .downcast::<Device>()
-                            .map_err(|_| KError::NoSuchDevice)?;
+                        let device = device_ops(loc.entry()).ok_or(KError::NoSuchDevice)?;
 
                         match device.mmap() {
                             DeviceMmap::None => {
@@ -237,6 +315,15 @@ pub fn sys_mmap(
                                 offset,
                                 &curr.as_thread().proc_data.aspace,
                             ),
+                            DeviceMmap::Shared(pages) => {
+                                if offset != 0
+                                    || page_size != PageSize::Size4K
+                                    || length > pages.len() * PAGE_SIZE_4K
+                                {
+                                    return Err(KError::InvalidInput);
+                                }
+                                Backend::new_shared(start, pages)
+                            }
                         }
                     }
                 }
@@ -257,7 +344,7 @@ pub fn sys_mmap(
     };
 
     let populate = map_flags.contains(MmapFlags::POPULATE);
-    aspace.map(start, length, permission_flags.into(), populate, backend)?;
+    aspace.map(start, length, mapping_flags, populate, backend)?;
 
     Ok(start.as_usize() as _)
 }
@@ -268,7 +355,18 @@ pub fn sys_munmap(addr: usize, length: usize) -> KResult<isize> {
     let mut aspace = curr.as_thread().proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
+    let files = VirtAddrRange::try_from_start_size(start_addr, length)
+        .map(|range| aspace.file_mappings(range))
+        .unwrap_or_default();
     aspace.unmap(start_addr, length)?;
+    // The address space is unlocked first, as writing back a page
+    // write-protects its mappings.
+    drop(aspace);
+    for (file, range) in files {
+        if let Err(err) = file.sync(range) {
+            warn!("Failed to write back unmapped pages {range:?}: {err:?}");
+        }
+    }
     Ok(0)
 }
 
@@ -285,59 +383,248 @@ pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> KResult<isize> {
 
     let curr = current();
     let mut aspace = curr.as_thread().proc_data.aspace.lock();
-    let length = align_up_4k(length);
-    let start_addr = VirtAddr::from(addr);
-    aspace.protect(start_addr, length, permission_flags.into())?;
+    mprotect(&mut aspace, addr, length, permission_flags, ENFORCE_WX)?;
 
     Ok(0)
 }
 
-pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) -> KResult<isize> {
+/// Changes the protection of the pages in `addr..addr + length` of `aspace`
+/// to `prot`.
+///
+/// Only the pages in the range change: mappings are split around it. If
+/// `enforce_wx`, they may only become writable and executable at once if they
+/// were created with [`MAP_JIT`].
+fn mprotect(
+    aspace: &mut AddrSpace,
+    addr: usize,
+    length: usize,
+    prot: MmapProt,
+    enforce_wx: bool,
+) -> KResult {
+    let length = align_up_4k(length);
+    let start = VirtAddr::from(addr);
+    if enforce_wx
+        && length > 0
+        && prot.contains(MmapProt::WRITE | MmapProt::EXEC)
+        && !aspace.can_access_range(start, length, MappingFlags::JIT)
+    {
+        return Err(KError::PermissionDenied);
+    }
+    aspace.protect(start, length, prot.into())
+}
+
+pub fn sys_mremap(
+    addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> KResult<isize> {
     debug!(
         "sys_mremap <= addr: {addr:#x}, old_size: {old_size:x}, new_size: {new_size:x}, flags: \
-         {flags:#x}"
+         {flags:#x}, new_addr: {new_addr:#x}"
     );
+    let curr = current();
+    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let end = VirtAddr::from(curr.as_thread().proc_data.user_space_end()).min(aspace.end());
+    let limit = VirtAddrRange::new(aspace.base(), end);
+    let addr = mremap(
+        &mut aspace,
+        limit,
+        addr,
+        old_size,
+        new_size,
+        flags,
+        new_addr,
+    )?;
+    Ok(addr.as_usize() as _)
+}
 
-    // TODO: full implementation
-
-    if !addr.is_multiple_of(PageSize::Size4K as usize) {
+/// Resizes or moves the mapping of `old_size` bytes at `addr`, within
+/// `limit`, and returns where it is mapped now.
+///
+/// Moving a mapping moves its pages: their contents are not copied.
+fn mremap(
+    aspace: &mut AddrSpace,
+    limit: VirtAddrRange,
+    addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> KResult<VirtAddr> {
+    let flags = MremapFlags::from_bits(flags).ok_or(KError::InvalidInput)?;
+    // TODO: MREMAP_DONTUNMAP
+    if flags.contains(MremapFlags::DONTUNMAP)
+        || (flags.contains(MremapFlags::FIXED) && !flags.contains(MremapFlags::MAYMOVE))
+        || !is_aligned_4k(addr)
+        || new_size == 0
+    {
         return Err(KError::InvalidInput);
     }
+    if old_size > limit.size() || new_size > limit.size() {
+        return Err(KError::NoMemory);
+    }
     let addr = VirtAddr::from(addr);
-
-    let curr = current();
-    let aspace = curr.as_thread().proc_data.aspace.lock();
     let old_size = align_up_4k(old_size);
     let new_size = align_up_4k(new_size);
+    let may_move = flags.contains(MremapFlags::MAYMOVE);
+    let find_free = |aspace: &AddrSpace| {
+        aspace
+            .find_free_area(limit.start, new_size, limit, PAGE_SIZE_4K)
+            .ok_or(KError::NoMemory)
+    };
 
-    let flags = aspace.find_area(addr).ok_or(KError::NoMemory)?.flags();
-    drop(aspace);
-    let new_addr = sys_mmap(
-        addr.as_usize(),
-        new_size,
-        flags.bits() as _,
-        MmapFlags::PRIVATE.bits(),
-        -1,
-        0,
-    )? as usize;
+    let target = if flags.contains(MremapFlags::FIXED) {
+        let target = VirtAddrRange::try_from_start_size(VirtAddr::from(new_addr), new_size)
+            .filter(|target| is_aligned_4k(new_addr) && limit.contains_range(*target))
+            .ok_or(KError::InvalidInput)?;
+        if target.overlaps(VirtAddrRange::from_start_size(addr, old_size)) {
+            return Err(KError::InvalidInput);
+        }
+        Some(target.start)
+    } else {
+        None
+    };
 
-    let copy_len = new_size.min(old_size);
-    let data = load_vec(addr.as_ptr(), copy_len)?;
-    write_vm_mem(new_addr as *mut u8, &data)?;
+    if old_size == 0 {
+        // A new mapping of the same pages, which only makes sense if they are
+        // shared.
+        if !may_move {
+            return Err(KError::InvalidInput);
+        }
+        let to = match target {
+            Some(to) => {
+                aspace.unmap(to, new_size)?;
+                to
+            }
+            None => find_free(aspace)?,
+        };
+        aspace.duplicate(addr, new_size, to)?;
+        return Ok(to);
+    }
 
-    sys_munmap(addr.as_usize(), old_size)?;
+    let area = aspace
+        .find_area(addr)
+        .filter(|area| area.end() - addr >= old_size)
+        .ok_or(KError::BadAddress)?;
+    if new_size > old_size && matches!(area.backend(), Backend::Shared(_) | Backend::Linear(_)) {
+        // Their pages are fixed when they are mapped.
+        return Err(KError::BadAddress);
+    }
 
-    Ok(new_addr as isize)
+    if let Some(to) = target {
+        aspace.unmap(to, new_size)?;
+        let size = old_size.min(new_size);
+        aspace.unmap(addr + size, old_size - size)?;
+        aspace.move_range(addr, size, to)?;
+        if new_size > size {
+            aspace.grow(to, size, new_size)?;
+        }
+        return Ok(to);
+    }
+
+    if new_size <= old_size {
+        aspace.unmap(addr + new_size, old_size - new_size)?;
+        return Ok(addr);
+    }
+    if addr
+        .as_usize()
+        .checked_add(new_size)
+        .is_some_and(|end| end <= limit.end.as_usize())
+    {
+        match aspace.grow(addr, old_size, new_size) {
+            Ok(()) => return Ok(addr),
+            Err(KError::NoMemory) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    if !may_move {
+        return Err(KError::NoMemory);
+    }
+    let to = find_free(aspace)?;
+    aspace.move_range(addr, old_size, to)?;
+    aspace.grow(to, old_size, new_size)?;
+    Ok(to)
 }
 
 pub fn sys_madvise(addr: usize, length: usize, advice: i32) -> KResult<isize> {
     debug!("sys_madvise <= addr: {addr:#x}, length: {length:x}, advice: {advice:#x}");
+    let curr = current();
+    madvise(&curr.as_thread().proc_data.aspace, addr, length, advice)?;
     Ok(0)
 }
 
+/// Applies `advice` to the pages in `addr..addr + length` of `aspace`.
+///
+/// The advice does not change the mappings, so they are not split. Like
+/// Linux, the advice is applied to the mapped parts of a range with holes,
+/// which then fails with `ENOMEM`.
+fn madvise(aspace: &Arc<Mutex<AddrSpace>>, addr: usize, length: usize, advice: i32) -> KResult {
+    if !is_aligned_4k(addr) {
+        return Err(KError::InvalidInput);
+    }
+    let start = VirtAddr::from(addr);
+    let size = align_up_4k(length);
+    if size < length || VirtAddrRange::try_from_start_size(start, size).is_none() {
+        return Err(KError::InvalidInput);
+    }
+    if size == 0 {
+        return Ok(());
+    }
+
+    let mut guard = aspace.lock();
+    if !guard.contains_range(start, size) {
+        return Err(KError::NoMemory);
+    }
+    let mapped = guard.can_access_range(start, size, MappingFlags::empty());
+    match advice as u32 {
+        MADV_DONTNEED => guard.discard(start, size)?,
+        MADV_FREE => {
+            drop(guard);
+            memspace::free_lazily(aspace, start, size)?;
+        }
+        MADV_WILLNEED => guard.will_need(start, size)?,
+        // Hints the kernel has no use for.
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_DONTFORK | MADV_DOFORK
+        | MADV_MERGEABLE | MADV_UNMERGEABLE | MADV_HUGEPAGE | MADV_NOHUGEPAGE | MADV_DONTDUMP
+        | MADV_DODUMP | MADV_COLD | MADV_PAGEOUT => {}
+        _ => return Err(KError::InvalidInput),
+    }
+    if !mapped {
+        return Err(KError::NoMemory);
+    }
+    Ok(())
+}
+
 pub fn sys_msync(addr: usize, length: usize, flags: u32) -> KResult<isize> {
     debug!("sys_msync <= addr: {addr:#x}, length: {length:x}, flags: {flags:#x}");
+    let Some(flags) = MsyncFlags::from_bits(flags) else {
+        return Err(KError::InvalidInput);
+    };
+    if flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) || !is_aligned_4k(addr) {
+        return Err(KError::InvalidInput);
+    }
+    let start = VirtAddr::from(addr);
+    let range =
+        VirtAddrRange::try_from_start_size(start, align_up_4k(length)).ok_or(KError::NoMemory)?;
 
+    let curr = current();
+    let aspace = curr.as_thread().proc_data.aspace.lock();
+    if !aspace.can_access_range(start, range.size(), MappingFlags::empty()) {
+        return Err(KError::NoMemory);
+    }
+    // Dirty pages are written back by the page cache in the background, so
+    // `MS_ASYNC` has nothing to start; `MS_INVALIDATE` has nothing to drop
+    // either, as mappings share the cached pages.
+    if !flags.contains(MsyncFlags::SYNC) {
+        return Ok(0);
+    }
+    let files = aspace.file_mappings(range);
+    drop(aspace);
+    for (file, range) in files {
+        file.sync(range)?;
+    }
     Ok(0)
 }
 
@@ -348,3 +635,437 @@ pub fn sys_mlock(addr: usize, length: usize) -> KResult<isize> {
 pub fn sys_mlock2(_addr: usize, _length: usize, _flags: u32) -> KResult<isize> {
     Ok(0)
 }
+
+#[cfg(unittest)]
+mod mremap_tests {
+    use khal::paging::MappingFlags;
+    use memaddr::va;
+    use unittest::def_test;
+
+    use super::*;
+
+    const PAGE: usize = PAGE_SIZE_4K;
+    const BASE: usize = 0x1000_0000;
+
+    fn setup() -> (AddrSpace, VirtAddrRange) {
+        let aspace = AddrSpace::new_empty(va!(BASE), 0x100 * PAGE).unwrap();
+        let limit = VirtAddrRange::new(aspace.base(), aspace.end());
+        (aspace, limit)
+    }
+
+    /// Maps `pages` private anonymous pages at page `page` of the space.
+    fn map(aspace: &mut AddrSpace, page: usize, pages: usize) -> VirtAddr {
+        let start = va!(BASE + page * PAGE);
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        aspace
+            .map(
+                start,
+                pages * PAGE,
+                flags,
+                true,
+                Backend::new_alloc(start, PageSize::Size4K),
+            )
+            .unwrap();
+        start
+    }
+
+    #[def_test]
+    fn test_mremap_grow_into_gap() {
+        let (mut aspace, limit) = setup();
+        let start = map(&mut aspace, 0, 2);
+        map(&mut aspace, 4, 1);
+
+        let grown = mremap(
+            &mut aspace,
+            limit,
+            start.as_usize(),
+            2 * PAGE,
+            4 * PAGE,
+            0,
+            0,
+        );
+        assert_eq!(grown, Ok(start));
+        let area = aspace.find_area(start + 3 * PAGE).unwrap();
+        assert_eq!((area.start(), area.size()), (start, 4 * PAGE));
+
+        // There is no gap left to grow into, and the mapping may not move.
+        let grown = mremap(
+            &mut aspace,
+            limit,
+            start.as_usize(),
+            4 * PAGE,
+            5 * PAGE,
+            0,
+            0,
+        );
+        assert_eq!(grown, Err(KError::NoMemory));
+
+        // Shrinking unmaps the tail.
+        let shrunk = mremap(&mut aspace, limit, start.as_usize(), 4 * PAGE, PAGE, 0, 0);
+        assert_eq!(shrunk, Ok(start));
+        assert!(aspace.find_area(start + PAGE).is_none());
+    }
+
+    #[def_test]
+    fn test_mremap_forced_move() {
+        let (mut aspace, limit) = setup();
+        let start = map(&mut aspace, 0, 2);
+        aspace.write(start + PAGE, b"moved").unwrap();
+        let (paddr, ..) = aspace.page_table().query(start + PAGE).unwrap();
+        // The target is mapped already, and gets replaced.
+        let target = map(&mut aspace, 8, 1);
+
+        let flags = MREMAP_MAYMOVE | MREMAP_FIXED;
+        let moved = mremap(
+            &mut aspace,
+            limit,
+            start.as_usize(),
+            2 * PAGE,
+            3 * PAGE,
+            flags,
+            target.as_usize(),
+        );
+        assert_eq!(moved, Ok(target));
+        assert!(aspace.find_area(start).is_none());
+        let area = aspace.find_area(target).unwrap();
+        assert_eq!((area.start(), area.size()), (target, 3 * PAGE));
+
+        // The page moved, rather than its contents.
+        assert_eq!(aspace.page_table().query(target + PAGE).unwrap().0, paddr);
+        let mut buf = [0; 5];
+        aspace.read(target + PAGE, &mut buf).unwrap();
+        assert_eq!(&buf, b"moved");
+
+        // Growing a blocked mapping moves it when allowed to.
+        map(&mut aspace, 11, 1);
+        let moved = mremap(
+            &mut aspace,
+            limit,
+            target.as_usize(),
+            3 * PAGE,
+            4 * PAGE,
+            MREMAP_MAYMOVE,
+            0,
+        )
+        .unwrap();
+        assert_ne!(moved, target);
+        assert_eq!(aspace.page_table().query(moved + PAGE).unwrap().0, paddr);
+    }
+
+    #[def_test]
+    fn test_mremap_errors() {
+        let (mut aspace, limit) = setup();
+        let start = map(&mut aspace, 0, 2);
+        map(&mut aspace, 3, 1);
+        let addr = start.as_usize();
+
+        let mut call = |addr, old_size, new_size, flags, new_addr| {
+            mremap(
+                &mut aspace,
+                limit,
+                addr,
+                old_size,
+                new_size,
+                flags,
+                new_addr,
+            )
+        };
+        assert_eq!(call(addr + 1, PAGE, PAGE, 0, 0), Err(KError::InvalidInput));
+        assert_eq!(call(addr, PAGE, 0, 0, 0), Err(KError::InvalidInput));
+        let fixed = BASE + 0x20 * PAGE;
+        assert_eq!(
+            call(addr, PAGE, PAGE, MREMAP_FIXED, fixed),
+            Err(KError::InvalidInput)
+        );
+        assert_eq!(
+            call(addr, PAGE, PAGE, MREMAP_MAYMOVE | MREMAP_FIXED, fixed + 1),
+            Err(KError::InvalidInput)
+        );
+        // Private mappings cannot be duplicated.
+        assert_eq!(
+            call(addr, 0, PAGE, MREMAP_MAYMOVE, 0),
+            Err(KError::InvalidInput)
+        );
+
+        // The old range runs into the hole at page 2.
+        assert_eq!(
+            call(addr, 4 * PAGE, 4 * PAGE, MREMAP_MAYMOVE, 0),
+            Err(KError::BadAddress)
+        );
+        assert_eq!(
+            call(BASE + 2 * PAGE, PAGE, 2 * PAGE, MREMAP_MAYMOVE, 0),
+            Err(KError::BadAddress)
+        );
+    }
+}
+
+#[cfg(unittest)]
+mod madvise_tests {
+    use khal::paging::MappingFlags;
+    use memaddr::va;
+    use unittest::def_test;
+
+    use super::*;
+
+    const PAGE: usize = PAGE_SIZE_4K;
+    const BASE: usize = 0x1000_0000;
+
+    /// Returns an address space with `pages` private anonymous pages mapped
+    /// and populated at its start.
+    fn setup(pages: usize) -> Arc<Mutex<AddrSpace>> {
+        let mut aspace = AddrSpace::new_empty(va!(BASE), 0x100 * PAGE).unwrap();
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        aspace
+            .map(
+                va!(BASE),
+                pages * PAGE,
+                flags,
+                true,
+                Backend::new_alloc(va!(BASE), PageSize::Size4K),
+            )
+            .unwrap();
+        Arc::new(Mutex::new(aspace))
+    }
+
+    /// Reads the byte at `addr`, faulting its page in as an access does.
+    fn load(aspace: &mut AddrSpace, addr: usize) -> u8 {
+        let vaddr = va!(addr);
+        if aspace.page_table().query(vaddr).is_err() {
+            aspace.handle_page_fault(vaddr, MappingFlags::READ).unwrap();
+        }
+        let mut buf = [0xff];
+        aspace.read(vaddr, &mut buf).unwrap();
+        buf[0]
+    }
+
+    /// Writes `value` at `addr`, faulting its page in as an access does.
+    fn store(aspace: &mut AddrSpace, addr: usize, value: u8) {
+        let vaddr = va!(addr);
+        if !aspace
+            .page_table()
+            .query(vaddr)
+            .is_ok_and(|(_, flags, _)| flags.contains(MappingFlags::WRITE))
+        {
+            aspace
+                .handle_page_fault(vaddr, MappingFlags::WRITE)
+                .unwrap();
+        }
+        aspace.write(vaddr, &[value]).unwrap();
+    }
+
+    #[def_test]
+    fn test_madvise_dontneed() {
+        let aspace = setup(4);
+        for page in 0..4 {
+            store(&mut aspace.lock(), BASE + page * PAGE + 5, 7);
+        }
+        let rss = aspace.lock().resident_size();
+        assert_eq!(rss, 4 * PAGE);
+
+        // The length is rounded up to whole pages.
+        madvise(&aspace, BASE + PAGE, PAGE + 1, MADV_DONTNEED as _).unwrap();
+        let mut aspace = aspace.lock();
+        assert_eq!(aspace.resident_size(), 2 * PAGE);
+        assert_eq!(load(&mut aspace, BASE + 5), 7);
+        assert_eq!(load(&mut aspace, BASE + PAGE + 5), 0);
+        assert_eq!(load(&mut aspace, BASE + 2 * PAGE + 5), 0);
+        assert_eq!(load(&mut aspace, BASE + 3 * PAGE + 5), 7);
+    }
+
+    #[def_test]
+    fn test_madvise_free() {
+        let aspace = setup(3);
+        for page in 0..3 {
+            store(&mut aspace.lock(), BASE + page * PAGE, 7);
+        }
+        madvise(&aspace, BASE, 3 * PAGE, MADV_FREE as _).unwrap();
+
+        // The data stays until memory runs short, and a page written since
+        // is kept then.
+        assert_eq!(load(&mut aspace.lock(), BASE), 7);
+        store(&mut aspace.lock(), BASE + PAGE, 8);
+        assert_eq!(aspace.lock().reclaim_lazy_free(usize::MAX), 2);
+
+        let mut aspace = aspace.lock();
+        assert_eq!(aspace.resident_size(), PAGE);
+        assert_eq!(load(&mut aspace, BASE), 0);
+        assert_eq!(load(&mut aspace, BASE + PAGE), 8);
+        assert_eq!(load(&mut aspace, BASE + 2 * PAGE), 0);
+    }
+
+    #[def_test]
+    fn test_madvise_errors() {
+        let aspace = setup(2);
+        assert_eq!(
+            madvise(&aspace, BASE + 1, PAGE, MADV_DONTNEED as _),
+            Err(KError::InvalidInput)
+        );
+        assert_eq!(
+            madvise(&aspace, BASE, PAGE, 1000),
+            Err(KError::InvalidInput)
+        );
+        assert_eq!(madvise(&aspace, BASE, 0, MADV_DONTNEED as _), Ok(()));
+
+        // The mapped part of a range with holes is still advised.
+        store(&mut aspace.lock(), BASE + PAGE, 7);
+        assert_eq!(
+            madvise(&aspace, BASE + PAGE, 2 * PAGE, MADV_DONTNEED as _),
+            Err(KError::NoMemory)
+        );
+        assert_eq!(load(&mut aspace.lock(), BASE + PAGE), 0);
+    }
+}
+
+#[cfg(unittest)]
+mod mprotect_tests {
+    use khal::paging::MappingFlags;
+    use memaddr::va;
+    use unittest::def_test;
+
+    use super::*;
+
+    const PAGE: usize = PAGE_SIZE_4K;
+    const BASE: usize = 0x1000_0000;
+
+    /// Returns an address space with `pages` private anonymous pages mapped
+    /// and populated read-write at its start, with `flags` added.
+    fn setup(pages: usize, flags: MappingFlags) -> AddrSpace {
+        let mut aspace = AddrSpace::new_empty(va!(BASE), 0x100 * PAGE).unwrap();
+        let flags = flags | MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        aspace
+            .map(
+                va!(BASE),
+                pages * PAGE,
+                flags,
+                true,
+                Backend::new_alloc(va!(BASE), PageSize::Size4K),
+            )
+            .unwrap();
+        aspace
+    }
+
+    fn page_flags(aspace: &AddrSpace, page: usize) -> MappingFlags {
+        aspace
+            .page_table()
+            .query(va!(BASE + page * PAGE))
+            .unwrap()
+            .1
+    }
+
+    #[def_test]
+    fn test_mprotect_split_at_start() {
+        let mut aspace = setup(4, MappingFlags::empty());
+        mprotect(&mut aspace, BASE, PAGE, MmapProt::READ, false).unwrap();
+        assert_eq!(aspace.map_count(), 2);
+
+        let area = aspace.find_area(va!(BASE)).unwrap();
+        assert_eq!((area.start(), area.size()), (va!(BASE), PAGE));
+        assert!(!area.flags().contains(MappingFlags::WRITE));
+        let area = aspace.find_area(va!(BASE + PAGE)).unwrap();
+        assert_eq!((area.start(), area.size()), (va!(BASE + PAGE), 3 * PAGE));
+        assert!(area.flags().contains(MappingFlags::WRITE));
+
+        // Only the entries of the pages protected change.
+        assert!(!page_flags(&aspace, 0).contains(MappingFlags::WRITE));
+        assert!(page_flags(&aspace, 1).contains(MappingFlags::WRITE));
+    }
+
+    #[def_test]
+    fn test_mprotect_split_in_middle() {
+        let mut aspace = setup(4, MappingFlags::empty());
+        // The length is rounded up to whole pages.
+        mprotect(&mut aspace, BASE + PAGE, PAGE + 1, MmapProt::READ, false).unwrap();
+        assert_eq!(aspace.map_count(), 3);
+
+        let area = aspace.find_area(va!(BASE + 2 * PAGE)).unwrap();
+        assert_eq!((area.start(), area.size()), (va!(BASE + PAGE), 2 * PAGE));
+        for (page, writable) in [(0, true), (1, false), (2, false), (3, true)] {
+            assert_eq!(
+                page_flags(&aspace, page).contains(MappingFlags::WRITE),
+                writable
+            );
+        }
+    }
+
+    #[def_test]
+    fn test_mprotect_remerge() {
+        let mut aspace = setup(4, MappingFlags::empty());
+        let rw = MmapProt::READ | MmapProt::WRITE;
+        mprotect(&mut aspace, BASE + PAGE, PAGE, MmapProt::READ, false).unwrap();
+        mprotect(&mut aspace, BASE + 3 * PAGE, PAGE, MmapProt::READ, false).unwrap();
+        assert_eq!(aspace.map_count(), 4);
+
+        // Restoring the protection merges the areas back.
+        mprotect(&mut aspace, BASE + PAGE, PAGE, rw, false).unwrap();
+        assert_eq!(aspace.map_count(), 2);
+        mprotect(&mut aspace, BASE + 3 * PAGE, PAGE, rw, false).unwrap();
+        assert_eq!(aspace.map_count(), 1);
+        let area = aspace.find_area(va!(BASE)).unwrap();
+        assert_eq!(area.size(), 4 * PAGE);
+
+        // Separate mappings of anonymous memory merge as well.
+        let start = va!(BASE + 4 * PAGE);
+        aspace
+            .map(
+                start,
+                PAGE,
+                MappingFlags::READ | MappingFlags::USER,
+                false,
+                Backend::new_alloc(start, PageSize::Size4K),
+            )
+            .unwrap();
+        assert_eq!(aspace.map_count(), 2);
+        mprotect(&mut aspace, start.as_usize(), PAGE, rw, false).unwrap();
+        assert_eq!(aspace.map_count(), 1);
+    }
+
+    #[def_test]
+    fn test_mprotect_write_xor_execute() {
+        let rwx = MmapProt::READ | MmapProt::WRITE | MmapProt::EXEC;
+        let mut aspace = setup(2, MappingFlags::empty());
+        assert_eq!(
+            mprotect(&mut aspace, BASE, PAGE, rwx, true),
+            Err(KError::PermissionDenied)
+        );
+        assert_eq!(aspace.map_count(), 1);
+        mprotect(
+            &mut aspace,
+            BASE,
+            PAGE,
+            MmapProt::READ | MmapProt::EXEC,
+            true,
+        )
+        .unwrap();
+        mprotect(&mut aspace, BASE + PAGE, PAGE, rwx, false).unwrap();
+
+        // JIT mappings stay so through changes of protection, and do not
+        // merge with others.
+        let mut aspace = setup(2, MappingFlags::JIT);
+        mprotect(&mut aspace, BASE, PAGE, MmapProt::READ, true).unwrap();
+        mprotect(&mut aspace, BASE, 2 * PAGE, rwx, true).unwrap();
+        let area = aspace.find_area(va!(BASE)).unwrap();
+        assert_eq!(area.size(), 2 * PAGE);
+        assert!(
+            area.flags()
+                .contains(MappingFlags::JIT | MappingFlags::EXECUTE)
+        );
+
+        let start = va!(BASE + 2 * PAGE);
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        aspace
+            .map(
+                start,
+                PAGE,
+                flags,
+                false,
+                Backend::new_alloc(start, PageSize::Size4K),
+            )
+            .unwrap();
+        assert_eq!(
+            mprotect(&mut aspace, BASE, 3 * PAGE, rwx, true),
+            Err(KError::PermissionDenied)
+        );
+        mprotect(&mut aspace, BASE, 3 * PAGE, MmapProt::READ, true).unwrap();
+        assert_eq!(aspace.map_count(), 2);
+    }
+}
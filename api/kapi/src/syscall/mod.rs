@@ -8,10 +8,13 @@
 //! to the appropriate handler functions based on the syscall number.
 //!
 //! The module is organized into submodules for different categories:
+//! - `compat`: Syscalls of 32-bit ARM programs (aarch64 with the `compat` feature)
+//! - `fault`: Syscall fault injection (debug builds only)
 //! - `fs`: File system operations
 //! - `io_mpx`: I/O multiplexing (select, poll, epoll)
 //! - `ipc`: Inter-process communication
 //! - `mm`: Memory management
+//! - `multiop`: Batches of file descriptor operations
 //! - `net`: Network operations
 //! - `resources`: Resource limits and usage
 //! - `signal`: Signal handling
@@ -20,11 +23,17 @@
 //! - `task`: Process and thread management
 //! - `time`: Time-related operations
 
+#[cfg(all(target_arch = "aarch64", feature = "compat"))]
+mod compat;
+#[cfg(debug_assertions)]
+pub mod fault;
 mod fs;
 mod io_mpx;
 mod ipc;
 mod mm;
+mod multiop;
 mod net;
+mod perf;
 mod resources;
 mod signal;
 mod sync;
@@ -32,28 +41,114 @@ mod sys;
 mod task;
 mod time;
 
-use kerrno::LinuxError;
+use flightrec::Event;
+use kcore::task::AsThread;
+use kerrno::{KError, KResult, LinuxError};
 use khal::uspace::UserContext;
+use ksignal::api::SyscallRestart;
+use ktask::current;
 use linux_sysno::Sysno;
+use static_keys::{define_tracepoint, trace_event};
 // Re-export sys_getrandom for use in TEE modules
 pub use sys::sys_getrandom;
 
 use self::{
-    fs::*, io_mpx::*, ipc::*, mm::*, net::*, resources::*, signal::*, sync::*, sys::*, task::*,
-    time::*,
+    fs::*, io_mpx::*, ipc::*, mm::*, multiop::*, net::*, perf::*, resources::*, signal::*, sync::*,
+    sys::*, task::*, time::*,
 };
 
+/// Length of the syscall instruction, which the trapped ip points past.
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_INSN_LEN: usize = 2;
+#[cfg(not(target_arch = "x86_64"))]
+const SYSCALL_INSN_LEN: usize = 4;
+
+define_tracepoint!(
+    /// Fired on every syscall entry with the syscall number and arguments.
+    SYS_ENTER
+);
+
 /// Dispatches a syscall from the given user context.
 pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
+    #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+    if uctx.is_aarch32() {
+        compat::dispatch_compat_syscall(uctx);
+        return;
+    }
+
     let Some(sysno) = Sysno::new(uctx.sysno()) else {
         warn!("Invalid syscall number: {}", uctx.sysno());
         uctx.set_retval(-LinuxError::ENOSYS.into_raw() as _);
         return;
     };
 
+    let (ip, arg0) = (uctx.ip(), uctx.arg0());
+    let result = run_syscall(sysno, uctx);
+    if matches!(result, Err(KError::Interrupted)) && is_restartable(sysno) {
+        // Issued again on the way back to user space, unless a handler
+        // without `SA_RESTART` runs first.
+        current()
+            .as_thread()
+            .signal
+            .set_syscall_restart(SyscallRestart {
+                ip: ip - SYSCALL_INSN_LEN,
+                sysno: sysno.id() as _,
+                arg0,
+            });
+    }
+    uctx.set_retval(result.unwrap_or_else(|err| -LinuxError::from(err).into_raw() as _) as _);
+}
+
+/// Returns whether `sysno` is restarted after being interrupted by a signal.
+fn is_restartable(sysno: Sysno) -> bool {
+    matches!(sysno, Sysno::wait4 | Sysno::waitid)
+}
+
+/// Runs the syscall `sysno` with the arguments in `uctx`, through the same
+/// tracing and interception as a call from user space.
+fn run_syscall(sysno: Sysno, uctx: &mut UserContext) -> KResult<isize> {
     trace!("Syscall {sysno:?}");
+    trace_event!(
+        SYS_ENTER,
+        "{sysno:?}({:#x}, {:#x}, {:#x})",
+        uctx.arg0(),
+        uctx.arg1(),
+        uctx.arg2()
+    );
+    let tid = current().id().as_u64();
+    let nr = sysno.id() as u32;
+    flightrec::record(
+        tid,
+        Event::SyscallEnter {
+            nr,
+            args: [
+                uctx.arg0(),
+                uctx.arg1(),
+                uctx.arg2(),
+                uctx.arg3(),
+                uctx.arg4(),
+                uctx.arg5(),
+            ]
+            .map(|arg| arg as u64),
+        },
+    );
+
+    #[cfg(debug_assertions)]
+    let result = fault::intercept(sysno, uctx, handle_syscall);
+    #[cfg(not(debug_assertions))]
+    let result = handle_syscall(sysno, uctx);
+    debug!("Syscall {sysno} return {result:?}");
+    let ret = match result {
+        Ok(ret) => ret as i64,
+        Err(err) => -(LinuxError::from(err).into_raw() as i64),
+    };
+    flightrec::record(tid, Event::SyscallExit { nr, ret });
+    result
+}
 
-    let result = match sysno {
+/// Runs the handler of `sysno`.
+fn handle_syscall(sysno: Sysno, uctx: &mut UserContext) -> KResult<isize> {
+    match sysno {
         // fs ctl
         Sysno::ioctl => sys_ioctl(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
         Sysno::chdir => sys_chdir(uctx.arg0() as _),
@@ -62,6 +157,14 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         #[cfg(target_arch = "x86_64")]
         Sysno::mkdir => sys_mkdir(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::mkdirat => sys_mkdirat(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::mknod => sys_mknod(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
+        Sysno::mknodat => sys_mknodat(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
         Sysno::getdents64 => sys_getdents64(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::link => sys_link(uctx.arg0() as _, uctx.arg1() as _),
@@ -164,7 +267,7 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         Sysno::write => sys_write(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
         Sysno::writev => sys_writev(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
         Sysno::lseek => sys_lseek(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
-        Sysno::truncate => sys_truncate(uctx.arg0().into(), uctx.arg1() as _),
+        Sysno::truncate => sys_truncate(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::ftruncate => sys_ftruncate(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::fallocate => sys_fallocate(
             uctx.arg0() as _,
@@ -309,6 +412,17 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         // event
         Sysno::eventfd2 => sys_eventfd2(uctx.arg0() as _, uctx.arg1() as _),
 
+        // inotify
+        Sysno::inotify_init1 => sys_inotify_init1(uctx.arg0() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::inotify_init => sys_inotify_init(),
+        Sysno::inotify_add_watch => sys_inotify_add_watch(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+        ),
+        Sysno::inotify_rm_watch => sys_inotify_rm_watch(uctx.arg0() as _, uctx.arg1() as _),
+
         // pidfd
         Sysno::pidfd_open => sys_pidfd_open(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::pidfd_getfd => sys_pidfd_getfd(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
@@ -359,6 +473,57 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         ),
         Sysno::statfs => sys_statfs(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::fstatfs => sys_fstatfs(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::getxattr => sys_getxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::lgetxattr => sys_lgetxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::fgetxattr => sys_fgetxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::setxattr => sys_setxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
+        Sysno::lsetxattr => sys_lsetxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
+        Sysno::fsetxattr => sys_fsetxattr(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
+        Sysno::listxattr => {
+            sys_listxattr(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
+        }
+        Sysno::llistxattr => {
+            sys_llistxattr(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
+        }
+        Sysno::flistxattr => {
+            sys_flistxattr(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
+        }
+        Sysno::removexattr => sys_removexattr(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::lremovexattr => sys_lremovexattr(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::fremovexattr => sys_fremovexattr(uctx.arg0() as _, uctx.arg1() as _),
 
         // mm
         Sysno::brk => sys_brk(uctx.arg0() as _),
@@ -378,6 +543,7 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
             uctx.arg1() as _,
             uctx.arg2() as _,
             uctx.arg3() as _,
+            uctx.arg4(),
         ),
         Sysno::madvise => sys_madvise(uctx.arg0(), uctx.arg1() as _, uctx.arg2() as _),
         Sysno::msync => sys_msync(uctx.arg0(), uctx.arg1() as _, uctx.arg2() as _),
@@ -410,7 +576,14 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
             sys_sched_setscheduler(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
         }
         Sysno::sched_getparam => sys_sched_getparam(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::sched_setparam => sys_sched_setparam(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::sched_get_priority_max => sys_sched_get_priority_max(uctx.arg0() as _),
+        Sysno::sched_get_priority_min => sys_sched_get_priority_min(uctx.arg0() as _),
+        Sysno::sched_rr_get_interval => {
+            sys_sched_rr_get_interval(uctx.arg0() as _, uctx.arg1() as _)
+        }
         Sysno::getpriority => sys_getpriority(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::setpriority => sys_setpriority(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
 
         // task ops
         Sysno::execve => sys_execve(uctx, uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
@@ -457,7 +630,19 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         Sysno::fork => sys_fork(uctx),
         Sysno::exit => sys_exit(uctx.arg0() as _),
         Sysno::exit_group => sys_exit_group(uctx.arg0() as _),
-        Sysno::wait4 => sys_waitpid(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
+        Sysno::wait4 => sys_wait4(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+        ),
+        Sysno::waitid => sys_waitid(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
         Sysno::getsid => sys_getsid(uctx.arg0() as _),
         Sysno::setsid => sys_setsid(),
         Sysno::getpgid => sys_getpgid(uctx.arg0() as _),
@@ -489,20 +674,16 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         Sysno::kill => sys_kill(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::tkill => sys_tkill(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::tgkill => sys_tgkill(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
-        Sysno::rt_sigqueueinfo => sys_rt_sigqueueinfo(
-            uctx.arg0() as _,
-            uctx.arg1() as _,
-            uctx.arg2() as _,
-            uctx.arg3() as _,
-        ),
+        Sysno::rt_sigqueueinfo => {
+            sys_rt_sigqueueinfo(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _)
+        }
         Sysno::rt_tgsigqueueinfo => sys_rt_tgsigqueueinfo(
             uctx.arg0() as _,
             uctx.arg1() as _,
             uctx.arg2() as _,
             uctx.arg3() as _,
-            uctx.arg4() as _,
         ),
-        Sysno::sigaltstack => sys_sigaltstack(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::sigaltstack => sys_sigaltstack(uctx, uctx.arg0() as _, uctx.arg1() as _),
         Sysno::futex => sys_futex(
             uctx.arg0() as _,
             uctx.arg1() as _,
@@ -537,9 +718,13 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
         Sysno::membarrier => sys_membarrier(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
 
         // time
-        Sysno::gettimeofday => sys_gettimeofday(uctx.arg0() as _),
+        Sysno::gettimeofday => sys_gettimeofday(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::settimeofday => sys_settimeofday(uctx.arg0() as _, uctx.arg1() as _),
+        #[cfg(target_arch = "x86_64")]
+        Sysno::time => sys_time(uctx.arg0() as _),
         Sysno::times => sys_times(uctx.arg0() as _),
         Sysno::clock_gettime => sys_clock_gettime(uctx.arg0() as _, uctx.arg1() as _),
+        Sysno::clock_settime => sys_clock_settime(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::clock_getres => sys_clock_getres(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::getitimer => sys_getitimer(uctx.arg0() as _, uctx.arg1() as _),
         Sysno::setitimer => sys_setitimer(uctx.arg0() as _, uctx.arg1() as _, uctx.arg2() as _),
@@ -625,6 +810,17 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
             uctx.arg4() as _,
         ),
 
+        // io_uring
+        Sysno::io_uring_setup => sys_io_uring_setup(uctx.arg0() as _, uctx.arg1().into()),
+        Sysno::io_uring_enter => sys_io_uring_enter(
+            uctx.arg0() as _,
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4().into(),
+            uctx.arg5() as _,
+        ),
+
         // signal file descriptors
         Sysno::signalfd4 => sys_signalfd4(
             uctx.arg0() as _,
@@ -633,13 +829,19 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
             uctx.arg3() as _,
         ),
 
+        // performance events
+        Sysno::perf_event_open => sys_perf_event_open(
+            uctx.arg0().into(),
+            uctx.arg1() as _,
+            uctx.arg2() as _,
+            uctx.arg3() as _,
+            uctx.arg4() as _,
+        ),
+
         // dummy fds
         Sysno::timerfd_create
         | Sysno::fanotify_init
-        | Sysno::inotify_init1
         | Sysno::userfaultfd
-        | Sysno::perf_event_open
-        | Sysno::io_uring_setup
         | Sysno::bpf
         | Sysno::fsopen
         | Sysno::fspick
@@ -648,6 +850,8 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
 
         Sysno::timer_create | Sysno::timer_gettime | Sysno::timer_settime => Ok(0),
 
+        Sysno::multiop => sys_multiop(uctx, uctx.arg0() as _, uctx.arg1() as _),
+
         _ => {
             #[cfg(feature = "tee")]
             {
@@ -666,8 +870,5 @@ pub fn dispatch_irq_syscall(uctx: &mut UserContext) {
                 Err(kerrno::KError::Unsupported)
             }
         }
-    };
-    debug!("Syscall {sysno} return {result:?}");
-
-    uctx.set_retval(result.unwrap_or_else(|err| -LinuxError::from(err).into_raw() as _) as _);
+    }
 }
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Batched file descriptor operations.
+//!
+//! `multiop(ops, count)` runs up to [`MULTIOP_MAX_OPS`] operations taken from
+//! a small set (read, write, pread, pwrite, close, lseek, fsync) in one
+//! syscall, to save the entry and exit cost of issuing them one by one.
+//!
+//! Each operation runs exactly as the syscall it stands for, through the same
+//! handler and the same interception, so fault injection rules see every
+//! operation as a call of its own. Operations run in order, and the result
+//! of each one, the return value or a negated errno, is stored in its
+//! `result` field:
+//!
+//! - The array is copied in before anything runs, and must be readable in
+//!   full: otherwise the call fails with `EFAULT` and runs nothing. It is
+//!   written back once the batch is over; if that faults the call fails with
+//!   `EFAULT` although the operations ran.
+//! - An invalid opcode or flag, more than [`MULTIOP_MAX_OPS`] operations or
+//!   buffers adding up to more than [`MULTIOP_MAX_BYTES`] fail the call with
+//!   `EINVAL` before anything runs.
+//! - A failing operation, such as one on a bad fd, does not stop the batch
+//!   unless it has [`MULTIOP_F_ABORT`] or failed with `EINTR`, so that
+//!   signals are handled without delay. Operations left out get
+//!   `-ECANCELED`.
+//!
+//! The call returns the number of operations that ran.
+
+use alloc::vec::Vec;
+
+use bytemuck::{Pod, Zeroable};
+use kerrno::{KError, KResult, LinuxError};
+use khal::uspace::UserContext;
+use linux_sysno::Sysno;
+use osvm::{load_vec, write_vm_mem};
+
+use super::run_syscall;
+
+/// Maximum number of operations in a batch.
+pub const MULTIOP_MAX_OPS: usize = 128;
+/// Maximum number of bytes the buffers of a batch may add up to.
+pub const MULTIOP_MAX_BYTES: usize = 1024 * 1024;
+
+/// `read(fd, buf, len)`.
+pub const MULTIOP_READ: u32 = 0;
+/// `write(fd, buf, len)`.
+pub const MULTIOP_WRITE: u32 = 1;
+/// `pread64(fd, buf, len, offset)`.
+pub const MULTIOP_PREAD: u32 = 2;
+/// `pwrite64(fd, buf, len, offset)`.
+pub const MULTIOP_PWRITE: u32 = 3;
+/// `close(fd)`.
+pub const MULTIOP_CLOSE: u32 = 4;
+/// `lseek(fd, offset, whence)`, with `whence` given in `len`.
+pub const MULTIOP_LSEEK: u32 = 5;
+/// `fsync(fd)`.
+pub const MULTIOP_FSYNC: u32 = 6;
+
+/// Stops the batch if the operation fails.
+pub const MULTIOP_F_ABORT: u32 = 1 << 0;
+
+/// One operation of a batch, as laid out in user memory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct MultiOp {
+    /// One of the `MULTIOP_*` opcodes.
+    pub opcode: u32,
+    /// `MULTIOP_F_*` flags.
+    pub flags: u32,
+    pub fd: i32,
+    /// Must be zero.
+    pub reserved: u32,
+    pub buf: u64,
+    pub len: u64,
+    pub offset: i64,
+    /// Set by the kernel: the return value of the operation, or a negated
+    /// errno.
+    pub result: i64,
+}
+
+impl MultiOp {
+    /// Returns the syscall this operation stands for, with its arguments.
+    fn syscall(&self) -> KResult<(Sysno, [usize; 4])> {
+        let (fd, buf, len, offset) = (
+            self.fd as usize,
+            self.buf as usize,
+            self.len as usize,
+            self.offset as usize,
+        );
+        Ok(match self.opcode {
+            MULTIOP_READ => (Sysno::read, [fd, buf, len, 0]),
+            MULTIOP_WRITE => (Sysno::write, [fd, buf, len, 0]),
+            MULTIOP_PREAD => (Sysno::pread64, [fd, buf, len, offset]),
+            MULTIOP_PWRITE => (Sysno::pwrite64, [fd, buf, len, offset]),
+            MULTIOP_CLOSE => (Sysno::close, [fd, 0, 0, 0]),
+            MULTIOP_LSEEK => (Sysno::lseek, [fd, offset, len, 0]),
+            MULTIOP_FSYNC => (Sysno::fsync, [fd, 0, 0, 0]),
+            _ => return Err(KError::InvalidInput),
+        })
+    }
+
+    /// Returns the number of bytes the operation transfers at most.
+    fn buf_len(&self) -> u64 {
+        match self.opcode {
+            MULTIOP_READ | MULTIOP_WRITE | MULTIOP_PREAD | MULTIOP_PWRITE => self.len,
+            _ => 0,
+        }
+    }
+}
+
+/// Checks a whole batch before running any of it.
+fn validate(ops: &[MultiOp]) -> KResult<()> {
+    if ops.len() > MULTIOP_MAX_OPS {
+        return Err(KError::InvalidInput);
+    }
+    let mut total = 0u64;
+    for op in ops {
+        op.syscall()?;
+        if op.flags & !MULTIOP_F_ABORT != 0 || op.reserved != 0 {
+            return Err(KError::InvalidInput);
+        }
+        total = total.saturating_add(op.buf_len());
+    }
+    if total > MULTIOP_MAX_BYTES as u64 {
+        return Err(KError::InvalidInput);
+    }
+    Ok(())
+}
+
+fn errno_result(err: LinuxError) -> i64 {
+    -(err.into_raw() as i64)
+}
+
+/// Runs `ops` in order with `run`, storing the results. Returns the number
+/// of operations that ran.
+fn run_batch(
+    ops: &mut [MultiOp],
+    mut run: impl FnMut(Sysno, [usize; 4]) -> KResult<isize>,
+) -> usize {
+    for (i, op) in ops.iter_mut().enumerate() {
+        let (sysno, args) = op.syscall().expect("batch validated before running");
+        match run(sysno, args) {
+            Ok(ret) => op.result = ret as i64,
+            Err(err) => {
+                let err = LinuxError::from(err);
+                op.result = errno_result(err);
+                if op.flags & MULTIOP_F_ABORT != 0 || err == LinuxError::EINTR {
+                    for op in &mut ops[i + 1..] {
+                        op.result = errno_result(LinuxError::ECANCELED);
+                    }
+                    return i + 1;
+                }
+            }
+        }
+    }
+    ops.len()
+}
+
+/// Runs a batch of `count` operations described by the array at `ops`.
+pub fn sys_multiop(uctx: &UserContext, ops: *mut MultiOp, count: usize) -> KResult<isize> {
+    debug!("sys_multiop <= ops: {ops:p}, count: {count}");
+    if count > MULTIOP_MAX_OPS {
+        return Err(KError::InvalidInput);
+    }
+    let mut batch: Vec<MultiOp> = load_vec(ops, count)?;
+    validate(&batch)?;
+
+    let ran = run_batch(&mut batch, |sysno, args| {
+        let mut sub = *uctx;
+        sub.set_sysno(sysno.id() as _);
+        sub.set_arg0(args[0]);
+        sub.set_arg1(args[1]);
+        sub.set_arg2(args[2]);
+        sub.set_arg3(args[3]);
+        run_syscall(sysno, &mut sub)
+    });
+
+    write_vm_mem(ops, &batch)?;
+    Ok(ran as _)
+}
+
+#[cfg(unittest)]
+mod multiop_tests {
+    use unittest::def_test;
+
+    use super::*;
+
+    fn op(opcode: u32, fd: i32, len: u64, flags: u32) -> MultiOp {
+        MultiOp {
+            opcode,
+            flags,
+            fd,
+            len,
+            ..Default::default()
+        }
+    }
+
+    #[def_test]
+    fn test_multiop_layout() {
+        assert_eq!(size_of::<MultiOp>(), 48);
+        let lseek = MultiOp {
+            offset: -4,
+            ..op(MULTIOP_LSEEK, 3, 2, 0)
+        };
+        assert_eq!(
+            lseek.syscall().unwrap(),
+            (Sysno::lseek, [3, -4i64 as usize, 2, 0])
+        );
+    }
+
+    #[def_test]
+    fn test_multiop_validate() {
+        assert!(validate(&[op(MULTIOP_READ, 0, 16, 0), op(MULTIOP_CLOSE, 0, 0, 0)]).is_ok());
+        // Unknown opcodes and flags, and used reserved fields.
+        assert!(validate(&[op(7, 0, 0, 0)]).is_err());
+        assert!(validate(&[op(MULTIOP_FSYNC, 0, 0, 1 << 5)]).is_err());
+        assert!(
+            validate(&[MultiOp {
+                reserved: 1,
+                ..op(MULTIOP_CLOSE, 0, 0, 0)
+            }])
+            .is_err()
+        );
+
+        // Only the buffers count towards the byte cap.
+        let half = (MULTIOP_MAX_BYTES / 2) as u64;
+        let ops = [
+            op(MULTIOP_READ, 0, half, 0),
+            op(MULTIOP_PWRITE, 1, half, 0),
+            op(MULTIOP_LSEEK, 1, u64::MAX, 0),
+        ];
+        assert!(validate(&ops).is_ok());
+        assert!(validate(&[op(MULTIOP_WRITE, 1, half + 1, 0), ops[0]]).is_err());
+        assert!(validate(&[op(MULTIOP_CLOSE, 0, 0, 0); MULTIOP_MAX_OPS + 1]).is_err());
+    }
+
+    #[def_test]
+    fn test_multiop_bad_fd_mid_batch() {
+        let run = |sysno: Sysno, args: [usize; 4]| match (sysno, args[0]) {
+            (_, 9) => Err(KError::BadFileDescriptor),
+            (Sysno::read, _) => Ok(args[2] as isize),
+            _ => Ok(0),
+        };
+
+        // Without the abort flag, a bad fd fails only its own operation.
+        let mut ops = [
+            op(MULTIOP_READ, 3, 8, 0),
+            op(MULTIOP_READ, 9, 8, 0),
+            op(MULTIOP_CLOSE, 3, 0, 0),
+        ];
+        assert_eq!(run_batch(&mut ops, run), 3);
+        let results: Vec<i64> = ops.iter().map(|op| op.result).collect();
+        assert_eq!(results, [8, errno_result(LinuxError::EBADF), 0]);
+
+        // With it, the rest of the batch is cancelled.
+        ops[1].flags = MULTIOP_F_ABORT;
+        assert_eq!(run_batch(&mut ops, run), 2);
+        assert_eq!(ops[1].result, errno_result(LinuxError::EBADF));
+        assert_eq!(ops[2].result, errno_result(LinuxError::ECANCELED));
+    }
+
+    #[def_test]
+    fn test_multiop_interrupted() {
+        let mut ops = [op(MULTIOP_READ, 3, 8, 0), op(MULTIOP_WRITE, 4, 8, 0)];
+        let ran = run_batch(&mut ops, |_, _| Err(KError::Interrupted));
+        assert_eq!(ran, 1);
+        assert_eq!(ops[0].result, errno_result(LinuxError::EINTR));
+        assert_eq!(ops[1].result, errno_result(LinuxError::ECANCELED));
+    }
+}
@@ -9,18 +9,59 @@
 
 use alloc::{sync::Arc, vec::Vec};
 
+use kcore::task::{AsThread, get_process_data};
 use kerrno::{KError, KResult};
-use linux_raw_sys::net::{SCM_RIGHTS, SOL_SOCKET, cmsghdr};
+use knet::{
+    options::UnixCredentials,
+    unix::{MAX_PASSED_FDS, PassedSockets},
+};
+use ktask::current;
+use linux_raw_sys::net::{SCM_CREDENTIALS, SCM_RIGHTS, SOL_SOCKET, cmsghdr, ucred};
 
 use crate::{
-    file::{FileLike, get_file_like},
+    file::{FileLike, Socket, get_file_like},
     mm::{UserConstPtr, UserPtr},
+    syscall::sys::{sys_getegid, sys_geteuid},
 };
 
+/// Rounds `len` up to the alignment of control messages, as `CMSG_ALIGN`
+/// does: each message of a buffer starts at such a boundary.
+pub const fn cmsg_align(len: usize) -> usize {
+    len.next_multiple_of(size_of::<usize>())
+}
+
+/// Returns the credentials of the current process.
+///
+/// Every task runs as root, see `sys_geteuid`.
+pub fn current_credentials() -> UnixCredentials {
+    UnixCredentials {
+        pid: current().as_thread().proc_data.proc.pid(),
+        uid: sys_geteuid().unwrap_or_default() as _,
+        gid: sys_getegid().unwrap_or_default() as _,
+    }
+}
+
+/// Checks that a process with credentials `real` may send `claimed` in an
+/// `SCM_CREDENTIALS` message: only privileged processes may claim another
+/// identity than their own.
+pub fn check_credentials(
+    claimed: &UnixCredentials,
+    real: &UnixCredentials,
+    privileged: bool,
+) -> KResult<()> {
+    if privileged || claimed == real {
+        Ok(())
+    } else {
+        Err(KError::OperationNotPermitted)
+    }
+}
+
 /// Control message types for socket operations (ancillary data)
 pub enum CMsg {
     /// SCM_RIGHTS: file descriptor passing between processes
     Rights { fds: Vec<Arc<dyn FileLike>> },
+    /// SCM_CREDENTIALS: credentials of the sender
+    Credentials(UnixCredentials),
 }
 impl CMsg {
     /// Parse a control message header and extract its data
@@ -34,7 +75,9 @@ impl CMsg {
                 .get_as_slice(hdr.cmsg_len - size_of::<cmsghdr>())?;
         Ok(match (hdr.cmsg_level as u32, hdr.cmsg_type as u32) {
             (SOL_SOCKET, SCM_RIGHTS) => {
-                if data.len() % size_of::<i32>() != 0 {
+                if data.len() % size_of::<i32>() != 0
+                    || data.len() / size_of::<i32>() > MAX_PASSED_FDS
+                {
                     return Err(KError::InvalidInput);
                 }
                 let mut fds = Vec::new();
@@ -48,6 +91,21 @@ impl CMsg {
                 }
                 Self::Rights { fds }
             }
+            (SOL_SOCKET, SCM_CREDENTIALS) => {
+                if data.len() != size_of::<ucred>() {
+                    return Err(KError::InvalidInput);
+                }
+                let field = |i: usize| u32::from_ne_bytes(data[i * 4..][..4].try_into().unwrap());
+                let cred = UnixCredentials {
+                    pid: field(0),
+                    uid: field(1),
+                    gid: field(2),
+                };
+                let real = current_credentials();
+                check_credentials(&cred, &real, real.uid == 0)?;
+                get_process_data(cred.pid)?;
+                Self::Credentials(cred)
+            }
             _ => {
                 return Err(KError::InvalidInput);
             }
@@ -55,6 +113,19 @@ impl CMsg {
     }
 }
 
+/// Returns the receive queues of the unix sockets among `fds`, for the
+/// transport to check that passing them makes no reference cycle.
+pub fn passed_sockets(fds: &[Arc<dyn FileLike>]) -> Option<PassedSockets> {
+    let queues: Vec<_> = fds
+        .iter()
+        .filter_map(|f| match &f.downcast_ref::<Socket>()?.0 {
+            knet::Socket::Unix(unix) => unix.rx_queue(),
+            _ => None,
+        })
+        .collect();
+    (!queues.is_empty()).then_some(PassedSockets(queues))
+}
+
 /// Builder for constructing control message buffers for socket I/O
 pub struct CMsgBuilder<'a> {
     hdr: UserPtr<cmsghdr>,
@@ -74,6 +145,9 @@ impl<'a> CMsgBuilder<'a> {
     }
 
     /// Add a control message with the specified level and type to the buffer
+    ///
+    /// The message is padded to the alignment of the next one, unless it is
+    /// the last that fits.
     pub fn push(
         &mut self,
         level: u32,
@@ -95,8 +169,62 @@ impl<'a> CMsgBuilder<'a> {
 
         let cmsg_len = size_of::<cmsghdr>() + body_len;
         hdr.cmsg_len = cmsg_len;
-        self.hdr = UserPtr::from(hdr as *const _ as usize + cmsg_len);
-        *self.len += cmsg_len;
+        let space = cmsg_align(cmsg_len).min(self.capacity - *self.len);
+        self.hdr = UserPtr::from(hdr as *const _ as usize + space);
+        *self.len += space;
         Ok(true)
     }
+
+    /// Add a control message holding `data`, or return `false` if there is
+    /// no room left for all of it
+    pub fn push_bytes(&mut self, level: u32, ty: u32, data: &[u8]) -> KResult<bool> {
+        if self.capacity - *self.len < size_of::<cmsghdr>() + data.len() {
+            return Ok(false);
+        }
+        self.push(level, ty, |body| {
+            body[..data.len()].copy_from_slice(data);
+            Ok(data.len())
+        })
+    }
+}
+
+#[cfg(unittest)]
+mod tests_cmsg {
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    #[def_test]
+    fn test_cmsg_align() {
+        assert_eq!(cmsg_align(0), 0);
+        assert_eq!(cmsg_align(1), size_of::<usize>());
+        // `struct ucred` is padded on 64-bit targets.
+        let len = size_of::<cmsghdr>() + size_of::<ucred>();
+        assert_eq!(cmsg_align(len), len.next_multiple_of(size_of::<usize>()));
+        assert_eq!(cmsg_align(size_of::<cmsghdr>()), size_of::<cmsghdr>());
+    }
+
+    #[def_test]
+    fn test_forged_credentials() {
+        let real = UnixCredentials {
+            pid: 42,
+            uid: 1000,
+            gid: 100,
+        };
+        assert!(check_credentials(&real, &real, false).is_ok());
+
+        let forged = [
+            UnixCredentials { pid: 1, ..real },
+            UnixCredentials { uid: 0, ..real },
+            UnixCredentials { gid: 0, ..real },
+        ];
+        for claimed in &forged {
+            assert_eq!(
+                check_credentials(claimed, &real, false),
+                Err(KError::OperationNotPermitted)
+            );
+            // Privileged processes may speak for others.
+            assert!(check_credentials(claimed, &real, true).is_ok());
+        }
+    }
 }
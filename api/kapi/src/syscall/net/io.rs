@@ -11,13 +11,17 @@
 //! - Ancillary data (control messages)
 
 use alloc::{boxed::Box, vec::Vec};
-use core::net::Ipv4Addr;
+use core::{ffi::c_int, net::Ipv4Addr};
 
 use kerrno::{KError, KResult};
 use kio::prelude::*;
-use knet::{CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx, SocketOps};
+use knet::{
+    CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx, SocketOps,
+    options::UnixCredentials,
+};
 use linux_raw_sys::net::{
-    MSG_PEEK, MSG_TRUNC, SCM_RIGHTS, SOL_SOCKET, cmsghdr, msghdr, sockaddr, socklen_t,
+    MSG_CMSG_CLOEXEC, MSG_CTRUNC, MSG_PEEK, MSG_TRUNC, SCM_CREDENTIALS, SCM_RIGHTS, SOL_SOCKET,
+    cmsghdr, msghdr, sockaddr, socklen_t, ucred,
 };
 
 use crate::{
@@ -25,10 +29,13 @@ use crate::{
     io::{IoVec, IoVectorBuf},
     mm::{UserConstPtr, UserPtr, VmBytes, VmBytesMut},
     socket::SocketAddrExt,
-    syscall::net::{CMsg, CMsgBuilder},
+    syscall::net::{CMsg, CMsgBuilder, cmsg_align, current_credentials, passed_sockets},
 };
 
 /// Send data on a socket with optional destination address and ancillary data
+///
+/// Data goes with the credentials of the current process unless others are
+/// given.
 fn send_impl(
     fd: i32,
     mut src: impl Read + IoBuf,
@@ -36,6 +43,7 @@ fn send_impl(
     addr: UserConstPtr<sockaddr>,
     addrlen: socklen_t,
     cmsg: Vec<CMsgData>,
+    credentials: Option<UnixCredentials>,
 ) -> KResult<isize> {
     let addr = if addr.is_null() || addrlen == 0 {
         None
@@ -52,6 +60,7 @@ fn send_impl(
             to: addr,
             flags: SendFlags::default(),
             cmsg,
+            credentials: Some(credentials.unwrap_or_else(current_credentials)),
         },
     )?;
 
@@ -67,13 +76,22 @@ pub fn sys_sendto(
     addr: UserConstPtr<sockaddr>,
     addrlen: socklen_t,
 ) -> KResult<isize> {
-    send_impl(fd, VmBytes::new(buf, len), flags, addr, addrlen, Vec::new())
+    send_impl(
+        fd,
+        VmBytes::new(buf, len),
+        flags,
+        addr,
+        addrlen,
+        Vec::new(),
+        None,
+    )
 }
 
 /// Send data with vectored I/O and ancillary data (control messages)
 pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> KResult<isize> {
     let msg = msg.get_as_ref()?;
     let mut cmsg = Vec::new();
+    let mut credentials = None;
     if !msg.msg_control.is_null() {
         let mut ptr = msg.msg_control as usize;
         let ptr_end = ptr + msg.msg_controllen;
@@ -82,8 +100,16 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> KResult<is
             if ptr_end - ptr < hdr.cmsg_len {
                 return Err(KError::InvalidInput);
             }
-            cmsg.push(Box::new(CMsg::parse(hdr)?) as CMsgData);
-            ptr += hdr.cmsg_len;
+            match CMsg::parse(hdr)? {
+                CMsg::Credentials(cred) => credentials = Some(cred),
+                CMsg::Rights { fds } => {
+                    if let Some(passed) = passed_sockets(&fds) {
+                        cmsg.push(Box::new(passed) as CMsgData);
+                    }
+                    cmsg.push(Box::new(CMsg::Rights { fds }) as CMsgData);
+                }
+            }
+            ptr += cmsg_align(hdr.cmsg_len);
         }
     }
     send_impl(
@@ -93,10 +119,15 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> KResult<is
         UserConstPtr::from(msg.msg_name as usize),
         msg.msg_namelen as socklen_t,
         cmsg,
+        credentials,
     )
 }
 
 /// Receive data from a socket with optional remote address and ancillary data collection
+///
+/// File descriptors received are installed in the fd table as far as they
+/// fit in the control buffer; with `msg_flags`, `MSG_CTRUNC` tells whether
+/// some control data was dropped.
 fn recv_impl(
     fd: i32,
     mut dst: impl Write + IoBufMut,
@@ -104,6 +135,7 @@ fn recv_impl(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
     cmsg_builder: Option<CMsgBuilder>,
+    msg_flags: Option<&mut c_int>,
 ) -> KResult<isize> {
     debug!("sys_recv <= fd: {fd}, flags: {flags}");
 
@@ -133,29 +165,62 @@ fn recv_impl(
         remote_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
     }
 
+    let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+    // Without a control buffer, whatever came along is dropped.
+    let mut truncated = cmsg_builder.is_none() && !cmsg.is_empty();
     if let Some(mut builder) = cmsg_builder {
         for cmsg in cmsg {
-            let Ok(cmsg) = cmsg.downcast::<CMsg>() else {
-                warn!("received unexpected cmsg");
-                continue;
+            let cmsg = match cmsg.downcast::<UnixCredentials>() {
+                Ok(cred) => Box::new(CMsg::Credentials(*cred)),
+                Err(cmsg) => match cmsg.downcast::<CMsg>() {
+                    Ok(cmsg) => cmsg,
+                    Err(_) => {
+                        warn!("received unexpected cmsg");
+                        continue;
+                    }
+                },
             };
 
             let pushed = match *cmsg {
-                CMsg::Rights { fds } => builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
-                    let mut written = 0;
-                    for (f, chunk) in fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>())) {
-                        let fd = add_file_like(f, false)?;
-                        chunk.copy_from_slice(&fd.to_ne_bytes());
-                        written += size_of::<i32>();
+                CMsg::Rights { fds } => {
+                    // Those that do not fit, or that the table has no room
+                    // for, are closed.
+                    let count = fds.len();
+                    let mut installed = 0;
+                    let pushed = builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
+                        for (f, chunk) in
+                            fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>()))
+                        {
+                            let Ok(fd) = add_file_like(f, cloexec) else {
+                                break;
+                            };
+                            chunk.copy_from_slice(&fd.to_ne_bytes());
+                            installed += 1;
+                        }
+                        Ok(installed * size_of::<i32>())
+                    })?;
+                    pushed && installed == count
+                }
+                CMsg::Credentials(cred) => {
+                    let mut data = [0; size_of::<ucred>()];
+                    for (field, chunk) in [cred.pid, cred.uid, cred.gid]
+                        .into_iter()
+                        .zip(data.chunks_exact_mut(size_of::<u32>()))
+                    {
+                        chunk.copy_from_slice(&field.to_ne_bytes());
                     }
-                    Ok(written)
-                })?,
+                    builder.push_bytes(SOL_SOCKET, SCM_CREDENTIALS, &data)?
+                }
             };
             if !pushed {
+                truncated = true;
                 break;
             }
         }
     }
+    if let Some(msg_flags) = msg_flags {
+        *msg_flags = if truncated { MSG_CTRUNC as c_int } else { 0 };
+    }
 
     debug!("sys_recv => fd: {fd}, recv: {recv}");
     Ok(recv as isize)
@@ -170,7 +235,15 @@ pub fn sys_recvfrom(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
 ) -> KResult<isize> {
-    recv_impl(fd, VmBytesMut::new(buf, len), flags, addr, addrlen, None)
+    recv_impl(
+        fd,
+        VmBytesMut::new(buf, len),
+        flags,
+        addr,
+        addrlen,
+        None,
+        None,
+    )
 }
 
 /// Receive data with vectored I/O and ancillary data (control messages)
@@ -188,5 +261,6 @@ pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> KResult<isize>
                 &mut msg.msg_controllen,
             )
         }),
+        Some(&mut msg.msg_flags),
     )
 }
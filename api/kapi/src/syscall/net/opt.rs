@@ -23,9 +23,14 @@ const PROTO_TCP: u32 = linux_raw_sys::net::IPPROTO_TCP as u32;
 const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
 
 mod conv {
-    use kerrno::{KError, KResult};
-    use knet::options::UnixCredentials;
-    use linux_raw_sys::{general::timeval, net::ucred};
+    use core::net::Ipv4Addr;
+
+    use kerrno::{KError, KResult, LinuxError};
+    use knet::options::{IpMembership, UnixCredentials};
+    use linux_raw_sys::{
+        general::timeval,
+        net::{in_addr, ip_mreq, ucred},
+    };
 
     use crate::time::TimeValueLike;
 
@@ -53,13 +58,18 @@ mod conv {
         }
     }
 
+    /// A socket timeout, where zero means none.
     pub struct Duration;
 
     impl Duration {
         pub fn sys_to_rust(val: timeval) -> KResult<core::time::Duration> {
+            if !(0..1_000_000).contains(&val.tv_usec) {
+                return Err(KError::from(LinuxError::EDOM));
+            }
             val.try_into_time_value()
         }
 
+        /// Truncates to whole microseconds.
         pub fn rust_to_sys(val: core::time::Duration) -> KResult<timeval> {
             Ok(timeval::from_time_value(val))
         }
@@ -84,6 +94,28 @@ mod conv {
             })
         }
     }
+
+    pub struct Mreq;
+
+    impl Mreq {
+        pub fn sys_to_rust(val: ip_mreq) -> KResult<IpMembership> {
+            Ok(IpMembership {
+                multiaddr: Ipv4Addr::from_bits(u32::from_be(val.imr_multiaddr.s_addr)),
+                interface: Ipv4Addr::from_bits(u32::from_be(val.imr_interface.s_addr)),
+            })
+        }
+
+        pub fn rust_to_sys(val: IpMembership) -> KResult<ip_mreq> {
+            Ok(ip_mreq {
+                imr_multiaddr: in_addr {
+                    s_addr: val.multiaddr.to_bits().to_be(),
+                },
+                imr_interface: in_addr {
+                    s_addr: val.interface.to_bits().to_be(),
+                },
+            })
+        }
+    }
 }
 
 macro_rules! call_dispatch {
@@ -109,6 +141,8 @@ macro_rules! call_dispatch {
             (PROTO_TCP, TCP_INFO) => TcpInfo,
 
             (PROTO_IP, IP_TTL) => Ttl as Int<u8>,
+            (PROTO_IP, IP_ADD_MEMBERSHIP) => AddMembership as Mreq,
+            (PROTO_IP, IP_DROP_MEMBERSHIP) => DropMembership as Mreq,
         }
     }};
     ($dispatch:ident, $in:expr, $($pat:pat => $which:ident $(as $conv:ty)?),* $(,)?) => {
@@ -12,7 +12,6 @@
 
 use alloc::boxed::Box;
 
-use kcore::task::AsThread;
 use kerrno::{KError, KResult, LinuxError};
 #[cfg(feature = "vsock")]
 use knet::vsock::{VsockSocket, VsockStreamTransport};
@@ -22,7 +21,6 @@ use knet::{
     udp::UdpSocket,
     unix::{DgramTransport, StreamTransport, UnixDomainSocket},
 };
-use ktask::current;
 use linux_raw_sys::{
     general::{O_CLOEXEC, O_NONBLOCK},
     net::{
@@ -35,6 +33,7 @@ use crate::{
     file::{FileLike, Socket},
     mm::{UserConstPtr, UserPtr},
     socket::SocketAddrExt,
+    syscall::net::current_credentials,
 };
 
 /// Create a new socket of the specified domain, type, and protocol
@@ -43,7 +42,7 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> KResult<isize> {
     // Extract the type bits (lower 8 bits, ignoring flags like SOCK_CLOEXEC)
     let ty = raw_ty & 0xFF;
 
-    let pid = current().as_thread().proc_data.proc.pid();
+    let cred = current_credentials();
     // Create the appropriate socket type based on domain and type
     let socket = match (domain, ty) {
         (AF_INET, SOCK_STREAM) => {
@@ -62,11 +61,11 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> KResult<isize> {
         }
         (AF_UNIX, SOCK_STREAM) => {
             // Unix domain stream socket
-            knet::Socket::Unix(Box::new(UnixDomainSocket::new(StreamTransport::new(pid))))
+            knet::Socket::Unix(Box::new(UnixDomainSocket::new(StreamTransport::new(cred))))
         }
         (AF_UNIX, SOCK_DGRAM) => {
             // Unix domain datagram socket
-            knet::Socket::Unix(Box::new(UnixDomainSocket::new(DgramTransport::new(pid))))
+            knet::Socket::Unix(Box::new(UnixDomainSocket::new(DgramTransport::new(cred))))
         }
         #[cfg(feature = "vsock")]
         (AF_VSOCK, SOCK_STREAM) => {
@@ -193,14 +192,14 @@ pub fn sys_socketpair(
         return Err(KError::from(LinuxError::EAFNOSUPPORT));
     }
 
-    let pid = current().as_thread().proc_data.proc.pid();
+    let cred = current_credentials();
     let (sock1, sock2) = match ty {
         SOCK_STREAM => {
-            let (sock1, sock2) = StreamTransport::new_pair(pid);
+            let (sock1, sock2) = StreamTransport::new_pair(cred);
             (UnixDomainSocket::new(sock1), UnixDomainSocket::new(sock2))
         }
         SOCK_DGRAM | SOCK_SEQPACKET => {
-            let (sock1, sock2) = DgramTransport::new_pair(pid);
+            let (sock1, sock2) = DgramTransport::new_pair(cred);
             (UnixDomainSocket::new(sock1), UnixDomainSocket::new(sock2))
         }
         _ => {
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Hardware performance event syscalls.
+//!
+//! Only counting-mode `PERF_TYPE_HARDWARE` events of a single task are
+//! supported, see [`kcore::perf`]. Sampling, mmap'd ring buffers and
+//! counting the children of the task are not: `inherit` is accepted, as
+//! `perf stat` sets it, but only the task itself is counted.
+
+use alloc::sync::Arc;
+
+use kcore::{
+    perf::{EventAttr, HwEvent},
+    task::{AsThread, get_task},
+};
+use kerrno::{KError, KResult};
+use ktask::current;
+
+use crate::{
+    file::{
+        FileLike,
+        perf::{
+            PERF_FORMAT_GROUP, PERF_FORMAT_ID, PERF_FORMAT_TOTAL_TIME_ENABLED,
+            PERF_FORMAT_TOTAL_TIME_RUNNING, PerfEventFile,
+        },
+    },
+    mm::UserConstPtr,
+};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+
+/// The size of the first published `struct perf_event_attr`.
+const PERF_ATTR_SIZE_VER0: u32 = 64;
+
+const ATTR_DISABLED: u64 = 1 << 0;
+const ATTR_EXCLUDE_USER: u64 = 1 << 4;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_FREQ: u64 = 1 << 10;
+const ATTR_ENABLE_ON_EXEC: u64 = 1 << 12;
+
+const PERF_FLAG_FD_CLOEXEC: u32 = 1 << 3;
+
+/// The fields of `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER0`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PerfEventAttr {
+    pub type_: u32,
+    pub size: u32,
+    pub config: u64,
+    /// Or `sample_freq` with `freq`.
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    /// The bit fields, from `disabled` up.
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+}
+
+/// Opens a hardware event counting what thread `pid`, or the caller with 0,
+/// does.
+///
+/// The thread must be of the calling process or of one of its children.
+pub fn sys_perf_event_open(
+    attr: UserConstPtr<PerfEventAttr>,
+    pid: i32,
+    cpu: i32,
+    group_fd: i32,
+    flags: u32,
+) -> KResult<isize> {
+    let attr = *attr.get_as_ref()?;
+    debug!(
+        "sys_perf_event_open <= type: {}, config: {:#x}, pid: {pid}, cpu: {cpu}, group_fd: \
+         {group_fd}, flags: {flags:#x}",
+        attr.type_, attr.config
+    );
+    if attr.size != 0 && attr.size < PERF_ATTR_SIZE_VER0 {
+        return Err(KError::InvalidInput);
+    }
+    if flags & !PERF_FLAG_FD_CLOEXEC != 0 {
+        return Err(KError::InvalidInput);
+    }
+    let read_format_mask = PERF_FORMAT_TOTAL_TIME_ENABLED
+        | PERF_FORMAT_TOTAL_TIME_RUNNING
+        | PERF_FORMAT_ID
+        | PERF_FORMAT_GROUP;
+    if attr.read_format & !read_format_mask != 0 {
+        return Err(KError::InvalidInput);
+    }
+    if attr.type_ != PERF_TYPE_HARDWARE {
+        return Err(KError::NotFound);
+    }
+    let event = HwEvent::from_config(attr.config).ok_or(KError::NotFound)?;
+    if attr.sample_period != 0 || attr.flags & ATTR_FREQ != 0 {
+        return Err(KError::OperationNotSupported);
+    }
+    // Events of all tasks on a CPU, and of a task on one CPU only.
+    if pid < 0 {
+        return Err(KError::InvalidInput);
+    }
+    if cpu != -1 {
+        return Err(KError::OperationNotSupported);
+    }
+
+    let task = get_task(pid as _)?;
+    let thr = task.as_thread();
+    let curr = current();
+    let proc = &curr.as_thread().proc_data.proc;
+    let target = &thr.proc_data.proc;
+    if !Arc::ptr_eq(target, proc)
+        && !target
+            .parent()
+            .is_some_and(|parent| Arc::ptr_eq(&parent, proc))
+    {
+        return Err(KError::PermissionDenied);
+    }
+
+    let leader = match group_fd {
+        -1 => None,
+        fd => Some(PerfEventFile::from_fd(fd)?),
+    };
+    let perf_event = thr.perf().open(
+        EventAttr {
+            event,
+            exclude_user: attr.flags & ATTR_EXCLUDE_USER != 0,
+            exclude_kernel: attr.flags & ATTR_EXCLUDE_KERNEL != 0,
+            disabled: attr.flags & ATTR_DISABLED != 0,
+            enable_on_exec: attr.flags & ATTR_ENABLE_ON_EXEC != 0,
+        },
+        leader.as_deref().map(PerfEventFile::event),
+    )?;
+    PerfEventFile::new(perf_event, attr.read_format)
+        .add_to_fd_table(flags & PERF_FLAG_FD_CLOEXEC != 0)
+        .map(|fd| fd as isize)
+}
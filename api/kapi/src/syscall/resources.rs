@@ -6,15 +6,19 @@
 //!
 //! This module provides syscalls for managing resource limits (prlimit64)
 //! and retrieving resource usage information (getrusage).
-use kcore::task::{AsThread, Thread, get_process_data, get_task};
+use kcore::task::{AsThread, get_process_data, get_task};
 use kerrno::{KError, KResult};
-use khal::time::TimeValue;
-use kprocess::Pid;
+use kprocess::{Pid, ResourceUsage};
 use ktask::current;
-use linux_raw_sys::general::{__kernel_old_timeval, RLIM_NLIMITS, rlimit64, rusage};
+use linux_raw_sys::general::{
+    __kernel_old_timeval, RLIM_NLIMITS, RLIMIT_SIGPENDING, rlimit64, rusage,
+};
 use osvm::{VirtMutPtr, VirtPtr};
 
-use crate::time::TimeValueLike;
+use crate::{
+    task::{mapped_kib, thread_usage},
+    time::TimeValueLike,
+};
 
 /// Get and/or set resource limits for a process
 pub fn sys_prlimit64(
@@ -53,38 +57,24 @@ pub fn sys_prlimit64(
         }
 
         limit.current = new_limit.rlim_cur;
+        if resource == RLIMIT_SIGPENDING {
+            proc_data
+                .signal
+                .set_queue_limit(usize::try_from(limit.current).unwrap_or(usize::MAX));
+        }
     }
 
     Ok(0)
 }
 
-#[derive(Default)]
-struct Rusage {
-    utime: TimeValue,
-    stime: TimeValue,
-}
-
-impl Rusage {
-    fn from_thread(thread: &Thread) -> Self {
-        let (utime, stime) = thread.time.borrow().output();
-        Self { utime, stime }
-    }
-
-    fn collate(mut self, other: Rusage) -> Self {
-        self.utime += other.utime;
-        self.stime += other.stime;
-        self
-    }
-}
-
-impl From<Rusage> for rusage {
-    fn from(value: Rusage) -> Self {
-        // FIXME: Zeroable
-        let mut usage: rusage = unsafe { core::mem::zeroed() };
-        usage.ru_utime = __kernel_old_timeval::from_time_value(value.utime);
-        usage.ru_stime = __kernel_old_timeval::from_time_value(value.stime);
-        usage
-    }
+/// Converts `usage` to the layout of `struct rusage`.
+pub(crate) fn to_rusage(usage: ResourceUsage) -> rusage {
+    // FIXME: Zeroable
+    let mut result: rusage = unsafe { core::mem::zeroed() };
+    result.ru_utime = __kernel_old_timeval::from_time_value(usage.utime);
+    result.ru_stime = __kernel_old_timeval::from_time_value(usage.stime);
+    result.ru_maxrss = usage.maxrss as _;
+    result
 }
 
 /// Get resource usage information for the current process, children, or specific thread
@@ -95,40 +85,31 @@ pub fn sys_getrusage(who: i32, usage: *mut rusage) -> KResult<isize> {
 
     let curr = current();
     let thr = curr.as_thread();
+    let proc = &thr.proc_data.proc;
 
     let result = match who {
         RUSAGE_SELF => {
-            thr.proc_data
-                .proc
-                .threads()
-                .into_iter()
-                .fold(Rusage::default(), |acc, tid| {
-                    if let Ok(task) = get_task(tid) {
-                        acc.collate(Rusage::from_thread(task.as_thread()))
-                    } else {
-                        acc
-                    }
-                })
-        }
-        RUSAGE_CHILDREN => {
-            thr.proc_data
-                .proc
+            let live = proc
                 .threads()
                 .into_iter()
-                .fold(Rusage::default(), |acc, child| {
-                    if let Ok(task) = get_task(child)
-                        && !curr.ptr_eq(&task)
-                    {
-                        acc.collate(Rusage::from_thread(task.as_thread()))
-                    } else {
-                        acc
-                    }
-                })
+                .filter_map(|tid| get_task(tid).ok())
+                .fold(ResourceUsage::default(), |acc, task| {
+                    acc.merge(thread_usage(task.as_thread()))
+                });
+            let maxrss = ResourceUsage {
+                maxrss: mapped_kib(&thr.proc_data),
+                ..Default::default()
+            };
+            proc.exited_usage().merge(live).merge(maxrss)
         }
-        RUSAGE_THREAD => Rusage::from_thread(thr),
+        RUSAGE_CHILDREN => proc.children_usage(),
+        RUSAGE_THREAD => thread_usage(thr).merge(ResourceUsage {
+            maxrss: mapped_kib(&thr.proc_data),
+            ..Default::default()
+        }),
         _ => return Err(KError::InvalidInput),
     };
-    usage.write_vm(result.into())?;
+    usage.write_vm(to_rusage(result))?;
 
     Ok(0)
 }
@@ -13,20 +13,19 @@
 use core::{future::poll_fn, task::Poll};
 
 use kcore::task::{
-    AsThread, processes, send_signal_to_process, send_signal_to_process_group,
-    send_signal_to_thread,
+    AsThread, processes, queue_signal_to_process, queue_signal_to_thread, send_signal_to_process,
+    send_signal_to_process_group, send_signal_to_thread,
 };
 use kerrno::{KError, KResult, LinuxError};
 use khal::uspace::UserContext;
 use kprocess::Pid;
-use ksignal::{SignalInfo, SignalSet, SignalStack, Signo};
+use ksignal::{SignalInfo, SignalSet, SignalStack, SignalStackError, Signo};
 use ktask::{
     current,
     future::{self, block_on},
 };
 use linux_raw_sys::general::{
-    MINSIGSTKSZ, SI_TKILL, SI_USER, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, kernel_sigaction, siginfo,
-    timespec,
+    SI_TKILL, SI_USER, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, kernel_sigaction, siginfo, timespec,
 };
 use osvm::{VirtMutPtr, VirtPtr};
 
@@ -206,16 +205,15 @@ pub(crate) fn make_queue_signal_info(
 }
 
 /// Queue a real-time signal with additional information to a process
-pub fn sys_rt_sigqueueinfo(
-    tgid: Pid,
-    signo: u32,
-    sig: *const SignalInfo,
-    sigsetsize: usize,
-) -> KResult<isize> {
-    check_sigset_size(sigsetsize)?;
-
-    let sig = make_queue_signal_info(tgid, signo, sig)?;
-    send_signal_to_process(tgid, sig)?;
+///
+/// The whole `siginfo` is queued, so its `si_value` reaches the handler.
+/// Fails with `EAGAIN` once the process has `RLIMIT_SIGPENDING` real-time
+/// signals queued.
+pub fn sys_rt_sigqueueinfo(tgid: Pid, signo: u32, sig: *const SignalInfo) -> KResult<isize> {
+    match make_queue_signal_info(tgid, signo, sig)? {
+        Some(sig) => queue_signal_to_process(tgid, sig)?,
+        None => send_signal_to_process(tgid, None)?,
+    }
     Ok(0)
 }
 
@@ -225,12 +223,11 @@ pub fn sys_rt_tgsigqueueinfo(
     tid: Pid,
     signo: u32,
     sig: *const SignalInfo,
-    sigsetsize: usize,
 ) -> KResult<isize> {
-    check_sigset_size(sigsetsize)?;
-
-    let sig = make_queue_signal_info(tgid, signo, sig)?;
-    send_signal_to_thread(Some(tgid), tid, sig)?;
+    match make_queue_signal_info(tgid, signo, sig)? {
+        Some(sig) => queue_signal_to_thread(tgid, tid, sig)?,
+        None => send_signal_to_thread(Some(tgid), tid, None)?,
+    }
     Ok(0)
 }
 
@@ -330,20 +327,29 @@ pub fn sys_rt_sigsuspend(
 }
 
 /// Set or retrieve the alternate signal stack
-pub fn sys_sigaltstack(ss: *const SignalStack, old_ss: *mut SignalStack) -> KResult<isize> {
+pub fn sys_sigaltstack(
+    uctx: &UserContext,
+    ss: *const SignalStack,
+    old_ss: *mut SignalStack,
+) -> KResult<isize> {
     let curr = current();
     let sig = &curr.as_thread().signal;
 
+    let ss = match ss.check_non_null() {
+        Some(ss) => Some(unsafe { ss.read_uninit()?.assume_init() }),
+        None => None,
+    };
+
     if let Some(old_ss) = old_ss.check_non_null() {
-        old_ss.write_vm(sig.stack())?;
+        old_ss.write_vm(sig.stack(uctx.sp()))?;
     }
 
-    if let Some(ss) = ss.check_non_null() {
-        let ss = unsafe { ss.read_uninit()?.assume_init() };
-        if ss.size <= MINSIGSTKSZ as usize {
-            return Err(KError::NoMemory);
-        }
-        sig.set_stack(ss);
+    if let Some(ss) = ss {
+        sig.set_stack(&ss, uctx.sp()).map_err(|err| match err {
+            SignalStackError::OnStack => KError::OperationNotPermitted,
+            SignalStackError::InvalidFlags => KError::InvalidInput,
+            SignalStackError::TooSmall => KError::NoMemory,
+        })?;
     }
     Ok(0)
 }
@@ -6,25 +6,32 @@
 //!
 //! This module implements fast userspace mutex (futex) operations including:
 //! - Futex wait and wake operations
-//! - Futex requeue operations
+//! - Futex requeue and wake-op operations
 //! - Robust futex lists
 //! - Priority-inheritance futexes
+//!
+//! Every operation checks or changes the futex word with the bucket lock of
+//! the futex held, which wakers take too, so a wake-up between the check of a
+//! waiter and its sleep is not lost.
 
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use kcore::{
-    futex::FutexKey,
+    futex::{FutexEntry, FutexKey, WakeOp, lock_pair},
     task::{AsThread, get_task},
 };
 use kerrno::{KError, KResult, LinuxError};
+use khal::time::{TimeValue, realtime, wall_time};
 use ktask::current;
 use linux_raw_sys::general::{
-    FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
-    FUTEX_WAKE_BITSET, robust_list_head, timespec,
+    FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_LOCK_PI, FUTEX_PRIVATE_FLAG, FUTEX_REQUEUE,
+    FUTEX_TID_MASK, FUTEX_UNLOCK_PI, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAITERS, FUTEX_WAKE,
+    FUTEX_WAKE_BITSET, FUTEX_WAKE_OP, robust_list_head, timespec,
 };
+use memaddr::VirtAddr;
 use osvm::{VirtMutPtr, VirtPtr};
 
-use crate::time::TimeValueLike;
+use crate::{task::release_robust_list, time::TimeValueLike};
 
 /// Helper to ensure a value is non-negative (unsigned interpretation)
 fn assert_unsigned(value: u32) -> KResult<u32> {
@@ -35,6 +42,72 @@ fn assert_unsigned(value: u32) -> KResult<u32> {
     }
 }
 
+/// Reads the timeout of a waiting operation, if any.
+fn read_timeout(timeout: *const timespec) -> KResult<Option<TimeValue>> {
+    let Some(ts) = timeout.check_non_null() else {
+        return Ok(None);
+    };
+    // FIXME: AnyBitPattern
+    let ts = unsafe { ts.read_uninit()?.assume_init() }.try_into_time_value()?;
+    Ok(Some(ts))
+}
+
+/// Runs `f` on the futex word at `uaddr`, atomically with other CPUs.
+fn with_word<R>(uaddr: usize, f: impl FnOnce(&AtomicU32) -> R) -> KResult<R> {
+    let curr = current();
+    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    Ok(f(aspace.atomic_u32(VirtAddr::from_usize(uaddr))?))
+}
+
+/// Takes the PI futex at `uaddr` for the current task, recording its tid in
+/// the word, or sleeps until the owner releases it.
+fn futex_lock_pi(futex: &FutexEntry, uaddr: usize, deadline: Option<TimeValue>) -> KResult<isize> {
+    let tid = current().id().as_u64() as u32;
+    loop {
+        let guard = futex.lock.lock();
+        let res = with_word(uaddr, |word| {
+            word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+                match val & FUTEX_TID_MASK {
+                    // Others still waiting keep the waiters bit set, so the
+                    // unlock comes to the kernel.
+                    0 if futex.wq.is_empty() => Some(tid),
+                    0 => Some(tid | FUTEX_WAITERS),
+                    owner if owner == tid => None,
+                    _ => Some(val | FUTEX_WAITERS),
+                }
+            })
+        })?;
+        match res {
+            Ok(val) if val & FUTEX_TID_MASK == 0 => return Ok(0),
+            Ok(val) => {
+                if get_task(val & FUTEX_TID_MASK).is_err() {
+                    return Err(KError::NoSuchProcess);
+                }
+                futex.wq.wait_releasing(u32::MAX, deadline, guard)?;
+            }
+            Err(_) => return Err(KError::from(LinuxError::EDEADLK)),
+        }
+    }
+}
+
+/// Releases the PI futex at `uaddr` held by the current task, waking the
+/// longest waiter to take it.
+fn futex_unlock_pi(futex: &FutexEntry, uaddr: usize) -> KResult<isize> {
+    let tid = current().id().as_u64() as u32;
+    let _guard = futex.lock.lock();
+    // The woken task sets the waiters bit again when taking the lock, unless
+    // more remain, then user space must not take it past them meanwhile.
+    let new = if futex.wq.len() > 1 { FUTEX_WAITERS } else { 0 };
+    with_word(uaddr, |word| {
+        word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+            (val & FUTEX_TID_MASK == tid).then_some(new)
+        })
+    })?
+    .map_err(|_| KError::OperationNotPermitted)?;
+    futex.wq.wake(1, u32::MAX);
+    Ok(0)
+}
+
 /// Fast userspace mutex (futex) system call.
 /// Implements Linux futex semantics for efficient synchronization primitives.
 pub fn sys_futex(
@@ -50,46 +123,37 @@ pub fn sys_futex(
          value3: {value3}",
     );
 
-    // Create a unique key for this futex (by virtual address and process)
-    let key = FutexKey::new_current(uaddr.addr());
+    // Without the private flag, futexes in shared memory are keyed by the
+    // memory, so that other processes mapping it reach them.
+    let shared = futex_op & FUTEX_PRIVATE_FLAG == 0;
+    let key = FutexKey::new_current(uaddr.addr(), shared);
 
     let curr = current();
     let thr = curr.as_thread();
     let proc_data = &thr.proc_data;
-    // Get the futex table for the current process
     let futex_table = proc_data.futex_table_for(&key);
 
     // Extract the command (lower bits) from the futex_op
     let command = futex_op & (FUTEX_CMD_MASK as u32);
     match command {
         FUTEX_WAIT | FUTEX_WAIT_BITSET => {
-            // Fast path: Check if the value at uaddr matches the expected value
-            if uaddr.read_vm()? != value {
-                return Err(KError::WouldBlock);
-            }
-
-            let timeout = if let Some(ts) = timeout.check_non_null() {
-                // FIXME: AnyBitPattern
-                let ts = unsafe { ts.read_uninit()?.assume_init() }.try_into_time_value()?;
-                Some(ts)
-            } else {
-                None
-            };
-
-            let futex = futex_table.get_or_insert(&key);
-
+            let deadline =
+                read_timeout(timeout)?.and_then(|timeout| timeout.checked_add(wall_time()));
             let bitset = if command == FUTEX_WAIT_BITSET {
                 value3
             } else {
                 u32::MAX
             };
+            if bitset == 0 {
+                return Err(KError::InvalidInput);
+            }
 
-            if !futex
-                .wq
-                .wait_if(bitset, timeout, || uaddr.read_vm() == Ok(value))?
-            {
+            let futex = futex_table.get_or_insert(&key);
+            let guard = futex.lock.lock();
+            if uaddr.read_vm()? != value {
                 return Err(KError::WouldBlock);
             }
+            futex.wq.wait_releasing(bitset, deadline, guard)?;
 
             if futex.owner_dead.swap(false, Ordering::SeqCst) {
                 Err(KError::from(LinuxError::EOWNERDEAD))
@@ -106,31 +170,50 @@ pub fn sys_futex(
                 } else {
                     u32::MAX
                 };
+                let _guard = futex.lock.lock();
                 count = futex.wq.wake(value as _, bitset);
             }
             ktask::yield_now();
             Ok(count as _)
         }
-        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE | FUTEX_WAKE_OP => {
             assert_unsigned(value)?;
-            if command == FUTEX_CMP_REQUEUE && uaddr.read_vm()? != value3 {
-                return Err(KError::WouldBlock);
-            }
+            // The timeout argument is the second count for these.
             let value2 = assert_unsigned(timeout.addr() as u32)?;
 
-            let futex = futex_table.get(&key);
-            let key2 = FutexKey::new_current(uaddr2.addr());
+            let key2 = FutexKey::new_current(uaddr2.addr(), shared);
             let table2 = proc_data.futex_table_for(&key2);
+            let futex = futex_table.get_or_insert(&key);
             let futex2 = table2.get_or_insert(&key2);
-
-            let mut count = 0;
-            if let Some(futex) = futex {
-                count = futex.wq.wake(value as _, u32::MAX);
-                if count == value as usize {
-                    count += futex.wq.requeue(value2 as _, &futex2.wq) as usize;
+            let _guards = lock_pair(&futex, &futex2);
+
+            if command == FUTEX_WAKE_OP {
+                let op = WakeOp::decode(value3)?;
+                let holds = with_word(uaddr2.addr(), |word| op.apply(word))?;
+                let mut count = futex.wq.wake(value as _, u32::MAX);
+                if holds {
+                    count += futex2.wq.wake(value2 as _, u32::MAX);
                 }
+                return Ok(count as _);
             }
-            Ok(count as _)
+
+            if command == FUTEX_CMP_REQUEUE && uaddr.read_vm()? != value3 {
+                return Err(KError::WouldBlock);
+            }
+            let count = futex.wq.wake(value as _, u32::MAX);
+            let moved = futex.wq.requeue(value2 as _, &futex2.wq);
+            Ok((count + moved) as _)
+        }
+        FUTEX_LOCK_PI => {
+            // The timeout is absolute, by CLOCK_REALTIME.
+            let deadline =
+                read_timeout(timeout)?.map(|ts| wall_time() + ts.saturating_sub(realtime()));
+            let futex = futex_table.get_or_insert(&key);
+            futex_lock_pi(&futex, uaddr.addr(), deadline)
+        }
+        FUTEX_UNLOCK_PI => {
+            let futex = futex_table.get_or_insert(&key);
+            futex_unlock_pi(&futex, uaddr.addr())
         }
         _ => Err(KError::Unsupported),
     }
@@ -152,7 +235,14 @@ pub fn sys_set_robust_list(head: *const robust_list_head, size: usize) -> KResul
     if size != size_of::<robust_list_head>() {
         return Err(KError::InvalidInput);
     }
-    current().as_thread().set_robust_list_head(head.addr());
+    let curr = current();
+    let thr = curr.as_thread();
+    if thr.set_robust_list_head(head.addr()) == 0 && !head.is_null() {
+        // Walks whatever list the thread has when it exits.
+        thr.exit_hooks()
+            .register("robust futex", release_robust_list)
+            .detach();
+    }
 
     Ok(0)
 }
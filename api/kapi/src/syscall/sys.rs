@@ -12,9 +12,11 @@
 
 use core::ffi::c_char;
 
-use kcore::task::processes;
+use kcore::task::{AsThread, processes};
 use kerrno::{KError, KResult};
 use kfs::FS_CONTEXT;
+use kprocess::ConnectorEvent;
+use ktask::current;
 use linux_raw_sys::{
     general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
     system::{new_utsname, sysinfo},
@@ -43,17 +45,42 @@ pub fn sys_getegid() -> KResult<isize> {
 }
 
 /// Set the user ID of the current process
-pub fn sys_setuid(_uid: u32) -> KResult<isize> {
-    debug!("sys_setuid <= uid: {_uid}");
+pub fn sys_setuid(uid: u32) -> KResult<isize> {
+    debug!("sys_setuid <= uid: {uid}");
+    notify_id_change(true, uid, uid);
     Ok(0)
 }
 
 /// Set the group ID of the current process
-pub fn sys_setgid(_gid: u32) -> KResult<isize> {
-    debug!("sys_setgid <= gid: {_gid}");
+pub fn sys_setgid(gid: u32) -> KResult<isize> {
+    debug!("sys_setgid <= gid: {gid}");
+    notify_id_change(false, gid, gid);
     Ok(0)
 }
 
+/// Reports new user (`uid`) or group IDs of the current process to process
+/// event listeners.
+///
+/// Everything runs as root, so an ID of -1, which is left as is, is 0.
+pub(crate) fn notify_id_change(uid: bool, real: u32, effective: u32) {
+    let id = |id| if id == u32::MAX { 0 } else { id };
+    let (real, effective) = (id(real), id(effective));
+    let pid = current().as_thread().proc_data.proc.pid();
+    kprocess::connector::notify(if uid {
+        ConnectorEvent::Uid {
+            pid,
+            ruid: real,
+            euid: effective,
+        }
+    } else {
+        ConnectorEvent::Gid {
+            pid,
+            rgid: real,
+            egid: effective,
+        }
+    });
+}
+
 /// Get the supplementary group IDs of the current process
 pub fn sys_getgroups(size: usize, list: *mut u32) -> KResult<isize> {
     debug!("sys_getgroups <= size: {size}");
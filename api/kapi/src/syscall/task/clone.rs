@@ -148,6 +148,11 @@ pub fn sys_clone(
     let old_proc_data = &curr.as_thread().proc_data;
 
     let mut new_task = new_user_task(&curr.name(), new_uctx, set_child_tid);
+    // The child inherits the nice value, the scheduling policy and the CPU
+    // affinity; exec keeps them all.
+    ktask::set_nice(&new_task, curr.nice() as _);
+    new_task.set_sched_policy(curr.sched_policy(), curr.rt_priority());
+    new_task.set_cpumask(curr.cpumask());
 
     let tid = new_task.id().as_u64() as Pid;
     if flags.contains(CloneFlags::PARENT_SETTID) {
@@ -192,9 +197,10 @@ pub fn sys_clone(
             signal_actions,
             exit_signal,
         );
-        proc_data.set_umask(old_proc_data.umask());
         // Inherit heap pointers from parent to ensure child's heap state is consistent after fork
         proc_data.set_heap_top(old_proc_data.get_heap_top());
+        proc_data.set_dumpable(old_proc_data.dumpable());
+        proc_data.set_compat(old_proc_data.is_compat());
 
         {
             let mut scope = proc_data.scope.write();
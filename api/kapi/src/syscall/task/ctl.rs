@@ -9,15 +9,20 @@
 //! - Process resource limits (prlimit, etc.)
 //! - Process information queries
 
-use core::ffi::c_char;
-
+use flightrec::MARKER_TEXT_LEN;
 use kcore::task::{AsThread, get_process_data};
 use kerrno::{KError, KResult};
-use ktask::current;
+use kfs::FS_CONTEXT;
+use kprocess::ConnectorEvent;
+use ksignal::Signo;
+use ktask::{TASK_COMM_LEN, current};
 use linux_raw_sys::general::{__user_cap_data_struct, __user_cap_header_struct};
-use osvm::{VirtMutPtr, VirtPtr, write_vm_mem};
+use osvm::{VirtMutPtr, VirtPtr, read_vm_cstr, write_vm_mem};
 
-use crate::mm::vm_load_string;
+use crate::{
+    syscall::sys::notify_id_change,
+    trace::{PR_SET_TRACE_MARKER, record_marker},
+};
 
 const CAPABILITY_VERSION_3: u32 = 0x20080522;
 
@@ -56,21 +61,25 @@ pub fn sys_capset(
     Ok(0)
 }
 
+/// Sets the file mode creation mask, which is shared with the threads and
+/// processes sharing the filesystem context (`CLONE_FS`).
 pub fn sys_umask(mask: u32) -> KResult<isize> {
-    let curr = current();
-    let old = curr.as_thread().proc_data.replace_umask(mask);
+    let old = FS_CONTEXT.lock().set_umask(mask);
     Ok(old as isize)
 }
 
-pub fn sys_setreuid(_ruid: u32, _euid: u32) -> KResult<isize> {
+pub fn sys_setreuid(ruid: u32, euid: u32) -> KResult<isize> {
+    notify_id_change(true, ruid, euid);
     Ok(0)
 }
 
-pub fn sys_setresuid(_ruid: u32, _euid: u32, _suid: u32) -> KResult<isize> {
+pub fn sys_setresuid(ruid: u32, euid: u32, _suid: u32) -> KResult<isize> {
+    notify_id_change(true, ruid, euid);
     Ok(0)
 }
 
-pub fn sys_setresgid(_rgid: u32, _egid: u32, _sgid: u32) -> KResult<isize> {
+pub fn sys_setresgid(rgid: u32, egid: u32, _sgid: u32) -> KResult<isize> {
+    notify_id_change(false, rgid, egid);
     Ok(0)
 }
 
@@ -88,8 +97,14 @@ pub fn sys_get_mempolicy(
 /// prctl() is called with a first argument describing what to do, and further
 /// arguments with a significance depending on the first one.
 /// The first argument can be:
-/// - PR_SET_NAME: set the name of the calling thread, using the value pointed to by `arg2`
-/// - PR_GET_NAME: get the name of the calling
+/// - PR_SET_NAME: set the name of the calling thread, using the value pointed to by `arg2`,
+///   truncated to 15 bytes
+/// - PR_GET_NAME: get the name of the calling thread into the 16-byte buffer at `arg2`
+/// - PR_SET_PDEATHSIG: set the signal sent to the calling thread when its parent exits,
+///   or clear it if `arg2` is 0
+/// - PR_GET_PDEATHSIG: get that signal into the `int` at `arg2`
+/// - PR_SET_DUMPABLE: set whether the calling process may be dumped, `arg2` being 0 or 1
+/// - PR_GET_DUMPABLE: return whether the calling process may be dumped
 /// - PR_SET_SECCOMP: enable seccomp mode, with the mode specified in `arg2`
 /// - PR_MCE_KILL: set the machine check exception policy
 /// - PR_SET_MM options: set various memory management options (start/end code/data/brk/stack)
@@ -106,15 +121,56 @@ pub fn sys_prctl(
 
     match option {
         PR_SET_NAME => {
-            let s = vm_load_string(arg2 as *const c_char)?;
-            current().set_name(&s);
+            let mut buf = [0; TASK_COMM_LEN - 1];
+            let len = read_vm_cstr(arg2 as *const u8, &mut buf)?;
+            let curr = current();
+            curr.set_comm(&buf[..len]);
+            kprocess::connector::notify(ConnectorEvent::Comm {
+                pid: curr.as_thread().proc_data.proc.pid(),
+                comm: curr.comm(),
+            });
         }
         PR_GET_NAME => {
-            let name = current().name();
-            let len = name.len().min(15);
-            let mut buf = [0; 16];
-            buf[..len].copy_from_slice(&name.as_bytes()[..len]);
-            write_vm_mem(arg2 as _, &buf)?;
+            write_vm_mem(arg2 as *mut u8, &current().comm())?;
+        }
+        PR_SET_PDEATHSIG => {
+            let signo = match arg2 {
+                0 => None,
+                _ => Some(
+                    u8::try_from(arg2)
+                        .ok()
+                        .and_then(Signo::from_repr)
+                        .ok_or(KError::InvalidInput)?,
+                ),
+            };
+            current().as_thread().set_pdeathsig(signo);
+        }
+        PR_GET_PDEATHSIG => {
+            let signo = current()
+                .as_thread()
+                .pdeathsig()
+                .map_or(0, |signo| signo as i32);
+            (arg2 as *mut i32).write_vm(signo)?;
+        }
+        PR_SET_DUMPABLE => {
+            let dumpable = match arg2 {
+                0 => false,
+                1 => true,
+                _ => return Err(KError::InvalidInput),
+            };
+            current().as_thread().proc_data.set_dumpable(dumpable);
+        }
+        PR_GET_DUMPABLE => {
+            return Ok(current().as_thread().proc_data.dumpable() as isize);
+        }
+        PR_SET_TRACE_MARKER => {
+            let mut text = [0; MARKER_TEXT_LEN];
+            let len = if arg3 == 0 {
+                0
+            } else {
+                read_vm_cstr(arg3 as *const u8, &mut text)?
+            };
+            record_marker(arg2 as u64, &text[..len]);
         }
         PR_SET_SECCOMP => {}
         PR_MCE_KILL => {}
@@ -6,20 +6,54 @@
 //!
 //! This module implements program execution operations including:
 //! - Execute a new program (execve, execveat, execveeat, etc.)
-//! - Program loading and initialization
+//! - Program loading and initialization, including `#!` scripts and
+//!   dynamically linked programs
 //! - Argument and environment passing
 
-use alloc::{string::ToString, sync::Arc, vec::Vec};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use core::ffi::c_char;
 
+use fs_ng_vfs::NodePermission;
 use kcore::{config::USER_HEAP_BASE, mm::load_user_app, task::AsThread};
 use kerrno::{KError, KResult};
 use kfs::FS_CONTEXT;
 use khal::uspace::UserContext;
+use kprocess::ConnectorEvent;
 use ktask::current;
-use osvm::load_vec_until_null;
+use osvm::{MemError, StrArray, load_str_array};
+
+use crate::{
+    file::{FD_TABLE, close_on_exec, unshare_fd_table},
+    mm::vm_load_path,
+};
+
+/// Maximum number of bytes taken by the argument and environment strings.
+const ARG_MAX: usize = 2 * 1024 * 1024;
+/// Maximum number of argument or environment strings.
+const MAX_ARG_STRINGS: usize = 0x7fff_ffff;
+
+/// Loads a null-terminated `argv` or `envp` array; a null array is empty.
+fn load_args(p: *const *const c_char, max_total_bytes: usize) -> KResult<StrArray> {
+    if p.is_null() {
+        return Ok(StrArray::default());
+    }
+    load_str_array(p.cast(), MAX_ARG_STRINGS, max_total_bytes).map_err(|err| match err {
+        MemError::NameTooLong => KError::ArgumentListTooLong,
+        err => err.into(),
+    })
+}
 
-use crate::{file::FD_TABLE, mm::vm_load_string};
+fn into_strings(array: StrArray) -> KResult<Vec<String>> {
+    array
+        .strings
+        .into_iter()
+        .map(|s| s.into_string().map_err(|_| KError::IllegalBytes))
+        .collect()
+}
 
 pub fn sys_execve(
     uctx: &mut UserContext,
@@ -27,27 +61,11 @@ pub fn sys_execve(
     argv: *const *const c_char,
     envp: *const *const c_char,
 ) -> KResult<isize> {
-    let path = vm_load_string(path)?;
-
-    let args = if argv.is_null() {
-        // Handle NULL argv (treat as empty array)
-        Vec::new()
-    } else {
-        load_vec_until_null(argv)?
-            .into_iter()
-            .map(vm_load_string)
-            .collect::<Result<Vec<_>, _>>()?
-    };
-
-    let envs = if envp.is_null() {
-        // Handle NULL envp (treat as empty array)
-        Vec::new()
-    } else {
-        load_vec_until_null(envp)?
-            .into_iter()
-            .map(vm_load_string)
-            .collect::<Result<Vec<_>, _>>()?
-    };
+    let path = vm_load_path(path)?;
+
+    let args = load_args(argv, ARG_MAX)?;
+    let envs = load_args(envp, ARG_MAX - args.total_bytes)?;
+    let (args, envs) = (into_strings(args)?, into_strings(envs)?);
 
     debug!("sys_execve <= path: {path:?}, args: {args:?}, envs: {envs:?}");
 
@@ -61,35 +79,55 @@ pub fn sys_execve(
     }
 
     let mut aspace = proc_data.aspace.lock();
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
+    let image = load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
     drop(aspace);
 
     let loc = FS_CONTEXT.lock().resolve(&path)?;
     curr.set_name(loc.name());
 
+    // Executing a setuid or setgid program drops what the old image may have
+    // set up to watch or be notified about the new one.
+    let mode = loc.metadata()?.mode;
+    let set_id = mode.contains(NodePermission::SET_UID)
+        || mode.contains(NodePermission::SET_GID | NodePermission::GROUP_EXEC);
+    if set_id {
+        curr.as_thread().set_pdeathsig(None);
+    }
+    proc_data.set_dumpable(!set_id);
+
     *proc_data.exe_path.write() = loc.absolute_path()?.to_string();
     *proc_data.cmdline.write() = Arc::new(args);
 
     proc_data.set_heap_top(USER_HEAP_BASE);
+    proc_data.set_compat(image.compat);
 
     *proc_data.signal.actions.lock() = Default::default();
 
     // Clear set_child_tid after exec since the original address is no longer valid
     curr.as_thread().set_clear_child_tid(0);
 
-    // Close CLOEXEC file descriptors
-    let mut fd_table = FD_TABLE.write();
-    let cloexec_fds = fd_table
-        .ids()
-        .filter(|it| fd_table.get(*it).unwrap().cloexec)
-        .collect::<Vec<_>>();
-    for fd in cloexec_fds {
-        fd_table.remove(fd);
+    // Close CLOEXEC file descriptors, in a table of our own if it was shared
+    // with CLONE_FILES.
+    unshare_fd_table();
+    close_on_exec(&mut FD_TABLE.write());
+
+    curr.as_thread().perf().enable_on_exec();
+
+    kprocess::connector::notify(ConnectorEvent::Exec {
+        pid: proc_data.proc.pid(),
+        comm: curr.comm(),
+    });
+
+    #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+    {
+        uctx.set_compat(image.compat);
+        if image.compat {
+            uctx.set_compat_ip(image.entry.as_usize());
+            uctx.set_sp(image.sp.as_usize());
+            return Ok(0);
+        }
     }
-    drop(fd_table);
-
-    uctx.set_ip(entry_point.as_usize());
-    uctx.set_sp(user_stack_base.as_usize());
+    uctx.set_ip(image.entry.as_usize());
+    uctx.set_sp(image.sp.as_usize());
     Ok(0)
 }
@@ -9,21 +9,28 @@
 //! - Sleep operations (sleep, nanosleep, etc.)
 //! - Scheduling priority (getpriority, setpriority, nice, etc.)
 //! - CPU affinity (sched_setaffinity, sched_getaffinity, etc.)
+//! - Scheduling policy (sched_setscheduler, sched_getparam, etc.)
 
-use kcore::task::{get_process_data, get_process_group};
+use alloc::{vec, vec::Vec};
+
+use kcore::{
+    task::{AsThread, get_process_group, get_task, tasks},
+    time::Clock,
+};
 use kerrno::{KError, KResult};
 use khal::time::TimeValue;
 use ktask::{
-    KCpuMask, current,
+    KCpuMask, KtaskRef, MAX_NICE, MAX_RT_PRIO, MIN_NICE, MIN_RT_PRIO, SchedPolicy, current,
     future::{block_on, interruptible, sleep},
 };
 use linux_raw_sys::general::{
-    __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
-    SCHED_RR, TIMER_ABSTIME, timespec,
+    __kernel_clockid_t, PRIO_PGRP, PRIO_PROCESS, PRIO_USER, RLIMIT_NICE, RLIMIT_RTPRIO,
+    SCHED_BATCH, SCHED_FIFO, SCHED_IDLE, SCHED_NORMAL, SCHED_RESET_ON_FORK, SCHED_RR,
+    TIMER_ABSTIME, timespec,
 };
 use osvm::{VirtMutPtr, VirtPtr, load_vec, write_vm_mem};
 
-use crate::time::TimeValueLike;
+use crate::{syscall::sys::sys_geteuid, time::TimeValueLike};
 
 pub fn sys_sched_yield() -> KResult<isize> {
     ktask::yield_now();
@@ -70,10 +77,8 @@ pub fn sys_clock_nanosleep(
     req: *const timespec,
     rem: *mut timespec,
 ) -> KResult<isize> {
-    // Select appropriate clock function based on clock_id
-    let clock = match clock_id as u32 {
-        CLOCK_REALTIME => khal::time::wall_time,
-        CLOCK_MONOTONIC => khal::time::monotonic_time,
+    let clock = match Clock::from_id(clock_id) {
+        Some(clock @ (Clock::Realtime | Clock::Monotonic | Clock::Boottime)) => clock,
         _ => {
             warn!("Unsupported clock_id: {clock_id}");
             return Err(KError::InvalidInput);
@@ -85,12 +90,12 @@ pub fn sys_clock_nanosleep(
 
     // If TIMER_ABSTIME flag is set, request is absolute time; otherwise it's relative
     let dur = if flags & TIMER_ABSTIME != 0 {
-        req.saturating_sub(clock())
+        req.saturating_sub(clock.now())
     } else {
         req
     };
 
-    let actual = sleep_impl(clock, dur);
+    let actual = sleep_impl(|| clock.now(), dur);
 
     if let Some(diff) = dur.checked_sub(actual) {
         debug!("sys_clock_nanosleep => rem: {diff:?}");
@@ -103,83 +108,231 @@ pub fn sys_clock_nanosleep(
     }
 }
 
+/// Size in bytes of the CPU masks of `sched_getaffinity` and
+/// `sched_setaffinity`: a bit for every possible CPU, in whole `long`s.
+const CPU_MASK_SIZE: usize =
+    platconfig::plat::CPU_NUM.div_ceil(usize::BITS as usize) * size_of::<usize>();
+
+/// Every task runs as root, see `sys_geteuid`.
+const TASK_UID: u32 = 0;
+
+/// Returns the task `pid` refers to, the calling thread for 0.
+fn sched_target(pid: i32) -> KResult<KtaskRef> {
+    if pid < 0 {
+        return Err(KError::NoSuchProcess);
+    }
+    get_task(pid as _)
+}
+
 pub fn sys_sched_getaffinity(pid: i32, cpusetsize: usize, user_mask: *mut u8) -> KResult<isize> {
-    // Check if the buffer is large enough
-    if cpusetsize * 8 < platconfig::plat::CPU_NUM {
+    // The buffer must hold the whole mask, in whole `long`s.
+    if cpusetsize < CPU_MASK_SIZE || !cpusetsize.is_multiple_of(size_of::<usize>()) {
         return Err(KError::InvalidInput);
     }
 
-    // TODO: support other threads
-    // Currently only supports getting the calling thread's affinity (pid=0)
-    if pid != 0 {
-        return Err(KError::OperationNotPermitted);
+    let mask = sched_target(pid)?.cpumask();
+    let mut mask_bytes = vec![0u8; CPU_MASK_SIZE];
+    for cpu in 0..platconfig::plat::CPU_NUM {
+        if mask.get(cpu) {
+            mask_bytes[cpu / 8] |= 1 << (cpu % 8);
+        }
     }
+    write_vm_mem(user_mask, &mask_bytes)?;
 
-    // Get current thread's CPU affinity mask
-    let mask = current().cpumask();
-    let mask_bytes = mask.as_bytes();
+    Ok(CPU_MASK_SIZE as _)
+}
 
-    // Write the mask to user space
-    write_vm_mem(user_mask, mask_bytes)?;
+pub fn sys_sched_setaffinity(pid: i32, cpusetsize: usize, user_mask: *const u8) -> KResult<isize> {
+    // Bits of CPUs beyond the kernel's are ignored, and missing ones are 0.
+    let size = cpusetsize.min(CPU_MASK_SIZE);
+    let mask_bytes = load_vec(user_mask, size)?;
+    let mut cpu_mask = KCpuMask::new();
+    for cpu in 0..(size * 8).min(platconfig::plat::CPU_NUM) {
+        if mask_bytes[cpu / 8] & (1 << (cpu % 8)) != 0 {
+            cpu_mask.set(cpu, true);
+        }
+    }
 
-    Ok(mask_bytes.len() as _)
+    // Fails if the mask holds no online CPU.
+    if !ktask::set_affinity(&sched_target(pid)?, cpu_mask) {
+        return Err(KError::InvalidInput);
+    }
+    Ok(0)
 }
 
-pub fn sys_sched_setaffinity(_pid: i32, cpusetsize: usize, user_mask: *const u8) -> KResult<isize> {
-    // Load the CPU mask from user space (limit to actual CPU count)
-    let size = cpusetsize.min(platconfig::plat::CPU_NUM.div_ceil(8));
-    let user_mask = load_vec(user_mask, size)?;
-    let mut cpu_mask = KCpuMask::new();
+/// Converts a Linux scheduling policy.
+///
+/// `SCHED_BATCH` and `SCHED_IDLE` are accepted as the normal policy.
+fn sched_policy(policy: u32) -> KResult<SchedPolicy> {
+    match policy {
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => Ok(SchedPolicy::Normal),
+        SCHED_FIFO => Ok(SchedPolicy::Fifo),
+        SCHED_RR => Ok(SchedPolicy::RoundRobin),
+        _ => Err(KError::InvalidInput),
+    }
+}
 
-    // Convert byte array to CPU mask bitset
-    for i in 0..(size * 8).min(platconfig::plat::CPU_NUM) {
-        if user_mask[i / 8] & (1 << (i % 8)) != 0 {
-            cpu_mask.set(i, true);
-        }
+/// Checks whether the caller may give `task` the real-time priority
+/// `rt_priority`, 0 for the normal policy.
+///
+/// Root may set any priority. Others may only change their own tasks, and
+/// only raise the priority as far as `RLIMIT_RTPRIO` allows.
+fn check_rt_priority(task: &KtaskRef, rt_priority: u8) -> KResult<()> {
+    let euid = sys_geteuid()? as u32;
+    if euid == 0 {
+        return Ok(());
+    }
+    if euid != TASK_UID {
+        return Err(KError::OperationNotPermitted);
+    }
+    let limit = current().as_thread().proc_data.rlim.read()[RLIMIT_RTPRIO].current;
+    if rt_priority > task.rt_priority() && rt_priority as u64 > limit {
+        return Err(KError::PermissionDenied);
     }
+    Ok(())
+}
 
-    // TODO: support other threads
-    // Apply the CPU affinity to the current thread
-    ktask::set_current_affinity(cpu_mask);
+/// Sets the policy of the task `pid` to `policy`, or keeps it if [`None`],
+/// with the priority in the `sched_param` at `param`.
+fn set_sched_param(pid: i32, policy: Option<SchedPolicy>, param: *const i32) -> KResult<isize> {
+    let priority = param
+        .check_non_null()
+        .ok_or(KError::InvalidInput)?
+        .read_vm()?;
+    let task = sched_target(pid)?;
+    let policy = policy.unwrap_or_else(|| task.sched_policy());
+    debug!("set_sched_param <= pid: {pid}, policy: {policy:?}, priority: {priority}");
 
+    let valid = if policy.is_rt() {
+        (MIN_RT_PRIO as i32..=MAX_RT_PRIO as i32).contains(&priority)
+    } else {
+        priority == 0
+    };
+    if !valid {
+        return Err(KError::InvalidInput);
+    }
+    check_rt_priority(&task, priority as u8)?;
+    ktask::set_scheduler(&task, policy, priority as u8);
     Ok(0)
 }
 
-pub fn sys_sched_getscheduler(_pid: i32) -> KResult<isize> {
-    Ok(SCHED_RR as _)
+pub fn sys_sched_getscheduler(pid: i32) -> KResult<isize> {
+    Ok(sched_target(pid)?.sched_policy() as _)
 }
 
-pub fn sys_sched_setscheduler(_pid: i32, _policy: i32, _param: *const ()) -> KResult<isize> {
-    Ok(0)
+pub fn sys_sched_setscheduler(pid: i32, policy: i32, param: *const i32) -> KResult<isize> {
+    // Children always inherit the policy, `SCHED_RESET_ON_FORK` is ignored.
+    let policy = sched_policy(policy as u32 & !SCHED_RESET_ON_FORK)?;
+    set_sched_param(pid, Some(policy), param)
+}
+
+pub fn sys_sched_setparam(pid: i32, param: *const i32) -> KResult<isize> {
+    set_sched_param(pid, None, param)
 }
 
-pub fn sys_sched_getparam(_pid: i32, _param: *mut ()) -> KResult<isize> {
+pub fn sys_sched_getparam(pid: i32, param: *mut i32) -> KResult<isize> {
+    let param = param.check_non_null().ok_or(KError::InvalidInput)?;
+    param.write_vm(sched_target(pid)?.rt_priority() as i32)?;
     Ok(0)
 }
 
-pub fn sys_getpriority(which: u32, who: u32) -> KResult<isize> {
-    debug!("sys_getpriority <= which: {which}, who: {who}");
+pub fn sys_sched_get_priority_max(policy: i32) -> KResult<isize> {
+    Ok(match sched_policy(policy as u32)? {
+        SchedPolicy::Normal => 0,
+        _ => MAX_RT_PRIO as _,
+    })
+}
 
-    match which {
-        PRIO_PROCESS => {
-            if who != 0 {
-                let _proc = get_process_data(who)?;
-            }
-            Ok(20)
-        }
+pub fn sys_sched_get_priority_min(policy: i32) -> KResult<isize> {
+    Ok(match sched_policy(policy as u32)? {
+        SchedPolicy::Normal => 0,
+        _ => MIN_RT_PRIO as _,
+    })
+}
+
+pub fn sys_sched_rr_get_interval(pid: i32, interval: *mut timespec) -> KResult<isize> {
+    // Only round-robin tasks have a time slice.
+    let slice = match sched_target(pid)?.sched_policy() {
+        SchedPolicy::RoundRobin => ktask::rr_timeslice(),
+        _ => TimeValue::ZERO,
+    };
+    interval.write_vm(timespec::from_time_value(slice))?;
+    Ok(0)
+}
+
+/// Collects the tasks selected by the `which` and `who` arguments of
+/// `getpriority` and `setpriority`.
+fn priority_targets(which: u32, who: u32) -> KResult<Vec<KtaskRef>> {
+    let targets: Vec<KtaskRef> = match which {
+        PRIO_PROCESS => vec![get_task(who)?],
         PRIO_PGRP => {
-            if who != 0 {
-                let _pg = get_process_group(who)?;
-            }
-            Ok(20)
+            let group = if who == 0 {
+                current().as_thread().proc_data.proc.group()
+            } else {
+                get_process_group(who)?
+            };
+            group
+                .processes()
+                .iter()
+                .flat_map(|proc| proc.threads())
+                .filter_map(|tid| get_task(tid).ok())
+                .collect()
         }
         PRIO_USER => {
-            if who == 0 {
-                Ok(20)
-            } else {
-                Err(KError::NoSuchProcess)
-            }
+            // Every task runs as root, see `sys_geteuid`.
+            let uid = if who == 0 { sys_geteuid()? as u32 } else { who };
+            if uid == 0 { tasks() } else { Vec::new() }
         }
-        _ => Err(KError::InvalidInput),
+        _ => return Err(KError::InvalidInput),
+    };
+    if targets.is_empty() {
+        return Err(KError::NoSuchProcess);
     }
+    Ok(targets)
+}
+
+/// Checks whether the caller may change the nice value of `task` to `nice`.
+///
+/// Root may set any value. Others may only renice their own tasks, and only
+/// lower the nice value as far as `RLIMIT_NICE` allows.
+fn check_nice(task: &KtaskRef, nice: i32) -> KResult<()> {
+    let euid = sys_geteuid()? as u32;
+    if euid == 0 {
+        return Ok(());
+    }
+    if euid != TASK_UID {
+        return Err(KError::OperationNotPermitted);
+    }
+    let limit = current().as_thread().proc_data.rlim.read()[RLIMIT_NICE].current;
+    if nice < task.nice() as i32 && (20 - nice) as u64 > limit {
+        return Err(KError::PermissionDenied);
+    }
+    Ok(())
+}
+
+pub fn sys_getpriority(which: u32, who: u32) -> KResult<isize> {
+    debug!("sys_getpriority <= which: {which}, who: {who}");
+
+    // Like Linux, returns `20 - nice` of the highest-priority task, so that
+    // the result is never negative.
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|task| task.nice())
+        .min()
+        .unwrap_or_default();
+    Ok((20 - nice as isize) as _)
+}
+
+pub fn sys_setpriority(which: u32, who: u32, nice: i32) -> KResult<isize> {
+    debug!("sys_setpriority <= which: {which}, who: {who}, nice: {nice}");
+
+    let nice = nice.clamp(MIN_NICE as i32, MAX_NICE as i32);
+    let targets = priority_targets(which, who)?;
+    for task in &targets {
+        check_nice(task, nice)?;
+    }
+    for task in &targets {
+        ktask::set_nice(task, nice);
+    }
+    Ok(0)
 }
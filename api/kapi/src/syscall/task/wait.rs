@@ -5,33 +5,46 @@
 //! Process waiting and status syscalls.
 //!
 //! This module implements process status waiting operations including:
-//! - Wait for process termination (wait, waitpid, waitid, etc.)
+//! - Wait for process termination (wait4, waitid)
+//! - Wait for job control stops and continues (`WUNTRACED`, `WCONTINUED`)
 //! - Process status retrieval and interpretation
-//! - Child process status monitoring
+//! - Child resource usage reporting
+//!
+//! Both syscalls block interruptibly and are restarted after a handler with
+//! `SA_RESTART`.
 
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 use core::{future::poll_fn, task::Poll};
 
 use bitflags::bitflags;
 use kcore::task::AsThread;
 use kerrno::{KError, KResult, LinuxError};
-use kprocess::{Pid, Process};
+use kprocess::{Pid, Process, ProcessEvent};
+use ksignal::{SignalInfo, Signo};
 use ktask::{
     current,
     future::{block_on, interruptible},
 };
 use linux_raw_sys::general::{
-    __WALL, __WCLONE, __WNOTHREAD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    __WALL, __WCLONE, __WNOTHREAD, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED,
+    P_ALL, P_PGID, P_PID, P_PIDFD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED, rusage,
+    siginfo,
 };
 use osvm::{VirtMutPtr, VirtPtr};
 
+use crate::{
+    file::{FileLike, PidFd},
+    syscall::resources::to_rusage,
+};
+
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     struct WaitOptions: u32 {
         /// Do not block when there are no processes wishing to report status.
         const WNOHANG = WNOHANG;
         /// Report the status of selected processes which are stopped due to a
-        /// `SIGTTIN`, `SIGTTOU`, `SIGTSTP`, or `SIGSTOP` signal.
+        /// `SIGTTIN`, `SIGTTOU`, `SIGTSTP`, or `SIGSTOP` signal. Also known as
+        /// `WSTOPPED` for `waitid`.
         const WUNTRACED = WUNTRACED;
         /// Report the status of selected processes which have terminated.
         const WEXITED = WEXITED;
@@ -50,7 +63,7 @@ bitflags! {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum WaitPid {
     /// Wait for any child process
     Any,
@@ -61,67 +74,220 @@ enum WaitPid {
 }
 
 impl WaitPid {
-    fn apply(&self, child: &Process) -> bool {
-        match self {
-            WaitPid::Any => true,
-            WaitPid::Pid(pid) => child.pid() == *pid,
-            WaitPid::Pgid(pgid) => child.group().pgid() == *pgid,
+    /// Decodes the `pid` argument of `wait4`, where `0` stands for the
+    /// caller's process group `own_pgid`.
+    fn from_wait4(pid: i32, own_pgid: Pid) -> Self {
+        match pid {
+            -1 => WaitPid::Any,
+            0 => WaitPid::Pgid(own_pgid),
+            pid if pid > 0 => WaitPid::Pid(pid as _),
+            pgid => WaitPid::Pgid(pgid.unsigned_abs()),
+        }
+    }
+
+    /// Returns the children of `proc` selected.
+    fn children(&self, proc: &Process) -> Vec<Arc<Process>> {
+        match *self {
+            WaitPid::Any => proc.children(),
+            WaitPid::Pid(pid) => proc
+                .children()
+                .into_iter()
+                .filter(|child| child.pid() == pid)
+                .collect(),
+            WaitPid::Pgid(pgid) => proc.children_in_group(pgid),
         }
     }
 }
 
-pub fn sys_waitpid(pid: i32, exit_code: *mut i32, options: u32) -> KResult<isize> {
-    let options = WaitOptions::from_bits_truncate(options);
-    info!("sys_waitpid <= pid: {pid:?}, options: {options:?}");
+/// Returns the change of state of `child` to report under `options`, if any.
+///
+/// A zombie is reaped unless `WNOWAIT` is set. Another waiter may reap it
+/// first, in which case it has nothing to report.
+fn next_event(child: &Process, options: WaitOptions) -> Option<ProcessEvent> {
+    let nowait = options.contains(WaitOptions::WNOWAIT);
+    if child.is_zombie() {
+        if !options.contains(WaitOptions::WEXITED) {
+            return None;
+        }
+        return if nowait {
+            child.exit_event()
+        } else {
+            child.reap()
+        };
+    }
+    child.take_job_event(!nowait, |event| match event {
+        ProcessEvent::Stopped(_) => options.contains(WaitOptions::WUNTRACED),
+        ProcessEvent::Continued => options.contains(WaitOptions::WCONTINUED),
+        _ => false,
+    })
+}
 
+/// Waits until a child of the caller selected by `pid` changes state as
+/// `options` asks for, returning the child and the change.
+///
+/// Returns `None` if `WNOHANG` is set and no child has anything to report.
+fn wait_child(pid: WaitPid, options: WaitOptions) -> KResult<Option<(Arc<Process>, ProcessEvent)>> {
     let curr = current();
-    let proc_data = &curr.as_thread().proc_data;
-    let proc = &proc_data.proc;
-
-    let pid = if pid == -1 {
-        WaitPid::Any
-    } else if pid == 0 {
-        WaitPid::Pgid(proc.group().pgid())
-    } else if pid > 0 {
-        WaitPid::Pid(pid as _)
-    } else {
-        WaitPid::Pgid(-pid as _)
-    };
+    let proc = &curr.as_thread().proc_data.proc;
 
     // FIXME: add back support for WALL & WCLONE, since ProcessData may drop before
     // Process now.
-    let children = proc
-        .children()
-        .into_iter()
-        .filter(|child| pid.apply(child))
-        .collect::<Vec<_>>();
-    if children.is_empty() {
-        return Err(KError::from(LinuxError::ECHILD));
-    }
-
-    let check_children = || {
-        if let Some(child) = children.iter().find(|child| child.is_zombie()) {
-            if !options.contains(WaitOptions::WNOWAIT) {
-                child.free();
-            }
-            if let Some(exit_code) = exit_code.check_non_null() {
-                exit_code.write_vm(child.exit_code())?;
-            }
-            Ok(Some(child.pid() as _))
+    block_on(interruptible(poll_fn(|cx| {
+        // Register before checking, so that a child changing state in
+        // between still wakes us.
+        proc.register_child_waiter(cx.waker());
+        let children = pid.children(proc);
+        if children.is_empty() {
+            return Poll::Ready(Err(KError::from(LinuxError::ECHILD)));
+        }
+        if let Some(found) = children
+            .into_iter()
+            .find_map(|child| next_event(&child, options).map(|event| (child, event)))
+        {
+            Poll::Ready(Ok(Some(found)))
         } else if options.contains(WaitOptions::WNOHANG) {
-            Ok(Some(0))
+            Poll::Ready(Ok(None))
         } else {
-            Ok(None)
+            Poll::Pending
         }
+    })))?
+}
+
+/// Returns the `si_code` and `si_status` of the `SIGCHLD` reporting `event`.
+fn child_code(event: ProcessEvent) -> (i32, i32) {
+    match event {
+        ProcessEvent::Exited(code) => (CLD_EXITED as _, code),
+        ProcessEvent::Signaled(signo) => (CLD_KILLED as _, signo as _),
+        ProcessEvent::Dumped(signo) => (CLD_DUMPED as _, signo as _),
+        ProcessEvent::Stopped(signo) => (CLD_STOPPED as _, signo as _),
+        ProcessEvent::Continued => (CLD_CONTINUED as _, Signo::SIGCONT as _),
+    }
+}
+
+/// Writes the usage of `child` to `usage` if it is not null.
+fn write_usage(child: &Process, usage: *mut rusage) -> KResult<()> {
+    if let Some(usage) = usage.check_non_null() {
+        usage.write_vm(to_rusage(child.total_usage()))?;
+    }
+    Ok(())
+}
+
+pub fn sys_wait4(pid: i32, status: *mut i32, options: u32, usage: *mut rusage) -> KResult<isize> {
+    let options = WaitOptions::from_bits_truncate(options);
+    info!("sys_wait4 <= pid: {pid:?}, options: {options:?}");
+    if options.intersects(WaitOptions::WEXITED | WaitOptions::WNOWAIT) {
+        return Err(KError::InvalidInput);
+    }
+
+    let own_pgid = current().as_thread().proc_data.proc.group().pgid();
+    let pid = WaitPid::from_wait4(pid, own_pgid);
+    let Some((child, event)) = wait_child(pid, options | WaitOptions::WEXITED)? else {
+        return Ok(0);
     };
+    if let Some(status) = status.check_non_null() {
+        status.write_vm(event.wait_status())?;
+    }
+    write_usage(&child, usage)?;
+    Ok(child.pid() as _)
+}
 
-    block_on(interruptible(poll_fn(|cx| {
-        match check_children().transpose() {
-            Some(res) => Poll::Ready(res),
-            None => {
-                proc_data.child_exit_event.register(cx.waker());
-                Poll::Pending
+pub fn sys_waitid(
+    idtype: u32,
+    id: u32,
+    info: *mut siginfo,
+    options: u32,
+    usage: *mut rusage,
+) -> KResult<isize> {
+    let options = WaitOptions::from_bits_truncate(options);
+    info!("sys_waitid <= idtype: {idtype}, id: {id}, options: {options:?}");
+    if !options.intersects(WaitOptions::WEXITED | WaitOptions::WUNTRACED | WaitOptions::WCONTINUED)
+    {
+        return Err(KError::InvalidInput);
+    }
+
+    let pid = match idtype {
+        P_ALL => WaitPid::Any,
+        P_PID if id > 0 => WaitPid::Pid(id),
+        P_PGID if id == 0 => WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid()),
+        P_PGID => WaitPid::Pgid(id),
+        P_PIDFD => WaitPid::Pid(PidFd::from_fd(id as _)?.process().pid()),
+        _ => return Err(KError::InvalidInput),
+    };
+
+    let found = wait_child(pid, options)?;
+    if let Some(info) = info.check_non_null() {
+        let sig = match &found {
+            Some((child, event)) => {
+                let (code, status) = child_code(*event);
+                SignalInfo::new_child(code, child.pid(), status).0
             }
-        }
-    })))?
+            // Nothing to report: `si_pid` and `si_signo` read as zero.
+            // FIXME: Zeroable
+            None => unsafe { core::mem::zeroed() },
+        };
+        info.write_vm(sig)?;
+    }
+    if let Some((child, _)) = &found {
+        write_usage(child, usage)?;
+    }
+    Ok(0)
+}
+
+#[cfg(unittest)]
+mod wait_tests {
+    use unittest::def_test;
+
+    use super::*;
+
+    #[def_test]
+    fn test_wait4_pid_selector() {
+        assert_eq!(WaitPid::from_wait4(-1, 7), WaitPid::Any);
+        assert_eq!(WaitPid::from_wait4(0, 7), WaitPid::Pgid(7));
+        assert_eq!(WaitPid::from_wait4(42, 7), WaitPid::Pid(42));
+        assert_eq!(WaitPid::from_wait4(-42, 7), WaitPid::Pgid(42));
+    }
+
+    #[def_test]
+    fn test_waitid_child_codes() {
+        assert_eq!(child_code(ProcessEvent::Exited(3)), (CLD_EXITED as _, 3));
+        assert_eq!(child_code(ProcessEvent::Signaled(9)), (CLD_KILLED as _, 9));
+        assert_eq!(child_code(ProcessEvent::Dumped(11)), (CLD_DUMPED as _, 11));
+        assert_eq!(
+            child_code(ProcessEvent::Stopped(19)),
+            (CLD_STOPPED as _, 19)
+        );
+        assert_eq!(
+            child_code(ProcessEvent::Continued),
+            (CLD_CONTINUED as _, Signo::SIGCONT as _)
+        );
+    }
+
+    #[def_test]
+    fn test_stopped_then_continued() {
+        let parent = Process::new_init(61_000);
+        let child = parent.fork(61_001);
+        let exited = WaitOptions::WEXITED;
+        let untraced = exited | WaitOptions::WUNTRACED;
+        let continued = exited | WaitOptions::WCONTINUED;
+
+        assert_eq!(next_event(&child, untraced | WaitOptions::WCONTINUED), None);
+
+        // A stop is reported once to WUNTRACED, but can be peeked with WNOWAIT.
+        assert!(child.stop(Signo::SIGTSTP as _));
+        assert_eq!(next_event(&child, continued), None);
+        let peeked = next_event(&child, untraced | WaitOptions::WNOWAIT);
+        assert_eq!(peeked, Some(ProcessEvent::Stopped(Signo::SIGTSTP as _)));
+        let stopped = next_event(&child, untraced).unwrap();
+        assert_eq!(stopped.wait_status(), ((Signo::SIGTSTP as i32) << 8) | 0x7f);
+        assert_eq!(next_event(&child, untraced), None);
+
+        // Then the continue, once, to WCONTINUED.
+        child.cont();
+        assert_eq!(next_event(&child, untraced), None);
+        let resumed = next_event(&child, continued).unwrap();
+        assert_eq!(resumed, ProcessEvent::Continued);
+        assert_eq!(resumed.wait_status(), 0xffff);
+        assert_eq!(next_event(&child, continued), None);
+        assert!(parent.children_in_group(parent.group().pgid()).len() == 1);
+    }
 }
@@ -9,57 +9,111 @@
 //! - Time queries (gettimeofday, gettime, etc.)
 //! - Timer management (setitimer, getitimer, timer_*, etc.)
 //! - Time conversions and utilities
-use kcore::{task::AsThread, time::ITimerType};
+use kcore::{
+    task::{AsThread, set_itimer},
+    time::{Clock, ITimerType},
+};
 use kerrno::{KError, KResult};
-use khal::time::{TimeValue, monotonic_time, monotonic_time_nanos, ns2t, wall_time};
+use khal::time::{monotonic_time_nanos, ns2t, set_realtime};
 use ktask::current;
 use linux_raw_sys::general::{
-    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE,
-    CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_COARSE,
-    CLOCK_THREAD_CPUTIME_ID, itimerval, timespec, timeval,
+    __kernel_clockid_t, __kernel_old_time_t, CLOCK_REALTIME, itimerval, timespec, timeval, timezone,
 };
 use osvm::{VirtMutPtr, VirtPtr};
 
-use crate::time::TimeValueLike;
+use crate::{syscall::sys::sys_geteuid, time::TimeValueLike};
+
+/// Checks whether the caller may set the clocks.
+///
+/// Only root, which holds `CAP_SYS_TIME`, may.
+fn check_set_time() -> KResult<()> {
+    if sys_geteuid()? != 0 {
+        return Err(KError::OperationNotPermitted);
+    }
+    Ok(())
+}
+
+fn clock_from_id(clock_id: __kernel_clockid_t) -> KResult<Clock> {
+    Clock::from_id(clock_id).ok_or_else(|| {
+        debug!("Unsupported clock {clock_id}");
+        KError::InvalidInput
+    })
+}
 
 /// Get the current time from the specified clock
 pub fn sys_clock_gettime(clock_id: __kernel_clockid_t, ts: *mut timespec) -> KResult<isize> {
-    let now = match clock_id as u32 {
-        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => wall_time(),
-        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
-            monotonic_time()
-        }
-        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
-            let (utime, stime) = current().as_thread().time.borrow().output();
-            utime + stime
-        }
-        _ => {
-            warn!("Called sys_clock_gettime for unsupported clock {clock_id}");
-            wall_time()
-            // return Err(KError::EINVAL);
-        }
-    };
+    let now = clock_from_id(clock_id)?.now();
     ts.write_vm(timespec::from_time_value(now))?;
     Ok(0)
 }
 
-/// Get the current time of day
-pub fn sys_gettimeofday(ts: *mut timeval) -> KResult<isize> {
-    ts.write_vm(timeval::from_time_value(wall_time()))?;
+/// Set the specified clock
+pub fn sys_clock_settime(clock_id: __kernel_clockid_t, ts: *const timespec) -> KResult<isize> {
+    // Only the real time clock may be set.
+    if clock_id as u32 != CLOCK_REALTIME {
+        return Err(KError::InvalidInput);
+    }
+    check_set_time()?;
+    let time = unsafe { ts.read_uninit()?.assume_init() }.try_into_time_value()?;
+    set_realtime(time);
     Ok(0)
 }
 
 /// Get the resolution of the specified clock
 pub fn sys_clock_getres(clock_id: __kernel_clockid_t, res: *mut timespec) -> KResult<isize> {
-    if clock_id as u32 != CLOCK_MONOTONIC && clock_id as u32 != CLOCK_REALTIME {
-        warn!("Called sys_clock_getres for unsupported clock {clock_id}");
-    }
+    let clock = clock_from_id(clock_id)?;
     if let Some(res) = res.check_non_null() {
-        res.write_vm(timespec::from_time_value(TimeValue::from_micros(1)))?;
+        res.write_vm(timespec::from_time_value(clock.resolution()))?;
+    }
+    Ok(0)
+}
+
+/// Get the current time of day
+///
+/// The kernel keeps no timezone, so `tz` is always filled with UTC.
+pub fn sys_gettimeofday(tv: *mut timeval, tz: *mut timezone) -> KResult<isize> {
+    if let Some(tv) = tv.check_non_null() {
+        tv.write_vm(timeval::from_time_value(Clock::Realtime.now()))?;
+    }
+    if let Some(tz) = tz.check_non_null() {
+        tz.write_vm(timezone {
+            tz_minuteswest: 0,
+            tz_dsttime: 0,
+        })?;
     }
     Ok(0)
 }
 
+/// Set the time of day
+///
+/// A timezone is checked but otherwise ignored, see [`sys_gettimeofday`].
+pub fn sys_settimeofday(tv: *const timeval, tz: *const timezone) -> KResult<isize> {
+    let time = match tv.check_non_null() {
+        Some(tv) => Some(unsafe { tv.read_uninit()?.assume_init() }.try_into_time_value()?),
+        None => None,
+    };
+    if let Some(tz) = tz.check_non_null() {
+        let tz = unsafe { tz.read_uninit()?.assume_init() };
+        if !(-15 * 60..=15 * 60).contains(&tz.tz_minuteswest) {
+            return Err(KError::InvalidInput);
+        }
+    }
+    check_set_time()?;
+    if let Some(time) = time {
+        set_realtime(time);
+    }
+    Ok(0)
+}
+
+/// Get the time in seconds since the epoch
+pub fn sys_time(tloc: *mut __kernel_old_time_t) -> KResult<isize> {
+    let secs = Clock::RealtimeCoarse.now().as_secs() as __kernel_old_time_t;
+    if let Some(tloc) = tloc.check_non_null() {
+        tloc.write_vm(secs)?;
+    }
+    Ok(secs as _)
+}
+
 #[repr(C)]
 pub struct Tms {
     /// user time
@@ -105,7 +159,6 @@ pub fn sys_setitimer(
     old_value: *mut itimerval,
 ) -> KResult<isize> {
     let ty = ITimerType::from_repr(which).ok_or(KError::InvalidInput)?;
-    let curr = current();
 
     let (interval, remained) = match new_value.check_non_null() {
         Some(new_value) => {
@@ -121,11 +174,7 @@ pub fn sys_setitimer(
 
     debug!("sys_setitimer <= type: {ty:?}, interval: {interval:?}, remained: {remained:?}");
 
-    let old = curr
-        .as_thread()
-        .time
-        .borrow_mut()
-        .set_itimer(ty, interval, remained);
+    let old = set_itimer(ty, interval, remained);
 
     if let Some(old_value) = old_value.check_non_null() {
         old_value.write_vm(itimerval {
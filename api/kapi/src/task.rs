@@ -4,24 +4,27 @@
 
 //! User task entry, exit, and robust futex cleanup helpers.
 
+use alloc::sync::Arc;
 use core::{ffi::c_long, sync::atomic::Ordering};
 
 use bytemuck::AnyBitPattern;
+use fs_ng_vfs::release_all_record_locks;
 use kcore::{
     futex::FutexKey,
     shm::SHM_MANAGER,
     task::{
-        AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
+        AsThread, ProcessData, Thread, get_task, send_signal_to_process, send_signal_to_thread,
         set_timer_state,
     },
     time::TimerState,
 };
 use kerrno::{KError, KResult};
 use khal::uspace::{ExceptionKind, ReturnReason, UserContext};
-use kprocess::Pid;
+use kprocess::{Pid, Process, ResourceUsage};
 use ksignal::{SignalInfo, Signo};
 use ktask::{TaskInner, current};
 use linux_raw_sys::general::ROBUST_LIST_LIMIT;
+use memspace::PageFaultError;
 use osvm::{VirtMutPtr, VirtPtr};
 
 use crate::{
@@ -50,18 +53,25 @@ pub fn new_user_task(name: &str, mut uctx: UserContext, set_child_tid: usize) ->
                 match reason {
                     ReturnReason::Syscall => dispatch_irq_syscall(&mut uctx),
                     ReturnReason::PageFault(addr, flags) => {
-                        if !thr
-                            .proc_data
-                            .aspace
-                            .lock()
-                            .dispatch_irq_page_fault(addr, flags)
-                        {
-                            info!(
-                                "{:?}: segmentation fault at {:#x} {:?}",
-                                thr.proc_data.proc, addr, flags
-                            );
-                            raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
-                                .expect("Failed to send SIGSEGV");
+                        let result = thr.proc_data.aspace.lock().handle_page_fault(addr, flags);
+                        match result {
+                            Ok(()) => {}
+                            Err(PageFaultError::BeyondEof) => {
+                                info!(
+                                    "{:?}: bus error at {:#x} {:?}",
+                                    thr.proc_data.proc, addr, flags
+                                );
+                                raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGBUS))
+                                    .expect("Failed to send SIGBUS");
+                            }
+                            Err(PageFaultError::Invalid) => {
+                                info!(
+                                    "{:?}: segmentation fault at {:#x} {:?}",
+                                    thr.proc_data.proc, addr, flags
+                                );
+                                raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
+                                    .expect("Failed to send SIGSEGV");
+                            }
                         }
                     }
                     ReturnReason::Interrupt => {}
@@ -93,6 +103,12 @@ pub fn new_user_task(name: &str, mut uctx: UserContext, set_child_tid: usize) ->
                 if !unblock_next_signal() {
                     while check_signals(thr, &mut uctx, None) {}
                 }
+                if let Some(restart) = thr.signal.take_syscall_restart() {
+                    restart.apply(&mut uctx);
+                }
+                // Whatever the preemption model, a task is preempted on its
+                // way back to user space.
+                ktask::resched_if_needed();
 
                 set_timer_state(&curr, TimerState::User);
                 curr.clear_interrupt();
@@ -129,7 +145,7 @@ fn dispatch_irq_futex_death(entry: *mut RobustList, offset: i64) -> KResult<()>
         .checked_add_signed(offset)
         .ok_or(KError::InvalidInput)?;
     let address: usize = address.try_into().map_err(|_| KError::InvalidInput)?;
-    let key = FutexKey::new_current(address);
+    let key = FutexKey::new_current(address, true);
 
     let curr = current();
     let futex_table = curr.as_thread().proc_data.futex_table_for(&key);
@@ -137,6 +153,7 @@ fn dispatch_irq_futex_death(entry: *mut RobustList, offset: i64) -> KResult<()>
     let Some(futex) = futex_table.get(&key) else {
         return Ok(());
     };
+    let _guard = futex.lock.lock();
     futex.owner_dead.store(true, Ordering::SeqCst);
     futex.wq.wake(1, u32::MAX);
     Ok(())
@@ -171,6 +188,38 @@ pub fn exit_robust_list(head: *const RobustListHead) -> KResult<()> {
     Ok(())
 }
 
+/// Releases the robust futexes of the exiting current thread, see
+/// `set_robust_list(2)`. Registered as an exit hook of the thread.
+pub fn release_robust_list() {
+    let curr = current();
+    let head = curr.as_thread().set_robust_list_head(0) as *const RobustListHead;
+    if !head.is_null()
+        && let Err(err) = exit_robust_list(head)
+    {
+        warn!("exit robust list failed: {err:?}");
+    }
+}
+
+/// Returns the usage of `thread` so far. The resident set size is left to
+/// the process, see [`mapped_kib`].
+pub fn thread_usage(thread: &Thread) -> ResourceUsage {
+    let (utime, stime) = thread.time.borrow().output();
+    ResourceUsage {
+        utime,
+        stime,
+        maxrss: 0,
+    }
+}
+
+/// Returns the size of the user mappings of `proc_data`, in KiB.
+///
+/// Pages are not counted as they are touched, so this stands in for the
+/// resident set size as an upper bound of it.
+pub fn mapped_kib(proc_data: &ProcessData) -> u64 {
+    let aspace = proc_data.aspace.lock();
+    aspace.areas().map(|area| area.size() as u64).sum::<u64>() / 1024
+}
+
 /// Exit the current thread or process group and perform cleanup.
 pub fn do_exit(exit_code: i32, group_exit: bool) {
     let curr = current();
@@ -180,35 +229,35 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
 
     let clear_child_tid = thr.clear_child_tid() as *mut u32;
     if clear_child_tid.write_vm(0).is_ok() {
-        let key = FutexKey::new_current(clear_child_tid as usize);
+        let key = FutexKey::new_current(clear_child_tid as usize, true);
         let table = thr.proc_data.futex_table_for(&key);
         let guard = table.get(&key);
         if let Some(futex) = guard {
+            let _lock = futex.lock.lock();
             futex.wq.wake(1, u32::MAX);
         }
         ktask::yield_now();
     }
-    let head = thr.robust_list_head() as *const RobustListHead;
-    if !head.is_null()
-        && let Err(err) = exit_robust_list(head)
-    {
-        warn!("exit robust list failed: {err:?}");
-    }
+    thr.exit_hooks().run();
 
     let process = &thr.proc_data.proc;
+    process.account_exited(thread_usage(thr));
     if process.exit_thread(curr.id().as_u64() as Pid, exit_code) {
+        process.account_exited(ResourceUsage {
+            maxrss: mapped_kib(&thr.proc_data),
+            ..Default::default()
+        });
+        let orphans = process.children();
         process.exit();
-        if let Some(parent) = process.parent() {
-            if let Some(signo) = thr.proc_data.exit_signal {
-                let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
-            }
-            if let Ok(data) = get_process_data(parent.pid()) {
-                data.child_exit_event.wake();
-            }
+        send_parent_death_signals(&orphans);
+        if let Some(parent) = process.parent()
+            && let Some(signo) = thr.proc_data.exit_signal
+        {
+            let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
         }
-        thr.proc_data.exit_event.wake();
 
         SHM_MANAGER.lock().clear_proc_shm(process.pid());
+        release_all_record_locks(process.pid() as _);
     }
     if group_exit && !process.is_group_exited() {
         process.group_exit();
@@ -220,6 +269,21 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
     thr.set_exit();
 }
 
+/// Sends their parent death signal, see `PR_SET_PDEATHSIG`, to the threads of
+/// the children of an exited process.
+fn send_parent_death_signals(children: &[Arc<Process>]) {
+    for child in children {
+        for tid in child.threads() {
+            if let Ok(task) = get_task(tid)
+                && let Some(thr) = task.try_as_thread()
+                && let Some(signo) = thr.pdeathsig()
+            {
+                let _ = send_signal_to_thread(None, tid, Some(SignalInfo::new_kernel(signo)));
+            }
+        }
+    }
+}
+
 /// Sends a fatal signal to the current process.
 pub fn raise_signal_fatal(sig: SignalInfo) -> KResult<()> {
     let curr = current();
@@ -6,12 +6,10 @@ use alloc::{format, sync::Arc, vec::Vec};
 use core::ffi::c_int;
 
 use fs_ng_vfs::{NodePermission, VfsError};
-use kcore::task::AsThread;
 use kerrno::{KError, KResult};
 use kfs::{FS_CONTEXT, File, FileBackend, FileFlags, OpenOptions, OpenResult};
 use kio::{Seek, SeekFrom};
 use ksync::RwLock;
-use ktask::current;
 use linux_raw_sys::general::*;
 use scope_local::scope_local;
 use slab::Slab;
@@ -186,8 +184,6 @@ impl FileVariant {
             flags,
             mode
         );
-        let mode = mode & !current().as_thread().proc_data.umask();
-
         let options = flags_to_options(flags as c_int, mode as __kernel_mode_t, (0, 0));
         let fd = with_fs(AT_FDCWD, |fs| options.open(fs, path))
             .and_then(|it| add_to_fd(it, flags as _))?;
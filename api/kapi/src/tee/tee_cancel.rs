@@ -4,10 +4,17 @@
 
 // Cancellation syscalls implementation for TEE using session-level state
 
-use core::{ffi::c_uint, slice};
+use alloc::sync::Arc;
+use core::{
+    ffi::c_uint,
+    slice,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use khal::time::wall_time;
-use tee_raw_sys::TeeTime;
+use ktask::WaitQueue;
+use tee_raw_sys::{TEE_ERROR_CANCEL, TeeTime};
 
 use crate::tee::{
     TeeResult,
@@ -15,6 +22,90 @@ use crate::tee::{
     user_access::copy_to_user,
 };
 
+/// Cancellation state of a session.
+///
+/// Shared between the session and whoever requests the cancellation, which
+/// may run on another CPU. Raising the flag or unmasking it wakes the
+/// session's cancellable sleeps, so they do not wait for their timeout to
+/// notice it.
+#[derive(Default)]
+pub struct TeeCancel {
+    requested: AtomicBool,
+    masked: AtomicBool,
+    wq: WaitQueue,
+}
+
+impl TeeCancel {
+    /// Requests the cancellation of the current operation of the session.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Release);
+        self.wq.notify_all(false);
+    }
+
+    /// Clears a pending cancellation request, e.g. when a new operation
+    /// starts.
+    pub fn clear(&self) {
+        self.requested.store(false, Ordering::Release);
+    }
+
+    /// Masks or unmasks cancellation, returning the previous mask.
+    pub fn set_masked(&self, masked: bool) -> bool {
+        let prev = self.masked.swap(masked, Ordering::AcqRel);
+        if !masked && self.requested.load(Ordering::Acquire) {
+            self.wq.notify_all(false);
+        }
+        prev
+    }
+
+    /// Whether cancellation is masked.
+    pub fn is_masked(&self) -> bool {
+        self.masked.load(Ordering::Acquire)
+    }
+
+    /// Whether a cancellation was requested and is not masked.
+    pub fn is_cancelled(&self) -> bool {
+        !self.is_masked() && self.requested.load(Ordering::Acquire)
+    }
+
+    /// Sleeps for `dur`, as a cancellable operation.
+    ///
+    /// Returns `TEE_ERROR_CANCEL` as soon as an unmasked cancellation is
+    /// pending, including one raised or unmasked during the sleep.
+    pub fn wait_timeout(&self, dur: Duration) -> TeeResult {
+        self.wait_timeout_until(dur, || false).map(|_| ())
+    }
+
+    /// Sleeps until `condition` holds or `dur` has elapsed, as a cancellable
+    /// operation.
+    ///
+    /// Returns whether the sleep timed out, or `TEE_ERROR_CANCEL` if an
+    /// unmasked cancellation ended it. Whoever makes `condition` true is
+    /// expected to wake the sleeper with [`TeeCancel::notify`].
+    pub fn wait_timeout_until<F>(&self, dur: Duration, mut condition: F) -> TeeResult<bool>
+    where
+        F: FnMut() -> bool,
+    {
+        let timed_out = self
+            .wq
+            .wait_timeout_until(dur, || self.is_cancelled() || condition());
+        if !timed_out && self.is_cancelled() {
+            return Err(TEE_ERROR_CANCEL);
+        }
+        Ok(timed_out)
+    }
+
+    /// Wakes the sleepers of [`TeeCancel::wait_timeout_until`] so they
+    /// re-check their condition.
+    pub fn notify(&self) {
+        self.wq.notify_all(false);
+    }
+}
+
+/// Returns the cancellation state of the current session.
+pub fn tee_session_cancel() -> TeeResult<Arc<TeeCancel>> {
+    with_tee_session_ctx(|ctx| Ok(ctx.cancel.clone()))
+}
+
 /// TEE_GetCancellationFlag
 /// Returns 1 if the session cancel flag is set and not masked, otherwise 0.
 /// Get the cancellation flag for the current session
@@ -37,11 +128,7 @@ pub fn sys_tee_scn_get_cancellation_flag(cancel: *mut c_uint) -> TeeResult {
 /// Unmask cancellation for the current session
 /// Returns previous masked state
 pub fn sys_tee_scn_unmask_cancellation(old_mask: *mut c_uint) -> TeeResult {
-    let prev = with_tee_session_ctx_mut(|ctx| {
-        let prev = ctx.cancel_mask;
-        ctx.cancel_mask = false;
-        Ok(prev)
-    })?;
+    let prev = with_tee_session_ctx_mut(|ctx| Ok(ctx.cancel.set_masked(false)))?;
     let prev_mask: u32 = if prev { 1 } else { 0 };
     copy_to_user(
         unsafe { slice::from_raw_parts_mut(old_mask as _, size_of::<u32>()) },
@@ -56,11 +143,7 @@ pub fn sys_tee_scn_unmask_cancellation(old_mask: *mut c_uint) -> TeeResult {
 /// Mask cancellation for the current session
 /// Returns previous masked state
 pub fn sys_tee_scn_mask_cancellation(old_mask: *mut c_uint) -> TeeResult {
-    let prev = with_tee_session_ctx_mut(|ctx| {
-        let prev = ctx.cancel_mask;
-        ctx.cancel_mask = true;
-        Ok(prev)
-    })?;
+    let prev = with_tee_session_ctx_mut(|ctx| Ok(ctx.cancel.set_masked(true)))?;
     let prev_mask: u32 = if prev { 1 } else { 0 };
     copy_to_user(
         unsafe { slice::from_raw_parts_mut(old_mask as _, size_of::<u32>()) },
@@ -70,12 +153,15 @@ pub fn sys_tee_scn_mask_cancellation(old_mask: *mut c_uint) -> TeeResult {
     Ok(())
 }
 
-fn tee_ta_session_is_cancelled(ctx: &TeeSessionCtx, curr_time: Option<&TeeTime>) -> bool {
-    if ctx.cancel_mask {
+pub(crate) fn tee_ta_session_is_cancelled(
+    ctx: &TeeSessionCtx,
+    curr_time: Option<&TeeTime>,
+) -> bool {
+    if ctx.cancel.is_masked() {
         return false;
     }
 
-    if ctx.cancel {
+    if ctx.cancel.is_cancelled() {
         return true;
     }
 
@@ -104,3 +190,105 @@ fn tee_time_get_sys_time() -> TeeTime {
         millis: systiem.subsec_millis(),
     }
 }
+
+#[cfg(feature = "tee_test")]
+pub mod tests_tee_cancel {
+    use unittest::{
+        test_fn, test_framework::TestDescriptor, test_framework_basic::TestResult, tests_name,
+    };
+
+    use super::*;
+
+    /// Upper bound on the time between a cancellation and the end of the
+    /// sleep it interrupts.
+    const CANCEL_LATENCY: Duration = Duration::from_millis(200);
+    const CANCEL_AFTER: Duration = Duration::from_millis(20);
+
+    /// Runs `f` on another task after `CANCEL_AFTER`.
+    fn later(f: impl FnOnce() + Send + 'static) -> ktask::KtaskRef {
+        ktask::spawn(move || {
+            ktask::sleep(CANCEL_AFTER);
+            f();
+        })
+    }
+
+    test_fn! {
+        using TestResult;
+
+        fn test_cancel_wait_mid_sleep() {
+            let cancel = Arc::new(TeeCancel::default());
+            let waker = later({
+                let cancel = cancel.clone();
+                move || cancel.request()
+            });
+
+            let start = wall_time();
+            assert_eq!(cancel.wait_timeout(Duration::from_secs(5)), Err(TEE_ERROR_CANCEL));
+            let elapsed = wall_time() - start;
+            assert!(elapsed >= CANCEL_AFTER);
+            assert!(elapsed < CANCEL_AFTER + CANCEL_LATENCY);
+            waker.join();
+
+            // A pending cancellation ends the next sleep right away.
+            assert_eq!(cancel.wait_timeout(Duration::from_secs(5)), Err(TEE_ERROR_CANCEL));
+            cancel.clear();
+            assert_eq!(cancel.wait_timeout(Duration::from_millis(1)), Ok(()));
+        }
+    }
+
+    test_fn! {
+        using TestResult;
+
+        fn test_cancel_masked_then_unmasked() {
+            let cancel = Arc::new(TeeCancel::default());
+            assert!(!cancel.set_masked(true));
+            cancel.request();
+            assert!(!cancel.is_cancelled());
+
+            // A masked cancellation does not interrupt the sleep.
+            assert_eq!(cancel.wait_timeout(CANCEL_AFTER), Ok(()));
+
+            // Unmasking from another task wakes the sleeper.
+            let waker = later({
+                let cancel = cancel.clone();
+                move || {
+                    cancel.set_masked(false);
+                }
+            });
+            let start = wall_time();
+            assert_eq!(cancel.wait_timeout(Duration::from_secs(5)), Err(TEE_ERROR_CANCEL));
+            assert!(wall_time() - start < CANCEL_AFTER + CANCEL_LATENCY);
+            waker.join();
+            assert!(cancel.is_cancelled());
+        }
+    }
+
+    test_fn! {
+        using TestResult;
+
+        fn test_cancel_wait_condition() {
+            let cancel = Arc::new(TeeCancel::default());
+            let done = Arc::new(AtomicBool::new(false));
+            let waker = later({
+                let (cancel, done) = (cancel.clone(), done.clone());
+                move || {
+                    done.store(true, Ordering::Release);
+                    cancel.notify();
+                }
+            });
+            let res = cancel.wait_timeout_until(Duration::from_secs(5), || {
+                done.load(Ordering::Acquire)
+            });
+            assert_eq!(res, Ok(false));
+            waker.join();
+        }
+    }
+
+    tests_name! {
+        TEST_TEE_CANCEL;
+        tee_cancel;
+        test_cancel_wait_mid_sleep,
+        test_cancel_masked_then_unmasked,
+        test_cancel_wait_condition,
+    }
+}
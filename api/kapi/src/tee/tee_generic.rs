@@ -9,6 +9,7 @@ use bincode::config;
 use kcore::task::AsThread;
 use knet::{
     SendOptions, SocketAddrEx, SocketOps,
+    options::UnixCredentials,
     unix::{StreamTransport, UnixAddr, UnixDomainSocket},
 };
 use ktask::current;
@@ -42,9 +43,9 @@ pub fn sys_tee_scn_log(buf: *const c_char, len: usize) -> TeeResult {
 /// Panic the current TEE application
 pub fn sys_tee_scn_panic(panic_code: u32) -> TeeResult {
     // Connect to current TA via Unix socket
-    let socket = UnixDomainSocket::new(StreamTransport::new(
+    let socket = UnixDomainSocket::new(StreamTransport::new(UnixCredentials::new(
         current().as_thread().proc_data.proc.pid(),
-    ));
+    )));
     let uuid = with_tee_ta_ctx(|ctx| Ok(ctx.uuid.clone()))?;
     let path = format!("/tmp/{}.sock", uuid);
     let remote_addr = SocketAddrEx::Unix(UnixAddr::Path(path.into()));
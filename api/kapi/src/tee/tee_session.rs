@@ -17,8 +17,9 @@ use slab::Slab;
 use tee_raw_sys::*;
 
 use crate::tee::{
-    TeeResult, tee_obj::tee_obj, tee_svc_cryp2::TeeCrypState, tee_svc_storage::tee_storage_enum,
-    tee_ta_manager::SessionIdentity, user_ta::user_ta_ctx, uuid::Uuid,
+    TeeResult, tee_cancel::TeeCancel, tee_obj::tee_obj, tee_svc_cryp2::TeeCrypState,
+    tee_svc_storage::tee_storage_enum, tee_ta_manager::SessionIdentity, user_ta::user_ta_ctx,
+    uuid::Uuid,
 };
 
 scope_local::scope_local! {
@@ -38,8 +39,7 @@ scope_local::scope_local! {
 /// This structure is attached to each thread handling a client session
 pub struct TeeSessionCtx {
     pub clnt_id: TEE_Identity,
-    pub cancel: bool,
-    pub cancel_mask: bool,
+    pub cancel: Arc<TeeCancel>,
     pub cancel_time: TeeTime,
     pub objects: Slab<Arc<Mutex<tee_obj>>>,
     pub storage_enums: Slab<Arc<Mutex<tee_storage_enum>>>,
@@ -68,8 +68,7 @@ impl Default for TeeSessionCtx {
                     clockSeqAndNode: [0; 8],
                 },
             },
-            cancel: false,
-            cancel_mask: false,
+            cancel: Arc::default(),
             cancel_time: TeeTime {
                 seconds: 0,
                 millis: 0,
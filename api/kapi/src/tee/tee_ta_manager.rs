@@ -8,6 +8,7 @@ use bincode::config;
 use kcore::task::AsThread;
 use knet::{
     RecvOptions, SendOptions, SocketAddrEx, SocketOps,
+    options::UnixCredentials,
     unix::{StreamTransport, UnixAddr, UnixDomainSocket},
 };
 use ktask::current;
@@ -27,9 +28,9 @@ pub struct SessionIdentity {
 
 pub fn tee_ta_init_session(uuid: String) -> TeeResult<u32> {
     // Connect to dest TA via Unix socket
-    let socket = UnixDomainSocket::new(StreamTransport::new(
+    let socket = UnixDomainSocket::new(StreamTransport::new(UnixCredentials::new(
         current().as_thread().proc_data.proc.pid(),
-    ));
+    )));
     let path = format!("/tmp/{}.sock", uuid);
     let remote_addr = SocketAddrEx::Unix(UnixAddr::Path(path.into()));
     socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
@@ -74,9 +75,9 @@ pub fn tee_ta_init_session(uuid: String) -> TeeResult<u32> {
 
 pub fn tee_ta_close_session(sess_id: SessionIdentity) -> TeeResult {
     // Connect to dest TA via Unix socket
-    let socket = UnixDomainSocket::new(StreamTransport::new(
+    let socket = UnixDomainSocket::new(StreamTransport::new(UnixCredentials::new(
         current().as_thread().proc_data.proc.pid(),
-    ));
+    )));
     let path = format!("/tmp/{}.sock", sess_id.uuid);
     let remote_addr = SocketAddrEx::Unix(UnixAddr::Path(path.into()));
     socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
@@ -103,9 +104,9 @@ pub fn tee_ta_invoke_command(
     _usr_param: *mut utee_params,
 ) -> TeeResult {
     // Connect to dest TA via Unix socket
-    let socket = UnixDomainSocket::new(StreamTransport::new(
+    let socket = UnixDomainSocket::new(StreamTransport::new(UnixCredentials::new(
         current().as_thread().proc_data.proc.pid(),
-    ));
+    )));
     let path = format!("/tmp/{}.sock", sess_id.uuid);
     let remote_addr = SocketAddrEx::Unix(UnixAddr::Path(path.into()));
     socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
@@ -3,6 +3,7 @@
 // See LICENSES for license details.
 
 use alloc::vec;
+use core::time::Duration;
 
 use khal::time::{TimeValue, wall_time};
 use tee_raw_sys::{
@@ -11,6 +12,7 @@ use tee_raw_sys::{
 
 use crate::tee::{
     TeeResult,
+    tee_cancel::tee_session_cancel,
     tee_session::{with_tee_session_ctx, with_tee_session_ctx_mut},
     user_access::{copy_from_user_struct, copy_to_user_struct},
 };
@@ -263,29 +265,15 @@ pub fn tee_time_set_ta_time(uuid: &TEE_UUID, time: &TeeTime) -> TeeResult {
 
 // Busy wait function
 pub fn tee_time_busy_wait(milliseconds_delay: u32) -> TeeResult {
-    let start_time = tee_time_get_sys_time();
-    let delay_seconds = milliseconds_delay / 1000;
-    let delay_millis = milliseconds_delay % 1000;
-
-    let delay_duration = TimeValue::new(delay_seconds as u64, delay_millis * 1_000_000);
-
-    let end_time = TimeValue::from_nanos(
-        (start_time.as_nanos() + delay_duration.as_nanos())
-            .try_into()
-            .unwrap_or(u64::MAX),
-    );
-
-    loop {
-        let current_time = tee_time_get_sys_time();
-        if current_time.as_nanos() >= end_time.as_nanos() {
-            break Ok(());
-        }
-        // Can add brief CPU yield here to avoid excessive CPU usage
-        core::hint::spin_loop();
-    }
+    khal::delay::mdelay(milliseconds_delay as u64);
+    Ok(())
 }
 
 /// Wait for a specified number of milliseconds
+///
+/// The wait is cancellable: it ends early with `TEE_ERROR_CANCEL` when the
+/// session is cancelled and cancellation is not masked.
 pub fn sys_tee_scn_wait(milliseconds_delay: u32) -> TeeResult {
-    tee_time_busy_wait(milliseconds_delay)
+    let cancel = tee_session_cancel()?;
+    cancel.wait_timeout(Duration::from_millis(milliseconds_delay as u64))
 }
@@ -18,7 +18,8 @@ use crate::tee::{
     fs_htree_tests::tests_fs_htree_tests::TEST_FS_HTREE_TESTS,
     huk_subkey::tests_huk_subkey::TEST_HUK_SUBKEY_DERIVE,
     libmbedtls::bignum::tests_tee_bignum::TEST_TEE_BIGNUM,
-    rng_software::tests_rng_software::TEST_RNG_SOFTWARE, tee_misc::tests_tee_misc::TEST_TEE_MISC,
+    rng_software::tests_rng_software::TEST_RNG_SOFTWARE,
+    tee_cancel::tests_tee_cancel::TEST_TEE_CANCEL, tee_misc::tests_tee_misc::TEST_TEE_MISC,
     tee_obj::tests_tee_obj::TEST_TEE_OBJ, tee_pobj::tests_tee_pobj::TEST_TEE_POBJ,
     tee_ree_fs::tests_tee_ree_fs::TEST_TEE_REE_FS,
     tee_session::tests_tee_session::TEST_TEE_SESSION,
@@ -43,6 +44,7 @@ pub fn tee_unit_test() {
             TEST_USER_ACCESS,
             TEST_TEE_BIGNUM,
             TEST_TEE_SESSION,
+            TEST_TEE_CANCEL,
             TEST_FILE_OPS,
             TEST_TEE_FS_DIRFILE,
             TEST_TEE_UTILS,
@@ -278,7 +278,9 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
                         }
                     },
                     "tty-reader".into(),
-                );
+                )
+                // Idle until there is input, possibly forever.
+                .set_hung_check_exempt(true);
                 Processor::External(poll_rx)
             }
             ProcessMode::None(poll_rx) => {
@@ -12,6 +12,7 @@ use kspin::SpinNoPreempt;
 
 pub mod job;
 pub mod ldisc;
+pub mod output;
 pub mod termios;
 
 #[repr(C)]
@@ -0,0 +1,383 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Output hygiene for writes from userspace to a terminal.
+//!
+//! A terminal can filter the escape sequences written by processes and limit
+//! the rate of their output. Kernel messages go to the console directly and
+//! are never subject to either.
+
+use alloc::{format, vec::Vec};
+use core::time::Duration;
+
+use bytemuck::AnyBitPattern;
+use kerrno::{KError, KResult};
+
+/// `_IOW('T', 0xe0, struct OutputConfig)`: sets the output settings.
+pub const TIOCSOUTPUT: u32 = 0x4010_54e0;
+/// `_IOR('T', 0xe1, struct OutputConfig)`: gets the output settings.
+pub const TIOCGOUTPUT: u32 = 0x8010_54e1;
+
+/// Longest CSI sequence kept by [`OutputFilter::BasicSgr`].
+const MAX_CSI_LEN: usize = 32;
+
+/// Escape sequence filtering applied to output from userspace.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFilter {
+    /// Output is written as is.
+    #[default]
+    Off               = 0,
+    /// Escape sequences and control characters other than `\t`, `\n`, `\r`
+    /// and backspace are removed.
+    StripNonPrintable = 1,
+    /// As [`OutputFilter::StripNonPrintable`], but SGR sequences setting
+    /// colors and attributes are kept.
+    BasicSgr          = 2,
+}
+
+/// What happens to output beyond the rate limit.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// The writer blocks until the limit lets the output through.
+    #[default]
+    Block = 0,
+    /// The output is dropped, and a line saying how much was dropped is
+    /// written once the limit lets output through again.
+    Drop  = 1,
+}
+
+/// Output settings of a terminal, as passed to [`TIOCSOUTPUT`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, AnyBitPattern)]
+pub struct OutputConfig {
+    /// An [`OutputFilter`].
+    pub filter: u32,
+    /// An [`Overflow`].
+    pub overflow: u32,
+    /// Sustained output rate in bytes per second, 0 for no limit.
+    pub rate: u32,
+    /// Bytes that can be written at once after a pause, never less than
+    /// `rate`.
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum EscState {
+    #[default]
+    Ground,
+    /// After ESC.
+    Esc,
+    /// In a control sequence, after ESC `[`.
+    Csi,
+    /// In a control string (OSC, DCS, SOS, PM or APC), up to BEL or ST.
+    Str,
+    /// After ESC in a control string.
+    StrEsc,
+}
+
+/// Stateful escape sequence filter.
+///
+/// Sequences split across writes are recognized, since the state is kept from
+/// one write to the next.
+#[derive(Default)]
+pub struct EscapeFilter {
+    state: EscState,
+    csi: Vec<u8>,
+}
+
+impl EscapeFilter {
+    /// Filters `input` according to `mode`, appending the output to `out`.
+    pub fn filter(&mut self, mode: OutputFilter, input: &[u8], out: &mut Vec<u8>) {
+        if mode == OutputFilter::Off {
+            out.extend_from_slice(input);
+            return;
+        }
+        for &ch in input {
+            self.push(mode, ch, out);
+        }
+    }
+
+    fn push(&mut self, mode: OutputFilter, ch: u8, out: &mut Vec<u8>) {
+        match self.state {
+            EscState::Ground => match ch {
+                0x1b => self.state = EscState::Esc,
+                b'\t' | b'\n' | b'\r' | 0x08 => out.push(ch),
+                0..0x20 | 0x7f => {}
+                _ => out.push(ch),
+            },
+            EscState::Esc => match ch {
+                b'[' => {
+                    self.csi.clear();
+                    self.state = EscState::Csi;
+                }
+                b']' | b'P' | b'X' | b'^' | b'_' => self.state = EscState::Str,
+                // Intermediate bytes, as in ESC ( B.
+                0x20..0x30 => {}
+                0x1b => {}
+                _ => self.state = EscState::Ground,
+            },
+            EscState::Csi => match ch {
+                // Parameters past the limit only need to be known to be there.
+                0x20..0x40 if self.csi.len() <= MAX_CSI_LEN => self.csi.push(ch),
+                0x20..0x40 => {}
+                0x40..0x7f => {
+                    if mode == OutputFilter::BasicSgr && ch == b'm' && is_basic_sgr(&self.csi) {
+                        out.extend_from_slice(b"\x1b[");
+                        out.extend_from_slice(&self.csi);
+                        out.push(b'm');
+                    }
+                    self.state = EscState::Ground;
+                }
+                0x1b => self.state = EscState::Esc,
+                // Other control characters in a sequence are dropped.
+                _ => {}
+            },
+            EscState::Str => match ch {
+                0x07 => self.state = EscState::Ground,
+                0x1b => self.state = EscState::StrEsc,
+                _ => {}
+            },
+            EscState::StrEsc => {
+                // Any escape ends the string; ESC \ is just the usual one.
+                self.state = EscState::Esc;
+                if ch == b'\\' {
+                    self.state = EscState::Ground;
+                } else {
+                    self.push(mode, ch, out);
+                }
+            }
+        }
+    }
+}
+
+fn is_basic_sgr(params: &[u8]) -> bool {
+    params.len() <= MAX_CSI_LEN && params.iter().all(|&b| b.is_ascii_digit() || b == b';')
+}
+
+/// Token bucket limiting a byte rate.
+struct TokenBucket {
+    rate: u64,
+    burst: u64,
+    tokens: u64,
+    last: Duration,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: u64, now: Duration) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Duration) {
+        let elapsed = now.saturating_sub(self.last).as_nanos();
+        let new = (elapsed * self.rate as u128 / 1_000_000_000) as u64;
+        if self.tokens + new >= self.burst {
+            self.tokens = self.burst;
+            self.last = now;
+        } else if new > 0 {
+            self.tokens += new;
+            // Keep the time worth less than a token for the next refill.
+            self.last += Duration::from_nanos(new * 1_000_000_000 / self.rate);
+        }
+    }
+
+    /// Takes up to `want` tokens, returning how many were taken.
+    fn take(&mut self, want: usize, now: Duration) -> usize {
+        self.refill(now);
+        let taken = self.tokens.min(want as u64);
+        self.tokens -= taken;
+        taken as usize
+    }
+
+    /// Time until the next token.
+    fn next_token(&self, now: Duration) -> Duration {
+        let next = self.last + Duration::from_nanos(1_000_000_000u64.div_ceil(self.rate));
+        next.saturating_sub(now).max(Duration::from_nanos(1))
+    }
+}
+
+/// What to do with pending output.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admit {
+    /// Write that many bytes, after a line saying how many bytes were dropped
+    /// before, if any.
+    Write(usize, Option<usize>),
+    /// Wait before trying again.
+    Wait(Duration),
+    /// Drop the output.
+    Drop,
+}
+
+/// Output hygiene state of a terminal.
+#[derive(Default)]
+pub struct OutputControl {
+    filter: OutputFilter,
+    overflow: Overflow,
+    config: OutputConfig,
+    bucket: Option<TokenBucket>,
+    escape: EscapeFilter,
+    dropped: usize,
+}
+
+impl OutputControl {
+    /// Returns the current settings.
+    pub fn config(&self) -> OutputConfig {
+        self.config
+    }
+
+    /// Changes the settings, resetting the rate limit.
+    pub fn set_config(&mut self, config: OutputConfig, now: Duration) -> KResult<()> {
+        let filter = match config.filter {
+            0 => OutputFilter::Off,
+            1 => OutputFilter::StripNonPrintable,
+            2 => OutputFilter::BasicSgr,
+            _ => return Err(KError::InvalidInput),
+        };
+        let overflow = match config.overflow {
+            0 => Overflow::Block,
+            1 => Overflow::Drop,
+            _ => return Err(KError::InvalidInput),
+        };
+        self.filter = filter;
+        self.overflow = overflow;
+        self.config = config;
+        self.bucket = (config.rate != 0).then(|| {
+            let burst = config.burst.max(config.rate);
+            TokenBucket::new(config.rate as u64, burst as u64, now)
+        });
+        Ok(())
+    }
+
+    /// Filters output from userspace, appending what should be written to
+    /// `out`.
+    pub fn filter(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.escape.filter(self.filter, input, out);
+    }
+
+    /// Decides what to do with `len` bytes of pending output.
+    pub fn admit(&mut self, len: usize, now: Duration) -> Admit {
+        let Some(bucket) = &mut self.bucket else {
+            return Admit::Write(len, None);
+        };
+        let n = bucket.take(len, now);
+        if n > 0 {
+            let dropped = core::mem::take(&mut self.dropped);
+            return Admit::Write(n, (dropped > 0).then_some(dropped));
+        }
+        match self.overflow {
+            Overflow::Block => Admit::Wait(bucket.next_token(now)),
+            Overflow::Drop => {
+                self.dropped += len;
+                Admit::Drop
+            }
+        }
+    }
+}
+
+/// The line written in place of dropped output.
+pub fn dropped_notice(dropped: usize) -> Vec<u8> {
+    format!("\r\n... {dropped} bytes dropped ...\r\n").into_bytes()
+}
+
+#[cfg(unittest)]
+mod output_tests {
+    use unittest::def_test;
+
+    use super::*;
+
+    fn filter_all(mode: OutputFilter, writes: &[&[u8]]) -> Vec<u8> {
+        let mut filter = EscapeFilter::default();
+        let mut out = Vec::new();
+        for input in writes {
+            filter.filter(mode, input, &mut out);
+        }
+        out
+    }
+
+    #[def_test]
+    fn test_output_filter_modes() {
+        let input: &[u8] = b"a\x1b]0;title\x07b\x1b[2Jc\x1b[1;31md\x1b[0m\x00\x7f\te\n";
+        assert_eq!(filter_all(OutputFilter::Off, &[input]), input);
+        assert_eq!(
+            filter_all(OutputFilter::StripNonPrintable, &[input]),
+            b"abcd\te\n"
+        );
+        assert_eq!(
+            filter_all(OutputFilter::BasicSgr, &[input]),
+            b"abc\x1b[1;31md\x1b[0m\te\n"
+        );
+
+        // Private modes, cursor moves and overlong parameters are not SGR.
+        let input: &[u8] = b"\x1b[?25l\x1b[10;10Hx\x1b[38;5;1m";
+        assert_eq!(
+            filter_all(OutputFilter::BasicSgr, &[input]),
+            b"x\x1b[38;5;1m"
+        );
+        let mut long = b"\x1b[".to_vec();
+        long.extend_from_slice(&[b'1'; MAX_CSI_LEN + 1]);
+        long.push(b'm');
+        assert_eq!(filter_all(OutputFilter::BasicSgr, &[&long]), b"");
+    }
+
+    #[def_test]
+    fn test_output_filter_split_writes() {
+        let input: &[u8] = b"x\x1b]2;evil\x1b\\y\x1b[31mz\x1bP1$r\x1b\\w";
+        let whole = filter_all(OutputFilter::BasicSgr, &[input]);
+        assert_eq!(whole, b"xy\x1b[31mzw");
+        for split in 0..input.len() {
+            let (a, b) = input.split_at(split);
+            assert_eq!(filter_all(OutputFilter::BasicSgr, &[a, b]), whole);
+        }
+        let bytes = input.iter().map(core::slice::from_ref).collect::<Vec<_>>();
+        assert_eq!(filter_all(OutputFilter::BasicSgr, &bytes), whole);
+    }
+
+    #[def_test]
+    fn test_output_rate_limit() {
+        let config = |overflow| OutputConfig {
+            filter: 0,
+            overflow,
+            rate: 1000,
+            burst: 4000,
+        };
+        let at = Duration::from_millis;
+
+        let mut ctl = OutputControl::default();
+        ctl.set_config(config(Overflow::Block as u32), at(0))
+            .unwrap();
+        assert_eq!(ctl.admit(10000, at(0)), Admit::Write(4000, None));
+        assert_eq!(ctl.admit(6000, at(0)), Admit::Wait(at(1)));
+        assert_eq!(ctl.admit(6000, at(1500)), Admit::Write(1500, None));
+        // Never more than the burst, however long the pause.
+        assert_eq!(ctl.admit(6000, at(100_000)), Admit::Write(4000, None));
+
+        let mut ctl = OutputControl::default();
+        ctl.set_config(config(Overflow::Drop as u32), at(0))
+            .unwrap();
+        assert_eq!(ctl.admit(4000, at(0)), Admit::Write(4000, None));
+        assert_eq!(ctl.admit(300, at(0)), Admit::Drop);
+        assert_eq!(ctl.admit(200, at(0)), Admit::Drop);
+        assert_eq!(ctl.admit(100, at(100)), Admit::Write(100, Some(500)));
+        assert_eq!(ctl.admit(100, at(100)), Admit::Drop);
+
+        assert!(
+            ctl.set_config(
+                OutputConfig {
+                    filter: 3,
+                    ..config(0)
+                },
+                at(0)
+            )
+            .is_err()
+        );
+        ctl.set_config(OutputConfig::default(), at(0)).unwrap();
+        assert_eq!(ctl.admit(1 << 20, at(0)), Admit::Write(1 << 20, None));
+    }
+}
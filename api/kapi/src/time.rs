@@ -2,9 +2,7 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-//! Time conversion helpers and interrupt accounting.
-
-use core::sync::atomic::{AtomicUsize, Ordering};
+//! Time conversion helpers.
 
 use kerrno::{KError, KResult};
 use khal::time::TimeValue;
@@ -142,15 +140,3 @@ impl TimeValueLike for __kernel_sock_timeval {
         ))
     }
 }
-
-static IRQ_CNT: AtomicUsize = AtomicUsize::new(0);
-
-/// Increment the interrupt count.
-pub(crate) fn inc_irq_cnt() {
-    IRQ_CNT.fetch_add(1, Ordering::Relaxed);
-}
-
-/// Get the current interrupt count.
-pub(crate) fn irq_cnt() -> usize {
-    IRQ_CNT.load(Ordering::Relaxed)
-}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! The flight recorder writer, appending recorded events to trace files.
+//!
+//! Recording is enabled by `flightrec=<path>[,size=<bytes>][,files=<count>]`
+//! on the kernel command line, see [`flightrec::Config`]. The files are
+//! `<path>.0`, `<path>.1`, ... written through the VFS by a writer task of
+//! the lowest priority.
+
+use alloc::{format, string::String, vec::Vec};
+
+use flightrec::{ClockParams, Config, Event, Record, TraceSink, TraceWriter};
+use kerrno::{KError, KResult};
+use kfs::{File, ROOT_FS_CONTEXT};
+use linux_sysno::Sysno;
+
+/// `prctl` option recording a marker: `arg2` is a value and `arg3` a
+/// NUL-terminated text, cut to [`flightrec::MARKER_TEXT_LEN`] bytes, or
+/// null.
+pub const PR_SET_TRACE_MARKER: u32 = 0x5846_5201;
+
+/// Trace files of a filesystem.
+struct FileSink {
+    path: String,
+    file: Option<File>,
+    offset: u64,
+}
+
+impl TraceSink for FileSink {
+    fn start_file(&mut self, slot: u32) -> KResult<()> {
+        let context = ROOT_FS_CONTEXT.get().ok_or(KError::NotFound)?;
+        self.file = Some(File::create(context, format!("{}.{slot}", self.path))?);
+        self.offset = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> KResult<()> {
+        let file = self.file.as_ref().ok_or(KError::BadState)?;
+        while !data.is_empty() {
+            let written = file.write_at(data, self.offset)?;
+            if written == 0 {
+                return Err(KError::WriteZero);
+            }
+            self.offset += written as u64;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
+    fn clock(&self) -> ClockParams {
+        ClockParams {
+            resolution_ns: khal::time::resolution_nanos(),
+            monotonic_ns: khal::time::monotonic_time_nanos(),
+            realtime_ns: khal::time::realtime_nanos(),
+        }
+    }
+}
+
+/// Starts the flight recorder if the kernel command line asks for it.
+pub fn start_flight_recorder() {
+    let cmdline = khal::dtb::get_chosen_bootargs().unwrap_or("");
+    let Some(config) = Config::from_cmdline(cmdline) else {
+        return;
+    };
+    info!(
+        "Flight recorder: {} files of {} bytes at {}",
+        config.file_count, config.file_size, config.path
+    );
+
+    let syscalls = Sysno::iter()
+        .map(|sysno| (sysno.id() as u32, sysno.name()))
+        .collect();
+    let sink = FileSink {
+        path: config.path.into(),
+        file: None,
+        offset: 0,
+    };
+    let mut writer = TraceWriter::new(sink, config.file_size, config.file_count, syscalls);
+    let task = ktask::spawn_with_name(
+        move || {
+            let mut batch = Vec::new();
+            loop {
+                batch.clear();
+                flightrec::drain(&mut |record: &Record| batch.push(*record));
+                if let Err(err) = writer.write(&batch) {
+                    warn!("Flight recorder stopped: {err:?}");
+                    flightrec::set_recording(false);
+                    return;
+                }
+                ktask::sleep(flightrec::WRITER_PERIOD);
+            }
+        },
+        "flightrec".into(),
+    );
+    ktask::set_nice(&task, ktask::MAX_NICE as i32);
+    flightrec::set_recording(true);
+}
+
+/// Records a marker of the current task.
+pub fn record_marker(value: u64, text: &[u8]) {
+    flightrec::record(ktask::current().id().as_u64(), Event::marker(value, text));
+}
@@ -135,18 +135,28 @@ fn get_process_hash() -> KResult<Vec<u8>> {
     use alloc::format;
 
     use kcore::task::AsThread;
-    use kfs::FS_CONTEXT;
+    use kfs::{COPY_BUF_SIZE, FS_CONTEXT, StreamMode};
+    use kio::Read;
     use ktask::current;
     use mbedtls::hash::{Md, Type};
 
     let pid = current().as_thread().proc_data.proc.pid();
     let proc_exe_path = format!("/proc/{}/exe", pid);
-    let fs = FS_CONTEXT.lock();
-    let data = fs.read(proc_exe_path).unwrap();
+    let mut exe = FS_CONTEXT
+        .lock()
+        .open_stream(proc_exe_path, StreamMode::Read)?;
 
     let mut sm3_result = vec![0u8; 32];
     let mut ctx = Md::new(Type::SM3).map_err(|_| KError::InvalidInput)?;
-    ctx.update(&data).map_err(|_| KError::InvalidInput)?;
+    // Hash the executable a chunk at a time rather than reading it whole.
+    let mut buf = vec![0u8; COPY_BUF_SIZE];
+    loop {
+        let n = exe.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.update(&buf[..n]).map_err(|_| KError::InvalidInput)?;
+    }
     let _len = ctx.finish(&mut sm3_result);
 
     info!("resm3_resultsult: {:x?}", sm3_result);
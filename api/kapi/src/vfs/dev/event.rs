@@ -2,60 +2,145 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use alloc::{format, sync::Arc};
-use core::{any::Any, task::Context, time::Duration};
+use alloc::{format, sync::Arc, vec::Vec};
+use core::{any::Any, iter, ptr::NonNull, task::Context, time::Duration};
 
 use bitmaps::Bitmap;
 use fs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
-use kcore::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
+use kalloc::GlobalPage;
+use kcore::{
+    task::{AsThread, get_process_data},
+    vfs::{DeviceMmap, DeviceOps, DirMapping, SimpleFs},
+};
 #[allow(unused_imports)]
 use kdriver::prelude::{
     DriverError, DriverOps, Event, EventType, InputDevice, InputDeviceId, InputDriverOps,
+    InputEventQueue, Retryability, RingProducer, ring_size,
 };
 use kerrno::{KError, KResult};
-use khal::time::wall_time;
-use kpoll::{IoEvents, Pollable};
+use khal::{mem::v2p, time::wall_time};
+use kpoll::{IoEvents, PollSet, Pollable};
 use ksync::Mutex;
+use ktask::current;
 use linux_raw_sys::{
     general::{__kernel_old_time_t, __kernel_suseconds_t},
     ioctl::{EVIOCGID, EVIOCGRAB, EVIOCGVERSION},
 };
+use memaddr::PAGE_SIZE_4K;
+use memspace::backend::SharedPages;
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
 use crate::mm::UserPtr;
 const KEY_CNT: usize = EventType::Key.bits_count();
 
-struct Inner {
-    device: InputDevice,
-    read_ahead: Option<(Duration, Event)>,
-    key_state: Bitmap<KEY_CNT>,
+/// `SYN_DROPPED`, telling the reader that events were lost.
+const SYN_DROPPED: u16 = 3;
+
+/// `_IOWR('E', 0xa0, struct evdev_ring_setup)`: delivers the events of the
+/// device through a ring shared with the caller instead of `read()`.
+const EVIOCRINGSETUP: u32 = 0xc010_45a0;
+/// `_IO('E', 0xa1)`: goes back from the ring to `read()`.
+const EVIOCRINGFREE: u32 = 0x45a1;
+
+/// Largest number of slots of a ring.
+const MAX_RING_ENTRIES: u32 = 1 << 14;
+
+/// How often events are moved from the device into an attached ring.
+const RING_PUMP_PERIOD: Duration = Duration::from_millis(1);
+
+/// Argument of [`EVIOCRINGSETUP`].
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EvdevRingSetup {
+    /// Number of slots, a power of two.
+    entries: u32,
+    /// Must be zero.
+    flags: u32,
+    /// Set to the size of the ring, to be mapped at offset 0.
+    size: u64,
 }
-impl Inner {
-    fn has_event(&mut self) -> bool {
-        if self.read_ahead.is_none() {
-            match self.device.read_event() {
-                Ok(event) => {
-                    if event.event_type == EventType::Key as u16 {
-                        if event.value == 0 {
-                            self.key_state.set(event.code as usize, false);
-                        } else if event.value == 1 {
-                            self.key_state.set(event.code as usize, true);
-                        }
-                    }
-                    self.read_ahead = Some((wall_time(), event));
-                }
-                Err(DriverError::WouldBlock) => {}
-                Err(err) => {
-                    warn!("Failed to read event: {err:?}");
-                }
-            }
+
+/// Ring through which the events of a device are delivered instead of
+/// `read()`, see [`EVIOCRINGSETUP`].
+///
+/// The ring holds `input_event` records, exactly as `read()` returns them.
+/// User space maps it shared, consumes records by advancing `tail`, and
+/// polls the device to wait for the ring to become non-empty. A full ring
+/// drops new events and then delivers `SYN_DROPPED`, after which the reader
+/// should resynchronize its state with the `EVIOCG*` ioctls.
+struct EventRing {
+    producer: Mutex<RingProducer<InputEvent>>,
+    pages: Arc<SharedPages>,
+    /// Process that attached the ring.
+    owner: u64,
+    poll: PollSet,
+}
+
+impl EventRing {
+    fn new(entries: u32, size: usize, owner: u64) -> KResult<Self> {
+        let mut memory = GlobalPage::alloc_contiguous(size / PAGE_SIZE_4K, PAGE_SIZE_4K)?;
+        memory.zero();
+        let mem = NonNull::new(memory.as_mut_ptr()).ok_or(KError::NoMemory)?;
+        // SAFETY: the memory is page aligned, `size` bytes long, and owned by
+        // `pages`, which outlives the producer.
+        let producer = unsafe { RingProducer::new(mem, entries) }.ok_or(KError::InvalidInput)?;
+        let start = v2p(memory.start_va());
+        let phys: Vec<_> = (0..size / PAGE_SIZE_4K)
+            .map(|page| start + page * PAGE_SIZE_4K)
+            .collect();
+        Ok(Self {
+            producer: Mutex::new(producer),
+            // Mappings keep the memory alive after the ring goes away, so a
+            // revoked ring never faults its reader.
+            pages: Arc::new(SharedPages::from_owned(phys, Arc::new(memory))),
+            owner,
+            poll: PollSet::new(),
+        })
+    }
+
+    /// Pushes `events` as one batch, waking up the reader if the ring was
+    /// empty.
+    fn fill(&self, events: impl Iterator<Item = (Duration, Event)>) {
+        let mut producer = self.producer.lock();
+        for (time, event) in events {
+            let dropped = || {
+                input_event(
+                    time,
+                    Event {
+                        event_type: EventType::Synchronization as u16,
+                        code: SYN_DROPPED,
+                        value: 0,
+                    },
+                )
+            };
+            producer.push(input_event(time, event), dropped);
+        }
+        if producer.publish() {
+            self.poll.wake();
         }
-        self.read_ahead.is_some()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.producer.lock().has_pending()
+    }
+
+    fn revoke(&self) {
+        self.producer.lock().revoke();
+        self.poll.wake();
     }
 }
 
+struct Inner {
+    read_ahead: Option<(Duration, Event)>,
+    key_state: Bitmap<KEY_CNT>,
+    ring: Option<Arc<EventRing>>,
+    /// The driver reported the device gone.
+    lost: bool,
+}
+
 pub struct EventDev {
-    inner: Mutex<Inner>,
+    queue: Arc<InputEventQueue<InputDevice>>,
+    inner: Arc<Mutex<Inner>>,
     ev_bits: Bitmap<{ EventType::COUNT as usize }>,
 }
 
@@ -74,35 +159,89 @@ impl EventDev {
             }
         }
 
-        // let mut out = [0u8; 2000];
-        // if device.get_event_bits(EventType::Absolute, &mut out).unwrap() {
-        //     let mut bits = Vec::new();
-        //     for i in 0..EventType::Absolute.bits_count() {
-        //         if (out[i / 8] >> (i % 8)) & 1 != 0 {
-        //             bits.push(i);
-        //         }
-        //     }
-        //     warn!("{bits:?}");
-        // } else {
-        //     warn!("failure");
-        // }
         Self {
-            inner: Mutex::new(Inner {
-                device,
+            queue: Arc::new(InputEventQueue::new(device)),
+            inner: Arc::new(Mutex::new(Inner {
                 read_ahead: None,
                 key_state: Bitmap::new(),
-            }),
+                ring: None,
+                lost: false,
+            })),
             ev_bits,
         }
     }
 
+    fn has_event(&self, inner: &mut Inner) -> bool {
+        if inner.read_ahead.is_none() {
+            inner.read_ahead = fetch_event(&self.queue, inner);
+        }
+        inner.read_ahead.is_some()
+    }
+
+    fn setup_ring(&self, arg: usize) -> KResult<usize> {
+        let setup = UserPtr::<EvdevRingSetup>::from(arg).get_as_mut()?;
+        if setup.flags != 0 || !(2..=MAX_RING_ENTRIES).contains(&setup.entries) {
+            return Err(KError::InvalidInput);
+        }
+        let size = ring_size::<InputEvent>(setup.entries).ok_or(KError::InvalidInput)?;
+        let owner = current_owner();
+        self.queue
+            .check_access(owner)
+            .map_err(|_| KError::ResourceBusy)?;
+
+        let mut inner = self.inner.lock();
+        if inner.lost {
+            return Err(KError::NoSuchDevice);
+        }
+        if inner
+            .ring
+            .as_ref()
+            .is_some_and(|ring| owner_alive(ring.owner))
+        {
+            return Err(KError::ResourceBusy);
+        }
+        let ring = Arc::new(EventRing::new(setup.entries, size, owner)?);
+        // Whatever `read()` has not returned yet goes to the ring, so the
+        // switch neither loses nor repeats events.
+        let read_ahead = inner.read_ahead.take();
+        ring.fill(
+            read_ahead
+                .into_iter()
+                .chain(iter::from_fn(|| fetch_event(&self.queue, &mut inner))),
+        );
+        if inner.lost {
+            return Err(KError::NoSuchDevice);
+        }
+        if let Some(old) = inner.ring.replace(ring.clone()) {
+            old.revoke();
+        }
+        drop(inner);
+
+        let (queue, inner) = (self.queue.clone(), self.inner.clone());
+        ktask::spawn_with_name(move || pump_ring(queue, inner, ring), "evdev-ring".into());
+        setup.size = size as u64;
+        Ok(0)
+    }
+
+    fn free_ring(&self) -> KResult<usize> {
+        let mut inner = self.inner.lock();
+        let ring = inner.ring.as_ref().ok_or(KError::InvalidInput)?;
+        if ring.owner != current_owner() && owner_alive(ring.owner) {
+            return Err(KError::OperationNotPermitted);
+        }
+        // Records left in the ring are not delivered to `read()`.
+        ring.revoke();
+        inner.ring = None;
+        Ok(0)
+    }
+
     fn get_event_bits(&self, arg: usize, size: usize, ty: u8) -> KResult<usize> {
         let bits = UserPtr::<u8>::from(arg).get_as_mut_slice(size)?;
         if ty == 0 {
             Ok(copy_bytes(self.ev_bits.as_bytes(), bits))
         } else {
             let ty = EventType::from_repr(ty).ok_or(KError::InvalidInput)?;
-            match self.inner.lock().device.get_event_bits(ty, bits) {
+            match self.queue.with_device(|dev| dev.get_event_bits(ty, bits)) {
                 Ok(true) => {}
                 Ok(false) => {
                     debug!("No events for {ty:?}");
@@ -116,6 +255,72 @@ impl EventDev {
     }
 }
 
+/// Takes the next event of the device, keeping track of the key states.
+fn fetch_event(
+    queue: &InputEventQueue<InputDevice>,
+    inner: &mut Inner,
+) -> Option<(Duration, Event)> {
+    if inner.lost {
+        return None;
+    }
+    match queue.pop_event() {
+        Ok(event) => {
+            if event.event_type == EventType::Key as u16 {
+                if event.value == 0 {
+                    inner.key_state.set(event.code as usize, false);
+                } else if event.value == 1 {
+                    inner.key_state.set(event.code as usize, true);
+                }
+            }
+            Some((wall_time(), event))
+        }
+        Err(DriverError::WouldBlock) => None,
+        Err(err) if err.retryability() == Retryability::DeviceLost => {
+            warn!("Input device lost: {err:?}");
+            inner.lost = true;
+            None
+        }
+        Err(err) => {
+            warn!("Failed to read event: {err:?}");
+            None
+        }
+    }
+}
+
+/// Moves the events of the device into `ring` for as long as it is attached
+/// and its owner lives.
+fn pump_ring(
+    queue: Arc<InputEventQueue<InputDevice>>,
+    inner: Arc<Mutex<Inner>>,
+    ring: Arc<EventRing>,
+) {
+    loop {
+        {
+            let mut inner = inner.lock();
+            if !inner.ring.as_ref().is_some_and(|r| Arc::ptr_eq(r, &ring)) {
+                return;
+            }
+            if !owner_alive(ring.owner) {
+                // Give the events back to `read()`.
+                ring.revoke();
+                inner.ring = None;
+                return;
+            }
+            ring.fill(iter::from_fn(|| fetch_event(&queue, &mut inner)));
+            if inner.lost {
+                // The ring stays attached, so `read()` fails as well.
+                ring.revoke();
+                return;
+            }
+        }
+        ktask::sleep(RING_PUMP_PERIOD);
+    }
+}
+
+fn owner_alive(owner: u64) -> bool {
+    get_process_data(owner as _).is_ok()
+}
+
 fn copy_bytes(src: &[u8], dst: &mut [u8]) -> usize {
     let len = src.len().min(dst.len());
     dst[..len].copy_from_slice(&src[..len]);
@@ -134,14 +339,14 @@ fn return_zero_bits(arg: usize, size: usize, bits: usize) -> KResult<usize> {
 }
 
 #[repr(C)]
-#[derive(FromBytes, IntoBytes, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable)]
 pub struct KernelTimeval {
     pub tv_sec: __kernel_old_time_t,
     pub tv_usec: __kernel_suseconds_t,
 }
 
 #[repr(C)]
-#[derive(FromBytes, IntoBytes, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable)]
 struct InputEvent {
     time: KernelTimeval,
     event_type: u16,
@@ -149,6 +354,18 @@ struct InputEvent {
     value: i32,
 }
 
+fn input_event(time: Duration, event: Event) -> InputEvent {
+    InputEvent {
+        time: KernelTimeval {
+            tv_sec: time.as_secs() as _,
+            tv_usec: time.subsec_micros() as _,
+        },
+        event_type: event.event_type,
+        code: event.code,
+        value: event.value as _,
+    }
+}
+
 #[unsafe(no_mangle)]
 #[inline(never)]
 pub extern "C" fn ongkey() {
@@ -163,31 +380,35 @@ impl DeviceOps for EventDev {
         if buf.len() < size_of::<InputEvent>() {
             return Err(KError::InvalidInput);
         }
+        self.queue
+            .check_access(current_owner())
+            .map_err(|_| KError::ResourceBusy)?;
         let mut read = 0;
         let mut inner = self.inner.lock();
+        if inner.ring.is_some() {
+            // Events go to the ring only; mixing both would split the stream.
+            return Err(if inner.lost {
+                KError::NoSuchDevice
+            } else {
+                KError::ResourceBusy
+            });
+        }
         for out in buf.chunks_exact_mut(size_of::<InputEvent>()) {
-            if !inner.has_event() {
+            if !self.has_event(&mut inner) {
                 break;
             }
             let Some((time, event)) = inner.read_ahead.take() else {
                 break;
             };
-            let input_event = InputEvent {
-                time: KernelTimeval {
-                    tv_sec: time.as_secs() as _,
-                    tv_usec: time.subsec_micros() as _,
-                },
-                event_type: event.event_type,
-                code: event.code,
-                value: event.value as _,
-            };
-            out.copy_from_slice(input_event.as_bytes());
+            out.copy_from_slice(input_event(time, event).as_bytes());
             read += out.len();
         }
-        if read == 0 {
-            Err(KError::WouldBlock)
-        } else {
+        if read > 0 {
             Ok(read)
+        } else if inner.lost {
+            Err(KError::NoSuchDevice)
+        } else {
+            Err(KError::WouldBlock)
         }
     }
 
@@ -207,6 +428,13 @@ impl DeviceOps for EventDev {
         Some(self)
     }
 
+    fn mmap(&self) -> DeviceMmap {
+        match &self.inner.lock().ring {
+            Some(ring) => DeviceMmap::Shared(ring.pages.clone()),
+            None => DeviceMmap::None,
+        }
+    }
+
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
         match cmd {
             EVIOCGVERSION => {
@@ -215,10 +443,23 @@ impl DeviceOps for EventDev {
             }
             EVIOCGID => {
                 *UserPtr::<InputDeviceId>::from(arg).get_as_mut()? =
-                    self.inner.lock().device.device_id();
+                    self.queue.with_device(|dev| dev.device_id());
                 Ok(0)
             }
-            EVIOCGRAB => Ok(0),
+            EVIOCGRAB => {
+                let owner = current_owner();
+                let res = if arg != 0 {
+                    self.queue.grab(owner)
+                } else {
+                    self.queue.ungrab(owner)
+                };
+                res.map(|_| 0).map_err(|err| match err {
+                    DriverError::ResourceBusy => KError::ResourceBusy,
+                    _ => KError::InvalidInput,
+                })
+            }
+            EVIOCRINGSETUP => self.setup_ring(arg),
+            EVIOCRINGFREE => self.free_ring(),
             other => {
                 // variable-length command
                 let mut tmp = other;
@@ -244,19 +485,21 @@ impl DeviceOps for EventDev {
                         match nr {
                             // EVIOCGNAME
                             0x06 => {
-                                return return_str(arg, size, self.inner.lock().device.name());
+                                return self
+                                    .queue
+                                    .with_device(|dev| return_str(arg, size, dev.name()));
                             }
                             // EVIOCGPHYS
                             0x07 => {
-                                return return_str(
-                                    arg,
-                                    size,
-                                    self.inner.lock().device.physical_location(),
-                                );
+                                return self.queue.with_device(|dev| {
+                                    return_str(arg, size, dev.physical_location())
+                                });
                             }
                             // EVIOCGUNIQ
                             0x08 => {
-                                return return_str(arg, size, self.inner.lock().device.unique_id());
+                                return self
+                                    .queue
+                                    .with_device(|dev| return_str(arg, size, dev.unique_id()));
                             }
                             // EVIOCGPROP
                             0x09 => {
@@ -308,37 +551,55 @@ impl DeviceOps for EventDev {
 impl Pollable for EventDev {
     fn poll(&self) -> IoEvents {
         let mut events = IoEvents::empty();
-        events.set(IoEvents::IN, self.inner.lock().has_event());
+        let mut inner = self.inner.lock();
+        let readable = match &inner.ring {
+            Some(ring) => ring.has_pending(),
+            None => self.has_event(&mut inner),
+        };
+        events.set(IoEvents::IN, readable);
+        events.set(IoEvents::HUP | IoEvents::ERR, inner.lost);
         events
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        if events.contains(IoEvents::IN) {
-            context.waker().wake_by_ref();
+        match &self.inner.lock().ring {
+            // The ring is filled in the background, which wakes us up.
+            Some(ring) if events.contains(IoEvents::IN) => ring.poll.register(context.waker()),
+            Some(_) => {}
+            None => self.queue.register(context, events),
         }
     }
 }
 
+/// Identifies the grabbing consumer of an event device by process.
+fn current_owner() -> u64 {
+    current().as_thread().proc_data.proc.pid() as u64
+}
+
 pub fn input_devices(fs: Arc<SimpleFs>) -> DirMapping {
     let mut inputs = DirMapping::new();
     let mut input_id = 0;
     let input_devices = inputdev::input_take_all();
     let mut keys = [0; 0x300usize.div_ceil(8)];
-    for (i, mut device) in input_devices.into_iter().enumerate() {
+    for mut device in input_devices {
         assert!(device.get_event_bits(EventType::Key, &mut keys).unwrap());
 
-        let dev = Device::new(
-            fs.clone(),
-            NodeType::CharacterDevice,
-            DeviceId::new(13, (i + 1) as _),
-            Arc::new(EventDev::new(device)),
-        );
+        let ops = Arc::new(EventDev::new(device));
 
+        // Input devices use major 13, with `mice` at minor 63 and `eventN`
+        // at minor 64 + N, as in Linux.
         const BTN_MOUSE: usize = 0x110;
         if keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0 {
             // Mouse
+            let dev = super::device(&fs, NodeType::CharacterDevice, DeviceId::new(13, 63), ops);
             inputs.add("mice", dev);
         } else {
+            let dev = super::device(
+                &fs,
+                NodeType::CharacterDevice,
+                DeviceId::new(13, 64 + input_id),
+                ops,
+            );
             inputs.add(format!("event{input_id}"), dev);
             input_id += 1;
         }
@@ -7,12 +7,19 @@ use core::{any::Any, slice};
 use fs_ng_vfs::{NodeFlags, VfsError, VfsResult};
 use kcore::vfs::{DeviceMmap, DeviceOps};
 #[allow(unused_imports)]
-use kdriver::prelude::DisplayDriverOps;
+use kdriver::prelude::{DisplayDriverOps, DriverError};
 use kerrno::KError;
 use khal::mem::v2p;
 use memaddr::{PhysAddrRange, VirtAddr};
 use osvm::VirtMutPtr;
 
+use crate::file::DmaBufFile;
+
+/// `FBIO_PRESENT_DMABUF`, specific to this kernel: shows the dma-buf whose
+/// file descriptor is the argument, and returns the number of bytes copied
+/// to the framebuffer, 0 if the device scans it out.
+const FBIO_PRESENT_DMABUF: u32 = 0x46f0;
+
 // Types from https://github.com/Tangzh33/asterinas
 
 #[repr(C)]
@@ -63,7 +70,7 @@ struct VarScreenInfo {
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-struct FixScreenInfo {
+pub(crate) struct FixScreenInfo {
     pub id: [u8; 16],       // Identification string, e.g., "TT Builtin"
     pub smem_start: u64,    // Start of framebuffer memory (physical address)
     pub smem_len: u32,      // Length of framebuffer memory
@@ -81,6 +88,51 @@ struct FixScreenInfo {
     pub reserved: [u16; 2], // Reserved for future compatibility
 }
 
+/// `struct fb_fix_screeninfo` of 32-bit programs, with 32-bit addresses.
+#[cfg(all(target_arch = "aarch64", feature = "compat"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FixScreenInfo32 {
+    id: [u8; 16],
+    smem_start: u32,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: u32,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+#[cfg(all(target_arch = "aarch64", feature = "compat"))]
+impl From<FixScreenInfo> for FixScreenInfo32 {
+    fn from(info: FixScreenInfo) -> Self {
+        FixScreenInfo32 {
+            id: info.id,
+            smem_start: info.smem_start as _,
+            smem_len: info.smem_len,
+            type_: info.type_,
+            type_aux: info.type_aux,
+            visual: info.visual,
+            xpanstep: info.xpanstep,
+            ypanstep: info.ypanstep,
+            ywrapstep: info.ywrapstep,
+            line_length: info.line_length,
+            mmio_start: info.mmio_start as _,
+            mmio_len: info.mmio_len,
+            accel: info.accel,
+            capabilities: info.capabilities,
+            reserved: info.reserved,
+        }
+    }
+}
+
 async fn refresh_task() {
     let delay = core::time::Duration::from_secs_f32(1. / 60.);
     loop {
@@ -222,6 +274,15 @@ impl DeviceOps for FrameBuffer {
             0x4606 => Err(KError::InvalidInput),
             // FBIOBLANK
             0x4611 => Err(KError::InvalidInput),
+            FBIO_PRESENT_DMABUF => {
+                let file = DmaBufFile::from_fd(arg as i32)?;
+                let fb = fbdevice::FbHandle::by_index(0).ok_or(KError::NoSuchDevice)?;
+                fb.present(file.buf()).map_err(|err| match err {
+                    DriverError::InvalidInput => KError::InvalidInput,
+                    DriverError::NoMemory => KError::NoMemory,
+                    _ => KError::Io,
+                })
+            }
             _ => Err(KError::NotATty),
         }
     }
@@ -7,12 +7,13 @@ use core::bstr::ByteStr;
 use kerrno::LinuxResult;
 use knet::{
     RecvOptions, SocketAddrEx, SocketOps,
+    options::UnixCredentials,
     unix::{DgramTransport, UnixAddr, UnixDomainSocket},
 };
 
 /// Bind /dev/log as a Unix domain socket for syslog messages
 pub fn bind_dev_log() -> LinuxResult<()> {
-    let server = UnixDomainSocket::new(DgramTransport::new(1));
+    let server = UnixDomainSocket::new(DgramTransport::new(UnixCredentials::new(1)));
     server.bind(SocketAddrEx::Unix(UnixAddr::Path("/dev/log".into())))?;
     ktask::spawn_with_name(
         move || {
@@ -31,6 +32,8 @@ pub fn bind_dev_log() -> LinuxResult<()> {
             }
         },
         "dev-log-server".into(),
-    );
+    )
+    // Idle until some client logs, possibly forever.
+    .set_hung_check_exempt(true);
     Ok(())
 }
@@ -15,14 +15,22 @@ mod log;
 mod r#loop;
 #[cfg(feature = "memtrack")]
 mod memtrack;
+pub mod procevents;
 mod rtc;
 pub mod tty;
+#[cfg(feature = "vcapture")]
+mod vcapture;
 
 use alloc::{format, sync::Arc};
 use core::any::Any;
 
+#[cfg(all(target_arch = "aarch64", feature = "compat"))]
+pub(crate) use fb::{FixScreenInfo, FixScreenInfo32};
 use fs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsResult};
-use kcore::vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs};
+use kcore::vfs::{
+    Device, DeviceKind, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs, alloc_device,
+    register_device,
+};
 use kerrno::KError;
 use ksync::Mutex;
 #[cfg(feature = "dev-log")]
@@ -155,13 +163,39 @@ impl DeviceOps for CpuDmaLatency {
     }
 }
 
+/// Registers `ops` as the driver of `dev` and creates its devfs node.
+fn device(
+    fs: &Arc<SimpleFs>,
+    node_type: NodeType,
+    dev: DeviceId,
+    ops: Arc<dyn DeviceOps>,
+) -> Arc<Device> {
+    let kind = DeviceKind::from_node_type(node_type).expect("not a device node type");
+    if let Err(err) = register_device(kind, dev, ops.clone()) {
+        warn!("devfs: failed to register device {dev:?}: {err:?}");
+    }
+    Device::new(fs.clone(), node_type, dev, ops)
+}
+
+/// Registers `ops` as a character device with a dynamic major and creates
+/// its devfs node.
+///
+/// Used for devices that have no fixed number in Linux either.
+fn dynamic_device(fs: &Arc<SimpleFs>, ops: Arc<dyn DeviceOps>) -> Arc<Device> {
+    let dev = alloc_device(DeviceKind::Char, ops.clone()).unwrap_or_else(|err| {
+        warn!("devfs: failed to allocate a device number: {err:?}");
+        DeviceId::default()
+    });
+    Device::new(fs.clone(), NodeType::CharacterDevice, dev, ops)
+}
+
 /// Build the devfs filesystem with all standard device entries
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
         "null",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(1, 3),
             Arc::new(Null),
@@ -169,8 +203,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "zero",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(1, 5),
             Arc::new(Zero),
@@ -178,8 +212,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "full",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(1, 7),
             Arc::new(Full),
@@ -187,8 +221,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "random",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(1, 8),
             Arc::new(Random::new()),
@@ -196,27 +230,19 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "urandom",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(1, 9),
             Arc::new(Random::new()),
         ),
     );
-    root.add(
-        "rtc0",
-        Device::new(
-            fs.clone(),
-            NodeType::CharacterDevice,
-            rtc::RTC0_DEVICE_ID,
-            Arc::new(rtc::Rtc),
-        ),
-    );
+    root.add("rtc0", dynamic_device(&fs, Arc::new(rtc::Rtc)));
     if fbdevice::fb_available() {
         root.add(
             "fb0",
-            Device::new(
-                fs.clone(),
+            device(
+                &fs,
                 NodeType::CharacterDevice,
                 DeviceId::new(29, 0),
                 Arc::new(fb::FrameBuffer::new()),
@@ -226,8 +252,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
 
     root.add(
         "tty",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(5, 0),
             Arc::new(tty::CurrentTty),
@@ -235,8 +261,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
     root.add(
         "console",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(5, 1),
             tty::N_TTY.clone(),
@@ -245,8 +271,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
 
     root.add(
         "ptmx",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(5, 2),
             Arc::new(tty::Ptmx(fs.clone())),
@@ -256,6 +282,10 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         "pts",
         SimpleDir::new_maker(fs.clone(), Arc::new(tty::PtsDir)),
     );
+    root.add(
+        "procevents",
+        dynamic_device(&fs, Arc::new(procevents::ProcEvents(fs.clone()))),
+    );
     #[cfg(feature = "dev-log")]
     root.add(
         "log",
@@ -265,18 +295,13 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     #[cfg(feature = "memtrack")]
     root.add(
         "memtrack",
-        Device::new(
-            fs.clone(),
-            NodeType::CharacterDevice,
-            DeviceId::new(114, 514),
-            Arc::new(memtrack::MemTrack),
-        ),
+        dynamic_device(&fs, Arc::new(memtrack::MemTrack)),
     );
 
     root.add(
         "cpu_dma_latency",
-        Device::new(
-            fs.clone(),
+        device(
+            &fs,
             NodeType::CharacterDevice,
             DeviceId::new(10, 1024),
             Arc::new(CpuDmaLatency),
@@ -291,11 +316,11 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
 
     // Loop devices
     for i in 0..16 {
-        let dev_id = DeviceId::new(7, 0);
+        let dev_id = DeviceId::new(7, i);
         root.add(
             format!("loop{i}"),
-            Device::new(
-                fs.clone(),
+            device(
+                &fs,
                 NodeType::BlockDevice,
                 dev_id,
                 Arc::new(r#loop::LoopDevice::new(i, dev_id)),
@@ -313,23 +338,19 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     #[cfg(all(feature = "dice", target_os = "none"))]
     root.add(
         "dice",
-        Device::new(
-            fs.clone(),
-            NodeType::CharacterDevice,
-            DeviceId::new(30, 0),
-            Arc::new(dice::DiceNodeInfo::new()),
-        ),
+        dynamic_device(&fs, Arc::new(dice::DiceNodeInfo::new())),
+    );
+
+    #[cfg(feature = "vcapture")]
+    root.add(
+        "vcapture",
+        dynamic_device(&fs, Arc::new(vcapture::VCapture)),
     );
 
     #[cfg(feature = "sev")]
     root.add(
         "csv-guest",
-        Device::new(
-            fs.clone(),
-            NodeType::CharacterDevice,
-            DeviceId::new(30, 1),
-            Arc::new(csv_guest::CsvGuestDevice::new()),
-        ),
+        dynamic_device(&fs, Arc::new(csv_guest::CsvGuestDevice::new())),
     );
 
     SimpleDir::new_maker(fs, Arc::new(root))
@@ -0,0 +1,289 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! /dev/procevents: a stream of process events for supervisors.
+//!
+//! Every open of the device is a listener of its own. It receives nothing
+//! until [`PROC_EVENTS_SUBSCRIBE`] says which events of which processes it
+//! wants; then reads return whole [`ProcEventRecord`]s, the oldest first, and
+//! block while there are none.
+
+use alloc::sync::Arc;
+use core::{any::Any, task::Context};
+
+use bytemuck::AnyBitPattern;
+use fs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use kcore::{
+    task::get_process_data,
+    vfs::{Device, DeviceOps, SimpleFs},
+};
+use kerrno::{KError, KResult};
+use kpoll::{IoEvents, Pollable};
+use kprocess::{
+    ConnectorEvent, ConnectorEvents, ConnectorListener, ProcessEvent, connector::COMM_LEN,
+};
+use ksync::Mutex;
+use osvm::VirtPtr;
+use zerocopy::{Immutable, IntoBytes};
+
+/// `_IOW('P', 1, struct proc_events_filter)`: subscribes to events.
+pub const PROC_EVENTS_SUBSCRIBE: u32 = 0x4010_5001;
+
+/// The `what` of a record standing for events dropped as the queue was full.
+const PROC_EVENT_OVERFLOW: u32 = 0x4000_0000;
+
+/// Events queued when the subscription leaves the capacity to us.
+const DEFAULT_CAPACITY: usize = 1024;
+/// The largest queue a listener may ask for.
+const MAX_CAPACITY: usize = 65536;
+
+/// What a listener subscribes to.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct ProcEventsFilter {
+    /// The `what` values of the events wanted.
+    events: u32,
+    /// Only events of this process and its descendants, or of every process
+    /// if 0.
+    pid: u32,
+    /// How many events may be queued, or 0 for a default.
+    capacity: u32,
+    _reserved: u32,
+}
+
+/// A process event as read from the device.
+///
+/// `what` is the kind of the event, as in the filter. For a fork, `pid` is
+/// the parent and `arg0` the child; for an exit, `arg0` is the exit code and
+/// `arg1` the terminating signal; for ID changes they are the real and
+/// effective IDs; exec and comm changes carry the new name in `comm`. An
+/// overflow record counts the events dropped at its place in `arg0`.
+#[repr(C)]
+#[derive(Default, Immutable, IntoBytes)]
+pub struct ProcEventRecord {
+    what: u32,
+    pid: u32,
+    arg0: u32,
+    arg1: u32,
+    comm: [u8; COMM_LEN],
+}
+
+impl From<ConnectorEvent> for ProcEventRecord {
+    fn from(event: ConnectorEvent) -> Self {
+        let what = event.kind().bits();
+        match event {
+            ConnectorEvent::Fork { parent, child } => Self {
+                what,
+                pid: parent,
+                arg0: child,
+                ..Default::default()
+            },
+            ConnectorEvent::Exec { pid, comm } | ConnectorEvent::Comm { pid, comm } => Self {
+                what,
+                pid,
+                comm,
+                ..Default::default()
+            },
+            ConnectorEvent::Exit { pid, status } => {
+                let (code, signo) = match status {
+                    ProcessEvent::Exited(code) => (code as u32, 0),
+                    ProcessEvent::Signaled(signo) | ProcessEvent::Dumped(signo) => (0, signo),
+                    _ => (0, 0),
+                };
+                Self {
+                    what,
+                    pid,
+                    arg0: code,
+                    arg1: signo,
+                    ..Default::default()
+                }
+            }
+            ConnectorEvent::Uid {
+                pid,
+                ruid: real,
+                euid: effective,
+            }
+            | ConnectorEvent::Gid {
+                pid,
+                rgid: real,
+                egid: effective,
+            } => Self {
+                what,
+                pid,
+                arg0: real,
+                arg1: effective,
+                ..Default::default()
+            },
+            ConnectorEvent::Overflow { lost } => Self {
+                what: PROC_EVENT_OVERFLOW,
+                arg0: lost.min(u32::MAX as u64) as u32,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The /dev/procevents node, opening which makes a [`ProcEventsListener`].
+pub struct ProcEvents(pub Arc<SimpleFs>);
+
+impl ProcEvents {
+    /// Creates the device of a new listener.
+    pub fn open(&self) -> Arc<Device> {
+        Device::new(
+            self.0.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::default(),
+            Arc::new(ProcEventsListener {
+                listener: Mutex::new(None),
+            }),
+        )
+    }
+}
+
+// Opening `ProcEvents` results in a `ProcEventsListener`, so these are never
+// used.
+impl DeviceOps for ProcEvents {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        unreachable!()
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        unreachable!()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// An open /dev/procevents. Closing it unsubscribes.
+pub struct ProcEventsListener {
+    listener: Mutex<Option<ConnectorListener>>,
+}
+
+impl ProcEventsListener {
+    fn subscribe(&self, filter: ProcEventsFilter) -> KResult<()> {
+        let events = ConnectorEvents::from_bits(filter.events).ok_or(KError::InvalidInput)?;
+        let root = match filter.pid {
+            0 => None,
+            pid => Some(get_process_data(pid)?.proc.clone()),
+        };
+        let capacity = match filter.capacity as usize {
+            0 => DEFAULT_CAPACITY,
+            n if n <= MAX_CAPACITY => n,
+            _ => return Err(KError::InvalidInput),
+        };
+        let mut listener = self.listener.lock();
+        // Drop the old subscription first, its queue goes with it.
+        *listener = None;
+        *listener = Some(ConnectorListener::subscribe(
+            events,
+            root.as_ref(),
+            capacity,
+        ));
+        Ok(())
+    }
+}
+
+impl DeviceOps for ProcEventsListener {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        const RECORD_SIZE: usize = size_of::<ProcEventRecord>();
+        if buf.len() < RECORD_SIZE {
+            return Err(KError::InvalidInput);
+        }
+        let guard = self.listener.lock();
+        let listener = guard.as_ref().ok_or(KError::InvalidInput)?;
+        let mut read = 0;
+        for chunk in buf.chunks_exact_mut(RECORD_SIZE) {
+            let Some(event) = listener.pop() else {
+                break;
+            };
+            chunk.copy_from_slice(ProcEventRecord::from(event).as_bytes());
+            read += RECORD_SIZE;
+        }
+        if read == 0 {
+            return Err(KError::WouldBlock);
+        }
+        Ok(read)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(KError::InvalidInput)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            PROC_EVENTS_SUBSCRIBE => {
+                self.subscribe((arg as *const ProcEventsFilter).read_vm()?)?;
+                Ok(0)
+            }
+            _ => Err(KError::NotATty),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for ProcEventsListener {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        let listener = self.listener.lock();
+        events.set(
+            IoEvents::IN,
+            listener.as_ref().is_some_and(|it| it.has_events()),
+        );
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN)
+            && let Some(listener) = self.listener.lock().as_ref()
+        {
+            listener.register_waker(context.waker());
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod procevents_tests {
+    use unittest::def_test;
+
+    use super::*;
+
+    #[def_test]
+    fn test_record_layout() {
+        assert_eq!(size_of::<ProcEventRecord>(), 32);
+        assert_eq!(size_of::<ProcEventsFilter>(), 16);
+        assert_eq!(PROC_EVENTS_SUBSCRIBE >> 16 & 0x3fff, 16);
+    }
+
+    #[def_test]
+    fn test_record_of_events() {
+        let exit = ProcEventRecord::from(ConnectorEvent::Exit {
+            pid: 7,
+            status: ProcessEvent::Signaled(9),
+        });
+        assert_eq!(
+            (exit.what, exit.pid, exit.arg0, exit.arg1),
+            (0x8000_0000, 7, 0, 9)
+        );
+        let fork = ProcEventRecord::from(ConnectorEvent::Fork {
+            parent: 1,
+            child: 2,
+        });
+        assert_eq!((fork.what, fork.pid, fork.arg0), (1, 1, 2));
+        let lost = ProcEventRecord::from(ConnectorEvent::Overflow { lost: 5 });
+        assert_eq!((lost.what, lost.arg0), (PROC_EVENT_OVERFLOW, 5));
+    }
+}
@@ -5,15 +5,12 @@
 use core::{any::Any, ffi::c_int};
 
 use chrono::{Datelike, Timelike};
-use fs_ng_vfs::{DeviceId, NodeFlags, VfsError, VfsResult};
+use fs_ng_vfs::{NodeFlags, VfsError, VfsResult};
 use linux_raw_sys::ioctl::RTC_RD_TIME;
 use osvm::VirtMutPtr;
 
 use crate::vfs::DeviceOps;
 
-/// The device ID for /dev/rtc0
-pub const RTC0_DEVICE_ID: DeviceId = DeviceId::new(250, 0);
-
 #[repr(C)]
 #[allow(non_camel_case_types, dead_code)]
 struct rtc_time {
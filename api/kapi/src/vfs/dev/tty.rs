@@ -2,12 +2,16 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{any::Any, ops::Deref, sync::atomic::Ordering, task::Context};
 
 use fs_ng_vfs::NodeFlags;
 use kcore::{task::AsThread, vfs::SimpleFs};
 use kerrno::{KError, KResult};
+use khal::time::monotonic_time;
 use kpoll::{IoEvents, Pollable};
 use kprocess::Process;
 use ksync::Mutex;
@@ -21,6 +25,7 @@ use crate::{
     terminal::{
         Terminal, WindowSize,
         ldisc::{LineDiscipline, ProcessMode, TtyConfig, TtyRead, TtyWrite},
+        output::{Admit, OutputConfig, OutputControl, TIOCGOUTPUT, TIOCSOUTPUT, dropped_notice},
         termios::{Termios, Termios2},
     },
     vfs::DeviceOps,
@@ -50,9 +55,15 @@ pub struct Tty<R, W> {
     terminal: Arc<Terminal>,
     ldisc: Mutex<LineDiscipline<R, W>>,
     writer: W,
+    output: Mutex<OutputControl>,
     is_ptm: bool,
 }
 
+/// Userspace output is written in chunks of this size, so that kernel
+/// messages, which take the console directly, wait for at most one chunk
+/// rather than a whole write.
+const OUTPUT_CHUNK: usize = 64;
+
 impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
     fn new(terminal: Arc<Terminal>, config: TtyConfig<R, W>) -> Arc<Self> {
         let writer = config.writer.clone();
@@ -63,6 +74,7 @@ impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
             terminal,
             ldisc,
             writer,
+            output: Mutex::new(OutputControl::default()),
             is_ptm,
         })
     }
@@ -107,7 +119,30 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     }
 
     fn write_at(&self, buf: &[u8], _offset: u64) -> KResult<usize> {
-        self.writer.write(buf);
+        if self.is_ptm {
+            self.writer.write(buf);
+            return Ok(buf.len());
+        }
+
+        let mut out = Vec::with_capacity(buf.len());
+        self.output.lock().filter(buf, &mut out);
+        let mut pending = &out[..];
+        while !pending.is_empty() {
+            let admit = self.output.lock().admit(pending.len(), monotonic_time());
+            match admit {
+                Admit::Write(n, dropped) => {
+                    if let Some(dropped) = dropped {
+                        self.writer.write(&dropped_notice(dropped));
+                    }
+                    for chunk in pending[..n].chunks(OUTPUT_CHUNK) {
+                        self.writer.write(chunk);
+                    }
+                    pending = &pending[n..];
+                }
+                Admit::Wait(dur) => ktask::sleep(dur),
+                Admit::Drop => break,
+            }
+        }
         Ok(buf.len())
     }
 
@@ -155,6 +190,13 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
             TIOCSWINSZ => {
                 *self.terminal.window_size.lock() = (arg as *const WindowSize).read_vm()?;
             }
+            TIOCGOUTPUT => {
+                (arg as *mut OutputConfig).write_vm(self.output.lock().config())?;
+            }
+            TIOCSOUTPUT => {
+                let config = (arg as *const OutputConfig).read_vm()?;
+                self.output.lock().set_config(config, monotonic_time())?;
+            }
             TIOCSPTLCK => {}
             TIOCGPTN => {
                 (arg as *mut u32).write_vm(self.pty_number())?;
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! `/dev/vcapture`, a synthetic capture device producing dma-bufs.
+//!
+//! It stands for a camera or a decoder when testing buffer sharing: each
+//! capture allocates a dma-buf, which a kernel task fills the way a device
+//! would, through the scatter-gather list, before signaling its fence.
+
+use core::any::Any;
+
+use dmabuf::{DmaBuf, DmaDirection};
+use fs_ng_vfs::{NodeFlags, VfsResult};
+use kerrno::KError;
+use osvm::{VirtMutPtr, VirtPtr};
+
+use crate::{
+    file::{DmaBufFile, FileLike},
+    vfs::DeviceOps,
+};
+
+/// `VCAPTURE_IOC_CAPTURE`, `_IOWR('V', 0xc0, struct vcapture_request)`.
+const VCAPTURE_IOC_CAPTURE: u32 = 0xc010_56c0;
+
+/// The argument of `VCAPTURE_IOC_CAPTURE`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct CaptureRequest {
+    /// The size of the frame in bytes.
+    size: u32,
+    /// The sequence number of the frame, which the data depends on.
+    seq: u32,
+    /// Out: the dma-buf holding the frame.
+    fd: i32,
+    /// Out: the FNV-1a hash of the `size` bytes of the frame.
+    checksum: u32,
+}
+
+/// Returns byte `index` of frame `seq`.
+fn pattern(seq: u32, index: usize) -> u8 {
+    ((index as u32).wrapping_mul(7) ^ (seq.wrapping_mul(0x9e37_79b9) >> 24)) as u8
+}
+
+fn checksum(seq: u32, size: usize) -> u32 {
+    (0..size).fold(0x811c_9dc5, |hash, i| {
+        (hash ^ pattern(seq, i) as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+pub struct VCapture;
+
+impl VCapture {
+    fn capture(&self, req: &mut CaptureRequest) -> VfsResult<()> {
+        let buf = DmaBuf::alloc(req.size as usize)?;
+        buf.fence().reset();
+
+        let seq = req.seq;
+        let mut attachment = buf.attach(DmaDirection::FromDevice);
+        ktask::spawn_with_name(
+            move || {
+                let mut index = 0;
+                for seg in attachment.map() {
+                    // SAFETY: the segment is mapped to this device.
+                    let data =
+                        unsafe { core::slice::from_raw_parts_mut(seg.vaddr.as_mut_ptr(), seg.len) };
+                    for byte in data {
+                        *byte = pattern(seq, index);
+                        index += 1;
+                    }
+                }
+                attachment.unmap();
+                attachment.buf().fence().signal();
+            },
+            "vcapture".into(),
+        );
+
+        req.checksum = checksum(seq, req.size as usize);
+        req.fd = DmaBufFile::new(buf).add_to_fd_table(true)?;
+        Ok(())
+    }
+}
+
+impl DeviceOps for VCapture {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(KError::InvalidInput)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(KError::InvalidInput)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            VCAPTURE_IOC_CAPTURE => {
+                let mut req = (arg as *const CaptureRequest).read_vm()?;
+                self.capture(&mut req)?;
+                (arg as *mut CaptureRequest).write_vm(req)?;
+                Ok(0)
+            }
+            _ => Err(KError::NotATty),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
@@ -6,7 +6,6 @@
 
 pub mod dev;
 mod proc;
-mod tmp;
 
 use fs_ng_vfs::{
     Filesystem, NodePermission,
@@ -14,8 +13,8 @@ use fs_ng_vfs::{
 };
 pub use kcore::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
 use kerrno::LinuxResult;
+pub use kfs::MemoryFs;
 use kfs::{FS_CONTEXT, FsContext};
-pub use tmp::MemoryFs;
 
 const DIR_PERMISSION: NodePermission = NodePermission::from_bits_truncate(0o755);
 
@@ -34,11 +33,11 @@ fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()>
 pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
     mount_at(&fs, "/dev", dev::new_devfs())?;
-    mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
+    mount_at(&fs, "/dev/shm", MemoryFs::new())?;
+    mount_at(&fs, "/tmp", MemoryFs::new())?;
     mount_at(&fs, "/proc", proc::new_procfs())?;
 
-    mount_at(&fs, "/sys", tmp::MemoryFs::new())?;
+    mount_at(&fs, "/sys", MemoryFs::new())?;
     let mut path = PathBuf::new();
     for comp in Path::new("/sys/class/graphics/fb0/device").components() {
         path.push(comp.as_str());
@@ -11,21 +11,28 @@ use alloc::{
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+use core::{any::Any, fmt, iter, time::Duration};
 
-use fs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
-use indoc::indoc;
+use fs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsError, VfsResult};
+use indoc::{formatdoc, indoc};
 use kcore::{
-    task::{AsThread, TaskStat, get_task, tasks},
+    config::{USER_HEAP_BASE, USER_STACK_TOP},
+    task::{AsThread, TaskStat, get_process_data, get_task, processes},
     vfs::{
-        DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
-        SimpleFileOperation, SimpleFs,
+        Device, DeviceOps, DirMaker, DirMapping, NodeOpsMux, RwFile, SeqChunk, SeqFile, SeqSource,
+        SimpleDir, SimpleDirOps, SimpleFile, SimpleFileOperation, SimpleFs,
     },
 };
+use kfs::ReadaheadStats;
+use khal::{irq, paging::MappingFlags};
+use kio::{Seek, SeekFrom};
 use kprocess::Process;
-use ktask::{KtaskRef, WeakKtaskRef, current};
+use ksync::Mutex;
+use ktask::{KtaskRef, TaskState, WeakKtaskRef, current};
+use memaddr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+use memspace::AddrSpace;
 
-use crate::file::FD_TABLE;
+use crate::file::{FD_TABLE, File, FileLike};
 
 const DUMMY_MEMINFO: &str = indoc! {"
     MemTotal:       32536204 kB
@@ -92,6 +99,32 @@ pub fn new_procfs() -> Filesystem {
     SimpleFs::new_with("proc".into(), 0x9fa0, builder)
 }
 
+/// Returns the task of a /proc/[pid] entry, or `NoSuchProcess` once it has
+/// exited, so that a read racing with the exit fails instead of showing what
+/// is left of the process.
+fn live_task(task: &WeakKtaskRef) -> VfsResult<KtaskRef> {
+    task.upgrade()
+        .filter(|task| task.state() != TaskState::Exited)
+        .ok_or(VfsError::NoSuchProcess)
+}
+
+/// Returns a live thread of the process `pid`, its leader if still there.
+fn process_task(pid: u32) -> VfsResult<KtaskRef> {
+    if pid == 0 {
+        return Err(VfsError::NotFound);
+    }
+    if let Ok(task) = get_task(pid) {
+        return Ok(task);
+    }
+    let proc_data = get_process_data(pid).map_err(|_| VfsError::NotFound)?;
+    proc_data
+        .proc
+        .threads()
+        .into_iter()
+        .find_map(|tid| get_task(tid).ok())
+        .ok_or(VfsError::NoSuchProcess)
+}
+
 struct ProcessTaskDir {
     fs: Arc<SimpleFs>,
     process: Weak<Process>,
@@ -111,7 +144,7 @@ impl SimpleDirOps for ProcessTaskDir {
     }
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
-        let process = self.process.upgrade().ok_or(VfsError::NotFound)?;
+        let process = self.process.upgrade().ok_or(VfsError::NoSuchProcess)?;
         let tid = name.parse::<u32>().map_err(|_| VfsError::NotFound)?;
         let task = get_task(tid).map_err(|_| VfsError::NotFound)?;
         if task.as_thread().proc_data.proc.pid() != process.pid() {
@@ -132,31 +165,316 @@ impl SimpleDirOps for ProcessTaskDir {
     }
 }
 
+/// Returns the state of a task as shown in /proc/[pid]/status.
+fn state_name(task: &KtaskRef) -> &'static str {
+    match task.state() {
+        TaskState::Running | TaskState::Ready => "R (running)",
+        TaskState::Blocked => "S (sleeping)",
+        TaskState::Exited => "Z (zombie)",
+    }
+}
+
 #[rustfmt::skip]
 fn task_status(task: &KtaskRef) -> String {
+    let proc_data = &task.as_thread().proc_data;
+    let proc = &proc_data.proc;
+    let name = task.name();
+    let fd_count = FD_TABLE.scope(&proc_data.scope.read()).read().count();
+    let (map_count, rss) = {
+        let mut aspace = proc_data.aspace.lock();
+        (aspace.map_count(), aspace.resident_size())
+    };
+    let wss = match proc_data.wss().and_then(|wss| wss.estimate()) {
+        Some(bytes) => format!("VmWss:\t{} kB\n", bytes / 1024),
+        None => String::new(),
+    };
     format!(
-        "Tgid:\t{}\n\
+        "Name:\t{}\n\
+        State:\t{}\n\
+        Tgid:\t{}\n\
         Pid:\t{}\n\
+        PPid:\t{}\n\
         Uid:\t0 0 0 0\n\
         Gid:\t0 0 0 0\n\
+        FDCount:\t{}\n\
+        MapCount:\t{}\n\
+        VmRSS:\t{} kB\n\
+        {}Threads:\t{}\n\
         Cpus_allowed:\t1\n\
         Cpus_allowed_list:\t0\n\
         Mems_allowed:\t1\n\
-        Mems_allowed_list:\t0",
-        task.as_thread().proc_data.proc.pid(),
-        task.id().as_u64()
+        Mems_allowed_list:\t0\n",
+        &name[..name.len().min(16)],
+        state_name(task),
+        proc.pid(),
+        task.id().as_u64(),
+        proc.parent().map_or(0, |parent| parent.pid()),
+        fd_count,
+        map_count,
+        rss / 1024,
+        wss,
+        proc.threads().len(),
+    )
+}
+
+/// Column where paths start in /proc/[pid]/maps, as on 64-bit Linux.
+const MAPS_PATH_COLUMN: usize = 73;
+
+/// A line of /proc/[pid]/maps.
+struct MapsLine<'a> {
+    range: VirtAddrRange,
+    flags: MappingFlags,
+    shared: bool,
+    offset: u64,
+    device: DeviceId,
+    inode: u64,
+    path: &'a str,
+}
+
+impl fmt::Display for MapsLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let perm = |flag, c| if self.flags.contains(flag) { c } else { '-' };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} {:02x}:{:02x} {} ",
+            self.range.start.as_usize(),
+            self.range.end.as_usize(),
+            perm(MappingFlags::READ, 'r'),
+            perm(MappingFlags::WRITE, 'w'),
+            perm(MappingFlags::EXECUTE, 'x'),
+            if self.shared { 's' } else { 'p' },
+            self.offset,
+            self.device.major(),
+            self.device.minor(),
+            self.inode,
+        );
+        if self.path.is_empty() {
+            writeln!(f, "{line}")
+        } else {
+            writeln!(
+                f,
+                "{line:<width$} {}",
+                self.path,
+                width = MAPS_PATH_COLUMN - 1
+            )
+        }
+    }
+}
+
+/// The areas of the address space of a task, shown in /proc/[pid]/maps.
+struct Maps(WeakKtaskRef);
+
+impl SeqSource for Maps {
+    type Key = VirtAddr;
+
+    fn render(&self, after: Option<&VirtAddr>, chunk: &mut SeqChunk<VirtAddr>) -> VfsResult<u64> {
+        let task = live_task(&self.0)?;
+        let proc_data = &task.as_thread().proc_data;
+        let heap = USER_HEAP_BASE..proc_data.get_heap_top();
+        let aspace = proc_data.aspace.lock();
+        for area in aspace.areas_after(after.copied()) {
+            if chunk.is_full() {
+                break;
+            }
+            let backend = area.backend();
+            let path;
+            let mut line = MapsLine {
+                range: VirtAddrRange::new(area.start(), area.end()),
+                flags: area.flags(),
+                shared: backend.is_shared(),
+                offset: 0,
+                device: DeviceId::default(),
+                inode: 0,
+                path: "",
+            };
+            if let Some((loc, offset)) = backend.mapped_file(area.start()) {
+                let meta = loc.metadata()?;
+                path = loc.absolute_path()?.to_string();
+                line.offset = offset;
+                line.device = DeviceId(meta.device);
+                line.inode = meta.inode;
+                line.path = &path;
+            } else if heap.contains(&area.start().as_usize()) {
+                line.path = "[heap]";
+            } else if area.end().as_usize() == USER_STACK_TOP {
+                line.path = "[stack]";
+            }
+            chunk.push(area.start(), &line);
+        }
+        Ok(aspace.areas_generation())
+    }
+}
+
+/// Bits of a word of /proc/[pid]/idle_pages.
+const IDLE_WORD_BITS: usize = u64::BITS as usize;
+/// Bytes of a word of /proc/[pid]/idle_pages.
+const IDLE_WORD_SIZE: usize = size_of::<u64>();
+
+/// The /proc/[pid]/idle_pages bitmap.
+///
+/// Like the page_idle bitmap of Linux, but indexed by virtual page: bit `j`
+/// of the `u64` at offset `8 * i` tells about the page `64 * i + j`, and is
+/// set if the page is mapped and was not accessed since it was marked idle.
+/// Writing a word marks the pages of its set bits idle. Reads and writes are
+/// in whole words.
+struct IdlePages(Weak<Mutex<AddrSpace>>);
+
+impl IdlePages {
+    /// Returns the address of the first page the bitmap tells about at
+    /// `offset`, the number of words of `len` bytes there within the
+    /// address space, and the part of the address space they cover.
+    fn words(
+        aspace: &AddrSpace,
+        offset: u64,
+        len: usize,
+    ) -> VfsResult<(VirtAddr, usize, VirtAddrRange)> {
+        if !offset.is_multiple_of(IDLE_WORD_SIZE as u64) || !len.is_multiple_of(IDLE_WORD_SIZE) {
+            return Err(VfsError::InvalidInput);
+        }
+        let span = IDLE_WORD_BITS * PAGE_SIZE_4K;
+        let first = usize::try_from(offset / IDLE_WORD_SIZE as u64)
+            .ok()
+            .and_then(|word| word.checked_mul(span))
+            .filter(|&first| first < aspace.end().as_usize());
+        let Some(first) = first else {
+            return Ok((
+                aspace.end(),
+                0,
+                VirtAddrRange::new(aspace.end(), aspace.end()),
+            ));
+        };
+        let first = VirtAddr::from(first);
+        let words = (len / IDLE_WORD_SIZE).min((aspace.end() - first).div_ceil(span));
+        let end = (first + words * span).min(aspace.end());
+        let range = VirtAddrRange::new(first.max(aspace.base()).min(end), end);
+        Ok((first, words, range))
+    }
+}
+
+impl DeviceOps for IdlePages {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let aspace = self.0.upgrade().ok_or(VfsError::NotFound)?;
+        let mut aspace = aspace.lock();
+        let (first, words, range) = Self::words(&aspace, offset, buf.len())?;
+        let mut bitmap = vec![0u64; words];
+        if !range.is_empty() {
+            let idle = aspace.idle_pages(range.start, range.size())?;
+            let skip = (range.start - first) / PAGE_SIZE_4K;
+            for page in 0..range.size() / PAGE_SIZE_4K {
+                if idle[page / IDLE_WORD_BITS] & (1 << (page % IDLE_WORD_BITS)) != 0 {
+                    let bit = skip + page;
+                    bitmap[bit / IDLE_WORD_BITS] |= 1 << (bit % IDLE_WORD_BITS);
+                }
+            }
+        }
+        for (chunk, word) in buf.chunks_exact_mut(IDLE_WORD_SIZE).zip(&bitmap) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        Ok(words * IDLE_WORD_SIZE)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let aspace = self.0.upgrade().ok_or(VfsError::NotFound)?;
+        let mut aspace = aspace.lock();
+        let (first, words, range) = Self::words(&aspace, offset, buf.len())?;
+        // Runs of set bits are marked at once.
+        let mut run: Option<VirtAddr> = None;
+        let bits = buf
+            .chunks_exact(IDLE_WORD_SIZE)
+            .take(words)
+            .flat_map(|chunk| {
+                let word = u64::from_ne_bytes(chunk.try_into().unwrap());
+                (0..IDLE_WORD_BITS).map(move |bit| word & (1 << bit) != 0)
+            });
+        for (page, set) in bits.enumerate() {
+            let vaddr = first + page * PAGE_SIZE_4K;
+            let set = set && range.contains(vaddr);
+            match run {
+                None if set => run = Some(vaddr),
+                Some(start) if !set => {
+                    aspace.mark_idle(start, vaddr - start)?;
+                    run = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run {
+            aspace.mark_idle(start, range.end - start)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// Formats /proc/[pid]/sched, in the layout of Linux.
+fn task_sched(task: &KtaskRef) -> String {
+    let stat = ktask::sched_stat(task);
+    let ms = |nanos: u64| format!("{}.{:06}", nanos / 1_000_000, nanos % 1_000_000);
+    // Real-time tasks rank above all normal ones, as in Linux.
+    let prio = if task.is_rt() {
+        99 - task.rt_priority() as i32
+    } else {
+        120 + stat.nice as i32
+    };
+    format!(
+        "{} ({}, #threads: {})\n\
+        -------------------------------------------------------------------\n\
+        se.vruntime                                  : {:>20}\n\
+        se.sum_exec_runtime                          : {:>20}\n\
+        se.load.weight                               : {:>20}\n\
+        policy                                       : {:>20}\n\
+        prio                                         : {:>20}\n",
+        task.name(),
+        task.id().as_u64(),
+        task.as_thread().proc_data.proc.threads().len(),
+        ms(stat.vruntime),
+        ms(stat.sum_exec_runtime),
+        stat.weight,
+        task.sched_policy() as u8,
+        prio,
     )
 }
 
-/// The /proc/[pid]/fd directory
+/// The /proc/[pid]/fd directory, or /proc/[pid]/fdinfo if `info` is set
 struct ThreadFdDir {
     fs: Arc<SimpleFs>,
     task: WeakKtaskRef,
+    info: bool,
+}
+
+/// Returns the contents of /proc/[pid]/fdinfo/[fd].
+fn fd_info(file: Arc<dyn FileLike>) -> String {
+    let Ok(file) = file.downcast_arc::<File>() else {
+        return "pos:\t0\n".into();
+    };
+    let mut file = file.inner();
+    let pos = file.seek(SeekFrom::Current(0)).unwrap_or_default();
+    let ReadaheadStats {
+        window,
+        sequential,
+        random,
+        batches,
+        pages,
+    } = file.readahead_stats();
+    formatdoc! {"
+        pos:\t{pos}
+        ra_window:\t{window}
+        ra_sequential:\t{sequential}
+        ra_random:\t{random}
+        ra_batches:\t{batches}
+        ra_pages:\t{pages}
+    "}
 }
 
 impl SimpleDirOps for ThreadFdDir {
     fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
-        let Some(task) = self.task.upgrade() else {
+        let Ok(task) = live_task(&self.task) else {
             return Box::new(iter::empty());
         };
         let ids = FD_TABLE
@@ -170,17 +488,30 @@ impl SimpleDirOps for ThreadFdDir {
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let fs = self.fs.clone();
-        let task = self.task.upgrade().ok_or(VfsError::NotFound)?;
+        let task = live_task(&self.task)?;
         let fd = name.parse::<u32>().map_err(|_| VfsError::NotFound)?;
-        let path = FD_TABLE
+        let file = FD_TABLE
             .scope(&task.as_thread().proc_data.scope.read())
             .read()
             .get(fd as _)
             .ok_or(VfsError::NotFound)?
             .inner
-            .path()
-            .into_owned();
-        Ok(SimpleFile::new(fs, NodeType::Symlink, move || Ok(path.clone())).into())
+            .clone();
+        let task = self.task.clone();
+        if self.info {
+            let info = fd_info(file);
+            return Ok(SimpleFile::new_regular(fs, move || {
+                live_task(&task)?;
+                Ok(info.clone())
+            })
+            .into());
+        }
+        let path = file.path().into_owned();
+        Ok(SimpleFile::new(fs, NodeType::Symlink, move || {
+            live_task(&task)?;
+            Ok(path.clone())
+        })
+        .into())
     }
 
     fn supports_dentry_cache(&self) -> bool {
@@ -200,6 +531,7 @@ impl SimpleDirOps for ThreadDir {
             [
                 "stat",
                 "status",
+                "sched",
                 "oom_score_adj",
                 "task",
                 "maps",
@@ -208,6 +540,10 @@ impl SimpleDirOps for ThreadDir {
                 "comm",
                 "exe",
                 "fd",
+                "fdinfo",
+                "idle_pages",
+                "clear_refs",
+                "wss",
             ]
             .into_iter()
             .map(Cow::Borrowed),
@@ -216,18 +552,30 @@ impl SimpleDirOps for ThreadDir {
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let fs = self.fs.clone();
-        let task = self.task.upgrade().ok_or(VfsError::NotFound)?;
+        let task = live_task(&self.task)?;
+        // Files look the task up again on every read, see `live_task`.
+        let weak = self.task.clone();
         Ok(match name {
             "stat" => SimpleFile::new_regular(fs, move || {
+                let task = live_task(&weak)?;
                 Ok(format!("{}", TaskStat::from_thread(&task)?).into_bytes())
             })
             .into(),
-            "status" => SimpleFile::new_regular(fs, move || Ok(task_status(&task))).into(),
+            "status" => {
+                SimpleFile::new_regular(fs, move || Ok(task_status(&live_task(&weak)?))).into()
+            }
+            "sched" => {
+                SimpleFile::new_regular(fs, move || Ok(task_sched(&live_task(&weak)?))).into()
+            }
             "oom_score_adj" => SimpleFile::new_regular(
                 fs,
                 RwFile::new(move |req| match req {
                     SimpleFileOperation::Read => Ok(Some(
-                        task.as_thread().oom_score_adj().to_string().into_bytes(),
+                        live_task(&weak)?
+                            .as_thread()
+                            .oom_score_adj()
+                            .to_string()
+                            .into_bytes(),
                     )),
                     SimpleFileOperation::Write(data) => {
                         if !data.is_empty() {
@@ -235,7 +583,7 @@ impl SimpleDirOps for ThreadDir {
                                 .ok()
                                 .and_then(|it| it.parse::<i32>().ok())
                                 .ok_or(VfsError::InvalidInput)?;
-                            task.as_thread().set_oom_score_adj(value);
+                            live_task(&weak)?.as_thread().set_oom_score_adj(value);
                         }
                         Ok(None)
                     }
@@ -250,20 +598,13 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
-            "maps" => SimpleFile::new_regular(fs, move || {
-                Ok(indoc! {"
-                    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
-                    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
-                    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
-                    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
-                "})
-            })
-            .into(),
+            "maps" => SeqFile::new_regular(fs, Maps(weak)).into(),
             "mounts" => SimpleFile::new_regular(fs, move || {
                 Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
             })
             .into(),
             "cmdline" => SimpleFile::new_regular(fs, move || {
+                let task = live_task(&weak)?;
                 let cmdline = task.as_thread().proc_data.cmdline.read();
                 let mut buf = Vec::new();
                 for arg in cmdline.iter() {
@@ -277,24 +618,15 @@ impl SimpleDirOps for ThreadDir {
                 fs,
                 RwFile::new(move |req| match req {
                     SimpleFileOperation::Read => {
-                        let mut bytes = vec![0; 16];
-                        let name = task.name();
-                        let copy_len = name.len().min(15);
-                        bytes[..copy_len].copy_from_slice(&name.as_bytes()[..copy_len]);
-                        bytes[copy_len] = b'\n';
+                        let comm = live_task(&weak)?.comm();
+                        let len = comm.iter().position(|&b| b == 0).unwrap_or(comm.len());
+                        let mut bytes = comm[..len].to_vec();
+                        bytes.push(b'\n');
                         Ok(Some(bytes))
                     }
                     SimpleFileOperation::Write(data) => {
                         if !data.is_empty() {
-                            let mut input = [0; 16];
-                            let copy_len = data.len().min(15);
-                            input[..copy_len].copy_from_slice(&data[..copy_len]);
-                            task.set_name(
-                                CStr::from_bytes_until_nul(&input)
-                                    .map_err(|_| VfsError::InvalidInput)?
-                                    .to_str()
-                                    .map_err(|_| VfsError::InvalidInput)?,
-                            );
+                            live_task(&weak)?.set_comm(data);
                         }
                         Ok(None)
                     }
@@ -302,14 +634,77 @@ impl SimpleDirOps for ThreadDir {
             )
             .into(),
             "exe" => SimpleFile::new(fs, NodeType::Symlink, move || {
-                Ok(task.as_thread().proc_data.exe_path.read().clone())
+                Ok(live_task(&weak)?
+                    .as_thread()
+                    .proc_data
+                    .exe_path
+                    .read()
+                    .clone())
             })
             .into(),
-            "fd" => SimpleDir::new_maker(
+            "idle_pages" => Device::new(
+                fs,
+                NodeType::RegularFile,
+                DeviceId::default(),
+                Arc::new(IdlePages(Arc::downgrade(
+                    &task.as_thread().proc_data.aspace,
+                ))),
+            )
+            .into(),
+            // Writing 1 marks all pages of the process idle.
+            "clear_refs" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => Ok(Some(Vec::new())),
+                    SimpleFileOperation::Write(data) => {
+                        if str::from_utf8(data).map(str::trim) != Ok("1") {
+                            return Err(VfsError::InvalidInput);
+                        }
+                        let task = live_task(&weak)?;
+                        let mut aspace = task.as_thread().proc_data.aspace.lock();
+                        let (base, size) = (aspace.base(), aspace.size());
+                        aspace.mark_idle(base, size)?;
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            // Reads the working set size estimate in kB; writing a sampling
+            // interval in milliseconds starts the estimator, and 0 stops it.
+            "wss" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| {
+                    let task = live_task(&weak)?;
+                    let proc_data = &task.as_thread().proc_data;
+                    match req {
+                        SimpleFileOperation::Read => {
+                            let wss = proc_data.wss().and_then(|wss| wss.estimate());
+                            Ok(Some(format!("{}\n", wss.unwrap_or(0) / 1024).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            let ms = str::from_utf8(data)
+                                .ok()
+                                .and_then(|it| it.trim().parse::<u64>().ok())
+                                .ok_or(VfsError::InvalidInput)?;
+                            if ms == 0 {
+                                if let Some(wss) = proc_data.wss() {
+                                    wss.stop();
+                                }
+                            } else {
+                                proc_data.wss_or_init().start(Duration::from_millis(ms));
+                            }
+                            Ok(None)
+                        }
+                    }
+                }),
+            )
+            .into(),
+            "fd" | "fdinfo" => SimpleDir::new_maker(
                 fs.clone(),
                 Arc::new(ThreadFdDir {
                     fs,
-                    task: Arc::downgrade(&task),
+                    task: weak,
+                    info: name == "fdinfo",
                 }),
             )
             .into(),
@@ -323,14 +718,17 @@ impl SimpleDirOps for ThreadDir {
 }
 
 /// Handles /proc/[pid] & /proc/self
+///
+/// Processes are listed, threads are found under /proc/[pid]/task but can
+/// be looked up here as well, as on Linux.
 struct ProcFsHandler(Arc<SimpleFs>);
 
 impl SimpleDirOps for ProcFsHandler {
     fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
         Box::new(
-            tasks()
+            processes()
                 .into_iter()
-                .map(|task| task.id().as_u64().to_string().into())
+                .map(|proc_data| proc_data.proc.pid().to_string().into())
                 .chain([Cow::Borrowed("self")]),
         )
     }
@@ -339,8 +737,8 @@ impl SimpleDirOps for ProcFsHandler {
         let task = if name == "self" {
             current().clone()
         } else {
-            let tid = name.parse::<u32>().map_err(|_| VfsError::NotFound)?;
-            get_task(tid).map_err(|_| VfsError::NotFound)?
+            let pid = name.parse::<u32>().map_err(|_| VfsError::NotFound)?;
+            process_task(pid)?
         };
         let node = NodeOpsMux::Dir(SimpleDir::new_maker(
             self.0.clone(),
@@ -357,6 +755,27 @@ impl SimpleDirOps for ProcFsHandler {
     }
 }
 
+/// The IRQ lines handled so far and their counts, shown in /proc/interrupts.
+struct Interrupts;
+
+impl SeqSource for Interrupts {
+    type Key = usize;
+
+    fn render(&self, after: Option<&usize>, chunk: &mut SeqChunk<usize>) -> VfsResult<u64> {
+        let generation = irq::irq_lines() as u64;
+        for irq in after.map_or(0, |irq| irq + 1)..irq::MAX_COUNTED_IRQS {
+            if chunk.is_full() {
+                break;
+            }
+            let count = irq::irq_count(irq);
+            if count > 0 {
+                chunk.push(irq, format_args!("{irq:>4}: {count:>10}\n"));
+            }
+        }
+        Ok(generation)
+    }
+}
+
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
@@ -390,9 +809,53 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         }),
     );
     root.add(
-        "interrupts",
-        SimpleFile::new_regular(fs.clone(), || Ok(format!("0: {}", crate::time::irq_cnt()))),
+        "selftest",
+        SimpleFile::new_regular(fs.clone(), || {
+            Ok(selftest::last_report().unwrap_or_else(|| "no self-test has run\n".to_string()))
+        }),
+    );
+    root.add(
+        "boottime",
+        SimpleFile::new_regular(fs.clone(), || Ok(boottime::report())),
+    );
+    root.add(
+        "preempt",
+        SimpleFile::new_regular(fs.clone(), || {
+            let mut text = format!("model={}\n", ktask::preempt_model());
+            if let Some(max) = ktask::max_non_preempt_section() {
+                text += &format!("max_section={max}\n");
+            }
+            Ok(text)
+        }),
+    );
+    root.add(
+        "refclock",
+        SimpleFile::new_regular(fs.clone(), || {
+            let params = khal::time::ReferenceParams::sample();
+            Ok(format!(
+                "freq_hz={}\nref={}\nmonotonic_ns={}\n",
+                params.freq, params.ticks, params.monotonic_ns
+            ))
+        }),
     );
+    root.add(
+        "kvmap",
+        SimpleFile::new_regular(fs.clone(), || {
+            let mut text = String::new();
+            khal::kvmap::write_report(&mut text).map_err(|_| VfsError::InvalidData)?;
+            Ok(text)
+        }),
+    );
+    root.add("interrupts", SeqFile::new_regular(fs.clone(), Interrupts));
+
+    root.add("net", {
+        let mut net = DirMapping::new();
+        net.add(
+            "netconfig",
+            SimpleFile::new_regular(fs.clone(), || Ok(knet::netconfig::report())),
+        );
+        SimpleDir::new_maker(fs.clone(), Arc::new(net))
+    });
 
     root.add("sys", {
         let mut sys = DirMapping::new();
@@ -404,13 +867,207 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
                 "pid_max",
                 SimpleFile::new_regular(fs.clone(), || Ok("32768\n")),
             );
+            kernel.add(
+                "sched_rr_timeslice_ms",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!("{}\n", ktask::rr_timeslice().as_millis()).into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<u64>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                if !ktask::set_rr_timeslice(Duration::from_millis(value)) {
+                                    return Err(VfsError::InvalidInput);
+                                }
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+            kernel.add(
+                "sched_rt_period_us",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!("{}\n", ktask::rt_bandwidth().period.as_micros()).into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<u64>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                let bandwidth = ktask::RtBandwidth {
+                                    period: Duration::from_micros(value),
+                                    ..ktask::rt_bandwidth()
+                                };
+                                if !ktask::set_rt_bandwidth(bandwidth) {
+                                    return Err(VfsError::InvalidInput);
+                                }
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+            // -1 disables RT throttling, as in Linux.
+            kernel.add(
+                "sched_rt_runtime_us",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => {
+                            let runtime = ktask::rt_bandwidth()
+                                .runtime
+                                .map_or(-1, |runtime| runtime.as_micros() as i64);
+                            Ok(Some(format!("{runtime}\n").into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<i64>().ok())
+                                    .filter(|&it| it >= -1)
+                                    .ok_or(VfsError::InvalidInput)?;
+                                let bandwidth = ktask::RtBandwidth {
+                                    runtime: (value >= 0)
+                                        .then(|| Duration::from_micros(value as u64)),
+                                    ..ktask::rt_bandwidth()
+                                };
+                                if !ktask::set_rt_bandwidth(bandwidth) {
+                                    return Err(VfsError::InvalidInput);
+                                }
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
 
             SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
         });
 
+        sys.add("vm", {
+            let mut vm = DirMapping::new();
+
+            vm.add(
+                "max_map_count",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!("{}\n", memspace::max_map_count()).into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<usize>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                memspace::set_max_map_count(value);
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(vm))
+        });
+
+        sys.add("fs", {
+            let mut fs_dir = DirMapping::new();
+
+            fs_dir.add(
+                "negative-dentry-stats",
+                SimpleFile::new_regular(fs.clone(), || {
+                    let stats = fs_ng_vfs::negative_dentry_stats();
+                    Ok(format!("hits {}\nmisses {}\n", stats.hits, stats.misses))
+                }),
+            );
+            #[cfg(feature = "ext4-scrub")]
+            fs_dir.add(
+                "scrub",
+                SimpleFile::new_regular(fs.clone(), || Ok(kfs::scrub::report())),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(fs_dir))
+        });
+
+        #[cfg(debug_assertions)]
+        sys.add("debug", {
+            use crate::syscall::fault;
+
+            let mut debug = DirMapping::new();
+
+            debug.add(
+                "syscall_faults",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(fault::format_rules().into_bytes())),
+                        SimpleFileOperation::Write(data) => {
+                            let data = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+                            for line in data.lines() {
+                                fault::apply_command(line)?;
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(debug))
+        });
+
         SimpleDir::new_maker(fs.clone(), Arc::new(sys))
     });
 
     let proc_dir = ProcFsHandler(fs.clone());
     SimpleDir::new_maker(fs, Arc::new(proc_dir.chain(root)))
 }
+
+#[cfg(unittest)]
+mod tests_proc {
+    use unittest::{assert_eq, def_test};
+
+    use super::*;
+
+    #[def_test]
+    fn test_maps_line() {
+        let mut line = MapsLine {
+            range: VirtAddrRange::from_start_size(VirtAddr::from(0x40_0000), 0x1000),
+            flags: MappingFlags::READ | MappingFlags::EXECUTE,
+            shared: false,
+            offset: 0x2000,
+            device: DeviceId::new(8, 2),
+            inode: 173521,
+            path: "/usr/bin/true",
+        };
+        let text = line.to_string();
+        assert_eq!(
+            &text[..MAPS_PATH_COLUMN],
+            "00400000-00401000 r-xp 00002000 08:02 173521                             "
+        );
+        assert_eq!(&text[MAPS_PATH_COLUMN..], "/usr/bin/true\n");
+
+        // Anonymous areas end after the inode.
+        line.flags = MappingFlags::READ | MappingFlags::WRITE;
+        line.shared = true;
+        line.offset = 0;
+        line.device = DeviceId::default();
+        line.inode = 0;
+        line.path = "";
+        assert_eq!(
+            line.to_string(),
+            "00400000-00401000 rw-s 00000000 00:00 0 \n"
+        );
+    }
+}
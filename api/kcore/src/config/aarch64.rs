@@ -29,3 +29,10 @@ pub const USER_INTERP_BASE: usize = 0x400_0000;
 
 /// The address of signal trampoline (placed at top of user heap).
 pub const SIGNAL_TRAMPOLINE: usize = 0x6000_1000;
+
+/// The end of the user space of 32-bit programs.
+#[cfg(feature = "compat")]
+pub const COMPAT_USER_SPACE_END: usize = 0x1_0000_0000;
+/// The highest address of the user stack of 32-bit programs.
+#[cfg(feature = "compat")]
+pub const COMPAT_USER_STACK_TOP: usize = 0xffff_0000;
@@ -4,26 +4,21 @@
 
 //! Futex implementation.
 
-use alloc::{
-    collections::vec_deque::VecDeque,
-    sync::{Arc, Weak},
-    vec::Vec,
-};
+use alloc::sync::{Arc, Weak};
 use core::{
-    future::poll_fn,
     ops::Deref,
-    sync::atomic::AtomicBool,
-    task::{Poll, Waker},
-    time::Duration,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
 use hashbrown::HashMap;
-use kerrno::KResult;
-use kspin::SpinNoIrq;
-use ksync::Mutex;
-use ktask::{
-    current,
-    future::{self, block_on, interruptible},
+use kerrno::{KError, KResult};
+use khal::time::TimeValue;
+use ksync::{Mutex, MutexGuard, WaitResult};
+use ktask::current;
+use linux_raw_sys::general::{
+    FUTEX_OP_ADD, FUTEX_OP_ANDN, FUTEX_OP_CMP_EQ, FUTEX_OP_CMP_GE, FUTEX_OP_CMP_GT,
+    FUTEX_OP_CMP_LE, FUTEX_OP_CMP_LT, FUTEX_OP_CMP_NE, FUTEX_OP_OPARG_SHIFT, FUTEX_OP_OR,
+    FUTEX_OP_SET, FUTEX_OP_XOR,
 };
 use memaddr::VirtAddr;
 use memspace::{
@@ -33,131 +28,127 @@ use memspace::{
 
 use crate::task::AsThread;
 
-/// Wait queue used by futex.
+/// Wait queue used by futex, whose waiters are tagged with their bitset.
 #[derive(Default)]
-pub struct WaitQueue {
-    queue: SpinNoIrq<VecDeque<(Waker, u32)>>,
-}
+pub struct WaitQueue(ksync::WaitQueue<u32>);
+
 impl WaitQueue {
     /// Creates a new `WaitQueue`.
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Waits if the given condition is met.
+    /// Waits until woken or the wall time reaches `deadline`, releasing
+    /// `guard`, the bucket lock of the futex, once queued.
     ///
-    /// Returns `false` if the condition is not met and no actual waiting
-    /// occurs.
-    pub fn wait_if(
+    /// A waker takes the bucket lock too, so it cannot miss a task that
+    /// checked the futex word under it.
+    pub fn wait_releasing<G>(
         &self,
         bitset: u32,
-        timeout: Option<Duration>,
-        condition: impl FnOnce() -> bool,
-    ) -> KResult<bool> {
-        let mut condition = Some(condition);
-        block_on(interruptible(future::timeout(
-            timeout,
-            poll_fn(|cx| {
-                if let Some(cond) = condition.take() {
-                    let mut queue = self.queue.lock();
-                    if !cond() {
-                        Poll::Ready(Ok(false))
-                    } else {
-                        queue.push_back((cx.waker().clone(), bitset));
-                        Poll::Pending
-                    }
-                } else {
-                    Poll::Ready(Ok(true))
-                }
-            }),
-        )))??
+        deadline: Option<TimeValue>,
+        guard: G,
+    ) -> KResult<()> {
+        match self.0.wait_releasing(bitset, deadline, guard) {
+            WaitResult::Notified => Ok(()),
+            WaitResult::TimedOut => Err(KError::TimedOut),
+            WaitResult::Interrupted => Err(KError::Interrupted),
+        }
     }
 
     /// Wakes up at most `count` tasks whose bitset intersects with the given
     /// bitmask.
     pub fn wake(&self, count: usize, mask: u32) -> usize {
-        let mut woke = 0;
-        self.queue.lock().retain(|(waker, bitset)| {
-            if woke >= count || (bitset & mask) == 0 {
-                true
-            } else {
-                waker.wake_by_ref();
-                woke += 1;
-                false
-            }
-        });
-        woke
+        self.0.notify_many_if(count, |bitset| bitset & mask != 0)
     }
 
     /// Checks if the wait queue is empty.
     pub fn is_empty(&self) -> bool {
-        self.queue.lock().is_empty()
+        self.0.is_empty()
+    }
+
+    /// Returns the number of waiting tasks.
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
 
     /// Requeue at most `count` tasks to the target wait queue.
-    pub fn requeue(&self, mut count: usize, target: &WaitQueue) -> usize {
-        let tasks: Vec<_> = {
-            let mut wq = self.queue.lock();
-            count = count.min(wq.len());
-            wq.drain(..count).collect()
-        };
-        if !tasks.is_empty() {
-            let mut wq = target.queue.lock();
-            wq.extend(tasks);
-        }
-        count
+    pub fn requeue(&self, count: usize, target: &WaitQueue) -> usize {
+        self.0.requeue(count, &target.0)
     }
 }
 
 /// A key that uniquely identifies a futex in the system.
 pub enum FutexKey {
-    /// A futex that is private to the current process.
+    /// A futex that is private to the current process, or in memory that is
+    /// not shared with other processes.
     Private {
         /// The memory address of the futex.
         address: usize,
     },
 
-    /// A futex in a shared memory region.
+    /// A futex in shared anonymous memory.
     Shared {
         /// The offset of the futex within the shared memory region.
         offset: usize,
         /// The shared memory region.
-        region: Result<Weak<SharedPages>, Weak<()>>,
+        region: Weak<SharedPages>,
+    },
+
+    /// A futex in a shared file mapping.
+    Inode {
+        /// The offset of the futex in the file.
+        offset: u64,
+        /// The filesystem of the file, as a pointer.
+        fs: usize,
+        /// The inode number of the file.
+        inode: u64,
     },
 }
 
 impl FutexKey {
     /// Creates a new `FutexKey`.
-    pub fn new(aspace: &AddrSpace, address: usize) -> Self {
-        if let Some(area) = aspace.find_area(VirtAddr::from_usize(address)) {
-            match area.backend() {
-                Backend::Shared(backend) => {
-                    return Self::Shared {
-                        offset: address - area.start().as_usize(),
-                        region: Ok(Arc::downgrade(backend.pages())),
-                    };
-                }
-                Backend::File(file) => {
-                    return Self::Shared {
-                        offset: address - area.start().as_usize(),
-                        region: Err(file.futex_dispatch_irq()),
-                    };
+    ///
+    /// Unless `shared` is set, as without `FUTEX_PRIVATE_FLAG`, the futex is
+    /// keyed by its address, even in shared memory.
+    pub fn new(aspace: &AddrSpace, address: usize, shared: bool) -> Self {
+        let Some(area) = aspace
+            .find_area(VirtAddr::from_usize(address))
+            .filter(|_| shared)
+        else {
+            return Self::Private { address };
+        };
+        match area.backend() {
+            Backend::Shared(backend) => Self::Shared {
+                offset: address - area.start().as_usize(),
+                region: Arc::downgrade(backend.pages()),
+            },
+            Backend::File(file) => {
+                let (loc, offset) = file.mapped_file(VirtAddr::from_usize(address));
+                Self::Inode {
+                    offset,
+                    fs: loc.filesystem() as *const _ as *const () as usize,
+                    inode: loc.inode(),
                 }
-                _ => {}
             }
+            _ => Self::Private { address },
         }
-        Self::Private { address }
     }
 
     /// Shortcut to create a `FutexKey` for the current task's address space.
-    pub fn new_current(address: usize) -> Self {
-        Self::new(&current().as_thread().proc_data.aspace.lock(), address)
+    pub fn new_current(address: usize, shared: bool) -> Self {
+        Self::new(
+            &current().as_thread().proc_data.aspace.lock(),
+            address,
+            shared,
+        )
     }
 
     fn as_usize(&self) -> usize {
         match self {
             FutexKey::Private { address } => *address,
             FutexKey::Shared { offset, .. } => *offset,
+            FutexKey::Inode { offset, .. } => *offset as usize,
         }
     }
 }
@@ -167,6 +158,10 @@ pub struct FutexEntry {
     /// The wait queue associated with this futex.
     pub wq: WaitQueue,
 
+    /// The bucket lock, held while the futex word is checked or changed
+    /// before waiting on or waking [`FutexEntry::wq`].
+    pub lock: Mutex<()>,
+
     /// Used by robust list, indicates if the owner of this futex is dead.
     pub owner_dead: AtomicBool,
 }
@@ -175,11 +170,84 @@ impl FutexEntry {
     fn new() -> Self {
         Self {
             wq: WaitQueue::new(),
+            lock: Mutex::new(()),
             owner_dead: AtomicBool::new(false),
         }
     }
 }
 
+/// Takes the bucket locks of two futexes, in the order of their addresses so
+/// that two tasks requeueing between them in opposite directions cannot
+/// deadlock. The second guard is `None` if both are the same futex.
+pub fn lock_pair<'a>(
+    a: &'a FutexEntry,
+    b: &'a FutexEntry,
+) -> (MutexGuard<'a, ()>, Option<MutexGuard<'a, ()>>) {
+    if core::ptr::eq(a, b) {
+        return (a.lock.lock(), None);
+    }
+    if (a as *const FutexEntry) < (b as *const FutexEntry) {
+        let first = a.lock.lock();
+        (first, Some(b.lock.lock()))
+    } else {
+        let second = b.lock.lock();
+        (a.lock.lock(), Some(second))
+    }
+}
+
+/// The operation of `FUTEX_WAKE_OP`, decoded from its `val3` argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakeOp {
+    op: u32,
+    oparg: u32,
+    cmp: u32,
+    cmparg: i32,
+}
+
+impl WakeOp {
+    /// Decodes `val3`, failing with [`KError::Unsupported`] for an unknown
+    /// operation or comparison, as Linux does.
+    pub fn decode(val3: u32) -> KResult<Self> {
+        let mut op = (val3 >> 28) & 0xf;
+        // Both arguments are 12-bit signed integers.
+        let mut oparg = ((val3 << 8) as i32 >> 20) as u32;
+        if op & FUTEX_OP_OPARG_SHIFT != 0 {
+            op &= !FUTEX_OP_OPARG_SHIFT;
+            oparg = 1 << (oparg & 31);
+        }
+        let cmp = (val3 >> 24) & 0xf;
+        if op > FUTEX_OP_XOR || cmp > FUTEX_OP_CMP_GE {
+            return Err(KError::Unsupported);
+        }
+        Ok(Self {
+            op,
+            oparg,
+            cmp,
+            cmparg: (val3 << 20) as i32 >> 20,
+        })
+    }
+
+    /// Applies the operation to `word`, returning whether the comparison
+    /// holds for its old value.
+    pub fn apply(&self, word: &AtomicU32) -> bool {
+        let old = match self.op {
+            FUTEX_OP_SET => word.swap(self.oparg, Ordering::SeqCst),
+            FUTEX_OP_ADD => word.fetch_add(self.oparg, Ordering::SeqCst),
+            FUTEX_OP_OR => word.fetch_or(self.oparg, Ordering::SeqCst),
+            FUTEX_OP_ANDN => word.fetch_and(!self.oparg, Ordering::SeqCst),
+            _ => word.fetch_xor(self.oparg, Ordering::SeqCst),
+        } as i32;
+        match self.cmp {
+            FUTEX_OP_CMP_EQ => old == self.cmparg,
+            FUTEX_OP_CMP_NE => old != self.cmparg,
+            FUTEX_OP_CMP_LT => old < self.cmparg,
+            FUTEX_OP_CMP_LE => old <= self.cmparg,
+            FUTEX_OP_CMP_GT => old > self.cmparg,
+            _ => old >= self.cmparg,
+        }
+    }
+}
+
 /// A table mapping memory addresses to futex wait queues.
 pub struct FutexTable(Mutex<HashMap<usize, Arc<FutexEntry>>>);
 
@@ -268,6 +336,53 @@ pub mod tests_futex {
         assert!(dst.is_empty());
     }
 
+    #[def_test]
+    fn test_lock_pair_same_entry() {
+        let entry = FutexEntry::new();
+        let (_first, second) = lock_pair(&entry, &entry);
+        assert!(second.is_none());
+    }
+
+    #[def_test]
+    fn test_lock_pair_both_orders() {
+        let a = FutexEntry::new();
+        let b = FutexEntry::new();
+        {
+            let (_first, second) = lock_pair(&a, &b);
+            assert!(second.is_some());
+            assert!(a.lock.try_lock().is_none());
+            assert!(b.lock.try_lock().is_none());
+        }
+        let (_first, second) = lock_pair(&b, &a);
+        assert!(second.is_some());
+    }
+
+    #[def_test]
+    fn test_wake_op_decode_apply() {
+        // FUTEX_OP(FUTEX_OP_ADD, 1, FUTEX_OP_CMP_GT, 0), as glibc encodes it.
+        let op =
+            WakeOp::decode((FUTEX_OP_ADD << 28) | (FUTEX_OP_CMP_GT << 24) | (1 << 12)).unwrap();
+        let word = AtomicU32::new(1);
+        assert!(op.apply(&word));
+        assert_eq!(word.load(Ordering::SeqCst), 2);
+        word.store(0, Ordering::SeqCst);
+        assert!(!op.apply(&word));
+
+        // Negative arguments are sign-extended, and compared signed.
+        let op = WakeOp::decode((FUTEX_OP_SET << 28) | (FUTEX_OP_CMP_LT << 24) | 0xfff).unwrap();
+        word.store(u32::MAX - 1, Ordering::SeqCst);
+        assert!(op.apply(&word));
+        assert_eq!(word.load(Ordering::SeqCst), 0);
+
+        let shift = FUTEX_OP_OR | FUTEX_OP_OPARG_SHIFT;
+        let op = WakeOp::decode((shift << 28) | (4 << 12)).unwrap();
+        op.apply(&word);
+        assert_eq!(word.load(Ordering::SeqCst), 1 << 4);
+
+        assert_eq!(WakeOp::decode(7 << 28), Err(KError::Unsupported));
+        assert_eq!(WakeOp::decode(6 << 24), Err(KError::Unsupported));
+    }
+
     #[def_test]
     fn test_futextable_insert_drop() {
         let table = FutexTable::new();
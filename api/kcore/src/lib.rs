@@ -19,6 +19,7 @@ pub mod config;
 pub mod futex;
 mod lrucache;
 pub mod mm;
+pub mod perf;
 pub mod resources;
 pub mod shm;
 pub mod task;
@@ -9,7 +9,9 @@ use core::{ffi::CStr, hint::unlikely, iter, mem::MaybeUninit};
 
 use extern_trait::extern_trait;
 use fs_ng_vfs::Location;
-use kernel_elf_parser::{AuxEntry, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region};
+use kernel_elf_parser::{
+    AuxEntry, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region, app_stack_region32,
+};
 use kerrno::{KError, KResult};
 use kfs::{CachedFile, FS_CONTEXT, FileBackend};
 use khal::{
@@ -20,7 +22,7 @@ use khal::{
 use kspin::IrqSave;
 use ksync::Mutex;
 use ktask::current;
-use memaddr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use memaddr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use memspace::{AddrSpace, backend::Backend};
 use osvm::{MemError, MemResult, VirtMemIo};
 use ouroboros::self_referencing;
@@ -137,6 +139,36 @@ fn map_elf<'a>(
     Ok(elf_parser)
 }
 
+/// Returns the page-aligned range of addresses the `PT_LOAD` segments of
+/// `elf` span, before relocation.
+fn load_span(elf: &ELFHeaders) -> (usize, usize) {
+    let (start, end) = elf
+        .ph
+        .iter()
+        .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
+        .fold((usize::MAX, 0), |(start, end), ph| {
+            let vaddr = ph.virtual_addr as usize;
+            (start.min(vaddr), end.max(vaddr + ph.mem_size as usize))
+        });
+    (start.align_down_4k(), end.align_up_4k())
+}
+
+/// Returns whether `elf` is a 32-bit ARM program, run in AArch32 state, or
+/// fails if it is not a program this kernel runs.
+fn is_compat_elf(elf: &ELFHeaders) -> KResult<bool> {
+    match elf.header.pt1.class() {
+        xmas_elf::header::Class::SixtyFour => Ok(false),
+        #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+        xmas_elf::header::Class::ThirtyTwo
+            if elf.header.pt2.machine().as_machine() == xmas_elf::header::Machine::Arm
+                && khal::uspace::aarch32_el0_supported() =>
+        {
+            Ok(true)
+        }
+        _ => Err(KError::InvalidExecutable),
+    }
+}
+
 fn map_elf_error(err: &'static str) -> KError {
     debug!("Failed to parse ELF file: {err}");
     KError::InvalidExecutable
@@ -178,7 +210,7 @@ impl ElfCacheEntry {
 
 struct ElfLoader(LruCache<ElfCacheEntry, 32>);
 
-type LoadResult = Result<(VirtAddr, Vec<AuxEntry>), Vec<u8>>;
+type LoadResult = Result<(VirtAddr, Vec<AuxEntry>, bool), Vec<u8>>;
 
 impl ElfLoader {
     const fn new() -> Self {
@@ -239,9 +271,36 @@ impl ElfLoader {
             (entry, None)
         };
 
+        let compat = is_compat_elf(elf.borrow_elf())?;
+        if let Some(ldso) = &ldso
+            && is_compat_elf(ldso.borrow_elf())? != compat
+        {
+            return Err(KError::InvalidExecutable);
+        }
+
+        // A position independent executable is relocated to the start of the
+        // user space, others are mapped where they are linked.
         let elf = map_elf(uspace, crate::config::USER_SPACE_BASE, elf)?;
+        // The dynamic linker goes to the first free range from
+        // `USER_INTERP_BASE` on, below the heap, so that a large executable
+        // does not overlap it.
         let ldso = ldso
-            .map(|elf| map_elf(uspace, crate::config::USER_INTERP_BASE, elf))
+            .map(|ldso| {
+                let (start, end) = load_span(ldso.borrow_elf());
+                let limit = VirtAddrRange::new(
+                    VirtAddr::from_usize(crate::config::USER_SPACE_BASE),
+                    VirtAddr::from_usize(crate::config::USER_HEAP_BASE),
+                );
+                let free = uspace
+                    .find_free_area(
+                        VirtAddr::from_usize(crate::config::USER_INTERP_BASE),
+                        end - start,
+                        limit,
+                        PAGE_SIZE_4K,
+                    )
+                    .ok_or(KError::NoMemory)?;
+                map_elf(uspace, free.as_usize() - start, ldso)
+            })
             .transpose()?;
 
         let entry = VirtAddr::from_usize(
@@ -252,12 +311,66 @@ impl ElfLoader {
             .aux_vector(PAGE_SIZE_4K, ldso.map(|elf| elf.base()))
             .collect::<Vec<_>>();
 
-        Ok(Ok((entry, auxv)))
+        Ok(Ok((entry, auxv, compat)))
     }
 }
 
 static ELF_LOADER: Mutex<ElfLoader> = Mutex::new(ElfLoader::new());
 
+/// Maximum number of interpreter scripts run through by one exec, as when the
+/// interpreter of a script is itself a script.
+const MAX_SCRIPT_DEPTH: usize = 4;
+
+/// Maximum length of the `#!` line, as Linux.
+const MAX_SCRIPT_LINE: usize = 256;
+
+/// Returns the arguments running the script at `path` with `args`, if `data`
+/// starts with a `#!` line.
+///
+/// As Linux, the line names the interpreter, then at most one argument made
+/// of the rest of the line. The interpreter gets them followed by `path` and
+/// the arguments of the script but the first.
+fn script_args(data: &[u8], path: &str, args: &[String]) -> KResult<Option<Vec<String>>> {
+    let Some(head) = data.strip_prefix(b"#!") else {
+        return Ok(None);
+    };
+    let head = &head[..head.len().min(MAX_SCRIPT_LINE - 2)];
+    let line = head.split(|c| *c == b'\n').next().unwrap_or_default();
+    let line = core::str::from_utf8(line)
+        .map_err(|_| KError::InvalidExecutable)?
+        .trim_ascii();
+    let (interp, arg) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((interp, arg)) => (interp, Some(arg.trim_ascii())),
+        None => (line, None),
+    };
+    if interp.is_empty() {
+        return Err(KError::InvalidExecutable);
+    }
+
+    let new_args = iter::once(interp)
+        .chain(arg)
+        .chain(iter::once(path))
+        .map(str::to_owned)
+        .chain(args.iter().skip(1).cloned())
+        .collect();
+    Ok(Some(new_args))
+}
+
+/// Returns the bytes `AT_RANDOM` points to.
+fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    let read = (|| -> KResult<usize> {
+        let loc = FS_CONTEXT.lock().resolve("/dev/urandom")?;
+        Ok(loc.entry().as_file()?.read_at(&mut bytes, 0)?)
+    })();
+    if read.ok() != Some(bytes.len()) {
+        // No device file yet, e.g. for init: fall back on the clock.
+        let now = khal::time::monotonic_time_nanos() as u128;
+        bytes = (now.wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835)).to_ne_bytes();
+    }
+    bytes
+}
+
 /// Clear the ELF cache.
 ///
 /// Useful for removing noises during memory leak detect.
@@ -265,8 +378,24 @@ pub fn clear_elf_cache() {
     ELF_LOADER.lock().0.flush();
 }
 
+/// A program loaded by [`load_user_app`].
+pub struct UserImage {
+    /// The entry point of the program.
+    pub entry: VirtAddr,
+    /// The initial stack pointer of the program.
+    pub sp: VirtAddr,
+    /// Whether the program is 32-bit ARM code, run in AArch32 state.
+    pub compat: bool,
+}
+
 /// Load the user app to the user address space.
 ///
+/// A script starting with `#!` runs its interpreter instead, through at most
+/// [`MAX_SCRIPT_DEPTH`] scripts. A dynamically linked program starts in the
+/// dynamic linker named by its `PT_INTERP`, with the auxiliary vector
+/// describing the program. With the `compat` feature on AArch64, 32-bit ARM
+/// programs are loaded too, with their stack below 4 GiB.
+///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
 /// - `args`: The arguments of the user app. The first argument is the path of
@@ -274,14 +403,23 @@ pub fn clear_elf_cache() {
 /// - `envs`: The environment variables of the user app.
 ///
 /// # Returns
-/// - The entry point of the user app.
-/// - The stack pointer of the user app.
+/// The entry point and the stack pointer of the user app.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
     path: Option<&str>,
     args: &[String],
     envs: &[String],
-) -> KResult<(VirtAddr, VirtAddr)> {
+) -> KResult<UserImage> {
+    load_user_app_at_depth(uspace, path, args, envs, 0)
+}
+
+fn load_user_app_at_depth(
+    uspace: &mut AddrSpace,
+    path: Option<&str>,
+    args: &[String],
+    envs: &[String],
+    depth: usize,
+) -> KResult<UserImage> {
     let path = path
         .or_else(|| args.first().map(String::as_str))
         .ok_or(KError::InvalidInput)?;
@@ -291,30 +429,27 @@ pub fn load_user_app(
         let new_args: Vec<String> = iter::once("/bin/sh".to_owned())
             .chain(args.iter().cloned())
             .collect();
-        return load_user_app(uspace, None, &new_args, envs);
+        return load_user_app_at_depth(uspace, None, &new_args, envs, depth);
     }
 
-    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, path)? } {
-        Ok((entry, auxv)) => (entry, auxv),
+    let (entry, auxv, compat) = match { ELF_LOADER.lock().load(uspace, path)? } {
+        Ok(loaded) => loaded,
         Err(data) => {
-            if data.starts_with(b"#!") {
-                let head = &data[2..data.len().min(256)];
-                let pos = head.iter().position(|c| *c == b'\n').unwrap_or(head.len());
-                let line = core::str::from_utf8(&head[..pos]).map_err(|_| KError::InvalidInput)?;
-
-                let new_args: Vec<String> = line
-                    .trim()
-                    .splitn(2, |c: char| c.is_ascii_whitespace())
-                    .map(|s| s.trim_ascii().to_owned())
-                    .chain(iter::once(path.to_owned()))
-                    .chain(args.iter().skip(1).cloned())
-                    .collect();
-                return load_user_app(uspace, None, &new_args, envs);
+            let new_args = script_args(&data, path, args)?.ok_or(KError::InvalidExecutable)?;
+            if depth >= MAX_SCRIPT_DEPTH {
+                return Err(KError::FilesystemLoop);
             }
-            return Err(KError::InvalidExecutable);
+            return load_user_app_at_depth(uspace, None, &new_args, envs, depth + 1);
         }
     };
 
+    #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+    let ustack_top = VirtAddr::from_usize(if compat {
+        crate::config::COMPAT_USER_STACK_TOP
+    } else {
+        crate::config::USER_STACK_TOP
+    });
+    #[cfg(not(all(target_arch = "aarch64", feature = "compat")))]
     let ustack_top = VirtAddr::from_usize(crate::config::USER_STACK_TOP);
     let ustack_size = crate::config::USER_STACK_SIZE;
     let ustack_start = ustack_top - ustack_size;
@@ -328,7 +463,11 @@ pub fn load_user_app(
         Backend::new_alloc(ustack_start, PageSize::Size4K),
     )?;
 
-    let stack_data = app_stack_region(args, envs, &auxv, ustack_top.into());
+    let stack_data = if compat {
+        app_stack_region32(args, envs, &auxv, &random_bytes(), ustack_top.into())
+    } else {
+        app_stack_region(args, envs, &auxv, &random_bytes(), ustack_top.into())
+    };
     let user_sp = ustack_top - stack_data.len();
     let user_sp_aligned = user_sp.align_down_4k();
     uspace.populate_area(
@@ -348,7 +487,11 @@ pub fn load_user_app(
         Backend::new_alloc(heap_start, PageSize::Size4K),
     )?;
 
-    Ok((entry, user_sp))
+    Ok(UserImage {
+        entry,
+        sp: user_sp,
+        compat,
+    })
 }
 
 /// Enables scoped access into user memory, allowing page faults to occur inside
@@ -368,9 +511,10 @@ pub fn access_user_memory<R>(f: impl FnOnce() -> R) -> R {
 #[allow(dead_code)]
 struct Vm(IrqSave);
 
+const USER_SPACE_END: usize = USER_SPACE_BASE + USER_SPACE_SIZE;
+
 /// Briefly checks if the given memory region is valid user memory.
 pub fn check_access(start: usize, len: usize) -> MemResult {
-    const USER_SPACE_END: usize = USER_SPACE_BASE + USER_SPACE_SIZE;
     let ok = (USER_SPACE_BASE..USER_SPACE_END).contains(&start) && (USER_SPACE_END - start) >= len;
     if unlikely(!ok) {
         Err(MemError::NoAccess)
@@ -408,15 +552,52 @@ unsafe impl VirtMemIo for Vm {
             Ok(())
         }
     }
+
+    fn read_mem_partial(&mut self, start: usize, buf: &mut [MaybeUninit<u8>]) -> MemResult<usize> {
+        let len = user_len(start, buf.len())?;
+        let not_copied = access_user_memory(|| unsafe {
+            user_copy(buf.as_mut_ptr() as *mut _, start as _, len)
+        });
+        copied(len, not_copied)
+    }
+
+    fn write_mem_partial(&mut self, start: usize, buf: &[u8]) -> MemResult<usize> {
+        let len = user_len(start, buf.len())?;
+        let not_copied =
+            access_user_memory(|| unsafe { user_copy(start as _, buf.as_ptr() as *const _, len) });
+        copied(len, not_copied)
+    }
+}
+
+/// Returns how many of the `len` bytes from `start` lie in user memory.
+fn user_len(start: usize, len: usize) -> MemResult<usize> {
+    check_access(start, 0)?;
+    Ok(len.min(USER_SPACE_END - start))
+}
+
+/// Turns the result of a `user_copy` of `len` bytes into the bytes copied.
+fn copied(len: usize, not_copied: usize) -> MemResult<usize> {
+    if unlikely(not_copied == len && len != 0) {
+        Err(MemError::NoAccess)
+    } else {
+        Ok(len - not_copied)
+    }
 }
 
 /// Unit tests.
 #[cfg(unittest)]
 pub mod tests_mm {
+    use alloc::{string::String, vec::Vec};
+
+    use kerrno::KError;
     use osvm::MemError;
     use unittest::def_test;
 
-    use super::{USER_SPACE_BASE, USER_SPACE_SIZE, check_access};
+    use super::{USER_SPACE_BASE, USER_SPACE_SIZE, check_access, script_args};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| String::from(*s)).collect()
+    }
 
     #[def_test]
     fn test_check_access_valid() {
@@ -434,4 +615,35 @@ pub mod tests_mm {
         let res = check_access(USER_SPACE_BASE, USER_SPACE_SIZE + 1);
         assert!(matches!(res, Err(MemError::NoAccess)));
     }
+
+    #[def_test]
+    fn test_script_args() {
+        let args = strings(&["script", "a1", "a2"]);
+        assert_eq!(script_args(b"\x7fELF", "/tmp/script", &args).unwrap(), None);
+
+        // The interpreter gets the path of the script instead of `argv[0]`.
+        let new_args = script_args(b"#!/bin/static\necho", "/tmp/script", &args).unwrap();
+        assert_eq!(
+            new_args,
+            Some(strings(&["/bin/static", "/tmp/script", "a1", "a2"]))
+        );
+
+        // The rest of the line is one argument.
+        let new_args = script_args(b"#! /bin/static -x  -y \n", "/tmp/script", &args).unwrap();
+        assert_eq!(
+            new_args,
+            Some(strings(&[
+                "/bin/static",
+                "-x  -y",
+                "/tmp/script",
+                "a1",
+                "a2"
+            ]))
+        );
+
+        assert!(matches!(
+            script_args(b"#!  \n", "/tmp/script", &args),
+            Err(KError::InvalidExecutable)
+        ));
+    }
 }
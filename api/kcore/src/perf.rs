@@ -0,0 +1,672 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Per-task hardware performance counters, in counting mode.
+//!
+//! The events of a task are virtualized: they are loaded into the hardware
+//! counters as the task is scheduled in and read back as it is scheduled
+//! out, so they count what it does and nothing else. A group of events is
+//! loaded as a whole or not at all. When the enabled groups need more
+//! counters than the PMU has, they take turns, another group first each time
+//! the task is scheduled in; the times an event was enabled and actually
+//! counting let user space scale its count.
+//!
+//! Changes to the events of a task running on another CPU take effect as it
+//! is next scheduled, and counts read meanwhile are as of its last
+//! schedule-out. Hardware counters may be 32 bits wide, so a task running
+//! for seconds without being scheduled out may wrap them.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kerrno::{KError, KResult};
+use khal::{percpu::this_cpu_id, time::monotonic_time_nanos};
+use kspin::SpinNoIrq;
+
+/// A generic hardware event, numbered as Linux `PERF_COUNT_HW_*`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwEvent {
+    /// CPU cycles.
+    CpuCycles       = 0,
+    /// Retired instructions.
+    Instructions    = 1,
+    /// Accesses to the data cache closest to the CPU.
+    CacheReferences = 2,
+    /// Refills of the data cache closest to the CPU.
+    CacheMisses     = 3,
+    /// Mispredicted branches.
+    BranchMisses    = 5,
+}
+
+impl HwEvent {
+    /// Returns the event of a `PERF_TYPE_HARDWARE` config.
+    pub fn from_config(config: u64) -> Option<Self> {
+        Some(match config {
+            0 => Self::CpuCycles,
+            1 => Self::Instructions,
+            2 => Self::CacheReferences,
+            3 => Self::CacheMisses,
+            5 => Self::BranchMisses,
+            _ => return None,
+        })
+    }
+}
+
+/// Hardware counters for the events of tasks, numbered from 0.
+pub trait Pmu: Sync {
+    /// Returns how many counters there are.
+    fn counters(&self) -> usize;
+    /// Starts counter `slot` counting `event` from zero, returning `false`
+    /// if the PMU cannot count it.
+    fn start(&self, slot: usize, event: HwEvent, exclude_user: bool, exclude_kernel: bool) -> bool;
+    /// Returns the count of counter `slot`.
+    fn read(&self, slot: usize) -> u64;
+    /// Stops counter `slot`.
+    fn stop(&self, slot: usize);
+}
+
+/// The PMU of the platform, without counters unless built with `pmu`.
+struct PlatformPmu;
+
+#[cfg(feature = "pmu")]
+impl Pmu for PlatformPmu {
+    fn counters(&self) -> usize {
+        khal::pmu::task_counters()
+    }
+
+    fn start(&self, slot: usize, event: HwEvent, exclude_user: bool, exclude_kernel: bool) -> bool {
+        khal::pmu::task_counter_start(slot, event as u32, exclude_user, exclude_kernel)
+    }
+
+    fn read(&self, slot: usize) -> u64 {
+        khal::pmu::task_counter_read(slot)
+    }
+
+    fn stop(&self, slot: usize) {
+        khal::pmu::task_counter_stop(slot)
+    }
+}
+
+#[cfg(not(feature = "pmu"))]
+impl Pmu for PlatformPmu {
+    fn counters(&self) -> usize {
+        0
+    }
+
+    fn start(
+        &self,
+        _slot: usize,
+        _event: HwEvent,
+        _exclude_user: bool,
+        _exclude_kernel: bool,
+    ) -> bool {
+        false
+    }
+
+    fn read(&self, _slot: usize) -> u64 {
+        0
+    }
+
+    fn stop(&self, _slot: usize) {}
+}
+
+/// What to count, and how an event starts.
+#[derive(Debug, Clone, Copy)]
+pub struct EventAttr {
+    /// The event counted.
+    pub event: HwEvent,
+    /// Do not count in user mode.
+    pub exclude_user: bool,
+    /// Do not count in kernel mode.
+    pub exclude_kernel: bool,
+    /// Start disabled.
+    pub disabled: bool,
+    /// Enable when the task executes a new program.
+    pub enable_on_exec: bool,
+}
+
+/// A count and the times to scale it by, in nanoseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EventCount {
+    /// The count.
+    pub value: u64,
+    /// How long the event was enabled while the task ran.
+    pub time_enabled: u64,
+    /// How long of that the event was actually counting.
+    pub time_running: u64,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+struct EventState {
+    id: u64,
+    attr: EventAttr,
+    enabled: bool,
+    /// The count up to the last schedule-out.
+    count: u64,
+    time_enabled: u64,
+    time_running: u64,
+    /// The hardware counter while loaded.
+    slot: Option<usize>,
+}
+
+impl EventState {
+    fn count(&self) -> EventCount {
+        EventCount {
+            value: self.count,
+            time_enabled: self.time_enabled,
+            time_running: self.time_running,
+        }
+    }
+}
+
+/// A group of events scheduled together, the leader first.
+struct Group(Vec<EventState>);
+
+struct ContextInner {
+    groups: Vec<Group>,
+    /// The group tried first as the task is scheduled in.
+    rotation: usize,
+    /// The CPU the task runs on.
+    on_cpu: Option<usize>,
+    /// When the times were last brought up to date.
+    stamp: u64,
+}
+
+impl ContextInner {
+    fn events_mut(&mut self) -> impl Iterator<Item = &mut EventState> {
+        self.groups.iter_mut().flat_map(|group| group.0.iter_mut())
+    }
+
+    fn find(&self, id: u64) -> Option<(usize, usize)> {
+        self.groups.iter().enumerate().find_map(|(group, events)| {
+            events
+                .0
+                .iter()
+                .position(|event| event.id == id)
+                .map(|index| (group, index))
+        })
+    }
+
+    /// Returns `true` if the task runs on this CPU, which means it is the
+    /// current task as long as the context is locked.
+    fn is_here(&self) -> bool {
+        self.on_cpu == Some(this_cpu_id())
+    }
+
+    /// Brings the times of the events up to `now`.
+    fn update_times(&mut self, now: u64) {
+        if self.on_cpu.is_some() {
+            let delta = now.saturating_sub(self.stamp);
+            for event in self.events_mut().filter(|event| event.enabled) {
+                event.time_enabled += delta;
+                if event.slot.is_some() {
+                    event.time_running += delta;
+                }
+            }
+        }
+        self.stamp = now;
+    }
+}
+
+/// The performance events of a task.
+pub struct PerfContext {
+    pmu: &'static dyn Pmu,
+    inner: SpinNoIrq<ContextInner>,
+}
+
+impl PerfContext {
+    /// Creates a context using the PMU of the platform.
+    pub fn new() -> Self {
+        Self::with_pmu(&PlatformPmu)
+    }
+
+    /// Creates a context using `pmu`.
+    pub fn with_pmu(pmu: &'static dyn Pmu) -> Self {
+        Self {
+            pmu,
+            inner: SpinNoIrq::new(ContextInner {
+                groups: Vec::new(),
+                rotation: 0,
+                on_cpu: None,
+                stamp: 0,
+            }),
+        }
+    }
+
+    /// Loads the enabled events into the hardware counters, whole groups as
+    /// far as they fit.
+    fn load(&self, inner: &mut ContextInner, rotate: bool) {
+        let groups = inner.groups.len();
+        let mut free = 0..self.pmu.counters();
+        let mut skipped = false;
+        for k in 0..groups {
+            let group = &mut inner.groups[(inner.rotation + k) % groups];
+            let needed = group.0.iter().filter(|event| event.enabled).count();
+            if needed > free.len() {
+                skipped = true;
+                continue;
+            }
+            for event in group.0.iter_mut().filter(|event| event.enabled) {
+                let slot = free.next().unwrap();
+                let attr = event.attr;
+                if self
+                    .pmu
+                    .start(slot, attr.event, attr.exclude_user, attr.exclude_kernel)
+                {
+                    event.slot = Some(slot);
+                }
+            }
+        }
+        if rotate && skipped {
+            inner.rotation = (inner.rotation + 1) % groups;
+        }
+    }
+
+    /// Reads the hardware counters back into the events.
+    fn unload(&self, inner: &mut ContextInner) {
+        for event in inner.events_mut() {
+            if let Some(slot) = event.slot.take() {
+                event.count += self.pmu.read(slot);
+                self.pmu.stop(slot);
+            }
+        }
+    }
+
+    /// Schedules the events in, as the task is about to run on this CPU.
+    pub fn sched_in(&self) {
+        let mut inner = self.inner.lock();
+        inner.on_cpu = Some(this_cpu_id());
+        inner.stamp = monotonic_time_nanos();
+        if !inner.groups.is_empty() {
+            self.load(&mut inner, true);
+        }
+    }
+
+    /// Schedules the events out, as the task stops running.
+    pub fn sched_out(&self) {
+        let mut inner = self.inner.lock();
+        if !inner.groups.is_empty() {
+            inner.update_times(monotonic_time_nanos());
+            self.unload(&mut inner);
+        }
+        inner.on_cpu = None;
+    }
+
+    /// Changes the events with `f`, reloading them if the task runs here.
+    fn modify<R>(&self, f: impl FnOnce(&mut ContextInner) -> R) -> R {
+        let mut inner = self.inner.lock();
+        inner.update_times(monotonic_time_nanos());
+        let here = inner.is_here();
+        if here {
+            self.unload(&mut inner);
+        }
+        let result = f(&mut inner);
+        if here {
+            self.load(&mut inner, false);
+        }
+        result
+    }
+
+    /// Opens an event, in the group of `leader` if given.
+    ///
+    /// Fails with `NotFound` without counters, and with `InvalidInput` if the
+    /// group would not fit into them or `leader` is of another task.
+    pub fn open(
+        self: &Arc<Self>,
+        attr: EventAttr,
+        leader: Option<&PerfEvent>,
+    ) -> KResult<PerfEvent> {
+        let counters = self.pmu.counters();
+        if counters == 0 {
+            return Err(KError::NotFound);
+        }
+        if leader.is_some_and(|leader| !Arc::ptr_eq(&leader.ctx, self)) {
+            return Err(KError::InvalidInput);
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let event = EventState {
+            id,
+            attr,
+            enabled: !attr.disabled,
+            count: 0,
+            time_enabled: 0,
+            time_running: 0,
+            slot: None,
+        };
+        self.modify(|inner| {
+            match leader {
+                Some(leader) => {
+                    let (group, index) = inner.find(leader.id).ok_or(KError::InvalidInput)?;
+                    let group = &mut inner.groups[group].0;
+                    // Only a leader leads a group.
+                    if index != 0 || group.len() >= counters {
+                        return Err(KError::InvalidInput);
+                    }
+                    group.push(event);
+                }
+                None => inner.groups.push(Group(alloc::vec![event])),
+            }
+            Ok(())
+        })?;
+        Ok(PerfEvent {
+            ctx: self.clone(),
+            id,
+        })
+    }
+
+    /// Enables the events to be enabled on exec.
+    pub fn enable_on_exec(&self) {
+        self.modify(|inner| {
+            for event in inner.events_mut() {
+                if event.attr.enable_on_exec {
+                    event.enabled = true;
+                }
+            }
+        });
+    }
+
+    /// Returns how many events are open.
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.groups.iter().map(|group| group.0.len()).sum()
+    }
+
+    /// Returns `true` if no event is open.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for PerfContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An open event of a task, closed when dropped.
+pub struct PerfEvent {
+    ctx: Arc<PerfContext>,
+    id: u64,
+}
+
+impl PerfEvent {
+    /// Returns the unique ID of the event.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Applies `f` to the event, or to its whole group with `group` if it
+    /// leads one.
+    fn for_each(&self, group: bool, mut f: impl FnMut(&mut EventState)) {
+        self.ctx.modify(|inner| {
+            let Some((index, position)) = inner.find(self.id) else {
+                return;
+            };
+            let events = &mut inner.groups[index].0;
+            if group && position == 0 {
+                events.iter_mut().for_each(f);
+            } else {
+                f(&mut events[position]);
+            }
+        });
+    }
+
+    /// Enables the event, or its group.
+    pub fn enable(&self, group: bool) {
+        self.for_each(group, |event| event.enabled = true);
+    }
+
+    /// Disables the event, or its group.
+    pub fn disable(&self, group: bool) {
+        self.for_each(group, |event| event.enabled = false);
+    }
+
+    /// Zeroes the count of the event, or of its group. The times are kept.
+    pub fn reset(&self, group: bool) {
+        self.for_each(group, |event| event.count = 0);
+    }
+
+    /// Returns the count of the event.
+    pub fn read(&self) -> EventCount {
+        self.read_group()
+            .map_or_else(EventCount::default, |counts| counts[0].1)
+    }
+
+    /// Returns the IDs and counts of the event and, if it leads a group, of
+    /// the rest of the group.
+    pub fn read_group(&self) -> Option<Vec<(u64, EventCount)>> {
+        let mut inner = self.ctx.inner.lock();
+        inner.update_times(monotonic_time_nanos());
+        let here = inner.is_here();
+        let (group, position) = inner.find(self.id)?;
+        let events = &inner.groups[group].0;
+        let events = if position == 0 {
+            &events[..]
+        } else {
+            &events[position..=position]
+        };
+        Some(
+            events
+                .iter()
+                .map(|event| {
+                    let mut count = event.count();
+                    if here && let Some(slot) = event.slot {
+                        count.value += self.ctx.pmu.read(slot);
+                    }
+                    (event.id, count)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Drop for PerfEvent {
+    fn drop(&mut self) {
+        self.ctx.modify(|inner| {
+            let Some((group, position)) = inner.find(self.id) else {
+                return;
+            };
+            let events = &mut inner.groups[group].0;
+            events.remove(position);
+            // The rest of a group losing its leader go on alone.
+            if position == 0 {
+                let rest = core::mem::take(events);
+                inner.groups.remove(group);
+                inner
+                    .groups
+                    .extend(rest.into_iter().map(|event| Group(alloc::vec![event])));
+            }
+            inner.rotation = 0;
+        });
+    }
+}
+
+#[cfg(unittest)]
+mod perf_tests {
+    use core::sync::atomic::{AtomicBool, AtomicU64};
+
+    use unittest::def_test;
+
+    use super::*;
+
+    /// Two counters; a running counter counts 10 per read.
+    struct FakePmu {
+        running: [AtomicBool; 2],
+        counts: [AtomicU64; 2],
+    }
+
+    impl Pmu for FakePmu {
+        fn counters(&self) -> usize {
+            2
+        }
+
+        fn start(&self, slot: usize, _event: HwEvent, _: bool, _: bool) -> bool {
+            self.counts[slot].store(0, Ordering::Relaxed);
+            self.running[slot].store(true, Ordering::Relaxed);
+            true
+        }
+
+        fn read(&self, slot: usize) -> u64 {
+            if self.running[slot].load(Ordering::Relaxed) {
+                self.counts[slot].fetch_add(10, Ordering::Relaxed) + 10
+            } else {
+                self.counts[slot].load(Ordering::Relaxed)
+            }
+        }
+
+        fn stop(&self, slot: usize) {
+            self.running[slot].store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn fake_context() -> Arc<PerfContext> {
+        let pmu = alloc::boxed::Box::leak(alloc::boxed::Box::new(FakePmu {
+            running: [const { AtomicBool::new(false) }; 2],
+            counts: [const { AtomicU64::new(0) }; 2],
+        }));
+        Arc::new(PerfContext::with_pmu(pmu))
+    }
+
+    fn attr(disabled: bool) -> EventAttr {
+        EventAttr {
+            event: HwEvent::Instructions,
+            exclude_user: false,
+            exclude_kernel: false,
+            disabled,
+            enable_on_exec: false,
+        }
+    }
+
+    #[def_test]
+    fn test_counts_only_while_scheduled() {
+        let ctx = fake_context();
+        let event = ctx.open(attr(false), None).unwrap();
+        assert_eq!(event.read().value, 0);
+        ctx.sched_in();
+        // Read while running adds the live count.
+        assert_eq!(event.read().value, 10);
+        ctx.sched_out();
+        assert_eq!(event.read().value, 20);
+        // Not running, nothing is added.
+        assert_eq!(event.read().value, 20);
+        let count = event.read();
+        assert_eq!(count.time_enabled, count.time_running);
+    }
+
+    #[def_test]
+    fn test_disabled_and_reset() {
+        let ctx = fake_context();
+        let event = ctx.open(attr(true), None).unwrap();
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(event.read(), EventCount::default());
+        event.enable(false);
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(event.read().value, 10);
+        event.reset(false);
+        assert_eq!(event.read().value, 0);
+        event.disable(false);
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(event.read().value, 0);
+    }
+
+    #[def_test]
+    fn test_groups_multiplexed() {
+        let ctx = fake_context();
+        let first = ctx.open(attr(false), None).unwrap();
+        let sibling = ctx.open(attr(false), Some(&first)).unwrap();
+        let second = ctx.open(attr(false), None).unwrap();
+        // A group never exceeds the counters.
+        assert!(ctx.open(attr(false), Some(&first)).is_err());
+        // Only a leader leads.
+        assert!(ctx.open(attr(false), Some(&sibling)).is_err());
+
+        // The groups take turns.
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!((first.read().value, sibling.read().value), (10, 10));
+        assert_eq!(second.read().value, 0);
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(second.read().value, 10);
+        assert_eq!(first.read().value, 10);
+
+        let group = first.read_group().unwrap();
+        assert_eq!(group.len(), 2);
+        assert_eq!(group[1].0, sibling.id());
+        assert_eq!(second.read_group().unwrap().len(), 1);
+
+        // Disabling the group leaves room for the other one.
+        first.disable(true);
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(second.read().value, 20);
+        drop(first);
+        assert_eq!(ctx.len(), 2);
+        drop((sibling, second));
+        assert!(ctx.is_empty());
+    }
+
+    #[def_test]
+    fn test_enable_on_exec() {
+        let ctx = fake_context();
+        let event = ctx
+            .open(
+                EventAttr {
+                    enable_on_exec: true,
+                    ..attr(true)
+                },
+                None,
+            )
+            .unwrap();
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(event.read().value, 0);
+        ctx.enable_on_exec();
+        ctx.sched_in();
+        ctx.sched_out();
+        assert_eq!(event.read().value, 10);
+    }
+
+    /// Counts the instructions of a loop of known length on the PMU of the
+    /// platform, if it has one counting them.
+    #[cfg(target_arch = "aarch64")]
+    #[def_test]
+    fn test_instruction_count_of_loop() {
+        const ITERATIONS: u64 = 100_000;
+        let ctx = Arc::new(PerfContext::new());
+        let Ok(event) = ctx.open(attr(false), None) else {
+            return;
+        };
+        let guard = kspin::NoPreemptIrqSave::new();
+        ctx.sched_in();
+        // Two instructions an iteration.
+        unsafe {
+            core::arch::asm!(
+                "1:",
+                "subs {n}, {n}, #1",
+                "b.ne 1b",
+                n = inout(reg) ITERATIONS => _,
+                options(nomem, nostack),
+            );
+        }
+        ctx.sched_out();
+        drop(guard);
+        let count = event.read();
+        // Not every PMU counts instructions, QEMU only with icount.
+        if count.time_running == 0 {
+            return;
+        }
+        let count = count.value;
+        let expected = 2 * ITERATIONS;
+        assert!(
+            count.abs_diff(expected) < expected / 100,
+            "counted {count} instructions, expected {expected}"
+        );
+    }
+}
@@ -6,11 +6,16 @@
 
 use core::ops::{Index, IndexMut};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_STACK};
+use ksignal::DEFAULT_SIGPENDING_LIMIT;
+use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_SIGPENDING, RLIMIT_STACK};
 
 /// The maximum number of open files
 pub const FILE_LIMIT: usize = 1024;
 
+/// The largest number of open files a process may raise its limit to, which
+/// bounds file descriptor numbers.
+pub const NR_OPEN: usize = 1 << 20;
+
 /// The limit for a specific resource
 #[derive(Default)]
 pub struct Rlimit {
@@ -46,7 +51,8 @@ impl Default for Rlimits {
     fn default() -> Self {
         let mut result = Self(Default::default());
         result[RLIMIT_STACK] = (crate::config::USER_STACK_SIZE as u64).into();
-        result[RLIMIT_NOFILE] = (FILE_LIMIT as u64).into();
+        result[RLIMIT_NOFILE] = Rlimit::new(FILE_LIMIT as u64, NR_OPEN as u64);
+        result[RLIMIT_SIGPENDING] = (DEFAULT_SIGPENDING_LIMIT as u64).into();
         result
     }
 }
@@ -94,5 +100,6 @@ pub mod tests_resources {
             crate::config::USER_STACK_SIZE as u64
         );
         assert_eq!(limits[RLIMIT_NOFILE].current, FILE_LIMIT as u64);
+        assert_eq!(limits[RLIMIT_NOFILE].max, NR_OPEN as u64);
     }
 }
@@ -17,14 +17,14 @@ use core::any::Any;
 use core::{
     cell::RefCell,
     ops::Deref,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicUsize, Ordering},
 };
 
 use extern_trait::extern_trait;
 use hashbrown::HashMap;
 use kerrno::{KError, KResult};
-use kpoll::PollSet;
-use kprocess::{Pid, Process, ProcessGroup, Session};
+use khal::time::TimeValue;
+use kprocess::{ExitHooks, ExitRegistration, Pid, Process, ProcessGroup, Session};
 use ksignal::{
     SignalInfo, Signo,
     api::{ProcessSignalManager, SignalActions, ThreadSignalManager},
@@ -32,15 +32,16 @@ use ksignal::{
 use ksync::{Mutex, RwLock, spin::SpinNoIrq};
 use ktask::{KtaskRef, TaskExt, TaskInner, WeakKtaskRef, current};
 use lazy_static::lazy_static;
-use memspace::AddrSpace;
+use memspace::{AddrSpace, WssEstimator};
 use scope_local::{ActiveScope, Scope};
 use weak_map::WeakMap;
 
 pub use self::stat::TaskStat;
 use crate::{
     futex::{FutexKey, FutexTable},
+    perf::PerfContext,
     resources::Rlimits,
-    time::{TimeManager, TimerState},
+    time::{ITimerType, TimeManager, TimerState, cancel_alarms},
 };
 
 ///  A wrapper type that assumes the inner type is `Sync`.
@@ -94,12 +95,25 @@ pub struct Thread {
     /// The OOM score adjustment value.
     oom_score_adj: AtomicI32,
 
+    /// The signal sent to the thread when its parent exits, or 0.
+    pdeathsig: AtomicU8,
+
     /// Ready to exit
     exit: AtomicBool,
 
     /// Indicates whether the thread is currently accessing user memory.
     accessing_user_memory: AtomicBool,
 
+    /// The hooks reaping the state the thread leaves behind, run as it
+    /// exits.
+    exit_hooks: ExitHooks,
+
+    /// The exit hook disarming the interval timers, while any is armed.
+    itimer_hook: SpinNoIrq<Option<ExitRegistration>>,
+
+    /// The performance events counting what the thread does.
+    perf: Arc<PerfContext>,
+
     /// Tee session context
     #[cfg(feature = "tee")]
     pub tee_session_ctx: Mutex<Option<Box<dyn TeeSessionCtxTrait>>>,
@@ -115,8 +129,12 @@ impl Thread {
             robust_list_head: AtomicUsize::new(0),
             time: AssumeSync(RefCell::new(TimeManager::new())),
             oom_score_adj: AtomicI32::new(200),
+            pdeathsig: AtomicU8::new(0),
             exit: AtomicBool::new(false),
             accessing_user_memory: AtomicBool::new(false),
+            exit_hooks: ExitHooks::new(),
+            itimer_hook: SpinNoIrq::new(None),
+            perf: Arc::new(PerfContext::new()),
             #[cfg(feature = "tee")]
             tee_session_ctx: Mutex::new(None),
         })
@@ -138,10 +156,10 @@ impl Thread {
         self.robust_list_head.load(Ordering::SeqCst)
     }
 
-    /// Set the robust list head.
-    pub fn set_robust_list_head(&self, robust_list_head: usize) {
+    /// Set the robust list head, returning the previous one.
+    pub fn set_robust_list_head(&self, robust_list_head: usize) -> usize {
         self.robust_list_head
-            .store(robust_list_head, Ordering::SeqCst);
+            .swap(robust_list_head, Ordering::SeqCst)
     }
 
     /// Get the oom score adjustment value.
@@ -154,6 +172,17 @@ impl Thread {
         self.oom_score_adj.store(value, Ordering::SeqCst);
     }
 
+    /// Get the signal sent to the thread when its parent exits.
+    pub fn pdeathsig(&self) -> Option<Signo> {
+        Signo::from_repr(self.pdeathsig.load(Ordering::Acquire))
+    }
+
+    /// Set the signal sent to the thread when its parent exits.
+    pub fn set_pdeathsig(&self, signo: Option<Signo>) {
+        self.pdeathsig
+            .store(signo.map_or(0, |signo| signo as u8), Ordering::Release);
+    }
+
     /// Check if the thread is ready to exit.
     pub fn pending_exit(&self) -> bool {
         self.exit.load(Ordering::Acquire)
@@ -175,6 +204,19 @@ impl Thread {
             .store(accessing, Ordering::Release);
     }
 
+    /// The hooks reaping the state the thread leaves behind.
+    ///
+    /// They run in the exiting thread, so they may still access its user
+    /// memory.
+    pub fn exit_hooks(&self) -> &ExitHooks {
+        &self.exit_hooks
+    }
+
+    /// The performance events counting what the thread does.
+    pub fn perf(&self) -> &Arc<PerfContext> {
+        &self.perf
+    }
+
     /// Set the tee session context.
     #[cfg(feature = "tee")]
     pub fn set_tee_session_ctx(&self, ctx: Box<dyn TeeSessionCtxTrait>) {
@@ -191,9 +233,11 @@ unsafe impl TaskExt for Box<Thread> {
         let scope = self.proc_data.scope.read();
         unsafe { ActiveScope::set(&scope) };
         core::mem::forget(scope);
+        self.perf.sched_in();
     }
 
     fn on_leave(&self) {
+        self.perf.sched_out();
         ActiveScope::set_global();
         unsafe { self.proc_data.scope.force_unlock_read() };
     }
@@ -236,21 +280,24 @@ pub struct ProcessData {
     /// The resource limits
     pub rlim: RwLock<Rlimits>,
 
-    /// The child exit wait event
-    pub child_exit_event: Arc<PollSet>,
-    /// Self exit event
-    pub exit_event: Arc<PollSet>,
     /// The exit signal of the thread
     pub exit_signal: Option<Signo>,
 
+    /// Whether the process may be dumped or attached to, see
+    /// `PR_SET_DUMPABLE`.
+    dumpable: AtomicBool,
+
+    /// Whether the process runs 32-bit ARM code, and so lives below 4 GiB.
+    compat: AtomicBool,
+
     /// The process signal manager
     pub signal: Arc<ProcessSignalManager>,
 
     /// The futex table.
     futex_table: Arc<FutexTable>,
 
-    /// The default mask for file permissions.
-    umask: AtomicU32,
+    /// The working set size estimator, created when first used.
+    wss: SpinNoIrq<Option<Arc<WssEstimator>>>,
 }
 
 impl ProcessData {
@@ -273,10 +320,11 @@ impl ProcessData {
 
             rlim: RwLock::default(),
 
-            child_exit_event: Arc::default(),
-            exit_event: Arc::default(),
             exit_signal,
 
+            dumpable: AtomicBool::new(true),
+            compat: AtomicBool::new(false),
+
             signal: Arc::new(ProcessSignalManager::new(
                 signal_actions,
                 crate::config::SIGNAL_TRAMPOLINE,
@@ -284,7 +332,7 @@ impl ProcessData {
 
             futex_table: Arc::new(FutexTable::new()),
 
-            umask: AtomicU32::new(0o022),
+            wss: SpinNoIrq::new(None),
         })
     }
 
@@ -298,6 +346,50 @@ impl ProcessData {
         self.heap_top.store(top, Ordering::Release)
     }
 
+    /// Returns whether the process may be dumped or attached to.
+    pub fn dumpable(&self) -> bool {
+        self.dumpable.load(Ordering::Acquire)
+    }
+
+    /// Sets whether the process may be dumped or attached to.
+    pub fn set_dumpable(&self, dumpable: bool) {
+        self.dumpable.store(dumpable, Ordering::Release)
+    }
+
+    /// Returns whether the process runs 32-bit ARM code.
+    pub fn is_compat(&self) -> bool {
+        self.compat.load(Ordering::Acquire)
+    }
+
+    /// Sets whether the process runs 32-bit ARM code, as `execve` does.
+    pub fn set_compat(&self, compat: bool) {
+        self.compat.store(compat, Ordering::Release)
+    }
+
+    /// Returns the end of the user space the process may map memory in.
+    pub fn user_space_end(&self) -> usize {
+        #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+        if self.is_compat() {
+            return crate::config::COMPAT_USER_SPACE_END;
+        }
+        crate::config::USER_SPACE_BASE + crate::config::USER_SPACE_SIZE
+    }
+
+    /// Returns the working set size estimator of the process, if it was
+    /// ever used.
+    pub fn wss(&self) -> Option<Arc<WssEstimator>> {
+        self.wss.lock().clone()
+    }
+
+    /// Returns the working set size estimator of the process, creating it
+    /// if needed.
+    pub fn wss_or_init(&self) -> Arc<WssEstimator> {
+        self.wss
+            .lock()
+            .get_or_insert_with(|| WssEstimator::new(&self.aspace))
+            .clone()
+    }
+
     /// Linux manual: A "clone" child is one which delivers no signal, or a
     /// signal other than SIGCHLD to its parent upon termination.
     pub fn is_clone_child(&self) -> bool {
@@ -308,34 +400,27 @@ impl ProcessData {
     pub fn futex_table_for(&self, key: &FutexKey) -> Arc<FutexTable> {
         match key {
             FutexKey::Private { .. } => self.futex_table.clone(),
-            FutexKey::Shared { region, .. } => {
-                let ptr = match region {
-                    Ok(pages) => Weak::as_ptr(pages) as usize,
-                    Err(key) => Weak::as_ptr(key) as usize,
-                };
-                SHARED_FUTEX_TABLES.lock().get_or_insert(ptr)
-            }
+            FutexKey::Shared { region, .. } => SHARED_FUTEX_TABLES
+                .lock()
+                .get_or_insert(SharedRegion::Pages(Weak::as_ptr(region) as usize)),
+            FutexKey::Inode { fs, inode, .. } => SHARED_FUTEX_TABLES
+                .lock()
+                .get_or_insert(SharedRegion::Inode(*fs, *inode)),
         }
     }
+}
 
-    /// Get the umask.
-    pub fn umask(&self) -> u32 {
-        self.umask.load(Ordering::SeqCst)
-    }
-
-    /// Set the umask.
-    pub fn set_umask(&self, umask: u32) {
-        self.umask.store(umask, Ordering::SeqCst);
-    }
-
-    /// Set the umask and return the old value.
-    pub fn replace_umask(&self, umask: u32) -> u32 {
-        self.umask.swap(umask, Ordering::SeqCst)
-    }
+/// The memory a shared futex table is for.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum SharedRegion {
+    /// Shared anonymous memory, by the address of its pages.
+    Pages(usize),
+    /// A file, by its filesystem and inode number.
+    Inode(usize, u64),
 }
 
 struct FutexTables {
-    map: HashMap<usize, Arc<FutexTable>>,
+    map: HashMap<SharedRegion, Arc<FutexTable>>,
     operations: usize,
 }
 impl FutexTables {
@@ -346,7 +431,7 @@ impl FutexTables {
         }
     }
 
-    fn get_or_insert(&mut self, key: usize) -> Arc<FutexTable> {
+    fn get_or_insert(&mut self, key: SharedRegion) -> Arc<FutexTable> {
         self.operations += 1;
         if self.operations == 100 {
             self.operations = 0;
@@ -468,6 +553,36 @@ pub fn poll_timer(task: &TaskInner) {
     });
 }
 
+/// Sets an interval timer of the current thread, returning the old interval
+/// and remaining time.
+///
+/// While any of its timers is armed, the thread has an exit hook disarming
+/// them, so that none fires for it once it exited.
+pub fn set_itimer(
+    ty: ITimerType,
+    interval_ns: usize,
+    remained_ns: usize,
+) -> (TimeValue, TimeValue) {
+    let curr = current();
+    let thr = curr.as_thread();
+    let (old, armed) = {
+        let mut time = thr.time.borrow_mut();
+        let old = time.set_itimer(ty, interval_ns, remained_ns);
+        (old, time.has_armed_itimers())
+    };
+    let mut hook = thr.itimer_hook.lock();
+    if !armed {
+        *hook = None;
+    } else if hook.is_none() {
+        *hook = Some(thr.exit_hooks.register("itimer", || {
+            let curr = current();
+            curr.as_thread().time.borrow_mut().cancel_itimers();
+            cancel_alarms(&curr);
+        }));
+    }
+    old
+}
+
 /// Sets the timer state.
 pub fn set_timer_state(task: &TaskInner, state: TimerState) {
     let Some(thr) = task.try_as_thread() else {
@@ -484,9 +599,32 @@ pub fn set_timer_state(task: &TaskInner, state: TimerState) {
 }
 
 fn send_signal_thread_inner(task: &TaskInner, thr: &Thread, sig: SignalInfo) {
+    let signo = sig.signo();
     if thr.signal.send_signal(sig) {
         task.interrupt();
     }
+    interrupt_group(&thr.proc_data, signo);
+}
+
+/// Interrupts every thread of the process for the signals acting on the
+/// whole thread group whatever the signal masks, so that none of them keeps
+/// running once the group is killed or stopped.
+///
+/// `SIGCONT` resumes a stopped group as soon as it is sent, even if it is
+/// blocked or ignored.
+fn interrupt_group(proc_data: &ProcessData, signo: Signo) {
+    if signo == Signo::SIGCONT {
+        proc_data.proc.cont();
+        return;
+    }
+    if !matches!(signo, Signo::SIGKILL | Signo::SIGSTOP) {
+        return;
+    }
+    for tid in proc_data.proc.threads() {
+        if let Ok(task) = get_task(tid) {
+            task.interrupt();
+        }
+    }
 }
 
 /// Sends a signal to a thread.
@@ -517,11 +655,57 @@ pub fn send_signal_to_process(pid: Pid, sig: Option<SignalInfo>) -> KResult<()>
         {
             task.interrupt();
         }
+        interrupt_group(&proc_data, signo);
     }
 
     Ok(())
 }
 
+/// Queues a signal to a thread, as `rt_tgsigqueueinfo` does.
+///
+/// Unlike [`send_signal_to_thread`], fails with [`KError::WouldBlock`] if the
+/// process already has as many real-time signals queued as its
+/// `RLIMIT_SIGPENDING` allows.
+pub fn queue_signal_to_thread(tgid: Pid, tid: Pid, sig: SignalInfo) -> KResult<()> {
+    let task = get_task(tid)?;
+    let thread = task.try_as_thread().ok_or(KError::OperationNotPermitted)?;
+    if thread.proc_data.proc.pid() != tgid {
+        return Err(KError::NoSuchProcess);
+    }
+
+    let signo = sig.signo();
+    info!("Queue signal {signo:?} to thread {tid}");
+    if thread
+        .signal
+        .queue_signal(sig)
+        .map_err(|_| KError::WouldBlock)?
+    {
+        task.interrupt();
+    }
+    interrupt_group(&thread.proc_data, signo);
+    Ok(())
+}
+
+/// Queues a signal to a process, as `rt_sigqueueinfo` does.
+///
+/// Fails with [`KError::WouldBlock`] like [`queue_signal_to_thread`].
+pub fn queue_signal_to_process(pid: Pid, sig: SignalInfo) -> KResult<()> {
+    let proc_data = get_process_data(pid)?;
+
+    let signo = sig.signo();
+    info!("Queue signal {signo:?} to process {pid}");
+    if let Some(tid) = proc_data
+        .signal
+        .queue_signal(sig)
+        .map_err(|_| KError::WouldBlock)?
+        && let Ok(task) = get_task(tid)
+    {
+        task.interrupt();
+    }
+    interrupt_group(&proc_data, signo);
+    Ok(())
+}
+
 /// Sends a signal to a process group.
 pub fn send_signal_to_process_group(pgid: Pid, sig: Option<SignalInfo>) -> KResult<()> {
     let pg = get_process_group(pgid)?;
@@ -3,12 +3,21 @@
 // See LICENSES for license details.
 
 use alloc::{borrow::ToOwned, fmt, string::String};
+use core::time::Duration;
 
 use kerrno::KResult;
 use ksignal::Signo;
 use ktask::{TaskInner, TaskState};
+use memaddr::PAGE_SIZE_4K;
 
-use crate::task::AsThread;
+use crate::task::{AsThread, get_task};
+
+/// Clock ticks per second of the times in `/proc`, `USER_HZ` on Linux.
+const USER_HZ: u64 = 100;
+
+fn jiffies(time: Duration) -> u64 {
+    (time.as_millis() as u64) * USER_HZ / 1000
+}
 
 /// Represents the `/proc/[pid]/stat` file.
 ///
@@ -49,10 +58,10 @@ pub struct TaskStat {
     pub cutime: u64,
     /// Kernel mode time of waited-for children in jiffies.
     pub cstime: u64,
-    /// Process priority.
-    pub priority: u32,
+    /// Process priority, `20 + nice` for normal tasks.
+    pub priority: i32,
     /// Nice value.
-    pub nice: u32,
+    pub nice: i32,
     /// Number of threads in this process.
     pub num_threads: u32,
     /// Obsolete (always 0 in Linux).
@@ -139,6 +148,27 @@ impl TaskStat {
         let ppid = proc.parent().map_or(0, |p| p.pid());
         let pgrp = proc.group().pgid();
         let session = proc.group().session().sid();
+
+        // Threads that exited are accounted to the process, the others still
+        // hold their own times. A thread updating its times on another CPU is
+        // skipped rather than waited for.
+        let mut usage = proc.exited_usage();
+        for tid in proc.threads() {
+            if let Ok(task) = get_task(tid)
+                && let Ok(time) = task.as_thread().time.try_borrow()
+            {
+                let (utime, stime) = time.output();
+                usage.utime += utime;
+                usage.stime += stime;
+            }
+        }
+        let children = proc.children_usage();
+
+        let (vsize, rss) = {
+            let mut aspace = proc_data.aspace.lock();
+            let vsize = aspace.areas().map(|area| area.size() as u64).sum();
+            (vsize, (aspace.resident_size() / PAGE_SIZE_4K) as i64)
+        };
         Ok(Self {
             pid,
             comm: comm.to_owned(),
@@ -146,7 +176,15 @@ impl TaskStat {
             ppid,
             pgrp,
             session,
+            utime: jiffies(usage.utime),
+            stime: jiffies(usage.stime),
+            cutime: jiffies(children.utime),
+            cstime: jiffies(children.stime),
+            priority: 20 + task.nice() as i32,
+            nice: task.nice() as i32,
             num_threads: proc.threads().len() as u32,
+            vsize,
+            rss,
             exit_signal: proc_data.exit_signal.unwrap_or(Signo::SIGCHLD) as u8,
             exit_code: proc.exit_code(),
             ..Default::default()
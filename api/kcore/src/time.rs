@@ -4,21 +4,33 @@
 
 //! Time management module.
 
-use alloc::{borrow::ToOwned, collections::binary_heap::BinaryHeap, sync::Arc};
+use alloc::{
+    borrow::ToOwned,
+    collections::binary_heap::BinaryHeap,
+    sync::{Arc, Weak},
+};
 use core::{mem, time::Duration};
 
 use event_listener::{Event, listener};
-use khal::time::{NANOS_PER_SEC, TimeValue, monotonic_time_nanos, wall_time};
+use khal::time::{
+    NANOS_PER_SEC, TICK_NANOS, TimeValue, boot_time, monotonic_time, monotonic_time_coarse,
+    monotonic_time_nanos, realtime, realtime_coarse, resolution_nanos, wall_time,
+};
 use ksignal::Signo;
 use ksync::Mutex;
 use ktask::{
-    WeakKtaskRef, current,
+    KtaskRef, WeakKtaskRef, current,
     future::{block_on, timeout_at},
 };
 use lazy_static::lazy_static;
+use linux_raw_sys::general::{
+    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_BOOTTIME_ALARM, CLOCK_MONOTONIC,
+    CLOCK_MONOTONIC_COARSE, CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME,
+    CLOCK_REALTIME_ALARM, CLOCK_REALTIME_COARSE, CLOCK_THREAD_CPUTIME_ID,
+};
 use strum::FromRepr;
 
-use crate::task::poll_timer;
+use crate::task::{AsThread, poll_timer};
 
 fn time_value_from_nanos(nanos: usize) -> TimeValue {
     let secs = nanos as u64 / NANOS_PER_SEC;
@@ -26,6 +38,69 @@ fn time_value_from_nanos(nanos: usize) -> TimeValue {
     TimeValue::new(secs, nsecs as u32)
 }
 
+/// A clock readable through `clock_gettime`.
+///
+/// System calls and the vDSO both read the clocks through this type, so that
+/// they agree on the value and resolution of each clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// Settable time since the epoch.
+    Realtime,
+    /// [`Realtime`](Self::Realtime) as of the last timer tick.
+    RealtimeCoarse,
+    /// Time since boot, not counting the time spent suspended.
+    Monotonic,
+    /// [`Monotonic`](Self::Monotonic) as of the last timer tick.
+    MonotonicCoarse,
+    /// Time since boot, counting the time spent suspended.
+    Boottime,
+    /// CPU time consumed by the calling process.
+    ProcessCputime,
+    /// CPU time consumed by the calling thread.
+    ThreadCputime,
+}
+
+impl Clock {
+    /// Returns the clock with the Linux clock ID `id`.
+    pub fn from_id(id: __kernel_clockid_t) -> Option<Self> {
+        Some(match id as u32 {
+            CLOCK_REALTIME | CLOCK_REALTIME_ALARM => Self::Realtime,
+            CLOCK_REALTIME_COARSE => Self::RealtimeCoarse,
+            // The counter is never slewed, so the raw clock is the same.
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW => Self::Monotonic,
+            CLOCK_MONOTONIC_COARSE => Self::MonotonicCoarse,
+            CLOCK_BOOTTIME | CLOCK_BOOTTIME_ALARM => Self::Boottime,
+            CLOCK_PROCESS_CPUTIME_ID => Self::ProcessCputime,
+            CLOCK_THREAD_CPUTIME_ID => Self::ThreadCputime,
+            _ => return None,
+        })
+    }
+
+    /// Returns the current time of the clock.
+    pub fn now(self) -> TimeValue {
+        match self {
+            Self::Realtime => realtime(),
+            Self::RealtimeCoarse => realtime_coarse(),
+            Self::Monotonic => monotonic_time(),
+            Self::MonotonicCoarse => monotonic_time_coarse(),
+            Self::Boottime => boot_time(),
+            // TODO: account the CPU time of the whole process
+            Self::ProcessCputime | Self::ThreadCputime => {
+                let (utime, stime) = current().as_thread().time.borrow().output();
+                utime + stime
+            }
+        }
+    }
+
+    /// Returns the resolution of the clock.
+    pub fn resolution(self) -> TimeValue {
+        match self {
+            Self::RealtimeCoarse | Self::MonotonicCoarse => TimeValue::from_nanos(TICK_NANOS),
+            _ => TimeValue::from_nanos(resolution_nanos()),
+        }
+    }
+}
+
 struct Entry {
     deadline: Duration,
     task: WeakKtaskRef,
@@ -212,6 +287,16 @@ impl TimeManager {
         )
     }
 
+    /// Returns `true` if any interval timer is armed.
+    pub fn has_armed_itimers(&self) -> bool {
+        self.itimers.iter().any(|it| it.remained_ns > 0)
+    }
+
+    /// Disarms all interval timers.
+    pub fn cancel_itimers(&mut self) {
+        self.itimers = Default::default();
+    }
+
     /// Gets the current interval and remaining time.
     pub fn get_itimer(&self, ty: ITimerType) -> (TimeValue, TimeValue) {
         let itimer = &self.itimers[ty as usize];
@@ -251,9 +336,11 @@ async fn alarm_task() {
                 poll_timer(&task);
             }
 
-            // 从队列中移除
+            // 从队列中移除，除非它已被取消
             let mut guard = ALARM_LIST.lock();
-            assert!(guard.pop().is_some_and(|it| it.deadline == deadline));
+            if guard.peek().is_some_and(|it| it.deadline == deadline) {
+                guard.pop();
+            }
         } else {
             // 任务未到期，等待到 deadline 或新任务插入
             listener!(EVENT_NEW_TIMER => listener);
@@ -272,13 +359,23 @@ async fn alarm_task() {
     }
 }
 
+/// Removes the pending alarms of `task`.
+pub fn cancel_alarms(task: &KtaskRef) {
+    let task = Arc::downgrade(task);
+    ALARM_LIST
+        .lock()
+        .retain(|it| !Weak::ptr_eq(&it.task, &task));
+}
+
 /// Spawns the alarm task.
 pub fn spawn_alarm_task() {
     ktask::spawn_raw(
         || block_on(alarm_task()),
         "alarm_task".to_owned(),
         platconfig::TASK_STACK_SIZE,
-    );
+    )
+    // Idle until a timer is armed, possibly forever.
+    .set_hung_check_exempt(true);
 }
 
 /// Unit tests.
@@ -287,7 +384,7 @@ pub mod tests_time {
     use ksignal::Signo;
     use unittest::def_test;
 
-    use super::{ITimerType, TimeManager};
+    use super::*;
 
     #[def_test]
     fn test_itimer_signo() {
@@ -313,4 +410,88 @@ pub mod tests_time {
         assert_eq!(s.as_secs(), 0);
         assert_eq!(s.subsec_nanos(), 0);
     }
+
+    /// Clocks read from the hardware counter, in the order they must not go
+    /// backwards in.
+    const PRECISE: [Clock; 3] = [Clock::Realtime, Clock::Monotonic, Clock::Boottime];
+
+    /// Slack allowed for the time spent between two reads.
+    const SLACK: Duration = Duration::from_millis(10);
+
+    #[def_test]
+    fn test_clock_ids() {
+        assert_eq!(
+            Clock::from_id(CLOCK_MONOTONIC_RAW as _),
+            Some(Clock::Monotonic)
+        );
+        assert_eq!(
+            Clock::from_id(CLOCK_BOOTTIME_ALARM as _),
+            Some(Clock::Boottime)
+        );
+        assert_eq!(Clock::from_id(-1), None);
+        assert_eq!(Clock::from_id(100), None);
+    }
+
+    #[def_test]
+    fn test_clocks_are_monotonic() {
+        for clock in PRECISE.into_iter().chain([Clock::MonotonicCoarse]) {
+            let mut last = clock.now();
+            for _ in 0..1000 {
+                let now = clock.now();
+                assert!(now >= last, "{clock:?} went backwards");
+                last = now;
+            }
+        }
+    }
+
+    #[def_test]
+    fn test_coarse_clocks_lag() {
+        khal::time::update_coarse_time();
+        for (coarse, precise) in [
+            (Clock::MonotonicCoarse, Clock::Monotonic),
+            (Clock::RealtimeCoarse, Clock::Realtime),
+        ] {
+            let before = coarse.now();
+            let now = precise.now();
+            // Never ahead, and behind by at most one tick.
+            assert!(before <= now);
+            assert!(now - before <= coarse.resolution() + SLACK);
+            assert_eq!(coarse.resolution(), Duration::from_nanos(TICK_NANOS));
+            assert!(precise.resolution() <= coarse.resolution());
+        }
+    }
+
+    #[def_test]
+    fn test_boottime_counts_suspend() {
+        let offsets = || {
+            let mono = Clock::Monotonic.now();
+            let boot = Clock::Boottime.now();
+            let real = Clock::Realtime.now();
+            (boot - mono, real - boot)
+        };
+        let (suspended, epoch) = offsets();
+        let step = Duration::from_millis(5);
+        khal::time::add_suspended_time(step);
+        let (suspended_after, epoch_after) = offsets();
+
+        // Boot time moves ahead of monotonic time, and real time along with it.
+        assert!(suspended_after.abs_diff(suspended + step) <= SLACK);
+        assert!(epoch_after.abs_diff(epoch) <= SLACK);
+    }
+
+    #[def_test]
+    fn test_set_realtime() {
+        let saved = Clock::Realtime.now();
+        let target = saved + Duration::from_secs(3600);
+        khal::time::set_realtime(target);
+        let now = Clock::Realtime.now();
+        assert!(now >= target && now - target <= SLACK);
+        // The coarse clock follows.
+        khal::time::update_coarse_time();
+        let coarse = Clock::RealtimeCoarse.now();
+        assert!(coarse.abs_diff(target) <= Clock::RealtimeCoarse.resolution() + SLACK);
+
+        khal::time::set_realtime(saved);
+        assert!(Clock::Realtime.now() < target);
+    }
 }
@@ -8,13 +8,14 @@ use alloc::sync::Arc;
 use core::{any::Any, task::Context};
 
 use fs_ng_vfs::{
-    DeviceId, FileNodeOps, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps,
+    DeviceId, DirEntry, FileNodeOps, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps,
     NodePermission, NodeType, VfsError, VfsResult,
 };
 use inherit_methods_macro::inherit_methods;
 use kfs::CachedFile;
 use kpoll::{IoEvents, Pollable};
 use memaddr::PhysAddrRange;
+use memspace::backend::SharedPages;
 
 use super::{SimpleFs, SimpleFsNode};
 
@@ -28,6 +29,8 @@ pub enum DeviceMmap {
     ReadOnly,
     /// Maps to a cached file.
     Cache(CachedFile),
+    /// Maps to pages shared with the device, from the first one on.
+    Shared(Arc<SharedPages>),
 }
 
 /// Trait for device operations.
@@ -168,3 +171,110 @@ impl Pollable for Device {
         }
     }
 }
+
+/// A device node stored on a regular filesystem, opened through the device
+/// number registry.
+///
+/// The metadata belongs to the inode on the filesystem, while I/O goes to the
+/// driver registered for its device number.
+pub struct DeviceFile {
+    inode: Arc<dyn FileNodeOps>,
+    ops: Arc<dyn DeviceOps>,
+}
+
+impl DeviceFile {
+    /// Creates a device file for `inode` backed by `ops`.
+    pub fn new(inode: Arc<dyn FileNodeOps>, ops: Arc<dyn DeviceOps>) -> Arc<Self> {
+        Arc::new(Self { inode, ops })
+    }
+
+    /// Returns the inner device operations.
+    pub fn inner(&self) -> &Arc<dyn DeviceOps> {
+        &self.ops
+    }
+}
+
+#[inherit_methods(from = "self.inode")]
+impl NodeOps for DeviceFile {
+    fn inode(&self) -> u64;
+
+    fn metadata(&self) -> VfsResult<Metadata>;
+
+    fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()>;
+
+    fn filesystem(&self) -> &dyn FilesystemOps;
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Err(VfsError::InvalidInput)
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(0)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        self.ops.flags()
+    }
+}
+
+impl FileNodeOps for DeviceFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.ops.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        self.ops.write_at(buf, offset)
+    }
+
+    fn append(&self, _buf: &[u8]) -> VfsResult<(usize, u64)> {
+        Err(VfsError::NotATty)
+    }
+
+    fn set_len(&self, _len: u64) -> VfsResult<()> {
+        if self.write_at(b"", 0).is_ok() {
+            Ok(())
+        } else {
+            Err(VfsError::BadFileDescriptor)
+        }
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::BadFileDescriptor)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        self.ops.ioctl(cmd, arg)
+    }
+}
+
+impl Pollable for DeviceFile {
+    fn poll(&self) -> IoEvents {
+        if let Some(pollable) = self.ops.as_pollable() {
+            pollable.poll()
+        } else {
+            IoEvents::IN | IoEvents::OUT
+        }
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if let Some(pollable) = self.ops.as_pollable() {
+            pollable.register(context, events);
+        }
+    }
+}
+
+/// Returns the device operations behind `entry`, if it is a [`Device`] or a
+/// [`DeviceFile`].
+pub fn device_ops(entry: &DirEntry) -> Option<Arc<dyn DeviceOps>> {
+    if let Ok(device) = entry.downcast::<Device>() {
+        Some(device.inner().clone())
+    } else if let Ok(device) = entry.downcast::<DeviceFile>() {
+        Some(device.inner().clone())
+    } else {
+        None
+    }
+}
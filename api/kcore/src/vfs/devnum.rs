@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Device number registry.
+//!
+//! Drivers register ranges of (major, minor) numbers for character or block
+//! devices. Opening a device node looks its number up here, so a node created
+//! with `mknod` on any filesystem reaches the same driver as the one in devfs.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::{Range, RangeInclusive};
+
+use fs_ng_vfs::{DeviceId, NodeType, VfsError, VfsResult};
+use kerrno::LinuxError;
+use ksync::Mutex;
+
+use super::DeviceOps;
+
+/// Kind of a device number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// Character device.
+    Char,
+    /// Block device.
+    Block,
+}
+
+impl DeviceKind {
+    /// Returns the device kind of a node type, if it is a device.
+    pub fn from_node_type(node_type: NodeType) -> Option<Self> {
+        match node_type {
+            NodeType::CharacterDevice => Some(Self::Char),
+            NodeType::BlockDevice => Some(Self::Block),
+            _ => None,
+        }
+    }
+}
+
+/// A driver owning one or more ranges of device numbers.
+pub trait DeviceDriver: Send + Sync {
+    /// Opens the device `dev`, which lies in a range registered by this
+    /// driver.
+    fn open(&self, dev: DeviceId) -> VfsResult<Arc<dyn DeviceOps>>;
+}
+
+/// Driver of a single device number.
+struct SingleDevice(Arc<dyn DeviceOps>);
+
+impl DeviceDriver for SingleDevice {
+    fn open(&self, _dev: DeviceId) -> VfsResult<Arc<dyn DeviceOps>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Majors handed out by [`alloc_region`], searched from the top like Linux
+/// does for character devices.
+const DYNAMIC_MAJORS: [RangeInclusive<u32>; 2] = [234..=254, 384..=511];
+
+struct Region {
+    kind: DeviceKind,
+    major: u32,
+    minors: Range<u32>,
+    driver: Arc<dyn DeviceDriver>,
+}
+
+impl Region {
+    fn overlaps(&self, kind: DeviceKind, major: u32, minors: &Range<u32>) -> bool {
+        self.kind == kind
+            && self.major == major
+            && self.minors.start < minors.end
+            && minors.start < self.minors.end
+    }
+}
+
+static REGIONS: Mutex<Vec<Region>> = Mutex::new(Vec::new());
+
+fn insert_region(
+    regions: &mut Vec<Region>,
+    kind: DeviceKind,
+    major: u32,
+    minors: Range<u32>,
+    driver: Arc<dyn DeviceDriver>,
+) -> VfsResult<()> {
+    if minors.is_empty() {
+        return Err(VfsError::InvalidInput);
+    }
+    if regions.iter().any(|r| r.overlaps(kind, major, &minors)) {
+        return Err(VfsError::ResourceBusy);
+    }
+    regions.push(Region {
+        kind,
+        major,
+        minors,
+        driver,
+    });
+    Ok(())
+}
+
+/// Registers `driver` for the minors `minors` of `major`.
+///
+/// Fails with `EBUSY` if any of the numbers is already registered.
+pub fn register_region(
+    kind: DeviceKind,
+    major: u32,
+    minors: Range<u32>,
+    driver: Arc<dyn DeviceDriver>,
+) -> VfsResult<()> {
+    insert_region(&mut REGIONS.lock(), kind, major, minors, driver)
+}
+
+/// Registers `driver` for the minors `minors` of an unused major, which is
+/// returned.
+pub fn alloc_region(
+    kind: DeviceKind,
+    minors: Range<u32>,
+    driver: Arc<dyn DeviceDriver>,
+) -> VfsResult<u32> {
+    let mut regions = REGIONS.lock();
+    let major = DYNAMIC_MAJORS
+        .iter()
+        .flat_map(|range| range.clone().rev())
+        .find(|&major| !regions.iter().any(|r| r.kind == kind && r.major == major))
+        .ok_or(VfsError::ResourceBusy)?;
+    insert_region(&mut regions, kind, major, minors, driver)?;
+    Ok(major)
+}
+
+/// Registers `ops` as the only device with number `dev`.
+pub fn register_device(kind: DeviceKind, dev: DeviceId, ops: Arc<dyn DeviceOps>) -> VfsResult<()> {
+    let minor = dev.minor();
+    register_region(
+        kind,
+        dev.major(),
+        minor..minor + 1,
+        Arc::new(SingleDevice(ops)),
+    )
+}
+
+/// Registers `ops` as the only device of an unused major, and returns its
+/// device number.
+pub fn alloc_device(kind: DeviceKind, ops: Arc<dyn DeviceOps>) -> VfsResult<DeviceId> {
+    let major = alloc_region(kind, 0..1, Arc::new(SingleDevice(ops)))?;
+    Ok(DeviceId::new(major, 0))
+}
+
+/// Unregisters the range previously registered with exactly these numbers.
+pub fn unregister_region(kind: DeviceKind, major: u32, minors: Range<u32>) -> VfsResult<()> {
+    let mut regions = REGIONS.lock();
+    let index = regions
+        .iter()
+        .position(|r| r.kind == kind && r.major == major && r.minors == minors)
+        .ok_or(VfsError::NotFound)?;
+    regions.remove(index);
+    Ok(())
+}
+
+/// Opens the device with number `dev` through its registered driver.
+///
+/// Fails with `ENXIO` if no driver is registered for the number.
+pub fn open_device(kind: DeviceKind, dev: DeviceId) -> VfsResult<Arc<dyn DeviceOps>> {
+    let (major, minor) = (dev.major(), dev.minor());
+    let driver = REGIONS
+        .lock()
+        .iter()
+        .find(|r| r.kind == kind && r.major == major && r.minors.contains(&minor))
+        .map(|r| r.driver.clone())
+        .ok_or(VfsError::from(LinuxError::ENXIO))?;
+    driver.open(dev)
+}
+
+#[cfg(unittest)]
+pub mod tests_devnum {
+    use core::any::Any;
+
+    use unittest::def_test;
+
+    use super::*;
+
+    struct Dummy;
+
+    impl DeviceOps for Dummy {
+        fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+            Ok(0)
+        }
+
+        fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+            Ok(buf.len())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[def_test]
+    fn test_register_overlap_is_busy() {
+        let ops: Arc<dyn DeviceOps> = Arc::new(Dummy);
+        register_device(DeviceKind::Char, DeviceId::new(500, 3), ops.clone()).unwrap();
+        assert_eq!(
+            register_device(DeviceKind::Char, DeviceId::new(500, 3), ops.clone()),
+            Err(VfsError::ResourceBusy)
+        );
+        // The same number of the other kind is a different device.
+        register_device(DeviceKind::Block, DeviceId::new(500, 3), ops).unwrap();
+        unregister_region(DeviceKind::Char, 500, 3..4).unwrap();
+        unregister_region(DeviceKind::Block, 500, 3..4).unwrap();
+    }
+
+    #[def_test]
+    fn test_open_unregistered_is_enxio() {
+        let dev = DeviceId::new(501, 0);
+        assert_eq!(
+            open_device(DeviceKind::Char, dev).err(),
+            Some(VfsError::from(LinuxError::ENXIO))
+        );
+        register_device(DeviceKind::Char, dev, Arc::new(Dummy)).unwrap();
+        let ops = open_device(DeviceKind::Char, dev).unwrap();
+        assert!(ops.as_any().is::<Dummy>());
+        unregister_region(DeviceKind::Char, 501, 0..1).unwrap();
+        assert!(open_device(DeviceKind::Char, dev).is_err());
+    }
+
+    #[def_test]
+    fn test_alloc_distinct_majors() {
+        let a = alloc_device(DeviceKind::Char, Arc::new(Dummy)).unwrap();
+        let b = alloc_device(DeviceKind::Char, Arc::new(Dummy)).unwrap();
+        assert_ne!(a.major(), b.major());
+        assert!(open_device(DeviceKind::Char, a).is_ok());
+        unregister_region(DeviceKind::Char, a.major(), 0..1).unwrap();
+        unregister_region(DeviceKind::Char, b.major(), 0..1).unwrap();
+    }
+}
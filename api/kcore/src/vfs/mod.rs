@@ -5,17 +5,21 @@
 //! Basic virtual filesystem support
 
 mod dev;
+mod devnum;
 mod dir;
 mod file;
 mod fs;
+mod seq;
 
 use alloc::sync::Arc;
 
 pub use dev::*;
+pub use devnum::*;
 pub use dir::*;
 pub use file::*;
 pub use fs::*;
 use fs_ng_vfs::{DirNodeOps, FileNodeOps, WeakDirEntry};
+pub use seq::*;
 
 /// A callback that builds a `Arc<dyn DirNodeOps>` for a given
 /// `WeakDirEntry`.
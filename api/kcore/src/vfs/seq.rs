@@ -0,0 +1,481 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Seq-file style files, rendering a changing collection a bounded chunk at a
+//! time.
+//!
+//! A [`SimpleFile`](super::SimpleFile) renders its whole content on every
+//! read, so a collection is either locked for as long as it takes to format
+//! all of it, or read torn: entries vanish or show twice when they move
+//! between two `read()` calls. A [`SeqFile`] instead walks the collection in
+//! the order of the keys of its entries, rendering at most
+//! [`SEQ_CHUNK_SIZE`] bytes per look at it, and goes on after the key of the
+//! last entry rendered. So the collection is locked for one chunk at a time,
+//! the content is never rendered at once, and an entry present throughout a
+//! read is shown exactly once, however the others change.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{any::Any, fmt};
+
+use fs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsError, VfsResult};
+use ksync::Mutex;
+
+use super::{Device, DeviceOps, SimpleFs};
+
+/// Bytes a [`SeqFile`] renders per look at its collection, unless a single
+/// entry is longer.
+pub const SEQ_CHUNK_SIZE: usize = 4096;
+
+/// Reads a [`SeqFile`] goes on with, see [`SeqFile::read_at`].
+const MAX_SESSIONS: usize = 8;
+
+/// What a [`SeqFile`] does when the collection changed since the previous
+/// chunk of a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnModified {
+    /// Goes on after the last entry shown.
+    Resume,
+    /// Goes on after the last entry shown, with the note written first.
+    Annotate(&'static str),
+    /// Starts over from the first entry, with the note written first. For
+    /// collections whose keys do not stay with their entries, e.g. the
+    /// positions in a ring buffer that may have wrapped.
+    Restart(&'static str),
+}
+
+/// A collection shown by a [`SeqFile`].
+pub trait SeqSource: Send + Sync + 'static {
+    /// The key of an entry, ordering the entries in the file.
+    type Key: Ord + Clone + Send + Sync + 'static;
+
+    /// Renders the entries with keys above `after`, or from the first entry
+    /// if it is `None`, into `chunk` in the order of their keys, until
+    /// [`SeqChunk::is_full`] or the last entry.
+    ///
+    /// The collection should be locked for this call only. Returns its
+    /// generation, a counter bumped by every change to it, read under the
+    /// same lock.
+    fn render(&self, after: Option<&Self::Key>, chunk: &mut SeqChunk<Self::Key>) -> VfsResult<u64>;
+
+    /// Returns what to do when the collection changed between two chunks.
+    fn on_modified(&self) -> OnModified {
+        OnModified::Resume
+    }
+}
+
+/// The entries rendered by one [`SeqSource::render`] call.
+pub struct SeqChunk<K> {
+    data: Vec<u8>,
+    limit: usize,
+    last: Option<K>,
+}
+
+impl<K> SeqChunk<K> {
+    fn new(limit: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            limit,
+            last: None,
+        }
+    }
+
+    /// Returns `true` if the chunk holds enough, and rendering should stop.
+    pub fn is_full(&self) -> bool {
+        self.data.len() >= self.limit
+    }
+
+    /// Appends the entry with `key`, which must be above those appended
+    /// before.
+    pub fn push(&mut self, key: K, entry: impl fmt::Display) {
+        use fmt::Write;
+
+        struct Bytes<'a>(&'a mut Vec<u8>);
+
+        impl Write for Bytes<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.extend_from_slice(s.as_bytes());
+                Ok(())
+            }
+        }
+
+        let _ = write!(Bytes(&mut self.data), "{entry}");
+        self.last = Some(key);
+    }
+}
+
+/// A read of a [`SeqFile`] in progress.
+struct Session<K> {
+    /// Offset of the next byte to read.
+    offset: u64,
+    /// Rendered bytes not read yet.
+    pending: Vec<u8>,
+    /// Bytes of `pending` read already.
+    read: usize,
+    /// Key of the last entry rendered.
+    cursor: Option<K>,
+    /// Generation of the collection when the last chunk was rendered, or
+    /// `None` before the first chunk.
+    generation: Option<u64>,
+    /// Whether the last entry was rendered.
+    done: bool,
+}
+
+impl<K> Session<K> {
+    fn new() -> Self {
+        Self {
+            offset: 0,
+            pending: Vec::new(),
+            read: 0,
+            cursor: None,
+            generation: None,
+            done: false,
+        }
+    }
+}
+
+/// A file showing a [`SeqSource`], see the [module-level docs](self).
+///
+/// The file has no per-open state, so a read in progress is recognized by
+/// its offset: a read at the offset where one stopped goes on with it, and
+/// other reads start over, skipping to their offset. Up to a few reads are
+/// kept, dropping the least recent.
+pub struct SeqFile<S: SeqSource> {
+    source: S,
+    chunk_size: usize,
+    sessions: Mutex<VecDeque<Session<S::Key>>>,
+}
+
+impl<S: SeqSource> SeqFile<S> {
+    /// Creates a file showing `source`.
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            chunk_size: SEQ_CHUNK_SIZE,
+            sessions: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Creates a read-only regular file node showing `source`.
+    pub fn new_regular(fs: Arc<SimpleFs>, source: S) -> Arc<Device> {
+        Device::new(
+            fs,
+            NodeType::RegularFile,
+            DeviceId::default(),
+            Arc::new(Self::new(source)),
+        )
+    }
+
+    /// Returns the shown collection.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Takes the read stopped at `offset`, or starts a new one, skipping to
+    /// `offset`. Reads at offset 0 always start anew.
+    fn session(&self, offset: u64) -> VfsResult<Session<S::Key>> {
+        if offset > 0 {
+            let mut sessions = self.sessions.lock();
+            if let Some(pos) = sessions.iter().position(|s| s.offset == offset) {
+                return Ok(sessions.remove(pos).unwrap());
+            }
+        }
+
+        let mut session = Session::new();
+        while session.offset < offset && self.fill(&mut session)? {
+            let pending = (session.pending.len() - session.read) as u64;
+            let skip = pending.min(offset - session.offset);
+            session.read += skip as usize;
+            session.offset += skip;
+        }
+        Ok(session)
+    }
+
+    /// Renders the next chunk into `session` once its rendered bytes are all
+    /// read. Returns `false` if there is nothing left.
+    fn fill(&self, session: &mut Session<S::Key>) -> VfsResult<bool> {
+        if session.read < session.pending.len() {
+            return Ok(true);
+        }
+        if session.done {
+            return Ok(false);
+        }
+        let mut chunk = SeqChunk::new(self.chunk_size);
+        let mut cursor = session.cursor.as_ref();
+        let mut note = None;
+        loop {
+            let generation = self.source.render(cursor, &mut chunk)?;
+            let modified = session.generation.is_some_and(|g| g != generation);
+            session.generation = Some(generation);
+            if !modified || note.is_some() {
+                break;
+            }
+            match self.source.on_modified() {
+                OnModified::Resume => break,
+                OnModified::Annotate(text) => {
+                    note = Some(text);
+                    break;
+                }
+                OnModified::Restart(text) => {
+                    note = Some(text);
+                    chunk = SeqChunk::new(self.chunk_size);
+                    cursor = None;
+                }
+            }
+        }
+
+        session.done = !chunk.is_full();
+        if chunk.last.is_some() {
+            session.cursor = chunk.last;
+        }
+        session.pending = chunk.data;
+        session.read = 0;
+        if let Some(note) = note {
+            session.pending.splice(..0, note.bytes());
+        }
+        Ok(!session.pending.is_empty())
+    }
+
+    /// Reads the content at `offset`, going on with the read stopped there
+    /// if any.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let mut session = self.session(offset)?;
+        let mut read = 0;
+        while read < buf.len() && self.fill(&mut session)? {
+            let pending = &session.pending[session.read..];
+            let len = pending.len().min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&pending[..len]);
+            session.read += len;
+            read += len;
+        }
+        session.offset = offset + read as u64;
+
+        let mut sessions = self.sessions.lock();
+        // Reads stopped at the same offset cannot be told apart, keep the
+        // latest.
+        sessions.retain(|s| s.offset != session.offset);
+        if sessions.len() >= MAX_SESSIONS {
+            sessions.pop_front();
+        }
+        sessions.push_back(session);
+        Ok(read)
+    }
+}
+
+impl<S: SeqSource> DeviceOps for SeqFile<S> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        SeqFile::read_at(self, buf, offset)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::BadFileDescriptor)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+#[cfg(unittest)]
+pub mod tests_seq {
+    use alloc::{collections::BTreeMap, string::String, vec};
+    use core::{
+        sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use khal::time::{TimeValue, wall_time};
+    use kspin::SpinNoIrq;
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    /// Entries `key -> value`, shown as `key value` lines.
+    #[derive(Default)]
+    struct Table {
+        entries: SpinNoIrq<(BTreeMap<u32, u32>, u64)>,
+        on_modified: Option<OnModified>,
+        /// Longest time the entries were locked for rendering, in
+        /// nanoseconds.
+        max_hold: AtomicU64,
+    }
+
+    impl Table {
+        fn insert(&self, key: u32, value: u32) {
+            let mut entries = self.entries.lock();
+            entries.0.insert(key, value);
+            entries.1 += 1;
+        }
+
+        fn remove(&self, key: u32) {
+            let mut entries = self.entries.lock();
+            entries.0.remove(&key);
+            entries.1 += 1;
+        }
+    }
+
+    impl SeqSource for Table {
+        type Key = u32;
+
+        fn render(&self, after: Option<&u32>, chunk: &mut SeqChunk<u32>) -> VfsResult<u64> {
+            let entries = self.entries.lock();
+            let start = wall_time();
+            let first = after.map_or(0, |key| key + 1);
+            for (&key, value) in entries.0.range(first..) {
+                if chunk.is_full() {
+                    break;
+                }
+                chunk.push(key, format_args!("{key:05} {value}\n"));
+            }
+            let hold = (wall_time() - start).as_nanos() as u64;
+            self.max_hold.fetch_max(hold, Ordering::Relaxed);
+            Ok(entries.1)
+        }
+
+        fn on_modified(&self) -> OnModified {
+            self.on_modified.unwrap_or(OnModified::Resume)
+        }
+    }
+
+    fn file(table: Table, chunk_size: usize) -> SeqFile<Table> {
+        SeqFile {
+            chunk_size,
+            ..SeqFile::new(table)
+        }
+    }
+
+    fn read_to_string(file: &SeqFile<Table>, offset: u64, step: usize) -> String {
+        let mut out = Vec::new();
+        let mut buf = vec![0; step];
+        loop {
+            let read = file.read_at(&mut buf, offset + out.len() as u64).unwrap();
+            if read == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[def_test]
+    fn test_seq_file_reads_in_parts() {
+        let table = Table::default();
+        for key in 0..100 {
+            table.insert(key, key * 2);
+        }
+        let file = file(table, 64);
+        let whole = read_to_string(&file, 0, 4096);
+        assert_eq!(whole.lines().count(), 100);
+        assert!(whole.starts_with("00000 0\n00001 2\n"));
+        assert_eq!(read_to_string(&file, 0, 7), whole);
+
+        // A read at another offset skips to it.
+        assert_eq!(read_to_string(&file, 100, 3), whole[100..]);
+        let mut buf = [0; 8];
+        assert_eq!(file.read_at(&mut buf, whole.len() as u64).unwrap(), 0);
+
+        // Reading again from the start sees the changes.
+        file.source().insert(100, 0);
+        assert_eq!(read_to_string(&file, 0, 4096), whole + "00100 0\n");
+    }
+
+    #[def_test]
+    fn test_seq_file_on_modified() {
+        let read_modified = |on_modified| {
+            let table = Table {
+                on_modified: Some(on_modified),
+                ..Table::default()
+            };
+            for key in [10, 20, 30] {
+                table.insert(key, 0);
+            }
+            // One entry per chunk, the first read takes the first one.
+            let file = file(table, 1);
+            let mut buf = [0; 8];
+            let read = file.read_at(&mut buf, 0).unwrap();
+            file.source().remove(10);
+            file.source().insert(5, 0);
+            file.source().insert(25, 0);
+            String::from_utf8(buf[..read].to_vec()).unwrap() + &read_to_string(&file, 8, 8)
+        };
+        assert_eq!(
+            read_modified(OnModified::Resume),
+            "00010 0\n00020 0\n00025 0\n00030 0\n"
+        );
+        assert_eq!(
+            read_modified(OnModified::Annotate("# changed\n")),
+            "00010 0\n# changed\n00020 0\n00025 0\n00030 0\n"
+        );
+        assert_eq!(
+            read_modified(OnModified::Restart("# restart\n")),
+            "00010 0\n# restart\n00005 0\n00020 0\n00025 0\n00030 0\n"
+        );
+    }
+
+    /// A reader taking one byte at a time with long pauses races a writer
+    /// churning the entries between the stable ones.
+    #[def_test]
+    fn test_seq_file_slow_reader_races_writer() {
+        const STABLE: u32 = 200;
+        /// Longest the entries may stay locked, and so stall the writer.
+        const MAX_HOLD: Duration = Duration::from_millis(1);
+
+        let table = Table::default();
+        for key in 0..STABLE {
+            table.insert(key * 2, 0);
+        }
+        let file = Arc::new(file(table, 256));
+        let done = Arc::new(AtomicBool::new(false));
+        let writes = Arc::new(AtomicUsize::new(0));
+
+        let writer = {
+            let (file, done, writes) = (file.clone(), done.clone(), writes.clone());
+            ktask::spawn(move || {
+                let mut n = 0u32;
+                while !done.load(Ordering::Acquire) {
+                    // Odd keys come and go, even ones change their values.
+                    let key = (n.wrapping_mul(7919) % STABLE) * 2 + 1;
+                    if n % 2 == 0 {
+                        file.source().insert(key, n);
+                    } else {
+                        file.source().remove(key);
+                    }
+                    file.source().insert((n % STABLE) * 2, n);
+                    writes.fetch_add(1, Ordering::Relaxed);
+                    n = n.wrapping_add(1);
+                    ktask::yield_now();
+                }
+            })
+        };
+
+        let mut out = Vec::new();
+        let mut byte = [0; 1];
+        while file.read_at(&mut byte, out.len() as u64).unwrap() == 1 {
+            out.push(byte[0]);
+            if out.len() % 64 == 0 {
+                ktask::sleep(Duration::from_millis(1));
+            } else {
+                ktask::yield_now();
+            }
+        }
+        done.store(true, Ordering::Release);
+        writer.join();
+
+        let keys: Vec<u32> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line[..5].parse().unwrap())
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        let stable: Vec<u32> = keys.into_iter().filter(|key| key % 2 == 0).collect();
+        assert_eq!(stable, (0..STABLE).map(|key| key * 2).collect::<Vec<_>>());
+
+        assert!(writes.load(Ordering::Relaxed) > 0);
+        let max_hold = TimeValue::from_nanos(file.source().max_hold.load(Ordering::Relaxed));
+        assert!(max_hold < MAX_HOLD);
+    }
+}
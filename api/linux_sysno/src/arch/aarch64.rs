@@ -694,6 +694,10 @@ syscall_enum! {
         removexattrat = 466,
         /// See [open_tree_attr(2)](https://man7.org/linux/man-pages/man2/open_tree_attr.2.html) for more info on this syscall.
         open_tree_attr = 467,
+
+        /// Kernel specific: runs a batch of file descriptor operations, see
+        /// `sys_multiop` in kapi.
+        multiop = 490,
     }
-    LAST: open_tree_attr;
+    LAST: multiop;
 }
@@ -695,6 +695,10 @@ syscall_enum! {
         /// See [open_tree_attr(2)](https://man7.org/linux/man-pages/man2/open_tree_attr.2.html) for more info on this syscall.
         open_tree_attr = 467,
 
+        /// Kernel specific: runs a batch of file descriptor operations, see
+        /// `sys_multiop` in kapi.
+        multiop = 490,
+
         /// TEE syscalls with prefix 500 for OP-TEE compatibility
         tee_scn_return = 500,
         tee_scn_log = 501,
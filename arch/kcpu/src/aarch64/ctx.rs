@@ -8,6 +8,10 @@ use core::{arch::naked_asm, fmt};
 
 use memaddr::VirtAddr;
 
+/// The bit of SPSR_EL1 telling that the exception was taken from AArch32
+/// state (`M[4]`).
+const SPSR_AARCH32: u64 = 1 << 4;
+
 /// Saved registers when a trap (exception) occurs.
 #[repr(C)]
 #[derive(Default, Clone, Copy)]
@@ -39,36 +43,58 @@ impl fmt::Debug for ExceptionContext {
 }
 
 impl ExceptionContext {
-    // There are 6 arguments for syscalls in AArch64 (x0-x5).
+    // There are 6 arguments for syscalls in AArch64 (x0-x5), and as many in
+    // AArch32 (r0-r5).
+
+    /// Returns whether the context was saved from AArch32 state.
+    ///
+    /// Only the user registers of AArch32 are visible at EL0: R0-R12 are
+    /// X0-X12, SP_usr is X13 and LR_usr is X14, CPSR is SPSR_EL1. The banked
+    /// registers of the other AArch32 modes need no room in the frame.
+    #[inline]
+    pub const fn is_aarch32(&self) -> bool {
+        cfg!(feature = "compat") && self.spsr & SPSR_AARCH32 != 0
+    }
+
+    /// Reads a general-purpose register, as 32 bits in AArch32 state, where
+    /// the upper halves of the X registers are unknown.
+    #[inline]
+    const fn reg(&self, idx: usize) -> usize {
+        if self.is_aarch32() {
+            self.x[idx] as u32 as usize
+        } else {
+            self.x[idx] as usize
+        }
+    }
 
     /// Gets the 0th syscall argument.
     pub const fn arg0(&self) -> usize {
-        self.x[0] as usize
+        self.reg(0)
     }
 
     /// Gets the 1st syscall argument.
     pub const fn arg1(&self) -> usize {
-        self.x[1] as usize
+        self.reg(1)
     }
 
     /// Gets the 2nd syscall argument.
     pub const fn arg2(&self) -> usize {
-        self.x[2] as usize
+        self.reg(2)
     }
 
     /// Gets the 3rd syscall argument.
     pub const fn arg3(&self) -> usize {
-        self.x[3] as usize
+        self.reg(3)
     }
 
     /// Gets the 4th syscall argument.
     pub const fn arg4(&self) -> usize {
-        self.x[4] as usize
+        self.reg(4)
     }
 
     /// Gets the 5th syscall argument.
     pub const fn arg5(&self) -> usize {
-        self.x[5] as usize
+        self.reg(5)
     }
 
     /// Sets the 0th syscall argument.
@@ -111,29 +137,50 @@ impl ExceptionContext {
         self.elr = val as u64;
     }
 
-    /// Get the syscall number (x8).
+    /// Get the syscall number (x8, or r7 in AArch32 state).
     pub const fn sysno(&self) -> usize {
-        self.x[8] as usize
+        if self.is_aarch32() {
+            self.reg(7)
+        } else {
+            self.x[8] as usize
+        }
     }
 
     /// Sets the syscall number.
     pub const fn set_sysno(&mut self, val: usize) {
-        self.x[8] = val as u64;
+        if self.is_aarch32() {
+            self.x[7] = val as u64;
+        } else {
+            self.x[8] = val as u64;
+        }
     }
 
     /// Gets the return value register.
     pub const fn retval(&self) -> usize {
-        self.x[0] as usize
+        self.reg(0)
     }
 
     /// Sets the return value register.
     pub const fn set_retval(&mut self, val: usize) {
-        self.x[0] = val as u64;
+        if self.is_aarch32() {
+            self.x[0] = val as u32 as u64;
+        } else {
+            self.x[0] = val as u64;
+        }
     }
 
-    /// Sets the return address (LR/x30).
+    /// Sets the return address (LR/x30, or LR_usr/x14 in AArch32 state).
     pub const fn set_ra(&mut self, val: usize) {
-        self.x[30] = val as u64;
+        if self.is_aarch32() {
+            self.x[14] = val as u64;
+        } else {
+            self.x[30] = val as u64;
+        }
+    }
+
+    /// Gets the frame pointer.
+    pub const fn fp(&self) -> usize {
+        self.x[29] as usize
     }
 
     /// Unwind the stack and get the backtrace.
@@ -218,6 +265,13 @@ pub struct TaskContext {
 }
 
 impl TaskContext {
+    /// Unwinds the kernel stack of a switched out task.
+    ///
+    /// Only meaningful while the task is not running.
+    pub fn backtrace(&self) -> backtrace::Backtrace {
+        backtrace::Backtrace::capture_trap(self.r29 as _, self.lr as _, self.lr as _)
+    }
+
     /// Creates a dummy context for a new task.
     ///
     /// Note the context is not initialized, it will be filled by [`switch_to`]
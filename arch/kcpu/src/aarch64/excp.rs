@@ -42,10 +42,12 @@ core::arch::global_asm!(
 );
 
 #[inline(always)]
-/// Returns true if the ISS indicates a translation or permission fault.
+/// Returns true if the ISS indicates a translation, access flag or permission
+/// fault.
 pub(super) fn check_page_fault(iss: u64) -> bool {
-    // Only dispatch_irq Translation fault and Permission fault
-    matches!(iss & 0b111100, 0b0100 | 0b1100)
+    // Access flag faults are taken by pages marked idle, as the hardware does
+    // not manage the flag.
+    matches!(iss & 0b111100, 0b0100 | 0b1000 | 0b1100)
 }
 
 /// Dispatches a page fault and panics if unhandled.
@@ -58,15 +60,17 @@ fn handle_page_fault(tf: &mut ExceptionContext, access_flags: PageFaultFlags) {
     if tf.fixup_exception() {
         return;
     }
+    if crate::excp::recover_kernel_fault(tf, vaddr, access_flags) {
+        return;
+    }
     core::hint::cold_path();
     panic!(
-        "Unhandled EL1 Page Fault @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}\n{}",
+        "Unhandled EL1 Page Fault @ {:#x}, fault_vaddr={:#x}, ESR={:#x} ({:?}):\n{:#x?}",
         tf.elr,
         vaddr,
         ESR_EL1.get(),
         access_flags,
         tf,
-        tf.backtrace()
     );
 }
 
@@ -117,14 +121,13 @@ fn dispatch_exception(tf: &mut ExceptionContext, kind: ArchTrap, source: ArchTra
                     let vaddr = va!(FAR_EL1.get() as usize);
                     panic!(
                         "Unhandled synchronous exception {:?} @ {:#x}: ESR={:#x} (EC {:#08b}, \
-                         FAR: {:#x} ISS {:#x})\n{}",
+                         FAR: {:#x} ISS {:#x})",
                         e,
                         tf.elr,
                         esr.get(),
                         esr.read(ESR_EL1::EC),
                         vaddr,
                         iss,
-                        tf.backtrace()
                     );
                 }
             }
@@ -18,6 +18,11 @@ use crate::excp::PageFaultFlags;
 pub use crate::userspace_common::{ExceptionKind, ReturnReason};
 
 /// Context to enter user space.
+///
+/// With the `compat` feature, the context may also run 32-bit ARM code in
+/// AArch32 state, see [`UserContext::new_compat`]. The stack pointer is then
+/// SP_usr, kept in X13 of the trap frame, and the TLS area is the read-only
+/// thread ID register TPIDRURO.
 #[repr(C, align(16))]
 #[derive(Debug, Clone, Copy)]
 pub struct UserContext {
@@ -26,6 +31,10 @@ pub struct UserContext {
     pub sp: u64,
     /// Software Thread ID Register (TPIDR_EL0).
     pub tpidr: u64,
+    /// Read-only Software Thread ID Register (TPIDRRO_EL0), which AArch32
+    /// code reads its TLS area from.
+    #[cfg(feature = "compat")]
+    pub tpidrro: u64,
 }
 
 impl UserContext {
@@ -51,26 +60,46 @@ impl UserContext {
             },
             sp: ustack_top.as_usize() as _,
             tpidr: 0,
+            #[cfg(feature = "compat")]
+            tpidrro: 0,
         }
     }
 
     /// Gets the stack pointer.
     pub const fn sp(&self) -> usize {
+        #[cfg(feature = "compat")]
+        if self.tf.is_aarch32() {
+            return self.tf.x[13] as u32 as usize;
+        }
         self.sp as _
     }
 
     /// Sets the stack pointer.
     pub const fn set_sp(&mut self, sp: usize) {
+        #[cfg(feature = "compat")]
+        if self.tf.is_aarch32() {
+            self.tf.x[13] = sp as u32 as u64;
+            return;
+        }
         self.sp = sp as _;
     }
 
     /// Gets the TLS area.
     pub const fn tls(&self) -> usize {
+        #[cfg(feature = "compat")]
+        if self.tf.is_aarch32() {
+            return self.tpidrro as _;
+        }
         self.tpidr as _
     }
 
     /// Sets the TLS area.
     pub const fn set_tls(&mut self, tls: usize) {
+        #[cfg(feature = "compat")]
+        if self.tf.is_aarch32() {
+            self.tpidrro = tls as _;
+            return;
+        }
         self.tpidr = tls as _;
     }
 
@@ -86,6 +115,11 @@ impl UserContext {
         }
 
         crate::instrs::disable_local(); // updated module reference from asm -> instrs
+        // Read-only at EL0, so it needs no saving on the way back.
+        #[cfg(feature = "compat")]
+        unsafe {
+            core::arch::asm!("msr tpidrro_el0, {}", in(reg) self.tpidrro);
+        }
         let trap_kind = unsafe { enter_user(self) };
 
         let ret = match trap_kind {
@@ -102,6 +136,8 @@ impl UserContext {
 
                 match esr.read_as_enum(ESR_EL1::EC) {
                     Some(ESR_EL1::EC::Value::SVC64) => ReturnReason::Syscall,
+                    #[cfg(feature = "compat")]
+                    Some(ESR_EL1::EC::Value::SVC32) => ReturnReason::Syscall,
                     Some(ESR_EL1::EC::Value::InstrAbortLowerEL) if check_page_fault(iss) => {
                         ReturnReason::PageFault(
                             va!(far),
@@ -157,7 +193,9 @@ impl ExceptionInfo {
     /// Returns a generalized kind of this exception.
     pub fn kind(&self) -> ExceptionKind {
         match self.esr.read_as_enum(ESR_EL1::EC) {
-            Some(ESR_EL1::EC::Value::BreakpointLowerEL) => ExceptionKind::Breakpoint,
+            Some(ESR_EL1::EC::Value::BreakpointLowerEL) | Some(ESR_EL1::EC::Value::Bkpt32) => {
+                ExceptionKind::Breakpoint
+            }
             Some(ESR_EL1::EC::Value::IllegalExecutionState) => ExceptionKind::IllegalInstruction,
             Some(ESR_EL1::EC::Value::PCAlignmentFault)
             | Some(ESR_EL1::EC::Value::SPAlignmentFault) => ExceptionKind::Misaligned,
@@ -165,3 +203,83 @@ impl ExceptionInfo {
         }
     }
 }
+
+#[cfg(feature = "compat")]
+mod compat {
+    use memaddr::VirtAddr;
+
+    use super::UserContext;
+
+    /// AArch32 user mode in `CPSR.M`, with `M[4]` set.
+    const PSR_AA32_MODE_USR: u64 = 0b1_0000;
+    /// Thumb state bit of `CPSR`.
+    const PSR_AA32_T_BIT: u64 = 1 << 5;
+    /// The bits of `CPSR` user code may change: the condition flags, `Q`,
+    /// `GE`, the `IT` state, `E` and `T`.
+    const PSR_AA32_USER_MASK: u64 = 0xfe0f_fe20;
+
+    impl UserContext {
+        /// Creates a new context running 32-bit ARM code in AArch32 state,
+        /// with the given entry point, user stack pointer, and the argument.
+        ///
+        /// An odd entry point starts in Thumb state.
+        pub fn new_compat(entry: usize, ustack_top: VirtAddr, arg0: usize) -> Self {
+            let mut uctx = Self::new(0, VirtAddr::from(0), arg0);
+            uctx.set_compat(true);
+            uctx.set_compat_ip(entry);
+            uctx.set_sp(ustack_top.as_usize());
+            uctx
+        }
+
+        /// Switches the context between AArch64 and AArch32 state, as exec
+        /// does when the new program runs in the other one.
+        ///
+        /// The processor state is reset to that of a new program.
+        pub fn set_compat(&mut self, compat: bool) {
+            if compat == self.tf.is_aarch32() {
+                return;
+            }
+            if compat {
+                self.tf.spsr = PSR_AA32_MODE_USR;
+            } else {
+                self.tf.spsr = Self::new(0, VirtAddr::from(0), 0).tf.spsr;
+                self.tpidrro = 0;
+            }
+        }
+
+        /// Returns whether the context runs in Thumb state.
+        pub const fn is_thumb(&self) -> bool {
+            self.tf.is_aarch32() && self.tf.spsr & PSR_AA32_T_BIT != 0
+        }
+
+        /// Jumps to `ip` in AArch32 state, interworking as `BX` does: an odd
+        /// address switches to Thumb state, an even one to ARM state.
+        pub fn set_compat_ip(&mut self, ip: usize) {
+            if ip & 1 != 0 {
+                self.tf.spsr |= PSR_AA32_T_BIT;
+            } else {
+                self.tf.spsr &= !PSR_AA32_T_BIT;
+            }
+            // Leave any `IT` block the old code was in.
+            self.tf.spsr &= !0x0600_fc00;
+            self.tf.elr = (ip & !1) as u32 as u64;
+        }
+
+        /// Sets `CPSR` from a value user space saved, e.g. in a signal frame,
+        /// keeping the context in AArch32 user mode.
+        pub fn set_compat_cpsr(&mut self, cpsr: u32) {
+            self.tf.spsr = (cpsr as u64 & PSR_AA32_USER_MASK) | PSR_AA32_MODE_USR;
+        }
+    }
+
+    /// Returns whether the CPUs can run AArch32 code at EL0.
+    pub fn aarch32_el0_supported() -> bool {
+        let pfr0: u64;
+        unsafe { core::arch::asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0) };
+        // EL0 handling, bits [3:0]: 0b0010 if AArch32 is supported too.
+        pfr0 & 0xf == 0b0010
+    }
+}
+
+#[cfg(feature = "compat")]
+pub use compat::aarch32_el0_supported;
@@ -20,6 +20,26 @@ pub static IRQ: [fn(usize) -> bool];
 #[def_trap_handler]
 pub static PAGE_FAULT: [fn(VirtAddr, PageFaultFlags) -> bool];
 
+/// A slice of handlers for kernel page faults that nothing else resolved.
+///
+/// A handler returns `true` if it took over the fault, typically by
+/// redirecting the trap frame so that only the faulting task is terminated.
+/// Unlike other traps, having no handler here is normal.
+#[def_trap_handler]
+pub static RECOVERABLE_FAULT: [fn(&mut TrapFrame, VirtAddr, PageFaultFlags) -> bool];
+
+/// Offers an unresolved kernel page fault to the [`RECOVERABLE_FAULT`]
+/// handlers.
+pub(crate) fn recover_kernel_fault(
+    tf: &mut TrapFrame,
+    vaddr: VirtAddr,
+    access_flags: PageFaultFlags,
+) -> bool {
+    RECOVERABLE_FAULT
+        .iter()
+        .any(|handler| handler(tf, vaddr, access_flags))
+}
+
 #[allow(unused_macros)]
 macro_rules! dispatch_irq_trap {
     ($trap:ident, $($args:tt)*) => {{
@@ -54,6 +74,18 @@ pub mod tests_excp {
         assert_eq!(PAGE_FAULT.len(), count);
     }
 
+    #[def_test]
+    fn test_no_recovery_without_handlers() {
+        if RECOVERABLE_FAULT.is_empty() {
+            let mut tf = TrapFrame::default();
+            assert!(!recover_kernel_fault(
+                &mut tf,
+                VirtAddr::from(0),
+                PageFaultFlags::READ
+            ));
+        }
+    }
+
     #[def_test]
     fn test_page_fault_flags_bits() {
         let flags = PageFaultFlags::empty();
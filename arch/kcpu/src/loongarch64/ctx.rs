@@ -235,6 +235,11 @@ impl ExceptionContext {
         self.regs.tp = tls_area;
     }
 
+    /// Gets the frame pointer.
+    pub const fn fp(&self) -> usize {
+        self.regs.fp
+    }
+
     /// Unwind the stack and get the backtrace.
     pub fn backtrace(&self) -> backtrace::Backtrace {
         backtrace::Backtrace::capture_trap(self.regs.fp as _, self.era as _, self.regs.ra as _)
@@ -272,6 +277,14 @@ pub struct TaskContext {
 }
 
 impl TaskContext {
+    /// Unwinds the kernel stack of a switched out task.
+    ///
+    /// Only meaningful while the task is not running.
+    pub fn backtrace(&self) -> backtrace::Backtrace {
+        // $r22 is the frame pointer.
+        backtrace::Backtrace::capture_trap(self.s[0], self.ra, self.ra)
+    }
+
     /// Creates a new default context for a new task.
     pub fn new() -> Self {
         Self::default()
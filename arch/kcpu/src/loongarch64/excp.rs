@@ -34,14 +34,13 @@ fn dispatch_irq_page_fault(tf: &mut ExceptionContext, access_flags: PageFaultFla
     if tf.fixup_exception() {
         return;
     }
+    if crate::excp::recover_kernel_fault(tf, vaddr, access_flags) {
+        return;
+    }
     core::hint::cold_path();
     panic!(
-        "Undispatch_irqd PLV0 Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
-        tf.era,
-        vaddr,
-        access_flags,
-        tf,
-        tf.backtrace()
+        "Undispatch_irqd PLV0 Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
+        tf.era, vaddr, access_flags, tf,
     );
 }
 
@@ -74,11 +73,8 @@ fn loongarch64_trap_handler(tf: &mut ExceptionContext) {
         }
         trap => {
             panic!(
-                "Undispatch_irqd trap {:?} @ {:#x}:\n{:#x?}\n{}",
-                trap,
-                tf.era,
-                tf,
-                tf.backtrace()
+                "Undispatch_irqd trap {:?} @ {:#x}:\n{:#x?}",
+                trap, tf.era, tf,
             );
         }
     }
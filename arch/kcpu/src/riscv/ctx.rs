@@ -283,6 +283,11 @@ impl ExceptionContext {
         self.regs.tp = tls_area;
     }
 
+    /// Gets the frame pointer.
+    pub const fn fp(&self) -> usize {
+        self.regs.s0
+    }
+
     /// Unwind the stack and get the backtrace.
     pub fn backtrace(&self) -> backtrace::Backtrace {
         backtrace::Backtrace::capture_trap(self.regs.s0 as _, self.sepc as _, self.regs.ra as _)
@@ -343,6 +348,13 @@ pub struct TaskContext {
 }
 
 impl TaskContext {
+    /// Unwinds the kernel stack of a switched out task.
+    ///
+    /// Only meaningful while the task is not running.
+    pub fn backtrace(&self) -> backtrace::Backtrace {
+        backtrace::Backtrace::capture_trap(self.s0, self.ra, self.ra)
+    }
+
     /// Creates a dummy context for a new task.
     ///
     /// Note the context is not initialized, it will be filled by [`switch_to`]
@@ -39,14 +39,13 @@ fn dispatch_irq_page_fault(tf: &mut ExceptionContext, access_flags: PageFaultFla
     if tf.fixup_exception() {
         return;
     }
+    if crate::excp::recover_kernel_fault(tf, vaddr, access_flags) {
+        return;
+    }
     core::hint::cold_path();
     panic!(
-        "Undispatch_irqd Supervisor Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}\n{}",
-        tf.sepc,
-        vaddr,
-        access_flags,
-        tf,
-        tf.backtrace()
+        "Undispatch_irqd Supervisor Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
+        tf.sepc, vaddr, access_flags, tf,
     );
 }
 
@@ -70,22 +69,20 @@ fn riscv_trap_handler(tf: &mut ExceptionContext) {
             }
             _ => {
                 panic!(
-                    "Undispatch_irqd trap {:?} @ {:#x}, stval={:#x}:\n{:#x?}\n{}",
+                    "Undispatch_irqd trap {:?} @ {:#x}, stval={:#x}:\n{:#x?}",
                     cause,
                     tf.sepc,
                     stval::read(),
                     tf,
-                    tf.backtrace()
                 );
             }
         }
     } else {
         panic!(
-            "Unknown trap {:#x?} @ {:#x}:\n{:#x?}\n{}",
+            "Unknown trap {:#x?} @ {:#x}:\n{:#x?}",
             scause.cause(),
             tf.sepc,
             tf,
-            tf.backtrace()
         );
     }
 
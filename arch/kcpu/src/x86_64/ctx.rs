@@ -161,6 +161,11 @@ impl ExceptionContext {
         self.rax = rax as _;
     }
 
+    /// Gets the frame pointer.
+    pub const fn fp(&self) -> usize {
+        self.rbp as usize
+    }
+
     /// Unwind the stack and get the backtrace.
     pub fn backtrace(&self) -> backtrace::Backtrace {
         backtrace::Backtrace::capture_trap(self.rbp as _, self.rip as _, 0)
@@ -286,6 +291,15 @@ pub struct TaskContext {
 }
 
 impl TaskContext {
+    /// Unwinds the kernel stack of a switched out task.
+    ///
+    /// Only meaningful while the task is not running.
+    pub fn backtrace(&self) -> backtrace::Backtrace {
+        // Safety: a switched out task keeps its `ContextSwitchFrame` at `rsp`.
+        let frame = unsafe { &*(self.rsp as *const ContextSwitchFrame) };
+        backtrace::Backtrace::capture_trap(frame.rbp as _, frame.rip as _, frame.rip as _)
+    }
+
     /// Creates a dummy context for a new task.
     ///
     /// Note the context is not initialized, it will be filled by [`switch_to`]
@@ -34,15 +34,13 @@ fn dispatch_irq_page_fault(tf: &mut ExceptionContext) {
     if tf.fixup_exception() {
         return;
     }
+    if crate::excp::recover_kernel_fault(tf, vaddr, access_flags) {
+        return;
+    }
     core::hint::cold_path();
     panic!(
-        "Undispatch_irqd #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}\n{}",
-        tf.rip,
-        vaddr,
-        tf.error_code,
-        access_flags,
-        tf,
-        tf.backtrace()
+        "Undispatch_irqd #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
+        tf.rip, vaddr, tf.error_code, access_flags, tf,
     );
 }
 
@@ -55,11 +53,8 @@ fn x86_trap_handler(tf: &mut ExceptionContext) {
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
-                "#GP @ {:#x}, error_code={:#x}:\n{:#x?}\n{}",
-                tf.rip,
-                tf.error_code,
-                tf,
-                tf.backtrace()
+                "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
+                tf.rip, tf.error_code, tf,
             );
         }
         IRQ_VECTOR_START..=IRQ_VECTOR_END => {
@@ -67,13 +62,12 @@ fn x86_trap_handler(tf: &mut ExceptionContext) {
         }
         _ => {
             panic!(
-                "Undispatch_irqd exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}\n{}",
+                "Undispatch_irqd exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}",
                 tf.vector,
                 vec_to_str(tf.vector),
                 tf.error_code,
                 tf.rip,
                 tf,
-                tf.backtrace()
             );
         }
     }
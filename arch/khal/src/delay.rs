@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Calibrated busy-wait delays for drivers.
+//!
+//! Delays count ticks of the platform timer when its counter runs at a
+//! constant rate: the generic timer on AArch64, the `time` CSR on RISC-V, the
+//! stable counter on LoongArch, and the TSC when the CPU reports it invariant.
+//! Otherwise they spin a number of loops calibrated against the timer by
+//! [`init`], which [`recalibrate`] redoes after the CPU clock changes.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use kplat::timer::{NS_MS, NS_US, now, now_ns, now_ticks, ns2t};
+
+/// How long the calibration loop runs.
+const CALIBRATION_NANOS: u64 = NS_MS;
+
+/// Delays longer than this are reported when requested in IRQ context.
+#[cfg(debug_assertions)]
+const IRQ_DELAY_WARN_NANOS: u64 = 100 * NS_US;
+
+/// Calibrated delay loops per microsecond, or 0 if delays count timer ticks.
+static LOOPS_PER_US: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the timer counter runs at a constant rate, independent of
+/// the CPU clock.
+fn counter_is_stable() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        raw_cpuid::CpuId::new()
+            .get_advanced_power_mgmt_info()
+            .is_some_and(|info| info.has_invariant_tsc())
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        true
+    }
+}
+
+#[inline(never)]
+fn delay_loops(loops: u64) {
+    for _ in 0..loops {
+        spin_loop();
+    }
+}
+
+/// Returns the number of delay loops per microsecond, measured against the
+/// timer.
+fn calibrate() -> u64 {
+    let mut loops = 1u64;
+    loop {
+        let start = now_ns();
+        delay_loops(loops);
+        let elapsed = now_ns() - start;
+        if elapsed >= CALIBRATION_NANOS {
+            return (loops * NS_US / elapsed).max(1);
+        }
+        loops *= 2;
+    }
+}
+
+/// Chooses how delays are measured, calibrating the delay loop if the timer
+/// counter is not stable.
+///
+/// Must be called once the platform timer works. Until then, delays count
+/// timer ticks.
+pub fn init() {
+    if counter_is_stable() {
+        info!("delay: using the timer counter");
+    } else {
+        recalibrate();
+        info!(
+            "delay: timer counter is not stable, {} loops/us",
+            LOOPS_PER_US.load(Ordering::Relaxed)
+        );
+    }
+}
+
+/// Recalibrates the delay loop.
+///
+/// Called by cpufreq when the CPU clock changes. Does nothing if delays count
+/// ticks of a stable counter.
+pub fn recalibrate() {
+    if !counter_is_stable() {
+        LOOPS_PER_US.store(calibrate(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(debug_assertions)]
+fn check_context(nanos: u64) {
+    if nanos > IRQ_DELAY_WARN_NANOS && crate::irq::in_irq() {
+        warn!("delay: busy-waiting {nanos} ns in IRQ context");
+    }
+}
+
+/// Busy-waits for at least `nanos` nanoseconds.
+pub fn ndelay(nanos: u64) {
+    #[cfg(debug_assertions)]
+    check_context(nanos);
+    match LOOPS_PER_US.load(Ordering::Relaxed) {
+        0 => {
+            // The first tick may be partial, so wait one more.
+            let ticks = ns2t(nanos) + 1;
+            let start = now_ticks();
+            while now_ticks() - start < ticks {
+                spin_loop();
+            }
+        }
+        loops_per_us => delay_loops(nanos.saturating_mul(loops_per_us).div_ceil(NS_US)),
+    }
+}
+
+/// Busy-waits for at least `micros` microseconds.
+pub fn udelay(micros: u64) {
+    ndelay(micros.saturating_mul(NS_US));
+}
+
+/// Busy-waits for at least `millis` milliseconds.
+pub fn mdelay(millis: u64) {
+    ndelay(millis.saturating_mul(NS_MS));
+}
+
+/// Spins until `done` returns `true` or the monotonic clock reaches
+/// `deadline`, and returns whether `done` returned `true`.
+///
+/// `done` is checked once more after the deadline, so a condition met while
+/// the caller was interrupted is not mistaken for a timeout:
+///
+/// ```ignore
+/// let deadline = khal::time::monotonic_time() + Duration::from_millis(10);
+/// if !spin_until(deadline, || regs.status.read() & READY != 0) {
+///     return Err(DriverError::Io);
+/// }
+/// ```
+pub fn spin_until(deadline: Duration, mut done: impl FnMut() -> bool) -> bool {
+    loop {
+        if done() {
+            return true;
+        }
+        if now() >= deadline {
+            return done();
+        }
+        spin_loop();
+    }
+}
+
+#[cfg(unittest)]
+#[allow(missing_docs)]
+pub mod tests_delay {
+    use unittest::def_test;
+
+    use super::*;
+
+    /// Slack for the timer resolution and for being interrupted.
+    const TOLERANCE_NANOS: u64 = 5 * NS_MS;
+
+    #[def_test]
+    fn test_delay_durations() {
+        for micros in [10, 100, 1000, 5000] {
+            let start = now_ns();
+            udelay(micros);
+            let elapsed = now_ns() - start;
+            let expected = micros * NS_US;
+            assert!(elapsed >= expected, "udelay({micros}) took {elapsed} ns");
+            assert!(
+                elapsed < expected + TOLERANCE_NANOS,
+                "udelay({micros}) took {elapsed} ns"
+            );
+        }
+    }
+
+    #[def_test]
+    fn test_spin_until_timeout() {
+        let start = now();
+        assert!(!spin_until(start + Duration::from_millis(1), || false));
+        assert!(now() - start >= Duration::from_millis(1));
+        assert!(spin_until(start, || true));
+
+        let mut polls = 0;
+        assert!(spin_until(now() + Duration::from_secs(1), || {
+            polls += 1;
+            polls == 3
+        }));
+    }
+}
@@ -21,6 +21,23 @@ pub fn get_bootarg() -> usize {
     *BOOTARG
 }
 
+/// Returns the physical range `(start, size)` of the device tree blob, if the
+/// boot argument points to one.
+///
+/// Only the header is read, so it is safe to call before [`get_fdt`].
+pub fn blob_range() -> Option<(usize, usize)> {
+    const FDT_MAGIC: u32 = 0xd00d_feed;
+
+    let paddr = get_bootarg();
+    if paddr == 0 || !paddr.is_multiple_of(4) {
+        return None;
+    }
+    let header = crate::mem::p2v(paddr.into()).as_ptr() as *const u32;
+    // The magic and the total size are the first two big-endian words.
+    let (magic, size) = unsafe { (header.read_volatile(), header.add(1).read_volatile()) };
+    (u32::from_be(magic) == FDT_MAGIC).then(|| (paddr, u32::from_be(size) as usize))
+}
+
 /// Get the cached FDT or initialize it if not already done.
 pub fn get_fdt() -> Option<&'static Fdt<'static>> {
     static CACHED_FDT: LazyInit<Option<Fdt<'static>>> = LazyInit::new();
@@ -56,6 +73,15 @@ pub fn get_chosen_bootargs() -> Option<&'static str> {
     *CACHED_BOOTARGS.init_once(init_bootargs())
 }
 
+/// Get the string property `name` of the chosen node of the device tree.
+pub fn get_chosen_str(name: &str) -> Option<&'static str> {
+    let fdt = get_fdt()?;
+    fdt.find_nodes("/chosen")
+        .next()?
+        .find_property(name)
+        .map(|prop| prop.str())
+}
+
 #[cfg(unittest)]
 #[allow(missing_docs)]
 pub mod tests_dtb {
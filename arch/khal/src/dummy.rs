@@ -121,6 +121,10 @@ impl SysCtrl for DummyPower {
     fn shutdown() -> ! {
         unimplemented!()
     }
+
+    fn reset() -> ! {
+        unimplemented!()
+    }
 }
 
 #[impl_dev_interface]
@@ -18,6 +18,48 @@ pub use platconfig::devices::IPI_IRQ;
 
 static IRQ_HOOK: AtomicUsize = AtomicUsize::new(0);
 
+/// Number of IRQ lines whose IRQs are counted, see [`irq_count`].
+pub const MAX_COUNTED_IRQS: usize = 1024;
+
+static IRQ_COUNTS: [AtomicUsize; MAX_COUNTED_IRQS] =
+    [const { AtomicUsize::new(0) }; MAX_COUNTED_IRQS];
+
+/// Number of IRQ lines with a non-zero count.
+static IRQ_LINES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of IRQs handled on line `irq`, by all CPUs.
+pub fn irq_count(irq: usize) -> usize {
+    IRQ_COUNTS
+        .get(irq)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the number of IRQ lines that were handled at least once.
+///
+/// It only grows, so it tells whether lines were added to the IRQ statistics.
+pub fn irq_lines() -> usize {
+    IRQ_LINES.load(Ordering::Acquire)
+}
+
+fn count_irq(irq: usize) {
+    if let Some(count) = IRQ_COUNTS.get(irq)
+        && count.fetch_add(1, Ordering::Relaxed) == 0
+    {
+        IRQ_LINES.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Nesting depth of IRQ handlers on this CPU.
+#[percpu::def_percpu]
+static IRQ_DEPTH: usize = 0;
+
+/// Returns whether the current CPU is handling an IRQ.
+pub fn in_irq() -> bool {
+    // SAFETY: the depth is only non-zero while IRQ handlers run, during which
+    // the task cannot migrate; a task that migrates reads 0 on either CPU.
+    unsafe { IRQ_DEPTH.read_current_raw() != 0 }
+}
+
 /// Register a hook function called after an IRQ is dispatched.
 ///
 /// This function can be called only once; subsequent calls will return false.
@@ -42,8 +84,11 @@ pub fn register_irq_hook(hook: fn(usize)) -> bool {
 #[register_trap_handler(IRQ)]
 pub fn irq_handler(vector: usize) -> bool {
     let guard = kspin::NoPreempt::new();
+    // SAFETY: IRQs are disabled and preemption is off.
+    unsafe { IRQ_DEPTH.write_current_raw(IRQ_DEPTH.read_current_raw() + 1) };
 
     if let Some(irq) = dispatch_irq(vector) {
+        count_irq(irq);
         let hook = IRQ_HOOK.load(Ordering::SeqCst);
         if hook != 0 {
             let hook = unsafe { core::mem::transmute::<usize, fn(usize)>(hook) };
@@ -51,6 +96,8 @@ pub fn irq_handler(vector: usize) -> bool {
         }
     }
 
+    unsafe { IRQ_DEPTH.write_current_raw(IRQ_DEPTH.read_current_raw() - 1) };
+
     let _ = guard; // rescheduling may occur when preemption is re-enabled.
     true
 }
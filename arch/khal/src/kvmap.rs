@@ -0,0 +1,664 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Kernel virtual address space map.
+//!
+//! The kernel address space is split into named regions, laid out per
+//! architecture downwards from the top of [`kernel_layout`]:
+//!
+//! | region    | contents                                                    |
+//! | --------- | ----------------------------------------------------------- |
+//! | `linear`  | RAM and the fixed MMIO ranges, from `PHYS_VIRT_OFFSET`      |
+//! | `ioremap` | device memory mapped at run time by [`ioremap`]             |
+//! | `percpu`  | per-CPU areas                                               |
+//! | `module`  | loadable code and trampolines                               |
+//! | `fixmap`  | fixed mappings such as the vDSO                             |
+//!
+//! The layout is computed at compile time and checked there to keep the
+//! regions apart and every MMIO range of the platform inside the linear map;
+//! [`assert_layout`] checks it against the platform at boot. Ranges used at
+//! run time are registered with [`claim`], which refuses ranges straddling
+//! two regions or overlapping an earlier claim, while `ioremap` allocates
+//! from an arena of its own. The usage of every region is accounted, a
+//! warning is logged when one passes [`HIGH_WATER_PERCENT`], and
+//! [`write_report`] renders the whole map.
+//!
+//! [`kernel_layout`]: crate::mem::kernel_layout
+
+use core::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use kspin::SpinNoIrq;
+use memaddr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use platconfig::{
+    devices::MMIO_RANGES,
+    plat::{KERNEL_ASPACE_BASE, KERNEL_ASPACE_SIZE, PHYS_VIRT_OFFSET},
+};
+
+use crate::mem::{kernel_layout, memory_regions, p2v};
+
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+const GIB: usize = 1024 * MIB;
+
+/// Alignment of every region boundary.
+const REGION_ALIGN: usize = 2 * MIB;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "riscv64")] {
+        // Sv39 leaves 256 GiB to the kernel, most of it for the linear map.
+        const IOREMAP_SIZE: usize = 16 * GIB;
+        const PERCPU_SIZE: usize = GIB;
+        const MODULE_SIZE: usize = GIB;
+    } else if #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "loongarch64"
+    ))] {
+        const IOREMAP_SIZE: usize = 1024 * GIB;
+        const PERCPU_SIZE: usize = 64 * GIB;
+        const MODULE_SIZE: usize = 2 * GIB;
+    } else {
+        const IOREMAP_SIZE: usize = 256 * MIB;
+        const PERCPU_SIZE: usize = 16 * MIB;
+        const MODULE_SIZE: usize = 16 * MIB;
+    }
+}
+const FIXMAP_SIZE: usize = 4 * MIB;
+
+/// Whether the platform has a kernel address space to lay out; the dummy
+/// platform has none.
+const ENABLED: bool = KERNEL_ASPACE_SIZE != 0;
+
+const fn sized(size: usize) -> usize {
+    if ENABLED { size } else { 0 }
+}
+
+/// Top of the regions. The partial block below the end of the address space
+/// is left unused.
+const TOP: usize = (KERNEL_ASPACE_BASE + KERNEL_ASPACE_SIZE) & !(REGION_ALIGN - 1);
+const FIXMAP_START: usize = TOP - sized(FIXMAP_SIZE);
+const MODULE_START: usize = FIXMAP_START - sized(MODULE_SIZE);
+const PERCPU_START: usize = MODULE_START - sized(PERCPU_SIZE);
+const IOREMAP_START: usize = PERCPU_START - sized(IOREMAP_SIZE);
+const LINEAR_START: usize = PHYS_VIRT_OFFSET;
+
+const _: () = {
+    if ENABLED {
+        assert!(
+            LINEAR_START >= KERNEL_ASPACE_BASE,
+            "the linear map starts below the kernel address space"
+        );
+        assert!(
+            LINEAR_START < IOREMAP_START,
+            "the linear map does not fit below the ioremap region"
+        );
+        assert!(IOREMAP_START.is_multiple_of(REGION_ALIGN));
+        let linear_size = IOREMAP_START - LINEAR_START;
+        let mut i = 0;
+        while i < MMIO_RANGES.len() {
+            let (base, size) = MMIO_RANGES[i];
+            assert!(
+                base + size <= linear_size,
+                "an MMIO range lies beyond the linear map"
+            );
+            i += 1;
+        }
+    }
+};
+
+/// Usage in percent from which a region is reported as running out.
+pub const HIGH_WATER_PERCENT: usize = 85;
+
+/// Maximum number of ranges registered with [`claim`].
+const MAX_CLAIMS: usize = 160;
+/// Maximum number of live [`ioremap`] mappings.
+const MAX_IOREMAPS: usize = 64;
+
+/// A region of the kernel address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvRegion {
+    /// Linear map of RAM and the fixed MMIO ranges.
+    Linear,
+    /// Device memory mapped by [`ioremap`].
+    Ioremap,
+    /// Per-CPU areas.
+    Percpu,
+    /// Loadable code and trampolines.
+    Module,
+    /// Fixed mappings such as the vDSO.
+    Fixmap,
+}
+
+impl KvRegion {
+    /// All regions, in ascending address order.
+    pub const ALL: [KvRegion; 5] = [
+        KvRegion::Linear,
+        KvRegion::Ioremap,
+        KvRegion::Percpu,
+        KvRegion::Module,
+        KvRegion::Fixmap,
+    ];
+
+    /// Returns the name of the region.
+    pub const fn name(self) -> &'static str {
+        match self {
+            KvRegion::Linear => "linear",
+            KvRegion::Ioremap => "ioremap",
+            KvRegion::Percpu => "percpu",
+            KvRegion::Module => "module",
+            KvRegion::Fixmap => "fixmap",
+        }
+    }
+
+    /// Returns the virtual address range of the region.
+    pub const fn range(self) -> Range<usize> {
+        match self {
+            KvRegion::Linear => LINEAR_START..IOREMAP_START,
+            KvRegion::Ioremap => IOREMAP_START..PERCPU_START,
+            KvRegion::Percpu => PERCPU_START..MODULE_START,
+            KvRegion::Module => MODULE_START..FIXMAP_START,
+            KvRegion::Fixmap => FIXMAP_START..TOP,
+        }
+    }
+
+    /// Returns the region holding all of `start..start + size`.
+    pub fn of(start: usize, size: usize) -> Option<Self> {
+        let end = start.checked_add(size)?;
+        Self::ALL.into_iter().find(|region| {
+            let range = region.range();
+            range.start <= start && end <= range.end && start < end
+        })
+    }
+
+    /// Returns the number of bytes of the region in use.
+    pub fn used(self) -> usize {
+        USED[self as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// Errors of the kernel address space map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvmapError {
+    /// The region has no room left, or too many ranges are registered.
+    NoSpace,
+    /// The range overlaps one in use.
+    Overlap,
+    /// The range is not inside a single region.
+    OutOfRegion,
+    /// Nothing is mapped or claimed at the address.
+    NotMapped,
+    /// The page table refused the mapping.
+    MapFailed,
+}
+
+static USED: [AtomicUsize; 5] = [const { AtomicUsize::new(0) }; 5];
+static WARNED: [AtomicBool; 5] = [const { AtomicBool::new(false) }; 5];
+
+fn add_usage(region: KvRegion, size: usize) {
+    let used = USED[region as usize].fetch_add(size, Ordering::Relaxed) + size;
+    let limit = region.range().len() / 100 * HIGH_WATER_PERCENT;
+    if used > limit && !WARNED[region as usize].swap(true, Ordering::Relaxed) {
+        warn!(
+            "kvmap: the {} region is {}% used ({used:#x} of {:#x} bytes)",
+            region.name(),
+            percent(used, region.range().len()),
+            region.range().len()
+        );
+    }
+}
+
+fn sub_usage(region: KvRegion, size: usize) {
+    let used = USED[region as usize].fetch_sub(size, Ordering::Relaxed) - size;
+    if used <= region.range().len() / 100 * HIGH_WATER_PERCENT {
+        WARNED[region as usize].store(false, Ordering::Relaxed);
+    }
+}
+
+fn percent(used: usize, total: usize) -> usize {
+    if total == 0 {
+        0
+    } else {
+        (used as u128 * 100 / total as u128) as usize
+    }
+}
+
+/// A range registered with [`claim`].
+struct Claim {
+    start: usize,
+    size: usize,
+    name: &'static str,
+}
+
+static CLAIMS: SpinNoIrq<heapless::Vec<Claim, MAX_CLAIMS>> = SpinNoIrq::new(heapless::Vec::new());
+
+/// Registers `size` bytes at `vaddr` as used by `name`.
+///
+/// The range must lie inside a single region other than `ioremap`, which
+/// belongs to [`ioremap`], and must not overlap any range claimed before.
+/// Returns the region of the range.
+pub fn claim(vaddr: VirtAddr, size: usize, name: &'static str) -> Result<KvRegion, KvmapError> {
+    let start = vaddr.as_usize();
+    let Some(region) = KvRegion::of(start, size) else {
+        error!(
+            "kvmap: {name} [{start:#x}, {:#x}) is not inside one region",
+            start.wrapping_add(size)
+        );
+        return Err(KvmapError::OutOfRegion);
+    };
+    if region == KvRegion::Ioremap {
+        error!("kvmap: {name} claims {start:#x} in the ioremap arena");
+        return Err(KvmapError::Overlap);
+    }
+    let mut claims = CLAIMS.lock();
+    if let Some(other) = claims
+        .iter()
+        .find(|it| start < it.start + it.size && it.start < start + size)
+    {
+        error!(
+            "kvmap: {name} [{start:#x}, {:#x}) overlaps {} [{:#x}, {:#x})",
+            start + size,
+            other.name,
+            other.start,
+            other.start + other.size
+        );
+        return Err(KvmapError::Overlap);
+    }
+    claims
+        .push(Claim { start, size, name })
+        .map_err(|_| KvmapError::NoSpace)?;
+    add_usage(region, size);
+    Ok(region)
+}
+
+/// Releases the range claimed at `vaddr`.
+pub fn release(vaddr: VirtAddr) -> Result<(), KvmapError> {
+    let mut claims = CLAIMS.lock();
+    let index = claims
+        .iter()
+        .position(|it| it.start == vaddr.as_usize())
+        .ok_or(KvmapError::NotMapped)?;
+    let claim = claims.swap_remove(index);
+    if let Some(region) = KvRegion::of(claim.start, claim.size) {
+        sub_usage(region, claim.size);
+    }
+    Ok(())
+}
+
+/// A virtual address range that mappings are carved from.
+///
+/// Every allocation is surrounded by at least `guard` unused bytes, so that
+/// running off the end of one mapping faults instead of reaching the next.
+/// Allocation is first-fit.
+struct VaArena<const N: usize> {
+    range: Range<usize>,
+    guard: usize,
+    /// Allocations as `(start, size)`, sorted by start.
+    blocks: heapless::Vec<(usize, usize), N>,
+}
+
+#[cfg_attr(not(any(feature = "paging", unittest)), allow(dead_code))]
+impl<const N: usize> VaArena<N> {
+    const fn new(range: Range<usize>, guard: usize) -> Self {
+        Self {
+            range,
+            guard,
+            blocks: heapless::Vec::new(),
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `align`, a power of two.
+    fn alloc(&mut self, size: usize, align: usize) -> Option<usize> {
+        if size == 0 || self.blocks.is_full() {
+            return None;
+        }
+        let mut cursor = self.range.start;
+        for i in 0..=self.blocks.len() {
+            let limit = self.blocks.get(i).map_or(self.range.end, |it| it.0);
+            let start = cursor.checked_add(self.guard + align - 1)? & !(align - 1);
+            if let Some(end) = start.checked_add(size)
+                && end.checked_add(self.guard)? <= limit
+            {
+                self.blocks.insert(i, (start, size)).ok()?;
+                return Some(start);
+            }
+            if let Some(&(start, size)) = self.blocks.get(i) {
+                cursor = start + size;
+            }
+        }
+        None
+    }
+
+    /// Frees the allocation at `start`, returning its size.
+    fn free(&mut self, start: usize) -> Option<usize> {
+        let index = self.blocks.iter().position(|it| it.0 == start)?;
+        Some(self.blocks.remove(index).1)
+    }
+
+    /// Returns the number of bytes allocated, not counting the guards.
+    fn used(&self) -> usize {
+        self.blocks.iter().map(|it| it.1).sum()
+    }
+}
+
+/// Memory type of a mapping made by [`ioremap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoAttr {
+    /// Device registers: uncached, and accesses are neither merged nor
+    /// reordered.
+    Device,
+    /// Normal memory without caching, such as a framebuffer.
+    NonCacheable,
+}
+
+/// Page table operations for [`ioremap`], implemented where the kernel page
+/// table lives.
+#[cfg(feature = "paging")]
+#[crate_interface::def_interface]
+pub trait KvmapIf {
+    /// Maps `size` bytes at `paddr` to `vaddr` in the kernel page table.
+    /// Both addresses and `size` are page aligned.
+    fn map(
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        size: usize,
+        flags: crate::paging::MappingFlags,
+    ) -> bool;
+
+    /// Unmaps `size` bytes at `vaddr` from the kernel page table.
+    fn unmap(vaddr: VirtAddr, size: usize) -> bool;
+}
+
+/// A live mapping of the ioremap arena.
+struct IoMapping {
+    vaddr: usize,
+    paddr: usize,
+    size: usize,
+    attr: IoAttr,
+    refs: usize,
+}
+
+struct Ioremap {
+    arena: VaArena<MAX_IOREMAPS>,
+    mappings: heapless::Vec<IoMapping, MAX_IOREMAPS>,
+}
+
+static IOREMAP: SpinNoIrq<Ioremap> = SpinNoIrq::new(Ioremap {
+    arena: VaArena::new(KvRegion::Ioremap.range(), PAGE_SIZE_4K),
+    mappings: heapless::Vec::new(),
+});
+
+/// Maps `size` bytes of device memory at `paddr` and returns the virtual
+/// address of `paddr`.
+///
+/// The pages around the range are mapped into the ioremap arena with `attr`,
+/// and stay mapped until as many [`iounmap`] calls as `ioremap` ones covering
+/// them. Needs the kernel page table, which is set up after
+/// [`crate::mem::init`]; without the `paging` feature the boot page table
+/// maps the physical address space linearly and this is [`p2v`].
+///
+/// User address spaces copy the kernel mappings when they are created, so
+/// on the architectures sharing page tables with user space the mappings
+/// are meant to be made at boot, while probing devices.
+pub fn ioremap(paddr: PhysAddr, size: usize, attr: IoAttr) -> Result<VirtAddr, KvmapError> {
+    #[cfg(not(feature = "paging"))]
+    {
+        let _ = (size, attr);
+        Ok(p2v(paddr))
+    }
+    #[cfg(feature = "paging")]
+    {
+        use crate::paging::MappingFlags;
+
+        let start = paddr.align_down_4k().as_usize();
+        let end = (paddr + size.max(1)).align_up_4k().as_usize();
+        let mut io = IOREMAP.lock();
+        if let Some(mapping) = io
+            .mappings
+            .iter_mut()
+            .find(|it| it.attr == attr && it.paddr <= start && end <= it.paddr + it.size)
+        {
+            mapping.refs += 1;
+            return Ok(VirtAddr::from(
+                mapping.vaddr + (paddr.as_usize() - mapping.paddr),
+            ));
+        }
+
+        let Some(vaddr) = io.arena.alloc(end - start, PAGE_SIZE_4K) else {
+            error!(
+                "kvmap: no room to map {paddr:?} ({size:#x} bytes), {:#x} bytes in use",
+                io.arena.used()
+            );
+            return Err(KvmapError::NoSpace);
+        };
+        let flags = match attr {
+            IoAttr::Device => MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
+            IoAttr::NonCacheable => {
+                MappingFlags::READ | MappingFlags::WRITE | MappingFlags::UNCACHED
+            }
+        };
+        if !crate_interface::call_interface!(KvmapIf::map(
+            VirtAddr::from(vaddr),
+            PhysAddr::from(start),
+            end - start,
+            flags
+        )) {
+            io.arena.free(vaddr);
+            return Err(KvmapError::MapFailed);
+        }
+        // The arena refuses allocations before the list fills up.
+        let _ = io.mappings.push(IoMapping {
+            vaddr,
+            paddr: start,
+            size: end - start,
+            attr,
+            refs: 1,
+        });
+        add_usage(KvRegion::Ioremap, end - start);
+        Ok(VirtAddr::from(vaddr + (paddr.as_usize() - start)))
+    }
+}
+
+/// Drops a mapping returned by [`ioremap`], unmapping its pages once no
+/// other `ioremap` call covers them.
+pub fn iounmap(vaddr: VirtAddr) -> Result<(), KvmapError> {
+    #[cfg(not(feature = "paging"))]
+    {
+        let _ = vaddr;
+        Ok(())
+    }
+    #[cfg(feature = "paging")]
+    {
+        let vaddr = vaddr.as_usize();
+        let mut io = IOREMAP.lock();
+        let index = io
+            .mappings
+            .iter()
+            .position(|it| (it.vaddr..it.vaddr + it.size).contains(&vaddr))
+            .ok_or(KvmapError::NotMapped)?;
+        let mapping = &mut io.mappings[index];
+        mapping.refs -= 1;
+        if mapping.refs > 0 {
+            return Ok(());
+        }
+        let (start, size) = (mapping.vaddr, mapping.size);
+        if !crate_interface::call_interface!(KvmapIf::unmap(VirtAddr::from(start), size)) {
+            io.mappings[index].refs = 1;
+            return Err(KvmapError::MapFailed);
+        }
+        io.mappings.swap_remove(index);
+        io.arena.free(start);
+        sub_usage(KvRegion::Ioremap, size);
+        Ok(())
+    }
+}
+
+/// Returns the physical address mapped at `vaddr` by [`ioremap`].
+pub fn io_v2p(vaddr: VirtAddr) -> Option<PhysAddr> {
+    let vaddr = vaddr.as_usize();
+    IOREMAP
+        .lock()
+        .mappings
+        .iter()
+        .find(|it| (it.vaddr..it.vaddr + it.size).contains(&vaddr))
+        .map(|it| PhysAddr::from(it.paddr + (vaddr - it.vaddr)))
+}
+
+/// Checks the layout against the platform, panicking on a mismatch.
+///
+/// The regions must lie in the kernel address space of the platform, and
+/// its linear map must start the `linear` region and cover every memory
+/// region.
+pub fn assert_layout() {
+    if !ENABLED {
+        return;
+    }
+    let (base, size) = kernel_layout();
+    assert_eq!(
+        (base.as_usize(), size),
+        (KERNEL_ASPACE_BASE, KERNEL_ASPACE_SIZE),
+        "kvmap: the platform reports another kernel address space"
+    );
+    let linear = KvRegion::Linear.range();
+    assert_eq!(
+        p2v(PhysAddr::from(0)).as_usize(),
+        linear.start,
+        "kvmap: the linear map does not start at PHYS_VIRT_OFFSET"
+    );
+    for region in memory_regions() {
+        let start = p2v(region.paddr).as_usize();
+        assert!(
+            linear.start <= start && start + region.size <= linear.end,
+            "kvmap: {} at {:?} lies beyond the linear map",
+            region.name,
+            region.paddr
+        );
+    }
+    debug!("kvmap: layout checked");
+}
+
+/// Writes the regions with their usage, the claimed ranges and the live
+/// ioremap mappings to `out`.
+pub fn write_report(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<8} {:>18} {:>18} {:>14} {:>14} {:>4}",
+        "region", "start", "end", "size_kb", "used_kb", "use%"
+    )?;
+    for region in KvRegion::ALL {
+        let range = region.range();
+        writeln!(
+            out,
+            "{:<8} {:#018x} {:#018x} {:>14} {:>14} {:>3}%",
+            region.name(),
+            range.start,
+            range.end,
+            range.len() / KIB,
+            region.used() / KIB,
+            percent(region.used(), range.len())
+        )?;
+    }
+
+    writeln!(out, "\nclaims:")?;
+    for claim in CLAIMS.lock().iter() {
+        writeln!(
+            out,
+            "  {:#018x}-{:#018x} {:>10} KiB {}",
+            claim.start,
+            claim.start + claim.size,
+            claim.size.div_ceil(KIB),
+            claim.name
+        )?;
+    }
+
+    writeln!(out, "\nioremap:")?;
+    for mapping in IOREMAP.lock().mappings.iter() {
+        writeln!(
+            out,
+            "  {:#018x}-{:#018x} {:>10} KiB -> {:#x} {:?} refs={}",
+            mapping.vaddr,
+            mapping.vaddr + mapping.size,
+            mapping.size / KIB,
+            mapping.paddr,
+            mapping.attr,
+            mapping.refs
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(unittest)]
+#[allow(missing_docs)]
+pub mod tests_kvmap {
+    use unittest::def_test;
+
+    use super::{KvRegion, VaArena};
+
+    const BASE: usize = 0x1000_0000;
+    const PAGE: usize = 0x1000;
+
+    #[def_test]
+    fn test_arena_guard_gaps() {
+        let mut arena = VaArena::<8>::new(BASE..BASE + 16 * PAGE, PAGE);
+        let a = arena.alloc(2 * PAGE, PAGE).unwrap();
+        let b = arena.alloc(PAGE, PAGE).unwrap();
+        assert_eq!(a, BASE + PAGE);
+        // One guard page between the two.
+        assert_eq!(b, a + 3 * PAGE);
+        assert_eq!(arena.used(), 3 * PAGE);
+    }
+
+    #[def_test]
+    fn test_arena_alignment() {
+        let mut arena = VaArena::<8>::new(BASE..BASE + 0x100_0000, PAGE);
+        let a = arena.alloc(PAGE, PAGE).unwrap();
+        let b = arena.alloc(PAGE, 0x10_0000).unwrap();
+        assert_eq!(b % 0x10_0000, 0);
+        assert!(b >= a + 2 * PAGE);
+    }
+
+    #[def_test]
+    fn test_arena_first_fit_reuse() {
+        let mut arena = VaArena::<8>::new(BASE..BASE + 16 * PAGE, PAGE);
+        let a = arena.alloc(PAGE, PAGE).unwrap();
+        let b = arena.alloc(PAGE, PAGE).unwrap();
+        assert_eq!(arena.free(a), Some(PAGE));
+        assert_eq!(arena.free(a), None);
+        // The hole left by `a` is reused, still away from `b`.
+        assert_eq!(arena.alloc(PAGE, PAGE), Some(a));
+        assert_eq!(arena.free(b), Some(PAGE));
+        assert_eq!(arena.used(), PAGE);
+    }
+
+    #[def_test]
+    fn test_arena_exhaustion() {
+        // Room for three pages with their guards, and a trailing guard.
+        let mut arena = VaArena::<8>::new(BASE..BASE + 7 * PAGE, PAGE);
+        assert!(arena.alloc(PAGE, PAGE).is_some());
+        assert!(arena.alloc(PAGE, PAGE).is_some());
+        assert!(arena.alloc(PAGE, PAGE).is_some());
+        assert_eq!(arena.alloc(PAGE, PAGE), None);
+        assert_eq!(arena.alloc(0, PAGE), None);
+
+        let mut small = VaArena::<2>::new(BASE..BASE + 64 * PAGE, PAGE);
+        assert!(small.alloc(PAGE, PAGE).is_some());
+        assert!(small.alloc(PAGE, PAGE).is_some());
+        assert_eq!(small.alloc(PAGE, PAGE), None);
+    }
+
+    #[def_test]
+    fn test_regions_ordered() {
+        for pair in KvRegion::ALL.windows(2) {
+            assert!(pair[0].range().end <= pair[1].range().start);
+        }
+        let fixmap = KvRegion::Fixmap.range();
+        if !fixmap.is_empty() {
+            assert_eq!(KvRegion::of(fixmap.start, 1), Some(KvRegion::Fixmap));
+            assert_eq!(KvRegion::of(fixmap.end - 1, 2), None);
+        }
+    }
+}
@@ -26,6 +26,7 @@
 //! - `tls`: Enable kernel space thread-local storage support.
 //! - `rtc`: Enable real-time clock support.
 //! - `uspace`: Enable user space support.
+//! - `compat`: Also run 32-bit ARM user space on AArch64.
 
 #![no_std]
 #![feature(doc_cfg)]
@@ -41,7 +42,9 @@ extern crate memaddr;
 
 // mod dummy;
 
+pub mod delay;
 pub mod dtb;
+pub mod kvmap;
 pub mod mem;
 pub mod percpu;
 pub mod time;
@@ -63,7 +66,7 @@ pub mod console {
 pub mod power {
     #[cfg(feature = "smp")]
     pub use kplat::sys::boot_ap;
-    pub use kplat::sys::shutdown;
+    pub use kplat::sys::{reset, shutdown};
 }
 
 #[cfg(feature = "crosvm")]
@@ -73,7 +76,9 @@ pub mod psci {
 
 /// Trap handling.
 pub mod trap {
-    pub use kcpu::excp::{IRQ, PAGE_FAULT, PageFaultFlags, register_trap_handler};
+    pub use kcpu::excp::{
+        IRQ, PAGE_FAULT, PageFaultFlags, RECOVERABLE_FAULT, register_trap_handler,
+    };
 }
 
 /// CPU register states for context switching.
@@ -118,6 +123,7 @@ pub mod nmi {
 pub mod pmu {
     pub use kplat::perf::{
         PerfCb, on_overflow as dispatch_irq_overflows, reg_cb as register_overflow_handler,
+        task_counter_read, task_counter_start, task_counter_stop, task_counters,
     };
 }
 /// Initializes the platform and boot argument.
@@ -125,6 +131,8 @@ pub mod pmu {
 pub fn early_init(cpu_id: usize, arg: usize) {
     dtb::init(arg);
     kplat::boot::early_init(cpu_id, arg);
+    // After the platform, which may only learn the RAM ranges now.
+    mem::early_init();
 }
 
 macro_rules! addr_of_sym {
@@ -5,11 +5,20 @@
 //! Physical memory management.
 
 use heapless::Vec;
-pub use kplat::memory::{
-    MemFlags, MemoryRegion, dma_regions, kernel_layout, mmio_regions, p2v, ram_regions,
-    rsvd_regions, total_ram, v2p,
+pub use kplat::{
+    memblock::{
+        EarlyTag, MemBlockError, Reservation, early_alloc, early_handoff, early_reservations,
+        early_reserve,
+    },
+    memory::{
+        MemFlags, MemoryRegion, dma_regions, kernel_layout, mmio_regions, p2v, ram_regions,
+        rsvd_regions, total_ram, v2p,
+    },
+};
+use kplat::{
+    memblock::{early_add_memory, early_free_ranges},
+    memory::check_overlap,
 };
-use kplat::memory::{check_overlap, sub_ranges};
 use lazyinit::LazyInit;
 pub use memaddr::{PAGE_SIZE_4K, PhysAddr, PhysAddrRange, VirtAddr, VirtAddrRange, pa, va};
 
@@ -41,7 +50,56 @@ pub unsafe fn clear_bss() {
     }
 }
 
+/// Seeds the early allocator with the RAM and the memory already in use:
+/// the kernel image, the platform reserved and DMA ranges and the device
+/// tree blob.
+pub(crate) fn early_init() {
+    for &range in ram_regions() {
+        early_add_memory(range).expect("too many RAM regions");
+    }
+
+    let reserve = |range: (usize, usize), tag: EarlyTag| {
+        if let Err(err) = early_reserve(range, tag) {
+            panic!("Cannot reserve {range:#x?} for {}: {err:?}", tag.name);
+        }
+    };
+    let kernel_start = v2p(addr_of_sym!(_skernel).into()).as_usize();
+    let kernel_size = addr_of_sym!(_ekernel) - addr_of_sym!(_skernel);
+    reserve((kernel_start, kernel_size), EarlyTag::KERNEL);
+    for &range in rsvd_regions() {
+        reserve(range, EarlyTag::permanent("reserved"));
+    }
+    for &range in dma_regions() {
+        reserve(range, EarlyTag::DMA);
+    }
+    if let Some(range) = crate::dtb::blob_range() {
+        reserve(range, EarlyTag::DTB);
+    }
+}
+
+/// Logs every early reservation with its tag and size.
+fn report_early_reservations() {
+    debug!("Early memory reservations:");
+    early_reservations(|r| {
+        debug!(
+            "  [{:#x}, {:#x}) {:>8} KiB {} ({})",
+            r.start,
+            r.end(),
+            r.size.div_ceil(1024),
+            r.tag.name,
+            if r.tag.permanent {
+                "permanent"
+            } else {
+                "temporary"
+            },
+        );
+    });
+}
+
 /// Initializes physical memory regions.
+///
+/// The free memory is what the early allocator has not reserved, see
+/// [`early_init`].
 pub fn init() {
     let mut all_regions = Vec::new();
     let mut push = |r: MemoryRegion| {
@@ -86,30 +144,21 @@ pub fn init() {
     for &(start, size) in mmio_regions() {
         push(MemoryRegion::new_mmio(start, size, "mmio"));
     }
-    for &(start, size) in rsvd_regions() {
-        push(MemoryRegion::new_rsvd(start, size, "reserved"));
-    }
     for &(start, size) in dma_regions() {
         push(MemoryRegion::new_dma(start, size, "dma"));
     }
+    // The kernel image and DMA regions are pushed above with finer flags.
+    early_reservations(|r| {
+        if r.tag != EarlyTag::KERNEL && r.tag != EarlyTag::DMA {
+            push(MemoryRegion::new_rsvd(r.start, r.size, r.tag.name));
+        }
+    });
 
-    // Combine kernel image range and reserved ranges
-    let kernel_start = v2p(addr_of_sym!(_skernel).into()).as_usize();
-    let kernel_size = addr_of_sym!(_ekernel) - addr_of_sym!(_skernel);
-    let mut reserved_ranges = rsvd_regions()
-        .iter()
-        .cloned()
-        .chain(core::iter::once((kernel_start, kernel_size))) // kernel image range is also reserved
-        .chain(dma_regions().iter().cloned()) // DMA regions are also reserved
-        .collect::<Vec<_, MAX_REGIONS>>();
-
-    // Remove all reserved ranges from RAM ranges, and push the remaining as free memory
-    reserved_ranges.sort_unstable_by_key(|&(start, _size)| start);
-    sub_ranges(ram_regions(), &reserved_ranges, |(start, size)| {
+    // Whatever is left of RAM is free memory
+    early_free_ranges(|(start, size)| {
         push(MemoryRegion::new_ram(start, size, "free memory"));
-    })
-    .inspect_err(|(a, b)| error!("Reserved memory region {a:#x?} overlaps with {b:#x?}"))
-    .unwrap();
+    });
+    report_early_reservations();
 
     // Check overlapping
     all_regions.sort_unstable_by_key(|r| r.paddr);
@@ -118,6 +167,7 @@ pub fn init() {
         .unwrap();
 
     ALL_MEM_REGIONS.init_once(all_regions);
+    crate::kvmap::assert_layout();
 }
 
 unsafe extern "C" {
@@ -6,11 +6,11 @@
 
 use kalloc::{UsageKind, global_allocator};
 use memaddr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
-use page_table::PagingHandler;
 #[doc(no_inline)]
 pub use page_table::{
     PageSize, PagingFlags as MappingFlags, PtError as PagingError, PtResult as PagingResult,
 };
+use page_table::{PagingHandler, PagingMetaData};
 
 use crate::mem::{p2v, v2p};
 
@@ -41,21 +41,31 @@ cfg_if::cfg_if! {
         /// The architecture-specific page table.
         pub type PageTable = page_table::x86_64::X64PageTable<PagingHandlerImpl>;
         pub type PageTableMut<'a> = page_table::x86_64::X64PageTableMut<'a, PagingHandlerImpl>;
+        type PagingMetaDataImpl = page_table::x86_64::X64PagingMetaData;
     } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
         /// The architecture-specific page table.
         pub type PageTable = page_table::riscv::Sv39PageTable<PagingHandlerImpl>;
         pub type PageTableMut<'a> = page_table::riscv::Sv39PageTableMut<'a, PagingHandlerImpl>;
+        type PagingMetaDataImpl = page_table::riscv::Sv39MetaData<VirtAddr>;
     } else if #[cfg(target_arch = "aarch64")]{
         /// The architecture-specific page table.
         pub type PageTable = page_table::aarch64::A64PageTable<PagingHandlerImpl>;
         pub type PageTableMut<'a> = page_table::aarch64::A64PageTableMut<'a, PagingHandlerImpl>;
+        type PagingMetaDataImpl = page_table::aarch64::A64PagingMetaData;
     } else if #[cfg(target_arch = "loongarch64")] {
         /// The architecture-specific page table.
         pub type PageTable = page_table::loongarch64::LA64PageTable<PagingHandlerImpl>;
         pub type PageTableMut<'a> = page_table::loongarch64::LA64PageTableMut<'a, PagingHandlerImpl>;
+        type PagingMetaDataImpl = page_table::loongarch64::LA64MetaData;
     }
 }
 
+/// Invalidates the TLB entries of the `size` bytes from `start` on the
+/// current CPU, flushing the whole TLB if the range is large.
+pub fn flush_tlb_range(start: VirtAddr, size: usize) {
+    PagingMetaDataImpl::flush_tlb_range(start, size);
+}
+
 #[cfg(all(unittest, feature = "paging"))]
 #[allow(missing_docs)]
 pub mod tests_paging {
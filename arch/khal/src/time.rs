@@ -3,18 +3,42 @@
 // See LICENSES for license details.
 
 //! Time-related operations.
+//!
+//! Besides the platform clocks, this module keeps the clocks seen by user
+//! space:
+//! - boot time is monotonic time plus the time spent suspended, during which
+//!   the monotonic counter stops;
+//! - real time is boot time plus an offset from the epoch, which starts as the
+//!   platform's and is changed by [`set_realtime`];
+//! - the coarse variants are the same clocks as of the last timer tick, and are
+//!   read without touching the hardware counter.
+//!
+//! [`wall_time`] stays the unadjusted platform clock that kernel timers are
+//! based on, so that setting the real time does not disturb pending sleeps.
 
+use core::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 pub use core::time::Duration;
 pub type TimeValue = Duration;
 
 // Aliases for kplat names if needed locally or exposed
 pub use kplat::timer::{
-    MS_SEC, NS_MS, NS_SEC, NS_SEC as NANOS_PER_SEC, NS_US, NS_US as NANOS_PER_MICROS, US_SEC,
-    arm_timer, freq, interrupt_id, now, now as monotonic_time, now_ns as monotonic_time_nanos,
-    now_ns, now_ticks, ns2t, offset_ns, spin_until, spin_wait, t2ns, wall as wall_time, wall,
+    MS_SEC, NS_MS, NS_SEC, NS_SEC as NANOS_PER_SEC, NS_US, NS_US as NANOS_PER_MICROS,
+    ReferenceParams, ReferenceTime, US_SEC, arm_timer, freq, interrupt_id, now,
+    now as monotonic_time, now_ns as monotonic_time_nanos, now_ns, now_ticks, ns2t, offset_ns,
+    reference_now, spin_until, spin_wait, t2ns, wall as wall_time, wall,
     wall_ns as wall_time_nanos, wall_ns,
 };
 
+/// Nanoseconds between two timer ticks.
+pub const TICK_NANOS: u64 = NS_SEC / platconfig::TICKS_PER_SEC as u64;
+
+/// Time spent suspended, in nanoseconds.
+static SUSPENDED_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Change made to the real time by [`set_realtime`], in nanoseconds.
+static REALTIME_ADJUST_NANOS: AtomicI64 = AtomicI64::new(0);
+/// Monotonic time at the last timer tick, in nanoseconds.
+static COARSE_NANOS: AtomicU64 = AtomicU64::new(0);
+
 /// Busy-wait for the given duration.
 pub fn busy_wait(dur: Duration) {
     spin_wait(dur);
@@ -25,12 +49,75 @@ pub fn busy_wait_until(deadline: TimeValue) {
     spin_until(deadline);
 }
 
+/// Returns the resolution of the clocks read from the hardware counter, in
+/// nanoseconds.
+pub fn resolution_nanos() -> u64 {
+    NS_SEC.div_ceil(freq()).max(1)
+}
+
+/// Returns the time since boot including the time spent suspended, in
+/// nanoseconds.
+pub fn boot_time_nanos() -> u64 {
+    now_ns() + SUSPENDED_NANOS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since boot including the time spent suspended.
+pub fn boot_time() -> TimeValue {
+    TimeValue::from_nanos(boot_time_nanos())
+}
+
+fn boot_to_realtime(boot_nanos: u64) -> u64 {
+    let nanos =
+        boot_nanos as i64 + offset_ns() as i64 + REALTIME_ADJUST_NANOS.load(Ordering::Relaxed);
+    nanos.max(0) as u64
+}
+
+/// Returns the real time since the epoch, in nanoseconds.
+pub fn realtime_nanos() -> u64 {
+    boot_to_realtime(boot_time_nanos())
+}
+
+/// Returns the real time since the epoch.
+pub fn realtime() -> TimeValue {
+    TimeValue::from_nanos(realtime_nanos())
+}
+
+/// Sets the real time to `time` since the epoch.
+pub fn set_realtime(time: TimeValue) {
+    let platform = boot_time_nanos() as i64 + offset_ns() as i64;
+    REALTIME_ADJUST_NANOS.store(time.as_nanos() as i64 - platform, Ordering::Relaxed);
+}
+
+/// Accounts for `dur` spent suspended.
+///
+/// Boot time and real time advance by `dur`, monotonic time does not.
+pub fn add_suspended_time(dur: Duration) {
+    SUSPENDED_NANOS.fetch_add(dur.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Records the monotonic time for the coarse clocks. Called on every timer
+/// tick.
+pub fn update_coarse_time() {
+    COARSE_NANOS.fetch_max(now_ns(), Ordering::Relaxed);
+}
+
+/// Returns the monotonic time as of the last timer tick.
+pub fn monotonic_time_coarse() -> TimeValue {
+    TimeValue::from_nanos(COARSE_NANOS.load(Ordering::Relaxed))
+}
+
+/// Returns the real time as of the last timer tick.
+pub fn realtime_coarse() -> TimeValue {
+    let boot_nanos = COARSE_NANOS.load(Ordering::Relaxed) + SUSPENDED_NANOS.load(Ordering::Relaxed);
+    TimeValue::from_nanos(boot_to_realtime(boot_nanos))
+}
+
 #[cfg(unittest)]
 #[allow(missing_docs)]
 pub mod tests_time {
     use unittest::def_test;
 
-    use super::{Duration, NANOS_PER_SEC};
+    use super::{Duration, NANOS_PER_SEC, ReferenceTime, monotonic_time_nanos, reference_now};
 
     #[def_test]
     fn test_duration_from_nanos() {
@@ -40,6 +127,23 @@ pub mod tests_time {
         assert_eq!(from, one);
     }
 
+    /// Every kernel timestamp, the monotonic clock included, is a reading of
+    /// the reference clock: events read back to back are ordered and apart
+    /// by no more than the time the reads take.
+    #[def_test]
+    fn test_reference_clock_is_monotonic_time() {
+        let before = reference_now();
+        let mono = monotonic_time_nanos();
+        let after = reference_now();
+        assert!(before <= after);
+        assert!(before.monotonic_ns() <= mono && mono <= after.monotonic_ns());
+
+        // Going back from nanoseconds rounds down to a whole tick.
+        let back = ReferenceTime::from_monotonic_ns(after.monotonic_ns());
+        assert!(back <= after);
+        assert!(after.monotonic_ns() - back.monotonic_ns() <= super::resolution_nanos());
+    }
+
     #[def_test]
     fn test_duration_ordering() {
         let short = Duration::from_millis(1);
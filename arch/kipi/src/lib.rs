@@ -83,7 +83,7 @@ pub fn run_on_cpu<T: Into<Callback>>(dest_cpu: usize, callback: T) -> Result<()>
         return Err(KipiError::InvalidCpuId);
     }
 
-    info!("Send IPI event to CPU {dest_cpu}");
+    debug!("Send IPI event to CPU {dest_cpu}");
 
     if dest_cpu == this_cpu_id() {
         // Execute callback on current CPU immediately
@@ -100,7 +100,7 @@ pub fn run_on_cpu<T: Into<Callback>>(dest_cpu: usize, callback: T) -> Result<()>
 
 /// Executes a callback on all other CPUs via IPI.
 pub fn run_on_each_cpu<T: Into<MulticastCallback>>(callback: T) -> Result<()> {
-    info!("Send IPI event to all other CPUs");
+    debug!("Send IPI event to all other CPUs");
     let current_cpu_id = this_cpu_id();
     let cpu_num = platconfig::plat::CPU_NUM;
     let callback = callback.into();
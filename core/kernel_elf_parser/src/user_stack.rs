@@ -40,6 +40,7 @@ use crate::auxv::{AuxEntry, AuxType};
 /// * `args` - Arguments of the application
 /// * `envs` - Environment variables of the application
 /// * `auxv` - Auxiliary vectors of the application
+/// * `random` - Random bytes pointed to by `AT_RANDOM`, unless `auxv` has it
 /// * `sp`   - Highest address of the stack
 ///
 /// # Return
@@ -49,7 +50,13 @@ use crate::auxv::{AuxEntry, AuxType};
 /// # Notes
 ///
 /// The detailed format is described in <https://articles.manugarg.com/aboutelfauxiliaryvectors.html>
-pub fn app_stack_region(args: &[String], envs: &[String], auxv: &[AuxEntry], sp: usize) -> Vec<u8> {
+pub fn app_stack_region(
+    args: &[String],
+    envs: &[String],
+    auxv: &[AuxEntry],
+    random: &[u8; 16],
+    sp: usize,
+) -> Vec<u8> {
     let mut data = VecDeque::new();
     let mut push = |src: &[u8]| -> usize {
         data.extend(src.iter().cloned());
@@ -57,8 +64,7 @@ pub fn app_stack_region(args: &[String], envs: &[String], auxv: &[AuxEntry], sp:
         sp - data.len()
     };
 
-    // define a random string with 16 bytes
-    let random_str_pos = push("0123456789abcdef".as_bytes());
+    let random_str_pos = push(random);
     // Push arguments and environment variables
     let envs_slice: Vec<_> = envs
         .iter()
@@ -131,3 +137,83 @@ pub fn app_stack_region(args: &[String], envs: &[String], auxv: &[AuxEntry], sp:
     result.extend_from_slice(second);
     result
 }
+
+/// Generate initial stack frame for a 32-bit application
+///
+/// The layout is that of [`app_stack_region`] with 4-byte words: pointers,
+/// `argc` and both halves of each auxiliary vector are 32 bits wide. The
+/// values of `auxv` must fit in 32 bits.
+///
+/// # Arguments
+///
+/// * `args` - Arguments of the application
+/// * `envs` - Environment variables of the application
+/// * `auxv` - Auxiliary vectors of the application
+/// * `random` - Random bytes pointed to by `AT_RANDOM`, unless `auxv` has it
+/// * `sp`   - Highest address of the stack, below 4 GiB
+///
+/// # Return
+///
+/// * [`Vec<u8>`] - Initial stack frame of the application
+pub fn app_stack_region32(
+    args: &[String],
+    envs: &[String],
+    auxv: &[AuxEntry],
+    random: &[u8; 16],
+    sp: usize,
+) -> Vec<u8> {
+    let mut data = VecDeque::new();
+    let mut push = |src: &[u8]| -> usize {
+        data.extend(src.iter().cloned());
+        data.rotate_right(src.len());
+        sp - data.len()
+    };
+
+    let random_str_pos = push(random);
+    let envs_slice: Vec<_> = envs
+        .iter()
+        .map(|env| {
+            push(b"\0");
+            push(env.as_bytes()) as u32
+        })
+        .collect();
+    let argv_slice: Vec<_> = args
+        .iter()
+        .map(|arg| {
+            push(b"\0");
+            push(arg.as_bytes()) as u32
+        })
+        .collect();
+    let strings_end = push(&[0; 4]);
+
+    // The words below the strings, from the lowest address up.
+    let mut words = Vec::new();
+    words.push(args.len() as u32);
+    words.extend_from_slice(&argv_slice);
+    words.push(0);
+    words.extend_from_slice(&envs_slice);
+    words.push(0);
+    let has = |ty: AuxType| auxv.iter().any(|entry| entry.get_type() == ty);
+    if !has(AuxType::EXECFN) {
+        words.extend([AuxType::EXECFN as u32, argv_slice[0]]);
+    }
+    if !has(AuxType::RANDOM) {
+        words.extend([AuxType::RANDOM as u32, random_str_pos as u32]);
+    }
+    for entry in auxv {
+        words.extend([entry.get_type() as u32, entry.value() as u32]);
+    }
+
+    // Align the stack pointer to 16 bytes.
+    let padding = (strings_end - words.len() * 4) % 16;
+    push(&[0; 16][..padding]);
+    let sp = push(words.as_bytes());
+
+    assert!(sp % 16 == 0);
+
+    let mut result = Vec::with_capacity(data.len());
+    let (first, second) = data.as_slices();
+    result.extend_from_slice(first);
+    result.extend_from_slice(second);
+    result
+}
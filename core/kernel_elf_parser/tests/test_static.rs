@@ -51,7 +51,94 @@ fn test_ustack(elf_parser: &ELFParser) {
     // The highest address of the user stack.
     let ustack_end = 0x4000_0000;
 
-    let stack_data = kernel_elf_parser::app_stack_region(&args, &envs, &auxv, ustack_end);
+    let random = [0x5a; 16];
+    let stack_data = kernel_elf_parser::app_stack_region(&args, &envs, &auxv, &random, ustack_end);
     // The first 8 bytes of the stack is the number of arguments.
     assert_eq!(stack_data[0..8], [3, 0, 0, 0, 0, 0, 0, 0]);
 }
+
+#[test]
+fn test_argv_layout() {
+    // The argv of `/tmp/script a1`, a script starting with `#!/bin/static -x`.
+    let args: Vec<String> = ["/bin/static", "-x", "/tmp/script", "a1"]
+        .map(String::from)
+        .to_vec();
+    let envs: Vec<String> = vec!["PATH=/bin".to_string()];
+    let random = [0x5a; 16];
+    let ustack_end = 0x4000_0000;
+
+    let stack_data = kernel_elf_parser::app_stack_region(&args, &envs, &[], &random, ustack_end);
+    let sp = ustack_end - stack_data.len();
+    let word = |addr: usize| {
+        let off = addr - sp;
+        usize::from_ne_bytes(stack_data[off..off + 8].try_into().unwrap())
+    };
+    let string = |addr: usize| {
+        let off = addr - sp;
+        let len = stack_data[off..].iter().position(|&c| c == 0).unwrap();
+        std::str::from_utf8(&stack_data[off..off + len]).unwrap()
+    };
+
+    assert_eq!(word(sp), args.len());
+    for (i, arg) in args.iter().enumerate() {
+        assert_eq!(string(word(sp + 8 * (1 + i))), arg);
+    }
+    let envp = sp + 8 * (args.len() + 2);
+    assert_eq!(word(sp + 8 * (args.len() + 1)), 0);
+    assert_eq!(string(word(envp)), "PATH=/bin");
+    assert_eq!(word(envp + 8), 0);
+
+    // AT_RANDOM points to the given bytes.
+    let mut auxv = envp + 16;
+    let random_pos = loop {
+        let (at, value) = (word(auxv), word(auxv + 8));
+        assert_ne!(at, 0, "no AT_RANDOM");
+        if at == kernel_elf_parser::AuxType::RANDOM as usize {
+            break value;
+        }
+        auxv += 16;
+    };
+    assert_eq!(stack_data[random_pos - sp..random_pos - sp + 16], random);
+}
+
+#[test]
+fn test_argv_layout32() {
+    let args: Vec<String> = ["/bin/arm", "a1"].map(String::from).to_vec();
+    let envs: Vec<String> = vec!["PATH=/bin".to_string()];
+    let random = [0xa5; 16];
+    let ustack_end = 0xffff_0000;
+
+    let stack_data =
+        kernel_elf_parser::app_stack_region32(&args, &envs, &[], &random, ustack_end);
+    let sp = ustack_end - stack_data.len();
+    assert_eq!(sp % 16, 0);
+    let word = |addr: usize| {
+        let off = addr - sp;
+        u32::from_ne_bytes(stack_data[off..off + 4].try_into().unwrap()) as usize
+    };
+    let string = |addr: usize| {
+        let off = addr - sp;
+        let len = stack_data[off..].iter().position(|&c| c == 0).unwrap();
+        std::str::from_utf8(&stack_data[off..off + len]).unwrap()
+    };
+
+    assert_eq!(word(sp), args.len());
+    for (i, arg) in args.iter().enumerate() {
+        assert_eq!(string(word(sp + 4 * (1 + i))), arg);
+    }
+    let envp = sp + 4 * (args.len() + 2);
+    assert_eq!(word(sp + 4 * (args.len() + 1)), 0);
+    assert_eq!(string(word(envp)), "PATH=/bin");
+    assert_eq!(word(envp + 4), 0);
+
+    let mut auxv = envp + 8;
+    let random_pos = loop {
+        let (at, value) = (word(auxv), word(auxv + 4));
+        assert_ne!(at, 0, "no AT_RANDOM");
+        if at == kernel_elf_parser::AuxType::RANDOM as usize {
+            break value;
+        }
+        auxv += 8;
+    };
+    assert_eq!(stack_data[random_pos - sp..random_pos - sp + 16], random);
+}
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! A condition variable implementation.
+
+use core::time::Duration;
+
+use khal::time::{TimeValue, wall_time};
+
+use crate::{MutexGuard, WaitQueue, WaitResult};
+
+/// A condition variable, to block tasks while waiting for a condition on the
+/// data protected by a [`Mutex`](crate::Mutex).
+///
+/// Waiters are woken in the order they started waiting. A task is enqueued
+/// before the mutex is released, so a notification sent by a task that took
+/// the mutex after that reaches it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ksync::{Condvar, Mutex};
+///
+/// static READY: Mutex<bool> = Mutex::new(false);
+/// static CONDVAR: Condvar = Condvar::new();
+///
+/// fn waiter() {
+///     let (ready, _) = CONDVAR.wait_while(READY.lock(), |ready| !*ready);
+///     assert!(*ready);
+/// }
+///
+/// fn notifier() {
+///     *READY.lock() = true;
+///     CONDVAR.notify_all();
+/// }
+/// ```
+#[derive(Default)]
+pub struct Condvar {
+    wq: WaitQueue,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+        }
+    }
+
+    fn wait_deadline<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        deadline: Option<TimeValue>,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        let waiter = self.wq.enqueue(());
+        let res = MutexGuard::unlocked(&mut guard, || self.wq.block(&waiter, deadline));
+        (guard, res)
+    }
+
+    /// Releases the mutex of `guard` and blocks until notified, then takes
+    /// the mutex again.
+    ///
+    /// Returns [`WaitResult::Notified`], or [`WaitResult::Interrupted`].
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> (MutexGuard<'a, T>, WaitResult) {
+        self.wait_deadline(guard, None)
+    }
+
+    /// Like [`Condvar::wait`], but gives up once `dur` has elapsed.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        self.wait_deadline(guard, Some(wall_time() + dur))
+    }
+
+    /// Like [`Condvar::wait`], but gives up once the wall time reaches
+    /// `deadline`.
+    pub fn wait_until<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: TimeValue,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        self.wait_deadline(guard, Some(deadline))
+    }
+
+    /// Waits while `condition` holds for the protected data, ignoring
+    /// notifications that leave it holding.
+    ///
+    /// Returns [`WaitResult::Notified`] once the condition is false, or
+    /// [`WaitResult::Interrupted`] with the condition possibly still holding.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        self.wait_deadline_while(guard, None, condition)
+    }
+
+    /// Like [`Condvar::wait_while`], but gives up once `dur` has elapsed,
+    /// returning [`WaitResult::TimedOut`] with the condition still holding.
+    pub fn wait_timeout_while<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+        condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        self.wait_deadline_while(guard, Some(wall_time() + dur), condition)
+    }
+
+    fn wait_deadline_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        deadline: Option<TimeValue>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, WaitResult) {
+        while condition(&mut *guard) {
+            let res;
+            (guard, res) = self.wait_deadline(guard, deadline);
+            if !res.is_notified() {
+                let res = if condition(&mut *guard) {
+                    res
+                } else {
+                    WaitResult::Notified
+                };
+                return (guard, res);
+            }
+        }
+        (guard, WaitResult::Notified)
+    }
+
+    /// Wakes the longest waiting task. Returns `true` if there was one.
+    pub fn notify_one(&self) -> bool {
+        self.wq.notify_one()
+    }
+
+    /// Wakes all waiting tasks. Returns the number of tasks woken.
+    pub fn notify_all(&self) -> usize {
+        self.wq.notify_all()
+    }
+}
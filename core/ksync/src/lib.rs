@@ -7,9 +7,13 @@
 //! This crate provides blocking synchronization primitives for kernel tasks:
 //!
 //! - [`Mutex`]: Mutual exclusion lock with configurable spinning
-//! - [`RwLock`]: Reader-writer lock (allows multiple readers or one writer)
+//! - [`RwLock`]: Reader-writer lock (allows multiple readers or one writer),
+//!   preferring writers, with upgradable reads
 //! - [`Semaphore`]: Counting semaphore for resource management
-//! - [`spin`]: Re-export of `kspin` for spinlocks
+//! - [`Condvar`]: Condition variable pairing with [`Mutex`]
+//! - [`WaitQueue`]: FIFO queue of sleeping tasks, with deadlines
+//! - [`spin`]: Re-export of `kspin` for spinlocks, including the IRQ-safe
+//!   reader-writer [`spin::SpinRwNoIrq`]
 //!
 //! # Examples
 //!
@@ -40,7 +44,7 @@
 //!
 //! ## RwLock
 //! ```no_run
-//! use ksync::RwLock;
+//! use ksync::{RwLock, RwLockUpgradableReadGuard};
 //!
 //! static CONFIG: RwLock<u32> = RwLock::new(0);
 //!
@@ -53,6 +57,14 @@
 //!     let mut config = CONFIG.write();
 //!     // exclusive writer
 //! }
+//!
+//! fn check_then_modify() {
+//!     let config = CONFIG.upgradable_read();
+//!     if *config == 0 {
+//!         // no writer gets in between
+//!         *RwLockUpgradableReadGuard::upgrade(config) = 1;
+//!     }
+//! }
 //! ```
 //!
 //! ## Semaphore
@@ -68,6 +80,30 @@
 //! }
 //! ```
 //!
+//! ## Condvar
+//! ```no_run
+//! use core::time::Duration;
+//!
+//! use ksync::{Condvar, Mutex, WaitResult};
+//!
+//! static QUEUE: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+//! static NOT_EMPTY: Condvar = Condvar::new();
+//!
+//! fn consumer() -> Option<u8> {
+//!     let (mut queue, res) =
+//!         NOT_EMPTY.wait_timeout_while(QUEUE.lock(), Duration::from_millis(10), |q| q.is_empty());
+//!     match res {
+//!         WaitResult::Notified => queue.pop(),
+//!         WaitResult::TimedOut | WaitResult::Interrupted => None,
+//!     }
+//! }
+//!
+//! fn producer() {
+//!     QUEUE.lock().push(42);
+//!     NOT_EMPTY.notify_one();
+//! }
+//! ```
+//!
 //! # Features
 //!
 //! - `stats`: Enable mutex statistics tracking (total locks, spins, blocks)
@@ -76,19 +112,25 @@
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+extern crate alloc;
+
 pub use kspin as spin;
 
+mod condvar;
 mod mutex;
 mod rwlock;
 mod semaphore;
 mod tests;
 mod util;
+mod wait_queue;
 
 #[cfg(feature = "stats")]
 pub use self::mutex::MutexStats;
 pub use self::{
+    condvar::Condvar,
     mutex::{Mutex, MutexGuard, RawMutex},
-    rwlock::{RawRwLock, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    rwlock::{RawRwLock, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard},
     semaphore::{Semaphore, SemaphoreGuard},
     util::SpinConfig,
+    wait_queue::{WaitQueue, WaitResult},
 };
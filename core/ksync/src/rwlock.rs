@@ -10,17 +10,27 @@ use event_listener::{Event, listener};
 use ktask::future::block_on;
 
 const WRITE_LOCKED: u32 = 1 << 31;
-const MAX_READERS: u32 = WRITE_LOCKED - 1;
+const UPGRADABLE: u32 = 1 << 30;
+const READERS_MASK: u32 = UPGRADABLE - 1;
+const MAX_READERS: u32 = READERS_MASK;
 
 /// A [`lock_api::RawRwLock`] implementation.
 ///
-/// Allows multiple readers or a single writer.
-/// The high bit of the state represents the write lock,
-/// and the low 31 bits represent the reader count.
+/// Allows multiple readers or a single writer, plus at most one upgradable
+/// reader alongside the readers. The high bit of the state represents the
+/// write lock, the next one the upgradable read lock, and the low 30 bits
+/// the reader count.
+///
+/// Writers are preferred: while one waits, new readers and upgradable
+/// readers wait behind it, so a stream of readers cannot starve it. A task
+/// must therefore not take a read lock it already holds again.
 pub struct RawRwLock {
-    state: AtomicU32, // High bit: write lock, low 31 bits: reader count
+    state: AtomicU32,
+    /// Writers and upgraders waiting for the readers to leave.
+    writers_waiting: AtomicU32,
     writer_event: Event,
     reader_event: Event,
+    upgrade_event: Event,
 }
 
 impl RawRwLock {
@@ -28,10 +38,78 @@ impl RawRwLock {
     pub const fn new() -> Self {
         Self {
             state: AtomicU32::new(0),
+            writers_waiting: AtomicU32::new(0),
             writer_event: Event::new(),
             reader_event: Event::new(),
+            upgrade_event: Event::new(),
         }
     }
+
+    /// Returns `true` if new readers must wait, because of a writer holding
+    /// the lock or waiting for it.
+    #[inline]
+    fn readers_blocked(&self, state: u32) -> bool {
+        state & WRITE_LOCKED != 0 || self.writers_waiting.load(Ordering::Acquire) != 0
+    }
+
+    /// Tries to add a reader, or with `upgradable` the upgradable reader.
+    #[inline]
+    fn try_lock_reader(&self, upgradable: bool) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if self.readers_blocked(state) || (upgradable && state & UPGRADABLE != 0) {
+                return false;
+            }
+            let new = if upgradable {
+                state | UPGRADABLE
+            } else if state & READERS_MASK >= MAX_READERS {
+                panic!("too many readers");
+            } else {
+                state + 1
+            };
+            match self
+                .state
+                .compare_exchange_weak(state, new, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(x) => state = x,
+            }
+        }
+    }
+
+    /// Adds a reader, or with `upgradable` the upgradable reader, blocking
+    /// while writers hold or wait for the lock.
+    #[inline]
+    fn lock_reader(&self, upgradable: bool) {
+        while !self.try_lock_reader(upgradable) {
+            listener!(self.reader_event => listener);
+            let state = self.state.load(Ordering::Acquire);
+            if self.readers_blocked(state) || (upgradable && state & UPGRADABLE != 0) {
+                block_on(listener);
+            }
+        }
+    }
+
+    /// Blocks on `event` until the state is `from`, then swaps in
+    /// `WRITE_LOCKED`. New readers wait meanwhile.
+    #[inline]
+    fn lock_writer(&self, from: u32, event: &Event) {
+        self.writers_waiting.fetch_add(1, Ordering::AcqRel);
+        loop {
+            if self
+                .state
+                .compare_exchange(from, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+            listener!(event => listener);
+            if self.state.load(Ordering::Acquire) != from {
+                block_on(listener);
+            }
+        }
+        self.writers_waiting.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 impl Default for RawRwLock {
@@ -48,78 +126,31 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
 
     #[inline]
     fn lock_shared(&self) {
-        loop {
-            let state = self.state.load(Ordering::Relaxed);
-
-            // Check if write locked
-            if state & WRITE_LOCKED != 0 {
-                listener!(self.reader_event => listener);
-                if self.state.load(Ordering::Acquire) & WRITE_LOCKED != 0 {
-                    block_on(listener);
-                }
-                continue;
-            }
-
-            // Check reader count
-            if state >= MAX_READERS {
-                panic!("too many readers");
-            }
-
-            // Try to increment reader count
-            match self.state.compare_exchange_weak(
-                state,
-                state + 1,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => return,
-                Err(_) => continue,
-            }
-        }
+        self.lock_reader(false);
     }
 
     #[inline]
     fn try_lock_shared(&self) -> bool {
-        let state = self.state.load(Ordering::Relaxed);
-
-        if state & WRITE_LOCKED != 0 || state >= MAX_READERS {
-            return false;
-        }
-
-        // Using strong compare_exchange here since this is a single-shot attempt
-        // without retry loop, unlike lock_shared which uses _weak in a loop
-        self.state
-            .compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
+        self.try_lock_reader(false)
     }
 
     #[inline]
     unsafe fn unlock_shared(&self) {
         let state = self.state.fetch_sub(1, Ordering::Release);
 
-        // Wake up a waiting writer if this was the last reader
-        if state == 1 {
-            self.writer_event.notify(1);
+        // Wake up a waiting writer or upgrader if this was the last reader
+        if state & READERS_MASK == 1 {
+            if state & UPGRADABLE != 0 {
+                self.upgrade_event.notify(1);
+            } else {
+                self.writer_event.notify(1);
+            }
         }
     }
 
     #[inline]
     fn lock_exclusive(&self) {
-        loop {
-            // Try to acquire write lock
-            match self
-                .state
-                .compare_exchange(0, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
-            {
-                Ok(_) => return,
-                Err(_) => {
-                    listener!(self.writer_event => listener);
-                    if self.state.load(Ordering::Acquire) != 0 {
-                        block_on(listener);
-                    }
-                }
-            }
-        }
+        self.lock_writer(0, &self.writer_event);
     }
 
     #[inline]
@@ -139,9 +170,64 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
     }
 }
 
+unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
+    #[inline]
+    fn lock_upgradable(&self) {
+        self.lock_reader(true);
+    }
+
+    #[inline]
+    fn try_lock_upgradable(&self) -> bool {
+        self.try_lock_reader(true)
+    }
+
+    #[inline]
+    unsafe fn unlock_upgradable(&self) {
+        let state = self.state.fetch_and(!UPGRADABLE, Ordering::Release);
+
+        // Wake up another upgradable reader, and a writer if there are no
+        // readers left
+        self.reader_event.notify(usize::MAX);
+        if state & READERS_MASK == 0 {
+            self.writer_event.notify(1);
+        }
+    }
+
+    #[inline]
+    unsafe fn upgrade(&self) {
+        // New readers wait behind the upgrade like behind a writer.
+        self.lock_writer(UPGRADABLE, &self.upgrade_event);
+    }
+
+    #[inline]
+    unsafe fn try_upgrade(&self) -> bool {
+        self.state
+            .compare_exchange(
+                UPGRADABLE,
+                WRITE_LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+}
+
+unsafe impl lock_api::RawRwLockDowngrade for RawRwLock {
+    #[inline]
+    unsafe fn downgrade(&self) {
+        self.state.store(1, Ordering::Release);
+
+        // Readers may come in unless writers are waiting
+        self.reader_event.notify(usize::MAX);
+    }
+}
+
 /// A reader-writer lock.
 pub type RwLock<T> = lock_api::RwLock<RawRwLock, T>;
 /// A read guard for a [`RwLock`].
 pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawRwLock, T>;
 /// A write guard for a [`RwLock`].
 pub type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawRwLock, T>;
+/// An upgradable read guard for a [`RwLock`], which can be upgraded to a
+/// write guard without letting another writer in between.
+pub type RwLockUpgradableReadGuard<'a, T> = lock_api::RwLockUpgradableReadGuard<'a, RawRwLock, T>;
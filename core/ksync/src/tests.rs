@@ -5,10 +5,19 @@
 extern crate alloc;
 
 use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
 
+use khal::time::wall_time;
+use ktask::{KtaskRef, spawn, yield_now};
 use unittest::{assert, assert_eq, def_test};
 
-use super::{Mutex, RwLock, Semaphore, SpinConfig};
+use super::{
+    Condvar, Mutex, RwLock, RwLockUpgradableReadGuard, Semaphore, SpinConfig, WaitQueue,
+    WaitResult, spin::SpinRwNoIrq,
+};
 
 // ============================================================================
 // Mutex Tests
@@ -259,6 +268,165 @@ fn test_rwlock_writer_blocks_readers() {
     }
 }
 
+#[def_test]
+fn test_rwlock_upgradable_guard() {
+    let lock = RwLock::new(1);
+
+    let up = lock.upgradable_read();
+    // Plain readers share the lock with the upgradable reader, another
+    // upgradable reader and writers do not.
+    assert_eq!(*lock.read(), 1);
+    assert!(lock.try_upgradable_read().is_none());
+    assert!(lock.try_write().is_none());
+
+    let r = lock.read();
+    let up = RwLockUpgradableReadGuard::try_upgrade(up).unwrap_err();
+    drop(r);
+
+    // Check then modify, with no writer in between.
+    if *up == 1 {
+        let mut w = RwLockUpgradableReadGuard::upgrade(up);
+        *w = 2;
+        assert!(lock.try_read().is_none());
+    }
+    assert_eq!(*lock.read(), 2);
+    assert!(lock.try_upgradable_read().is_some());
+}
+
+#[def_test]
+fn test_rwlock_waiting_writer_blocks_readers() {
+    let lock = Arc::new(RwLock::new(0));
+    let r = lock.read();
+
+    let writer = {
+        let lock = lock.clone();
+        spawn(move || *lock.write() += 1)
+    };
+    // Let the writer start waiting for the reader to leave.
+    while lock.try_read().is_some() {
+        yield_now();
+    }
+    assert!(lock.try_upgradable_read().is_none());
+    drop(r);
+
+    writer.join();
+    assert_eq!(*lock.read(), 1);
+}
+
+/// Spawns `readers` tasks checking that the two halves of the pair always
+/// match until the writers are done, and `writers` tasks each updating the
+/// pair `rounds` times with `update`. Returns how many distinct values
+/// the readers observed.
+fn stress_rwlock<L: Send + Sync + 'static>(
+    lock: Arc<L>,
+    readers: usize,
+    writers: usize,
+    read: fn(&L) -> (usize, usize),
+    update: fn(&L),
+    rounds: usize,
+) -> usize {
+    let done = Arc::new(AtomicBool::new(false));
+    let observed = Arc::new(AtomicUsize::new(0));
+    let reader_tasks: Vec<KtaskRef> = (0..readers)
+        .map(|_| {
+            let (lock, done, observed) = (lock.clone(), done.clone(), observed.clone());
+            spawn(move || {
+                let mut last = usize::MAX;
+                while !done.load(Ordering::Acquire) {
+                    let (a, b) = read(&lock);
+                    core::assert_eq!(a, b);
+                    if a != last {
+                        last = a;
+                        observed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    yield_now();
+                }
+            })
+        })
+        .collect();
+    let writer_tasks: Vec<KtaskRef> = (0..writers)
+        .map(|_| {
+            let lock = lock.clone();
+            spawn(move || {
+                for _ in 0..rounds {
+                    update(&lock);
+                    yield_now();
+                }
+            })
+        })
+        .collect();
+
+    // Readers never leave before the writers are done, so the writers
+    // finishing shows they were not starved.
+    for task in writer_tasks {
+        task.join();
+    }
+    done.store(true, Ordering::Release);
+    for task in reader_tasks {
+        task.join();
+    }
+    observed.load(Ordering::Relaxed)
+}
+
+#[def_test]
+fn test_rwlock_stress_readers_and_writers() {
+    let lock = Arc::new(RwLock::new((0, 0)));
+    let observed = stress_rwlock(
+        lock.clone(),
+        8,
+        2,
+        |lock| *lock.read(),
+        |lock| {
+            let mut w = lock.write();
+            w.0 += 1;
+            yield_now();
+            w.1 += 1;
+        },
+        200,
+    );
+    assert_eq!(*lock.read(), (400, 400));
+    assert!(observed > 8);
+}
+
+#[def_test]
+fn test_rwlock_stress_upgradable_check_then_modify() {
+    let lock = Arc::new(RwLock::new((0, 0)));
+    stress_rwlock(
+        lock.clone(),
+        4,
+        4,
+        |lock| *lock.read(),
+        |lock| {
+            let up = lock.upgradable_read();
+            let next = up.0 + 1;
+            yield_now();
+            let mut w = RwLockUpgradableReadGuard::upgrade(up);
+            *w = (next, next);
+        },
+        100,
+    );
+    // The value read before the upgrade is still current after it.
+    assert_eq!(*lock.read(), (400, 400));
+}
+
+#[def_test]
+fn test_spin_rwlock_stress_readers_and_writers() {
+    let lock = Arc::new(SpinRwNoIrq::new((0, 0)));
+    stress_rwlock(
+        lock.clone(),
+        8,
+        2,
+        |lock| *lock.read(),
+        |lock| {
+            let mut w = lock.write();
+            w.0 += 1;
+            w.1 += 1;
+        },
+        200,
+    );
+    assert_eq!(*lock.read(), (400, 400));
+}
+
 // ============================================================================
 // Semaphore Tests
 // ============================================================================
@@ -370,3 +538,176 @@ fn test_semaphore_multiple_release_overflow() {
     assert_eq!(sem.available_permits(), 0);
     assert!(!sem.try_acquire());
 }
+
+// ============================================================================
+// WaitQueue and Condvar Tests
+// ============================================================================
+
+/// How late a timed-out waiter may wake, for timer and scheduling latency.
+const WAKE_SLACK: Duration = Duration::from_millis(20);
+
+#[def_test]
+fn test_wait_queue_times_out() {
+    let wq = WaitQueue::new();
+    let timeout = Duration::from_millis(50);
+
+    let start = wall_time();
+    assert_eq!(wq.wait_timeout(timeout), WaitResult::TimedOut);
+    let elapsed = wall_time() - start;
+    assert!(elapsed >= timeout);
+    assert!(elapsed < timeout + WAKE_SLACK);
+
+    // The waiter left the queue, no notification is spent on it.
+    assert!(wq.is_empty());
+    assert!(!wq.notify_one());
+
+    // A deadline in the past does not block.
+    assert_eq!(wq.wait_until(start), WaitResult::TimedOut);
+}
+
+#[def_test]
+fn test_wait_queue_woken_before_deadline() {
+    let wq = Arc::new(WaitQueue::new());
+    let deadline = wall_time() + Duration::from_millis(100);
+
+    let notifier = {
+        let wq = wq.clone();
+        spawn(move || {
+            // Just before the deadline, leaving room for the slack.
+            ktask::sleep_until(deadline - WAKE_SLACK);
+            while !wq.notify_one() {
+                yield_now();
+            }
+        })
+    };
+    assert_eq!(wq.wait_until(deadline), WaitResult::Notified);
+    assert!(wall_time() < deadline);
+    notifier.join();
+}
+
+#[def_test]
+fn test_wait_queue_wait_releasing() {
+    let state = Arc::new((Mutex::new(false), WaitQueue::new()));
+
+    let waiter = {
+        let state = state.clone();
+        spawn(move || {
+            let (ready, wq) = &*state;
+            let guard = ready.lock();
+            core::assert!(!*guard);
+            // The flag is set only once the task waits.
+            core::assert_eq!(wq.wait_releasing((), None, guard), WaitResult::Notified);
+        })
+    };
+    let (ready, wq) = &*state;
+    while wq.len() == 0 {
+        yield_now();
+    }
+    *ready.lock() = true;
+    wq.notify_all();
+    waiter.join();
+    assert_eq!(wq.len(), 0);
+}
+
+#[def_test]
+fn test_wait_queue_interrupted() {
+    let wq = Arc::new(WaitQueue::new());
+    let waiter = {
+        let wq = wq.clone();
+        spawn(move || core::assert_eq!(wq.wait(), WaitResult::Interrupted))
+    };
+    while wq.is_empty() {
+        yield_now();
+    }
+    waiter.interrupt();
+    waiter.join();
+    assert!(wq.is_empty());
+}
+
+#[def_test]
+fn test_wait_queue_fifo_and_tags() {
+    let wq = Arc::new(WaitQueue::<u32>::new());
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let started = Arc::new(AtomicUsize::new(0));
+
+    // Tags 1, 2, 1, 2, waiting in that order.
+    let waiters: Vec<KtaskRef> = (0..4)
+        .map(|i| {
+            let (wq, order, started) = (wq.clone(), order.clone(), started.clone());
+            let tag = i as u32 % 2 + 1;
+            let waiter = spawn(move || {
+                let res = wq.wait_if(tag, None, || {
+                    started.fetch_add(1, Ordering::AcqRel);
+                    true
+                });
+                core::assert_eq!(res, Some(WaitResult::Notified));
+                order.lock().push(i);
+            });
+            // The condition runs with the waiter being enqueued.
+            while started.load(Ordering::Acquire) <= i {
+                yield_now();
+            }
+            waiter
+        })
+        .collect();
+
+    // Wake one at a time, so the woken tasks record in the order of waking.
+    let wake = |notify: &dyn Fn() -> usize, expected: usize| {
+        let woken = order.lock().len();
+        core::assert_eq!(notify(), expected);
+        while order.lock().len() < woken + expected {
+            yield_now();
+        }
+    };
+    wake(&|| wq.notify_many_if(1, |&tag| tag == 2), 1);
+    wake(&|| wq.notify_many_if(1, |&tag| tag == 2), 1);
+    wake(&|| wq.notify_many_if(1, |&tag| tag == 2), 0);
+    wake(&|| wq.notify_one() as usize, 1);
+    wake(&|| wq.notify_all(), 1);
+    for waiter in waiters {
+        waiter.join();
+    }
+    assert_eq!(*order.lock(), [1, 3, 0, 2]);
+
+    // A false condition does not block.
+    assert_eq!(wq.wait_if(1, None, || false), None);
+}
+
+#[def_test]
+fn test_condvar_wait_while() {
+    let pair = Arc::new((Mutex::new(0), Condvar::new()));
+
+    let consumer = {
+        let pair = pair.clone();
+        spawn(move || {
+            let (lock, cvar) = &*pair;
+            let (value, res) = cvar.wait_while(lock.lock(), |value| *value < 3);
+            core::assert_eq!(res, WaitResult::Notified);
+            core::assert_eq!(*value, 3);
+        })
+    };
+    for _ in 0..3 {
+        let (lock, cvar) = &*pair;
+        *lock.lock() += 1;
+        cvar.notify_all();
+        yield_now();
+    }
+    consumer.join();
+}
+
+#[def_test]
+fn test_condvar_wait_timeout_while() {
+    let lock = Mutex::new(false);
+    let cvar = Condvar::new();
+    let timeout = Duration::from_millis(30);
+
+    let start = wall_time();
+    let (guard, res) = cvar.wait_timeout_while(lock.lock(), timeout, |ready| !*ready);
+    assert_eq!(res, WaitResult::TimedOut);
+    assert!(!*guard);
+    assert!(wall_time() - start >= timeout);
+
+    // The mutex is released while waiting.
+    drop(guard);
+    assert!(lock.try_lock().is_some());
+}
@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! A FIFO wait queue with deadlines.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::poll_fn,
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use khal::time::{TimeValue, wall_time};
+use kspin::SpinNoIrq;
+use ktask::future::{block_on, interruptible, timeout_at};
+
+/// How a wait on a [`WaitQueue`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    /// Another task notified the waiter.
+    Notified,
+    /// The deadline passed first.
+    TimedOut,
+    /// The waiting task was interrupted, e.g. by a signal.
+    Interrupted,
+}
+
+impl WaitResult {
+    /// Returns `true` if the waiter was notified.
+    pub fn is_notified(self) -> bool {
+        self == Self::Notified
+    }
+
+    /// Returns `true` if the deadline passed first.
+    pub fn timed_out(self) -> bool {
+        self == Self::TimedOut
+    }
+}
+
+#[derive(Default)]
+struct WaiterState {
+    notified: bool,
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+/// A task in a [`WaitQueue`], with the tag it waits with.
+pub(crate) struct Waiter<T> {
+    tag: T,
+    state: SpinNoIrq<WaiterState>,
+}
+
+impl<T> Waiter<T> {
+    /// Marks the waiter notified, returning its waker, or `None` if the
+    /// waiter gave up waiting already.
+    fn notify(&self) -> Option<Option<Waker>> {
+        let mut state = self.state.lock();
+        if state.cancelled {
+            return None;
+        }
+        state.notified = true;
+        Some(state.waker.take())
+    }
+}
+
+/// A queue of sleeping tasks, woken in the order they started waiting.
+///
+/// Each waiter carries a tag of type `T`, which notifiers can select waiters
+/// by, see [`WaitQueue::notify_many_if`].
+///
+/// All waits can end by a notification, by the deadline passing, or by the
+/// task being interrupted, see [`WaitResult`]. A waiter that times out or is
+/// interrupted leaves the queue before the wait returns, and a notification
+/// racing with that is not lost: the wait returns [`WaitResult::Notified`]
+/// then, so every notification counted by the notifier is seen by exactly one
+/// waiter.
+///
+/// # Examples
+///
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use ksync::{WaitQueue, WaitResult};
+///
+/// static WQ: WaitQueue = WaitQueue::new();
+///
+/// fn waiter() {
+///     match WQ.wait_timeout(Duration::from_millis(10)) {
+///         WaitResult::Notified => {}
+///         WaitResult::TimedOut => {}
+///         WaitResult::Interrupted => {}
+///     }
+/// }
+///
+/// fn notifier() {
+///     WQ.notify_one();
+/// }
+/// ```
+pub struct WaitQueue<T = ()> {
+    queue: SpinNoIrq<VecDeque<Arc<Waiter<T>>>>,
+}
+
+impl<T> Default for WaitQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> WaitQueue<T> {
+    /// Creates an empty wait queue.
+    pub const fn new() -> Self {
+        Self {
+            queue: SpinNoIrq::new(VecDeque::new()),
+        }
+    }
+
+    /// Appends a waiter with `tag` to the queue.
+    pub(crate) fn enqueue(&self, tag: T) -> Arc<Waiter<T>> {
+        let waiter = Arc::new(Waiter {
+            tag,
+            state: SpinNoIrq::new(WaiterState::default()),
+        });
+        self.queue.lock().push_back(waiter.clone());
+        waiter
+    }
+
+    /// Blocks until `waiter`, enqueued by [`WaitQueue::enqueue`], is
+    /// notified or `deadline` passes.
+    pub(crate) fn block(&self, waiter: &Waiter<T>, deadline: Option<TimeValue>) -> WaitResult {
+        let res = block_on(interruptible(timeout_at(
+            deadline,
+            poll_fn(|cx| {
+                let mut state = waiter.state.lock();
+                if state.notified {
+                    Poll::Ready(())
+                } else {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }),
+        )));
+        let res = match res {
+            Ok(Ok(())) => return WaitResult::Notified,
+            Ok(Err(_)) => WaitResult::TimedOut,
+            Err(_) => WaitResult::Interrupted,
+        };
+
+        // Leave the queue, unless a notification came in meanwhile. The
+        // waiter may have been requeued, then a notifier of the other queue
+        // skips it.
+        let mut queue = self.queue.lock();
+        let mut state = waiter.state.lock();
+        if state.notified {
+            return WaitResult::Notified;
+        }
+        state.cancelled = true;
+        state.waker = None;
+        drop(state);
+        if let Some(pos) = queue.iter().position(|w| core::ptr::eq(&**w, waiter)) {
+            queue.remove(pos);
+        }
+        res
+    }
+
+    /// Blocks the current task with `tag` if `condition` holds, until it is
+    /// notified or `deadline` passes.
+    ///
+    /// The condition is checked with the queue locked, so a notification
+    /// after it changed cannot be missed. It must not sleep. Returns `None`
+    /// without blocking if it does not hold.
+    pub fn wait_if(
+        &self,
+        tag: T,
+        deadline: Option<TimeValue>,
+        condition: impl FnOnce() -> bool,
+    ) -> Option<WaitResult> {
+        let waiter = {
+            let mut queue = self.queue.lock();
+            if !condition() {
+                return None;
+            }
+            let waiter = Arc::new(Waiter {
+                tag,
+                state: SpinNoIrq::new(WaiterState::default()),
+            });
+            queue.push_back(waiter.clone());
+            waiter
+        };
+        Some(self.block(&waiter, deadline))
+    }
+
+    /// Blocks the current task with `tag` until it is notified or `deadline`
+    /// passes, dropping `guard` once the task is in the queue.
+    ///
+    /// If `guard` holds a lock that notifiers take too, a condition checked
+    /// under it cannot change unnoticed before the task blocks. Unlike with
+    /// [`WaitQueue::wait_if`], checking it may sleep, e.g. to fault in user
+    /// memory.
+    pub fn wait_releasing<G>(&self, tag: T, deadline: Option<TimeValue>, guard: G) -> WaitResult {
+        let waiter = self.enqueue(tag);
+        drop(guard);
+        self.block(&waiter, deadline)
+    }
+
+    /// Wakes up to `count` waiters whose tag matches `predicate`, the longest
+    /// waiting first. Returns the number of waiters woken.
+    pub fn notify_many_if(&self, count: usize, mut predicate: impl FnMut(&T) -> bool) -> usize {
+        let mut wakers = alloc::vec::Vec::new();
+        self.queue.lock().retain(|waiter| {
+            if wakers.len() >= count || !predicate(&waiter.tag) {
+                return true;
+            }
+            if let Some(waker) = waiter.notify() {
+                wakers.push(waker);
+            }
+            false
+        });
+        let woken = wakers.len();
+        wakers.into_iter().flatten().for_each(Waker::wake);
+        woken
+    }
+
+    /// Wakes up to `count` waiters, the longest waiting first. Returns the
+    /// number of waiters woken.
+    pub fn notify_many(&self, count: usize) -> usize {
+        self.notify_many_if(count, |_| true)
+    }
+
+    /// Wakes the longest waiting waiter. Returns `true` if there was one.
+    pub fn notify_one(&self) -> bool {
+        self.notify_many(1) == 1
+    }
+
+    /// Wakes all waiters. Returns the number of waiters woken.
+    pub fn notify_all(&self) -> usize {
+        self.notify_many(usize::MAX)
+    }
+
+    /// Moves up to `count` waiters, the longest waiting first, to the end of
+    /// `target` without waking them. Returns the number of waiters moved.
+    pub fn requeue(&self, count: usize, target: &WaitQueue<T>) -> usize {
+        let moved: VecDeque<_> = {
+            let mut queue = self.queue.lock();
+            queue.retain(|waiter| !waiter.state.lock().cancelled);
+            let count = count.min(queue.len());
+            queue.drain(..count).collect()
+        };
+        let count = moved.len();
+        if count > 0 {
+            target.queue.lock().extend(moved);
+        }
+        count
+    }
+
+    /// Returns `true` if no task is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of waiting tasks.
+    pub fn len(&self) -> usize {
+        self.queue
+            .lock()
+            .iter()
+            .filter(|waiter| !waiter.state.lock().cancelled)
+            .count()
+    }
+}
+
+impl WaitQueue {
+    /// Blocks the current task until it is notified.
+    ///
+    /// Returns [`WaitResult::Notified`], or [`WaitResult::Interrupted`].
+    pub fn wait(&self) -> WaitResult {
+        let waiter = self.enqueue(());
+        self.block(&waiter, None)
+    }
+
+    /// Blocks the current task until it is notified, or `dur` has elapsed.
+    pub fn wait_timeout(&self, dur: Duration) -> WaitResult {
+        self.wait_until(wall_time() + dur)
+    }
+
+    /// Blocks the current task until it is notified, or the wall time reaches
+    /// `deadline`.
+    pub fn wait_until(&self, deadline: TimeValue) -> WaitResult {
+        let waiter = self.enqueue(());
+        self.block(&waiter, Some(deadline))
+    }
+}
@@ -39,17 +39,23 @@ cfg_if::cfg_if! {
     if #[cfg(feature = "sched-rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type KTask = axsched::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = axsched::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type NormalScheduler = axsched::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+    } else if #[cfg(feature = "sched-fair")] {
+        pub(crate) type KTask = crate::fair::FairTask;
+        pub(crate) type NormalScheduler = crate::fair::FairScheduler;
     } else if #[cfg(feature = "sched-cfs")] {
         pub(crate) type KTask = axsched::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = axsched::CFScheduler<TaskInner>;
+        pub(crate) type NormalScheduler = axsched::CFScheduler<TaskInner>;
     } else {
         // If no scheduler features are set, use FIFO as the default.
         pub(crate) type KTask = axsched::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = axsched::FifoScheduler<TaskInner>;
+        pub(crate) type NormalScheduler = axsched::FifoScheduler<TaskInner>;
     }
 }
 
+/// The scheduler of a run queue: real-time tasks first, then the normal ones.
+pub(crate) type Scheduler = crate::rt::RtScheduler<NormalScheduler>;
+
 #[cfg(feature = "preempt")]
 struct KernelGuardIfImpl;
 
@@ -104,7 +110,7 @@ pub fn init_scheduler_with_cpu_num(cpu_num: usize) {
 
     crate::run_queue::init();
 
-    info!("  use {} scheduler.", Scheduler::scheduler_name());
+    info!("  use {} scheduler.", NormalScheduler::scheduler_name());
 }
 
 pub(crate) fn active_cpu_num() -> usize {
@@ -154,6 +160,27 @@ where
     spawn_raw(f, name, platconfig::TASK_STACK_SIZE)
 }
 
+/// Spawns a new task that only ever runs on CPU `cpu`.
+///
+/// The affinity of the task cannot be changed later, see [`set_affinity`].
+/// As with [`spawn`], the task name is an empty string and its stack size
+/// [`platconfig::TASK_STACK_SIZE`].
+///
+/// Returns the task reference.
+///
+/// # Panics
+///
+/// Panics if `cpu` is not online.
+pub fn spawn_pinned<F>(cpu: usize, f: F) -> KtaskRef
+where
+    F: FnOnce() + Send + 'static,
+{
+    assert!(is_cpu_online(cpu), "CPU {cpu} is not online");
+    let mut task = TaskInner::new(f, String::new(), platconfig::TASK_STACK_SIZE);
+    task.pin(cpu);
+    spawn_task(task)
+}
+
 /// Spawns a new task with the default parameters.
 ///
 /// The default task name is an empty string. The default task stack size is
@@ -180,40 +207,120 @@ pub fn set_prio(prio: isize) -> bool {
     current_run_queue::<NoPreemptIrqSave>().set_current_priority(prio)
 }
 
-/// Set the affinity for the current task.
-/// [`KCpuMask`] is used to specify the CPU affinity.
-/// Returns `true` if the affinity is set successfully.
+/// Sets the nice value of `task`.
 ///
-/// TODO: support set the affinity for other tasks.
-pub fn set_current_affinity(cpumask: KCpuMask) -> bool {
-    if cpumask.is_empty() {
-        false
-    } else {
-        let curr = current().clone();
-
-        curr.set_cpumask(cpumask);
-        // After setting the affinity, we need to check if current cpu matches
-        // the affinity. If not, we need to migrate the task to the correct CPU.
-        #[cfg(feature = "smp")]
-        if !cpumask.get(khal::percpu::this_cpu_id()) {
-            const MIGRATION_TASK_STACK_SIZE: usize = 4096;
-            // Spawn a new migration task for migrating.
-            let migration_task = TaskInner::new(
-                move || crate::run_queue::migrate_entry(curr),
-                "migration-task".into(),
-                MIGRATION_TASK_STACK_SIZE,
-            )
-            .into_arc();
-
-            // Migrate the current task to the correct CPU using the migration task.
-            current_run_queue::<NoPreemptIrqSave>().migrate_current(migration_task);
-
-            assert!(cpumask.get(khal::percpu::this_cpu_id()), "Migration failed");
-        }
+/// The nice value ranges from [`MIN_NICE`](crate::MIN_NICE) to
+/// [`MAX_NICE`](crate::MAX_NICE); it only affects scheduling under the
+/// `sched-fair` scheduler, which picks up the new weight at the next tick.
+///
+/// Returns `false` if `nice` is out of range.
+pub fn set_nice(task: &TaskInner, nice: i32) -> bool {
+    if (crate::MIN_NICE as i32..=crate::MAX_NICE as i32).contains(&nice) {
+        task.set_nice(nice as i8);
         true
+    } else {
+        false
     }
 }
 
+/// Sets the scheduling policy and the real-time priority of `task`, returns
+/// `false` if they do not fit together, see
+/// [`TaskInner::set_sched_policy`].
+///
+/// A ready task is requeued under its new policy, and the CPU of the task
+/// reschedules if it should now run something else.
+pub fn set_scheduler(task: &KtaskRef, policy: crate::SchedPolicy, rt_priority: u8) -> bool {
+    crate::run_queue::set_scheduler(task, policy, rt_priority)
+}
+
+/// Scheduling statistics of a task.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedStat {
+    /// Nice value.
+    pub nice: i8,
+    /// Weight derived from the nice value.
+    pub weight: u32,
+    /// Virtual runtime in nanoseconds, 0 if the scheduler does not track it.
+    pub vruntime: u64,
+    /// CPU time charged by the scheduler in nanoseconds, 0 if the scheduler
+    /// does not track it.
+    pub sum_exec_runtime: u64,
+}
+
+/// Returns the scheduling statistics of `task`.
+pub fn sched_stat(task: &KtaskRef) -> SchedStat {
+    #[cfg(feature = "sched-fair")]
+    let (vruntime, sum_exec_runtime) = (task.vruntime(), task.sum_exec_runtime());
+    #[cfg(not(feature = "sched-fair"))]
+    let (vruntime, sum_exec_runtime) = (0, 0);
+    let nice = task.nice();
+    SchedStat {
+        nice,
+        weight: crate::nice_to_weight(nice),
+        vruntime,
+        sum_exec_runtime,
+    }
+}
+
+/// Scheduling statistics of a CPU.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuSchedStat {
+    /// Context switches.
+    pub switches: u64,
+    /// Tasks taken from other run queues by this CPU when it had nothing to
+    /// run.
+    pub steals: u64,
+    /// Tasks moved onto this CPU from another one, by wakeups, steals, load
+    /// balancing or affinity changes.
+    pub migrations: u64,
+    /// Tasks ready to run on this CPU.
+    pub nr_ready: usize,
+}
+
+/// Returns the scheduling statistics of CPU `cpu_id`, or `None` if it is not
+/// online.
+pub fn cpu_sched_stat(cpu_id: usize) -> Option<CpuSchedStat> {
+    crate::run_queue::cpu_sched_stat(cpu_id)
+}
+
+/// Returns whether CPU `cpu_id` is online, i.e. runs tasks.
+pub fn is_cpu_online(cpu_id: usize) -> bool {
+    crate::run_queue::is_online(cpu_id)
+}
+
+/// Set the affinity for the current task, see [`set_affinity`].
+pub fn set_current_affinity(cpumask: KCpuMask) -> bool {
+    set_affinity(&current().clone(), cpumask)
+}
+
+/// Sets the CPU affinity of `task`.
+///
+/// Returns `false` without changing anything if `task` is pinned (see
+/// [`spawn_pinned`]) or `cpumask` holds no online CPU.
+///
+/// The current task moves to a CPU of the mask at once. Another task still
+/// running on a CPU it may no longer use moves at the next reschedule point
+/// of that CPU, and a ready one when that CPU next picks a task.
+pub fn set_affinity(task: &KtaskRef, cpumask: KCpuMask) -> bool {
+    if task.is_pinned()
+        || !(0..platconfig::plat::CPU_NUM).any(|cpu| cpumask.get(cpu) && is_cpu_online(cpu))
+    {
+        return false;
+    }
+    task.set_cpumask(cpumask);
+    // After setting the affinity, we need to check if current cpu matches
+    // the affinity. If not, we need to migrate the task to the correct CPU.
+    #[cfg(feature = "smp")]
+    if current().ptr_eq(task) && !cpumask.get(khal::percpu::this_cpu_id()) {
+        let migration_task = crate::run_queue::migration_task(task.clone());
+        // Migrate the current task to the correct CPU using the migration task.
+        current_run_queue::<NoPreemptIrqSave>().migrate_current(migration_task);
+
+        assert!(cpumask.get(khal::percpu::this_cpu_id()), "Migration failed");
+    }
+    true
+}
+
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
@@ -230,6 +337,28 @@ pub fn sleep_until(deadline: khal::time::TimeValue) {
     crate::future::block_on(crate::future::sleep_until(deadline));
 }
 
+/// Runs `f` inside a recovery region of the current task.
+///
+/// If `f` makes a bad kernel access (a page fault that nothing resolves), the
+/// kernel reports an oops and exits only the current task, instead of
+/// panicking. Locks held by the task at that point stay held, so this is
+/// meant for self-contained work such as probing untrusted structures.
+pub fn recoverable<R>(f: impl FnOnce() -> R) -> R {
+    struct Region(CurrentTask);
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            self.0.leave_recovery_region();
+        }
+    }
+
+    let region = Region(current());
+    region.0.enter_recovery_region();
+    let ret = f();
+    drop(region);
+    ret
+}
+
 /// Exits the current task.
 pub fn exit(exit_code: i32) -> ! {
     current_run_queue::<NoPreemptIrqSave>().exit_current(exit_code)
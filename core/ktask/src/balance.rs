@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Placement and load balancing policy of the per-CPU run queues.
+//!
+//! A woken task goes back to the CPU it last ran on, where its working set
+//! may still be cached. Once it has been off that CPU for longer than
+//! [`CACHE_HOT_NANOS`] there is nothing left to lose, and it is woken on the
+//! CPU of its waker instead, which likely touched the data it waited for. New
+//! tasks go to the least loaded CPU.
+//!
+//! A CPU about to go idle steals a ready task from the busiest run queue, and
+//! every [`BALANCE_INTERVAL_TICKS`] each CPU pulls one from the busiest queue
+//! if that is at least [`IMBALANCE_THRESHOLD`] tasks longer than its own.
+//!
+//! The functions here only decide; the run queues do the moving. Loads are
+//! given per CPU index, `None` for CPUs that are not online.
+#![cfg_attr(not(any(test, feature = "smp")), allow(dead_code))]
+
+/// How long a task's working set is assumed to stay in the caches of the
+/// CPU it ran on, in nanoseconds.
+pub const CACHE_HOT_NANOS: u64 = 500_000;
+
+/// Timer ticks between two periodic load balancing passes of a CPU.
+pub const BALANCE_INTERVAL_TICKS: u64 = {
+    let ticks = platconfig::TICKS_PER_SEC as u64 / 10;
+    if ticks == 0 { 1 } else { ticks }
+};
+
+/// Difference of load from which the periodic pass moves a task.
+///
+/// Moving a task between queues one task apart would only swap them.
+pub const IMBALANCE_THRESHOLD: usize = 2;
+
+/// Selects the CPU to put a task on.
+///
+/// `allowed` tells whether the task may run on a CPU, `prev` is the CPU it
+/// last ran on (`None` if it never ran), `waker` the CPU doing the wakeup,
+/// and `cache_hot` whether the task ran recently enough to benefit from the
+/// caches of `prev`.
+///
+/// # Panics
+///
+/// Panics if the task may not run on any online CPU.
+pub fn select_cpu(
+    allowed: impl Fn(usize) -> bool,
+    prev: Option<usize>,
+    waker: usize,
+    cache_hot: bool,
+    loads: &[Option<usize>],
+) -> usize {
+    let usable = |cpu: usize| loads.get(cpu).is_some_and(Option::is_some) && allowed(cpu);
+    match prev {
+        Some(prev) if cache_hot && usable(prev) => prev,
+        Some(_) if usable(waker) => waker,
+        Some(prev) if usable(prev) => prev,
+        _ => (0..loads.len())
+            .filter(|&cpu| usable(cpu))
+            .min_by_key(|&cpu| loads[cpu])
+            .expect("No available CPU for task execution"),
+    }
+}
+
+/// Returns the most loaded CPU other than `this`, if its load is at least
+/// `min_load`.
+///
+/// Ties go to the lowest CPU index.
+pub fn find_busiest(this: usize, loads: &[Option<usize>], min_load: usize) -> Option<usize> {
+    loads
+        .iter()
+        .enumerate()
+        .filter_map(|(cpu, load)| Some((cpu, (*load)?)))
+        .filter(|&(cpu, load)| cpu != this && load >= min_load)
+        .fold(
+            None,
+            |busiest: Option<(usize, usize)>, (cpu, load)| match busiest {
+                Some((_, max)) if max >= load => busiest,
+                _ => Some((cpu, load)),
+            },
+        )
+        .map(|(cpu, _)| cpu)
+}
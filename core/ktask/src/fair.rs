@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Weighted fair scheduler.
+//!
+//! A simplified CFS: every task accumulates a virtual runtime, which is its
+//! CPU time scaled by `NICE_0_WEIGHT / weight`, and the task with the smallest
+//! virtual runtime runs next. Under contention, tasks therefore receive CPU
+//! time in proportion to the weights of their nice values.
+//!
+//! CPU time is charged by timer ticks: the task running when a tick arrives is
+//! charged the whole tick.
+#![cfg_attr(not(any(test, feature = "sched-fair")), allow(dead_code))]
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{
+    ops::Deref,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axsched::BaseScheduler;
+
+use crate::TaskInner;
+
+/// Lowest (highest priority) nice value.
+pub const MIN_NICE: i8 = -20;
+/// Highest (lowest priority) nice value.
+pub const MAX_NICE: i8 = 19;
+
+/// Weight of a task with nice value 0.
+pub const NICE_0_WEIGHT: u32 = 1024;
+
+/// Weights of nice values -20..=19, as in Linux `sched_prio_to_weight`.
+///
+/// Each step of nice value changes the share of CPU time by about 10%.
+#[rustfmt::skip]
+const NICE_TO_WEIGHT: [u32; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */ 9548, 7620, 6100, 4904, 3906,
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277,
+    /*   0 */ 1024, 820, 655, 526, 423,
+    /*   5 */ 335, 272, 215, 172, 137,
+    /*  10 */ 110, 87, 70, 56, 45,
+    /*  15 */ 36, 29, 23, 18, 15,
+];
+
+/// Length of a timer tick in nanoseconds.
+const TICK_NANOS: u64 = khal::time::NANOS_PER_SEC / platconfig::TICKS_PER_SEC as u64;
+
+/// How far (in virtual runtime) the current task may run ahead of the
+/// leftmost ready task before it is preempted.
+const PREEMPT_GRANULARITY: u64 = TICK_NANOS;
+
+/// Returns the weight of the nice value `nice`, which is clamped to the valid
+/// range.
+pub const fn nice_to_weight(nice: i8) -> u32 {
+    let nice = if nice < MIN_NICE {
+        MIN_NICE
+    } else if nice > MAX_NICE {
+        MAX_NICE
+    } else {
+        nice
+    };
+    NICE_TO_WEIGHT[(nice - MIN_NICE) as usize]
+}
+
+/// Converts `delta` nanoseconds of CPU time into virtual runtime.
+const fn calc_vruntime(delta: u64, weight: u32) -> u64 {
+    delta * NICE_0_WEIGHT as u64 / weight as u64
+}
+
+/// A task of the [`FairScheduler`].
+pub struct FairTask {
+    inner: TaskInner,
+    /// Virtual runtime in nanoseconds.
+    vruntime: AtomicU64,
+    /// Total CPU time charged to the task, in nanoseconds.
+    sum_exec_runtime: AtomicU64,
+}
+
+impl FairTask {
+    /// Creates a new [`FairTask`] from the inner task struct.
+    pub const fn new(inner: TaskInner) -> Self {
+        Self {
+            inner,
+            vruntime: AtomicU64::new(0),
+            sum_exec_runtime: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a reference to the inner task struct.
+    pub const fn inner(&self) -> &TaskInner {
+        &self.inner
+    }
+
+    /// Returns the current weight of the task.
+    pub fn weight(&self) -> u32 {
+        nice_to_weight(self.inner.nice())
+    }
+
+    /// Returns the virtual runtime of the task, in nanoseconds.
+    pub fn vruntime(&self) -> u64 {
+        self.vruntime.load(Ordering::Acquire)
+    }
+
+    /// Returns the total CPU time charged to the task, in nanoseconds.
+    pub fn sum_exec_runtime(&self) -> u64 {
+        self.sum_exec_runtime.load(Ordering::Acquire)
+    }
+
+    fn set_vruntime(&self, vruntime: u64) {
+        self.vruntime.store(vruntime, Ordering::Release);
+    }
+
+    /// Charges `delta` nanoseconds of CPU time.
+    fn charge(&self, delta: u64) {
+        self.sum_exec_runtime.fetch_add(delta, Ordering::AcqRel);
+        self.vruntime
+            .fetch_add(calc_vruntime(delta, self.weight()), Ordering::AcqRel);
+    }
+
+    /// Key of the task in the ready queue.
+    ///
+    /// The virtual runtime does not change while a task is queued, so the key
+    /// is stable.
+    fn key(&self) -> (u64, u64) {
+        (self.vruntime(), self.inner.id().as_u64())
+    }
+}
+
+impl Deref for FairTask {
+    type Target = TaskInner;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// Weighted fair scheduler, see the [module-level documentation](self).
+pub struct FairScheduler {
+    ready_queue: BTreeMap<(u64, u64), Arc<FairTask>>,
+    /// Monotonic lower bound of the virtual runtimes on this queue.
+    min_vruntime: u64,
+}
+
+impl FairScheduler {
+    /// Creates a new empty [`FairScheduler`].
+    pub const fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    /// Gets the name of the scheduler.
+    pub fn scheduler_name() -> &'static str {
+        "Weighted fair"
+    }
+
+    fn update_min_vruntime(&mut self, curr: u64) {
+        let leftmost = self
+            .ready_queue
+            .first_key_value()
+            .map_or(curr, |((vruntime, _), _)| *vruntime);
+        self.min_vruntime = self.min_vruntime.max(curr.min(leftmost));
+    }
+
+    fn enqueue(&mut self, task: Arc<FairTask>) {
+        // A task that slept (or comes from another CPU) is placed at the
+        // front of the queue but gains no credit for the time it was away.
+        // The upper bound keeps a task migrated from a busier CPU from
+        // starving here; it is never reached by tasks of this queue.
+        let max_lag = PREEMPT_GRANULARITY + calc_vruntime(TICK_NANOS, task.weight());
+        let vruntime = task
+            .vruntime()
+            .clamp(self.min_vruntime, self.min_vruntime + max_lag);
+        task.set_vruntime(vruntime);
+        self.ready_queue.insert(task.key(), task);
+    }
+}
+
+impl Default for FairScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseScheduler for FairScheduler {
+    type SchedItem = Arc<FairTask>;
+
+    fn init(&mut self) {}
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        self.enqueue(task);
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        self.ready_queue.remove(&task.key())
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        let (_, task) = self.ready_queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(task.vruntime());
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, _preempt: bool) {
+        self.enqueue(prev);
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        current.charge(TICK_NANOS);
+        let vruntime = current.vruntime();
+        self.update_min_vruntime(vruntime);
+        self.ready_queue
+            .first_key_value()
+            .is_some_and(|((leftmost, _), _)| vruntime > leftmost + PREEMPT_GRANULARITY)
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        if (MIN_NICE as isize..=MAX_NICE as isize).contains(&prio) {
+            task.set_nice(prio as i8);
+            true
+        } else {
+            false
+        }
+    }
+}
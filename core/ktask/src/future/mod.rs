@@ -106,6 +106,8 @@ impl From<Interrupted> for KError {
 pub async fn interruptible<F: IntoFuture>(f: F) -> Result<F::Output, Interrupted> {
     let mut f = pin!(f.into_future());
     let curr = current();
+    #[cfg(feature = "watchdog")]
+    let _wait = crate::task::WakeableWait::enter();
     poll_fn(|cx| {
         if curr.poll_interrupt(cx).is_ready() {
             return Poll::Ready(Err(Interrupted));
@@ -125,6 +125,8 @@ pub async fn sleep(duration: Duration) {
 pub async fn sleep_until(deadline: TimeValue) {
     let key = with_current(|r| r.add(deadline));
     if let Some(key) = key {
+        #[cfg(feature = "watchdog")]
+        let _wait = crate::task::WakeableWait::enter();
         TimerFuture(key).await;
     }
 }
@@ -91,6 +91,29 @@ impl GlobalTaskRegistry {
             f(weak);
         }
     }
+
+    /// Visits the occupied slots from `start` on until `f` returns `false`,
+    /// returning the slot to resume from, which is 0 after the last slot.
+    #[inline]
+    fn for_each_from(
+        &self,
+        cpu_id: usize,
+        start: usize,
+        mut f: impl FnMut(&WeakKtaskRef) -> bool,
+    ) -> usize {
+        for (i, slot) in self.slots[cpu_id].iter().enumerate().skip(start) {
+            let ptr = slot.load(Ordering::Acquire);
+            if ptr == 0 {
+                continue;
+            }
+            // Safety: ptr is either 0 or a valid Box<WeakKtaskRef>.
+            let weak = unsafe { &*(ptr as *const WeakKtaskRef) };
+            if !f(weak) {
+                return i + 1;
+            }
+        }
+        0
+    }
 }
 
 static GLOBAL_TASK_REGISTRY: GlobalTaskRegistry = GlobalTaskRegistry::new();
@@ -112,3 +135,14 @@ pub(crate) fn sweep_watchdog_tasks(cpu_id: usize) {
 pub(crate) fn for_each_watchdog_task(cpu_id: usize, f: impl FnMut(&WeakKtaskRef)) {
     GLOBAL_TASK_REGISTRY.for_each(cpu_id, f);
 }
+
+/// Iterate the given CPU's watchdog registry from slot `start` on, until `f`
+/// returns `false`. Returns the slot to resume from.
+#[inline]
+pub(crate) fn for_each_watchdog_task_from(
+    cpu_id: usize,
+    start: usize,
+    f: impl FnMut(&WeakKtaskRef) -> bool,
+) -> usize {
+    GLOBAL_TASK_REGISTRY.for_each_from(cpu_id, start, f)
+}
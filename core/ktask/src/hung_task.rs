@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Detection of tasks hung in uninterruptible waits.
+
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use khal::{
+    percpu::this_cpu_id,
+    time::{now_ticks, t2ns},
+};
+
+use crate::{KtaskRef, TaskInner, TaskState};
+
+/// Registry slot where the next scan of each CPU resumes.
+static SCAN_CURSORS: [AtomicUsize; platconfig::plat::CPU_NUM] =
+    [const { AtomicUsize::new(0) }; platconfig::plat::CPU_NUM];
+
+/// A task blocked in an uninterruptible wait for longer than the timeout.
+pub struct HungTask {
+    task: KtaskRef,
+    blocked_ns: u64,
+}
+
+impl HungTask {
+    /// Returns the hung task.
+    pub fn task(&self) -> &KtaskRef {
+        &self.task
+    }
+
+    /// Returns how long the task had been blocked when it was found.
+    pub fn blocked_ns(&self) -> u64 {
+        self.blocked_ns
+    }
+}
+
+/// Formats the report: the task, how long it has been blocked, what it waits
+/// on and its kernel stack.
+impl fmt::Display for HungTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let task: &TaskInner = &self.task;
+        writeln!(
+            f,
+            "{} blocked for {}.{:03}s",
+            task.id_name(),
+            self.blocked_ns / 1_000_000_000,
+            self.blocked_ns / 1_000_000 % 1000
+        )?;
+        if let Some((lock, _since)) = task.waiting_snapshot() {
+            writeln!(f, "  waiting on lock {lock:#x}")?;
+        }
+        let queue = task.waiting_queue();
+        if queue != 0 {
+            writeln!(f, "  parked on wait queue {queue:#x}")?;
+        }
+        for lock in task.held_locks_snapshot().into_iter().filter(|&l| l != 0) {
+            writeln!(f, "  holding lock {lock:#x}")?;
+        }
+        // The stack is only stable while the task stays blocked.
+        if task.state() == TaskState::Blocked {
+            write!(f, "{}", task.backtrace())
+        } else {
+            write!(f, "  (woke up before its stack was unwound)")
+        }
+    }
+}
+
+/// Returns the tick at which `task` blocked and for how long, if it has
+/// been blocked in an uninterruptible wait for more than `timeout_ns`.
+fn hung_for(task: &TaskInner, now: u64, timeout_ns: u64) -> Option<(u64, u64)> {
+    if task.is_hung_check_exempt() || !task.in_uninterruptible_wait() {
+        return None;
+    }
+    let since = task.last_switch();
+    if since == 0 {
+        return None;
+    }
+    let blocked_ns = t2ns(now.saturating_sub(since));
+    (blocked_ns > timeout_ns).then_some((since, blocked_ns))
+}
+
+/// Scans up to `batch` tasks created on the current CPU for ones blocked in
+/// an uninterruptible wait for more than `timeout_ns`, resuming where the
+/// previous scan on this CPU stopped.
+///
+/// A pass over `n` tasks thus takes `n / batch` scans. Each hung wait is
+/// returned once, however long it lasts.
+pub fn scan_hung_tasks(timeout_ns: u64, batch: usize) -> Vec<HungTask> {
+    let cpu_id = this_cpu_id();
+    let now = now_ticks();
+    let mut hung = Vec::new();
+    let mut budget = batch;
+
+    // The gc task frees registry slots, keep it from running meanwhile.
+    let _g = kspin::NoPreempt::new();
+    let cursor = SCAN_CURSORS[cpu_id].load(Ordering::Relaxed);
+    let next = crate::global_task_queue::for_each_watchdog_task_from(cpu_id, cursor, |weak| {
+        if let Some(task) = weak.upgrade()
+            && let Some((since, blocked_ns)) = hung_for(&task, now, timeout_ns)
+            && task.mark_hung_reported(since)
+        {
+            hung.push(HungTask { task, blocked_ns });
+        }
+        budget -= 1;
+        budget > 0
+    });
+    SCAN_CURSORS[cpu_id].store(next, Ordering::Relaxed);
+    hung
+}
@@ -8,12 +8,23 @@
 //!
 //! # Cargo Features
 //!
-//! - `preempt`: Enable preemptive scheduling.
+//! - `preempt`: Enable preemptive scheduling. Where tasks are preempted is
+//!   chosen at boot, see [`PreemptModel`].
+//! - `preempt-debug`: Keep the longest non-preemptible section observed, see
+//!   [`max_non_preempt_section`].
 //! - `sched-fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //! - `sched-rr`: Use the [Round-robin preemptive scheduler][2]. It also enables
 //!   `preempt` features if it is enabled.
 //! - `sched-cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   `preempt` features if it is enabled.
+//! - `sched-fair`: Use the in-tree weighted fair scheduler, which shares CPU
+//!   time in proportion to the weights of nice values. It also enables the
+//!   `preempt` features if it is enabled.
+//! - `ipi`: Preempt a remote CPU by IPI when a real-time task of higher
+//!   priority than its current task is woken up there.
+//!
+//! Whatever the scheduler, tasks of the real-time policies run before it, see
+//! [`SchedPolicy`].
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
@@ -30,12 +41,33 @@ extern crate alloc;
 #[macro_use]
 mod run_queue;
 mod api;
+mod balance;
+mod fair;
 #[cfg(feature = "watchdog")]
 mod global_task_queue;
+#[cfg(feature = "watchdog")]
+mod hung_task;
+mod preempt;
+mod rt;
 mod task;
 mod timers;
 mod wait_queue;
 
 pub mod future;
 
-pub use self::api::{sleep, sleep_until, yield_now, *};
+#[cfg(feature = "watchdog")]
+pub use self::hung_task::{HungTask, scan_hung_tasks};
+pub use self::{
+    api::{sleep, sleep_until, yield_now, *},
+    fair::{MAX_NICE, MIN_NICE, NICE_0_WEIGHT, nice_to_weight},
+    preempt::{
+        NonPreemptSection, PreemptModel, cond_resched, init_preempt_model, max_non_preempt_section,
+        need_resched, preempt_model, resched_if_needed, reset_max_non_preempt_section,
+        set_preempt_model,
+    },
+    rt::{
+        MAX_RT_PRIO, MIN_RT_PRIO, RtBandwidth, SchedPolicy, rr_timeslice, rt_bandwidth,
+        set_rr_timeslice, set_rt_bandwidth,
+    },
+    task::TASK_COMM_LEN,
+};
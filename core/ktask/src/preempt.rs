@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Preemption points and the preemption model.
+//!
+//! With the `preempt` feature, the scheduler tick and wakeups mark the
+//! current task as needing rescheduling. The preemption model decides where
+//! the mark is honored:
+//!
+//! - [`PreemptModel::None`]: only when the task blocks, yields or returns to
+//!   user space.
+//! - [`PreemptModel::Voluntary`]: also at the preemption points long kernel
+//!   loops pass, see [`cond_resched`].
+//! - [`PreemptModel::Full`]: also whenever preemption is enabled again, e.g.
+//!   when the last spinlock held is released or an interrupt returns.
+//!
+//! The model is picked with `preempt=none|voluntary|full` on the kernel
+//! command line. Without the `preempt` feature there is nothing to honor and
+//! the model is always `None`.
+//!
+//! With the `preempt-debug` feature, the longest time a task went on running
+//! after being marked is kept for [`max_non_preempt_section`].
+
+use core::{
+    fmt,
+    panic::Location,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::TaskId;
+
+/// Where the kernel may preempt the current task.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreemptModel {
+    /// Tasks are only switched when they block, yield or return to user
+    /// space.
+    None      = 0,
+    /// Tasks are also switched at the preemption points, see [`cond_resched`].
+    Voluntary = 1,
+    /// Tasks are also switched whenever preemption is enabled again.
+    Full      = 2,
+}
+
+impl PreemptModel {
+    /// Returns the name used on the command line.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            PreemptModel::None => "none",
+            PreemptModel::Voluntary => "voluntary",
+            PreemptModel::Full => "full",
+        }
+    }
+
+    /// Picks the model from a kernel command line, or returns `None` if
+    /// `preempt=` is absent or names no model.
+    pub fn from_cmdline(cmdline: &str) -> Option<PreemptModel> {
+        let value = cmdline
+            .split_ascii_whitespace()
+            .filter_map(|arg| arg.strip_prefix("preempt="))
+            .last()?;
+        match value {
+            "none" => Some(PreemptModel::None),
+            "voluntary" => Some(PreemptModel::Voluntary),
+            "full" => Some(PreemptModel::Full),
+            other => {
+                warn!("unknown preemption model {other:?}");
+                None
+            }
+        }
+    }
+
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PreemptModel::Voluntary,
+            2 => PreemptModel::Full,
+            _ => PreemptModel::None,
+        }
+    }
+}
+
+impl fmt::Display for PreemptModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The default keeps what the `preempt` feature always did.
+#[cfg(feature = "preempt")]
+const DEFAULT_MODEL: PreemptModel = PreemptModel::Full;
+#[cfg(not(feature = "preempt"))]
+const DEFAULT_MODEL: PreemptModel = PreemptModel::None;
+
+static PREEMPT_MODEL: AtomicU8 = AtomicU8::new(DEFAULT_MODEL as u8);
+
+/// Returns the preemption model in effect.
+#[inline]
+pub fn preempt_model() -> PreemptModel {
+    PreemptModel::from_u8(PREEMPT_MODEL.load(Ordering::Relaxed))
+}
+
+/// Sets the preemption model, and returns the model in effect.
+///
+/// Without the `preempt` feature, only [`PreemptModel::None`] is available.
+pub fn set_preempt_model(model: PreemptModel) -> PreemptModel {
+    if !cfg!(feature = "preempt") && model != PreemptModel::None {
+        warn!("preemption model {model} needs the `preempt` feature");
+        return preempt_model();
+    }
+    PREEMPT_MODEL.store(model as u8, Ordering::Relaxed);
+    model
+}
+
+/// Sets the preemption model selected by `preempt=` on the kernel command
+/// line, if any.
+pub fn init_preempt_model(cmdline: &str) {
+    if let Some(model) = PreemptModel::from_cmdline(cmdline) {
+        set_preempt_model(model);
+    }
+    info!("  preemption model: {}", preempt_model());
+}
+
+/// Returns whether the current task has been asked to give way to another
+/// task.
+#[inline]
+pub fn need_resched() -> bool {
+    #[cfg(feature = "preempt")]
+    if let Some(curr) = crate::current_may_uninit() {
+        return curr.need_resched();
+    }
+    false
+}
+
+/// A preemption point: gives way to a task waiting for the CPU if the
+/// current task has been asked to, and returns whether it did.
+///
+/// Long kernel loops call this once per unit of work, with no spinlock held;
+/// holding one, it does nothing. The point is honored under the
+/// [`Voluntary`](PreemptModel::Voluntary) and [`Full`](PreemptModel::Full)
+/// models.
+#[inline]
+#[track_caller]
+pub fn cond_resched() -> bool {
+    #[cfg(feature = "preempt")]
+    if preempt_model() != PreemptModel::None
+        && let Some(curr) = crate::current_may_uninit()
+    {
+        #[cfg(feature = "preempt-debug")]
+        {
+            let point = Location::caller();
+            if curr.need_resched() && curr.can_preempt(0) {
+                curr.preempt_trace().finish(curr.id(), Some(point));
+            }
+            curr.preempt_trace().pass(point);
+        }
+        drop(curr);
+        return crate::TaskInner::current_check_preempt_pending();
+    }
+    false
+}
+
+/// Gives way to a task waiting for the CPU if the current task has been
+/// asked to, whatever the preemption model, and returns whether it did.
+///
+/// Called where the kernel hands the CPU back to user space.
+#[inline]
+pub fn resched_if_needed() -> bool {
+    #[cfg(feature = "preempt")]
+    if crate::current_may_uninit().is_some() {
+        return crate::TaskInner::current_check_preempt_pending();
+    }
+    false
+}
+
+/// The longest a task went on running after being asked to give way.
+#[derive(Debug, Clone, Copy)]
+pub struct NonPreemptSection {
+    /// How long the task went on running, in nanoseconds.
+    pub duration_ns: u64,
+    /// The task.
+    pub task: TaskId,
+    /// The last preemption point the task passed before it was asked.
+    pub since: Option<&'static Location<'static>>,
+    /// The preemption point the task gave way at, or `None` if it gave way
+    /// when it blocked, yielded, enabled preemption or returned to user
+    /// space.
+    pub until: Option<&'static Location<'static>>,
+}
+
+impl fmt::Display for NonPreemptSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:03}ms in task {}",
+            self.duration_ns / 1_000_000,
+            self.duration_ns / 1_000 % 1_000,
+            self.task.as_u64()
+        )?;
+        if let Some(since) = self.since {
+            write!(f, " since {since}")?;
+        }
+        match self.until {
+            Some(until) => write!(f, " until {until}"),
+            None => f.write_str(" until the task switched"),
+        }
+    }
+}
+
+/// Returns the longest non-preemptible section observed since boot or the
+/// last [`reset_max_non_preempt_section`].
+///
+/// Always `None` without the `preempt-debug` feature.
+pub fn max_non_preempt_section() -> Option<NonPreemptSection> {
+    #[cfg(feature = "preempt-debug")]
+    return *trace::MAX_SECTION.lock();
+    #[cfg(not(feature = "preempt-debug"))]
+    None
+}
+
+/// Forgets the longest non-preemptible section observed so far.
+pub fn reset_max_non_preempt_section() {
+    #[cfg(feature = "preempt-debug")]
+    {
+        *trace::MAX_SECTION.lock() = None;
+    }
+}
+
+#[cfg(feature = "preempt-debug")]
+pub(crate) use trace::PreemptTrace;
+
+#[cfg(feature = "preempt-debug")]
+mod trace {
+    use core::{
+        panic::Location,
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    };
+
+    use kspin::SpinNoIrq;
+
+    use super::NonPreemptSection;
+    use crate::TaskId;
+
+    pub(super) static MAX_SECTION: SpinNoIrq<Option<NonPreemptSection>> = SpinNoIrq::new(None);
+
+    fn location(ptr: usize) -> Option<&'static Location<'static>> {
+        // Safety: non-zero values are stored from `&'static Location`s.
+        (ptr != 0).then(|| unsafe { &*(ptr as *const Location<'static>) })
+    }
+
+    /// Per-task record of the pending reschedule, timed with the reference
+    /// clock.
+    pub(crate) struct PreemptTrace {
+        /// Reference clock reading when the task was asked to give way, or
+        /// zero.
+        requested: AtomicU64,
+        /// `last_point` when the task was asked to give way.
+        requested_after: AtomicUsize,
+        /// The last preemption point the task passed.
+        last_point: AtomicUsize,
+    }
+
+    impl PreemptTrace {
+        pub(crate) const fn new() -> Self {
+            Self {
+                requested: AtomicU64::new(0),
+                requested_after: AtomicUsize::new(0),
+                last_point: AtomicUsize::new(0),
+            }
+        }
+
+        /// Notes that the task has been asked to give way, unless it
+        /// already was.
+        pub(crate) fn request(&self) {
+            let now = khal::time::now_ticks().max(1);
+            if self
+                .requested
+                .compare_exchange(0, now, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                let point = self.last_point.load(Ordering::Relaxed);
+                self.requested_after.store(point, Ordering::Relaxed);
+            }
+        }
+
+        /// Forgets the pending request without measuring it.
+        pub(crate) fn cancel(&self) {
+            self.requested.store(0, Ordering::Release);
+        }
+
+        /// Notes that the task passed the preemption point `point`.
+        pub(crate) fn pass(&self, point: &'static Location<'static>) {
+            self.last_point
+                .store(point as *const _ as usize, Ordering::Relaxed);
+        }
+
+        /// Measures the pending request, if any, as the task `task` gives
+        /// way at `until`.
+        pub(crate) fn finish(&self, task: TaskId, until: Option<&'static Location<'static>>) {
+            let requested = self.requested.swap(0, Ordering::AcqRel);
+            if requested == 0 {
+                return;
+            }
+            let ticks = khal::time::now_ticks().saturating_sub(requested);
+            let duration_ns = khal::time::t2ns(ticks);
+            let mut max = MAX_SECTION.lock();
+            if max.is_none_or(|max| duration_ns > max.duration_ns) {
+                *max = Some(NonPreemptSection {
+                    duration_ns,
+                    task,
+                    since: location(self.requested_after.load(Ordering::Relaxed)),
+                    until,
+                });
+            }
+        }
+    }
+}
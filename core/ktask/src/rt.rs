@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Real-time scheduling class.
+//!
+//! Tasks of the [`Fifo`](SchedPolicy::Fifo) and
+//! [`RoundRobin`](SchedPolicy::RoundRobin) policies have a priority from
+//! [`MIN_RT_PRIO`] to [`MAX_RT_PRIO`] and always run before tasks of the
+//! normal policy, which are left to the scheduler chosen by cargo features.
+//! The ready real-time task of the highest priority runs: a FIFO task until it
+//! blocks or yields, a round-robin task for [`rr_timeslice`], after which it
+//! goes behind the other ready tasks of its priority.
+//!
+//! Real-time tasks may only use `runtime` out of every `period` of
+//! [`rt_bandwidth`] on a CPU. Once they have, the CPU is throttled: ready
+//! normal tasks run first until the period ends, so that a runaway real-time
+//! task cannot lock up the system.
+
+use alloc::collections::VecDeque;
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axsched::BaseScheduler;
+
+use crate::KtaskRef;
+
+/// Lowest real-time priority.
+pub const MIN_RT_PRIO: u8 = 1;
+/// Highest real-time priority.
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Length of a timer tick in nanoseconds.
+pub(crate) const TICK_NANOS: u64 = khal::time::NANOS_PER_SEC / platconfig::TICKS_PER_SEC as u64;
+
+/// Scheduling policy of a task, numbered as the Linux `SCHED_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SchedPolicy {
+    /// Time-sharing by nice value (`SCHED_OTHER`).
+    Normal     = 0,
+    /// First in, first out real-time (`SCHED_FIFO`).
+    Fifo       = 1,
+    /// Round-robin real-time (`SCHED_RR`).
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    /// Returns whether this is a real-time policy.
+    pub const fn is_rt(self) -> bool {
+        !matches!(self, Self::Normal)
+    }
+
+    pub(crate) const fn from_u8(policy: u8) -> Self {
+        match policy {
+            1 => Self::Fifo,
+            2 => Self::RoundRobin,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// Time slice of round-robin tasks in nanoseconds, 100 ms as in Linux.
+static RR_TIMESLICE_NANOS: AtomicU64 = AtomicU64::new(100_000_000);
+/// Length of a bandwidth period in nanoseconds.
+static RT_PERIOD_NANOS: AtomicU64 = AtomicU64::new(1_000_000_000);
+/// Real-time runtime allowed per period in nanoseconds, `u64::MAX` if
+/// unlimited.
+static RT_RUNTIME_NANOS: AtomicU64 = AtomicU64::new(950_000_000);
+/// Whether throttling has been reported, see [`RtScheduler::charge`].
+static THROTTLE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the time slice of round-robin tasks.
+pub fn rr_timeslice() -> Duration {
+    Duration::from_nanos(RR_TIMESLICE_NANOS.load(Ordering::Relaxed))
+}
+
+/// Sets the time slice of round-robin tasks, returns `false` if it is shorter
+/// than a timer tick.
+///
+/// It applies to slices started from now on.
+pub fn set_rr_timeslice(slice: Duration) -> bool {
+    let nanos = slice.as_nanos();
+    if nanos < TICK_NANOS as u128 || nanos > u64::MAX as u128 {
+        return false;
+    }
+    RR_TIMESLICE_NANOS.store(nanos as u64, Ordering::Relaxed);
+    true
+}
+
+/// CPU time real-time tasks may use on each CPU, see the [module
+/// documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtBandwidth {
+    /// Length of a period.
+    pub period: Duration,
+    /// Time real-time tasks may run per period, [`None`] if unlimited.
+    pub runtime: Option<Duration>,
+}
+
+/// Returns the real-time bandwidth, 950 ms every second by default.
+pub fn rt_bandwidth() -> RtBandwidth {
+    let runtime = RT_RUNTIME_NANOS.load(Ordering::Relaxed);
+    RtBandwidth {
+        period: Duration::from_nanos(RT_PERIOD_NANOS.load(Ordering::Relaxed)),
+        runtime: (runtime != u64::MAX).then(|| Duration::from_nanos(runtime)),
+    }
+}
+
+/// Sets the real-time bandwidth, returns `false` if the period is shorter
+/// than a timer tick or the runtime is longer than the period.
+pub fn set_rt_bandwidth(bandwidth: RtBandwidth) -> bool {
+    let period = bandwidth.period.as_nanos();
+    if period < TICK_NANOS as u128 || period >= u64::MAX as u128 {
+        return false;
+    }
+    let runtime = match bandwidth.runtime {
+        Some(runtime) if runtime > bandwidth.period => return false,
+        Some(runtime) => runtime.as_nanos() as u64,
+        None => u64::MAX,
+    };
+    RT_PERIOD_NANOS.store(period as u64, Ordering::Relaxed);
+    RT_RUNTIME_NANOS.store(runtime, Ordering::Relaxed);
+    true
+}
+
+/// A scheduler running real-time tasks before the tasks of the normal
+/// scheduler `S`.
+pub(crate) struct RtScheduler<S> {
+    /// Ready real-time tasks of priority `p` at index `p - 1`.
+    queues: [VecDeque<KtaskRef>; MAX_RT_PRIO as usize],
+    /// Bit `p - 1` is set if the queue of priority `p` is not empty.
+    bitmap: u128,
+    /// Scheduler of the normal tasks.
+    normal: S,
+    /// Number of ready tasks in `normal`.
+    nr_normal: usize,
+    /// Start of the current bandwidth period, in nanoseconds.
+    period_start: u64,
+    /// CPU time real-time tasks used in the current period, in nanoseconds.
+    rt_time: u64,
+    /// Whether real-time tasks used up the runtime of the current period.
+    throttled: bool,
+}
+
+impl<S: BaseScheduler<SchedItem = KtaskRef>> RtScheduler<S> {
+    /// Creates a new empty [`RtScheduler`] over the normal scheduler `normal`.
+    pub const fn new(normal: S) -> Self {
+        Self {
+            queues: [const { VecDeque::new() }; MAX_RT_PRIO as usize],
+            bitmap: 0,
+            normal,
+            nr_normal: 0,
+            period_start: 0,
+            rt_time: 0,
+            throttled: false,
+        }
+    }
+
+    /// Returns the highest priority of the ready real-time tasks, 0 if there
+    /// are none.
+    fn highest_ready_prio(&self) -> u8 {
+        (u128::BITS - self.bitmap.leading_zeros()) as u8
+    }
+
+    fn enqueue_rt(&mut self, task: KtaskRef, head: bool) {
+        let index = task.rt_priority() as usize - 1;
+        if head {
+            self.queues[index].push_front(task);
+        } else {
+            self.queues[index].push_back(task);
+        }
+        self.bitmap |= 1 << index;
+    }
+
+    fn dequeue_rt(&mut self) -> Option<KtaskRef> {
+        let index = self.highest_ready_prio().checked_sub(1)? as usize;
+        let task = self.queues[index].pop_front();
+        if self.queues[index].is_empty() {
+            self.bitmap &= !(1 << index);
+        }
+        task
+    }
+
+    /// Starts a new bandwidth period if the current one is over at `now`.
+    fn refresh(&mut self, now: u64) {
+        let period = RT_PERIOD_NANOS.load(Ordering::Relaxed);
+        if now.saturating_sub(self.period_start) < period {
+            return;
+        }
+        self.period_start = now - (now - self.period_start) % period;
+        self.rt_time = 0;
+        if self.throttled {
+            self.throttled = false;
+            debug!("sched: RT throttling deactivated");
+        }
+    }
+
+    /// Charges a tick to the real-time tasks, throttling them once they
+    /// used up the runtime of the period.
+    fn charge(&mut self) {
+        self.rt_time += TICK_NANOS;
+        if !self.throttled && self.rt_time >= RT_RUNTIME_NANOS.load(Ordering::Relaxed) {
+            self.throttled = true;
+            if !THROTTLE_WARNED.swap(true, Ordering::Relaxed) {
+                warn!("sched: RT throttling activated");
+            } else {
+                debug!("sched: RT throttling activated");
+            }
+        }
+    }
+
+    /// Takes the next task to run at monotonic time `now`, see
+    /// [`BaseScheduler::pick_next_task`].
+    pub(crate) fn pick_next_task_at(&mut self, now: u64) -> Option<KtaskRef> {
+        self.refresh(now);
+        if !self.throttled
+            && let Some(task) = self.dequeue_rt()
+        {
+            return Some(task);
+        }
+        // Throttled real-time tasks still run when nothing else would.
+        match self.normal.pick_next_task() {
+            Some(task) => {
+                self.nr_normal -= 1;
+                Some(task)
+            }
+            None => self.dequeue_rt(),
+        }
+    }
+
+    /// Charges a tick to `current` at monotonic time `now`, see
+    /// [`BaseScheduler::task_tick`].
+    pub(crate) fn task_tick_at(&mut self, current: &KtaskRef, now: u64) -> bool {
+        self.refresh(now);
+        if !current.is_rt() {
+            let resched = self.normal.task_tick(current);
+            return resched || (!self.throttled && self.bitmap != 0);
+        }
+
+        self.charge();
+        if self.throttled && self.nr_normal > 0 {
+            return true;
+        }
+        let prio = current.rt_priority();
+        if self.highest_ready_prio() > prio {
+            return true;
+        }
+        if current.sched_policy() == SchedPolicy::RoundRobin && current.consume_rr_slice(TICK_NANOS)
+        {
+            // Rotates only among tasks of the same priority.
+            if self.queues[prio as usize - 1].is_empty() {
+                current.refill_rr_slice();
+            } else {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<S: BaseScheduler<SchedItem = KtaskRef>> BaseScheduler for RtScheduler<S> {
+    type SchedItem = KtaskRef;
+
+    fn init(&mut self) {
+        self.normal.init();
+    }
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        if task.is_rt() {
+            self.enqueue_rt(task, false);
+        } else {
+            self.normal.add_task(task);
+            self.nr_normal += 1;
+        }
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        if !task.is_rt() {
+            let task = self.normal.remove_task(task)?;
+            self.nr_normal -= 1;
+            return Some(task);
+        }
+        let index = task.rt_priority() as usize - 1;
+        let queue = &mut self.queues[index];
+        let task = queue.remove(queue.iter().position(|t| KtaskRef::ptr_eq(t, task))?);
+        if queue.is_empty() {
+            self.bitmap &= !(1 << index);
+        }
+        task
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        self.pick_next_task_at(khal::time::monotonic_time_nanos())
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        if !prev.is_rt() {
+            self.normal.put_prev_task(prev, preempt);
+            self.nr_normal += 1;
+            return;
+        }
+        // A preempted task keeps its place at the head of its priority,
+        // unless its round-robin slice is over.
+        let expired = prev.sched_policy() == SchedPolicy::RoundRobin && prev.rr_slice_expired();
+        if expired {
+            prev.refill_rr_slice();
+        }
+        self.enqueue_rt(prev, preempt && !expired);
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        self.task_tick_at(current, khal::time::monotonic_time_nanos())
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        self.normal.set_priority(task, prio)
+    }
+}
@@ -3,6 +3,10 @@
 // See LICENSES for license details.
 
 //! Per-CPU run queue implementation and scheduling helpers.
+//!
+//! Each CPU schedules from its own run queue. Tasks move between queues when
+//! they are woken up, when an idle CPU steals them and when the periodic load
+//! balancer pulls them, following the policy of [`balance`](crate::balance).
 
 #[cfg(feature = "smp")]
 use alloc::sync::Weak;
@@ -10,6 +14,7 @@ use alloc::{collections::VecDeque, sync::Arc};
 use core::{
     future::poll_fn,
     mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 
@@ -19,8 +24,10 @@ use khal::percpu::this_cpu_id;
 use kspin::{BaseGuard, SpinNoIrqGuard, SpinRaw};
 use lazyinit::LazyInit;
 
+#[cfg(feature = "smp")]
+use crate::balance;
 use crate::{
-    KCpuMask, KtaskRef, Scheduler, TaskInner,
+    CpuSchedStat, KCpuMask, KtaskRef, NormalScheduler, SchedPolicy, Scheduler, TaskInner,
     future::block_on,
     task::{CurrentTask, TaskState},
 };
@@ -62,6 +69,14 @@ static mut RUN_QUEUES: [MaybeUninit<&'static mut RunQueue>; platconfig::plat::CP
 #[allow(clippy::declare_interior_mutable_const)] // It's ok because it's used only for initialization `RUN_QUEUES`.
 const ARRAY_REPEAT_VALUE: MaybeUninit<&'static mut RunQueue> = MaybeUninit::uninit();
 
+/// Whether the entry of each CPU in [`RUN_QUEUES`] is initialized.
+static ONLINE: [AtomicBool; platconfig::plat::CPU_NUM] =
+    [const { AtomicBool::new(false) }; platconfig::plat::CPU_NUM];
+
+/// Most tasks looked at in a run queue for one to steal.
+#[cfg(feature = "smp")]
+const STEAL_SCAN: usize = 4;
+
 /// Returns a reference to the current run queue in [`CurrentRunQueueRef`].
 ///
 /// ## Safety
@@ -85,38 +100,51 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
     }
 }
 
-/// Selects the run queue index based on a CPU set bitmap and load balancing.
-///
-/// This function filters the available run queues based on the provided `cpumask` and
-/// selects the run queue index for the next task. The selection is based on a round-robin algorithm.
-///
-/// ## Arguments
-///
-/// * `cpumask` - A bitmap representing the CPUs that are eligible for task execution.
+/// Selects the run queue index for `task`, see [`balance::select_cpu`].
 ///
-/// ## Returns
-///
-/// The index (cpu_id) of the selected run queue.
+/// The task is treated as new if it never ran, and as cache-hot if it was
+/// switched out less than [`balance::CACHE_HOT_NANOS`] ago.
 ///
 /// ## Panics
 ///
-/// This function will panic if `cpu_mask` is empty, indicating that there are no available CPUs for task execution.
+/// This function will panic if the task may not run on any online CPU.
 #[cfg(feature = "smp")]
-// The modulo operation is safe here because `platconfig::plat::CPU_NUM` is always greater than 1 with "smp" enabled.
-#[allow(clippy::modulo_one)]
 #[inline]
-fn select_run_queue_index(cpumask: KCpuMask) -> usize {
-    use core::sync::atomic::{AtomicUsize, Ordering};
-    static RUN_QUEUE_INDEX: AtomicUsize = AtomicUsize::new(0);
+fn select_run_queue_index(task: &KtaskRef) -> usize {
+    let last_ran = task.last_ran();
+    let prev = (last_ran != 0).then(|| task.cpu_id() as usize);
+    let cache_hot =
+        khal::time::monotonic_time_nanos().saturating_sub(last_ran) < balance::CACHE_HOT_NANOS;
+    let cpumask = task.cpumask();
+    balance::select_cpu(
+        |cpu| cpumask.get(cpu),
+        prev,
+        this_cpu_id(),
+        cache_hot,
+        &cpu_loads(RunQueue::load),
+    )
+}
 
-    assert!(!cpumask.is_empty(), "No available CPU for task execution");
+/// Returns `load` of the run queue of each CPU, `None` for CPUs that are not
+/// online.
+#[cfg(feature = "smp")]
+fn cpu_loads(load: fn(&RunQueue) -> usize) -> [Option<usize>; platconfig::plat::CPU_NUM] {
+    core::array::from_fn(|index| online_run_queue(index).map(load))
+}
 
-    // Round-robin selection of the run queue index.
-    loop {
-        let index = RUN_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % platconfig::plat::CPU_NUM;
-        if cpumask.get(index) {
-            return index;
-        }
+/// Returns whether CPU `index` is online.
+pub(crate) fn is_online(index: usize) -> bool {
+    ONLINE
+        .get(index)
+        .is_some_and(|online| online.load(Ordering::Acquire))
+}
+
+/// Returns the run queue of CPU `index`, or `None` if it is not online.
+fn online_run_queue(index: usize) -> Option<&'static RunQueue> {
+    if is_online(index) {
+        Some(get_run_queue(index))
+    } else {
+        None
     }
 }
 
@@ -136,7 +164,6 @@ fn select_run_queue_index(cpumask: KCpuMask) -> usize {
 /// ## Panics
 ///
 /// This function will panic if the index is out of bounds.
-#[cfg(feature = "smp")]
 #[inline]
 fn get_run_queue(index: usize) -> &'static mut RunQueue {
     unsafe { RUN_QUEUES[index].assume_init_mut() }
@@ -145,7 +172,8 @@ fn get_run_queue(index: usize) -> &'static mut RunQueue {
 /// Selects the appropriate run queue for the provided task.
 ///
 /// * In a single-core system, this function always returns a reference to the global run queue.
-/// * In a multi-core system, this function selects the run queue based on the task's CPU affinity and load balance.
+/// * In a multi-core system, this function selects the run queue based on the task's CPU affinity,
+///   the CPU it last ran on and the load of each CPU, see [`balance`](crate::balance).
 ///
 /// ## Arguments
 ///
@@ -154,11 +182,6 @@ fn get_run_queue(index: usize) -> &'static mut RunQueue {
 /// ## Returns
 ///
 /// * [`KRunQueueRef`] - a static reference to the selected [`RunQueue`] (current or remote).
-///
-/// ## TODO
-///
-/// 1. Implement better load balancing across CPUs for more efficient task distribution.
-/// 2. Use a more generic load balancing algorithm that can be customized or replaced.
 #[inline]
 pub(crate) fn select_run_queue<G: BaseGuard>(task: &KtaskRef) -> KRunQueueRef<'static, G> {
     let irq_state = G::acquire();
@@ -175,7 +198,7 @@ pub(crate) fn select_run_queue<G: BaseGuard>(task: &KtaskRef) -> KRunQueueRef<'s
     #[cfg(feature = "smp")]
     {
         // When SMP is enabled, select the run queue based on the task's CPU affinity and load balance.
-        let index = select_run_queue_index(task.cpumask());
+        let index = select_run_queue_index(task);
         KRunQueueRef {
             inner: get_run_queue(index),
             state: irq_state,
@@ -192,6 +215,25 @@ pub(crate) struct RunQueue {
     /// Since irq and preempt are preserved by the kernel guard hold by `KRunQueueRef`,
     /// we just use a simple raw spin lock here.
     scheduler: SpinRaw<Scheduler>,
+    /// Number of ready tasks in `scheduler`, only changed with it locked.
+    nr_ready: AtomicUsize,
+    /// Real-time priority of the running task, 0 if it is not real-time.
+    curr_rt_prio: AtomicU8,
+    /// Whether a task other than the idle task is running on this CPU.
+    #[cfg(feature = "smp")]
+    busy: AtomicBool,
+    /// Timer ticks until the next periodic load balancing pass.
+    #[cfg(feature = "smp")]
+    balance_countdown: u64,
+    stat: RunQueueStat,
+}
+
+/// Counters of a [`RunQueue`], see [`CpuSchedStat`].
+#[derive(Default)]
+struct RunQueueStat {
+    switches: AtomicU64,
+    steals: AtomicU64,
+    migrations: AtomicU64,
 }
 
 /// A reference to the run queue with specific guard.
@@ -248,7 +290,14 @@ impl<G: BaseGuard> KRunQueueRef<'_, G> {
             let _g = kspin::NoPreempt::new();
             crate::global_task_queue::record_task_for_watchdog(&task);
         }
-        self.inner.scheduler.lock().add_task(task);
+        #[cfg(feature = "smp")]
+        task.set_cpu_id(self.inner.cpu_id as _);
+        let rt_priority = task.rt_priority();
+        let mut scheduler = self.inner.scheduler.lock();
+        scheduler.add_task(task);
+        self.inner.nr_ready.fetch_add(1, Ordering::Relaxed);
+        drop(scheduler);
+        self.inner.check_preempt_rt(rt_priority);
     }
 
     /// Unblock one task by inserting it into the run queue.
@@ -257,6 +306,8 @@ impl<G: BaseGuard> KRunQueueRef<'_, G> {
     /// which means the task is already unblocked by other cores.
     pub fn unblock_task(&mut self, task: KtaskRef, resched: bool) {
         let task_id_name = task.id_name();
+        let tid = task.id().as_u64();
+        let rt_priority = task.rt_priority();
         // Try to change the state of the task from `Blocked` to `Ready`,
         // if successful, the task will be put into this run queue,
         // otherwise, the task is already unblocked by other cores.
@@ -269,12 +320,20 @@ impl<G: BaseGuard> KRunQueueRef<'_, G> {
             // Since now, the task to be unblocked is in the `Ready` state.
             let cpu_id = self.inner.cpu_id;
             debug!("task unblock: {task_id_name} on run_queue {cpu_id}");
+            flightrec::record(
+                crate::current_may_uninit().map_or(0, |curr| curr.id().as_u64()),
+                flightrec::Event::SchedWakeup {
+                    tid,
+                    cpu: cpu_id as u32,
+                },
+            );
             // Note: when the task is unblocked on another CPU's run queue,
             // we just ingiore the `resched` flag.
             if resched && cpu_id == this_cpu_id() {
                 #[cfg(feature = "preempt")]
                 crate::current().set_preempt_pending(true);
             }
+            self.inner.check_preempt_rt(rt_priority);
         }
     }
 }
@@ -287,6 +346,20 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+        // Its affinity changed while it ran, see `move_off_current`.
+        #[cfg(all(feature = "smp", feature = "preempt"))]
+        if !curr.is_idle() && !curr.cpumask().get(self.inner.cpu_id) {
+            curr.set_preempt_pending(true);
+        }
+
+        #[cfg(feature = "smp")]
+        {
+            self.inner.balance_countdown -= 1;
+            if self.inner.balance_countdown == 0 {
+                self.inner.balance_countdown = balance::BALANCE_INTERVAL_TICKS;
+                self.inner.load_balance();
+            }
+        }
     }
 
     /// Yield the current task and reschedule.
@@ -296,6 +369,10 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         let curr = &self.current_task;
         trace!("task yield: {}", curr.id_name());
         assert!(curr.is_running());
+        #[cfg(feature = "smp")]
+        if self.move_off_current() {
+            return;
+        }
 
         self.inner
             .put_task_with_state(curr.clone(), TaskState::Running, false);
@@ -324,6 +401,26 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         self.inner.switch_to(crate::current(), migration_task);
     }
 
+    /// Moves the current task to another run queue if its affinity no longer
+    /// allows this CPU, see [`crate::set_affinity`].
+    ///
+    /// Returns `true` once the task runs again, on a CPU of its mask.
+    #[cfg(feature = "smp")]
+    fn move_off_current(&mut self) -> bool {
+        let curr = &self.current_task;
+        if curr.is_idle() || curr.cpumask().get(self.inner.cpu_id) {
+            return false;
+        }
+        debug!(
+            "task {} may no longer run on CPU {}",
+            curr.id_name(),
+            self.inner.cpu_id
+        );
+        let migration_task = migration_task(curr.clone());
+        self.migrate_current(migration_task);
+        true
+    }
+
     /// Preempts the current task and reschedules.
     /// This function is used to preempt the current task and reschedule
     /// to next task on current run queue.
@@ -352,6 +449,10 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
             can_preempt
         );
         if can_preempt {
+            #[cfg(feature = "smp")]
+            if self.move_off_current() {
+                return;
+            }
             self.inner
                 .put_task_with_state(curr.clone(), TaskState::Running, true);
             self.inner.resched();
@@ -444,15 +545,171 @@ impl RunQueue {
         .into_arc();
         // gc task should be pinned to the current CPU.
         gc_task.set_cpumask(KCpuMask::one_shot(cpu_id));
+        // gc task sleeps until some task exits, which may never happen.
+        gc_task.set_hung_check_exempt(true);
 
-        let mut scheduler = Scheduler::new();
+        let mut scheduler = Scheduler::new(NormalScheduler::new());
         scheduler.add_task(gc_task);
         Self {
             cpu_id,
             scheduler: SpinRaw::new(scheduler),
+            nr_ready: AtomicUsize::new(1),
+            curr_rt_prio: AtomicU8::new(0),
+            #[cfg(feature = "smp")]
+            busy: AtomicBool::new(false),
+            // Staggered, so that the CPUs do not all balance on the same tick.
+            #[cfg(feature = "smp")]
+            balance_countdown: balance::BALANCE_INTERVAL_TICKS + cpu_id as u64,
+            stat: RunQueueStat::default(),
+        }
+    }
+
+    /// Makes this CPU reschedule if a task of real-time priority
+    /// `rt_priority` was just queued here and outranks the running task.
+    fn check_preempt_rt(&self, rt_priority: u8) {
+        if rt_priority > self.curr_rt_prio.load(Ordering::Relaxed) {
+            self.resched_cpu();
+        }
+    }
+
+    /// Makes this CPU reschedule at its next preemption point.
+    ///
+    /// A remote CPU is only told by IPI, without it the change waits for its
+    /// next timer tick.
+    fn resched_cpu(&self) {
+        #[cfg(feature = "preempt")]
+        if self.cpu_id == this_cpu_id() {
+            crate::current().set_preempt_pending(true);
+        } else {
+            #[cfg(all(feature = "smp", feature = "ipi"))]
+            let _ = kipi::run_on_cpu(self.cpu_id, || crate::current().set_preempt_pending(true));
+        }
+    }
+
+    /// Returns the number of ready tasks in this run queue.
+    fn nr_ready(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of tasks that want this CPU: the ready ones, and
+    /// the running one unless it is the idle task.
+    #[cfg(feature = "smp")]
+    fn load(&self) -> usize {
+        self.nr_ready() + self.busy.load(Ordering::Relaxed) as usize
+    }
+
+    /// Puts `task` into the scheduler of this run queue.
+    ///
+    /// If `preempt`, keep its time slice, otherwise reset it.
+    fn put_task(&self, task: KtaskRef, preempt: bool) {
+        let mut scheduler = self.scheduler.lock();
+        scheduler.put_prev_task(task, preempt);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the next task to run out of the scheduler of this run queue.
+    fn pick_next_task(&self) -> Option<KtaskRef> {
+        let mut scheduler = self.scheduler.lock();
+        let task = scheduler.pick_next_task()?;
+        self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        Some(task)
+    }
+
+    /// Takes the next task to run on this CPU.
+    ///
+    /// Ready tasks whose affinity no longer allows this CPU are passed on to
+    /// a run queue of their mask on the way.
+    #[cfg(feature = "smp")]
+    fn pick_allowed_task(&self) -> Option<KtaskRef> {
+        loop {
+            let task = self.pick_next_task()?;
+            if !task.cpumask().get(self.cpu_id) && !task.on_cpu() {
+                let target = get_run_queue(select_run_queue_index(&task));
+                debug!(
+                    "task {} moved by affinity from run_queue {} to {}",
+                    task.id_name(),
+                    self.cpu_id,
+                    target.cpu_id
+                );
+                task.set_cpu_id(target.cpu_id as _);
+                target.stat.migrations.fetch_add(1, Ordering::Relaxed);
+                target.put_task(task, false);
+                continue;
+            }
+            return Some(task);
         }
     }
 
+    /// Takes a ready task that may run on `cpu` out of this run queue.
+    ///
+    /// Tasks this CPU is still switching away from (see
+    /// [`TaskInner::on_cpu`]) are left alone, so that only ready tasks move,
+    /// never running ones. At most [`STEAL_SCAN`] tasks are looked at, and
+    /// those passed over go back behind the others, as if they had yielded.
+    #[cfg(feature = "smp")]
+    fn take_task_for(&self, cpu: usize) -> Option<KtaskRef> {
+        let mut scheduler = self.scheduler.lock();
+        let mut passed: [Option<KtaskRef>; STEAL_SCAN] = Default::default();
+        let mut taken = None;
+        for slot in &mut passed {
+            let Some(task) = scheduler.pick_next_task() else {
+                break;
+            };
+            if task.cpumask().get(cpu) && !task.on_cpu() {
+                taken = Some(task);
+                break;
+            }
+            *slot = Some(task);
+        }
+        for task in passed.into_iter().flatten() {
+            scheduler.put_prev_task(task, false);
+        }
+        if taken.is_some() {
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    /// Steals a ready task from the busiest other run queue, for this CPU to
+    /// run instead of going idle.
+    #[cfg(feature = "smp")]
+    fn steal_task(&self) -> Option<KtaskRef> {
+        let busiest = balance::find_busiest(self.cpu_id, &cpu_loads(RunQueue::nr_ready), 1)?;
+        let task = get_run_queue(busiest).take_task_for(self.cpu_id)?;
+        debug!(
+            "task steal: {} from run_queue {busiest} to {}",
+            task.id_name(),
+            self.cpu_id
+        );
+        task.set_cpu_id(self.cpu_id as _);
+        self.stat.steals.fetch_add(1, Ordering::Relaxed);
+        self.stat.migrations.fetch_add(1, Ordering::Relaxed);
+        Some(task)
+    }
+
+    /// Pulls a ready task from the busiest run queue into this one, if that
+    /// queue is at least [`balance::IMBALANCE_THRESHOLD`] tasks longer.
+    #[cfg(feature = "smp")]
+    fn load_balance(&self) {
+        let min_load = self.load() + balance::IMBALANCE_THRESHOLD;
+        let Some(busiest) =
+            balance::find_busiest(self.cpu_id, &cpu_loads(RunQueue::load), min_load)
+        else {
+            return;
+        };
+        let Some(task) = get_run_queue(busiest).take_task_for(self.cpu_id) else {
+            return;
+        };
+        debug!(
+            "task balance: {} from run_queue {busiest} to {}",
+            task.id_name(),
+            self.cpu_id
+        );
+        task.set_cpu_id(self.cpu_id as _);
+        self.stat.migrations.fetch_add(1, Ordering::Relaxed);
+        self.put_task(task, false);
+    }
+
     /// Puts target task into current run queue with `Ready` state
     /// if its state matches `current_state` (except idle task).
     ///
@@ -490,8 +747,13 @@ impl RunQueue {
             }
             // TODO: priority
             #[cfg(feature = "smp")]
-            task.set_cpu_id(self.cpu_id as _);
-            self.scheduler.lock().put_prev_task(task, preempt);
+            {
+                if task.cpu_id() as usize != self.cpu_id && task.last_ran() != 0 {
+                    self.stat.migrations.fetch_add(1, Ordering::Relaxed);
+                }
+                task.set_cpu_id(self.cpu_id as _);
+            }
+            self.put_task(task, preempt);
             true
         } else {
             false
@@ -500,15 +762,18 @@ impl RunQueue {
 
     /// Core reschedule subroutine.
     /// Pick the next task to run and switch to it.
+    ///
+    /// With nothing to run on this run queue, a task is stolen from another
+    /// one before falling back to the idle task.
     fn resched(&mut self) {
-        let next = self
-            .scheduler
-            .lock()
-            .pick_next_task()
-            .unwrap_or_else(|| unsafe {
-                // Safety: IRQs must be disabled at this time.
-                IDLE_TASK.current_ref_raw().get_unchecked().clone()
-            });
+        #[cfg(not(feature = "smp"))]
+        let next = self.pick_next_task();
+        #[cfg(feature = "smp")]
+        let next = self.pick_allowed_task().or_else(|| self.steal_task());
+        let next = next.unwrap_or_else(|| unsafe {
+            // Safety: IRQs must be disabled at this time.
+            IDLE_TASK.current_ref_raw().get_unchecked().clone()
+        });
         assert!(
             next.is_ready(),
             "next {} is not ready: {:?}",
@@ -529,18 +794,41 @@ impl RunQueue {
             prev_task.id_name(),
             next_task.id_name()
         );
+        #[cfg(feature = "preempt-debug")]
+        prev_task.preempt_trace().finish(prev_task.id(), None);
         #[cfg(feature = "preempt")]
         next_task.set_preempt_pending(false);
         next_task.set_state(TaskState::Running);
+        self.curr_rt_prio
+            .store(next_task.rt_priority(), Ordering::Relaxed);
         if prev_task.ptr_eq(&next_task) {
             return;
         }
+        self.stat.switches.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "smp")]
+        self.busy.store(!next_task.is_idle(), Ordering::Relaxed);
+        prev_task.set_last_ran(khal::time::monotonic_time_nanos());
+        flightrec::record(
+            prev_task.id().as_u64(),
+            flightrec::Event::SchedSwitch {
+                prev: prev_task.id().as_u64(),
+                next: next_task.id().as_u64(),
+                prev_state: prev_task.state() as u64,
+            },
+        );
 
         // Claim the task as running, we do this before switching to it
         // such that any running task will have this set.
         #[cfg(feature = "smp")]
         next_task.set_on_cpu(true);
 
+        #[cfg(feature = "watchdog")]
+        {
+            let now = khal::time::now_ticks();
+            prev_task.record_switch(now);
+            next_task.record_switch(now);
+        }
+
         #[cfg(feature = "task-ext")]
         {
             use crate::TaskExt;
@@ -627,17 +915,66 @@ fn poll_gc(cx: &mut Context<'_>) -> Poll<()> {
     Poll::Pending
 }
 
+/// Creates a task that moves `task`, the current task, to a run queue of its
+/// affinity once switched to, see [`CurrentRunQueueRef::migrate_current`].
+/// Changes the scheduling policy of `task`, see [`crate::set_scheduler`].
+pub(crate) fn set_scheduler(task: &KtaskRef, policy: SchedPolicy, rt_priority: u8) -> bool {
+    let _guard = kspin::NoPreemptIrqSave::new();
+    let rq = get_run_queue(task.cpu_id() as usize);
+    let mut scheduler = rq.scheduler.lock();
+    // A ready task is taken out, so that it is queued under its new policy.
+    let queued = scheduler.remove_task(task);
+    let changed = task.set_sched_policy(policy, rt_priority);
+    let requeued = queued.is_some();
+    if let Some(task) = queued {
+        scheduler.add_task(task);
+    }
+    drop(scheduler);
+
+    if changed {
+        if requeued {
+            rq.check_preempt_rt(rt_priority);
+        } else if task.is_running() {
+            // It may no longer outrank the ready tasks.
+            rq.resched_cpu();
+        }
+    }
+    changed
+}
+
+#[cfg(feature = "smp")]
+pub(crate) fn migration_task(task: KtaskRef) -> KtaskRef {
+    const MIGRATION_TASK_STACK_SIZE: usize = 4096;
+    TaskInner::new(
+        move || migrate_entry(task),
+        "migration-task".into(),
+        MIGRATION_TASK_STACK_SIZE,
+    )
+    .into_arc()
+}
+
 /// The task routine for migrating the current task to the correct CPU.
 ///
 /// It calls `select_run_queue` to get the correct run queue for the task, and
 /// then puts the task to the scheduler of target run queue.
 #[cfg(feature = "smp")]
 pub(crate) fn migrate_entry(migrated_task: KtaskRef) {
-    select_run_queue::<kspin::NoPreemptIrqSave>(&migrated_task)
-        .inner
-        .scheduler
-        .lock()
-        .put_prev_task(migrated_task, false)
+    let rq = select_run_queue::<kspin::NoPreemptIrqSave>(&migrated_task);
+    migrated_task.set_cpu_id(rq.inner.cpu_id as _);
+    rq.inner.stat.migrations.fetch_add(1, Ordering::Relaxed);
+    rq.inner.put_task(migrated_task, false);
+}
+
+/// Returns the scheduling statistics of CPU `cpu_id`, or `None` if it is not
+/// online.
+pub(crate) fn cpu_sched_stat(cpu_id: usize) -> Option<CpuSchedStat> {
+    let rq = online_run_queue(cpu_id)?;
+    Some(CpuSchedStat {
+        switches: rq.stat.switches.load(Ordering::Relaxed),
+        steals: rq.stat.steals.load(Ordering::Relaxed),
+        migrations: rq.stat.migrations.load(Ordering::Relaxed),
+        nr_ready: rq.nr_ready(),
+    })
 }
 
 /// Clear the `on_cpu` field of previous task running on this CPU.
@@ -673,10 +1010,14 @@ pub(crate) fn init() {
 
     RUN_QUEUE.with_current(|rq| {
         rq.init_once(RunQueue::new(cpu_id));
+        // The `main` task runs from now on.
+        #[cfg(feature = "smp")]
+        rq.busy.store(true, Ordering::Relaxed);
     });
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    ONLINE[cpu_id].store(true, Ordering::Release);
 }
 
 pub(crate) fn init_secondary() {
@@ -696,4 +1037,5 @@ pub(crate) fn init_secondary() {
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    ONLINE[cpu_id].store(true, Ordering::Release);
 }
@@ -5,17 +5,18 @@
 //! Core task data structures and lifecycle helpers.
 
 use alloc::{boxed::Box, string::String, sync::Arc};
-#[cfg(feature = "preempt")]
-use core::sync::atomic::AtomicUsize;
 use core::{
     alloc::Layout,
     cell::{Cell, UnsafeCell},
     fmt,
     future::poll_fn,
     mem::ManuallyDrop,
-    ops::Deref,
+    ops::{Deref, Range},
     ptr::NonNull,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicU32, AtomicU64, Ordering},
+    sync::atomic::{
+        AtomicBool, AtomicI8, AtomicI32, AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize,
+        Ordering,
+    },
     task::{Context, Poll},
 };
 
@@ -26,7 +27,25 @@ use khal::tls::TlsArea;
 use kspin::SpinNoIrq;
 use memaddr::{VirtAddr, align_up_4k};
 
-use crate::{KCpuMask, KTask, KtaskRef, future::block_on};
+#[cfg(feature = "preempt-debug")]
+use crate::preempt::PreemptTrace;
+use crate::{KCpuMask, KTask, KtaskRef, SchedPolicy, future::block_on, rt};
+
+/// The size of a task name, including the terminating NUL byte.
+pub const TASK_COMM_LEN: usize = 16;
+
+/// Truncates `name` at its first NUL byte and to `TASK_COMM_LEN - 1` bytes,
+/// and pads it with NULs.
+fn comm_from_bytes(name: &[u8]) -> [u8; TASK_COMM_LEN] {
+    let mut comm = [0; TASK_COMM_LEN];
+    let len = name
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(name.len())
+        .min(TASK_COMM_LEN - 1);
+    comm[..len].copy_from_slice(&name[..len]);
+    comm
+}
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -75,6 +94,14 @@ struct PerTaskRecording {
     /// Tick timestamp when we started waiting on `waiting_lock`.
     waiting_since: AtomicUsize,
     held_locks: HeldLocks,
+    /// 0 = not in a wait queue, otherwise the wait queue address.
+    waiting_queue: AtomicUsize,
+    /// Tick timestamp when the task was last switched in or out.
+    last_switch: AtomicU64,
+    /// Nesting depth of waits that a timer or a signal ends.
+    wakeable_waits: AtomicUsize,
+    /// `last_switch` of the wait last reported as hung.
+    hung_reported: AtomicU64,
 }
 
 #[cfg(feature = "watchdog")]
@@ -84,6 +111,10 @@ impl PerTaskRecording {
             waiting_lock: AtomicUsize::new(0),
             waiting_since: AtomicUsize::new(0),
             held_locks: [const { AtomicUsize::new(0) }; HELD_LOCK_SLOTS],
+            waiting_queue: AtomicUsize::new(0),
+            last_switch: AtomicU64::new(0),
+            wakeable_waits: AtomicUsize::new(0),
+            hung_reported: AtomicU64::new(0),
         }
     }
 }
@@ -91,18 +122,33 @@ impl PerTaskRecording {
 /// The inner task structure.
 pub struct TaskInner {
     id: TaskId,
-    name: SpinNoIrq<String>,
+    /// The task name, NUL-padded.
+    name: SpinNoIrq<[u8; TASK_COMM_LEN]>,
     is_idle: bool,
     is_init: bool,
+    /// Whether the task may block forever, see [`TaskInner::set_hung_check_exempt`].
+    hung_check_exempt: AtomicBool,
 
     entry: Cell<Option<Box<dyn FnOnce()>>>,
     state: AtomicU8,
 
-    /// CPU affinity mask.
+    /// CPUs the task is allowed to run on.
     cpumask: SpinNoIrq<KCpuMask>,
+    /// Whether the affinity is fixed, see [`crate::spawn_pinned`].
+    pinned: bool,
+    /// Nice value, from -20 (highest priority) to 19 (lowest).
+    nice: AtomicI8,
+    /// Scheduling policy in the high byte and real-time priority in the low
+    /// byte, so that they change together.
+    sched: AtomicU16,
+    /// Time left of the round-robin slice, in nanoseconds.
+    rr_slice_left: AtomicU64,
 
     /// Used to indicate the CPU ID where the task is running or will run.
     cpu_id: AtomicU32,
+    /// Monotonic time the task was last switched out, in nanoseconds, 0 if
+    /// it never was.
+    last_ran: AtomicU64,
     /// Used to indicate whether the task is running on a CPU.
     #[cfg(feature = "smp")]
     on_cpu: AtomicBool,
@@ -111,6 +157,9 @@ pub struct TaskInner {
     need_resched: AtomicBool,
     #[cfg(feature = "preempt")]
     preempt_disable_count: AtomicUsize,
+    /// When the task was asked to give way, see [`crate::max_non_preempt_section`].
+    #[cfg(feature = "preempt-debug")]
+    preempt_trace: PreemptTrace,
 
     interrupted: AtomicBool,
     interrupt_waker: AtomicWaker,
@@ -118,6 +167,9 @@ pub struct TaskInner {
     exit_code: AtomicI32,
     wait_for_exit: AtomicWaker,
 
+    /// Nesting depth of recovery regions, see [`crate::recoverable`].
+    recovery_depth: AtomicUsize,
+
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
 
@@ -192,12 +244,28 @@ impl TaskInner {
 
     /// Gets the name of the task.
     pub fn name(&self) -> String {
-        self.name.lock().clone()
+        let comm = self.comm();
+        let len = comm.iter().position(|&b| b == 0).unwrap_or(comm.len());
+        String::from_utf8_lossy(&comm[..len]).into_owned()
     }
 
     /// Set the name of the task.
+    ///
+    /// Names are truncated to `TASK_COMM_LEN - 1` bytes.
     pub fn set_name(&self, name: &str) {
-        *self.name.lock() = String::from(name);
+        self.set_comm(name.as_bytes());
+    }
+
+    /// Gets the name of the task as NUL-padded bytes.
+    pub fn comm(&self) -> [u8; TASK_COMM_LEN] {
+        *self.name.lock()
+    }
+
+    /// Sets the name of the task from bytes, stopping at the first NUL byte.
+    ///
+    /// Names are truncated to `TASK_COMM_LEN - 1` bytes.
+    pub fn set_comm(&self, name: &[u8]) {
+        *self.name.lock() = comm_from_bytes(name);
     }
 
     /// Get a combined string of the task ID and name.
@@ -251,6 +319,45 @@ impl TaskInner {
         }
     }
 
+    /// Returns the address range of the kernel stack.
+    #[inline]
+    pub fn kernel_stack_range(&self) -> Option<Range<VirtAddr>> {
+        self.kstack.as_ref().map(|s| s.bottom()..s.top())
+    }
+
+    /// Returns whether the task is inside a recovery region.
+    ///
+    /// A bad kernel access inside a recovery region kills only this task
+    /// instead of panicking the kernel.
+    #[inline]
+    pub fn in_recovery_region(&self) -> bool {
+        self.recovery_depth.load(Ordering::Relaxed) > 0
+    }
+
+    #[inline]
+    pub(crate) fn enter_recovery_region(&self) {
+        self.recovery_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn leave_recovery_region(&self) {
+        self.recovery_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Marks the task as one that legitimately blocks forever, such as a
+    /// kernel service thread waiting for work, so that it is never reported
+    /// as hung.
+    #[inline]
+    pub fn set_hung_check_exempt(&self, exempt: bool) {
+        self.hung_check_exempt.store(exempt, Ordering::Relaxed);
+    }
+
+    /// Returns whether the task is never reported as hung.
+    #[inline]
+    pub fn is_hung_check_exempt(&self) -> bool {
+        self.is_idle || self.hung_check_exempt.load(Ordering::Relaxed)
+    }
+
     /// Returns the CPU ID where the task is running or will run.
     ///
     /// Note: the task may not be running on the CPU, it just exists in the run queue.
@@ -259,6 +366,13 @@ impl TaskInner {
         self.cpu_id.load(Ordering::Acquire)
     }
 
+    /// Returns the time the task was last switched out, in nanoseconds of
+    /// monotonic time, or 0 if it has not run yet.
+    #[inline]
+    pub fn last_ran(&self) -> u64 {
+        self.last_ran.load(Ordering::Acquire)
+    }
+
     /// Gets the cpu affinity mask of the task.
     ///
     /// Returns the cpu affinity mask of the task in type [`KCpuMask`].
@@ -269,6 +383,10 @@ impl TaskInner {
 
     /// Sets the cpu affinity mask of the task.
     ///
+    /// Wakeups, stealing and load balancing only put the task on CPUs of the
+    /// mask. This only records the mask; [`set_affinity`](crate::set_affinity)
+    /// also moves the task off a CPU it may no longer run on.
+    ///
     /// # Arguments
     /// `cpumask` - The cpu affinity mask to be set in type [`KCpuMask`].
     #[inline]
@@ -276,6 +394,91 @@ impl TaskInner {
         *self.cpumask.lock() = cpumask
     }
 
+    /// Returns whether the affinity of the task is fixed, see
+    /// [`spawn_pinned`](crate::spawn_pinned).
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Fixes the affinity of the task to `cpu`.
+    pub(crate) fn pin(&mut self, cpu: usize) {
+        self.set_cpumask(KCpuMask::one_shot(cpu));
+        self.pinned = true;
+    }
+
+    /// Gets the nice value of the task.
+    #[inline]
+    pub fn nice(&self) -> i8 {
+        self.nice.load(Ordering::Relaxed)
+    }
+
+    /// Sets the nice value of the task, see [`crate::set_nice`].
+    #[inline]
+    pub(crate) fn set_nice(&self, nice: i8) {
+        self.nice.store(nice, Ordering::Relaxed)
+    }
+
+    /// Gets the scheduling policy of the task.
+    #[inline]
+    pub fn sched_policy(&self) -> SchedPolicy {
+        SchedPolicy::from_u8((self.sched.load(Ordering::Relaxed) >> 8) as u8)
+    }
+
+    /// Gets the real-time priority of the task, 0 for normal tasks.
+    #[inline]
+    pub fn rt_priority(&self) -> u8 {
+        self.sched.load(Ordering::Relaxed) as u8
+    }
+
+    /// Returns whether the task has a real-time policy.
+    #[inline]
+    pub fn is_rt(&self) -> bool {
+        self.rt_priority() != 0
+    }
+
+    /// Sets the scheduling policy and the real-time priority of a task that
+    /// is not spawned yet, returns `false` if they do not fit together.
+    ///
+    /// Spawned tasks must be changed with
+    /// [`set_scheduler`](crate::set_scheduler), which also requeues them.
+    pub fn set_sched_policy(&self, policy: SchedPolicy, rt_priority: u8) -> bool {
+        let valid = if policy.is_rt() {
+            (rt::MIN_RT_PRIO..=rt::MAX_RT_PRIO).contains(&rt_priority)
+        } else {
+            rt_priority == 0
+        };
+        if valid {
+            self.sched.store(
+                ((policy as u16) << 8) | rt_priority as u16,
+                Ordering::Relaxed,
+            );
+            self.refill_rr_slice();
+        }
+        valid
+    }
+
+    /// Charges `nanos` to the round-robin slice, returns whether it is over.
+    pub(crate) fn consume_rr_slice(&self, nanos: u64) -> bool {
+        let left = self
+            .rr_slice_left
+            .load(Ordering::Relaxed)
+            .saturating_sub(nanos);
+        self.rr_slice_left.store(left, Ordering::Relaxed);
+        left == 0
+    }
+
+    /// Returns whether the round-robin slice is over.
+    pub(crate) fn rr_slice_expired(&self) -> bool {
+        self.rr_slice_left.load(Ordering::Relaxed) == 0
+    }
+
+    /// Starts a new round-robin slice.
+    pub(crate) fn refill_rr_slice(&self) {
+        self.rr_slice_left
+            .store(rt::rr_timeslice().as_nanos() as u64, Ordering::Relaxed);
+    }
+
     /// Polls whether the task has been interrupted.
     #[inline]
     pub fn poll_interrupt(&self, cx: &Context) -> Poll<()> {
@@ -386,6 +589,59 @@ impl TaskInner {
         }
         out
     }
+
+    /// Getter: address of the wait queue the task is parked on (0 means none).
+    #[cfg(feature = "watchdog")]
+    #[inline(always)]
+    pub fn waiting_queue(&self) -> usize {
+        self.record_lock.waiting_queue.load(Ordering::Acquire)
+    }
+
+    #[cfg(feature = "watchdog")]
+    #[inline(always)]
+    pub(crate) fn set_waiting_queue(&self, queue: usize) {
+        self.record_lock
+            .waiting_queue
+            .store(queue, Ordering::Release);
+    }
+
+    /// Getter: tick when the task was last switched in or out (0 means never).
+    #[cfg(feature = "watchdog")]
+    #[inline(always)]
+    pub fn last_switch(&self) -> u64 {
+        self.record_lock.last_switch.load(Ordering::Acquire)
+    }
+
+    #[cfg(feature = "watchdog")]
+    #[inline(always)]
+    pub(crate) fn record_switch(&self, now: u64) {
+        self.record_lock.last_switch.store(now, Ordering::Release);
+    }
+
+    /// Returns whether the task is blocked in a wait that neither a timer nor
+    /// a signal ends, so that only the event it waits for wakes it up.
+    #[cfg(feature = "watchdog")]
+    pub fn in_uninterruptible_wait(&self) -> bool {
+        self.state() == TaskState::Blocked
+            && self.record_lock.wakeable_waits.load(Ordering::Acquire) == 0
+    }
+
+    /// Records that the wait ending at `last_switch` is reported as hung.
+    ///
+    /// Returns `false` if it was reported already.
+    #[cfg(feature = "watchdog")]
+    pub(crate) fn mark_hung_reported(&self, last_switch: u64) -> bool {
+        self.record_lock
+            .hung_reported
+            .swap(last_switch, Ordering::AcqRel)
+            != last_switch
+    }
+
+    /// Unwinds the kernel stack of the task, which must not be running.
+    #[cfg(feature = "watchdog")]
+    pub fn backtrace(&self) -> backtrace::Backtrace {
+        self.ctx().backtrace()
+    }
 }
 
 // private methods
@@ -398,24 +654,33 @@ impl TaskInner {
 
         Self {
             id,
-            name: SpinNoIrq::new(name),
+            name: SpinNoIrq::new(comm_from_bytes(name.as_bytes())),
             is_idle: false,
             is_init: false,
+            hung_check_exempt: AtomicBool::new(false),
             entry: Cell::new(None),
             state: AtomicU8::new(TaskState::Ready as u8),
             // By default, the task is allowed to run on all CPUs.
             cpumask: SpinNoIrq::new(cpumask),
+            pinned: false,
+            nice: AtomicI8::new(0),
+            sched: AtomicU16::new(0),
+            rr_slice_left: AtomicU64::new(0),
             cpu_id: AtomicU32::new(0),
+            last_ran: AtomicU64::new(0),
             #[cfg(feature = "smp")]
             on_cpu: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
+            #[cfg(feature = "preempt-debug")]
+            preempt_trace: PreemptTrace::new(),
             interrupted: AtomicBool::new(false),
             interrupt_waker: AtomicWaker::new(),
             exit_code: AtomicI32::new(0),
             wait_for_exit: AtomicWaker::new(),
+            recovery_depth: AtomicUsize::new(0),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             #[cfg(feature = "task-ext")]
@@ -499,9 +764,27 @@ impl TaskInner {
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
+        #[cfg(feature = "preempt-debug")]
+        if pending {
+            self.preempt_trace.request();
+        } else {
+            self.preempt_trace.cancel();
+        }
         self.need_resched.store(pending, Ordering::Release)
     }
 
+    #[inline]
+    #[cfg(feature = "preempt")]
+    pub(crate) fn need_resched(&self) -> bool {
+        self.need_resched.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    #[cfg(feature = "preempt-debug")]
+    pub(crate) fn preempt_trace(&self) -> &PreemptTrace {
+        &self.preempt_trace
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn can_preempt(&self, current_disable_count: usize) -> bool {
@@ -517,14 +800,19 @@ impl TaskInner {
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn enable_preempt(&self, resched: bool) {
-        if self.preempt_disable_count.fetch_sub(1, Ordering::Release) == 1 && resched {
+        if self.preempt_disable_count.fetch_sub(1, Ordering::Release) == 1
+            && resched
+            && crate::preempt_model() == crate::PreemptModel::Full
+        {
             // If current task is pending to be preempted, do rescheduling.
             Self::current_check_preempt_pending();
         }
     }
 
+    /// Reschedules if the current task is pending to be preempted and
+    /// preemption is enabled, and returns whether it did.
     #[cfg(feature = "preempt")]
-    fn current_check_preempt_pending() {
+    pub(crate) fn current_check_preempt_pending() -> bool {
         use kspin::NoPreemptIrqSave;
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
@@ -532,9 +820,11 @@ impl TaskInner {
             // disable preemption here, because the klogger may cause preemption.
             let mut rq = crate::current_run_queue::<NoPreemptIrqSave>();
             if curr.need_resched.load(Ordering::Acquire) {
-                rq.preempt_resched()
+                rq.preempt_resched();
+                return true;
             }
         }
+        false
     }
 
     /// Notify all tasks that join on this task.
@@ -549,6 +839,13 @@ impl TaskInner {
         self.ctx.get()
     }
 
+    /// Records that the task was switched out at `now`, in nanoseconds of
+    /// monotonic time.
+    #[inline]
+    pub(crate) fn set_last_ran(&self, now: u64) {
+        self.last_ran.store(now, Ordering::Release);
+    }
+
     /// Set the CPU ID where the task is running or will run.
     #[cfg(feature = "smp")]
     #[inline]
@@ -580,7 +877,7 @@ impl fmt::Debug for TaskInner {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut ds = f.debug_struct("TaskInner");
         ds.field("id", &self.id)
-            .field("name", &self.name)
+            .field("name", &self.name())
             .field("state", &self.state());
 
         #[cfg(feature = "watchdog")]
@@ -618,6 +915,10 @@ impl TaskStack {
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
+
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::from(self.ptr.as_ptr() as usize)
+    }
 }
 
 impl Drop for TaskStack {
@@ -626,6 +927,53 @@ impl Drop for TaskStack {
     }
 }
 
+/// Marks the current task as in a wait that a timer or a signal ends, for as
+/// long as it lives.
+#[cfg(feature = "watchdog")]
+pub(crate) struct WakeableWait(KtaskRef);
+
+#[cfg(feature = "watchdog")]
+impl WakeableWait {
+    pub(crate) fn enter() -> Self {
+        let task = crate::current().clone();
+        task.record_lock
+            .wakeable_waits
+            .fetch_add(1, Ordering::AcqRel);
+        Self(task)
+    }
+}
+
+#[cfg(feature = "watchdog")]
+impl Drop for WakeableWait {
+    fn drop(&mut self) {
+        self.0
+            .record_lock
+            .wakeable_waits
+            .fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Records the wait queue the current task is parked on, for as long as it
+/// lives.
+#[cfg(feature = "watchdog")]
+pub(crate) struct QueuedWait(KtaskRef);
+
+#[cfg(feature = "watchdog")]
+impl QueuedWait {
+    pub(crate) fn enter(queue: usize) -> Self {
+        let task = crate::current().clone();
+        task.set_waiting_queue(queue);
+        Self(task)
+    }
+}
+
+#[cfg(feature = "watchdog")]
+impl Drop for QueuedWait {
+    fn drop(&mut self) {
+        self.0.set_waiting_queue(0);
+    }
+}
+
 /// A wrapper of [`KtaskRef`] as the current task.
 ///
 /// It won't change the reference count of the task when created or dropped.
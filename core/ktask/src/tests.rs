@@ -129,3 +129,362 @@ fn test_task_join() {
         assert_eq!(task.join(), i as _);
     }
 }
+
+#[test]
+fn test_recovery_region_nesting() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    assert!(!current().in_recovery_region());
+    ktask::recoverable(|| {
+        assert!(current().in_recovery_region());
+        ktask::recoverable(|| assert!(current().in_recovery_region()));
+        assert!(current().in_recovery_region());
+    });
+    assert!(!current().in_recovery_region());
+}
+
+fn new_fair_task(nice: i32) -> std::sync::Arc<crate::fair::FairTask> {
+    let task = crate::fair::FairTask::new(crate::TaskInner::new(|| {}, "fair".into(), 0x1000));
+    assert!(ktask::set_nice(&task, nice));
+    std::sync::Arc::new(task)
+}
+
+/// Runs two tasks with nice values `a` and `b` on a weighted fair scheduler
+/// for `ticks` timer ticks, returning the ticks each of them ran.
+fn fair_contention(a: i32, b: i32, ticks: usize) -> (usize, usize) {
+    use std::sync::Arc;
+
+    use axsched::BaseScheduler;
+
+    let (ta, tb) = (new_fair_task(a), new_fair_task(b));
+    let mut sched = crate::fair::FairScheduler::new();
+    sched.add_task(ta.clone());
+    sched.add_task(tb.clone());
+
+    let (mut ran_a, mut ran_b) = (0, 0);
+    let mut curr = sched.pick_next_task().unwrap();
+    for _ in 0..ticks {
+        if Arc::ptr_eq(&curr, &ta) {
+            ran_a += 1;
+        } else {
+            ran_b += 1;
+        }
+        if sched.task_tick(&curr) {
+            sched.put_prev_task(curr, true);
+            curr = sched.pick_next_task().unwrap();
+        }
+    }
+    assert_eq!(
+        ta.sum_exec_runtime() + tb.sum_exec_runtime(),
+        ticks as u64 * (1_000_000_000 / platconfig::TICKS_PER_SEC as u64)
+    );
+    (ran_a, ran_b)
+}
+
+#[test]
+fn test_sched_fair_share_ratio() {
+    for (a, b) in [(0, 0), (0, 1), (0, 5), (0, 10), (-5, 5), (0, 19)] {
+        let (ran_a, ran_b) = fair_contention(a, b, 100_000);
+        let expected =
+            ktask::nice_to_weight(a as i8) as f64 / ktask::nice_to_weight(b as i8) as f64;
+        let actual = ran_a as f64 / ran_b as f64;
+        println!("sched-fair: nice {a} vs {b}: {actual:.2} (expected {expected:.2})");
+        assert!(
+            (actual / expected - 1.0).abs() < 0.05,
+            "nice {a} vs {b}: share ratio {actual:.2}, expected {expected:.2}"
+        );
+    }
+}
+
+#[test]
+fn test_sched_fair_late_task_gets_no_credit() {
+    use axsched::BaseScheduler;
+
+    let (busy, late) = (new_fair_task(0), new_fair_task(0));
+    let mut sched = crate::fair::FairScheduler::new();
+    sched.add_task(busy.clone());
+
+    // `busy` runs alone for a while.
+    let curr = sched.pick_next_task().unwrap();
+    for _ in 0..1000 {
+        assert!(!sched.task_tick(&curr));
+    }
+
+    // A task that joins late starts from the current minimum, instead of
+    // monopolizing the CPU until it catches up.
+    sched.add_task(late.clone());
+    assert_eq!(late.vruntime(), busy.vruntime());
+    assert!(!sched.task_tick(&curr));
+    assert!(sched.task_tick(&curr));
+}
+
+#[test]
+fn test_set_nice_range() {
+    let task = crate::TaskInner::new(|| {}, "nice".into(), 0x1000);
+    assert!(!ktask::set_nice(&task, 20));
+    assert!(!ktask::set_nice(&task, -21));
+    assert!(ktask::set_nice(&task, -20));
+    assert_eq!(task.nice(), -20);
+}
+
+#[test]
+#[cfg(feature = "watchdog")]
+fn test_hung_task_lost_wakeup() {
+    use std::time::{Duration, Instant};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+    static WQ: WaitQueue = WaitQueue::new();
+
+    // The notification comes before anyone waits, so the wakeup is lost.
+    WQ.notify_one(false);
+    let start = Instant::now();
+    let task = ktask::spawn(|| WQ.wait());
+
+    let is_task = |hung: &crate::HungTask| std::sync::Arc::ptr_eq(hung.task(), &task);
+    let timeout_ns = TIMEOUT.as_nanos() as u64;
+    let report = loop {
+        if let Some(hung) = crate::scan_hung_tasks(timeout_ns, usize::MAX)
+            .into_iter()
+            .find(is_task)
+        {
+            break hung;
+        }
+        assert!(start.elapsed() < 2 * TIMEOUT, "hung task not reported");
+        ktask::yield_now();
+    };
+    assert!(report.blocked_ns() > timeout_ns);
+    assert_eq!(report.task().waiting_queue(), &WQ as *const _ as usize);
+    println!("hung task: {report}");
+
+    // The same wait is reported only once.
+    for _ in 0..2 {
+        let hung = crate::scan_hung_tasks(timeout_ns, usize::MAX);
+        assert!(!hung.iter().any(is_task));
+    }
+
+    WQ.notify_one(true);
+    task.join();
+}
+
+#[test]
+fn test_preempt_model_from_cmdline() {
+    use crate::PreemptModel;
+
+    assert_eq!(PreemptModel::from_cmdline(""), None);
+    assert_eq!(
+        PreemptModel::from_cmdline("console=ttyS0 preempt=voluntary"),
+        Some(PreemptModel::Voluntary)
+    );
+    assert_eq!(
+        PreemptModel::from_cmdline("preempt=none preempt=full"),
+        Some(PreemptModel::Full)
+    );
+    assert_eq!(PreemptModel::from_cmdline("preempt=lazy"), None);
+    assert_eq!(PreemptModel::Voluntary.as_str(), "voluntary");
+
+    // Outside a task, a preemption point has nothing to give way to.
+    assert!(!crate::cond_resched());
+}
+
+#[test]
+fn test_balance_select_cpu() {
+    use crate::balance::select_cpu;
+
+    let loads = [Some(3), Some(0), Some(1), None];
+    let any = |_| true;
+    // A cache-hot task goes back where it ran, a cold one to its waker.
+    assert_eq!(select_cpu(any, Some(2), 0, true, &loads), 2);
+    assert_eq!(select_cpu(any, Some(2), 0, false, &loads), 0);
+    // Unless its affinity forbids it.
+    assert_eq!(select_cpu(|cpu| cpu != 2, Some(2), 0, true, &loads), 0);
+    assert_eq!(select_cpu(|cpu| cpu != 0, Some(2), 0, false, &loads), 2);
+    // New tasks, and tasks allowed neither, go to the least loaded CPU.
+    assert_eq!(select_cpu(any, None, 0, false, &loads), 1);
+    assert_eq!(select_cpu(|cpu| cpu >= 2, Some(0), 0, true, &loads), 2);
+    // CPUs that are not online are never chosen.
+    assert_eq!(select_cpu(any, Some(3), 3, true, &loads), 1);
+}
+
+#[test]
+fn test_balance_find_busiest() {
+    use crate::balance::find_busiest;
+
+    let loads = [Some(1), Some(4), None, Some(4), Some(0)];
+    assert_eq!(find_busiest(0, &loads, 1), Some(1));
+    assert_eq!(find_busiest(1, &loads, 1), Some(3));
+    assert_eq!(find_busiest(4, &loads, 5), None);
+    assert_eq!(find_busiest(0, &[Some(0), Some(0)], 1), None);
+    assert_eq!(find_busiest(0, &[Some(2)], 0), None);
+}
+
+#[test]
+fn test_task_comm_truncation() {
+    use crate::{TASK_COMM_LEN, TaskInner};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    let task = TaskInner::new(|| {}, "a-rather-long-task-name".into(), 0x1000);
+    assert_eq!(task.name(), "a-rather-long-t");
+    assert_eq!(task.comm()[TASK_COMM_LEN - 1], 0);
+
+    task.set_comm(b"worker\0garbage");
+    assert_eq!(task.name(), "worker");
+    assert_eq!(&task.comm()[..7], b"worker\0");
+}
+
+#[test]
+fn test_set_affinity() {
+    use crate::KCpuMask;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    let task = ktask::spawn_raw(ktask::yield_now, "affinity".into(), 0x1000);
+    assert!(!ktask::set_affinity(&task, KCpuMask::new()));
+    assert!(ktask::set_affinity(&task, KCpuMask::one_shot(0)));
+    assert!(task.cpumask().get(0));
+
+    let pinned = ktask::spawn_pinned(0, ktask::yield_now);
+    assert!(pinned.is_pinned());
+    assert!(!ktask::set_affinity(&pinned, KCpuMask::one_shot(0)));
+
+    task.join();
+    pinned.join();
+}
+
+fn new_rt_task(policy: crate::SchedPolicy, rt_priority: u8) -> crate::KtaskRef {
+    let task = crate::TaskInner::new(|| {}, "rt".into(), 0x1000);
+    assert!(task.set_sched_policy(policy, rt_priority));
+    task.into_arc()
+}
+
+#[test]
+fn test_sched_rt_priority_order() {
+    use std::sync::Arc;
+
+    use axsched::BaseScheduler;
+
+    use crate::{NormalScheduler, SchedPolicy, Scheduler};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    let normal = new_rt_task(SchedPolicy::Normal, 0);
+    let low = new_rt_task(SchedPolicy::Fifo, 10);
+    let high = new_rt_task(SchedPolicy::RoundRobin, 50);
+    let low2 = new_rt_task(SchedPolicy::Fifo, 10);
+    assert!(!normal.set_sched_policy(SchedPolicy::Fifo, 0));
+    assert!(!normal.set_sched_policy(SchedPolicy::Normal, 1));
+    assert!(!normal.set_sched_policy(SchedPolicy::RoundRobin, 100));
+
+    let mut sched = Scheduler::new(NormalScheduler::new());
+    for task in [&normal, &low, &high, &low2] {
+        sched.add_task(task.clone());
+    }
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &high));
+
+    // A preempted FIFO task stays ahead of the tasks of its priority, one
+    // that yields goes behind them.
+    let curr = sched.pick_next_task_at(0).unwrap();
+    assert!(Arc::ptr_eq(&curr, &low));
+    sched.put_prev_task(curr, true);
+    let curr = sched.pick_next_task_at(0).unwrap();
+    assert!(Arc::ptr_eq(&curr, &low));
+    sched.put_prev_task(curr, false);
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &low2));
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &low));
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &normal));
+    assert!(sched.pick_next_task_at(0).is_none());
+}
+
+#[test]
+fn test_sched_rt_round_robin() {
+    use std::{sync::Arc, time::Duration};
+
+    use axsched::BaseScheduler;
+
+    use crate::{NormalScheduler, SchedPolicy, Scheduler, rt::TICK_NANOS};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    let slice = ktask::rr_timeslice();
+    assert!(!ktask::set_rr_timeslice(Duration::ZERO));
+    assert!(ktask::set_rr_timeslice(Duration::from_nanos(
+        TICK_NANOS * 2
+    )));
+
+    let (a, b) = (
+        new_rt_task(SchedPolicy::RoundRobin, 5),
+        new_rt_task(SchedPolicy::RoundRobin, 5),
+    );
+    let mut sched = Scheduler::new(NormalScheduler::new());
+    sched.add_task(a.clone());
+    let curr = sched.pick_next_task_at(0).unwrap();
+
+    // Alone at its priority, the task keeps running after its slice.
+    for _ in 0..4 {
+        assert!(!sched.task_tick_at(&curr, 0));
+    }
+
+    // Otherwise it goes behind the other one, even though preempted.
+    sched.add_task(b.clone());
+    assert!(!sched.task_tick_at(&curr, 0));
+    assert!(sched.task_tick_at(&curr, 0));
+    sched.put_prev_task(curr, true);
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &b));
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(0).unwrap(), &a));
+
+    assert!(ktask::set_rr_timeslice(slice));
+}
+
+#[test]
+fn test_sched_rt_throttling() {
+    use std::{sync::Arc, time::Duration};
+
+    use axsched::BaseScheduler;
+
+    use crate::{NormalScheduler, RtBandwidth, SchedPolicy, Scheduler, rt::TICK_NANOS};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(ktask::init_scheduler);
+
+    let bandwidth = ktask::rt_bandwidth();
+    let period = TICK_NANOS * 10;
+    assert!(!ktask::set_rt_bandwidth(RtBandwidth {
+        period: Duration::from_nanos(period),
+        runtime: Some(Duration::from_nanos(period + 1)),
+    }));
+    assert!(ktask::set_rt_bandwidth(RtBandwidth {
+        period: Duration::from_nanos(period),
+        runtime: Some(Duration::from_nanos(TICK_NANOS * 2)),
+    }));
+
+    let hog = new_rt_task(SchedPolicy::Fifo, 99);
+    let normal = new_rt_task(SchedPolicy::Normal, 0);
+    let mut sched = Scheduler::new(NormalScheduler::new());
+    sched.add_task(hog.clone());
+    sched.add_task(normal.clone());
+
+    // Once the runtime is used up, the normal task gets to run...
+    let curr = sched.pick_next_task_at(0).unwrap();
+    assert!(Arc::ptr_eq(&curr, &hog));
+    assert!(!sched.task_tick_at(&curr, 0));
+    assert!(sched.task_tick_at(&curr, TICK_NANOS));
+    sched.put_prev_task(curr, true);
+    let curr = sched.pick_next_task_at(TICK_NANOS * 2).unwrap();
+    assert!(Arc::ptr_eq(&curr, &normal));
+    assert!(!sched.task_tick_at(&curr, TICK_NANOS * 2));
+
+    // ...until the next period.
+    assert!(sched.task_tick_at(&curr, period));
+    sched.put_prev_task(curr, true);
+    assert!(Arc::ptr_eq(&sched.pick_next_task_at(period).unwrap(), &hog));
+
+    assert!(ktask::set_rt_bandwidth(bandwidth));
+}
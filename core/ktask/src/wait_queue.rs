@@ -55,6 +55,8 @@ impl WaitQueue {
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
     pub fn wait(&self) {
+        #[cfg(feature = "watchdog")]
+        let _wait = crate::task::QueuedWait::enter(self as *const _ as usize);
         listener!(self.event => listener);
         block_on(listener)
     }
@@ -68,6 +70,8 @@ impl WaitQueue {
     where
         F: FnMut() -> bool,
     {
+        #[cfg(feature = "watchdog")]
+        let _wait = crate::task::QueuedWait::enter(self as *const _ as usize);
         block_on(async {
             loop {
                 if condition() {
@@ -13,12 +13,79 @@ pub const PMU_OVERFLOW_IRQ: u32 = 23;
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum PmuEvent {
+    L1dCacheRefill = 0x03, // Level 1 data cache refill
+    L1dCache       = 0x04, // Level 1 data cache access
+    InstRetired    = 0x08, // Instruction architecturally executed
+    BrMisPred      = 0x10, // Mispredicted or not predicted branch
     CpuCycles      = 0x11, // Cpu Cycles counter
     MemAccess      = 0x13, // Data memory access
     L2dCache       = 0x16, // Level 2 data cache access
     L2dCacheRefill = 0x17, // Level 2 data cache refill
 }
 
+/// `PMEVTYPER<n>_EL0.P`: do not count at EL1.
+const EVTYPER_EXCLUDE_EL1: u64 = 1 << 31;
+/// `PMEVTYPER<n>_EL0.U`: do not count at EL0.
+const EVTYPER_EXCLUDE_EL0: u64 = 1 << 30;
+
+/// Returns the number of event counters, 0 without a PMU.
+pub fn event_counters() -> u32 {
+    let aa64dfr0: u64 = mrs!(ID_AA64DFR0_EL1);
+    let pmu_ver = (aa64dfr0 >> 8) & 0xF;
+    if pmu_ver == 0 || pmu_ver == 0xF {
+        return 0;
+    }
+    let pmcr: u64 = mrs!(PMCR_EL0);
+    ((pmcr >> 11) & 0x1F) as u32
+}
+
+/// Returns `true` if the PMU implements the common `event`.
+pub fn event_implemented(event: PmuEvent) -> bool {
+    let pmceid0: u64 = mrs!(PMCEID0_EL0);
+    pmceid0 & (1 << event as u32) != 0
+}
+
+/// Starts event counter `index` counting `event` from zero, without
+/// overflow interrupt, at the exception levels not excluded.
+///
+/// Counting counters are 32 bits wide, the caller reads them before they
+/// wrap.
+pub fn start_counting(index: u32, event: PmuEvent, exclude_el0: bool, exclude_el1: bool) {
+    let mut evtyper = event as u64;
+    if exclude_el0 {
+        evtyper |= EVTYPER_EXCLUDE_EL0;
+    }
+    if exclude_el1 {
+        evtyper |= EVTYPER_EXCLUDE_EL1;
+    }
+    msr!(PMCNTENCLR_EL0, 1u64 << index);
+    msr!(PMINTENCLR_EL1, 1u64 << index);
+    msr!(PMSELR_EL0, index as u64);
+    isb!();
+    msr!(PMXEVTYPER_EL0, evtyper);
+    msr!(PMXEVCNTR_EL0, 0u64);
+    msr!(PMOVSCLR_EL0, 1u64 << index);
+
+    let pmcr: u64 = mrs!(PMCR_EL0);
+    msr!(PMCR_EL0, pmcr | (1 << 0)); // Set E
+    msr!(PMCNTENSET_EL0, 1u64 << index);
+    isb!();
+}
+
+/// Returns the value of event counter `index`.
+pub fn read_counting(index: u32) -> u32 {
+    msr!(PMSELR_EL0, index as u64);
+    isb!();
+    let value: u64 = mrs!(PMXEVCNTR_EL0);
+    value as u32
+}
+
+/// Stops event counter `index`.
+pub fn stop_counting(index: u32) {
+    msr!(PMCNTENCLR_EL0, 1u64 << index);
+    isb!();
+}
+
 /// Error type for PMU operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PmuError {
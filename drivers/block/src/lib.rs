@@ -7,6 +7,8 @@
 #![no_std]
 #![cfg_attr(doc, feature(doc_cfg))]
 
+extern crate alloc;
+
 // #[cfg(feature = "bcm2835-sdhci")]
 // pub mod bcm2835sdhci;
 
@@ -21,8 +23,39 @@ pub mod ramdisk_static;
 // #[cfg(feature = "sdmmc")]
 // pub mod sdmmc;
 
+pub mod partition;
+pub mod queue;
+mod request;
+
 #[doc(no_inline)]
-pub use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
+pub use driver_base::{
+    DeviceError, DeviceKind, DriverError, DriverOps, DriverResult, ErrorClass, Retryability,
+};
+
+pub use self::request::{BlockRequestExt, RetryPolicy};
+
+/// Sizes of a block device that upper layers may align their requests to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    /// Size of the logical sectors, see [`BlockDriverOps::block_size`].
+    pub logical_block_size: usize,
+    /// Size of the physical sectors, see
+    /// [`BlockDriverOps::physical_block_size`].
+    pub physical_block_size: usize,
+    /// Preferred request size, or 0 if unknown.
+    pub optimal_io_size: usize,
+}
+
+impl BlockLimits {
+    /// Returns the limits reported by `dev`.
+    pub fn of<D: BlockDriverOps + ?Sized>(dev: &D) -> Self {
+        Self {
+            logical_block_size: dev.block_size(),
+            physical_block_size: dev.physical_block_size(),
+            optimal_io_size: dev.optimal_io_size(),
+        }
+    }
+}
 
 /// Operations that require a block storage device driver to implement.
 pub trait BlockDriverOps: DriverOps {
@@ -31,8 +64,24 @@ pub trait BlockDriverOps: DriverOps {
     /// The total size of the device is `num_blocks() * block_size()`.
     fn num_blocks(&self) -> u64;
     /// The size of each block in bytes.
+    ///
+    /// This is the logical sector size, the unit of requests to the driver.
     fn block_size(&self) -> usize;
 
+    /// The size in bytes of the physical sectors, a multiple of the block
+    /// size: 4096 for a 512e disk, which emulates 512-byte sectors.
+    ///
+    /// Writes of whole physical sectors spare the device a read-modify-write.
+    fn physical_block_size(&self) -> usize {
+        self.block_size()
+    }
+
+    /// The preferred size in bytes of requests, or 0 if the device reports
+    /// none.
+    fn optimal_io_size(&self) -> usize {
+        0
+    }
+
     /// Reads blocked data from the given block.
     ///
     /// The size of the buffer may exceed the block size, in which case multiple
@@ -0,0 +1,652 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Partition tables.
+//!
+//! [`scan`] reads the partition table at the start of a disk: either an MBR,
+//! whose logical partitions are chained in extended boot records, or a GPT.
+//! The GPT header and entry array are checked against their CRC-32, and the
+//! backup header at the end of the disk is used if the primary one is
+//! damaged. All addresses are in device blocks.
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+use driver_base::{DriverError, DriverResult};
+
+use crate::{BlockDriverOps, BlockRequestExt};
+
+/// Size of the MBR and of the part of a block a GPT header may take up.
+const SECTOR_SIZE: usize = 512;
+
+/// Offset of the partition entries in an MBR or EBR.
+const MBR_ENTRIES: usize = 446;
+/// Size of an MBR partition entry.
+const MBR_ENTRY_SIZE: usize = 16;
+/// Boot signature at the end of an MBR or EBR.
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// System IDs of extended partitions.
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// System ID of the protective partition covering a GPT disk.
+const MBR_GPT_PROTECTIVE: u8 = 0xee;
+/// System ID of Linux filesystem partitions.
+const MBR_LINUX: u8 = 0x83;
+/// Number of the first logical partition.
+const FIRST_LOGICAL: u32 = 5;
+/// Upper bound on logical partitions, against loops in the EBR chain.
+const MAX_LOGICAL: u32 = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Size of the GPT header fields covered by the header CRC.
+const GPT_HEADER_SIZE: usize = 92;
+/// Size of a GPT partition entry in revision 1.0.
+const GPT_ENTRY_SIZE: usize = 128;
+/// Upper bound on the size of the GPT entry array.
+const GPT_MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// A GUID, in the mixed-endian layout GPT stores it in.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Partition type of the EFI system partition.
+    pub const EFI_SYSTEM: Self = Self::from_fields(
+        0xc12a_7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    /// Partition type of Linux filesystem data.
+    pub const LINUX_FILESYSTEM: Self = Self::from_fields(
+        0x0fc6_3daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+    /// The GUID of unused GPT entries.
+    pub const ZERO: Self = Self([0; 16]);
+
+    /// Creates a GUID from the fields of its textual form.
+    pub const fn from_fields(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Self {
+        let d1 = d1.to_le_bytes();
+        let d2 = d2.to_le_bytes();
+        let d3 = d3.to_le_bytes();
+        Self([
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], d4[0], d4[1], d4[2], d4[3],
+            d4[4], d4[5], d4[6], d4[7],
+        ])
+    }
+
+    /// Parses the textual form `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, in
+    /// either case.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut fields = s.split('-');
+        let mut field = |len: usize| {
+            let field = fields.next().filter(|it| it.len() == len)?;
+            u64::from_str_radix(field, 16)
+                .ok()
+                .filter(|_| field.bytes().all(|b| b.is_ascii_hexdigit()))
+        };
+        let d1 = field(8)? as u32;
+        let d2 = field(4)? as u16;
+        let d3 = field(4)? as u16;
+        let d4 = field(4)?.to_be_bytes();
+        let d5 = field(12)?.to_be_bytes();
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Self::from_fields(
+            d1,
+            d2,
+            d3,
+            [d4[6], d4[7], d5[2], d5[3], d5[4], d5[5], d5[6], d5[7]],
+        ))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+        )?;
+        for (i, byte) in b[8..].iter().enumerate() {
+            if i == 2 {
+                f.write_str("-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Type of a partition, as recorded in the partition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// System ID of an MBR partition.
+    Mbr(u8),
+    /// Partition type GUID of a GPT partition.
+    Gpt(Guid),
+}
+
+impl PartitionType {
+    /// Whether the partition is meant to hold a Linux filesystem.
+    pub fn is_linux(&self) -> bool {
+        match self {
+            PartitionType::Mbr(id) => *id == MBR_LINUX,
+            PartitionType::Gpt(guid) => *guid == Guid::LINUX_FILESYSTEM,
+        }
+    }
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(id) => write!(f, "{id:#04x}"),
+            PartitionType::Gpt(guid) => write!(f, "{guid}"),
+        }
+    }
+}
+
+/// A partition found by [`scan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// Number of the partition, as in `vda1`.
+    ///
+    /// Primary MBR partitions keep the number of their slot, and logical ones
+    /// are numbered from 5. GPT partitions take the number of their entry.
+    pub number: u32,
+    /// First block of the partition.
+    pub start: u64,
+    /// Number of blocks in the partition.
+    pub blocks: u64,
+    /// Type of the partition.
+    pub kind: PartitionType,
+    /// Unique partition GUID, for GPT partitions.
+    pub uuid: Option<Guid>,
+}
+
+impl PartitionInfo {
+    /// Returns the block of the parent device that a request of `len` bytes
+    /// at `block_id` of the partition starts at.
+    ///
+    /// Fails with [`DriverError::Io`] if the request does not fit in the
+    /// partition.
+    pub fn translate(&self, block_id: u64, len: usize, block_size: usize) -> DriverResult<u64> {
+        let blocks = len.div_ceil(block_size) as u64;
+        match block_id.checked_add(blocks) {
+            Some(end) if end <= self.blocks => Ok(self.start + block_id),
+            _ => Err(DriverError::Io),
+        }
+    }
+}
+
+/// Reads the partition table of `dev`.
+///
+/// Returns the partitions in order of their number, or nothing if the disk
+/// has no partition table. Entries that point outside of the disk are left
+/// out.
+pub fn scan<D: BlockDriverOps + ?Sized>(dev: &mut D) -> DriverResult<Vec<PartitionInfo>> {
+    if dev.block_size() < SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    let mbr = read(dev, 0)?;
+    let Some(entries) = mbr_entries(&mbr) else {
+        return Ok(Vec::new());
+    };
+    if entries.iter().any(|it| it.kind == MBR_GPT_PROTECTIVE) {
+        return scan_gpt(dev);
+    }
+    scan_mbr(dev, &entries)
+}
+
+fn read<D: BlockDriverOps + ?Sized>(dev: &mut D, block_id: u64) -> DriverResult<Vec<u8>> {
+    let mut buf = vec![0u8; dev.block_size()];
+    dev.submit_read(block_id, &mut buf)?;
+    Ok(buf)
+}
+
+fn le_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn guid(buf: &[u8], offset: usize) -> Guid {
+    Guid(buf[offset..offset + 16].try_into().unwrap())
+}
+
+/// A used entry of an MBR or EBR.
+#[derive(Clone, Copy)]
+struct MbrEntry {
+    slot: u32,
+    kind: u8,
+    start: u64,
+    blocks: u64,
+}
+
+/// Returns the used entries of the MBR or EBR in `sector`, or `None` if it
+/// is not one.
+///
+/// A FAT boot sector carries the same signature, but its boot code in place
+/// of the entries rarely has valid boot indicators.
+fn mbr_entries(sector: &[u8]) -> Option<Vec<MbrEntry>> {
+    if sector[SECTOR_SIZE - 2..SECTOR_SIZE] != MBR_SIGNATURE {
+        return None;
+    }
+    let mut entries = Vec::new();
+    for slot in 0..4 {
+        let entry = &sector[MBR_ENTRIES + slot * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        if !matches!(entry[0], 0x00 | 0x80) {
+            return None;
+        }
+        let (kind, start, blocks) = (entry[4], le_u32(entry, 8), le_u32(entry, 12));
+        if kind != 0 && blocks != 0 {
+            entries.push(MbrEntry {
+                slot: slot as u32 + 1,
+                kind,
+                start: start as u64,
+                blocks: blocks as u64,
+            });
+        }
+    }
+    Some(entries)
+}
+
+/// Returns whether `blocks` blocks at `start` lie within `limit`, warning
+/// about the partition `number` otherwise.
+fn fits(number: u32, start: u64, blocks: u64, limit: u64) -> bool {
+    let fits = start > 0 && start.checked_add(blocks).is_some_and(|end| end <= limit);
+    if !fits {
+        log::warn!("partition {number} ({blocks} blocks at {start}) lies beyond the disk");
+    }
+    fits
+}
+
+fn scan_mbr<D: BlockDriverOps + ?Sized>(
+    dev: &mut D,
+    entries: &[MbrEntry],
+) -> DriverResult<Vec<PartitionInfo>> {
+    let num_blocks = dev.num_blocks();
+    let mut partitions = Vec::new();
+    let mut extended = None;
+    for entry in entries {
+        if !fits(entry.slot, entry.start, entry.blocks, num_blocks) {
+            continue;
+        }
+        if MBR_EXTENDED.contains(&entry.kind) {
+            extended.get_or_insert(*entry);
+            continue;
+        }
+        partitions.push(PartitionInfo {
+            number: entry.slot,
+            start: entry.start,
+            blocks: entry.blocks,
+            kind: PartitionType::Mbr(entry.kind),
+            uuid: None,
+        });
+    }
+    if let Some(extended) = extended {
+        scan_logical(dev, &extended, &mut partitions)?;
+    }
+    Ok(partitions)
+}
+
+/// Follows the chain of EBRs in the `extended` partition.
+///
+/// Logical partitions are relative to their EBR, and the next EBR to the
+/// start of the extended partition.
+fn scan_logical<D: BlockDriverOps + ?Sized>(
+    dev: &mut D,
+    extended: &MbrEntry,
+    partitions: &mut Vec<PartitionInfo>,
+) -> DriverResult {
+    let end = extended.start + extended.blocks;
+    let mut ebr = extended.start;
+    let mut number = FIRST_LOGICAL;
+    for _ in 0..MAX_LOGICAL {
+        let Some(entries) = mbr_entries(&read(dev, ebr)?) else {
+            log::warn!("invalid extended boot record at block {ebr}");
+            break;
+        };
+        let (mut logical, mut next) = (None, None);
+        for entry in entries {
+            if MBR_EXTENDED.contains(&entry.kind) {
+                next.get_or_insert(extended.start + entry.start);
+            } else {
+                logical.get_or_insert(entry);
+            }
+        }
+        if let Some(entry) = logical {
+            let start = ebr + entry.start;
+            if fits(number, start, entry.blocks, end) {
+                partitions.push(PartitionInfo {
+                    number,
+                    start,
+                    blocks: entry.blocks,
+                    kind: PartitionType::Mbr(entry.kind),
+                    uuid: None,
+                });
+            }
+            number += 1;
+        }
+        // Each EBR must come after the previous one, or the chain loops.
+        match next {
+            Some(next) if next > ebr && next < end => ebr = next,
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// A GPT header that passed its checks.
+struct GptHeader {
+    first_usable: u64,
+    last_usable: u64,
+    entries: u64,
+    num_entries: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+fn scan_gpt<D: BlockDriverOps + ?Sized>(dev: &mut D) -> DriverResult<Vec<PartitionInfo>> {
+    let backup = dev.num_blocks() - 1;
+    for lba in [1, backup] {
+        let Some(header) = gpt_header(&read(dev, lba)?, lba, dev.num_blocks()) else {
+            log::warn!("invalid GPT header at block {lba}");
+            continue;
+        };
+        let block_size = dev.block_size();
+        let len = header.num_entries * header.entry_size;
+        let mut entries = vec![0u8; len.next_multiple_of(block_size)];
+        dev.submit_read(header.entries, &mut entries)?;
+        if kdecompress::crc32(&entries[..len]) != header.entries_crc {
+            log::warn!("invalid GPT entry array at block {}", header.entries);
+            continue;
+        }
+        if lba != 1 {
+            log::warn!("primary GPT is damaged, using the backup");
+        }
+        let mut partitions = Vec::new();
+        for (i, entry) in entries[..len].chunks_exact(header.entry_size).enumerate() {
+            let kind = guid(entry, 0);
+            if kind == Guid::ZERO {
+                continue;
+            }
+            let number = i as u32 + 1;
+            let (first, last) = (le_u64(entry, 32), le_u64(entry, 40));
+            if first < header.first_usable || last > header.last_usable || first > last {
+                log::warn!("partition {number} ({first}..={last}) lies outside the usable blocks");
+                continue;
+            }
+            partitions.push(PartitionInfo {
+                number,
+                start: first,
+                blocks: last - first + 1,
+                kind: PartitionType::Gpt(kind),
+                uuid: Some(guid(entry, 16)),
+            });
+        }
+        return Ok(partitions);
+    }
+    Ok(Vec::new())
+}
+
+/// Checks the GPT header read from block `lba` of a disk of `num_blocks`
+/// blocks.
+fn gpt_header(block: &[u8], lba: u64, num_blocks: u64) -> Option<GptHeader> {
+    if &block[..8] != GPT_SIGNATURE {
+        return None;
+    }
+    let size = le_u32(block, 12) as usize;
+    if !(GPT_HEADER_SIZE..=block.len()).contains(&size) {
+        return None;
+    }
+    let mut header = block[..size].to_vec();
+    header[16..20].fill(0);
+    if kdecompress::crc32(&header) != le_u32(block, 16) || le_u64(block, 24) != lba {
+        return None;
+    }
+    let header = GptHeader {
+        first_usable: le_u64(block, 40),
+        last_usable: le_u64(block, 48),
+        entries: le_u64(block, 72),
+        num_entries: le_u32(block, 80) as usize,
+        entry_size: le_u32(block, 84) as usize,
+        entries_crc: le_u32(block, 88),
+    };
+    let entries_len = header.num_entries.checked_mul(header.entry_size)?;
+    let entries_blocks = entries_len.div_ceil(block.len()) as u64;
+    let valid = header.entry_size >= GPT_ENTRY_SIZE
+        && header.entry_size.is_multiple_of(GPT_ENTRY_SIZE)
+        && entries_len <= GPT_MAX_ENTRIES_SIZE
+        && header.last_usable < num_blocks
+        && header.first_usable <= header.last_usable
+        && header
+            .entries
+            .checked_add(entries_blocks)
+            .is_some_and(|end| header.entries > 0 && end <= num_blocks);
+    valid.then_some(header)
+}
+
+#[cfg(unittest)]
+mod tests_partition {
+    use alloc::{vec, vec::Vec};
+
+    use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    const BLOCKS: u64 = 2048;
+
+    /// A disk in memory.
+    struct MemDisk(Vec<u8>);
+
+    impl MemDisk {
+        fn new() -> Self {
+            Self(vec![0; BLOCKS as usize * SECTOR_SIZE])
+        }
+
+        fn sector(&mut self, lba: u64) -> &mut [u8] {
+            &mut self.0[lba as usize * SECTOR_SIZE..][..SECTOR_SIZE]
+        }
+
+        /// Writes an MBR or EBR with `entries` of (system ID, start, blocks)
+        /// at `lba`.
+        fn mbr(&mut self, lba: u64, entries: &[(u8, u32, u32)]) {
+            let sector = self.sector(lba);
+            for (i, (kind, start, blocks)) in entries.iter().enumerate() {
+                let entry = &mut sector[MBR_ENTRIES + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+                entry[4] = *kind;
+                entry[8..12].copy_from_slice(&start.to_le_bytes());
+                entry[12..16].copy_from_slice(&blocks.to_le_bytes());
+            }
+            sector[510..512].copy_from_slice(&MBR_SIGNATURE);
+        }
+
+        /// Writes a GPT header at `lba` with 4 entries in the block at
+        /// `entries`, and the entries `parts` of (type, first, last).
+        fn gpt(&mut self, lba: u64, entries: u64, parts: &[(Guid, u64, u64)]) {
+            let mut array = vec![0u8; 4 * GPT_ENTRY_SIZE];
+            for (i, (kind, first, last)) in parts.iter().enumerate() {
+                let entry = &mut array[i * GPT_ENTRY_SIZE..][..GPT_ENTRY_SIZE];
+                entry[..16].copy_from_slice(&kind.0);
+                entry[16] = i as u8 + 1;
+                entry[32..40].copy_from_slice(&first.to_le_bytes());
+                entry[40..48].copy_from_slice(&last.to_le_bytes());
+            }
+            self.sector(entries).copy_from_slice(&array);
+
+            let header = self.sector(lba);
+            header[..8].copy_from_slice(GPT_SIGNATURE);
+            header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&lba.to_le_bytes());
+            header[40..48].copy_from_slice(&34u64.to_le_bytes());
+            header[48..56].copy_from_slice(&(BLOCKS - 34).to_le_bytes());
+            header[72..80].copy_from_slice(&entries.to_le_bytes());
+            header[80..84].copy_from_slice(&4u32.to_le_bytes());
+            header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+            header[88..92].copy_from_slice(&kdecompress::crc32(&array).to_le_bytes());
+            let crc = kdecompress::crc32(&header[..GPT_HEADER_SIZE]);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+        }
+    }
+
+    impl DriverOps for MemDisk {
+        fn name(&self) -> &str {
+            "mem"
+        }
+
+        fn device_kind(&self) -> DeviceKind {
+            DeviceKind::Block
+        }
+    }
+
+    impl BlockDriverOps for MemDisk {
+        fn num_blocks(&self) -> u64 {
+            BLOCKS
+        }
+
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+            let offset = block_id as usize * SECTOR_SIZE;
+            let data = self
+                .0
+                .get(offset..offset + buf.len())
+                .ok_or(DriverError::Io)?;
+            buf.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn write_block(&mut self, _block_id: u64, _buf: &[u8]) -> DriverResult {
+            Err(DriverError::Unsupported)
+        }
+
+        fn flush(&mut self) -> DriverResult {
+            Ok(())
+        }
+    }
+
+    fn numbers(partitions: &[PartitionInfo]) -> Vec<(u32, u64, u64)> {
+        partitions
+            .iter()
+            .map(|it| (it.number, it.start, it.blocks))
+            .collect()
+    }
+
+    #[def_test]
+    fn test_guid() {
+        let text = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+        assert_eq!(Guid::parse(text), Some(Guid::LINUX_FILESYSTEM));
+        assert_eq!(
+            Guid::parse("0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+            Some(Guid::LINUX_FILESYSTEM)
+        );
+        assert_eq!(alloc::format!("{}", Guid::LINUX_FILESYSTEM), text);
+        assert_eq!(Guid::LINUX_FILESYSTEM.0[..4], [0xaf, 0x3d, 0xc6, 0x0f]);
+        assert_eq!(Guid::parse("0fc63daf-8483-4772-8e79"), None);
+        assert_eq!(Guid::parse("0fc63daf-8483-4772-8e79-3d69d8477de4-00"), None);
+        assert_eq!(Guid::parse("+fc63daf-8483-4772-8e79-3d69d8477de4"), None);
+    }
+
+    #[def_test]
+    fn test_no_table() {
+        let mut disk = MemDisk::new();
+        assert_eq!(scan(&mut disk), Ok(Vec::new()));
+        // Boot code where the entries would be.
+        disk.mbr(0, &[]);
+        disk.sector(0)[MBR_ENTRIES] = 0x31;
+        assert_eq!(scan(&mut disk), Ok(Vec::new()));
+    }
+
+    #[def_test]
+    fn test_mbr_extended() {
+        let mut disk = MemDisk::new();
+        disk.mbr(
+            0,
+            &[
+                (MBR_LINUX, 64, 256),
+                (0x05, 512, 1024),
+                (MBR_LINUX, 2000, 100),
+            ],
+        );
+        // Logical partitions at 520 and 840, and a chain pointing backwards.
+        disk.mbr(512, &[(MBR_LINUX, 8, 200), (0x05, 320, 400)]);
+        disk.mbr(832, &[(0x82, 8, 100), (0x05, 0, 400)]);
+        let partitions = scan(&mut disk).unwrap();
+        assert_eq!(
+            numbers(&partitions),
+            vec![(1, 64, 256), (5, 520, 200), (6, 840, 100)]
+        );
+        assert!(partitions[0].kind.is_linux());
+        assert_eq!(partitions[2].kind, PartitionType::Mbr(0x82));
+        assert!(!partitions[2].kind.is_linux());
+    }
+
+    #[def_test]
+    fn test_gpt() {
+        let mut disk = MemDisk::new();
+        disk.mbr(0, &[(MBR_GPT_PROTECTIVE, 1, BLOCKS as u32 - 1)]);
+        let parts = [
+            (Guid::EFI_SYSTEM, 34, 99),
+            (Guid::ZERO, 0, 0),
+            (Guid::LINUX_FILESYSTEM, 100, 1999),
+            (Guid::LINUX_FILESYSTEM, 1000, 4000),
+        ];
+        disk.gpt(1, 2, &parts);
+        disk.gpt(BLOCKS - 1, BLOCKS - 33, &parts);
+        let partitions = scan(&mut disk).unwrap();
+        assert_eq!(numbers(&partitions), vec![(1, 34, 66), (3, 100, 1900)]);
+        assert_eq!(
+            partitions[1].kind,
+            PartitionType::Gpt(Guid::LINUX_FILESYSTEM)
+        );
+        assert_eq!(partitions[1].uuid.unwrap().0[0], 3);
+
+        // A damaged primary header falls back to the backup.
+        disk.sector(1)[40] ^= 1;
+        assert_eq!(
+            numbers(&scan(&mut disk).unwrap()),
+            vec![(1, 34, 66), (3, 100, 1900)]
+        );
+        // So does a damaged entry array.
+        disk.gpt(1, 2, &parts);
+        disk.sector(2)[32] ^= 1;
+        assert_eq!(scan(&mut disk).unwrap().len(), 2);
+        disk.sector(BLOCKS - 33)[32] ^= 1;
+        assert_eq!(scan(&mut disk), Ok(Vec::new()));
+    }
+
+    #[def_test]
+    fn test_translate() {
+        let info = PartitionInfo {
+            number: 1,
+            start: 100,
+            blocks: 10,
+            kind: PartitionType::Mbr(MBR_LINUX),
+            uuid: None,
+        };
+        assert_eq!(info.translate(0, 512, 512), Ok(100));
+        assert_eq!(info.translate(8, 1024, 512), Ok(108));
+        assert_eq!(info.translate(9, 1024, 512), Err(DriverError::Io));
+        assert_eq!(info.translate(10, 512, 512), Err(DriverError::Io));
+        assert_eq!(info.translate(u64::MAX, 512, 512), Err(DriverError::Io));
+    }
+}
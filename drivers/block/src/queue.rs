@@ -0,0 +1,860 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Request queues of block devices.
+//!
+//! Instead of issuing every request to the driver as it comes, filesystems
+//! [`submit`](BlockQueue::submit) requests to the queue of the device and
+//! [`wait`](BlockQueue::wait) for them later, or have a callback run on
+//! completion. Queued requests are dispatched one after the other by
+//! whoever waits:
+//!
+//! - Reads or writes of adjacent blocks are merged into a single request to
+//!   the driver, up to a maximum size.
+//! - Requests are dispatched in ascending block order from where the last
+//!   one ended, wrapping around, except that the oldest request goes first
+//!   once it has been passed over for [`DEADLINE`] dispatches.
+//! - A request never overtakes an older one it conflicts with: one of
+//!   overlapping blocks if either writes, or any flush.
+//!
+//! The device is locked only while a request is issued, and failed requests
+//! are retried with the [default policy](RetryPolicy::DEFAULT).
+//!
+//! Requests of whole blocks aside, [`read_at`](BlockQueue::read_at) and
+//! [`write_at`](BlockQueue::write_at) access any range of bytes. Sectors
+//! only partly covered go through a bounce buffer, and are read back before
+//! being written; such read-modify-writes are serialized, so that partial
+//! writes to different bytes of a sector are not lost.
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use driver_base::{DriverError, DriverResult};
+use kspin::SpinNoPreempt;
+
+use crate::{BlockDriverOps, BlockLimits, RetryPolicy};
+
+/// Maximum size in bytes of merged requests by [`BlockQueue::new`].
+pub const DEFAULT_MAX_SEGMENT: usize = 128 * 1024;
+
+/// Number of dispatches after which the oldest request is dispatched ahead of
+/// the block order.
+pub const DEADLINE: u64 = 16;
+
+/// Operation of a [`Bio`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BioOp {
+    /// Reads blocks into the buffer.
+    Read,
+    /// Writes the buffer to blocks.
+    Write,
+    /// Flushes the device to write all pending data to the storage.
+    Flush,
+}
+
+/// A block request: `op` on the blocks from `block_id` on, as many as `buf`
+/// holds.
+#[derive(Debug)]
+pub struct Bio {
+    /// Operation of the request.
+    pub op: BioOp,
+    /// First block of the request.
+    pub block_id: u64,
+    /// Data to write or buffer to read into, a multiple of the block size.
+    /// Empty for flushes.
+    pub buf: Vec<u8>,
+}
+
+impl Bio {
+    /// Returns a request to read `len` bytes from `block_id` on.
+    pub fn read(block_id: u64, len: usize) -> Self {
+        Self {
+            op: BioOp::Read,
+            block_id,
+            buf: vec![0; len],
+        }
+    }
+
+    /// Returns a request to write `data` from `block_id` on.
+    pub fn write(block_id: u64, data: Vec<u8>) -> Self {
+        Self {
+            op: BioOp::Write,
+            block_id,
+            buf: data,
+        }
+    }
+
+    /// Returns a request to flush the device.
+    pub fn flush() -> Self {
+        Self {
+            op: BioOp::Flush,
+            block_id: 0,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Statistics of a [`BlockQueue`].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct BlockQueueStats {
+    /// Requests submitted.
+    pub requests: u64,
+    /// Requests merged into another one instead of being dispatched on their
+    /// own.
+    pub merged: u64,
+    /// Requests issued to the driver, after merging.
+    pub dispatched: u64,
+    /// Blocks read or written.
+    pub sectors: u64,
+    /// Requests currently queued.
+    pub queued: usize,
+    /// Most requests queued at once.
+    pub max_depth: usize,
+}
+
+/// Called with the result and the buffer of a completed request.
+type Callback = Box<dyn FnOnce(DriverResult, Vec<u8>) + Send>;
+
+struct Completion {
+    done: AtomicBool,
+    result: SpinNoPreempt<Option<(DriverResult, Vec<u8>)>>,
+}
+
+/// Handle to wait for a submitted request with, see [`BlockQueue::wait`].
+#[must_use]
+pub struct BioHandle(Arc<Completion>);
+
+impl BioHandle {
+    /// Returns the handle of `bio`, which failed with `err` before it could
+    /// be queued.
+    pub fn failed(bio: Bio, err: DriverError) -> Self {
+        Self(Arc::new(Completion {
+            done: AtomicBool::new(true),
+            result: SpinNoPreempt::new(Some((Err(err), bio.buf))),
+        }))
+    }
+
+    /// Returns whether the request has completed.
+    pub fn is_done(&self) -> bool {
+        self.0.done.load(Ordering::Acquire)
+    }
+}
+
+/// How the submitter of a request learns about its completion.
+enum Notify {
+    Handle(Arc<Completion>),
+    Callback(Callback),
+}
+
+impl Notify {
+    /// Completes the request, returning the callback to run, if any.
+    fn complete(
+        self,
+        result: DriverResult,
+        buf: Vec<u8>,
+    ) -> Option<(Callback, DriverResult, Vec<u8>)> {
+        match self {
+            Notify::Handle(completion) => {
+                *completion.result.lock() = Some((result, buf));
+                completion.done.store(true, Ordering::Release);
+                None
+            }
+            Notify::Callback(callback) => Some((callback, result, buf)),
+        }
+    }
+}
+
+struct Queued {
+    bio: Bio,
+    /// Number of dispatches after which the request goes first.
+    deadline: u64,
+    notify: Notify,
+}
+
+impl Queued {
+    /// Returns the block after the last block of the request.
+    fn end(&self, block_size: usize) -> u64 {
+        self.bio.block_id + (self.bio.buf.len() / block_size) as u64
+    }
+
+    /// Returns whether `self` must not be dispatched before `older`.
+    fn conflicts(&self, older: &Self, block_size: usize) -> bool {
+        match (self.bio.op, older.bio.op) {
+            (BioOp::Flush, _) | (_, BioOp::Flush) => true,
+            (BioOp::Read, BioOp::Read) => false,
+            _ => {
+                self.bio.block_id < older.end(block_size)
+                    && older.bio.block_id < self.end(block_size)
+            }
+        }
+    }
+}
+
+struct QueueState {
+    /// Queued requests, oldest first.
+    requests: Vec<Queued>,
+    /// Number of dispatches so far.
+    dispatches: u64,
+    /// Block after the last dispatched request.
+    head: u64,
+    stats: BlockQueueStats,
+}
+
+impl QueueState {
+    /// Takes the next request to dispatch out of the queue, along with the
+    /// requests merged with it, sorted by block.
+    fn pick(&mut self, block_size: usize, max_segment: usize) -> Option<Vec<Queued>> {
+        let requests = &self.requests;
+        let ready: Vec<usize> = (0..requests.len())
+            .filter(|&i| {
+                let request = &requests[i];
+                requests[..i]
+                    .iter()
+                    .all(|older| !request.conflicts(older, block_size))
+            })
+            .collect();
+        let oldest = *ready.first()?;
+        let chosen = if requests[oldest].deadline <= self.dispatches {
+            oldest
+        } else {
+            let head = self.head;
+            ready
+                .iter()
+                .copied()
+                .min_by_key(|&i| {
+                    let block_id = requests[i].bio.block_id;
+                    (block_id < head, block_id)
+                })
+                .unwrap()
+        };
+
+        let op = requests[chosen].bio.op;
+        let mut start = requests[chosen].bio.block_id;
+        let mut end = requests[chosen].end(block_size);
+        let mut len = requests[chosen].bio.buf.len();
+        let mut batch = vec![chosen];
+        while op != BioOp::Flush {
+            let next = ready.iter().copied().find(|&i| {
+                let request = &requests[i];
+                request.bio.op == op
+                    && !batch.contains(&i)
+                    && len + request.bio.buf.len() <= max_segment
+                    && (request.bio.block_id == end || request.end(block_size) == start)
+            });
+            let Some(next) = next else {
+                break;
+            };
+            start = start.min(requests[next].bio.block_id);
+            end = end.max(requests[next].end(block_size));
+            len += requests[next].bio.buf.len();
+            batch.push(next);
+        }
+
+        self.stats.merged += batch.len() as u64 - 1;
+        self.stats.dispatched += 1;
+        self.stats.sectors += end - start;
+        self.dispatches += 1;
+        if op != BioOp::Flush {
+            self.head = end;
+        }
+        batch.sort_unstable();
+        let mut taken: Vec<_> = batch
+            .into_iter()
+            .rev()
+            .map(|i| self.requests.remove(i))
+            .collect();
+        taken.sort_unstable_by_key(|it| it.bio.block_id);
+        Some(taken)
+    }
+}
+
+/// A block device with a queue of pending requests.
+pub struct BlockQueue<D> {
+    device: SpinNoPreempt<D>,
+    state: SpinNoPreempt<QueueState>,
+    /// Set while somebody dispatches requests.
+    dispatching: AtomicBool,
+    /// Held across the read-modify-write of a partial sector.
+    rmw: SpinNoPreempt<()>,
+    limits: BlockLimits,
+    block_size: usize,
+    max_segment: usize,
+}
+
+impl<D: BlockDriverOps> BlockQueue<D> {
+    /// Creates a queue merging requests up to [`DEFAULT_MAX_SEGMENT`] bytes.
+    pub fn new(device: D) -> Self {
+        Self::with_max_segment(device, DEFAULT_MAX_SEGMENT)
+    }
+
+    /// Creates a queue merging requests up to `max_segment` bytes.
+    pub fn with_max_segment(device: D, max_segment: usize) -> Self {
+        Self {
+            limits: BlockLimits::of(&device),
+            block_size: device.block_size(),
+            device: SpinNoPreempt::new(device),
+            state: SpinNoPreempt::new(QueueState {
+                requests: Vec::new(),
+                dispatches: 0,
+                head: 0,
+                stats: BlockQueueStats::default(),
+            }),
+            dispatching: AtomicBool::new(false),
+            rmw: SpinNoPreempt::new(()),
+            max_segment,
+        }
+    }
+
+    /// Runs `f` with exclusive access to the device.
+    pub fn with_device<R>(&self, f: impl FnOnce(&mut D) -> R) -> R {
+        f(&mut self.device.lock())
+    }
+
+    /// Returns the block size of the device.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the sector sizes and the preferred request size of the
+    /// device.
+    pub fn limits(&self) -> BlockLimits {
+        self.limits
+    }
+
+    /// Returns the statistics of the queue.
+    pub fn stats(&self) -> BlockQueueStats {
+        let state = self.state.lock();
+        BlockQueueStats {
+            queued: state.requests.len(),
+            ..state.stats
+        }
+    }
+
+    /// Queues `bio`, to be waited for with the returned handle.
+    pub fn submit(&self, bio: Bio) -> BioHandle {
+        let completion = Arc::new(Completion {
+            done: AtomicBool::new(false),
+            result: SpinNoPreempt::new(None),
+        });
+        self.enqueue(bio, Notify::Handle(completion.clone()));
+        BioHandle(completion)
+    }
+
+    /// Queues `bio`, and calls `callback` once it has completed.
+    ///
+    /// The callback is run by whoever dispatched the request, and must not
+    /// wait for requests itself.
+    pub fn submit_with(
+        &self,
+        bio: Bio,
+        callback: impl FnOnce(DriverResult, Vec<u8>) + Send + 'static,
+    ) {
+        self.enqueue(bio, Notify::Callback(Box::new(callback)));
+    }
+
+    fn enqueue(&self, bio: Bio, notify: Notify) {
+        if bio.op != BioOp::Flush
+            && (bio.buf.is_empty() || !bio.buf.len().is_multiple_of(self.block_size))
+        {
+            if let Some((callback, result, buf)) =
+                notify.complete(Err(DriverError::InvalidInput), bio.buf)
+            {
+                callback(result, buf);
+            }
+            return;
+        }
+        let mut state = self.state.lock();
+        let deadline = state.dispatches + DEADLINE;
+        state.requests.push(Queued {
+            bio,
+            deadline,
+            notify,
+        });
+        state.stats.requests += 1;
+        state.stats.max_depth = state.stats.max_depth.max(state.requests.len());
+    }
+
+    /// Waits for the request of `handle`, dispatching queued requests in the
+    /// meantime, and returns its result and buffer.
+    pub fn wait(&self, handle: BioHandle) -> (DriverResult, Vec<u8>) {
+        while !handle.is_done() {
+            if self.try_dispatch().is_none() {
+                spin_loop();
+            }
+        }
+        handle.0.result.lock().take().unwrap()
+    }
+
+    /// Dispatches all queued requests.
+    pub fn run(&self) {
+        loop {
+            match self.try_dispatch() {
+                Some(true) => {}
+                Some(false) => return,
+                None => spin_loop(),
+            }
+        }
+    }
+
+    /// Reads blocks like [`BlockDriverOps::read_block`], after the queued
+    /// requests it conflicts with.
+    pub fn read(&self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+        let len = buf.len();
+        if let Some(result) =
+            self.try_direct("read", block_id, len, |dev| dev.read_block(block_id, buf))
+        {
+            return result;
+        }
+        let (result, data) = self.wait(self.submit(Bio::read(block_id, len)));
+        if result.is_ok() {
+            buf.copy_from_slice(&data);
+        }
+        result
+    }
+
+    /// Writes blocks like [`BlockDriverOps::write_block`], after the queued
+    /// requests it conflicts with.
+    pub fn write(&self, block_id: u64, buf: &[u8]) -> DriverResult {
+        if let Some(result) = self.try_direct("write", block_id, buf.len(), |dev| {
+            dev.write_block(block_id, buf)
+        }) {
+            return result;
+        }
+        self.wait(self.submit(Bio::write(block_id, buf.to_vec()))).0
+    }
+
+    /// Reads `buf.len()` bytes at byte `offset` of the device, whatever their
+    /// alignment.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> DriverResult {
+        let mut done = 0;
+        while done < buf.len() {
+            let (block_id, start, len) = self.split(offset + done as u64, buf.len() - done);
+            let dst = &mut buf[done..done + len];
+            if start == 0 && len.is_multiple_of(self.block_size) {
+                self.read(block_id, dst)?;
+            } else {
+                let mut sector = vec![0; self.block_size];
+                self.read(block_id, &mut sector)?;
+                dst.copy_from_slice(&sector[start..start + len]);
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` at byte `offset` of the device, whatever its alignment.
+    ///
+    /// The sectors at either end that `buf` only partly covers are read,
+    /// patched and written back.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> DriverResult {
+        let mut done = 0;
+        while done < buf.len() {
+            let (block_id, start, len) = self.split(offset + done as u64, buf.len() - done);
+            let src = &buf[done..done + len];
+            if start == 0 && len.is_multiple_of(self.block_size) {
+                self.write(block_id, src)?;
+            } else {
+                let _rmw = self.rmw.lock();
+                let mut sector = vec![0; self.block_size];
+                self.read(block_id, &mut sector)?;
+                sector[start..start + len].copy_from_slice(src);
+                self.write(block_id, &sector)?;
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Returns the next piece of a request of `len` bytes at byte `offset`:
+    /// its block, where it starts in the block and its length. The piece is
+    /// either the whole sectors from an aligned `offset` on, or what the
+    /// request covers of a single sector.
+    fn split(&self, offset: u64, len: usize) -> (u64, usize, usize) {
+        let block_id = offset / self.block_size as u64;
+        let start = (offset % self.block_size as u64) as usize;
+        if start == 0 && len >= self.block_size {
+            (block_id, 0, len - len % self.block_size)
+        } else {
+            (block_id, start, len.min(self.block_size - start))
+        }
+    }
+
+    /// Flushes the device like [`BlockDriverOps::flush`], after all queued
+    /// requests.
+    pub fn flush(&self) -> DriverResult {
+        if let Some(result) = self.try_direct("flush", 0, 0, |dev| dev.flush()) {
+            return result;
+        }
+        self.wait(self.submit(Bio::flush())).0
+    }
+
+    /// Issues a request of `len` bytes to the device right away if nothing
+    /// is queued or being dispatched.
+    fn try_direct(
+        &self,
+        kind: &str,
+        block_id: u64,
+        len: usize,
+        op: impl FnMut(&mut D) -> DriverResult,
+    ) -> Option<DriverResult> {
+        if !self.try_claim() {
+            return None;
+        }
+        let mut state = self.state.lock();
+        if !state.requests.is_empty() {
+            drop(state);
+            self.dispatching.store(false, Ordering::Release);
+            return None;
+        }
+        state.stats.requests += 1;
+        state.stats.dispatched += 1;
+        state.stats.sectors += (len / self.block_size) as u64;
+        state.stats.max_depth = state.stats.max_depth.max(1);
+        drop(state);
+
+        let result = RetryPolicy::DEFAULT.run(&mut *self.device.lock(), kind, block_id, op);
+        self.dispatching.store(false, Ordering::Release);
+        Some(result)
+    }
+
+    fn try_claim(&self) -> bool {
+        self.dispatching
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Dispatches the next requests unless somebody else is dispatching.
+    ///
+    /// Returns whether there were any, or `None` if somebody else is.
+    fn try_dispatch(&self) -> Option<bool> {
+        if !self.try_claim() {
+            return None;
+        }
+        let batch = self.state.lock().pick(self.block_size, self.max_segment);
+        let Some(mut batch) = batch else {
+            self.dispatching.store(false, Ordering::Release);
+            return Some(false);
+        };
+
+        let op = batch[0].bio.op;
+        let block_id = batch[0].bio.block_id;
+        let mut device = self.device.lock();
+        let dev = &mut *device;
+        let policy = RetryPolicy::DEFAULT;
+        let result = match op {
+            BioOp::Read if batch.len() == 1 => policy.run(dev, "read", block_id, |dev| {
+                dev.read_block(block_id, &mut batch[0].bio.buf)
+            }),
+            BioOp::Read => {
+                let len = batch.iter().map(|it| it.bio.buf.len()).sum::<usize>();
+                let mut buf = vec![0; len];
+                let result = policy.run(dev, "read", block_id, |dev| {
+                    dev.read_block(block_id, &mut buf)
+                });
+                if result.is_ok() {
+                    let mut rest = buf.as_slice();
+                    for queued in &mut batch {
+                        let (data, next) = rest.split_at(queued.bio.buf.len());
+                        queued.bio.buf.copy_from_slice(data);
+                        rest = next;
+                    }
+                }
+                result
+            }
+            BioOp::Write if batch.len() == 1 => policy.run(dev, "write", block_id, |dev| {
+                dev.write_block(block_id, &batch[0].bio.buf)
+            }),
+            BioOp::Write => {
+                let buf = batch
+                    .iter()
+                    .map(|it| it.bio.buf.as_slice())
+                    .collect::<Vec<_>>()
+                    .concat();
+                policy.run(dev, "write", block_id, |dev| {
+                    dev.write_block(block_id, &buf)
+                })
+            }
+            BioOp::Flush => policy.run(dev, "flush", 0, |dev| dev.flush()),
+        };
+        drop(device);
+
+        let callbacks: Vec<_> = batch
+            .into_iter()
+            .filter_map(|it| it.notify.complete(result, it.bio.buf))
+            .collect();
+        self.dispatching.store(false, Ordering::Release);
+        for (callback, result, buf) in callbacks {
+            callback(result, buf);
+        }
+        Some(true)
+    }
+}
+
+#[cfg(unittest)]
+mod tests_queue {
+    use alloc::{sync::Arc, vec, vec::Vec};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use driver_base::{DeviceKind, DriverError, DriverOps};
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    const BLOCK: usize = 512;
+
+    /// An in-memory disk of 64 blocks that records the requests issued to
+    /// it.
+    struct MemDisk {
+        data: Vec<u8>,
+        issued: Vec<(BioOp, u64, usize)>,
+        block_size: usize,
+        physical_block_size: usize,
+    }
+
+    impl MemDisk {
+        fn new() -> Self {
+            Self::with_sectors(BLOCK, BLOCK)
+        }
+
+        fn with_sectors(logical: usize, physical: usize) -> Self {
+            Self {
+                data: vec![0; 64 * logical],
+                issued: Vec::new(),
+                block_size: logical,
+                physical_block_size: physical,
+            }
+        }
+    }
+
+    impl DriverOps for MemDisk {
+        fn name(&self) -> &str {
+            "mem"
+        }
+
+        fn device_kind(&self) -> DeviceKind {
+            DeviceKind::Block
+        }
+    }
+
+    impl BlockDriverOps for MemDisk {
+        fn num_blocks(&self) -> u64 {
+            64
+        }
+
+        fn block_size(&self) -> usize {
+            self.block_size
+        }
+
+        fn physical_block_size(&self) -> usize {
+            self.physical_block_size
+        }
+
+        fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+            let start = block_id as usize * self.block_size;
+            buf.copy_from_slice(&self.data[start..start + buf.len()]);
+            self.issued
+                .push((BioOp::Read, block_id, buf.len() / self.block_size));
+            Ok(())
+        }
+
+        fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DriverResult {
+            let start = block_id as usize * self.block_size;
+            self.data[start..start + buf.len()].copy_from_slice(buf);
+            self.issued
+                .push((BioOp::Write, block_id, buf.len() / self.block_size));
+            Ok(())
+        }
+
+        fn flush(&mut self) -> DriverResult {
+            self.issued.push((BioOp::Flush, 0, 0));
+            Ok(())
+        }
+    }
+
+    fn issued(queue: &BlockQueue<MemDisk>) -> Vec<(BioOp, u64, usize)> {
+        queue.with_device(|dev| dev.issued.clone())
+    }
+
+    #[def_test]
+    fn test_adjacent_requests_merge() {
+        let queue = BlockQueue::new(MemDisk::new());
+        let writes: Vec<_> = [6u8, 5, 7]
+            .into_iter()
+            .map(|block| queue.submit(Bio::write(block as u64, vec![block; BLOCK])))
+            .collect();
+        let far = queue.submit(Bio::write(20, vec![20; BLOCK]));
+        for handle in writes {
+            assert_eq!(queue.wait(handle).0, Ok(()));
+        }
+        assert_eq!(queue.wait(far).0, Ok(()));
+
+        let (result, data) = queue.wait(queue.submit(Bio::read(5, 3 * BLOCK)));
+        assert_eq!(result, Ok(()));
+        for (i, block) in data.chunks(BLOCK).enumerate() {
+            assert!(block.iter().all(|&b| b == 5 + i as u8));
+        }
+
+        assert_eq!(
+            issued(&queue),
+            [
+                (BioOp::Write, 5, 3),
+                (BioOp::Write, 20, 1),
+                (BioOp::Read, 5, 3)
+            ]
+        );
+        let stats = queue.stats();
+        assert_eq!(stats.requests, 5);
+        assert_eq!(stats.merged, 2);
+        assert_eq!(stats.dispatched, 3);
+        assert_eq!(stats.sectors, 7);
+        assert_eq!(stats.max_depth, 4);
+        assert_eq!(stats.queued, 0);
+    }
+
+    #[def_test]
+    fn test_merge_size_limit() {
+        let queue = BlockQueue::with_max_segment(MemDisk::new(), 2 * BLOCK);
+        for block in 0..5 {
+            let _ = queue.submit(Bio::write(block, vec![1; BLOCK]));
+        }
+        queue.run();
+        assert_eq!(
+            issued(&queue),
+            [
+                (BioOp::Write, 0, 2),
+                (BioOp::Write, 2, 2),
+                (BioOp::Write, 4, 1)
+            ]
+        );
+    }
+
+    #[def_test]
+    fn test_conflicting_requests_keep_order() {
+        let queue = BlockQueue::new(MemDisk::new());
+        let _ = queue.submit(Bio::write(9, vec![1; BLOCK]));
+        let read = queue.submit(Bio::read(9, BLOCK));
+        let _ = queue.submit(Bio::flush());
+        let _ = queue.submit(Bio::write(0, vec![2; BLOCK]));
+        let (result, data) = queue.wait(read);
+        assert_eq!(result, Ok(()));
+        assert!(data.iter().all(|&b| b == 1));
+        queue.run();
+        assert_eq!(
+            issued(&queue),
+            [
+                (BioOp::Write, 9, 1),
+                (BioOp::Read, 9, 1),
+                (BioOp::Flush, 0, 0),
+                (BioOp::Write, 0, 1)
+            ]
+        );
+    }
+
+    #[def_test]
+    fn test_deadline_overrides_block_order() {
+        let queue = BlockQueue::new(MemDisk::new());
+        let _ = queue.submit(Bio::read(60, BLOCK));
+        for block in 0..20 {
+            let _ = queue.submit(Bio::read(block * 2, BLOCK));
+        }
+        queue.run();
+        let order: Vec<_> = issued(&queue).iter().map(|it| it.1).collect();
+        let passed_over: Vec<u64> = (0..DEADLINE).map(|it| it * 2).collect();
+        assert_eq!(&order[..DEADLINE as usize], &passed_over[..]);
+        assert_eq!(order[DEADLINE as usize], 60);
+    }
+
+    #[def_test]
+    fn test_callbacks_and_invalid_requests() {
+        let queue = BlockQueue::new(MemDisk::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = done.clone();
+        queue.submit_with(Bio::write(1, vec![3; BLOCK]), move |result, buf| {
+            assert_eq!(result, Ok(()));
+            assert_eq!(buf.len(), BLOCK);
+            flag.store(true, Ordering::Release);
+        });
+        assert!(!done.load(Ordering::Acquire));
+        queue.run();
+        assert!(done.load(Ordering::Acquire));
+
+        let (result, _) = queue.wait(queue.submit(Bio::read(0, BLOCK + 1)));
+        assert_eq!(result, Err(DriverError::InvalidInput));
+
+        // Nothing queued: issued directly.
+        let mut buf = [0; BLOCK];
+        queue.read(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 3));
+        assert_eq!(queue.stats().requests, 2);
+    }
+
+    /// Writes and reads back byte ranges at every misalignment, checking the
+    /// disk against a plain buffer holding what was written.
+    fn check_byte_requests(logical: usize, physical: usize) {
+        let queue = BlockQueue::new(MemDisk::with_sectors(logical, physical));
+        let limits = queue.limits();
+        assert_eq!(limits.logical_block_size, logical);
+        assert_eq!(limits.physical_block_size, physical);
+
+        let mut model = vec![0u8; 64 * logical];
+        let starts = [0, 1, logical / 2, logical - 1];
+        let lens = [
+            0,
+            1,
+            logical / 2,
+            logical - 1,
+            logical,
+            logical + 1,
+            3 * logical - 1,
+            3 * logical,
+        ];
+        let mut seed = 1u8;
+        for start in starts {
+            for len in lens {
+                let offset = 2 * logical + start;
+                let data: Vec<u8> = (0..len).map(|i| seed.wrapping_add(i as u8)).collect();
+                seed = seed.wrapping_add(37);
+
+                let reads = |queue: &BlockQueue<MemDisk>| {
+                    issued(queue)
+                        .iter()
+                        .filter(|it| it.0 == BioOp::Read)
+                        .count()
+                };
+                let before = reads(&queue);
+                queue.write_at(offset as u64, &data).unwrap();
+                model[offset..offset + len].copy_from_slice(&data);
+                // Only partly covered sectors are read first.
+                let end = start + len;
+                let partial = (start / logical..end.div_ceil(logical))
+                    .filter(|k| len > 0 && (start > k * logical || (k + 1) * logical > end))
+                    .count();
+                assert_eq!(reads(&queue) - before, partial);
+
+                let mut buf = vec![0; len + 2];
+                queue.read_at(offset as u64 - 1, &mut buf).unwrap();
+                assert_eq!(buf[..], model[offset - 1..offset + len + 1]);
+            }
+        }
+        assert_eq!(queue.with_device(|dev| dev.data.clone()), model);
+    }
+
+    #[def_test]
+    fn test_byte_requests_512() {
+        check_byte_requests(512, 512);
+    }
+
+    #[def_test]
+    fn test_byte_requests_4096_logical() {
+        check_byte_requests(4096, 4096);
+    }
+
+    #[def_test]
+    fn test_byte_requests_512e() {
+        check_byte_requests(512, 4096);
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Block requests as issued by filesystems, with retries.
+//!
+//! A failed request is retried while its error is
+//! [`Transient`](Retryability::Transient), up to the limit of the
+//! [`RetryPolicy`]. Permanent errors and lost devices are returned at once.
+//! Every failure is logged with the detail reported by the device.
+
+use driver_base::{DriverResult, Retryability};
+
+use crate::BlockDriverOps;
+
+/// How often failed block requests are repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one for transient errors.
+    pub max_retries: u32,
+}
+
+impl RetryPolicy {
+    /// The policy used by [`BlockRequestExt`].
+    pub const DEFAULT: Self = Self { max_retries: 3 };
+    /// A policy that never retries.
+    pub const NEVER: Self = Self { max_retries: 0 };
+
+    /// Runs `op`, the request `kind` on `block_id` of `dev`, retrying it
+    /// according to the policy.
+    pub fn run<D: BlockDriverOps + ?Sized, T>(
+        &self,
+        dev: &mut D,
+        kind: &str,
+        block_id: u64,
+        mut op: impl FnMut(&mut D) -> DriverResult<T>,
+    ) -> DriverResult<T> {
+        let mut attempt = 0;
+        loop {
+            let err = match op(dev) {
+                Ok(value) => {
+                    if attempt > 0 {
+                        let dev_name = dev.name();
+                        log::info!(
+                            "{dev_name}: {kind} of block {block_id} succeeded after {attempt} \
+                             retries"
+                        );
+                    }
+                    return Ok(value);
+                }
+                Err(err) => err,
+            };
+            let dev_name = dev.name();
+            match err.retryability() {
+                Retryability::Transient if attempt < self.max_retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "{dev_name}: {kind} of block {block_id} failed: {err}, retrying \
+                         ({attempt}/{})",
+                        self.max_retries
+                    );
+                }
+                Retryability::Transient => {
+                    log::error!(
+                        "{dev_name}: {kind} of block {block_id} failed: {err}, giving up after \
+                         {attempt} retries"
+                    );
+                    return Err(err);
+                }
+                Retryability::Permanent => {
+                    log::error!("{dev_name}: {kind} of block {block_id} failed: {err}");
+                    return Err(err);
+                }
+                Retryability::DeviceLost => {
+                    log::error!(
+                        "{dev_name}: {kind} of block {block_id} failed: {err}, device lost"
+                    );
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+/// Block requests with the [default retry policy](RetryPolicy::DEFAULT).
+///
+/// Filesystems should use these instead of calling [`BlockDriverOps`]
+/// directly.
+pub trait BlockRequestExt: BlockDriverOps {
+    /// Reads blocks like [`BlockDriverOps::read_block`], retrying transient
+    /// failures.
+    fn submit_read(&mut self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+        RetryPolicy::DEFAULT.run(self, "read", block_id, |dev| dev.read_block(block_id, buf))
+    }
+
+    /// Writes blocks like [`BlockDriverOps::write_block`], retrying transient
+    /// failures.
+    fn submit_write(&mut self, block_id: u64, buf: &[u8]) -> DriverResult {
+        RetryPolicy::DEFAULT.run(self, "write", block_id, |dev| {
+            dev.write_block(block_id, buf)
+        })
+    }
+
+    /// Flushes the device like [`BlockDriverOps::flush`], retrying transient
+    /// failures.
+    fn submit_flush(&mut self) -> DriverResult {
+        RetryPolicy::DEFAULT.run(self, "flush", 0, |dev| dev.flush())
+    }
+}
+
+impl<T: BlockDriverOps + ?Sized> BlockRequestExt for T {}
+
+#[cfg(unittest)]
+mod tests_request {
+    use driver_base::{DeviceError, DeviceKind, DriverError, DriverOps, ErrorClass};
+    use unittest::{assert_eq, def_test};
+
+    use super::*;
+
+    /// A disk that fails its next requests with the given errors.
+    struct FaultyDisk {
+        faults: [Option<DriverError>; 8],
+        next_fault: usize,
+        attempts: usize,
+    }
+
+    impl FaultyDisk {
+        fn new(faults: &[DriverError]) -> Self {
+            let mut disk = Self {
+                faults: [None; 8],
+                next_fault: 0,
+                attempts: 0,
+            };
+            for (slot, fault) in disk.faults.iter_mut().zip(faults) {
+                *slot = Some(*fault);
+            }
+            disk
+        }
+
+        fn request(&mut self) -> DriverResult {
+            self.attempts += 1;
+            let fault = self.faults.get(self.next_fault).copied().flatten();
+            self.next_fault += 1;
+            fault.map_or(Ok(()), Err)
+        }
+    }
+
+    impl DriverOps for FaultyDisk {
+        fn name(&self) -> &str {
+            "faulty"
+        }
+
+        fn device_kind(&self) -> DeviceKind {
+            DeviceKind::Block
+        }
+    }
+
+    impl BlockDriverOps for FaultyDisk {
+        fn num_blocks(&self) -> u64 {
+            16
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn read_block(&mut self, _block_id: u64, _buf: &mut [u8]) -> DriverResult {
+            self.request()
+        }
+
+        fn write_block(&mut self, _block_id: u64, _buf: &[u8]) -> DriverResult {
+            self.request()
+        }
+
+        fn flush(&mut self) -> DriverResult {
+            self.request()
+        }
+    }
+
+    const CRC: DriverError = DriverError::device(ErrorClass::Transport, 0x84);
+    const TIMEOUT: DriverError = DriverError::device(ErrorClass::Timeout, 0);
+
+    #[def_test]
+    fn test_transient_errors_are_retried() {
+        let mut disk = FaultyDisk::new(&[CRC, TIMEOUT]);
+        let mut buf = [0u8; 512];
+        assert_eq!(disk.submit_read(3, &mut buf), Ok(()));
+        assert_eq!(disk.attempts, 3);
+
+        let mut disk = FaultyDisk::new(&[CRC; 8]);
+        assert_eq!(disk.submit_write(3, &buf), Err(CRC));
+        assert_eq!(disk.attempts, 1 + RetryPolicy::DEFAULT.max_retries as usize);
+    }
+
+    #[def_test]
+    fn test_permanent_errors_propagate() {
+        let media = DriverError::Device(
+            DeviceError::new(ErrorClass::Media, 0x51).with_sense(&[0x70, 0, 0x03]),
+        );
+        for err in [
+            media,
+            DriverError::device(ErrorClass::DeviceLost, 0xffff_ffff),
+            DriverError::InvalidInput,
+            DriverError::Io,
+        ] {
+            let mut disk = FaultyDisk::new(&[err]);
+            assert_eq!(disk.submit_flush(), Err(err));
+            assert_eq!(disk.attempts, 1);
+        }
+
+        let mut disk = FaultyDisk::new(&[CRC]);
+        assert_eq!(
+            RetryPolicy::NEVER.run(&mut disk, "flush", 0, |dev| dev.flush()),
+            Err(CRC)
+        );
+    }
+}
@@ -47,6 +47,17 @@ impl<'a> FrameBuffer<'a> {
     }
 }
 
+/// A physically contiguous part of a buffer to scan out.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanoutSegment {
+    /// The address the CPU accesses the segment at.
+    pub vaddr: usize,
+    /// The address the device accesses the segment at.
+    pub bus_addr: u64,
+    /// The length of the segment in bytes.
+    pub len: usize,
+}
+
 /// Operations that require a graphics device driver to implement.
 pub trait DisplayDriverOps: DriverOps {
     /// Get the display information.
@@ -60,6 +71,17 @@ pub trait DisplayDriverOps: DriverOps {
 
     /// Flush framebuffer to the screen.
     fn flush(&mut self) -> DriverResult;
+
+    /// Scans out the buffer made of `segments`, laid out as the
+    /// framebuffer, instead of the framebuffer; or the framebuffer again if
+    /// `segments` is empty.
+    ///
+    /// The buffer must stay alive until another one is scanned out. Devices
+    /// that can only scan out their framebuffer return
+    /// [`DriverError::Unsupported`], and the buffer is copied to it instead.
+    fn set_scanout(&mut self, _segments: &[ScanoutSegment]) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
 }
 
 mod tests;
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Failures reported by devices, and how callers should react to them.
+
+use core::fmt;
+
+use kerrno::{KError, LinuxError};
+
+use crate::DriverError;
+
+/// How a failed request should be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retryability {
+    /// The same request may succeed if issued again.
+    Transient,
+    /// The request will fail again; report it to the caller.
+    Permanent,
+    /// The device is gone, every further request will fail.
+    DeviceLost,
+}
+
+/// The class of a failure reported by a device, similar to an errno.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The medium could not be read or written, e.g. an uncorrectable ECC
+    /// error.
+    Media,
+    /// The data was corrupted between host and device, e.g. a CRC error on
+    /// the link.
+    Transport,
+    /// The device did not complete the request in time.
+    Timeout,
+    /// The device cannot take the request now, e.g. its queue is full.
+    Busy,
+    /// The device refused the request, e.g. an unsupported command.
+    Rejected,
+    /// There is no medium in the device.
+    NoMedium,
+    /// The medium is write-protected.
+    WriteProtected,
+    /// The device stopped responding or was removed.
+    DeviceLost,
+    /// The device failed without telling why.
+    Unknown,
+}
+
+impl ErrorClass {
+    /// Stable error message for display/logging.
+    pub const fn message(self) -> &'static str {
+        match self {
+            ErrorClass::Media => "Media error",
+            ErrorClass::Transport => "Transport error",
+            ErrorClass::Timeout => "Device timed out",
+            ErrorClass::Busy => "Device is busy",
+            ErrorClass::Rejected => "Request rejected by device",
+            ErrorClass::NoMedium => "No medium found",
+            ErrorClass::WriteProtected => "Medium is write-protected",
+            ErrorClass::DeviceLost => "Device lost",
+            ErrorClass::Unknown => "Device error",
+        }
+    }
+
+    /// Returns whether repeating a request that failed this way may succeed.
+    pub const fn retryability(self) -> Retryability {
+        match self {
+            ErrorClass::Transport | ErrorClass::Timeout | ErrorClass::Busy => {
+                Retryability::Transient
+            }
+            ErrorClass::DeviceLost => Retryability::DeviceLost,
+            ErrorClass::Media
+            | ErrorClass::Rejected
+            | ErrorClass::NoMedium
+            | ErrorClass::WriteProtected
+            | ErrorClass::Unknown => Retryability::Permanent,
+        }
+    }
+}
+
+/// Sense data returned by a device along with a failure, e.g. SCSI fixed
+/// format sense data.
+///
+/// Longer data is truncated to [`SenseData::CAPACITY`] bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SenseData {
+    len: u8,
+    bytes: [u8; Self::CAPACITY],
+}
+
+impl SenseData {
+    /// Maximum number of bytes kept.
+    pub const CAPACITY: usize = 18;
+    /// No sense data.
+    pub const EMPTY: Self = Self {
+        len: 0,
+        bytes: [0; Self::CAPACITY],
+    };
+
+    /// Copies up to [`CAPACITY`](Self::CAPACITY) bytes of `data`.
+    pub fn new(data: &[u8]) -> Self {
+        let len = data.len().min(Self::CAPACITY);
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..len].copy_from_slice(&data[..len]);
+        Self {
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    /// Returns the sense data bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Returns `true` if there is no sense data.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl fmt::Debug for SenseData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SenseData({:02x?})", self.as_bytes())
+    }
+}
+
+/// A failure reported by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceError {
+    /// What kind of failure it is.
+    pub class: ErrorClass,
+    /// The raw device status, e.g. the virtio-blk status byte. Its meaning
+    /// depends on the driver.
+    pub status: u32,
+    /// Sense data from the device, if any.
+    pub sense: SenseData,
+}
+
+impl DeviceError {
+    /// Creates an error without sense data.
+    pub const fn new(class: ErrorClass, status: u32) -> Self {
+        Self {
+            class,
+            status,
+            sense: SenseData::EMPTY,
+        }
+    }
+
+    /// Attaches the sense data `sense`.
+    pub fn with_sense(mut self, sense: &[u8]) -> Self {
+        self.sense = SenseData::new(sense);
+        self
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (status {:#x}", self.class.message(), self.status)?;
+        if !self.sense.is_empty() {
+            f.write_str(", sense")?;
+            for byte in self.sense.as_bytes() {
+                write!(f, " {byte:02x}")?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+impl From<DeviceError> for DriverError {
+    fn from(err: DeviceError) -> Self {
+        DriverError::Device(err)
+    }
+}
+
+/// Converts a driver error into the error returned by system calls.
+///
+/// Device errors keep their class: a timeout becomes `ETIMEDOUT`, a lost
+/// device `ENODEV` and so on, rather than a generic `EIO`.
+impl From<DriverError> for KError {
+    fn from(err: DriverError) -> Self {
+        match err {
+            DriverError::AlreadyExists => KError::AlreadyExists,
+            DriverError::WouldBlock => KError::WouldBlock,
+            DriverError::BadState => KError::BadState,
+            DriverError::InvalidInput => KError::InvalidInput,
+            DriverError::Io => KError::Io,
+            DriverError::NoMemory => KError::NoMemory,
+            DriverError::ResourceBusy => KError::ResourceBusy,
+            DriverError::Unsupported => KError::Unsupported,
+            DriverError::Device(err) => match err.class {
+                ErrorClass::Media | ErrorClass::Transport | ErrorClass::Unknown => KError::Io,
+                ErrorClass::Timeout => KError::TimedOut,
+                ErrorClass::Busy => KError::ResourceBusy,
+                ErrorClass::Rejected => KError::OperationNotSupported,
+                ErrorClass::NoMedium => KError::from(LinuxError::ENOMEDIUM),
+                ErrorClass::WriteProtected => KError::ReadOnlyFilesystem,
+                ErrorClass::DeviceLost => KError::NoSuchDevice,
+            },
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod tests_error {
+    extern crate alloc;
+    use alloc::string::ToString;
+
+    use unittest::{assert_eq, def_test};
+
+    use super::*;
+
+    #[def_test]
+    fn test_device_error_display() {
+        let err = DeviceError::new(ErrorClass::Media, 0x51).with_sense(&[0x70, 0x00, 0x03]);
+        assert_eq!(
+            DriverError::from(err).to_string(),
+            "Media error (status 0x51, sense 70 00 03)"
+        );
+        assert_eq!(
+            DriverError::device(ErrorClass::Timeout, 0).to_string(),
+            "Device timed out (status 0x0)"
+        );
+        assert_eq!(
+            SenseData::new(&[0xaa; 32]).as_bytes().len(),
+            SenseData::CAPACITY
+        );
+    }
+
+    #[def_test]
+    fn test_kerror_keeps_class() {
+        let cases = [
+            (ErrorClass::Media, KError::Io),
+            (ErrorClass::Timeout, KError::TimedOut),
+            (ErrorClass::Rejected, KError::OperationNotSupported),
+            (ErrorClass::NoMedium, KError::from(LinuxError::ENOMEDIUM)),
+            (ErrorClass::WriteProtected, KError::ReadOnlyFilesystem),
+            (ErrorClass::DeviceLost, KError::NoSuchDevice),
+        ];
+        for (class, expected) in cases {
+            assert_eq!(KError::from(DriverError::device(class, 0)), expected);
+        }
+        assert_eq!(
+            KError::from(DriverError::ResourceBusy),
+            KError::ResourceBusy
+        );
+    }
+}
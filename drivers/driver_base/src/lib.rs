@@ -20,6 +20,10 @@
 #![no_std]
 #![allow(rustdoc::broken_intra_doc_links)]
 
+mod error;
+
+pub use self::error::{DeviceError, ErrorClass, Retryability, SenseData};
+
 /// All supported device kinds.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum DeviceKind {
@@ -38,7 +42,7 @@ pub enum DeviceKind {
 }
 
 /// The error type for driver operation failures.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DriverError {
     /// An entity already exists.
     AlreadyExists,
@@ -56,9 +60,16 @@ pub enum DriverError {
     ResourceBusy,
     /// This operation is unsupported or unimplemented.
     Unsupported,
+    /// The device reported a failure, with what it told about it.
+    Device(DeviceError),
 }
 
 impl DriverError {
+    /// Shorthand for a [`DriverError::Device`] without sense data.
+    pub const fn device(class: ErrorClass, status: u32) -> Self {
+        DriverError::Device(DeviceError::new(class, status))
+    }
+
     /// Stable error message for display/logging.
     pub const fn message(&self) -> &'static str {
         match self {
@@ -70,13 +81,31 @@ impl DriverError {
             DriverError::NoMemory => "Not enough memory",
             DriverError::ResourceBusy => "Resource is busy",
             DriverError::Unsupported => "Unsupported operation",
+            DriverError::Device(err) => err.class.message(),
+        }
+    }
+
+    /// Returns whether repeating the failed request may succeed.
+    ///
+    /// Errors that do not come from the device are permanent, except for
+    /// the ones caused by a temporary lack of resources.
+    pub const fn retryability(&self) -> Retryability {
+        match self {
+            DriverError::WouldBlock | DriverError::NoMemory | DriverError::ResourceBusy => {
+                Retryability::Transient
+            }
+            DriverError::Device(err) => err.class.retryability(),
+            _ => Retryability::Permanent,
         }
     }
 }
 
 impl core::fmt::Display for DriverError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.write_str(self.message())
+        match self {
+            DriverError::Device(err) => write!(f, "{err}"),
+            _ => f.write_str(self.message()),
+        }
     }
 }
 
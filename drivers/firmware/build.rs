@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+use std::{
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Collects the regular files under `dir`, with their paths relative to
+/// `root`.
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, files)?;
+        } else if path.is_file() {
+            let name = path.strip_prefix(root).unwrap();
+            let name = name
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-env-changed=FIRMWARE_DIR");
+    let mut files = Vec::new();
+    if let Ok(dir) = std::env::var("FIRMWARE_DIR")
+        && !dir.is_empty()
+    {
+        println!("cargo:rerun-if-changed={dir}");
+        let dir = Path::new(&dir);
+        collect(dir, dir, &mut files)?;
+        files.sort();
+    }
+
+    let mut out = String::new();
+    for (i, (_, path)) in files.iter().enumerate() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        writeln!(
+            out,
+            "#[unsafe(link_section = \".rodata.firmware\")]\nstatic FIRMWARE_{i}: [u8; {}] = \
+             *include_bytes!({:?});",
+            std::fs::metadata(path)?.len(),
+            path.canonicalize()?,
+        )
+        .unwrap();
+    }
+    out.push_str("static BUILTIN: &[(&str, &[u8])] = &[\n");
+    for (i, (name, _)) in files.iter().enumerate() {
+        writeln!(out, "    ({name:?}, &FIRMWARE_{i}),").unwrap();
+    }
+    out.push_str("];\n");
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("builtin.rs"), out)
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Copies of firmware in DMA memory, for devices fetching it themselves.
+
+use core::{alloc::Layout, slice};
+
+use kdma::{DMAInfo, DmaBusAddress, allocate_dma_memory, deallocate_dma_memory};
+use kerrno::{KError, KResult};
+
+use crate::FirmwareBlob;
+
+/// Firmware copied into coherent DMA memory, freed on drop.
+pub struct DmaFirmware {
+    dma: DMAInfo,
+    layout: Layout,
+    len: usize,
+}
+
+// SAFETY: the memory is owned by the `DmaFirmware` and only read through it.
+unsafe impl Send for DmaFirmware {}
+unsafe impl Sync for DmaFirmware {}
+
+impl DmaFirmware {
+    /// Returns the address of the firmware for the device.
+    pub fn bus_addr(&self) -> DmaBusAddress {
+        self.dma.bus_addr
+    }
+
+    /// Returns the firmware content.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: `len` bytes were initialized from the firmware.
+        unsafe { slice::from_raw_parts(self.dma.cpu_addr.as_ptr(), self.len) }
+    }
+
+    /// Returns the size of the firmware in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the firmware is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for DmaFirmware {
+    fn drop(&mut self) {
+        unsafe { deallocate_dma_memory(self.dma, self.layout) };
+    }
+}
+
+impl FirmwareBlob {
+    /// Copies the firmware into coherent DMA memory aligned to `align`.
+    ///
+    /// Fails with [`KError::InvalidInput`] if `align` is not a power of two.
+    pub fn to_dma(&self, align: usize) -> KResult<DmaFirmware> {
+        let len = self.len();
+        let layout =
+            Layout::from_size_align(len.max(1), align).map_err(|_| KError::InvalidInput)?;
+        let dma = unsafe { allocate_dma_memory(layout) }.map_err(|_| KError::NoMemory)?;
+        // SAFETY: the allocation holds at least `len` bytes and is not shared.
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.as_ptr(), dma.cpu_addr.as_ptr(), len);
+        }
+        Ok(DmaFirmware { dma, layout, len })
+    }
+}
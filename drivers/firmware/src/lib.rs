@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Firmware loading for device drivers.
+//!
+//! Firmware is looked up by name, such as `vendor/device.bin`, in order:
+//!
+//! 1. among the firmware built into the kernel, the files under the directory
+//!    named by the `FIRMWARE_DIR` environment variable at build time;
+//! 2. with the loaders added by [`register_firmware_loader`], in the order
+//!    they were added. The runtime adds one reading the initramfs and the
+//!    root filesystem's `/lib/firmware` once they are mounted, and then calls
+//!    [`firmware_loaders_ready`].
+//!
+//! Drivers probing before that get built-in firmware only, and
+//! [`KError::WouldBlock`] for the rest: they either defer their probe or use
+//! [`request_firmware_nowait`].
+//!
+//! Loaded firmware is shared: requesting it again while a [`FirmwareBlob`] of
+//! it is alive returns the same data, which is freed with the last handle.
+#![no_std]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod dma;
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{fmt, ops::Deref};
+
+pub use dma::DmaFirmware;
+use kerrno::{KError, KResult};
+use kspin::SpinNoIrq;
+
+include!(concat!(env!("OUT_DIR"), "/builtin.rs"));
+
+/// Loads the firmware of the given name, failing with [`KError::NotFound`]
+/// to let the next loader try.
+pub type FirmwareLoader = fn(&str) -> KResult<Vec<u8>>;
+
+/// Called with the result of [`request_firmware_nowait`].
+pub type FirmwareCallback = Box<dyn FnOnce(KResult<FirmwareBlob>) + Send>;
+
+enum FirmwareData {
+    Builtin(&'static [u8]),
+    Loaded(Vec<u8>),
+}
+
+struct Firmware {
+    name: String,
+    data: FirmwareData,
+}
+
+/// A shared handle to loaded firmware.
+#[derive(Clone)]
+pub struct FirmwareBlob(Arc<Firmware>);
+
+impl FirmwareBlob {
+    /// Returns the name the firmware was requested with.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Returns the firmware content.
+    pub fn data(&self) -> &[u8] {
+        match &self.0.data {
+            FirmwareData::Builtin(data) => data,
+            FirmwareData::Loaded(data) => data,
+        }
+    }
+
+    /// Returns whether the firmware is built into the kernel.
+    pub fn is_builtin(&self) -> bool {
+        matches!(self.0.data, FirmwareData::Builtin(_))
+    }
+
+    /// Returns whether `self` and `other` share the same data.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for FirmwareBlob {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data()
+    }
+}
+
+impl fmt::Debug for FirmwareBlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FirmwareBlob")
+            .field("name", &self.name())
+            .field("len", &self.len())
+            .field("builtin", &self.is_builtin())
+            .finish()
+    }
+}
+
+struct RegistryState {
+    loaders: Vec<FirmwareLoader>,
+    ready: bool,
+    pending: Vec<(String, FirmwareCallback)>,
+    cache: BTreeMap<String, Weak<Firmware>>,
+}
+
+/// The firmware sources and the firmware loaded from them.
+struct Registry {
+    builtin: &'static [(&'static str, &'static [u8])],
+    state: SpinNoIrq<RegistryState>,
+}
+
+impl Registry {
+    const fn new(builtin: &'static [(&'static str, &'static [u8])]) -> Self {
+        Self {
+            builtin,
+            state: SpinNoIrq::new(RegistryState {
+                loaders: Vec::new(),
+                ready: false,
+                pending: Vec::new(),
+                cache: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn builtin(&self, name: &str) -> Option<&'static [u8]> {
+        self.builtin
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, data)| *data)
+    }
+
+    fn register(&self, loader: FirmwareLoader) {
+        self.state.lock().loaders.push(loader);
+    }
+
+    fn request(&self, name: &str) -> KResult<FirmwareBlob> {
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+            return Err(KError::InvalidInput);
+        }
+        let loaders = {
+            let state = self.state.lock();
+            if let Some(firmware) = state.cache.get(name).and_then(Weak::upgrade) {
+                return Ok(FirmwareBlob(firmware));
+            }
+            if self.builtin(name).is_none() && !state.ready {
+                return Err(KError::WouldBlock);
+            }
+            state.loaders.clone()
+        };
+
+        // Loaders may sleep on I/O, so they run without the lock held.
+        let data = match self.builtin(name) {
+            Some(data) => FirmwareData::Builtin(data),
+            None => FirmwareData::Loaded(Self::load(&loaders, name)?),
+        };
+        let mut state = self.state.lock();
+        state
+            .cache
+            .retain(|_, firmware| firmware.strong_count() > 0);
+        // Someone else may have loaded it in the meantime.
+        if let Some(firmware) = state.cache.get(name).and_then(Weak::upgrade) {
+            return Ok(FirmwareBlob(firmware));
+        }
+        let firmware = Arc::new(Firmware {
+            name: name.to_string(),
+            data,
+        });
+        state
+            .cache
+            .insert(name.to_string(), Arc::downgrade(&firmware));
+        Ok(FirmwareBlob(firmware))
+    }
+
+    fn load(loaders: &[FirmwareLoader], name: &str) -> KResult<Vec<u8>> {
+        for loader in loaders {
+            match loader(name) {
+                Err(KError::NotFound) => continue,
+                Err(err) => {
+                    warn!("firmware: failed to load {name}: {err:?}");
+                    return Err(err);
+                }
+                Ok(data) => {
+                    debug!("firmware: loaded {name}, {} bytes", data.len());
+                    return Ok(data);
+                }
+            }
+        }
+        warn!("firmware: {name} not found");
+        Err(KError::NotFound)
+    }
+
+    fn request_nowait(&self, name: &str, callback: FirmwareCallback) {
+        {
+            let mut state = self.state.lock();
+            if !state.ready && self.builtin(name).is_none() {
+                state.pending.push((name.to_string(), callback));
+                return;
+            }
+        }
+        callback(self.request(name));
+    }
+
+    fn set_ready(&self) {
+        let pending = {
+            let mut state = self.state.lock();
+            state.ready = true;
+            core::mem::take(&mut state.pending)
+        };
+        for (name, callback) in pending {
+            callback(self.request(&name));
+        }
+    }
+
+    fn release(&self, firmware: FirmwareBlob) {
+        let name = firmware.name().to_string();
+        drop(firmware);
+        let mut state = self.state.lock();
+        if state
+            .cache
+            .get(&name)
+            .is_some_and(|firmware| firmware.strong_count() == 0)
+        {
+            state.cache.remove(&name);
+        }
+    }
+}
+
+static REGISTRY: Registry = Registry::new(BUILTIN);
+
+/// Adds `loader` after the registered ones.
+pub fn register_firmware_loader(loader: FirmwareLoader) {
+    REGISTRY.register(loader);
+}
+
+/// Marks the registered loaders as usable, completing the requests made with
+/// [`request_firmware_nowait`] so far.
+///
+/// Called by the runtime once filesystems are mounted, or right away when
+/// there are none.
+pub fn firmware_loaders_ready() {
+    REGISTRY.set_ready();
+}
+
+/// Returns the firmware `name`, from the built-in firmware or else from the
+/// first loader that has it.
+///
+/// Fails with [`KError::WouldBlock`] if it is not built in and the loaders
+/// are not ready yet, with [`KError::NotFound`] if no source has it, and with
+/// [`KError::InvalidInput`] for names going up with `..` or starting with
+/// `/`.
+pub fn request_firmware(name: &str) -> KResult<FirmwareBlob> {
+    REGISTRY.request(name)
+}
+
+/// Calls `callback` with the firmware `name` once it can be loaded.
+///
+/// Built-in firmware, and any firmware once the loaders are ready, is passed
+/// to `callback` right away, on the calling thread. Otherwise `callback` is
+/// called from [`firmware_loaders_ready`].
+pub fn request_firmware_nowait(name: &str, callback: FirmwareCallback) {
+    REGISTRY.request_nowait(name, callback);
+}
+
+/// Drops `firmware`, freeing its data if this was the last handle to it.
+pub fn release_firmware(firmware: FirmwareBlob) {
+    REGISTRY.release(firmware);
+}
+
+#[cfg(unittest)]
+mod tests_firmware {
+    use alloc::{boxed::Box, vec, vec::Vec};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    static BUILTIN_TABLE: &[(&str, &[u8])] = &[("vendor/builtin.bin", b"builtin")];
+
+    fn initramfs_loader(name: &str) -> KResult<Vec<u8>> {
+        match name {
+            "vendor/early.bin" => Ok(vec![1, 2, 3]),
+            _ => Err(KError::NotFound),
+        }
+    }
+
+    fn rootfs_loader(name: &str) -> KResult<Vec<u8>> {
+        match name {
+            "vendor/early.bin" => Ok(vec![0xff]),
+            "vendor/late.bin" => Ok(vec![4, 5]),
+            "vendor/broken.bin" => Err(KError::Io),
+            _ => Err(KError::NotFound),
+        }
+    }
+
+    fn ready_registry() -> Registry {
+        let registry = Registry::new(BUILTIN_TABLE);
+        registry.register(initramfs_loader);
+        registry.register(rootfs_loader);
+        registry.set_ready();
+        registry
+    }
+
+    #[def_test]
+    fn test_builtin_before_loaders() {
+        let registry = Registry::new(BUILTIN_TABLE);
+        registry.register(rootfs_loader);
+        let firmware = registry.request("vendor/builtin.bin").unwrap();
+        assert!(firmware.is_builtin());
+        assert_eq!(firmware.data(), b"builtin");
+        assert_eq!(
+            registry.request("vendor/late.bin").unwrap_err(),
+            KError::WouldBlock
+        );
+    }
+
+    #[def_test]
+    fn test_loaders_in_order() {
+        let registry = ready_registry();
+        let early = registry.request("vendor/early.bin").unwrap();
+        assert!(!early.is_builtin());
+        assert_eq!(early.data(), &[1, 2, 3]);
+        assert_eq!(registry.request("vendor/late.bin").unwrap().data(), &[4, 5]);
+    }
+
+    #[def_test]
+    fn test_not_found() {
+        let registry = ready_registry();
+        assert_eq!(
+            registry.request("vendor/missing.bin").unwrap_err(),
+            KError::NotFound
+        );
+        assert_eq!(
+            registry.request("vendor/broken.bin").unwrap_err(),
+            KError::Io
+        );
+        for name in ["", "/lib/firmware/a.bin", "vendor/../../etc/passwd"] {
+            assert_eq!(registry.request(name).unwrap_err(), KError::InvalidInput);
+        }
+    }
+
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_loader(name: &str) -> KResult<Vec<u8>> {
+        LOADS.fetch_add(1, Ordering::Relaxed);
+        rootfs_loader(name)
+    }
+
+    #[def_test]
+    fn test_cache_and_release() {
+        let registry = Registry::new(BUILTIN_TABLE);
+        registry.register(counting_loader);
+        registry.set_ready();
+        LOADS.store(0, Ordering::Relaxed);
+
+        let first = registry.request("vendor/late.bin").unwrap();
+        let second = registry.request("vendor/late.bin").unwrap();
+        assert!(first.ptr_eq(&second));
+        assert_eq!(LOADS.load(Ordering::Relaxed), 1);
+
+        registry.release(first);
+        assert!(registry.state.lock().cache.contains_key("vendor/late.bin"));
+        registry.release(second);
+        assert!(!registry.state.lock().cache.contains_key("vendor/late.bin"));
+
+        registry.request("vendor/late.bin").unwrap();
+        assert_eq!(LOADS.load(Ordering::Relaxed), 2);
+    }
+
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    #[def_test]
+    fn test_nowait_deferred_until_ready() {
+        let registry = Registry::new(BUILTIN_TABLE);
+        registry.register(rootfs_loader);
+        COMPLETED.store(0, Ordering::Relaxed);
+
+        let callback = |expected: usize| -> FirmwareCallback {
+            Box::new(move |result| {
+                assert_eq!(result.unwrap().len(), expected);
+                COMPLETED.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+        registry.request_nowait("vendor/builtin.bin", callback(7));
+        assert_eq!(COMPLETED.load(Ordering::Relaxed), 1);
+        registry.request_nowait("vendor/late.bin", callback(2));
+        assert_eq!(COMPLETED.load(Ordering::Relaxed), 1);
+
+        registry.set_ready();
+        assert_eq!(COMPLETED.load(Ordering::Relaxed), 2);
+        registry.request_nowait("vendor/late.bin", callback(2));
+        assert_eq!(COMPLETED.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Gesture storm: a two-finger gesture reported at a high rate, delivered to
+//! a reader once through `read()` and once through the shared event ring.
+//!
+//! The `read()` path is modeled by a pipe, read one event at a time and then
+//! into a 64-event buffer like libevdev does. The ring is filled in batches
+//! every millisecond, like the kernel does, and the reader only makes a
+//! syscall to sleep on its doorbell when the ring runs empty. The CPU time
+//! of the reader thread, taken from `/proc/thread-self/schedstat`, and its
+//! syscall count are compared, and the checksums of the delivered streams
+//! must match the one generated. Run with `cargo bench -p input`.
+
+use std::{
+    fs,
+    io::{PipeReader, PipeWriter, Read, Write},
+    ptr::NonNull,
+    thread,
+    time::{Duration, Instant},
+};
+
+use input::{RING_CONTROL_SIZE, RingConsumer, RingProducer, ring_size};
+
+/// Packets in the storm, each moving two fingers.
+const PACKETS: usize = 40_000;
+/// Time between two packets, i.e. an 8 kHz report rate.
+const PACKET_INTERVAL: Duration = Duration::from_micros(125);
+const RING_ENTRIES: u32 = 1024;
+/// How often the kernel moves events into the ring.
+const PUMP_PERIOD: Duration = Duration::from_millis(1);
+
+/// `struct input_event` of a 64-bit process.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Record {
+    sec: i64,
+    usec: i64,
+    event_type: u16,
+    code: u16,
+    value: i32,
+}
+
+impl Record {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() }
+    }
+}
+
+fn packet(step: usize, start: Instant) -> Vec<Record> {
+    let time = start.elapsed();
+    let record = |event_type, code, value| Record {
+        sec: time.as_secs() as i64,
+        usec: time.subsec_micros() as i64,
+        event_type,
+        code,
+        value,
+    };
+    let mut records = Vec::new();
+    for slot in 0..2 {
+        // ABS_MT_SLOT, ABS_MT_POSITION_X, ABS_MT_POSITION_Y
+        records.push(record(3, 0x2f, slot));
+        records.push(record(3, 0x35, (step * 3) as i32 + slot));
+        records.push(record(3, 0x36, (step * 5) as i32 + slot));
+    }
+    // SYN_REPORT
+    records.push(record(0, 0, 0));
+    records
+}
+
+/// Generates the storm at its report rate, handing each packet to `emit`.
+fn storm(mut emit: impl FnMut(&[Record])) {
+    let start = Instant::now();
+    for step in 0..PACKETS {
+        while start.elapsed() < PACKET_INTERVAL * step as u32 {
+            std::hint::spin_loop();
+        }
+        emit(&packet(step, start));
+    }
+}
+
+fn checksum(hash: u64, record: &Record) -> u64 {
+    let word = ((record.event_type as u64) << 48)
+        | ((record.code as u64) << 32)
+        | record.value as u32 as u64;
+    (hash ^ word).wrapping_mul(0x0000_0100_0000_01b3)
+}
+
+const CHECKSUM_SEED: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// CPU time consumed by the calling thread.
+fn thread_cpu_time() -> Duration {
+    let stat = fs::read_to_string("/proc/thread-self/schedstat").unwrap();
+    let nanos = stat.split_whitespace().next().unwrap().parse().unwrap();
+    Duration::from_nanos(nanos)
+}
+
+/// What the reader observed.
+struct Delivery {
+    records: usize,
+    checksum: u64,
+    syscalls: usize,
+    cpu: Duration,
+}
+
+/// Delivers the storm through a pipe read `batch` events at a time.
+fn read_path(batch: usize) -> Delivery {
+    let (mut rx, mut tx): (PipeReader, PipeWriter) = std::io::pipe().unwrap();
+    thread::scope(|s| {
+        let reader = s.spawn(move || {
+            let cpu = thread_cpu_time();
+            let mut delivery = Delivery {
+                records: 0,
+                checksum: CHECKSUM_SEED,
+                syscalls: 0,
+                cpu: Duration::ZERO,
+            };
+            let mut buf = vec![0; batch * size_of::<Record>()];
+            let mut filled = 0;
+            loop {
+                let len = rx.read(&mut buf[filled..]).unwrap();
+                delivery.syscalls += 1;
+                if len == 0 {
+                    break;
+                }
+                filled += len;
+                let whole = filled - filled % size_of::<Record>();
+                for chunk in buf[..whole].chunks_exact(size_of::<Record>()) {
+                    delivery.checksum = checksum(delivery.checksum, &Record::from_bytes(chunk));
+                    delivery.records += 1;
+                }
+                buf.copy_within(whole..filled, 0);
+                filled -= whole;
+            }
+            delivery.cpu = thread_cpu_time() - cpu;
+            delivery
+        });
+        storm(|records| {
+            for record in records {
+                tx.write_all(record.as_bytes()).unwrap();
+            }
+        });
+        drop(tx);
+        reader.join().unwrap()
+    })
+}
+
+#[repr(C, align(4096))]
+#[derive(Clone)]
+struct Page([u8; RING_CONTROL_SIZE]);
+
+fn ring_path() -> Delivery {
+    let size = ring_size::<Record>(RING_ENTRIES).unwrap();
+    let mut memory = vec![Page([0; RING_CONTROL_SIZE]); size / RING_CONTROL_SIZE];
+    let mem = NonNull::new(memory.as_mut_ptr().cast::<u8>()).unwrap();
+    let mut producer = unsafe { RingProducer::<Record>::new(mem, RING_ENTRIES) }.unwrap();
+    let mut consumer = unsafe { RingConsumer::<Record>::new(mem) };
+    let (mut doorbell_rx, mut doorbell_tx) = std::io::pipe().unwrap();
+
+    let delivery = thread::scope(|s| {
+        let reader = s.spawn(move || {
+            let cpu = thread_cpu_time();
+            let mut delivery = Delivery {
+                records: 0,
+                checksum: CHECKSUM_SEED,
+                syscalls: 0,
+                cpu: Duration::ZERO,
+            };
+            let mut bell = [0; 64];
+            loop {
+                // The ring is revoked after the last record, so one more
+                // round delivers everything.
+                let revoked = consumer.is_revoked();
+                while let Some(record) = consumer.pop() {
+                    delivery.checksum = checksum(delivery.checksum, &record);
+                    delivery.records += 1;
+                }
+                if revoked {
+                    break;
+                }
+                delivery.syscalls += 1;
+                if doorbell_rx.read(&mut bell).unwrap() == 0 {
+                    break;
+                }
+            }
+            delivery.cpu = thread_cpu_time() - cpu;
+            delivery
+        });
+        let dropped = || Record {
+            event_type: 0,
+            code: 3,
+            ..Default::default()
+        };
+        let mut pending = Vec::new();
+        let mut pump = |records: &[Record]| {
+            for record in records {
+                producer.push(*record, dropped);
+            }
+            if producer.publish() {
+                doorbell_tx.write_all(&[1]).unwrap();
+            }
+        };
+        let mut last_pump = Instant::now();
+        storm(|records| {
+            pending.extend_from_slice(records);
+            if last_pump.elapsed() >= PUMP_PERIOD {
+                pump(&pending);
+                pending.clear();
+                last_pump = Instant::now();
+            }
+        });
+        pump(&pending);
+        producer.revoke();
+        doorbell_tx.write_all(&[1]).unwrap();
+        reader.join().unwrap()
+    });
+    let dropped = unsafe { mem.cast::<input::RingHeader>().as_ref() }
+        .dropped
+        .load(std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(dropped, 0, "the ring overflowed");
+    drop(memory);
+    delivery
+}
+
+fn report(name: &str, delivery: &Delivery) {
+    eprintln!(
+        "{name:>6}: {} events, {} syscalls, {:?} CPU ({:.1} us per 1000 events), checksum {:016x}",
+        delivery.records,
+        delivery.syscalls,
+        delivery.cpu,
+        delivery.cpu.as_secs_f64() * 1e9 / delivery.records as f64,
+        delivery.checksum,
+    );
+}
+
+fn main() {
+    let mut expected = CHECKSUM_SEED;
+    let start = Instant::now();
+    for step in 0..PACKETS {
+        for record in packet(step, start) {
+            expected = checksum(expected, &record);
+        }
+    }
+
+    let single = read_path(1);
+    report("read", &single);
+    let batched = read_path(64);
+    report("read64", &batched);
+    let ring = ring_path();
+    report("ring", &ring);
+
+    for delivery in [&single, &batched] {
+        assert_eq!(
+            delivery.checksum, expected,
+            "read() delivered a different stream"
+        );
+    }
+    assert_eq!(
+        ring.checksum, expected,
+        "the ring delivered a different stream"
+    );
+}
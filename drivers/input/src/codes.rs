@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Event codes, as defined in Linux `input-event-codes.h`.
+//!
+//! Only the codes used by drivers in this tree are listed; any other code
+//! from the Linux header is equally valid.
+
+/// Codes of [`EventType::Synchronization`](crate::EventType::Synchronization)
+/// events.
+pub mod syn {
+    pub const SYN_REPORT: u16 = 0;
+    pub const SYN_CONFIG: u16 = 1;
+    pub const SYN_MT_REPORT: u16 = 2;
+    pub const SYN_DROPPED: u16 = 3;
+}
+
+/// Codes of [`EventType::Key`](crate::EventType::Key) events.
+pub mod key {
+    pub const KEY_RESERVED: u16 = 0;
+    pub const KEY_ESC: u16 = 1;
+    pub const KEY_1: u16 = 2;
+    pub const KEY_2: u16 = 3;
+    pub const KEY_3: u16 = 4;
+    pub const KEY_4: u16 = 5;
+    pub const KEY_5: u16 = 6;
+    pub const KEY_6: u16 = 7;
+    pub const KEY_7: u16 = 8;
+    pub const KEY_8: u16 = 9;
+    pub const KEY_9: u16 = 10;
+    pub const KEY_0: u16 = 11;
+    pub const KEY_BACKSPACE: u16 = 14;
+    pub const KEY_TAB: u16 = 15;
+    pub const KEY_Q: u16 = 16;
+    pub const KEY_W: u16 = 17;
+    pub const KEY_E: u16 = 18;
+    pub const KEY_R: u16 = 19;
+    pub const KEY_T: u16 = 20;
+    pub const KEY_Y: u16 = 21;
+    pub const KEY_U: u16 = 22;
+    pub const KEY_I: u16 = 23;
+    pub const KEY_O: u16 = 24;
+    pub const KEY_P: u16 = 25;
+    pub const KEY_ENTER: u16 = 28;
+    pub const KEY_LEFTCTRL: u16 = 29;
+    pub const KEY_A: u16 = 30;
+    pub const KEY_S: u16 = 31;
+    pub const KEY_D: u16 = 32;
+    pub const KEY_F: u16 = 33;
+    pub const KEY_G: u16 = 34;
+    pub const KEY_H: u16 = 35;
+    pub const KEY_J: u16 = 36;
+    pub const KEY_K: u16 = 37;
+    pub const KEY_L: u16 = 38;
+    pub const KEY_LEFTSHIFT: u16 = 42;
+    pub const KEY_Z: u16 = 44;
+    pub const KEY_X: u16 = 45;
+    pub const KEY_C: u16 = 46;
+    pub const KEY_V: u16 = 47;
+    pub const KEY_B: u16 = 48;
+    pub const KEY_N: u16 = 49;
+    pub const KEY_M: u16 = 50;
+    pub const KEY_RIGHTSHIFT: u16 = 54;
+    pub const KEY_LEFTALT: u16 = 56;
+    pub const KEY_SPACE: u16 = 57;
+    pub const KEY_CAPSLOCK: u16 = 58;
+    pub const KEY_F1: u16 = 59;
+    pub const KEY_F2: u16 = 60;
+    pub const KEY_F3: u16 = 61;
+    pub const KEY_F4: u16 = 62;
+    pub const KEY_F5: u16 = 63;
+    pub const KEY_F6: u16 = 64;
+    pub const KEY_F7: u16 = 65;
+    pub const KEY_F8: u16 = 66;
+    pub const KEY_F9: u16 = 67;
+    pub const KEY_F10: u16 = 68;
+    pub const KEY_F11: u16 = 87;
+    pub const KEY_F12: u16 = 88;
+    pub const KEY_RIGHTCTRL: u16 = 97;
+    pub const KEY_RIGHTALT: u16 = 100;
+    pub const KEY_HOME: u16 = 102;
+    pub const KEY_UP: u16 = 103;
+    pub const KEY_PAGEUP: u16 = 104;
+    pub const KEY_LEFT: u16 = 105;
+    pub const KEY_RIGHT: u16 = 106;
+    pub const KEY_END: u16 = 107;
+    pub const KEY_DOWN: u16 = 108;
+    pub const KEY_PAGEDOWN: u16 = 109;
+    pub const KEY_INSERT: u16 = 110;
+    pub const KEY_DELETE: u16 = 111;
+    pub const KEY_POWER: u16 = 116;
+
+    pub const BTN_LEFT: u16 = 0x110;
+    pub const BTN_RIGHT: u16 = 0x111;
+    pub const BTN_MIDDLE: u16 = 0x112;
+    pub const BTN_TOOL_FINGER: u16 = 0x145;
+    pub const BTN_TOUCH: u16 = 0x14a;
+    pub const BTN_TOOL_DOUBLETAP: u16 = 0x14d;
+    pub const BTN_TOOL_TRIPLETAP: u16 = 0x14e;
+
+    /// Largest key code.
+    pub const KEY_MAX: u16 = 0x2ff;
+}
+
+/// Codes of [`EventType::Relative`](crate::EventType::Relative) events.
+pub mod rel {
+    pub const REL_X: u16 = 0x00;
+    pub const REL_Y: u16 = 0x01;
+    pub const REL_Z: u16 = 0x02;
+    pub const REL_HWHEEL: u16 = 0x06;
+    pub const REL_WHEEL: u16 = 0x08;
+    pub const REL_WHEEL_HI_RES: u16 = 0x0b;
+    pub const REL_HWHEEL_HI_RES: u16 = 0x0c;
+
+    /// Largest relative axis code.
+    pub const REL_MAX: u16 = 0x0f;
+}
+
+/// Codes of [`EventType::Absolute`](crate::EventType::Absolute) events.
+pub mod abs {
+    pub const ABS_X: u16 = 0x00;
+    pub const ABS_Y: u16 = 0x01;
+    pub const ABS_Z: u16 = 0x02;
+    pub const ABS_PRESSURE: u16 = 0x18;
+
+    pub const ABS_MT_SLOT: u16 = 0x2f;
+    pub const ABS_MT_TOUCH_MAJOR: u16 = 0x30;
+    pub const ABS_MT_TOUCH_MINOR: u16 = 0x31;
+    pub const ABS_MT_ORIENTATION: u16 = 0x34;
+    pub const ABS_MT_POSITION_X: u16 = 0x35;
+    pub const ABS_MT_POSITION_Y: u16 = 0x36;
+    pub const ABS_MT_TOOL_TYPE: u16 = 0x37;
+    pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+    pub const ABS_MT_PRESSURE: u16 = 0x3a;
+
+    /// Largest absolute axis code.
+    pub const ABS_MAX: u16 = 0x3f;
+}
@@ -8,10 +8,23 @@
 
 #![no_std]
 
+extern crate alloc;
+
+pub mod codes;
+mod packet;
+mod queue;
+mod ring;
+
 #[doc(no_inline)]
 pub use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
 use strum::FromRepr;
 
+pub use self::{
+    packet::{EventCaps, EventPacketBuilder},
+    queue::{DEFAULT_QUEUE_CAPACITY, InputEventQueue, InputQueueStats},
+    ring::{RING_CONTROL_SIZE, RING_REVOKED, RingConsumer, RingHeader, RingProducer, ring_size},
+};
+
 /// Input event categories defined by the Linux input subsystem.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, FromRepr)]
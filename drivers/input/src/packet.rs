@@ -0,0 +1,439 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Assembly of event packets, including multi-touch slots.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    DriverError, DriverResult, Event, EventType, InputDriverOps,
+    codes::{abs, syn},
+};
+
+/// Value of `ABS_MT_TRACKING_ID` that ends a contact.
+const TRACKING_ID_NONE: u32 = u32::MAX;
+
+/// Tracking ids wrap around after this value, like in Linux.
+const TRACKING_ID_MAX: u16 = u16::MAX;
+
+/// Supported key, relative and absolute codes of a device.
+#[derive(Debug, Clone)]
+pub struct EventCaps {
+    key: [u8; EventType::Key.bits_count().div_ceil(8)],
+    rel: [u8; EventType::Relative.bits_count().div_ceil(8)],
+    abs: [u8; EventType::Absolute.bits_count().div_ceil(8)],
+}
+
+impl EventCaps {
+    /// Creates an empty set of capabilities.
+    pub const fn new() -> Self {
+        Self {
+            key: [0; EventType::Key.bits_count().div_ceil(8)],
+            rel: [0; EventType::Relative.bits_count().div_ceil(8)],
+            abs: [0; EventType::Absolute.bits_count().div_ceil(8)],
+        }
+    }
+
+    /// Reads the capabilities of `device` with
+    /// [`get_event_bits`](InputDriverOps::get_event_bits).
+    pub fn from_device<D: InputDriverOps + ?Sized>(device: &mut D) -> DriverResult<Self> {
+        let mut caps = Self::new();
+        for ty in [EventType::Key, EventType::Relative, EventType::Absolute] {
+            let bits = caps.bits_mut(ty).unwrap();
+            if !device.get_event_bits(ty, bits)? {
+                bits.fill(0);
+            }
+        }
+        Ok(caps)
+    }
+
+    /// Marks `code` of type `ty` as supported.
+    ///
+    /// Returns [`DriverError::InvalidInput`] if `code` is out of range, and
+    /// [`DriverError::Unsupported`] for types other than key, relative and
+    /// absolute.
+    pub fn set(&mut self, ty: EventType, code: u16) -> DriverResult {
+        let bits = self.bits_mut(ty).ok_or(DriverError::Unsupported)?;
+        let byte = bits
+            .get_mut(code as usize / 8)
+            .ok_or(DriverError::InvalidInput)?;
+        *byte |= 1 << (code % 8);
+        Ok(())
+    }
+
+    /// Returns whether `code` of type `ty` is supported.
+    pub fn supports(&self, ty: EventType, code: u16) -> bool {
+        let bits = match ty {
+            EventType::Key => &self.key[..],
+            EventType::Relative => &self.rel[..],
+            EventType::Absolute => &self.abs[..],
+            _ => return false,
+        };
+        bits.get(code as usize / 8)
+            .is_some_and(|byte| byte & (1 << (code % 8)) != 0)
+    }
+
+    fn bits_mut(&mut self, ty: EventType) -> Option<&mut [u8]> {
+        match ty {
+            EventType::Key => Some(&mut self.key),
+            EventType::Relative => Some(&mut self.rel),
+            EventType::Absolute => Some(&mut self.abs),
+            _ => None,
+        }
+    }
+}
+
+impl Default for EventCaps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A contact occupying a multi-touch slot.
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+    /// Identifier of the contact given by the driver.
+    id: u32,
+    /// `ABS_MT_TRACKING_ID` assigned to the contact.
+    tracking_id: u16,
+}
+
+/// Collects the events of one device report into a packet terminated by
+/// `SYN_REPORT`.
+///
+/// Every code is checked against the device's [`EventCaps`]; unsupported
+/// codes are refused with [`DriverError::Unsupported`] and leave the packet
+/// untouched.
+///
+/// For touch devices, the builder also speaks the type B multi-touch
+/// protocol: [`touch`](Self::touch) puts a contact into a slot, emitting
+/// `ABS_MT_SLOT` and a fresh `ABS_MT_TRACKING_ID`, so that subsequent
+/// `ABS_MT_*` events apply to it; [`lift`](Self::lift) ends the contact
+/// and frees its slot for the next one. The selected slot carries over from
+/// one packet to the next, as it does for readers of the stream.
+pub struct EventPacketBuilder {
+    caps: EventCaps,
+    events: Vec<Event>,
+    slots: Vec<Option<Contact>>,
+    current_slot: Option<usize>,
+    next_tracking_id: u16,
+}
+
+impl EventPacketBuilder {
+    /// Creates a builder for a device without multi-touch slots.
+    pub fn new(caps: EventCaps) -> Self {
+        Self {
+            caps,
+            events: Vec::new(),
+            slots: Vec::new(),
+            current_slot: None,
+            next_tracking_id: 0,
+        }
+    }
+
+    /// Creates a builder tracking up to `slots` simultaneous contacts.
+    ///
+    /// Returns [`DriverError::Unsupported`] if the device does not report
+    /// `ABS_MT_SLOT` and `ABS_MT_TRACKING_ID`.
+    pub fn with_slots(caps: EventCaps, slots: usize) -> DriverResult<Self> {
+        if !caps.supports(EventType::Absolute, abs::ABS_MT_SLOT)
+            || !caps.supports(EventType::Absolute, abs::ABS_MT_TRACKING_ID)
+        {
+            return Err(DriverError::Unsupported);
+        }
+        let mut builder = Self::new(caps);
+        builder.slots = vec![None; slots];
+        Ok(builder)
+    }
+
+    /// Adds a key event; `value` is 0 for release, 1 for press and 2 for
+    /// autorepeat.
+    pub fn key(&mut self, code: u16, value: u32) -> DriverResult {
+        if value > 2 {
+            return Err(DriverError::InvalidInput);
+        }
+        self.check(EventType::Key, code)?;
+        self.emit(EventType::Key, code, value);
+        Ok(())
+    }
+
+    /// Adds a relative axis event.
+    pub fn rel(&mut self, code: u16, delta: i32) -> DriverResult {
+        self.check(EventType::Relative, code)?;
+        self.emit(EventType::Relative, code, delta as u32);
+        Ok(())
+    }
+
+    /// Adds an absolute axis event.
+    ///
+    /// `ABS_MT_*` events apply to the slot selected by the last
+    /// [`touch`](Self::touch). `ABS_MT_SLOT` and `ABS_MT_TRACKING_ID` are
+    /// managed by the builder and refused with [`DriverError::InvalidInput`].
+    pub fn abs(&mut self, code: u16, value: i32) -> DriverResult {
+        if code == abs::ABS_MT_SLOT || code == abs::ABS_MT_TRACKING_ID {
+            return Err(DriverError::InvalidInput);
+        }
+        self.check(EventType::Absolute, code)?;
+        self.emit(EventType::Absolute, code, value as u32);
+        Ok(())
+    }
+
+    /// Selects the slot of `contact` and returns it.
+    ///
+    /// A contact that is not yet touching gets the lowest free slot and a new
+    /// tracking id. Returns [`DriverError::ResourceBusy`] if all slots are
+    /// taken.
+    pub fn touch(&mut self, contact: u32) -> DriverResult<usize> {
+        if let Some(slot) = self.find(contact) {
+            self.select(slot);
+            return Ok(slot);
+        }
+        let slot = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(DriverError::ResourceBusy)?;
+        let tracking_id = self.next_tracking_id;
+        self.next_tracking_id = if tracking_id == TRACKING_ID_MAX {
+            0
+        } else {
+            tracking_id + 1
+        };
+        self.slots[slot] = Some(Contact {
+            id: contact,
+            tracking_id,
+        });
+        self.select(slot);
+        self.emit(
+            EventType::Absolute,
+            abs::ABS_MT_TRACKING_ID,
+            tracking_id as u32,
+        );
+        Ok(slot)
+    }
+
+    /// Ends `contact` and frees its slot.
+    ///
+    /// Returns [`DriverError::InvalidInput`] if `contact` is not touching.
+    pub fn lift(&mut self, contact: u32) -> DriverResult {
+        let slot = self.find(contact).ok_or(DriverError::InvalidInput)?;
+        self.slots[slot] = None;
+        self.select(slot);
+        self.emit(
+            EventType::Absolute,
+            abs::ABS_MT_TRACKING_ID,
+            TRACKING_ID_NONE,
+        );
+        Ok(())
+    }
+
+    /// Returns the tracking id of the contact in `slot`, if any.
+    pub fn tracking_id(&self, slot: usize) -> Option<u16> {
+        self.slots.get(slot)?.map(|contact| contact.tracking_id)
+    }
+
+    /// Returns the number of contacts currently touching.
+    pub fn active_contacts(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Returns whether no event has been added since the last packet.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Terminates the pending events with `SYN_REPORT` and returns them.
+    ///
+    /// Returns an empty packet if no event was added.
+    pub fn finish(&mut self) -> Vec<Event> {
+        if self.events.is_empty() {
+            return Vec::new();
+        }
+        self.emit(EventType::Synchronization, syn::SYN_REPORT, 0);
+        core::mem::take(&mut self.events)
+    }
+
+    /// Discards the pending events.
+    ///
+    /// Slot assignments are kept, but the next `ABS_MT_*` event is preceded
+    /// by `ABS_MT_SLOT` again.
+    pub fn discard(&mut self) {
+        self.events.clear();
+        self.current_slot = None;
+    }
+
+    fn check(&self, ty: EventType, code: u16) -> DriverResult {
+        if code as usize >= ty.bits_count() {
+            return Err(DriverError::InvalidInput);
+        }
+        if !self.caps.supports(ty, code) {
+            return Err(DriverError::Unsupported);
+        }
+        Ok(())
+    }
+
+    fn find(&self, contact: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.is_some_and(|c| c.id == contact))
+    }
+
+    fn select(&mut self, slot: usize) {
+        if self.current_slot != Some(slot) {
+            self.current_slot = Some(slot);
+            self.emit(EventType::Absolute, abs::ABS_MT_SLOT, slot as u32);
+        }
+    }
+
+    fn emit(&mut self, ty: EventType, code: u16, value: u32) {
+        self.events.push(Event {
+            event_type: ty as u16,
+            code,
+            value,
+        });
+    }
+}
+
+#[cfg(unittest)]
+mod tests_packet {
+    use unittest::def_test;
+
+    use super::{EventCaps, EventPacketBuilder, TRACKING_ID_NONE};
+    use crate::{
+        DriverError, Event, EventType,
+        codes::{abs, key, rel, syn},
+    };
+
+    fn touch_caps() -> EventCaps {
+        let mut caps = EventCaps::new();
+        caps.set(EventType::Key, key::BTN_TOUCH).unwrap();
+        for code in [
+            abs::ABS_X,
+            abs::ABS_Y,
+            abs::ABS_MT_SLOT,
+            abs::ABS_MT_TRACKING_ID,
+            abs::ABS_MT_POSITION_X,
+            abs::ABS_MT_POSITION_Y,
+        ] {
+            caps.set(EventType::Absolute, code).unwrap();
+        }
+        caps
+    }
+
+    fn ev(ty: EventType, code: u16, value: u32) -> Event {
+        Event {
+            event_type: ty as u16,
+            code,
+            value,
+        }
+    }
+
+    #[def_test]
+    fn test_packet_order() {
+        let mut b = EventPacketBuilder::with_slots(touch_caps(), 2).unwrap();
+        assert_eq!(b.touch(7).unwrap(), 0);
+        b.abs(abs::ABS_MT_POSITION_X, 10).unwrap();
+        b.abs(abs::ABS_MT_POSITION_Y, 20).unwrap();
+        b.key(key::BTN_TOUCH, 1).unwrap();
+        assert_eq!(
+            b.finish(),
+            [
+                ev(EventType::Absolute, abs::ABS_MT_SLOT, 0),
+                ev(EventType::Absolute, abs::ABS_MT_TRACKING_ID, 0),
+                ev(EventType::Absolute, abs::ABS_MT_POSITION_X, 10),
+                ev(EventType::Absolute, abs::ABS_MT_POSITION_Y, 20),
+                ev(EventType::Key, key::BTN_TOUCH, 1),
+                ev(EventType::Synchronization, syn::SYN_REPORT, 0),
+            ]
+        );
+        assert!(b.is_empty());
+
+        // The slot stays selected in the next packet.
+        assert_eq!(b.touch(7).unwrap(), 0);
+        b.abs(abs::ABS_MT_POSITION_X, 11).unwrap();
+        assert_eq!(
+            b.finish(),
+            [
+                ev(EventType::Absolute, abs::ABS_MT_POSITION_X, 11),
+                ev(EventType::Synchronization, syn::SYN_REPORT, 0),
+            ]
+        );
+        assert!(b.finish().is_empty());
+    }
+
+    #[def_test]
+    fn test_slot_reuse_after_lift() {
+        let mut b = EventPacketBuilder::with_slots(touch_caps(), 2).unwrap();
+        assert_eq!(b.touch(100).unwrap(), 0);
+        assert_eq!(b.touch(200).unwrap(), 1);
+        assert!(matches!(b.touch(300), Err(DriverError::ResourceBusy)));
+        b.finish();
+
+        b.lift(100).unwrap();
+        assert_eq!(
+            b.finish(),
+            [
+                ev(EventType::Absolute, abs::ABS_MT_SLOT, 0),
+                ev(
+                    EventType::Absolute,
+                    abs::ABS_MT_TRACKING_ID,
+                    TRACKING_ID_NONE
+                ),
+                ev(EventType::Synchronization, syn::SYN_REPORT, 0),
+            ]
+        );
+        assert_eq!(b.active_contacts(), 1);
+        assert!(matches!(b.lift(100), Err(DriverError::InvalidInput)));
+
+        // The freed slot goes to the next contact, with a new tracking id.
+        assert_eq!(b.touch(300).unwrap(), 0);
+        assert_eq!(b.tracking_id(0), Some(2));
+        assert_eq!(b.tracking_id(1), Some(1));
+        assert_eq!(
+            b.finish(),
+            [
+                ev(EventType::Absolute, abs::ABS_MT_TRACKING_ID, 2),
+                ev(EventType::Synchronization, syn::SYN_REPORT, 0),
+            ]
+        );
+    }
+
+    #[def_test]
+    fn test_tracking_id_wraps() {
+        let mut b = EventPacketBuilder::with_slots(touch_caps(), 1).unwrap();
+        b.next_tracking_id = u16::MAX;
+        b.touch(1).unwrap();
+        b.lift(1).unwrap();
+        b.touch(2).unwrap();
+        assert_eq!(b.tracking_id(0), Some(0));
+    }
+
+    #[def_test]
+    fn test_unsupported_codes() {
+        let mut b = EventPacketBuilder::new(touch_caps());
+        assert!(matches!(
+            b.rel(rel::REL_X, 1),
+            Err(DriverError::Unsupported)
+        ));
+        assert!(matches!(
+            b.key(key::KEY_A, 1),
+            Err(DriverError::Unsupported)
+        ));
+        assert!(matches!(
+            b.key(key::BTN_TOUCH, 3),
+            Err(DriverError::InvalidInput)
+        ));
+        assert!(matches!(
+            b.abs(abs::ABS_MAX + 1, 0),
+            Err(DriverError::InvalidInput)
+        ));
+        assert!(matches!(
+            b.abs(abs::ABS_MT_TRACKING_ID, 0),
+            Err(DriverError::InvalidInput)
+        ));
+        assert!(b.is_empty());
+
+        assert!(EventPacketBuilder::with_slots(EventCaps::new(), 2).is_err());
+    }
+}
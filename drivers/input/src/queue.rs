@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Buffered event queue on top of an input device.
+
+use alloc::{vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
+
+use kpoll::{IoEvents, PollSet, Pollable};
+use kspin::SpinNoIrq;
+
+use crate::{DriverError, DriverResult, Event, InputDriverOps};
+
+/// Number of events buffered by [`InputEventQueue::new`].
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/// Owner value meaning the queue is not grabbed.
+const NO_OWNER: u64 = 0;
+
+/// Statistics of an [`InputEventQueue`].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct InputQueueStats {
+    /// Events currently buffered.
+    pub queued: usize,
+    /// Capacity of the buffer.
+    pub capacity: usize,
+    /// Events dropped because the buffer was full.
+    pub dropped: u64,
+}
+
+/// Fixed-capacity ring of events; the oldest event is dropped on overflow.
+struct EventRing {
+    events: Vec<Event>,
+    head: usize,
+    len: usize,
+    dropped: u64,
+}
+
+impl EventRing {
+    fn new(capacity: usize) -> Self {
+        let empty = Event {
+            event_type: 0,
+            code: 0,
+            value: 0,
+        };
+        Self {
+            events: vec![empty; capacity],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, event: Event) {
+        let cap = self.events.len();
+        if cap == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.len == cap {
+            self.head = (self.head + 1) % cap;
+            self.len -= 1;
+            self.dropped += 1;
+        }
+        self.events[(self.head + self.len) % cap] = event;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % self.events.len();
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+/// An input device with a buffer of pending events.
+///
+/// Events reach the buffer either from an interrupt handler through
+/// [`push_event`](Self::push_event), or by draining the device's
+/// [`read_event`](InputDriverOps::read_event) when the buffer runs dry.
+/// Readers take them out with [`pop_event`](Self::pop_event), and can wait
+/// for them through [`Pollable`].
+///
+/// Like `EVIOCGRAB`, one consumer may [`grab`](Self::grab) the queue; until
+/// it lets go, every other consumer is refused with
+/// [`DriverError::ResourceBusy`].
+pub struct InputEventQueue<D> {
+    device: SpinNoIrq<D>,
+    ring: SpinNoIrq<EventRing>,
+    poll_rx: PollSet,
+    grab_owner: AtomicU64,
+}
+
+impl<D: InputDriverOps> InputEventQueue<D> {
+    /// Creates a queue buffering up to [`DEFAULT_QUEUE_CAPACITY`] events.
+    pub fn new(device: D) -> Self {
+        Self::with_capacity(device, DEFAULT_QUEUE_CAPACITY)
+    }
+
+    /// Creates a queue buffering up to `capacity` events.
+    pub fn with_capacity(device: D, capacity: usize) -> Self {
+        Self {
+            device: SpinNoIrq::new(device),
+            ring: SpinNoIrq::new(EventRing::new(capacity)),
+            poll_rx: PollSet::new(),
+            grab_owner: AtomicU64::new(NO_OWNER),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the device.
+    pub fn with_device<R>(&self, f: impl FnOnce(&mut D) -> R) -> R {
+        f(&mut self.device.lock())
+    }
+
+    /// Queues an event and wakes up waiting readers.
+    ///
+    /// Meant for interrupt handlers. If the queue is full, the oldest event
+    /// is dropped and counted in [`stats`](Self::stats).
+    pub fn push_event(&self, event: Event) {
+        self.ring.lock().push(event);
+        self.poll_rx.wake();
+    }
+
+    /// Takes the oldest pending event without blocking.
+    ///
+    /// Returns `Err(DriverError::WouldBlock)` if there is none.
+    pub fn pop_event(&self) -> DriverResult<Event> {
+        if let Some(event) = self.ring.lock().pop() {
+            return Ok(event);
+        }
+        self.fill()?;
+        self.ring.lock().pop().ok_or(DriverError::WouldBlock)
+    }
+
+    /// Returns whether an event is pending.
+    pub fn has_event(&self) -> bool {
+        if self.ring.lock().len > 0 {
+            return true;
+        }
+        let _ = self.fill();
+        self.ring.lock().len > 0
+    }
+
+    /// Moves the events the device has ready into the buffer.
+    fn fill(&self) -> DriverResult {
+        let mut device = self.device.lock();
+        loop {
+            match device.read_event() {
+                Ok(event) => self.ring.lock().push(event),
+                Err(DriverError::WouldBlock) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Discards all pending events.
+    pub fn flush(&self) {
+        let mut ring = self.ring.lock();
+        ring.head = 0;
+        ring.len = 0;
+    }
+
+    /// Returns buffer statistics.
+    pub fn stats(&self) -> InputQueueStats {
+        let ring = self.ring.lock();
+        InputQueueStats {
+            queued: ring.len,
+            capacity: ring.events.len(),
+            dropped: ring.dropped,
+        }
+    }
+
+    /// Claims the queue for `owner`, a non-zero consumer identifier.
+    ///
+    /// Grabbing again by the same owner succeeds; any other owner gets
+    /// [`DriverError::ResourceBusy`].
+    pub fn grab(&self, owner: u64) -> DriverResult {
+        if owner == NO_OWNER {
+            return Err(DriverError::InvalidInput);
+        }
+        match self
+            .grab_owner
+            .compare_exchange(NO_OWNER, owner, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => Ok(()),
+            Err(current) if current == owner => Ok(()),
+            Err(_) => Err(DriverError::ResourceBusy),
+        }
+    }
+
+    /// Releases a grab held by `owner`.
+    ///
+    /// Returns [`DriverError::InvalidInput`] if `owner` does not hold it.
+    pub fn ungrab(&self, owner: u64) -> DriverResult {
+        self.grab_owner
+            .compare_exchange(owner, NO_OWNER, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| ())
+            .map_err(|_| DriverError::InvalidInput)
+    }
+
+    /// Checks that `owner` may consume events, i.e. the queue is not grabbed
+    /// by someone else.
+    pub fn check_access(&self, owner: u64) -> DriverResult {
+        match self.grab_owner.load(Ordering::Acquire) {
+            NO_OWNER => Ok(()),
+            current if current == owner => Ok(()),
+            _ => Err(DriverError::ResourceBusy),
+        }
+    }
+}
+
+impl<D: InputDriverOps> Pollable for InputEventQueue<D> {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.has_event());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+            // Events of a device that does not interrupt only show up when
+            // it is read, so keep the waiter polling.
+            context.waker().wake_by_ref();
+        }
+    }
+}
+
+#[cfg(unittest)]
+mod tests_queue {
+    use unittest::def_test;
+
+    use super::EventRing;
+    use crate::Event;
+
+    fn event(code: u16) -> Event {
+        Event {
+            event_type: 1,
+            code,
+            value: 1,
+        }
+    }
+
+    #[def_test]
+    fn test_ring_fifo() {
+        let mut ring = EventRing::new(4);
+        ring.push(event(1));
+        ring.push(event(2));
+        assert_eq!(ring.pop(), Some(event(1)));
+        assert_eq!(ring.pop(), Some(event(2)));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[def_test]
+    fn test_ring_overflow_drops_oldest() {
+        let mut ring = EventRing::new(2);
+        for code in 1..=5 {
+            ring.push(event(code));
+        }
+        assert_eq!(ring.dropped, 3);
+        assert_eq!(ring.pop(), Some(event(4)));
+        assert_eq!(ring.pop(), Some(event(5)));
+    }
+}
@@ -0,0 +1,463 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Event ring shared with a reader in user space.
+//!
+//! The ring memory starts with a control page holding a [`RingHeader`],
+//! followed by `entries` slots of one record each. The kernel writes records
+//! and advances `head`; the reader consumes them and advances `tail`. Both
+//! are free-running counters, and record `i` lives in slot
+//! `i & (entries - 1)`, so the ring holds `head - tail` records. Records are
+//! pushed in batches, which the kernel publishes by advancing `head` once.
+//!
+//! When the ring is full, new records are dropped and counted in `dropped`.
+//! Once there is room again, a marker record, `SYN_DROPPED` for evdev, is
+//! written before the next record, telling the reader that it missed some.
+
+use core::{
+    marker::PhantomData,
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+/// Size of the control page at the start of the ring memory.
+pub const RING_CONTROL_SIZE: usize = 4096;
+
+/// [`RingHeader::flags`] bit set once the ring receives no more records,
+/// e.g. because the device went away.
+pub const RING_REVOKED: u32 = 1 << 0;
+
+/// Control block of a ring, shared with the reader.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RingHeader {
+    /// Number of records written; only the kernel advances it.
+    pub head: AtomicU32,
+    /// Number of records consumed; only the reader advances it.
+    pub tail: AtomicU32,
+    /// Number of slots, a power of two.
+    pub entries: u32,
+    /// Size of a slot in bytes.
+    pub slot_size: u32,
+    /// Number of times `head` went past the last slot.
+    pub wraps: AtomicU32,
+    /// Incremented each time the ring goes from empty to non-empty, and when
+    /// it is revoked.
+    pub doorbell: AtomicU32,
+    /// Number of records dropped because the ring was full.
+    pub dropped: AtomicU64,
+    /// `RING_*` flags.
+    pub flags: AtomicU32,
+    _reserved: u32,
+}
+
+/// Returns the size of the memory of a ring of `entries` records of `T`,
+/// rounded up to whole pages, or `None` if `entries` is not a power of two.
+pub fn ring_size<T>(entries: u32) -> Option<usize> {
+    if !entries.is_power_of_two() {
+        return None;
+    }
+    let slots = (entries as usize).checked_mul(size_of::<T>())?;
+    RING_CONTROL_SIZE
+        .checked_add(slots)
+        .map(|size| size.next_multiple_of(RING_CONTROL_SIZE))
+}
+
+/// Writer side of a ring.
+///
+/// The producer keeps its own copy of everything but `tail`, so a reader
+/// scribbling over the control page can only lose records, never make the
+/// producer write outside of the ring.
+pub struct RingProducer<T> {
+    header: NonNull<RingHeader>,
+    slots: NonNull<T>,
+    mask: u32,
+    head: u32,
+    /// `head` as last shown to the reader.
+    published: u32,
+    overflowed: bool,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for RingProducer<T> {}
+
+impl<T: Copy> RingProducer<T> {
+    /// Initializes a ring of `entries` records in `mem`.
+    ///
+    /// Returns `None` if `entries` is not a power of two.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be aligned to [`RING_CONTROL_SIZE`], valid for
+    /// [`ring_size::<T>(entries)`](ring_size) bytes for as long as the
+    /// producer exists, and not written by anyone but the reader.
+    pub unsafe fn new(mem: NonNull<u8>, entries: u32) -> Option<Self> {
+        ring_size::<T>(entries)?;
+        let header = mem.cast::<RingHeader>();
+        unsafe {
+            header.write(RingHeader {
+                entries,
+                slot_size: size_of::<T>() as u32,
+                ..Default::default()
+            });
+        }
+        Some(Self {
+            header,
+            slots: unsafe { mem.add(RING_CONTROL_SIZE) }.cast(),
+            mask: entries - 1,
+            head: 0,
+            published: 0,
+            overflowed: false,
+            _marker: PhantomData,
+        })
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { self.header.as_ref() }
+    }
+
+    /// Returns the number of free slots.
+    ///
+    /// A `tail` that makes no sense counts as a full ring.
+    fn free(&self) -> u32 {
+        let used = self
+            .head
+            .wrapping_sub(self.header().tail.load(Ordering::Acquire));
+        (self.mask + 1).saturating_sub(used)
+    }
+
+    fn write(&mut self, record: T) {
+        let slot = (self.head & self.mask) as usize;
+        // Volatile, as the reader may access the slot concurrently if it
+        // misbehaves.
+        unsafe { self.slots.add(slot).write_volatile(record) };
+        self.head = self.head.wrapping_add(1);
+        if self.head & self.mask == 0 {
+            self.header().wraps.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Appends `record`, preceded by `marker()` if records were dropped
+    /// since the last one written.
+    ///
+    /// The reader sees it after the next [`publish`](Self::publish).
+    pub fn push(&mut self, record: T, marker: impl FnOnce() -> T) {
+        let mut free = self.free();
+        if self.overflowed && free > 0 {
+            self.write(marker());
+            self.overflowed = false;
+            free -= 1;
+        }
+        if free == 0 {
+            self.header().dropped.fetch_add(1, Ordering::Relaxed);
+            self.overflowed = true;
+        } else {
+            self.write(record);
+        }
+    }
+
+    /// Shows the records pushed so far to the reader.
+    ///
+    /// Returns whether the ring went from empty to non-empty, i.e. whether
+    /// the reader should be woken up.
+    pub fn publish(&mut self) -> bool {
+        if self.head == self.published {
+            return false;
+        }
+        let header = self.header();
+        let was_empty = header.tail.load(Ordering::Acquire) == self.published;
+        header.head.store(self.head, Ordering::Release);
+        if was_empty {
+            header.doorbell.fetch_add(1, Ordering::Release);
+        }
+        self.published = self.head;
+        was_empty
+    }
+
+    /// Returns whether published records wait for the reader.
+    pub fn has_pending(&self) -> bool {
+        self.header().tail.load(Ordering::Acquire) != self.published
+    }
+
+    /// Marks the ring as receiving no more records.
+    pub fn revoke(&self) {
+        let header = self.header();
+        header.flags.fetch_or(RING_REVOKED, Ordering::Release);
+        header.doorbell.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Reader side of a ring, as user space implements it.
+pub struct RingConsumer<T> {
+    header: NonNull<RingHeader>,
+    slots: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for RingConsumer<T> {}
+
+impl<T: Copy> RingConsumer<T> {
+    /// Attaches to a ring initialized by [`RingProducer::new`].
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be the memory of a live ring of records of `T`, and there
+    /// must be no other consumer.
+    pub unsafe fn new(mem: NonNull<u8>) -> Self {
+        Self {
+            header: mem.cast(),
+            slots: unsafe { mem.add(RING_CONTROL_SIZE) }.cast(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the control block of the ring.
+    pub fn header(&self) -> &RingHeader {
+        unsafe { self.header.as_ref() }
+    }
+
+    /// Takes the oldest record, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let header = self.header();
+        let tail = header.tail.load(Ordering::Relaxed);
+        if header.head.load(Ordering::Acquire) == tail {
+            return None;
+        }
+        let slot = (tail & (header.entries - 1)) as usize;
+        let record = unsafe { self.slots.add(slot).read_volatile() };
+        header.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(record)
+    }
+
+    /// Returns whether the ring was revoked.
+    pub fn is_revoked(&self) -> bool {
+        self.header().flags.load(Ordering::Acquire) & RING_REVOKED != 0
+    }
+}
+
+#[cfg(unittest)]
+mod tests_ring {
+    use alloc::{vec, vec::Vec};
+    use core::{ptr::NonNull, sync::atomic::Ordering};
+
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::{RING_CONTROL_SIZE, RingConsumer, RingProducer, ring_size};
+    use crate::{
+        DeviceKind, DriverError, DriverOps, DriverResult, Event, EventType, InputDeviceId,
+        InputDriverOps, InputEventQueue,
+        codes::{abs, syn},
+    };
+
+    #[repr(C, align(4096))]
+    #[derive(Clone)]
+    struct Page([u8; RING_CONTROL_SIZE]);
+
+    /// Page-aligned memory for a ring.
+    struct RingMemory(Vec<Page>);
+
+    impl RingMemory {
+        fn new(entries: u32) -> Self {
+            let size = ring_size::<Event>(entries).unwrap();
+            Self(vec![Page([0; RING_CONTROL_SIZE]); size / RING_CONTROL_SIZE])
+        }
+
+        fn ptr(&mut self) -> NonNull<u8> {
+            NonNull::new(self.0.as_mut_ptr().cast()).unwrap()
+        }
+    }
+
+    fn ring(mem: &mut RingMemory, entries: u32) -> (RingProducer<Event>, RingConsumer<Event>) {
+        unsafe {
+            (
+                RingProducer::new(mem.ptr(), entries).unwrap(),
+                RingConsumer::new(mem.ptr()),
+            )
+        }
+    }
+
+    fn ev(code: u16, value: u32) -> Event {
+        Event {
+            event_type: EventType::Absolute as u16,
+            code,
+            value,
+        }
+    }
+
+    fn dropped_marker() -> Event {
+        Event {
+            event_type: EventType::Synchronization as u16,
+            code: syn::SYN_DROPPED,
+            value: 0,
+        }
+    }
+
+    /// A gesture storm: two fingers moving, one packet per step.
+    fn gesture(steps: u32) -> Vec<Event> {
+        let mut events = Vec::new();
+        for step in 0..steps {
+            for slot in 0..2 {
+                events.push(ev(abs::ABS_MT_SLOT, slot));
+                events.push(ev(abs::ABS_MT_POSITION_X, step * 3 + slot));
+                events.push(ev(abs::ABS_MT_POSITION_Y, step * 5 + slot));
+            }
+            events.push(Event {
+                event_type: EventType::Synchronization as u16,
+                code: syn::SYN_REPORT,
+                value: 0,
+            });
+        }
+        events
+    }
+
+    fn checksum(events: impl IntoIterator<Item = Event>) -> u64 {
+        events
+            .into_iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, event| {
+                let word = ((event.event_type as u64) << 48)
+                    | ((event.code as u64) << 32)
+                    | event.value as u64;
+                (hash ^ word).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+
+    /// Device replaying a fixed list of events.
+    struct Replay(Vec<Event>);
+
+    impl DriverOps for Replay {
+        fn name(&self) -> &str {
+            "replay"
+        }
+
+        fn device_kind(&self) -> DeviceKind {
+            DeviceKind::Input
+        }
+    }
+
+    impl InputDriverOps for Replay {
+        fn device_id(&self) -> InputDeviceId {
+            InputDeviceId {
+                bus_type: 0,
+                vendor: 0,
+                product: 0,
+                version: 0,
+            }
+        }
+
+        fn physical_location(&self) -> &str {
+            ""
+        }
+
+        fn unique_id(&self) -> &str {
+            ""
+        }
+
+        fn get_event_bits(&mut self, _ty: EventType, _out: &mut [u8]) -> DriverResult<bool> {
+            Ok(false)
+        }
+
+        fn read_event(&mut self) -> DriverResult<Event> {
+            if self.0.is_empty() {
+                return Err(DriverError::WouldBlock);
+            }
+            Ok(self.0.remove(0))
+        }
+    }
+
+    #[def_test]
+    fn test_ring_size() {
+        assert_eq!(ring_size::<Event>(3), None);
+        assert_eq!(ring_size::<Event>(8), Some(2 * RING_CONTROL_SIZE));
+        assert_eq!(ring_size::<Event>(1024), Some(3 * RING_CONTROL_SIZE));
+    }
+
+    #[def_test]
+    fn test_ring_stream_matches_read_path() {
+        let events = gesture(100);
+
+        let queue = InputEventQueue::with_capacity(Replay(events.clone()), events.len());
+        let read = checksum(core::iter::from_fn(|| queue.pop_event().ok()));
+
+        // A small ring drained in uneven batches, so that it wraps many times.
+        let mut mem = RingMemory::new(16);
+        let (mut producer, mut consumer) = ring(&mut mem, 16);
+        let mut delivered = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            producer.push(*event, dropped_marker);
+            producer.publish();
+            if i % 11 == 10 {
+                delivered.extend(core::iter::from_fn(|| consumer.pop()));
+            }
+        }
+        delivered.extend(core::iter::from_fn(|| consumer.pop()));
+
+        assert_eq!(delivered.len(), events.len());
+        assert_eq!(checksum(delivered), read);
+        assert_eq!(checksum(events), read);
+        assert!(consumer.header().wraps.load(Ordering::Relaxed) >= 40);
+        assert_eq!(consumer.header().dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[def_test]
+    fn test_ring_doorbell_on_empty_transition() {
+        let mut mem = RingMemory::new(4);
+        let (mut producer, mut consumer) = ring(&mut mem, 4);
+        producer.push(ev(0, 1), dropped_marker);
+        producer.push(ev(0, 2), dropped_marker);
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.publish());
+        assert!(!producer.publish());
+        producer.push(ev(0, 3), dropped_marker);
+        assert!(!producer.publish());
+        assert_eq!(consumer.header().doorbell.load(Ordering::Relaxed), 1);
+        while consumer.pop().is_some() {}
+        assert!(!producer.has_pending());
+        producer.push(ev(0, 4), dropped_marker);
+        assert!(producer.publish());
+        assert!(producer.has_pending());
+        assert_eq!(consumer.header().doorbell.load(Ordering::Relaxed), 2);
+    }
+
+    #[def_test]
+    fn test_ring_overflow_marks_dropped() {
+        let mut mem = RingMemory::new(4);
+        let (mut producer, mut consumer) = ring(&mut mem, 4);
+        for value in 1..=6 {
+            producer.push(ev(0, value), dropped_marker);
+        }
+        producer.publish();
+        assert_eq!(consumer.header().dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(consumer.pop(), Some(ev(0, 1)));
+        assert_eq!(consumer.pop(), Some(ev(0, 2)));
+        producer.push(ev(0, 7), dropped_marker);
+        producer.publish();
+        let rest: Vec<_> = core::iter::from_fn(|| consumer.pop()).collect();
+        assert_eq!(rest, [ev(0, 3), ev(0, 4), dropped_marker(), ev(0, 7)]);
+        assert_eq!(consumer.header().dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[def_test]
+    fn test_ring_ignores_bogus_tail() {
+        let mut mem = RingMemory::new(4);
+        let (mut producer, consumer) = ring(&mut mem, 4);
+        producer.push(ev(0, 1), dropped_marker);
+        producer.publish();
+        // A tail ahead of head must not let the producer overwrite records.
+        consumer.header().tail.store(100, Ordering::Relaxed);
+        producer.push(ev(0, 2), dropped_marker);
+        assert!(!producer.publish());
+        assert_eq!(consumer.header().head.load(Ordering::Relaxed), 1);
+        assert_eq!(consumer.header().dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[def_test]
+    fn test_ring_revoke() {
+        let mut mem = RingMemory::new(4);
+        let (producer, consumer) = ring(&mut mem, 4);
+        assert!(!consumer.is_revoked());
+        producer.revoke();
+        assert!(consumer.is_revoked());
+        assert_eq!(consumer.header().doorbell.load(Ordering::Relaxed), 1);
+    }
+}
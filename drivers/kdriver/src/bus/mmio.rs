@@ -13,7 +13,9 @@ impl AllDevices {
         #[cfg(feature = "virtio")]
         for reg in platconfig::devices::VIRTIO_MMIO_RANGES {
             for_each_drivers!(type Driver, {
-                if let Some(dev) = Driver::probe_mmio(reg.0, reg.1) {
+                let name = core::any::type_name::<Driver>();
+                let dev = boottime::time_probe(name, || Driver::probe_mmio(reg.0, reg.1));
+                if let Some(dev) = dev {
                     info!(
                         "registered a new {:?} device at [PA:{:#x}, PA:{:#x}): {:?}",
                         dev.device_kind(),
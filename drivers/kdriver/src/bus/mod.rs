@@ -6,4 +6,4 @@
 #[cfg(bus = "mmio")]
 mod mmio;
 #[cfg(bus = "pci")]
-mod pci;
+pub(crate) mod pci;
@@ -13,6 +13,22 @@ use crate::{AllDevices, prelude::*};
 
 const PCI_BAR_NUM: u8 = 6;
 
+#[cfg(target_arch = "x86_64")]
+const PCI_IRQ_BASE: usize = 0x20;
+#[cfg(target_arch = "riscv64")]
+const PCI_IRQ_BASE: usize = 0x20;
+#[cfg(target_arch = "loongarch64")]
+const PCI_IRQ_BASE: usize = 0x10;
+#[cfg(target_arch = "aarch64")]
+const PCI_IRQ_BASE: usize = 0x23;
+
+/// Returns the IRQ number of the INTx line of `bdf`, swizzled over the four
+/// lines as the platform routes them.
+#[allow(dead_code)]
+pub(crate) fn legacy_irq(bdf: DeviceFunction) -> usize {
+    PCI_IRQ_BASE + (bdf.device & 3) as usize
+}
+
 /// Configure PCI BARs and enable the device.
 fn config_pci_device<C: ConfigurationAccess>(
     root: &mut PciRoot<C>,
@@ -133,7 +149,11 @@ impl AllDevices {
                 }
                 match config_pci_device(&mut root, bdf, &mut allocator) {
                     Ok(_) => for_each_drivers!(type Driver, {
-                        if let Some(dev) = Driver::probe_pci(&mut root, bdf, &dev_info) {
+                        let name = core::any::type_name::<Driver>();
+                        let dev = boottime::time_probe(name, || {
+                            Driver::probe_pci(&mut root, bdf, &dev_info)
+                        });
+                        if let Some(dev) = dev {
                             info!(
                                 "registered a new {:?} device at {}: {:?}",
                                 dev.device_kind(),
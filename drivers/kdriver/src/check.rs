@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Self-tests of probed devices.
+//!
+//! Devices are handed over to their subsystems right after probing, so the
+//! I/O part of a test runs at probe time and the registered self-test reports
+//! the result.
+
+use alloc::{format, string::String, vec, vec::Vec};
+
+use kspin::SpinNoIrq;
+use selftest::{Outcome, TestContext};
+
+use crate::prelude::*;
+
+/// Result of reading the first block of each block device, in probe order.
+static FIRST_BLOCK_READS: SpinNoIrq<Vec<(String, DriverResult<u64>)>> = SpinNoIrq::new(Vec::new());
+
+/// Reads block 0 of `dev`, which has just been probed.
+pub(crate) fn read_first_block(dev: &mut BlockDevice) {
+    let mut buf = vec![0u8; dev.block_size()];
+    let result = dev.read_block(0, &mut buf).map(|()| dev.num_blocks());
+    FIRST_BLOCK_READS.lock().push((dev.name().into(), result));
+}
+
+fn check_first_block(ctx: &mut TestContext) -> Outcome {
+    let reads = FIRST_BLOCK_READS.lock();
+    if reads.is_empty() {
+        return Outcome::Skip("no block devices".into());
+    }
+    let mut failed = Vec::new();
+    for (i, (name, result)) in reads.iter().enumerate() {
+        match result {
+            Ok(blocks) => ctx.measure(format!("block{i}_blocks"), *blocks as i64),
+            Err(err) => failed.push(format!("block{i} ({name}): {err}")),
+        }
+    }
+    if failed.is_empty() {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("cannot read block 0 of {}", failed.join(", ")))
+    }
+}
+
+selftest::register_selftest!(Boot, "block-read", check_first_block);
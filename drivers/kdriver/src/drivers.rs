@@ -176,7 +176,7 @@ cfg_if::cfg_if! {
                 bdf: pci::DeviceFunction,
                 dev_info: &pci::DeviceFunctionInfo,
             ) -> Option<crate::DeviceEnum> {
-                use net::ixgbe::{INTEL_82599, INTEL_VEND, IxgbeNic};
+                use net::ixgbe::{INTEL_82599, INTEL_VEND, IrqMode, IxgbeNic};
                 if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82599 {
                     // Intel 10Gb Network
                     info!("ixgbe PCI device found at {:?}", bdf);
@@ -189,11 +189,30 @@ cfg_if::cfg_if! {
                     let bar_info = root.bar_info(bdf, 0).unwrap();
                     match bar_info {
                         pci::BarInfo::Memory { address, size, .. } => {
-                            let ixgbe_nic = IxgbeNic::<IxgbeHalImpl, QS, QN>::init(
-                                khal::mem::p2v((address as usize).into()).into(),
+                            // The platforms route no MSI-X vector, so the NIC
+                            // takes the INTx line of its slot.
+                            #[cfg(not(feature = "ixgbe-polling"))]
+                            let irq_mode = IrqMode::Legacy(crate::bus::pci::legacy_irq(bdf));
+                            #[cfg(feature = "ixgbe-polling")]
+                            let irq_mode = IrqMode::Polling;
+                            let mut ixgbe_nic = IxgbeNic::<IxgbeHalImpl, QS, QN>::init(
+                                khal::kvmap::ioremap(
+                                    (address as usize).into(),
+                                    size as usize,
+                                    khal::kvmap::IoAttr::Device,
+                                )
+                                .expect("failed to map ixgbe BAR0")
+                                .into(),
                                 size as usize,
+                                irq_mode,
                             )
                             .expect("failed to initialize ixgbe device");
+                            if let Some(irq) = irq_mode.irq()
+                                && !crate::ixgbe::register_irq(irq, ixgbe_nic.interrupt_ack())
+                            {
+                                warn!("ixgbe: cannot take IRQ {irq}, polling instead");
+                                ixgbe_nic.set_irq_mode(IrqMode::Polling);
+                            }
                             return Some(DeviceEnum::from_net(ixgbe_nic));
                         }
                         pci::BarInfo::IO { .. } => {
@@ -2,11 +2,47 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use kdma::{DmaBusAddress, DMAInfo, allocate_dma_memory, deallocate_dma_memory};
-use net::ixgbe::{IxgbeHal, PhysAddr as IxgbePhysAddr};
-use khal::mem::{p2v, v2p};
 use core::{alloc::Layout, ptr::NonNull};
 
+use kdma::{DMAInfo, DmaBusAddress, allocate_dma_memory, deallocate_dma_memory};
+use khal::{
+    kvmap::{IoAttr, io_v2p, ioremap},
+    mem::v2p,
+};
+use kspin::SpinNoIrq;
+use net::ixgbe::{InterruptAck, IxgbeHal, PhysAddr as IxgbePhysAddr};
+
+/// Most ixgbe NICs that can take interrupts; the others are polled.
+const MAX_IRQ_NICS: usize = 4;
+
+/// What acknowledges the interrupts of each NIC, by handler slot.
+static IRQ_ACKS: SpinNoIrq<[Option<InterruptAck>; MAX_IRQ_NICS]> =
+    SpinNoIrq::new([None; MAX_IRQ_NICS]);
+
+/// Clears the interrupt causes of the NIC of handler slot `SLOT`, which
+/// deasserts a level-triggered line. The task waiting for the NIC is woken
+/// by the IRQ hook of the scheduler afterwards.
+fn ack_irq<const SLOT: usize>() {
+    if let Some(ack) = IRQ_ACKS.lock()[SLOT] {
+        ack.ack();
+    }
+}
+
+/// Registers the handler of `irq`, which acknowledges the interrupts of the
+/// NIC through `ack`, and returns whether it was registered.
+pub(crate) fn register_irq(irq: usize, ack: InterruptAck) -> bool {
+    const HANDLERS: [fn(); MAX_IRQ_NICS] = [ack_irq::<0>, ack_irq::<1>, ack_irq::<2>, ack_irq::<3>];
+    let mut acks = IRQ_ACKS.lock();
+    let Some(slot) = acks.iter().position(Option::is_none) else {
+        return false;
+    };
+    if !khal::irq::register(irq, HANDLERS[slot]) {
+        return false;
+    }
+    acks[slot] = Some(ack);
+    true
+}
+
 /// HAL implementation for the ixgbe driver.
 pub struct IxgbeHalImpl;
 
@@ -29,12 +65,15 @@ unsafe impl IxgbeHal for IxgbeHalImpl {
         0
     }
 
-    unsafe fn mmio_p2v(paddr: IxgbePhysAddr, _size: usize) -> NonNull<u8> {
-        NonNull::new(p2v(paddr.into()).as_mut_ptr()).unwrap()
+    unsafe fn mmio_p2v(paddr: IxgbePhysAddr, size: usize) -> NonNull<u8> {
+        let vaddr = ioremap(paddr.into(), size, IoAttr::Device)
+            .unwrap_or_else(|err| panic!("ixgbe: cannot map {paddr:#x}: {err:?}"));
+        NonNull::new(vaddr.as_mut_ptr()).unwrap()
     }
 
     unsafe fn mmio_v2p(vaddr: NonNull<u8>, _size: usize) -> IxgbePhysAddr {
-        v2p((vaddr.as_ptr() as usize).into()).into()
+        let vaddr = (vaddr.as_ptr() as usize).into();
+        io_v2p(vaddr).unwrap_or_else(|| v2p(vaddr)).into()
     }
 
     fn wait_until(duration: core::time::Duration) -> Result<(), &'static str> {
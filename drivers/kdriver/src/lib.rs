@@ -14,6 +14,8 @@
 #![feature(doc_cfg)]
 #![feature(associated_type_defaults)]
 
+extern crate alloc;
+
 #[macro_use]
 extern crate log;
 
@@ -21,6 +23,8 @@ extern crate log;
 mod macros;
 
 mod bus;
+#[cfg(feature = "block")]
+mod check;
 mod drivers;
 mod dummy;
 mod structs;
@@ -33,6 +37,9 @@ mod virtio;
 
 pub mod prelude;
 
+#[cfg(feature = "block")]
+pub use block::{partition, queue};
+
 #[allow(unused_imports)]
 use self::prelude::*;
 #[cfg(feature = "block")]
@@ -72,7 +79,8 @@ impl AllDevices {
     /// Probes all supported devices.
     fn probe(&mut self) {
         for_each_drivers!(type Driver, {
-            if let Some(dev) = Driver::probe_global() {
+            let name = core::any::type_name::<Driver>();
+            if let Some(dev) = boottime::time_probe(name, Driver::probe_global) {
                 info!(
                     "registered a new {:?} device: {:?}",
                     dev.device_kind(),
@@ -87,11 +95,15 @@ impl AllDevices {
     /// Adds device to corresponding container.
     #[allow(dead_code)]
     fn add_device(&mut self, dev: DeviceEnum) {
+        selftest::add_device(kind_name(dev.device_kind()), dev.name());
         match dev {
             #[cfg(feature = "net")]
             DeviceEnum::Net(dev) => self.net.push(dev),
             #[cfg(feature = "block")]
-            DeviceEnum::Block(dev) => self.block.push(dev),
+            DeviceEnum::Block(mut dev) => {
+                check::read_first_block(&mut dev);
+                self.block.push(dev)
+            }
             #[cfg(feature = "display")]
             DeviceEnum::Display(dev) => self.display.push(dev),
             #[cfg(feature = "input")]
@@ -102,6 +114,18 @@ impl AllDevices {
     }
 }
 
+/// Returns the kind of device as named in the platform's expected device list.
+fn kind_name(kind: DeviceKind) -> &'static str {
+    match kind {
+        DeviceKind::Block => "block",
+        DeviceKind::Char => "char",
+        DeviceKind::Net => "net",
+        DeviceKind::Display => "display",
+        DeviceKind::Input => "input",
+        DeviceKind::Vsock => "vsock",
+    }
+}
+
 /// Initializes all device drivers.
 pub fn init_drivers() -> AllDevices {
     info!("Initialize device drivers...");
@@ -4,23 +4,32 @@
 
 //! Device driver prelude that includes some traits and types.
 
-pub use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
+pub use driver_base::{
+    DeviceError, DeviceKind, DriverError, DriverOps, DriverResult, ErrorClass, Retryability,
+};
 #[cfg(feature = "block")]
-pub use {crate::structs::BlockDevice, block::BlockDriverOps};
+pub use {
+    crate::structs::BlockDevice,
+    block::{BlockDriverOps, BlockLimits, BlockRequestExt, RetryPolicy},
+};
 #[cfg(feature = "display")]
 pub use {
     crate::structs::DisplayDevice,
-    display::{DisplayDriverOps, DisplayInfo},
+    display::{DisplayDriverOps, DisplayInfo, ScanoutSegment},
 };
 #[cfg(feature = "input")]
 pub use {
     crate::structs::InputDevice,
-    input::{Event, EventType, InputDeviceId, InputDriverOps},
+    input::{
+        Event, EventType, InputDeviceId, InputDriverOps, InputEventQueue, RingProducer, ring_size,
+    },
 };
 #[cfg(feature = "net")]
 pub use {
     crate::structs::NetDevice,
-    net::{NetBufHandle, NetDriverOps},
+    net::{
+        Coalesce, MacAddress, NetBufCpuIf, NetBufHandle, NetCapabilities, NetDriverOps, TxOffload,
+    },
 };
 #[cfg(feature = "vsock")]
 pub use {
@@ -7,9 +7,9 @@ use core::{marker::PhantomData, ptr::NonNull};
 
 use cfg_if::cfg_if;
 use driver_base::{DeviceKind, DriverOps, DriverResult};
-use khal::mem::p2v;
 #[cfg(feature = "crosvm")]
 use khal::psci::{dma_share, dma_unshare};
+use khal::{kvmap::IoAttr, mem::p2v};
 use virtio::{BufferDirection, PhysAddr, VirtIoHal};
 
 use crate::{DeviceEnum, drivers::DriverProbe};
@@ -225,9 +225,11 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
     }
 
     #[inline]
-    unsafe fn mmio_phys_to_virt(paddr: PhysAddr, _size: usize) -> NonNull<u8> {
-        let paddr_usize = paddr as usize;
-        NonNull::new(p2v(paddr_usize.into()).as_mut_ptr()).unwrap()
+    unsafe fn mmio_phys_to_virt(paddr: PhysAddr, size: usize) -> NonNull<u8> {
+        // PCI BARs may lie outside the MMIO ranges of the linear map.
+        let vaddr = khal::kvmap::ioremap((paddr as usize).into(), size, IoAttr::Device)
+            .unwrap_or_else(|err| panic!("virtio: cannot map BAR at {paddr:#x}: {err:?}"));
+        NonNull::new(vaddr.as_mut_ptr()).unwrap()
     }
 
     #[allow(unused_variables)]
@@ -11,7 +11,7 @@ pub use fxmac_rs::KernelFunc;
 use fxmac_rs::{self, FXmac, FXmacGetMacAddress, FXmacLwipPortTx, FXmacRecvHandler, xmac_init};
 use log::*;
 
-use crate::{MacAddress, NetBufHandle, NetDriverOps};
+use crate::{MacAddress, NetBufHandle, NetCapabilities, NetDriverOps};
 
 const QS: usize = 64;
 
@@ -60,6 +60,11 @@ impl NetDriverOps for FXmacNic {
         MacAddress(self.hwaddr)
     }
 
+    fn capabilities(&self) -> NetCapabilities {
+        // The GEM checksum engines are left disabled by `fxmac_rs`.
+        NetCapabilities::empty()
+    }
+
     fn rx_queue_len(&self) -> usize {
         QS
     }
@@ -3,21 +3,182 @@
 // See LICENSES for license details.
 
 //! Intel ixgbe NIC driver implementation.
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 use core::{convert::From, mem::ManuallyDrop, ptr::NonNull};
 
-use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
+use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult, ErrorClass};
 pub use ixgbe_driver::{INTEL_82599, INTEL_VEND, IxgbeHal, PhysAddr};
 use ixgbe_driver::{IxgbeDevice, IxgbeError, IxgbeNetBuf, MemPool, NicDevice};
 use log::*;
 
-use crate::{MacAddress, NetBufHandle, NetDriverOps};
+use crate::{Coalesce, MacAddress, NetBufHandle, NetCapabilities, NetDriverOps, TxOffload};
 
 const RECV_BATCH_SIZE: usize = 64;
 const RX_BUFFER_SIZE: usize = 1024;
 const MEM_POOL: usize = 4096;
 const MEM_POOL_ENTRY_SIZE: usize = 2048;
 
+/// Device Status Register.
+const IXGBE_STATUS: usize = 0x00008;
+/// Filter Control Register.
+const IXGBE_FCTRL: usize = 0x05080;
+/// Multicast Promiscuous Enable.
+const IXGBE_FCTRL_MPE: u32 = 1 << 8;
+/// Unicast Promiscuous Enable.
+const IXGBE_FCTRL_UPE: u32 = 1 << 9;
+/// Multicast Control Register.
+const IXGBE_MCSTCTRL: usize = 0x05090;
+/// Multicast Filter Enable; the offset bits (MO) are left at 0, which hashes
+/// bits 47:36 of the address.
+const IXGBE_MCSTCTRL_MFE: u32 = 1 << 2;
+/// Multicast Table Array, 128 registers of 32 bits.
+const IXGBE_MTA: usize = 0x05200;
+const IXGBE_MTA_LEN: usize = 128;
+/// Extended Interrupt Throttle Register of vector 0, which queue 0 receives
+/// and transmits on.
+const IXGBE_EITR0: usize = 0x00820;
+/// ITR interval, bits 11:3 in units of 2 us.
+const IXGBE_EITR_ITR_MASK: u32 = 0x0000_0ff8;
+/// Counter Write Disable: writing the interval leaves the running count.
+const IXGBE_EITR_CNT_WDIS: u32 = 1 << 31;
+/// Extended Interrupt Mask Set and Clear Registers.
+const IXGBE_EIMS: usize = 0x00880;
+const IXGBE_EIMC: usize = 0x00888;
+/// Interrupt cause of queue 0.
+const IXGBE_EIMS_RTX_QUEUE0: u32 = 1 << 0;
+/// All interrupt causes.
+const IXGBE_IRQ_CLEAR_MASK: u32 = 0x7fff_ffff;
+/// Extended Interrupt Cause Register, cleared on read outside of MSI-X mode.
+const IXGBE_EICR: usize = 0x00800;
+/// Extended Interrupt Auto Clear Register, for causes signalled by MSI-X.
+const IXGBE_EIAC: usize = 0x00810;
+/// General Purpose Interrupt Enable.
+const IXGBE_GPIE: usize = 0x00898;
+/// One MSI-X vector per cause group, as mapped by the IVARs.
+const IXGBE_GPIE_MSIX_MODE: u32 = 1 << 4;
+/// Other Clear Disable: reading EICR leaves the causes auto-cleared by EIAC.
+const IXGBE_GPIE_OCD: u32 = 1 << 5;
+/// Pending bits of MSI-X vectors are kept in the PBA, as MSI-X requires.
+const IXGBE_GPIE_PBA_SUPPORT: u32 = 1 << 31;
+/// Interrupt Vector Allocation Register of queues 0 and 1: bits 7:0 map
+/// receive queue 0 and bits 15:8 transmit queue 0.
+const IXGBE_IVAR0: usize = 0x00900;
+/// Valid bit of an IVAR entry, whose bits 5:0 are the vector.
+const IXGBE_IVAR_ALLOC_VAL: u32 = 0x80;
+/// Interval between interrupts programmed at initialization, about 20000
+/// interrupts per second.
+const DEFAULT_ITR_USECS: u32 = 50;
+
+/// Shift of MACLEN in the `vlan_macip_lens` field of a context descriptor.
+const IXGBE_ADVTXD_MACLEN_SHIFT: u32 = 9;
+/// Context descriptor type.
+const IXGBE_ADVTXD_DTYP_CTXT: u32 = 0x0020_0000;
+/// Advanced descriptor format.
+const IXGBE_ADVTXD_DCMD_DEXT: u32 = 0x2000_0000;
+/// The packet is IPv4.
+const IXGBE_ADVTXD_TUCMD_IPV4: u32 = 0x0000_0400;
+/// The L4 header is TCP; UDP is encoded as 0.
+const IXGBE_ADVTXD_TUCMD_L4T_TCP: u32 = 0x0000_0800;
+/// Shift of POPTS in the `olinfo_status` field of a data descriptor.
+const IXGBE_ADVTXD_POPTS_SHIFT: u32 = 8;
+/// Insert the IPv4 header checksum.
+const IXGBE_TXD_POPTS_IXSM: u32 = 0x01;
+/// Insert the TCP/UDP checksum.
+const IXGBE_TXD_POPTS_TXSM: u32 = 0x02;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETH_P_8021Q: u16 = 0x8100;
+/// Offset of the TCP checksum field in the TCP header.
+const TCP_CSUM_OFFSET: u16 = 16;
+/// Offset of the UDP checksum field in the UDP header.
+const UDP_CSUM_OFFSET: u16 = 6;
+
+/// How the NIC signals that queue 0 received or transmitted packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqMode {
+    /// No interrupt, for platforms that cannot route one: the network stack
+    /// polls the NIC.
+    Polling,
+    /// A legacy INTx or MSI interrupt, with its IRQ number. The cause is read
+    /// from EICR, which deasserts the line.
+    Legacy(usize),
+    /// MSI-X vector 0, which the platform routed to the IRQ number given.
+    Msix(usize),
+}
+
+impl IrqMode {
+    /// Returns the IRQ number, or `None` when polling.
+    pub fn irq(&self) -> Option<usize> {
+        match *self {
+            IrqMode::Polling => None,
+            IrqMode::Legacy(irq) | IrqMode::Msix(irq) => Some(irq),
+        }
+    }
+}
+
+/// The register BAR of a NIC.
+#[derive(Debug, Clone, Copy)]
+struct Regs(usize);
+
+impl Regs {
+    fn read(self, reg: usize) -> u32 {
+        // SAFETY: `reg` is a register offset within the BAR mapped here.
+        unsafe { core::ptr::read_volatile((self.0 + reg) as *const u32) }
+    }
+
+    fn write(self, reg: usize, value: u32) {
+        // SAFETY: `reg` is a register offset within the BAR mapped here.
+        unsafe { core::ptr::write_volatile((self.0 + reg) as *mut u32, value) }
+    }
+}
+
+/// Acknowledges the interrupts of a NIC from its IRQ handler, which cannot
+/// reach the driver while the network stack holds it.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptAck {
+    regs: Regs,
+    mode: IrqMode,
+}
+
+impl InterruptAck {
+    /// Clears the causes of an interrupt, and returns whether the NIC raised
+    /// it, for lines shared with other devices.
+    pub fn ack(&self) -> bool {
+        match self.mode {
+            IrqMode::Polling => false,
+            IrqMode::Legacy(_) => self.regs.read(IXGBE_EICR) != 0,
+            // The message cleared its cause through EIAC.
+            IrqMode::Msix(_) => true,
+        }
+    }
+}
+
+/// Routes the interrupts of queue 0 to vector 0 as `mode` asks, throttled
+/// to one every `itr_usecs`, and unmasks them unless polling.
+fn program_interrupts(regs: Regs, mode: IrqMode, itr_usecs: u32) {
+    regs.write(IXGBE_EIMC, IXGBE_IRQ_CLEAR_MASK);
+    let msix_bits = IXGBE_GPIE_MSIX_MODE | IXGBE_GPIE_OCD | IXGBE_GPIE_PBA_SUPPORT;
+    let gpie = regs.read(IXGBE_GPIE);
+    let (gpie, eiac) = match mode {
+        IrqMode::Msix(_) => (gpie | msix_bits, IXGBE_EIMS_RTX_QUEUE0),
+        _ => (gpie & !msix_bits, 0),
+    };
+    regs.write(IXGBE_GPIE, gpie);
+    regs.write(IXGBE_EIAC, eiac);
+    // Outside of MSI-X mode, vector 0 is bit 0 of EICR.
+    let ivar = regs.read(IXGBE_IVAR0) & !0xffff;
+    regs.write(
+        IXGBE_IVAR0,
+        ivar | IXGBE_IVAR_ALLOC_VAL | (IXGBE_IVAR_ALLOC_VAL << 8),
+    );
+    regs.write(IXGBE_EITR0, eitr(itr_usecs));
+    // Drops the causes left from before.
+    regs.read(IXGBE_EICR);
+    if mode != IrqMode::Polling {
+        regs.write(IXGBE_EIMS, IXGBE_EIMS_RTX_QUEUE0);
+    }
+}
+
 /// The ixgbe NIC device driver.
 ///
 /// `QS` is the ixgbe queue size, `QN` is the ixgbe queue num.
@@ -25,6 +186,13 @@ pub struct IxgbeNic<H: IxgbeHal, const QS: usize, const QN: u16> {
     inner: IxgbeDevice<H, QS>,
     mem_pool: Arc<MemPool>,
     rx_buffer_queue: VecDeque<NetBufHandle>,
+    /// Virtual address of the register BAR.
+    base: usize,
+    irq_mode: IrqMode,
+    /// Joined multicast addresses, which the MTA is rebuilt from.
+    multicast: Vec<MacAddress>,
+    /// Throttling of vector 0, as programmed.
+    itr_usecs: u32,
 }
 
 unsafe impl<H: IxgbeHal, const QS: usize, const QN: u16> Sync for IxgbeNic<H, QS, QN> {}
@@ -33,23 +201,184 @@ unsafe impl<H: IxgbeHal, const QS: usize, const QN: u16> Send for IxgbeNic<H, QS
 impl<H: IxgbeHal, const QS: usize, const QN: u16> IxgbeNic<H, QS, QN> {
     /// Creates a net ixgbe NIC instance and initialize, or returns a error if
     /// any step fails.
-    pub fn init(base: usize, len: usize) -> DriverResult<Self> {
+    ///
+    /// The NIC interrupts as `irq_mode` asks, throttled to one every
+    /// [`DEFAULT_ITR_USECS`] until [`set_coalesce`](NetDriverOps::set_coalesce)
+    /// says otherwise.
+    pub fn init(base: usize, len: usize, irq_mode: IrqMode) -> DriverResult<Self> {
         let mem_pool = MemPool::allocate::<H>(MEM_POOL, MEM_POOL_ENTRY_SIZE)
             .map_err(|_| DriverError::NoMemory)?;
         let inner = IxgbeDevice::<H, QS>::init(base, len, QN, QN, &mem_pool).map_err(|err| {
-            error!("Failed to initialize ixgbe device: {err:?}");
-            DriverError::BadState
+            let dev_err = device_error(base, ErrorClass::Unknown);
+            error!("Failed to initialize ixgbe device: {err:?}, {dev_err}");
+            dev_err
         })?;
 
         let rx_buffer_queue = VecDeque::with_capacity(RX_BUFFER_SIZE);
-        Ok(Self {
+        let mut nic = Self {
             inner,
             mem_pool,
             rx_buffer_queue,
-        })
+            base,
+            irq_mode,
+            multicast: Vec::new(),
+            itr_usecs: DEFAULT_ITR_USECS,
+        };
+        nic.set_irq_mode(irq_mode);
+        Ok(nic)
+    }
+
+    /// Switches to interrupting as `mode` asks, such as to polling if the
+    /// IRQ handler could not be registered.
+    pub fn set_irq_mode(&mut self, mode: IrqMode) {
+        self.irq_mode = mode;
+        program_interrupts(Regs(self.base), mode, self.itr_usecs);
+    }
+
+    /// Returns the IRQ number of the NIC, or `None` when it is polled.
+    pub fn irq_number(&self) -> Option<usize> {
+        self.irq_mode.irq()
+    }
+
+    /// Returns what acknowledges the interrupts of the NIC from its IRQ
+    /// handler.
+    pub fn interrupt_ack(&self) -> InterruptAck {
+        InterruptAck {
+            regs: Regs(self.base),
+            mode: self.irq_mode,
+        }
+    }
+
+    /// Clears the causes of an interrupt, and returns whether the NIC raised
+    /// it.
+    pub fn ack_interrupt(&self) -> bool {
+        self.interrupt_ack().ack()
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        Regs(self.base).read(reg)
+    }
+
+    fn write_reg(&self, reg: usize, value: u32) {
+        Regs(self.base).write(reg, value)
+    }
+
+    /// Rewrites the Multicast Table Array from the joined addresses.
+    ///
+    /// Bits are shared by addresses that hash alike, so the table is rebuilt
+    /// rather than updated bit by bit.
+    fn write_mta(&self) {
+        let mut mta = [0u32; IXGBE_MTA_LEN];
+        for addr in &self.multicast {
+            let hash = mta_hash(addr);
+            mta[hash >> 5] |= 1 << (hash & 0x1f);
+        }
+        for (i, word) in mta.iter().enumerate() {
+            self.write_reg(IXGBE_MTA + i * 4, *word);
+        }
+        let mcstctrl = if self.multicast.is_empty() {
+            0
+        } else {
+            IXGBE_MCSTCTRL_MFE
+        };
+        self.write_reg(IXGBE_MCSTCTRL, mcstctrl);
+    }
+}
+
+/// Returns the EITR value that throttles interrupts to one per `usecs`.
+///
+/// The interval is kept in units of 2 us, so odd values round down.
+fn eitr(usecs: u32) -> u32 {
+    ((usecs.min(IXGBE_EITR_ITR_MASK >> 2) << 2) & IXGBE_EITR_ITR_MASK) | IXGBE_EITR_CNT_WDIS
+}
+
+/// Offload setup of a transmitted frame: an advanced context descriptor and
+/// the POPTS bits of the data descriptor that refers to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TxContext {
+    vlan_macip_lens: u32,
+    type_tucmd_mlhl: u32,
+    olinfo_popts: u32,
+}
+
+impl TxContext {
+    /// Builds the context for `frame`, or returns `None` if no offload is
+    /// requested.
+    fn new(offload: &TxOffload, frame: &[u8]) -> DriverResult<Option<Self>> {
+        if offload.is_empty() {
+            return Ok(None);
+        }
+        if offload.gso_size != 0 {
+            // TSO is not advertised: a pool entry cannot hold a super-frame.
+            return Err(DriverError::InvalidInput);
+        }
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        let mac_len = if ethertype == ETH_P_8021Q {
+            ETH_HEADER_LEN + 4
+        } else {
+            ETH_HEADER_LEN
+        };
+        let ip_len = if offload.csum_start != 0 {
+            (offload.csum_start as usize)
+                .checked_sub(mac_len)
+                .ok_or(DriverError::InvalidInput)?
+        } else {
+            let version_ihl = *frame.get(mac_len).ok_or(DriverError::InvalidInput)?;
+            (version_ihl as usize & 0xf) * 4
+        };
+        // IPLEN is 9 bits wide and MACLEN 7 bits wide.
+        if ip_len >= 1 << 9 || mac_len >= 1 << 7 {
+            return Err(DriverError::InvalidInput);
+        }
+
+        let mut tucmd = IXGBE_ADVTXD_DCMD_DEXT | IXGBE_ADVTXD_DTYP_CTXT;
+        let mut popts = 0;
+        if offload.ipv4_csum {
+            tucmd |= IXGBE_ADVTXD_TUCMD_IPV4;
+            popts |= IXGBE_TXD_POPTS_IXSM;
+        }
+        if offload.csum_start != 0 {
+            match offload.csum_offset {
+                TCP_CSUM_OFFSET => tucmd |= IXGBE_ADVTXD_TUCMD_L4T_TCP,
+                UDP_CSUM_OFFSET => {}
+                // The NIC only knows where TCP and UDP keep their checksum.
+                _ => return Err(DriverError::Unsupported),
+            }
+            popts |= IXGBE_TXD_POPTS_TXSM;
+        }
+        Ok(Some(Self {
+            vlan_macip_lens: ((mac_len as u32) << IXGBE_ADVTXD_MACLEN_SHIFT) | ip_len as u32,
+            type_tucmd_mlhl: tucmd,
+            olinfo_popts: popts << IXGBE_ADVTXD_POPTS_SHIFT,
+        }))
+    }
+
+    /// Returns the four words of the context descriptor.
+    fn descriptor(&self) -> [u32; 4] {
+        [self.vlan_macip_lens, 0, self.type_tucmd_mlhl, 0]
     }
 }
 
+/// Builds the error for a failure of the NIC at `base`, keeping its status
+/// register.
+///
+/// A removed or hung device reads all ones, which is reported as
+/// [`ErrorClass::DeviceLost`] instead of `class`.
+fn device_error(base: usize, class: ErrorClass) -> DriverError {
+    // SAFETY: the status register lies within the BAR mapped at `base`.
+    let status = unsafe { core::ptr::read_volatile((base + IXGBE_STATUS) as *const u32) };
+    if status == u32::MAX {
+        DriverError::device(ErrorClass::DeviceLost, status)
+    } else {
+        DriverError::device(class, status)
+    }
+}
+
+/// Returns the MTA bit of `addr`: bits 47:36 of the address, for MO = 0.
+fn mta_hash(addr: &MacAddress) -> usize {
+    ((addr.0[4] as usize >> 4) | ((addr.0[5] as usize) << 4)) & 0xfff
+}
+
 impl<H: IxgbeHal, const QS: usize, const QN: u16> DriverOps for IxgbeNic<H, QS, QN> {
     fn name(&self) -> &str {
         self.inner.get_driver_name()
@@ -58,6 +387,10 @@ impl<H: IxgbeHal, const QS: usize, const QN: u16> DriverOps for IxgbeNic<H, QS,
     fn device_kind(&self) -> DeviceKind {
         DeviceKind::Net
     }
+
+    fn irq(&self) -> Option<usize> {
+        self.irq_number()
+    }
 }
 
 impl<H: IxgbeHal, const QS: usize, const QN: u16> NetDriverOps for IxgbeNic<H, QS, QN> {
@@ -120,19 +453,37 @@ impl<H: IxgbeHal, const QS: usize, const QN: u16> NetDriverOps for IxgbeNic<H, Q
                 }
                 Err(e) => match e {
                     IxgbeError::NotReady => Err(DriverError::WouldBlock),
-                    _ => Err(DriverError::BadState),
+                    _ => Err(device_error(self.base, ErrorClass::Transport)),
                 },
             }
         }
     }
 
+    fn capabilities(&self) -> NetCapabilities {
+        // RX checksum status is kept in the descriptor ring, which is owned by
+        // `ixgbe_driver` and not reported with the received buffers.
+        NetCapabilities::TX_CSUM_IPV4 | NetCapabilities::TX_CSUM_TCP | NetCapabilities::TX_CSUM_UDP
+    }
+
     fn send(&mut self, tx_buf: NetBufHandle) -> DriverResult {
+        let ctx = TxContext::new(&tx_buf.offload(), tx_buf.data())?;
         let tx_buf = ixgbe_ptr_to_buf(tx_buf, &self.mem_pool)?;
-        match self.inner.send(0, tx_buf) {
+        let result = match ctx {
+            Some(ctx) => {
+                self.inner
+                    .send_with_context(0, tx_buf, ctx.descriptor(), ctx.olinfo_popts)
+            }
+            None => self.inner.send(0, tx_buf),
+        };
+        match result {
             Ok(_) => Ok(()),
             Err(err) => match err {
                 IxgbeError::QueueFull => Err(DriverError::WouldBlock),
-                _ => panic!("Unexpected err: {:?}", err),
+                _ => {
+                    let dev_err = device_error(self.base, ErrorClass::Transport);
+                    warn!("ixgbe: send failed: {err:?}, {dev_err}");
+                    Err(dev_err)
+                }
             },
         }
     }
@@ -141,6 +492,67 @@ impl<H: IxgbeHal, const QS: usize, const QN: u16> NetDriverOps for IxgbeNic<H, Q
         let tx_buf = IxgbeNetBuf::alloc(&self.mem_pool, size).map_err(|_| DriverError::NoMemory)?;
         Ok(NetBufHandle::from(tx_buf))
     }
+
+    fn set_promiscuous(&mut self, enable: bool) -> DriverResult {
+        let mut fctrl = self.read_reg(IXGBE_FCTRL);
+        if enable {
+            fctrl |= IXGBE_FCTRL_UPE | IXGBE_FCTRL_MPE;
+        } else {
+            fctrl &= !(IXGBE_FCTRL_UPE | IXGBE_FCTRL_MPE);
+        }
+        self.write_reg(IXGBE_FCTRL, fctrl);
+        Ok(())
+    }
+
+    fn add_multicast(&mut self, addr: MacAddress) -> DriverResult {
+        if !addr.is_multicast() {
+            return Err(DriverError::InvalidInput);
+        }
+        if !self.multicast.contains(&addr) {
+            self.multicast.push(addr);
+            self.write_mta();
+        }
+        Ok(())
+    }
+
+    fn remove_multicast(&mut self, addr: MacAddress) -> DriverResult {
+        let Some(pos) = self.multicast.iter().position(|it| *it == addr) else {
+            return Err(DriverError::InvalidInput);
+        };
+        self.multicast.swap_remove(pos);
+        self.write_mta();
+        Ok(())
+    }
+
+    fn coalesce(&self) -> Option<Coalesce> {
+        Some(Coalesce {
+            rx_usecs: self.itr_usecs,
+            ..Default::default()
+        })
+    }
+
+    fn set_coalesce(&mut self, coalesce: Coalesce) -> DriverResult {
+        // Queue 0 receives and transmits on the same vector, throttled by
+        // the receive setting as Linux does, and the 82599 only throttles
+        // by time.
+        if coalesce.tx_usecs != 0 || coalesce.rx_max_frames != 0 || coalesce.tx_max_frames != 0 {
+            return Err(DriverError::InvalidInput);
+        }
+        let eitr = eitr(coalesce.rx_usecs);
+        self.write_reg(IXGBE_EITR0, eitr);
+        self.itr_usecs = (eitr & IXGBE_EITR_ITR_MASK) >> 2;
+        Ok(())
+    }
+
+    fn set_rx_interrupt(&mut self, enable: bool) -> DriverResult {
+        if self.irq_mode == IrqMode::Polling {
+            // The interrupt stays masked.
+            return Ok(());
+        }
+        let reg = if enable { IXGBE_EIMS } else { IXGBE_EIMC };
+        self.write_reg(reg, IXGBE_EIMS_RTX_QUEUE0);
+        Ok(())
+    }
 }
 
 impl From<IxgbeNetBuf> for NetBufHandle {
@@ -282,6 +694,133 @@ pub mod tests_ixgbe {
         }
     }
 
+    #[def_test]
+    fn test_ixgbe_mta_hash() {
+        // 01:00:5e:00:00:fb (mDNS) hashes bits 47:36.
+        let mdns = MacAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]);
+        assert_eq!(mta_hash(&mdns), 0xfb0);
+        let all_nodes = MacAddress([0x33, 0x33, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(mta_hash(&all_nodes), 0x010);
+        assert!(mta_hash(&MacAddress([0xff; 6])) < IXGBE_MTA_LEN * 32);
+    }
+
+    #[def_test]
+    fn test_ixgbe_eitr() {
+        assert_eq!(eitr(0), IXGBE_EITR_CNT_WDIS);
+        // 2 us units in bits 11:3.
+        assert_eq!(eitr(2) & IXGBE_EITR_ITR_MASK, 1 << 3);
+        assert_eq!(eitr(64) & IXGBE_EITR_ITR_MASK, 32 << 3);
+        assert_eq!(eitr(65), eitr(64));
+        assert_eq!(eitr(u32::MAX) & IXGBE_EITR_ITR_MASK, IXGBE_EITR_ITR_MASK);
+    }
+
+    /// Returns zeroed memory standing in for the registers up to IVAR0.
+    fn mock_regs() -> (Vec<u32>, Regs) {
+        let mut mmio = vec![0u32; (IXGBE_IVAR0 + 4) / 4];
+        let regs = Regs(mmio.as_mut_ptr() as usize);
+        (mmio, regs)
+    }
+
+    #[def_test]
+    fn test_ixgbe_program_legacy_interrupts() {
+        let (mut mmio, regs) = mock_regs();
+        mmio[IXGBE_GPIE / 4] = IXGBE_GPIE_MSIX_MODE | IXGBE_GPIE_PBA_SUPPORT;
+        mmio[IXGBE_IVAR0 / 4] = 0x1234_0000;
+        program_interrupts(regs, IrqMode::Legacy(40), DEFAULT_ITR_USECS);
+        assert_eq!(regs.read(IXGBE_EIMC), IXGBE_IRQ_CLEAR_MASK);
+        assert_eq!(regs.read(IXGBE_GPIE), 0);
+        assert_eq!(regs.read(IXGBE_EIAC), 0);
+        // Queue 1 keeps its mapping.
+        assert_eq!(regs.read(IXGBE_IVAR0), 0x1234_8080);
+        assert_eq!(regs.read(IXGBE_EITR0), eitr(DEFAULT_ITR_USECS));
+        assert_eq!(regs.read(IXGBE_EIMS), IXGBE_EIMS_RTX_QUEUE0);
+
+        let ack = InterruptAck {
+            regs,
+            mode: IrqMode::Legacy(40),
+        };
+        assert!(!ack.ack());
+        mmio[IXGBE_EICR / 4] = IXGBE_EIMS_RTX_QUEUE0;
+        assert!(ack.ack());
+    }
+
+    #[def_test]
+    fn test_ixgbe_program_msix_interrupts() {
+        let (_mmio, regs) = mock_regs();
+        program_interrupts(regs, IrqMode::Msix(48), 100);
+        assert_eq!(
+            regs.read(IXGBE_GPIE),
+            IXGBE_GPIE_MSIX_MODE | IXGBE_GPIE_OCD | IXGBE_GPIE_PBA_SUPPORT
+        );
+        assert_eq!(regs.read(IXGBE_EIAC), IXGBE_EIMS_RTX_QUEUE0);
+        assert_eq!(regs.read(IXGBE_IVAR0), 0x8080);
+        assert_eq!(regs.read(IXGBE_EITR0) & IXGBE_EITR_ITR_MASK, 50 << 3);
+        assert_eq!(regs.read(IXGBE_EIMS), IXGBE_EIMS_RTX_QUEUE0);
+        assert_eq!(IrqMode::Msix(48).irq(), Some(48));
+    }
+
+    #[def_test]
+    fn test_ixgbe_polling_leaves_interrupts_masked() {
+        let (_mmio, regs) = mock_regs();
+        program_interrupts(regs, IrqMode::Polling, DEFAULT_ITR_USECS);
+        assert_eq!(regs.read(IXGBE_EIMC), IXGBE_IRQ_CLEAR_MASK);
+        assert_eq!(regs.read(IXGBE_EIMS), 0);
+        assert_eq!(IrqMode::Polling.irq(), None);
+        let ack = InterruptAck {
+            regs,
+            mode: IrqMode::Polling,
+        };
+        assert!(!ack.ack());
+    }
+
+    #[def_test]
+    fn test_ixgbe_tx_context() {
+        let mut frame = vec![0u8; 64];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x45;
+        assert!(matches!(
+            TxContext::new(&TxOffload::default(), &frame),
+            Ok(None)
+        ));
+
+        let tcp = TxOffload {
+            csum_start: 34,
+            csum_offset: TCP_CSUM_OFFSET,
+            ipv4_csum: true,
+            gso_size: 0,
+        };
+        let ctx = TxContext::new(&tcp, &frame).unwrap().unwrap();
+        assert_eq!(ctx.vlan_macip_lens, (14 << 9) | 20);
+        assert_eq!(
+            ctx.type_tucmd_mlhl,
+            IXGBE_ADVTXD_DCMD_DEXT
+                | IXGBE_ADVTXD_DTYP_CTXT
+                | IXGBE_ADVTXD_TUCMD_IPV4
+                | IXGBE_ADVTXD_TUCMD_L4T_TCP
+        );
+        assert_eq!(ctx.olinfo_popts, 0x300);
+
+        // UDP behind a VLAN tag, L4 checksum only.
+        frame[12..14].copy_from_slice(&[0x81, 0x00]);
+        let udp = TxOffload {
+            csum_start: 38,
+            csum_offset: UDP_CSUM_OFFSET,
+            ..Default::default()
+        };
+        let ctx = TxContext::new(&udp, &frame).unwrap().unwrap();
+        assert_eq!(ctx.vlan_macip_lens, (18 << 9) | 20);
+        assert_eq!(ctx.olinfo_popts, 0x200);
+
+        let tso = TxOffload {
+            gso_size: 1448,
+            ..tcp
+        };
+        assert!(matches!(
+            TxContext::new(&tso, &frame),
+            Err(DriverError::InvalidInput)
+        ));
+    }
+
     #[def_test]
     fn test_ixgbe_mac_address_boundary_conditions() {
         // Test MAC address validation and edge cases
@@ -20,12 +20,58 @@ extern crate alloc;
 pub use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult};
 
 mod net_buf;
-pub use self::net_buf::{NetBuf, NetBufBox, NetBufHandle, NetBufPool};
+use bitflags::bitflags;
+
+pub use self::net_buf::{
+    NetBuf, NetBufBox, NetBufCpuIf, NetBufHandle, NetBufPool, NetBufPoolStats, TxOffload,
+};
 
 /// The hardware (MAC) address of a NIC.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MacAddress(pub [u8; 6]);
 
+impl MacAddress {
+    /// Whether this is a group (multicast or broadcast) address.
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+bitflags! {
+    /// Offloads a NIC can perform in hardware.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct NetCapabilities: u32 {
+        /// Fills in the IPv4 header checksum of transmitted packets.
+        const TX_CSUM_IPV4 = 1 << 0;
+        /// Fills in the TCP checksum of transmitted packets, as requested by
+        /// [`TxOffload::csum_start`] and [`TxOffload::csum_offset`].
+        const TX_CSUM_TCP = 1 << 1;
+        /// Fills in the UDP checksum of transmitted packets, as requested by
+        /// [`TxOffload::csum_start`] and [`TxOffload::csum_offset`].
+        const TX_CSUM_UDP = 1 << 2;
+        /// Verifies the IP, TCP and UDP checksums of received packets, and
+        /// drops packets that fail verification.
+        const RX_CSUM = 1 << 3;
+        /// Splits TCP packets larger than the MTU into segments of
+        /// [`TxOffload::gso_size`] bytes.
+        const TSO = 1 << 4;
+    }
+}
+
+/// Interrupt coalescing settings of a NIC, the hardware part of Linux's
+/// `struct ethtool_coalesce`.
+///
+/// An interrupt is raised once `*_usecs` have passed since the first pending
+/// packet, or once `*_max_frames` packets are pending, whichever comes first.
+/// Zero leaves a limit out; all zeros raise an interrupt for every packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Coalesce {
+    pub rx_usecs: u32,
+    pub rx_max_frames: u32,
+    pub tx_usecs: u32,
+    pub tx_max_frames: u32,
+}
+
 /// Operations that require a network device (NIC) driver to implement.
 pub trait NetDriverOps: DriverOps {
     /// The hardware address of the NIC.
@@ -53,7 +99,18 @@ pub trait NetDriverOps: DriverOps {
     /// returns [`DriverResult`].
     fn recycle_tx(&mut self) -> DriverResult;
 
+    /// Offloads the NIC performs in hardware.
+    ///
+    /// The network stack only attaches a [`TxOffload`] request to a transmit
+    /// buffer if the matching capability is advertised here.
+    fn capabilities(&self) -> NetCapabilities {
+        NetCapabilities::empty()
+    }
+
     /// Transmits a packet in the buffer to the network, without blocking.
+    ///
+    /// Offloads requested by [`NetBufHandle::offload`] are performed by the
+    /// NIC.
     fn send(&mut self, tx_buf: NetBufHandle) -> DriverResult;
 
     /// Receives a packet from the network and stores it in the [`NetBuf`].
@@ -67,4 +124,58 @@ pub trait NetDriverOps: DriverOps {
 
     /// Allocate a memory buffer of a specified size for network transmission.
     fn alloc_tx_buf(&mut self, size: usize) -> DriverResult<NetBufHandle>;
+
+    /// Enables or disables promiscuous mode, in which the NIC receives all
+    /// frames regardless of their destination address.
+    ///
+    /// Returns [`DriverError::Unsupported`] if the NIC cannot filter in
+    /// hardware; the caller then filters received frames itself.
+    fn set_promiscuous(&mut self, _enable: bool) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
+
+    /// Starts receiving frames sent to the multicast address `addr`.
+    ///
+    /// Returns [`DriverError::InvalidInput`] if `addr` is not a multicast
+    /// address, and [`DriverError::Unsupported`] if the NIC has no multicast
+    /// filter.
+    fn add_multicast(&mut self, _addr: MacAddress) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
+
+    /// Stops receiving frames sent to the multicast address `addr`.
+    ///
+    /// Returns [`DriverError::Unsupported`] if the NIC has no multicast
+    /// filter.
+    fn remove_multicast(&mut self, _addr: MacAddress) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
+
+    /// The interrupt coalescing settings of the NIC, or `None` if it cannot
+    /// coalesce interrupts in hardware.
+    fn coalesce(&self) -> Option<Coalesce> {
+        None
+    }
+
+    /// Programs the interrupt coalescing timers and counters of the NIC.
+    ///
+    /// Values are rounded to what the NIC supports, as reported by
+    /// [`NetDriverOps::coalesce`] afterwards. Returns
+    /// [`DriverError::InvalidInput`] for settings the NIC cannot follow, and
+    /// [`DriverError::Unsupported`] if it has no coalescing in hardware.
+    fn set_coalesce(&mut self, _coalesce: Coalesce) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
+
+    /// Enables or disables the receive interrupt, for the network stack to
+    /// poll the NIC while it is busy.
+    ///
+    /// Packets received while the interrupt was disabled do not raise it
+    /// once it is enabled again, so the caller checks
+    /// [`NetDriverOps::can_rx`] after enabling it. Returns
+    /// [`DriverError::Unsupported`] if the interrupt cannot be masked, in
+    /// which case it stays enabled.
+    fn set_rx_interrupt(&mut self, _enable: bool) -> DriverResult {
+        Err(DriverError::Unsupported)
+    }
 }
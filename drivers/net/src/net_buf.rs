@@ -3,13 +3,50 @@
 // See LICENSES for license details.
 
 //! Network buffer types and pool allocator.
+//!
+//! Each CPU keeps a small cache of free buffers of every pool, so that most
+//! allocations and frees do not touch the pool lock. Caches are refilled from
+//! and drained to the shared pool in batches. A cache never holds more than
+//! [`CPU_CACHE_SIZE`] buffers, so buffers freed on another CPU than they were
+//! allocated on, as with RX on one CPU and protocol processing on another,
+//! flow back through the shared pool instead of piling up. Once the pool is
+//! exhausted, buffers left in the caches of other CPUs are taken over.
 use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
 use core::ptr::NonNull;
 
+use crate_interface::call_interface;
+use kspin::SpinNoPreempt;
 use spin::Mutex;
 
 use crate::{DriverError, DriverResult};
 
+/// Per-packet offload requests of a transmitted frame.
+///
+/// The checksum fields follow the Linux `CHECKSUM_PARTIAL` convention: the
+/// checksum field holds the sum of the pseudo-header, and the NIC adds the
+/// bytes from `csum_start` to the end of the frame and stores the complement
+/// at `csum_start + csum_offset`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxOffload {
+    /// Offset from the start of the frame where checksumming starts, or 0 if
+    /// no L4 checksum is requested.
+    pub csum_start: u16,
+    /// Offset of the checksum field from `csum_start`.
+    pub csum_offset: u16,
+    /// Whether the NIC should fill in the IPv4 header checksum.
+    pub ipv4_csum: bool,
+    /// Size of the TCP segments to split the frame into, or 0 to send the
+    /// frame as is.
+    pub gso_size: u16,
+}
+
+impl TxOffload {
+    /// Whether no offload is requested.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// A raw buffer handle for network devices.
 pub struct NetBufHandle {
     // The raw pointer of the owning object.
@@ -17,6 +54,7 @@ pub struct NetBufHandle {
     // The pointer to the payload data.
     data_ptr: NonNull<u8>,
     data_len: usize,
+    offload: TxOffload,
 }
 
 impl NetBufHandle {
@@ -26,6 +64,7 @@ impl NetBufHandle {
             owner_ptr,
             data_ptr,
             data_len,
+            offload: TxOffload::default(),
         }
     }
 
@@ -53,6 +92,16 @@ impl NetBufHandle {
     pub fn data_mut(&mut self) -> &mut [u8] {
         unsafe { core::slice::from_raw_parts_mut(self.data_ptr.as_ptr(), self.data_len) }
     }
+
+    /// Return the offloads requested for the packet.
+    pub fn offload(&self) -> TxOffload {
+        self.offload
+    }
+
+    /// Request offloads for the packet.
+    pub fn set_offload(&mut self, offload: TxOffload) {
+        self.offload = offload;
+    }
 }
 
 const MIN_BUFFER_LEN: usize = 1526;
@@ -81,10 +130,10 @@ pub type NetBufBox = Box<NetBuf>;
 pub struct NetBuf {
     hdr_len: usize,
     payload_len: usize,
+    offload: TxOffload,
     buf_len: usize,
     base_ptr: NonNull<u8>,
-    pool_offset: usize,
-    pool: Arc<NetBufPool>,
+    slot: Arc<PoolSlot>,
 }
 
 unsafe impl Send for NetBuf {}
@@ -156,15 +205,61 @@ impl NetBuf {
         self.payload_len = payload_len;
     }
 
+    /// Returns the offloads requested for the packet.
+    pub const fn offload(&self) -> TxOffload {
+        self.offload
+    }
+
+    /// Requests offloads for the packet.
+    pub fn set_offload(&mut self, offload: TxOffload) {
+        self.offload = offload;
+    }
+
+    /// Splits the buffer into two views at `offset` bytes from its start,
+    /// without copying.
+    ///
+    /// Header and payload keep their position in memory: the parts of them
+    /// before `offset` stay in the first view, the rest moves to the second.
+    /// The offload request stays with the first view. Both views share the
+    /// pool buffer, which returns to the pool once both are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is greater than the capacity.
+    pub fn split_at(self, offset: usize) -> (NetBuf, NetBuf) {
+        assert!(offset <= self.buf_len, "split offset out of bounds");
+        let frame_len = self.hdr_len + self.payload_len;
+        let front_hdr_len = self.hdr_len.min(offset);
+        let back_hdr_len = self.hdr_len.saturating_sub(offset);
+        let back = NetBuf {
+            hdr_len: back_hdr_len,
+            payload_len: frame_len.saturating_sub(offset) - back_hdr_len,
+            offload: TxOffload::default(),
+            buf_len: self.buf_len - offset,
+            base_ptr: unsafe { self.base_ptr.add(offset) },
+            slot: self.slot.clone(),
+        };
+        let front = NetBuf {
+            hdr_len: front_hdr_len,
+            payload_len: frame_len.min(offset) - front_hdr_len,
+            buf_len: offset,
+            ..self
+        };
+        (front, back)
+    }
+
     /// Converts the buffer into a [`NetBufHandle`].
     pub fn into_handle(mut self: Box<Self>) -> NetBufHandle {
         let data_ptr = self.payload_mut().as_mut_ptr();
         let data_len = self.payload_len;
-        NetBufHandle::new(
+        let offload = self.offload;
+        let mut handle = NetBufHandle::new(
             NonNull::new(Box::into_raw(self) as *mut u8).unwrap(),
             NonNull::new(data_ptr).unwrap(),
             data_len,
-        )
+        );
+        handle.set_offload(offload);
+        handle
     }
 
     /// Restore [`NetBuf`] from a handle.
@@ -174,54 +269,164 @@ impl NetBuf {
     /// This function is unsafe because it may cause some memory issues,
     /// so we must ensure that it is called after calling `into_handle`.
     pub unsafe fn from_handle(handle: NetBufHandle) -> Box<Self> {
-        unsafe { Box::from_raw(handle.owner_ptr::<Self>()) }
+        let mut buf = unsafe { Box::from_raw(handle.owner_ptr::<Self>()) };
+        buf.offload = handle.offload;
+        buf
     }
 }
 
-impl Drop for NetBuf {
-    /// Deallocates the buffer into the [`NetBufPool`].
+/// A buffer of a [`NetBufPool`], shared by the views split from it.
+///
+/// It is deallocated into the pool when the last view is dropped.
+struct PoolSlot {
+    addr: usize,
+    pool: Arc<NetBufPool>,
+}
+
+impl Drop for PoolSlot {
     fn drop(&mut self) {
-        self.pool.release_offset(self.pool_offset);
+        self.pool.release(self.addr);
+    }
+}
+
+/// Interface to the CPUs, for the per-CPU buffer caches.
+#[crate_interface::def_interface]
+pub trait NetBufCpuIf {
+    /// Returns the ID of the current CPU.
+    fn this_cpu_id() -> usize;
+
+    /// Returns the number of CPUs.
+    fn cpu_num() -> usize;
+
+    /// A preemption point, where the current task gives way to a task
+    /// waiting for the CPU.
+    fn cond_resched();
+}
+
+/// Number of free buffers a CPU caches at most per pool.
+const CPU_CACHE_SIZE: usize = 32;
+/// Number of buffers moved between a CPU cache and the shared pool at once.
+const CPU_CACHE_BATCH: usize = CPU_CACHE_SIZE / 2;
+
+/// Usage statistics of a [`NetBufPool`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetBufPoolStats {
+    /// Buffers currently allocated.
+    pub allocated: usize,
+    /// Buffers currently free, in the shared pool or cached by CPUs.
+    pub free: usize,
+    /// Buffers currently free in the caches of the CPUs.
+    pub cached: usize,
+    /// Highest number of buffers out of the shared pool at once, allocated
+    /// or cached.
+    pub peak: usize,
+    /// Allocations served from the cache of the allocating CPU.
+    pub local_hits: u64,
+    /// Allocations that had to refill the cache of the allocating CPU or
+    /// grow the pool.
+    pub refills: u64,
+    /// Allocations that failed because the pool was exhausted.
+    pub failures: u64,
+}
+
+struct PoolState {
+    /// Addresses of the free buffers not cached by any CPU.
+    free: Vec<usize>,
+    /// Buffers owned by the pool, free or not.
+    total: usize,
+    peak: usize,
+    refills: u64,
+    failures: u64,
+}
+
+impl PoolState {
+    fn update_peak(&mut self) {
+        self.peak = self.peak.max(self.total - self.free.len());
+    }
+}
+
+/// Free buffers cached by a CPU.
+struct CpuCacheState {
+    free: Vec<usize>,
+    hits: u64,
+}
+
+/// A cache of free buffers for one CPU, on a cache line of its own.
+#[repr(align(64))]
+struct CpuCache(SpinNoPreempt<CpuCacheState>);
+
+impl CpuCache {
+    fn new_array(cpu_num: usize) -> Box<[Self]> {
+        (0..cpu_num.max(1))
+            .map(|_| {
+                Self(SpinNoPreempt::new(CpuCacheState {
+                    free: Vec::with_capacity(CPU_CACHE_SIZE),
+                    hits: 0,
+                }))
+            })
+            .collect()
     }
 }
 
 /// A pool of [`NetBuf`]s to speed up buffer allocation.
 ///
-/// It divides a large memory into several equal parts for each buffer.
+/// It divides a large memory into several equal parts for each buffer. If
+/// allowed, it grows by allocating more buffers separately when exhausted,
+/// and gives them back on [`shrink_to_fit`](Self::shrink_to_fit).
 pub struct NetBufPool {
-    slot_count: usize,
+    max_slots: usize,
     buf_len: usize,
     storage: Vec<u8>,
-    free_offsets: Mutex<Vec<usize>>,
+    state: Mutex<PoolState>,
+    /// Indexed by CPU ID. Always locked before `state`.
+    caches: Box<[CpuCache]>,
 }
 
 impl NetBufPool {
     /// Creates a new pool with the given `slot_count`, and all buffer lengths are
     /// set to `buf_len`.
     pub fn new(slot_count: usize, buf_len: usize) -> DriverResult<Arc<Self>> {
-        if slot_count == 0 {
+        Self::new_with_limits(slot_count, slot_count, buf_len)
+    }
+
+    /// Creates a new pool with `initial` buffers of `buf_len` bytes, which
+    /// grows up to `max` buffers on demand.
+    pub fn new_with_limits(initial: usize, max: usize, buf_len: usize) -> DriverResult<Arc<Self>> {
+        if max == 0 || initial > max {
             return Err(DriverError::InvalidInput);
         }
         if !(MIN_BUFFER_LEN..=MAX_BUFFER_LEN).contains(&buf_len) {
             return Err(DriverError::InvalidInput);
         }
 
-        let storage = vec![0; slot_count * buf_len];
-        let mut free_offsets = Vec::with_capacity(slot_count);
-        for i in 0..slot_count {
-            free_offsets.push(i * buf_len);
+        let storage = vec![0; initial * buf_len];
+        let mut free = Vec::with_capacity(initial);
+        for i in 0..initial {
+            free.push(storage.as_ptr() as usize + i * buf_len);
         }
         Ok(Arc::new(Self {
-            slot_count,
+            max_slots: max,
             buf_len,
             storage,
-            free_offsets: Mutex::new(free_offsets),
+            state: Mutex::new(PoolState {
+                free,
+                total: initial,
+                peak: 0,
+                refills: 0,
+                failures: 0,
+            }),
+            caches: CpuCache::new_array(call_interface!(NetBufCpuIf::cpu_num)),
         }))
     }
 
-    /// Returns the capacity of the pool.
-    pub const fn capacity(&self) -> usize {
-        self.slot_count
+    /// Returns the number of buffers the pool currently holds.
+    pub fn capacity(&self) -> usize {
+        self.state.lock().total
+    }
+
+    /// Returns the number of buffers the pool may grow to.
+    pub const fn max_capacity(&self) -> usize {
+        self.max_slots
     }
 
     /// Returns the length of each buffer.
@@ -229,36 +434,195 @@ impl NetBufPool {
         self.buf_len
     }
 
+    /// Returns the usage statistics of the pool.
+    ///
+    /// The CPU caches are not locked all at once, so the numbers may be
+    /// slightly off while buffers are allocated and freed.
+    pub fn stats(&self) -> NetBufPoolStats {
+        let (mut cached, mut local_hits) = (0, 0);
+        for cache in self.caches.iter() {
+            let cache = cache.0.lock();
+            cached += cache.free.len();
+            local_hits += cache.hits;
+        }
+        let state = self.state.lock();
+        let free = state.free.len() + cached;
+        NetBufPoolStats {
+            allocated: state.total.saturating_sub(free),
+            free,
+            cached,
+            peak: state.peak,
+            local_hits,
+            refills: state.refills,
+            failures: state.failures,
+        }
+    }
+
     /// Allocates a buffer from the pool.
     ///
-    /// Returns `None` if no buffer is available.
+    /// Returns `None` if no buffer is available and the pool cannot grow.
     pub fn alloc_buf(self: &Arc<Self>) -> Option<NetBuf> {
-        let pool_offset = self.free_offsets.lock().pop()?;
-        let buf_ptr =
-            unsafe { NonNull::new(self.storage.as_ptr().add(pool_offset) as *mut u8).unwrap() };
+        let addr = {
+            let mut cache = self.local_cache().lock();
+            match cache.free.pop() {
+                Some(addr) => {
+                    cache.hits += 1;
+                    addr
+                }
+                None => self.refill(&mut cache)?,
+            }
+        };
+
         Some(NetBuf {
             hdr_len: 0,
             payload_len: 0,
+            offload: TxOffload::default(),
             buf_len: self.buf_len,
-            base_ptr: buf_ptr,
-            pool_offset,
-            pool: Arc::clone(self),
+            base_ptr: NonNull::new(addr as *mut u8).unwrap(),
+            slot: Arc::new(PoolSlot {
+                addr,
+                pool: Arc::clone(self),
+            }),
         })
     }
 
     /// Allocates a buffer wrapped in a [`Box`] from the pool.
     ///
-    /// Returns `None` if no buffer is available.
+    /// Returns `None` if no buffer is available and the pool cannot grow.
     pub fn alloc_boxed(self: &Arc<Self>) -> Option<NetBufBox> {
         Some(Box::new(self.alloc_buf()?))
     }
 
-    /// Deallocates a buffer at the given offset.
+    /// Frees the buffers the pool has grown by that are not in use, and
+    /// returns how many were freed.
     ///
-    /// `pool_offset` must be a multiple of `buf_len`.
-    fn release_offset(&self, pool_offset: usize) {
-        debug_assert_eq!(pool_offset % self.buf_len, 0);
-        self.free_offsets.lock().push(pool_offset);
+    /// Meant for memory pressure: the initial buffers are kept. The caches of
+    /// all CPUs are drained first.
+    pub fn shrink_to_fit(&self) -> usize {
+        for cache in self.caches.iter() {
+            let mut cache = cache.0.lock();
+            self.state.lock().free.append(&mut cache.free);
+        }
+
+        let mut state = self.state.lock();
+        let mut grown = Vec::new();
+        state.free.retain(|&addr| {
+            let initial = self.is_initial(addr);
+            if !initial {
+                grown.push(addr);
+            }
+            initial
+        });
+        state.total -= grown.len();
+        drop(state);
+
+        for &addr in &grown {
+            unsafe { self.free_grown(addr) };
+            call_interface!(NetBufCpuIf::cond_resched);
+        }
+        grown.len()
+    }
+
+    /// Returns the buffer cache of the current CPU.
+    fn local_cache(&self) -> &SpinNoPreempt<CpuCacheState> {
+        let cpu = call_interface!(NetBufCpuIf::this_cpu_id);
+        &self.caches[cpu % self.caches.len()].0
+    }
+
+    /// Refills the empty `cache` and returns a buffer, or returns a newly
+    /// allocated buffer if the shared pool is empty.
+    ///
+    /// Once the pool cannot grow, takes over buffers cached by other CPUs.
+    fn refill(&self, cache: &mut CpuCacheState) -> Option<usize> {
+        let mut state = self.state.lock();
+        let batch = state.free.len().min(CPU_CACHE_BATCH);
+        let addr = if batch > 0 {
+            let at = state.free.len() - batch;
+            cache.free.extend(state.free.drain(at..));
+            cache.free.pop().unwrap()
+        } else if state.total < self.max_slots {
+            // Reserve the slot, and allocate it without holding the lock.
+            state.total += 1;
+            drop(state);
+            let addr = Box::into_raw(vec![0u8; self.buf_len].into_boxed_slice()) as *mut u8;
+            state = self.state.lock();
+            addr as usize
+        } else {
+            drop(state);
+            let stolen = self.steal(cache);
+            state = self.state.lock();
+            if !stolen {
+                state.failures += 1;
+                return None;
+            }
+            cache.free.pop().unwrap()
+        };
+        state.refills += 1;
+        state.update_peak();
+        Some(addr)
+    }
+
+    /// Moves half of the buffers cached by another CPU into `cache`, and
+    /// returns whether there were any.
+    fn steal(&self, cache: &mut CpuCacheState) -> bool {
+        for other in self.caches.iter() {
+            // This skips the cache of the current CPU, which is locked, and
+            // cannot deadlock with another CPU stealing at the same time.
+            let Some(mut other) = other.0.try_lock() else {
+                continue;
+            };
+            let count = other.free.len().div_ceil(2);
+            if count > 0 {
+                let at = other.free.len() - count;
+                cache.free.extend(other.free.drain(at..));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns whether `addr` is one of the buffers the pool was created
+    /// with.
+    fn is_initial(&self, addr: usize) -> bool {
+        self.storage.as_ptr_range().contains(&(addr as *const u8))
+    }
+
+    /// Frees a buffer allocated when the pool grew.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be a free grown buffer, removed from the free list.
+    unsafe fn free_grown(&self, addr: usize) {
+        let buf = core::ptr::slice_from_raw_parts_mut(addr as *mut u8, self.buf_len);
+        drop(unsafe { Box::from_raw(buf) });
+    }
+
+    /// Deallocates the buffer at `addr` into the cache of the current CPU.
+    fn release(&self, addr: usize) {
+        let mut cache = self.local_cache().lock();
+        if cache.free.len() == CPU_CACHE_SIZE {
+            // Give the least recently freed buffers back to the shared pool.
+            self.state
+                .lock()
+                .free
+                .extend(cache.free.drain(..CPU_CACHE_BATCH));
+        }
+        cache.free.push(addr);
+    }
+}
+
+impl Drop for NetBufPool {
+    fn drop(&mut self) {
+        // Every buffer holds a reference to the pool, so all are free here.
+        let mut free = core::mem::take(&mut self.state.get_mut().free);
+        for cache in self.caches.iter_mut() {
+            free.append(&mut cache.0.get_mut().free);
+        }
+        for addr in free {
+            if !self.is_initial(addr) {
+                unsafe { self.free_grown(addr) };
+            }
+        }
     }
 }
 
@@ -271,6 +635,14 @@ pub mod tests_netbuf {
 
     use super::*;
 
+    /// Creates a pool with caches for `cpus` CPUs, all of which stand for
+    /// the current CPU if there is only one.
+    fn pool_with_cpus(initial: usize, max: usize, cpus: usize) -> Arc<NetBufPool> {
+        let mut pool = NetBufPool::new_with_limits(initial, max, MIN_BUFFER_LEN).unwrap();
+        Arc::get_mut(&mut pool).unwrap().caches = CpuCache::new_array(cpus);
+        pool
+    }
+
     #[def_test]
     fn test_netbuf_boundary_conditions() {
         // Test NetBufHandle creation with boundary values
@@ -372,4 +744,142 @@ pub mod tests_netbuf {
             }
         }
     }
+
+    #[def_test]
+    fn test_netbuf_pool_grow_and_shrink() {
+        let pool = pool_with_cpus(2, 4, 1);
+        assert!(NetBufPool::new_with_limits(4, 2, MIN_BUFFER_LEN).is_err());
+
+        let bufs: Vec<_> = (0..4).map(|_| pool.alloc_buf().unwrap()).collect();
+        assert_eq!(pool.capacity(), 4);
+        assert!(pool.alloc_buf().is_none());
+        assert_eq!(
+            pool.stats(),
+            NetBufPoolStats {
+                allocated: 4,
+                free: 0,
+                cached: 0,
+                peak: 4,
+                local_hits: 1,
+                refills: 3,
+                failures: 1,
+            }
+        );
+
+        drop(bufs);
+        assert_eq!(pool.stats().free, 4);
+        assert_eq!(pool.shrink_to_fit(), 2);
+        assert_eq!(pool.capacity(), 2);
+        assert_eq!(pool.stats().free, 2);
+        assert_eq!(pool.stats().peak, 4);
+        assert_eq!(pool.shrink_to_fit(), 0);
+    }
+
+    #[def_test]
+    fn test_netbuf_pool_cpu_cache() {
+        let pool = pool_with_cpus(64, 64, 1);
+        let bufs: Vec<_> = (0..40).map(|_| pool.alloc_buf().unwrap()).collect();
+        // The cache is refilled in batches.
+        let stats = pool.stats();
+        assert_eq!((stats.local_hits, stats.refills), (37, 3));
+        assert_eq!(stats.cached, 3 * CPU_CACHE_BATCH - 40);
+
+        // Freed buffers beyond the cache size go back to the shared pool.
+        drop(bufs);
+        let stats = pool.stats();
+        assert_eq!((stats.allocated, stats.free), (0, 64));
+        assert_eq!(stats.cached, CPU_CACHE_SIZE);
+        assert_eq!(pool.state.lock().free.len(), 64 - CPU_CACHE_SIZE);
+
+        assert!(pool.alloc_buf().is_some());
+        assert_eq!(pool.stats().local_hits, 38);
+    }
+
+    #[def_test]
+    fn test_netbuf_pool_steal() {
+        let pool = pool_with_cpus(4, 4, 2);
+        let cpu = call_interface!(NetBufCpuIf::this_cpu_id) % 2;
+        drop(
+            (0..4)
+                .map(|_| pool.alloc_buf().unwrap())
+                .collect::<Vec<_>>(),
+        );
+
+        // Buffers freed on the other CPU are taken over once the pool is
+        // exhausted.
+        let mut local = pool.caches[cpu].0.lock();
+        let freed = core::mem::take(&mut local.free);
+        drop(local);
+        pool.caches[1 - cpu].0.lock().free.extend(freed);
+        let bufs: Vec<_> = (0..4).map(|_| pool.alloc_buf().unwrap()).collect();
+        assert!(pool.alloc_buf().is_none());
+        assert_eq!(pool.stats().failures, 1);
+        drop(bufs);
+        assert_eq!(pool.stats().free, 4);
+    }
+
+    #[def_test]
+    fn test_netbuf_split_at() {
+        let pool = NetBufPool::new(1, MIN_BUFFER_LEN).unwrap();
+        let mut buf = pool.alloc_buf().unwrap();
+        buf.set_hdr_len(10);
+        buf.set_payload_len(100);
+        buf.payload_mut().fill(0x5a);
+
+        let (front, back) = buf.split_at(64);
+        assert_eq!(front.capacity(), 64);
+        assert_eq!((front.hdr_len(), front.payload_len()), (10, 54));
+        assert_eq!(back.capacity(), MIN_BUFFER_LEN - 64);
+        assert_eq!((back.hdr_len(), back.payload_len()), (0, 46));
+        assert!(back.payload().iter().all(|&b| b == 0x5a));
+        // The payload is not copied.
+        assert_eq!(
+            back.payload().as_ptr(),
+            front.frame().as_ptr().wrapping_add(64)
+        );
+
+        // The buffer returns to the pool only once both views are dropped.
+        drop(front);
+        assert_eq!(pool.stats().allocated, 1);
+        let back = Box::new(back).into_handle();
+        assert!(pool.alloc_buf().is_none());
+        drop(unsafe { NetBuf::from_handle(back) });
+        assert_eq!(pool.stats().allocated, 0);
+        assert!(pool.alloc_buf().is_some());
+    }
+
+    #[def_test]
+    fn test_netbuf_pool_concurrent() {
+        const TASKS: usize = 4;
+        const ROUNDS: usize = 200;
+
+        let pool = NetBufPool::new_with_limits(2, 6, MIN_BUFFER_LEN).unwrap();
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|i| {
+                let pool = pool.clone();
+                ktask::spawn(move || {
+                    for round in 0..ROUNDS {
+                        if let Some(mut buf) = pool.alloc_buf() {
+                            buf.set_payload_len(1);
+                            buf.payload_mut()[0] = i as u8;
+                            ktask::yield_now();
+                            assert_eq!(buf.payload()[0], i as u8);
+                        }
+                        if round % 50 == 0 {
+                            pool.shrink_to_fit();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.join(), 0);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocated, 0);
+        assert_eq!(stats.free, pool.capacity());
+        assert!(stats.peak <= 6);
+        assert!(pool.capacity() >= 2);
+    }
 }
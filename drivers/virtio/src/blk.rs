@@ -4,11 +4,30 @@
 
 //! VirtIO block driver adapter.
 use block::BlockDriverOps;
-use driver_base::{DeviceKind, DriverOps, DriverResult};
+use driver_base::{DeviceKind, DriverError, DriverOps, DriverResult, ErrorClass};
 use virtio_drivers::{Hal, device::blk::VirtIOBlk as InnerDev, transport::Transport};
 
 use crate::as_driver_error;
 
+/// `VIRTIO_BLK_S_IOERR`: the device failed to read or write the medium.
+const VIRTIO_BLK_S_IOERR: u32 = 1;
+/// `VIRTIO_BLK_S_UNSUPP`: the device does not support the request.
+const VIRTIO_BLK_S_UNSUPP: u32 = 2;
+
+/// Converts the status of a failed block request into a [`DriverError`]
+/// that keeps the status byte reported by the device.
+const fn as_blk_error(e: virtio_drivers::Error) -> DriverError {
+    match e {
+        virtio_drivers::Error::IoError => {
+            DriverError::device(ErrorClass::Media, VIRTIO_BLK_S_IOERR)
+        }
+        virtio_drivers::Error::Unsupported => {
+            DriverError::device(ErrorClass::Rejected, VIRTIO_BLK_S_UNSUPP)
+        }
+        e => as_driver_error(e),
+    }
+}
+
 /// The VirtIO block device driver.
 pub struct VirtIoBlkDev<H: Hal, T: Transport> {
     inner: InnerDev<H, T>,
@@ -51,13 +70,13 @@ impl<H: Hal, T: Transport> BlockDriverOps for VirtIoBlkDev<H, T> {
     fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DriverResult {
         self.inner
             .read_blocks(block_id as _, buf)
-            .map_err(as_driver_error)
+            .map_err(as_blk_error)
     }
 
     fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DriverResult {
         self.inner
             .write_blocks(block_id as _, buf)
-            .map_err(as_driver_error)
+            .map_err(as_blk_error)
     }
 
     fn flush(&mut self) -> DriverResult {
@@ -91,4 +110,21 @@ mod tests {
         fn assert_send_sync<T: Send + Sync>() {}
         assert_send_sync::<VirtIoBlkDev<MockHal, MockTransport>>();
     }
+
+    #[def_test]
+    fn test_virtio_blk_error_status() {
+        let err = as_blk_error(virtio_drivers::Error::IoError);
+        assert_eq!(
+            err,
+            DriverError::device(ErrorClass::Media, VIRTIO_BLK_S_IOERR)
+        );
+        assert_eq!(
+            as_blk_error(virtio_drivers::Error::Unsupported),
+            DriverError::device(ErrorClass::Rejected, VIRTIO_BLK_S_UNSUPP)
+        );
+        assert_eq!(
+            as_blk_error(virtio_drivers::Error::QueueFull),
+            DriverError::ResourceBusy
+        );
+    }
 }
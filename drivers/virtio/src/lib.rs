@@ -120,7 +120,7 @@ const fn as_device_kind(t: VirtIoDevType) -> Option<DeviceKind> {
 pub(crate) const fn as_driver_error(e: virtio_drivers::Error) -> DriverError {
     use virtio_drivers::{Error::*, device::socket::SocketError::*};
     match e {
-        QueueFull => DriverError::BadState,
+        QueueFull => DriverError::ResourceBusy,
         NotReady => DriverError::WouldBlock,
         WrongToken => DriverError::BadState,
         AlreadyUsed => DriverError::AlreadyExists,
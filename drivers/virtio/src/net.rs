@@ -202,4 +202,15 @@ impl<H: Hal, T: Transport, const QS: usize> NetDriverOps for VirtIoNetDev<H, T,
         // 2. Return the buffer.
         Ok(net_buf.into_handle())
     }
+
+    fn set_rx_interrupt(&mut self, enable: bool) -> DriverResult {
+        // Suppresses the used buffer notifications of both queues; the
+        // device has no coalescing timers of its own.
+        if enable {
+            self.inner.enable_interrupts();
+        } else {
+            self.inner.disable_interrupts();
+        }
+        Ok(())
+    }
 }
@@ -37,10 +37,17 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         .expect("Failed to get executable absolute path");
     let name = loc.name();
 
-    let (entry_vaddr, ustack_top) = load_user_app(&mut uspace, None, args, envs)
+    let image = load_user_app(&mut uspace, None, args, envs)
         .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
 
-    let uctx = UserContext::new(entry_vaddr.into(), ustack_top, 0);
+    #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+    let uctx = if image.compat {
+        UserContext::new_compat(image.entry.into(), image.sp, 0)
+    } else {
+        UserContext::new(image.entry.into(), image.sp, 0)
+    };
+    #[cfg(not(all(target_arch = "aarch64", feature = "compat")))]
+    let uctx = UserContext::new(image.entry.into(), image.sp, 0);
 
     let mut task = new_user_task(name, uctx, 0);
     task.ctx_mut().set_page_table_root(uspace.page_table_root());
@@ -64,6 +71,7 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         kapi::file::add_stdio(&mut FD_TABLE.scope_mut(&mut scope).write())
             .expect("Failed to add stdio");
     }
+    proc_data.set_compat(image.compat);
     let thr = Thread::new(pid, proc_data);
 
     *task.task_ext_mut() = Some(unsafe { KTaskExt::from_impl(thr) });
@@ -29,6 +29,11 @@ fn main() {
     use kfs::FS_CONTEXT;
     kapi::init();
 
+    #[cfg(feature = "fault-inject-heap")]
+    kruntime::fault_inject::corrupt_heap_and_panic();
+    #[cfg(feature = "fault-inject-nested")]
+    kruntime::fault_inject::fault_in_panic();
+
     let args = CMDLINE
         .iter()
         .copied()
@@ -36,6 +41,7 @@ fn main() {
         .collect::<Vec<_>>();
     let envs = [];
 
+    boottime::boot_mark("init");
     let exit_code = entry::run_initproc(&args, &envs);
     info!("Init process exited with code: {exit_code:?}");
 
@@ -16,6 +16,7 @@ mod types;
 
 mod test_path;
 mod test_types;
+mod test_xattr;
 
 pub use fs::*;
 pub use mount::*;
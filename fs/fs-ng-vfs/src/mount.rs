@@ -3,28 +3,32 @@
 // See LICENSES for license details.
 
 //! Mountpoints and location resolution for the VFS.
-use alloc::{
-    string::String,
-    sync::{Arc, Weak},
-    vec,
-};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use core::{
     iter, mem,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     task::Context,
 };
 
 use hashbrown::HashMap;
 use inherit_methods_macro::inherit_methods;
 use kpoll::{IoEvents, Pollable};
+use ksync::{RwLock, RwLockUpgradableReadGuard};
 
 use crate::{
-    DirEntry, DirEntrySink, Filesystem, FilesystemOps, Metadata, MetadataUpdate, Mutex, MutexGuard,
-    NodeFlags, NodePermission, NodeType, OpenOptions, ReferenceKey, TypeMap, VfsError, VfsResult,
+    DeviceId, DirEntry, DirEntrySink, Filesystem, FilesystemOps, FsEvents, Metadata,
+    MetadataUpdate, MutexGuard, NegativeDentries, NodeFlags, NodePermission, NodeType, OpenOptions,
+    ReferenceKey, TypeMap, VfsError, VfsResult, XattrFlags, dir_flag, is_watching, next_cookie,
     path::{DOT, DOTDOT, PathBuf},
 };
 
 /// A mounted filesystem instance and its relationships.
+///
+/// Mounts form a tree: each mount is attached at a location of its parent
+/// and owns the mounts attached below it. A bind mount shows the filesystem
+/// of another mount from any of its entries on, with children and flags of
+/// its own. Mounts do not propagate to each other, i.e. every mount is the
+/// only member of its peer group.
 #[derive(Debug)]
 pub struct Mountpoint {
     /// Root dir entry in the mountpoint.
@@ -32,10 +36,17 @@ pub struct Mountpoint {
     /// Location in the parent mountpoint.
     location: Option<Location>,
     /// Children of the mountpoint - tracks nested mounts under this mountpoint.
-    /// Maps from directory entry keys to weak references to child mountpoints.
-    child_mounts: Mutex<HashMap<ReferenceKey, Weak<Self>>>,
+    /// Maps from the keys of the entries they are attached at to the child
+    /// mountpoints.
+    child_mounts: RwLock<HashMap<ReferenceKey, Arc<Self>>>,
+    /// Shared by all mounts of the same filesystem instance.
+    users: Arc<()>,
+    /// Whether modifications through this mount are refused.
+    read_only: AtomicBool,
     /// Device ID
     device: u64,
+    /// Negative dentries looked up through this mount.
+    negative: NegativeDentries,
 }
 
 impl Mountpoint {
@@ -47,11 +58,51 @@ impl Mountpoint {
         Arc::new(Self {
             root,
             location: location_in_parent,
-            child_mounts: Mutex::default(),
+            child_mounts: RwLock::default(),
+            users: Arc::default(),
+            read_only: AtomicBool::new(false),
             device: DEVICE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            negative: NegativeDentries::new(),
+        })
+    }
+
+    /// Creates a bind mount of `source` at `location_in_parent`, with the
+    /// flags of the mount of `source`.
+    fn new_bind(source: &Location, location_in_parent: Option<Location>) -> Arc<Self> {
+        let mountpoint = &source.mountpoint;
+        Arc::new(Self {
+            root: source.entry.clone(),
+            location: location_in_parent,
+            child_mounts: RwLock::default(),
+            users: mountpoint.users.clone(),
+            read_only: AtomicBool::new(mountpoint.is_read_only()),
+            device: mountpoint.device,
+            negative: NegativeDentries::new(),
         })
     }
 
+    /// Attaches copies of the mounts at or below `entry` to `target`, a bind
+    /// mount of `entry`, recursively.
+    ///
+    /// Only the mounts present when called are copied, so `target` may be
+    /// attached below `entry` afterwards without copying itself.
+    fn copy_children(&self, entry: &DirEntry, target: &Arc<Self>) -> VfsResult<()> {
+        let children: Vec<_> = self.child_mounts.read().values().cloned().collect();
+        for child in children {
+            let at = &child.location.as_ref().unwrap().entry;
+            if !entry.is_ancestor_of(at)? {
+                continue;
+            }
+            let copy = Self::new_bind(
+                &child.root_location(),
+                Some(Location::new(target.clone(), at.clone())),
+            );
+            child.copy_children(&child.root, &copy)?;
+            target.child_mounts.write().insert(at.key(), copy);
+        }
+        Ok(())
+    }
+
     /// Create a root mountpoint for a filesystem.
     pub fn new_root(fs: &Filesystem) -> Arc<Self> {
         Self::new(fs, None)
@@ -72,6 +123,28 @@ impl Mountpoint {
         self.location.is_none()
     }
 
+    /// Returns the mounts attached within this mountpoint.
+    pub fn children(&self) -> Vec<Arc<Self>> {
+        self.child_mounts.read().values().cloned().collect()
+    }
+
+    /// Returns the mount attached at `entry`, if any.
+    fn child_mount(&self, entry: &DirEntry) -> Option<Arc<Self>> {
+        self.child_mounts.read().get(&entry.key()).cloned()
+    }
+
+    /// Returns whether modifications through this mount are refused with
+    /// [`VfsError::ReadOnlyFilesystem`].
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    /// Sets whether modifications through this mount are refused. Other
+    /// mounts of the same filesystem are not affected.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
     /// Returns the effective (visible) mountpoint by traversing the mount chain.
     ///
     /// When multiple filesystems are mounted at the same location, they form a chain
@@ -89,10 +162,10 @@ impl Mountpoint {
     /// # Implementation
     ///
     /// Follows the chain: root mount -> mnt1 -> mnt2 -> ... -> final mount
-    /// by checking if each root directory has a mountpoint attached.
+    /// by checking if a mountpoint is attached at each root.
     pub(crate) fn resolve_final_mount(self: &Arc<Self>) -> Arc<Mountpoint> {
         let mut mountpoint = self.clone();
-        while let Some(mount) = mountpoint.root.as_dir().unwrap().mountpoint() {
+        while let Some(mount) = mountpoint.child_mount(&mountpoint.root) {
             mountpoint = mount;
         }
         mountpoint
@@ -102,6 +175,20 @@ impl Mountpoint {
     pub fn device(self: &Arc<Self>) -> u64 {
         self.device
     }
+
+    /// Returns the maximum number of negative dentries kept for lookups
+    /// through this mount.
+    pub fn negative_dentry_limit(&self) -> usize {
+        self.negative.limit()
+    }
+
+    /// Sets the maximum number of negative dentries kept for lookups through
+    /// this mount, evicting the least recently used ones beyond it.
+    ///
+    /// A limit of zero disables negative dentries for the mount.
+    pub fn set_negative_dentry_limit(&self, limit: usize) {
+        self.negative.set_limit(limit);
+    }
 }
 
 /// A resolved location within a mountpoint.
@@ -117,8 +204,6 @@ impl Location {
 
     pub fn filesystem(&self) -> &dyn FilesystemOps;
 
-    pub fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()>;
-
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> VfsResult<u64>;
 
@@ -130,8 +215,6 @@ impl Location {
 
     pub fn node_type(&self) -> NodeType;
 
-    pub fn is_root_of_mount(&self) -> bool;
-
     pub fn read_link(&self) -> VfsResult<String>;
 
     pub fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize>;
@@ -139,6 +222,12 @@ impl Location {
     pub fn flags(&self) -> NodeFlags;
 
     pub fn user_data(&self) -> MutexGuard<'_, TypeMap>;
+
+    pub fn get_encryption_context(&self) -> VfsResult<Vec<u8>>;
+
+    pub fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>>;
+
+    pub fn list_xattr(&self) -> VfsResult<Vec<String>>;
 }
 
 impl Location {
@@ -161,6 +250,22 @@ impl Location {
         &self.entry
     }
 
+    /// Returns whether this is the root of its mount, where `..` leaves the
+    /// mount.
+    pub fn is_root_of_mount(&self) -> bool {
+        // An entry moved out from below the root of a bind mount reaches the
+        // root of the filesystem instead.
+        self.entry.ptr_eq(&self.mountpoint.root) || self.entry.is_root_of_mount()
+    }
+
+    /// Fails with [`VfsError::ReadOnlyFilesystem`] if the mount is read-only.
+    fn check_writable(&self) -> VfsResult<()> {
+        if self.mountpoint.is_read_only() {
+            return Err(VfsError::ReadOnlyFilesystem);
+        }
+        Ok(())
+    }
+
     /// Returns the name of this location within its parent directory.
     pub fn name(&self) -> &str {
         if self.is_root_of_mount() {
@@ -180,7 +285,7 @@ impl Location {
 
     /// Returns `true` if this is the global root location.
     pub fn is_root(&self) -> bool {
-        self.mountpoint.is_root() && self.entry.is_root_of_mount()
+        self.mountpoint.is_root() && self.is_root_of_mount()
     }
 
     /// Ensure the location refers to a directory.
@@ -200,12 +305,39 @@ impl Location {
         Ok(metadata)
     }
 
+    /// Updates metadata of the entry.
+    pub fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
+        self.check_writable()?;
+        self.entry.update_metadata(update)
+    }
+
+    /// Sets the encryption context of the entry.
+    pub fn set_encryption_context(&self, context: &[u8]) -> VfsResult<()> {
+        self.check_writable()?;
+        self.entry.set_encryption_context(context)
+    }
+
+    /// Sets the extended attribute `name` of the entry.
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> VfsResult<()> {
+        self.check_writable()?;
+        self.entry.set_xattr(name, value, flags)
+    }
+
+    /// Removes the extended attribute `name` of the entry.
+    pub fn remove_xattr(&self, name: &str) -> VfsResult<()> {
+        self.check_writable()?;
+        self.entry.remove_xattr(name)
+    }
+
     /// Build the absolute path for this location.
     pub fn absolute_path(&self) -> VfsResult<PathBuf> {
         let mut components = vec![];
         let mut cur = self.clone();
         loop {
-            cur.entry.collect_absolute_path(&mut components);
+            while !cur.is_root_of_mount() {
+                components.push(cur.entry.name().to_owned());
+                cur = cur.with_entry(cur.entry.parent().unwrap());
+            }
             cur = match cur.mountpoint.location() {
                 Some(loc) => loc,
                 None => break,
@@ -221,14 +353,17 @@ impl Location {
         Arc::ptr_eq(&self.mountpoint, &other.mountpoint) && self.entry.ptr_eq(&other.entry)
     }
 
-    /// Returns `true` if this location is a mountpoint directory.
+    /// Returns `true` if a filesystem is mounted at this location.
     pub fn is_mountpoint(&self) -> bool {
-        self.entry.as_dir().is_ok_and(|it| it.is_mountpoint())
+        self.mountpoint
+            .child_mounts
+            .read()
+            .contains_key(&self.entry.key())
     }
 
     /// See [`Mountpoint::resolve_final_mount`].
     fn resolve_final_mount(self) -> Self {
-        let Some(mountpoint) = self.entry.as_dir().ok().and_then(|it| it.mountpoint()) else {
+        let Some(mountpoint) = self.mountpoint.child_mount(&self.entry) else {
             return self;
         };
         let mountpoint = mountpoint.resolve_final_mount();
@@ -242,7 +377,12 @@ impl Location {
             DOT => self.clone(),
             DOTDOT => self.parent().unwrap_or_else(|| self.clone()),
             _ => {
-                let loc = Self::new(self.mountpoint.clone(), self.entry.as_dir()?.lookup(name)?);
+                let entry = self.entry.as_dir()?.lookup_in_mount(
+                    &self.entry,
+                    name,
+                    &self.mountpoint.negative,
+                )?;
+                let loc = Self::new(self.mountpoint.clone(), entry);
                 loc.resolve_final_mount()
             }
         })
@@ -255,10 +395,31 @@ impl Location {
         node_type: NodeType,
         permission: NodePermission,
     ) -> VfsResult<Self> {
-        self.entry
+        self.check_writable()?;
+        let entry = self.entry.as_dir()?.create(name, node_type, permission)?;
+        self.entry.notify_entry(
+            FsEvents::CREATE | dir_flag(node_type == NodeType::Directory),
+            name,
+            0,
+        );
+        Ok(self.with_entry(entry))
+    }
+
+    /// Create a character or block device node under this directory.
+    pub fn mknod(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<Self> {
+        self.check_writable()?;
+        let entry = self
+            .entry
             .as_dir()?
-            .create(name, node_type, permission)
-            .map(|entry| self.with_entry(entry))
+            .mknod(name, node_type, permission, rdev)?;
+        self.entry.notify_entry(FsEvents::CREATE, name, 0);
+        Ok(self.with_entry(entry))
     }
 
     /// Create a hard link to an existing node.
@@ -266,10 +427,10 @@ impl Location {
         if !Arc::ptr_eq(&self.mountpoint, &node.mountpoint) {
             return Err(VfsError::CrossesDevices);
         }
-        self.entry
-            .as_dir()?
-            .link(name, &node.entry)
-            .map(|entry| self.with_entry(entry))
+        self.check_writable()?;
+        let entry = self.entry.as_dir()?.link(name, &node.entry)?;
+        self.entry.notify_entry(FsEvents::CREATE, name, 0);
+        Ok(self.with_entry(entry))
     }
 
     /// Rename an entry within the same mountpoint.
@@ -277,25 +438,66 @@ impl Location {
         if !Arc::ptr_eq(&self.mountpoint, &dst_dir.mountpoint) {
             return Err(VfsError::CrossesDevices);
         }
+        self.check_writable()?;
         if !self.ptr_eq(dst_dir) && self.entry.is_ancestor_of(&dst_dir.entry)? {
             return Err(VfsError::InvalidInput);
         }
-        self.entry
-            .as_dir()?
-            .rename(src_name, dst_dir.entry.as_dir()?, dst_name)
+        let dir = self.entry.as_dir()?;
+        // Looked up beforehand only to be told about the move.
+        let moved = is_watching().then(|| dir.lookup(src_name).ok()).flatten();
+        dir.rename(src_name, dst_dir.entry.as_dir()?, dst_name)?;
+        if let Some(moved) = moved {
+            let isdir = dir_flag(moved.is_dir());
+            let cookie = next_cookie();
+            self.entry
+                .notify_entry(FsEvents::MOVED_FROM | isdir, src_name, cookie);
+            dst_dir
+                .entry
+                .notify_entry(FsEvents::MOVED_TO | isdir, dst_name, cookie);
+            moved.notify(FsEvents::MOVE_SELF);
+        }
+        Ok(())
     }
 
     /// Remove a file or directory entry.
     pub fn unlink(&self, name: &str, is_dir: bool) -> VfsResult<()> {
-        self.entry.as_dir()?.unlink(name, is_dir)
+        self.check_writable()?;
+        let dir = self.entry.as_dir()?;
+        // Looked up beforehand only to be told about the deletion.
+        let entry = is_watching().then(|| dir.lookup(name).ok()).flatten();
+        dir.unlink(name, is_dir)?;
+        self.entry
+            .notify_entry(FsEvents::DELETE | dir_flag(is_dir), name, 0);
+        // Other hard links keep the inode alive.
+        if let Some(entry) = entry
+            && (is_dir || entry.metadata().ok().is_none_or(|it| it.nlink == 0))
+        {
+            entry.notify(FsEvents::DELETE_SELF);
+        }
+        Ok(())
     }
 
     /// Open a file entry with options.
     pub fn open_file(&self, name: &str, options: &OpenOptions) -> VfsResult<Location> {
-        self.entry
-            .as_dir()?
-            .open_file(name, options)
-            .map(|entry| self.with_entry(entry).resolve_final_mount())
+        let dir = self.entry.as_dir()?;
+        let (entry, created) = if options.create && self.mountpoint.is_read_only() {
+            // Files that exist may still be opened.
+            let options = OpenOptions {
+                create: false,
+                ..options.clone()
+            };
+            dir.open_or_create(name, &options)
+                .map_err(|err| match err.canonicalize() {
+                    VfsError::NotFound => VfsError::ReadOnlyFilesystem,
+                    _ => err,
+                })?
+        } else {
+            dir.open_or_create(name, options)?
+        };
+        if created {
+            self.entry.notify_entry(FsEvents::CREATE, name, 0);
+        }
+        Ok(self.with_entry(entry).resolve_final_mount())
     }
 
     /// Read directory entries starting from the given offset.
@@ -305,45 +507,99 @@ impl Location {
 
     /// Mount a filesystem at this location.
     pub fn mount(&self, fs: &Filesystem) -> VfsResult<Arc<Mountpoint>> {
-        let mut mountpoint = self.entry.as_dir()?.mount_at_this_dir.lock();
-        if mountpoint.is_some() {
-            return Err(VfsError::ResourceBusy);
+        let dir = self.entry.as_dir()?;
+        let result = self.attach(Mountpoint::new(fs, Some(self.clone())))?;
+        dir.forget_negatives();
+        Ok(result)
+    }
+
+    /// Makes `source` appear at this location as well, sharing its inodes.
+    ///
+    /// With `recursive`, the mounts below `source` are bound along. Binding
+    /// a directory into its own subtree does not repeat the new mount.
+    pub fn bind_mount(&self, source: &Location, recursive: bool) -> VfsResult<Arc<Mountpoint>> {
+        if source.is_dir() != self.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        let mount = Mountpoint::new_bind(source, Some(self.clone()));
+        if recursive {
+            source.mountpoint.copy_children(&source.entry, &mount)?;
+        }
+        let result = self.attach(mount)?;
+        if let Ok(dir) = self.entry.as_dir() {
+            dir.forget_negatives();
         }
-        let result = Mountpoint::new(fs, Some(self.clone()));
-        *mountpoint = Some(result.clone());
-        self.mountpoint
-            .child_mounts
-            .lock()
-            .insert(self.entry.key(), Arc::downgrade(&result));
         Ok(result)
     }
 
+    /// Attaches `mount`, created to be mounted at this location.
+    fn attach(&self, mount: Arc<Mountpoint>) -> VfsResult<Arc<Mountpoint>> {
+        let children = self.mountpoint.child_mounts.upgradable_read();
+        let key = self.entry.key();
+        if children.contains_key(&key) {
+            return Err(VfsError::ResourceBusy);
+        }
+        RwLockUpgradableReadGuard::upgrade(children).insert(key, mount.clone());
+        Ok(mount)
+    }
+
     /// Unmount the filesystem rooted at this location.
     pub fn unmount(&self) -> VfsResult<()> {
-        if !self.is_root_of_mount() {
+        if !self.entry.ptr_eq(&self.mountpoint.root) {
             return Err(VfsError::InvalidInput);
         }
-        if !self.mountpoint.child_mounts.lock().is_empty() {
+        if !self.mountpoint.child_mounts.read().is_empty() {
             return Err(VfsError::ResourceBusy);
         }
-        assert!(self.entry.ptr_eq(&self.mountpoint.root));
-        self.entry.as_dir()?.forget();
+        // Other mounts of the filesystem keep using the cached entries.
+        if Arc::strong_count(&self.mountpoint.users) == 1
+            && let Ok(dir) = self.entry.as_dir()
+        {
+            dir.forget();
+        }
+        self.detach()
+    }
+
+    /// Detaches the filesystem rooted at this location from the mount tree.
+    ///
+    /// Unlike [`unmount`](Self::unmount), this neither requires the mount to
+    /// be unused nor to have no nested mounts: locations already resolved
+    /// into it stay valid, and the filesystem is released with the last of
+    /// them.
+    pub fn detach(&self) -> VfsResult<()> {
+        if !self.entry.ptr_eq(&self.mountpoint.root) {
+            return Err(VfsError::InvalidInput);
+        }
         if let Some(parent_loc) = &self.mountpoint.location {
-            *parent_loc.entry.as_dir()?.mount_at_this_dir.lock() = None;
+            let children = parent_loc.mountpoint.child_mounts.upgradable_read();
+            let key = parent_loc.entry.key();
+            // Detached before, and maybe replaced since.
+            if children
+                .get(&key)
+                .is_some_and(|it| Arc::ptr_eq(it, &self.mountpoint))
+            {
+                RwLockUpgradableReadGuard::upgrade(children).remove(&key);
+            }
         }
         Ok(())
     }
 
+    /// Returns whether the mount rooted at this location is used by anything
+    /// other than `self`, such as open files, working directories or nested
+    /// mounts.
+    pub fn is_mount_busy(&self) -> bool {
+        // One reference is held by the parent mount.
+        Arc::strong_count(&self.mountpoint) > 2
+    }
+
     /// Recursively unmount this filesystem and all children.
     pub fn unmount_all(&self) -> VfsResult<()> {
-        if !self.is_root_of_mount() {
+        if !self.entry.ptr_eq(&self.mountpoint.root) {
             return Err(VfsError::InvalidInput);
         }
-        let children = mem::take(&mut *self.mountpoint.child_mounts.lock());
+        let children = mem::take(&mut *self.mountpoint.child_mounts.write());
         for (_, child) in children {
-            if let Some(child) = child.upgrade() {
-                child.root_location().unmount_all()?;
-            }
+            child.root_location().unmount_all()?;
         }
         self.unmount()
     }
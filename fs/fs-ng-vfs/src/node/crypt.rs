@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Hooks for encrypted directories.
+//!
+//! The VFS does not know how names are encrypted. A directory is encrypted
+//! when the registered [`NameCipherResolver`] returns a [`NameCipher`] for it;
+//! [`DirNode`](super::DirNode) then translates between the names seen by
+//! users and the names stored by the filesystem.
+use alloc::{string::String, sync::Arc};
+
+use super::{DirEntry, DirNodeOps};
+use crate::{Mutex, VfsResult};
+
+/// Translation of the entry names of an encrypted directory.
+pub trait NameCipher: Send + Sync {
+    /// Returns the name stored by the filesystem for the user-visible `name`.
+    ///
+    /// `create` is set when a new entry is about to be created, which
+    /// requires the key. Without the key, existing entries can still be
+    /// found by the names [`decrypt_name`](Self::decrypt_name) reported.
+    fn encrypt_name(&self, name: &str, create: bool) -> VfsResult<String>;
+
+    /// Returns the user-visible name of the stored name `stored`.
+    fn decrypt_name(&self, stored: &str) -> String;
+
+    /// Counter that changes whenever the user-visible names may have changed,
+    /// e.g. because a key was added or removed. Cached entries of the
+    /// directory are dropped when it does.
+    fn generation(&self) -> u64;
+
+    /// Sets up the encryption of `entry`, just created in the directory.
+    fn init_child(&self, entry: &DirEntry) -> VfsResult<()>;
+
+    /// Checks that `entry` may be linked or moved into the directory.
+    fn check_link(&self, entry: &DirEntry) -> VfsResult<()>;
+}
+
+/// Returns the name cipher of a directory, or `None` if it is not encrypted.
+pub type NameCipherResolver = fn(&dyn DirNodeOps) -> VfsResult<Option<Arc<dyn NameCipher>>>;
+
+static RESOLVER: Mutex<Option<NameCipherResolver>> = Mutex::new(None);
+
+/// Registers the resolver consulted for every directory.
+///
+/// Directories whose cipher was already resolved are not affected.
+pub fn set_name_cipher_resolver(resolver: NameCipherResolver) {
+    *RESOLVER.lock() = Some(resolver);
+}
+
+pub(crate) fn resolve_name_cipher(ops: &dyn DirNodeOps) -> VfsResult<Option<Arc<dyn NameCipher>>> {
+    let resolver = *RESOLVER.lock();
+    match resolver {
+        Some(resolver) => resolver(ops),
+        None => Ok(None),
+    }
+}
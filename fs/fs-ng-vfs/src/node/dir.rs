@@ -3,18 +3,28 @@
 // See LICENSES for license details.
 
 //! Directory node traits and helpers.
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
 use core::{
     mem,
     ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 
-use super::DirEntry;
+use super::{
+    DirEntry, NameCipher, NegativeDentries,
+    crypt::resolve_name_cipher,
+    negative::{count_hit, count_miss},
+};
 use crate::{
-    MetadataUpdate, Mountpoint, Mutex, MutexGuard, NodeOps, NodePermission, NodeType, VfsError,
-    VfsResult,
+    DeviceId, Metadata, MetadataUpdate, Mutex, MutexGuard, NodeOps, NodePermission, NodeType,
+    VfsError, VfsResult,
     path::{DOT, DOTDOT, MAX_NAME_LEN, verify_entry_name},
 };
 
@@ -38,9 +48,19 @@ impl<F: FnMut(&str, u64, NodeType, u64) -> bool> DirEntrySink for F {
 
 type DirChildren = HashMap<String, DirEntry>;
 
+/// Maximum number of inodes handed to [`DirNodeOps::prefetch_inodes`] after a
+/// single [`DirNode::read_dir`] call.
+const READAHEAD_BATCH: usize = 256;
+
 /// Directory node operations.
 pub trait DirNodeOps: NodeOps {
-    /// Reads directory entries.
+    /// Reads directory entries, from the cookie `offset` on.
+    ///
+    /// Each entry is passed to `sink` with the cookie of the entry after it,
+    /// from which a later call resumes; `0` is the start of the directory.
+    /// Implementations should resume at the first entry not returned yet,
+    /// even if entries were removed or added in between, and read no more
+    /// of the directory than needed to fill `sink`.
     ///
     /// Returns the number of entries read.
     ///
@@ -51,6 +71,27 @@ pub trait DirNodeOps: NodeOps {
     /// Lookups a directory entry by name.
     fn lookup(&self, name: &str) -> VfsResult<DirEntry>;
 
+    /// Hints that the children with the given inode numbers, as just reported
+    /// by [`read_dir`](Self::read_dir), are about to be inspected.
+    ///
+    /// Filesystems may use this to fetch the on-disk inodes in one sorted
+    /// batch so that the following `stat` calls are served from cache. The
+    /// default implementation does nothing.
+    fn prefetch_inodes(&self, _inodes: &[u64]) {}
+
+    /// Returns the metadata of several children at once, identified by the
+    /// inode numbers reported by [`read_dir`](Self::read_dir).
+    ///
+    /// `sink` is called once for every inode, in the given order. Filesystems
+    /// that cannot access nodes by inode number return `Unsupported`.
+    fn stat_inodes(
+        &self,
+        _inodes: &[u64],
+        _sink: &mut dyn FnMut(u64, VfsResult<Metadata>),
+    ) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+
     /// Returns whether directory entries can be cached.
     ///
     /// Some filesystems may not support caching directory entries because:
@@ -87,6 +128,21 @@ pub trait DirNodeOps: NodeOps {
         permission: NodePermission,
     ) -> VfsResult<DirEntry>;
 
+    /// Creates a character or block device node with device number `rdev`.
+    ///
+    /// Filesystems that cannot store device numbers keep the default, which
+    /// fails with `EPERM`.
+    fn mknod(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<DirEntry> {
+        let _ = (name, node_type, permission, rdev);
+        Err(VfsError::OperationNotPermitted)
+    }
+
     /// Creates a link to a node.
     fn link(&self, name: &str, node: &DirEntry) -> VfsResult<DirEntry>;
 
@@ -140,11 +196,29 @@ impl Default for OpenOptions {
     }
 }
 
+type Cipher = Option<Arc<dyn NameCipher>>;
+
 /// Directory node wrapper with dentry cache support.
+///
+/// The dentry cache is keyed by user-visible names, which differ from the
+/// names passed to [`DirNodeOps`] in encrypted directories (see
+/// [`NameCipher`]).
+///
+/// Names that were looked up through a [`Location`](crate::Location) and
+/// not found are remembered as negative dentries until an entry of that name
+/// is created, or until the mount evicts them (see [`negative_dentry_stats`]).
+///
+/// [`negative_dentry_stats`]: crate::negative_dentry_stats
 pub struct DirNode {
     ops: Arc<dyn DirNodeOps>,
     dentry_cache: Mutex<DirChildren>,
-    pub(crate) mount_at_this_dir: Mutex<Option<Arc<Mountpoint>>>,
+    /// Names known to be missing. Innermost lock: nothing else is locked
+    /// while it is held.
+    negative: Mutex<HashSet<String>>,
+    /// Name cipher of the directory, `None` until resolved.
+    cipher: Mutex<Option<Cipher>>,
+    /// Generation of the cipher the cached entries were looked up with.
+    cipher_generation: AtomicU64,
 }
 
 impl Deref for DirNode {
@@ -167,7 +241,9 @@ impl DirNode {
         Self {
             ops,
             dentry_cache: Mutex::default(),
-            mount_at_this_dir: Mutex::default(),
+            negative: Mutex::default(),
+            cipher: Mutex::default(),
+            cipher_generation: AtomicU64::new(0),
         }
     }
 
@@ -185,6 +261,42 @@ impl DirNode {
             .map_err(|_| VfsError::InvalidInput)
     }
 
+    /// Returns the name cipher of the directory, or `None` if it is not
+    /// encrypted.
+    ///
+    /// Cached entries are dropped if the cipher's generation changed since
+    /// they were looked up.
+    pub fn name_cipher(&self) -> VfsResult<Cipher> {
+        let mut guard = self.cipher.lock();
+        let cipher = match &*guard {
+            Some(cipher) => cipher.clone(),
+            None => guard.insert(resolve_name_cipher(&*self.ops)?).clone(),
+        };
+        drop(guard);
+
+        if let Some(cipher) = &cipher {
+            let generation = cipher.generation();
+            if self.cipher_generation.swap(generation, Ordering::AcqRel) != generation {
+                self.forget();
+            }
+        }
+        Ok(cipher)
+    }
+
+    /// Makes the next operation resolve the name cipher again, e.g. after
+    /// the directory became encrypted.
+    pub fn reset_name_cipher(&self) {
+        *self.cipher.lock() = None;
+        self.forget();
+    }
+
+    fn stored_name<'a>(cipher: &Cipher, name: &'a str, create: bool) -> VfsResult<Cow<'a, str>> {
+        match cipher {
+            Some(cipher) => cipher.encrypt_name(name, create).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(name)),
+        }
+    }
+
     fn forget_entry(children: &mut DirChildren, name: &str) {
         if let Some(entry) = children.remove(name)
             && let Ok(dir) = entry.as_dir()
@@ -193,12 +305,44 @@ impl DirNode {
         }
     }
 
-    fn lookup_locked(&self, name: &str, children: &mut DirChildren) -> VfsResult<DirEntry> {
+    /// Looks up `name`, answering from the dentry cache and the negative
+    /// dentries if possible.
+    ///
+    /// With `charge`, the entry of this directory and the mount it was
+    /// reached through, a name the filesystem does not find is remembered as
+    /// a negative dentry charged to that mount.
+    fn lookup_locked(
+        &self,
+        name: &str,
+        cipher: &Cipher,
+        children: &mut DirChildren,
+        charge: Option<(&DirEntry, &NegativeDentries)>,
+    ) -> VfsResult<DirEntry> {
         use hashbrown::hash_map::Entry;
         match children.entry(name.to_owned()) {
             Entry::Occupied(e) => Ok(e.get().clone()),
             Entry::Vacant(e) => {
-                let node = self.ops.lookup(name)?;
+                if self.negative.lock().contains(name) {
+                    count_hit();
+                    if let Some((this, mount)) = charge {
+                        mount.touch(this, name);
+                    }
+                    return Err(VfsError::NotFound);
+                }
+                let node = match self.ops.lookup(&Self::stored_name(cipher, name, false)?) {
+                    Ok(node) => node,
+                    Err(err) if err.canonicalize() == VfsError::NotFound => {
+                        count_miss();
+                        if let Some((this, mount)) = charge
+                            && self.ops.supports_dentry_cache()
+                        {
+                            self.negative.lock().insert(name.to_owned());
+                            mount.touch(this, name);
+                        }
+                        return Err(err);
+                    }
+                    Err(err) => return Err(err),
+                };
                 if self.ops.supports_dentry_cache() {
                     e.insert(node.clone());
                 }
@@ -207,19 +351,52 @@ impl DirNode {
         }
     }
 
-    /// Looks up a directory entry by name.
-    pub fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+    fn lookup_charged(
+        &self,
+        name: &str,
+        charge: Option<(&DirEntry, &NegativeDentries)>,
+    ) -> VfsResult<DirEntry> {
         if name.len() > MAX_NAME_LEN {
             return Err(VfsError::NameTooLong);
         }
+        let cipher = self.name_cipher()?;
         // Fast path
         if self.ops.supports_dentry_cache() {
-            self.lookup_locked(name, &mut self.dentry_cache.lock())
+            self.lookup_locked(name, &cipher, &mut self.dentry_cache.lock(), charge)
         } else {
-            self.ops.lookup(name)
+            self.ops.lookup(&Self::stored_name(&cipher, name, false)?)
         }
     }
 
+    /// Looks up a directory entry by name.
+    ///
+    /// Existing negative dentries are used, but no new ones are recorded,
+    /// since there is no mount to charge them to.
+    pub fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        self.lookup_charged(name, None)
+    }
+
+    /// Looks up a directory entry by name, where `this` is the entry of this
+    /// directory, reached through the mount owning `negative`.
+    pub(crate) fn lookup_in_mount(
+        &self,
+        this: &DirEntry,
+        name: &str,
+        negative: &NegativeDentries,
+    ) -> VfsResult<DirEntry> {
+        self.lookup_charged(name, Some((this, negative)))
+    }
+
+    /// Drops the negative dentry `name`, if any.
+    pub(crate) fn forget_negative(&self, name: &str) {
+        self.negative.lock().remove(name);
+    }
+
+    /// Drops all negative dentries of the directory.
+    pub(crate) fn forget_negatives(&self) {
+        self.negative.lock().clear();
+    }
+
     /// Looks up a directory entry by name in cache.
     pub fn lookup_cache(&self, name: &str) -> Option<DirEntry> {
         if self.ops.supports_dentry_cache() {
@@ -239,18 +416,61 @@ impl DirNode {
     }
 
     /// Read directory entries starting at `offset`.
+    ///
+    /// The inodes of the returned entries are prefetched afterwards, since
+    /// directory listings are usually followed by a `stat` of every entry.
     pub fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
-        self.ops.read_dir(offset, sink)
+        let cipher = self.name_cipher()?;
+        let mut inodes = Vec::new();
+        let count = self
+            .ops
+            .read_dir(offset, &mut |name: &str, ino, node_type, offset| {
+                let accepted = match &cipher {
+                    Some(cipher) if name != DOT && name != DOTDOT => {
+                        sink.accept(&cipher.decrypt_name(name), ino, node_type, offset)
+                    }
+                    _ => sink.accept(name, ino, node_type, offset),
+                };
+                if !accepted {
+                    return false;
+                }
+                if inodes.len() < READAHEAD_BATCH && name != DOT && name != DOTDOT {
+                    inodes.push(ino);
+                }
+                true
+            })?;
+        // Called after `read_dir` returned: the filesystem may hold a lock
+        // while iterating.
+        if !inodes.is_empty() {
+            self.ops.prefetch_inodes(&inodes);
+        }
+        Ok(count)
+    }
+
+    /// Returns the metadata of several children by inode number.
+    ///
+    /// See [`DirNodeOps::stat_inodes`].
+    pub fn stat_inodes(
+        &self,
+        inodes: &[u64],
+        sink: &mut dyn FnMut(u64, VfsResult<Metadata>),
+    ) -> VfsResult<()> {
+        self.ops.stat_inodes(inodes, sink)
     }
 
     /// Creates a link to a node.
     pub fn link(&self, name: &str, node: &DirEntry) -> VfsResult<DirEntry> {
         verify_entry_name(name)?;
 
-        self.ops.link(name, node).inspect(|entry| {
-            self.dentry_cache
-                .lock()
-                .insert(name.to_owned(), entry.clone());
+        let cipher = self.name_cipher()?;
+        if let Some(cipher) = &cipher {
+            cipher.check_link(node)?;
+        }
+        let stored = Self::stored_name(&cipher, name, true)?;
+        self.ops.link(&stored, node).inspect(|entry| {
+            let mut children = self.dentry_cache.lock();
+            self.forget_negative(name);
+            children.insert(name.to_owned(), entry.clone());
         })
     }
 
@@ -258,15 +478,17 @@ impl DirNode {
     pub fn unlink(&self, name: &str, is_dir: bool) -> VfsResult<()> {
         verify_entry_name(name)?;
 
+        let cipher = self.name_cipher()?;
         let mut children = self.dentry_cache.lock();
-        let entry = self.lookup_locked(name, &mut children)?;
+        let entry = self.lookup_locked(name, &cipher, &mut children, None)?;
         match (entry.is_dir(), is_dir) {
             (true, false) => return Err(VfsError::IsADirectory),
             (false, true) => return Err(VfsError::NotADirectory),
             _ => {}
         }
 
-        self.ops.unlink(name).inspect(|_| {
+        let stored = Self::stored_name(&cipher, name, false)?;
+        self.ops.unlink(&stored).inspect(|_| {
             Self::forget_entry(&mut children, name);
         })
     }
@@ -274,7 +496,7 @@ impl DirNode {
     /// Returns whether the directory contains children.
     pub fn has_children(&self) -> VfsResult<bool> {
         let mut has_children = false;
-        self.read_dir(0, &mut |name: &str, _, _, _| {
+        self.ops.read_dir(0, &mut |name: &str, _, _, _| {
             if name != DOT && name != DOTDOT {
                 has_children = true;
                 false
@@ -285,14 +507,29 @@ impl DirNode {
         Ok(has_children)
     }
 
+    /// Sets up the encryption of `entry`, just created as `stored`, removing
+    /// it again on failure.
+    fn init_child(&self, cipher: &Cipher, stored: &str, entry: &DirEntry) -> VfsResult<()> {
+        let Some(cipher) = cipher else {
+            return Ok(());
+        };
+        cipher.init_child(entry).inspect_err(|_| {
+            let _ = self.ops.unlink(stored);
+        })
+    }
+
     fn create_locked(
         &self,
         name: &str,
         node_type: NodeType,
         permission: NodePermission,
+        cipher: &Cipher,
         children: &mut DirChildren,
     ) -> VfsResult<DirEntry> {
-        let entry = self.ops.create(name, node_type, permission)?;
+        let stored = Self::stored_name(cipher, name, true)?;
+        let entry = self.ops.create(&stored, node_type, permission)?;
+        self.init_child(cipher, &stored, &entry)?;
+        self.forget_negative(name);
         children.insert(name.to_owned(), entry.clone());
         Ok(entry)
     }
@@ -305,7 +542,39 @@ impl DirNode {
         permission: NodePermission,
     ) -> VfsResult<DirEntry> {
         verify_entry_name(name)?;
-        self.create_locked(name, node_type, permission, &mut self.dentry_cache.lock())
+        let cipher = self.name_cipher()?;
+        self.create_locked(
+            name,
+            node_type,
+            permission,
+            &cipher,
+            &mut self.dentry_cache.lock(),
+        )
+    }
+
+    /// Creates a character or block device node.
+    ///
+    /// See [`DirNodeOps::mknod`].
+    pub fn mknod(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<DirEntry> {
+        verify_entry_name(name)?;
+        if !matches!(node_type, NodeType::CharacterDevice | NodeType::BlockDevice) {
+            return Err(VfsError::InvalidInput);
+        }
+
+        let cipher = self.name_cipher()?;
+        let mut children = self.dentry_cache.lock();
+        let stored = Self::stored_name(&cipher, name, true)?;
+        let entry = self.ops.mknod(&stored, node_type, permission, rdev)?;
+        self.init_child(&cipher, &stored, &entry)?;
+        self.forget_negative(name);
+        children.insert(name.to_owned(), entry.clone());
+        Ok(entry)
     }
 
     fn lock_both_cache<'a>(
@@ -329,14 +598,21 @@ impl DirNode {
         verify_entry_name(src_name)?;
         verify_entry_name(dst_name)?;
 
+        let src_cipher = self.name_cipher()?;
+        let dst_cipher = dst_dir.name_cipher()?;
         let (mut src_children, mut dst_children) = self.lock_both_cache(dst_dir);
 
-        let src = self.lookup_locked(src_name, &mut src_children)?;
+        let src = self.lookup_locked(src_name, &src_cipher, &mut src_children, None)?;
+        if let Some(cipher) = &dst_cipher {
+            cipher.check_link(&src)?;
+        }
         if let Ok(dst) = dst_dir.lookup_locked(
             dst_name,
+            &dst_cipher,
             dst_children
                 .as_mut()
                 .map_or_else(|| src_children.deref_mut(), DerefMut::deref_mut),
+            None,
         ) {
             if src.node_type() == NodeType::Directory {
                 if let Ok(dir) = dst.as_dir()
@@ -351,57 +627,69 @@ impl DirNode {
         drop(src_children);
         drop(dst_children);
 
-        self.ops.rename(src_name, dst_dir, dst_name).inspect(|_| {
-            let (mut src_children, mut dst_children) = self.lock_both_cache(dst_dir);
-            Self::forget_entry(&mut src_children, src_name);
-            Self::forget_entry(
-                dst_children
-                    .as_mut()
-                    .map_or_else(|| src_children.deref_mut(), DerefMut::deref_mut),
-                dst_name,
-            );
-        })
+        let src_stored = Self::stored_name(&src_cipher, src_name, false)?;
+        let dst_stored = Self::stored_name(&dst_cipher, dst_name, true)?;
+        self.ops
+            .rename(&src_stored, dst_dir, &dst_stored)
+            .inspect(|_| {
+                let (mut src_children, mut dst_children) = self.lock_both_cache(dst_dir);
+                dst_dir.forget_negative(dst_name);
+                Self::forget_entry(&mut src_children, src_name);
+                Self::forget_entry(
+                    dst_children
+                        .as_mut()
+                        .map_or_else(|| src_children.deref_mut(), DerefMut::deref_mut),
+                    dst_name,
+                );
+            })
     }
 
     /// Opens (or creates) a file in the directory.
     pub fn open_file(&self, name: &str, options: &OpenOptions) -> VfsResult<DirEntry> {
+        self.open_or_create(name, options).map(|(entry, _)| entry)
+    }
+
+    /// Opens (or creates) a file in the directory, returning whether it was
+    /// created.
+    pub(crate) fn open_or_create(
+        &self,
+        name: &str,
+        options: &OpenOptions,
+    ) -> VfsResult<(DirEntry, bool)> {
         verify_entry_name(name)?;
 
+        let cipher = self.name_cipher()?;
         let mut children = self.dentry_cache.lock();
-        match self.lookup_locked(name, &mut children) {
+        match self.lookup_locked(name, &cipher, &mut children, None) {
             Ok(val) => {
                 if options.create_new {
                     return Err(VfsError::AlreadyExists);
                 }
-                return Ok(val);
+                return Ok((val, false));
             }
             Err(err) if err.canonicalize() == VfsError::NotFound && options.create => {}
             Err(err) => return Err(err),
         }
-        let entry =
-            self.create_locked(name, options.node_type, options.permission, &mut children)?;
+        let entry = self.create_locked(
+            name,
+            options.node_type,
+            options.permission,
+            &cipher,
+            &mut children,
+        )?;
         if options.user.is_some() {
             entry.update_metadata(MetadataUpdate {
                 owner: options.user,
                 ..Default::default()
             })?;
         }
-        Ok(entry)
-    }
-
-    /// Returns the mountpoint attached to this directory, if any.
-    pub fn mountpoint(&self) -> Option<Arc<Mountpoint>> {
-        self.mount_at_this_dir.lock().clone()
-    }
-
-    /// Returns `true` if a filesystem is mounted at this directory.
-    pub fn is_mountpoint(&self) -> bool {
-        self.mount_at_this_dir.lock().is_some()
+        Ok((entry, true))
     }
 
     /// Clears the cache of directory entries & user data, allowing them to be
     /// released.
     pub(crate) fn forget(&self) {
+        self.forget_negatives();
         for (_, child) in mem::take(self.dentry_cache.lock().deref_mut()) {
             if let Ok(dir) = child.as_dir() {
                 dir.forget();
@@ -11,6 +11,20 @@ use kpoll::Pollable;
 use super::NodeOps;
 use crate::{VfsError, VfsResult};
 
+/// How [`FileNodeOps::fallocate`] changes the space of a byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallocateMode {
+    /// Allocates the blocks of the range; holes in it read as zeros as
+    /// before. The file grows to the end of the range unless `keep_size`.
+    Allocate { keep_size: bool },
+    /// Deallocates the blocks of the range, which then reads as zeros. The
+    /// size of the file never changes.
+    PunchHole,
+    /// Zeros the range, keeping its blocks allocated. The file grows to the
+    /// end of the range unless `keep_size`.
+    ZeroRange { keep_size: bool },
+}
+
 /// File node operations.
 pub trait FileNodeOps: NodeOps + Pollable {
     /// Reads a number of bytes starting from a given offset.
@@ -35,6 +49,47 @@ pub trait FileNodeOps: NodeOps + Pollable {
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NotATty)
     }
+
+    /// Returns the alignment required of the offsets and lengths of direct
+    /// I/O, or `None` if the file does not support direct I/O.
+    ///
+    /// Files that keep no cache of their own may be accessed directly at any
+    /// alignment.
+    fn direct_io_align(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    /// Returns the alignment required of the buffers of direct I/O, such as
+    /// the logical sector size of the device the file is on.
+    fn direct_io_mem_align(&self) -> Option<usize> {
+        self.direct_io_align()
+    }
+
+    /// Reads from the file bypassing any cache kept by the filesystem.
+    ///
+    /// `offset` and the length of `buf` are multiples of
+    /// [`direct_io_align`](Self::direct_io_align). Cached data that is newer
+    /// than the backing store must be taken into account.
+    fn read_direct(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.read_at(buf, offset)
+    }
+
+    /// Writes to the file bypassing any cache kept by the filesystem.
+    ///
+    /// `offset` and the length of `buf` are multiples of
+    /// [`direct_io_align`](Self::direct_io_align). Cached copies of the
+    /// written data must be dropped or updated.
+    fn write_direct(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        self.write_at(buf, offset)
+    }
+
+    /// Changes the space allocated to the `len` bytes at `offset`.
+    ///
+    /// Callers keeping a cache of the file write back and drop the cached
+    /// pages of the range first.
+    fn fallocate(&self, _mode: FallocateMode, _offset: u64, _len: u64) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
 }
 
 /// Wrapper around a file node implementation.
@@ -0,0 +1,347 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Advisory file locks.
+//!
+//! Two independent kinds of locks are kept per inode, as on Linux:
+//!
+//! - BSD-style locks ([`DirEntry::flock`]) cover the whole file and belong to
+//!   an open file, so they are shared by its duplicates.
+//! - POSIX record locks ([`DirEntry::set_record_lock`]) cover byte ranges and
+//!   belong to a process. Ranges of one owner are split and merged so that
+//!   they never overlap.
+//!
+//! Nothing here blocks. A request that conflicts fails with `EAGAIN`; if a
+//! waker was given, it is woken once a lock of the inode is released, so that
+//! the caller can retry.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::task::Waker;
+
+use kerrno::LinuxError;
+use kpoll::PollSet;
+
+use super::DirEntry;
+use crate::{Mutex, VfsError, VfsResult};
+
+/// Identifies the owner of a lock.
+///
+/// For [`flock`](DirEntry::flock) locks this is the open file, for record
+/// locks the process.
+pub type LockOwner = usize;
+
+/// Type of an advisory lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    /// A shared lock, held by any number of owners.
+    Read,
+    /// An exclusive lock, held by a single owner.
+    Write,
+}
+
+impl LockType {
+    fn conflicts_with(self, other: LockType) -> bool {
+        self == LockType::Write || other == LockType::Write
+    }
+}
+
+/// A POSIX lock on the bytes `start..=end` of a file.
+///
+/// An `end` of `u64::MAX` extends the lock to the end of the file, however
+/// large it grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLock {
+    /// The owner of the lock: a process, or an open file.
+    pub owner: LockOwner,
+    /// The process reported as holding the lock, or `None` if it is owned by
+    /// an open file.
+    pub pid: Option<u32>,
+    /// Type of the lock.
+    pub ty: LockType,
+    /// First byte covered.
+    pub start: u64,
+    /// Last byte covered.
+    pub end: u64,
+}
+
+impl RecordLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    /// Returns whether `next`, which starts after `self`, continues it.
+    fn touches(&self, next: &RecordLock) -> bool {
+        self.end == u64::MAX || next.start <= self.end + 1
+    }
+}
+
+/// The locks of one inode.
+#[derive(Default)]
+struct InodeLocks {
+    flocks: Vec<(LockOwner, LockType)>,
+    records: Vec<RecordLock>,
+    /// Woken whenever a lock is released.
+    waiters: PollSet,
+}
+
+impl InodeLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.records.is_empty()
+    }
+
+    fn conflicting_record(
+        &self,
+        owner: LockOwner,
+        ty: LockType,
+        start: u64,
+        end: u64,
+    ) -> Option<RecordLock> {
+        self.records
+            .iter()
+            .find(|lock| {
+                lock.owner != owner && lock.ty.conflicts_with(ty) && lock.overlaps(start, end)
+            })
+            .copied()
+    }
+
+    /// Removes the locks of `owner` on `start..=end`, then adds `new` if given.
+    fn replace_records(&mut self, owner: LockOwner, start: u64, end: u64, new: Option<RecordLock>) {
+        let mut own = Vec::new();
+        self.records.retain(|lock| {
+            if lock.owner != owner {
+                return true;
+            }
+            if !lock.overlaps(start, end) {
+                own.push(*lock);
+                return false;
+            }
+            // Keep the parts outside of the range.
+            if lock.start < start {
+                own.push(RecordLock {
+                    end: start - 1,
+                    ..*lock
+                });
+            }
+            if lock.end > end {
+                own.push(RecordLock {
+                    start: end + 1,
+                    ..*lock
+                });
+            }
+            false
+        });
+        own.extend(new);
+
+        own.sort_unstable_by_key(|lock| lock.start);
+        let mut merged: Vec<RecordLock> = Vec::with_capacity(own.len());
+        for lock in own {
+            match merged.last_mut() {
+                Some(last) if last.ty == lock.ty && last.touches(&lock) => {
+                    last.end = last.end.max(lock.end);
+                }
+                _ => merged.push(lock),
+            }
+        }
+        self.records.extend(merged);
+    }
+}
+
+/// Identifies an inode: the address of its filesystem and its number.
+type InodeKey = (usize, u64);
+
+struct LockTable {
+    inodes: BTreeMap<InodeKey, InodeLocks>,
+    /// Record lock owners waiting for a lock, and the owner they wait for.
+    blocked: BTreeMap<LockOwner, LockOwner>,
+}
+
+impl LockTable {
+    /// Returns whether `owner` waiting for `holder` would close a cycle of
+    /// waiting owners.
+    fn would_deadlock(&self, owner: LockOwner, holder: LockOwner) -> bool {
+        let mut current = holder;
+        // Every owner waits for at most one other, so a chain longer than the
+        // number of waiting owners is a cycle that does not include `owner`.
+        for _ in 0..=self.blocked.len() {
+            if current == owner {
+                return true;
+            }
+            match self.blocked.get(&current) {
+                Some(&next) => current = next,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Wakes the waiters of `key` and drops its entry once it has no locks.
+    fn released(&mut self, key: InodeKey) {
+        if let Some(locks) = self.inodes.get(&key) {
+            locks.waiters.wake();
+            if locks.is_empty() {
+                self.inodes.remove(&key);
+            }
+        }
+    }
+}
+
+static LOCKS: Mutex<LockTable> = Mutex::new(LockTable {
+    inodes: BTreeMap::new(),
+    blocked: BTreeMap::new(),
+});
+
+impl DirEntry {
+    fn lock_key(&self) -> InodeKey {
+        let fs = self.filesystem() as *const _ as *const () as usize;
+        (fs, self.inode())
+    }
+
+    /// Applies a BSD-style lock of type `ty` on the whole file for the open
+    /// file `owner`, or removes its lock if `ty` is `None`.
+    ///
+    /// An existing lock of `owner` is converted. If the new lock conflicts,
+    /// the old one is released first, as Linux does, and the request fails
+    /// with `EAGAIN` after registering `waker`.
+    pub fn flock(
+        &self,
+        owner: LockOwner,
+        ty: Option<LockType>,
+        waker: Option<&Waker>,
+    ) -> VfsResult<()> {
+        let key = self.lock_key();
+        let mut table = LOCKS.lock();
+        let locks = table.inodes.entry(key).or_default();
+        let held = locks.flocks.iter().position(|(o, _)| *o == owner);
+
+        let Some(ty) = ty else {
+            if let Some(index) = held {
+                locks.flocks.swap_remove(index);
+            }
+            table.released(key);
+            return Ok(());
+        };
+        if let Some(index) = held
+            && locks.flocks[index].1 == ty
+        {
+            return Ok(());
+        }
+        let conflict = locks
+            .flocks
+            .iter()
+            .any(|&(o, held_ty)| o != owner && held_ty.conflicts_with(ty));
+        match (conflict, held) {
+            (false, Some(index)) => locks.flocks[index].1 = ty,
+            (false, None) => locks.flocks.push((owner, ty)),
+            (true, held) => {
+                if let Some(waker) = waker {
+                    locks.waiters.register(waker);
+                }
+                if let Some(index) = held {
+                    locks.flocks.swap_remove(index);
+                    table.released(key);
+                } else if locks.is_empty() {
+                    table.inodes.remove(&key);
+                }
+                return Err(VfsError::WouldBlock);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the record lock `lock`.
+    ///
+    /// Locks of the same owner that overlap it are split or merged as needed.
+    /// A conflicting request fails with `EAGAIN`. If a `waker` is given, it is
+    /// registered and the owner counts as waiting until the request succeeds
+    /// or [`cancel_lock_wait`] is called; a request that would then wait in a
+    /// cycle fails with `EDEADLK` instead.
+    pub fn set_record_lock(&self, lock: RecordLock, waker: Option<&Waker>) -> VfsResult<()> {
+        if lock.start > lock.end {
+            return Err(VfsError::InvalidInput);
+        }
+        let key = self.lock_key();
+        let mut table = LOCKS.lock();
+        let locks = table.inodes.entry(key).or_default();
+
+        if let Some(conflict) = locks.conflicting_record(lock.owner, lock.ty, lock.start, lock.end)
+        {
+            let Some(waker) = waker else {
+                return Err(VfsError::WouldBlock);
+            };
+            locks.waiters.register(waker);
+            if table.would_deadlock(lock.owner, conflict.owner) {
+                table.blocked.remove(&lock.owner);
+                return Err(VfsError::from(LinuxError::EDEADLK));
+            }
+            table.blocked.insert(lock.owner, conflict.owner);
+            return Err(VfsError::WouldBlock);
+        }
+
+        locks.replace_records(lock.owner, lock.start, lock.end, Some(lock));
+        table.blocked.remove(&lock.owner);
+        table.released(key);
+        Ok(())
+    }
+
+    /// Removes the record locks of `owner` on the bytes `start..=end`,
+    /// splitting locks that extend beyond them.
+    pub fn unlock_records(&self, owner: LockOwner, start: u64, end: u64) -> VfsResult<()> {
+        if start > end {
+            return Err(VfsError::InvalidInput);
+        }
+        let key = self.lock_key();
+        let mut table = LOCKS.lock();
+        if let Some(locks) = table.inodes.get_mut(&key) {
+            locks.replace_records(owner, start, end, None);
+            table.released(key);
+        }
+        Ok(())
+    }
+
+    /// Returns a record lock of another owner that conflicts with `lock`, if
+    /// any.
+    pub fn test_record_lock(&self, lock: &RecordLock) -> Option<RecordLock> {
+        let table = LOCKS.lock();
+        table
+            .inodes
+            .get(&self.lock_key())?
+            .conflicting_record(lock.owner, lock.ty, lock.start, lock.end)
+    }
+
+    /// Removes all record locks of `owner` on this file, e.g. when the
+    /// process closes any descriptor of it.
+    pub fn release_record_locks(&self, owner: LockOwner) {
+        let key = self.lock_key();
+        let mut table = LOCKS.lock();
+        if let Some(locks) = table.inodes.get_mut(&key) {
+            locks.records.retain(|lock| lock.owner != owner);
+            table.released(key);
+        }
+    }
+}
+
+/// Stops counting `owner` as waiting for a record lock, e.g. after its wait
+/// was interrupted.
+pub fn cancel_lock_wait(owner: LockOwner) {
+    LOCKS.lock().blocked.remove(&owner);
+}
+
+/// Removes all record locks of `owner` on all files, e.g. when the process
+/// exits.
+pub fn release_all_record_locks(owner: LockOwner) {
+    let mut table = LOCKS.lock();
+    table.blocked.remove(&owner);
+    let keys: Vec<_> = table
+        .inodes
+        .iter_mut()
+        .filter_map(|(key, locks)| {
+            let len = locks.records.len();
+            locks.records.retain(|lock| lock.owner != owner);
+            (locks.records.len() != len).then_some(*key)
+        })
+        .collect();
+    for key in keys {
+        table.released(key);
+    }
+}
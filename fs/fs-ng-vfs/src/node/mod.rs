@@ -3,8 +3,13 @@
 // See LICENSES for license details.
 
 //! VFS node types and directory entry wrappers.
+mod crypt;
 mod dir;
 mod file;
+mod lock;
+mod negative;
+mod notify;
+mod xattr;
 
 use alloc::{
     borrow::ToOwned,
@@ -21,10 +26,17 @@ use core::{
 };
 
 use bitflags::bitflags;
+pub use crypt::*;
 pub use dir::*;
 pub use file::*;
 use inherit_methods_macro::inherit_methods;
 use kpoll::{IoEvents, Pollable};
+pub use lock::{LockOwner, LockType, RecordLock, cancel_lock_wait, release_all_record_locks};
+pub(crate) use negative::NegativeDentries;
+pub use negative::{DEFAULT_NEGATIVE_DENTRY_LIMIT, NegativeDentryStats, negative_dentry_stats};
+pub(crate) use notify::{dir_flag, is_watching, next_cookie};
+pub use notify::{FsEvents, FsWatch};
+pub use xattr::*;
 use smallvec::SmallVec;
 
 use crate::{
@@ -60,6 +72,12 @@ bitflags! {
         /// This could prevent higher layers from attempting to add unnecessary
         /// non-blocking handling.
         const BLOCKING = 0x0008;
+
+        /// Indicates that this node is on a read-only filesystem.
+        ///
+        /// Regular files cannot be opened for writing, which would otherwise
+        /// only fail once the page cache is written back.
+        const READ_ONLY = 0x0010;
     }
 }
 
@@ -93,6 +111,51 @@ pub trait NodeOps: Send + Sync + 'static {
     fn flags(&self) -> NodeFlags {
         NodeFlags::empty()
     }
+
+    /// Gets the encryption context of the node.
+    ///
+    /// The context is an opaque blob of the encryption layer that the
+    /// filesystem stores along with the inode. Nodes without one fail with
+    /// `ENODATA`; filesystems that cannot store contexts keep the default,
+    /// which fails with `EOPNOTSUPP`.
+    fn get_encryption_context(&self) -> VfsResult<Vec<u8>> {
+        Err(VfsError::OperationNotSupported)
+    }
+
+    /// Sets the encryption context of the node.
+    ///
+    /// See [`get_encryption_context`](Self::get_encryption_context).
+    fn set_encryption_context(&self, _context: &[u8]) -> VfsResult<()> {
+        Err(VfsError::OperationNotSupported)
+    }
+
+    /// Gets the value of the extended attribute `name`.
+    ///
+    /// `name` is a full name accepted by [`XattrNamespace::parse`]. Missing
+    /// attributes fail with `ENODATA`; filesystems without extended
+    /// attributes keep the default, which fails with `EOPNOTSUPP`.
+    fn get_xattr(&self, _name: &str) -> VfsResult<Vec<u8>> {
+        Err(VfsError::OperationNotSupported)
+    }
+
+    /// Sets the extended attribute `name` to `value`.
+    ///
+    /// See [`get_xattr`](Self::get_xattr).
+    fn set_xattr(&self, _name: &str, _value: &[u8], _flags: XattrFlags) -> VfsResult<()> {
+        Err(VfsError::OperationNotSupported)
+    }
+
+    /// Returns the full names of the extended attributes of the node.
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        Err(VfsError::OperationNotSupported)
+    }
+
+    /// Removes the extended attribute `name`.
+    ///
+    /// See [`get_xattr`](Self::get_xattr).
+    fn remove_xattr(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::OperationNotSupported)
+    }
 }
 
 enum Node {
@@ -258,6 +321,18 @@ impl DirEntry {
     pub fn flags(&self) -> NodeFlags;
 
     pub fn sync(&self, data_only: bool) -> VfsResult<()>;
+
+    pub fn get_encryption_context(&self) -> VfsResult<Vec<u8>>;
+
+    pub fn set_encryption_context(&self, context: &[u8]) -> VfsResult<()>;
+
+    pub fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>>;
+
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> VfsResult<()>;
+
+    pub fn list_xattr(&self) -> VfsResult<Vec<String>>;
+
+    pub fn remove_xattr(&self, name: &str) -> VfsResult<()>;
 }
 
 impl DirEntry {
@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Negative dentries: names known to be missing from a directory.
+//!
+//! A lookup that fails with `ENOENT` leaves the name in the negative set of
+//! the directory, so that looking it up again does not reach the filesystem.
+//! The names are charged to the mount they were looked up through, which
+//! keeps at most [`NegativeDentries::limit`] of them and drops the least
+//! recently used ones beyond that.
+
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use hashbrown::HashMap;
+
+use super::{DirEntry, ReferenceKey, WeakDirEntry};
+use crate::Mutex;
+
+/// Default number of negative dentries kept per mount.
+pub const DEFAULT_NEGATIVE_DENTRY_LIMIT: usize = 1024;
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of lookups of missing names, for debugging.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NegativeDentryStats {
+    /// Lookups answered by a negative dentry.
+    pub hits: u64,
+    /// Lookups the filesystem answered with `ENOENT`.
+    pub misses: u64,
+}
+
+/// Returns the negative dentry counters, summed over all mounts.
+pub fn negative_dentry_stats() -> NegativeDentryStats {
+    NegativeDentryStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn count_hit() {
+    HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn count_miss() {
+    MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Default)]
+struct Lru {
+    next_tick: u64,
+    /// Last use of each entry, keyed by directory address and name.
+    ticks: HashMap<ReferenceKey, u64>,
+    /// Entries by last use, oldest first.
+    by_age: BTreeMap<u64, (ReferenceKey, WeakDirEntry)>,
+}
+
+impl Lru {
+    /// Removes the oldest entries until at most `limit` are left, returning
+    /// them.
+    fn evict(&mut self, limit: usize) -> Vec<(WeakDirEntry, String)> {
+        let mut victims = Vec::new();
+        while self.by_age.len() > limit {
+            let (_, (key, dir)) = self.by_age.pop_first().unwrap();
+            self.ticks.remove(&key);
+            victims.push((dir, key.1));
+        }
+        victims
+    }
+}
+
+/// The negative dentries charged to a mount, in LRU order.
+///
+/// Only the order is kept here; the names themselves live in the negative
+/// sets of their directories, which may drop them at any time, e.g. when a
+/// file of that name is created. Such stale entries count against the limit
+/// until they are evicted.
+pub(crate) struct NegativeDentries {
+    limit: AtomicUsize,
+    lru: Mutex<Lru>,
+}
+
+impl NegativeDentries {
+    pub fn new() -> Self {
+        Self {
+            limit: AtomicUsize::new(DEFAULT_NEGATIVE_DENTRY_LIMIT),
+            lru: Mutex::default(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+        let victims = self.lru.lock().evict(limit);
+        Self::drop_victims(victims);
+    }
+
+    /// Marks the negative dentry `name` of `dir` as just used, adding it if
+    /// needed.
+    ///
+    /// The directory's negative set must already contain `name`.
+    pub fn touch(&self, dir: &DirEntry, name: &str) {
+        let victims = {
+            let mut lru = self.lru.lock();
+            let tick = lru.next_tick;
+            lru.next_tick += 1;
+            let key = (dir.as_ptr(), name.to_owned());
+            if let Some(old) = lru.ticks.insert(key.clone(), tick) {
+                lru.by_age.remove(&old);
+            }
+            lru.by_age.insert(tick, (key, dir.downgrade()));
+            lru.evict(self.limit())
+        };
+        // The LRU lock is released first: `forget_negative` takes the lock
+        // of another directory.
+        Self::drop_victims(victims);
+    }
+
+    fn drop_victims(victims: Vec<(WeakDirEntry, String)>) {
+        for (dir, name) in victims {
+            if let Some(dir) = dir.upgrade()
+                && let Ok(dir) = dir.as_dir()
+            {
+                dir.forget_negative(&name);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for NegativeDentries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegativeDentries")
+            .field("limit", &self.limit())
+            .field("len", &self.lru.lock().by_age.len())
+            .finish()
+    }
+}
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Change notification, as used by inotify.
+//!
+//! Watches are kept per inode, as on Linux, so that they see the changes made
+//! through any name of the inode, and survive its entries being dropped from
+//! the dentry cache. Directories are told about changes of their entries, and
+//! about some changes of the files they contain (see [`FsEvents::CHILD`]).
+//!
+//! [`Location`](crate::Location) reports changes of directories. Changes of
+//! file data and closes are reported by whoever writes and closes files,
+//! with [`DirEntry::notify`].
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use bitflags::bitflags;
+
+use super::DirEntry;
+use crate::Mutex;
+
+bitflags! {
+    /// Changes of an inode, with the values of the inotify mask bits.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct FsEvents: u32 {
+        /// The file was read.
+        const ACCESS = 0x1;
+        /// The file was written or truncated.
+        const MODIFY = 0x2;
+        /// The metadata changed.
+        const ATTRIB = 0x4;
+        /// A file opened for writing was closed.
+        const CLOSE_WRITE = 0x8;
+        /// A file not opened for writing was closed.
+        const CLOSE_NOWRITE = 0x10;
+        /// The file was opened.
+        const OPEN = 0x20;
+        /// An entry was renamed out of the directory.
+        const MOVED_FROM = 0x40;
+        /// An entry was renamed into the directory.
+        const MOVED_TO = 0x80;
+        /// An entry was created in the directory.
+        const CREATE = 0x100;
+        /// An entry was removed from the directory.
+        const DELETE = 0x200;
+        /// The inode itself was deleted.
+        const DELETE_SELF = 0x400;
+        /// The inode itself was renamed.
+        const MOVE_SELF = 0x800;
+        /// The subject of the event is a directory.
+        const ISDIR = 0x4000_0000;
+    }
+}
+
+impl FsEvents {
+    /// Events of a file that are also reported to its parent directory.
+    pub const CHILD: Self = Self::ACCESS
+        .union(Self::MODIFY)
+        .union(Self::ATTRIB)
+        .union(Self::CLOSE_WRITE)
+        .union(Self::CLOSE_NOWRITE)
+        .union(Self::OPEN);
+}
+
+/// Receives the changes of the inodes it watches.
+pub trait FsWatch: Send + Sync {
+    /// Called with the `events` of the watched inode, or of its entry `name`
+    /// if it is a directory.
+    ///
+    /// The two halves of a rename carry the same nonzero `cookie`, other
+    /// events zero.
+    fn notify(&self, events: FsEvents, name: Option<&str>, cookie: u32);
+}
+
+type InodeKey = (usize, u64);
+
+static WATCHES: Mutex<BTreeMap<InodeKey, Vec<Weak<dyn FsWatch>>>> = Mutex::new(BTreeMap::new());
+/// Number of watched inodes, so that changes nobody watches cost nothing.
+static WATCHED: AtomicUsize = AtomicUsize::new(0);
+static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+/// Returns whether any inode is watched.
+pub(crate) fn is_watching() -> bool {
+    WATCHED.load(Ordering::Acquire) != 0
+}
+
+/// Returns a cookie for the two halves of a rename.
+pub(crate) fn next_cookie() -> u32 {
+    loop {
+        let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+        if cookie != 0 {
+            return cookie;
+        }
+    }
+}
+
+impl DirEntry {
+    fn watch_key(&self) -> InodeKey {
+        let fs = self.filesystem() as *const _ as *const () as usize;
+        (fs, self.inode())
+    }
+
+    /// Makes `watch` see the changes of this inode.
+    ///
+    /// Only a weak reference is kept: the watch ends when it is dropped.
+    pub fn add_watch(&self, watch: &Arc<dyn FsWatch>) {
+        let mut watches = WATCHES.lock();
+        let list = watches.entry(self.watch_key()).or_insert_with(|| {
+            WATCHED.fetch_add(1, Ordering::AcqRel);
+            Vec::new()
+        });
+        list.retain(|it| it.strong_count() > 0);
+        list.push(Arc::downgrade(watch));
+    }
+
+    /// Stops `watch` from seeing the changes of this inode.
+    pub fn remove_watch(&self, watch: &Arc<dyn FsWatch>) {
+        let key = self.watch_key();
+        let mut watches = WATCHES.lock();
+        let Some(list) = watches.get_mut(&key) else {
+            return;
+        };
+        let watch = Arc::downgrade(watch);
+        list.retain(|it| it.strong_count() > 0 && !Weak::ptr_eq(it, &watch));
+        if list.is_empty() {
+            watches.remove(&key);
+            WATCHED.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    fn deliver(&self, events: FsEvents, name: Option<&str>, cookie: u32) {
+        let key = self.watch_key();
+        let targets = {
+            let mut watches = WATCHES.lock();
+            let Some(list) = watches.get_mut(&key) else {
+                return;
+            };
+            let targets = list.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+            if targets.is_empty() {
+                watches.remove(&key);
+                WATCHED.fetch_sub(1, Ordering::AcqRel);
+            }
+            targets
+        };
+        // Watches may remove themselves in `notify`.
+        for watch in targets {
+            watch.notify(events, name, cookie);
+        }
+    }
+
+    /// Reports `events` of this inode to its watches, and those of
+    /// [`FsEvents::CHILD`] to the watches of its parent directory.
+    ///
+    /// [`FsEvents::ISDIR`] is added for directories.
+    pub fn notify(&self, mut events: FsEvents) {
+        if !is_watching() {
+            return;
+        }
+        if self.is_dir() {
+            events |= FsEvents::ISDIR;
+        }
+        self.deliver(events, None, 0);
+        let child = events & FsEvents::CHILD;
+        if !child.is_empty()
+            && let Some(parent) = self.parent()
+        {
+            parent.deliver(child | (events & FsEvents::ISDIR), Some(self.name()), 0);
+        }
+    }
+
+    /// Reports `events` of the entry `name` of this directory to its watches.
+    pub(crate) fn notify_entry(&self, events: FsEvents, name: &str, cookie: u32) {
+        if is_watching() {
+            self.deliver(events, Some(name), cookie);
+        }
+    }
+}
+
+/// Returns [`FsEvents::ISDIR`] for directories.
+pub(crate) fn dir_flag(is_dir: bool) -> FsEvents {
+    if is_dir {
+        FsEvents::ISDIR
+    } else {
+        FsEvents::empty()
+    }
+}
@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Extended attributes.
+//!
+//! Attributes are addressed by their full name, namespace prefix included,
+//! e.g. `user.mime_type` or `security.capability`. Values are raw bytes the
+//! VFS never interprets. Names are validated against the Linux rules by
+//! [`XattrNamespace::parse`] before they reach a filesystem.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use bitflags::bitflags;
+use kerrno::LinuxError;
+
+use crate::{Mutex, VfsError, VfsResult};
+
+/// Maximum length of an attribute name, prefix included.
+pub const XATTR_NAME_MAX: usize = 255;
+/// Maximum size of an attribute value.
+pub const XATTR_SIZE_MAX: usize = 65536;
+/// Maximum size of the name list returned by `listxattr`.
+pub const XATTR_LIST_MAX: usize = 65536;
+
+/// Returns the error for a missing attribute.
+pub fn no_xattr() -> VfsError {
+    VfsError::from(LinuxError::ENODATA)
+}
+
+bitflags! {
+    /// Flags of `setxattr`.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct XattrFlags: u32 {
+        /// Fail with `EEXIST` if the attribute already exists.
+        const CREATE = 1;
+        /// Fail with `ENODATA` if the attribute does not exist.
+        const REPLACE = 2;
+    }
+}
+
+impl XattrFlags {
+    /// Checks the flags against whether the attribute `exists`.
+    pub fn check(self, exists: bool) -> VfsResult<()> {
+        if exists && self.contains(Self::CREATE) {
+            Err(VfsError::AlreadyExists)
+        } else if !exists && self.contains(Self::REPLACE) {
+            Err(no_xattr())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Namespace of an extended attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XattrNamespace {
+    /// `user.*`: regular files and directories, subject to file permissions.
+    User,
+    /// `trusted.*`: privileged processes only.
+    Trusted,
+    /// `security.*`: security modules and file capabilities.
+    Security,
+    /// `system.*`: kernel objects such as POSIX ACLs.
+    System,
+}
+
+impl XattrNamespace {
+    const PREFIXES: [(&'static str, Self); 4] = [
+        ("user.", Self::User),
+        ("trusted.", Self::Trusted),
+        ("security.", Self::Security),
+        ("system.", Self::System),
+    ];
+
+    /// Returns the namespace of the full attribute name `name`.
+    ///
+    /// Fails with `ERANGE` if the name is empty or longer than
+    /// [`XATTR_NAME_MAX`], `EOPNOTSUPP` if it has no known prefix and
+    /// `EINVAL` if it is nothing but a prefix.
+    pub fn parse(name: &str) -> VfsResult<Self> {
+        if name.is_empty() || name.len() > XATTR_NAME_MAX {
+            return Err(VfsError::OutOfRange);
+        }
+        let (prefix, namespace) = Self::PREFIXES
+            .into_iter()
+            .find(|(prefix, _)| name.starts_with(prefix))
+            .ok_or(VfsError::OperationNotSupported)?;
+        if name.len() == prefix.len() {
+            return Err(VfsError::InvalidInput);
+        }
+        Ok(namespace)
+    }
+}
+
+/// In-memory attributes of a node, for filesystems without backing storage.
+#[derive(Default)]
+pub struct XattrMap {
+    attrs: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl XattrMap {
+    /// Creates an empty attribute map.
+    pub const fn new() -> Self {
+        Self {
+            attrs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the value of the attribute `name`.
+    pub fn get(&self, name: &str) -> VfsResult<Vec<u8>> {
+        self.attrs.lock().get(name).cloned().ok_or_else(no_xattr)
+    }
+
+    /// Sets the attribute `name` to `value`.
+    pub fn set(&self, name: &str, value: &[u8], flags: XattrFlags) -> VfsResult<()> {
+        let mut attrs = self.attrs.lock();
+        flags.check(attrs.contains_key(name))?;
+        attrs.insert(name.into(), value.into());
+        Ok(())
+    }
+
+    /// Returns the names of all attributes.
+    pub fn list(&self) -> Vec<String> {
+        self.attrs.lock().keys().cloned().collect()
+    }
+
+    /// Removes the attribute `name`.
+    pub fn remove(&self, name: &str) -> VfsResult<()> {
+        self.attrs
+            .lock()
+            .remove(name)
+            .map(drop)
+            .ok_or_else(no_xattr)
+    }
+}
@@ -16,6 +16,30 @@ pub const DOTDOT: &str = "..";
 /// Maximum filename length.
 pub const MAX_NAME_LEN: usize = 255;
 
+/// Maximum path length, terminator included.
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Validates a path as passed to a system call, before it is resolved.
+///
+/// A path is a byte string. With its terminator it must fit in
+/// [`MAX_PATH_LEN`], and each of its names in [`MAX_NAME_LEN`], or it is
+/// refused with `ENAMETOOLONG`, whether or not it names anything. A NUL,
+/// which a C string cannot carry, is refused with `EINVAL`. Whether an empty
+/// path is acceptable is up to the caller.
+pub fn verify_path(path: &[u8]) -> VfsResult<()> {
+    if path.contains(&0) {
+        return Err(VfsError::InvalidInput);
+    }
+    if path.len() >= MAX_PATH_LEN
+        || path
+            .split(|&b| b == b'/')
+            .any(|name| name.len() > MAX_NAME_LEN)
+    {
+        return Err(VfsError::NameTooLong);
+    }
+    Ok(())
+}
+
 /// Validate a directory entry name.
 pub(crate) fn verify_entry_name(name: &str) -> VfsResult<()> {
     if name == DOT || name == DOTDOT {
@@ -208,6 +232,12 @@ impl Path {
         })
     }
 
+    /// Returns `true` if the `Path` names a directory by ending with a slash,
+    /// or with `/.` which resolves the same.
+    pub fn has_trailing_slash(&self) -> bool {
+        self.inner.ends_with('/') || self.inner.ends_with("/.")
+    }
+
     /// Returns `true` if the `Path` is absolute, i.e., if it is independent of
     /// the current directory.
     pub fn is_absolute(&self) -> bool {
@@ -3,11 +3,14 @@
 #![cfg(unittest)]
 
 extern crate alloc;
-use alloc::vec::Vec;
+use alloc::{format, string::String, vec::Vec};
 
-use unittest::{assert_eq, def_test};
+use unittest::{assert, assert_eq, def_test};
 
-use crate::path::Path;
+use crate::{
+    VfsError,
+    path::{MAX_NAME_LEN, MAX_PATH_LEN, Path, verify_path},
+};
 
 #[def_test]
 fn test_path_normalization_complex() {
@@ -47,3 +50,39 @@ fn test_path_components_bidirectional() {
         assert_eq!(forward, backward, "Failed for path: {path_str}");
     }
 }
+
+#[def_test]
+fn test_verify_path_limits() {
+    let name = "n".repeat(MAX_NAME_LEN);
+    assert_eq!(verify_path(name.as_bytes()), Ok(()));
+    let long_name = format!("/tmp/{name}n/x");
+    assert_eq!(
+        verify_path(long_name.as_bytes()),
+        Err(VfsError::NameTooLong)
+    );
+
+    // The terminator counts towards the limit.
+    let mut path = String::new();
+    while path.len() + 4 < MAX_PATH_LEN {
+        path.push_str("/abc");
+    }
+    path.truncate(MAX_PATH_LEN - 1);
+    assert_eq!(verify_path(path.as_bytes()), Ok(()));
+    path.push('d');
+    assert_eq!(verify_path(path.as_bytes()), Err(VfsError::NameTooLong));
+
+    assert_eq!(verify_path(b"/tmp/a\0b"), Err(VfsError::InvalidInput));
+    // Names are bytes: anything but NUL and `/` goes.
+    assert_eq!(verify_path(b"/tmp/\xff\xfe"), Ok(()));
+    assert_eq!(verify_path(b""), Ok(()));
+}
+
+#[def_test]
+fn test_trailing_slash() {
+    for path in ["dir/", "/", "dir/.", "dir//", "./"] {
+        assert!(Path::new(path).has_trailing_slash(), "{path}");
+    }
+    for path in ["dir", ".", "..", "dir/..", "dir/.x"] {
+        assert!(!Path::new(path).has_trailing_slash(), "{path}");
+    }
+}
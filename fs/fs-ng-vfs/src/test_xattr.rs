@@ -0,0 +1,68 @@
+#![cfg(unittest)]
+
+use alloc::{string::String, vec};
+
+use kerrno::LinuxError;
+use unittest::{assert, assert_eq, def_test};
+
+use crate::{VfsError, XattrFlags, XattrMap, XattrNamespace};
+
+#[def_test]
+fn test_xattr_namespace_parse() {
+    assert_eq!(XattrNamespace::parse("user.foo"), Ok(XattrNamespace::User));
+    assert_eq!(
+        XattrNamespace::parse("trusted.overlay.opaque"),
+        Ok(XattrNamespace::Trusted)
+    );
+    assert_eq!(
+        XattrNamespace::parse("security.capability"),
+        Ok(XattrNamespace::Security)
+    );
+    assert_eq!(
+        XattrNamespace::parse("system.posix_acl_access"),
+        Ok(XattrNamespace::System)
+    );
+
+    assert_eq!(XattrNamespace::parse(""), Err(VfsError::OutOfRange));
+    let long = String::from("user.") + &"x".repeat(251);
+    assert_eq!(XattrNamespace::parse(&long), Err(VfsError::OutOfRange));
+    assert!(XattrNamespace::parse(&long[..255]).is_ok());
+    assert_eq!(
+        XattrNamespace::parse("foo.bar"),
+        Err(VfsError::OperationNotSupported)
+    );
+    assert_eq!(XattrNamespace::parse("user."), Err(VfsError::InvalidInput));
+}
+
+#[def_test]
+fn test_xattr_map() {
+    let map = XattrMap::new();
+    let no_data = Err(VfsError::from(LinuxError::ENODATA));
+    assert_eq!(map.get("user.a"), no_data.clone());
+    assert_eq!(
+        map.set("user.a", b"1", XattrFlags::REPLACE),
+        Err(VfsError::from(LinuxError::ENODATA))
+    );
+
+    map.set("user.a", b"1", XattrFlags::CREATE).unwrap();
+    assert_eq!(
+        map.set("user.a", b"2", XattrFlags::CREATE),
+        Err(VfsError::AlreadyExists)
+    );
+    map.set("user.a", b"2", XattrFlags::REPLACE).unwrap();
+    map.set("user.b", b"", XattrFlags::empty()).unwrap();
+    assert_eq!(map.get("user.a"), Ok(vec![b'2']));
+    assert_eq!(map.get("user.b"), Ok(vec![]));
+    assert_eq!(
+        map.list(),
+        vec![String::from("user.a"), String::from("user.b")]
+    );
+
+    map.remove("user.a").unwrap();
+    assert_eq!(
+        map.remove("user.a"),
+        Err(VfsError::from(LinuxError::ENODATA))
+    );
+    assert_eq!(map.get("user.a"), no_data);
+    assert_eq!(map.list(), vec![String::from("user.b")]);
+}
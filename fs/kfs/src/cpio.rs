@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Extraction of `newc` cpio archives, the format of Linux initramfs images.
+
+use core::str;
+
+use fs_ng_vfs::{DeviceId, MetadataUpdate, NodePermission, NodeType, VfsError, VfsResult};
+
+use crate::FsContext;
+
+pub(crate) const MAGIC: &[u8] = b"070701";
+pub(crate) const MAGIC_CRC: &[u8] = b"070702";
+pub(crate) const HEADER_LEN: usize = 110;
+pub(crate) const TRAILER: &str = "TRAILER!!!";
+
+pub(crate) const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+pub(crate) const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// An entry of a cpio archive.
+#[derive(Debug)]
+pub struct CpioEntry<'a> {
+    /// Path of the entry, relative to the extraction root.
+    pub name: &'a str,
+    /// File type and permission bits.
+    pub mode: u32,
+    /// Owner user ID.
+    pub uid: u32,
+    /// Owner group ID.
+    pub gid: u32,
+    /// Modification time, in seconds since the epoch.
+    pub mtime: u32,
+    /// Device number of device nodes.
+    pub rdev: DeviceId,
+    /// File content, or the target of a symlink.
+    pub data: &'a [u8],
+}
+
+impl CpioEntry<'_> {
+    /// Returns the node type of the entry.
+    pub fn node_type(&self) -> Option<NodeType> {
+        Some(match self.mode & S_IFMT {
+            S_IFSOCK => NodeType::Socket,
+            S_IFLNK => NodeType::Symlink,
+            S_IFREG => NodeType::RegularFile,
+            S_IFBLK => NodeType::BlockDevice,
+            S_IFDIR => NodeType::Directory,
+            S_IFCHR => NodeType::CharacterDevice,
+            S_IFIFO => NodeType::Fifo,
+            _ => return None,
+        })
+    }
+
+    /// Returns the permission bits of the entry.
+    pub fn permission(&self) -> NodePermission {
+        NodePermission::from_bits_truncate((self.mode & 0o7777) as u16)
+    }
+}
+
+/// Iterator over the entries of a `newc` cpio archive.
+pub struct CpioReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> CpioReader<'a> {
+    /// Creates a reader over the archive `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub(crate) fn field(header: &[u8], index: usize) -> VfsResult<u32> {
+        let raw = &header[6 + index * 8..6 + (index + 1) * 8];
+        let raw = str::from_utf8(raw).map_err(|_| VfsError::InvalidData)?;
+        u32::from_str_radix(raw, 16).map_err(|_| VfsError::InvalidData)
+    }
+
+    fn read_entry(&mut self) -> VfsResult<Option<CpioEntry<'a>>> {
+        let data = self.data;
+        let header = data
+            .get(self.offset..self.offset + HEADER_LEN)
+            .ok_or(VfsError::InvalidData)?;
+        if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+            return Err(VfsError::InvalidData);
+        }
+        let field = |index| Self::field(header, index);
+        let mode = field(1)?;
+        let uid = field(2)?;
+        let gid = field(3)?;
+        let mtime = field(5)?;
+        let size = field(6)? as usize;
+        let rdev = DeviceId::new(field(9)?, field(10)?);
+        let name_size = field(11)? as usize;
+
+        let name_start = self.offset + HEADER_LEN;
+        let name = data
+            .get(name_start..name_start + name_size)
+            .ok_or(VfsError::InvalidData)?;
+        let name = name.strip_suffix(b"\0").ok_or(VfsError::InvalidData)?;
+        let name = str::from_utf8(name).map_err(|_| VfsError::InvalidData)?;
+
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let content = data
+            .get(data_start..data_start + size)
+            .ok_or(VfsError::InvalidData)?;
+        self.offset = (data_start + size).next_multiple_of(4);
+
+        if name == TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(CpioEntry {
+            name,
+            mode,
+            uid,
+            gid,
+            mtime,
+            rdev,
+            data: content,
+        }))
+    }
+}
+
+impl<'a> Iterator for CpioReader<'a> {
+    type Item = VfsResult<CpioEntry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        match self.read_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.offset = self.data.len();
+                None
+            }
+            Err(err) => {
+                self.offset = self.data.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Extracts the `newc` cpio archive `data` into the root of `fs`.
+///
+/// Device nodes are recreated with their device numbers, which needs a
+/// filesystem that supports `mknod` (such as tmpfs).
+pub fn extract(fs: &FsContext, data: &[u8]) -> VfsResult<()> {
+    for entry in CpioReader::new(data) {
+        let entry = entry?;
+        let path = entry.name.trim_start_matches("./").trim_start_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        let Some(node_type) = entry.node_type() else {
+            warn!("cpio: skipping {path} with unknown mode {:#o}", entry.mode);
+            continue;
+        };
+        let permission = entry.permission();
+        let loc = match node_type {
+            NodeType::Directory => match fs.create_dir(path, permission) {
+                Err(VfsError::AlreadyExists) => fs.resolve_no_follow(path)?,
+                result => result?,
+            },
+            NodeType::Symlink => {
+                let target = str::from_utf8(entry.data).map_err(|_| VfsError::InvalidData)?;
+                fs.symlink(target, path)?
+            }
+            NodeType::RegularFile => {
+                fs.write(path, entry.data)?;
+                fs.resolve_no_follow(path)?
+            }
+            _ => fs.mknod(path, node_type, permission, entry.rdev)?,
+        };
+        // The archive modes are kept as they are, whatever the umask.
+        loc.update_metadata(MetadataUpdate {
+            mode: (node_type != NodeType::Symlink).then_some(permission),
+            owner: Some((entry.uid, entry.gid)),
+            mtime: Some(core::time::Duration::from_secs(entry.mtime as u64)),
+            ..Default::default()
+        })?;
+    }
+    Ok(())
+}
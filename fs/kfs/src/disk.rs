@@ -3,171 +3,69 @@
 // See LICENSES for license details.
 
 //! Block device wrapper with a seekable cursor.
-//! Seekable block device wrapper.
-use alloc::{boxed::Box, vec};
-use core::mem;
+//!
+//! Reads and writes go to the block layer at any byte offset, which takes
+//! care of the sectors they only partly cover.
+use kdriver::prelude::*;
 
-use kdriver::{BlockDevice as KBlockDevice, prelude::*};
-
-/// Consume `cnt` bytes from the front of a slice.
-fn take<'a>(buf: &mut &'a [u8], cnt: usize) -> &'a [u8] {
-    let (first, rem) = buf.split_at(cnt);
-    *buf = rem;
-    first
-}
-
-/// Consume `cnt` bytes from the front of a mutable slice.
-fn take_mut<'a>(buf: &mut &'a mut [u8], cnt: usize) -> &'a mut [u8] {
-    // use mem::take to circumvent lifetime issues
-    let (first, rem) = mem::take(buf).split_at_mut(cnt);
-    *buf = rem;
-    first
-}
+use crate::Partition;
 
 /// A disk device with a cursor.
 pub struct SeekableDisk {
-    dev: KBlockDevice,
-
-    block_id: u64,
-    offset: usize,
-    block_size_log2: u8,
-
-    read_buffer: Box<[u8]>,
-    write_buffer: Box<[u8]>,
-    /// Whether we have unsaved changes in the write buffer.
-    ///
-    /// It's guaranteed that when `offset == 0`, write_buffer_dirty is false.
-    write_buffer_dirty: bool,
+    dev: Partition,
+    position: u64,
 }
 
 impl SeekableDisk {
     /// Create a new disk.
-    pub fn new(dev: KBlockDevice) -> Self {
-        assert!(dev.block_size().is_power_of_two());
-        let block_size_log2 = dev.block_size().trailing_zeros() as u8;
-        let read_buffer = vec![0u8; dev.block_size()].into_boxed_slice();
-        let write_buffer = vec![0u8; dev.block_size()].into_boxed_slice();
-        Self {
-            dev,
-            block_id: 0,
-            offset: 0,
-            block_size_log2,
-            read_buffer,
-            write_buffer,
-            write_buffer_dirty: false,
-        }
+    pub fn new(dev: Partition) -> Self {
+        Self { dev, position: 0 }
     }
 
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() << self.block_size_log2
+        self.dev.num_blocks() * self.dev.block_size() as u64
     }
 
     /// Get the block size.
     pub fn block_size(&self) -> usize {
-        1 << self.block_size_log2
+        self.dev.block_size()
     }
 
     /// Get the position of the cursor.
     pub fn position(&self) -> u64 {
-        (self.block_id << self.block_size_log2) + self.offset as u64
+        self.position
     }
 
     /// Set the position of the cursor.
     pub fn set_position(&mut self, pos: u64) -> DriverResult<()> {
-        self.flush()?;
-        self.block_id = pos >> self.block_size_log2;
-        self.offset = pos as usize & (self.block_size() - 1);
+        self.position = pos;
         Ok(())
     }
 
     /// Write all pending changes to the disk.
     pub fn flush(&mut self) -> DriverResult<()> {
-        if self.write_buffer_dirty {
-            self.dev.write_block(self.block_id, &self.write_buffer)?;
-            self.write_buffer_dirty = false;
-        }
-        Ok(())
+        self.dev.flush_disk()
     }
 
-    fn read_partial(&mut self, buf: &mut &mut [u8]) -> DriverResult<usize> {
-        self.flush()?;
-        self.dev.read_block(self.block_id, &mut self.read_buffer)?;
-
-        let data = &self.read_buffer[self.offset..];
-        let length = buf.len().min(data.len());
-        take_mut(buf, length).copy_from_slice(&data[..length]);
-
-        self.offset += length;
-        if self.offset == self.block_size() {
-            self.block_id += 1;
-            self.offset = 0;
-        }
-
-        Ok(length)
+    /// Returns how many of `len` bytes at the cursor are on the disk.
+    fn available(&self, len: usize) -> usize {
+        self.size().saturating_sub(self.position).min(len as u64) as usize
     }
 
     /// Read from the disk, returns the number of bytes read.
-    pub fn read(&mut self, mut buf: &mut [u8]) -> DriverResult<usize> {
-        let mut read = 0;
-        if self.offset != 0 {
-            read += self.read_partial(&mut buf)?;
-        }
-        if buf.len() >= self.block_size() {
-            let blocks = buf.len() >> self.block_size_log2;
-            let length = blocks << self.block_size_log2;
-            self.dev
-                .read_block(self.block_id, take_mut(&mut buf, length))?;
-            read += length;
-
-            self.block_id += blocks as u64;
-        }
-        if !buf.is_empty() {
-            read += self.read_partial(&mut buf)?;
-        }
-
-        Ok(read)
-    }
-
-    fn write_partial(&mut self, buf: &mut &[u8]) -> DriverResult<usize> {
-        if !self.write_buffer_dirty {
-            self.dev.read_block(self.block_id, &mut self.write_buffer)?;
-            self.write_buffer_dirty = true;
-        }
-
-        let data = &mut self.write_buffer[self.offset..];
-        let length = buf.len().min(data.len());
-        data[..length].copy_from_slice(take(buf, length));
-
-        self.offset += length;
-        if self.offset == self.block_size() {
-            self.flush()?;
-            self.block_id += 1;
-            self.offset = 0;
-        }
-
-        Ok(length)
+    pub fn read(&mut self, buf: &mut [u8]) -> DriverResult<usize> {
+        let len = self.available(buf.len());
+        self.dev.read_at(self.position, &mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
     }
 
     /// Write to the disk, returns the number of bytes written.
-    pub fn write(&mut self, mut buf: &[u8]) -> DriverResult<usize> {
-        let mut written = 0;
-        if self.offset != 0 {
-            written += self.write_partial(&mut buf)?;
-        }
-        if buf.len() >= self.block_size() {
-            let blocks = buf.len() >> self.block_size_log2;
-            let length = blocks << self.block_size_log2;
-            self.dev
-                .write_block(self.block_id, take(&mut buf, length))?;
-            written += length;
-
-            self.block_id += blocks as u64;
-        }
-        if !buf.is_empty() {
-            written += self.write_partial(&mut buf)?;
-        }
-
-        Ok(written)
+    pub fn write(&mut self, buf: &[u8]) -> DriverResult<usize> {
+        let len = self.available(buf.len());
+        self.dev.write_at(self.position, &buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
     }
 }
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Cache of decoded directory listings, keyed by directory inode, and
+//! streaming of directory entries from disk.
+//!
+//! Listings keep the on-disk offsets reported by the backend, so a
+//! `getdents` cookie obtained from a cached listing stays valid after the
+//! listing is invalidated and re-read: ext4 never moves the surviving entries
+//! of a linear directory block on create or unlink.
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::num::NonZeroUsize;
+
+use fs_ng_vfs::{DirEntrySink, NodeType, VfsResult};
+use lru::LruCache;
+
+/// Number of directories whose listings are kept.
+const DIR_CACHE_CAPACITY: usize = 32;
+
+/// Directories with more entries than this are read from disk on every
+/// `read_dir` rather than cached, from the cookie on.
+pub(crate) const MAX_CACHED_ENTRIES: usize = 1024;
+
+/// A single decoded directory entry.
+pub(crate) struct CachedDirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub node_type: NodeType,
+    /// Offset of this entry.
+    pub offset: u64,
+    /// Offset of the entry following this one.
+    pub next_offset: u64,
+}
+
+/// A complete listing of one directory.
+#[derive(Default)]
+pub(crate) struct DirListing {
+    entries: Vec<CachedDirEntry>,
+}
+
+impl DirListing {
+    /// Appends an entry; entries must be pushed in directory order.
+    pub fn push(&mut self, entry: CachedDirEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Feeds the entries at or after `offset` into `sink`.
+    ///
+    /// Returns the number of entries accepted.
+    pub fn read(&self, offset: u64, sink: &mut dyn DirEntrySink) -> usize {
+        let start = self.entries.partition_point(|e| e.offset < offset);
+        let mut count = 0;
+        for entry in &self.entries[start..] {
+            if !sink.accept(&entry.name, entry.ino, entry.node_type, entry.next_offset) {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Feeds the entries at or after the cookie `offset` into `sink`, reading
+/// them from `entries`, which starts at the beginning of the directory block
+/// holding `offset`.
+///
+/// An entry may have been removed since the cookie was handed out, leaving
+/// it in the middle of another record; only block boundaries are sure to
+/// start an entry, hence reading from there and skipping what comes before
+/// the cookie. As surviving entries never move, none is returned twice or
+/// skipped.
+///
+/// With `collect`, reading from the start of the directory goes on after
+/// `sink` is full, to return the complete listing unless it has more than
+/// [`MAX_CACHED_ENTRIES`] entries.
+///
+/// Returns the number of entries accepted, and the listing.
+pub(crate) fn read_entries(
+    entries: impl Iterator<Item = VfsResult<CachedDirEntry>>,
+    offset: u64,
+    sink: &mut dyn DirEntrySink,
+    collect: bool,
+) -> VfsResult<(usize, Option<DirListing>)> {
+    let mut listing = (collect && offset == 0).then(DirListing::default);
+    let mut count = 0;
+    let mut full = false;
+    for entry in entries {
+        let entry = entry?;
+        if entry.offset < offset {
+            continue;
+        }
+        if !full {
+            if sink.accept(&entry.name, entry.ino, entry.node_type, entry.next_offset) {
+                count += 1;
+            } else {
+                full = true;
+            }
+        }
+        if let Some(list) = &mut listing {
+            if list.len() < MAX_CACHED_ENTRIES {
+                list.push(entry);
+            } else {
+                listing = None;
+            }
+        }
+        if full && listing.is_none() {
+            break;
+        }
+    }
+    Ok((count, listing))
+}
+
+/// LRU cache of directory listings.
+pub(crate) struct DirCache {
+    listings: LruCache<u32, Arc<DirListing>>,
+}
+
+impl DirCache {
+    pub fn new() -> Self {
+        Self {
+            listings: LruCache::new(NonZeroUsize::new(DIR_CACHE_CAPACITY).unwrap()),
+        }
+    }
+
+    pub fn get(&mut self, ino: u32) -> Option<Arc<DirListing>> {
+        self.listings.get(&ino).cloned()
+    }
+
+    pub fn insert(&mut self, ino: u32, listing: Arc<DirListing>) {
+        self.listings.put(ino, listing);
+    }
+
+    /// Drops the listing of `ino` after its contents changed.
+    pub fn invalidate(&mut self, ino: u32) {
+        self.listings.pop(&ino);
+    }
+}
+
+#[cfg(unittest)]
+mod tests_dir_cache {
+    use alloc::{string::ToString, sync::Arc, vec::Vec};
+
+    use fs_ng_vfs::NodeType;
+    use unittest::def_test;
+
+    use super::{CachedDirEntry, DirCache, DirListing, MAX_CACHED_ENTRIES, read_entries};
+
+    /// Bytes per directory block of [`OnDiskDir`].
+    const BLOCK: u64 = 256;
+    /// Bytes per entry of [`OnDiskDir`].
+    const RECORD: u64 = 16;
+
+    /// Entries of a linear directory laid out in blocks, as `(offset, name)`.
+    struct OnDiskDir(Vec<(u64, alloc::string::String)>);
+
+    impl OnDiskDir {
+        fn new(count: u64) -> Self {
+            Self(
+                (0..count)
+                    .map(|i| (i * RECORD, alloc::format!("file{i}")))
+                    .collect(),
+            )
+        }
+
+        /// Removes `name`; the entries after it stay where they are.
+        fn unlink(&mut self, name: &str) {
+            self.0.retain(|(_, n)| n != name);
+        }
+
+        /// Reads from `offset` on as a backend does, from its block start.
+        fn read(
+            &self,
+            offset: u64,
+            sink: &mut dyn fs_ng_vfs::DirEntrySink,
+            collect: bool,
+        ) -> (usize, Option<DirListing>) {
+            let start = offset - offset % BLOCK;
+            let entries = self
+                .0
+                .iter()
+                .filter(move |(off, _)| *off >= start)
+                .map(|(off, name)| {
+                    Ok(CachedDirEntry {
+                        name: name.clone(),
+                        ino: off / RECORD + 100,
+                        node_type: NodeType::RegularFile,
+                        offset: *off,
+                        next_offset: off + RECORD,
+                    })
+                });
+            read_entries(entries, offset, sink, collect).unwrap()
+        }
+    }
+
+    /// Reads up to `max` entries from `*cookie` on, as one `getdents` call.
+    fn getdents(dir: &OnDiskDir, cookie: &mut u64, max: usize) -> Vec<alloc::string::String> {
+        let mut names = Vec::new();
+        dir.read(
+            *cookie,
+            &mut |name: &str, _, _, next| {
+                if names.len() == max {
+                    return false;
+                }
+                names.push(name.to_string());
+                *cookie = next;
+                true
+            },
+            false,
+        );
+        names
+    }
+
+    fn listing() -> DirListing {
+        let mut listing = DirListing::default();
+        for (i, name) in [".", "..", "a", "b"].iter().enumerate() {
+            listing.push(CachedDirEntry {
+                name: name.to_string(),
+                ino: i as u64 + 10,
+                node_type: NodeType::RegularFile,
+                offset: i as u64 * 16,
+                next_offset: (i as u64 + 1) * 16,
+            });
+        }
+        listing
+    }
+
+    #[def_test]
+    fn test_listing_resumes_at_cookie() {
+        let listing = listing();
+        let mut names = Vec::new();
+        let count = listing.read(32, &mut |name: &str, _, _, _| {
+            names.push(name.to_string());
+            true
+        });
+        assert_eq!(count, 2);
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[def_test]
+    fn test_listing_cookie_between_entries() {
+        // A cookie that no longer points at an entry resumes at the next one.
+        let listing = listing();
+        let mut first = None;
+        listing.read(40, &mut |_: &str, ino, _, _| {
+            first.get_or_insert(ino);
+            false
+        });
+        assert_eq!(first, Some(13));
+    }
+
+    #[def_test]
+    fn test_read_entries_in_several_calls() {
+        let mut dir = OnDiskDir::new(300);
+        let mut cookie = 0;
+        let mut seen = Vec::new();
+        let mut calls = 0;
+        loop {
+            let names = getdents(&dir, &mut cookie, 100);
+            if names.is_empty() {
+                break;
+            }
+            calls += 1;
+            seen.extend(names);
+            if calls == 1 {
+                // Unlinked between calls: the next entry due, and one
+                // already returned ahead of the cookie in its block.
+                dir.unlink("file100");
+                dir.unlink("file99");
+            }
+        }
+        assert!(calls >= 3);
+        assert_eq!(seen.len(), 299);
+        assert!(!seen.iter().any(|name| name == "file100"));
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), seen.len());
+    }
+
+    #[def_test]
+    fn test_read_entries_cookie_in_removed_record() {
+        let mut dir = OnDiskDir::new(40);
+        let mut cookie = 0;
+        assert_eq!(getdents(&dir, &mut cookie, 20).len(), 20);
+        // The cookie points at file20, removed since: reading resumes at
+        // file21 although the cookie is no longer an entry boundary.
+        dir.unlink("file20");
+        assert_eq!(getdents(&dir, &mut cookie, 1), ["file21"]);
+    }
+
+    #[def_test]
+    fn test_read_entries_collects_small_listings() {
+        let mut first = Vec::new();
+        let small = OnDiskDir::new(40);
+        let (count, listing) = small.read(
+            0,
+            &mut |name: &str, _, _, _| {
+                if first.len() == 4 {
+                    return false;
+                }
+                first.push(name.to_string());
+                true
+            },
+            true,
+        );
+        assert_eq!(count, 4);
+        assert_eq!(first, ["file0", "file1", "file2", "file3"]);
+        // The listing is complete although the sink stopped early.
+        assert_eq!(listing.unwrap().len(), 40);
+
+        let large = OnDiskDir::new(MAX_CACHED_ENTRIES as u64 + 1);
+        let (_, listing) = large.read(0, &mut |_: &str, _, _, _| true, true);
+        assert!(listing.is_none());
+        // Only reads from the start of a directory collect its listing.
+        let (_, listing) = small.read(RECORD, &mut |_: &str, _, _, _| true, true);
+        assert!(listing.is_none());
+    }
+
+    #[def_test]
+    fn test_dir_cache_invalidate() {
+        let mut cache = DirCache::new();
+        cache.insert(2, Arc::new(listing()));
+        assert!(cache.get(2).is_some());
+        cache.invalidate(2);
+        assert!(cache.get(2).is_none());
+    }
+}
@@ -0,0 +1,412 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Write-back cache of device blocks for [`Ext4Disk`](super::Ext4Disk).
+//!
+//! `ext4_rs` reads a whole filesystem block even to look at a single inode
+//! or directory entry, so the same device blocks are read over and over.
+//! The cache keeps a fixed number of them, evicting the least recently used
+//! one and writing it back first if it is dirty. Buffers are allocated once
+//! and recycled.
+//!
+//! The state lock is never held across device I/O. A block being read in or
+//! written back is marked busy in the meantime; other users of that block
+//! wait until it is ready again, so that every block has at most one request
+//! in flight and writes to it reach the device in order. Neither is the
+//! device locked by the cache, so that other blocks can be accessed while a
+//! request is waited for.
+//!
+//! On [`flush`](BlockCache::flush), all dirty blocks are submitted before
+//! waiting for any of them, so that the request queue of the device merges
+//! adjacent ones into a single write.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{hint::spin_loop, mem};
+
+use kdriver::{
+    prelude::{BlockDriverOps, DriverResult},
+    queue::Bio,
+};
+use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
+use lru::LruCache;
+
+use crate::Partition;
+
+/// A device accessed in whole blocks of the cache, each of which may span
+/// several blocks of the device.
+pub(crate) trait BlockIo {
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> DriverResult;
+    fn write_block(&self, block: u64, buf: &[u8]) -> DriverResult;
+
+    /// Writes each buffer to its block, and returns the result of each.
+    fn write_batch(&self, blocks: &[(u64, Box<[u8]>)]) -> Vec<DriverResult> {
+        blocks
+            .iter()
+            .map(|(block, data)| self.write_block(*block, data))
+            .collect()
+    }
+
+    fn flush(&self) -> DriverResult;
+}
+
+impl BlockIo for Partition {
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> DriverResult {
+        self.read_at(block * buf.len() as u64, buf)
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> DriverResult {
+        self.write_at(block * buf.len() as u64, buf)
+    }
+
+    fn write_batch(&self, blocks: &[(u64, Box<[u8]>)]) -> Vec<DriverResult> {
+        let handles = blocks
+            .iter()
+            .map(|(block, data)| {
+                let block_id = block * self.blocks_per(data.len());
+                self.submit(Bio::write(block_id, data.to_vec()))
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| self.wait(handle).0)
+            .collect()
+    }
+
+    fn flush(&self) -> DriverResult {
+        self.flush_disk()
+    }
+}
+
+impl Partition {
+    /// Returns the number of device blocks in a cache block of `len` bytes.
+    fn blocks_per(&self, len: usize) -> u64 {
+        (len / self.block_size()) as u64
+    }
+}
+
+enum Slot {
+    Ready {
+        data: Box<[u8]>,
+        dirty: bool,
+    },
+    /// The block is being read in or written back.
+    Busy,
+}
+
+struct CacheState {
+    blocks: LruCache<u64, Slot>,
+    /// Buffers not holding any block.
+    free: Vec<Box<[u8]>>,
+}
+
+/// A write-back cache in front of the block device `D`.
+pub(crate) struct BlockCache<D> {
+    dev: D,
+    state: Mutex<CacheState>,
+    block_size: usize,
+}
+
+impl<D: BlockIo> BlockCache<D> {
+    /// Creates a cache of `capacity` blocks of `dev`, which are
+    /// `block_size` bytes each.
+    pub fn new(dev: D, block_size: usize, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            dev,
+            state: Mutex::new(CacheState {
+                blocks: LruCache::unbounded(),
+                free: (0..capacity)
+                    .map(|_| vec![0; block_size].into_boxed_slice())
+                    .collect(),
+            }),
+            block_size,
+        }
+    }
+
+    /// Reads `buf.len()` bytes at byte `offset` of the device.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> DriverResult {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % self.block_size;
+            let len = (self.block_size - start).min(buf.len() - done);
+            self.access((pos / self.block_size) as u64, false, |data, _| {
+                buf[done..done + len].copy_from_slice(&data[start..start + len]);
+            })?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at byte `offset` of the device.
+    ///
+    /// The data reaches the device when the blocks are evicted or on
+    /// [`flush`](Self::flush).
+    pub fn write(&self, offset: usize, data: &[u8]) -> DriverResult {
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done;
+            let start = pos % self.block_size;
+            let len = (self.block_size - start).min(data.len() - done);
+            // A block that is overwritten entirely need not be read first.
+            let overwrite = len == self.block_size;
+            self.access((pos / self.block_size) as u64, overwrite, |block, dirty| {
+                block[start..start + len].copy_from_slice(&data[done..done + len]);
+                *dirty = true;
+            })?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes all dirty blocks back at once in ascending order, then flushes
+    /// the device.
+    pub fn flush(&self) -> DriverResult {
+        let mut batch = {
+            let mut state = self.state.lock();
+            let dirty = state
+                .blocks
+                .iter()
+                .filter(|(_, slot)| matches!(slot, Slot::Ready { dirty: true, .. }))
+                .map(|(block, _)| *block)
+                .collect::<Vec<_>>();
+            dirty
+                .into_iter()
+                .map(|block| {
+                    let slot = state.blocks.peek_mut(&block).unwrap();
+                    let Slot::Ready { data, .. } = mem::replace(slot, Slot::Busy) else {
+                        unreachable!()
+                    };
+                    (block, data)
+                })
+                .collect::<Vec<_>>()
+        };
+        batch.sort_unstable_by_key(|(block, _)| *block);
+
+        let results = self.dev.write_batch(&batch);
+        let mut result = Ok(());
+        let mut state = self.state.lock();
+        for ((block, data), res) in batch.into_iter().zip(results) {
+            if let Some(slot) = state.blocks.peek_mut(&block) {
+                *slot = Slot::Ready {
+                    data,
+                    dirty: res.is_err(),
+                };
+            }
+            result = result.and(res);
+        }
+        drop(state);
+        result?;
+        self.dev.flush()
+    }
+
+    /// Calls `f` with the data of `block` and its dirty flag, reading it in
+    /// unless `overwrite` is set.
+    fn access<R>(
+        &self,
+        block: u64,
+        overwrite: bool,
+        f: impl FnOnce(&mut [u8], &mut bool) -> R,
+    ) -> DriverResult<R> {
+        let mut state = loop {
+            let mut state = self.state.lock();
+            match state.blocks.get_mut(&block) {
+                Some(Slot::Ready { data, dirty }) => return Ok(f(data, dirty)),
+                Some(Slot::Busy) => {
+                    drop(state);
+                    spin_loop();
+                }
+                None => break state,
+            }
+        };
+
+        // Claim the block so that nobody reads it in twice.
+        state.blocks.put(block, Slot::Busy);
+        let mut data = match self.take_buffer(state) {
+            Ok(data) => data,
+            Err(err) => {
+                self.state.lock().blocks.pop(&block);
+                return Err(err);
+            }
+        };
+        if !overwrite && let Err(err) = self.dev.read_block(block, &mut data) {
+            let mut state = self.state.lock();
+            state.blocks.pop(&block);
+            state.free.push(data);
+            return Err(err);
+        }
+
+        let mut dirty = false;
+        let result = f(&mut data, &mut dirty);
+        let mut state = self.state.lock();
+        if let Some(slot) = state.blocks.peek_mut(&block) {
+            *slot = Slot::Ready { data, dirty };
+        }
+        Ok(result)
+    }
+
+    /// Returns a free buffer, evicting the least recently used block that is
+    /// not busy if there is none. Releases the lock.
+    fn take_buffer<'a>(&'a self, mut state: MutexGuard<'a, CacheState>) -> DriverResult<Box<[u8]>> {
+        loop {
+            if let Some(data) = state.free.pop() {
+                return Ok(data);
+            }
+            let victim = state
+                .blocks
+                .iter()
+                .rev()
+                .find(|(_, slot)| matches!(slot, Slot::Ready { .. }))
+                .map(|(block, _)| *block);
+            let Some(victim) = victim else {
+                // All buffers are in flight and come back shortly.
+                drop(state);
+                spin_loop();
+                state = self.state.lock();
+                continue;
+            };
+            if let Some(Slot::Ready { dirty: false, .. }) = state.blocks.peek(&victim) {
+                let Some(Slot::Ready { data, .. }) = state.blocks.pop(&victim) else {
+                    unreachable!()
+                };
+                return Ok(data);
+            }
+            if let Some(data) = self.write_back(state, victim)? {
+                return Ok(data);
+            }
+            state = self.state.lock();
+        }
+    }
+
+    /// Writes `block` back if it is still dirty, and evicts it. Releases the
+    /// lock.
+    fn write_back(
+        &self,
+        mut state: MutexGuard<'_, CacheState>,
+        block: u64,
+    ) -> DriverResult<Option<Box<[u8]>>> {
+        let Some(slot @ Slot::Ready { dirty: true, .. }) = state.blocks.peek_mut(&block) else {
+            return Ok(None);
+        };
+        let Slot::Ready { data, .. } = mem::replace(slot, Slot::Busy) else {
+            unreachable!()
+        };
+        drop(state);
+
+        let result = self.dev.write_block(block, &data);
+        let mut state = self.state.lock();
+        if let Err(err) = result {
+            if let Some(slot) = state.blocks.peek_mut(&block) {
+                *slot = Slot::Ready { data, dirty: true };
+            }
+            return Err(err);
+        }
+        state.blocks.pop(&block);
+        Ok(Some(data))
+    }
+}
+
+#[cfg(unittest)]
+mod tests_cache {
+    use alloc::{vec, vec::Vec};
+
+    use unittest::def_test;
+
+    use super::*;
+
+    const BLOCK: usize = 512;
+
+    /// An in-memory disk that records the blocks written, in order.
+    struct MemDisk(Mutex<DiskState>);
+
+    struct DiskState {
+        data: Vec<u8>,
+        reads: usize,
+        writes: Vec<u64>,
+    }
+
+    impl MemDisk {
+        fn new() -> Self {
+            Self(Mutex::new(DiskState {
+                data: vec![0; 16 * BLOCK],
+                reads: 0,
+                writes: Vec::new(),
+            }))
+        }
+
+        fn lock(&self) -> MutexGuard<'_, DiskState> {
+            self.0.lock()
+        }
+    }
+
+    impl BlockIo for MemDisk {
+        fn read_block(&self, block: u64, buf: &mut [u8]) -> DriverResult {
+            let mut disk = self.lock();
+            let start = block as usize * BLOCK;
+            buf.copy_from_slice(&disk.data[start..start + buf.len()]);
+            disk.reads += 1;
+            Ok(())
+        }
+
+        fn write_block(&self, block: u64, buf: &[u8]) -> DriverResult {
+            let mut disk = self.lock();
+            let start = block as usize * BLOCK;
+            disk.data[start..start + buf.len()].copy_from_slice(buf);
+            disk.writes.push(block);
+            Ok(())
+        }
+
+        fn flush(&self) -> DriverResult {
+            Ok(())
+        }
+    }
+
+    #[def_test]
+    fn test_read_after_write() {
+        let cache = BlockCache::new(MemDisk::new(), BLOCK, 4);
+        cache.write(100, b"hello").unwrap();
+        cache.write(2 * BLOCK - 2, b"span").unwrap();
+
+        let mut buf = [0; 5];
+        cache.read(100, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        let mut buf = [0; 4];
+        cache.read(2 * BLOCK - 2, &mut buf).unwrap();
+        assert_eq!(&buf, b"span");
+
+        // Each block was read once, and nothing was written yet.
+        let dev = cache.dev.lock();
+        assert_eq!(dev.reads, 3);
+        assert!(dev.writes.is_empty());
+    }
+
+    #[def_test]
+    fn test_eviction_and_flush_order() {
+        let cache = BlockCache::new(MemDisk::new(), BLOCK, 2);
+        // Whole blocks are not read before being overwritten.
+        for block in 0..4u8 {
+            cache
+                .write(block as usize * BLOCK, &[block + 1; BLOCK])
+                .unwrap();
+        }
+        assert_eq!(cache.dev.lock().reads, 0);
+        assert_eq!(cache.dev.lock().writes, [0, 1]);
+
+        // An evicted block is read back with the data written back.
+        cache.write(1, &[9]).unwrap();
+        let mut buf = [0; 3];
+        cache.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [1, 9, 1]);
+
+        cache.flush().unwrap();
+        let dev = cache.dev.lock();
+        assert_eq!(dev.reads, 1);
+        assert_eq!(dev.writes, [0, 1, 2, 0, 3]);
+        assert_eq!(dev.data[..3], [1, 9, 1]);
+        for block in 1..4 {
+            let data = &dev.data[block * BLOCK..(block + 1) * BLOCK];
+            assert!(data.iter().all(|&b| b == block as u8 + 1));
+        }
+    }
+}
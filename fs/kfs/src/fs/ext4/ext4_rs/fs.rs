@@ -10,26 +10,36 @@ use ext4_rs::Ext4;
 use fs_ng_vfs::{
     DirEntry, DirNode, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
-use kdriver::BlockDevice as KBlockDevice;
 use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
 
 use super::{Ext4Disk, Inode};
+use crate::Partition;
 
 const EXT4_ROOT_INODE: u32 = 2;
 
+/// Number of filesystem blocks kept by the block cache of the disk.
+const CACHE_BLOCKS: usize = 256;
+
 /// Ext4 filesystem implementation.
 pub struct Ext4Filesystem {
     inner: Mutex<Ext4>,
+    disk: Arc<Ext4Disk>,
     root_dir: OnceCell<DirEntry>,
+    /// Logical sector size of the device.
+    sector_size: usize,
 }
 
 impl Ext4Filesystem {
     /// Create a new ext4 filesystem instance backed by a block device.
-    pub fn new(dev: KBlockDevice) -> VfsResult<Filesystem> {
-        let ext4 = Ext4::open(Arc::new(Ext4Disk::new(dev)));
+    pub fn new(dev: Partition) -> VfsResult<Filesystem> {
+        let sector_size = dev.limits().logical_block_size;
+        let disk = Arc::new(Ext4Disk::new(dev, CACHE_BLOCKS));
+        let ext4 = Ext4::open(disk.clone());
         let fs = Arc::new(Self {
             inner: Mutex::new(ext4),
+            disk,
             root_dir: OnceCell::new(),
+            sector_size,
         });
         let _ = fs.root_dir.set(DirEntry::new_dir(
             |this| DirNode::new(Inode::new(fs.clone(), EXT4_ROOT_INODE, Some(this), None)),
@@ -42,6 +52,11 @@ impl Ext4Filesystem {
     pub(crate) fn lock(&self) -> MutexGuard<'_, Ext4> {
         self.inner.lock()
     }
+
+    /// Returns the logical sector size of the device.
+    pub(crate) fn sector_size(&self) -> usize {
+        self.sector_size
+    }
 }
 
 unsafe impl Send for Ext4Filesystem {}
@@ -80,6 +95,6 @@ impl FilesystemOps for Ext4Filesystem {
     }
 
     fn flush(&self) -> VfsResult<()> {
-        Ok(())
+        self.disk.flush()
     }
 }
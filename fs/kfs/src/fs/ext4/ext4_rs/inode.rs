@@ -92,7 +92,7 @@ impl NodeOps for Inode {
             inode: self.ino as _,
             device: 0,
             nlink: inode.links_count() as u64,
-            mode: NodePermission::from_bits_truncate(inode.mode & 0o777),
+            mode: NodePermission::from_bits_truncate(inode.mode & 0o7777),
             node_type: inode_to_vfs_type(inode.file_type()),
             uid: inode.uid() as u32,
             gid: inode.gid() as u32,
@@ -144,7 +144,7 @@ impl NodeOps for Inode {
     }
 
     fn sync(&self, _data_only: bool) -> VfsResult<()> {
-        Ok(())
+        self.fs.flush()
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
@@ -228,6 +228,23 @@ impl FileNodeOps for Inode {
     fn set_symlink(&self, _target: &str) -> VfsResult<()> {
         Err(VfsError::Unsupported)
     }
+
+    fn direct_io_align(&self) -> Option<usize> {
+        Some(BLOCK_SIZE.max(self.fs.sector_size()))
+    }
+
+    fn direct_io_mem_align(&self) -> Option<usize> {
+        Some(self.fs.sector_size())
+    }
+
+    // Reads are served by the block cache of the disk, which always holds
+    // the latest data; writes are pushed through it to the device.
+
+    fn write_direct(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let written = self.write_at(buf, offset)?;
+        self.fs.flush()?;
+        Ok(written)
+    }
 }
 
 impl Pollable for Inode {
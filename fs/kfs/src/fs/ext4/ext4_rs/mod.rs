@@ -3,99 +3,60 @@
 // See LICENSES for license details.
 
 //! Ext4 filesystem adapter (ext4_rs backend).
+mod cache;
 mod fs;
 mod inode;
 mod util;
 
 use alloc::{vec, vec::Vec};
-use core::cmp::min;
 
 use ext4_rs::{BLOCK_SIZE, BlockDevice};
 pub use fs::*;
+use fs_ng_vfs::{VfsError, VfsResult};
 pub use inode::*;
-use kdriver::{BlockDevice as KBlockDevice, prelude::BlockDriverOps};
-use kspin::SpinNoPreempt as Mutex;
+use kdriver::prelude::BlockDriverOps;
+
+use self::cache::BlockCache;
+use crate::Partition;
 
 const FS_BLOCK_SIZE: usize = BLOCK_SIZE;
 
-/// Block device wrapper implementing ext4_rs block device APIs.
+/// Block device wrapper implementing the ext4_rs device trait, with a
+/// write-back cache of filesystem blocks.
+///
+/// Each filesystem block is read or written as one request of the device
+/// blocks it spans.
 pub(crate) struct Ext4Disk {
-    inner: Mutex<KBlockDevice>,
-    block_size: usize,
+    cache: BlockCache<Partition>,
 }
 
 impl Ext4Disk {
-    pub(crate) fn new(dev: KBlockDevice) -> Self {
-        let block_size = dev.block_size();
+    /// Wraps `dev`, caching up to `cache_blocks` filesystem blocks.
+    pub(crate) fn new(dev: Partition, cache_blocks: usize) -> Self {
+        let block_size = FS_BLOCK_SIZE.max(dev.block_size());
         Self {
-            inner: Mutex::new(dev),
-            block_size,
+            cache: BlockCache::new(dev, block_size, cache_blocks),
         }
     }
+
+    /// Writes all cached changes to the device.
+    pub(crate) fn flush(&self) -> VfsResult<()> {
+        self.cache.flush().map_err(VfsError::from)
+    }
 }
 
 impl BlockDevice for Ext4Disk {
     fn read_offset(&self, offset: usize) -> Vec<u8> {
-        let mut dev = self.inner.lock();
-        let dev_block = self.block_size;
         let mut buf = vec![0u8; FS_BLOCK_SIZE];
-        let start_block = offset / dev_block;
-        let mut current_block = start_block;
-        let mut offset_in_block = offset % dev_block;
-        let mut total_bytes_read = 0;
-
-        while total_bytes_read < buf.len() {
-            let bytes_to_copy = min(buf.len() - total_bytes_read, dev_block - offset_in_block);
-
-            let mut block_data = vec![0u8; dev_block];
-            dev.read_block(current_block as u64, &mut block_data)
-                .expect("ext4_rs: read_block failed");
-
-            buf[total_bytes_read..total_bytes_read + bytes_to_copy]
-                .copy_from_slice(&block_data[offset_in_block..offset_in_block + bytes_to_copy]);
-
-            total_bytes_read += bytes_to_copy;
-            offset_in_block = 0;
-            current_block += 1;
-        }
-
+        self.cache
+            .read(offset, &mut buf)
+            .expect("ext4_rs: read_block failed");
         buf
     }
 
     fn write_offset(&self, offset: usize, data: &[u8]) {
-        if data.is_empty() {
-            return;
-        }
-
-        let mut dev = self.inner.lock();
-        let dev_block = self.block_size;
-        let bytes_to_write = data.len();
-        let start_block = offset / dev_block;
-        let mut current_block = start_block;
-        let mut offset_in_block = offset % dev_block;
-        let mut total_bytes_written = 0;
-
-        while total_bytes_written < bytes_to_write {
-            let bytes_to_copy = min(
-                bytes_to_write - total_bytes_written,
-                dev_block - offset_in_block,
-            );
-
-            let mut block_data = vec![0u8; dev_block];
-            if bytes_to_copy != dev_block || offset_in_block != 0 {
-                dev.read_block(current_block as u64, &mut block_data)
-                    .expect("ext4_rs: read_block failed");
-            }
-
-            block_data[offset_in_block..offset_in_block + bytes_to_copy]
-                .copy_from_slice(&data[total_bytes_written..total_bytes_written + bytes_to_copy]);
-
-            dev.write_block(current_block as u64, &block_data)
-                .expect("ext4_rs: write_block failed");
-
-            total_bytes_written += bytes_to_copy;
-            offset_in_block = 0;
-            current_block += 1;
-        }
+        self.cache
+            .write(offset, data)
+            .expect("ext4_rs: write_block failed");
     }
 }
@@ -4,7 +4,6 @@ use core::cell::OnceCell;
 use fs_ng_vfs::{
     DirEntry, DirNode, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
-use kdriver::BlockDevice as KBlockDevice;
 use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
 use lwext4_rust::{FsConfig, ffi::EXT4_ROOT_INO};
 
@@ -12,22 +11,30 @@ use super::{
     Ext4Disk, Inode,
     util::{LwExt4Filesystem, into_vfs_err},
 };
+use crate::{Partition, fs::ext4::dir_cache::DirCache};
 
 const EXT4_CONFIG: FsConfig = FsConfig { bcache_size: 256 };
 
 pub struct Ext4Filesystem {
     inner: Mutex<LwExt4Filesystem>,
+    /// Decoded directory listings; always locked after `inner`.
+    dir_cache: Mutex<DirCache>,
     root_dir: OnceCell<DirEntry>,
+    /// Logical sector size of the device.
+    sector_size: usize,
 }
 
 impl Ext4Filesystem {
-    pub fn new(dev: KBlockDevice) -> VfsResult<Filesystem> {
+    pub fn new(dev: Partition) -> VfsResult<Filesystem> {
+        let sector_size = dev.limits().logical_block_size;
         let ext4 =
             lwext4_rust::Ext4Filesystem::new(Ext4Disk(dev), EXT4_CONFIG).map_err(into_vfs_err)?;
 
         let fs = Arc::new(Self {
             inner: Mutex::new(ext4),
+            dir_cache: Mutex::new(DirCache::new()),
             root_dir: OnceCell::new(),
+            sector_size,
         });
         let _ = fs.root_dir.set(DirEntry::new_dir(
             |this| DirNode::new(Inode::new(fs.clone(), EXT4_ROOT_INO, Some(this))),
@@ -39,6 +46,15 @@ impl Ext4Filesystem {
     pub(crate) fn lock(&self) -> MutexGuard<'_, LwExt4Filesystem> {
         self.inner.lock()
     }
+
+    pub(crate) fn dir_cache(&self) -> MutexGuard<'_, DirCache> {
+        self.dir_cache.lock()
+    }
+
+    /// Returns the logical sector size of the device.
+    pub(crate) fn sector_size(&self) -> usize {
+        self.sector_size
+    }
 }
 
 unsafe impl Send for Ext4Filesystem {}
@@ -1,4 +1,4 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
+use alloc::{borrow::ToOwned, string::String, sync::Arc, vec::Vec};
 use core::{any::Any, task::Context};
 
 use fs_ng_vfs::{
@@ -13,6 +13,40 @@ use super::{
     Ext4Filesystem,
     util::{LwExt4Filesystem, into_vfs_err, into_vfs_type},
 };
+use crate::fs::ext4::dir_cache::{CachedDirEntry, read_entries};
+
+/// Number of inodes read per lock acquisition when prefetching.
+const PREFETCH_CHUNK: usize = 32;
+
+fn metadata_locked(fs: &mut LwExt4Filesystem, ino: u32) -> VfsResult<Metadata> {
+    let mut attr = FileAttr::default();
+    fs.get_attr(ino, &mut attr).map_err(into_vfs_err)?;
+    Ok(Metadata {
+        inode: ino as _,
+        device: attr.device,
+        nlink: attr.nlink,
+        mode: NodePermission::from_bits_truncate(attr.mode as u16),
+        node_type: into_vfs_type(attr.node_type),
+        uid: attr.uid,
+        gid: attr.gid,
+        size: attr.size,
+        block_size: attr.block_size,
+        blocks: attr.blocks,
+        rdev: DeviceId::default(),
+        atime: attr.atime,
+        mtime: attr.mtime,
+        ctime: attr.ctime,
+    })
+}
+
+/// Returns the distinct inode numbers of `inodes` in ascending order, so
+/// that inode table blocks are visited sequentially.
+fn sorted_inodes(inodes: &[u64]) -> Vec<u32> {
+    let mut sorted = inodes.iter().map(|&ino| ino as u32).collect::<Vec<_>>();
+    sorted.sort_unstable();
+    sorted.dedup();
+    sorted
+}
 
 pub struct Inode {
     fs: Arc<Ext4Filesystem>,
@@ -50,6 +84,55 @@ impl Inode {
         Ok(self.create_entry(&entry, name))
     }
 
+    /// Reads the entries from the cookie `offset` on, from the cached
+    /// listing of this directory, or from disk, caching the listing if it is
+    /// small.
+    fn read_dir_at(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        if let Some(listing) = self.fs.dir_cache().get(self.ino) {
+            return Ok(listing.read(offset, sink));
+        }
+        let mut fs = self.fs.lock();
+        // Re-check: another reader may have filled the cache meanwhile.
+        if let Some(listing) = self.fs.dir_cache().get(self.ino) {
+            return Ok(listing.read(offset, sink));
+        }
+        let block_size = fs.stat().map_err(into_vfs_err)?.block_size as u64;
+        let mut reader = fs
+            .read_dir(self.ino, offset - offset % block_size)
+            .map_err(into_vfs_err)?;
+        let entries = core::iter::from_fn(|| {
+            let entry = reader.current()?;
+            let offset = reader.offset();
+            let name = match core::str::from_utf8(entry.name()) {
+                Ok(name) => name.to_owned(),
+                Err(_) => return Some(Err(VfsError::InvalidData)),
+            };
+            let ino = entry.ino() as u64;
+            let node_type = into_vfs_type(entry.inode_type());
+            if let Err(err) = reader.step() {
+                return Some(Err(into_vfs_err(err)));
+            }
+            Some(Ok(CachedDirEntry {
+                name,
+                ino,
+                node_type,
+                offset,
+                next_offset: reader.offset(),
+            }))
+        });
+        let (count, listing) = read_entries(entries, offset, sink, true)?;
+        if let Some(listing) = listing {
+            self.fs.dir_cache().insert(self.ino, Arc::new(listing));
+        }
+        Ok(count)
+    }
+
+    /// Drops the cached listing of this directory. Must be called with the
+    /// filesystem locked, before the lock is released.
+    fn invalidate_listing(&self) {
+        self.fs.dir_cache().invalidate(self.ino);
+    }
+
     fn update_ctime_locked(&self, fs: &mut LwExt4Filesystem, ino: u32) -> VfsResult<()> {
         fs.with_inode_ref(ino, |ino| {
             ino.update_ctime();
@@ -65,27 +148,7 @@ impl NodeOps for Inode {
     }
 
     fn metadata(&self) -> VfsResult<Metadata> {
-        let mut attr = FileAttr::default();
-        self.fs
-            .lock()
-            .get_attr(self.ino, &mut attr)
-            .map_err(into_vfs_err)?;
-        Ok(Metadata {
-            inode: self.ino as _,
-            device: attr.device,
-            nlink: attr.nlink,
-            mode: NodePermission::from_bits_truncate(attr.mode as u16),
-            node_type: into_vfs_type(attr.node_type),
-            uid: attr.uid,
-            gid: attr.gid,
-            size: attr.size,
-            block_size: attr.block_size,
-            blocks: attr.blocks,
-            rdev: DeviceId::default(),
-            atime: attr.atime,
-            mtime: attr.mtime,
-            ctime: attr.ctime,
-        })
+        metadata_locked(&mut self.fs.lock(), self.ino)
     }
 
     fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
@@ -168,6 +231,32 @@ impl FileNodeOps for Inode {
             .set_symlink(self.ino, target.as_bytes())
             .map_err(into_vfs_err)
     }
+
+    fn direct_io_align(&self) -> Option<usize> {
+        let stat = self.fs.lock().stat().ok()?;
+        Some((stat.block_size as usize).max(self.fs.sector_size()))
+    }
+
+    fn direct_io_mem_align(&self) -> Option<usize> {
+        Some(self.fs.sector_size())
+    }
+
+    // lwext4 moves whole aligned blocks between the device and the buffer
+    // without its block cache, but blocks touched by partial writes may still
+    // be dirty there, so the cache is written back around direct I/O.
+
+    fn read_direct(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let mut fs = self.fs.lock();
+        fs.flush().map_err(into_vfs_err)?;
+        fs.read_at(self.ino, buf, offset).map_err(into_vfs_err)
+    }
+
+    fn write_direct(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let mut fs = self.fs.lock();
+        let written = fs.write_at(self.ino, buf, offset).map_err(into_vfs_err)?;
+        fs.flush().map_err(into_vfs_err)?;
+        Ok(written)
+    }
 }
 
 impl Pollable for Inode {
@@ -180,22 +269,7 @@ impl Pollable for Inode {
 
 impl DirNodeOps for Inode {
     fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
-        let mut fs = self.fs.lock();
-        let mut reader = fs.read_dir(self.ino, offset).map_err(into_vfs_err)?;
-        let mut count = 0;
-        while let Some(entry) = reader.current() {
-            let name = core::str::from_utf8(entry.name())
-                .map_err(|_| VfsError::InvalidData)?
-                .to_owned();
-            let ino = entry.ino() as u64;
-            let node_type = into_vfs_type(entry.inode_type());
-            reader.step().map_err(into_vfs_err)?;
-            if !sink.accept(&name, ino, node_type, reader.offset()) {
-                break;
-            }
-            count += 1;
-        }
-        Ok(count)
+        self.read_dir_at(offset, sink)
     }
 
     fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
@@ -203,6 +277,40 @@ impl DirNodeOps for Inode {
         self.lookup_locked(&mut fs, name)
     }
 
+    fn prefetch_inodes(&self, inodes: &[u64]) {
+        // Reading the attributes pulls the inode table blocks into the block
+        // cache; release the lock between chunks to keep other users moving.
+        let mut attr = FileAttr::default();
+        for chunk in sorted_inodes(inodes).chunks(PREFETCH_CHUNK) {
+            let mut fs = self.fs.lock();
+            for &ino in chunk {
+                let _ = fs.get_attr(ino, &mut attr);
+            }
+        }
+    }
+
+    fn stat_inodes(
+        &self,
+        inodes: &[u64],
+        sink: &mut dyn FnMut(u64, VfsResult<Metadata>),
+    ) -> VfsResult<()> {
+        let results = {
+            let mut fs = self.fs.lock();
+            sorted_inodes(inodes)
+                .into_iter()
+                .map(|ino| (ino, metadata_locked(&mut fs, ino)))
+                .collect::<Vec<_>>()
+        };
+        for &ino in inodes {
+            let result = results
+                .binary_search_by_key(&(ino as u32), |(ino, _)| *ino)
+                .map_err(|_| VfsError::NotFound)
+                .and_then(|idx| results[idx].1.clone());
+            sink(ino, result);
+        }
+        Ok(())
+    }
+
     fn create(
         &self,
         name: &str,
@@ -228,6 +336,7 @@ impl DirNodeOps for Inode {
         let ino = fs
             .create(self.ino, name, inode_type, permission.bits() as _)
             .map_err(into_vfs_err)?;
+        self.invalidate_listing();
         self.update_ctime_locked(&mut fs, ino)?;
 
         let reference = Reference::new(
@@ -252,18 +361,25 @@ impl DirNodeOps for Inode {
         let mut fs = self.fs.lock();
         fs.link(self.ino, name, node.inode() as _)
             .map_err(into_vfs_err)?;
+        self.invalidate_listing();
         self.update_ctime_locked(&mut fs, node.inode() as _)?;
         self.lookup_locked(&mut fs, name)
     }
 
     fn unlink(&self, name: &str) -> VfsResult<()> {
-        self.fs.lock().unlink(self.ino, name).map_err(into_vfs_err)
+        let mut fs = self.fs.lock();
+        fs.unlink(self.ino, name).map_err(into_vfs_err)?;
+        self.invalidate_listing();
+        Ok(())
     }
 
     fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
         let dst_dir: Arc<Self> = dst_dir.downcast().map_err(|_| VfsError::InvalidInput)?;
         let mut fs = self.fs.lock();
         fs.rename(self.ino, src_name, dst_dir.ino, dst_name)
-            .map_err(into_vfs_err)
+            .map_err(into_vfs_err)?;
+        self.invalidate_listing();
+        dst_dir.invalidate_listing();
+        Ok(())
     }
 }
@@ -5,27 +5,32 @@ mod util;
 pub use fs::*;
 pub use inode::*;
 #[allow(unused_imports)]
-use kdriver::{BlockDevice as KBlockDevice, prelude::BlockDriverOps};
+use kdriver::prelude::BlockDriverOps;
 use lwext4_rust::{BlockDevice, Ext4Error, Ext4Result, ffi::EIO};
 
-pub(crate) struct Ext4Disk(KBlockDevice);
+use crate::Partition;
+
+/// Size of the blocks lwext4 addresses the device in, whatever its sectors.
+const LWEXT4_BLOCK_SIZE: u64 = 512;
+
+pub(crate) struct Ext4Disk(Partition);
 
 impl BlockDevice for Ext4Disk {
     fn read_blocks(&mut self, block_id: u64, buf: &mut [u8]) -> Ext4Result<usize> {
         self.0
-            .read_block(block_id, buf)
+            .read_at(block_id * LWEXT4_BLOCK_SIZE, buf)
             .map_err(|_| Ext4Error::new(EIO as _, None))?;
         Ok(buf.len())
     }
 
     fn write_blocks(&mut self, block_id: u64, buf: &[u8]) -> Ext4Result<usize> {
         self.0
-            .write_block(block_id, buf)
+            .write_at(block_id * LWEXT4_BLOCK_SIZE, buf)
             .map_err(|_| Ext4Error::new(EIO as _, None))?;
         Ok(buf.len())
     }
 
     fn num_blocks(&self) -> Ext4Result<u64> {
-        Ok(self.0.num_blocks())
+        Ok(self.0.num_blocks() * self.0.block_size() as u64 / LWEXT4_BLOCK_SIZE)
     }
 }
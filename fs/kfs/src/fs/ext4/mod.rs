@@ -1,8 +1,12 @@
+mod dir_cache;
+
 #[cfg(feature = "ext4-rsext4")]
 mod rsext4;
 
 #[cfg(feature = "ext4-rsext4")]
 pub use rsext4::Ext4Filesystem as Rsext4Filesystem;
+#[cfg(feature = "ext4-rsext4")]
+pub use rsext4::Inode as Rsext4Inode;
 
 #[cfg(feature = "ext4-lwext4")]
 mod lwext4;
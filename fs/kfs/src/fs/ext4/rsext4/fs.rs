@@ -3,17 +3,23 @@
 // See LICENSES for license details.
 
 //! Ext4 filesystem adapter (rsext4 backend).
-use alloc::sync::Arc;
-use core::cell::OnceCell;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cell::OnceCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use fs_ng_vfs::{
     DirEntry, DirNode, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
-use kdriver::BlockDevice as KBlockDevice;
 use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
-use rsext4::Jbd2Dev;
+use rsext4::{
+    Jbd2Dev,
+    scrub::{Finding, ScrubCursor, ScrubProgress},
+};
 
 use super::{Ext4Disk, Inode, util::into_vfs_err};
+use crate::Partition;
 
 const EXT4_ROOT_INO: u32 = 2;
 
@@ -35,17 +41,24 @@ impl Ext4State {
 pub struct Ext4Filesystem {
     inner: Mutex<Ext4State>,
     root_dir: OnceCell<DirEntry>,
+    /// Number of foreground operations started, watched by the scrubber.
+    activity: AtomicU64,
+    /// Logical sector size of the device.
+    sector_size: usize,
 }
 
 impl Ext4Filesystem {
     /// Create a new ext4 filesystem instance backed by a block device.
-    pub fn new(dev: KBlockDevice) -> VfsResult<Filesystem> {
+    pub fn new(dev: Partition) -> VfsResult<Filesystem> {
+        let sector_size = dev.limits().logical_block_size;
         let mut dev = Jbd2Dev::initial_jbd2dev(0, Ext4Disk(dev), false);
         let fs = rsext4::mount(&mut dev).map_err(into_vfs_err)?;
 
         let fs = Arc::new(Self {
             inner: Mutex::new(Ext4State { fs, dev }),
             root_dir: OnceCell::new(),
+            activity: AtomicU64::new(0),
+            sector_size,
         });
         let _ = fs.root_dir.set(DirEntry::new_dir(
             |this| {
@@ -63,8 +76,39 @@ impl Ext4Filesystem {
 
     /// Lock the inner ext4 filesystem state.
     pub(crate) fn lock(&self) -> MutexGuard<'_, Ext4State> {
+        self.activity.fetch_add(1, Ordering::Relaxed);
         self.inner.lock()
     }
+
+    /// Returns the logical sector size of the device.
+    pub(crate) fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    /// Returns the number of foreground operations started so far.
+    pub(crate) fn activity(&self) -> u64 {
+        self.activity.load(Ordering::Relaxed)
+    }
+
+    /// Returns the UUID of the filesystem.
+    pub(crate) fn uuid(&self) -> [u8; 16] {
+        self.inner.lock().fs.superblock.s_uuid
+    }
+
+    /// Scrubs about `budget` blocks from `cursor` on, see
+    /// [`rsext4::scrub::scrub_step`]. This does not count as foreground
+    /// activity.
+    pub(crate) fn scrub_step(
+        &self,
+        cursor: &mut ScrubCursor,
+        budget: u32,
+        repair: bool,
+        findings: &mut Vec<Finding>,
+    ) -> VfsResult<ScrubProgress> {
+        let mut state = self.inner.lock();
+        let (fs, dev) = state.split();
+        rsext4::scrub::scrub_step(fs, dev, cursor, budget, repair, findings).map_err(into_vfs_err)
+    }
 }
 
 unsafe impl Send for Ext4Filesystem {}
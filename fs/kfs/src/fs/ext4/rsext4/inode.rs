@@ -8,21 +8,78 @@ use alloc::{
     format,
     string::{String, ToString},
     sync::Arc,
+    vec::Vec,
 };
 use core::{any::Any, task::Context};
 
 use fs_ng_vfs::{
-    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, FilesystemOps,
-    Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, Reference, VfsError,
-    VfsResult, WeakDirEntry,
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FallocateMode, FileNode, FileNodeOps,
+    FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
+    Reference, VfsError, VfsResult, WeakDirEntry, XattrFlags, no_xattr,
 };
 use kpoll::{IoEvents, Pollable};
-use rsext4::{BLOCK_SIZE, Jbd2Dev};
+use rsext4::{
+    BLOCK_SIZE, Jbd2Dev,
+    entries::Ext4DirEntryInfo,
+    xattr::{XattrEntry, read_xattrs, write_xattrs},
+};
 
 use super::{
     Ext4Disk, Ext4Filesystem,
-    util::{dir_entry_type_to_vfs, inode_to_vfs_type, into_vfs_err, vfs_type_to_dir_entry},
+    util::{
+        dir_entry_type_to_vfs, inode_rdev, inode_to_vfs_type, into_vfs_err, set_inode_rdev,
+        vfs_type_to_dir_entry,
+    },
 };
+use crate::fs::ext4::dir_cache::{CachedDirEntry, read_entries};
+
+/// Groups consecutive blocks, given by their physical block or `None` for a
+/// hole, into runs that can be transferred as one request. Returns the index
+/// of the first block of each run, its physical block and its length.
+fn block_runs(blocks: impl IntoIterator<Item = Option<u64>>) -> Vec<(usize, Option<u64>, usize)> {
+    let mut runs: Vec<(usize, Option<u64>, usize)> = Vec::new();
+    for (index, phys) in blocks.into_iter().enumerate() {
+        if let Some((_, start, len)) = runs.last_mut() {
+            let extends = match (*start, phys) {
+                (Some(start), Some(phys)) => start + *len as u64 == phys,
+                (None, None) => true,
+                _ => false,
+            };
+            if extends {
+                *len += 1;
+                continue;
+            }
+        }
+        runs.push((index, phys, 1));
+    }
+    runs
+}
+
+/// Decodes the live entries of the directory block `lblock`.
+fn block_entries(lblock: u32, data: &[u8]) -> VfsResult<Vec<CachedDirEntry>> {
+    let base = lblock as u64 * BLOCK_SIZE as u64;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let rec_len = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+        if rec_len < 8 || pos + rec_len > data.len() {
+            break;
+        }
+        if let Some(entry) = Ext4DirEntryInfo::parse_from_bytes(&data[pos..pos + rec_len]) {
+            entries.push(CachedDirEntry {
+                name: core::str::from_utf8(entry.name)
+                    .map_err(|_| VfsError::InvalidData)?
+                    .to_owned(),
+                ino: entry.inode as u64,
+                node_type: dir_entry_type_to_vfs(entry.file_type),
+                offset: base + pos as u64,
+                next_offset: base + (pos + rec_len) as u64,
+            });
+        }
+        pos += rec_len;
+    }
+    Ok(entries)
+}
 
 /// Ext4 inode wrapper used to implement VFS nodes.
 pub struct Inode {
@@ -48,6 +105,11 @@ impl Inode {
         })
     }
 
+    /// Returns the filesystem of the inode.
+    pub(crate) fn filesystem(&self) -> &Arc<Ext4Filesystem> {
+        &self.fs
+    }
+
     fn create_entry(
         &self,
         ino: u32,
@@ -68,7 +130,7 @@ impl Inode {
         } else {
             DirEntry::new_file(
                 FileNode::new(Inode::new(self.fs.clone(), ino, None, path)),
-                inode_to_vfs_type(inode.is_dir(), inode.is_file(), inode.is_symlink()),
+                inode_to_vfs_type(inode),
                 reference,
             )
         }
@@ -120,18 +182,23 @@ impl NodeOps for Inode {
         let mut state = self.fs.lock();
         let (fs, dev) = state.split();
         let inode = fs.get_inode_by_num(dev, self.ino).map_err(into_vfs_err)?;
+        let node_type = inode_to_vfs_type(&inode);
+        let rdev = match node_type {
+            NodeType::CharacterDevice | NodeType::BlockDevice => inode_rdev(&inode),
+            _ => DeviceId::default(),
+        };
         Ok(Metadata {
             inode: self.ino as _,
             device: 0,
             nlink: inode.i_links_count as _,
-            mode: NodePermission::from_bits_truncate(inode.i_mode & 0o777),
-            node_type: inode_to_vfs_type(inode.is_dir(), inode.is_file(), inode.is_symlink()),
+            mode: NodePermission::from_bits_truncate(inode.i_mode & 0o7777),
+            node_type,
             uid: inode.uid(),
             gid: inode.gid(),
             size: inode.size(),
             block_size: fs.superblock.block_size(),
             blocks: inode.blocks_count(),
-            rdev: DeviceId::default(),
+            rdev,
             atime: core::time::Duration::from_secs(inode.i_atime as u64),
             mtime: core::time::Duration::from_secs(inode.i_mtime as u64),
             ctime: core::time::Duration::from_secs(inode.i_ctime as u64),
@@ -143,7 +210,7 @@ impl NodeOps for Inode {
         let (fs, dev) = state.split();
         fs.modify_inode(dev, self.ino, |inode| {
             if let Some(mode) = update.mode {
-                inode.i_mode = (inode.i_mode & !0o777) | mode.bits();
+                inode.i_mode = (inode.i_mode & !0o7777) | (mode.bits() & 0o7777);
             }
             if let Some((uid, gid)) = update.owner {
                 inode.i_uid = (uid & 0xffff) as u16;
@@ -188,6 +255,55 @@ impl NodeOps for Inode {
     fn flags(&self) -> NodeFlags {
         NodeFlags::BLOCKING
     }
+
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        read_xattrs(fs, dev, self.ino)
+            .map_err(into_vfs_err)?
+            .into_iter()
+            .find(|entry| entry.is(name))
+            .map(|entry| entry.value)
+            .ok_or_else(no_xattr)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> VfsResult<()> {
+        let entry = XattrEntry::new(name, value).ok_or(VfsError::OperationNotSupported)?;
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        let mut entries = read_xattrs(fs, dev, self.ino).map_err(into_vfs_err)?;
+        let existing = entries.iter().position(|entry| entry.is(name));
+        flags.check(existing.is_some())?;
+        match existing {
+            Some(index) => entries[index] = entry,
+            None => entries.push(entry),
+        }
+        write_xattrs(fs, dev, self.ino, &entries).map_err(into_vfs_err)?;
+        Self::update_ctime_with(fs, dev, self.ino)
+    }
+
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        Ok(read_xattrs(fs, dev, self.ino)
+            .map_err(into_vfs_err)?
+            .iter()
+            .filter_map(XattrEntry::full_name)
+            .collect())
+    }
+
+    fn remove_xattr(&self, name: &str) -> VfsResult<()> {
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        let mut entries = read_xattrs(fs, dev, self.ino).map_err(into_vfs_err)?;
+        let index = entries
+            .iter()
+            .position(|entry| entry.is(name))
+            .ok_or_else(no_xattr)?;
+        entries.remove(index);
+        write_xattrs(fs, dev, self.ino, &entries).map_err(into_vfs_err)?;
+        Self::update_ctime_with(fs, dev, self.ino)
+    }
 }
 
 impl FileNodeOps for Inode {
@@ -290,6 +406,99 @@ impl FileNodeOps for Inode {
         Ok((buf.len(), length + buf.len() as u64))
     }
 
+    fn direct_io_align(&self) -> Option<usize> {
+        Some(BLOCK_SIZE.max(self.fs.sector_size()))
+    }
+
+    fn direct_io_mem_align(&self) -> Option<usize> {
+        Some(self.fs.sector_size())
+    }
+
+    fn read_direct(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        let mut inode = fs.get_inode_by_num(dev, self.ino).map_err(into_vfs_err)?;
+        let file_size = inode.size();
+        if buf.is_empty() || offset >= file_size {
+            return Ok(0);
+        }
+        if !inode.have_extend_header_and_use_extend() {
+            return Err(VfsError::Unsupported);
+        }
+        let to_read = core::cmp::min(buf.len() as u64, file_size - offset) as usize;
+
+        let extent_map = rsext4::loopfile::resolve_inode_block_allextend(fs, dev, &mut inode)
+            .map_err(into_vfs_err)?;
+        let first_lbn = (offset / BLOCK_SIZE as u64) as u32;
+        let lbns = first_lbn..first_lbn + to_read.div_ceil(BLOCK_SIZE) as u32;
+        let runs = block_runs(lbns.map(|lbn| extent_map.get(&lbn).copied()));
+        for (index, start, count) in runs {
+            let run = &mut buf[index * BLOCK_SIZE..(index + count) * BLOCK_SIZE];
+            match start {
+                Some(start) => {
+                    // The cached copies of the blocks may be newer than the disk.
+                    for phys in start..start + count as u64 {
+                        fs.datablock_cache.flush(dev, phys).map_err(into_vfs_err)?;
+                    }
+                    dev.read_blocks(run, start as u32, count as u32)
+                        .map_err(into_vfs_err)?;
+                }
+                None => run.fill(0),
+            }
+        }
+        Ok(to_read)
+    }
+
+    fn write_direct(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        let mut inode = fs.get_inode_by_num(dev, self.ino).map_err(into_vfs_err)?;
+        let first_lbn = (offset / BLOCK_SIZE as u64) as u32;
+        let lbns = first_lbn..first_lbn + (buf.len() / BLOCK_SIZE) as u32;
+
+        let end = offset + buf.len() as u64;
+        let mapped = if end <= inode.size() && inode.have_extend_header_and_use_extend() {
+            let extent_map = rsext4::loopfile::resolve_inode_block_allextend(fs, dev, &mut inode)
+                .map_err(into_vfs_err)?;
+            lbns.clone()
+                .map(|lbn| extent_map.get(&lbn).copied())
+                .collect::<Option<Vec<_>>>()
+        } else {
+            None
+        };
+
+        let Some(blocks) = mapped else {
+            // Blocks have to be allocated or the size changes, which only the
+            // regular path does; push the written blocks out of the cache
+            // afterwards.
+            rsext4::file::write_file_with_ino(dev, fs, self.ino, offset, buf)
+                .map_err(into_vfs_err)?;
+            let mut inode = fs.get_inode_by_num(dev, self.ino).map_err(into_vfs_err)?;
+            let extent_map = rsext4::loopfile::resolve_inode_block_allextend(fs, dev, &mut inode)
+                .map_err(into_vfs_err)?;
+            for lbn in lbns {
+                if let Some(&phys) = extent_map.get(&lbn) {
+                    fs.datablock_cache.flush(dev, phys).map_err(into_vfs_err)?;
+                    fs.datablock_cache.invalidate(phys);
+                }
+            }
+            return Ok(buf.len());
+        };
+        for (index, start, count) in block_runs(blocks.into_iter().map(Some)) {
+            let start = start.expect("all blocks are mapped");
+            for phys in start..start + count as u64 {
+                fs.datablock_cache.invalidate(phys);
+            }
+            let run = &buf[index * BLOCK_SIZE..(index + count) * BLOCK_SIZE];
+            dev.write_blocks(run, start as u32, count as u32, false)
+                .map_err(into_vfs_err)?;
+        }
+        Ok(buf.len())
+    }
+
     fn set_len(&self, len: u64) -> VfsResult<()> {
         let mut state = self.fs.lock();
         let (fs, dev) = state.split();
@@ -374,6 +583,23 @@ impl FileNodeOps for Inode {
 
         Ok(())
     }
+
+    fn fallocate(&self, mode: FallocateMode, offset: u64, len: u64) -> VfsResult<()> {
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        match mode {
+            FallocateMode::Allocate { keep_size } => {
+                rsext4::file::fallocate_with_ino(dev, fs, self.ino, offset, len, keep_size)
+            }
+            FallocateMode::PunchHole => {
+                rsext4::file::punch_hole_with_ino(dev, fs, self.ino, offset, len)
+            }
+            FallocateMode::ZeroRange { keep_size } => {
+                rsext4::file::zero_range_with_ino(dev, fs, self.ino, offset, len, keep_size)
+            }
+        }
+        .map_err(into_vfs_err)
+    }
 }
 
 impl Pollable for Inode {
@@ -393,36 +619,28 @@ impl DirNodeOps for Inode {
         let blocks = rsext4::loopfile::resolve_inode_block_allextend(fs, dev, &mut inode)
             .map_err(into_vfs_err)?;
 
-        let mut idx = 0u64;
-        let mut count = 0usize;
-        for &phys in blocks.values() {
-            let cached = fs
-                .datablock_cache
-                .get_or_load(dev, phys)
-                .map_err(into_vfs_err)?;
-            let data = &cached.data[..BLOCK_SIZE];
-            let iter = rsext4::entries::DirEntryIterator::new(data);
-            for (entry, _) in iter {
-                if entry.inode == 0 {
-                    continue;
+        // Cookies are byte offsets in the directory; decode the blocks one at
+        // a time from the one holding `offset`.
+        let mut blocks = blocks.range((offset / BLOCK_SIZE as u64) as u32..);
+        let mut pending = Vec::new().into_iter();
+        let entries = core::iter::from_fn(|| {
+            loop {
+                if let Some(entry) = pending.next() {
+                    return Some(Ok(entry));
                 }
-                if idx < offset {
-                    idx += 1;
-                    continue;
-                }
-                let name = core::str::from_utf8(entry.name)
-                    .map_err(|_| VfsError::InvalidData)?
-                    .to_owned();
-                let node_type = dir_entry_type_to_vfs(entry.file_type);
-                idx += 1;
-                if !sink.accept(&name, entry.inode as u64, node_type, idx) {
-                    return Ok(count);
+                let (&lblock, &phys) = blocks.next()?;
+                let block = fs
+                    .datablock_cache
+                    .get_or_load(dev, phys)
+                    .map_err(into_vfs_err)
+                    .and_then(|cached| block_entries(lblock, &cached.data[..BLOCK_SIZE]));
+                match block {
+                    Ok(block) => pending = block.into_iter(),
+                    Err(err) => return Some(Err(err)),
                 }
-                count += 1;
             }
-        }
-
-        Ok(count)
+        });
+        Ok(read_entries(entries, offset, sink, false)?.0)
     }
 
     fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
@@ -496,6 +714,48 @@ impl DirNodeOps for Inode {
         })
     }
 
+    fn mknod(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<DirEntry> {
+        let path = join_child_path(&self.dir_path()?, name);
+        let mut state = self.fs.lock();
+        let (fs, dev) = state.split();
+        if rsext4::dir::get_inode_with_num(fs, dev, &path)
+            .map_err(into_vfs_err)?
+            .is_some()
+        {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let file_type = vfs_type_to_dir_entry(node_type).ok_or(VfsError::InvalidData)?;
+        let (ino, _inode) = rsext4::file::mkfile_with_ino(dev, fs, &path, None, Some(file_type))
+            .ok_or(VfsError::InvalidInput)?;
+        let mode_bits = permission.bits();
+        fs.modify_inode(dev, ino, |node| {
+            node.i_mode = (node.i_mode & !0o7777) | (mode_bits & 0o7777);
+            // Device inodes keep the device number where the block map would
+            // be, so they must not carry an extent tree.
+            node.i_flags &= !rsext4::disknode::Ext4Inode::EXT4_EXTENTS_FL;
+            set_inode_rdev(node, rdev);
+        })
+        .map_err(into_vfs_err)?;
+        Self::update_ctime_with(fs, dev, ino)?;
+
+        let reference = Reference::new(
+            self.this.as_ref().and_then(WeakDirEntry::upgrade),
+            name.to_owned(),
+        );
+        Ok(DirEntry::new_file(
+            FileNode::new(Inode::new(self.fs.clone(), ino, None, Some(path))),
+            node_type,
+            reference,
+        ))
+    }
+
     fn link(&self, name: &str, node: &DirEntry) -> VfsResult<DirEntry> {
         let dir_path = self.dir_path()?;
         let link_path = join_child_path(&dir_path, name);
@@ -10,59 +10,45 @@ mod util;
 pub use fs::*;
 pub use inode::*;
 #[allow(unused_imports)]
-use kdriver::{BlockDevice as KBlockDevice, prelude::BlockDriverOps};
+use kdriver::prelude::BlockDriverOps;
 use rsext4::{
     BlockDevice,
     error::{BlockDevError, BlockDevResult},
 };
 
+use crate::Partition;
+
 const FS_BLOCK_SIZE: usize = rsext4::BLOCK_SIZE;
 
 /// Block device wrapper implementing the ext4 driver traits.
-pub(crate) struct Ext4Disk(KBlockDevice);
+pub(crate) struct Ext4Disk(Partition);
+
+impl Ext4Disk {
+    /// Returns the length of `count` filesystem blocks, checking that
+    /// `provided` bytes hold them.
+    fn request_len(provided: usize, count: u32) -> BlockDevResult<usize> {
+        let required = FS_BLOCK_SIZE * count as usize;
+        if provided < required {
+            return Err(BlockDevError::BufferTooSmall { provided, required });
+        }
+        Ok(required)
+    }
+}
 
+// Filesystem blocks are addressed in bytes, so that the block layer deals
+// with devices whose sectors are larger than them or not a divisor of them.
 impl BlockDevice for Ext4Disk {
     fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-        let dev_block = self.0.block_size();
-        if !FS_BLOCK_SIZE.is_multiple_of(dev_block) {
-            return Err(BlockDevError::InvalidBlockSize {
-                size: dev_block,
-                expected: FS_BLOCK_SIZE,
-            });
-        }
-        let factor = (FS_BLOCK_SIZE / dev_block) as u64;
-        let required_size = FS_BLOCK_SIZE * count as usize;
-        if buffer.len() < required_size {
-            return Err(BlockDevError::BufferTooSmall {
-                provided: buffer.len(),
-                required: required_size,
-            });
-        }
-        let start_block = block_id as u64 * factor;
+        let len = Self::request_len(buffer.len(), count)?;
         self.0
-            .write_block(start_block, &buffer[..required_size])
+            .write_at(block_id as u64 * FS_BLOCK_SIZE as u64, &buffer[..len])
             .map_err(|_| BlockDevError::WriteError)
     }
 
     fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-        let dev_block = self.0.block_size();
-        if !FS_BLOCK_SIZE.is_multiple_of(dev_block) {
-            return Err(BlockDevError::InvalidBlockSize {
-                size: dev_block,
-                expected: FS_BLOCK_SIZE,
-            });
-        }
-        let factor = (FS_BLOCK_SIZE / dev_block) as u64;
-        let required_size = FS_BLOCK_SIZE * count as usize;
-        if buffer.len() < required_size {
-            return Err(BlockDevError::BufferTooSmall {
-                provided: buffer.len(),
-                required: required_size,
-            });
-        }
-        let start_block = block_id as u64 * factor;
+        let len = Self::request_len(buffer.len(), count)?;
         self.0
-            .read_block(start_block, &mut buffer[..required_size])
+            .read_at(block_id as u64 * FS_BLOCK_SIZE as u64, &mut buffer[..len])
             .map_err(|_| BlockDevError::ReadError)
     }
 
@@ -85,7 +71,7 @@ impl BlockDevice for Ext4Disk {
     }
 
     fn flush(&mut self) -> BlockDevResult<()> {
-        self.0.flush().map_err(|_| BlockDevError::IoError)
+        self.0.flush_disk().map_err(|_| BlockDevError::IoError)
     }
 
     fn is_open(&self) -> bool {
@@ -3,9 +3,12 @@
 // See LICENSES for license details.
 
 //! Ext4 adapter utilities.
-use fs_ng_vfs::{NodeType, VfsError};
+use fs_ng_vfs::{DeviceId, NodeType, VfsError};
 use kerrno::LinuxError;
-use rsext4::error::{BlockDevError, RSEXT4Error};
+use rsext4::{
+    disknode::Ext4Inode,
+    error::{BlockDevError, RSEXT4Error},
+};
 
 /// Convert rsext4 block device errors into VFS errors.
 pub fn into_vfs_err(err: BlockDevError) -> VfsError {
@@ -44,16 +47,42 @@ pub fn into_vfs_err_mount(err: RSEXT4Error) -> VfsError {
     VfsError::from(linux_error).canonicalize()
 }
 
-/// Convert ext4 inode types to VFS node types.
-pub fn inode_to_vfs_type(is_dir: bool, is_file: bool, is_symlink: bool) -> NodeType {
-    if is_dir {
-        NodeType::Directory
-    } else if is_file {
-        NodeType::RegularFile
-    } else if is_symlink {
-        NodeType::Symlink
+/// Convert the file type bits of an ext4 inode mode to VFS node types.
+pub fn inode_to_vfs_type(inode: &Ext4Inode) -> NodeType {
+    match inode.i_mode & Ext4Inode::S_IFMT {
+        Ext4Inode::S_IFDIR => NodeType::Directory,
+        Ext4Inode::S_IFREG => NodeType::RegularFile,
+        Ext4Inode::S_IFLNK => NodeType::Symlink,
+        Ext4Inode::S_IFCHR => NodeType::CharacterDevice,
+        Ext4Inode::S_IFBLK => NodeType::BlockDevice,
+        Ext4Inode::S_IFIFO => NodeType::Fifo,
+        Ext4Inode::S_IFSOCK => NodeType::Socket,
+        _ => NodeType::Unknown,
+    }
+}
+
+/// Read the device number stored in a device inode.
+///
+/// Like Linux, small numbers use the old 16-bit encoding in `i_block[0]` and
+/// others use the new 32-bit encoding in `i_block[1]`.
+pub fn inode_rdev(inode: &Ext4Inode) -> DeviceId {
+    let old = inode.i_block[0];
+    if old != 0 {
+        DeviceId::new((old >> 8) & 0xff, old & 0xff)
+    } else {
+        let new = inode.i_block[1];
+        DeviceId::new((new & 0xfff00) >> 8, (new & 0xff) | ((new >> 12) & 0xfff00))
+    }
+}
+
+/// Store a device number in a device inode, see [`inode_rdev`].
+pub fn set_inode_rdev(inode: &mut Ext4Inode, rdev: DeviceId) {
+    let (major, minor) = (rdev.major(), rdev.minor());
+    inode.i_block = [0; 15];
+    if major < 256 && minor < 256 {
+        inode.i_block[0] = (major << 8) | minor;
     } else {
-        NodeType::Unknown
+        inode.i_block[1] = (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12);
     }
 }
 
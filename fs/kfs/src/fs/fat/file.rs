@@ -161,6 +161,11 @@ impl FileNodeOps for FatFileNode {
     fn set_symlink(&self, _target: &str) -> VfsResult<()> {
         Err(VfsError::PermissionDenied)
     }
+
+    fn direct_io_align(&self) -> Option<usize> {
+        // `fatfs` reads and writes clusters through its own buffers.
+        None
+    }
 }
 
 impl Pollable for FatFileNode {
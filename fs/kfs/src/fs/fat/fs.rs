@@ -9,12 +9,11 @@ use core::marker::PhantomPinned;
 use fs_ng_vfs::{
     DirEntry, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
-use kdriver::BlockDevice as KBlockDevice;
 use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
 use slab::Slab;
 
 use super::{dir::FatDirNode, ff, util::into_vfs_err};
-use crate::disk::SeekableDisk;
+use crate::{Partition, disk::SeekableDisk};
 
 /// Inner FAT filesystem state.
 pub struct FatFilesystemInner {
@@ -43,7 +42,7 @@ pub struct FatFilesystem {
 
 impl FatFilesystem {
     /// Create a new FAT filesystem instance backed by a block device.
-    pub fn new(dev: KBlockDevice) -> Filesystem {
+    pub fn new(dev: Partition) -> Filesystem {
         let mut inner = FatFilesystemInner {
             inner: ff::FileSystem::new(SeekableDisk::new(dev), fatfs::FsOptions::new())
                 .expect("failed to initialize FAT filesystem"),
@@ -9,12 +9,37 @@ mod fat;
 #[cfg(feature = "ext4")]
 mod ext4;
 
+#[cfg(feature = "squashfs")]
+mod squashfs;
+
+mod tmp;
+
+use alloc::{boxed::Box, vec};
+
 use cfg_if::cfg_if;
-use fs_ng_vfs::{Filesystem, VfsResult};
-use kdriver::BlockDevice as KBlockDevice;
+use fs_ng_vfs::{Filesystem, VfsError, VfsResult};
+use kdriver::prelude::*;
+
+#[cfg(all(feature = "ext4", feature = "ext4-rsext4"))]
+pub(crate) use self::ext4::{Rsext4Filesystem, Rsext4Inode};
+#[cfg(feature = "squashfs")]
+pub use self::squashfs::{FileImage, ImageSource, SquashFilesystem};
+pub use self::tmp::MemoryFs;
+use crate::{File, Partition};
 
 /// Create the default filesystem instance for the given block device.
-pub fn new_default(_dev: KBlockDevice) -> VfsResult<Filesystem> {
+///
+/// A squashfs image on the device is mounted as such, whichever other
+/// filesystem is the default.
+pub fn new_default(_dev: Partition) -> VfsResult<Filesystem> {
+    #[cfg(feature = "squashfs")]
+    let _dev = {
+        let mut dev = _dev;
+        if probe("squashfs", &mut dev)? {
+            return squashfs::SquashFilesystem::new(dev);
+        }
+        dev
+    };
     cfg_if! {
         if #[cfg(feature = "ext4")] {
             ext4::Ext4Filesystem::new(_dev)
@@ -25,3 +50,64 @@ pub fn new_default(_dev: KBlockDevice) -> VfsResult<Filesystem> {
         }
     }
 }
+
+/// Returns whether filesystems of type `fs_type`, as named by `mount(2)`,
+/// can be created on block devices.
+pub fn is_supported(fs_type: &str) -> bool {
+    match fs_type {
+        #[cfg(feature = "ext4")]
+        "ext4" => true,
+        #[cfg(feature = "fat")]
+        "vfat" | "msdos" => true,
+        #[cfg(feature = "squashfs")]
+        "squashfs" => true,
+        _ => false,
+    }
+}
+
+/// Returns whether filesystems of type `fs_type` are read-only, and may be
+/// created on image files as well as block devices.
+pub fn is_read_only(fs_type: &str) -> bool {
+    matches!(fs_type, "squashfs")
+}
+
+/// Checks the superblock signature of `fs_type` on `dev`, so that mounting a
+/// device with the wrong type fails before the device is handed over.
+pub fn probe(fs_type: &str, dev: &mut Partition) -> VfsResult<bool> {
+    let block_size = dev.block_size();
+    let mut buf = vec![0u8; 2048usize.next_multiple_of(block_size)];
+    dev.read_block(0, &mut buf).map_err(|_| VfsError::Io)?;
+    Ok(match fs_type {
+        // `s_magic` of the superblock at offset 1024
+        "ext4" => buf[1080..1082] == [0x53, 0xef],
+        // boot sector signature and jump instruction
+        "vfat" | "msdos" => buf[510..512] == [0x55, 0xaa] && matches!(buf[0], 0xeb | 0xe9),
+        // `s_magic` at the start of the superblock
+        "squashfs" => buf[..4] == *b"hsqs",
+        _ => false,
+    })
+}
+
+/// Create a read-only filesystem of type `fs_type` on the image file `file`.
+pub fn new_on_file(fs_type: &str, _file: File) -> VfsResult<Filesystem> {
+    match fs_type {
+        #[cfg(feature = "squashfs")]
+        "squashfs" => {
+            squashfs::SquashFilesystem::from_source(Box::new(squashfs::FileImage::new(_file)?))
+        }
+        _ => Err(VfsError::NoSuchDevice),
+    }
+}
+
+/// Create a filesystem of type `fs_type` on the given block device.
+pub fn new_by_type(fs_type: &str, _dev: Partition) -> VfsResult<Filesystem> {
+    match fs_type {
+        #[cfg(feature = "ext4")]
+        "ext4" => ext4::Ext4Filesystem::new(_dev),
+        #[cfg(feature = "fat")]
+        "vfat" | "msdos" => Ok(fat::FatFilesystem::new(_dev)),
+        #[cfg(feature = "squashfs")]
+        "squashfs" => squashfs::SquashFilesystem::new(_dev),
+        _ => Err(VfsError::NoSuchDevice),
+    }
+}
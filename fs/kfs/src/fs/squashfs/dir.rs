@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Squashfs directory listings.
+//!
+//! A listing is a series of runs, each a header followed by up to 256
+//! entries whose inodes are in the same metadata block. Entries are sorted
+//! by name, which lets lookups in large directories start from the run the
+//! directory index points at.
+use alloc::vec::Vec;
+use core::{cmp::Ordering, ops::ControlFlow};
+
+use fs_ng_vfs::{NodeType, VfsError, VfsResult};
+
+use super::{METADATA_SIZE, MetaReader, SquashFilesystem, inode::DirInode};
+
+/// An entry of a directory listing.
+pub(super) struct RawEntry {
+    pub name: Vec<u8>,
+    /// The reference of the inode, as taken by
+    /// [`SquashFilesystem::read_inode`].
+    pub inode_ref: u64,
+    pub ino: u32,
+    pub node_type: NodeType,
+}
+
+fn entry_type(entry_type: u16) -> VfsResult<NodeType> {
+    Ok(match entry_type {
+        1 => NodeType::Directory,
+        2 => NodeType::RegularFile,
+        3 => NodeType::Symlink,
+        4 => NodeType::BlockDevice,
+        5 => NodeType::CharacterDevice,
+        6 => NodeType::Fifo,
+        7 => NodeType::Socket,
+        _ => return Err(VfsError::InvalidData),
+    })
+}
+
+impl SquashFilesystem {
+    /// Calls `f` with the entries of `dir`, in order, from the run header at
+    /// `offset` of the listing, which is in the metadata block `block`.
+    fn walk_dir(
+        &self,
+        dir: &DirInode,
+        block: u32,
+        offset: u32,
+        mut f: impl FnMut(RawEntry) -> ControlFlow<()>,
+    ) -> VfsResult<()> {
+        let len = dir.size.saturating_sub(3) as usize;
+        let mut pos = offset as usize;
+        let mut r = MetaReader::new(
+            self,
+            self.sb.directory_table + block as u64,
+            (dir.offset as usize + pos) % METADATA_SIZE,
+        )?;
+        while pos < len {
+            let count = r.u32()? as usize + 1;
+            let start = r.u32()? as u64;
+            let base = r.u32()?;
+            pos += 12;
+            if count > 256 {
+                return Err(VfsError::InvalidData);
+            }
+            for _ in 0..count {
+                let offset = r.u16()? as u64;
+                let delta = r.u16()? as i16;
+                let node_type = entry_type(r.u16()?)?;
+                let name_len = r.u16()? as usize + 1;
+                if name_len > 256 {
+                    return Err(VfsError::InvalidData);
+                }
+                let name = r.bytes(name_len)?;
+                pos += 8 + name_len;
+                let entry = RawEntry {
+                    name,
+                    inode_ref: start << 16 | offset,
+                    ino: base.wrapping_add_signed(delta as i32),
+                    node_type,
+                };
+                if f(entry).is_break() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the entries of `dir`, in order.
+    pub(super) fn for_each_entry(
+        &self,
+        dir: &DirInode,
+        f: impl FnMut(RawEntry) -> ControlFlow<()>,
+    ) -> VfsResult<()> {
+        self.walk_dir(dir, dir.block, 0, f)
+    }
+
+    /// Looks up `name` in `dir`.
+    ///
+    /// The scan starts from the last run of the index whose first name is not
+    /// after `name`, and stops at the first name after it.
+    pub(super) fn find_entry(&self, dir: &DirInode, name: &[u8]) -> VfsResult<Option<RawEntry>> {
+        let (mut block, mut offset) = (dir.block, 0);
+        for index in &dir.index {
+            if index.name.as_slice() > name {
+                break;
+            }
+            (block, offset) = (index.start, index.index);
+        }
+        let mut found = None;
+        self.walk_dir(dir, block, offset, |entry| {
+            match entry.name.as_slice().cmp(name) {
+                Ordering::Less => return ControlFlow::Continue(()),
+                Ordering::Equal => found = Some(entry),
+                Ordering::Greater => {}
+            }
+            ControlFlow::Break(())
+        })?;
+        Ok(found)
+    }
+}
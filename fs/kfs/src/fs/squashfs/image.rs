@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Sources squashfs images are read from.
+use alloc::vec;
+
+use fs_ng_vfs::{VfsError, VfsResult};
+use kdriver::prelude::BlockDriverOps;
+use ksync::Mutex;
+
+use crate::{File, Partition};
+
+/// Where the bytes of a squashfs image come from.
+pub trait ImageSource: Send + Sync {
+    /// Returns the size of the image in bytes.
+    fn size(&self) -> u64;
+
+    /// Fills `buf` with the bytes at `offset` of the image.
+    ///
+    /// Fails with `EIO` if the range is not within the image.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()>;
+}
+
+fn check_range(size: u64, len: usize, offset: u64) -> VfsResult<()> {
+    match offset.checked_add(len as u64) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(VfsError::Io),
+    }
+}
+
+/// An image on a block device, such as a partition.
+pub struct BlockImage(Mutex<Partition>);
+
+impl BlockImage {
+    /// Creates a source reading `dev`.
+    pub fn new(dev: Partition) -> Self {
+        Self(Mutex::new(dev))
+    }
+}
+
+impl ImageSource for BlockImage {
+    fn size(&self) -> u64 {
+        let dev = self.0.lock();
+        dev.num_blocks() * dev.block_size() as u64
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+        let dev = self.0.lock();
+        let block_size = dev.block_size() as u64;
+        check_range(dev.num_blocks() * block_size, buf.len(), offset)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        // Whole blocks are read, into a bounce buffer unless `buf` is made of
+        // them.
+        let first = offset / block_size;
+        let skip = (offset % block_size) as usize;
+        if skip == 0 && buf.len().is_multiple_of(block_size as usize) {
+            return dev.read_blocks(first, buf).map_err(|_| VfsError::Io);
+        }
+        let len = (skip + buf.len()).next_multiple_of(block_size as usize);
+        let mut bounce = vec![0u8; len];
+        dev.read_blocks(first, &mut bounce)
+            .map_err(|_| VfsError::Io)?;
+        buf.copy_from_slice(&bounce[skip..skip + buf.len()]);
+        Ok(())
+    }
+}
+
+/// An image in memory, such as one loaded by the bootloader.
+impl ImageSource for &'static [u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+        check_range(self.len() as u64, buf.len(), offset)?;
+        let offset = offset as usize;
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+        Ok(())
+    }
+}
+
+/// An image in a regular file, such as an app bundle.
+pub struct FileImage {
+    file: File,
+    size: u64,
+}
+
+impl FileImage {
+    /// Creates a source reading `file`, which must not change while mounted.
+    pub fn new(file: File) -> VfsResult<Self> {
+        let size = file.location().len()?;
+        Ok(Self { file, size })
+    }
+}
+
+impl ImageSource for FileImage {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> VfsResult<()> {
+        check_range(self.size, buf.len(), offset)?;
+        while !buf.is_empty() {
+            let n = self.file.read_at(&mut *buf, offset)?;
+            if n == 0 {
+                return Err(VfsError::Io);
+            }
+            buf = &mut buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Squashfs inodes.
+use alloc::vec::Vec;
+
+use fs_ng_vfs::{DeviceId, NodeType, VfsError, VfsResult};
+
+use super::{DATA_UNCOMPRESSED, METADATA_SIZE, MetaReader, SquashFilesystem};
+
+/// Fragment index of files without a fragment.
+const NO_FRAGMENT: u32 = u32::MAX;
+
+/// An entry of the index of a large directory, pointing at the header of the
+/// listing that starts a metadata block.
+#[derive(Debug, Clone)]
+pub(super) struct DirIndex {
+    /// The offset of the header in the listing.
+    pub index: u32,
+    /// The position of the metadata block, relative to the directory table.
+    pub start: u32,
+    /// The name of the first entry after the header.
+    pub name: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct DirInode {
+    /// The position of the metadata block the listing starts in, relative
+    /// to the directory table.
+    pub block: u32,
+    /// The offset of the listing in its first metadata block.
+    pub offset: u16,
+    /// The size of the listing, plus 3 for the `.` and `..` it omits.
+    pub size: u32,
+    /// The inode number of the parent directory.
+    pub parent: u32,
+    pub index: Vec<DirIndex>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct FileInode {
+    pub size: u64,
+    /// The position and size field of each full block, in order; a size of 0
+    /// marks a hole.
+    pub blocks: Vec<(u64, u32)>,
+    /// The fragment index and offset of the tail, if not in a block.
+    pub fragment: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum InodeKind {
+    Dir(DirInode),
+    File(FileInode),
+    Symlink(Vec<u8>),
+    Device(NodeType, DeviceId),
+    Ipc(NodeType),
+}
+
+/// A parsed inode.
+#[derive(Debug, Clone)]
+pub(super) struct Inode {
+    pub ino: u32,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u32,
+    pub nlink: u32,
+    pub kind: InodeKind,
+}
+
+impl Inode {
+    pub fn node_type(&self) -> NodeType {
+        match &self.kind {
+            InodeKind::Dir(_) => NodeType::Directory,
+            InodeKind::File(_) => NodeType::RegularFile,
+            InodeKind::Symlink(_) => NodeType::Symlink,
+            InodeKind::Device(node_type, _) | InodeKind::Ipc(node_type) => *node_type,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match &self.kind {
+            InodeKind::Dir(dir) => dir.size as u64,
+            InodeKind::File(file) => file.size,
+            InodeKind::Symlink(target) => target.len() as u64,
+            InodeKind::Device(..) | InodeKind::Ipc(_) => 0,
+        }
+    }
+}
+
+/// Decodes the device number of a device inode.
+fn device_id(dev: u32) -> DeviceId {
+    DeviceId::new((dev >> 8) & 0xfff, (dev & 0xff) | ((dev >> 12) & 0xf_ff00))
+}
+
+impl SquashFilesystem {
+    /// Reads the inode `inode_ref`, the position of its metadata block
+    /// relative to the inode table shifted by 16, and its offset in it.
+    pub(super) fn read_inode(&self, inode_ref: u64) -> VfsResult<Inode> {
+        let block = self.sb.inode_table + (inode_ref >> 16);
+        let offset = (inode_ref & 0xffff) as usize;
+        if offset >= METADATA_SIZE {
+            return Err(VfsError::InvalidData);
+        }
+        let mut r = MetaReader::new(self, block, offset)?;
+
+        let inode_type = r.u16()?;
+        let mode = r.u16()?;
+        let uid = self.id(r.u16()?)?;
+        let gid = self.id(r.u16()?)?;
+        let mtime = r.u32()?;
+        let ino = r.u32()?;
+
+        let (nlink, kind) = match inode_type {
+            1 => {
+                let block = r.u32()?;
+                let nlink = r.u32()?;
+                let size = r.u16()? as u32;
+                let offset = r.u16()?;
+                let parent = r.u32()?;
+                let dir = DirInode {
+                    block,
+                    offset,
+                    size,
+                    parent,
+                    index: Vec::new(),
+                };
+                (nlink, InodeKind::Dir(dir))
+            }
+            8 => {
+                let nlink = r.u32()?;
+                let size = r.u32()?;
+                let block = r.u32()?;
+                let parent = r.u32()?;
+                let index_count = r.u16()?;
+                let offset = r.u16()?;
+                let _xattr = r.u32()?;
+                let mut index = Vec::with_capacity(index_count as usize);
+                for _ in 0..index_count {
+                    let idx = r.u32()?;
+                    let start = r.u32()?;
+                    let name_len = r.u32()? as usize + 1;
+                    if name_len > 256 {
+                        return Err(VfsError::InvalidData);
+                    }
+                    index.push(DirIndex {
+                        index: idx,
+                        start,
+                        name: r.bytes(name_len)?,
+                    });
+                }
+                let dir = DirInode {
+                    block,
+                    offset,
+                    size,
+                    parent,
+                    index,
+                };
+                (nlink, InodeKind::Dir(dir))
+            }
+            2 => {
+                let blocks_start = r.u32()? as u64;
+                let fragment = r.u32()?;
+                let frag_offset = r.u32()?;
+                let size = r.u32()? as u64;
+                let file = self.read_file(&mut r, blocks_start, size, fragment, frag_offset)?;
+                (1, InodeKind::File(file))
+            }
+            9 => {
+                let blocks_start = r.u64()?;
+                let size = r.u64()?;
+                let _sparse = r.u64()?;
+                let nlink = r.u32()?;
+                let fragment = r.u32()?;
+                let frag_offset = r.u32()?;
+                let _xattr = r.u32()?;
+                let file = self.read_file(&mut r, blocks_start, size, fragment, frag_offset)?;
+                (nlink, InodeKind::File(file))
+            }
+            3 | 10 => {
+                let nlink = r.u32()?;
+                let len = r.u32()? as usize;
+                if len > METADATA_SIZE {
+                    return Err(VfsError::InvalidData);
+                }
+                (nlink, InodeKind::Symlink(r.bytes(len)?))
+            }
+            4 | 5 | 11 | 12 => {
+                let nlink = r.u32()?;
+                let dev = device_id(r.u32()?);
+                let node_type = if matches!(inode_type, 4 | 11) {
+                    NodeType::BlockDevice
+                } else {
+                    NodeType::CharacterDevice
+                };
+                (nlink, InodeKind::Device(node_type, dev))
+            }
+            6 | 7 | 13 | 14 => {
+                let nlink = r.u32()?;
+                let node_type = if matches!(inode_type, 6 | 13) {
+                    NodeType::Fifo
+                } else {
+                    NodeType::Socket
+                };
+                (nlink, InodeKind::Ipc(node_type))
+            }
+            _ => return Err(VfsError::InvalidData),
+        };
+        Ok(Inode {
+            ino,
+            mode,
+            uid,
+            gid,
+            mtime,
+            nlink,
+            kind,
+        })
+    }
+
+    /// Reads the block list of a file inode.
+    fn read_file(
+        &self,
+        r: &mut MetaReader<'_>,
+        blocks_start: u64,
+        size: u64,
+        fragment: u32,
+        frag_offset: u32,
+    ) -> VfsResult<FileInode> {
+        let block_size = self.sb.block_size as u64;
+        let (count, fragment) = if fragment == NO_FRAGMENT {
+            (size.div_ceil(block_size), None)
+        } else {
+            (size / block_size, Some((fragment, frag_offset)))
+        };
+        // Each block takes 4 bytes of metadata at least.
+        if count * 4 > self.sb.bytes_used {
+            return Err(VfsError::InvalidData);
+        }
+        let mut blocks = Vec::with_capacity(count as usize);
+        let mut pos = blocks_start;
+        for _ in 0..count {
+            let size = r.u32()?;
+            blocks.push((pos, size));
+            pos += (size & (DATA_UNCOMPRESSED - 1)) as u64;
+        }
+        Ok(FileInode {
+            size,
+            blocks,
+            fragment,
+        })
+    }
+}
@@ -0,0 +1,408 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Read-only squashfs (version 4.0).
+//!
+//! Metadata (inodes, directory listings, the id and fragment tables) is
+//! stored in blocks of up to 8 KiB, each compressed on its own; file data in
+//! blocks of the filesystem block size, the tail of a file possibly packed
+//! with others into a fragment block. Decompressed blocks are kept in small
+//! LRU caches, as the page cache above only holds what files were read.
+//!
+//! The zlib and LZ4 compressors are supported.
+mod dir;
+mod image;
+mod inode;
+mod node;
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::num::NonZeroUsize;
+
+use fs_ng_vfs::{
+    DirEntry, DirNode, Filesystem, FilesystemOps, Reference, StatFs, VfsError, VfsResult,
+    path::MAX_NAME_LEN,
+};
+use ksync::Mutex;
+use lru::LruCache;
+
+pub use self::image::{BlockImage, FileImage, ImageSource};
+use self::node::SquashNode;
+use crate::Partition;
+
+/// `s_magic` of the superblock, "hsqs".
+pub const SQUASHFS_MAGIC: u32 = 0x7371_7368;
+
+/// Size of the superblock.
+const SUPERBLOCK_SIZE: usize = 96;
+/// Largest decompressed size of a metadata block.
+const METADATA_SIZE: usize = 8192;
+/// Set in the header of a metadata block stored uncompressed.
+const METADATA_UNCOMPRESSED: u16 = 1 << 15;
+/// Set in the size of a data block stored uncompressed.
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+/// Size of an entry of the fragment table.
+const FRAGMENT_ENTRY_SIZE: usize = 16;
+
+/// Number of metadata blocks cached.
+const METADATA_CACHE_BLOCKS: usize = 64;
+/// Number of data and fragment blocks cached.
+const DATA_CACHE_BLOCKS: usize = 16;
+
+/// Compressors, by their id in the superblock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compressor {
+    Zlib,
+    Lz4,
+}
+
+impl Compressor {
+    fn from_id(id: u16) -> Option<Self> {
+        match id {
+            1 => Some(Self::Zlib),
+            5 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// The fields of the superblock used.
+#[derive(Debug, Clone)]
+struct SuperBlock {
+    inode_count: u32,
+    block_size: u32,
+    fragment_count: u32,
+    compressor: Compressor,
+    id_count: u16,
+    root_inode: u64,
+    bytes_used: u64,
+    id_table: u64,
+    inode_table: u64,
+    directory_table: u64,
+    fragment_table: u64,
+}
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+impl SuperBlock {
+    fn parse(buf: &[u8; SUPERBLOCK_SIZE]) -> VfsResult<Self> {
+        if le32(buf, 0) != SQUASHFS_MAGIC {
+            return Err(VfsError::InvalidInput);
+        }
+        let (major, minor) = (le16(buf, 28), le16(buf, 30));
+        if (major, minor) != (4, 0) {
+            warn!("squashfs: unsupported version {major}.{minor}");
+            return Err(VfsError::Unsupported);
+        }
+        let block_size = le32(buf, 12);
+        let block_log = le16(buf, 22);
+        if !(12..=20).contains(&block_log) || block_size != 1 << block_log {
+            warn!("squashfs: bad block size {block_size}");
+            return Err(VfsError::InvalidData);
+        }
+        let compression = le16(buf, 20);
+        let Some(compressor) = Compressor::from_id(compression) else {
+            warn!("squashfs: unsupported compressor {compression}");
+            return Err(VfsError::Unsupported);
+        };
+        Ok(Self {
+            inode_count: le32(buf, 4),
+            block_size,
+            fragment_count: le32(buf, 16),
+            compressor,
+            id_count: le16(buf, 26),
+            root_inode: le64(buf, 32),
+            bytes_used: le64(buf, 40),
+            id_table: le64(buf, 48),
+            inode_table: le64(buf, 64),
+            directory_table: le64(buf, 72),
+            fragment_table: le64(buf, 80),
+        })
+    }
+}
+
+/// A decompressed metadata block, with the position of the next one.
+#[derive(Clone)]
+struct MetaBlock {
+    data: Arc<[u8]>,
+    next: u64,
+}
+
+/// A squashfs filesystem.
+pub struct SquashFilesystem {
+    image: Box<dyn ImageSource>,
+    sb: SuperBlock,
+    /// The uids and gids, which inodes refer to by index.
+    ids: Vec<u32>,
+    /// The positions of the metadata blocks of the fragment table.
+    fragment_index: Vec<u64>,
+    /// Metadata blocks by position.
+    meta_cache: Mutex<LruCache<u64, MetaBlock>>,
+    /// Data and fragment blocks by position.
+    data_cache: Mutex<LruCache<u64, Arc<[u8]>>>,
+    root_dir: Mutex<Option<DirEntry>>,
+}
+
+impl SquashFilesystem {
+    /// Creates a filesystem on the partition `dev`.
+    pub fn new(dev: Partition) -> VfsResult<Filesystem> {
+        Self::from_source(Box::new(BlockImage::new(dev)))
+    }
+
+    /// Creates a filesystem on the image at `image` in memory.
+    pub fn from_memory(image: &'static [u8]) -> VfsResult<Filesystem> {
+        Self::from_source(Box::new(image))
+    }
+
+    /// Creates a filesystem on the image read from `image`.
+    pub fn from_source(image: Box<dyn ImageSource>) -> VfsResult<Filesystem> {
+        let mut buf = [0; SUPERBLOCK_SIZE];
+        image.read_exact_at(&mut buf, 0)?;
+        let sb = SuperBlock::parse(&buf)?;
+        if sb.bytes_used > image.size() {
+            warn!(
+                "squashfs: image truncated to {} of {} bytes",
+                image.size(),
+                sb.bytes_used
+            );
+            return Err(VfsError::InvalidData);
+        }
+
+        let mut fs = Self {
+            image,
+            sb,
+            ids: Vec::new(),
+            fragment_index: Vec::new(),
+            meta_cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(METADATA_CACHE_BLOCKS).unwrap(),
+            )),
+            data_cache: Mutex::new(LruCache::new(NonZeroUsize::new(DATA_CACHE_BLOCKS).unwrap())),
+            root_dir: Mutex::new(None),
+        };
+        let ids = fs.read_table(fs.sb.id_table, fs.sb.id_count as usize * 4)?;
+        fs.ids = ids.chunks_exact(4).map(|id| le32(id, 0)).collect();
+        fs.fragment_index = fs.read_index(
+            fs.sb.fragment_table,
+            fs.sb.fragment_count as usize * FRAGMENT_ENTRY_SIZE,
+        )?;
+
+        let root = fs.read_inode(fs.sb.root_inode)?;
+        let fs = Arc::new(fs);
+        *fs.root_dir.lock() = Some(DirEntry::new_dir(
+            |this| DirNode::new(SquashNode::new(fs.clone(), root, Some(this))),
+            Reference::root(),
+        ));
+        Ok(Filesystem::new(fs))
+    }
+
+    fn decompress(&self, src: &[u8], dst: &mut [u8]) -> VfsResult<usize> {
+        match self.sb.compressor {
+            Compressor::Zlib => kdecompress::zlib_decompress(src, dst),
+            Compressor::Lz4 => kdecompress::lz4_decompress(src, dst),
+        }
+        .map_err(|err| {
+            warn!("squashfs: corrupted block: {err:?}");
+            VfsError::Io
+        })
+    }
+
+    /// Reads the metadata block at `pos`.
+    fn metadata_block(&self, pos: u64) -> VfsResult<MetaBlock> {
+        if let Some(block) = self.meta_cache.lock().get(&pos) {
+            return Ok(block.clone());
+        }
+        let mut header = [0; 2];
+        self.image.read_exact_at(&mut header, pos)?;
+        let header = u16::from_le_bytes(header);
+        let len = (header & !METADATA_UNCOMPRESSED) as usize;
+        if len == 0 || len > METADATA_SIZE {
+            return Err(VfsError::InvalidData);
+        }
+        let mut raw = vec![0; len];
+        self.image.read_exact_at(&mut raw, pos + 2)?;
+        let data = if header & METADATA_UNCOMPRESSED != 0 {
+            raw
+        } else {
+            let mut data = vec![0; METADATA_SIZE];
+            let n = self.decompress(&raw, &mut data)?;
+            data.truncate(n);
+            data
+        };
+        let block = MetaBlock {
+            data: data.into(),
+            next: pos + 2 + len as u64,
+        };
+        self.meta_cache.lock().put(pos, block.clone());
+        Ok(block)
+    }
+
+    /// Reads the positions of the metadata blocks holding a table of `len`
+    /// bytes, which are stored at `start`.
+    fn read_index(&self, start: u64, len: usize) -> VfsResult<Vec<u64>> {
+        // Empty tables may be left out, with their start set to all ones.
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buf = vec![0; len.div_ceil(METADATA_SIZE) * 8];
+        self.image.read_exact_at(&mut buf, start)?;
+        Ok(buf.chunks_exact(8).map(|pos| le64(pos, 0)).collect())
+    }
+
+    /// Reads a table of `len` bytes whose index is at `start`.
+    fn read_table(&self, start: u64, len: usize) -> VfsResult<Vec<u8>> {
+        let mut table = Vec::with_capacity(len);
+        for pos in self.read_index(start, len)? {
+            let block = self.metadata_block(pos)?;
+            let n = block.data.len().min(len - table.len());
+            table.extend_from_slice(&block.data[..n]);
+        }
+        if table.len() < len {
+            return Err(VfsError::InvalidData);
+        }
+        Ok(table)
+    }
+
+    /// Returns the id at `index` of the id table.
+    fn id(&self, index: u16) -> VfsResult<u32> {
+        self.ids
+            .get(index as usize)
+            .copied()
+            .ok_or(VfsError::InvalidData)
+    }
+
+    /// Returns the position and the size field of fragment block `index`.
+    fn fragment(&self, index: u32) -> VfsResult<(u64, u32)> {
+        if index >= self.sb.fragment_count {
+            return Err(VfsError::InvalidData);
+        }
+        let offset = index as usize * FRAGMENT_ENTRY_SIZE;
+        let block = self.fragment_index[offset / METADATA_SIZE];
+        let mut reader = MetaReader::new(self, block, offset % METADATA_SIZE)?;
+        let start = reader.u64()?;
+        let size = reader.u32()?;
+        Ok((start, size))
+    }
+
+    /// Reads the data or fragment block at `pos`, whose size field is
+    /// `size`.
+    fn data_block(&self, pos: u64, size: u32) -> VfsResult<Arc<[u8]>> {
+        if let Some(block) = self.data_cache.lock().get(&pos) {
+            return Ok(block.clone());
+        }
+        let len = (size & (DATA_UNCOMPRESSED - 1)) as usize;
+        let block_size = self.sb.block_size as usize;
+        if len > block_size {
+            return Err(VfsError::InvalidData);
+        }
+        let mut raw = vec![0; len];
+        self.image.read_exact_at(&mut raw, pos)?;
+        let data: Arc<[u8]> = if size & DATA_UNCOMPRESSED != 0 {
+            raw.into()
+        } else {
+            let mut data = vec![0; block_size];
+            let n = self.decompress(&raw, &mut data)?;
+            data.truncate(n);
+            data.into()
+        };
+        self.data_cache.lock().put(pos, data.clone());
+        Ok(data)
+    }
+}
+
+impl FilesystemOps for SquashFilesystem {
+    fn name(&self) -> &str {
+        "squashfs"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root_dir.lock().clone().unwrap()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        let block_size = self.sb.block_size as u64;
+        Ok(StatFs {
+            fs_type: SQUASHFS_MAGIC,
+            block_size: block_size as _,
+            blocks: self.sb.bytes_used.div_ceil(block_size),
+            blocks_free: 0,
+            blocks_available: 0,
+
+            file_count: self.sb.inode_count as _,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: 0,
+            mount_flags: 0,
+        })
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        Ok(())
+    }
+}
+
+/// Reads the metadata stream from a position, across blocks.
+struct MetaReader<'a> {
+    fs: &'a SquashFilesystem,
+    block: MetaBlock,
+    offset: usize,
+}
+
+impl<'a> MetaReader<'a> {
+    /// Starts reading at `offset` of the metadata block at `pos`.
+    fn new(fs: &'a SquashFilesystem, pos: u64, offset: usize) -> VfsResult<Self> {
+        let block = fs.metadata_block(pos)?;
+        if offset > block.data.len() {
+            return Err(VfsError::InvalidData);
+        }
+        Ok(Self { fs, block, offset })
+    }
+
+    fn read(&mut self, mut buf: &mut [u8]) -> VfsResult<()> {
+        while !buf.is_empty() {
+            if self.offset == self.block.data.len() {
+                self.block = self.fs.metadata_block(self.block.next)?;
+                self.offset = 0;
+            }
+            let n = buf.len().min(self.block.data.len() - self.offset);
+            buf[..n].copy_from_slice(&self.block.data[self.offset..self.offset + n]);
+            self.offset += n;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) -> VfsResult<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16(&mut self) -> VfsResult<u16> {
+        let mut buf = [0; 2];
+        self.read(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> VfsResult<u32> {
+        let mut buf = [0; 4];
+        self.read(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> VfsResult<u64> {
+        let mut buf = [0; 8];
+        self.read(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
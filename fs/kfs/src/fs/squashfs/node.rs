@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Squashfs nodes.
+use alloc::{string::String, sync::Arc};
+use core::{any::Any, ops::ControlFlow, task::Context, time::Duration};
+
+use fs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, FilesystemOps,
+    Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, Reference, VfsError,
+    VfsResult, WeakDirEntry,
+};
+use kpoll::{IoEvents, Pollable};
+
+use super::{
+    SquashFilesystem,
+    dir::RawEntry,
+    inode::{DirInode, FileInode, Inode, InodeKind},
+};
+
+/// A squashfs node.
+pub struct SquashNode {
+    fs: Arc<SquashFilesystem>,
+    inode: Inode,
+    this: Option<WeakDirEntry>,
+}
+
+impl SquashNode {
+    pub(super) fn new(
+        fs: Arc<SquashFilesystem>,
+        inode: Inode,
+        this: Option<WeakDirEntry>,
+    ) -> Arc<Self> {
+        Arc::new(Self { fs, inode, this })
+    }
+
+    fn dir(&self) -> VfsResult<&DirInode> {
+        match &self.inode.kind {
+            InodeKind::Dir(dir) => Ok(dir),
+            _ => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn create_entry(&self, entry: RawEntry) -> VfsResult<DirEntry> {
+        let inode = self.fs.read_inode(entry.inode_ref)?;
+        let name = String::from_utf8(entry.name).map_err(|_| VfsError::InvalidData)?;
+        let reference = Reference::new(self.this.as_ref().and_then(WeakDirEntry::upgrade), name);
+        let node_type = inode.node_type();
+        Ok(if node_type == NodeType::Directory {
+            DirEntry::new_dir(
+                |this| DirNode::new(SquashNode::new(self.fs.clone(), inode, Some(this))),
+                reference,
+            )
+        } else {
+            DirEntry::new_file(
+                FileNode::new(SquashNode::new(self.fs.clone(), inode, None)),
+                node_type,
+                reference,
+            )
+        })
+    }
+
+    /// Copies the data of `file` at `offset` to `buf`, which does not go past
+    /// the end of the file.
+    fn read_file(&self, file: &FileInode, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+        let block_size = self.fs.sb.block_size as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = (pos / block_size) as usize;
+            let within = (pos % block_size) as usize;
+            let n = (buf.len() - done).min(block_size as usize - within);
+            let dst = &mut buf[done..done + n];
+
+            let (data, start) = if let Some(&(block_pos, size)) = file.blocks.get(index) {
+                if size == 0 {
+                    dst.fill(0);
+                    done += n;
+                    continue;
+                }
+                (self.fs.data_block(block_pos, size)?, within)
+            } else {
+                let (fragment, frag_offset) = file.fragment.ok_or(VfsError::InvalidData)?;
+                let (block_pos, size) = self.fs.fragment(fragment)?;
+                (
+                    self.fs.data_block(block_pos, size)?,
+                    frag_offset as usize + within,
+                )
+            };
+            let src = data.get(start..start + n).ok_or(VfsError::InvalidData)?;
+            dst.copy_from_slice(src);
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+impl NodeOps for SquashNode {
+    fn inode(&self) -> u64 {
+        self.inode.ino as _
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let inode = &self.inode;
+        let rdev = match inode.kind {
+            InodeKind::Device(_, rdev) => rdev,
+            _ => DeviceId::default(),
+        };
+        let mtime = Duration::from_secs(inode.mtime as u64);
+        Ok(Metadata {
+            inode: inode.ino as _,
+            device: 0,
+            nlink: inode.nlink as _,
+            mode: NodePermission::from_bits_truncate(inode.mode & 0o7777),
+            node_type: inode.node_type(),
+            uid: inode.uid,
+            gid: inode.gid,
+            size: inode.size(),
+            block_size: self.fs.sb.block_size as _,
+            blocks: inode.size().div_ceil(512),
+            rdev,
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+        })
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(self.inode.size())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::BLOCKING | NodeFlags::READ_ONLY
+    }
+}
+
+impl FileNodeOps for SquashNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let size = self.inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let buf = &mut buf[..len];
+        match &self.inode.kind {
+            InodeKind::File(file) => self.read_file(file, buf, offset)?,
+            InodeKind::Symlink(target) => {
+                let offset = offset as usize;
+                buf.copy_from_slice(&target[offset..offset + len]);
+            }
+            InodeKind::Dir(_) => return Err(VfsError::IsADirectory),
+            InodeKind::Device(..) | InodeKind::Ipc(_) => return Err(VfsError::InvalidInput),
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn append(&self, _buf: &[u8]) -> VfsResult<(usize, u64)> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn set_len(&self, _len: u64) -> VfsResult<()> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    /// Blocks are compressed: reads always go through the page cache.
+    fn direct_io_align(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl Pollable for SquashNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for SquashNode {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let dir = self.dir()?;
+        let ino = self.inode.ino as u64;
+        // The parent of the root is past the last inode.
+        let parent = match dir.parent {
+            parent if parent > self.fs.sb.inode_count => ino,
+            parent => parent as u64,
+        };
+        let mut count = 0;
+        for (i, (name, ino)) in [(".", ino), ("..", parent)].into_iter().enumerate() {
+            if offset > i as u64 {
+                continue;
+            }
+            if !sink.accept(name, ino, NodeType::Directory, i as u64 + 1) {
+                return Ok(count);
+            }
+            count += 1;
+        }
+
+        let mut next = 2;
+        let mut result = Ok(());
+        self.fs.for_each_entry(dir, |entry| {
+            next += 1;
+            if next <= offset {
+                return ControlFlow::Continue(());
+            }
+            let Ok(name) = core::str::from_utf8(&entry.name) else {
+                result = Err(VfsError::InvalidData);
+                return ControlFlow::Break(());
+            };
+            if !sink.accept(name, entry.ino as u64, entry.node_type, next) {
+                return ControlFlow::Break(());
+            }
+            count += 1;
+            ControlFlow::Continue(())
+        })?;
+        result.map(|_| count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let this = || self.this.as_ref().and_then(WeakDirEntry::upgrade);
+        match name {
+            "." => this().ok_or(VfsError::NotFound),
+            ".." => this()
+                .and_then(|entry| entry.parent())
+                .ok_or(VfsError::NotFound),
+            _ => {
+                let entry = self
+                    .fs
+                    .find_entry(self.dir()?, name.as_bytes())?
+                    .ok_or(VfsError::NotFound)?;
+                self.create_entry(entry)
+            }
+        }
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn mknod(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+        _rdev: DeviceId,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+
+    fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnlyFilesystem)
+    }
+}
@@ -2,16 +2,17 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
+//! In-memory filesystem (tmpfs).
+use alloc::{borrow::ToOwned, string::String, sync::Arc, vec::Vec};
 use core::{any::Any, borrow::Borrow, cmp::Ordering, task::Context, time::Duration};
 
 use fs_ng_vfs::{
     DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
     FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
-    Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry, XattrFlags, XattrMap,
+    path::MAX_NAME_LEN,
 };
 use hashbrown::HashMap;
-use kcore::vfs::dummy_stat_fs;
 use kpoll::{IoEvents, Pollable};
 use ksync::Mutex;
 use slab::Slab;
@@ -53,20 +54,34 @@ impl Borrow<str> for FileName {
     }
 }
 
+const TMPFS_MAGIC: u32 = 0x01021994;
+const BLOCK_SIZE: u64 = 4096;
+
 /// A simple in-memory filesystem that supports basic file operations.
 pub struct MemoryFs {
     inodes: Mutex<Slab<Arc<Inode>>>,
     root: Mutex<Option<DirEntry>>,
+    /// Maximum size of all file contents, in bytes.
+    size_limit: Option<u64>,
+    /// Blocks used by file contents.
+    used_blocks: Mutex<u64>,
 }
 
 impl MemoryFs {
-    /// Creates a new empty memory filesystem.
     /// Create a new empty in-memory filesystem (tmpfs)
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Filesystem {
+        Self::with_size_limit(None)
+    }
+
+    /// Create a new empty in-memory filesystem whose file contents may take
+    /// up to `size_limit` bytes; writing past it fails with `ENOSPC`.
+    pub fn with_size_limit(size_limit: Option<u64>) -> Filesystem {
         let fs = Arc::new(Self {
             inodes: Mutex::new(Slab::new()),
             root: Mutex::default(),
+            size_limit,
+            used_blocks: Mutex::new(0),
         });
         let root_ino = Inode::new(
             &fs,
@@ -84,6 +99,23 @@ impl MemoryFs {
     fn get(&self, ino: u64) -> Arc<Inode> {
         self.inodes.lock()[ino as usize - 1].clone()
     }
+
+    /// Accounts for a file growing or shrinking from `old_len` to `new_len`.
+    fn resize(&self, old_len: u64, new_len: u64) -> VfsResult<()> {
+        let old_blocks = old_len.div_ceil(BLOCK_SIZE);
+        let new_blocks = new_len.div_ceil(BLOCK_SIZE);
+        let mut used = self.used_blocks.lock();
+        let used_after = *used - old_blocks + new_blocks;
+        if new_blocks > old_blocks
+            && self
+                .size_limit
+                .is_some_and(|limit| used_after > limit / BLOCK_SIZE)
+        {
+            return Err(VfsError::StorageFull);
+        }
+        *used = used_after;
+        Ok(())
+    }
 }
 
 impl FilesystemOps for MemoryFs {
@@ -96,7 +128,26 @@ impl FilesystemOps for MemoryFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(0x01021994))
+        let used = *self.used_blocks.lock();
+        // Without a limit, report as much free space as is in use.
+        let blocks = self
+            .size_limit
+            .map_or((used * 2).max(100), |limit| limit / BLOCK_SIZE);
+        let free = blocks.saturating_sub(used);
+        Ok(StatFs {
+            fs_type: TMPFS_MAGIC,
+            block_size: BLOCK_SIZE as _,
+            blocks,
+            blocks_free: free,
+            blocks_available: free,
+
+            file_count: 0,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: 0,
+            mount_flags: 0,
+        })
     }
 }
 
@@ -106,6 +157,9 @@ fn release_inode(fs: &MemoryFs, inode: &Arc<Inode>, nlink: u64) {
     metadata.nlink -= nlink;
     if metadata.nlink == 0 && Arc::strong_count(inode) == 2 {
         inodes.remove(metadata.inode as usize - 1);
+        if let NodeContent::File(file) = &inode.content {
+            let _ = fs.resize(*file.length.lock(), 0);
+        }
     }
 }
 
@@ -133,6 +187,7 @@ struct Inode {
     ino: u64,
     metadata: Mutex<Metadata>,
     content: NodeContent,
+    xattrs: XattrMap,
 }
 
 impl Inode {
@@ -170,6 +225,7 @@ impl Inode {
             ino,
             metadata: Mutex::new(metadata),
             content,
+            xattrs: XattrMap::new(),
         });
         entry.insert(result.clone());
         drop(inodes);
@@ -306,6 +362,22 @@ impl NodeOps for MemoryNode {
     fn flags(&self) -> NodeFlags {
         NodeFlags::ALWAYS_CACHE
     }
+
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        self.inode.xattrs.get(name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> VfsResult<()> {
+        self.inode.xattrs.set(name, value, flags)
+    }
+
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        Ok(self.inode.xattrs.list())
+    }
+
+    fn remove_xattr(&self, name: &str) -> VfsResult<()> {
+        self.inode.xattrs.remove(name)
+    }
 }
 
 impl FileNodeOps for MemoryNode {
@@ -329,13 +401,17 @@ impl FileNodeOps for MemoryNode {
     }
 
     fn set_len(&self, len: u64) -> VfsResult<()> {
-        *self.inode.as_file()?.length.lock() = len;
+        let mut length = self.inode.as_file()?.length.lock();
+        self.fs.resize(*length, len)?;
+        *length = len;
         Ok(())
     }
 
     fn set_symlink(&self, target: &str) -> VfsResult<()> {
         let file = self.inode.as_file()?;
-        *file.length.lock() = target.len() as u64;
+        let mut length = file.length.lock();
+        self.fs.resize(*length, target.len() as u64)?;
+        *length = target.len() as u64;
         *file.symlink.lock() = Some(target.to_owned());
         Ok(())
     }
@@ -400,6 +476,25 @@ impl DirNodeOps for MemoryNode {
         self.new_entry(name, node_type, inode)
     }
 
+    fn mknod(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<DirEntry> {
+        let dir = self.inode.as_dir()?;
+        let mut entries = dir.entries.lock();
+
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let inode = Inode::new(&self.fs, Some(self.inode.ino), node_type, permission);
+        inode.metadata.lock().rdev = rdev;
+        entries.insert(name.into(), InodeRef::new(self.fs.clone(), inode.ino));
+        self.new_entry(name, node_type, inode)
+    }
+
     fn link(&self, name: &str, target: &DirEntry) -> VfsResult<DirEntry> {
         let dir = self.inode.as_dir()?;
         let mut entries = dir.entries.lock();
@@ -9,12 +9,12 @@
 use alloc::{collections::vec_deque::VecDeque, string::String, vec::Vec};
 
 use fs_ng_vfs::{
-    Location, Metadata, NodePermission, NodeType, VfsResult,
+    DeviceId, Location, Metadata, NodePermission, NodeType, VfsResult,
     path::{Path, PathBuf},
 };
 use kio::{Read, Write};
 
-use crate::{File, PathResolver, ReadDir, WorkingContext};
+use crate::{File, PathResolver, ReadDir, ResolveFlags, WorkingContext};
 
 /// Filesystem operations - combines path resolution and working context
 ///
@@ -64,6 +64,30 @@ impl FsOperations {
         self.context.chdir(dir)
     }
 
+    /// Changes the root directory
+    #[inline]
+    pub fn set_root_dir(&mut self, root: Location) -> VfsResult<()> {
+        self.context.set_root(root)
+    }
+
+    /// Returns the file mode creation mask
+    #[inline]
+    pub fn umask(&self) -> u32 {
+        self.context.umask()
+    }
+
+    /// Sets the file mode creation mask, returning the previous one
+    #[inline]
+    pub fn set_umask(&mut self, mask: u32) -> u32 {
+        self.context.set_umask(mask)
+    }
+
+    /// Clears the bits of `mode` set in the umask
+    #[inline]
+    pub fn apply_umask(&self, mode: NodePermission) -> NodePermission {
+        self.context.apply_umask(mode)
+    }
+
     /// Creates a new context with a different current working directory
     #[inline]
     pub fn with_current_dir(&self, current_dir: Location) -> VfsResult<Self> {
@@ -78,15 +102,60 @@ impl FsOperations {
     /// Resolves a path starting from current_dir
     #[inline]
     pub fn resolve(&self, path: impl AsRef<Path>) -> VfsResult<Location> {
-        self.resolver
-            .resolve(self.context.cwd(), path.as_ref(), true)
+        self.resolve_with_flags(path, ResolveFlags::empty())
     }
 
-    /// Resolves a path without following symlinks
+    /// Resolves a path without following a symlink in the final component,
+    /// unless the path ends with a slash
     #[inline]
     pub fn resolve_no_follow(&self, path: impl AsRef<Path>) -> VfsResult<Location> {
+        self.resolve_with_flags(
+            path,
+            ResolveFlags::NO_FOLLOW | ResolveFlags::FOLLOW_TRAILING_SLASH,
+        )
+    }
+
+    /// Resolves a path starting from current_dir, treating a symlink in the
+    /// final component as `flags` say
+    #[inline]
+    pub fn resolve_with_flags(
+        &self,
+        path: impl AsRef<Path>,
+        flags: ResolveFlags,
+    ) -> VfsResult<Location> {
+        self.resolver.resolve(
+            self.context.root(),
+            self.context.cwd(),
+            path.as_ref(),
+            flags,
+        )
+    }
+
+    /// Resolves a path to its parent directory and entry name
+    #[inline]
+    pub fn resolve_parent(&self, path: &Path) -> VfsResult<(Location, String)> {
         self.resolver
-            .resolve(self.context.cwd(), path.as_ref(), false)
+            .resolve_parent(self.context.root(), self.context.cwd(), path)
+    }
+
+    /// Resolves a path that is expected not to exist
+    #[inline]
+    pub fn resolve_nonexistent<'a>(&self, path: &'a Path) -> VfsResult<(Location, &'a str)> {
+        self.resolver
+            .resolve_nonexistent(self.context.root(), self.context.cwd(), path)
+    }
+
+    /// Resolves a path to create anything but a directory at
+    ///
+    /// With a trailing slash the path names a directory, so this fails with
+    /// `EEXIST` if the entry exists and `ENOENT` if it does not.
+    pub fn resolve_new_file<'a>(&self, path: &'a Path) -> VfsResult<(Location, &'a str)> {
+        let (dir, name) = self.resolve_nonexistent(path)?;
+        if path.has_trailing_slash() {
+            dir.lookup_no_follow(name)?;
+            return Err(fs_ng_vfs::VfsError::AlreadyExists);
+        }
+        Ok((dir, name))
     }
 
     // ========== File Operations ==========
@@ -139,7 +208,7 @@ impl FsOperations {
 
     /// Removes a file from the filesystem
     pub fn remove_file(&self, path: impl AsRef<Path>) -> VfsResult<()> {
-        let entry = self.resolve_no_follow(path.as_ref())?;
+        let entry = self.resolve_with_flags(path, ResolveFlags::NO_FOLLOW)?;
         entry
             .parent()
             .ok_or(fs_ng_vfs::VfsError::IsADirectory)?
@@ -148,7 +217,7 @@ impl FsOperations {
 
     /// Removes a directory from the filesystem
     pub fn remove_dir(&self, path: impl AsRef<Path>) -> VfsResult<()> {
-        let entry = self.resolve_no_follow(path.as_ref())?;
+        let entry = self.resolve_with_flags(path, ResolveFlags::NO_FOLLOW)?;
         entry
             .parent()
             .ok_or(fs_ng_vfs::VfsError::ResourceBusy)?
@@ -157,21 +226,46 @@ impl FsOperations {
 
     /// Renames a file or directory to a new name
     pub fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> VfsResult<()> {
-        let (src_dir, src_name) = self
-            .resolver
-            .resolve_parent(self.context.cwd(), from.as_ref())?;
-        let (dst_dir, dst_name) = self
-            .resolver
-            .resolve_parent(self.context.cwd(), to.as_ref())?;
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (src_dir, src_name) = self.resolve_parent(from)?;
+        let (dst_dir, dst_name) = self.resolve_parent(to)?;
+        // With a trailing slash on either path, only a directory may move.
+        if from.has_trailing_slash() || to.has_trailing_slash() {
+            src_dir.lookup_no_follow(&src_name)?.check_is_dir()?;
+        }
         src_dir.rename(&src_name, &dst_dir, &dst_name)
     }
 
     /// Creates a new, empty directory at the provided path
+    ///
+    /// The umask is applied to `mode`.
     pub fn create_dir(&self, path: impl AsRef<Path>, mode: NodePermission) -> VfsResult<Location> {
-        let (dir, name) = self
-            .resolver
-            .resolve_nonexistent(self.context.cwd(), path.as_ref())?;
-        dir.create(name, NodeType::Directory, mode)
+        let (dir, name) = self.resolve_nonexistent(path.as_ref())?;
+        dir.create(name, NodeType::Directory, self.apply_umask(mode))
+    }
+
+    /// Creates a filesystem node of type `node_type` at the provided path.
+    ///
+    /// Character and block devices are created with device number `rdev`,
+    /// which is ignored for other node types. The umask is applied to `mode`.
+    pub fn mknod(
+        &self,
+        path: impl AsRef<Path>,
+        node_type: NodeType,
+        mode: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<Location> {
+        let (dir, name) = self.resolve_new_file(path.as_ref())?;
+        let mode = self.apply_umask(mode);
+        match node_type {
+            NodeType::CharacterDevice | NodeType::BlockDevice => {
+                dir.mknod(name, node_type, mode, rdev)
+            }
+            NodeType::RegularFile | NodeType::Fifo | NodeType::Socket => {
+                dir.create(name, node_type, mode)
+            }
+            _ => Err(fs_ng_vfs::VfsError::InvalidInput),
+        }
     }
 
     /// Creates a new hard link on the filesystem
@@ -181,9 +275,7 @@ impl FsOperations {
         new_path: impl AsRef<Path>,
     ) -> VfsResult<Location> {
         let old = self.resolve(old_path.as_ref())?;
-        let (new_dir, new_name) = self
-            .resolver
-            .resolve_nonexistent(self.context.cwd(), new_path.as_ref())?;
+        let (new_dir, new_name) = self.resolve_new_file(new_path.as_ref())?;
         new_dir.link(new_name, &old)
     }
 
@@ -193,9 +285,7 @@ impl FsOperations {
         target: impl AsRef<str>,
         link_path: impl AsRef<Path>,
     ) -> VfsResult<Location> {
-        let (dir, name) = self
-            .resolver
-            .resolve_nonexistent(self.context.cwd(), link_path.as_ref())?;
+        let (dir, name) = self.resolve_new_file(link_path.as_ref())?;
         if dir.lookup_no_follow(name).is_ok() {
             return Err(fs_ng_vfs::VfsError::AlreadyExists);
         }
@@ -0,0 +1,601 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Filesystem-level encryption of directory trees.
+//!
+//! A lite version of Linux fscrypt with v2 policies. An empty directory is
+//! given a policy naming a master key; everything created below it inherits
+//! the policy and gets an encryption context with a fresh nonce, which the
+//! filesystem stores with the inode (see
+//! [`NodeOps::get_encryption_context`](fs_ng_vfs::NodeOps::get_encryption_context)).
+//!
+//! Keys are derived as in Linux: the master key is identified by
+//! HKDF-SHA512 with the `fscrypt\0` prefix and context 1, and every inode
+//! encrypts with a key derived with context 2 and its nonce.
+//!
+//! - File contents are encrypted with AES-256-XTS in page-sized data units,
+//!   using the page index as the tweak. This happens in the page cache, so
+//!   encrypted files are never opened for direct I/O. The last data unit is
+//!   encrypted at its length with ciphertext stealing; if it is shorter than
+//!   an AES block, it is XORed with the encryption of a zero block instead,
+//!   so rewriting such a tail reveals the XOR of the old and new bytes.
+//! - Entry names are padded and encrypted with AES-256-CTS-CBC and a zero IV,
+//!   then stored base64url-encoded. Names therefore cannot be longer than 191
+//!   bytes. Symlink targets are not encrypted.
+//!
+//! Without the key, encrypted files cannot be opened (`ENOKEY`) and names are
+//! listed in their stored form, which can still be used to look up, rename
+//! or delete entries. Removing a key writes back and drops the plaintext
+//! pages of the files using it.
+use alloc::{
+    borrow::ToOwned,
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use fs_ng_vfs::{
+    DirEntry, DirNodeOps, FileNode, Location, NameCipher, NodeType, VfsError, VfsResult,
+    WeakDirEntry, set_name_cipher_resolver,
+};
+use kcrypto::{Aes256Cts, Aes256Xts, Hkdf, Sha512, zeroize};
+use kerrno::LinuxError;
+use ksync::Mutex;
+
+/// Version of the supported policy format.
+pub const POLICY_V2: u8 = 2;
+/// AES-256-XTS, the supported contents encryption mode.
+pub const MODE_AES_256_XTS: u8 = 1;
+/// AES-256-CTS-CBC, the supported filenames encryption mode.
+pub const MODE_AES_256_CTS: u8 = 4;
+/// Mask of the policy flags selecting the filename padding.
+pub const POLICY_FLAGS_PAD_MASK: u8 = 0x03;
+
+/// Size of a master key identifier.
+pub const KEY_IDENTIFIER_SIZE: usize = 16;
+/// Minimum size of a master key.
+pub const MIN_KEY_SIZE: usize = 16;
+/// Maximum size of a master key.
+pub const MAX_KEY_SIZE: usize = 64;
+
+/// Identifier of a master key.
+pub type KeyIdentifier = [u8; KEY_IDENTIFIER_SIZE];
+
+const NONCE_SIZE: usize = 16;
+const DATA_UNIT_SIZE: usize = 4096;
+const LOG2_DATA_UNIT_SIZE: u8 = 12;
+
+const HKDF_PREFIX: &[u8] = b"fscrypt\0";
+const HKDF_CONTEXT_KEY_IDENTIFIER: u8 = 1;
+const HKDF_CONTEXT_PER_FILE_ENC_KEY: u8 = 2;
+
+/// Longest ciphertext of a name; its base64url encoding has 255 characters.
+const MAX_NAME_CIPHERTEXT: usize = 191;
+
+fn enokey() -> VfsError {
+    VfsError::from(LinuxError::ENOKEY)
+}
+
+fn enodata() -> VfsError {
+    VfsError::from(LinuxError::ENODATA)
+}
+
+/// An encryption policy, laid out like Linux `struct fscrypt_policy_v2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionPolicy {
+    /// Contents encryption mode.
+    pub contents_mode: u8,
+    /// Filenames encryption mode.
+    pub filenames_mode: u8,
+    /// Policy flags.
+    pub flags: u8,
+    /// Log2 of the data unit size, or 0 for the default.
+    pub log2_data_unit_size: u8,
+    /// Identifier of the master key.
+    pub master_key_identifier: KeyIdentifier,
+}
+
+impl EncryptionPolicy {
+    /// Size of the policy in bytes.
+    pub const SIZE: usize = 24;
+
+    /// Creates a policy with the supported modes and the default padding.
+    pub fn new(master_key_identifier: KeyIdentifier) -> Self {
+        Self {
+            contents_mode: MODE_AES_256_XTS,
+            filenames_mode: MODE_AES_256_CTS,
+            flags: POLICY_FLAGS_PAD_MASK,
+            log2_data_unit_size: 0,
+            master_key_identifier,
+        }
+    }
+
+    /// Parses a policy, failing with `EINVAL` if it is not supported.
+    pub fn from_bytes(bytes: &[u8]) -> VfsResult<Self> {
+        if bytes.len() < Self::SIZE || bytes[0] != POLICY_V2 || bytes[5..8] != [0; 3] {
+            return Err(VfsError::InvalidInput);
+        }
+        let policy = Self {
+            contents_mode: bytes[1],
+            filenames_mode: bytes[2],
+            flags: bytes[3],
+            log2_data_unit_size: bytes[4],
+            master_key_identifier: bytes[8..Self::SIZE].try_into().unwrap(),
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Serializes the policy.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = POLICY_V2;
+        bytes[1] = self.contents_mode;
+        bytes[2] = self.filenames_mode;
+        bytes[3] = self.flags;
+        bytes[4] = self.log2_data_unit_size;
+        bytes[8..].copy_from_slice(&self.master_key_identifier);
+        bytes
+    }
+
+    fn validate(&self) -> VfsResult<()> {
+        let supported = self.contents_mode == MODE_AES_256_XTS
+            && self.filenames_mode == MODE_AES_256_CTS
+            && self.flags & !POLICY_FLAGS_PAD_MASK == 0
+            && matches!(self.log2_data_unit_size, 0 | LOG2_DATA_UNIT_SIZE);
+        if supported {
+            Ok(())
+        } else {
+            Err(VfsError::InvalidInput)
+        }
+    }
+
+    fn name_padding(&self) -> usize {
+        4 << (self.flags & POLICY_FLAGS_PAD_MASK)
+    }
+}
+
+/// Encryption context of an inode, laid out like Linux
+/// `struct fscrypt_context_v2`.
+#[derive(Clone, Copy)]
+struct Context {
+    policy: EncryptionPolicy,
+    nonce: [u8; NONCE_SIZE],
+}
+
+impl Context {
+    const SIZE: usize = EncryptionPolicy::SIZE + NONCE_SIZE;
+
+    fn new(policy: EncryptionPolicy) -> Self {
+        Self {
+            policy,
+            nonce: new_nonce(),
+        }
+    }
+
+    /// Parses the result of `get_encryption_context`, returning `None` for
+    /// nodes that are not encrypted.
+    fn load(context: VfsResult<Vec<u8>>) -> VfsResult<Option<Self>> {
+        let bytes = match context {
+            Ok(bytes) => bytes,
+            Err(VfsError::OperationNotSupported) => return Ok(None),
+            Err(err) if err == enodata() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if bytes.len() != Self::SIZE {
+            return Err(VfsError::InvalidData);
+        }
+        let policy =
+            EncryptionPolicy::from_bytes(&bytes[..EncryptionPolicy::SIZE]).map_err(|_| {
+                warn!("fscrypt: unsupported encryption context");
+                VfsError::InvalidData
+            })?;
+        Ok(Some(Self {
+            policy,
+            nonce: bytes[EncryptionPolicy::SIZE..].try_into().unwrap(),
+        }))
+    }
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..EncryptionPolicy::SIZE].copy_from_slice(&self.policy.to_bytes());
+        bytes[EncryptionPolicy::SIZE..].copy_from_slice(&self.nonce);
+        bytes
+    }
+}
+
+/// Returns a nonce for a new inode.
+///
+/// Nonces are stored in the clear and only need to be unique, which mixing
+/// a counter with the boot and wall clocks ensures.
+fn new_nonce() -> [u8; NONCE_SIZE] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = Sha512::new();
+    hasher.update(&COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hasher.update(&khal::time::monotonic_time_nanos().to_le_bytes());
+    hasher.update(&khal::time::wall_time_nanos().to_le_bytes());
+    hasher.finalize()[..NONCE_SIZE].try_into().unwrap()
+}
+
+struct MasterKey {
+    hkdf: Hkdf,
+}
+
+impl MasterKey {
+    fn identifier(&self) -> KeyIdentifier {
+        let mut identifier = [0; KEY_IDENTIFIER_SIZE];
+        self.hkdf.expand(
+            &[HKDF_PREFIX, &[HKDF_CONTEXT_KEY_IDENTIFIER]],
+            &mut identifier,
+        );
+        identifier
+    }
+
+    fn derive_file_key(&self, nonce: &[u8; NONCE_SIZE], key: &mut [u8]) {
+        self.hkdf
+            .expand(&[HKDF_PREFIX, &[HKDF_CONTEXT_PER_FILE_ENC_KEY], nonce], key);
+    }
+}
+
+static KEYRING: Mutex<BTreeMap<KeyIdentifier, Arc<MasterKey>>> = Mutex::new(BTreeMap::new());
+
+/// Bumped whenever a key is added or removed.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn master_key(identifier: &KeyIdentifier) -> VfsResult<Arc<MasterKey>> {
+    KEYRING.lock().get(identifier).cloned().ok_or_else(enokey)
+}
+
+/// Adds the master key `raw` to the keyring and returns its identifier.
+///
+/// Adding a key that is already present succeeds.
+pub fn add_key(raw: &[u8]) -> VfsResult<KeyIdentifier> {
+    if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&raw.len()) {
+        return Err(VfsError::InvalidInput);
+    }
+    let key = MasterKey {
+        hkdf: Hkdf::extract(&[], raw),
+    };
+    let identifier = key.identifier();
+    KEYRING
+        .lock()
+        .entry(identifier)
+        .or_insert_with(|| Arc::new(key));
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    Ok(identifier)
+}
+
+/// Returns whether the key `identifier` is in the keyring.
+pub fn key_present(identifier: &KeyIdentifier) -> bool {
+    KEYRING.lock().contains_key(identifier)
+}
+
+/// Removes the key `identifier` from the keyring.
+///
+/// The cached pages of files encrypted with the key are written back and
+/// dropped first. Fails with `ENOKEY` if the key is not present.
+pub fn remove_key(identifier: &KeyIdentifier) -> VfsResult<()> {
+    if !key_present(identifier) {
+        return Err(enokey());
+    }
+    let caches = {
+        let mut caches = PLAINTEXT_CACHES.lock();
+        caches.retain(|cache| cache.cache.strong_count() > 0);
+        caches
+            .iter()
+            .filter(|cache| cache.identifier == *identifier)
+            .filter_map(|cache| Some((cache.entry.upgrade()?, cache.cache.upgrade()?)))
+            .collect::<Vec<_>>()
+    };
+    for (entry, cache) in caches {
+        if let Err(err) = entry.as_file().and_then(|file| cache.evict(file)) {
+            warn!("fscrypt: failed to evict {:?}: {err:?}", entry.name());
+        }
+    }
+    KEYRING.lock().remove(identifier);
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// A key derived from an encryption context, derived again after the
+/// keyring changed.
+struct DerivedKey<T> {
+    cached: Mutex<Option<(u64, Arc<T>)>>,
+}
+
+impl<T> DerivedKey<T> {
+    fn new() -> Self {
+        Self {
+            cached: Mutex::new(None),
+        }
+    }
+
+    fn get<const N: usize>(
+        &self,
+        context: &Context,
+        make: impl FnOnce(&[u8; N]) -> T,
+    ) -> VfsResult<Arc<T>> {
+        let generation = GENERATION.load(Ordering::Acquire);
+        let mut cached = self.cached.lock();
+        if let Some((cached_generation, key)) = &*cached
+            && *cached_generation == generation
+        {
+            return Ok(key.clone());
+        }
+        *cached = None;
+
+        let master = master_key(&context.policy.master_key_identifier)?;
+        let mut raw = [0u8; N];
+        master.derive_file_key(&context.nonce, &mut raw);
+        let key = Arc::new(make(&raw));
+        zeroize(&mut raw);
+        *cached = Some((generation, key.clone()));
+        Ok(key)
+    }
+}
+
+/// Holder of plaintext pages that must be dropped when their key is removed.
+pub(crate) trait PlaintextCache: Send + Sync {
+    /// Writes back and drops all cached pages of `file`.
+    fn evict(&self, file: &FileNode) -> VfsResult<()>;
+}
+
+struct PlaintextCacheEntry {
+    identifier: KeyIdentifier,
+    entry: WeakDirEntry,
+    cache: Weak<dyn PlaintextCache>,
+}
+
+static PLAINTEXT_CACHES: Mutex<Vec<PlaintextCacheEntry>> = Mutex::new(Vec::new());
+
+/// Contents encryption of a regular file.
+pub(crate) struct FileCrypt {
+    context: Context,
+    key: DerivedKey<Aes256Xts>,
+}
+
+impl FileCrypt {
+    /// Returns the contents encryption of the file at `loc`, or `None` if it
+    /// is not encrypted.
+    pub(crate) fn for_location(loc: &Location) -> Option<Arc<Self>> {
+        if loc.node_type() != NodeType::RegularFile {
+            return None;
+        }
+        match Context::load(loc.get_encryption_context()) {
+            Ok(context) => context.map(|context| {
+                Arc::new(Self {
+                    context,
+                    key: DerivedKey::new(),
+                })
+            }),
+            Err(err) => {
+                warn!("fscrypt: failed to read encryption context: {err:?}");
+                None
+            }
+        }
+    }
+
+    /// Registers `cache`, holding the plaintext of `entry`, to be evicted
+    /// when the key is removed.
+    pub(crate) fn register_cache(&self, entry: &DirEntry, cache: Weak<dyn PlaintextCache>) {
+        let mut caches = PLAINTEXT_CACHES.lock();
+        caches.retain(|cache| cache.cache.strong_count() > 0);
+        caches.push(PlaintextCacheEntry {
+            identifier: self.context.policy.master_key_identifier,
+            entry: entry.downgrade(),
+            cache,
+        });
+    }
+
+    fn crypt_unit(&self, index: u32, data: &mut [u8], encrypt: bool) -> VfsResult<()> {
+        debug_assert!(data.len() <= DATA_UNIT_SIZE);
+        if data.is_empty() {
+            return Ok(());
+        }
+        let key = self
+            .key
+            .get(&self.context, |raw: &[u8; Aes256Xts::KEY_SIZE]| {
+                Aes256Xts::new(raw)
+            })?;
+        let mut iv = [0u8; 16];
+        iv[..8].copy_from_slice(&(index as u64).to_le_bytes());
+        if data.len() >= 16 {
+            if encrypt {
+                key.encrypt(&iv, data);
+            } else {
+                key.decrypt(&iv, data);
+            }
+        } else {
+            let mut stream = [0u8; 16];
+            key.encrypt(&iv, &mut stream);
+            for (byte, s) in data.iter_mut().zip(stream) {
+                *byte ^= s;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encrypts the data unit (page) `index` in place. `data` is shorter than
+    /// a page only for the last unit of the file.
+    pub(crate) fn encrypt_unit(&self, index: u32, data: &mut [u8]) -> VfsResult<()> {
+        self.crypt_unit(index, data, true)
+    }
+
+    /// Decrypts the data unit (page) `index` in place.
+    pub(crate) fn decrypt_unit(&self, index: u32, data: &mut [u8]) -> VfsResult<()> {
+        self.crypt_unit(index, data, false)
+    }
+}
+
+/// Checks that the node at `loc` can be opened, and returns whether it is an
+/// encrypted regular file.
+///
+/// Fails with `ENOKEY` if the file is encrypted and its key is missing.
+pub(crate) fn prepare_open(loc: &Location) -> VfsResult<bool> {
+    if loc.node_type() != NodeType::RegularFile {
+        return Ok(false);
+    }
+    match Context::load(loc.get_encryption_context())? {
+        Some(context) => {
+            master_key(&context.policy.master_key_identifier)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(BASE64_ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3f] as char);
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            out.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Name encryption of a directory.
+struct DirCipher {
+    context: Context,
+    key: DerivedKey<Aes256Cts>,
+}
+
+impl DirCipher {
+    fn key(&self) -> VfsResult<Arc<Aes256Cts>> {
+        self.key
+            .get(&self.context, |raw: &[u8; Aes256Cts::KEY_SIZE]| {
+                Aes256Cts::new(raw)
+            })
+    }
+}
+
+impl NameCipher for DirCipher {
+    fn encrypt_name(&self, name: &str, create: bool) -> VfsResult<String> {
+        let key = match self.key() {
+            Ok(key) => key,
+            Err(err) if create => return Err(err),
+            // Without the key, entries are found by their stored names.
+            Err(_) => {
+                return match base64_decode(name) {
+                    Some(data) if (16..=MAX_NAME_CIPHERTEXT).contains(&data.len()) => {
+                        Ok(name.to_owned())
+                    }
+                    _ => Err(VfsError::NotFound),
+                };
+            }
+        };
+        if name.len() > MAX_NAME_CIPHERTEXT {
+            return Err(VfsError::NameTooLong);
+        }
+        let len = name
+            .len()
+            .next_multiple_of(self.context.policy.name_padding())
+            .clamp(16, MAX_NAME_CIPHERTEXT);
+        let mut data = vec![0u8; len];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        key.encrypt(&[0; 16], &mut data);
+        Ok(base64_encode(&data))
+    }
+
+    fn decrypt_name(&self, stored: &str) -> String {
+        let Ok(key) = self.key() else {
+            return stored.to_owned();
+        };
+        let Some(mut data) = base64_decode(stored).filter(|data| data.len() >= 16) else {
+            return stored.to_owned();
+        };
+        key.decrypt(&[0; 16], &mut data);
+        let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        data.truncate(len);
+        String::from_utf8(data).unwrap_or_else(|_| stored.to_owned())
+    }
+
+    fn generation(&self) -> u64 {
+        GENERATION.load(Ordering::Acquire)
+    }
+
+    fn init_child(&self, entry: &DirEntry) -> VfsResult<()> {
+        entry.set_encryption_context(&Context::new(self.context.policy).to_bytes())
+    }
+
+    fn check_link(&self, entry: &DirEntry) -> VfsResult<()> {
+        match Context::load(entry.get_encryption_context())? {
+            Some(context) if context.policy == self.context.policy => Ok(()),
+            _ => Err(VfsError::CrossesDevices),
+        }
+    }
+}
+
+fn resolve_name_cipher(dir: &dyn DirNodeOps) -> VfsResult<Option<Arc<dyn NameCipher>>> {
+    Ok(Context::load(dir.get_encryption_context())?.map(|context| {
+        Arc::new(DirCipher {
+            context,
+            key: DerivedKey::new(),
+        }) as _
+    }))
+}
+
+/// Sets the encryption policy of the empty directory `loc`.
+///
+/// Setting the policy a directory already has succeeds; a different one
+/// fails with `EEXIST`. The key must have been added.
+pub fn set_policy(loc: &Location, policy: &EncryptionPolicy) -> VfsResult<()> {
+    policy.validate()?;
+    let dir = loc.entry().as_dir()?;
+    if let Some(context) = Context::load(loc.get_encryption_context())? {
+        return if context.policy == *policy {
+            Ok(())
+        } else {
+            Err(VfsError::AlreadyExists)
+        };
+    }
+    master_key(&policy.master_key_identifier)?;
+    if dir.has_children()? {
+        return Err(VfsError::DirectoryNotEmpty);
+    }
+    loc.set_encryption_context(&Context::new(*policy).to_bytes())?;
+    dir.reset_name_cipher();
+    Ok(())
+}
+
+/// Returns the encryption policy of `loc`, failing with `ENODATA` if it is
+/// not encrypted.
+pub fn get_policy(loc: &Location) -> VfsResult<EncryptionPolicy> {
+    Context::load(loc.get_encryption_context())?
+        .map(|context| context.policy)
+        .ok_or_else(enodata)
+}
+
+/// Enables encrypted directories.
+pub fn init() {
+    set_name_cipher_resolver(resolve_name_cipher);
+}
@@ -0,0 +1,775 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Random access to the members of zip, ar and `newc` cpio archives.
+//!
+//! An [`ArchiveReader`] indexes an archive once when opened, then reads
+//! members one at a time without extracting the others. Stored members are
+//! read from the archive as the stream advances; deflated zip members are
+//! inflated whole when opened, as the shared decompressor is one-shot.
+//!
+//! A [`Manifest`] of SHA-256 digests may be set on a reader, after which
+//! only the members it lists can be read, and only with matching content.
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{fmt, str};
+
+use fs_ng_vfs::{VfsError, VfsResult, path::Path};
+use kcrypto::Sha256;
+use kio::{SeekFrom, prelude::*};
+
+use super::{FileStream, FsContext, StreamMode};
+use crate::cpio;
+
+/// Largest size of a member that is inflated in memory when opened.
+pub const MAX_INFLATED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Size of the chunks members are hashed in.
+const HASH_CHUNK: usize = 16 * 1024;
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const AR_HEADER_LEN: u64 = 60;
+
+const ZIP_LOCAL_SIG: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_SIG: u32 = 0x0201_4b50;
+const ZIP_EOCD_SIG: u32 = 0x0605_4b50;
+const ZIP_LOCAL_LEN: usize = 30;
+const ZIP_CENTRAL_LEN: usize = 46;
+const ZIP_EOCD_LEN: usize = 22;
+/// Set in the flags of an encrypted zip member.
+const ZIP_FLAG_ENCRYPTED: u16 = 1 << 0;
+/// DEFLATE cannot expand data by more than this ratio.
+const DEFLATE_MAX_RATIO: u64 = 1032;
+
+/// Where the bytes of an archive come from.
+pub trait ArchiveSource: Send + Sync {
+    /// Returns the size of the archive in bytes.
+    fn size(&self) -> u64;
+
+    /// Fills `buf` with the bytes at `offset` of the archive.
+    ///
+    /// Fails with `EIO` if the range is not within the archive.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()>;
+}
+
+fn check_range(size: u64, len: usize, offset: u64) -> VfsResult<()> {
+    match offset.checked_add(len as u64) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(VfsError::Io),
+    }
+}
+
+/// An archive in memory, such as one loaded by the bootloader.
+impl ArchiveSource for &'static [u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+        check_range(self.len() as u64, buf.len(), offset)?;
+        let offset = offset as usize;
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+        Ok(())
+    }
+}
+
+/// An archive read into memory.
+impl ArchiveSource for Vec<u8> {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+        check_range(self.len() as u64, buf.len(), offset)?;
+        let offset = offset as usize;
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+        Ok(())
+    }
+}
+
+/// An archive in a file, which must not change while it is read.
+impl ArchiveSource for FileStream {
+    fn size(&self) -> u64 {
+        self.location().len().unwrap_or(0)
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> VfsResult<()> {
+        while !buf.is_empty() {
+            let n = self.read_at(&mut *buf, offset)?;
+            if n == 0 {
+                return Err(VfsError::Io);
+            }
+            buf = &mut buf[n..];
+            offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// An error reading an archive, with the member it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveError {
+    /// Name of the offending member, or `#` and its index in the archive if
+    /// its name could not be read. `None` for errors about the whole archive.
+    pub member: Option<String>,
+    /// What went wrong.
+    pub error: VfsError,
+}
+
+impl ArchiveError {
+    fn new(member: impl ToString, error: VfsError) -> Self {
+        Self {
+            member: Some(member.to_string()),
+            error,
+        }
+    }
+}
+
+/// Returns the error of the malformed `member`.
+fn invalid(member: &str) -> ArchiveError {
+    ArchiveError::new(member, VfsError::InvalidData)
+}
+
+impl From<VfsError> for ArchiveError {
+    fn from(error: VfsError) -> Self {
+        Self {
+            member: None,
+            error,
+        }
+    }
+}
+
+impl From<ArchiveError> for VfsError {
+    fn from(err: ArchiveError) -> Self {
+        err.error
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.member {
+            Some(member) => write!(f, "member {member}: {}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Result type of archive operations.
+pub type ArchiveResult<T> = Result<T, ArchiveError>;
+
+/// Formats of archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Zip, with members stored or deflated.
+    Zip,
+    /// Unix `ar`, with GNU or BSD long names.
+    Ar,
+    /// `newc` cpio, the format of Linux initramfs images.
+    Cpio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Stored,
+    Deflate,
+}
+
+/// A regular file in an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    /// Path of the member in the archive.
+    pub name: String,
+    /// Size of the content of the member.
+    pub size: u64,
+    compression: Compression,
+    /// Size of the member in the archive.
+    stored_size: u64,
+    /// Offset of the member data in the archive.
+    offset: u64,
+    /// CRC-32 of the content, for zip members.
+    crc32: Option<u32>,
+}
+
+impl ArchiveMember {
+    /// Returns whether the member is compressed in the archive.
+    pub fn is_compressed(&self) -> bool {
+        self.compression != Compression::Stored
+    }
+}
+
+/// SHA-256 digests of archive members, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    digests: BTreeMap<String, [u8; 32]>,
+}
+
+impl Manifest {
+    /// Creates an empty manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the output of `sha256sum`: one line per member, with the
+    /// digest in hex, then a space and a space or `*`, then the name.
+    pub fn parse(text: &str) -> VfsResult<Self> {
+        let mut manifest = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (hex, name) = line.split_at_checked(64).ok_or(VfsError::InvalidData)?;
+            let name = name
+                .strip_prefix("  ")
+                .or_else(|| name.strip_prefix(" *"))
+                .ok_or(VfsError::InvalidData)?;
+            let mut digest = [0; 32];
+            for (byte, i) in digest.iter_mut().zip((0..64).step_by(2)) {
+                *byte = hex
+                    .get(i..i + 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or(VfsError::InvalidData)?;
+            }
+            manifest.insert(name, digest);
+        }
+        Ok(manifest)
+    }
+
+    /// Sets the digest of the member `name`.
+    pub fn insert(&mut self, name: impl Into<String>, digest: [u8; 32]) {
+        self.digests.insert(name.into(), digest);
+    }
+
+    /// Returns the digest of the member `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8; 32]> {
+        self.digests.get(name)
+    }
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(buf[pos..pos + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+/// Reads the `len` bytes at `offset` of `source`, failing with `EINVAL`
+/// like for a malformed archive if they are not within it.
+fn read_vec(source: &dyn ArchiveSource, offset: u64, len: u64) -> VfsResult<Vec<u8>> {
+    match offset.checked_add(len) {
+        Some(end) if end <= source.size() => {}
+        _ => return Err(VfsError::InvalidData),
+    }
+    let mut buf = vec![0; len as usize];
+    source.read_exact_at(&mut buf, offset)?;
+    Ok(buf)
+}
+
+fn detect(source: &dyn ArchiveSource) -> VfsResult<ArchiveFormat> {
+    let mut magic = [0; 8];
+    let len = source.size().min(8) as usize;
+    source.read_exact_at(&mut magic[..len], 0)?;
+    Ok(if magic[..] == *AR_MAGIC {
+        ArchiveFormat::Ar
+    } else if magic[..6] == *cpio::MAGIC || magic[..6] == *cpio::MAGIC_CRC {
+        ArchiveFormat::Cpio
+    } else {
+        // Anything may precede the members of a zip archive, such as a
+        // signature block: only the end of the archive tells.
+        ArchiveFormat::Zip
+    })
+}
+
+/// Finds the end of central directory record of a zip archive, which is
+/// followed by a comment of up to 64 KiB.
+fn zip_eocd(source: &dyn ArchiveSource) -> VfsResult<(u64, Vec<u8>)> {
+    let size = source.size();
+    if size < ZIP_EOCD_LEN as u64 {
+        return Err(VfsError::InvalidData);
+    }
+    let tail_len = size.min(ZIP_EOCD_LEN as u64 + 0xffff) as usize;
+    let tail_pos = size - tail_len as u64;
+    let tail = read_vec(source, tail_pos, tail_len as u64)?;
+    let pos = (0..=tail_len - ZIP_EOCD_LEN)
+        .rev()
+        .find(|&i| {
+            u32_at(&tail, i) == ZIP_EOCD_SIG
+                && i + ZIP_EOCD_LEN + u16_at(&tail, i + 20) as usize == tail_len
+        })
+        .ok_or(VfsError::InvalidData)?;
+    Ok((
+        tail_pos + pos as u64,
+        tail[pos..pos + ZIP_EOCD_LEN].to_vec(),
+    ))
+}
+
+fn parse_zip(source: &dyn ArchiveSource) -> ArchiveResult<Vec<ArchiveMember>> {
+    let (eocd_pos, eocd) = zip_eocd(source)?;
+    let disk = u16_at(&eocd, 4);
+    let cd_disk = u16_at(&eocd, 6);
+    let disk_entries = u16_at(&eocd, 8);
+    let entries = u16_at(&eocd, 10);
+    let cd_size = u32_at(&eocd, 12);
+    let cd_offset = u32_at(&eocd, 16);
+    if disk != 0 || cd_disk != 0 || disk_entries != entries {
+        warn!("archive: multi-disk zip archives are not supported");
+        return Err(VfsError::Unsupported.into());
+    }
+    if entries == u16::MAX || cd_size == u32::MAX || cd_offset == u32::MAX {
+        warn!("archive: zip64 archives are not supported");
+        return Err(VfsError::Unsupported.into());
+    }
+    let (cd_offset, cd_size) = (cd_offset as u64, cd_size as u64);
+    if cd_offset + cd_size > eocd_pos {
+        return Err(VfsError::InvalidData.into());
+    }
+    let cd = read_vec(source, cd_offset, cd_size)?;
+
+    let mut members: Vec<ArchiveMember> = Vec::new();
+    let mut pos = 0;
+    for index in 0..entries {
+        let index = format!("#{index}");
+        let header = cd
+            .get(pos..pos + ZIP_CENTRAL_LEN)
+            .ok_or_else(|| invalid(&index))?;
+        if u32_at(header, 0) != ZIP_CENTRAL_SIG {
+            return Err(invalid(&index));
+        }
+        let flags = u16_at(header, 8);
+        let method = u16_at(header, 10);
+        let crc32 = u32_at(header, 16);
+        let stored_size = u32_at(header, 20) as u64;
+        let size = u32_at(header, 24) as u64;
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        let local_offset = u32_at(header, 42) as u64;
+        let name_start = pos + ZIP_CENTRAL_LEN;
+        let name = cd
+            .get(name_start..name_start + name_len)
+            .ok_or_else(|| invalid(&index))?;
+        let name = str::from_utf8(name).map_err(|_| invalid(&index))?;
+        pos = name_start + name_len + extra_len + comment_len;
+        if pos > cd.len() {
+            return Err(invalid(name));
+        }
+        if name.ends_with('/') {
+            continue;
+        }
+
+        if flags & ZIP_FLAG_ENCRYPTED != 0 {
+            return Err(ArchiveError::new(name, VfsError::Unsupported));
+        }
+        let compression = match method {
+            0 if stored_size == size => Compression::Stored,
+            0 => return Err(invalid(name)),
+            8 => Compression::Deflate,
+            _ => return Err(ArchiveError::new(name, VfsError::Unsupported)),
+        };
+        if compression == Compression::Deflate
+            && size > stored_size.saturating_mul(DEFLATE_MAX_RATIO)
+        {
+            return Err(invalid(name));
+        }
+
+        // The data follows the local header, whose name and extra field may
+        // differ in length from those of the central directory.
+        let local = read_vec(source, local_offset, ZIP_LOCAL_LEN as u64)
+            .map_err(|err| ArchiveError::new(name, err))?;
+        if u32_at(&local, 0) != ZIP_LOCAL_SIG {
+            return Err(invalid(name));
+        }
+        let offset = local_offset
+            + ZIP_LOCAL_LEN as u64
+            + u16_at(&local, 26) as u64
+            + u16_at(&local, 28) as u64;
+        if offset + stored_size > cd_offset {
+            return Err(invalid(name));
+        }
+        // A second member of the same name would make what is read depend
+        // on which one is looked up.
+        if members.iter().any(|member| member.name == name) {
+            return Err(invalid(name));
+        }
+        members.push(ArchiveMember {
+            name: name.into(),
+            size,
+            compression,
+            stored_size,
+            offset,
+            crc32: Some(crc32),
+        });
+    }
+    Ok(members)
+}
+
+/// Parses the decimal field of an ar header.
+fn ar_field(field: &[u8]) -> Option<u64> {
+    str::from_utf8(field).ok()?.trim_end().parse().ok()
+}
+
+fn parse_ar(source: &dyn ArchiveSource) -> ArchiveResult<Vec<ArchiveMember>> {
+    let size = source.size();
+    let mut members: Vec<ArchiveMember> = Vec::new();
+    // The GNU table of names too long for the header.
+    let mut long_names = Vec::new();
+    let mut pos = AR_MAGIC.len() as u64;
+    let mut index = 0;
+    while pos < size {
+        let id = format!("#{index}");
+        index += 1;
+        let header =
+            read_vec(source, pos, AR_HEADER_LEN).map_err(|err| ArchiveError::new(&id, err))?;
+        if &header[58..60] != b"`\n" {
+            return Err(invalid(&id));
+        }
+        let mut len = ar_field(&header[48..58]).ok_or_else(|| invalid(&id))?;
+        let mut offset = pos + AR_HEADER_LEN;
+        if offset.checked_add(len).is_none_or(|end| end > size) {
+            return Err(invalid(&id));
+        }
+        pos = (offset + len).next_multiple_of(2);
+
+        let raw = &header[..16];
+        let name = match raw.trim_ascii_end() {
+            // The symbol tables of GNU ar.
+            b"/" | b"/SYM64/" => continue,
+            b"//" => {
+                long_names = read_vec(source, offset, len)?;
+                continue;
+            }
+            raw if raw.starts_with(b"#1/") => {
+                // BSD: the name is at the start of the data.
+                let name_len = ar_field(&raw[3..]).filter(|&n| n <= len);
+                let name_len = name_len.ok_or_else(|| invalid(&id))?;
+                let name = read_vec(source, offset, name_len)?;
+                offset += name_len;
+                len -= name_len;
+                // It is padded with NULs to keep the data aligned.
+                let name = name.trim_ascii_end().split(|&b| b == 0).next().unwrap();
+                String::from_utf8(name.to_vec()).map_err(|_| invalid(&id))?
+            }
+            raw if raw.len() > 1 && raw[0] == b'/' => {
+                let start = ar_field(&raw[1..]).ok_or_else(|| invalid(&id))? as usize;
+                let rest = long_names.get(start..).ok_or_else(|| invalid(&id))?;
+                let end = rest
+                    .windows(2)
+                    .position(|w| w == b"/\n")
+                    .ok_or_else(|| invalid(&id))?;
+                String::from_utf8(rest[..end].to_vec()).map_err(|_| invalid(&id))?
+            }
+            raw => {
+                let raw = raw.strip_suffix(b"/").unwrap_or(raw);
+                String::from_utf8(raw.to_vec()).map_err(|_| invalid(&id))?
+            }
+        };
+        if name.is_empty() || members.iter().any(|member| member.name == name) {
+            return Err(invalid(if name.is_empty() { &id } else { &name }));
+        }
+        members.push(ArchiveMember {
+            name,
+            size: len,
+            compression: Compression::Stored,
+            stored_size: len,
+            offset,
+            crc32: None,
+        });
+    }
+    Ok(members)
+}
+
+fn parse_cpio(source: &dyn ArchiveSource) -> ArchiveResult<Vec<ArchiveMember>> {
+    let size = source.size();
+    let mut members: Vec<ArchiveMember> = Vec::new();
+    let mut pos = 0;
+    let mut index = 0;
+    while pos < size {
+        let id = format!("#{index}");
+        index += 1;
+        let header = read_vec(source, pos, cpio::HEADER_LEN as u64)
+            .map_err(|err| ArchiveError::new(&id, err))?;
+        if &header[..6] != cpio::MAGIC && &header[..6] != cpio::MAGIC_CRC {
+            return Err(invalid(&id));
+        }
+        let field = |index| cpio::CpioReader::field(&header, index).map_err(|_| invalid(&id));
+        let mode = field(1)?;
+        let len = field(6)? as u64;
+        let name_size = field(11)? as u64;
+
+        let name_start = pos + cpio::HEADER_LEN as u64;
+        let name = read_vec(source, name_start, name_size).map_err(|_| invalid(&id))?;
+        let name = name.strip_suffix(b"\0").ok_or_else(|| invalid(&id))?;
+        let name = str::from_utf8(name).map_err(|_| invalid(&id))?;
+        let offset = (name_start + name_size).next_multiple_of(4);
+        if offset + len > size {
+            return Err(invalid(name));
+        }
+        pos = (offset + len).next_multiple_of(4);
+
+        if name == cpio::TRAILER {
+            break;
+        }
+        if mode & cpio::S_IFMT != cpio::S_IFREG {
+            continue;
+        }
+        let name = name.trim_start_matches("./").trim_start_matches('/');
+        if members.iter().any(|member| member.name == name) {
+            return Err(invalid(name));
+        }
+        members.push(ArchiveMember {
+            name: name.into(),
+            size: len,
+            compression: Compression::Stored,
+            stored_size: len,
+            offset,
+            crc32: None,
+        });
+    }
+    Ok(members)
+}
+
+/// A zip, ar or cpio archive, indexed for reading members at random.
+pub struct ArchiveReader {
+    source: Arc<dyn ArchiveSource>,
+    format: ArchiveFormat,
+    members: Vec<ArchiveMember>,
+    manifest: Option<Manifest>,
+}
+
+impl ArchiveReader {
+    /// Opens the archive at `path`.
+    pub fn open(context: &FsContext, path: impl AsRef<Path>) -> ArchiveResult<Self> {
+        Self::from_stream(FileStream::open(context, path, StreamMode::Read)?)
+    }
+
+    /// Opens the archive in the file `stream` is on. The position of the
+    /// stream is not used.
+    pub fn from_stream(stream: FileStream) -> ArchiveResult<Self> {
+        Self::from_source(Arc::new(stream))
+    }
+
+    /// Opens the archive `source`, whose format is found from its content.
+    pub fn from_source(source: Arc<dyn ArchiveSource>) -> ArchiveResult<Self> {
+        let format = detect(&*source)?;
+        let members = match format {
+            ArchiveFormat::Zip => parse_zip(&*source),
+            ArchiveFormat::Ar => parse_ar(&*source),
+            ArchiveFormat::Cpio => parse_cpio(&*source),
+        }
+        .inspect_err(|err| warn!("archive: malformed {format:?} archive: {err}"))?;
+        Ok(Self {
+            source,
+            format,
+            members,
+            manifest: None,
+        })
+    }
+
+    /// Returns the format of the archive.
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Returns the regular files of the archive, in the order they are in.
+    pub fn members(&self) -> &[ArchiveMember] {
+        &self.members
+    }
+
+    /// Returns the member `name`.
+    pub fn member(&self, name: &str) -> Option<&ArchiveMember> {
+        self.members.iter().find(|member| member.name == name)
+    }
+
+    /// Requires the members read from now on to be listed in `manifest`, with
+    /// the same digest.
+    pub fn set_manifest(&mut self, manifest: Manifest) {
+        self.manifest = Some(manifest);
+    }
+
+    /// Opens the member `name` for reading.
+    ///
+    /// With a manifest set, a member it does not list fails with `EACCES`,
+    /// and one whose digest does not match with `EINVAL`; the content is
+    /// hashed before the stream is returned.
+    pub fn read_member(&self, name: &str) -> ArchiveResult<MemberReader> {
+        let member = self
+            .member(name)
+            .ok_or_else(|| ArchiveError::new(name, VfsError::NotFound))?;
+        let expected = match &self.manifest {
+            Some(manifest) => Some(
+                *manifest
+                    .get(name)
+                    .ok_or_else(|| ArchiveError::new(name, VfsError::PermissionDenied))?,
+            ),
+            None => None,
+        };
+        let reader = self
+            .open_member(member)
+            .map_err(|err| ArchiveError::new(name, err))?;
+        if let Some(expected) = expected
+            && reader
+                .digest()
+                .map_err(|err| ArchiveError::new(name, err))?
+                != expected
+        {
+            warn!("archive: member {name} does not match the manifest");
+            return Err(ArchiveError::new(name, VfsError::InvalidData));
+        }
+        Ok(reader)
+    }
+
+    /// Reads the whole member `name`, as [`ArchiveReader::read_member`] does.
+    pub fn read_member_to_vec(&self, name: &str) -> ArchiveResult<Vec<u8>> {
+        let mut reader = self.read_member(name)?;
+        let mut buf = Vec::with_capacity(reader.len() as usize);
+        reader
+            .read_to_end(&mut buf)
+            .map_err(|err| ArchiveError::new(name, err))?;
+        Ok(buf)
+    }
+
+    fn open_member(&self, member: &ArchiveMember) -> VfsResult<MemberReader> {
+        let data = match member.compression {
+            Compression::Stored => MemberData::Source {
+                source: self.source.clone(),
+                offset: member.offset,
+            },
+            Compression::Deflate => {
+                if member.size > MAX_INFLATED_SIZE {
+                    return Err(VfsError::NoMemory);
+                }
+                let src = read_vec(&*self.source, member.offset, member.stored_size)?;
+                let mut data = vec![0; member.size as usize];
+                match kdecompress::inflate(&src, &mut data) {
+                    Ok((_, len)) if len == data.len() => {}
+                    _ => return Err(VfsError::InvalidData),
+                }
+                if member
+                    .crc32
+                    .is_some_and(|crc| crc != kdecompress::crc32(&data))
+                {
+                    return Err(VfsError::InvalidData);
+                }
+                MemberData::Memory(data)
+            }
+        };
+        let crc = match data {
+            MemberData::Source { .. } => member.crc32.map(|crc| (crc, 0, 0)),
+            MemberData::Memory(_) => None,
+        };
+        Ok(MemberReader {
+            name: member.name.clone(),
+            data,
+            len: member.size,
+            pos: 0,
+            crc,
+        })
+    }
+}
+
+enum MemberData {
+    Source {
+        source: Arc<dyn ArchiveSource>,
+        offset: u64,
+    },
+    Memory(Vec<u8>),
+}
+
+/// The content of an archive member.
+///
+/// The position may be saved with [`Seek`] and restored later to resume
+/// reading there.
+pub struct MemberReader {
+    name: String,
+    data: MemberData,
+    len: u64,
+    pos: u64,
+    /// The expected CRC-32, and that of the content before the offset that
+    /// follows, checked once the whole content has been read in order.
+    crc: Option<(u32, u32, u64)>,
+}
+
+impl MemberReader {
+    /// Returns the name of the member.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the size of the content.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the content at `offset` to `buf`, up to its end.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let n = (buf.len() as u64).min(self.len.saturating_sub(offset)) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+        match &self.data {
+            MemberData::Source {
+                source,
+                offset: start,
+            } => {
+                source.read_exact_at(&mut buf[..n], start + offset)?;
+            }
+            MemberData::Memory(data) => {
+                let offset = offset as usize;
+                buf[..n].copy_from_slice(&data[offset..offset + n]);
+            }
+        }
+        Ok(n)
+    }
+
+    /// Returns the SHA-256 digest of the content.
+    pub fn digest(&self) -> VfsResult<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; HASH_CHUNK.min(self.len as usize)];
+        let mut offset = 0;
+        while offset < self.len {
+            let n = self.read_at(&mut buf, offset)?;
+            hasher.update(&buf[..n]);
+            offset += n as u64;
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+impl Read for MemberReader {
+    fn read(&mut self, buf: &mut [u8]) -> kio::Result<usize> {
+        let n = self.read_at(buf, self.pos)?;
+        if let Some((expected, crc, checked)) = &mut self.crc
+            && *checked == self.pos
+        {
+            *crc = kdecompress::crc32_update(*crc, &buf[..n]);
+            *checked += n as u64;
+            if *checked == self.len && *crc != *expected {
+                warn!("archive: member {} fails its CRC check", self.name);
+                return Err(VfsError::InvalidData);
+            }
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for MemberReader {
+    fn seek(&mut self, pos: SeekFrom) -> kio::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or(VfsError::InvalidInput)?;
+        Ok(self.pos)
+    }
+}
@@ -6,6 +6,7 @@
 use alloc::{
     boxed::Box,
     sync::{Arc, Weak},
+    vec,
     vec::Vec,
 };
 #[cfg(feature = "times")]
@@ -13,7 +14,8 @@ use core::sync::atomic::{AtomicU8, Ordering};
 use core::{num::NonZeroUsize, ops::Range, task::Context};
 
 use fs_ng_vfs::{
-    FileNode, Location, NodeFlags, NodePermission, NodeType, VfsError, VfsResult, path::Path,
+    FallocateMode, FileNode, FsEvents, Location, Metadata, MetadataUpdate, NodeFlags,
+    NodePermission, NodeType, VfsError, VfsResult, path::Path,
 };
 use intrusive_collections::{LinkedList, LinkedListAtomicLink, intrusive_adapter};
 use kalloc::{UsageKind, global_allocator};
@@ -24,6 +26,7 @@ use ksync::{Mutex, RwLock};
 use lru::LruCache;
 
 use super::FsContext;
+use crate::fscrypt::{self, FileCrypt, PlaintextCache};
 
 bitflags::bitflags! {
     /// Access mode flags for an opened file.
@@ -34,6 +37,7 @@ bitflags::bitflags! {
         const EXECUTE = 4;
         const APPEND = 8;
         const PATH = 16;
+        const DIRECT = 32;
     }
 }
 
@@ -159,7 +163,10 @@ impl OpenOptions {
         self
     }
 
-    /// Sets the option to open the file with direct I/O.\
+    /// Sets the option to open the file with direct I/O.
+    ///
+    /// Regular files then bypass all caches, and opening them fails with
+    /// [`VfsError::InvalidInput`] if their filesystem does not support it.
     pub fn direct(&mut self, direct: bool) -> &mut Self {
         self.direct = direct;
         self
@@ -200,6 +207,14 @@ impl OpenOptions {
             }
             loc.check_is_dir()?;
         }
+        if flags.contains(FileFlags::WRITE)
+            && (loc.flags().contains(NodeFlags::READ_ONLY) || loc.mountpoint().is_read_only())
+            && loc.node_type() == NodeType::RegularFile
+        {
+            return Err(VfsError::ReadOnlyFilesystem);
+        }
+        // Encrypted files are only ever decrypted in the page cache.
+        let encrypted = !self.path && fscrypt::prepare_open(&loc)?;
         if self.truncate {
             loc.entry().as_file()?.set_len(0)?;
         }
@@ -217,11 +232,22 @@ impl OpenOptions {
                 || self.path
                 || self.direct
                 || loc.flags().contains(NodeFlags::NON_CACHEABLE);
-            let backend = if !direct || loc.flags().contains(NodeFlags::ALWAYS_CACHE) {
+            let backend = if encrypted || !direct || loc.flags().contains(NodeFlags::ALWAYS_CACHE) {
                 FileBackend::new_cached(loc)
             } else {
                 FileBackend::new_direct(loc)
             };
+            let mut flags = flags;
+            if let FileBackend::Direct(loc) = &backend
+                && self.direct
+                && !self.path
+                && loc.node_type() == NodeType::RegularFile
+            {
+                if loc.entry().as_file()?.direct_io_align().is_none() {
+                    return Err(VfsError::InvalidInput);
+                }
+                flags |= FileFlags::DIRECT;
+            }
             OpenResult::File(File::new(backend, flags))
         })
     }
@@ -237,6 +263,11 @@ impl OpenOptions {
         if !self.is_valid() {
             return Err(VfsError::InvalidInput);
         }
+        // A path ending with a slash names a directory, which `O_CREAT` does
+        // not open.
+        if self.create && path.as_ref().has_trailing_slash() {
+            return Err(VfsError::IsADirectory);
+        }
 
         let loc = match context.resolve_parent(path.as_ref()) {
             Ok((parent, name)) => {
@@ -246,12 +277,16 @@ impl OpenOptions {
                         create: self.create,
                         create_new: self.create_new,
                         node_type: self.node_type,
-                        permission: NodePermission::from_bits_truncate(self.mode as _),
+                        permission: context
+                            .apply_umask(NodePermission::from_bits_truncate(self.mode as _)),
                         user: self.user,
                     },
                 )?;
                 if !self.no_follow {
                     context.resolve(path)?
+                } else if loc.node_type() == NodeType::Symlink && !self.path {
+                    // Only `O_PATH` may open the symlink itself.
+                    return Err(VfsError::FilesystemLoop);
                 } else {
                     loc
                 }
@@ -309,6 +344,15 @@ impl Default for OpenOptions {
 
 const PAGE_SIZE: usize = 4096;
 
+/// Maximum number of bytes passed to the filesystem by one direct I/O call.
+const DIRECT_IO_CHUNK: usize = 64 * 1024;
+
+/// Readahead window of a file that starts being read sequentially.
+pub const READAHEAD_MIN: u64 = 16 * 1024;
+
+/// Readahead window a file read sequentially grows to.
+pub const READAHEAD_MAX: u64 = 128 * 1024;
+
 #[derive(Debug)]
 pub struct PageCache {
     addr: VirtAddr,
@@ -354,7 +398,20 @@ impl Drop for PageCache {
     }
 }
 
-type EvictListenerFn = dyn Fn(u32, &PageCache) + Send + Sync;
+/// What happened to a cached page, as told to the listeners of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageEvent {
+    /// The page is dropped from the cache: mappings of it must go.
+    Evicted,
+    /// The page is about to be written back: writable mappings of it must
+    /// become read-only, so that the next store through them marks it dirty
+    /// again.
+    Cleaned,
+}
+
+/// A listener returns whether it could handle the event. A page stays dirty
+/// if a listener could not write-protect it.
+type EvictListenerFn = dyn Fn(u32, PageEvent) -> bool + Send + Sync;
 
 struct EvictListener {
     listener: Box<EvictListenerFn>,
@@ -366,13 +423,21 @@ intrusive_adapter!(EvictListenerAdapter = Box<EvictListener>: EvictListener { li
 struct CachedFileShared {
     page_cache: Mutex<LruCache<u32, PageCache>>,
     evict_listeners: Mutex<LinkedList<EvictListenerAdapter>>,
+    /// Contents encryption; cached pages then hold the plaintext.
+    crypt: Option<Arc<FileCrypt>>,
+    /// Only one thread can append to the file at a time, while multiple
+    /// writers are permitted. Shared by all openers, so that appenders
+    /// through different open files do not overwrite each other.
+    append_lock: RwLock<()>,
 }
 
 impl CachedFileShared {
-    pub fn new() -> Self {
+    pub fn new(crypt: Option<Arc<FileCrypt>>) -> Self {
         Self {
             page_cache: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())),
             evict_listeners: Mutex::new(LinkedList::default()),
+            crypt,
+            append_lock: RwLock::new(()),
         }
     }
 
@@ -380,28 +445,105 @@ impl CachedFileShared {
         Self {
             page_cache: Mutex::new(LruCache::unbounded()),
             evict_listeners: Mutex::new(LinkedList::default()),
+            crypt: None,
+            append_lock: RwLock::new(()),
+        }
+    }
+
+    fn evict_cache(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
+        self.notify(pn, PageEvent::Evicted);
+        self.write_back(file, pn, page)
+    }
+
+    /// Tells the listeners about `event` on page `pn`, and returns whether
+    /// all of them handled it.
+    fn notify(&self, pn: u32, event: PageEvent) -> bool {
+        let mut handled = true;
+        for listener in self.evict_listeners.lock().iter() {
+            handled &= (listener.listener)(pn, event);
+        }
+        handled
+    }
+
+    /// Writes back page `pn` if it is dirty, keeping it cached.
+    ///
+    /// Mappings of the page are write-protected first, so that stores made
+    /// after the write back mark the page dirty again. If a mapping could
+    /// not be, the page is left dirty.
+    fn clean(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
+        if !page.dirty {
+            return Ok(());
+        }
+        let protected = self.notify(pn, PageEvent::Cleaned);
+        self.write_back(file, pn, page)?;
+        page.dirty = !protected;
+        Ok(())
+    }
+
+    fn write_back(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
+        if page.dirty {
+            let page_start = pn as u64 * PAGE_SIZE as u64;
+            let len = (file.len()?.saturating_sub(page_start)).min(PAGE_SIZE as u64) as usize;
+            if len > 0 {
+                if let Some(crypt) = &self.crypt {
+                    let mut data = page.data()[..len].to_vec();
+                    crypt.encrypt_unit(pn, &mut data)?;
+                    file.write_at(&data, page_start)?;
+                } else {
+                    file.write_at(&page.data()[..len], page_start)?;
+                }
+            }
+            page.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Writes back the cached pages overlapping `range`, and drops them too if
+    /// `drop` is set.
+    fn sync_range(&self, file: &FileNode, range: Range<u64>, drop: bool) -> VfsResult<()> {
+        let pages =
+            (range.start / PAGE_SIZE as u64) as u32..range.end.div_ceil(PAGE_SIZE as u64) as u32;
+        let mut guard = self.page_cache.lock();
+        let keys = guard
+            .iter()
+            .map(|(pn, _)| *pn)
+            .filter(|pn| pages.contains(pn))
+            .collect::<Vec<_>>();
+        for pn in keys {
+            if drop {
+                if let Some(mut page) = guard.pop(&pn) {
+                    self.evict_cache(file, pn, &mut page)?;
+                }
+            } else if let Some(page) = guard.peek_mut(&pn) {
+                self.clean(file, pn, page)?;
+            }
+            ktask::cond_resched();
         }
+        Ok(())
+    }
+
+    /// Writes back and drops all cached pages.
+    fn evict_all(&self, file: &FileNode) -> VfsResult<()> {
+        let mut guard = self.page_cache.lock();
+        while let Some((pn, mut page)) = guard.pop_lru() {
+            self.evict_cache(file, pn, &mut page)?;
+            ktask::cond_resched();
+        }
+        Ok(())
     }
 }
 
+impl PlaintextCache for CachedFileShared {
+    fn evict(&self, file: &FileNode) -> VfsResult<()> {
+        self.evict_all(file)
+    }
+}
+
+#[derive(Clone)]
 pub struct CachedFile {
     inner: Location,
     shared: Arc<CachedFileShared>,
     in_memory: bool,
-    /// Only one thread can append to the file at a time, while multiple writers
-    /// are permitted.
-    append_lock: RwLock<()>,
-}
-
-impl Clone for CachedFile {
-    fn clone(&self) -> Self {
-        Self {
-            inner: self.inner.clone(),
-            shared: self.shared.clone(),
-            in_memory: self.in_memory,
-            append_lock: RwLock::new(()),
-        }
-    }
 }
 
 enum FileUserData {
@@ -430,7 +572,12 @@ impl CachedFile {
                 let shared = Arc::new(CachedFileShared::new_unbounded());
                 (shared.clone(), FileUserData::Strong(shared))
             } else {
-                let shared = Arc::new(CachedFileShared::new());
+                let crypt = FileCrypt::for_location(&location);
+                let shared = Arc::new(CachedFileShared::new(crypt.clone()));
+                if let Some(crypt) = crypt {
+                    let cache: Weak<dyn PlaintextCache> = Arc::downgrade(&shared) as _;
+                    crypt.register_cache(location.entry(), cache);
+                }
                 let user_data = FileUserData::Weak(Arc::downgrade(&shared));
                 (shared, user_data)
             };
@@ -443,7 +590,6 @@ impl CachedFile {
             inner: location,
             shared,
             in_memory,
-            append_lock: RwLock::new(()),
         }
     }
 
@@ -455,9 +601,12 @@ impl CachedFile {
         self.in_memory
     }
 
+    /// Adds a listener told about the pages evicted from the cache or
+    /// written back, see [`PageEvent`], and returns the handle to remove it
+    /// with.
     pub fn add_evict_listener<F>(&self, listener: F) -> usize
     where
-        F: Fn(u32, &PageCache) + Send + Sync + 'static,
+        F: Fn(u32, PageEvent) -> bool + Send + Sync + 'static,
     {
         let pointer = Box::new(EvictListener {
             listener: Box::new(listener),
@@ -482,18 +631,7 @@ impl CachedFile {
     }
 
     fn evict_cache(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
-        for listener in self.shared.evict_listeners.lock().iter() {
-            (listener.listener)(pn, page);
-        }
-        if page.dirty {
-            let page_start = pn as u64 * PAGE_SIZE as u64;
-            let len = (file.len()?.saturating_sub(page_start)).min(PAGE_SIZE as u64) as usize;
-            if len > 0 {
-                file.write_at(&page.data()[..len], page_start)?;
-            }
-            page.dirty = false;
-        }
-        Ok(())
+        self.shared.evict_cache(file, pn, page)
     }
 
     fn page_or_insert<'a>(
@@ -501,32 +639,127 @@ impl CachedFile {
         file: &FileNode,
         cache: &'a mut LruCache<u32, PageCache>,
         pn: u32,
+    ) -> VfsResult<(&'a mut PageCache, Option<(u32, PageCache)>)> {
+        self.page_or_insert_with(file, cache, pn, false)
+    }
+
+    /// Returns page `pn`, inserting it if it is not cached. A new page is
+    /// zero-filled if `zeroed` is set, and read from the file otherwise.
+    fn page_or_insert_with<'a>(
+        &self,
+        file: &FileNode,
+        cache: &'a mut LruCache<u32, PageCache>,
+        pn: u32,
+        zeroed: bool,
     ) -> VfsResult<(&'a mut PageCache, Option<(u32, PageCache)>)> {
         // TODO: Matching the result of `get_mut` confuses compiler. See
         // https://users.rust-lang.org/t/return-do-not-release-mutable-borrow/55757.
         if cache.contains(&pn) {
             return Ok((cache.get_mut(&pn).unwrap(), None));
         }
-        let mut evicted = None;
-        if cache.len() == cache.cap().get() {
-            // Cache is full, remove the least recently used page
-            if let Some((pn, mut page)) = cache.pop_lru() {
-                self.evict_cache(file, pn, &mut page)?;
-                evicted = Some((pn, page));
-            }
-        }
+        let evicted = self.make_room(file, cache)?;
 
         // Page not in cache, read it
         let mut page = PageCache::new()?;
-        if self.in_memory {
+        if self.in_memory || zeroed {
             page.data().fill(0);
         } else {
-            file.read_at(page.data(), pn as u64 * PAGE_SIZE as u64)?;
+            let read = file.read_at(page.data(), pn as u64 * PAGE_SIZE as u64)?;
+            if let Some(crypt) = &self.shared.crypt {
+                crypt.decrypt_unit(pn, &mut page.data()[..read])?;
+            }
+            // Past the end of file, in case the file grows later.
+            page.data()[read..].fill(0);
         }
         cache.put(pn, page);
         Ok((cache.get_mut(&pn).unwrap(), evicted))
     }
 
+    /// Evicts the least recently used page if the cache is full.
+    fn make_room(
+        &self,
+        file: &FileNode,
+        cache: &mut LruCache<u32, PageCache>,
+    ) -> VfsResult<Option<(u32, PageCache)>> {
+        if cache.len() < cache.cap().get() {
+            return Ok(None);
+        }
+        let Some((pn, mut page)) = cache.pop_lru() else {
+            return Ok(None);
+        };
+        self.evict_cache(file, pn, &mut page)?;
+        Ok(Some((pn, page)))
+    }
+
+    /// Reads the pages overlapping `range` that are not cached yet, each run
+    /// of them with a single request to the file, and returns how many pages
+    /// were read.
+    pub fn read_ahead(&self, range: Range<u64>) -> VfsResult<usize> {
+        if self.in_memory {
+            return Ok(0);
+        }
+        let file = self.inner.entry().as_file()?;
+        let end_page = range.end.div_ceil(PAGE_SIZE as u64) as u32;
+        let mut pn = (range.start / PAGE_SIZE as u64) as u32;
+        let mut guard = self.shared.page_cache.lock();
+        let mut pages = 0;
+        while pn < end_page {
+            if guard.contains(&pn) {
+                pn += 1;
+                continue;
+            }
+            let run_end = (pn + 1..end_page)
+                .find(|pn| guard.contains(pn))
+                .unwrap_or(end_page);
+            let mut buf = vec![0; (run_end - pn) as usize * PAGE_SIZE];
+            let read = file.read_at(&mut buf, pn as u64 * PAGE_SIZE as u64)?;
+            for (i, data) in buf[..read].chunks_mut(PAGE_SIZE).enumerate() {
+                let pn = pn + i as u32;
+                if let Some(crypt) = &self.shared.crypt {
+                    crypt.decrypt_unit(pn, data)?;
+                }
+                self.make_room(file, &mut guard)?;
+                let mut page = PageCache::new()?;
+                page.data()[..data.len()].copy_from_slice(data);
+                page.data()[data.len()..].fill(0);
+                guard.put(pn, page);
+                pages += 1;
+            }
+            if read < buf.len() {
+                break;
+            }
+            pn = run_end;
+            ktask::cond_resched();
+        }
+        Ok(pages)
+    }
+
+    /// Changes the length of the underlying file from `old_len` to `new_len`.
+    ///
+    /// The last data unit of an encrypted file is encrypted at its length, so
+    /// the page holding the old end of file is decrypted before and written
+    /// back after the change. Pages past the old end of file are holes that
+    /// would not decrypt to zeroes, so they are written as well.
+    fn resize(&self, file: &FileNode, old_len: u64, new_len: u64) -> VfsResult<()> {
+        if self.shared.crypt.is_none() {
+            return file.set_len(new_len);
+        }
+        let mut guard = self.shared.page_cache.lock();
+        let boundary = old_len.min(new_len);
+        if boundary % PAGE_SIZE as u64 != 0 {
+            let pn = (boundary / PAGE_SIZE as u64) as u32;
+            self.page_or_insert(file, &mut guard, pn)?.0.mark_dirty();
+        }
+        file.set_len(new_len)?;
+        let new_pages = old_len.div_ceil(PAGE_SIZE as u64)..new_len.div_ceil(PAGE_SIZE as u64);
+        for pn in new_pages {
+            self.page_or_insert_with(file, &mut guard, pn as u32, true)?
+                .0
+                .mark_dirty();
+        }
+        Ok(())
+    }
+
     pub fn with_page<R>(&self, pn: u32, f: impl FnOnce(Option<&mut PageCache>) -> R) -> R {
         f(self.shared.page_cache.lock().get_mut(&pn))
     }
@@ -554,6 +787,7 @@ impl CachedFile {
         let mut page_offset = (range.start % PAGE_SIZE as u64) as usize;
         for pn in start_page..end_page {
             let page_start = pn as u64 * PAGE_SIZE as u64;
+            ktask::cond_resched();
 
             let mut guard = self.shared.page_cache.lock();
             let page = self.page_or_insert(file, &mut guard, pn)?.0;
@@ -591,8 +825,10 @@ impl CachedFile {
         self.with_pages(
             offset..end,
             |file| {
-                if end > file.len()? {
-                    file.set_len(end)?;
+                let len = file.len()?;
+                if end > len {
+                    self.resize(file, len, end)?;
+                    self.zero_tail(len, end);
                 }
                 Ok(0)
             },
@@ -608,12 +844,12 @@ impl CachedFile {
     }
 
     pub fn write_at(&self, buf: impl Read + IoBuf, offset: u64) -> VfsResult<usize> {
-        let _guard = self.append_lock.read();
+        let _guard = self.shared.append_lock.read();
         self.write_at_locked(buf, offset)
     }
 
     pub fn append(&self, buf: impl Read + IoBuf) -> VfsResult<(usize, u64)> {
-        let _guard = self.append_lock.write();
+        let _guard = self.shared.append_lock.write();
         let file = self.inner.entry().as_file()?;
         let len = file.len()?;
         self.write_at_locked(buf, len)
@@ -623,50 +859,78 @@ impl CachedFile {
     pub fn set_len(&self, len: u64) -> VfsResult<()> {
         let file = self.inner.entry().as_file()?;
         let old_len = file.len()?;
-        file.set_len(len)?;
+        self.resize(file, old_len, len)?;
 
-        let old_last_page = (old_len / PAGE_SIZE as u64) as u32;
-        let new_last_page = (len / PAGE_SIZE as u64) as u32;
         if old_len < len {
+            self.zero_tail(old_len, len);
+        } else {
+            // The data past the new end of file must read as zeroes if the
+            // file grows again.
             let mut guard = self.shared.page_cache.lock();
-            if let Some(page) = guard.get_mut(&old_last_page) {
-                let page_start = old_last_page as u64 * PAGE_SIZE as u64;
-                let old_page_offset = (old_len - page_start) as usize;
-                let new_page_offset = (len - page_start).min(PAGE_SIZE as u64) as usize;
-                page.data()[old_page_offset..new_page_offset].fill(0);
+            let new_last_page = (len / PAGE_SIZE as u64) as u32;
+            if let Some(page) = guard.get_mut(&new_last_page) {
+                let page_start = new_last_page as u64 * PAGE_SIZE as u64;
+                page.data()[(len - page_start) as usize..].fill(0);
             }
-        } else if old_last_page > new_last_page {
             // For truncating, we need to remove all pages that are beyond the
-            // new length
+            // new length, and their mappings with them: accessing them raises
+            // `SIGBUS` from now on.
             // TODO(mivik): can this be more efficient?
-            let mut guard = self.shared.page_cache.lock();
+            let end_page = len.div_ceil(PAGE_SIZE as u64);
             let keys = guard
                 .iter()
                 .map(|(k, _)| *k)
-                .filter(|it| *it > new_last_page)
+                .filter(|it| *it as u64 >= end_page)
                 .collect::<Vec<_>>();
             for pn in keys {
-                if let Some(mut page) = guard.pop(&pn)
-                    && !self.in_memory
-                {
+                if let Some(mut page) = guard.pop(&pn) {
                     // Don't write back pages since they're discarded
                     page.dirty = false;
                     self.evict_cache(file, pn, &mut page)?;
                 }
+                ktask::cond_resched();
             }
         }
         Ok(())
     }
 
-    pub fn sync(&self, data_only: bool) -> VfsResult<()> {
+    /// Zeroes the cached data between `old_len` and `new_len` in the page
+    /// holding the old end of file, as the file grows.
+    ///
+    /// Stores past the end of file through a shared mapping stay in that page,
+    /// and must not show up in the file once it grows over them.
+    fn zero_tail(&self, old_len: u64, new_len: u64) {
+        let pn = (old_len / PAGE_SIZE as u64) as u32;
+        let page_start = pn as u64 * PAGE_SIZE as u64;
+        if old_len == page_start {
+            return;
+        }
+        if let Some(page) = self.shared.page_cache.lock().get_mut(&pn) {
+            let end = (new_len - page_start).min(PAGE_SIZE as u64) as usize;
+            page.data()[(old_len - page_start) as usize..end].fill(0);
+        }
+    }
+
+    /// Writes back the dirty cached pages overlapping `range`, and flushes the
+    /// data of the file.
+    ///
+    /// This is `msync` of a shared mapping of the file: the pages stay cached
+    /// and mapped, write-protected until they are stored to again.
+    pub fn sync_range(&self, range: Range<u64>) -> VfsResult<()> {
         if self.in_memory {
             return Ok(());
         }
         let file = self.inner.entry().as_file()?;
-        let mut guard = self.shared.page_cache.lock();
-        while let Some((pn, mut page)) = guard.pop_lru() {
-            self.evict_cache(file, pn, &mut page)?;
+        self.shared.sync_range(file, range, false)?;
+        file.sync(true)
+    }
+
+    pub fn sync(&self, data_only: bool) -> VfsResult<()> {
+        if self.in_memory {
+            return Ok(());
         }
+        let file = self.inner.entry().as_file()?;
+        self.shared.evict_all(file)?;
         file.sync(data_only)?;
         Ok(())
     }
@@ -728,6 +992,7 @@ impl FileBackend {
                     })
             })),
         }
+        .inspect(|written| self.modified(*written))
     }
 
     pub fn append(&self, mut src: impl Read + IoBuf) -> VfsResult<(usize, u64)> {
@@ -744,6 +1009,80 @@ impl FileBackend {
                 .map(|n| (n, end))
             }
         }
+        .inspect(|(written, _)| self.modified(*written))
+    }
+
+    /// Reports a write of `written` bytes to the watches of the file.
+    fn modified(&self, written: usize) {
+        if written > 0 {
+            self.location().entry().notify(FsEvents::MODIFY);
+        }
+    }
+
+    /// Reads bypassing all caches.
+    ///
+    /// `offset` and the length of `dst` must be multiples of the alignment
+    /// the file requires for direct I/O. Dirty pages of the range cached by
+    /// other openers of the file are written back first.
+    pub fn read_direct(&self, mut dst: impl Write + IoBufMut, offset: u64) -> VfsResult<usize> {
+        let Self::Direct(loc) = self else {
+            return self.read_at(dst, offset);
+        };
+        let file = loc.entry().as_file()?;
+        let len = dst.remaining_mut();
+        check_direct_io(file, offset, len)?;
+        if let Some(shared) = cached_shared(loc) {
+            shared.sync_range(file, offset..offset + len as u64, false)?;
+        }
+
+        let mut buf = vec![0; len.min(DIRECT_IO_CHUNK)];
+        let mut read = 0;
+        while read < len {
+            let chunk = &mut buf[..(len - read).min(DIRECT_IO_CHUNK)];
+            let n = file.read_direct(chunk, offset + read as u64)?;
+            dst.write_all(&chunk[..n])?;
+            read += n;
+            if n < chunk.len() {
+                break;
+            }
+            ktask::cond_resched();
+        }
+        Ok(read)
+    }
+
+    /// Writes bypassing all caches.
+    ///
+    /// `offset` and the length of `src` must be multiples of the alignment
+    /// the file requires for direct I/O. Pages of the range cached by other
+    /// openers of the file are written back and dropped, so that they do
+    /// not hide or overwrite the new data.
+    pub fn write_direct(&self, mut src: impl Read + IoBuf, offset: u64) -> VfsResult<usize> {
+        let Self::Direct(loc) = self else {
+            return self.write_at(src, offset);
+        };
+        let file = loc.entry().as_file()?;
+        let len = src.remaining();
+        check_direct_io(file, offset, len)?;
+        let range = offset..offset + len as u64;
+        let shared = cached_shared(loc);
+        if let Some(shared) = &shared {
+            shared.sync_range(file, range.clone(), true)?;
+        }
+
+        let mut buf = vec![0; len.min(DIRECT_IO_CHUNK)];
+        let mut written = 0;
+        while written < len {
+            let chunk = &mut buf[..(len - written).min(DIRECT_IO_CHUNK)];
+            src.read_exact(chunk)?;
+            written += file.write_direct(chunk, offset + written as u64)?;
+            ktask::cond_resched();
+        }
+        // Pages read in by others in the meantime are stale.
+        if let Some(shared) = &shared {
+            shared.sync_range(file, range, true)?;
+        }
+        self.modified(written);
+        Ok(written)
     }
 
     pub fn location(&self) -> &Location {
@@ -764,7 +1103,173 @@ impl FileBackend {
         match self {
             Self::Cached(cached) => cached.set_len(len),
             Self::Direct(loc) => loc.entry().as_file()?.set_len(len),
+        }?;
+        self.location().entry().notify(FsEvents::MODIFY);
+        Ok(())
+    }
+
+    /// Changes the space allocated to the `len` bytes at `offset`, see
+    /// [`FallocateMode`].
+    ///
+    /// Pages of the range cached by any opener of the file are written back
+    /// and dropped around punching or zeroing, so that none of them hides
+    /// the zeros. If the filesystem cannot allocate ahead, allocating only
+    /// grows the file as [`set_len`](Self::set_len) does.
+    pub fn fallocate(&self, mode: FallocateMode, offset: u64, len: u64) -> VfsResult<()> {
+        let loc = self.location();
+        let file = loc.entry().as_file()?;
+        let end = offset.checked_add(len).ok_or(VfsError::InvalidInput)?;
+        let old_len = file.len()?;
+
+        let shared = if let FallocateMode::Allocate { keep_size } = mode {
+            match file.fallocate(mode, offset, len) {
+                Err(VfsError::Unsupported) => {
+                    if !keep_size && end > old_len {
+                        self.set_len(end)?;
+                    }
+                    return Ok(());
+                }
+                result => result?,
+            }
+            None
+        } else {
+            // Punching and zeroing go around the cache, which is all there is
+            // of in-memory files, and would expose the ciphertext of zeros.
+            if FileCrypt::for_location(loc).is_some() {
+                return Err(VfsError::Unsupported);
+            }
+            let shared = match self {
+                Self::Cached(cached) if cached.in_memory => return Err(VfsError::Unsupported),
+                Self::Cached(cached) => Some(cached.shared.clone()),
+                Self::Direct(loc) => cached_shared(loc),
+            };
+            if let Some(shared) = &shared {
+                shared.sync_range(file, offset..end, true)?;
+            }
+            file.fallocate(mode, offset, len)?;
+            shared
+        };
+
+        // Pages read in by others in the meantime are stale.
+        if let Some(shared) = &shared {
+            shared.sync_range(file, offset..end, true)?;
+        }
+        if let Self::Cached(cached) = self {
+            let new_len = file.len()?;
+            if new_len > old_len {
+                cached.zero_tail(old_len, new_len);
+            }
+        }
+        loc.entry().notify(FsEvents::MODIFY);
+        Ok(())
+    }
+}
+
+/// Checks that `len` bytes at `offset` may be accessed directly in `file`.
+fn check_direct_io(file: &FileNode, offset: u64, len: usize) -> VfsResult<()> {
+    let align = file.direct_io_align().ok_or(VfsError::InvalidInput)?;
+    if !offset.is_multiple_of(align as u64) || !len.is_multiple_of(align) {
+        return Err(VfsError::InvalidInput);
+    }
+    Ok(())
+}
+
+/// Returns the page cache of the file at `loc`, if it is opened cached.
+fn cached_shared(loc: &Location) -> Option<Arc<CachedFileShared>> {
+    loc.user_data()
+        .get::<FileUserData>()
+        .and_then(FileUserData::get)
+}
+
+/// Expected access pattern of an open file, see `posix_fadvise(2)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileAdvice {
+    /// Read ahead once sequential reads are detected.
+    #[default]
+    Normal,
+    /// Read ahead a full window from the first sequential read on.
+    Sequential,
+    /// Never read ahead.
+    Random,
+}
+
+/// Readahead statistics of an open file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadaheadStats {
+    /// Current window in bytes, zero unless the file is read sequentially.
+    pub window: u64,
+    /// Reads that started where the previous one ended.
+    pub sequential: u64,
+    /// Other reads, each of which closed the window.
+    pub random: u64,
+    /// Times data was read ahead.
+    pub batches: u64,
+    /// Pages read ahead.
+    pub pages: u64,
+}
+
+/// Sequential read detector of an open file.
+#[derive(Default)]
+struct Readahead {
+    advice: FileAdvice,
+    /// Where a read continuing the last one starts, `None` after a seek.
+    next: Option<u64>,
+    /// End of the data read ahead so far.
+    end: u64,
+    stats: ReadaheadStats,
+}
+
+impl Readahead {
+    fn new() -> Self {
+        // Reading from the start of the file counts as sequential.
+        Self {
+            next: Some(0),
+            ..Default::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = None;
+        self.end = 0;
+        self.stats.window = 0;
+    }
+
+    /// Records a read of `len` bytes at `offset` of a file of `size` bytes,
+    /// and returns the range to read ahead before serving it, if any.
+    ///
+    /// Data is read ahead once the reader gets within half a window of the
+    /// end of what was read ahead last, and the window doubles each time.
+    fn on_read(&mut self, offset: u64, len: u64, size: u64) -> Option<Range<u64>> {
+        let end = offset.saturating_add(len).min(size);
+        let sequential = self.next == Some(offset);
+        self.next = Some(end);
+        if self.advice == FileAdvice::Random || end <= offset {
+            return None;
+        }
+        if !sequential {
+            self.stats.random += 1;
+            self.end = 0;
+            self.stats.window = 0;
+            return None;
+        }
+        self.stats.sequential += 1;
+
+        let window = self.stats.window;
+        if window != 0 && self.end >= end + window / 2 {
+            return None;
+        }
+        self.stats.window = match (window, self.advice) {
+            (0, FileAdvice::Sequential) => READAHEAD_MAX,
+            (0, _) => READAHEAD_MIN,
+            (window, _) => (window * 2).min(READAHEAD_MAX),
+        };
+        let start = self.end.max(offset);
+        self.end = (end + self.stats.window).min(size);
+        if start >= self.end {
+            return None;
         }
+        self.stats.batches += 1;
+        Some(start..self.end)
     }
 }
 
@@ -773,6 +1278,7 @@ pub struct File {
     inner: FileBackend,
     flags: FileFlags,
     position: Option<Mutex<u64>>,
+    readahead: Mutex<Readahead>,
     #[cfg(feature = "times")]
     access_flags: AtomicU8,
 }
@@ -792,6 +1298,7 @@ impl File {
             inner,
             flags,
             position,
+            readahead: Mutex::new(Readahead::new()),
             #[cfg(feature = "times")]
             access_flags: AtomicU8::new(0),
         }
@@ -838,14 +1345,80 @@ impl File {
         self.inner.location()
     }
 
+    /// Returns the alignment required of the offsets and lengths of reads and
+    /// writes if the file was opened for direct I/O.
+    pub fn direct_io_align(&self) -> Option<usize> {
+        if !self.flags.contains(FileFlags::DIRECT) {
+            return None;
+        }
+        self.location().entry().as_file().ok()?.direct_io_align()
+    }
+
+    /// Returns the alignment required of the buffers of reads and writes if
+    /// the file was opened for direct I/O.
+    pub fn direct_io_mem_align(&self) -> Option<usize> {
+        if !self.flags.contains(FileFlags::DIRECT) {
+            return None;
+        }
+        self.location()
+            .entry()
+            .as_file()
+            .ok()?
+            .direct_io_mem_align()
+    }
+
     /// Reads a number of bytes starting from a given offset.
     pub fn read_at(&self, dst: impl Write + IoBufMut, offset: u64) -> VfsResult<usize> {
-        self.access(FileFlags::READ)?.read_at(dst, offset)
+        let backend = self.access(FileFlags::READ)?;
+        if self.flags.contains(FileFlags::DIRECT) {
+            return backend.read_direct(dst, offset);
+        }
+        if let FileBackend::Cached(cached) = backend {
+            self.read_ahead(cached, offset, dst.remaining_mut());
+        }
+        backend.read_at(dst, offset)
+    }
+
+    /// Fills the page cache ahead of a read of `len` bytes at `offset` if
+    /// the file is being read sequentially.
+    fn read_ahead(&self, cached: &CachedFile, offset: u64, len: usize) {
+        if cached.in_memory() {
+            return;
+        }
+        let Ok(size) = cached.location().len() else {
+            return;
+        };
+        let Some(range) = self.readahead.lock().on_read(offset, len as u64, size) else {
+            return;
+        };
+        // Failures surface when the data is actually read.
+        match cached.read_ahead(range) {
+            Ok(pages) => self.readahead.lock().stats.pages += pages as u64,
+            Err(err) => debug!("Readahead failed: {err:?}"),
+        }
+    }
+
+    /// Sets the expected access pattern, which restarts sequential read
+    /// detection.
+    pub fn advise(&self, advice: FileAdvice) {
+        let mut readahead = self.readahead.lock();
+        readahead.advice = advice;
+        readahead.reset();
+    }
+
+    /// Returns the readahead statistics of this open file.
+    pub fn readahead_stats(&self) -> ReadaheadStats {
+        self.readahead.lock().stats
     }
 
     /// Writes a number of bytes starting from a given offset.
     pub fn write_at(&self, src: impl Read + IoBuf, offset: u64) -> VfsResult<usize> {
-        self.access(FileFlags::WRITE)?.write_at(src, offset)
+        let backend = self.access(FileFlags::WRITE)?;
+        if self.flags.contains(FileFlags::DIRECT) {
+            backend.write_direct(src, offset)
+        } else {
+            backend.write_at(src, offset)
+        }
     }
 
     /// Attempts to sync OS-internal file content and metadata to disk.
@@ -857,6 +1430,22 @@ impl File {
         self.inner.sync(data_only)
     }
 
+    /// Returns the metadata of the inode this file was opened on.
+    pub fn metadata(&self) -> VfsResult<Metadata> {
+        self.location().metadata()
+    }
+
+    /// Updates the metadata of the inode this file was opened on.
+    ///
+    /// The inode is not looked up again by path, so this keeps affecting the
+    /// same file after it is renamed, replaced or unlinked. Files opened with
+    /// `O_PATH` are refused with [`VfsError::BadFileDescriptor`].
+    pub fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
+        self.access(FileFlags::empty())?
+            .location()
+            .update_metadata(update)
+    }
+
     pub fn read(&self, dst: impl Write + IoBufMut) -> kio::Result<usize> {
         #[cfg(feature = "times")]
         {
@@ -879,7 +1468,12 @@ impl File {
         }
         if let Some(pos) = self.position.as_ref() {
             let mut pos = pos.lock();
-            if let Ok(f) = self.access(FileFlags::APPEND) {
+            if self.flags.contains(FileFlags::APPEND | FileFlags::DIRECT) {
+                let len = self.location().len()?;
+                self.write_at(src, len).inspect(|n| {
+                    *pos = len + *n as u64;
+                })
+            } else if let Ok(f) = self.access(FileFlags::APPEND) {
                 f.append(src).map(|(written, new_size)| {
                     *pos = new_size;
                     written
@@ -932,6 +1526,9 @@ impl Seek for &File {
                     .checked_add_signed(off)
                     .ok_or(VfsError::InvalidInput)?,
             };
+            if new_pos != *guard {
+                self.readahead.lock().reset();
+            }
             *guard = new_pos;
             Ok(new_pos)
         } else {
@@ -950,21 +1547,31 @@ impl Pollable for File {
     }
 }
 
-#[cfg(feature = "times")]
 impl Drop for File {
     fn drop(&mut self) {
-        let flags = self.access_flags.load(Ordering::Acquire);
-        if flags != 0 {
-            let mut update = fs_ng_vfs::MetadataUpdate::default();
-            if flags & 1 != 0 {
-                update.atime = Some(khal::time::wall_time());
-            }
-            if flags & 2 != 0 {
-                update.mtime = Some(khal::time::wall_time());
-            }
-            if let Err(err) = self.inner.location().update_metadata(update) {
-                warn!("Failed to update file times on drop: {err:?}");
+        #[cfg(feature = "times")]
+        {
+            let flags = self.access_flags.load(Ordering::Acquire);
+            if flags != 0 && !self.location().mountpoint().is_read_only() {
+                let mut update = fs_ng_vfs::MetadataUpdate::default();
+                if flags & 1 != 0 {
+                    update.atime = Some(khal::time::wall_time());
+                }
+                if flags & 2 != 0 {
+                    update.mtime = Some(khal::time::wall_time());
+                }
+                if let Err(err) = self.inner.location().update_metadata(update) {
+                    warn!("Failed to update file times on drop: {err:?}");
+                }
             }
         }
+        if !self.is_path() {
+            let events = if self.flags.contains(FileFlags::WRITE) {
+                FsEvents::CLOSE_WRITE
+            } else {
+                FsEvents::CLOSE_NOWRITE
+            };
+            self.location().entry().notify(events);
+        }
     }
 }
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Firmware blobs for drivers, looked up in directories and archives.
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use fs_ng_vfs::{VfsError, VfsResult};
+use ksync::Mutex;
+
+#[cfg(feature = "archive")]
+use super::ArchiveReader;
+use super::ROOT_FS_CONTEXT;
+
+/// Directory searched when no source is configured, as on Linux.
+pub const DEFAULT_FIRMWARE_DIR: &str = "/lib/firmware";
+
+/// Where [`request_firmware`] looks for firmware.
+#[derive(Clone)]
+pub enum FirmwareSource {
+    /// A directory of the root filesystem, with firmware at paths relative
+    /// to it.
+    Directory(String),
+    /// An archive, with firmware as members.
+    #[cfg(feature = "archive")]
+    Archive(Arc<ArchiveReader>),
+}
+
+static SOURCES: Mutex<Vec<FirmwareSource>> = Mutex::new(Vec::new());
+
+/// Adds `source` after the configured ones.
+pub fn add_firmware_source(source: FirmwareSource) {
+    SOURCES.lock().push(source);
+}
+
+/// Returns the firmware `name`, such as `vendor/device.bin`, from the first
+/// configured source that has it.
+///
+/// Without a configured source, [`DEFAULT_FIRMWARE_DIR`] is searched. Names
+/// going up with `..` or starting with `/` fail with `EINVAL`.
+pub fn request_firmware(name: &str) -> VfsResult<Vec<u8>> {
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+        return Err(VfsError::InvalidInput);
+    }
+    let mut sources = SOURCES.lock().clone();
+    if sources.is_empty() {
+        sources.push(FirmwareSource::Directory(DEFAULT_FIRMWARE_DIR.into()));
+    }
+    for source in &sources {
+        let result = match source {
+            FirmwareSource::Directory(dir) => {
+                let Some(context) = ROOT_FS_CONTEXT.get() else {
+                    continue;
+                };
+                context.read(alloc::format!("{dir}/{name}"))
+            }
+            #[cfg(feature = "archive")]
+            FirmwareSource::Archive(archive) => {
+                archive.read_member_to_vec(name).map_err(VfsError::from)
+            }
+        };
+        match result {
+            Err(VfsError::NotFound) => continue,
+            Err(err) => {
+                warn!("firmware: failed to load {name}: {err}");
+                return Err(err);
+            }
+            Ok(data) => {
+                debug!("firmware: loaded {name}, {} bytes", data.len());
+                return Ok(data);
+            }
+        }
+    }
+    warn!("firmware: {name} not found");
+    Err(VfsError::NotFound)
+}
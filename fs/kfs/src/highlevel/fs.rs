@@ -12,13 +12,14 @@ use alloc::{
 };
 
 use fs_ng_vfs::{
-    Location, Metadata, NodePermission, NodeType, VfsError, VfsResult,
+    DeviceId, Location, Metadata, NodePermission, NodeType, VfsResult,
     path::{Path, PathBuf},
 };
 use ksync::Mutex;
 use ktypes::Once;
 
-use crate::PathResolver;
+use super::{FileStream, StreamMode};
+use crate::ResolveFlags;
 
 #[allow(dead_code)]
 /// Maximum symlink follow depth for legacy APIs.
@@ -90,6 +91,26 @@ impl FsContext {
         self.inner.set_current_dir(current_dir)
     }
 
+    /// Changes the root directory, leaving the current directory alone.
+    pub fn set_root_dir(&mut self, root_dir: Location) -> VfsResult<()> {
+        self.inner.set_root_dir(root_dir)
+    }
+
+    /// Returns the file mode creation mask.
+    pub fn umask(&self) -> u32 {
+        self.inner.umask()
+    }
+
+    /// Sets the file mode creation mask, returning the previous one.
+    pub fn set_umask(&mut self, mask: u32) -> u32 {
+        self.inner.set_umask(mask)
+    }
+
+    /// Clears the bits of `mode` set in the umask.
+    pub fn apply_umask(&self, mode: NodePermission) -> NodePermission {
+        self.inner.apply_umask(mode)
+    }
+
     /// Create a new context with a different current directory.
     pub fn with_current_dir(&self, current_dir: Location) -> VfsResult<Self> {
         Ok(Self {
@@ -102,34 +123,31 @@ impl FsContext {
         self.inner.resolve(path)
     }
 
-    /// Resolves a path starting from `current_dir`, without following symlinks.
+    /// Resolves a path starting from `current_dir`, without following a
+    /// symlink in the final component unless the path ends with a slash.
     pub fn resolve_no_follow(&self, path: impl AsRef<Path>) -> VfsResult<Location> {
         self.inner.resolve_no_follow(path)
     }
 
-    /// Resolves a path to its parent directory and entry name
+    /// Resolves a path starting from `current_dir`, treating a symlink in the
+    /// final component as `flags` say.
+    pub fn resolve_with_flags(
+        &self,
+        path: impl AsRef<Path>,
+        flags: ResolveFlags,
+    ) -> VfsResult<Location> {
+        self.inner.resolve_with_flags(path, flags)
+    }
+
     /// Resolve a path to its parent directory and entry name.
     pub fn resolve_parent<'a>(&self, path: &'a Path) -> VfsResult<(Location, Cow<'a, str>)> {
-        // Use inner resolver but convert String to Cow
-        let resolver = PathResolver::new();
-        let (dir, name) = resolver.resolve_parent(self.inner.current_dir(), path)?;
+        let (dir, name) = self.inner.resolve_parent(path)?;
         Ok((dir, Cow::Owned(name)))
     }
 
-    /// Resolves a path for a nonexistent entry
     /// Resolve a path that is expected not to exist.
     pub fn resolve_nonexistent<'a>(&self, path: &'a Path) -> VfsResult<(Location, &'a str)> {
-        // This method returns a lifetime-bound &str, so we need to use the path's lifetime
-        // We can only return a reference to something in the path itself
-        let entry_name = path.file_name().ok_or(VfsError::InvalidInput)?;
-        let mut components = path.components();
-        components.next_back();
-
-        let resolver = PathResolver::new();
-        let dir =
-            resolver.resolve_components_internal(self.inner.current_dir(), components, &mut 0)?;
-        dir.check_is_dir()?;
-        Ok((dir, entry_name))
+        self.inner.resolve_nonexistent(path)
     }
 
     /// Retrieves metadata for the file.
@@ -152,6 +170,11 @@ impl FsContext {
         self.inner.write(path, buf)
     }
 
+    /// Opens a file as a stream.
+    pub fn open_stream(&self, path: impl AsRef<Path>, mode: StreamMode) -> VfsResult<FileStream> {
+        FileStream::open(self, path, mode)
+    }
+
     /// Returns an iterator over the entries in a directory.
     pub fn read_dir(&self, path: impl AsRef<Path>) -> VfsResult<ReadDir> {
         self.inner.read_dir(path)
@@ -177,6 +200,17 @@ impl FsContext {
         self.inner.create_dir(path, mode)
     }
 
+    /// Creates a filesystem node (device, FIFO, socket or regular file).
+    pub fn mknod(
+        &self,
+        path: impl AsRef<Path>,
+        node_type: NodeType,
+        mode: NodePermission,
+        rdev: DeviceId,
+    ) -> VfsResult<Location> {
+        self.inner.mknod(path, node_type, mode, rdev)
+    }
+
     /// Creates a new hard link on the filesystem.
     pub fn link(
         &self,
@@ -3,9 +3,17 @@
 // See LICENSES for license details.
 
 //! High-level filesystem APIs (std-like wrappers).
+#[cfg(feature = "archive")]
+mod archive;
 mod file;
+mod firmware;
 mod fs;
+mod stream;
 
+#[cfg(feature = "archive")]
+pub use archive::*;
 pub use file::*;
+pub use firmware::*;
 // Re-export the wrapper FsContext for backward compatibility
 pub use fs::{FS_CONTEXT, FsContext, ROOT_FS_CONTEXT, ReadDir, ReadDirEntry};
+pub use stream::*;
@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Streaming access to files.
+use alloc::vec;
+
+use fs_ng_vfs::{Location, VfsResult, path::Path};
+use kio::{SeekFrom, prelude::*};
+
+use super::{File, FileFlags, FsContext, OpenOptions};
+
+/// Size of the buffer used by [`copy_stream`].
+pub const COPY_BUF_SIZE: usize = 16 * 1024;
+
+/// How [`FsContext::open_stream`] opens a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamMode {
+    /// Reads an existing file.
+    Read,
+    /// Reads and writes an existing file.
+    ReadWrite,
+    /// Writes a file, creating it or truncating it first.
+    Write,
+    /// Appends to a file, creating it if needed.
+    ///
+    /// Every write lands at the end of the file as it is when the write
+    /// happens, even with other appenders on the same file.
+    Append,
+}
+
+impl StreamMode {
+    fn options(self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        match self {
+            StreamMode::Read => options.read(true),
+            StreamMode::ReadWrite => options.read(true).write(true),
+            StreamMode::Write => options.write(true).create(true).truncate(true),
+            StreamMode::Append => options.append(true).create(true),
+        };
+        options
+    }
+}
+
+/// A file opened for streaming.
+///
+/// The stream keeps a position, which [`Read`], [`Write`] and [`Seek`] use
+/// and advance. [`FileStream::read_at`] and [`FileStream::write_at`] take
+/// an explicit offset instead and leave the position alone.
+pub struct FileStream {
+    file: File,
+}
+
+impl FileStream {
+    /// Opens the file at `path` in `mode`.
+    pub fn open(context: &FsContext, path: impl AsRef<Path>, mode: StreamMode) -> VfsResult<Self> {
+        mode.options()
+            .open(context, path.as_ref())?
+            .into_file()
+            .map(Self::new)
+    }
+
+    /// Wraps an open file.
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Reads at `offset` without moving the position.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    /// Writes at `offset` without moving the position.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        self.file.write_at(buf, offset)
+    }
+
+    /// Returns the current position.
+    pub fn position(&mut self) -> VfsResult<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+
+    /// Truncates or extends the file to `len` bytes.
+    pub fn set_len(&self, len: u64) -> VfsResult<()> {
+        self.file.access(FileFlags::WRITE)?.set_len(len)
+    }
+
+    /// Writes the file data and metadata back to the filesystem.
+    pub fn sync(&self) -> VfsResult<()> {
+        self.file.sync(false)
+    }
+
+    /// Returns the location of the file.
+    pub fn location(&self) -> &Location {
+        self.file.location()
+    }
+
+    /// Returns the underlying file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Unwraps the underlying file.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl Read for FileStream {
+    fn read(&mut self, buf: &mut [u8]) -> kio::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for FileStream {
+    fn write(&mut self, buf: &[u8]) -> kio::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> kio::Result {
+        self.file.flush()
+    }
+}
+
+impl Seek for FileStream {
+    fn seek(&mut self, pos: SeekFrom) -> kio::Result<u64> {
+        (&self.file).seek(pos)
+    }
+}
+
+/// Copies up to `limit` bytes from `src` to `dst`, returning how many were
+/// copied.
+///
+/// It stops early at the end of `src`. At most [`COPY_BUF_SIZE`] bytes are
+/// buffered whatever the limit.
+pub fn copy_stream<R, W>(src: &mut R, dst: &mut W, limit: u64) -> VfsResult<u64>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut buf = vec![0; limit.min(COPY_BUF_SIZE as u64) as usize];
+    let mut copied = 0;
+    while copied < limit {
+        let len = (limit - copied).min(buf.len() as u64) as usize;
+        let n = src.read(&mut buf[..len])?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok(copied)
+}
@@ -13,9 +13,26 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+mod test_archive;
+mod test_cpio;
+mod test_dcache;
+mod test_direct_io;
+mod test_fscrypt;
+mod test_latency;
+mod test_lock;
+mod test_mmap_shared;
+mod test_mount;
+mod test_notify;
 mod test_path_resolver;
+mod test_path_rules;
+mod test_readahead;
+mod test_scrub;
+mod test_squashfs;
+mod test_stream;
 mod test_working_context;
 
+use alloc::vec::Vec;
+
 use kdriver::{BlockDevice as KBlockDevice, DeviceContainer, prelude::*};
 
 #[cfg(feature = "fat")]
@@ -23,37 +40,64 @@ mod disk;
 #[cfg_attr(test, allow(dead_code))]
 pub(crate) mod fs;
 
+pub mod cpio;
+pub mod fscrypt;
+#[cfg(all(feature = "ext4", feature = "ext4-rsext4"))]
+pub mod scrub;
+
 // New refactored components
 mod fs_operations;
 mod path_resolver;
 mod working_context;
 
 mod highlevel;
+mod mount;
+mod partition;
 // Export new components (FsOperations for advanced use)
+pub use fs::MemoryFs;
+#[cfg(feature = "squashfs")]
+pub use fs::{FileImage, ImageSource, SquashFilesystem};
 pub use fs_operations::FsOperations;
 pub use highlevel::*;
-pub use path_resolver::PathResolver;
-pub use working_context::WorkingContext;
+pub use mount::{
+    MountFlags, UnmountFlags, bind_mount, block_devices, mount_blockdev, mount_tmpfs,
+    register_block_device, remount, umount,
+};
+pub use partition::Partition;
+pub use path_resolver::{PathResolver, ResolveFlags};
+pub use working_context::{DEFAULT_UMASK, WorkingContext};
 
 /// Initialize the filesystem subsystem and mount the root filesystem.
 pub fn init_filesystems(mut block_devs: DeviceContainer<KBlockDevice>) {
     info!("Initialize filesystem subsystem...");
+    fscrypt::init();
 
-    let dev = {
+    // Devices are named after their probing order, whichever is the root.
+    let default = {
         #[cfg(feature = "crosvm")]
         {
             // must have two block devices: secure and non-secure
             // we only use the second blk
-            block_devs
-                .take_nth(1)
-                .expect("Less than two block devices found!")
+            assert!(block_devs.len() >= 2, "Less than two block devices found!");
+            mount::block_device_name(1)
         }
         #[cfg(not(feature = "crosvm"))]
         {
-            block_devs.take_one().expect("No block device found!")
+            assert!(!block_devs.is_empty(), "No block device found!");
+            mount::block_device_name(block_devs.len() - 1)
         }
     };
-    info!("  use block device 0: {:?}", dev.name());
+    let mut devs = block_devs
+        .drain(..)
+        .enumerate()
+        .flat_map(|(i, dev)| partition::scan(mount::block_device_name(i), dev))
+        .collect::<Vec<_>>();
+    let dev = partition::take_root(&mut devs, platconfig::devices::ROOT, &default);
+    info!("  use block device {}", dev.name());
+    mount::register_root_device(&dev);
+    for dev in devs {
+        register_block_device(dev);
+    }
 
     let fs = fs::new_default(dev).expect("Failed to initialize filesystem");
     info!("  filesystem type: {:?}", fs.name());
@@ -61,3 +105,28 @@ pub fn init_filesystems(mut block_devs: DeviceContainer<KBlockDevice>) {
     let mp = fs_ng_vfs::Mountpoint::new_root(&fs);
     ROOT_FS_CONTEXT.call_once(|| FsContext::new(mp.root_location()));
 }
+
+/// Initialize the filesystem subsystem with the squashfs image at `image` in
+/// memory, such as one loaded by the bootloader, as the root filesystem.
+///
+/// All block devices are registered for mounting.
+#[cfg(feature = "squashfs")]
+pub fn init_filesystems_from_image(
+    image: &'static [u8],
+    mut block_devs: DeviceContainer<KBlockDevice>,
+) {
+    info!("Initialize filesystem subsystem...");
+    fscrypt::init();
+
+    for (i, dev) in block_devs.drain(..).enumerate() {
+        partition::scan(mount::block_device_name(i), dev)
+            .into_iter()
+            .for_each(register_block_device);
+    }
+
+    let fs = SquashFilesystem::from_memory(image).expect("Failed to initialize filesystem");
+    info!("  filesystem type: {:?}", fs.name());
+
+    let mp = fs_ng_vfs::Mountpoint::new_root(&fs);
+    ROOT_FS_CONTEXT.call_once(|| FsContext::new(mp.root_location()));
+}
@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Mounting block devices and tmpfs instances.
+//!
+//! Block devices not used for the root filesystem are kept in a registry by
+//! name (`vda`, `vda1`, `vdb`, ...), from which they are handed to the
+//! filesystem mounted on them. Filesystems own their device, so a device
+//! cannot be mounted again once a filesystem has been created on it, and
+//! neither can a disk with a mounted partition or the partitions of a
+//! mounted disk.
+//!
+//! Read-only filesystems, such as squashfs, may also be mounted from an image
+//! file, as app bundles are.
+//!
+//! Files and directories can be bound elsewhere, and such bind mounts made
+//! read-only on their own.
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use fs_ng_vfs::{NodeType, VfsError, VfsResult, path::Path};
+use kdriver::prelude::*;
+use ksync::Mutex;
+
+use crate::{
+    FsContext, OpenOptions, fs,
+    partition::{Extent, Partition},
+};
+
+bitflags::bitflags! {
+    /// Flags for mounting a block device, with the values of `mount(2)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MountFlags: u32 {
+        /// Mount read-only. Only supported by read-only filesystems, but bind
+        /// mounts of any filesystem may be remounted read-only.
+        const RDONLY = 1;
+        /// Ignore set-user-ID and set-group-ID bits. Accepted, not enforced.
+        const NOSUID = 2;
+        /// Disallow access to device files. Accepted, not enforced.
+        const NODEV = 4;
+        /// Disallow program execution. Accepted, not enforced.
+        const NOEXEC = 8;
+        /// Make writes synchronous. Accepted, not enforced.
+        const SYNCHRONOUS = 16;
+        /// Change the flags of an existing mount, see [`remount`].
+        const REMOUNT = 32;
+        /// Make a file or directory visible elsewhere, see [`bind_mount`].
+        const BIND = 1 << 12;
+        /// Bind the mounts below the source as well.
+        const REC = 1 << 14;
+        /// Suppress warnings.
+        const SILENT = 1 << 15;
+        /// Do not update access times.
+        const NOATIME = 1 << 10;
+        /// Do not update directory access times.
+        const NODIRATIME = 1 << 11;
+        /// Update access times relative to modification times.
+        const RELATIME = 1 << 21;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags for unmounting, with the values of `umount2(2)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UnmountFlags: u32 {
+        /// Force the unmount. Has no additional effect.
+        const FORCE = 1;
+        /// Detach the mount even if it is busy; it is released once unused.
+        const DETACH = 2;
+        /// Do not follow a symlink at the target.
+        const NOFOLLOW = 8;
+    }
+}
+
+/// A registered block device.
+struct Registered {
+    /// The device, or `None` once handed to a filesystem.
+    dev: Option<Partition>,
+    /// The blocks of the disk the device covers.
+    extent: Extent,
+}
+
+/// Registered block devices by name.
+static BLOCK_DEVICES: Mutex<BTreeMap<String, Registered>> = Mutex::new(BTreeMap::new());
+
+/// Returns the conventional name of the `index`-th block device.
+pub(crate) fn block_device_name(index: usize) -> String {
+    let mut suffix = Vec::new();
+    let mut n = index + 1;
+    while n > 0 {
+        n -= 1;
+        suffix.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    suffix.reverse();
+    let mut name = "vd".to_string();
+    name.extend(suffix.into_iter().map(char::from));
+    name
+}
+
+/// Makes `dev` available for mounting under its name.
+pub fn register_block_device(dev: Partition) {
+    let name = dev.name().to_string();
+    info!("  registered block device {name}");
+    let extent = dev.extent();
+    BLOCK_DEVICES.lock().insert(
+        name,
+        Registered {
+            dev: Some(dev),
+            extent,
+        },
+    );
+}
+
+/// Records `dev`, which the root filesystem has been created on, as in use.
+pub(crate) fn register_root_device(dev: &Partition) {
+    let registered = Registered {
+        dev: None,
+        extent: dev.extent(),
+    };
+    BLOCK_DEVICES.lock().insert(dev.name().into(), registered);
+}
+
+/// Returns the names of the registered block devices.
+pub fn block_devices() -> Vec<String> {
+    BLOCK_DEVICES.lock().keys().cloned().collect()
+}
+
+/// Mounts the block device `dev` with a filesystem of type `fs_type` at
+/// `path`.
+///
+/// `dev` is a registered name such as `vdb` or `vdb1`, optionally prefixed by
+/// `/dev/`. For read-only filesystem types, it may instead be the path of an
+/// image file. Fails with `ENODEV` for unsupported filesystem types, `EINVAL`
+/// if the device does not contain such a filesystem, and `EBUSY` if it shares
+/// blocks with a mounted device.
+pub fn mount_blockdev(
+    context: &FsContext,
+    path: impl AsRef<Path>,
+    dev: &str,
+    fs_type: &str,
+    flags: MountFlags,
+) -> VfsResult<()> {
+    let read_only = fs::is_read_only(fs_type);
+    if flags.contains(MountFlags::RDONLY) && !read_only {
+        return Err(VfsError::OperationNotSupported);
+    }
+    if !fs::is_supported(fs_type) {
+        return Err(VfsError::NoSuchDevice);
+    }
+    let target = context.resolve(path)?;
+    target.check_is_dir()?;
+
+    let name = dev.strip_prefix("/dev/").unwrap_or(dev);
+    if read_only && !BLOCK_DEVICES.lock().contains_key(name) {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(context, dev)?
+            .into_file()?;
+        if file.location().node_type() != NodeType::RegularFile {
+            return Err(VfsError::InvalidInput);
+        }
+        let filesystem = fs::new_on_file(fs_type, file)?;
+        target
+            .mount(&filesystem)?
+            .set_read_only(flags.contains(MountFlags::RDONLY));
+        info!(
+            "Mounted {fs_type} image {dev} at {:?}",
+            target.absolute_path()?
+        );
+        return Ok(());
+    }
+    let device = {
+        let mut devices = BLOCK_DEVICES.lock();
+        let extent = devices.get(name).ok_or(VfsError::NotFound)?.extent;
+        if devices
+            .values()
+            .any(|it| it.dev.is_none() && it.extent.overlaps(&extent))
+        {
+            return Err(VfsError::ResourceBusy);
+        }
+        let slot = &mut devices.get_mut(name).unwrap().dev;
+        if !fs::probe(fs_type, slot.as_mut().unwrap())? {
+            return Err(VfsError::InvalidInput);
+        }
+        slot.take().unwrap()
+    };
+    let filesystem = fs::new_by_type(fs_type, device)?;
+    target
+        .mount(&filesystem)?
+        .set_read_only(flags.contains(MountFlags::RDONLY));
+    info!(
+        "Mounted {fs_type} on {name} at {:?}",
+        target.absolute_path()?
+    );
+    Ok(())
+}
+
+/// Mounts a new tmpfs at `path`, whose file contents may take up to
+/// `size_limit` bytes.
+pub fn mount_tmpfs(
+    context: &FsContext,
+    path: impl AsRef<Path>,
+    size_limit: Option<u64>,
+) -> VfsResult<()> {
+    let target = context.resolve(path)?;
+    target.check_is_dir()?;
+    target.mount(&fs::MemoryFs::with_size_limit(size_limit))?;
+    Ok(())
+}
+
+/// Makes the file or directory at `source` also appear at `path`, which must
+/// be of the same kind.
+///
+/// The bind mount shares the inodes of `source` and starts out with the
+/// flags of its mount. With `recursive`, the mounts below `source` are bound
+/// as well.
+pub fn bind_mount(
+    context: &FsContext,
+    source: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+    recursive: bool,
+) -> VfsResult<()> {
+    let source = context.resolve(source)?;
+    let target = context.resolve(path)?;
+    target.bind_mount(&source, recursive)?;
+    info!(
+        "Bound {:?} at {:?}",
+        source.absolute_path()?,
+        target.absolute_path()?
+    );
+    Ok(())
+}
+
+/// Changes the flags of the mount at `path`.
+///
+/// Only whether the mount is read-only can be changed, and only for the
+/// mount itself with [`MountFlags::BIND`], which makes it possible to have a
+/// read-only bind mount of a writable filesystem. Changing the flags of the
+/// filesystem fails with `EOPNOTSUPP`.
+pub fn remount(context: &FsContext, path: impl AsRef<Path>, flags: MountFlags) -> VfsResult<()> {
+    let target = context.resolve(path)?;
+    if !target.is_root_of_mount() {
+        return Err(VfsError::InvalidInput);
+    }
+    let read_only = flags.contains(MountFlags::RDONLY);
+    let mountpoint = target.mountpoint();
+    if flags.contains(MountFlags::BIND) {
+        mountpoint.set_read_only(read_only);
+    } else if mountpoint.is_read_only() != read_only {
+        return Err(VfsError::OperationNotSupported);
+    }
+    Ok(())
+}
+
+/// Unmounts the filesystem mounted at `path`.
+///
+/// Fails with `EBUSY` if anything under the mount is in use, such as open
+/// files, working directories or nested mounts, unless
+/// [`UnmountFlags::DETACH`] is given.
+pub fn umount(context: &FsContext, path: impl AsRef<Path>, flags: UnmountFlags) -> VfsResult<()> {
+    let target = if flags.contains(UnmountFlags::NOFOLLOW) {
+        context.resolve_no_follow(path)?
+    } else {
+        context.resolve(path)?
+    };
+    if !target.is_root_of_mount() {
+        return Err(VfsError::InvalidInput);
+    }
+    if target.is_root() {
+        return Err(VfsError::ResourceBusy);
+    }
+    if flags.contains(UnmountFlags::DETACH) {
+        return target.detach();
+    }
+    if target.is_mount_busy() {
+        return Err(VfsError::ResourceBusy);
+    }
+    target.filesystem().flush()?;
+    target.unmount()
+}
@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Block devices as filesystems see them: whole disks and their partitions.
+//!
+//! Every disk is scanned for a partition table when the filesystem subsystem
+//! starts. The disk itself and each partition on it become a [`Partition`],
+//! named as Linux names them (`vda`, `vda1`, ...). They share the disk, and
+//! a partition fails requests outside of its own blocks with `EIO` instead of
+//! passing them on to its neighbours.
+//!
+//! Requests go through the [request queue](BlockQueue) of the disk, where
+//! those of adjacent blocks are merged. Besides [`BlockDriverOps`], which
+//! blocks until each request is done, filesystems may
+//! [`submit`](Partition::submit) several requests before waiting for them,
+//! or [`read_at`](Partition::read_at) and [`write_at`](Partition::write_at)
+//! any range of bytes, leaving partial sectors to the block layer.
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+
+use kdriver::{
+    BlockDevice as KBlockDevice,
+    partition::{self, Guid, PartitionInfo, PartitionType},
+    prelude::*,
+    queue::{Bio, BioHandle, BioOp, BlockQueue, BlockQueueStats},
+};
+
+/// A whole disk or one of its partitions.
+pub struct Partition {
+    disk: Arc<BlockQueue<KBlockDevice>>,
+    name: String,
+    block_size: usize,
+    /// The partition, or `None` for the whole disk.
+    info: Option<PartitionInfo>,
+    num_blocks: u64,
+}
+
+impl Partition {
+    /// Wraps the whole disk `dev`, named `name`.
+    pub fn disk(name: String, dev: KBlockDevice) -> Self {
+        Self {
+            block_size: dev.block_size(),
+            num_blocks: dev.num_blocks(),
+            disk: Arc::new(BlockQueue::new(dev)),
+            name,
+            info: None,
+        }
+    }
+
+    /// Returns the partition `info` of the same disk.
+    fn partition(&self, info: PartitionInfo) -> Self {
+        Self {
+            disk: self.disk.clone(),
+            name: partition_name(&self.name, info.number),
+            block_size: self.block_size,
+            info: Some(info),
+            num_blocks: info.blocks,
+        }
+    }
+
+    /// Returns the partition number, or `None` for a whole disk.
+    pub fn number(&self) -> Option<u32> {
+        self.info.map(|it| it.number)
+    }
+
+    /// Returns the partition type, or `None` for a whole disk.
+    pub fn kind(&self) -> Option<PartitionType> {
+        self.info.map(|it| it.kind)
+    }
+
+    /// Returns the unique partition GUID of a GPT partition.
+    pub fn uuid(&self) -> Option<Guid> {
+        self.info.and_then(|it| it.uuid)
+    }
+
+    /// Returns the blocks of the disk this device covers.
+    pub(crate) fn extent(&self) -> Extent {
+        let start = self.info.map_or(0, |it| it.start);
+        Extent {
+            disk: Arc::as_ptr(&self.disk) as usize,
+            start,
+            end: start + self.num_blocks,
+        }
+    }
+
+    /// Returns whether this device is what the root setting `root` names:
+    /// either its name, optionally prefixed by `/dev/`, or `PARTUUID=`
+    /// followed by its unique GUID.
+    pub(crate) fn matches(&self, root: &str) -> bool {
+        match root.strip_prefix("PARTUUID=") {
+            Some(uuid) => Guid::parse(uuid).is_some_and(|uuid| self.uuid() == Some(uuid)),
+            None => root.strip_prefix("/dev/").unwrap_or(root) == self.name,
+        }
+    }
+
+    /// Returns the sector sizes and the preferred request size of the disk.
+    pub fn limits(&self) -> BlockLimits {
+        self.disk.limits()
+    }
+
+    /// Returns the statistics of the request queue of the disk, which its
+    /// partitions share.
+    pub fn queue_stats(&self) -> BlockQueueStats {
+        self.disk.stats()
+    }
+
+    /// Queues `bio` without waiting for it, see [`wait`](Self::wait).
+    ///
+    /// The request fails with `EIO` if the blocks are outside of the device.
+    pub fn submit(&self, mut bio: Bio) -> BioHandle {
+        if bio.op != BioOp::Flush {
+            match self.request(bio.block_id, bio.buf.len()) {
+                Ok(block_id) => bio.block_id = block_id,
+                Err(err) => return BioHandle::failed(bio, err),
+            }
+        }
+        self.disk.submit(bio)
+    }
+
+    /// Waits for a request queued by [`submit`](Self::submit), and returns
+    /// its result and buffer.
+    pub fn wait(&self, handle: BioHandle) -> (DriverResult, Vec<u8>) {
+        self.disk.wait(handle)
+    }
+
+    /// Reads blocks like [`BlockDriverOps::read_block`], retrying transient
+    /// failures.
+    pub fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+        let block_id = self.request(block_id, buf.len())?;
+        self.disk.read(block_id, buf)
+    }
+
+    /// Writes blocks like [`BlockDriverOps::write_block`], retrying transient
+    /// failures.
+    pub fn write_blocks(&self, block_id: u64, buf: &[u8]) -> DriverResult {
+        let block_id = self.request(block_id, buf.len())?;
+        self.disk.write(block_id, buf)
+    }
+
+    /// Reads `buf.len()` bytes at byte `offset` of the device, whatever their
+    /// alignment.
+    ///
+    /// Fails with `EIO` if the bytes are outside of the device.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> DriverResult {
+        let offset = self.byte_request(offset, buf.len())?;
+        self.disk.read_at(offset, buf)
+    }
+
+    /// Writes `buf` at byte `offset` of the device, whatever its alignment.
+    ///
+    /// Fails with `EIO` if the bytes are outside of the device.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> DriverResult {
+        let offset = self.byte_request(offset, buf.len())?;
+        self.disk.write_at(offset, buf)
+    }
+
+    /// Flushes the disk like [`BlockDriverOps::flush`] once the requests
+    /// queued before are done, retrying transient failures.
+    pub fn flush_disk(&self) -> DriverResult {
+        self.disk.flush()
+    }
+
+    fn request(&self, block_id: u64, len: usize) -> DriverResult<u64> {
+        match &self.info {
+            Some(info) => info.translate(block_id, len, self.block_size),
+            None => Ok(block_id),
+        }
+    }
+
+    /// Returns the byte of the disk that a request of `len` bytes at byte
+    /// `offset` of the device starts at.
+    fn byte_request(&self, offset: u64, len: usize) -> DriverResult<u64> {
+        let block_size = self.block_size as u64;
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.num_blocks * block_size => {
+                Ok(self.info.map_or(0, |it| it.start) * block_size + offset)
+            }
+            _ => Err(DriverError::Io),
+        }
+    }
+}
+
+/// A range of blocks of a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Extent {
+    disk: usize,
+    start: u64,
+    end: u64,
+}
+
+impl Extent {
+    /// Returns whether the two ranges share blocks.
+    pub(crate) fn overlaps(&self, other: &Extent) -> bool {
+        self.disk == other.disk && self.start < other.end && other.start < self.end
+    }
+}
+
+impl DriverOps for Partition {
+    /// Returns the name, such as `vda` or `vda1`.
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn device_kind(&self) -> DeviceKind {
+        DeviceKind::Block
+    }
+}
+
+impl BlockDriverOps for Partition {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DriverResult {
+        self.read_blocks(block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DriverResult {
+        self.write_blocks(block_id, buf)
+    }
+
+    fn flush(&mut self) -> DriverResult {
+        self.flush_disk()
+    }
+}
+
+/// Returns the name of partition `number` of the disk `disk`: `vda1`, or
+/// `nvme0n1p1` if the disk name ends in a digit.
+pub(crate) fn partition_name(disk: &str, number: u32) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{disk}p{number}")
+    } else {
+        format!("{disk}{number}")
+    }
+}
+
+/// Wraps the disk `dev` named `name`, and returns it followed by its
+/// partitions.
+///
+/// A disk whose partition table cannot be read is used as a whole.
+pub(crate) fn scan(name: String, mut dev: KBlockDevice) -> Vec<Partition> {
+    let partitions = partition::scan(&mut dev).unwrap_or_else(|err| {
+        warn!("  cannot read the partition table of {name}: {err}");
+        Vec::new()
+    });
+    let mut devices = vec![Partition::disk(name, dev)];
+    for info in partitions {
+        let partition = devices[0].partition(info);
+        info!(
+            "  {}: {} blocks at {}, type {}",
+            partition.name, info.blocks, info.start, info.kind
+        );
+        devices.push(partition);
+    }
+    devices
+}
+
+/// Picks the device of the root filesystem among `devices`, as the scan of
+/// each disk returned them, and removes it.
+///
+/// With an empty `root` setting, the first Linux partition of the disk
+/// `default` is used, or the disk itself if it has none.
+pub(crate) fn take_root(devices: &mut Vec<Partition>, root: &str, default: &str) -> Partition {
+    let index = if root.is_empty() {
+        let disk = devices
+            .iter()
+            .position(|it| it.name == default)
+            .expect("No block device found!");
+        let extent = devices[disk].extent();
+        devices
+            .iter()
+            .position(|it| {
+                it.extent().overlaps(&extent) && it.kind().is_some_and(|kind| kind.is_linux())
+            })
+            .unwrap_or(disk)
+    } else {
+        devices
+            .iter()
+            .position(|it| it.matches(root))
+            .unwrap_or_else(|| panic!("Root device {root} not found!"))
+    };
+    devices.remove(index)
+}
+
+#[cfg(unittest)]
+mod tests_partition {
+    use unittest::{assert_eq, def_test};
+
+    use super::*;
+
+    #[def_test]
+    fn test_partition_name() {
+        assert_eq!(partition_name("vda", 1), "vda1");
+        assert_eq!(partition_name("vdab", 12), "vdab12");
+        assert_eq!(partition_name("nvme0n1", 2), "nvme0n1p2");
+    }
+}
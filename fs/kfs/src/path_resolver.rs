@@ -5,23 +5,100 @@
 //! Path resolution module
 //!
 //! Provides stateless path resolution functionality, separated from filesystem operations.
+//!
+//! # Trailing slashes
+//!
+//! A path ending with a slash, or with `/.`, names a directory. What that
+//! means depends on the operation, as on Linux:
+//!
+//! | Operation                              | Final component          | Result               |
+//! |----------------------------------------|--------------------------|----------------------|
+//! | lookup (`open`, `stat`, `chdir`, ...)  | not a directory          | `ENOTDIR`            |
+//! | lookup not following (`lstat`, ...)    | symlink                  | followed             |
+//! | `open` with `O_CREAT`                  | any                      | `EISDIR`             |
+//! | `mkdir`                                | missing                  | created              |
+//! | `mknod`, `symlink`, new path of `link` | missing / existing       | `ENOENT` / `EEXIST`  |
+//! | `unlink`                               | directory / other        | `EISDIR` / `ENOTDIR` |
+//! | `rmdir`                                | not a directory, symlink | `ENOTDIR`            |
+//! | `rename`, on either path               | source not a directory   | `ENOTDIR`            |
+//!
+//! Paths are checked for length before they get here, see
+//! [`verify_path`](fs_ng_vfs::path::verify_path).
 
-use alloc::{borrow::ToOwned, string::String};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    string::String,
+    vec::Vec,
+};
 
 use fs_ng_vfs::{
     Location, NodeType, VfsError, VfsResult,
-    path::{Component, Components, Path, PathBuf},
+    path::{Component, Components, Path},
 };
 
 /// Default maximum symlink follow depth
 pub const DEFAULT_MAX_SYMLINKS: usize = 40;
 
+bitflags::bitflags! {
+    /// Flags controlling how [`PathResolver`] treats the final component of a
+    /// path. Symlinks in the other components are always followed.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ResolveFlags: u8 {
+        /// Do not follow a symlink in the final component, as for `unlink`
+        /// or `O_NOFOLLOW`.
+        const NO_FOLLOW = 1;
+        /// Follow a symlink in the final component despite `NO_FOLLOW` if
+        /// the path ends with a slash, as for `lstat`.
+        const FOLLOW_TRAILING_SLASH = 2;
+    }
+}
+
+/// A step of the walk of a path.
+enum Step<'a> {
+    /// Go to the root.
+    Root,
+    /// Go to the parent, but not above the root.
+    Parent,
+    /// Look up a name, following a symlink.
+    Name(Cow<'a, str>),
+    /// Require a directory, after a symlink target ending with a slash.
+    Dir,
+}
+
+impl<'a> Step<'a> {
+    fn new(component: Component<'a>) -> Option<Self> {
+        match component {
+            Component::RootDir => Some(Self::Root),
+            Component::CurDir => None,
+            Component::ParentDir => Some(Self::Parent),
+            Component::Normal(name) => Some(Self::Name(Cow::Borrowed(name))),
+        }
+    }
+
+    fn into_owned(self) -> Step<'static> {
+        match self {
+            Self::Root => Step::Root,
+            Self::Parent => Step::Parent,
+            Self::Name(name) => Step::Name(Cow::Owned(name.into_owned())),
+            Self::Dir => Step::Dir,
+        }
+    }
+}
+
 /// Path resolver - stateless path resolution logic
 ///
 /// This component handles all path resolution logic including:
 /// - Absolute and relative path resolution
 /// - Symlink following with loop detection
 /// - Path component normalization (`.` and `..`)
+///
+/// Resolution is a loop over a stack of steps rather than a recursion into
+/// symlink targets, so neither long paths nor deep symlinks grow the kernel
+/// stack; the symlink limit bounds the work.
+///
+/// Absolute paths and symlink targets start from the `root` passed in, and
+/// `..` never leaves it. Relative symlink targets start from the directory
+/// holding the symlink. Either may cross mountpoints.
 #[derive(Debug, Clone)]
 pub struct PathResolver {
     max_symlinks: usize,
@@ -45,59 +122,52 @@ impl PathResolver {
     /// Resolves a path starting from the given base location
     ///
     /// # Arguments
+    /// * `root` - The root directory of absolute paths
     /// * `base` - The base directory to resolve from (typically root or cwd)
     /// * `path` - The path to resolve
-    /// * `follow_symlinks` - Whether to follow symlinks
+    /// * `flags` - How to treat a symlink in the final component
     ///
     /// # Returns
-    /// The resolved `Location`, or an error if the path doesn't exist or a loop is detected
+    /// The resolved `Location`, or an error if the path doesn't exist or more
+    /// symlinks than the limit are met (`FilesystemLoop`)
     pub fn resolve(
         &self,
+        root: &Location,
         base: &Location,
         path: &Path,
-        follow_symlinks: bool,
+        flags: ResolveFlags,
     ) -> VfsResult<Location> {
         let mut follow_count = 0;
-        self.resolve_with_count(base, path, follow_symlinks, &mut follow_count)
-    }
-
-    /// Internal resolution with symlink counter
-    fn resolve_with_count(
-        &self,
-        base: &Location,
-        path: &Path,
-        follow_symlinks: bool,
-        follow_count: &mut usize,
-    ) -> VfsResult<Location> {
-        let entry_name = path.file_name();
-        let mut components = path.components();
-
-        // If path has a file name, we need to resolve parent first
-        if entry_name.is_some() {
-            components.next_back();
-        }
-
-        let dir = self.resolve_components(base, components, follow_count)?;
-        dir.check_is_dir()?;
+        let (dir, name) = self.resolve_inner(root, base, path, &mut follow_count)?;
+        let Some(name) = name else {
+            return Ok(dir);
+        };
 
-        match entry_name {
-            Some(name) => {
-                if follow_symlinks {
-                    self.lookup(&dir, name, follow_count)
-                } else {
-                    dir.lookup_no_follow(name)
-                }
-            }
-            None => Ok(dir),
+        let trailing_slash = path.has_trailing_slash();
+        let follow = !flags.contains(ResolveFlags::NO_FOLLOW)
+            || (trailing_slash && flags.contains(ResolveFlags::FOLLOW_TRAILING_SLASH));
+        let loc = if follow {
+            self.lookup(root, &dir, name, &mut follow_count)?
+        } else {
+            dir.lookup_no_follow(name)?
+        };
+        if trailing_slash {
+            loc.check_is_dir()?;
         }
+        Ok(loc)
     }
 
     /// Resolves a path to its parent directory and entry name
     ///
     /// # Returns
     /// `(parent_directory, entry_name)` tuple
-    pub fn resolve_parent(&self, base: &Location, path: &Path) -> VfsResult<(Location, String)> {
-        let (dir, name) = self.resolve_inner(base, path, &mut 0)?;
+    pub fn resolve_parent(
+        &self,
+        root: &Location,
+        base: &Location,
+        path: &Path,
+    ) -> VfsResult<(Location, String)> {
+        let (dir, name) = self.resolve_inner(root, base, path, &mut 0)?;
         if let Some(name) = name {
             Ok((dir, name.to_owned()))
         } else if let Some(parent) = dir.parent() {
@@ -112,10 +182,11 @@ impl PathResolver {
     /// Verifies that the parent directory exists but the entry doesn't
     pub fn resolve_nonexistent<'a>(
         &self,
+        root: &Location,
         base: &Location,
         path: &'a Path,
     ) -> VfsResult<(Location, &'a str)> {
-        let (dir, name) = self.resolve_inner(base, path, &mut 0)?;
+        let (dir, name) = self.resolve_inner(root, base, path, &mut 0)?;
         if let Some(name) = name {
             Ok((dir, name))
         } else {
@@ -123,9 +194,11 @@ impl PathResolver {
         }
     }
 
-    /// Internal helper for resolve_parent and resolve_nonexistent
+    /// Resolves all components of a path but the final one, which is
+    /// returned unless it is `.`, `..` or the root.
     fn resolve_inner<'a>(
         &self,
+        root: &Location,
         base: &Location,
         path: &'a Path,
         follow_count: &mut usize,
@@ -135,7 +208,7 @@ impl PathResolver {
         if entry_name.is_some() {
             components.next_back();
         }
-        let dir = self.resolve_components(base, components, follow_count)?;
+        let dir = self.resolve_components(root, base, components, follow_count)?;
         dir.check_is_dir()?;
         Ok((dir, entry_name))
     }
@@ -144,83 +217,87 @@ impl PathResolver {
     #[doc(hidden)]
     pub fn resolve_components_internal(
         &self,
+        root: &Location,
         base: &Location,
         components: Components,
         follow_count: &mut usize,
     ) -> VfsResult<Location> {
-        self.resolve_components(base, components, follow_count)
+        self.resolve_components(root, base, components, follow_count)
     }
 
-    /// Resolves path components iteratively
+    /// Resolves path components iteratively, following all symlinks
     fn resolve_components(
         &self,
+        root: &Location,
         base: &Location,
         components: Components,
         follow_count: &mut usize,
     ) -> VfsResult<Location> {
-        let mut current = base.clone();
-
-        for comp in components {
-            match comp {
-                Component::CurDir => {
-                    // `.` - stay in current directory
-                }
-                Component::ParentDir => {
-                    // `..` - go to parent
-                    current = current.parent().unwrap_or_else(|| base.clone());
-                }
-                Component::RootDir => {
-                    // `/` - go to root
-                    current = self.find_root(&current);
-                }
-                Component::Normal(name) => {
-                    // Regular component - lookup and potentially follow symlink
-                    current = self.lookup(&current, name, follow_count)?;
-                }
-            }
-        }
-
-        Ok(current)
+        let steps = components.rev().filter_map(Step::new).collect();
+        self.walk(root, base, steps, follow_count)
     }
 
     /// Looks up a name in a directory and follows symlinks if needed
-    fn lookup(&self, dir: &Location, name: &str, follow_count: &mut usize) -> VfsResult<Location> {
-        let loc = dir.lookup_no_follow(name)?;
-        self.try_resolve_symlink(dir, loc, follow_count)
+    fn lookup(
+        &self,
+        root: &Location,
+        dir: &Location,
+        name: &str,
+        follow_count: &mut usize,
+    ) -> VfsResult<Location> {
+        let steps = alloc::vec![Step::Name(Cow::Borrowed(name))];
+        self.walk(root, dir, steps, follow_count)
     }
 
-    /// Attempts to resolve a symlink
-    fn try_resolve_symlink(
+    /// Takes `steps` from the top of the stack starting at `base`. The steps
+    /// of the target of a symlink met are pushed in its place, so relative
+    /// targets start from the directory holding the symlink.
+    fn walk(
         &self,
+        root: &Location,
         base: &Location,
-        loc: Location,
+        mut steps: Vec<Step<'_>>,
         follow_count: &mut usize,
     ) -> VfsResult<Location> {
-        if loc.node_type() != NodeType::Symlink {
-            return Ok(loc);
-        }
-
-        if *follow_count >= self.max_symlinks {
-            return Err(VfsError::FilesystemLoop);
-        }
-
-        *follow_count += 1;
-        let target = loc.read_link()?;
-        if target.is_empty() {
-            return Err(VfsError::NotFound);
-        }
-
-        // Resolve the symlink target
-        self.resolve_components(base, PathBuf::from(target).components(), follow_count)
-    }
-
-    /// Finds the root of the filesystem
-    fn find_root(&self, loc: &Location) -> Location {
-        let mut current = loc.clone();
-        while let Some(parent) = current.parent() {
-            current = parent;
+        let mut current = base.clone();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Root => current = root.clone(),
+                Step::Parent => {
+                    // `..` crosses back out of mounts; `..` of the root is the
+                    // root itself. Only a directory has one: `file/..` fails.
+                    current.check_is_dir()?;
+                    if !current.ptr_eq(root)
+                        && let Some(parent) = current.parent()
+                    {
+                        current = parent;
+                    }
+                }
+                Step::Name(name) => {
+                    let loc = current.lookup_no_follow(&name)?;
+                    if loc.node_type() != NodeType::Symlink {
+                        current = loc;
+                        continue;
+                    }
+                    if *follow_count >= self.max_symlinks {
+                        return Err(VfsError::FilesystemLoop);
+                    }
+                    *follow_count += 1;
+                    let target = loc.read_link()?;
+                    if target.is_empty() {
+                        return Err(VfsError::NotFound);
+                    }
+                    let target = Path::new(&target);
+                    if target.has_trailing_slash() {
+                        steps.push(Step::Dir);
+                    }
+                    let target_steps = target.components().rev().filter_map(Step::new);
+                    steps.extend(target_steps.map(Step::into_owned));
+                }
+                Step::Dir => current.check_is_dir()?,
+            }
         }
-        current
+        Ok(current)
     }
 }
 
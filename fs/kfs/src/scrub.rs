@@ -0,0 +1,327 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Background consistency scrubbing of ext4 filesystems.
+//!
+//! A task at the lowest priority walks a mounted filesystem a few blocks at
+//! a time (see [`rsext4::scrub`]): superblocks and group descriptors and
+//! their backups, the orphan list, inode checksums, extent trees, directory
+//! entries and, on filesystems with `metadata_csum`, the checksums of every
+//! metadata block. Reads go through the filesystem caches, are capped at a
+//! configured bandwidth and pause while foreground operations are running.
+//!
+//! Findings are logged and kept in a report, see [`report`]. With repair
+//! enabled, backup superblocks and group descriptors are rewritten from the
+//! primary copy and unused inodes at the head of the orphan list are freed;
+//! anything else is left to an offline fsck.
+//!
+//! The position is saved to a state file on the scrubbed filesystem, so
+//! that a pass over a large filesystem completes across reboots.
+use alloc::{
+    collections::VecDeque,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{fmt::Write, time::Duration};
+
+use fs_ng_vfs::{VfsError, VfsResult};
+use ksync::Mutex;
+use rsext4::scrub::{Finding, SCRUB_STATE_SIZE, ScrubCursor};
+
+use crate::{
+    File, ROOT_FS_CONTEXT,
+    fs::{Rsext4Filesystem, Rsext4Inode},
+};
+
+/// Default read bandwidth, in bytes per second.
+const DEFAULT_BANDWIDTH: u64 = 1 << 20;
+/// Default pause between two passes.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Name of the state file at the root of the scrubbed filesystem.
+const STATE_FILE: &str = ".scrub";
+/// Blocks read in one step, with the filesystem locked.
+const STEP_BLOCKS: u32 = 16;
+/// Steps between two saves of the state.
+const SAVE_STEPS: u32 = 256;
+/// How long to wait for the filesystem to be mounted.
+const MOUNT_RETRY: Duration = Duration::from_secs(5);
+/// First and longest pause while foreground operations are running.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+/// Findings kept in the report.
+const MAX_FINDINGS: usize = 256;
+
+/// Configuration of the scrubber.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrubConfig<'a> {
+    /// Mountpoint of the filesystem to scrub.
+    pub mountpoint: &'a str,
+    /// Read bandwidth, in bytes per second.
+    pub bandwidth: u64,
+    /// Whether to repair what can be repaired safely.
+    pub repair: bool,
+    /// Path of the state file, by default at the root of the filesystem.
+    pub state_path: Option<&'a str>,
+    /// Pause between two passes.
+    pub interval: Duration,
+}
+
+impl<'a> ScrubConfig<'a> {
+    /// Picks the configuration from a kernel command line.
+    ///
+    /// `scrub=<mountpoint>[,bw=<bytes>][,repair][,state=<path>][,interval=<secs>]`
+    /// scrubs the filesystem at `mountpoint`; the bandwidth is per second and
+    /// takes a `K`, `M` or `G` suffix. Without the argument, nothing is
+    /// scrubbed.
+    pub fn from_cmdline(cmdline: &'a str) -> Option<Self> {
+        let value = cmdline
+            .split_ascii_whitespace()
+            .filter_map(|arg| arg.strip_prefix("scrub="))
+            .next_back()?;
+        let mut options = value.split(',');
+        let mountpoint = options.next().filter(|path| path.starts_with('/'))?;
+        let mut config = Self {
+            mountpoint,
+            bandwidth: DEFAULT_BANDWIDTH,
+            repair: false,
+            state_path: None,
+            interval: DEFAULT_INTERVAL,
+        };
+        for option in options {
+            if option == "repair" {
+                config.repair = true;
+            } else if let Some(bandwidth) = option.strip_prefix("bw=").and_then(parse_size)
+                && bandwidth > 0
+            {
+                config.bandwidth = bandwidth;
+            } else if let Some(path) = option.strip_prefix("state=")
+                && path.starts_with('/')
+            {
+                config.state_path = Some(path);
+            } else if let Some(secs) = option
+                .strip_prefix("interval=")
+                .and_then(|n| n.parse().ok())
+            {
+                config.interval = Duration::from_secs(secs);
+            }
+        }
+        Some(config)
+    }
+
+    /// Returns the path of the state file.
+    pub fn state_path(&self) -> String {
+        match self.state_path {
+            Some(path) => path.to_string(),
+            None => format!("{}/{STATE_FILE}", self.mountpoint.trim_end_matches('/')),
+        }
+    }
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'K' | b'k' => (&size[..size.len() - 1], 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Pacing of the scrubber.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    bandwidth: u64,
+    backoff: Duration,
+}
+
+impl Throttle {
+    pub(crate) fn new(bandwidth: u64) -> Self {
+        Self {
+            bandwidth,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    /// Returns how long to pause after reading `bytes` to keep to the
+    /// bandwidth.
+    pub(crate) fn delay(&self, bytes: u64) -> Duration {
+        let nanos = bytes as u128 * 1_000_000_000 / self.bandwidth as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    /// Returns how long to pause after foreground activity was seen, twice
+    /// as long as the last time.
+    pub(crate) fn busy(&mut self) -> Duration {
+        self.backoff = (self.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+        self.backoff
+    }
+
+    /// Notes that the filesystem was idle for the last pause.
+    pub(crate) fn idle(&mut self) {
+        self.backoff = Duration::ZERO;
+    }
+}
+
+/// Findings of the scrubber.
+struct Report {
+    mountpoint: String,
+    repair: bool,
+    cursor: ScrubCursor,
+    findings: VecDeque<Finding>,
+    /// Findings dropped from the front of `findings`.
+    dropped: usize,
+}
+
+static REPORT: Mutex<Option<Report>> = Mutex::new(None);
+
+impl Report {
+    fn record(&mut self, finding: Finding) {
+        if self.findings.len() == MAX_FINDINGS {
+            self.findings.pop_front();
+            self.dropped += 1;
+        }
+        self.findings.push_back(finding);
+    }
+}
+
+/// Returns the report of the scrubber, one finding a line after a header,
+/// or an empty string if it is not running.
+pub fn report() -> String {
+    let report = REPORT.lock();
+    let Some(report) = report.as_ref() else {
+        return String::new();
+    };
+    let mut out = String::new();
+    let _ = writeln!(out, "mountpoint: {}", report.mountpoint);
+    let _ = writeln!(out, "repair: {}", report.repair);
+    let _ = writeln!(out, "passes: {}", report.cursor.pass);
+    let _ = writeln!(out, "next inode: {}", report.cursor.next_ino);
+    let _ = writeln!(out, "findings: {}", report.findings.len() + report.dropped);
+    for finding in &report.findings {
+        let _ = writeln!(out, "{finding}");
+    }
+    out
+}
+
+/// Starts the scrubber if the kernel command line asks for it.
+pub fn start_scrubber() {
+    let cmdline = khal::dtb::get_chosen_bootargs().unwrap_or("");
+    let Some(config) = ScrubConfig::from_cmdline(cmdline) else {
+        return;
+    };
+    info!(
+        "Scrubber: {} at {} bytes/s{}",
+        config.mountpoint,
+        config.bandwidth,
+        if config.repair { " with repair" } else { "" }
+    );
+    *REPORT.lock() = Some(Report {
+        mountpoint: config.mountpoint.to_string(),
+        repair: config.repair,
+        cursor: ScrubCursor::default(),
+        findings: VecDeque::new(),
+        dropped: 0,
+    });
+
+    let mountpoint = config.mountpoint.to_string();
+    let state_path = config.state_path();
+    let (bandwidth, repair, interval) = (config.bandwidth, config.repair, config.interval);
+    let task = ktask::spawn_with_name(
+        move || {
+            let fs = loop {
+                match open_fs(&mountpoint) {
+                    Ok(fs) => break fs,
+                    Err(_) => ktask::sleep(MOUNT_RETRY),
+                }
+            };
+            let mut scrubber = Scrubber {
+                fs,
+                state_path,
+                repair,
+                throttle: Throttle::new(bandwidth),
+            };
+            if let Err(err) = scrubber.run(interval) {
+                warn!("Scrubber of {mountpoint} stopped: {err:?}");
+            }
+        },
+        "scrub".into(),
+    );
+    ktask::set_nice(&task, ktask::MAX_NICE as i32);
+}
+
+/// Returns the rsext4 filesystem mounted at `mountpoint`.
+fn open_fs(mountpoint: &str) -> VfsResult<Arc<Rsext4Filesystem>> {
+    let context = ROOT_FS_CONTEXT.get().ok_or(VfsError::NotFound)?;
+    let loc = context.resolve(mountpoint)?;
+    let inode = loc.entry().downcast::<Rsext4Inode>()?;
+    Ok(inode.filesystem().clone())
+}
+
+struct Scrubber {
+    fs: Arc<Rsext4Filesystem>,
+    state_path: String,
+    repair: bool,
+    throttle: Throttle,
+}
+
+impl Scrubber {
+    fn run(&mut self, interval: Duration) -> VfsResult<()> {
+        let uuid = self.fs.uuid();
+        let mut cursor = self.load_state(&uuid).unwrap_or_default();
+        let mut steps = 0u32;
+        loop {
+            let mut findings = Vec::new();
+            let progress =
+                self.fs
+                    .scrub_step(&mut cursor, STEP_BLOCKS, self.repair, &mut findings)?;
+            {
+                let mut report = REPORT.lock();
+                let report = report.as_mut().unwrap();
+                for finding in findings {
+                    warn!("Scrubber of {}: {finding}", report.mountpoint);
+                    report.record(finding);
+                }
+                report.cursor = cursor;
+            }
+
+            steps += 1;
+            if progress.pass_done || steps.is_multiple_of(SAVE_STEPS) {
+                // The state file is written like any other, so a failure
+                // only costs progress after a reboot.
+                if let Err(err) = self.save_state(&cursor, &uuid) {
+                    warn!("Scrubber failed to save its state: {err:?}");
+                }
+            }
+            let mut seen = self.fs.activity();
+            if progress.pass_done {
+                ktask::sleep(interval);
+            } else {
+                let bytes = progress.blocks as u64 * rsext4::BLOCK_SIZE as u64;
+                ktask::sleep(self.throttle.delay(bytes));
+            }
+            // Wait for a pause in foreground operations.
+            while self.fs.activity() != seen {
+                seen = self.fs.activity();
+                ktask::sleep(self.throttle.busy());
+            }
+            self.throttle.idle();
+        }
+    }
+
+    fn load_state(&self, uuid: &[u8; 16]) -> Option<ScrubCursor> {
+        let file = File::open(ROOT_FS_CONTEXT.get()?, &self.state_path).ok()?;
+        let mut data = [0u8; SCRUB_STATE_SIZE];
+        let read = file.read_at(&mut data[..], 0).ok()?;
+        ScrubCursor::decode(&data[..read], uuid)
+    }
+
+    fn save_state(&self, cursor: &ScrubCursor, uuid: &[u8; 16]) -> VfsResult<()> {
+        let context = ROOT_FS_CONTEXT.get().ok_or(VfsError::NotFound)?;
+        let file = File::create(context, &self.state_path)?;
+        file.write_at(&cursor.encode(uuid)[..], 0)?;
+        file.sync(false)
+    }
+}
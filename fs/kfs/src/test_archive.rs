@@ -0,0 +1,395 @@
+//! Unit tests for the archive reader.
+//!
+//! Archives are made by small writers. Deflated zip members use stored
+//! DEFLATE blocks, which is enough to go through the decompressor.
+
+#![cfg(all(unittest, feature = "archive"))]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+
+use fs_ng_vfs::VfsError;
+use kcrypto::Sha256;
+use kio::{SeekFrom, prelude::*};
+use unittest::def_test;
+
+use crate::{
+    ArchiveError, ArchiveFormat, ArchiveReader, FirmwareSource, Manifest, add_firmware_source,
+    request_firmware,
+};
+
+/// Makes a zip archive of `(name, data, deflate)` members.
+fn zip(members: &[(&str, &[u8], bool)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for &(name, data, deflate) in members {
+        let stored = if deflate {
+            let len = data.len() as u16;
+            let mut block = vec![0x01];
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(&(!len).to_le_bytes());
+            block.extend_from_slice(data);
+            block
+        } else {
+            data.to_vec()
+        };
+        let method: u16 = if deflate { 8 } else { 0 };
+        let crc = kdecompress::crc32(data);
+        let offset = out.len() as u32;
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&[20, 0, 0, 0]);
+        out.extend_from_slice(&method.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&stored);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+        central.extend_from_slice(&method.to_le_bytes());
+        central.extend_from_slice(&[0; 4]);
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        central.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 12]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let cd_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&cd_offset.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out
+}
+
+/// Appends an ar member header for `name` and `len` bytes of data.
+fn ar_header(out: &mut Vec<u8>, name: &str, len: usize) {
+    out.extend_from_slice(
+        format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{len:<10}`\n", 0, 0, 0, 644).as_bytes(),
+    );
+}
+
+/// Makes a GNU ar archive, with a long name table.
+fn ar(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut out = b"!<arch>\n".to_vec();
+    let mut table = String::new();
+    let mut names = Vec::new();
+    for &(name, _) in members {
+        if name.len() > 15 {
+            names.push(format!("/{}", table.len()));
+            table.push_str(&format!("{name}/\n"));
+        } else {
+            names.push(format!("{name}/"));
+        }
+    }
+    if !table.is_empty() {
+        ar_header(&mut out, "//", table.len());
+        out.extend_from_slice(table.as_bytes());
+        out.resize(out.len().next_multiple_of(2), b'\n');
+    }
+    for (name, &(_, data)) in names.iter().zip(members) {
+        ar_header(&mut out, name, data.len());
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(2), b'\n');
+    }
+    out
+}
+
+/// Appends a `newc` entry to `archive`.
+fn cpio_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let fields = [
+        1,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{field:08x}").as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+fn open(data: Vec<u8>) -> Result<ArchiveReader, ArchiveError> {
+    ArchiveReader::from_source(Arc::new(data))
+}
+
+fn bundle() -> Vec<u8> {
+    zip(&[
+        ("ta/8aaaf200.ta", b"trusted application", false),
+        ("fw/", b"", false),
+        ("fw/wifi.bin", &[0x5a; 3000], true),
+        ("config.txt", b"mode=secure\n", true),
+    ])
+}
+
+#[def_test]
+fn test_archive_zip_members() {
+    let reader = open(bundle()).unwrap();
+    assert_eq!(reader.format(), ArchiveFormat::Zip);
+    let names: Vec<_> = reader.members().iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["ta/8aaaf200.ta", "fw/wifi.bin", "config.txt"]);
+    assert_eq!(reader.member("fw/wifi.bin").unwrap().size, 3000);
+    assert!(reader.member("fw/wifi.bin").unwrap().is_compressed());
+
+    assert_eq!(
+        reader.read_member_to_vec("ta/8aaaf200.ta").unwrap(),
+        b"trusted application"
+    );
+    assert_eq!(
+        reader.read_member_to_vec("fw/wifi.bin").unwrap(),
+        [0x5a; 3000]
+    );
+    assert_eq!(
+        reader.read_member_to_vec("config.txt").unwrap(),
+        b"mode=secure\n"
+    );
+    let err = reader.read_member("missing").err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("missing"));
+    assert_eq!(err.error, VfsError::NotFound);
+}
+
+#[def_test]
+fn test_archive_member_seek() {
+    let reader = open(bundle()).unwrap();
+    let mut member = reader.read_member("ta/8aaaf200.ta").unwrap();
+    let mut buf = [0; 7];
+    member.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"trusted");
+    // Save the position and resume from it after reading further.
+    let checkpoint = member.stream_position().unwrap();
+    member.read_exact(&mut buf[..4]).unwrap();
+    member.seek(SeekFrom::Start(checkpoint)).unwrap();
+    let mut rest = Vec::new();
+    member.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b" application");
+}
+
+#[def_test]
+fn test_archive_zip_crc() {
+    let mut data = bundle();
+    let pos = data
+        .windows(19)
+        .position(|w| w == b"trusted application")
+        .unwrap();
+    data[pos] = b'T';
+    let reader = open(data.clone()).unwrap();
+    let err = reader.read_member_to_vec("ta/8aaaf200.ta").err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("ta/8aaaf200.ta"));
+    assert_eq!(err.error, VfsError::InvalidData);
+
+    // Deflated members are checked as they are inflated.
+    let pos = data.windows(3).position(|w| w == [0x5a; 3]).unwrap();
+    data[pos] = 0;
+    let err = open(data)
+        .unwrap()
+        .read_member("fw/wifi.bin")
+        .err()
+        .unwrap();
+    assert_eq!(err.member.as_deref(), Some("fw/wifi.bin"));
+}
+
+#[def_test]
+fn test_archive_manifest() {
+    let manifest = format!(
+        "{}  ta/8aaaf200.ta\n{} *fw/wifi.bin\n",
+        hex(&Sha256::digest(b"trusted application")),
+        hex(&Sha256::digest(b"not the firmware")),
+    );
+    let mut reader = open(bundle()).unwrap();
+    reader.set_manifest(Manifest::parse(&manifest).unwrap());
+    assert_eq!(
+        reader.read_member_to_vec("ta/8aaaf200.ta").unwrap(),
+        b"trusted application"
+    );
+
+    let err = reader.read_member("fw/wifi.bin").err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("fw/wifi.bin"));
+    assert_eq!(err.error, VfsError::InvalidData);
+    let err = reader.read_member("config.txt").err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("config.txt"));
+    assert_eq!(err.error, VfsError::PermissionDenied);
+
+    assert!(Manifest::parse("1234  short\n").is_err());
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[def_test]
+fn test_archive_ar() {
+    let data = ar(&[
+        ("short.bin", b"abc"),
+        ("a-rather-long-firmware-name.bin", b"long"),
+        ("odd", b"x"),
+    ]);
+    let reader = open(data).unwrap();
+    assert_eq!(reader.format(), ArchiveFormat::Ar);
+    assert_eq!(reader.members().len(), 3);
+    assert_eq!(reader.read_member_to_vec("short.bin").unwrap(), b"abc");
+    assert_eq!(
+        reader
+            .read_member_to_vec("a-rather-long-firmware-name.bin")
+            .unwrap(),
+        b"long"
+    );
+    assert_eq!(reader.read_member_to_vec("odd").unwrap(), b"x");
+
+    // BSD ar puts long names before the data.
+    let mut data = b"!<arch>\n".to_vec();
+    ar_header(&mut data, "#1/20", 20 + 5);
+    data.extend_from_slice(b"bsd-long-name.bin\0\0\0hello");
+    data.push(b'\n');
+    let reader = open(data).unwrap();
+    assert_eq!(
+        reader.read_member_to_vec("bsd-long-name.bin").unwrap(),
+        b"hello"
+    );
+}
+
+#[def_test]
+fn test_archive_cpio() {
+    let mut data = Vec::new();
+    cpio_entry(&mut data, "lib", 0o040755, b"");
+    cpio_entry(&mut data, "lib/fw.bin", 0o100644, b"firmware");
+    cpio_entry(&mut data, "lib/link", 0o120777, b"fw.bin");
+    cpio_entry(&mut data, "TRAILER!!!", 0, b"");
+    let reader = open(data).unwrap();
+    assert_eq!(reader.format(), ArchiveFormat::Cpio);
+    assert_eq!(reader.members().len(), 1);
+    assert_eq!(
+        reader.read_member_to_vec("lib/fw.bin").unwrap(),
+        b"firmware"
+    );
+}
+
+#[def_test]
+fn test_archive_names_offending_member() {
+    // A local header that is not one.
+    let mut data = bundle();
+    let pos = data
+        .windows(4)
+        .rposition(|w| w == 0x0403_4b50u32.to_le_bytes())
+        .unwrap();
+    data[pos] = 0;
+    let err = open(data).err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("config.txt"));
+    assert_eq!(err.error, VfsError::InvalidData);
+
+    // An unknown compression method.
+    let mut data = zip(&[("a", b"a", false), ("b", b"b", false)]);
+    let central = data
+        .windows(4)
+        .rposition(|w| w == 0x0201_4b50u32.to_le_bytes())
+        .unwrap();
+    data[central + 10] = 14;
+    let err = open(data).err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("b"));
+    assert_eq!(err.error, VfsError::Unsupported);
+
+    // An ar member running past the end.
+    let mut data = ar(&[("a", b"abc"), ("b", b"def")]);
+    data.truncate(data.len() - 2);
+    let err = open(data).err().unwrap();
+    assert_eq!(err.member.as_deref(), Some("#1"));
+
+    // Two members of the same name.
+    let err = open(zip(&[("a", b"1", false), ("a", b"2", false)]))
+        .err()
+        .unwrap();
+    assert_eq!(err.member.as_deref(), Some("a"));
+}
+
+/// Opens `data` and reads all its members, which must not panic whatever
+/// the errors.
+fn exercise(data: Vec<u8>) {
+    let Ok(reader) = open(data) else {
+        return;
+    };
+    for member in reader.members() {
+        let _ = reader.read_member_to_vec(&member.name);
+    }
+}
+
+#[def_test]
+fn test_archive_fuzz() {
+    let mut cpio = Vec::new();
+    cpio_entry(&mut cpio, "a", 0o100644, b"first");
+    cpio_entry(&mut cpio, "b", 0o100644, b"second");
+    cpio_entry(&mut cpio, "TRAILER!!!", 0, b"");
+    let archives = [
+        bundle(),
+        ar(&[
+            ("short", b"abc"),
+            ("a-rather-long-firmware-name.bin", b"long"),
+        ]),
+        cpio,
+    ];
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for archive in &archives {
+        for len in 0..archive.len() {
+            exercise(archive[..len].to_vec());
+        }
+        for _ in 0..2000 {
+            let mut data = archive.clone();
+            for _ in 0..1 + random() % 4 {
+                let pos = random() as usize % data.len();
+                data[pos] = match random() % 3 {
+                    0 => random() as u8,
+                    1 => 0xff,
+                    _ => 0,
+                };
+            }
+            exercise(data);
+        }
+    }
+}
+
+#[def_test]
+fn test_archive_request_firmware() {
+    let reader = open(bundle()).unwrap();
+    add_firmware_source(FirmwareSource::Archive(Arc::new(reader)));
+    assert_eq!(request_firmware("fw/wifi.bin").unwrap(), [0x5a; 3000]);
+    assert_eq!(request_firmware("fw/missing.bin"), Err(VfsError::NotFound));
+    assert_eq!(
+        request_firmware("../fw/wifi.bin"),
+        Err(VfsError::InvalidInput)
+    );
+    assert_eq!(
+        request_firmware("/fw/wifi.bin"),
+        Err(VfsError::InvalidInput)
+    );
+}
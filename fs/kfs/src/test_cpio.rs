@@ -0,0 +1,74 @@
+//! Unit tests for the cpio reader.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+
+use fs_ng_vfs::{NodeType, VfsError};
+use unittest::def_test;
+
+use crate::cpio::CpioReader;
+
+/// Appends a `newc` entry to `archive`.
+fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, rdev: (u32, u32), data: &[u8]) {
+    let fields = [
+        1,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        rdev.0,
+        rdev.1,
+        name.len() as u32 + 1,
+        0,
+    ];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{field:08x}").as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+#[def_test]
+fn test_cpio_reads_device_nodes() {
+    let mut archive = Vec::new();
+    push_entry(&mut archive, "dev", 0o040755, (0, 0), b"");
+    push_entry(&mut archive, "dev/console", 0o020600, (5, 1), b"");
+    push_entry(&mut archive, "dev/sda", 0o060660, (8, 0), b"");
+    push_entry(&mut archive, "init", 0o100755, (0, 0), b"#!/bin/sh\n");
+    push_entry(&mut archive, "TRAILER!!!", 0, (0, 0), b"");
+
+    let entries = CpioReader::new(&archive)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].node_type(), Some(NodeType::Directory));
+    assert_eq!(entries[1].name, "dev/console");
+    assert_eq!(entries[1].node_type(), Some(NodeType::CharacterDevice));
+    assert_eq!(entries[1].rdev.major(), 5);
+    assert_eq!(entries[1].rdev.minor(), 1);
+    assert_eq!(entries[1].permission().bits(), 0o600);
+    assert_eq!(entries[2].node_type(), Some(NodeType::BlockDevice));
+    assert_eq!(entries[2].rdev.major(), 8);
+    assert_eq!(entries[3].data, b"#!/bin/sh\n");
+}
+
+#[def_test]
+fn test_cpio_rejects_bad_magic() {
+    let mut archive = Vec::new();
+    push_entry(&mut archive, "init", 0o100755, (0, 0), b"");
+    archive[..6].copy_from_slice(b"070707");
+    let mut reader = CpioReader::new(&archive);
+    assert!(matches!(reader.next(), Some(Err(VfsError::InvalidData))));
+    assert!(reader.next().is_none());
+}
@@ -0,0 +1,93 @@
+//! Unit tests for negative dentry caching.
+
+#![cfg(unittest)]
+
+use alloc::sync::Arc;
+
+use fs_ng_vfs::{Mountpoint, NegativeDentryStats, NodePermission, VfsError, negative_dentry_stats};
+use unittest::def_test;
+
+use crate::{FsContext, MemoryFs, UnmountFlags, mount_tmpfs, umount};
+
+fn create_context() -> (Arc<Mountpoint>, FsContext) {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    (mp, ctx)
+}
+
+/// Returns the counter changes since `before`, as `(hits, misses)`.
+fn stats_since(before: NegativeDentryStats) -> (u64, u64) {
+    let now = negative_dentry_stats();
+    (now.hits - before.hits, now.misses - before.misses)
+}
+
+#[def_test]
+fn test_create_after_failed_lookup() {
+    let (_mp, ctx) = create_context();
+    let before = negative_dentry_stats();
+    assert_eq!(ctx.resolve("/file").unwrap_err(), VfsError::NotFound);
+    assert_eq!(ctx.resolve("/file").unwrap_err(), VfsError::NotFound);
+    assert_eq!(stats_since(before), (1, 1));
+
+    ctx.write("/file", b"data").unwrap();
+    assert_eq!(ctx.read("/file").unwrap(), b"data");
+
+    assert_eq!(ctx.resolve("/dir").unwrap_err(), VfsError::NotFound);
+    ctx.create_dir("/dir", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    assert!(ctx.resolve("/dir").unwrap().is_dir());
+}
+
+#[def_test]
+fn test_rename_and_link_after_failed_lookup() {
+    let (_mp, ctx) = create_context();
+    ctx.write("/src", b"data").unwrap();
+
+    assert_eq!(ctx.resolve("/dst").unwrap_err(), VfsError::NotFound);
+    ctx.rename("/src", "/dst").unwrap();
+    assert_eq!(ctx.read("/dst").unwrap(), b"data");
+
+    assert_eq!(ctx.resolve("/hard").unwrap_err(), VfsError::NotFound);
+    ctx.link("/dst", "/hard").unwrap();
+    assert_eq!(ctx.read("/hard").unwrap(), b"data");
+}
+
+#[def_test]
+fn test_mount_drops_negative_dentries() {
+    let (_mp, ctx) = create_context();
+    ctx.create_dir("/mnt", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    assert_eq!(ctx.resolve("/mnt/file").unwrap_err(), VfsError::NotFound);
+
+    mount_tmpfs(&ctx, "/mnt", None).unwrap();
+    umount(&ctx, "/mnt", UnmountFlags::empty()).unwrap();
+
+    // The negative dentry of the directory below was dropped by the mount.
+    let before = negative_dentry_stats();
+    assert_eq!(ctx.resolve("/mnt/file").unwrap_err(), VfsError::NotFound);
+    assert_eq!(stats_since(before), (0, 1));
+}
+
+#[def_test]
+fn test_negative_dentry_lru() {
+    let (mp, ctx) = create_context();
+    mp.set_negative_dentry_limit(2);
+    let before = negative_dentry_stats();
+    for name in ["/a", "/b", "/c"] {
+        assert_eq!(ctx.resolve(name).unwrap_err(), VfsError::NotFound);
+    }
+    assert_eq!(stats_since(before), (0, 3));
+
+    // "/a" was evicted as the least recently used one, "/c" was kept.
+    let before = negative_dentry_stats();
+    assert_eq!(ctx.resolve("/c").unwrap_err(), VfsError::NotFound);
+    assert_eq!(stats_since(before), (1, 0));
+    assert_eq!(ctx.resolve("/a").unwrap_err(), VfsError::NotFound);
+    assert_eq!(stats_since(before), (1, 1));
+
+    mp.set_negative_dentry_limit(0);
+    let before = negative_dentry_stats();
+    assert_eq!(ctx.resolve("/c").unwrap_err(), VfsError::NotFound);
+    assert_eq!(ctx.resolve("/c").unwrap_err(), VfsError::NotFound);
+    assert_eq!(stats_since(before), (0, 2));
+}
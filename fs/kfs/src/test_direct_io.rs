@@ -0,0 +1,326 @@
+//! Unit tests for direct I/O.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Context,
+    time::Duration,
+};
+
+use fs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Metadata, MetadataUpdate, Mountpoint, NodeOps, NodePermission, NodeType,
+    Reference, StatFs, VfsError, VfsResult,
+};
+use kpoll::{IoEvents, Pollable};
+use ksync::Mutex;
+use unittest::def_test;
+
+use crate::{File, FsContext, OpenOptions};
+
+const ALIGN: usize = 512;
+
+/// A file whose contents stand for the blocks on a disk.
+pub(crate) struct Disk {
+    ino: u64,
+    pub data: Mutex<Vec<u8>>,
+    align: Option<usize>,
+    /// Number of direct reads and writes.
+    direct_ops: AtomicUsize,
+    /// Number of reads, direct or not.
+    pub reads: AtomicUsize,
+}
+
+/// A filesystem with a root directory holding `/disk`, which supports direct
+/// I/O, and `/nodirect`, which does not.
+pub(crate) struct DiskFs {
+    root: Mutex<Option<DirEntry>>,
+    pub disk: Arc<Disk>,
+    nodirect: Arc<Disk>,
+}
+
+impl DiskFs {
+    fn new() -> (Filesystem, Arc<DiskFs>) {
+        let disk = |ino, align| {
+            Arc::new(Disk {
+                ino,
+                data: Mutex::default(),
+                align,
+                direct_ops: AtomicUsize::new(0),
+                reads: AtomicUsize::new(0),
+            })
+        };
+        let fs = Arc::new(Self {
+            root: Mutex::default(),
+            disk: disk(2, Some(ALIGN)),
+            nodirect: disk(3, None),
+        });
+        *fs.root.lock() = Some(DirEntry::new_dir(
+            |_| {
+                DirNode::new(Arc::new(DiskNode {
+                    fs: fs.clone(),
+                    disk: None,
+                }))
+            },
+            Reference::root(),
+        ));
+        (Filesystem::new(fs.clone()), fs)
+    }
+}
+
+impl FilesystemOps for DiskFs {
+    fn name(&self) -> &str {
+        "diskfs"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root.lock().clone().unwrap()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+/// The root directory if `disk` is `None`, a file otherwise.
+struct DiskNode {
+    fs: Arc<DiskFs>,
+    disk: Option<Arc<Disk>>,
+}
+
+impl DiskNode {
+    fn disk(&self) -> &Disk {
+        self.disk.as_ref().unwrap()
+    }
+}
+
+impl NodeOps for DiskNode {
+    fn inode(&self) -> u64 {
+        self.disk.as_ref().map_or(1, |disk| disk.ino)
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let (node_type, size) = match &self.disk {
+            Some(disk) => (NodeType::RegularFile, disk.data.lock().len() as u64),
+            None => (NodeType::Directory, 0),
+        };
+        Ok(Metadata {
+            device: 0,
+            inode: self.inode(),
+            nlink: 1,
+            mode: NodePermission::from_bits_truncate(0o755),
+            node_type,
+            uid: 0,
+            gid: 0,
+            size,
+            block_size: 0,
+            blocks: 0,
+            rdev: DeviceId::default(),
+            atime: Duration::default(),
+            mtime: Duration::default(),
+            ctime: Duration::default(),
+        })
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        self.fs.as_ref()
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl FileNodeOps for DiskNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.disk().reads.fetch_add(1, Ordering::SeqCst);
+        let data = self.disk().data.lock();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let mut data = self.disk().data.lock();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        let mut data = self.disk().data.lock();
+        data.extend_from_slice(buf);
+        Ok((buf.len(), data.len() as u64))
+    }
+
+    fn set_len(&self, len: u64) -> VfsResult<()> {
+        self.disk().data.lock().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn direct_io_align(&self) -> Option<usize> {
+        self.disk().align
+    }
+
+    fn read_direct(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        assert!(buf.len().is_multiple_of(ALIGN) && offset.is_multiple_of(ALIGN as u64));
+        self.disk().direct_ops.fetch_add(1, Ordering::SeqCst);
+        self.read_at(buf, offset)
+    }
+
+    fn write_direct(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        assert!(buf.len().is_multiple_of(ALIGN) && offset.is_multiple_of(ALIGN as u64));
+        self.disk().direct_ops.fetch_add(1, Ordering::SeqCst);
+        self.write_at(buf, offset)
+    }
+}
+
+impl Pollable for DiskNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for DiskNode {
+    fn read_dir(&self, _offset: u64, _sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        Ok(0)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let disk = match name {
+            "disk" => self.fs.disk.clone(),
+            "nodirect" => self.fs.nodirect.clone(),
+            _ => return Err(VfsError::NotFound),
+        };
+        Ok(DirEntry::new_file(
+            FileNode::new(Arc::new(DiskNode {
+                fs: self.fs.clone(),
+                disk: Some(disk),
+            })),
+            NodeType::RegularFile,
+            Reference::new(Some(self.fs.root_dir()), name.into()),
+        ))
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+pub(crate) fn open(ctx: &FsContext, path: &str, direct: bool) -> VfsResult<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .direct(direct)
+        .open(ctx, path)
+        .and_then(|result| result.into_file())
+}
+
+pub(crate) fn setup() -> (FsContext, Arc<DiskFs>) {
+    let (fs, disk_fs) = DiskFs::new();
+    let ctx = FsContext::new(Mountpoint::new_root(&fs).root_location());
+    (ctx, disk_fs)
+}
+
+#[def_test]
+fn test_direct_io_alignment() {
+    let (ctx, fs) = setup();
+    let file = open(&ctx, "/disk", true).unwrap();
+    assert_eq!(file.direct_io_align(), Some(ALIGN));
+    // Buffers are aligned like offsets unless the file says otherwise.
+    assert_eq!(file.direct_io_mem_align(), Some(ALIGN));
+
+    let data = vec![0x5a; 3 * ALIGN];
+    assert_eq!(file.write_at(&data[..], ALIGN as u64), Ok(3 * ALIGN));
+    assert_eq!(fs.disk.data.lock()[ALIGN..], data[..]);
+
+    assert_eq!(file.write_at(&data[..100], 0), Err(VfsError::InvalidInput));
+    let mut buf = vec![0; 2 * ALIGN];
+    assert_eq!(file.read_at(&mut buf[..], 10), Err(VfsError::InvalidInput));
+
+    // Reads stop at the end of the file.
+    assert_eq!(file.read_at(&mut buf[..], 3 * ALIGN as u64), Ok(ALIGN));
+    assert_eq!(buf[..ALIGN], data[..ALIGN]);
+    assert_eq!(fs.disk.direct_ops.load(Ordering::SeqCst), 2);
+
+    // Files opened without `O_DIRECT` go through the page cache.
+    let cached = open(&ctx, "/disk", false).unwrap();
+    assert_eq!(cached.direct_io_align(), None);
+    assert_eq!(cached.read_at(&mut buf[..10], 3), Ok(10));
+    assert_eq!(fs.disk.direct_ops.load(Ordering::SeqCst), 2);
+}
+
+#[def_test]
+fn test_direct_io_coherent_with_cache() {
+    let (ctx, fs) = setup();
+    let cached = open(&ctx, "/disk", false).unwrap();
+    let direct = open(&ctx, "/disk", true).unwrap();
+
+    // A direct read sees data still dirty in the page cache.
+    assert_eq!(cached.write_at(&[1; ALIGN][..], 0), Ok(ALIGN));
+    let mut buf = vec![0; ALIGN];
+    assert_eq!(direct.read_at(&mut buf[..], 0), Ok(ALIGN));
+    assert_eq!(buf, [1; ALIGN]);
+
+    // A direct write drops the stale cached page.
+    let mut page = vec![0; 16];
+    assert_eq!(cached.read_at(&mut page[..], 0), Ok(16));
+    assert_eq!(direct.write_at(&[2; ALIGN][..], 0), Ok(ALIGN));
+    assert_eq!(cached.read_at(&mut page[..], 0), Ok(16));
+    assert_eq!(page, [2; 16]);
+
+    // Nothing cached overwrites the direct write later on.
+    cached.sync(false).unwrap();
+    assert_eq!(fs.disk.data.lock()[..], [2; ALIGN]);
+}
+
+#[def_test]
+fn test_direct_io_unsupported() {
+    let (ctx, _fs) = setup();
+    assert!(matches!(
+        open(&ctx, "/nodirect", true),
+        Err(VfsError::InvalidInput)
+    ));
+    let file = open(&ctx, "/nodirect", false).unwrap();
+    assert_eq!(file.write_at(&b"data"[..], 0), Ok(4));
+}
@@ -0,0 +1,465 @@
+//! Unit tests for encrypted directories.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{
+    borrow::ToOwned,
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{any::Any, task::Context, time::Duration};
+
+use fs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Metadata, MetadataUpdate, Mountpoint, NodeOps, NodePermission, NodeType,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+};
+use kerrno::LinuxError;
+use kpoll::{IoEvents, Pollable};
+use ksync::Mutex;
+use unittest::def_test;
+
+use crate::{
+    FileFlags, FsContext, OpenOptions,
+    fscrypt::{self, EncryptionPolicy},
+};
+
+/// In-memory node of [`MemFs`] that keeps file contents in the node itself,
+/// so that the stored (encrypted) bytes can be inspected.
+struct MemInode {
+    ino: u64,
+    node_type: NodeType,
+    data: Mutex<Vec<u8>>,
+    entries: Mutex<BTreeMap<String, Arc<MemInode>>>,
+    context: Mutex<Option<Vec<u8>>>,
+}
+
+impl MemInode {
+    fn new(fs: &MemFs, node_type: NodeType) -> Arc<Self> {
+        let mut next_ino = fs.next_ino.lock();
+        *next_ino += 1;
+        Arc::new(Self {
+            ino: *next_ino,
+            node_type,
+            data: Mutex::default(),
+            entries: Mutex::default(),
+            context: Mutex::default(),
+        })
+    }
+}
+
+struct MemFs {
+    root: Mutex<Option<DirEntry>>,
+    root_inode: Mutex<Option<Arc<MemInode>>>,
+    next_ino: Mutex<u64>,
+}
+
+impl MemFs {
+    fn new() -> (Filesystem, Arc<MemFs>) {
+        let fs = Arc::new(Self {
+            root: Mutex::default(),
+            root_inode: Mutex::default(),
+            next_ino: Mutex::new(0),
+        });
+        let inode = MemInode::new(&fs, NodeType::Directory);
+        *fs.root_inode.lock() = Some(inode.clone());
+        *fs.root.lock() = Some(DirEntry::new_dir(
+            |this| {
+                DirNode::new(Arc::new(MemNode {
+                    fs: fs.clone(),
+                    inode,
+                    this: Some(this),
+                }))
+            },
+            Reference::root(),
+        ));
+        (Filesystem::new(fs.clone()), fs)
+    }
+
+    /// Returns the inode at the stored path `names`.
+    fn raw(&self, names: &[&str]) -> Arc<MemInode> {
+        let mut inode = self.root_inode.lock().clone().unwrap();
+        for name in names {
+            let next = inode.entries.lock()[*name].clone();
+            inode = next;
+        }
+        inode
+    }
+}
+
+impl FilesystemOps for MemFs {
+    fn name(&self) -> &str {
+        "memfs"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root.lock().clone().unwrap()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+struct MemNode {
+    fs: Arc<MemFs>,
+    inode: Arc<MemInode>,
+    this: Option<WeakDirEntry>,
+}
+
+impl MemNode {
+    fn new_entry(&self, name: &str, inode: Arc<MemInode>) -> DirEntry {
+        let fs = self.fs.clone();
+        let node_type = inode.node_type;
+        let reference = Reference::new(
+            self.this.as_ref().and_then(WeakDirEntry::upgrade),
+            name.to_owned(),
+        );
+        if node_type == NodeType::Directory {
+            DirEntry::new_dir(
+                |this| {
+                    DirNode::new(Arc::new(MemNode {
+                        fs,
+                        inode,
+                        this: Some(this),
+                    }))
+                },
+                reference,
+            )
+        } else {
+            DirEntry::new_file(
+                FileNode::new(Arc::new(MemNode {
+                    fs,
+                    inode,
+                    this: None,
+                })),
+                node_type,
+                reference,
+            )
+        }
+    }
+}
+
+impl NodeOps for MemNode {
+    fn inode(&self) -> u64 {
+        self.inode.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        Ok(Metadata {
+            device: 0,
+            inode: self.inode.ino,
+            nlink: 1,
+            mode: NodePermission::from_bits_truncate(0o755),
+            node_type: self.inode.node_type,
+            uid: 0,
+            gid: 0,
+            size: self.inode.data.lock().len() as u64,
+            block_size: 0,
+            blocks: 0,
+            rdev: DeviceId::default(),
+            atime: Duration::default(),
+            mtime: Duration::default(),
+            ctime: Duration::default(),
+        })
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        self.fs.as_ref()
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn get_encryption_context(&self) -> VfsResult<Vec<u8>> {
+        self.inode
+            .context
+            .lock()
+            .clone()
+            .ok_or(VfsError::from(LinuxError::ENODATA))
+    }
+
+    fn set_encryption_context(&self, context: &[u8]) -> VfsResult<()> {
+        *self.inode.context.lock() = Some(context.to_vec());
+        Ok(())
+    }
+}
+
+impl FileNodeOps for MemNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let data = self.inode.data.lock();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let mut data = self.inode.data.lock();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        let mut data = self.inode.data.lock();
+        data.extend_from_slice(buf);
+        Ok((buf.len(), data.len() as u64))
+    }
+
+    fn set_len(&self, len: u64) -> VfsResult<()> {
+        self.inode.data.lock().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn set_symlink(&self, target: &str) -> VfsResult<()> {
+        *self.inode.data.lock() = target.as_bytes().to_vec();
+        Ok(())
+    }
+}
+
+impl Pollable for MemNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for MemNode {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let entries = self.inode.entries.lock();
+        let names = [(".", self.inode.ino, NodeType::Directory)]
+            .into_iter()
+            .chain([("..", self.inode.ino, NodeType::Directory)])
+            .chain(
+                entries
+                    .iter()
+                    .map(|(name, inode)| (name.as_str(), inode.ino, inode.node_type)),
+            );
+        let mut count = 0;
+        for (i, (name, ino, node_type)) in names.enumerate().skip(offset as usize) {
+            if !sink.accept(name, ino, node_type, i as u64 + 1) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let inode = self
+            .inode
+            .entries
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::NotFound)?;
+        Ok(self.new_entry(name, inode))
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        let mut entries = self.inode.entries.lock();
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let inode = MemInode::new(&self.fs, node_type);
+        entries.insert(name.to_owned(), inode.clone());
+        Ok(self.new_entry(name, inode))
+    }
+
+    fn link(&self, name: &str, node: &DirEntry) -> VfsResult<DirEntry> {
+        let inode = node.downcast::<Self>()?.inode.clone();
+        self.inode
+            .entries
+            .lock()
+            .insert(name.to_owned(), inode.clone());
+        Ok(self.new_entry(name, inode))
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        self.inode
+            .entries
+            .lock()
+            .remove(name)
+            .map(drop)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
+        let inode = self
+            .inode
+            .entries
+            .lock()
+            .remove(src_name)
+            .ok_or(VfsError::NotFound)?;
+        dst_dir
+            .downcast::<Self>()?
+            .inode
+            .entries
+            .lock()
+            .insert(dst_name.to_owned(), inode);
+        Ok(())
+    }
+}
+
+fn list(ctx: &FsContext, path: &str) -> Vec<String> {
+    ctx.read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().name)
+        .filter(|name| name != "." && name != "..")
+        .collect()
+}
+
+#[def_test]
+fn test_fscrypt_lock_unlock() {
+    fscrypt::init();
+    let (fs, raw) = MemFs::new();
+    let mp = Mountpoint::new_root(&fs);
+    let ctx = FsContext::new(mp.root_location());
+
+    let key = [0x5a; 64];
+    let identifier = fscrypt::add_key(&key).unwrap();
+    let policy = EncryptionPolicy::new(identifier);
+
+    let dir = ctx
+        .create_dir("/secret", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    fscrypt::set_policy(&dir, &policy).unwrap();
+    assert_eq!(fscrypt::get_policy(&dir).unwrap(), policy);
+
+    let contents = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    ctx.write("/secret/notes.txt", &contents).unwrap();
+    ctx.write("/secret/tiny", b"hi").unwrap();
+    assert_eq!(ctx.read("/secret/notes.txt").unwrap(), contents);
+
+    // The filesystem only stores ciphertext.
+    let stored = raw
+        .raw(&["secret"])
+        .entries
+        .lock()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(stored.len(), 2);
+    assert!(
+        stored
+            .iter()
+            .all(|name| name != "notes.txt" && name != "tiny")
+    );
+    let ciphertext = stored
+        .iter()
+        .map(|name| raw.raw(&["secret", name]).data.lock().clone())
+        .find(|data| data.len() == contents.len())
+        .unwrap();
+    assert_ne!(ciphertext, contents);
+
+    // Lock: names are listed as stored and files cannot be opened.
+    fscrypt::remove_key(&identifier).unwrap();
+    let mut locked = list(&ctx, "/secret");
+    locked.sort();
+    let mut expected = stored.clone();
+    expected.sort();
+    assert_eq!(locked, expected);
+    for name in &locked {
+        assert_eq!(
+            ctx.read(alloc::format!("/secret/{name}")).unwrap_err(),
+            VfsError::from(LinuxError::ENOKEY)
+        );
+    }
+    assert_eq!(
+        ctx.write("/secret/new", b"x").unwrap_err(),
+        VfsError::from(LinuxError::ENOKEY)
+    );
+    assert_eq!(
+        fscrypt::remove_key(&identifier).unwrap_err(),
+        VfsError::from(LinuxError::ENOKEY)
+    );
+
+    // Unlock: plaintext names and contents are back.
+    assert_eq!(fscrypt::add_key(&key).unwrap(), identifier);
+    let mut unlocked = list(&ctx, "/secret");
+    unlocked.sort();
+    assert_eq!(unlocked, vec!["notes.txt".to_string(), "tiny".to_string()]);
+    assert_eq!(ctx.read("/secret/notes.txt").unwrap(), contents);
+    assert_eq!(ctx.read("/secret/tiny").unwrap(), b"hi");
+}
+
+#[def_test]
+fn test_fscrypt_policy_rules() {
+    fscrypt::init();
+    let (fs, _raw) = MemFs::new();
+    let mp = Mountpoint::new_root(&fs);
+    let ctx = FsContext::new(mp.root_location());
+    let perm = NodePermission::from_bits_truncate(0o755);
+
+    let identifier = fscrypt::add_key(&[0x33; 32]).unwrap();
+    let policy = EncryptionPolicy::new(identifier);
+
+    // Only empty directories can be encrypted.
+    let full = ctx.create_dir("/full", perm).unwrap();
+    ctx.write("/full/file", b"data").unwrap();
+    assert_eq!(
+        fscrypt::set_policy(&full, &policy).unwrap_err(),
+        VfsError::DirectoryNotEmpty
+    );
+    assert_eq!(
+        fscrypt::get_policy(&full).unwrap_err(),
+        VfsError::from(LinuxError::ENODATA)
+    );
+
+    let dir = ctx.create_dir("/enc", perm).unwrap();
+    fscrypt::set_policy(&dir, &policy).unwrap();
+    fscrypt::set_policy(&dir, &policy).unwrap();
+    let other = EncryptionPolicy::new([0; 16]);
+    assert_eq!(
+        fscrypt::set_policy(&dir, &other).unwrap_err(),
+        VfsError::AlreadyExists
+    );
+
+    // Children inherit the policy, and unencrypted files cannot be moved in.
+    let sub = ctx.create_dir("/enc/sub", perm).unwrap();
+    assert_eq!(fscrypt::get_policy(&sub).unwrap(), policy);
+    assert_eq!(
+        ctx.rename("/full/file", "/enc/file").unwrap_err(),
+        VfsError::CrossesDevices
+    );
+
+    // Resizing keeps the contents decryptable.
+    ctx.write("/enc/sub/grow", b"abc").unwrap();
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&ctx, "/enc/sub/grow")
+        .and_then(|result| result.into_file())
+        .unwrap();
+    file.access(FileFlags::WRITE)
+        .unwrap()
+        .set_len(9000)
+        .unwrap();
+    drop(file);
+    let mut expected = b"abc".to_vec();
+    expected.resize(9000, 0);
+    assert_eq!(ctx.read("/enc/sub/grow").unwrap(), expected);
+}
@@ -0,0 +1,89 @@
+//! Unit tests for scheduling latency under filesystem load.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use khal::time::{TICK_NANOS, monotonic_time_nanos};
+use ktask::PreemptModel;
+use unittest::def_test;
+
+use crate::test_direct_io::{open, setup};
+
+/// Bytes the background load copies per round.
+const STRESS_LEN: usize = 1024 * 1024;
+/// Wakeups measured.
+const SAMPLES: usize = 32;
+/// How late the wakeup of a high-priority task may be, in timer ticks. A
+/// timed wakeup is only noticed at a tick, so it is late by up to one tick
+/// even on an idle CPU.
+const LATENCY_TARGET_TICKS: u64 = 3;
+
+/// Sleeps `samples` times for a tick at the highest priority, and returns
+/// the latest wakeup past its deadline, in nanoseconds.
+fn wakeup_jitter_ns(samples: usize) -> u64 {
+    let curr = ktask::current();
+    let nice = curr.nice();
+    ktask::set_nice(&curr, ktask::MIN_NICE as i32);
+    let mut worst = 0;
+    for _ in 0..samples {
+        let deadline = monotonic_time_nanos() + TICK_NANOS;
+        ktask::sleep_until(Duration::from_nanos(deadline));
+        worst = worst.max(monotonic_time_nanos().saturating_sub(deadline));
+    }
+    ktask::set_nice(&curr, nice as i32);
+    worst
+}
+
+#[def_test]
+fn test_sched_latency_under_fs_stress() {
+    let model = ktask::preempt_model();
+    if model == PreemptModel::None {
+        // Nothing preempts the load, and the test would never end.
+        return;
+    }
+    // Only the preemption points may cut the load short.
+    ktask::set_preempt_model(PreemptModel::Voluntary);
+    ktask::reset_max_non_preempt_section();
+
+    let (ctx, fs) = setup();
+    *fs.disk.data.lock() = vec![0; STRESS_LEN];
+    let stop = Arc::new(AtomicBool::new(false));
+    let stress = ktask::spawn({
+        let stop = stop.clone();
+        move || {
+            let cached = open(&ctx, "/disk", false).unwrap();
+            let direct = open(&ctx, "/disk", true).unwrap();
+            let mut buf = vec![0u8; STRESS_LEN];
+            let mut round = 0u8;
+            while !stop.load(Ordering::Acquire) {
+                buf.fill(round);
+                assert_eq!(cached.write_at(&buf[..], 0).unwrap(), STRESS_LEN);
+                // Write back the page cache, then copy around it.
+                cached.sync(true).unwrap();
+                assert_eq!(direct.read_at(&mut buf[..], 0).unwrap(), STRESS_LEN);
+                assert!(buf.iter().all(|&b| b == round));
+                round = round.wrapping_add(1);
+            }
+        }
+    });
+
+    let jitter = wakeup_jitter_ns(SAMPLES);
+    stop.store(true, Ordering::Release);
+    stress.join();
+    ktask::set_preempt_model(model);
+
+    if let Some(max) = ktask::max_non_preempt_section() {
+        info!("longest non-preemptible section: {max}");
+    }
+    assert!(
+        jitter <= LATENCY_TARGET_TICKS * TICK_NANOS,
+        "worst wakeup latency {jitter}ns under filesystem load"
+    );
+}
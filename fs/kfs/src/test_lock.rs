@@ -0,0 +1,185 @@
+//! Unit tests for advisory file locks.
+
+#![cfg(unittest)]
+
+use alloc::{sync::Arc, task::Wake};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+use fs_ng_vfs::{
+    DirEntry, LockType, Mountpoint, RecordLock, VfsError, cancel_lock_wait,
+    release_all_record_locks,
+};
+use kerrno::LinuxError;
+use unittest::def_test;
+
+use crate::{FsContext, MemoryFs};
+
+fn create_file() -> DirEntry {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    ctx.write("/file", b"data").unwrap();
+    ctx.resolve("/file").unwrap().entry().clone()
+}
+
+fn record(owner: usize, ty: LockType, start: u64, end: u64) -> RecordLock {
+    RecordLock {
+        owner,
+        pid: Some(owner as u32),
+        ty,
+        start,
+        end,
+    }
+}
+
+#[derive(Default)]
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[def_test]
+fn test_flock_shared_and_exclusive() {
+    let file = create_file();
+    file.flock(1, Some(LockType::Read), None).unwrap();
+    file.flock(2, Some(LockType::Read), None).unwrap();
+    assert_eq!(
+        file.flock(3, Some(LockType::Write), None),
+        Err(VfsError::WouldBlock)
+    );
+
+    // A failed upgrade drops the shared lock, as on Linux.
+    assert_eq!(
+        file.flock(2, Some(LockType::Write), None),
+        Err(VfsError::WouldBlock)
+    );
+    file.flock(1, None, None).unwrap();
+    file.flock(2, Some(LockType::Write), None).unwrap();
+    assert_eq!(
+        file.flock(1, Some(LockType::Read), None),
+        Err(VfsError::WouldBlock)
+    );
+    file.flock(2, None, None).unwrap();
+}
+
+#[def_test]
+fn test_flock_wakes_waiter() {
+    let file = create_file();
+    let counter = Arc::new(CountingWaker::default());
+    let waker = Waker::from(counter.clone());
+
+    file.flock(1, Some(LockType::Write), None).unwrap();
+    assert_eq!(
+        file.flock(2, Some(LockType::Write), Some(&waker)),
+        Err(VfsError::WouldBlock)
+    );
+    assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    file.flock(1, None, None).unwrap();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    file.flock(2, Some(LockType::Write), None).unwrap();
+    file.flock(2, None, None).unwrap();
+}
+
+#[def_test]
+fn test_record_lock_split_and_merge() {
+    let file = create_file();
+    file.set_record_lock(record(1, LockType::Write, 0, 99), None)
+        .unwrap();
+    file.unlock_records(1, 10, 19).unwrap();
+
+    // The hole is free, the parts around it are still locked.
+    assert_eq!(
+        file.test_record_lock(&record(2, LockType::Write, 10, 19)),
+        None
+    );
+    assert_eq!(
+        file.test_record_lock(&record(2, LockType::Read, 5, 15)),
+        Some(record(1, LockType::Write, 0, 9))
+    );
+    assert_eq!(
+        file.test_record_lock(&record(2, LockType::Read, 15, 25)),
+        Some(record(1, LockType::Write, 20, 99))
+    );
+
+    // Filling the hole merges the three locks again.
+    file.set_record_lock(record(1, LockType::Write, 10, 19), None)
+        .unwrap();
+    assert_eq!(
+        file.test_record_lock(&record(2, LockType::Read, 50, 50)),
+        Some(record(1, LockType::Write, 0, 99))
+    );
+
+    // A read lock in the middle splits a write lock in three.
+    file.set_record_lock(record(1, LockType::Read, 40, 59), None)
+        .unwrap();
+    file.set_record_lock(record(2, LockType::Read, 45, u64::MAX), None)
+        .unwrap_err();
+    file.set_record_lock(record(2, LockType::Read, 45, 55), None)
+        .unwrap();
+    assert_eq!(
+        file.test_record_lock(&record(3, LockType::Write, 60, u64::MAX)),
+        Some(record(1, LockType::Write, 60, 99))
+    );
+    file.release_record_locks(1);
+    file.release_record_locks(2);
+}
+
+#[def_test]
+fn test_record_lock_deadlock() {
+    let file = create_file();
+    let waker = Waker::noop();
+    file.set_record_lock(record(1, LockType::Write, 0, 9), None)
+        .unwrap();
+    file.set_record_lock(record(2, LockType::Write, 10, 19), None)
+        .unwrap();
+
+    assert_eq!(
+        file.set_record_lock(record(1, LockType::Write, 10, 19), Some(waker)),
+        Err(VfsError::WouldBlock)
+    );
+    assert_eq!(
+        file.set_record_lock(record(2, LockType::Write, 0, 9), Some(waker)),
+        Err(VfsError::from(LinuxError::EDEADLK))
+    );
+
+    // Once the first owner stops waiting, the second one may wait.
+    cancel_lock_wait(1);
+    assert_eq!(
+        file.set_record_lock(record(2, LockType::Write, 0, 9), Some(waker)),
+        Err(VfsError::WouldBlock)
+    );
+    cancel_lock_wait(2);
+    file.release_record_locks(1);
+    file.release_record_locks(2);
+}
+
+#[def_test]
+fn test_release_record_locks() {
+    let file = create_file();
+    let other = create_file();
+    file.set_record_lock(record(1, LockType::Write, 0, u64::MAX), None)
+        .unwrap();
+    other
+        .set_record_lock(record(1, LockType::Read, 0, 9), None)
+        .unwrap();
+    file.release_record_locks(2);
+    assert!(
+        file.test_record_lock(&record(2, LockType::Read, 0, 0))
+            .is_some()
+    );
+
+    release_all_record_locks(1);
+    assert_eq!(
+        file.test_record_lock(&record(2, LockType::Write, 0, u64::MAX)),
+        None
+    );
+    assert_eq!(
+        other.test_record_lock(&record(2, LockType::Write, 0, u64::MAX)),
+        None
+    );
+}
@@ -0,0 +1,147 @@
+//! Unit tests for the page cache as shared file mappings use it.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use ksync::Mutex;
+use unittest::def_test;
+
+use crate::{
+    CachedFile, FileBackend, FsContext, PageEvent,
+    test_direct_io::{DiskFs, open, setup},
+};
+
+const PAGE: usize = 4096;
+
+/// Returns the page cache of `/disk`, as a mapping of it gets it.
+fn cached(ctx: &FsContext) -> CachedFile {
+    match open(ctx, "/disk", false).unwrap().backend().unwrap() {
+        FileBackend::Cached(cache) => cache.clone(),
+        FileBackend::Direct(_) => panic!("/disk is not cached"),
+    }
+}
+
+/// Sets up `/disk` holding `pages` pages of ones, and returns its page cache.
+fn setup_file(pages: usize) -> (FsContext, Arc<DiskFs>, CachedFile) {
+    let (ctx, fs) = setup();
+    *fs.disk.data.lock() = vec![1; pages * PAGE];
+    let cache = cached(&ctx);
+    (ctx, fs, cache)
+}
+
+/// Stores `value` at `offset` of page `pn`, as a store through a mapping does.
+fn store(cache: &CachedFile, pn: u32, offset: usize, value: u8) {
+    cache
+        .with_page_or_insert(pn, |page, _| {
+            page.data()[offset] = value;
+            page.mark_dirty();
+            Ok(())
+        })
+        .unwrap();
+}
+
+/// Adds a listener recording the events, answering `handled`.
+fn record(cache: &CachedFile, handled: bool) -> Arc<Mutex<Vec<(u32, PageEvent)>>> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    cache.add_evict_listener({
+        let events = events.clone();
+        move |pn, event| {
+            events.lock().push((pn, event));
+            handled
+        }
+    });
+    events
+}
+
+#[def_test]
+fn test_mmap_shared_pages() {
+    let (ctx, _fs, first) = setup_file(2);
+    let second = cached(&ctx);
+
+    // Both mappings map the same frame.
+    let paddr = |cache: &CachedFile| {
+        cache
+            .with_page_or_insert(1, |page, _| Ok(page.paddr()))
+            .unwrap()
+    };
+    assert_eq!(paddr(&first), paddr(&second));
+
+    store(&first, 1, 7, 42);
+    let mut buf = [0; 1];
+    assert_eq!(second.read_at(&mut buf[..], (PAGE + 7) as u64), Ok(1));
+    assert_eq!(buf, [42]);
+}
+
+#[def_test]
+fn test_mmap_sync_range() {
+    let (_ctx, fs, cache) = setup_file(2);
+    let events = record(&cache, true);
+    store(&cache, 0, 0, 5);
+    store(&cache, 1, 0, 6);
+
+    cache.sync_range(0..PAGE as u64).unwrap();
+    assert_eq!(fs.disk.data.lock()[0], 5);
+    assert_eq!(fs.disk.data.lock()[PAGE], 1);
+    assert_eq!(*events.lock(), [(0, PageEvent::Cleaned)]);
+
+    // The page is clean once written back: it is not written again.
+    fs.disk.data.lock()[0] = 9;
+    cache.sync_range(0..PAGE as u64).unwrap();
+    assert_eq!(fs.disk.data.lock()[0], 9);
+    assert_eq!(events.lock().len(), 1);
+}
+
+#[def_test]
+fn test_mmap_sync_range_unprotected() {
+    let (_ctx, fs, cache) = setup_file(1);
+    // A mapping that cannot be write-protected may still be stored to.
+    let _events = record(&cache, false);
+    store(&cache, 0, 0, 5);
+
+    cache.sync_range(0..PAGE as u64).unwrap();
+    assert_eq!(fs.disk.data.lock()[0], 5);
+    fs.disk.data.lock()[0] = 9;
+    cache.sync_range(0..PAGE as u64).unwrap();
+    assert_eq!(fs.disk.data.lock()[0], 5);
+}
+
+#[def_test]
+fn test_mmap_truncate() {
+    let (_ctx, fs, cache) = setup_file(3);
+    for pn in 0..3 {
+        store(&cache, pn, 0, 5);
+    }
+    let events = record(&cache, true);
+
+    // Pages wholly past the new end of file are unmapped and dropped, without
+    // being written back.
+    cache.set_len(PAGE as u64 + 10).unwrap();
+    assert_eq!(*events.lock(), [(2, PageEvent::Evicted)]);
+    assert_eq!(fs.disk.data.lock().len(), PAGE + 10);
+
+    // Stores past the end of file in the last page do not show up when the
+    // file grows over them.
+    store(&cache, 1, 20, 7);
+    cache.set_len(2 * PAGE as u64).unwrap();
+    let mut buf = [0xff; 16];
+    assert_eq!(cache.read_at(&mut buf[..], PAGE as u64 + 10), Ok(16));
+    assert_eq!(buf, [0; 16]);
+    assert_eq!(events.lock().len(), 1);
+}
+
+#[def_test]
+fn test_mmap_write_past_eof() {
+    let (_ctx, _fs, cache) = setup_file(1);
+    cache.set_len(100).unwrap();
+    store(&cache, 0, 200, 7);
+
+    // Extending the file by writing zeroes the gap the same way.
+    assert_eq!(cache.write_at(&[3][..], 300), Ok(1));
+    let mut buf = [0xff; 201];
+    assert_eq!(cache.read_at(&mut buf[..], 100), Ok(201));
+    assert_eq!(buf[..200], [0; 200]);
+    assert_eq!(buf[200], 3);
+}
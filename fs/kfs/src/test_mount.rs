@@ -0,0 +1,253 @@
+//! Unit tests for mounting and unmounting.
+
+#![cfg(unittest)]
+
+use fs_ng_vfs::{Mountpoint, NodePermission, VfsError};
+use unittest::def_test;
+
+use crate::{
+    FsContext, MemoryFs, MountFlags, UnmountFlags, bind_mount, mount::block_device_name,
+    mount_tmpfs, remount, umount,
+};
+
+fn create_context() -> FsContext {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    ctx.create_dir("/mnt", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    ctx
+}
+
+#[def_test]
+fn test_mount_dotdot_crosses_mounts() {
+    let ctx = create_context();
+    mount_tmpfs(&ctx, "/mnt", None).unwrap();
+    ctx.write("/mnt/file", b"data").unwrap();
+
+    let mnt = ctx.resolve("/mnt").unwrap();
+    assert!(mnt.is_root_of_mount());
+    assert!(ctx.resolve("/mnt/..").unwrap().is_root());
+    assert!(ctx.resolve("/..").unwrap().is_root());
+    assert_eq!(ctx.read("/mnt/../mnt/file").unwrap(), b"data");
+    assert_eq!(ctx.read("/../../mnt/file").unwrap(), b"data");
+
+    let inner = ctx.with_current_dir(mnt).unwrap();
+    assert_eq!(inner.read("file").unwrap(), b"data");
+    assert!(inner.resolve("..").unwrap().is_root());
+}
+
+#[def_test]
+fn test_umount_busy_and_detach() {
+    let ctx = create_context();
+    assert_eq!(
+        umount(&ctx, "/mnt", UnmountFlags::empty()).unwrap_err(),
+        VfsError::InvalidInput
+    );
+    assert_eq!(
+        umount(&ctx, "/", UnmountFlags::empty()).unwrap_err(),
+        VfsError::ResourceBusy
+    );
+
+    mount_tmpfs(&ctx, "/mnt", None).unwrap();
+    ctx.write("/mnt/file", b"data").unwrap();
+    let open = crate::File::open(&ctx, "/mnt/file").unwrap();
+    assert_eq!(
+        umount(&ctx, "/mnt", UnmountFlags::empty()).unwrap_err(),
+        VfsError::ResourceBusy
+    );
+    umount(&ctx, "/mnt", UnmountFlags::DETACH).unwrap();
+
+    // The file stays usable, while the path shows the directory below.
+    let mut buf = [0; 4];
+    open.read_at(&mut buf[..], 0).unwrap();
+    assert_eq!(&buf, b"data");
+    assert_eq!(ctx.resolve("/mnt/file").unwrap_err(), VfsError::NotFound);
+    drop(open);
+
+    mount_tmpfs(&ctx, "/mnt", None).unwrap();
+    umount(&ctx, "/mnt", UnmountFlags::empty()).unwrap();
+    assert!(!ctx.resolve("/mnt").unwrap().is_root_of_mount());
+}
+
+#[def_test]
+fn test_tmpfs_size_limit() {
+    let ctx = create_context();
+    mount_tmpfs(&ctx, "/mnt", Some(8192)).unwrap();
+    ctx.write("/mnt/a", [1u8; 8192]).unwrap();
+    assert_eq!(
+        ctx.write("/mnt/b", [1u8; 1]).unwrap_err(),
+        VfsError::StorageFull
+    );
+    // Truncating frees the space again.
+    ctx.write("/mnt/a", [1u8; 4096]).unwrap();
+    ctx.write("/mnt/b", [1u8; 4096]).unwrap();
+}
+
+fn mode() -> NodePermission {
+    NodePermission::from_bits_truncate(0o755)
+}
+
+#[def_test]
+fn test_bind_mount_aliases() {
+    let ctx = create_context();
+    ctx.create_dir("/mnt/src", mode()).unwrap();
+    ctx.create_dir("/mnt/src/dir", mode()).unwrap();
+    ctx.write("/mnt/src/dir/file", b"data").unwrap();
+    ctx.create_dir("/alias", mode()).unwrap();
+    bind_mount(&ctx, "/mnt/src", "/alias", false).unwrap();
+
+    // Changes through either alias show through the other.
+    assert_eq!(ctx.read("/alias/dir/file").unwrap(), b"data");
+    ctx.rename("/alias/dir/file", "/alias/dir/moved").unwrap();
+    assert_eq!(ctx.read("/mnt/src/dir/moved").unwrap(), b"data");
+    assert_eq!(
+        ctx.resolve("/mnt/src/dir/file").unwrap_err(),
+        VfsError::NotFound
+    );
+    ctx.remove_file("/mnt/src/dir/moved").unwrap();
+    assert_eq!(
+        ctx.resolve("/alias/dir/moved").unwrap_err(),
+        VfsError::NotFound
+    );
+    // Renames do not cross mounts, even of the same filesystem.
+    ctx.write("/alias/file", b"data").unwrap();
+    assert_eq!(
+        ctx.rename("/alias/file", "/mnt/file").unwrap_err(),
+        VfsError::CrossesDevices
+    );
+
+    // `..` leaves the bind mount where it is mounted.
+    let alias = ctx.resolve("/alias").unwrap();
+    assert!(alias.is_root_of_mount());
+    assert!(ctx.resolve("/alias/..").unwrap().is_root());
+    assert_eq!(
+        ctx.resolve("/alias/dir")
+            .unwrap()
+            .absolute_path()
+            .unwrap()
+            .as_str(),
+        "/alias/dir"
+    );
+    let inner = ctx.with_current_dir(alias).unwrap();
+    assert!(inner.resolve("../mnt/src/dir").is_ok());
+
+    // Files can be bound onto files.
+    ctx.write("/mnt/other", b"other").unwrap();
+    assert_eq!(
+        bind_mount(&ctx, "/mnt/other", "/mnt", false).unwrap_err(),
+        VfsError::NotADirectory
+    );
+    bind_mount(&ctx, "/mnt/other", "/alias/file", false).unwrap();
+    assert_eq!(ctx.read("/alias/file").unwrap(), b"other");
+    umount(&ctx, "/alias/file", UnmountFlags::empty()).unwrap();
+    assert_eq!(ctx.read("/alias/file").unwrap(), b"data");
+
+    // Unmounting the alias leaves the original alone.
+    drop(inner);
+    umount(&ctx, "/alias", UnmountFlags::empty()).unwrap();
+    assert_eq!(ctx.resolve("/alias/dir").unwrap_err(), VfsError::NotFound);
+    assert_eq!(ctx.read("/mnt/src/file").unwrap(), b"data");
+}
+
+#[def_test]
+fn test_recursive_bind_mount() {
+    let ctx = create_context();
+    ctx.create_dir("/mnt/sub", mode()).unwrap();
+    mount_tmpfs(&ctx, "/mnt/sub", None).unwrap();
+    ctx.write("/mnt/sub/file", b"data").unwrap();
+    ctx.create_dir("/flat", mode()).unwrap();
+    ctx.create_dir("/tree", mode()).unwrap();
+
+    bind_mount(&ctx, "/mnt", "/flat", false).unwrap();
+    assert!(!ctx.resolve("/flat/sub").unwrap().is_root_of_mount());
+    assert_eq!(
+        ctx.resolve("/flat/sub/file").unwrap_err(),
+        VfsError::NotFound
+    );
+
+    bind_mount(&ctx, "/mnt", "/tree", true).unwrap();
+    assert!(ctx.resolve("/tree/sub").unwrap().is_root_of_mount());
+    assert_eq!(ctx.read("/tree/sub/file").unwrap(), b"data");
+    ctx.write("/tree/sub/new", b"new").unwrap();
+    assert_eq!(ctx.read("/mnt/sub/new").unwrap(), b"new");
+
+    // Binding a directory into itself copies the mounts below it once.
+    ctx.create_dir("/mnt/loop", mode()).unwrap();
+    bind_mount(&ctx, "/mnt", "/mnt/loop", true).unwrap();
+    assert_eq!(ctx.read("/mnt/loop/sub/file").unwrap(), b"data");
+    assert!(!ctx.resolve("/mnt/loop/loop").unwrap().is_root_of_mount());
+
+    // The copies are mounts of their own.
+    assert_eq!(
+        umount(&ctx, "/tree", UnmountFlags::empty()).unwrap_err(),
+        VfsError::ResourceBusy
+    );
+    umount(&ctx, "/tree/sub", UnmountFlags::empty()).unwrap();
+    umount(&ctx, "/tree", UnmountFlags::empty()).unwrap();
+    assert_eq!(ctx.read("/mnt/sub/file").unwrap(), b"data");
+    assert_eq!(ctx.read("/mnt/loop/sub/file").unwrap(), b"data");
+}
+
+#[def_test]
+fn test_read_only_bind_mount() {
+    let ctx = create_context();
+    ctx.write("/mnt/file", b"data").unwrap();
+    ctx.create_dir("/ro", mode()).unwrap();
+    bind_mount(&ctx, "/mnt", "/ro", false).unwrap();
+    remount(
+        &ctx,
+        "/ro",
+        MountFlags::REMOUNT | MountFlags::BIND | MountFlags::RDONLY,
+    )
+    .unwrap();
+
+    assert_eq!(ctx.read("/ro/file").unwrap(), b"data");
+    assert_eq!(
+        ctx.write("/ro/file", b"new").unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+    assert_eq!(
+        ctx.write("/ro/new", b"new").unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+    assert_eq!(
+        ctx.create_dir("/ro/dir", mode()).unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+    assert_eq!(
+        ctx.remove_file("/ro/file").unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+    assert_eq!(
+        ctx.rename("/ro/file", "/ro/moved").unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+
+    // The original stays writable, and its changes show through the alias.
+    ctx.write("/mnt/file", b"new").unwrap();
+    assert_eq!(ctx.read("/ro/file").unwrap(), b"new");
+
+    // Binds of a read-only mount start out read-only.
+    ctx.create_dir("/mnt/again", mode()).unwrap();
+    bind_mount(&ctx, "/ro", "/mnt/again", false).unwrap();
+    assert_eq!(
+        ctx.write("/mnt/again/file", b"data").unwrap_err(),
+        VfsError::ReadOnlyFilesystem
+    );
+
+    // Only the flags of the bind mount itself can be changed.
+    assert_eq!(
+        remount(&ctx, "/ro", MountFlags::REMOUNT).unwrap_err(),
+        VfsError::OperationNotSupported
+    );
+    remount(&ctx, "/ro", MountFlags::REMOUNT | MountFlags::BIND).unwrap();
+    ctx.write("/ro/file", b"data").unwrap();
+}
+
+#[def_test]
+fn test_block_device_names() {
+    assert_eq!(block_device_name(0), "vda");
+    assert_eq!(block_device_name(1), "vdb");
+    assert_eq!(block_device_name(25), "vdz");
+    assert_eq!(block_device_name(26), "vdaa");
+}
@@ -0,0 +1,149 @@
+//! Unit tests for change notification.
+
+#![cfg(unittest)]
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use fs_ng_vfs::{FsEvents, FsWatch, Location, Mountpoint, NodePermission};
+use ksync::Mutex;
+use unittest::def_test;
+
+use crate::{FsContext, MemoryFs};
+
+type Record = (FsEvents, Option<String>, u32);
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<Record>>);
+
+impl FsWatch for Recorder {
+    fn notify(&self, events: FsEvents, name: Option<&str>, cookie: u32) {
+        self.0
+            .lock()
+            .push((events, name.map(ToString::to_string), cookie));
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<Record> {
+        core::mem::take(&mut *self.0.lock())
+    }
+}
+
+fn create_context() -> (Arc<Mountpoint>, FsContext) {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    (mp, ctx)
+}
+
+fn watch(loc: &Location) -> Arc<Recorder> {
+    let recorder = Arc::new(Recorder::default());
+    loc.entry()
+        .add_watch(&(recorder.clone() as Arc<dyn FsWatch>));
+    recorder
+}
+
+fn named(events: FsEvents, name: &str) -> Record {
+    (events, Some(name.into()), 0)
+}
+
+#[def_test]
+fn test_directory_events() {
+    let (_mp, ctx) = create_context();
+    let mode = NodePermission::from_bits_truncate(0o755);
+    let dir = ctx.create_dir("/dir", mode).unwrap();
+    let recorder = watch(&dir);
+
+    ctx.write("/dir/file", b"data").unwrap();
+    ctx.create_dir("/dir/sub", mode).unwrap();
+    ctx.remove_file("/dir/file").unwrap();
+    ctx.remove_dir("/dir/sub").unwrap();
+    assert_eq!(
+        recorder.take(),
+        [
+            named(FsEvents::CREATE, "file"),
+            named(FsEvents::MODIFY, "file"),
+            named(FsEvents::CLOSE_WRITE, "file"),
+            named(FsEvents::CREATE | FsEvents::ISDIR, "sub"),
+            named(FsEvents::DELETE, "file"),
+            named(FsEvents::DELETE | FsEvents::ISDIR, "sub"),
+        ]
+    );
+
+    // Reading reports nothing but the close.
+    ctx.write("/dir/file", b"data").unwrap();
+    recorder.take();
+    assert_eq!(ctx.read("/dir/file").unwrap(), b"data");
+    assert_eq!(recorder.take(), [named(FsEvents::CLOSE_NOWRITE, "file")]);
+}
+
+#[def_test]
+fn test_rename_events() {
+    let (_mp, ctx) = create_context();
+    let mode = NodePermission::from_bits_truncate(0o755);
+    let src = watch(&ctx.create_dir("/src", mode).unwrap());
+    let dst = watch(&ctx.create_dir("/dst", mode).unwrap());
+    ctx.write("/src/a", b"data").unwrap();
+    let file = watch(&ctx.resolve("/src/a").unwrap());
+    src.take();
+
+    ctx.rename("/src/a", "/dst/b").unwrap();
+    let from = src.take();
+    let to = dst.take();
+    assert_eq!(from.len(), 1);
+    assert_eq!(to.len(), 1);
+    let (from_events, from_name, cookie) = &from[0];
+    assert_eq!(*from_events, FsEvents::MOVED_FROM);
+    assert_eq!(from_name.as_deref(), Some("a"));
+    assert_ne!(*cookie, 0);
+    assert_eq!(to[0], (FsEvents::MOVED_TO, Some("b".into()), *cookie));
+    assert_eq!(file.take(), [(FsEvents::MOVE_SELF, None, 0)]);
+
+    // The watch follows the inode, not the name.
+    ctx.write("/dst/b", b"more").unwrap();
+    assert!(file.take().contains(&(FsEvents::MODIFY, None, 0)));
+}
+
+#[def_test]
+fn test_file_events() {
+    let (_mp, ctx) = create_context();
+    ctx.write("/file", b"data").unwrap();
+    let file = watch(&ctx.resolve("/file").unwrap());
+
+    ctx.write("/file", b"new").unwrap();
+    assert_eq!(
+        file.take(),
+        [
+            (FsEvents::MODIFY, None, 0),
+            (FsEvents::CLOSE_WRITE, None, 0)
+        ]
+    );
+
+    // The inode is deleted only with its last link.
+    ctx.link("/file", "/hard").unwrap();
+    ctx.remove_file("/file").unwrap();
+    assert_eq!(file.take(), []);
+    ctx.remove_file("/hard").unwrap();
+    assert_eq!(file.take(), [(FsEvents::DELETE_SELF, None, 0)]);
+}
+
+#[def_test]
+fn test_removed_watch() {
+    let (_mp, ctx) = create_context();
+    let root = ctx.root_dir().clone();
+    let recorder = watch(&root);
+    let kept = watch(&root);
+    root.entry()
+        .remove_watch(&(recorder.clone() as Arc<dyn FsWatch>));
+
+    ctx.write("/file", b"data").unwrap();
+    assert_eq!(recorder.take(), []);
+    assert!(!kept.take().is_empty());
+
+    // Dropped watches are forgotten.
+    drop(kept);
+    ctx.write("/file", b"data").unwrap();
+}
@@ -2,9 +2,10 @@
 
 #![cfg(unittest)]
 
+use fs_ng_vfs::{Mountpoint, NodePermission, NodeType, VfsError, path::Path};
 use unittest::def_test;
 
-use crate::PathResolver;
+use crate::{FsContext, MemoryFs, OpenOptions, PathResolver, ResolveFlags, mount_tmpfs};
 
 #[def_test]
 fn test_path_resolver_max_symlinks_config() {
@@ -24,3 +25,146 @@ fn test_path_resolver_clone() {
     let resolver1 = PathResolver::with_max_symlinks(20);
     let _resolver2 = resolver1.clone();
 }
+
+/// Creates a context holding `/bin/sh` and `/usr`.
+fn create_context() -> FsContext {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    let mode = NodePermission::from_bits_truncate(0o755);
+    ctx.create_dir("/bin", mode).unwrap();
+    ctx.create_dir("/usr", mode).unwrap();
+    ctx.write("/bin/sh", b"sh").unwrap();
+    ctx
+}
+
+#[def_test]
+fn test_symlink_targets() {
+    let ctx = create_context();
+    ctx.symlink("/bin", "/usr/bin").unwrap();
+    ctx.symlink("../bin/sh", "/usr/sh").unwrap();
+    ctx.symlink("usr/sh", "/sh").unwrap();
+
+    // Absolute targets start from the root, relative ones from the directory
+    // holding the symlink, also when the symlink is not the final component.
+    assert_eq!(ctx.read("/usr/bin/sh").unwrap(), b"sh");
+    assert_eq!(ctx.read("/usr/sh").unwrap(), b"sh");
+    assert_eq!(ctx.read("/sh").unwrap(), b"sh");
+    assert!(ctx.resolve("/usr/bin/..").unwrap().is_root());
+
+    // A trailing slash requires a directory.
+    assert!(ctx.resolve("/usr/bin/").unwrap().is_dir());
+    assert_eq!(ctx.resolve("/sh/").unwrap_err(), VfsError::NotADirectory);
+}
+
+#[def_test]
+fn test_symlink_loops() {
+    let ctx = create_context();
+    ctx.symlink("self", "/self").unwrap();
+    ctx.symlink("/b", "/a").unwrap();
+    ctx.symlink("/a", "/b").unwrap();
+
+    for path in ["/self", "/a", "/b/sh", "/usr/../a"] {
+        assert_eq!(ctx.resolve(path).unwrap_err(), VfsError::FilesystemLoop);
+    }
+    let link = ctx.resolve_no_follow("/a").unwrap();
+    assert_eq!(link.node_type(), NodeType::Symlink);
+    assert_eq!(link.read_link().unwrap(), "/b");
+}
+
+#[def_test]
+fn test_symlink_limit() {
+    let ctx = create_context();
+    ctx.symlink("/bin", "/l0").unwrap();
+    ctx.symlink("/l0", "/l1").unwrap();
+    ctx.symlink("/l1", "/l2").unwrap();
+
+    let root = ctx.root_dir();
+    let path = Path::new("/l2/sh");
+    let resolve =
+        |max| PathResolver::with_max_symlinks(max).resolve(root, root, path, ResolveFlags::empty());
+    assert!(resolve(3).unwrap().is_file());
+    assert_eq!(resolve(2).unwrap_err(), VfsError::FilesystemLoop);
+}
+
+#[def_test]
+fn test_symlink_dangling() {
+    let ctx = create_context();
+    ctx.symlink("/nowhere", "/dangling").unwrap();
+
+    assert_eq!(ctx.resolve("/dangling").unwrap_err(), VfsError::NotFound);
+    assert_eq!(ctx.resolve("/dangling/x").unwrap_err(), VfsError::NotFound);
+    let link = ctx.resolve_no_follow("/dangling").unwrap();
+    assert_eq!(link.node_type(), NodeType::Symlink);
+
+    ctx.remove_file("/dangling").unwrap();
+    assert_eq!(
+        ctx.resolve_no_follow("/dangling").unwrap_err(),
+        VfsError::NotFound
+    );
+}
+
+#[def_test]
+fn test_symlink_no_follow_flags() {
+    let ctx = create_context();
+    ctx.symlink("/bin", "/link").unwrap();
+
+    let no_follow = |path| ctx.resolve_with_flags(path, ResolveFlags::NO_FOLLOW);
+    assert_eq!(no_follow("/link").unwrap().node_type(), NodeType::Symlink);
+    assert_eq!(no_follow("/link/").unwrap_err(), VfsError::NotADirectory);
+    // Symlinks before the final component are followed regardless.
+    assert!(no_follow("/link/sh").unwrap().is_file());
+
+    // `lstat` follows the symlink if the path ends with a slash.
+    assert_eq!(
+        ctx.resolve_no_follow("/link").unwrap().node_type(),
+        NodeType::Symlink
+    );
+    assert!(ctx.resolve_no_follow("/link/").unwrap().is_dir());
+    assert!(ctx.resolve_no_follow("/link/.").unwrap().is_dir());
+
+    // Only `O_PATH` opens the symlink itself.
+    let mut options = OpenOptions::new();
+    options.read(true).no_follow(true);
+    assert!(matches!(
+        options.open(&ctx, "/link"),
+        Err(VfsError::FilesystemLoop)
+    ));
+    assert!(options.path(true).open(&ctx, "/link").is_ok());
+
+    // Removing the symlink leaves its target alone.
+    ctx.remove_file("/link").unwrap();
+    assert_eq!(ctx.read("/bin/sh").unwrap(), b"sh");
+}
+
+#[def_test]
+fn test_symlink_across_mounts() {
+    let ctx = create_context();
+    ctx.create_dir("/mnt", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    mount_tmpfs(&ctx, "/mnt", None).unwrap();
+    ctx.write("/mnt/file", b"tmp").unwrap();
+    ctx.symlink("/mnt/file", "/in").unwrap();
+    ctx.symlink("../bin/sh", "/mnt/out").unwrap();
+    ctx.symlink("/mnt", "/usr/mnt").unwrap();
+
+    assert_eq!(ctx.read("/in").unwrap(), b"tmp");
+    assert_eq!(ctx.read("/mnt/out").unwrap(), b"sh");
+    assert_eq!(ctx.read("/usr/mnt/out").unwrap(), b"sh");
+    assert!(ctx.resolve("/usr/mnt").unwrap().is_root_of_mount());
+}
+
+#[def_test]
+fn test_symlink_stays_in_root() {
+    let ctx = create_context();
+    ctx.create_dir("/usr/bin", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    ctx.write("/usr/bin/sh", b"usr").unwrap();
+    ctx.symlink("/bin/sh", "/usr/abs").unwrap();
+    ctx.symlink("../../../bin/sh", "/usr/rel").unwrap();
+
+    // Absolute targets and `..` stay below the root of the context.
+    let usr = FsContext::new(ctx.resolve("/usr").unwrap());
+    assert_eq!(usr.read("/abs").unwrap(), b"usr");
+    assert_eq!(usr.read("/rel").unwrap(), b"usr");
+    assert_eq!(usr.read("/../../bin/sh").unwrap(), b"usr");
+}
@@ -0,0 +1,161 @@
+//! Unit tests for the treatment of pathological paths, against the results of
+//! Linux.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::format;
+
+use fs_ng_vfs::{
+    DeviceId, Mountpoint, NodePermission, NodeType, VfsResult,
+    path::{MAX_NAME_LEN, MAX_PATH_LEN, verify_path},
+};
+use kerrno::LinuxError;
+use unittest::{assert_eq, def_test};
+
+use crate::{FsContext, MemoryFs, OpenOptions};
+
+/// What is done with a path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Stat,
+    Lstat,
+    Open,
+    Creat,
+    Mkdir,
+    Mknod,
+    Symlink,
+    /// Links `/file` at the path.
+    LinkTo,
+    /// Links the path at `/new`.
+    LinkFrom,
+    Unlink,
+    Rmdir,
+    /// Renames the path to `/new`.
+    RenameFrom,
+    /// Renames `/file` to the path.
+    RenameFile,
+    /// Renames `/dir` to the path.
+    RenameDir,
+}
+
+/// Creates a context holding `/dir`, `/file`, `/link` to `dir`, `/flink` to
+/// `file` and `/dangling` to nowhere.
+fn create_context() -> FsContext {
+    let mp = Mountpoint::new_root(&MemoryFs::new());
+    let ctx = FsContext::new(mp.root_location());
+    ctx.create_dir("/dir", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    ctx.write("/file", b"file").unwrap();
+    ctx.symlink("dir", "/link").unwrap();
+    ctx.symlink("file", "/flink").unwrap();
+    ctx.symlink("nowhere", "/dangling").unwrap();
+    ctx
+}
+
+/// Does `op` on `path` as the system call would, path checks included.
+fn run(op: Op, path: &str) -> VfsResult<()> {
+    verify_path(path.as_bytes())?;
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT.into());
+    }
+    let ctx = create_context();
+    let mode = NodePermission::from_bits_truncate(0o644);
+    match op {
+        Op::Stat => ctx.resolve(path).map(drop),
+        Op::Lstat => ctx.resolve_no_follow(path).map(drop),
+        Op::Open => OpenOptions::new().read(true).open(&ctx, path).map(drop),
+        Op::Creat => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&ctx, path)
+            .map(drop),
+        Op::Mkdir => ctx.create_dir(path, mode).map(drop),
+        Op::Mknod => ctx
+            .mknod(path, NodeType::Fifo, mode, DeviceId::default())
+            .map(drop),
+        Op::Symlink => ctx.symlink("file", path).map(drop),
+        Op::LinkTo => ctx.link("/file", path).map(drop),
+        Op::LinkFrom => ctx.link(path, "/new").map(drop),
+        Op::Unlink => ctx.remove_file(path),
+        Op::Rmdir => ctx.remove_dir(path),
+        Op::RenameFrom => ctx.rename(path, "/new"),
+        Op::RenameFile => ctx.rename("/file", path),
+        Op::RenameDir => ctx.rename("/dir", path),
+    }
+}
+
+#[def_test]
+fn test_paths_like_linux() {
+    let long_name = format!("/{}", "n".repeat(MAX_NAME_LEN + 1));
+    let longest_name = format!("/{}", "n".repeat(MAX_NAME_LEN));
+    // Slashes only, so the longest path names the root.
+    let longest_path = "/".repeat(MAX_PATH_LEN - 1);
+    let long_path = "/".repeat(MAX_PATH_LEN);
+
+    // As recorded on Linux 6.6, on tmpfs.
+    let cases: &[(Op, &str, Result<(), LinuxError>)] = &[
+        (Op::Stat, "", Err(LinuxError::ENOENT)),
+        (Op::Stat, "/dir/", Ok(())),
+        (Op::Stat, "/dir/.", Ok(())),
+        (Op::Stat, "//dir//", Ok(())),
+        (Op::Stat, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::Stat, "/file/.", Err(LinuxError::ENOTDIR)),
+        (Op::Stat, "/file/..", Err(LinuxError::ENOTDIR)),
+        (Op::Stat, "/file/x", Err(LinuxError::ENOTDIR)),
+        (Op::Stat, "/missing/", Err(LinuxError::ENOENT)),
+        (Op::Stat, "/link/", Ok(())),
+        (Op::Stat, "/link/..", Ok(())),
+        (Op::Stat, "/flink/", Err(LinuxError::ENOTDIR)),
+        (Op::Stat, "/dangling/", Err(LinuxError::ENOENT)),
+        (Op::Stat, long_name.as_str(), Err(LinuxError::ENAMETOOLONG)),
+        (Op::Stat, longest_name.as_str(), Err(LinuxError::ENOENT)),
+        (Op::Stat, longest_path.as_str(), Ok(())),
+        (Op::Stat, long_path.as_str(), Err(LinuxError::ENAMETOOLONG)),
+        (Op::Lstat, "/link", Ok(())),
+        (Op::Lstat, "/link/", Ok(())),
+        (Op::Lstat, "/flink/", Err(LinuxError::ENOTDIR)),
+        (Op::Lstat, "/dangling", Ok(())),
+        (Op::Open, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::Open, "/flink/", Err(LinuxError::ENOTDIR)),
+        (Op::Creat, "/new", Ok(())),
+        (Op::Creat, "/new/", Err(LinuxError::EISDIR)),
+        (Op::Creat, "/file/", Err(LinuxError::EISDIR)),
+        (Op::Creat, "/dir/", Err(LinuxError::EISDIR)),
+        (Op::Creat, long_name.as_str(), Err(LinuxError::ENAMETOOLONG)),
+        (Op::Mkdir, "/new/", Ok(())),
+        (Op::Mkdir, "/dir/", Err(LinuxError::EEXIST)),
+        (Op::Mkdir, "/file/", Err(LinuxError::EEXIST)),
+        (Op::Mkdir, longest_name.as_str(), Ok(())),
+        (Op::Mkdir, long_name.as_str(), Err(LinuxError::ENAMETOOLONG)),
+        (Op::Mknod, "/new/", Err(LinuxError::ENOENT)),
+        (Op::Mknod, "/file/", Err(LinuxError::EEXIST)),
+        (Op::Symlink, "/new/", Err(LinuxError::ENOENT)),
+        (Op::Symlink, "/dir/", Err(LinuxError::EEXIST)),
+        (Op::LinkTo, "/new/", Err(LinuxError::ENOENT)),
+        (Op::LinkFrom, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::Unlink, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::Unlink, "/dir", Err(LinuxError::EISDIR)),
+        (Op::Unlink, "/dir/", Err(LinuxError::EISDIR)),
+        (Op::Unlink, "/link/", Err(LinuxError::ENOTDIR)),
+        (Op::Unlink, "/missing/", Err(LinuxError::ENOENT)),
+        (Op::Rmdir, "/dir/", Ok(())),
+        (Op::Rmdir, "/file", Err(LinuxError::ENOTDIR)),
+        (Op::Rmdir, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::Rmdir, "/link/", Err(LinuxError::ENOTDIR)),
+        (Op::RenameFrom, "/file/", Err(LinuxError::ENOTDIR)),
+        (Op::RenameFrom, "/dir/", Ok(())),
+        (Op::RenameFrom, "/missing/", Err(LinuxError::ENOENT)),
+        (Op::RenameFile, "/new/", Err(LinuxError::ENOTDIR)),
+        (Op::RenameDir, "/new/", Ok(())),
+    ];
+    for (op, path, expected) in cases {
+        // The case goes along, to be told on failure.
+        let case = (*op, &path[..path.len().min(32)]);
+        assert_eq!(
+            (case, run(*op, path).map_err(LinuxError::from)),
+            (case, *expected)
+        );
+    }
+}
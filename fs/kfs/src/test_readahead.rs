@@ -0,0 +1,110 @@
+//! Unit tests for readahead.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use kio::{Seek, SeekFrom};
+use unittest::def_test;
+
+use crate::{
+    FileAdvice, FsContext, READAHEAD_MAX, READAHEAD_MIN,
+    test_direct_io::{DiskFs, open, setup},
+};
+
+const PAGE: usize = 4096;
+
+/// Sets up `/disk` holding `pages` pages, each filled with its number.
+fn setup_pages(pages: usize) -> (FsContext, Arc<DiskFs>, Vec<u8>) {
+    let (ctx, fs) = setup();
+    let data = (0..pages * PAGE)
+        .map(|i| (i / PAGE) as u8)
+        .collect::<Vec<_>>();
+    *fs.disk.data.lock() = data.clone();
+    (ctx, fs, data)
+}
+
+#[def_test]
+fn test_readahead_sequential() {
+    let (ctx, fs, data) = setup_pages(256);
+    let file = open(&ctx, "/disk", false).unwrap();
+
+    let mut buf = vec![0; PAGE];
+    for offset in (0..data.len()).step_by(PAGE) {
+        assert_eq!(file.read(&mut buf[..]).unwrap(), PAGE);
+        assert_eq!(buf, data[offset..offset + PAGE]);
+    }
+
+    // Every page was read ahead, in batches growing to the largest window.
+    let stats = file.readahead_stats();
+    assert_eq!(stats.window, READAHEAD_MAX);
+    assert_eq!(stats.sequential, 256);
+    assert_eq!(stats.random, 0);
+    assert_eq!(stats.pages, 256);
+    assert_eq!(fs.disk.reads.load(Ordering::SeqCst) as u64, stats.batches);
+    assert!(stats.batches < 256 / 8);
+}
+
+#[def_test]
+fn test_readahead_random() {
+    let (ctx, fs, data) = setup_pages(64);
+    let file = open(&ctx, "/disk", false).unwrap();
+
+    let mut buf = vec![0; PAGE];
+    for pn in [9, 3, 20, 14] {
+        assert_eq!(file.read_at(&mut buf[..], (pn * PAGE) as u64), Ok(PAGE));
+        assert_eq!(buf, data[pn * PAGE..(pn + 1) * PAGE]);
+    }
+    let stats = file.readahead_stats();
+    assert_eq!((stats.random, stats.batches, stats.window), (4, 0, 0));
+    assert_eq!(fs.disk.reads.load(Ordering::SeqCst), 4);
+
+    // Not even sequential reads are read ahead once advised against.
+    file.advise(FileAdvice::Random);
+    for pn in [30, 31, 32] {
+        assert_eq!(file.read_at(&mut buf[..], (pn * PAGE) as u64), Ok(PAGE));
+    }
+    assert_eq!(file.readahead_stats().batches, 0);
+    assert_eq!(fs.disk.reads.load(Ordering::SeqCst), 7);
+
+    // Sequential reads start with the largest window when advised to.
+    file.advise(FileAdvice::Sequential);
+    for pn in [40, 41] {
+        assert_eq!(file.read_at(&mut buf[..], (pn * PAGE) as u64), Ok(PAGE));
+    }
+    let stats = file.readahead_stats();
+    assert_eq!((stats.batches, stats.window), (1, READAHEAD_MAX));
+    assert_eq!(stats.pages, 64 - 41);
+}
+
+#[def_test]
+fn test_readahead_seek_resets() {
+    let (ctx, _fs, _data) = setup_pages(64);
+    let file = open(&ctx, "/disk", false).unwrap();
+
+    let mut buf = vec![0; PAGE];
+    file.read(&mut buf[..]).unwrap();
+    assert_eq!(file.readahead_stats().window, READAHEAD_MIN);
+
+    // Querying the position is not a seek.
+    assert_eq!((&file).seek(SeekFrom::Current(0)), Ok(PAGE as u64));
+    assert_eq!(file.readahead_stats().window, READAHEAD_MIN);
+
+    assert_eq!(
+        (&file).seek(SeekFrom::Start(40 * PAGE as u64)),
+        Ok(40 * PAGE as u64)
+    );
+    assert_eq!(file.readahead_stats().window, 0);
+    file.read(&mut buf[..]).unwrap();
+    assert_eq!(file.readahead_stats().random, 1);
+    assert_eq!(file.readahead_stats().window, 0);
+
+    // Reading on from there is sequential again.
+    file.read(&mut buf[..]).unwrap();
+    let stats = file.readahead_stats();
+    assert_eq!((stats.sequential, stats.batches), (2, 2));
+    assert_eq!(stats.window, READAHEAD_MIN);
+}
@@ -0,0 +1,62 @@
+//! Unit tests for the scrubber configuration and pacing.
+
+#![cfg(all(unittest, feature = "ext4", feature = "ext4-rsext4"))]
+
+extern crate alloc;
+
+use core::time::Duration;
+
+use unittest::{assert, assert_eq, def_test};
+
+use crate::scrub::{ScrubConfig, Throttle};
+
+#[def_test]
+fn test_scrub_config_from_cmdline() {
+    assert_eq!(ScrubConfig::from_cmdline("console=ttyS0"), None);
+    assert_eq!(ScrubConfig::from_cmdline("scrub="), None);
+    assert_eq!(ScrubConfig::from_cmdline("scrub=data"), None);
+
+    let config = ScrubConfig::from_cmdline("scrub=/").unwrap();
+    assert_eq!(config.mountpoint, "/");
+    assert!(!config.repair);
+    assert_eq!(config.state_path(), "/.scrub");
+
+    let config =
+        ScrubConfig::from_cmdline("quiet scrub=/data/,bw=4M,repair,state=/var/scrub,interval=60")
+            .unwrap();
+    assert_eq!(
+        config,
+        ScrubConfig {
+            mountpoint: "/data/",
+            bandwidth: 4 << 20,
+            repair: true,
+            state_path: Some("/var/scrub"),
+            interval: Duration::from_secs(60),
+        }
+    );
+    assert_eq!(config.state_path(), "/var/scrub");
+
+    // Bad options keep their defaults.
+    let default = ScrubConfig::from_cmdline("scrub=/data").unwrap();
+    let config = ScrubConfig::from_cmdline("scrub=/data,bw=0,state=rel,interval=soon").unwrap();
+    assert_eq!(config, default);
+    assert_eq!(config.state_path(), "/data/.scrub");
+}
+
+#[def_test]
+fn test_scrub_throttle() {
+    let mut throttle = Throttle::new(1 << 20);
+    assert_eq!(throttle.delay(1 << 20), Duration::from_secs(1));
+    assert_eq!(throttle.delay(4096), Duration::from_nanos(3_906_250));
+
+    // Foreground activity doubles the pause up to a cap.
+    assert_eq!(throttle.busy(), Duration::from_millis(100));
+    assert_eq!(throttle.busy(), Duration::from_millis(200));
+    let mut last = Duration::ZERO;
+    for _ in 0..16 {
+        last = throttle.busy();
+    }
+    assert_eq!(last, Duration::from_secs(8));
+    throttle.idle();
+    assert_eq!(throttle.busy(), Duration::from_millis(100));
+}
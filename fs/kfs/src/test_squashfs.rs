@@ -0,0 +1,964 @@
+//! Unit tests for squashfs.
+//!
+//! Images are made by a small writer following the layout of mksquashfs:
+//! tails of files packed into fragment blocks, runs of directory entries
+//! split at metadata block boundaries and indexed, and blocks stored
+//! uncompressed when compressing does not make them smaller. The compressors
+//! only encode runs of a byte, which is enough to get both kinds of blocks.
+
+#![cfg(all(unittest, feature = "squashfs"))]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::ops::Range;
+
+use fs_ng_vfs::{Filesystem, Mountpoint, NodePermission, NodeType, VfsError};
+use kcrypto::Sha512;
+use unittest::def_test;
+
+use crate::{FsContext, MemoryFs, MountFlags, OpenOptions, fs::SquashFilesystem, mount_blockdev};
+
+const BLOCK_SIZE: usize = 4096;
+const METADATA_SIZE: usize = 8192;
+const MTIME: u32 = 1_700_000_000;
+const UID: u32 = 0;
+const GID: u32 = 1000;
+
+enum Node {
+    Dir(Vec<(String, Node)>),
+    File(Vec<u8>),
+    Symlink(&'static str),
+    Device(NodeType, u32, u32),
+    Fifo,
+}
+
+impl Node {
+    fn node_type(&self) -> NodeType {
+        match self {
+            Node::Dir(_) => NodeType::Directory,
+            Node::File(_) => NodeType::RegularFile,
+            Node::Symlink(_) => NodeType::Symlink,
+            Node::Device(node_type, ..) => *node_type,
+            Node::Fifo => NodeType::Fifo,
+        }
+    }
+
+    fn mode(&self) -> u16 {
+        match self {
+            Node::Dir(_) => 0o755,
+            Node::Symlink(_) => 0o777,
+            Node::Device(..) => 0o600,
+            Node::File(_) | Node::Fifo => 0o644,
+        }
+    }
+}
+
+fn dir<const N: usize>(children: [(&str, Node); N]) -> Node {
+    Node::Dir(
+        children
+            .into_iter()
+            .map(|(name, node)| (name.to_string(), node))
+            .collect(),
+    )
+}
+
+/// Returns `len` bytes of noise.
+fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect()
+}
+
+/// Returns `len` bytes made of runs of 100 equal bytes.
+fn runs(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / 100) as u8).collect()
+}
+
+/// Returns the tree the tests pack into images.
+fn source_tree() -> Node {
+    let many = (0..600)
+        .map(|i| {
+            let node = Node::File(format!("{i}\n").into_bytes());
+            (format!("entry_{i:04}_of_a_large_dir"), node)
+        })
+        .collect();
+    let mut sparse = vec![0; BLOCK_SIZE];
+    sparse.extend(noise(BLOCK_SIZE + 10, 7));
+    dir([
+        ("hello.txt", Node::File(b"Hello, squashfs!\n".to_vec())),
+        ("empty", Node::File(Vec::new())),
+        ("noise.bin", Node::File(noise(3 * BLOCK_SIZE + 1000, 1))),
+        ("runs.bin", Node::File(runs(2 * BLOCK_SIZE))),
+        ("sparse.bin", Node::File(sparse)),
+        ("link", Node::Symlink("hello.txt")),
+        (
+            "dev",
+            dir([
+                ("null", Node::Device(NodeType::CharacterDevice, 1, 3)),
+                ("sda", Node::Device(NodeType::BlockDevice, 8, 0)),
+                ("wide", Node::Device(NodeType::CharacterDevice, 259, 300)),
+                ("fifo", Node::Fifo),
+            ]),
+        ),
+        ("many", Node::Dir(many)),
+        (
+            "a",
+            dir([(
+                "b",
+                dir([
+                    ("c", dir([("deep.txt", Node::File(b"deep\n".to_vec()))])),
+                    ("empty_dir", dir([])),
+                ]),
+            )]),
+        ),
+    ])
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Compressor {
+    Zlib,
+    Lz4,
+}
+
+/// What the writer did.
+#[derive(Default, Debug)]
+struct Stats {
+    compressed: usize,
+    stored: usize,
+    dir_index_entries: usize,
+}
+
+/// Splits `data` into literals, each followed by a repeat of the byte before
+/// of some length, or 0 for the last.
+///
+/// No repeat starts in the last 12 bytes or covers the last 5, as LZ4
+/// requires.
+fn tokens(data: &[u8]) -> Vec<(Range<usize>, usize)> {
+    let n = data.len();
+    let mut out = Vec::new();
+    let (mut lit, mut i) = (0, 0);
+    while i < n {
+        if i > 0 && i + 12 < n {
+            let mut len = 0;
+            while i + len < n - 5 && data[i + len] == data[i - 1] {
+                len += 1;
+            }
+            if len >= 4 {
+                out.push((lit..i, len));
+                i += len;
+                lit = i;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    out.push((lit..n, 0));
+    out
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, bits: u32) {
+        self.acc |= (value as u64) << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which goes most significant bit first.
+    fn code(&mut self, code: u32, bits: u32) {
+        self.put(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// Writes a symbol of the fixed literal/length code.
+    fn symbol(&mut self, sym: u32) {
+        match sym {
+            0..=143 => self.code(0x30 + sym, 8),
+            144..=255 => self.code(0x190 + sym - 144, 9),
+            256..=279 => self.code(sym - 256, 7),
+            _ => self.code(0xc0 + sym - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// Compresses `data` into a zlib stream of one fixed Huffman block.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    const BASE: [usize; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const EXTRA: [u32; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    let mut bits = BitWriter::default();
+    // BFINAL, then BTYPE 1
+    bits.put(1, 1);
+    bits.put(1, 2);
+    for (lits, mut len) in tokens(data) {
+        for &byte in &data[lits] {
+            bits.symbol(byte as u32);
+        }
+        while len > 0 {
+            let mut take = len.min(258);
+            if (1..3).contains(&(len - take)) {
+                take = len - 3;
+            }
+            let idx = BASE.iter().rposition(|&base| base <= take).unwrap();
+            bits.symbol(257 + idx as u32);
+            bits.put((take - BASE[idx]) as u32, EXTRA[idx]);
+            // distance 1
+            bits.code(0, 5);
+            len -= take;
+        }
+    }
+    bits.symbol(256);
+    let mut out = vec![0x78, 0x01];
+    out.extend(bits.finish());
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// Compresses `data` into an LZ4 block.
+fn lz4(data: &[u8]) -> Vec<u8> {
+    fn length(out: &mut Vec<u8>, mut n: usize) {
+        while n >= 255 {
+            out.push(255);
+            n -= 255;
+        }
+        out.push(n as u8);
+    }
+    let mut out = Vec::new();
+    for (lits, len) in tokens(data) {
+        let extra = len.saturating_sub(4);
+        out.push(((lits.len().min(15) as u8) << 4) | extra.min(15) as u8);
+        if lits.len() >= 15 {
+            length(&mut out, lits.len() - 15);
+        }
+        out.extend(&data[lits]);
+        if len > 0 {
+            out.extend(1u16.to_le_bytes());
+            if extra >= 15 {
+                length(&mut out, extra - 15);
+            }
+        }
+    }
+    out
+}
+
+impl Compressor {
+    fn id(self) -> u16 {
+        match self {
+            Compressor::Zlib => 1,
+            Compressor::Lz4 => 5,
+        }
+    }
+
+    /// Compresses `data`, unless that does not make it smaller.
+    fn pack(self, stats: &mut Stats, data: &[u8]) -> Option<Vec<u8>> {
+        let packed = match self {
+            Compressor::Zlib => zlib(data),
+            Compressor::Lz4 => lz4(data),
+        };
+        if packed.len() < data.len() {
+            stats.compressed += 1;
+            Some(packed)
+        } else {
+            stats.stored += 1;
+            None
+        }
+    }
+}
+
+/// Writes a table of metadata blocks.
+struct MetaWriter {
+    comp: Compressor,
+    out: Vec<u8>,
+    block: Vec<u8>,
+    /// The position of each block in the table.
+    starts: Vec<u32>,
+}
+
+impl MetaWriter {
+    fn new(comp: Compressor) -> Self {
+        Self {
+            comp,
+            out: Vec::new(),
+            block: Vec::new(),
+            starts: Vec::new(),
+        }
+    }
+
+    /// Returns the position of the next byte: the position of its block in
+    /// the table, and its offset in the block.
+    fn pos(&self) -> (u32, u16) {
+        (self.out.len() as u32, self.block.len() as u16)
+    }
+
+    fn write(&mut self, stats: &mut Stats, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = data.len().min(METADATA_SIZE - self.block.len());
+            self.block.extend(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == METADATA_SIZE {
+                self.flush(stats);
+            }
+        }
+    }
+
+    fn flush(&mut self, stats: &mut Stats) {
+        if self.block.is_empty() {
+            return;
+        }
+        self.starts.push(self.out.len() as u32);
+        let block = core::mem::take(&mut self.block);
+        match self.comp.pack(stats, &block) {
+            Some(packed) => {
+                self.out.extend((packed.len() as u16).to_le_bytes());
+                self.out.extend(packed);
+            }
+            None => {
+                self.out.extend((block.len() as u16 | 0x8000).to_le_bytes());
+                self.out.extend(block);
+            }
+        }
+    }
+
+    /// Writes the table at the end of `image`, and returns the positions of
+    /// its blocks.
+    fn finish(mut self, stats: &mut Stats, image: &mut Vec<u8>) -> Vec<u64> {
+        self.flush(stats);
+        let start = image.len() as u64;
+        image.extend(self.out);
+        self.starts.iter().map(|&pos| start + pos as u64).collect()
+    }
+}
+
+/// Writes a squashfs image.
+struct Builder {
+    comp: Compressor,
+    /// Whether tails of files go into fragment blocks.
+    use_fragments: bool,
+    image: Vec<u8>,
+    inodes: MetaWriter,
+    dirs: MetaWriter,
+    fragments: Vec<(u64, u32)>,
+    fragment: Vec<u8>,
+    last_ino: u32,
+    stats: Stats,
+}
+
+impl Builder {
+    fn new(comp: Compressor, use_fragments: bool) -> Self {
+        Self {
+            comp,
+            use_fragments,
+            // room for the superblock
+            image: vec![0; 96],
+            inodes: MetaWriter::new(comp),
+            dirs: MetaWriter::new(comp),
+            fragments: Vec::new(),
+            fragment: Vec::new(),
+            last_ino: 1,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Writes a data block, and returns its size field.
+    fn write_block(&mut self, data: &[u8]) -> u32 {
+        match self.comp.pack(&mut self.stats, data) {
+            Some(packed) => {
+                self.image.extend(&packed);
+                packed.len() as u32
+            }
+            None => {
+                self.image.extend(data);
+                data.len() as u32 | 1 << 24
+            }
+        }
+    }
+
+    fn flush_fragment(&mut self) {
+        if self.fragment.is_empty() {
+            return;
+        }
+        let pos = self.image.len() as u64;
+        let data = core::mem::take(&mut self.fragment);
+        let size = self.write_block(&data);
+        self.fragments.push((pos, size));
+    }
+
+    fn inode_header(&mut self, node: &Node, inode_type: u16, ino: u32) -> u64 {
+        let (block, offset) = self.inodes.pos();
+        let mut header = Vec::new();
+        header.extend(inode_type.to_le_bytes());
+        header.extend(node.mode().to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(MTIME.to_le_bytes());
+        header.extend(ino.to_le_bytes());
+        self.inodes.write(&mut self.stats, &header);
+        (block as u64) << 16 | offset as u64
+    }
+
+    fn inode_body(&mut self, fields: &[&[u8]]) {
+        for field in fields {
+            self.inodes.write(&mut self.stats, field);
+        }
+    }
+
+    /// Writes `node`, numbered `ino`, and returns its inode reference.
+    ///
+    /// The root has no parent, and refers to the inode past the last.
+    fn add(&mut self, node: &Node, ino: u32, parent: Option<u32>) -> u64 {
+        match node {
+            Node::Dir(children) => self.add_dir(node, children, ino, parent),
+            Node::File(data) => self.add_file(node, data, ino),
+            Node::Symlink(target) => {
+                let inode = self.inode_header(node, 3, ino);
+                self.inode_body(&[
+                    &1u32.to_le_bytes(),
+                    &(target.len() as u32).to_le_bytes(),
+                    target.as_bytes(),
+                ]);
+                inode
+            }
+            Node::Device(node_type, major, minor) => {
+                let inode_type = if *node_type == NodeType::BlockDevice {
+                    4
+                } else {
+                    5
+                };
+                let inode = self.inode_header(node, inode_type, ino);
+                let dev = major << 8 | (minor & 0xff) | (minor & !0xff) << 12;
+                self.inode_body(&[&1u32.to_le_bytes(), &dev.to_le_bytes()]);
+                inode
+            }
+            Node::Fifo => {
+                let inode = self.inode_header(node, 6, ino);
+                self.inode_body(&[&1u32.to_le_bytes()]);
+                inode
+            }
+        }
+    }
+
+    fn add_file(&mut self, node: &Node, data: &[u8], ino: u32) -> u64 {
+        let start = self.image.len() as u64;
+        let full = if self.use_fragments {
+            data.len() / BLOCK_SIZE * BLOCK_SIZE
+        } else {
+            data.len()
+        };
+        let mut sizes = Vec::new();
+        for block in data[..full].chunks(BLOCK_SIZE) {
+            // Holes are blocks of zeroes, which take no space.
+            if block.len() == BLOCK_SIZE && block.iter().all(|&b| b == 0) {
+                sizes.push(0);
+            } else {
+                sizes.push(self.write_block(block));
+            }
+        }
+        let tail = &data[full..];
+        let (fragment, offset) = if tail.is_empty() {
+            (u32::MAX, 0)
+        } else {
+            if self.fragment.len() + tail.len() > BLOCK_SIZE {
+                self.flush_fragment();
+            }
+            let offset = self.fragment.len() as u32;
+            self.fragment.extend(tail);
+            (self.fragments.len() as u32, offset)
+        };
+        let sizes = sizes
+            .iter()
+            .flat_map(|size: &u32| size.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let holes = sizes.chunks(4).filter(|size| *size == [0; 4]).count();
+        if holes > 0 {
+            let inode = self.inode_header(node, 9, ino);
+            self.inode_body(&[
+                &start.to_le_bytes(),
+                &(data.len() as u64).to_le_bytes(),
+                &((holes * BLOCK_SIZE) as u64).to_le_bytes(),
+                &1u32.to_le_bytes(),
+                &fragment.to_le_bytes(),
+                &offset.to_le_bytes(),
+                &u32::MAX.to_le_bytes(),
+                &sizes,
+            ]);
+            inode
+        } else {
+            let inode = self.inode_header(node, 2, ino);
+            self.inode_body(&[
+                &(start as u32).to_le_bytes(),
+                &fragment.to_le_bytes(),
+                &offset.to_le_bytes(),
+                &(data.len() as u32).to_le_bytes(),
+                &sizes,
+            ]);
+            inode
+        }
+    }
+
+    fn add_dir(
+        &mut self,
+        node: &Node,
+        children: &[(String, Node)],
+        ino: u32,
+        parent: Option<u32>,
+    ) -> u64 {
+        let mut children = children.iter().collect::<Vec<_>>();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        let inos = children
+            .iter()
+            .map(|_| {
+                self.last_ino += 1;
+                self.last_ino
+            })
+            .collect::<Vec<_>>();
+        let refs = children
+            .iter()
+            .zip(&inos)
+            .map(|((_, child), &child_ino)| self.add(child, child_ino, Some(ino)))
+            .collect::<Vec<_>>();
+
+        // A run ends at 256 entries, at an inode in another block, or where
+        // the listing enters another metadata block.
+        let mut runs: Vec<Vec<usize>> = Vec::new();
+        let mut offset = self.dirs.pos().1 as usize;
+        let (mut block, mut run_block) = (0, 0);
+        let advance = |offset: &mut usize, block: &mut usize, len: usize| {
+            *offset += len;
+            *block += *offset / METADATA_SIZE;
+            *offset %= METADATA_SIZE;
+        };
+        for (i, (name, _)) in children.iter().enumerate() {
+            let new_run = match runs.last() {
+                None => true,
+                Some(run) => {
+                    run.len() == 256
+                        || refs[run[0]] >> 16 != refs[i] >> 16
+                        || block != run_block
+                        || inos[i] - inos[run[0]] > i16::MAX as u32
+                }
+            };
+            if new_run {
+                runs.push(Vec::new());
+                run_block = block;
+                advance(&mut offset, &mut block, 12);
+            }
+            runs.last_mut().unwrap().push(i);
+            advance(&mut offset, &mut block, 8 + name.len());
+        }
+
+        let (start_block, start_offset) = self.dirs.pos();
+        let mut index = Vec::new();
+        let mut index_block = start_block;
+        let mut len = 0;
+        for run in runs {
+            let first = run[0];
+            let (block, _) = self.dirs.pos();
+            if block != index_block {
+                index.push((len, block, children[first].0.as_bytes()));
+                index_block = block;
+            }
+            let mut listing = Vec::new();
+            listing.extend((run.len() as u32 - 1).to_le_bytes());
+            listing.extend(((refs[first] >> 16) as u32).to_le_bytes());
+            listing.extend(inos[first].to_le_bytes());
+            for i in run {
+                let (name, child) = children[i];
+                let entry_type: u16 = match child.node_type() {
+                    NodeType::Directory => 1,
+                    NodeType::RegularFile => 2,
+                    NodeType::Symlink => 3,
+                    NodeType::BlockDevice => 4,
+                    NodeType::CharacterDevice => 5,
+                    _ => 6,
+                };
+                listing.extend((refs[i] as u16).to_le_bytes());
+                listing.extend(((inos[i] - inos[first]) as u16).to_le_bytes());
+                listing.extend(entry_type.to_le_bytes());
+                listing.extend((name.len() as u16 - 1).to_le_bytes());
+                listing.extend(name.as_bytes());
+            }
+            len += listing.len() as u32;
+            self.dirs.write(&mut self.stats, &listing);
+        }
+        self.stats.dir_index_entries += index.len();
+
+        let nlink = 2 + children
+            .iter()
+            .filter(|(_, child)| matches!(child, Node::Dir(_)))
+            .count() as u32;
+        let parent = parent.unwrap_or(self.last_ino + 1);
+        if index.is_empty() && len + 3 <= u16::MAX as u32 {
+            let inode = self.inode_header(node, 1, ino);
+            self.inode_body(&[
+                &start_block.to_le_bytes(),
+                &nlink.to_le_bytes(),
+                &(len as u16 + 3).to_le_bytes(),
+                &start_offset.to_le_bytes(),
+                &parent.to_le_bytes(),
+            ]);
+            return inode;
+        }
+        let inode = self.inode_header(node, 8, ino);
+        self.inode_body(&[
+            &nlink.to_le_bytes(),
+            &(len + 3).to_le_bytes(),
+            &start_block.to_le_bytes(),
+            &parent.to_le_bytes(),
+            &(index.len() as u16).to_le_bytes(),
+            &start_offset.to_le_bytes(),
+            &u32::MAX.to_le_bytes(),
+        ]);
+        for (offset, block, name) in index {
+            self.inode_body(&[
+                &offset.to_le_bytes(),
+                &block.to_le_bytes(),
+                &(name.len() as u32 - 1).to_le_bytes(),
+                name,
+            ]);
+        }
+        inode
+    }
+
+    /// Writes the image of `root`.
+    fn build(mut self, root: &Node) -> (Vec<u8>, Stats) {
+        let root_inode = self.add(root, 1, None);
+        self.flush_fragment();
+
+        let mut stats = self.stats;
+        let mut image = self.image;
+        let inode_table = image.len() as u64;
+        self.inodes.finish(&mut stats, &mut image);
+        let directory_table = image.len() as u64;
+        self.dirs.finish(&mut stats, &mut image);
+
+        let mut table = |entries: Vec<u8>, image: &mut Vec<u8>| {
+            let mut meta = MetaWriter::new(self.comp);
+            meta.write(&mut stats, &entries);
+            let blocks = meta.finish(&mut stats, image);
+            let start = image.len() as u64;
+            for pos in blocks {
+                image.extend(pos.to_le_bytes());
+            }
+            start
+        };
+        let fragments = self
+            .fragments
+            .iter()
+            .flat_map(|&(pos, size)| [pos.to_le_bytes(), (size as u64).to_le_bytes()])
+            .flatten()
+            .collect();
+        let fragment_table = table(fragments, &mut image);
+        let ids = [UID, GID].iter().flat_map(|id| id.to_le_bytes()).collect();
+        let id_table = table(ids, &mut image);
+
+        let mut sb = Vec::new();
+        sb.extend(0x7371_7368u32.to_le_bytes());
+        sb.extend(self.last_ino.to_le_bytes());
+        sb.extend(MTIME.to_le_bytes());
+        sb.extend((BLOCK_SIZE as u32).to_le_bytes());
+        sb.extend((self.fragments.len() as u32).to_le_bytes());
+        sb.extend(self.comp.id().to_le_bytes());
+        // block_log, flags, id count, version
+        for field in [12u16, 0, 2, 4, 0] {
+            sb.extend(field.to_le_bytes());
+        }
+        let bytes_used = image.len() as u64;
+        for field in [
+            root_inode,
+            bytes_used,
+            id_table,
+            u64::MAX,
+            inode_table,
+            directory_table,
+            fragment_table,
+            u64::MAX,
+        ] {
+            sb.extend(field.to_le_bytes());
+        }
+        image[..96].copy_from_slice(&sb);
+        image.resize(image.len().next_multiple_of(BLOCK_SIZE), 0);
+        (image, stats)
+    }
+}
+
+fn build(comp: Compressor, use_fragments: bool) -> (&'static [u8], Stats) {
+    let (image, stats) = Builder::new(comp, use_fragments).build(&source_tree());
+    (image.leak(), stats)
+}
+
+/// Hashes a node from its path, type, mode and contents.
+fn record(hasher: &mut Sha512, path: &str, node_type: NodeType, mode: u16, contents: &[u8]) {
+    hasher.update(path.as_bytes());
+    hasher.update(&[0, node_type as u8]);
+    hasher.update(&mode.to_le_bytes());
+    hasher.update(&(contents.len() as u64).to_le_bytes());
+    hasher.update(contents);
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.ends_with('/') {
+        format!("{path}{name}")
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+fn device_contents(major: u32, minor: u32) -> Vec<u8> {
+    let mut contents = major.to_le_bytes().to_vec();
+    contents.extend(minor.to_le_bytes());
+    contents
+}
+
+/// Hashes the tree at `node` in the order of the names.
+fn hash_source(hasher: &mut Sha512, path: &str, node: &Node) {
+    let contents = match node {
+        Node::Dir(children) => {
+            let mut names = children.iter().map(|(name, _)| name).collect::<Vec<_>>();
+            names.sort();
+            names.iter().fold(Vec::new(), |mut contents, name| {
+                contents.extend(name.as_bytes());
+                contents.push(0);
+                contents
+            })
+        }
+        Node::File(data) => data.clone(),
+        Node::Symlink(target) => target.as_bytes().to_vec(),
+        Node::Device(_, major, minor) => device_contents(*major, *minor),
+        Node::Fifo => Vec::new(),
+    };
+    record(hasher, path, node.node_type(), node.mode(), &contents);
+    if let Node::Dir(children) = node {
+        let mut children = children.iter().collect::<Vec<_>>();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, child) in children {
+            hash_source(hasher, &join(path, name), child);
+        }
+    }
+}
+
+/// Hashes the tree at `path` below `root` as [`hash_source`] does, reading
+/// it through the VFS.
+fn hash_vfs(hasher: &mut Sha512, ctx: &FsContext, root: &str, path: &str) {
+    let full = format!("{}{path}", root.trim_end_matches('/'));
+    let full = full.as_str();
+    let loc = ctx.resolve_no_follow(full).unwrap();
+    let meta = loc.metadata().unwrap();
+    assert_eq!((meta.uid, meta.gid), (UID, GID), "{path}");
+    assert_eq!(meta.mtime.as_secs(), MTIME as u64, "{path}");
+    let mut names = Vec::new();
+    let contents = match meta.node_type {
+        NodeType::Directory => {
+            for entry in ctx.read_dir(full).unwrap() {
+                let name = entry.unwrap().name;
+                if name != "." && name != ".." {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            names.iter().fold(Vec::new(), |mut contents, name| {
+                contents.extend(name.as_bytes());
+                contents.push(0);
+                contents
+            })
+        }
+        NodeType::RegularFile => {
+            let data = ctx.read(full).unwrap();
+            assert_eq!(meta.size, data.len() as u64, "{path}");
+            data
+        }
+        NodeType::Symlink => loc.read_link().unwrap().into_bytes(),
+        NodeType::CharacterDevice | NodeType::BlockDevice => {
+            device_contents(meta.rdev.major(), meta.rdev.minor())
+        }
+        _ => Vec::new(),
+    };
+    record(hasher, path, meta.node_type, meta.mode.bits(), &contents);
+    for name in names {
+        hash_vfs(hasher, ctx, root, &join(path, &name));
+    }
+}
+
+/// Checks the tree at `root` against the source tree.
+fn check_tree(ctx: &FsContext, root: &str) {
+    let mut expected = Sha512::new();
+    hash_source(&mut expected, "/", &source_tree());
+    let mut actual = Sha512::new();
+    hash_vfs(&mut actual, ctx, root, "/");
+    assert_eq!(actual.finalize(), expected.finalize());
+}
+
+fn root_context(fs: &Filesystem) -> FsContext {
+    FsContext::new(Mountpoint::new_root(fs).root_location())
+}
+
+fn memory_root(image: &'static [u8]) -> FsContext {
+    root_context(&SquashFilesystem::from_memory(image).unwrap())
+}
+
+#[def_test]
+fn test_squashfs_zlib_tree() {
+    let (image, stats) = build(Compressor::Zlib, true);
+    assert!(stats.compressed > 0 && stats.stored > 0, "{stats:?}");
+    check_tree(&memory_root(image), "/");
+}
+
+#[def_test]
+fn test_squashfs_lz4_image_file() {
+    let (image, stats) = build(Compressor::Lz4, false);
+    assert!(stats.compressed > 0 && stats.stored > 0, "{stats:?}");
+
+    let ctx = FsContext::new(Mountpoint::new_root(&MemoryFs::new()).root_location());
+    ctx.create_dir("/mnt", NodePermission::from_bits_truncate(0o755))
+        .unwrap();
+    ctx.write("/bundle.sqsh", image).unwrap();
+    assert_eq!(
+        mount_blockdev(&ctx, "/mnt", "/bundle.sqsh", "ext4", MountFlags::RDONLY),
+        Err(VfsError::OperationNotSupported)
+    );
+    mount_blockdev(&ctx, "/mnt", "/bundle.sqsh", "squashfs", MountFlags::RDONLY).unwrap();
+    check_tree(&ctx, "/mnt");
+
+    assert_eq!(
+        ctx.write("/mnt/hello.txt", b"changed"),
+        Err(VfsError::ReadOnlyFilesystem)
+    );
+    assert_eq!(
+        ctx.write("/mnt/new", b"new"),
+        Err(VfsError::ReadOnlyFilesystem)
+    );
+    assert_eq!(
+        ctx.remove_file("/mnt/hello.txt"),
+        Err(VfsError::ReadOnlyFilesystem)
+    );
+    assert_eq!(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&ctx, "/mnt/hello.txt")
+            .err(),
+        Some(VfsError::ReadOnlyFilesystem)
+    );
+    // Blocks are compressed, so there is no direct I/O.
+    let direct = OpenOptions::new()
+        .read(true)
+        .direct(true)
+        .open(&ctx, "/mnt/runs.bin");
+    assert!(direct.is_err());
+}
+
+#[def_test]
+fn test_squashfs_dir_index() {
+    let (image, stats) = build(Compressor::Zlib, true);
+    assert!(stats.dir_index_entries >= 2, "{stats:?}");
+    let ctx = memory_root(image);
+
+    for i in [0, 1, 299, 300, 301, 598, 599] {
+        let path = format!("/many/entry_{i:04}_of_a_large_dir");
+        assert_eq!(ctx.read(&path).unwrap(), format!("{i}\n").as_bytes());
+    }
+    for name in ["a", "entry_0300", "entry_0300_of_a_large_dir_", "zzz"] {
+        assert_eq!(
+            ctx.resolve(join("/many", name)).unwrap_err(),
+            VfsError::NotFound
+        );
+    }
+    let count = ctx.read_dir("/many").unwrap().count();
+    assert_eq!(count, 602);
+}
+
+#[def_test]
+fn test_squashfs_metadata() {
+    let (image, _) = build(Compressor::Zlib, true);
+    let fs = SquashFilesystem::from_memory(image).unwrap();
+    let ctx = root_context(&fs);
+
+    let null = ctx.metadata("/dev/null").unwrap();
+    assert_eq!(null.node_type, NodeType::CharacterDevice);
+    assert_eq!((null.rdev.major(), null.rdev.minor()), (1, 3));
+    let wide = ctx.metadata("/dev/wide").unwrap();
+    assert_eq!((wide.rdev.major(), wide.rdev.minor()), (259, 300));
+    assert_eq!(ctx.metadata("/dev/fifo").unwrap().node_type, NodeType::Fifo);
+    assert_eq!(ctx.read("/link").unwrap(), b"Hello, squashfs!\n");
+    // The root and `a/b` hold 3 and 2 directories.
+    assert_eq!(ctx.metadata("/").unwrap().nlink, 5);
+    assert_eq!(ctx.metadata("/a/b").unwrap().nlink, 4);
+    assert!(ctx.resolve("/a/b/c/../../b/c/deep.txt").is_ok());
+
+    let stat = fs.stat().unwrap();
+    assert_eq!(stat.fs_type, 0x7371_7368);
+    assert_eq!(stat.block_size, BLOCK_SIZE as u32);
+
+    // Reads across blocks, holes and the fragment.
+    let mut expected = vec![0; BLOCK_SIZE];
+    expected.extend(noise(BLOCK_SIZE + 10, 7));
+    let file = crate::File::open(&ctx, "/sparse.bin").unwrap();
+    let mut buf = vec![0; 300];
+    for offset in [0, BLOCK_SIZE - 100, 2 * BLOCK_SIZE - 100] {
+        let n = file.read_at(&mut buf[..], offset as u64).unwrap();
+        assert_eq!(buf[..n], expected[offset..offset + n]);
+    }
+}
+
+#[def_test]
+fn test_squashfs_bad_images() {
+    let (image, _) = build(Compressor::Zlib, true);
+    let mut bad = image.to_vec();
+    bad[0] = b'x';
+    assert_eq!(
+        SquashFilesystem::from_memory(bad.leak()).err(),
+        Some(VfsError::InvalidInput)
+    );
+    // xz
+    let mut bad = image.to_vec();
+    bad[20] = 4;
+    assert_eq!(
+        SquashFilesystem::from_memory(bad.leak()).err(),
+        Some(VfsError::Unsupported)
+    );
+    // Cut short of what the superblock says is used.
+    assert_eq!(
+        SquashFilesystem::from_memory(&image[..image.len() / 2]).err(),
+        Some(VfsError::InvalidData)
+    );
+}
@@ -0,0 +1,128 @@
+//! Unit tests for file streams.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+
+use kio::{SeekFrom, prelude::*};
+use unittest::def_test;
+
+use crate::{
+    StreamMode, copy_stream,
+    test_direct_io::{open, setup},
+};
+
+#[def_test]
+fn test_stream_position() {
+    let (ctx, fs) = setup();
+    let mut stream = ctx.open_stream("/disk", StreamMode::ReadWrite).unwrap();
+    stream.write_all(b"hello world").unwrap();
+    assert_eq!(stream.position(), Ok(11));
+
+    // Explicit offsets leave the position alone.
+    let mut buf = [0; 5];
+    assert_eq!(stream.read_at(&mut buf, 6), Ok(5));
+    assert_eq!(&buf, b"world");
+    assert_eq!(stream.write_at(b"H", 0), Ok(1));
+    assert_eq!(stream.position(), Ok(11));
+
+    assert_eq!(stream.seek(SeekFrom::Start(0)), Ok(0));
+    assert_eq!(stream.read(&mut buf), Ok(5));
+    assert_eq!(&buf, b"Hello");
+    assert_eq!(stream.seek(SeekFrom::End(-2)), Ok(9));
+    assert_eq!(stream.read(&mut buf), Ok(2));
+    assert_eq!(stream.read(&mut buf), Ok(0));
+
+    stream.sync().unwrap();
+    assert_eq!(fs.disk.data.lock()[..], b"Hello world"[..]);
+
+    // Write mode truncates.
+    let stream = ctx.open_stream("/disk", StreamMode::Write).unwrap();
+    assert_eq!(stream.location().len(), Ok(0));
+    assert!(stream.read_at(&mut buf, 0).is_err());
+}
+
+#[def_test]
+fn test_stream_copy() {
+    let (ctx, _fs) = setup();
+    let data = (0..40000).map(|i| i as u8).collect::<Vec<_>>();
+    ctx.write("/disk", &data).unwrap();
+
+    let mut src = ctx.open_stream("/disk", StreamMode::Read).unwrap();
+    let mut dst = ctx.open_stream("/nodirect", StreamMode::Write).unwrap();
+    assert_eq!(copy_stream(&mut src, &mut dst, 5), Ok(5));
+    assert_eq!(src.position(), Ok(5));
+
+    // Stops at the end of the source.
+    assert_eq!(copy_stream(&mut src, &mut dst, u64::MAX), Ok(39995));
+    assert_eq!(copy_stream(&mut src, &mut dst, u64::MAX), Ok(0));
+    dst.sync().unwrap();
+    drop(dst);
+
+    let mut copied = vec![0; data.len()];
+    let dst = ctx.open_stream("/nodirect", StreamMode::Read).unwrap();
+    assert_eq!(dst.read_at(&mut copied, 0), Ok(data.len()));
+    assert_eq!(copied, data);
+}
+
+#[def_test]
+fn test_stream_concurrent_append() {
+    let (ctx, fs) = setup();
+    let mut a = ctx.open_stream("/disk", StreamMode::Append).unwrap();
+    let mut b = ctx.open_stream("/disk", StreamMode::Append).unwrap();
+    let other = open(&ctx, "/disk", false).unwrap();
+
+    // Each append lands at the end of the file, whatever the position of
+    // the stream, even after a write through an unrelated handle.
+    for i in 0..64u8 {
+        a.write_all(&[b'a'; 3]).unwrap();
+        b.write_all(&[b'b'; 5]).unwrap();
+        if i % 8 == 0 {
+            let len = other.location().len().unwrap();
+            assert_eq!(other.write_at(&b"cc"[..], len), Ok(2));
+        }
+    }
+    assert_eq!(b.position(), b.location().len());
+    a.sync().unwrap();
+
+    let data = fs.disk.data.lock();
+    assert_eq!(data.len(), 64 * 8 + 8 * 2);
+    let mut chunks = &data[..];
+    for i in 0..64 {
+        assert_eq!(chunks[..8], *b"aaabbbbb");
+        chunks = &chunks[8..];
+        if i % 8 == 0 {
+            assert_eq!(chunks[..2], *b"cc");
+            chunks = &chunks[2..];
+        }
+    }
+}
+
+#[def_test]
+fn test_stream_read_racing_truncate() {
+    let (ctx, _fs) = setup();
+    let writer = ctx.open_stream("/disk", StreamMode::ReadWrite).unwrap();
+    let mut reader = ctx.open_stream("/disk", StreamMode::Read).unwrap();
+    assert_eq!(writer.write_at(b"0123456789", 0), Ok(10));
+
+    let mut buf = [0; 4];
+    assert_eq!(reader.read(&mut buf), Ok(4));
+    assert_eq!(&buf, b"0123");
+
+    // The reader is now past the end of the file.
+    writer.set_len(2).unwrap();
+    assert_eq!(reader.read(&mut buf), Ok(0));
+
+    // The file grows again without the truncated bytes coming back.
+    assert_eq!(writer.write_at(b"xy", 8), Ok(2));
+    let mut buf = [0xff; 8];
+    assert_eq!(reader.read(&mut buf), Ok(6));
+    assert_eq!(buf[..6], *b"\0\0\0\0xy");
+
+    writer.set_len(1).unwrap();
+    writer.set_len(6).unwrap();
+    assert_eq!(reader.read_at(&mut buf, 0), Ok(6));
+    assert_eq!(buf[..6], *b"0\0\0\0\0\0");
+}
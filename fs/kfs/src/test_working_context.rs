@@ -2,7 +2,8 @@
 
 #![cfg(unittest)]
 
-use unittest::{TestResult, assert, def_test};
+use fs_ng_vfs::{DeviceId, NodePermission, NodeType, VfsError};
+use unittest::{TestResult, assert, assert_eq, def_test};
 
 use crate::WorkingContext;
 
@@ -18,7 +19,7 @@ fn create_test_fs() -> fs_ng_vfs::Filesystem {
     // Create a 2MB ramdisk
     let ramdisk = RamDisk::new(2 * 1024 * 1024);
     let dev = Box::new(ramdisk);
-    let block_dev = BlockDevice::new(dev);
+    let block_dev = crate::Partition::disk("vda".into(), BlockDevice::new(dev));
 
     // Create FAT filesystem on the ramdisk
     crate::fs::fat::FatFilesystem::new(block_dev)
@@ -128,3 +129,76 @@ fn test_working_context_basic() -> TestResult {
 
     TestResult::Ok
 }
+
+/// Creates a context on a tmpfs holding `/jail/bin/sh` and `/bin/sh`.
+fn create_memory_context() -> crate::FsContext {
+    let mp = fs_ng_vfs::Mountpoint::new_root(&crate::MemoryFs::new());
+    let ctx = crate::FsContext::new(mp.root_location());
+    let mode = NodePermission::from_bits_truncate(0o755);
+    for dir in ["/bin", "/jail", "/jail/bin"] {
+        ctx.create_dir(dir, mode).unwrap();
+    }
+    ctx.write("/bin/sh", b"host").unwrap();
+    ctx.write("/jail/bin/sh", b"jail").unwrap();
+    ctx
+}
+
+fn mode_of(ctx: &crate::FsContext, path: &str) -> u16 {
+    ctx.metadata(path).unwrap().mode.bits()
+}
+
+#[def_test]
+fn test_working_context_umask() -> TestResult {
+    let mut ctx = create_memory_context();
+    assert_eq!(ctx.umask(), crate::DEFAULT_UMASK);
+    assert_eq!(ctx.set_umask(0o077), crate::DEFAULT_UMASK);
+    // Only the permission bits are kept.
+    assert_eq!(ctx.set_umask(0o7027), 0o077);
+    assert_eq!(ctx.umask(), 0o027);
+
+    let mode = NodePermission::from_bits_truncate(0o777);
+    ctx.create_dir("/dir", mode).unwrap();
+    assert_eq!(mode_of(&ctx, "/dir"), 0o750);
+    ctx.mknod("/fifo", NodeType::Fifo, mode, DeviceId::default())
+        .unwrap();
+    assert_eq!(mode_of(&ctx, "/fifo"), 0o750);
+    crate::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o666)
+        .open(&ctx, "/file")
+        .unwrap();
+    assert_eq!(mode_of(&ctx, "/file"), 0o640);
+
+    // Copies of the context, as made by fork, have their own umask.
+    let mut copy = ctx.clone();
+    copy.set_umask(0);
+    assert_eq!(ctx.umask(), 0o027);
+
+    TestResult::Ok
+}
+
+#[def_test]
+fn test_working_context_set_root() -> TestResult {
+    let mut ctx = create_memory_context();
+    let jail = ctx.resolve("/jail").unwrap();
+    assert_eq!(
+        ctx.set_root_dir(ctx.resolve("/bin/sh").unwrap()),
+        Err(VfsError::NotADirectory)
+    );
+
+    // The current directory is left alone.
+    ctx.set_current_dir(ctx.resolve("/jail/bin").unwrap())
+        .unwrap();
+    ctx.set_root_dir(jail.clone()).unwrap();
+    assert!(ctx.root_dir().entry().ptr_eq(jail.entry()));
+    assert_eq!(ctx.read("sh").unwrap(), b"jail");
+
+    // Absolute paths and ".." stop at the new root.
+    assert_eq!(ctx.read("/bin/sh").unwrap(), b"jail");
+    assert_eq!(ctx.read("../../../bin/sh").unwrap(), b"jail");
+    assert_eq!(ctx.read("/../../bin/../../bin/sh").unwrap(), b"jail");
+    assert!(ctx.resolve("/..").unwrap().entry().ptr_eq(jail.entry()));
+
+    TestResult::Ok
+}
@@ -4,18 +4,23 @@
 
 //! Working directory context
 //!
-//! Lightweight state management for root and current working directory.
+//! Lightweight state management for root and current working directory, and
+//! the file mode creation mask.
 
-use fs_ng_vfs::{Location, VfsResult};
+use fs_ng_vfs::{Location, NodePermission, VfsResult};
+
+/// Default file mode creation mask
+pub const DEFAULT_UMASK: u32 = 0o022;
 
 /// Working context - manages root and current working directory
 ///
-/// This is a lightweight structure that only holds directory state.
-/// It does not contain any path resolution or file operation logic.
+/// This is a lightweight structure that only holds directory state and the
+/// umask. It does not contain any path resolution or file operation logic.
 #[derive(Debug, Clone)]
 pub struct WorkingContext {
     root_dir: Location,
     current_dir: Location,
+    umask: u32,
 }
 
 impl WorkingContext {
@@ -27,6 +32,7 @@ impl WorkingContext {
         Self {
             root_dir: root.clone(),
             current_dir: root,
+            umask: DEFAULT_UMASK,
         }
     }
 
@@ -42,6 +48,38 @@ impl WorkingContext {
         &self.current_dir
     }
 
+    /// Changes the root directory
+    ///
+    /// Absolute paths are then resolved from `root`, and ".." does not go
+    /// above it. As with `chroot(2)`, the current directory is left alone.
+    ///
+    /// # Errors
+    /// Returns `NotADirectory` if the target is not a directory
+    pub fn set_root(&mut self, root: Location) -> VfsResult<()> {
+        root.check_is_dir()?;
+        self.root_dir = root;
+        Ok(())
+    }
+
+    /// Returns the file mode creation mask
+    #[inline]
+    pub fn umask(&self) -> u32 {
+        self.umask
+    }
+
+    /// Sets the file mode creation mask, returning the previous one
+    ///
+    /// Only the permission bits of `mask` are kept.
+    pub fn set_umask(&mut self, mask: u32) -> u32 {
+        core::mem::replace(&mut self.umask, mask & 0o777)
+    }
+
+    /// Clears the bits of `mode` set in the umask
+    #[inline]
+    pub fn apply_umask(&self, mode: NodePermission) -> NodePermission {
+        mode - NodePermission::from_bits_truncate(self.umask as u16)
+    }
+
     /// Changes the current working directory
     ///
     /// # Errors
@@ -64,6 +102,7 @@ impl WorkingContext {
         Ok(Self {
             root_dir: self.root_dir.clone(),
             current_dir: dir,
+            umask: self.umask,
         })
     }
 }
@@ -0,0 +1,171 @@
+//! # 元数据校验和模块
+//!
+//! 按 Linux ext4 的算法计算 `metadata_csum`（crc32c）和 `gdt_csum`（crc16）
+//! 校验和，供一致性检查使用。
+//!
+//! 与内核的 `ext4_chksum` 一致，crc32c 不做首尾取反：初值由调用者给出，
+//! 结果即为磁盘上存放的值。本模块只计算，不负责写回；rsext4 的写路径目前
+//! 不维护校验和。
+
+use crate::{disknode::*, endian::*, superblock::*};
+
+/// crc32c（Castagnoli）反射多项式
+const CRC32C_POLY: u32 = 0x82F6_3B78;
+/// crc16（ANSI）反射多项式
+const CRC16_POLY: u16 = 0xA001;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC16_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// 超级块中 `s_checksum` 的偏移
+pub const SUPERBLOCK_CSUM_OFFSET: usize = 0x3FC;
+/// 组描述符中 `bg_checksum` 的偏移
+pub const GROUP_DESC_CSUM_OFFSET: usize = 0x1E;
+/// inode 中 `l_i_checksum_lo` 的偏移
+pub const INODE_CSUM_LO_OFFSET: usize = 0x7C;
+/// inode 中 `i_checksum_hi` 的偏移
+pub const INODE_CSUM_HI_OFFSET: usize = 0x82;
+
+/// 以 `seed` 为初值计算 crc32c，不做首尾取反
+pub fn crc32c(seed: u32, data: &[u8]) -> u32 {
+    data.iter().fold(seed, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// 以 `seed` 为初值计算 crc16，不做首尾取反
+pub fn crc16(seed: u16, data: &[u8]) -> u16 {
+    data.iter().fold(seed, |crc, &byte| {
+        CRC16_TABLE[((crc ^ byte as u16) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// 文件系统是否启用了 `metadata_csum`
+pub fn has_metadata_csum(sb: &Ext4Superblock) -> bool {
+    sb.has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_METADATA_CSUM)
+}
+
+/// 元数据校验和的种子：`csum_seed` 特性下取 `s_checksum_seed`，否则由 UUID 算出
+pub fn csum_seed(sb: &Ext4Superblock) -> u32 {
+    if sb.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_CSUM_SEED) {
+        sb.s_checksum_seed
+    } else {
+        crc32c(!0, &sb.s_uuid)
+    }
+}
+
+/// 计算磁盘上 1024 字节超级块的校验和
+pub fn superblock_csum(raw: &[u8]) -> u32 {
+    crc32c(!0, &raw[..SUPERBLOCK_CSUM_OFFSET])
+}
+
+/// 计算组描述符的校验和，`raw` 为磁盘上 `s_desc_size` 字节的描述符
+///
+/// 启用 `metadata_csum` 时取 crc32c 的低 16 位，启用 `gdt_csum` 时为 crc16，
+/// 都没有启用时返回 `None`。
+pub fn group_desc_csum(sb: &Ext4Superblock, group: u32, raw: &[u8]) -> Option<u16> {
+    let group = group.to_le_bytes();
+    let tail = GROUP_DESC_CSUM_OFFSET + 2;
+    if has_metadata_csum(sb) {
+        let mut csum = crc32c(csum_seed(sb), &group);
+        csum = crc32c(csum, &raw[..GROUP_DESC_CSUM_OFFSET]);
+        csum = crc32c(csum, &[0, 0]);
+        csum = crc32c(csum, &raw[tail..]);
+        Some(csum as u16)
+    } else if sb.has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_GDT_CSUM) {
+        let mut csum = crc16(!0, &sb.s_uuid);
+        csum = crc16(csum, &group);
+        csum = crc16(csum, &raw[..GROUP_DESC_CSUM_OFFSET]);
+        if sb.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT) {
+            csum = crc16(csum, &raw[tail..]);
+        }
+        Some(csum)
+    } else {
+        None
+    }
+}
+
+/// inode 相关校验和（inode 本身、extent 块、目录块）的种子
+pub fn inode_csum_seed(seed: u32, ino: u32, generation: u32) -> u32 {
+    let csum = crc32c(seed, &ino.to_le_bytes());
+    crc32c(csum, &generation.to_le_bytes())
+}
+
+/// 计算磁盘上 inode 的校验和，返回 `(计算值, 存放值)`
+///
+/// `raw` 为完整的 `s_inode_size` 字节。`i_checksum_hi` 不在 `i_extra_isize`
+/// 范围内时只比较低 16 位，两个返回值都只保留低 16 位。
+pub fn inode_csum(seed: u32, ino: u32, raw: &[u8]) -> (u32, u32) {
+    let generation = read_u32_le(&raw[0x64..]);
+    let lo = INODE_CSUM_LO_OFFSET;
+    let hi = INODE_CSUM_HI_OFFSET;
+    let old_size = Ext4Inode::GOOD_OLD_INODE_SIZE as usize;
+    let has_hi = raw.len() > old_size && old_size + read_u16_le(&raw[0x80..]) as usize >= hi + 2;
+
+    let mut csum = crc32c(inode_csum_seed(seed, ino, generation), &raw[..lo]);
+    csum = crc32c(csum, &[0, 0]);
+    if has_hi {
+        csum = crc32c(csum, &raw[lo + 2..hi]);
+        csum = crc32c(csum, &[0, 0]);
+        csum = crc32c(csum, &raw[hi + 2..]);
+        let stored = read_u16_le(&raw[lo..]) as u32 | (read_u16_le(&raw[hi..]) as u32) << 16;
+        (csum, stored)
+    } else {
+        csum = crc32c(csum, &raw[lo + 2..]);
+        (csum & 0xFFFF, read_u16_le(&raw[lo..]) as u32)
+    }
+}
+
+/// 计算位图块的校验和，`len` 为位图的有效字节数
+pub fn bitmap_csum(seed: u32, bitmap: &[u8], len: usize) -> u32 {
+    crc32c(seed, &bitmap[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc_check_values() {
+        // 标准 crc32c 与 crc16-ARC 的校验值，crc32c 需补上首尾取反
+        assert_eq!(crc32c(!0, b"123456789") ^ !0, 0xE306_9283);
+        assert_eq!(crc16(0, b"123456789"), 0xBB3D);
+    }
+}
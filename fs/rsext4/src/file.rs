@@ -11,7 +11,7 @@ use log::{debug, error, info, warn};
 
 use crate::{
     blockdev::*, config::*, dir::*, disknode::*, entries::*, error::*, ext4::*, extents_tree::*,
-    loopfile::*,
+    loopfile::*, xattr::drop_inode_xattrs,
 };
 
 /// 重命名文件或目录
@@ -253,6 +253,198 @@ pub fn truncate_with_ino<B: BlockDevice>(
 
     Ok(())
 }
+/// Recomputes `i_blocks` of an extent-mapped inode from its mapped blocks.
+fn update_iblocks<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<()> {
+    let alloc_blocks = resolve_inode_block_allextend(fs, device, inode)?.len() as u64;
+    let iblocks_used = alloc_blocks.saturating_mul(BLOCK_SIZE as u64 / 512);
+    inode.i_blocks_lo = (iblocks_used & 0xffff_ffff) as u32;
+    inode.l_i_blocks_high = ((iblocks_used >> 32) & 0xffff) as u16;
+    Ok(())
+}
+
+/// Returns the logical blocks `first..=last` covering the `len > 0` bytes at
+/// `offset`.
+fn covered_lbns(offset: u64, len: u64) -> BlockDevResult<(u32, u32)> {
+    let block_bytes = BLOCK_SIZE as u64;
+    let end = offset.checked_add(len).ok_or(BlockDevError::InvalidInput)?;
+    let last = (end - 1) / block_bytes;
+    if last > u32::MAX as u64 {
+        return Err(BlockDevError::InvalidInput);
+    }
+    Ok(((offset / block_bytes) as u32, last as u32))
+}
+
+/// Allocates zeroed blocks for the holes among the blocks covering `len`
+/// bytes at `offset`, then grows the file to the end of the range unless
+/// `keep_size`. The content of the file does not change.
+///
+/// Only extent-mapped files are supported.
+pub fn fallocate_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> BlockDevResult<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let (first, last) = covered_lbns(offset, len)?;
+    let mut inode = fs.get_inode_by_num(device, inode_num)?;
+    if !inode.is_file() {
+        return Err(BlockDevError::Unsupported);
+    }
+    if fs.superblock.has_extents() && !inode.have_extend_header_and_use_extend() {
+        inode.i_flags |= Ext4Inode::EXT4_EXTENTS_FL;
+        inode.write_extend_header();
+    }
+    if !fs.superblock.has_extents() {
+        return Err(BlockDevError::Unsupported);
+    }
+
+    let blocks_map = resolve_inode_block_allextend(fs, device, &mut inode)?;
+    let mut new_blocks: Vec<(u32, u64)> = Vec::new();
+    for lbn in first..=last {
+        if blocks_map.contains_key(&lbn) {
+            continue;
+        }
+        let phys = fs.alloc_block(device)?;
+        fs.datablock_cache.modify_new(phys, |data| data.fill(0));
+        new_blocks.push((lbn, phys));
+    }
+
+    // Insert one extent per run of consecutive blocks.
+    let mut tree = ExtentTree::new(&mut inode);
+    let mut idx = 0;
+    while idx < new_blocks.len() {
+        let (start_lbn, start_phys) = new_blocks[idx];
+        let mut run_len = 1u32;
+        idx += 1;
+        while idx < new_blocks.len()
+            && run_len < 0x7FFF
+            && new_blocks[idx] == (start_lbn + run_len, start_phys + run_len as u64)
+        {
+            run_len += 1;
+            idx += 1;
+        }
+        tree.insert_extent(
+            fs,
+            Ext4Extent::new(start_lbn, start_phys, run_len as u16),
+            device,
+        )?;
+    }
+
+    let end = offset + len;
+    if !keep_size && end > inode.size() {
+        inode.i_size_lo = (end & 0xffff_ffff) as u32;
+        inode.i_size_high = (end >> 32) as u32;
+    }
+    update_iblocks(device, fs, &mut inode)?;
+    fs.modify_inode(device, inode_num, |td| {
+        *td = inode;
+    })
+}
+
+/// Deallocates the blocks lying entirely within the `len` bytes at `offset`
+/// and zeros the rest of the range, so that all of it reads as zeros. The
+/// size of the file does not change.
+///
+/// Only extent-mapped files are supported.
+pub fn punch_hole_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+) -> BlockDevResult<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let (first, last) = covered_lbns(offset, len)?;
+    let mut inode = fs.get_inode_by_num(device, inode_num)?;
+    if !inode.is_file() || !inode.have_extend_header_and_use_extend() {
+        return Err(BlockDevError::Unsupported);
+    }
+
+    let block_bytes = BLOCK_SIZE as u64;
+    let end = offset + len;
+    let blocks_map = resolve_inode_block_allextend(fs, device, &mut inode)?;
+    // The blocks at either end only partly in the range are zeroed in place.
+    for lbn in [first, last] {
+        let block_start = lbn as u64 * block_bytes;
+        let start = offset.max(block_start) - block_start;
+        let stop = end.min(block_start + block_bytes) - block_start;
+        if stop - start == block_bytes {
+            continue;
+        }
+        if let Some(&phys) = blocks_map.get(&lbn) {
+            fs.datablock_cache.modify(device, phys, |data| {
+                data[start as usize..stop as usize].fill(0);
+            })?;
+        }
+    }
+
+    let full = offset.div_ceil(block_bytes)..end / block_bytes;
+    if full.start < full.end {
+        let full = full.start as u32..full.end as u32;
+        // `remove_extend` frees the given number of mapped blocks from its
+        // start on, skipping holes.
+        loop {
+            let blocks_map = resolve_inode_block_allextend(fs, device, &mut inode)?;
+            let mut mapped = blocks_map.range(full.clone());
+            let Some((&start_lbn, _)) = mapped.next() else {
+                break;
+            };
+            let count = core::cmp::min(1 + mapped.count(), 0x7FFF);
+            let mut tree = ExtentTree::new(&mut inode);
+            tree.remove_extend(fs, Ext4Extent::new(start_lbn, 0, count as u16), device)?;
+        }
+    }
+
+    update_iblocks(device, fs, &mut inode)?;
+    fs.modify_inode(device, inode_num, |td| {
+        *td = inode;
+    })
+}
+
+/// Zeros the `len` bytes at `offset`, allocating the holes among their
+/// blocks, then grows the file to the end of the range unless `keep_size`.
+pub fn zero_range_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> BlockDevResult<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    covered_lbns(offset, len)?;
+    let size = fs.get_inode_by_num(device, inode_num)?.size();
+    let end = offset + len;
+    // Past the end of file with the size kept, the file already reads as
+    // zeros: only allocate there.
+    let zero_end = if keep_size { end.min(size) } else { end };
+    let zeros = alloc::vec![0u8; 16 * BLOCK_SIZE];
+    let mut pos = offset;
+    while pos < zero_end {
+        let n = core::cmp::min(zero_end - pos, zeros.len() as u64);
+        write_file_with_ino(device, fs, inode_num, pos, &zeros[..n as usize])?;
+        pos += n;
+    }
+    if end > pos.max(offset) {
+        let start = pos.max(offset);
+        fallocate_with_ino(device, fs, inode_num, start, end - start, keep_size)?;
+    }
+    Ok(())
+}
+
 pub fn create_symbol_link<B: BlockDevice>(
     device: &mut Jbd2Dev<B>,
     fs: &mut Ext4FileSystem,
@@ -266,6 +458,20 @@ pub fn create_symbol_link<B: BlockDevice>(
     if get_file_inode(fs, device, &src_norm)?.is_none() {
         return Err(BlockDevError::InvalidInput);
     }
+    symlink(device, fs, src_path, &dst_norm)
+}
+
+/// 创建符号链接 `link_path`，内容为 `target`
+///
+/// 与 [`create_symbol_link`] 不同，不检查 `target` 是否存在，目标原样保存，
+/// 可以是相对路径或悬空链接（与 POSIX `symlink` 语义一致）
+pub fn symlink<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    target: &str,
+    link_path: &str,
+) -> BlockDevResult<()> {
+    let dst_norm = split_paren_child_and_tranlatevalid(link_path);
     if get_file_inode(fs, device, &dst_norm)?.is_some() {
         return Err(BlockDevError::InvalidInput);
     }
@@ -295,7 +501,7 @@ pub fn create_symbol_link<B: BlockDevice>(
     // 为新链接分配 inode
     let new_ino = fs.alloc_inode(device)?;
 
-    let target_bytes = src_path.as_bytes();
+    let target_bytes = target.as_bytes();
     let target_len = target_bytes.len();
     let size_lo = (target_len as u64 & 0xffffffff) as u32;
     let size_hi = ((target_len as u64) >> 32) as u32;
@@ -375,7 +581,8 @@ pub fn create_symbol_link<B: BlockDevice>(
     Ok(())
 }
 
-fn read_symlink_target<B: BlockDevice>(
+/// 读取符号链接 inode 中保存的目标路径
+pub fn read_symlink_target<B: BlockDevice>(
     device: &mut Jbd2Dev<B>,
     fs: &mut Ext4FileSystem,
     inode: &mut Ext4Inode,
@@ -879,6 +1086,7 @@ pub fn unlink<B: BlockDevice>(
                 return;
             }
         }
+        drop_inode_xattrs(fs, block_dev, target_ino, &target_inode);
         if let Err(e) = fs.free_inode(block_dev, target_ino) {
             warn!("free_inode failed for inode {target_ino}: {e:?}");
             return;
@@ -1355,6 +1563,7 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
                 return;
             }
         }
+        drop_inode_xattrs(fs, block_dev, frame.ino_num, &cur_inode);
         if let Err(e) = fs.free_inode(block_dev, frame.ino_num) {
             warn!(
                 "free_inode failed for inode {}: {:?} path={}",
@@ -1429,6 +1638,7 @@ pub fn delete_file<B: BlockDevice>(
                 return;
             }
         }
+        drop_inode_xattrs(fs, block_dev, ino_num, &target_inode);
         // 释放inode
         if let Err(e) = fs.free_inode(block_dev, ino_num) {
             warn!("free_inode failed for inode {ino_num}: {e:?}");
@@ -1883,3 +2093,187 @@ pub fn write_file_with_ino<B: BlockDevice>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+    use super::*;
+
+    struct MemBlockDev {
+        data: Vec<u8>,
+        total_blocks: u64,
+    }
+
+    impl BlockDevice for MemBlockDev {
+        fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            let end = start + count as usize * BLOCK_SIZE;
+            self.data[start..end].copy_from_slice(&buffer[..end - start]);
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            let end = start + count as usize * BLOCK_SIZE;
+            buffer[..end - start].copy_from_slice(&self.data[start..end]);
+            Ok(())
+        }
+
+        fn open(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn total_blocks(&self) -> u64 {
+            self.total_blocks
+        }
+
+        fn block_size(&self) -> u32 {
+            BLOCK_SIZE as u32
+        }
+    }
+
+    fn setup_fs(total_blocks: u64) -> (Jbd2Dev<MemBlockDev>, Ext4FileSystem) {
+        let dev = MemBlockDev {
+            data: vec![0u8; total_blocks as usize * BLOCK_SIZE],
+            total_blocks,
+        };
+        let mut jbd = Jbd2Dev::initial_jbd2dev(0, dev, false);
+        mkfs(&mut jbd).unwrap();
+        let fs = mount(&mut jbd).unwrap();
+        (jbd, fs)
+    }
+
+    fn block_map(
+        dev: &mut Jbd2Dev<MemBlockDev>,
+        fs: &mut Ext4FileSystem,
+        ino: u32,
+    ) -> (Ext4Inode, BTreeMap<u32, u64>) {
+        let mut inode = fs.get_inode_by_num(dev, ino).unwrap();
+        let map = resolve_inode_block_allextend(fs, dev, &mut inode).unwrap();
+        (inode, map)
+    }
+
+    fn read_block(dev: &mut Jbd2Dev<MemBlockDev>, fs: &mut Ext4FileSystem, phys: u64) -> Vec<u8> {
+        fs.datablock_cache.get_or_load(dev, phys).unwrap().data[..BLOCK_SIZE].to_vec()
+    }
+
+    #[test]
+    fn test_punch_hole_frees_blocks() {
+        let (mut dev, mut fs) = setup_fs(8192);
+        let data = vec![0xabu8; 4 * BLOCK_SIZE];
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", Some(&data), None).unwrap();
+
+        // Punch the middle two blocks and half of the last one.
+        let half = BLOCK_SIZE as u64 / 2;
+        punch_hole_with_ino(
+            &mut dev,
+            &mut fs,
+            ino,
+            BLOCK_SIZE as u64,
+            2 * BLOCK_SIZE as u64 + half,
+        )
+        .unwrap();
+        let (inode, map) = block_map(&mut dev, &mut fs, ino);
+        assert_eq!(inode.size(), 4 * BLOCK_SIZE as u64);
+        assert_eq!(map.keys().copied().collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(inode.i_blocks_lo as usize, 2 * BLOCK_SIZE / 512);
+
+        let first = read_block(&mut dev, &mut fs, map[&0]);
+        assert!(first.iter().all(|&b| b == 0xab));
+        let last = read_block(&mut dev, &mut fs, map[&3]);
+        assert!(last[..half as usize].iter().all(|&b| b == 0));
+        assert!(last[half as usize..].iter().all(|&b| b == 0xab));
+    }
+
+    #[test]
+    fn test_fallocate_keep_size() {
+        let (mut dev, mut fs) = setup_fs(8192);
+        let data = vec![0xabu8; BLOCK_SIZE];
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", Some(&data), None).unwrap();
+        let block = BLOCK_SIZE as u64;
+
+        fallocate_with_ino(&mut dev, &mut fs, ino, 2 * block, 2 * block, true).unwrap();
+        let (inode, map) = block_map(&mut dev, &mut fs, ino);
+        assert_eq!(inode.size(), block);
+        assert_eq!(map.keys().copied().collect::<Vec<_>>(), [0, 2, 3]);
+        assert!(
+            read_block(&mut dev, &mut fs, map[&2])
+                .iter()
+                .all(|&b| b == 0)
+        );
+
+        // Without `keep_size` the holes are filled and the file grows.
+        fallocate_with_ino(&mut dev, &mut fs, ino, 0, 5 * block, false).unwrap();
+        let (inode, map) = block_map(&mut dev, &mut fs, ino);
+        assert_eq!(inode.size(), 5 * block);
+        assert_eq!(map.len(), 5);
+        assert_eq!(inode.i_blocks_lo as usize, 5 * BLOCK_SIZE / 512);
+        assert!(
+            read_block(&mut dev, &mut fs, map[&0])
+                .iter()
+                .all(|&b| b == 0xab)
+        );
+    }
+
+    #[test]
+    fn test_xattr_block_lifecycle() {
+        use crate::xattr::{XattrEntry, read_xattrs, write_xattrs};
+
+        let (mut dev, mut fs) = setup_fs(8192);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", Some(b"data"), None).unwrap();
+        let free = fs.superblock.free_blocks_count();
+
+        let first = [XattrEntry::new("user.a", b"1").unwrap()];
+        write_xattrs(&mut fs, &mut dev, ino, &first).unwrap();
+        assert_eq!(read_xattrs(&mut fs, &mut dev, ino).unwrap(), first);
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        let block = inode.file_acl();
+        assert_ne!(block, 0);
+        assert_eq!(inode.i_blocks_lo as usize, 2 * BLOCK_SIZE / 512);
+        assert_eq!(fs.superblock.free_blocks_count(), free - 1);
+
+        // A shared block is copied before it is changed.
+        fs.datablock_cache
+            .modify(&mut dev, block, |data| data[4] = 2)
+            .unwrap();
+        let second = [XattrEntry::new("security.capability", &[1, 2, 3]).unwrap()];
+        write_xattrs(&mut fs, &mut dev, ino, &second).unwrap();
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert_ne!(inode.file_acl(), block);
+        assert_eq!(inode.i_blocks_lo as usize, 2 * BLOCK_SIZE / 512);
+        assert_eq!(read_block(&mut dev, &mut fs, block)[4], 1);
+        assert_eq!(read_xattrs(&mut fs, &mut dev, ino).unwrap(), second);
+
+        // The last reference frees the block along with the inode.
+        unlink(&mut fs, &mut dev, "/f");
+        assert_eq!(fs.superblock.free_blocks_count(), free);
+    }
+
+    #[test]
+    fn test_zero_range() {
+        let (mut dev, mut fs) = setup_fs(8192);
+        let data = vec![0xabu8; 2 * BLOCK_SIZE];
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", Some(&data), None).unwrap();
+        let block = BLOCK_SIZE as u64;
+
+        zero_range_with_ino(&mut dev, &mut fs, ino, block / 2, 3 * block, true).unwrap();
+        let (inode, map) = block_map(&mut dev, &mut fs, ino);
+        assert_eq!(inode.size(), 2 * block);
+        assert_eq!(map.len(), 4);
+        let first = read_block(&mut dev, &mut fs, map[&0]);
+        assert!(first[..BLOCK_SIZE / 2].iter().all(|&b| b == 0xab));
+        assert!(first[BLOCK_SIZE / 2..].iter().all(|&b| b == 0));
+        assert!(
+            read_block(&mut dev, &mut fs, map[&1])
+                .iter()
+                .all(|&b| b == 0)
+        );
+    }
+}
@@ -10,6 +10,7 @@
 //! - 数据结构管理（superblock, inodetable_cache, datablock_cache）
 //! - 辅助工具和配置（tool, config, endian）
 //! - 日志系统（jbd2）
+//! - 在线一致性检查（scrub, checksum）
 
 #![no_std]
 
@@ -31,8 +32,8 @@ pub use dir::mkdir;
 pub use error::{Ext4Result, RSEXT4Error};
 pub use ext4::{Ext4FileSystem, find_file, mkfs, mount, umount};
 pub use file::{
-    create_symbol_link, delete_dir, delete_file, link, mkfile, mv, read_file, rename, truncate,
-    unlink, write_file,
+    create_symbol_link, delete_dir, delete_file, link, mkfile, mv, read_file, rename, symlink,
+    truncate, unlink, write_file,
 };
 
 pub mod api;
@@ -41,6 +42,7 @@ pub mod bitmap_cache;
 pub mod blockdev;
 pub mod blockgroup_description;
 pub mod bmalloc;
+pub mod checksum;
 pub mod config;
 pub mod datablock_cache;
 pub mod dir;
@@ -55,5 +57,7 @@ pub mod hashtree;
 pub mod inodetable_cache;
 pub mod jbd2;
 pub mod loopfile;
+pub mod scrub;
 pub mod superblock;
 pub mod tool;
+pub mod xattr;
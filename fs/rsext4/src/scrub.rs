@@ -0,0 +1,1038 @@
+//! # 在线一致性检查模块
+//!
+//! 在文件系统挂载期间增量地检查元数据，发现静默损坏：
+//!
+//! - 主超级块和块组描述符：静态字段是否与挂载时一致、位置是否越界、校验和；
+//! - 备份超级块和备份块组描述符：是否与主副本一致；
+//! - 孤儿链表（`s_last_orphan`）和链接数为 0 却不在链表上的 inode；
+//! - 每个已分配 inode 的校验和、extent 树结构和 extent 块校验和；
+//! - 目录块中目录项的布局和目录块校验和；
+//! - 块位图和 inode 位图的校验和。
+//!
+//! 校验和只在启用 `metadata_csum`（块组描述符还有 `gdt_csum`）时检查。
+//! rsext4 的写路径不维护校验和，它改写过的此类文件系统上的元数据会被报告为
+//! 校验和错误，离线 fsck 同样会报告这些错误。
+//!
+//! 检查按 [`ScrubCursor`] 推进，每次 [`scrub_step`] 读取约 `budget` 个块后
+//! 返回，游标可以编码保存，重启后继续。一个 inode 的 extent 树一次查完，
+//! 大目录的目录块可以分多次检查。
+//!
+//! 修复只做可以证明安全的操作：用主副本重写不一致的备份超级块和备份块组
+//! 描述符，以及清除孤儿链表头部不占用任何块的 inode。其余问题只报告，
+//! 留给离线 fsck。
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    bitmap_cache::*, blockdev::*, blockgroup_description::*, checksum::*, config::*, disknode::*,
+    endian::*, error::*, ext4::*, superblock::*, tool::*,
+};
+
+/// 编码后的游标大小
+pub const SCRUB_STATE_SIZE: usize = 40;
+const SCRUB_STATE_MAGIC: [u8; 4] = *b"SCRB";
+const SCRUB_STATE_VERSION: u32 = 1;
+
+/// extent 树节点头的魔数
+const EXT4_EXT_MAGIC: u16 = 0xF30A;
+/// extent 树的最大深度
+const EXT4_EXT_MAX_DEPTH: u16 = 5;
+/// 已初始化 extent 的最大长度，更长的表示未初始化 extent
+const EXT_INIT_MAX_LEN: u16 = 32768;
+/// extent 树节点头和每个表项的大小
+const EXT_ENTRY_SIZE: usize = 12;
+/// 目录块尾部校验和项的大小
+const DIR_TAIL_SIZE: usize = 12;
+/// 最多沿孤儿链表走的 inode 数，防止链表成环
+const MAX_ORPHAN_CHAIN: u32 = 1024;
+
+/// 备份超级块允许与主超级块不同的特性位，与 e2fsck 一致
+const INCOMPAT_IGNORE: u32 =
+    Ext4Superblock::EXT4_FEATURE_INCOMPAT_EXTENTS | Ext4Superblock::EXT4_FEATURE_INCOMPAT_RECOVER;
+const RO_COMPAT_IGNORE: u32 = Ext4Superblock::EXT4_FEATURE_RO_COMPAT_LARGE_FILE
+    | Ext4Superblock::EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | Ext4Superblock::EXT4_FEATURE_RO_COMPAT_ORPHAN_PRESENT;
+
+/// 超级块中创建后不再改变的字段 `(偏移, 长度)`，特性字段另行比较
+const SUPERBLOCK_STATIC_FIELDS: [(usize, usize); 12] = [
+    (0x00, 8),  // s_inodes_count, s_blocks_count_lo
+    (0x14, 20), // s_first_data_block .. s_inodes_per_group
+    (0x38, 2),  // s_magic
+    (0x4C, 4),  // s_rev_level
+    (0x54, 6),  // s_first_ino, s_inode_size
+    (0x5C, 4),  // s_feature_compat
+    (0x68, 16), // s_uuid
+    (0xFE, 2),  // s_desc_size
+    (0x150, 4), // s_blocks_count_hi
+    (0x60, 0),  // s_feature_incompat，见 INCOMPAT_IGNORE
+    (0x64, 0),  // s_feature_ro_compat，见 RO_COMPAT_IGNORE
+    (0x5A, 0),  // s_block_group_nr，各副本不同
+];
+
+/// 块组描述符中的位置字段 `(偏移, 长度)`：位图和 inode 表的块号
+const GROUP_DESC_LOCATION_FIELDS: [(usize, usize); 2] = [(0x00, 12), (0x20, 12)];
+
+/// 检查进度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrubCursor {
+    /// 已完成的检查轮数
+    pub pass: u32,
+    /// 下一个要检查的 inode 号，0 表示本轮还没有检查超级块和块组描述符
+    pub next_ino: u32,
+    /// `next_ino` 为目录时，下一个要检查的目录块的序号
+    pub dir_block: u32,
+}
+
+impl ScrubCursor {
+    /// 编码游标，`uuid` 用于识别文件系统
+    pub fn encode(&self, uuid: &[u8; 16]) -> [u8; SCRUB_STATE_SIZE] {
+        let mut data = [0u8; SCRUB_STATE_SIZE];
+        data[0..4].copy_from_slice(&SCRUB_STATE_MAGIC);
+        write_u32_le(SCRUB_STATE_VERSION, &mut data[4..8]);
+        data[8..24].copy_from_slice(uuid);
+        write_u32_le(self.pass, &mut data[24..28]);
+        write_u32_le(self.next_ino, &mut data[28..32]);
+        write_u32_le(self.dir_block, &mut data[32..36]);
+        let csum = crc32c(!0, &data[..36]);
+        write_u32_le(csum, &mut data[36..40]);
+        data
+    }
+
+    /// 解码游标，数据损坏或属于其他文件系统时返回 `None`
+    pub fn decode(data: &[u8], uuid: &[u8; 16]) -> Option<Self> {
+        if data.len() != SCRUB_STATE_SIZE
+            || data[0..4] != SCRUB_STATE_MAGIC
+            || read_u32_le(&data[4..8]) != SCRUB_STATE_VERSION
+            || data[8..24] != *uuid
+            || crc32c(!0, &data[..36]) != read_u32_le(&data[36..40])
+        {
+            return None;
+        }
+        Some(Self {
+            pass: read_u32_le(&data[24..28]),
+            next_ino: read_u32_le(&data[28..32]),
+            dir_block: read_u32_le(&data[32..36]),
+        })
+    }
+}
+
+/// 发现的问题类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    /// 主超级块的魔数或静态字段与挂载时不一致
+    Superblock,
+    /// 主超级块校验和错误
+    SuperblockChecksum,
+    /// 备份超级块与主超级块不一致
+    SuperblockBackup,
+    /// 块组描述符的位置字段越界或与挂载时不一致
+    GroupDesc,
+    /// 块组描述符校验和错误
+    GroupDescChecksum,
+    /// 备份块组描述符与主副本不一致
+    GroupDescBackup,
+    /// 块位图或 inode 位图校验和错误
+    BitmapChecksum,
+    /// inode 校验和错误
+    InodeChecksum,
+    /// extent 树结构错误：节点头损坏、块号越界或逻辑块重叠
+    ExtentTree,
+    /// extent 块校验和错误
+    ExtentChecksum,
+    /// 目录项布局错误
+    DirEntry,
+    /// 目录块校验和错误
+    DirChecksum,
+    /// 孤儿链表上的 inode
+    Orphan,
+    /// 链接数为 0 却不在孤儿链表上的 inode
+    Unattached,
+}
+
+impl FindingKind {
+    /// 问题类型的名字
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Superblock => "superblock",
+            Self::SuperblockChecksum => "superblock-checksum",
+            Self::SuperblockBackup => "superblock-backup",
+            Self::GroupDesc => "group-desc",
+            Self::GroupDescChecksum => "group-desc-checksum",
+            Self::GroupDescBackup => "group-desc-backup",
+            Self::BitmapChecksum => "bitmap-checksum",
+            Self::InodeChecksum => "inode-checksum",
+            Self::ExtentTree => "extent-tree",
+            Self::ExtentChecksum => "extent-checksum",
+            Self::DirEntry => "dir-entry",
+            Self::DirChecksum => "dir-checksum",
+            Self::Orphan => "orphan",
+            Self::Unattached => "unattached-inode",
+        }
+    }
+}
+
+/// 一个发现的问题
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    /// 问题类型
+    pub kind: FindingKind,
+    /// 相关的 inode 号，与具体 inode 无关时为 0
+    pub inode: u32,
+    /// 相关的块号，问题在 inode 内时为 0
+    pub block: u64,
+    /// 是否已经修复
+    pub repaired: bool,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} inode {} block {}",
+            self.kind.as_str(),
+            self.inode,
+            self.block
+        )?;
+        if self.repaired {
+            f.write_str(" repaired")?;
+        }
+        Ok(())
+    }
+}
+
+/// 一次 [`scrub_step`] 的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrubProgress {
+    /// 从磁盘读取的块数（命中缓存的不计）
+    pub blocks: u32,
+    /// 是否完成了一轮检查
+    pub pass_done: bool,
+}
+
+/// 从 `cursor` 处继续检查，读取约 `budget` 个块后返回
+///
+/// 新发现的问题追加到 `findings`。`repair` 为真时修复可以证明安全的问题。
+/// 调用者需保证期间没有其他操作访问文件系统。
+pub fn scrub_step<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    dev: &mut Jbd2Dev<B>,
+    cursor: &mut ScrubCursor,
+    budget: u32,
+    repair: bool,
+    findings: &mut Vec<Finding>,
+) -> BlockDevResult<ScrubProgress> {
+    // inode 表经块设备直接读取，先写回缓存中的 inode
+    fs.inodetable_cahce.flush_all(dev)?;
+    let inodes_count = fs.superblock.s_inodes_count;
+    let inodes_per_group = fs.superblock.s_inodes_per_group;
+    let mut scrub = Scrub {
+        fs,
+        dev,
+        findings,
+        repair,
+        blocks: 0,
+        quiet: false,
+        itable: None,
+    };
+    let mut pass_done = false;
+
+    while scrub.blocks < budget {
+        if cursor.next_ino == 0 {
+            scrub.check_metadata()?;
+            cursor.next_ino = 1;
+            continue;
+        }
+        if cursor.next_ino > inodes_count {
+            *cursor = ScrubCursor {
+                pass: cursor.pass + 1,
+                next_ino: 0,
+                dir_block: 0,
+            };
+            pass_done = true;
+            break;
+        }
+        let ino = cursor.next_ino;
+        let group = (ino - 1) / inodes_per_group;
+        let index = (ino - 1) % inodes_per_group;
+        if index == 0 && cursor.dir_block == 0 {
+            scrub.check_group(group)?;
+        }
+        match scrub.next_allocated(group, index)? {
+            None => {
+                cursor.next_ino = (group + 1) * inodes_per_group + 1;
+                cursor.dir_block = 0;
+                continue;
+            }
+            Some(next) if next != index => {
+                cursor.next_ino = group * inodes_per_group + next + 1;
+                cursor.dir_block = 0;
+                continue;
+            }
+            Some(_) => {}
+        }
+        match scrub.check_inode(ino, cursor.dir_block, budget)? {
+            Some(dir_block) => {
+                cursor.dir_block = dir_block;
+                break;
+            }
+            None => {
+                cursor.next_ino += 1;
+                cursor.dir_block = 0;
+            }
+        }
+    }
+
+    Ok(ScrubProgress {
+        blocks: scrub.blocks,
+        pass_done,
+    })
+}
+
+/// 一次检查的上下文
+struct Scrub<'a, B: BlockDevice> {
+    fs: &'a mut Ext4FileSystem,
+    dev: &'a mut Jbd2Dev<B>,
+    findings: &'a mut Vec<Finding>,
+    repair: bool,
+    /// 已读取的块数
+    blocks: u32,
+    /// 为真时不记录问题，用于续查大目录时重走已经查过的 extent 树
+    quiet: bool,
+    /// 最近读取的 inode 表块
+    itable: Option<(u64, Vec<u8>)>,
+}
+
+impl<B: BlockDevice> Scrub<'_, B> {
+    fn report(&mut self, kind: FindingKind, inode: u32, block: u64) {
+        if !self.quiet {
+            self.findings.push(Finding {
+                kind,
+                inode,
+                block,
+                repaired: false,
+            });
+        }
+    }
+
+    fn mark_repaired(&mut self) {
+        if let Some(finding) = self.findings.last_mut() {
+            finding.repaired = true;
+        }
+    }
+
+    fn blocks_count(&self) -> u64 {
+        self.fs.superblock.blocks_count()
+    }
+
+    fn csum_seed(&self) -> Option<u32> {
+        has_metadata_csum(&self.fs.superblock).then(|| csum_seed(&self.fs.superblock))
+    }
+
+    /// 经块设备读取一个块
+    fn read_raw(&mut self, block: u64) -> BlockDevResult<Vec<u8>> {
+        self.blocks += 1;
+        self.dev.read_block(block as u32)?;
+        Ok(self.dev.buffer()[..BLOCK_SIZE].to_vec())
+    }
+
+    /// 经数据块缓存读取一个块，目录块的修改先落在这里
+    fn read_cached(&mut self, block: u64) -> BlockDevResult<Vec<u8>> {
+        if self.fs.datablock_cache.get(block).is_none() {
+            self.blocks += 1;
+        }
+        let cached = self.fs.datablock_cache.get_or_load(self.dev, block)?;
+        Ok(cached.data[..BLOCK_SIZE].to_vec())
+    }
+
+    fn write_raw(&mut self, block: u64, data: &[u8]) -> BlockDevResult<()> {
+        self.dev.read_block(block as u32)?;
+        self.dev.buffer_mut()[..data.len()].copy_from_slice(data);
+        self.dev.write_block(block as u32, true)
+    }
+
+    fn gdt_blocks(&self) -> u64 {
+        let bytes = self.fs.group_count as u64 * self.fs.superblock.get_desc_size() as u64;
+        bytes.div_ceil(BLOCK_SIZE as u64)
+    }
+
+    fn has_backup(&self, group: u32) -> bool {
+        let sb = &self.fs.superblock;
+        if sb.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_META_BG) {
+            return false;
+        }
+        !sb.has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER)
+            || need_redundant_backup(group)
+    }
+
+    /// 检查超级块、块组描述符、它们的备份和孤儿链表
+    fn check_metadata(&mut self) -> BlockDevResult<()> {
+        let sb = self.fs.superblock;
+        let mut expected = [0u8; Ext4Superblock::SUPERBLOCK_SIZE];
+        sb.to_disk_bytes(&mut expected);
+
+        let block0 = self.read_raw(0)?;
+        let start = Ext4Superblock::SUPERBLOCK_OFFSET as usize;
+        let primary = block0[start..start + Ext4Superblock::SUPERBLOCK_SIZE].to_vec();
+        let mut primary_ok = true;
+        if !same_superblock(&primary, &expected) {
+            self.report(FindingKind::Superblock, 0, 0);
+            primary_ok = false;
+        }
+        if has_metadata_csum(&sb)
+            && superblock_csum(&primary) != read_u32_le(&primary[SUPERBLOCK_CSUM_OFFSET..])
+        {
+            self.report(FindingKind::SuperblockChecksum, 0, 0);
+            primary_ok = false;
+        }
+
+        let desc_size = sb.get_desc_size() as usize;
+        let mut gdt = Vec::new();
+        for i in 0..self.gdt_blocks() {
+            gdt.extend(self.read_raw(1 + i)?);
+        }
+        let mut gdt_ok = true;
+        for group in 0..self.fs.group_count {
+            let offset = group as usize * desc_size;
+            let raw = &gdt[offset..offset + desc_size];
+            let block = 1 + (offset / BLOCK_SIZE) as u64;
+            let mut expected = vec![0u8; desc_size];
+            self.fs.group_descs[group as usize].to_disk_bytes(&mut expected);
+            if !same_locations(raw, &expected) || !self.locations_in_range(group) {
+                self.report(FindingKind::GroupDesc, 0, block);
+                gdt_ok = false;
+            }
+            if let Some(csum) = group_desc_csum(&sb, group, raw)
+                && csum != read_u16_le(&raw[GROUP_DESC_CSUM_OFFSET..])
+            {
+                self.report(FindingKind::GroupDescChecksum, 0, block);
+                gdt_ok = false;
+            }
+        }
+
+        for group in 1..self.fs.group_count {
+            if !self.has_backup(group) {
+                continue;
+            }
+            let start = group as u64 * sb.blocks_per_group() as u64;
+            let backup = self.read_raw(start)?;
+            if !same_superblock(&backup[..Ext4Superblock::SUPERBLOCK_SIZE], &primary) {
+                self.report(FindingKind::SuperblockBackup, 0, start);
+                if self.repair && primary_ok {
+                    let mut copy = primary.clone();
+                    write_u16_le(group as u16, &mut copy[0x5A..0x5C]);
+                    if has_metadata_csum(&sb) {
+                        let csum = superblock_csum(&copy);
+                        write_u32_le(csum, &mut copy[SUPERBLOCK_CSUM_OFFSET..]);
+                    }
+                    self.write_raw(start, &copy)?;
+                    self.mark_repaired();
+                }
+            }
+            for i in 0..self.gdt_blocks() {
+                let block = start + 1 + i;
+                let backup = self.read_raw(block)?;
+                let primary = &gdt[i as usize * BLOCK_SIZE..(i as usize + 1) * BLOCK_SIZE];
+                let same = backup
+                    .chunks_exact(desc_size)
+                    .zip(primary.chunks_exact(desc_size))
+                    .all(|(backup, primary)| same_locations(backup, primary));
+                if !same {
+                    self.report(FindingKind::GroupDescBackup, 0, block);
+                    if self.repair && gdt_ok {
+                        self.write_raw(block, primary)?;
+                        self.mark_repaired();
+                    }
+                }
+            }
+        }
+
+        self.check_orphans()
+    }
+
+    /// 块组的位图和 inode 表是否都在文件系统内
+    fn locations_in_range(&self, group: u32) -> bool {
+        let desc = &self.fs.group_descs[group as usize];
+        let end = self.blocks_count();
+        let table_blocks = self.fs.superblock.inode_table_blocks() as u64;
+        (1..end).contains(&desc.block_bitmap())
+            && (1..end).contains(&desc.inode_bitmap())
+            && desc.inode_table() >= 1
+            && desc.inode_table() + table_blocks <= end
+    }
+
+    /// 沿孤儿链表检查，修复时清除链表头部不占用任何块的 inode
+    fn check_orphans(&mut self) -> BlockDevResult<()> {
+        let mut ino = self.fs.superblock.s_last_orphan;
+        let mut head = true;
+        for _ in 0..MAX_ORPHAN_CHAIN {
+            if ino == 0 {
+                break;
+            }
+            if ino > self.fs.superblock.s_inodes_count {
+                self.report(FindingKind::Orphan, ino, 0);
+                break;
+            }
+            let raw = self.read_inode(ino)?;
+            let inode = Ext4Inode::from_disk_bytes(&raw);
+            // 链表上的 inode 用 i_dtime 存放下一个 inode 号
+            let next = inode.i_dtime;
+            self.report(FindingKind::Orphan, ino, 0);
+            let clearable = inode.i_links_count == 0
+                && !inode.is_dir()
+                && inode.blocks_count() == 0
+                && inode.file_acl() == 0;
+            if head && self.repair && clearable {
+                self.clear_orphan(ino, next)?;
+                self.mark_repaired();
+            } else {
+                head = false;
+            }
+            ino = next;
+        }
+        Ok(())
+    }
+
+    fn clear_orphan(&mut self, ino: u32, next: u32) -> BlockDevResult<()> {
+        self.fs.superblock.s_last_orphan = next;
+        self.fs.free_inode(self.dev, ino)?;
+        self.fs.inodetable_cahce.flush_all(self.dev)?;
+        self.fs.bitmap_cache.flush_all(self.dev)?;
+        self.fs.sync_group_descriptors(self.dev)?;
+        self.fs.sync_superblock(self.dev)?;
+        self.itable = None;
+        Ok(())
+    }
+
+    /// 检查块组的位图校验和
+    fn check_group(&mut self, group: u32) -> BlockDevResult<()> {
+        let Some(seed) = self.csum_seed() else {
+            return Ok(());
+        };
+        let desc = self.fs.group_descs[group as usize];
+        let wide =
+            self.fs.superblock.get_desc_size() as usize >= Ext4GroupDesc::EXT4_DESC_SIZE_64BIT;
+        if !desc.is_block_bitmap_uninit() {
+            let len = self.fs.superblock.blocks_per_group() as usize / 8;
+            let bitmap = self.read_bitmap(CacheKey::new_block(group), desc.block_bitmap())?;
+            let (csum, stored) = split_csum(
+                bitmap_csum(seed, &bitmap, len),
+                desc.bg_block_bitmap_csum_lo,
+                desc.bg_block_bitmap_csum_hi,
+                wide,
+            );
+            if csum != stored {
+                self.report(FindingKind::BitmapChecksum, 0, desc.block_bitmap());
+            }
+        }
+        if !desc.is_inode_bitmap_uninit() {
+            let len = self.fs.superblock.inodes_per_group() as usize / 8;
+            let bitmap = self.read_bitmap(CacheKey::new_inode(group), desc.inode_bitmap())?;
+            let (csum, stored) = split_csum(
+                bitmap_csum(seed, &bitmap, len),
+                desc.bg_inode_bitmap_csum_lo,
+                desc.bg_inode_bitmap_csum_hi,
+                wide,
+            );
+            if csum != stored {
+                self.report(FindingKind::BitmapChecksum, 0, desc.inode_bitmap());
+            }
+        }
+        Ok(())
+    }
+
+    /// 经位图缓存读取位图，位图的修改先落在这里
+    fn read_bitmap(&mut self, key: CacheKey, block: u64) -> BlockDevResult<Vec<u8>> {
+        if self.fs.bitmap_cache.get(&key).is_none() {
+            self.blocks += 1;
+        }
+        let cached = self.fs.bitmap_cache.get_or_load(self.dev, key, block)?;
+        Ok(cached.data.clone())
+    }
+
+    /// 返回块组内从 `index` 起第一个已分配 inode 的下标
+    fn next_allocated(&mut self, group: u32, index: u32) -> BlockDevResult<Option<u32>> {
+        let desc = self.fs.group_descs[group as usize];
+        if desc.is_inode_bitmap_uninit() {
+            return Ok(None);
+        }
+        let bitmap = self.read_bitmap(CacheKey::new_inode(group), desc.inode_bitmap())?;
+        let count = self.fs.superblock.inodes_per_group();
+        Ok((index..count).find(|&i| bitmap[i as usize / 8] & (1 << (i % 8)) != 0))
+    }
+
+    /// 读取 inode 的原始字节
+    fn read_inode(&mut self, ino: u32) -> BlockDevResult<Vec<u8>> {
+        let sb = &self.fs.superblock;
+        let inode_size = sb.inode_size() as usize;
+        let group = (ino - 1) / sb.s_inodes_per_group;
+        let index = (ino - 1) % sb.s_inodes_per_group;
+        let table = self
+            .fs
+            .group_descs
+            .get(group as usize)
+            .ok_or(BlockDevError::Corrupted)?
+            .inode_table();
+        let offset = index as usize * inode_size;
+        let block = table + (offset / BLOCK_SIZE) as u64;
+        if self
+            .itable
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != block)
+        {
+            let data = self.read_raw(block)?;
+            self.itable = Some((block, data));
+        }
+        let (_, data) = self.itable.as_ref().unwrap();
+        let offset = offset % BLOCK_SIZE;
+        Ok(data[offset..offset + inode_size].to_vec())
+    }
+
+    /// 检查一个已分配的 inode，目录块没查完时返回下一个目录块的序号
+    fn check_inode(
+        &mut self,
+        ino: u32,
+        dir_block: u32,
+        budget: u32,
+    ) -> BlockDevResult<Option<u32>> {
+        let raw = self.read_inode(ino)?;
+        let inode = Ext4Inode::from_disk_bytes(&raw);
+        let seed = self.csum_seed();
+        self.quiet = dir_block > 0;
+
+        if let Some(seed) = seed
+            && raw.iter().any(|&b| b != 0)
+        {
+            let (csum, stored) = inode_csum(seed, ino, &raw);
+            if csum != stored {
+                self.report(FindingKind::InodeChecksum, ino, 0);
+            }
+        }
+        let first_ino = match self.fs.superblock.s_first_ino {
+            0 => RESERVED_INODES + 1,
+            n => n,
+        };
+        // 孤儿链表上的 inode 已在 check_orphans 中报告
+        if ino >= first_ino && inode.i_links_count == 0 && self.fs.superblock.s_last_orphan == 0 {
+            self.report(FindingKind::Unattached, ino, 0);
+        }
+        if inode.i_mode == 0
+            || inode.i_flags & Ext4Inode::EXT4_EXTENTS_FL == 0
+            || inode.i_flags & Ext4Inode::EXT4_INLINE_DATA_FL != 0
+        {
+            self.quiet = false;
+            return Ok(None);
+        }
+
+        let inode_seed = seed.map(|seed| inode_csum_seed(seed, ino, inode.i_generation));
+        let mut leaves = inode.is_dir().then(Vec::new);
+        let root = &raw[0x28..0x64];
+        self.check_extent_node(ino, inode_seed, root, 0, None, 0, &mut leaves)?;
+        self.quiet = false;
+        match leaves {
+            Some(leaves) => self.check_dir_blocks(ino, inode_seed, &leaves, dir_block, budget),
+            None => Ok(None),
+        }
+    }
+
+    /// 检查 extent 树节点 `node`，它位于块 `block`（树根在 inode 内时为 0）
+    ///
+    /// `depth` 为父节点要求的深度，`first` 为父索引项给出的起始逻辑块。
+    /// 目录的叶子 extent 收集到 `leaves` 中。
+    #[allow(clippy::too_many_arguments)]
+    fn check_extent_node(
+        &mut self,
+        ino: u32,
+        inode_seed: Option<u32>,
+        node: &[u8],
+        block: u64,
+        depth: Option<u16>,
+        first: u32,
+        leaves: &mut Option<Vec<(u32, u64, u32)>>,
+    ) -> BlockDevResult<()> {
+        let magic = read_u16_le(&node[0..2]);
+        let entries = read_u16_le(&node[2..4]) as usize;
+        let max = read_u16_le(&node[4..6]) as usize;
+        let node_depth = read_u16_le(&node[6..8]);
+        let tail = EXT_ENTRY_SIZE * (max + 1);
+        if magic != EXT4_EXT_MAGIC
+            || entries > max
+            || tail > node.len()
+            || node_depth > EXT4_EXT_MAX_DEPTH
+            || depth.is_some_and(|depth| depth != node_depth)
+        {
+            self.report(FindingKind::ExtentTree, ino, block);
+            return Ok(());
+        }
+        if block != 0
+            && let Some(seed) = inode_seed
+            && (tail + 4 > node.len() || crc32c(seed, &node[..tail]) != read_u32_le(&node[tail..]))
+        {
+            self.report(FindingKind::ExtentChecksum, ino, block);
+        }
+
+        let blocks_count = self.blocks_count();
+        let mut next_logical = first as u64;
+        for i in 0..entries {
+            let entry = &node[EXT_ENTRY_SIZE * (i + 1)..EXT_ENTRY_SIZE * (i + 2)];
+            let logical = read_u32_le(&entry[0..4]);
+            if (logical as u64) < next_logical {
+                self.report(FindingKind::ExtentTree, ino, block);
+                return Ok(());
+            }
+            if node_depth == 0 {
+                let raw_len = read_u16_le(&entry[4..6]);
+                let len = if raw_len > EXT_INIT_MAX_LEN {
+                    raw_len - EXT_INIT_MAX_LEN
+                } else {
+                    raw_len
+                } as u64;
+                let start =
+                    (read_u16_le(&entry[6..8]) as u64) << 32 | read_u32_le(&entry[8..12]) as u64;
+                if len == 0 || start == 0 || start + len > blocks_count {
+                    self.report(FindingKind::ExtentTree, ino, block);
+                    return Ok(());
+                }
+                if let Some(leaves) = leaves {
+                    leaves.push((logical, start, len as u32));
+                }
+                next_logical = logical as u64 + len;
+            } else {
+                let child =
+                    (read_u16_le(&entry[8..10]) as u64) << 32 | read_u32_le(&entry[4..8]) as u64;
+                if child == 0 || child >= blocks_count {
+                    self.report(FindingKind::ExtentTree, ino, block);
+                    return Ok(());
+                }
+                let data = self.read_raw(child)?;
+                self.check_extent_node(
+                    ino,
+                    inode_seed,
+                    &data,
+                    child,
+                    Some(node_depth - 1),
+                    logical,
+                    leaves,
+                )?;
+                next_logical = logical as u64 + 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// 从第 `start` 个目录块起检查目录块，预算用完时返回下一个目录块的序号
+    fn check_dir_blocks(
+        &mut self,
+        ino: u32,
+        inode_seed: Option<u32>,
+        leaves: &[(u32, u64, u32)],
+        start: u32,
+        budget: u32,
+    ) -> BlockDevResult<Option<u32>> {
+        let blocks = leaves.iter().flat_map(|&(logical, phys, len)| {
+            (0..len).map(move |i| (logical + i, phys + i as u64))
+        });
+        for (n, (logical, phys)) in blocks.enumerate().skip(start as usize) {
+            if n > start as usize && self.blocks >= budget {
+                return Ok(Some(n as u32));
+            }
+            let data = self.read_cached(phys)?;
+            self.check_dir_block(ino, inode_seed, logical, phys, &data);
+        }
+        Ok(None)
+    }
+
+    fn check_dir_block(
+        &mut self,
+        ino: u32,
+        inode_seed: Option<u32>,
+        logical: u32,
+        phys: u64,
+        data: &[u8],
+    ) {
+        let mut end = BLOCK_SIZE;
+        let tail = &data[BLOCK_SIZE - DIR_TAIL_SIZE..];
+        // 目录块尾部的校验和项；htree 内部节点没有这一项
+        if let Some(seed) = inode_seed
+            && read_u32_le(&tail[0..4]) == 0
+            && read_u16_le(&tail[4..6]) == DIR_TAIL_SIZE as u16
+            && tail[6] == 0
+            && tail[7] == 0xDE
+        {
+            end -= DIR_TAIL_SIZE;
+            if crc32c(seed, &data[..end]) != read_u32_le(&tail[8..12]) {
+                self.report(FindingKind::DirChecksum, ino, phys);
+            }
+        }
+
+        let inodes_count = self.fs.superblock.s_inodes_count;
+        let mut pos = 0;
+        let mut index = 0;
+        while pos < end {
+            if pos + 8 > end {
+                self.report(FindingKind::DirEntry, ino, phys);
+                return;
+            }
+            let entry_ino = read_u32_le(&data[pos..pos + 4]);
+            let rec_len = read_u16_le(&data[pos + 4..pos + 6]) as usize;
+            let name_len = data[pos + 6] as usize;
+            let bad_layout = rec_len < 12
+                || !rec_len.is_multiple_of(4)
+                || pos + rec_len > end
+                || name_len + 8 > rec_len
+                || entry_ino > inodes_count;
+            // 第一个目录块以 "." 和 ".." 开头
+            let bad_dots = logical == 0
+                && index < 2
+                && (name_len != index + 1
+                    || data[pos + 8..pos + 8 + name_len].iter().any(|&c| c != b'.')
+                    || (index == 0 && entry_ino != ino));
+            if bad_layout || bad_dots {
+                self.report(FindingKind::DirEntry, ino, phys);
+                return;
+            }
+            pos += rec_len;
+            index += 1;
+        }
+    }
+}
+
+/// 两份超级块的静态字段是否一致
+fn same_superblock(a: &[u8], b: &[u8]) -> bool {
+    let same_fields = SUPERBLOCK_STATIC_FIELDS
+        .iter()
+        .all(|&(offset, len)| a[offset..offset + len] == b[offset..offset + len]);
+    let incompat = |sb: &[u8]| read_u32_le(&sb[0x60..]) & !INCOMPAT_IGNORE;
+    let ro_compat = |sb: &[u8]| read_u32_le(&sb[0x64..]) & !RO_COMPAT_IGNORE;
+    same_fields && incompat(a) == incompat(b) && ro_compat(a) == ro_compat(b)
+}
+
+/// 两份块组描述符的位置字段是否一致
+fn same_locations(a: &[u8], b: &[u8]) -> bool {
+    GROUP_DESC_LOCATION_FIELDS
+        .iter()
+        .filter(|&&(offset, len)| offset + len <= a.len())
+        .all(|&(offset, len)| a[offset..offset + len] == b[offset..offset + len])
+}
+
+/// 按描述符宽度截取位图校验和，返回 `(计算值, 存放值)`
+fn split_csum(csum: u32, lo: u16, hi: u16, wide: bool) -> (u32, u32) {
+    if wide {
+        (csum, (hi as u32) << 16 | lo as u32)
+    } else {
+        (csum & 0xFFFF, lo as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{dir::*, file::*, loopfile::*};
+
+    struct MemBlockDev {
+        data: Vec<u8>,
+        total_blocks: u64,
+    }
+
+    impl BlockDevice for MemBlockDev {
+        fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            let end = start + count as usize * BLOCK_SIZE;
+            self.data[start..end].copy_from_slice(&buffer[..end - start]);
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+            let start = block_id as usize * BLOCK_SIZE;
+            let end = start + count as usize * BLOCK_SIZE;
+            buffer[..end - start].copy_from_slice(&self.data[start..end]);
+            Ok(())
+        }
+
+        fn open(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn total_blocks(&self) -> u64 {
+            self.total_blocks
+        }
+
+        fn block_size(&self) -> u32 {
+            BLOCK_SIZE as u32
+        }
+    }
+
+    /// 两个块组，块组 1 上有备份超级块
+    const TOTAL_BLOCKS: u64 = 40960;
+
+    fn setup_fs() -> (Jbd2Dev<MemBlockDev>, Ext4FileSystem) {
+        let dev = MemBlockDev {
+            data: vec![0u8; TOTAL_BLOCKS as usize * BLOCK_SIZE],
+            total_blocks: TOTAL_BLOCKS,
+        };
+        let mut jbd = Jbd2Dev::initial_jbd2dev(0, dev, false);
+        mkfs(&mut jbd).unwrap();
+        let mut fs = mount(&mut jbd).unwrap();
+        mkdir(&mut jbd, &mut fs, "/a/b").unwrap();
+        for i in 0..40 {
+            let path = alloc::format!("/a/file{i}");
+            mkfile(&mut jbd, &mut fs, &path, Some(&[i as u8; 5000]), None).unwrap();
+        }
+        (jbd, fs)
+    }
+
+    /// 从头检查一整轮，返回发现的问题
+    fn scrub_pass(
+        dev: &mut Jbd2Dev<MemBlockDev>,
+        fs: &mut Ext4FileSystem,
+        budget: u32,
+        repair: bool,
+    ) -> Vec<Finding> {
+        let mut cursor = ScrubCursor::default();
+        let mut findings = Vec::new();
+        loop {
+            let progress = scrub_step(fs, dev, &mut cursor, budget, repair, &mut findings).unwrap();
+            if progress.pass_done {
+                break;
+            }
+        }
+        assert_eq!(cursor.pass, 1);
+        findings
+    }
+
+    fn kinds(findings: &[Finding]) -> Vec<FindingKind> {
+        findings.iter().map(|finding| finding.kind).collect()
+    }
+
+    #[test]
+    fn test_clean_fs_has_no_findings() {
+        let (mut dev, mut fs) = setup_fs();
+        assert_eq!(scrub_pass(&mut dev, &mut fs, 1000, false), []);
+        // 小预算下多次续查，结果相同
+        assert_eq!(scrub_pass(&mut dev, &mut fs, 1, false), []);
+    }
+
+    #[test]
+    fn test_detects_bad_dir_entry() {
+        let (mut dev, mut fs) = setup_fs();
+        let (ino, mut inode) = get_file_inode(&mut fs, &mut dev, "/a").unwrap().unwrap();
+        let map = resolve_inode_block_allextend(&mut fs, &mut dev, &mut inode).unwrap();
+        let phys = map[&0];
+        fs.datablock_cache
+            .modify(&mut dev, phys, |data| write_u16_le(10, &mut data[16..18]))
+            .unwrap();
+
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, false);
+        assert_eq!(kinds(&findings), [FindingKind::DirEntry]);
+        assert_eq!(findings[0].inode, ino);
+        assert_eq!(findings[0].block, phys);
+    }
+
+    #[test]
+    fn test_detects_bad_extent_tree() {
+        let (mut dev, mut fs) = setup_fs();
+        let (ino, _) = get_file_inode(&mut fs, &mut dev, "/a/file3")
+            .unwrap()
+            .unwrap();
+        fs.modify_inode(&mut dev, ino, |inode| inode.i_block[0] = 0)
+            .unwrap();
+        let (other, _) = get_file_inode(&mut fs, &mut dev, "/a/file4")
+            .unwrap()
+            .unwrap();
+        // 第一个 extent 的起始块号越界
+        fs.modify_inode(&mut dev, other, |inode| inode.i_block[5] = u32::MAX)
+            .unwrap();
+
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, false);
+        assert_eq!(kinds(&findings), [FindingKind::ExtentTree; 2]);
+        assert_eq!(findings[0].inode, ino);
+        assert_eq!(findings[1].inode, other);
+    }
+
+    #[test]
+    fn test_detects_unattached_inode() {
+        let (mut dev, mut fs) = setup_fs();
+        let (ino, _) = get_file_inode(&mut fs, &mut dev, "/a/file7")
+            .unwrap()
+            .unwrap();
+        fs.modify_inode(&mut dev, ino, |inode| inode.i_links_count = 0)
+            .unwrap();
+
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, true);
+        assert_eq!(kinds(&findings), [FindingKind::Unattached]);
+        assert!(!findings[0].repaired);
+    }
+
+    #[test]
+    fn test_repairs_backup_superblock() {
+        let (mut dev, mut fs) = setup_fs();
+        let backup = fs.superblock.blocks_per_group() as u64;
+        dev.read_block(backup as u32).unwrap();
+        let mut data = dev.buffer()[..BLOCK_SIZE].to_vec();
+        write_u32_le(12345, &mut data[0x00..0x04]);
+        dev.buffer_mut()[..BLOCK_SIZE].copy_from_slice(&data);
+        dev.write_block(backup as u32, true).unwrap();
+
+        assert_eq!(
+            kinds(&scrub_pass(&mut dev, &mut fs, 1000, false)),
+            [FindingKind::SuperblockBackup]
+        );
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, true);
+        assert_eq!(kinds(&findings), [FindingKind::SuperblockBackup]);
+        assert!(findings[0].repaired);
+        assert_eq!(scrub_pass(&mut dev, &mut fs, 1000, false), []);
+
+        dev.read_block(backup as u32).unwrap();
+        assert_eq!(read_u16_le(&dev.buffer()[0x5A..]), 1);
+    }
+
+    #[test]
+    fn test_clears_orphan() {
+        let (mut dev, mut fs) = setup_fs();
+        let ino = fs.alloc_inode(&mut dev).unwrap();
+        fs.modify_inode(&mut dev, ino, |inode| {
+            inode.i_mode = Ext4Inode::S_IFREG | 0o644;
+            inode.i_links_count = 0;
+            inode.i_dtime = 0;
+        })
+        .unwrap();
+        fs.superblock.s_last_orphan = ino;
+        let free_inodes = fs.superblock.s_free_inodes_count;
+
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, false);
+        assert_eq!(kinds(&findings), [FindingKind::Orphan]);
+        assert!(!findings[0].repaired);
+
+        let findings = scrub_pass(&mut dev, &mut fs, 1000, true);
+        assert_eq!(kinds(&findings), [FindingKind::Orphan]);
+        assert!(findings[0].repaired);
+        assert_eq!(fs.superblock.s_last_orphan, 0);
+        assert_eq!(fs.superblock.s_free_inodes_count, free_inodes + 1);
+        assert_eq!(scrub_pass(&mut dev, &mut fs, 1000, false), []);
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let uuid = [7u8; 16];
+        let cursor = ScrubCursor {
+            pass: 3,
+            next_ino: 42,
+            dir_block: 5,
+        };
+        let data = cursor.encode(&uuid);
+        assert_eq!(ScrubCursor::decode(&data, &uuid), Some(cursor));
+        assert_eq!(ScrubCursor::decode(&data, &[8u8; 16]), None);
+        let mut bad = data;
+        bad[30] ^= 1;
+        assert_eq!(ScrubCursor::decode(&bad, &uuid), None);
+        assert_eq!(ScrubCursor::decode(&data[..20], &uuid), None);
+    }
+}
@@ -0,0 +1,406 @@
+//! # 扩展属性模块
+//!
+//! 读写 inode 的扩展属性块（`i_file_acl` 指向的外部块）。
+//!
+//! 块内布局与 Linux ext4 相同：32 字节的块头之后是按
+//! `(name_index, name_len, name)` 排序的属性项，以 4 字节的 0 结尾；
+//! 属性值从块尾向前存放，均按 4 字节对齐。块可以被多个 inode 共享
+//! （`h_refcount`），修改共享块时先复制一份。
+//!
+//! 不支持 inode 内（`i_extra_isize` 之后）的属性、`ea_inode` 特性和块校验和。
+
+use alloc::{string::String, vec, vec::Vec};
+
+use log::warn;
+
+use crate::{blockdev::*, config::*, disknode::*, endian::*, error::*, ext4::*};
+
+/// 扩展属性块的魔数
+pub const EXT4_XATTR_MAGIC: u32 = 0xEA02_0000;
+
+/// 块头大小
+const HEADER_SIZE: usize = 32;
+/// 属性项的固定部分大小（不含名字）
+const ENTRY_SIZE: usize = 16;
+
+const NAME_HASH_SHIFT: u32 = 5;
+const VALUE_HASH_SHIFT: u32 = 16;
+const BLOCK_HASH_SHIFT: u32 = 16;
+
+/// 名字索引与前缀的对应关系。完整匹配的 POSIX ACL 名字排在 `system.` 之前。
+const NAME_PREFIXES: [(u8, &str); 6] = [
+    (1, "user."),
+    (2, "system.posix_acl_access"),
+    (3, "system.posix_acl_default"),
+    (4, "trusted."),
+    (6, "security."),
+    (7, "system."),
+];
+
+/// 一个扩展属性
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XattrEntry {
+    /// 名字索引，对应名字前缀
+    pub name_index: u8,
+    /// 去掉前缀后的名字
+    pub name: Vec<u8>,
+    /// 属性值
+    pub value: Vec<u8>,
+}
+
+impl XattrEntry {
+    /// 由完整名字（含前缀）创建属性，前缀未知时返回 `None`
+    pub fn new(full_name: &str, value: &[u8]) -> Option<Self> {
+        let (name_index, name) = split_name(full_name)?;
+        Some(Self {
+            name_index,
+            name: name.to_vec(),
+            value: value.to_vec(),
+        })
+    }
+
+    /// 返回完整名字，索引未知或名字不是 UTF-8 时返回 `None`
+    pub fn full_name(&self) -> Option<String> {
+        let (_, prefix) = NAME_PREFIXES
+            .iter()
+            .find(|(index, _)| *index == self.name_index)?;
+        let name = core::str::from_utf8(&self.name).ok()?;
+        let mut full = String::from(*prefix);
+        full.push_str(name);
+        Some(full)
+    }
+
+    /// 判断是否是完整名字为 `full_name` 的属性
+    pub fn is(&self, full_name: &str) -> bool {
+        split_name(full_name)
+            .is_some_and(|(index, name)| index == self.name_index && name == self.name)
+    }
+
+    fn sort_key(&self) -> (u8, usize, &[u8]) {
+        (self.name_index, self.name.len(), &self.name)
+    }
+
+    /// 属性项在块中占用的大小
+    fn entry_len(&self) -> usize {
+        (ENTRY_SIZE + self.name.len()).next_multiple_of(4)
+    }
+
+    fn hash(&self) -> u32 {
+        let mut hash = 0u32;
+        for &c in &self.name {
+            hash = hash.rotate_left(NAME_HASH_SHIFT) ^ c as u32;
+        }
+        let mut padded = self.value.clone();
+        padded.resize(self.value.len().next_multiple_of(4), 0);
+        for word in padded.chunks_exact(4) {
+            hash = hash.rotate_left(VALUE_HASH_SHIFT) ^ read_u32_le(word);
+        }
+        hash
+    }
+}
+
+/// 把完整名字拆成名字索引和去掉前缀后的名字
+fn split_name(full_name: &str) -> Option<(u8, &[u8])> {
+    NAME_PREFIXES.iter().find_map(|&(index, prefix)| {
+        let name = full_name.strip_prefix(prefix)?;
+        // POSIX ACL 的名字必须完整匹配
+        (!matches!(index, 2 | 3) || name.is_empty()).then_some((index, name.as_bytes()))
+    })
+}
+
+/// 解析扩展属性块，返回引用计数和属性列表
+pub fn parse_xattr_block(data: &[u8]) -> BlockDevResult<(u32, Vec<XattrEntry>)> {
+    if data.len() < HEADER_SIZE + 4
+        || read_u32_le(&data[0..4]) != EXT4_XATTR_MAGIC
+        || read_u32_le(&data[8..12]) != 1
+    {
+        return Err(BlockDevError::Corrupted);
+    }
+    let refcount = read_u32_le(&data[4..8]);
+    let mut entries = Vec::new();
+    let mut pos = HEADER_SIZE;
+    loop {
+        if pos + 4 > data.len() {
+            return Err(BlockDevError::Corrupted);
+        }
+        if read_u32_le(&data[pos..pos + 4]) == 0 {
+            break;
+        }
+        if pos + ENTRY_SIZE > data.len() {
+            return Err(BlockDevError::Corrupted);
+        }
+        let name_len = data[pos] as usize;
+        let name_index = data[pos + 1];
+        let value_offs = read_u16_le(&data[pos + 2..pos + 4]) as usize;
+        let value_inum = read_u32_le(&data[pos + 4..pos + 8]);
+        let value_size = read_u32_le(&data[pos + 8..pos + 12]) as usize;
+        if value_inum != 0 {
+            // 值存放在单独 inode 中（ea_inode 特性）
+            return Err(BlockDevError::Unsupported);
+        }
+        let name_end = pos + ENTRY_SIZE + name_len;
+        let value_end = value_offs.checked_add(value_size);
+        if name_end > data.len() || value_end.is_none_or(|end| end > data.len()) {
+            return Err(BlockDevError::Corrupted);
+        }
+        entries.push(XattrEntry {
+            name_index,
+            name: data[pos + ENTRY_SIZE..name_end].to_vec(),
+            value: data[value_offs..value_offs + value_size].to_vec(),
+        });
+        pos = name_end.next_multiple_of(4);
+    }
+    Ok((refcount, entries))
+}
+
+/// 生成扩展属性块，放不下时返回 [`BlockDevError::NoSpace`]
+pub fn build_xattr_block(entries: &[XattrEntry], refcount: u32) -> BlockDevResult<Vec<u8>> {
+    let mut sorted: Vec<&XattrEntry> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+
+    let mut data = vec![0u8; BLOCK_SIZE];
+    let mut pos = HEADER_SIZE;
+    let mut value_start = BLOCK_SIZE;
+    let mut block_hash = 0u32;
+    for entry in sorted {
+        let value_len = entry.value.len().next_multiple_of(4);
+        // 最后还要留出 4 字节的结束标记
+        if entry.name.len() > u8::MAX as usize
+            || pos + entry.entry_len() + 4 + value_len > value_start
+        {
+            return Err(BlockDevError::NoSpace);
+        }
+        value_start -= value_len;
+        let value_offs = if entry.value.is_empty() {
+            0
+        } else {
+            value_start
+        };
+        data[value_start..value_start + entry.value.len()].copy_from_slice(&entry.value);
+
+        let hash = entry.hash();
+        data[pos] = entry.name.len() as u8;
+        data[pos + 1] = entry.name_index;
+        write_u16_le(value_offs as u16, &mut data[pos + 2..pos + 4]);
+        write_u32_le(0, &mut data[pos + 4..pos + 8]);
+        write_u32_le(entry.value.len() as u32, &mut data[pos + 8..pos + 12]);
+        write_u32_le(hash, &mut data[pos + 12..pos + 16]);
+        data[pos + ENTRY_SIZE..pos + ENTRY_SIZE + entry.name.len()].copy_from_slice(&entry.name);
+        pos += entry.entry_len();
+        block_hash = block_hash.rotate_left(BLOCK_HASH_SHIFT) ^ hash;
+    }
+
+    write_u32_le(EXT4_XATTR_MAGIC, &mut data[0..4]);
+    write_u32_le(refcount, &mut data[4..8]);
+    write_u32_le(1, &mut data[8..12]);
+    write_u32_le(block_hash, &mut data[12..16]);
+    Ok(data)
+}
+
+/// 修改 inode 的 i_blocks（以 512 字节扇区计）
+fn add_xattr_block_sectors(inode: &mut Ext4Inode, add: bool) {
+    let sectors = (BLOCK_SIZE / 512) as u64;
+    let blocks = if add {
+        inode.blocks_count().saturating_add(sectors)
+    } else {
+        inode.blocks_count().saturating_sub(sectors)
+    };
+    inode.i_blocks_lo = blocks as u32;
+    inode.l_i_blocks_high = (blocks >> 32) as u16;
+}
+
+fn set_file_acl(inode: &mut Ext4Inode, block: u64) {
+    inode.i_file_acl_lo = block as u32;
+    inode.l_i_file_acl_high = (block >> 32) as u16;
+}
+
+/// 读取 inode 的所有扩展属性
+pub fn read_xattrs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<XattrEntry>> {
+    let block = fs.get_inode_by_num(block_dev, inode_num)?.file_acl();
+    if block == 0 {
+        return Ok(Vec::new());
+    }
+    let cached = fs.datablock_cache.get_or_load(block_dev, block)?;
+    parse_xattr_block(&cached.data[..BLOCK_SIZE]).map(|(_, entries)| entries)
+}
+
+/// 用 `entries` 替换 inode 的所有扩展属性
+///
+/// 属性为空时释放属性块。原有的块只被本 inode 引用时原地改写，
+/// 否则分配新块并减少原块的引用计数。
+pub fn write_xattrs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    entries: &[XattrEntry],
+) -> BlockDevResult<()> {
+    let old_block = fs.get_inode_by_num(block_dev, inode_num)?.file_acl();
+    if entries.is_empty() {
+        if old_block != 0 {
+            release_xattr_block(fs, block_dev, old_block)?;
+            fs.modify_inode(block_dev, inode_num, |inode| {
+                set_file_acl(inode, 0);
+                add_xattr_block_sectors(inode, false);
+            })?;
+        }
+        return Ok(());
+    }
+
+    let data = build_xattr_block(entries, 1)?;
+    if old_block != 0 {
+        let cached = fs.datablock_cache.get_or_load(block_dev, old_block)?;
+        let (refcount, _) = parse_xattr_block(&cached.data[..BLOCK_SIZE])?;
+        if refcount <= 1 {
+            return fs
+                .datablock_cache
+                .modify(block_dev, old_block, |block| block.copy_from_slice(&data));
+        }
+    }
+
+    let new_block = fs.alloc_block(block_dev)?;
+    fs.datablock_cache
+        .modify_new(new_block, |block| block.copy_from_slice(&data));
+    if old_block != 0 {
+        release_xattr_block(fs, block_dev, old_block)?;
+    }
+    fs.modify_inode(block_dev, inode_num, |inode| {
+        set_file_acl(inode, new_block);
+        // 共享块也计入每个引用它的 inode
+        if old_block == 0 {
+            add_xattr_block_sectors(inode, true);
+        }
+    })
+}
+
+/// 放弃对扩展属性块 `block` 的一次引用，最后一次引用时释放该块
+///
+/// 只处理块本身，调用者负责清除 inode 中的块号。
+pub fn release_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    block: u64,
+) -> BlockDevResult<()> {
+    let cached = fs.datablock_cache.get_or_load(block_dev, block)?;
+    let (refcount, _) = parse_xattr_block(&cached.data[..BLOCK_SIZE])?;
+    if refcount <= 1 {
+        fs.free_block(block_dev, block)
+    } else {
+        fs.datablock_cache.modify(block_dev, block, |data| {
+            write_u32_le(refcount - 1, &mut data[4..8]);
+        })
+    }
+}
+
+/// 释放即将删除的 inode 引用的扩展属性块，失败时只记录警告
+pub(crate) fn drop_inode_xattrs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &Ext4Inode,
+) {
+    let block = inode.file_acl();
+    if block != 0
+        && let Err(e) = release_xattr_block(fs, block_dev, block)
+    {
+        warn!("release xattr block {block} of inode {inode_num} failed: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(full_name: &str, value: &[u8]) -> XattrEntry {
+        XattrEntry::new(full_name, value).unwrap()
+    }
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(split_name("user.foo"), Some((1, &b"foo"[..])));
+        assert_eq!(
+            split_name("security.capability"),
+            Some((6, &b"capability"[..]))
+        );
+        assert_eq!(split_name("system.posix_acl_access"), Some((2, &b""[..])));
+        assert_eq!(
+            split_name("system.posix_acl_accessx"),
+            Some((7, &b"posix_acl_accessx"[..]))
+        );
+        assert_eq!(split_name("other.foo"), None);
+        assert_eq!(
+            entry("system.posix_acl_default", b"")
+                .full_name()
+                .as_deref(),
+            Some("system.posix_acl_default")
+        );
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let entries = [
+            entry("user.zz", b"last"),
+            entry(
+                "security.capability",
+                &[1, 0, 0, 2, 0x20, 0, 0, 0, 0, 0, 0, 0],
+            ),
+            entry("user.a", b""),
+            entry("trusted.overlay.opaque", b"y"),
+        ];
+        let data = build_xattr_block(&entries, 1).unwrap();
+        let (refcount, parsed) = parse_xattr_block(&data).unwrap();
+        assert_eq!(refcount, 1);
+        // 按索引、名字长度和名字排序
+        let names: Vec<_> = parsed.iter().map(|e| e.full_name().unwrap()).collect();
+        assert_eq!(
+            names,
+            [
+                "user.a",
+                "user.zz",
+                "trusted.overlay.opaque",
+                "security.capability"
+            ]
+        );
+        for e in &entries {
+            assert!(parsed.contains(e));
+        }
+    }
+
+    #[test]
+    fn test_hash_matches_linux() {
+        // 与 debugfs `ea_set /f user.test hello` 生成的属性项一致
+        let data = build_xattr_block(&[entry("user.test", b"hello")], 1).unwrap();
+        assert_eq!(
+            &data[HEADER_SIZE..HEADER_SIZE + 24],
+            &[
+                0x04, 0x01, 0xf8, 0x0f, 0, 0, 0, 0, 0x05, 0, 0, 0, 0x17, 0xf6, 0x53, 0x65, b't',
+                b'e', b's', b't', 0, 0, 0, 0
+            ]
+        );
+        assert_eq!(&data[4088..4096], b"hello\0\0\0");
+        // 只有一项时块哈希等于该项的哈希
+        assert_eq!(read_u32_le(&data[12..16]), 0x6553_f617);
+    }
+
+    #[test]
+    fn test_block_full() {
+        let big = vec![0u8; BLOCK_SIZE / 2];
+        let entries = [entry("user.a", &big), entry("user.b", &big)];
+        assert_eq!(build_xattr_block(&entries, 1), Err(BlockDevError::NoSpace));
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(parse_xattr_block(&[0u8; BLOCK_SIZE]).is_err());
+        let mut data = build_xattr_block(&[entry("user.a", b"value")], 1).unwrap();
+        // 值越过块尾
+        write_u16_le(
+            (BLOCK_SIZE - 2) as u16,
+            &mut data[HEADER_SIZE + 2..HEADER_SIZE + 4],
+        );
+        assert_eq!(parse_xattr_block(&data), Err(BlockDevError::Corrupted));
+    }
+}
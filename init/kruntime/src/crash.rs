@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Allocation-free failure reporting.
+//!
+//! Shared by the panic handler and the oops path for recoverable kernel
+//! faults. Nothing here allocates or takes a `ksync`/`kspin` lock: output goes
+//! to the raw console writer behind a dedicated try-lock with a timeout, and
+//! is mirrored into a static crash record.
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use khal::{
+    context::TrapFrame,
+    time::ReferenceTime,
+    trap::{PageFaultFlags, RECOVERABLE_FAULT, register_trap_handler},
+};
+use memaddr::{PAGE_SIZE_4K, VirtAddr};
+
+/// Size of the crash record.
+const CRASH_RECORD_SIZE: usize = 16 * 1024;
+/// Spins before a crash console owner is considered dead.
+const CONSOLE_LOCK_SPINS: usize = 1 << 22;
+/// Most raw frames printed in a failure report.
+const MAX_REPORT_FRAMES: usize = 32;
+/// A trap taken this close to the bottom of the kernel stack is treated as a
+/// stack overflow.
+const STACK_RED_ZONE: usize = 1024;
+/// How far below the kernel stack an overflowed trap frame is looked for.
+const STACK_GUARD_SIZE: usize = 4 * PAGE_SIZE_4K;
+
+const NO_OWNER: usize = usize::MAX;
+
+/// Exit code of a task killed by an oops.
+const OOPS_EXIT_CODE: i32 = -14; // -EFAULT
+
+struct CrashRecord {
+    buf: UnsafeCell<[u8; CRASH_RECORD_SIZE]>,
+    /// Bytes reserved so far; may exceed the capacity.
+    len: AtomicUsize,
+}
+
+// SAFETY: writers reserve disjoint ranges with `fetch_add` before copying.
+unsafe impl Sync for CrashRecord {}
+
+static CRASH_RECORD: CrashRecord = CrashRecord {
+    buf: UnsafeCell::new([0; CRASH_RECORD_SIZE]),
+    len: AtomicUsize::new(0),
+};
+
+impl CrashRecord {
+    fn append(&self, bytes: &[u8]) {
+        let start = self.len.fetch_add(bytes.len(), Ordering::Relaxed);
+        let n = bytes.len().min(CRASH_RECORD_SIZE.saturating_sub(start));
+        if n > 0 {
+            unsafe {
+                let dst = self.buf.get().cast::<u8>().add(start);
+                core::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, n);
+            }
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        let len = self.len.load(Ordering::Acquire).min(CRASH_RECORD_SIZE);
+        unsafe { &(*self.buf.get())[..len] }
+    }
+}
+
+/// Returns the failure reports written since boot.
+///
+/// The record keeps the first [`CRASH_RECORD_SIZE`] bytes of every panic and
+/// oops report, so it stays readable after the console has scrolled away or
+/// when the console itself is broken.
+pub fn crash_record() -> &'static [u8] {
+    CRASH_RECORD.as_bytes()
+}
+
+static CONSOLE_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+/// Exclusive access to the raw console for a failure report.
+///
+/// Reports from different CPUs are serialized, but a CPU never waits forever:
+/// if the owner does not finish within [`CONSOLE_LOCK_SPINS`], it is assumed
+/// to have died mid-report and the console is shared. A CPU that faults while
+/// owning the console re-enters it immediately.
+pub(crate) struct CrashConsole {
+    owned: bool,
+}
+
+impl CrashConsole {
+    pub fn acquire() -> Self {
+        let cpu = khal::percpu::this_cpu_id();
+        for _ in 0..CONSOLE_LOCK_SPINS {
+            match CONSOLE_OWNER.compare_exchange(
+                NO_OWNER,
+                cpu,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Self { owned: true },
+                Err(owner) if owner == cpu => break,
+                Err(_) => core::hint::spin_loop(),
+            }
+        }
+        Self { owned: false }
+    }
+}
+
+impl Write for CrashConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        kplat::io::write_data_atomic(s.as_bytes());
+        CRASH_RECORD.append(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Drop for CrashConsole {
+    fn drop(&mut self) {
+        if self.owned {
+            CONSOLE_OWNER.store(NO_OWNER, Ordering::Release);
+        }
+    }
+}
+
+/// Writes when the failure happened, as the monotonic time of log lines and
+/// as the raw reference clock count.
+pub(crate) fn write_timestamp(out: &mut CrashConsole, now: ReferenceTime) {
+    let _ = writeln!(out, "time: {now} ref={}", now.ticks());
+}
+
+/// Writes the raw frames starting at `fp`, without symbolization.
+pub(crate) fn write_raw_backtrace(out: &mut CrashConsole, fp: usize) {
+    let _ = writeln!(out, "Raw backtrace (fp={fp:#x}):");
+    let mut index = 0;
+    backtrace::walk_stack(fp, &mut |frame| {
+        let _ = writeln!(out, "  #{index:<2} {frame}");
+        index += 1;
+        index < MAX_REPORT_FRAMES
+    });
+    if index == 0 {
+        let _ = writeln!(out, "  <no frames>");
+    }
+}
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub(crate) fn current_fp() -> usize {
+    use backtrace::arch::{ArchBacktrace, CurrentArch};
+    CurrentArch::current_fp()
+}
+
+/// Returns whether `tf` was saved at the very bottom of the current task's
+/// kernel stack or below it, i.e. the task ran out of stack.
+///
+/// Such a fault must not be handled on the same stack beyond a minimal dump.
+pub(crate) fn is_stack_overflow(tf: &TrapFrame) -> bool {
+    let Some(curr) = ktask::current_may_uninit() else {
+        return false;
+    };
+    let Some(stack) = curr.kernel_stack_range() else {
+        return false;
+    };
+    // Kernel traps save the frame on the interrupted stack.
+    let sp = tf as *const TrapFrame as usize;
+    let bottom = stack.start.as_usize();
+    sp < bottom + STACK_RED_ZONE && sp + STACK_GUARD_SIZE >= bottom
+}
+
+/// Resets the machine from a failure path.
+pub(crate) fn hard_reset() -> ! {
+    #[cfg(feature = "fault-inject")]
+    crate::fault_inject::echo_crash_record();
+    khal::power::reset()
+}
+
+/// Reports a bad kernel access inside a recovery region and arranges for the
+/// faulting task to exit once the trap returns.
+#[register_trap_handler(RECOVERABLE_FAULT)]
+fn oops(tf: &mut TrapFrame, vaddr: VirtAddr, flags: PageFaultFlags) -> bool {
+    let Some(curr) = ktask::current_may_uninit() else {
+        return false;
+    };
+    if !curr.in_recovery_region() || is_stack_overflow(tf) {
+        return false;
+    }
+
+    let now = khal::time::reference_now();
+    let mut out = CrashConsole::acquire();
+    let _ = writeln!(
+        out,
+        "---[ Oops: bad kernel access at {:#x} ({:?}) on CPU {}, task {} ]---",
+        vaddr.as_usize(),
+        flags,
+        khal::percpu::this_cpu_id(),
+        curr.id().as_u64(),
+    );
+    write_timestamp(&mut out, now);
+    let _ = writeln!(out, "{tf:#x?}");
+    write_raw_backtrace(&mut out, tf.fp());
+    let _ = writeln!(out, "---[ killing task {} ]---", curr.id().as_u64());
+    drop(out);
+
+    // Resume in `oops_exit` instead of the faulting instruction. The stack
+    // stays as it was, which is fine since `oops_exit` never returns.
+    tf.set_ip(oops_exit as *const () as usize);
+    #[cfg(target_arch = "x86_64")]
+    {
+        // Function entry expects `rsp + 8` to be 16-byte aligned.
+        tf.rsp = (tf.rsp & !0xf) - 8;
+    }
+    true
+}
+
+extern "C" fn oops_exit() -> ! {
+    ktask::exit(OOPS_EXIT_CODE)
+}
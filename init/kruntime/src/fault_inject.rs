@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Fault injection for testing the panic path.
+//!
+//! Each injector leaves the kernel in a broken state and panics; the test
+//! harness then checks that a readable report still reaches the console and
+//! the crash record.
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set to make the panic handler fault while reporting.
+static FAULT_IN_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Overwrites the heap past a small allocation, then panics.
+///
+/// The allocator metadata following the block is clobbered, so any
+/// allocation made by the panic path is likely to fault or hang.
+pub fn corrupt_heap_and_panic() -> ! {
+    let block = Box::leak(Box::new([0u8; 64]));
+    // SAFETY: deliberately not; this is the fault being injected.
+    unsafe { core::ptr::write_bytes(block.as_mut_ptr(), 0xa5, 4096) };
+    panic!("fault injection: heap corrupted");
+}
+
+/// Panics, and makes the panic handler fault while it reports.
+pub fn fault_in_panic() -> ! {
+    FAULT_IN_PANIC.store(true, Ordering::Relaxed);
+    panic!("fault injection: fault inside the panic handler");
+}
+
+/// Called by the panic handler midway through its report.
+pub(crate) fn fault_in_panic_handler() {
+    if FAULT_IN_PANIC.swap(false, Ordering::Relaxed) {
+        // SAFETY: deliberately not; this is the fault being injected.
+        unsafe { core::ptr::null_mut::<usize>().write_volatile(0) };
+    }
+}
+
+/// Prints the crash record, standing in for reading it back after reset.
+pub(crate) fn echo_crash_record() {
+    let record = crate::crash::crash_record();
+    kplat::io::write_data_atomic(b"---[ crash record ]---\n");
+    kplat::io::write_data_atomic(record);
+    kplat::io::write_data_atomic(b"---[ end of crash record ]---\n");
+}
@@ -3,11 +3,101 @@
 // See LICENSES for license details.
 
 //! Panic handler for the runtime.
-use core::panic::PanicInfo;
+//!
+//! The handler never allocates and never takes a lock it could wait on
+//! forever, since the panic may come from a corrupted heap or from a CPU that
+//! holds the logger's locks. Panics nest: a fault while reporting panics
+//! again, so a per-CPU depth counter selects how much is still safe to do.
+use core::{fmt::Write, panic::PanicInfo};
+
+use crate::crash::{self, CrashConsole};
+
+/// Nesting depth of panics on this CPU.
+#[percpu::def_percpu]
+static PANIC_DEPTH: usize = 0;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    kprintln!("{}", info);
-    kprintln!("{}", backtrace::Backtrace::capture());
+    // Safety: the counter is only touched by this CPU, and nothing returns
+    // from here to observe it again.
+    let depth = unsafe {
+        let depth = PANIC_DEPTH.read_current_raw();
+        PANIC_DEPTH.write_current_raw(depth + 1);
+        depth
+    };
+    khal::asm::disable_local();
+
+    match depth {
+        0 => full_report(info),
+        1 => minimal_report(info),
+        // Even the minimal report faulted: do nothing but reset.
+        _ => crash::hard_reset(),
+    }
+}
+
+/// Reports the first panic on this CPU in full, then stops the system.
+fn full_report(info: &PanicInfo) -> ! {
+    let tf = khal::context::active_exception_context();
+    if tf.is_some_and(crash::is_stack_overflow) {
+        // No stack left to format a full report on.
+        minimal_report(info);
+    }
+
+    let now = khal::time::reference_now();
+    let mut out = CrashConsole::acquire();
+    // Records still queued were logged before the panic.
+    klogger::flush_log_queue_for_panic(&mut out);
+    let _ = writeln!(
+        out,
+        "---[ KERNEL PANIC on CPU {} ]---",
+        khal::percpu::this_cpu_id()
+    );
+    crash::write_timestamp(&mut out, now);
+    if let Some(curr) = ktask::current_may_uninit() {
+        let _ = writeln!(out, "task: {}", curr.id().as_u64());
+    }
+    #[cfg(feature = "fault-inject")]
+    crate::fault_inject::fault_in_panic_handler();
+    let _ = writeln!(out, "{info}");
+
+    match tf {
+        Some(tf) => {
+            let _ = writeln!(out, "Exception context:\n{tf:#x?}");
+            crash::write_raw_backtrace(&mut out, tf.fp());
+        }
+        None => crash::write_raw_backtrace(&mut out, crash::current_fp()),
+    }
+    let _ = writeln!(out, "---[ end of panic report ]---");
+    drop(out);
+
+    #[cfg(feature = "fault-inject")]
+    crate::fault_inject::echo_crash_record();
     khal::power::shutdown()
 }
+
+/// Reports a panic raised while already panicking, then resets.
+///
+/// Only the location and the faulting registers are printed: the panic
+/// message may be what faulted in the first place.
+fn minimal_report(info: &PanicInfo) -> ! {
+    let now = khal::time::reference_now();
+    let mut out = CrashConsole::acquire();
+    let _ = writeln!(
+        out,
+        "---[ RECURSIVE PANIC on CPU {} ]---",
+        khal::percpu::this_cpu_id()
+    );
+    crash::write_timestamp(&mut out, now);
+    if let Some(loc) = info.location() {
+        let _ = writeln!(out, "at {}:{}:{}", loc.file(), loc.line(), loc.column());
+    }
+    if let Some(tf) = khal::context::active_exception_context() {
+        let _ = writeln!(out, "pc={:#x} fp={:#x}", tf.ip(), tf.fp());
+        if crash::is_stack_overflow(tf) {
+            let _ = writeln!(out, "kernel stack overflow");
+        }
+    }
+    let _ = writeln!(out, "---[ resetting ]---");
+    drop(out);
+    crash::hard_reset()
+}
@@ -10,6 +10,9 @@
 //! - `fs`: Enable filesystem support.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
+//! - `jump-label`: Live-patch static key sites instead of testing a flag.
+//! - `fault-inject`: Build the fault injection hooks used to test the panic
+//!   path.
 //!
 //! All the features are optional and disabled by default.
 
@@ -19,9 +22,18 @@
 #[macro_use]
 extern crate klogger;
 
+#[cfg(all(target_os = "none", not(test)))]
+mod crash;
+#[cfg(all(feature = "fault-inject", target_os = "none", not(test)))]
+pub mod fault_inject;
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
+#[cfg(all(target_os = "none", not(test)))]
+pub use self::crash::crash_record;
+
+#[cfg(all(target_os = "none", not(test)))]
+mod log_latency;
 #[cfg(feature = "smp")]
 mod mp;
 
@@ -66,8 +78,9 @@ impl klogger::LoggerAdapter for LogIfImpl {
         khal::console::write_data(s.as_bytes());
     }
 
-    fn now() -> core::time::Duration {
-        khal::time::monotonic_time()
+    fn reference_now() -> (u64, core::time::Duration) {
+        let now = khal::time::reference_now();
+        (now.ticks(), now.monotonic())
     }
 
     fn cpu_id() -> Option<usize> {
@@ -88,6 +101,27 @@ impl klogger::LoggerAdapter for LogIfImpl {
             None
         }
     }
+
+    fn task_name() -> Option<[u8; 16]> {
+        if is_init_ok() {
+            ktask::current_may_uninit().map(|curr| curr.comm())
+        } else {
+            None
+        }
+    }
+}
+
+struct FlightRecIfImpl;
+
+#[crate_interface::impl_interface]
+impl flightrec::FlightRecorderAdapter for FlightRecIfImpl {
+    fn now_ns() -> u64 {
+        khal::time::monotonic_time_nanos()
+    }
+
+    fn cpu_id() -> usize {
+        khal::percpu::this_cpu_id()
+    }
 }
 
 use core::sync::atomic::{AtomicUsize, Ordering};
@@ -111,6 +145,61 @@ impl kdma::DmaPageTableIf for DmaPageTableImpl {
     }
 }
 
+#[cfg(feature = "paging")]
+struct KvmapImpl;
+
+#[cfg(feature = "paging")]
+#[crate_interface::impl_interface]
+impl khal::kvmap::KvmapIf for KvmapImpl {
+    fn map(
+        vaddr: memaddr::VirtAddr,
+        paddr: memaddr::PhysAddr,
+        size: usize,
+        flags: khal::paging::MappingFlags,
+    ) -> bool {
+        memspace::kernel_layout()
+            .lock()
+            .map_linear(vaddr, paddr, size, flags)
+            .inspect_err(|err| error!("Cannot map {paddr:?} at {vaddr:?}: {err:?}"))
+            .is_ok()
+    }
+
+    fn unmap(vaddr: memaddr::VirtAddr, size: usize) -> bool {
+        memspace::kernel_layout()
+            .lock()
+            .unmap(vaddr, size)
+            .inspect_err(|err| error!("Cannot unmap {vaddr:?}: {err:?}"))
+            .is_ok()
+    }
+}
+
+#[cfg(feature = "jump-label")]
+struct TextPokeImpl;
+
+#[cfg(feature = "jump-label")]
+#[crate_interface::impl_interface]
+impl static_keys::TextPokeIf for TextPokeImpl {
+    fn set_text_writable(range: core::ops::Range<usize>, writable: bool) -> bool {
+        use khal::paging::MappingFlags;
+
+        let mut flags = MappingFlags::READ | MappingFlags::EXECUTE;
+        if writable {
+            flags |= MappingFlags::WRITE;
+        }
+        memspace::kernel_layout()
+            .lock()
+            .protect(range.start.into(), range.len(), flags)
+            .is_ok()
+    }
+
+    fn sync_remote_icache() {
+        #[cfg(feature = "ipi")]
+        if is_init_ok() {
+            let _ = kipi::run_on_each_cpu(static_keys::local_icache_sync);
+        }
+    }
+}
+
 /// The main entry point of the runtime.
 ///
 /// It is called from the bootstrapping code in the specific platform crate (see
@@ -124,6 +213,7 @@ impl kdma::DmaPageTableIf for DmaPageTableImpl {
 #[cfg_attr(not(test), kplat::main)]
 pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
     unsafe { khal::mem::clear_bss() };
+    boottime::boot_mark("early-init");
     khal::percpu::init_primary(cpu_id);
     khal::early_init(cpu_id, arg);
 
@@ -157,6 +247,7 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
     info!("Logging is enabled.");
     info!("Primary CPU {cpu_id} started, arg = {arg:#x}.");
 
+    boottime::boot_mark("memory");
     khal::mem::init();
     info!("Found physcial memory regions:");
     for r in khal::mem::memory_regions() {
@@ -171,6 +262,8 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
 
     #[cfg(feature = "alloc")]
     init_allocator();
+    #[cfg(feature = "alloc")]
+    boottime::init(khal::dtb::get_chosen_bootargs().unwrap_or(""));
 
     {
         use core::ops::Range;
@@ -197,39 +290,77 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
     #[cfg(feature = "paging")]
     memspace::init_memory_management();
 
+    static_keys::init();
+
+    boottime::boot_mark("platform");
     info!("Initialize platform devices...");
     khal::final_init(cpu_id, arg);
+    khal::delay::init();
 
     ktask::init_scheduler();
+    ktask::init_preempt_model(khal::dtb::get_chosen_bootargs().unwrap_or(""));
+    spawn_log_writer();
 
     #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    #[allow(unused_variables)]
+    let all_devices = {
+        boottime::boot_mark("drivers");
+        kdriver::init_drivers()
+    };
+
+    // Before the subsystems take over the devices, so that a missing device
+    // is reported rather than failing their initialization.
+    #[cfg(feature = "alloc")]
     {
-        #[allow(unused_variables)]
-        let all_devices = kdriver::init_drivers();
+        boottime::boot_mark("selftest");
+        run_selftests();
+    }
 
+    #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    {
         #[cfg(feature = "fs")]
-        kfs::init_filesystems(all_devices.block);
+        {
+            boottime::boot_mark("filesystem");
+            kfs::init_filesystems(all_devices.block);
+            firmware::register_firmware_loader(kfs::request_firmware);
+        }
 
         #[cfg(feature = "net")]
-        knet::init_network(all_devices.net);
+        {
+            boottime::boot_mark("network");
+            knet::init_network(all_devices.net);
+        }
         #[cfg(feature = "vsock")]
         knet::init_vsock(all_devices.vsock);
 
         #[cfg(feature = "display")]
-        fbdevice::fb_init(all_devices.display);
+        {
+            boottime::boot_mark("display");
+            fbdevice::fb_init(all_devices.display);
+        }
 
         #[cfg(feature = "input")]
         inputdev::init_input(all_devices.input);
     }
+    // Drivers waiting for firmware from filesystems get it, or learn that
+    // there is none.
+    firmware::firmware_loaders_ready();
 
     #[cfg(feature = "smp")]
-    self::mp::start_secondary_cpus(cpu_id);
+    {
+        boottime::boot_mark("smp");
+        self::mp::start_secondary_cpus(cpu_id);
+    }
 
+    boottime::boot_mark("interrupts");
     info!("Initialize interrupt handlers...");
     init_interrupt();
 
     #[cfg(feature = "watchdog")]
-    watchdog::init_primary();
+    {
+        watchdog::init_hung_task_detection(khal::dtb::get_chosen_bootargs().unwrap_or(""));
+        watchdog::init_primary();
+    }
 
     kinit_setup::init_cb();
 
@@ -240,11 +371,58 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
         core::hint::spin_loop();
     }
 
+    boottime::boot_mark("main");
     unsafe { main() };
 
     ktask::exit(0);
 }
 
+/// Spawns the task writing queued log records out to the consoles, then lets
+/// logging calls return without waiting for it.
+fn spawn_log_writer() {
+    /// How long records may wait in the queue.
+    const LOG_WRITER_PERIOD: core::time::Duration = core::time::Duration::from_millis(5);
+
+    let writer = ktask::spawn_with_name(
+        || {
+            loop {
+                klogger::drain_log_queue();
+                // Slow consoles get a batch at a time; let other tasks run in
+                // between.
+                if klogger::log_backlog_pending() {
+                    ktask::yield_now();
+                } else {
+                    ktask::sleep(LOG_WRITER_PERIOD);
+                }
+            }
+        },
+        "klogd".into(),
+    );
+    ktask::set_nice(&writer, ktask::MAX_NICE as i32);
+    klogger::set_async_logging(true);
+}
+
+/// Runs the self-tests selected by the `selftest=` boot argument.
+///
+/// After a `selftest=manufacturing` run the kernel does not go on booting:
+/// it powers off if every test passed and resets otherwise.
+#[cfg(feature = "alloc")]
+fn run_selftests() {
+    use selftest::{Level, Verdict};
+
+    let cmdline = khal::dtb::get_chosen_bootargs().unwrap_or("");
+    let Some(level) = Level::from_cmdline(cmdline) else {
+        return;
+    };
+    let report = selftest::run(level);
+    if level == Level::Manufacturing {
+        match report.verdict() {
+            Verdict::Pass => khal::power::shutdown(),
+            Verdict::Fail => khal::power::reset(),
+        }
+    }
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
     use khal::mem::{MemFlags, memory_regions, p2v, v2p};
@@ -275,6 +453,12 @@ fn init_allocator() {
         }
     }
 
+    // Temporary early reservations are free from now on.
+    khal::mem::early_handoff(|(start, size)| {
+        kalloc::global_add_memory(p2v(start.into()).as_usize(), size)
+            .expect("add early memory region failed");
+    });
+
     let dma_regions = || memory_regions().filter(|r| r.flags.contains(MemFlags::UNCACHED));
     for r in dma_regions() {
         kalloc::global_init_dma_page_allocator(p2v(r.paddr).as_usize(), r.size);
@@ -283,8 +467,7 @@ fn init_allocator() {
 
 fn init_interrupt() {
     // Setup timer interrupt handler
-    const PERIODIC_INTERVAL_NANOS: u64 =
-        khal::time::NANOS_PER_SEC / platconfig::TICKS_PER_SEC as u64;
+    const PERIODIC_INTERVAL_NANOS: u64 = khal::time::TICK_NANOS;
 
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
@@ -302,6 +485,7 @@ fn init_interrupt() {
 
     khal::irq::register(khal::time::interrupt_id(), || {
         update_timer();
+        khal::time::update_coarse_time();
         ktask::on_timer_tick();
     });
 
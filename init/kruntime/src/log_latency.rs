@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Self-test of the latency of logging from IRQ context with a slow console
+//! attached.
+//!
+//! A `warn!` with IRQs disabled, as in an IRQ handler, is timed with the
+//! consoles in place, then again with a stand-in for a framebuffer console
+//! that takes [`SLOW_NANOS_PER_BYTE`] to render each byte. `Warn` records are
+//! made synchronous meanwhile, the strictest setting for real-time paths.
+//! Slow consoles are only written to by the log writer task, so the stand-in
+//! may not add more than [`LATENCY_MARGIN_NS`], and must still get the
+//! records afterwards.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use khal::time::monotonic_time_nanos;
+use klogger::{Level, LogConsole};
+use selftest::{Outcome, TestContext};
+
+/// Records timed with and without the slow console.
+const SAMPLES: usize = 8;
+/// How long the stand-in console takes to render a byte.
+const SLOW_NANOS_PER_BYTE: u64 = 2_000;
+/// How much the slow console may add to the latency of a logging call.
+const LATENCY_MARGIN_NS: u64 = 50_000;
+/// How long the log writer task is given to write the records out.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stands in for a framebuffer console.
+struct SlowConsole {
+    /// Records written, one per line.
+    lines: AtomicUsize,
+}
+
+impl LogConsole for SlowConsole {
+    fn name(&self) -> &str {
+        "selftest-slow"
+    }
+
+    fn is_fast(&self) -> bool {
+        false
+    }
+
+    fn write_str(&self, s: &str) {
+        let until = monotonic_time_nanos() + s.len() as u64 * SLOW_NANOS_PER_BYTE;
+        while monotonic_time_nanos() < until {
+            spin_loop();
+        }
+        let lines = s.bytes().filter(|&b| b == b'\n').count();
+        self.lines.fetch_add(lines, Ordering::Relaxed);
+    }
+}
+
+static SLOW_CONSOLE: SlowConsole = SlowConsole {
+    lines: AtomicUsize::new(0),
+};
+
+/// Returns the longest time a `warn!` with IRQs disabled took, in
+/// nanoseconds.
+fn irq_warn_max_ns(phase: &str) -> u64 {
+    (0..SAMPLES)
+        .map(|i| {
+            let _irq = kspin::NoPreemptIrqSave::new();
+            let start = monotonic_time_nanos();
+            warn!("log-latency selftest: {phase}, sample {i}");
+            monotonic_time_nanos() - start
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn check_log_latency(ctx: &mut TestContext) -> Outcome {
+    let sync_level = klogger::sync_log_level();
+    klogger::set_sync_log_level(Level::Warn);
+    let baseline = irq_warn_max_ns("without slow console");
+    SLOW_CONSOLE.lines.store(0, Ordering::Relaxed);
+    if !klogger::register_log_console(&SLOW_CONSOLE) {
+        klogger::set_sync_log_level(sync_level);
+        return Outcome::Skip("no free console slot".into());
+    }
+    let slow = irq_warn_max_ns("with slow console");
+    klogger::set_sync_log_level(sync_level);
+
+    let deadline = monotonic_time_nanos() + DRAIN_TIMEOUT.as_nanos() as u64;
+    while klogger::log_backlog_pending() && monotonic_time_nanos() < deadline {
+        ktask::sleep(Duration::from_millis(1));
+    }
+    let stats = klogger::log_consoles().find(|it| it.name == SLOW_CONSOLE.name());
+    klogger::unregister_log_console(SLOW_CONSOLE.name());
+
+    ctx.measure("irq_warn_max_ns", baseline as i64);
+    ctx.measure("irq_warn_slow_console_max_ns", slow as i64);
+    if let Some(stats) = stats {
+        ctx.measure("slow_console_high_water_bytes", stats.high_water as i64);
+        ctx.measure("slow_console_dropped", stats.dropped as i64);
+    }
+    if slow > baseline + LATENCY_MARGIN_NS {
+        return Outcome::Fail("a slow console holds up logging from IRQ context".into());
+    }
+    if SLOW_CONSOLE.lines.load(Ordering::Relaxed) < SAMPLES {
+        return Outcome::Fail("records did not reach the slow console".into());
+    }
+    Outcome::Pass
+}
+
+selftest::register_selftest!(Boot, "log-latency", check_log_latency);
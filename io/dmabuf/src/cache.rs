@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Data cache maintenance of buffers shared with devices.
+//!
+//! Only AArch64 needs it among the supported architectures: DMA is cache
+//! coherent on x86_64, and on the RISC-V and LoongArch machines supported.
+//! There a barrier orders the accesses of the CPU with the ones of the
+//! device.
+
+use memaddr::VirtAddr;
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use core::arch::asm;
+
+    use memaddr::VirtAddr;
+
+    /// Returns the smallest data cache line size, from `CTR_EL0.DminLine`.
+    fn line_size() -> usize {
+        let ctr: u64;
+        // SAFETY: reading `CTR_EL0` has no side effect.
+        unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+        4 << ((ctr >> 16) & 0xf)
+    }
+
+    macro_rules! dc_range {
+        ($op:literal, $vaddr:expr, $len:expr) => {{
+            let line = line_size();
+            let start = $vaddr.as_usize() & !(line - 1);
+            let end = $vaddr.as_usize() + $len;
+            for addr in (start..end).step_by(line) {
+                // SAFETY: the line is mapped, and its data is kept.
+                unsafe { asm!(concat!("dc ", $op, ", {}"), in(reg) addr) };
+            }
+            // SAFETY: barriers have no side effect.
+            unsafe { asm!("dsb sy") };
+        }};
+    }
+
+    pub fn clean(vaddr: VirtAddr, len: usize) {
+        dc_range!("cvac", vaddr, len);
+    }
+
+    pub fn clean_invalidate(vaddr: VirtAddr, len: usize) {
+        dc_range!("civac", vaddr, len);
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+mod arch {
+    use core::sync::atomic::{Ordering, fence};
+
+    use memaddr::VirtAddr;
+
+    pub fn clean(_vaddr: VirtAddr, _len: usize) {
+        fence(Ordering::SeqCst);
+    }
+
+    pub fn clean_invalidate(_vaddr: VirtAddr, _len: usize) {
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Writes the cached data of `vaddr..vaddr + len` back to memory, where
+/// devices see it.
+pub fn clean(vaddr: VirtAddr, len: usize) {
+    arch::clean(vaddr, len);
+}
+
+/// Writes the cached data of `vaddr..vaddr + len` back to memory and drops
+/// it from the caches, so that the CPU reads what devices wrote.
+///
+/// Nothing is lost if the CPU wrote the range: cleaning first is the safe
+/// choice when the buffer may hold data of both the CPU and a device.
+pub fn clean_invalidate(vaddr: VirtAddr, len: usize) {
+    arch::clean_invalidate(vaddr, len);
+}
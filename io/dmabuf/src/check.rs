@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Self-test of buffer sharing between drivers.
+
+use alloc::{format, vec};
+
+use memaddr::PAGE_SIZE_4K;
+use selftest::{Outcome, TestContext};
+
+use crate::{DmaBuf, DmaDirection};
+
+/// Size of the buffer tried, not a multiple of the page size.
+const SIZE: usize = 5 * PAGE_SIZE_4K + 100;
+
+fn pattern(i: usize) -> u8 {
+    (i as u8) ^ 0x3c ^ (i >> 12) as u8
+}
+
+/// Hands a buffer from a synthetic producer, which fills it through its
+/// scatter-gather list as a DMA engine would, to a consumer which reads it
+/// back the same way after the producer let go of the buffer, then checks
+/// the CPU view of the same pages.
+fn check_sharing(ctx: &mut TestContext) -> Outcome {
+    let Ok(buf) = DmaBuf::alloc(SIZE) else {
+        return Outcome::Fail(format!("cannot allocate {SIZE} bytes"));
+    };
+    ctx.measure("segments", buf.segments().len() as i64);
+
+    let mut producer = buf.attach(DmaDirection::FromDevice);
+    let mut consumer = buf.attach(DmaDirection::ToDevice);
+    drop(buf);
+
+    producer.buf().fence().reset();
+    let mut offset = 0;
+    for seg in producer.map() {
+        for i in 0..seg.len {
+            // SAFETY: the segment is mapped to the producer.
+            unsafe {
+                seg.vaddr
+                    .as_mut_ptr()
+                    .add(i)
+                    .write_volatile(pattern(offset + i))
+            };
+        }
+        offset += seg.len;
+    }
+    producer.unmap();
+    producer.buf().fence().signal();
+    drop(producer);
+
+    consumer.buf().fence().wait();
+    let mut errors = 0;
+    let mut offset = 0;
+    for seg in consumer.map() {
+        errors += (0..seg.len)
+            // SAFETY: the segment is mapped to the consumer.
+            .filter(
+                |&i| unsafe { seg.vaddr.as_ptr().add(i).read_volatile() } != pattern(offset + i),
+            )
+            .count();
+        offset += seg.len;
+    }
+    consumer.unmap();
+
+    let buf = consumer.buf();
+    buf.begin_cpu_access(DmaDirection::FromDevice);
+    let mut data = vec![0; SIZE];
+    let read = buf.read_at(0, &mut data);
+    buf.end_cpu_access(DmaDirection::FromDevice);
+    errors += data
+        .iter()
+        .enumerate()
+        .filter(|&(i, &b)| b != pattern(i))
+        .count();
+
+    ctx.measure("errors", errors as i64);
+    if offset != buf.size() || read != SIZE {
+        return Outcome::Fail(format!(
+            "the buffer maps {offset} bytes and reads {read}, not {}",
+            buf.size()
+        ));
+    }
+    if errors != 0 {
+        return Outcome::Fail(format!("{errors} bytes read back wrong"));
+    }
+    Outcome::Pass
+}
+
+selftest::register_selftest!(Boot, "dma-buf", check_sharing);
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! The completion of a buffer.
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use kpoll::{IoEvents, PollSet, Pollable};
+
+/// Tells whether the data of a [`DmaBuf`](crate::DmaBuf) is ready.
+///
+/// A fence starts signaled, as a new buffer holds no pending work. The
+/// producer resets it before starting to fill the buffer, and signals it once
+/// the data is in place; consumers wait for it, or poll it for
+/// [`IoEvents::IN`].
+pub struct DmaFence {
+    signaled: AtomicBool,
+    waiters: PollSet,
+}
+
+impl DmaFence {
+    pub(crate) fn new() -> Self {
+        Self {
+            signaled: AtomicBool::new(true),
+            waiters: PollSet::new(),
+        }
+    }
+
+    /// Returns whether the fence is signaled.
+    pub fn is_signaled(&self) -> bool {
+        self.signaled.load(Ordering::Acquire)
+    }
+
+    /// Marks the work on the buffer as pending.
+    pub fn reset(&self) {
+        self.signaled.store(false, Ordering::Release);
+    }
+
+    /// Marks the work on the buffer as done, and wakes up the waiters.
+    pub fn signal(&self) {
+        self.signaled.store(true, Ordering::Release);
+        self.waiters.wake();
+    }
+
+    /// Waits until the fence is signaled.
+    pub fn wait(&self) {
+        ktask::future::block_on(poll_fn(|cx| {
+            if self.is_signaled() {
+                return Poll::Ready(());
+            }
+            self.waiters.register(cx.waker());
+            if self.is_signaled() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }));
+    }
+}
+
+impl Pollable for DmaFence {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.is_signaled());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.waiters.register(context.waker());
+        }
+    }
+}
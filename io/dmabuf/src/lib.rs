@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Buffers shared between drivers and user space without copies.
+//!
+//! A [`DmaBuf`] is a set of physically contiguous segments of cacheable
+//! memory, taken from the DMA zone. It is reference counted: the exporter,
+//! the drivers [attached](DmaBuf::attach) to it and the user mappings each
+//! hold a reference, and the pages are freed with the last one.
+//!
+//! As the CPU maps the pages cacheable, ownership moves between the CPU and
+//! the devices explicitly, as with Linux dma-buf:
+//!
+//! - a driver [maps](DmaBufAttachment::map) its attachment to get the
+//!   scatter-gather list before the device accesses the buffer, and unmaps
+//!   it once the device is done;
+//! - the CPU brackets its accesses with [`DmaBuf::begin_cpu_access`] and
+//!   [`DmaBuf::end_cpu_access`], which user space does through
+//!   `DMA_BUF_IOCTL_SYNC`.
+//!
+//! Each buffer has one [`DmaFence`], signaled by the producer once the data
+//! is in place. There is no fence timeline: a producer resets it before
+//! filling the buffer again.
+#![no_std]
+
+#[macro_use]
+extern crate log;
+
+extern crate alloc;
+
+mod cache;
+mod check;
+mod fence;
+
+use alloc::{sync::Arc, vec::Vec};
+
+use kalloc::{UsageKind, global_allocator};
+use kdma::{DmaBusAddress, p2b};
+use kerrno::{KError, KResult};
+use khal::mem::v2p;
+use memaddr::{PAGE_SIZE_4K, PhysAddr, VirtAddr, align_up_4k};
+
+pub use self::fence::DmaFence;
+
+/// Largest segment allocated, in pages.
+///
+/// Smaller segments are used when the DMA zone is too fragmented for it.
+const MAX_SEGMENT_PAGES: usize = 256;
+
+/// Which way data moves between the memory and a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads the buffer.
+    ToDevice,
+    /// The device writes the buffer.
+    FromDevice,
+    /// The device reads and writes the buffer.
+    Bidirectional,
+}
+
+impl DmaDirection {
+    fn device_reads(self) -> bool {
+        self != DmaDirection::FromDevice
+    }
+
+    fn device_writes(self) -> bool {
+        self != DmaDirection::ToDevice
+    }
+}
+
+/// A physically contiguous part of a [`DmaBuf`], an entry of its
+/// scatter-gather list.
+#[derive(Debug, Clone, Copy)]
+pub struct DmaSegment {
+    /// The address the CPU accesses the segment at.
+    pub vaddr: VirtAddr,
+    /// The address devices access the segment at.
+    pub bus_addr: DmaBusAddress,
+    /// The length of the segment in bytes, a multiple of the page size.
+    pub len: usize,
+}
+
+impl DmaSegment {
+    /// Returns the physical address of the segment.
+    pub fn paddr(&self) -> PhysAddr {
+        v2p(self.vaddr)
+    }
+}
+
+/// A buffer shared between drivers and user space.
+pub struct DmaBuf {
+    segments: Vec<DmaSegment>,
+    size: usize,
+    fence: DmaFence,
+}
+
+impl DmaBuf {
+    /// Allocates a zeroed buffer of `size` bytes, rounded up to pages.
+    pub fn alloc(size: usize) -> KResult<Arc<Self>> {
+        if size == 0 {
+            return Err(KError::InvalidInput);
+        }
+        let size = align_up_4k(size);
+        // Dropping the buffer frees what was allocated if this fails.
+        let mut buf = DmaBuf {
+            segments: Vec::new(),
+            size,
+            fence: DmaFence::new(),
+        };
+        let mut pages = size / PAGE_SIZE_4K;
+        let mut chunk = MAX_SEGMENT_PAGES;
+        while pages > 0 {
+            let n = chunk.min(pages);
+            match global_allocator().alloc_dma_pages(n, PAGE_SIZE_4K, UsageKind::Dma) {
+                Ok(vaddr) => {
+                    let vaddr = VirtAddr::from(vaddr);
+                    let len = n * PAGE_SIZE_4K;
+                    // SAFETY: the pages were just allocated.
+                    unsafe { vaddr.as_mut_ptr().write_bytes(0, len) };
+                    buf.segments.push(DmaSegment {
+                        vaddr,
+                        bus_addr: p2b(v2p(vaddr)),
+                        len,
+                    });
+                    pages -= n;
+                }
+                Err(_) if n > 1 => chunk = n / 2,
+                Err(_) => return Err(KError::NoMemory),
+            }
+        }
+        // No stale line may be written back over what a device writes.
+        buf.sync_for_device(DmaDirection::Bidirectional);
+        debug!(
+            "dma-buf: {size:#x} bytes in {} segments",
+            buf.segments.len()
+        );
+        Ok(Arc::new(buf))
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the segments of the buffer, in order.
+    pub fn segments(&self) -> &[DmaSegment] {
+        &self.segments
+    }
+
+    /// Returns the physical addresses of the pages of the buffer, in order.
+    pub fn pages(&self) -> impl Iterator<Item = PhysAddr> + '_ {
+        self.segments.iter().flat_map(|seg| {
+            (0..seg.len)
+                .step_by(PAGE_SIZE_4K)
+                .map(move |offset| seg.paddr() + offset)
+        })
+    }
+
+    /// Returns the fence telling when the data of the buffer is ready.
+    pub fn fence(&self) -> &DmaFence {
+        &self.fence
+    }
+
+    /// Attaches a device accessing the buffer in `dir`.
+    pub fn attach(self: &Arc<Self>, dir: DmaDirection) -> DmaBufAttachment {
+        DmaBufAttachment {
+            buf: self.clone(),
+            dir,
+            mapped: false,
+        }
+    }
+
+    /// Prepares the CPU to access the buffer, after devices accessed it in
+    /// `dir`.
+    ///
+    /// Data written by devices becomes visible to the CPU.
+    pub fn begin_cpu_access(&self, dir: DmaDirection) {
+        self.sync_for_cpu(dir);
+    }
+
+    /// Ends an access of the CPU started by [`Self::begin_cpu_access`],
+    /// before devices access the buffer in `dir`.
+    ///
+    /// Data written by the CPU becomes visible to devices.
+    pub fn end_cpu_access(&self, dir: DmaDirection) {
+        if dir.device_reads() {
+            for seg in &self.segments {
+                cache::clean(seg.vaddr, seg.len);
+            }
+        }
+    }
+
+    fn sync_for_device(&self, dir: DmaDirection) {
+        for seg in &self.segments {
+            if dir.device_writes() {
+                cache::clean_invalidate(seg.vaddr, seg.len);
+            } else {
+                cache::clean(seg.vaddr, seg.len);
+            }
+        }
+    }
+
+    fn sync_for_cpu(&self, dir: DmaDirection) {
+        if dir.device_writes() {
+            for seg in &self.segments {
+                cache::clean_invalidate(seg.vaddr, seg.len);
+            }
+        }
+    }
+
+    /// Copies the data at `offset` of the buffer to `dst`, and returns the
+    /// number of bytes copied.
+    ///
+    /// The CPU must own the buffer, see [`Self::begin_cpu_access`].
+    pub fn read_at(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let mut done = 0;
+        self.for_each_chunk(offset, dst.len(), |ptr, n| {
+            // SAFETY: the chunk is within the buffer, and `dst` is large enough.
+            unsafe { ptr.copy_to_nonoverlapping(dst[done..].as_mut_ptr(), n) };
+            done += n;
+        });
+        done
+    }
+
+    /// Copies `src` to `offset` of the buffer, and returns the number of bytes
+    /// copied.
+    ///
+    /// The CPU must own the buffer, see [`Self::begin_cpu_access`].
+    pub fn write_at(&self, offset: usize, src: &[u8]) -> usize {
+        let mut done = 0;
+        self.for_each_chunk(offset, src.len(), |ptr, n| {
+            // SAFETY: the chunk is within the buffer, and `src` is large enough.
+            unsafe { ptr.copy_from_nonoverlapping(src[done..].as_ptr(), n) };
+            done += n;
+        });
+        done
+    }
+
+    /// Calls `f` with each contiguous part of the buffer within
+    /// `offset..offset + len`, in order.
+    fn for_each_chunk(&self, mut offset: usize, len: usize, mut f: impl FnMut(*mut u8, usize)) {
+        let end = offset.saturating_add(len).min(self.size);
+        let mut seg_start = 0;
+        for seg in &self.segments {
+            if offset >= end {
+                break;
+            }
+            let seg_end = seg_start + seg.len;
+            if offset < seg_end {
+                let n = seg_end.min(end) - offset;
+                // SAFETY: the offset is within the segment.
+                f(unsafe { seg.vaddr.as_mut_ptr().add(offset - seg_start) }, n);
+                offset += n;
+            }
+            seg_start = seg_end;
+        }
+    }
+}
+
+impl Drop for DmaBuf {
+    fn drop(&mut self) {
+        for seg in &self.segments {
+            global_allocator().dealloc_dma_pages(
+                seg.vaddr.as_usize(),
+                seg.len / PAGE_SIZE_4K,
+                UsageKind::Dma,
+            );
+        }
+    }
+}
+
+/// A device attached to a [`DmaBuf`].
+///
+/// The attachment keeps the buffer alive. The device may only access the
+/// buffer while the attachment is mapped.
+pub struct DmaBufAttachment {
+    buf: Arc<DmaBuf>,
+    dir: DmaDirection,
+    mapped: bool,
+}
+
+impl DmaBufAttachment {
+    /// Returns the buffer attached to.
+    pub fn buf(&self) -> &Arc<DmaBuf> {
+        &self.buf
+    }
+
+    /// Returns the direction the device accesses the buffer in.
+    pub fn direction(&self) -> DmaDirection {
+        self.dir
+    }
+
+    /// Hands the buffer over to the device, and returns the scatter-gather
+    /// list to program it with.
+    pub fn map(&mut self) -> &[DmaSegment] {
+        if !self.mapped {
+            self.buf.sync_for_device(self.dir);
+            self.mapped = true;
+        }
+        self.buf.segments()
+    }
+
+    /// Takes the buffer back from the device once it is done with it.
+    pub fn unmap(&mut self) {
+        if self.mapped {
+            self.buf.sync_for_cpu(self.dir);
+            self.mapped = false;
+        }
+    }
+}
+
+impl Drop for DmaBufAttachment {
+    fn drop(&mut self) {
+        self.unmap();
+    }
+}
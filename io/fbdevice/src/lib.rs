@@ -3,41 +3,198 @@
 // See LICENSES for license details.
 
 //! Framebuffer device initialization and access helpers.
+//!
+//! All display devices discovered at boot are kept in a registry. Device 0 is
+//! the primary framebuffer used by [`fb_info`] and [`fb_flush`]; the other
+//! devices are reachable by index or name through [`FbHandle`].
+//!
+//! A [`DmaBuf`] laid out as a framebuffer can be shown with
+//! [`FbHandle::present`], scanned out by the device if it can.
 #![no_std]
 
 #[macro_use]
 extern crate log;
+extern crate alloc;
 
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use dmabuf::{DmaBuf, DmaBufAttachment, DmaDirection};
 pub use kdriver::prelude::DisplayInfo;
 use kdriver::{DeviceContainer, prelude::*};
-use ksync::Mutex;
+use ksync::{Mutex, MutexGuard};
 use lazyinit::LazyInit;
 
-static PRIMARY_FB: LazyInit<Mutex<DisplayDevice>> = LazyInit::new();
+/// A registered framebuffer device.
+struct FbEntry {
+    name: String,
+    dev: Mutex<DisplayDevice>,
+    /// The buffer scanned out instead of the framebuffer, if any.
+    scanout: Mutex<Option<DmaBufAttachment>>,
+}
+
+static FB_DEVICES: LazyInit<Vec<FbEntry>> = LazyInit::new();
 
 /// Initialize the framebuffer subsystem with available devices.
 pub fn fb_init(mut display_devs: DeviceContainer<DisplayDevice>) {
     info!("Initialize framebuffer subsystem...");
 
-    if let Some(dev) = display_devs.take_one() {
-        info!("  use framebuffer device 0: {:?}", dev.name());
-        PRIMARY_FB.init_once(Mutex::new(dev));
-    } else {
+    let mut devices = Vec::new();
+    while let Some(dev) = display_devs.take_one() {
+        info!(
+            "  use framebuffer device {}: {:?}",
+            devices.len(),
+            dev.name()
+        );
+        devices.push(FbEntry {
+            name: String::from(dev.name()),
+            dev: Mutex::new(dev),
+            scanout: Mutex::new(None),
+        });
+    }
+    if devices.is_empty() {
         warn!("  No framebuffer device found!");
     }
+    FB_DEVICES.init_once(devices);
+}
+
+fn devices() -> &'static [FbEntry] {
+    FB_DEVICES.get().map_or(&[], |devs| devs.as_slice())
+}
+
+/// Returns the number of registered framebuffer devices.
+pub fn fb_count() -> usize {
+    devices().len()
 }
 
 /// Returns whether a primary framebuffer is available.
 pub fn fb_available() -> bool {
-    PRIMARY_FB.is_inited()
+    fb_count() > 0
 }
 
 /// Returns display information for the primary framebuffer.
 pub fn fb_info() -> DisplayInfo {
-    PRIMARY_FB.lock().info()
+    fb_info_of(0).expect("no primary framebuffer device")
 }
 
 /// Flush the primary framebuffer to the display.
 pub fn fb_flush() -> bool {
-    PRIMARY_FB.lock().flush().is_ok()
+    fb_flush_of(0)
+}
+
+/// Returns display information for the framebuffer at `index`.
+pub fn fb_info_of(index: usize) -> Option<DisplayInfo> {
+    FbHandle::by_index(index).map(|fb| fb.info())
+}
+
+/// Flush the framebuffer at `index` to the display.
+///
+/// Returns `false` if the device does not exist or the flush failed.
+pub fn fb_flush_of(index: usize) -> bool {
+    FbHandle::by_index(index).is_some_and(|fb| fb.flush())
+}
+
+/// A handle to one registered framebuffer device.
+///
+/// Handles are cheap to copy and stay valid for the lifetime of the kernel.
+#[derive(Clone, Copy)]
+pub struct FbHandle {
+    index: usize,
+    entry: &'static FbEntry,
+}
+
+impl FbHandle {
+    /// Returns the handle of the framebuffer at `index`.
+    pub fn by_index(index: usize) -> Option<Self> {
+        devices().get(index).map(|entry| Self { index, entry })
+    }
+
+    /// Returns the handle of the first framebuffer named `name`.
+    pub fn by_name(name: &str) -> Option<Self> {
+        devices()
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(index, entry)| Self { index, entry })
+    }
+
+    /// Returns the registry index of this device.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the device name as reported by the driver.
+    pub fn name(&self) -> &'static str {
+        &self.entry.name
+    }
+
+    /// Returns display information for this device.
+    pub fn info(&self) -> DisplayInfo {
+        self.entry.dev.lock().info()
+    }
+
+    /// Flush this device to the display.
+    pub fn flush(&self) -> bool {
+        self.entry.dev.lock().flush().is_ok()
+    }
+
+    /// Shows `buf`, laid out as the framebuffer, once its fence is signaled.
+    ///
+    /// The device scans the buffer out if it can, and keeps it until the next
+    /// one is presented. Otherwise the buffer is copied to the framebuffer.
+    /// Returns the number of bytes copied.
+    pub fn present(&self, buf: &Arc<DmaBuf>) -> DriverResult<usize> {
+        let info = self.info();
+        if buf.size() < info.fb_size {
+            return Err(DriverError::InvalidInput);
+        }
+        buf.fence().wait();
+
+        let mut attachment = buf.attach(DmaDirection::ToDevice);
+        let segments = attachment
+            .map()
+            .iter()
+            .map(|seg| ScanoutSegment {
+                vaddr: seg.vaddr.as_usize(),
+                bus_addr: seg.bus_addr.as_u64(),
+                len: seg.len,
+            })
+            .collect::<Vec<_>>();
+        let mut dev = self.lock();
+        let mut scanout = self.entry.scanout.lock();
+        match dev.set_scanout(&segments) {
+            Ok(()) => {
+                // The buffer scanned out before is released.
+                *scanout = Some(attachment);
+                Ok(0)
+            }
+            Err(DriverError::Unsupported) => {
+                drop(attachment);
+                buf.begin_cpu_access(DmaDirection::FromDevice);
+                // SAFETY: the framebuffer is mapped for the lifetime of the
+                // kernel, and the device is locked.
+                let fb = unsafe {
+                    core::slice::from_raw_parts_mut(info.fb_base_vaddr as *mut u8, info.fb_size)
+                };
+                let copied = buf.read_at(0, fb);
+                buf.end_cpu_access(DmaDirection::FromDevice);
+                dev.flush()?;
+                Ok(copied)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Locks the underlying device for direct access.
+    pub fn lock(&self) -> MutexGuard<'static, DisplayDevice> {
+        self.entry.dev.lock()
+    }
+}
+
+impl core::fmt::Debug for FbHandle {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FbHandle")
+            .field("index", &self.index)
+            .field("name", &self.entry.name)
+            .finish()
+    }
 }
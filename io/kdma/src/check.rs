@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Self-test of coherent DMA memory.
+
+use alloc::format;
+use core::alloc::Layout;
+
+use memaddr::{PAGE_SIZE_4K, pa};
+use selftest::{Outcome, TestContext};
+
+/// Sizes tried, covering both the byte and the page allocator.
+const SIZES: [usize; 2] = [512, 4 * PAGE_SIZE_4K];
+
+fn pattern(i: usize) -> u8 {
+    (i as u8) ^ 0xa5 ^ (i >> 8) as u8
+}
+
+/// Checks that coherent DMA memory can be allocated and freed, that its bus
+/// address leads back to its CPU address, and that a pattern written through
+/// the uncached mapping reads back intact.
+fn check_coherent(ctx: &mut TestContext) -> Outcome {
+    for size in SIZES {
+        let layout = Layout::from_size_align(size, 64).unwrap();
+        let Ok(dma) = (unsafe { crate::allocate_dma_memory(layout) }) else {
+            return Outcome::Fail(format!("cannot allocate {size} bytes"));
+        };
+        let paddr = pa!(dma.bus_addr.as_u64() as usize - platconfig::plat::PHYS_BUS_OFFSET);
+        let mapped = khal::mem::p2v(paddr).as_ptr() == dma.cpu_addr.as_ptr().cast_const();
+
+        let buf = dma.cpu_addr.as_ptr();
+        for i in 0..size {
+            unsafe { buf.add(i).write_volatile(pattern(i)) };
+        }
+        let errors = (0..size)
+            .filter(|&i| unsafe { buf.add(i).read_volatile() } != pattern(i))
+            .count();
+        unsafe { crate::deallocate_dma_memory(dma, layout) };
+
+        ctx.measure(format!("errors_{size}"), errors as i64);
+        if !mapped {
+            return Outcome::Fail(format!(
+                "bus address {:?} does not map to the CPU address",
+                dma.bus_addr
+            ));
+        }
+        if errors != 0 {
+            return Outcome::Fail(format!("{errors} of {size} bytes read back wrong"));
+        }
+    }
+    Outcome::Pass
+}
+
+selftest::register_selftest!(Boot, "dma-coherent", check_coherent);
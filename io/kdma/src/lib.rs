@@ -7,6 +7,7 @@
 
 extern crate alloc;
 
+mod check;
 mod dma;
 
 use core::{alloc::Layout, ptr::NonNull};
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Hung task detection: reports tasks stuck in uninterruptible waits.
+//!
+//! The watchdog task of each CPU scans the tasks created on that CPU a batch
+//! at a time, so a scan stays cheap however many tasks there are.
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use khal::percpu::this_cpu_id;
+use log::{error, warn};
+
+/// Default hung task timeout in nanoseconds (120 seconds).
+pub const DEFAULT_HUNG_TASK_TIMEOUT_NS: u64 = 120_000_000_000;
+
+/// Number of tasks examined by one scan.
+pub const HUNG_TASK_SCAN_BATCH: usize = 128;
+
+/// Number of scans per timeout.
+///
+/// A pass over up to `SCANS_PER_TIMEOUT * HUNG_TASK_SCAN_BATCH` tasks of a CPU
+/// completes within one timeout, so a hung task is reported within two.
+const SCANS_PER_TIMEOUT: u64 = 8;
+
+/// Hung task timeout in nanoseconds, 0 disables detection.
+static HUNG_TASK_TIMEOUT_NS: AtomicU64 = AtomicU64::new(DEFAULT_HUNG_TASK_TIMEOUT_NS);
+
+/// Whether to panic when a hung task is found.
+static HUNG_TASK_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Per-CPU timestamp of the last scan (nanoseconds).
+#[percpu::def_percpu]
+static LAST_SCAN_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the time a task may stay blocked in an uninterruptible wait before
+/// it is reported, 0 disables detection.
+pub fn set_hung_task_timeout(timeout_ns: u64) {
+    HUNG_TASK_TIMEOUT_NS.store(timeout_ns, Ordering::Relaxed);
+}
+
+/// Sets whether to panic when a hung task is found, e.g. to fail CI runs.
+pub fn set_hung_task_panic(panic: bool) {
+    HUNG_TASK_PANIC.store(panic, Ordering::Relaxed);
+}
+
+/// Reads the options in the kernel command line.
+///
+/// `hung_task.timeout_secs=<secs>` sets the timeout (0 disables detection),
+/// and `hung_task.panic=1` panics when a hung task is found.
+pub fn init_hung_task_detection(cmdline: &str) {
+    for arg in cmdline.split_ascii_whitespace() {
+        if let Some(secs) = arg.strip_prefix("hung_task.timeout_secs=") {
+            match secs.parse::<u64>() {
+                Ok(secs) => set_hung_task_timeout(secs * 1_000_000_000),
+                Err(_) => warn!("invalid hung task timeout {secs:?}"),
+            }
+        } else if let Some(panic) = arg.strip_prefix("hung_task.panic=") {
+            set_hung_task_panic(panic == "1");
+        }
+    }
+}
+
+/// Scans the next batch of tasks of the current CPU if a scan is due, and
+/// reports the hung ones.
+///
+/// Called from the watchdog task.
+pub fn check_hung_tasks(now_ns: u64) {
+    let timeout_ns = HUNG_TASK_TIMEOUT_NS.load(Ordering::Relaxed);
+    if timeout_ns == 0 {
+        return;
+    }
+    let last_scan = unsafe { LAST_SCAN_NS.current_ref_raw() };
+    if now_ns.saturating_sub(last_scan.load(Ordering::Relaxed)) < timeout_ns / SCANS_PER_TIMEOUT {
+        return;
+    }
+    last_scan.store(now_ns, Ordering::Relaxed);
+
+    for hung in ktask::scan_hung_tasks(timeout_ns, HUNG_TASK_SCAN_BATCH) {
+        error!(
+            "[watchdog] hung task on cpu {}, blocked for more than {} seconds: {hung}",
+            this_cpu_id(),
+            timeout_ns / 1_000_000_000
+        );
+        if HUNG_TASK_PANIC.load(Ordering::Relaxed) {
+            panic!("hung task: {}", hung.task().id_name());
+        }
+    }
+}
@@ -4,7 +4,6 @@
 
 //! Watchdog initialization and NMI handler setup.
 use khal::{context::TrapFrame, percpu::this_cpu_id};
-use ktask::{KCpuMask, TaskInner};
 use log::debug;
 
 use crate::rendezvous as rv;
@@ -17,12 +16,13 @@ static mut TRAP_FRAMES: [Option<&TrapFrame>; platconfig::plat::CPU_NUM] =
 ///
 /// It sets up:
 /// - soft lockup detection (timer + watchdog task)
+/// - hung task detection (watchdog task)
 /// - hard lockup detection (PMU/NMI based)
 fn init_common() {
     init_softlockup_detection();
 
     // Register hard lockup detection task.
-    crate::register_hardlockup_detection_task();
+    crate::register_hardlockup_detection_task(this_cpu_id());
 
     // Register mutex deadlock check
     crate::register_watchdog_task(&crate::watchdog_task::MUTEX_DEADLOCK_CHECK);
@@ -104,19 +104,18 @@ pub fn init_softlockup_detection() {
         }
     });
 
-    // Watchdog task that periodically "touches" the soft lockup timestamp.
-    let watchdog_task = TaskInner::new(
-        move || loop {
-            crate::touch_softlockup(khal::time::monotonic_time_nanos());
+    // Watchdog task that periodically "touches" the soft lockup timestamp,
+    // and scans for hung tasks.
+    // Bound to the local CPU, whose timestamp it touches.
+    let watchdog_task = ktask::spawn_pinned(this_cpu_id(), move || {
+        loop {
+            let now_ns = khal::time::monotonic_time_nanos();
+            crate::touch_softlockup(now_ns);
+            crate::check_hung_tasks(now_ns);
             ktask::yield_now();
-        },
-        "watchdog".into(),
-        platconfig::TASK_STACK_SIZE,
-    );
-
-    // Bind watchdog task to the local CPU.
-    watchdog_task.set_cpumask(KCpuMask::one_shot(this_cpu_id()));
-    ktask::spawn_task(watchdog_task);
+        }
+    });
+    watchdog_task.set_name("watchdog");
 }
 
 /// Initialize watchdogs on the primary CPU.
@@ -2,13 +2,17 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-//! Watchdog subsystem for soft/hard lockup detection.
+//! Watchdog subsystem for soft/hard lockup and hung task detection.
 #![no_std]
+pub mod hung_task;
 pub mod init;
 pub mod lockup_detection;
 pub mod rendezvous;
 pub mod watchdog_task;
 pub use crate::{
+    hung_task::{
+        check_hung_tasks, init_hung_task_detection, set_hung_task_panic, set_hung_task_timeout,
+    },
     init::{init_primary, init_secondary},
     lockup_detection::{
         check_softlockup, register_hardlockup_detection_task, timer_tick, touch_softlockup,
@@ -133,10 +133,17 @@ pub fn check_softlockup(now_ns: u64) -> bool {
     }
 }
 
-/// Register the hard lockup detection task on the current CPU.
-pub fn register_hardlockup_detection_task() {
-    let task: &'static LockupDetection = unsafe { LOCKUP_DETECTION.current_ref_raw() };
-    crate::watchdog_task::register_watchdog_task(task);
+/// Register the hard lockup detection task on CPU `cpu`.
+///
+/// The registration runs in a task pinned to `cpu`, so that it reaches the
+/// per-CPU state of that CPU whichever CPU the caller runs on.
+pub fn register_hardlockup_detection_task(cpu: usize) {
+    let registrar = ktask::spawn_pinned(cpu, move || {
+        assert_eq!(khal::percpu::this_cpu_id(), cpu);
+        let task: &'static LockupDetection = unsafe { LOCKUP_DETECTION.current_ref_raw() };
+        crate::watchdog_task::register_watchdog_task(task);
+    });
+    registrar.set_name("hardlockup");
 }
 
 impl WatchdogTask for LockupDetection {
@@ -290,7 +290,15 @@ impl GlobalAllocator {
         }
         #[cfg(not(feature = "level-1"))]
         {
-            let addr = self.palloc.lock().allocate_pages(num_pages, align_pow2)?;
+            let result = self.palloc.lock().allocate_pages(num_pages, align_pow2);
+            let addr = match result {
+                // The heap grows with the byte allocator locked, which a
+                // reclaimer may need.
+                Err(_) if kind != UsageKind::RustHeap && reclaim(num_pages) => {
+                    self.palloc.lock().allocate_pages(num_pages, align_pow2)?
+                }
+                result => result?,
+            };
             if !matches!(kind, UsageKind::RustHeap) {
                 self.usages.lock().alloc(kind, num_pages * PAGE_SIZE);
             }
@@ -368,6 +376,36 @@ impl GlobalAllocator {
         self.palloc.lock().deallocate_pages(va, num_pages);
     }
 
+    /// Gives back several allocations of `num_pages` pages each, starting
+    /// from the addresses in `vas`, taking the allocator locks only once.
+    ///
+    /// Each allocation must be valid for [`dealloc_pages`].
+    ///
+    /// [`dealloc_pages`]: GlobalAllocator::dealloc_pages
+    pub fn dealloc_pages_bulk(&self, vas: &[usize], num_pages: usize, kind: UsageKind) {
+        if vas.is_empty() {
+            return;
+        }
+        self.usages
+            .lock()
+            .dealloc(kind, vas.len() * num_pages * PAGE_SIZE);
+        #[cfg(feature = "level-1")]
+        {
+            let mut balloc = self.balloc.lock();
+            let layout = Layout::from_size_align(num_pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+            for &va in vas {
+                balloc.deallocate(NonNull::new(va as *mut u8).unwrap(), layout);
+            }
+        }
+        #[cfg(not(feature = "level-1"))]
+        {
+            let mut palloc = self.palloc.lock();
+            for &va in vas {
+                palloc.deallocate_pages(va, num_pages);
+            }
+        }
+    }
+
     /// Gives back the allocated DMA pages starts from `va` to the DMA page allocator.
     pub fn dealloc_dma_pages(&self, va: usize, num_pages: usize, kind: UsageKind) {
         self.usages.lock().dealloc(kind, num_pages * PAGE_SIZE);
@@ -491,6 +529,55 @@ where
     });
 }
 
+/// Frees pages on memory pressure, returning how many of the requested
+/// number it freed.
+///
+/// Reclaimers run in the context of the allocation that failed, which may
+/// hold any lock: they must only try locks, and give up on contention.
+pub type Reclaimer = fn(usize) -> usize;
+
+static RECLAIMERS: SpinNoIrq<[Option<Reclaimer>; 4]> = SpinNoIrq::new([None; 4]);
+
+/// Set while reclaimers run, so that their own allocations do not reclaim.
+#[cfg(not(feature = "level-1"))]
+static RECLAIMING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Registers `reclaimer` to be called when the page allocator runs out of
+/// memory.
+///
+/// # Panics
+///
+/// Panics if too many reclaimers are registered.
+pub fn register_reclaimer(reclaimer: Reclaimer) {
+    let mut reclaimers = RECLAIMERS.lock();
+    let slot = reclaimers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many reclaimers");
+    *slot = Some(reclaimer);
+}
+
+/// Runs the reclaimers until `pages` pages are freed, and returns whether
+/// any was.
+#[cfg(not(feature = "level-1"))]
+fn reclaim(pages: usize) -> bool {
+    use core::sync::atomic::Ordering;
+
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    let reclaimers = *RECLAIMERS.lock();
+    let mut freed = 0;
+    for reclaimer in reclaimers.iter().flatten() {
+        if freed >= pages {
+            break;
+        }
+        freed += reclaimer(pages - freed);
+    }
+    RECLAIMING.store(false, Ordering::Release);
+    freed > 0
+}
+
 #[cfg_attr(all(target_os = "none", not(test)), global_allocator)]
 static GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator::new();
 
@@ -16,7 +16,7 @@ pub trait MemorySetBackend: Clone {
     /// The address type used in the memory area.
     type Addr: MemoryAddr;
     /// The flags type used in the memory area.
-    type Flags: Copy;
+    type Flags: Copy + PartialEq;
     /// The page table type used in the memory area.
     type PageTable;
 
@@ -40,4 +40,12 @@ pub trait MemorySetBackend: Clone {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool;
+
+    /// Whether an area with this backend may be merged with the area right
+    /// after it, backed by `next`, when both have the same flags.
+    ///
+    /// Areas are never merged by default.
+    fn can_merge(&self, _next: &Self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Index of the free gaps between memory areas.
+//!
+//! The gaps are kept in a treap ordered by start address. Every node also
+//! caches the size of the largest gap in its subtree, so a first-fit search
+//! skips whole subtrees whose gaps are all too small and runs in logarithmic
+//! time however many areas there are.
+
+use alloc::boxed::Box;
+
+type Link = Option<Box<Node>>;
+
+struct Node {
+    start: usize,
+    size: usize,
+    /// The largest `size` in this subtree.
+    max: usize,
+    priority: u64,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    fn new(start: usize, size: usize) -> Box<Self> {
+        Box::new(Self {
+            start,
+            size,
+            max: size,
+            priority: priority(start),
+            left: None,
+            right: None,
+        })
+    }
+
+    fn update(&mut self) {
+        self.max = self.size.max(max_of(&self.left)).max(max_of(&self.right));
+    }
+}
+
+/// Derives the treap priority of a node from its key, which keeps the tree
+/// balanced without a random number generator.
+fn priority(start: usize) -> u64 {
+    // The finalizer of SplitMix64.
+    let mut x = start as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn max_of(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.max)
+}
+
+/// Splits `link` into the nodes with a start below `key` and the others.
+fn split(link: Link, key: usize) -> (Link, Link) {
+    let Some(mut node) = link else {
+        return (None, None);
+    };
+    if node.start < key {
+        let (left, right) = split(node.right.take(), key);
+        node.right = left;
+        node.update();
+        (Some(node), right)
+    } else {
+        let (left, right) = split(node.left.take(), key);
+        node.left = right;
+        node.update();
+        (left, Some(node))
+    }
+}
+
+/// Joins two trees, all starts in `left` being below those in `right`.
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// The free gaps of a [`MemorySet`](crate::MemorySet).
+pub(crate) struct GapTree {
+    root: Link,
+}
+
+impl GapTree {
+    pub const fn new() -> Self {
+        Self { root: None }
+    }
+
+    /// Adds the gap `start..start + size`.
+    pub fn insert(&mut self, start: usize, size: usize) {
+        let (left, right) = split(self.root.take(), start);
+        self.root = merge(merge(left, Some(Node::new(start, size))), right);
+    }
+
+    /// Removes the gaps that start within `start..end`.
+    pub fn remove_range(&mut self, start: usize, end: usize) {
+        let (left, rest) = split(self.root.take(), start);
+        let (_removed, right) = split(rest, end);
+        self.root = merge(left, right);
+    }
+
+    /// Removes all gaps.
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    /// Returns the first result of `fit(start, size)` over the gaps of at
+    /// least `min_size` bytes that end after `from` and start before `until`,
+    /// in address order.
+    pub fn find(
+        &self,
+        from: usize,
+        until: usize,
+        min_size: usize,
+        mut fit: impl FnMut(usize, usize) -> Option<usize>,
+    ) -> Option<usize> {
+        find_in(&self.root, from, until, min_size, &mut fit)
+    }
+}
+
+fn find_in(
+    link: &Link,
+    from: usize,
+    until: usize,
+    min_size: usize,
+    fit: &mut impl FnMut(usize, usize) -> Option<usize>,
+) -> Option<usize> {
+    let node = link.as_ref()?;
+    if node.max < min_size {
+        return None;
+    }
+    // Gaps do not overlap, so those left of a gap starting at or below `from`
+    // end at or below `from` too.
+    if node.start > from
+        && let Some(found) = find_in(&node.left, from, until, min_size, fit)
+    {
+        return Some(found);
+    }
+    if node.start >= until {
+        return None;
+    }
+    if node.size >= min_size
+        && let Some(found) = fit(node.start, node.size)
+    {
+        return Some(found);
+    }
+    find_in(&node.right, from, until, min_size, fit)
+}
@@ -8,6 +8,7 @@ extern crate alloc;
 
 mod area;
 mod backend;
+mod gap;
 mod set;
 
 pub use self::{area::MemoryArea, backend::MemorySetBackend, set::MemorySet};
@@ -21,6 +22,8 @@ pub enum MemorySetError {
     AlreadyExists,
     /// The backend page table is in a bad state.
     BadState,
+    /// The operation would exceed the maximum number of memory areas.
+    TooManyAreas,
 }
 
 impl From<MemorySetError> for kerrno::KError {
@@ -29,6 +32,7 @@ impl From<MemorySetError> for kerrno::KError {
             MemorySetError::InvalidParam => kerrno::KError::InvalidInput,
             MemorySetError::AlreadyExists => kerrno::KError::AlreadyExists,
             MemorySetError::BadState => kerrno::KError::BadState,
+            MemorySetError::TooManyAreas => kerrno::KError::NoMemory,
         }
     }
 }
@@ -38,10 +42,12 @@ pub type MemorySetResult<T = ()> = Result<T, MemorySetError>;
 
 #[cfg(unittest)]
 mod tests_memset {
-    use memaddr::{VirtAddr, va};
+    use alloc::vec::Vec;
+
+    use memaddr::{AddrRange, VirtAddr, va};
     use unittest::def_test;
 
-    use super::{MemoryArea, MemorySet, MemorySetBackend};
+    use super::{MemoryArea, MemorySet, MemorySetBackend, MemorySetError};
 
     #[derive(Clone, Copy)]
     struct DummyBackend;
@@ -79,6 +85,17 @@ mod tests_memset {
         ) -> bool {
             true
         }
+
+        fn can_merge(&self, _next: &Self) -> bool {
+            true
+        }
+    }
+
+    /// Returns the ranges and flags of the areas of `set`.
+    fn layout(set: &MemorySet<DummyBackend>) -> Vec<(usize, usize, u8)> {
+        set.iter()
+            .map(|area| (area.start().as_usize(), area.end().as_usize(), area.flags()))
+            .collect()
     }
 
     #[def_test]
@@ -107,4 +124,214 @@ mod tests_memset {
         let overlap = set.overlaps(memaddr::AddrRange::from_start_size(va!(0x2800), 0x100));
         assert!(overlap);
     }
+
+    #[def_test]
+    fn test_memory_set_find_free_area() {
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        let limit = AddrRange::new(va!(0x1000), va!(0x10_0000));
+        for (start, size) in [(0x1000, 0x1000), (0x3000, 0x2000), (0x8000, 0x1000)] {
+            let area = MemoryArea::new(va!(start), size, 0x1, DummyBackend);
+            set.map(area, &mut page_table, false).unwrap();
+        }
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x1000, limit, 0x1000),
+            Some(va!(0x2000))
+        );
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x2000, limit, 0x1000),
+            Some(va!(0x5000))
+        );
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x4000, limit, 0x4000),
+            Some(va!(0xc000))
+        );
+
+        // The freed range is found again.
+        set.unmap(va!(0x3000), 0x2000, &mut page_table).unwrap();
+        assert_eq!(
+            set.find_free_area(va!(0x2800), 0x3000, limit, 0x1000),
+            Some(va!(0x3000))
+        );
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x10_0000, limit, 0x1000),
+            None
+        );
+    }
+
+    #[def_test]
+    fn test_memory_set_max_areas() {
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        set.set_max_areas(2);
+        let area = MemoryArea::new(va!(0x1000), 0x4000, 0x1, DummyBackend);
+        set.map(area, &mut page_table, false).unwrap();
+
+        // Protecting the middle would split the area in three.
+        assert_eq!(
+            set.protect(va!(0x2000), 0x1000, |_| Some(0x3), &mut page_table),
+            Err(MemorySetError::TooManyAreas)
+        );
+        set.protect(va!(0x1000), 0x1000, |_| Some(0x3), &mut page_table)
+            .unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(
+            set.unmap(va!(0x3000), 0x1000, &mut page_table),
+            Err(MemorySetError::TooManyAreas)
+        );
+        let area = MemoryArea::new(va!(0x8000), 0x1000, 0x1, DummyBackend);
+        assert_eq!(
+            set.map(area, &mut page_table, false),
+            Err(MemorySetError::TooManyAreas)
+        );
+        set.unmap(va!(0x1000), 0x2000, &mut page_table).unwrap();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[def_test]
+    fn test_memory_set_protect_split_and_merge() {
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        let area = MemoryArea::new(va!(0x1000), 0x4000, 0x1, DummyBackend);
+        set.map(area, &mut page_table, false).unwrap();
+
+        // Split at the start.
+        set.protect(va!(0x1000), 0x1000, |_| Some(0x3), &mut page_table)
+            .unwrap();
+        assert_eq!(layout(&set), [(0x1000, 0x2000, 0x3), (0x2000, 0x5000, 0x1)]);
+
+        // Split in the middle.
+        set.protect(va!(0x3000), 0x1000, |_| Some(0x3), &mut page_table)
+            .unwrap();
+        assert_eq!(set.len(), 4);
+        assert_eq!(
+            layout(&set),
+            [
+                (0x1000, 0x2000, 0x3),
+                (0x2000, 0x3000, 0x1),
+                (0x3000, 0x4000, 0x3),
+                (0x4000, 0x5000, 0x1),
+            ]
+        );
+
+        // Filling the hole merges the areas on both sides.
+        set.protect(va!(0x2000), 0x1000, |_| Some(0x3), &mut page_table)
+            .unwrap();
+        assert_eq!(layout(&set), [(0x1000, 0x4000, 0x3), (0x4000, 0x5000, 0x1)]);
+        // Protecting the whole range leaves a single area again.
+        set.protect(va!(0x1000), 0x4000, |_| Some(0x1), &mut page_table)
+            .unwrap();
+        assert_eq!(layout(&set), [(0x1000, 0x5000, 0x1)]);
+
+        // Areas apart are not merged.
+        let area = MemoryArea::new(va!(0x6000), 0x1000, 0x1, DummyBackend);
+        set.map(area, &mut page_table, false).unwrap();
+        set.protect(va!(0x1000), 0x6000, |_| Some(0x1), &mut page_table)
+            .unwrap();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[def_test]
+    fn test_memory_set_take_and_extend() {
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        let area = MemoryArea::new(va!(0x1000), 0x4000, 0x1, DummyBackend);
+        set.map(area, &mut page_table, false).unwrap();
+
+        // Taking the middle of an area leaves both sides.
+        let taken = set.take(va!(0x2000), 0x1000).unwrap();
+        assert_eq!(
+            taken.va_range(),
+            AddrRange::from_start_size(va!(0x2000), 0x1000)
+        );
+        assert_eq!(set.len(), 2);
+        assert!(set.find(va!(0x2000)).is_none());
+        assert_eq!(
+            set.take(va!(0x1000), 0x2000).err(),
+            Some(MemorySetError::InvalidParam)
+        );
+
+        // The hole is free again, and may be filled without mapping.
+        let limit = AddrRange::new(va!(0x1000), va!(0x10_0000));
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x1000, limit, 0x1000),
+            Some(va!(0x2000))
+        );
+        set.insert(taken).unwrap();
+        assert_eq!(set.len(), 3);
+
+        assert_eq!(
+            set.extend(va!(0x2000), va!(0x4000), &mut page_table),
+            Err(MemorySetError::AlreadyExists)
+        );
+        set.extend(va!(0x3000), va!(0x8000), &mut page_table)
+            .unwrap();
+        assert_eq!(set.find(va!(0x7000)).unwrap().start(), va!(0x3000));
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x1000, limit, 0x1000),
+            Some(va!(0x8000))
+        );
+    }
+
+    #[def_test]
+    fn test_memory_set_generation_and_iter_after() {
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        for start in [0x1000, 0x3000, 0x5000] {
+            let area = MemoryArea::new(va!(start), 0x1000, 0x1, DummyBackend);
+            set.map(area, &mut page_table, false).unwrap();
+        }
+        let starts = |set: &MemorySet<DummyBackend>, addr| {
+            set.iter_after(Some(va!(addr)))
+                .map(|area| area.start().as_usize())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(set.iter_after(None).count(), 3);
+        assert_eq!(starts(&set, 0x1000), [0x3000, 0x5000]);
+        assert_eq!(starts(&set, 0x2000), [0x3000, 0x5000]);
+        assert!(starts(&set, 0x5000).is_empty());
+
+        // A walk resuming after 0x3000 sees the changes behind it only.
+        let generation = set.generation();
+        assert_eq!(set.generation(), generation);
+        set.unmap(va!(0x1000), 0x1000, &mut page_table).unwrap();
+        let area = MemoryArea::new(va!(0x7000), 0x1000, 0x1, DummyBackend);
+        set.map(area, &mut page_table, false).unwrap();
+        assert!(set.generation() > generation);
+        assert_eq!(starts(&set, 0x3000), [0x5000, 0x7000]);
+
+        let generation = set.generation();
+        set.protect(va!(0x5000), 0x1000, |_| Some(0x3), &mut page_table)
+            .unwrap();
+        assert!(set.generation() > generation);
+    }
+
+    #[def_test]
+    fn test_memory_set_many_areas() {
+        const COUNT: usize = 200_000;
+        let mut set: MemorySet<DummyBackend> = MemorySet::new();
+        let mut page_table = ();
+        let limit = AddrRange::new(va!(0x1000), va!(1 << 40));
+        // Alternating flags keep the areas apart, as many tiny mappings do.
+        for i in 0..COUNT {
+            let start = set
+                .find_free_area(va!(0x1000), 0x1000, limit, 0x1000)
+                .unwrap();
+            let area = MemoryArea::new(start, 0x1000, (i % 2) as u8, DummyBackend);
+            set.map(area, &mut page_table, false).unwrap();
+        }
+        assert_eq!(set.len(), COUNT);
+        assert!(set.find(va!(COUNT * 0x1000)).is_some());
+
+        let mut batches = 0;
+        while set.clear_batch(1024, &mut page_table).unwrap() > 0 {
+            batches += 1;
+        }
+        assert!(set.is_empty());
+        assert_eq!(batches, COUNT / 1024);
+        assert_eq!(
+            set.find_free_area(va!(0x1000), 0x1000, limit, 0x1000),
+            Some(va!(0x1000))
+        );
+    }
 }
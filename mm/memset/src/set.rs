@@ -6,15 +6,22 @@
 use alloc::collections::BTreeMap;
 #[allow(unused_imports)] // this is a weird false alarm
 use alloc::vec::Vec;
-use core::fmt;
+use core::{fmt, ops::Bound};
 
 use memaddr::{AddrRange, MemoryAddr};
 
-use crate::{MemoryArea, MemorySetBackend, MemorySetError, MemorySetResult};
+use crate::{MemoryArea, MemorySetBackend, MemorySetError, MemorySetResult, gap::GapTree};
 
 /// A container that maintains memory mappings ([`MemoryArea`]).
+///
+/// Areas are kept ordered by address and the free gaps between them are
+/// indexed by size, so that lookups, mappings and free area searches take
+/// logarithmic time in the number of areas.
 pub struct MemorySet<B: MemorySetBackend> {
     areas: BTreeMap<B::Addr, MemoryArea<B>>,
+    gaps: GapTree,
+    max_areas: usize,
+    generation: u64,
 }
 
 impl<B: MemorySetBackend> MemorySet<B> {
@@ -22,6 +29,9 @@ impl<B: MemorySetBackend> MemorySet<B> {
     pub const fn new() -> Self {
         Self {
             areas: BTreeMap::new(),
+            gaps: GapTree::new(),
+            max_areas: usize::MAX,
+            generation: 0,
         }
     }
 
@@ -30,6 +40,20 @@ impl<B: MemorySetBackend> MemorySet<B> {
         self.areas.len()
     }
 
+    /// Returns the maximum number of memory areas.
+    pub fn max_areas(&self) -> usize {
+        self.max_areas
+    }
+
+    /// Sets the maximum number of memory areas.
+    ///
+    /// Operations that would create areas beyond the limit fail with
+    /// [`MemorySetError::TooManyAreas`]. Existing areas are kept even if there
+    /// are more of them.
+    pub fn set_max_areas(&mut self, max_areas: usize) {
+        self.max_areas = max_areas;
+    }
+
     /// Returns `true` if the memory set contains no memory areas.
     pub fn is_empty(&self) -> bool {
         self.areas.is_empty()
@@ -40,6 +64,29 @@ impl<B: MemorySetBackend> MemorySet<B> {
         self.areas.values()
     }
 
+    /// Returns the iterator over the memory areas starting after `addr`, or
+    /// all of them if it is `None`, in address order.
+    ///
+    /// This lets a walk over the areas go on from where it stopped, even if
+    /// the memory set changed meanwhile.
+    pub fn iter_after(&self, addr: Option<B::Addr>) -> impl Iterator<Item = &MemoryArea<B>> {
+        self.areas
+            .range((
+                addr.map_or(Bound::Unbounded, Bound::Excluded),
+                Bound::Unbounded,
+            ))
+            .map(|(_, area)| area)
+    }
+
+    /// Returns a counter bumped by the operations changing the areas.
+    ///
+    /// If it is the same at two points in time, the areas did not change in
+    /// between. The converse does not hold: operations may bump it without
+    /// changing anything.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     /// Returns whether the given address range overlaps with any existing area.
     pub fn overlaps(&self, range: AddrRange<B::Addr>) -> bool {
         if let Some((_, before)) = self.areas.range(..range.start).last()
@@ -55,6 +102,15 @@ impl<B: MemorySetBackend> MemorySet<B> {
         false
     }
 
+    /// Returns the iterator over the memory areas that overlap with the given
+    /// address range, in address order.
+    pub fn iter_range(&self, range: AddrRange<B::Addr>) -> impl Iterator<Item = &MemoryArea<B>> {
+        let first = self.first_key_from(range.start);
+        self.areas
+            .range(first..range.end.max(first))
+            .map(|(_, area)| area)
+    }
+
     /// Finds the memory area that contains the given address.
     pub fn find(&self, addr: B::Addr) -> Option<&MemoryArea<B>> {
         let candidate = self.areas.range(..=addr).last().map(|(_, a)| a);
@@ -86,25 +142,19 @@ impl<B: MemorySetBackend> MemorySet<B> {
             // size must be a multiple of align.
             return None;
         }
-        // brute force: try each area's end address as the start.
-        let mut last_end: <B as MemorySetBackend>::Addr = hint.max(limit.start).align_up(align);
-        if let Some((_, area)) = self.areas.range(..last_end).last() {
-            last_end = last_end.max(area.end()).align_up(align);
-        }
-        for (&addr, area) in self.areas.range(last_end..) {
-            if last_end.checked_add(size).is_some_and(|end| end <= addr) {
-                return Some(last_end);
-            }
-            last_end = area.end().align_up(align);
-        }
-        if last_end
-            .checked_add(size)
-            .is_some_and(|end| end <= limit.end)
-        {
-            Some(last_end)
-        } else {
-            None
+        let from: usize = hint.max(limit.start).align_up(align).into();
+        let limit_end: usize = limit.end.into();
+        let fit = |gap_start: usize, gap_size: usize| {
+            let start: usize = B::Addr::from(gap_start.max(from)).align_up(align).into();
+            let end = start.checked_add(size)?;
+            (end <= gap_start + gap_size && end <= limit_end).then_some(start)
+        };
+        if self.areas.is_empty() {
+            return fit(0, usize::MAX).map(B::Addr::from);
         }
+        self.gaps
+            .find(from, limit_end, size, fit)
+            .map(B::Addr::from)
     }
 
     /// Add a new memory mapping.
@@ -125,6 +175,9 @@ impl<B: MemorySetBackend> MemorySet<B> {
             return Err(MemorySetError::InvalidParam);
         }
 
+        if self.areas.len() >= self.max_areas {
+            return Err(MemorySetError::TooManyAreas);
+        }
         if self.overlaps(area.va_range()) {
             if unmap_overlap {
                 self.unmap(area.start(), area.size(), page_table)?;
@@ -134,7 +187,91 @@ impl<B: MemorySetBackend> MemorySet<B> {
         }
 
         area.map_area(page_table)?;
+        let range = area.va_range();
+        assert!(self.areas.insert(area.start(), area).is_none());
+        self.update_gaps(range.start, range.end);
+        Ok(())
+    }
+
+    /// Inserts `area`, whose pages are already mapped.
+    ///
+    /// Unlike [`Self::map`], the backend is not asked to map it, and it must
+    /// not overlap any existing area.
+    pub fn insert(&mut self, area: MemoryArea<B>) -> MemorySetResult {
+        if area.va_range().is_empty() {
+            return Err(MemorySetError::InvalidParam);
+        }
+        if self.areas.len() >= self.max_areas {
+            return Err(MemorySetError::TooManyAreas);
+        }
+        if self.overlaps(area.va_range()) {
+            return Err(MemorySetError::AlreadyExists);
+        }
+        let range = area.va_range();
         assert!(self.areas.insert(area.start(), area).is_none());
+        self.update_gaps(range.start, range.end);
+        Ok(())
+    }
+
+    /// Removes the part of an area within the given address range, and
+    /// returns it, leaving its pages mapped.
+    ///
+    /// The range must lie in a single area, which is split around it.
+    pub fn take(&mut self, start: B::Addr, size: usize) -> MemorySetResult<MemoryArea<B>> {
+        let range =
+            AddrRange::try_from_start_size(start, size).ok_or(MemorySetError::InvalidParam)?;
+        let (&area_start, area) = self
+            .areas
+            .range(..=start)
+            .next_back()
+            .ok_or(MemorySetError::InvalidParam)?;
+        if range.is_empty() || area.end() < range.end {
+            return Err(MemorySetError::InvalidParam);
+        }
+        // The area is split in two if the range is in its middle.
+        if area_start < start && area.end() > range.end && self.areas.len() >= self.max_areas {
+            return Err(MemorySetError::TooManyAreas);
+        }
+
+        let mut taken = self.areas.remove(&area_start).unwrap();
+        if let Some(right) = taken.split(range.end) {
+            self.areas.insert(range.end, right);
+        }
+        if let Some(middle) = taken.split(start) {
+            self.areas.insert(area_start, taken);
+            taken = middle;
+        }
+        self.update_gaps(start, range.end);
+        Ok(taken)
+    }
+
+    /// Extends the area starting at `start` up to `new_end`, mapping the
+    /// part added.
+    ///
+    /// The part added must not overlap any existing area.
+    pub fn extend(
+        &mut self,
+        start: B::Addr,
+        new_end: B::Addr,
+        page_table: &mut B::PageTable,
+    ) -> MemorySetResult {
+        let area = self.areas.get(&start).ok_or(MemorySetError::InvalidParam)?;
+        let end = area.end();
+        if new_end <= end {
+            return Err(MemorySetError::InvalidParam);
+        }
+        if self.overlaps(AddrRange::new(end, new_end)) {
+            return Err(MemorySetError::AlreadyExists);
+        }
+        let area = self.areas.get_mut(&start).unwrap();
+        if !area
+            .backend()
+            .map(end, new_end.sub_addr(end), area.flags(), page_table)
+        {
+            return Err(MemorySetError::BadState);
+        }
+        area.set_end(new_end);
+        self.update_gaps(end, new_end);
         Ok(())
     }
 
@@ -158,15 +295,39 @@ impl<B: MemorySetBackend> MemorySet<B> {
 
         let end = range.end;
 
+        // Unmapping the middle of an area splits it in two.
+        if self.areas.len() >= self.max_areas
+            && self
+                .areas
+                .range(..start)
+                .next_back()
+                .is_some_and(|(_, before)| before.end() > end)
+        {
+            return Err(MemorySetError::TooManyAreas);
+        }
+
+        let result = self.unmap_range(start, end, page_table);
+        self.update_gaps(start, end);
+        result
+    }
+
+    fn unmap_range(
+        &mut self,
+        start: B::Addr,
+        end: B::Addr,
+        page_table: &mut B::PageTable,
+    ) -> MemorySetResult {
         // Unmap entire areas that are contained by the range.
-        self.areas.retain(|_, area| {
-            if area.va_range().contained_in(range) {
-                area.unmap_area(page_table).unwrap();
-                false
-            } else {
-                true
-            }
-        });
+        let contained: Vec<_> = self
+            .areas
+            .range(start..end)
+            .filter(|(_, area)| area.end() <= end)
+            .map(|(&area_start, _)| area_start)
+            .collect();
+        for area_start in contained {
+            let area = self.areas.remove(&area_start).unwrap();
+            area.unmap_area(page_table).unwrap();
+        }
 
         // Shrink right if the area intersects with the left boundary.
         if let Some((&before_start, before)) = self.areas.range_mut(..start).last() {
@@ -206,9 +367,40 @@ impl<B: MemorySetBackend> MemorySet<B> {
             area.unmap_area(page_table)?;
         }
         self.areas.clear();
+        self.gaps.clear();
+        self.generation += 1;
         Ok(())
     }
 
+    /// Removes at most `max` memory areas, starting from the lowest address,
+    /// and their underlying mappings.
+    ///
+    /// Returns the number of areas left. This lets callers tear down large
+    /// memory sets in batches, e.g. to reschedule in between.
+    pub fn clear_batch(
+        &mut self,
+        max: usize,
+        page_table: &mut B::PageTable,
+    ) -> MemorySetResult<usize> {
+        let Some((&start, _)) = self.areas.first_key_value() else {
+            return Ok(0);
+        };
+        let mut end = start;
+        let mut result = Ok(());
+        for _ in 0..max {
+            let Some((_, area)) = self.areas.pop_first() else {
+                break;
+            };
+            end = area.end();
+            result = area.unmap_area(page_table);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.update_gaps(start, end);
+        result.map(|_| self.areas.len())
+    }
+
     /// Change the flags of memory mappings within the given address range.
     ///
     /// `update_flags` is a function that receives old flags and processes
@@ -218,6 +410,9 @@ impl<B: MemorySetBackend> MemorySet<B> {
     /// Memory areas will be skipped according to `update_flags`. Memory areas
     /// that are fully contained in the range or contains the range or
     /// intersects with the boundary will be dispatch_irqd similarly to `munmap`.
+    ///
+    /// Afterwards, the areas in and around the range are merged where they
+    /// can be, see [`MemorySetBackend::can_merge`].
     pub fn protect(
         &mut self,
         start: B::Addr,
@@ -228,20 +423,32 @@ impl<B: MemorySetBackend> MemorySet<B> {
         let end = start
             .checked_add(size)
             .ok_or(MemorySetError::InvalidParam)?;
+        // Areas are only split if their flags change.
+        let first = self.first_key_from(start);
+        let mut splits = 0;
+        if let Some((&area_start, area)) = self.areas.range(first..end).next()
+            && area_start < start
+            && update_flags(area.flags()).is_some()
+        {
+            splits += 1;
+        }
+        if let Some((_, area)) = self.areas.range(first..end).next_back()
+            && area.end() > end
+            && update_flags(area.flags()).is_some()
+        {
+            splits += 1;
+        }
+        if splits > 0 && self.areas.len() + splits > self.max_areas {
+            return Err(MemorySetError::TooManyAreas);
+        }
+        self.generation += 1;
+
         let mut to_insert = Vec::new();
-        for (&area_start, area) in self.areas.iter_mut() {
+        for (&area_start, area) in self.areas.range_mut(first..end) {
             let area_end = area.end();
 
             if let Some(new_flags) = update_flags(area.flags()) {
-                if area_start >= end {
-                    // [ prot ]
-                    //          [ area ]
-                    break;
-                } else if area_end <= start {
-                    //          [ prot ]
-                    // [ area ]
-                    // Do nothing
-                } else if area_start >= start && area_end <= end {
+                if area_start >= start && area_end <= end {
                     // [   prot   ]
                     //   [ area ]
                     area.protect_area(new_flags, page_table)?;
@@ -279,8 +486,90 @@ impl<B: MemorySetBackend> MemorySet<B> {
             }
         }
         self.areas.extend(to_insert);
+        self.merge_range(start, end);
         Ok(())
     }
+
+    /// Merges the areas overlapping `start..end`, and those right next to it,
+    /// with the areas right after them where they have the same flags and
+    /// their backends allow it.
+    ///
+    /// The page table is not changed: merged areas map the same.
+    fn merge_range(&mut self, start: B::Addr, end: B::Addr) {
+        let mut cursor = match self.areas.range(..start).next_back() {
+            Some((&area_start, _)) => area_start,
+            None => start,
+        };
+        loop {
+            let mut areas = self.areas.range(cursor..);
+            let Some((&left_start, left)) = areas.next() else {
+                return;
+            };
+            let Some((&right_start, right)) = areas.next() else {
+                return;
+            };
+            if left_start >= end {
+                return;
+            }
+            if left.end() == right_start
+                && left.flags() == right.flags()
+                && left.backend().can_merge(right.backend())
+            {
+                let right = self.areas.remove(&right_start).unwrap();
+                let left = self.areas.get_mut(&left_start).unwrap();
+                left.set_end(right.end());
+            } else {
+                cursor = right_start;
+            }
+        }
+    }
+
+    /// Returns the start of the area containing `addr`, or `addr` itself if
+    /// there is none, which is where a search for areas from `addr` starts.
+    fn first_key_from(&self, addr: B::Addr) -> B::Addr {
+        match self.areas.range(..addr).next_back() {
+            Some((&area_start, area)) if area.end() > addr => area_start,
+            _ => addr,
+        }
+    }
+
+    /// Recomputes the free gaps around `start..end` after the areas there
+    /// changed.
+    fn update_gaps(&mut self, start: B::Addr, end: B::Addr) {
+        // Every change to the set of areas ends here, except for `protect`.
+        self.generation += 1;
+        if self.areas.is_empty() {
+            self.gaps.clear();
+            return;
+        }
+        // The affected gaps run from the end of the last area before `start`
+        // to the start of the first area at or after `end`.
+        let mut cursor = self
+            .areas
+            .range(..start)
+            .next_back()
+            .map_or(0, |(_, area)| area.end().into());
+        let until = self
+            .areas
+            .range(end..)
+            .next()
+            .map_or(usize::MAX, |(&area_start, _)| area_start.into());
+        self.gaps.remove_range(cursor.min(start.into()), until);
+
+        for (&area_start, area) in self.areas.range(start..) {
+            let area_start: usize = area_start.into();
+            if area_start > cursor {
+                self.gaps.insert(cursor, area_start - cursor);
+            }
+            cursor = area.end().into();
+            if area_start >= until {
+                return;
+            }
+        }
+        if cursor < usize::MAX {
+            self.gaps.insert(cursor, usize::MAX - cursor);
+        }
+    }
 }
 
 impl<B: MemorySetBackend> Default for MemorySet<B> {
@@ -3,13 +3,13 @@
 // See LICENSES for license details.
 
 //! Address space implementation backed by memory sets and page tables.
-use alloc::sync::Arc;
-use core::{fmt, ops::DerefMut};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{fmt, ops::DerefMut, sync::atomic::AtomicU32};
 
 use kerrno::{KError, KResult, k_bail};
 use khal::{
     mem::p2v,
-    paging::{MappingFlags, PageTable},
+    paging::{MappingFlags, PageSize, PageTable, PageTableMut, PagingError},
     trap::PageFaultFlags,
 };
 use ksync::Mutex;
@@ -18,13 +18,34 @@ use memaddr::{
 };
 use memset::{MemoryArea, MemorySet};
 
-use crate::backend::{Backend, BackendOps};
+use crate::{
+    backend::{Backend, BackendOps, cow, file::FileBackend, map_paging_err},
+    max_map_count, tlb,
+};
+
+/// Number of areas removed by [`AddrSpace::clear`] between reschedules.
+const CLEAR_BATCH: usize = 1024;
+
+/// Why a page fault could not be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// The address is not mapped, or not with the access rights.
+    Invalid,
+    /// The address is in a file mapping, past the end of the file.
+    BeyondEof,
+}
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pgtbl: PageTable,
+    /// Whether pages were marked idle, and may fault to get their accessed
+    /// bit set.
+    idle_tracked: bool,
+    /// Ranges of anonymous pages given up by `MADV_FREE`, which are freed
+    /// under memory pressure unless written again.
+    lazy_free: Vec<VirtAddrRange>,
 }
 
 impl AddrSpace {
@@ -69,6 +90,8 @@ impl AddrSpace {
             range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pgtbl: PageTable::try_new().map_err(|_| KError::NoMemory)?,
+            idle_tracked: false,
+            lazy_free: Vec::new(),
         })
     }
 
@@ -156,6 +179,7 @@ impl AddrSpace {
         backend: Backend,
     ) -> KResult {
         self.validate_region(start, size)?;
+        self.update_map_limit();
 
         let area = MemoryArea::new(start, size, flags, backend);
         self.areas.map(area, &mut self.pgtbl, false)?;
@@ -179,8 +203,15 @@ impl AddrSpace {
         let mut modify = self.pgtbl.modify();
         while let Some(area) = self.areas.find(start) {
             let range = VirtAddrRange::new(start, area.end().min(end));
-            area.backend()
-                .populate(range, area.flags(), access_flags, &mut modify)?;
+            match area
+                .backend()
+                .populate(range, area.flags(), access_flags, &mut modify)
+            {
+                // The rest of a file mapping past the end of file is left
+                // unpopulated, and raises `SIGBUS` when accessed.
+                Ok(_) | Err(KError::OutOfRange) => {}
+                Err(err) => return Err(err),
+            }
             start = area.end();
             assert!(start.is_aligned_4k());
             if start >= end {
@@ -202,11 +233,104 @@ impl AddrSpace {
     /// aligned.
     pub fn unmap(&mut self, start: VirtAddr, size: usize) -> KResult {
         self.validate_region(start, size)?;
+        self.update_map_limit();
 
         self.areas.unmap(start, size, &mut self.pgtbl)?;
+        self.forget_lazy_free(VirtAddrRange::from_start_size(start, size));
         Ok(())
     }
 
+    /// Returns the area holding all of `start..start + size`.
+    ///
+    /// Fails with [`KError::BadAddress`] if the range is not mapped, or spans
+    /// several areas.
+    fn area_holding(&self, start: VirtAddr, size: usize) -> KResult<&MemoryArea<Backend>> {
+        self.areas
+            .find(start)
+            .filter(|area| area.end() - start >= size)
+            .ok_or(KError::BadAddress)
+    }
+
+    /// Grows the mapping of `start..start + old_size` in place to `new_size`
+    /// bytes.
+    ///
+    /// The range must be at the end of an area, which is extended over the
+    /// free addresses after it. Fails with [`KError::NoMemory`] if they are
+    /// not free, and with [`KError::BadAddress`] if the area cannot grow.
+    pub fn grow(&mut self, start: VirtAddr, old_size: usize, new_size: usize) -> KResult {
+        self.validate_region(start, new_size)?;
+        let area = self.area_holding(start, old_size)?;
+        if let Backend::Shared(_) | Backend::Linear(_) = area.backend() {
+            // Their pages are fixed when they are mapped.
+            k_bail!(BadAddress, "mapping cannot grow");
+        }
+        if !(start + new_size).is_aligned(area.backend().page_size()) {
+            k_bail!(InvalidInput, "size is not aligned");
+        }
+        if area.end() != start + old_size {
+            k_bail!(NoMemory);
+        }
+        let area_start = area.start();
+        self.areas
+            .extend(area_start, start + new_size, &mut self.pgtbl)
+            .map_err(|_| KError::NoMemory)
+    }
+
+    /// Moves the mapping of `from..from + size` to `to`, keeping the pages
+    /// mapped.
+    ///
+    /// The range must lie in a single area, and `to..to + size` must be free.
+    pub fn move_range(&mut self, from: VirtAddr, size: usize, to: VirtAddr) -> KResult {
+        self.validate_region(to, size)?;
+        let area = self.area_holding(from, size)?;
+        let page_size = area.backend().page_size();
+        if !from.is_aligned(page_size) || !to.is_aligned(page_size) || !page_size.is_aligned(size) {
+            k_bail!(InvalidInput, "address is not aligned");
+        }
+        // The area is split around the range moved.
+        let splits = (area.start() < from) as usize + (area.end() > from + size) as usize;
+        let backend = area.backend().relocate(from, to)?;
+        self.update_map_limit();
+        if self.areas.len() + splits > self.areas.max_areas() {
+            k_bail!(NoMemory);
+        }
+        if self
+            .areas
+            .overlaps(VirtAddrRange::from_start_size(to, size))
+        {
+            k_bail!(AlreadyExists);
+        }
+
+        let area = self.areas.take(from, size)?;
+        self.forget_lazy_free(VirtAddrRange::from_start_size(from, size));
+        let moved = MemoryArea::new(to, size, area.flags(), backend);
+        let mut modify = self.pgtbl.modify();
+        let result = move_pages(&mut modify, from, to, size, page_size);
+        tlb::flush_all_cpus(&mut modify);
+        drop(modify);
+        if let Err(err) = result {
+            self.areas.insert(area)?;
+            return Err(err);
+        }
+        self.areas.insert(moved)?;
+        Ok(())
+    }
+
+    /// Maps `to..to + size` to the same pages as the shared mapping at
+    /// `from`.
+    ///
+    /// Fails with [`KError::InvalidInput`] if the mapping at `from` is
+    /// private.
+    pub fn duplicate(&mut self, from: VirtAddr, size: usize, to: VirtAddr) -> KResult {
+        let area = self.areas.find(from).ok_or(KError::BadAddress)?;
+        if let Backend::Cow(_) = area.backend() {
+            k_bail!(InvalidInput, "mapping is private");
+        }
+        let flags = area.flags();
+        let backend = area.backend().relocate(from, to)?;
+        self.map(to, size, flags, false, backend)
+    }
+
     /// To process data in this area with the given function.
     ///
     /// Now it supports reading and writing data in the given interval.
@@ -265,22 +389,87 @@ impl AddrSpace {
         })
     }
 
+    /// Returns the word at `addr`, for the kernel to access atomically while
+    /// user space may access it on other CPUs, as with futexes.
+    ///
+    /// The page is faulted in for writing first, so that a copy-on-write page
+    /// is copied. Fails with [`KError::InvalidInput`] if `addr` is not
+    /// aligned, or [`KError::BadAddress`] if it is not mapped writable.
+    pub fn atomic_u32(&mut self, addr: VirtAddr) -> KResult<&AtomicU32> {
+        if !addr.is_aligned(align_of::<u32>()) {
+            k_bail!(InvalidInput);
+        }
+        let writable = matches!(
+            self.pgtbl.query(addr),
+            Ok((_, flags, _)) if flags.contains(MappingFlags::WRITE)
+        );
+        if !writable {
+            self.handle_page_fault(addr, PageFaultFlags::WRITE)
+                .map_err(|_| KError::BadAddress)?;
+        }
+        let (paddr, ..) = self.pgtbl.query(addr).map_err(|_| KError::BadAddress)?;
+        // SAFETY: the page is mapped and stays so while `self` is borrowed,
+        // and the address is aligned.
+        Ok(unsafe { AtomicU32::from_ptr(p2v(paddr).as_mut_ptr_of()) })
+    }
+
     /// Updates mapping within the specified virtual address range.
     ///
+    /// Areas are split where the range starts or ends within them, and
+    /// merged with their neighbours again where they end up the same. Areas
+    /// keep whether they are [`MappingFlags::JIT`].
+    ///
     /// Returns an error if the address range is out of the address space or not
-    /// aligned.
+    /// aligned, or if a mapping does not allow the new flags.
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> KResult {
         self.validate_region(start, size)?;
+        self.update_map_limit();
 
-        self.areas
-            .protect(start, size, |_| Some(flags), &mut self.pgtbl)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        let mut modify = self.pgtbl.modify();
+        for area in self.areas.iter_range(range) {
+            let from = area.start().max(range.start);
+            let to = area.end().min(range.end);
+            let new_flags = flags | (area.flags() & MappingFlags::JIT);
+            area.backend()
+                .on_protect(VirtAddrRange::new(from, to), new_flags, &mut modify)?;
+        }
+        drop(modify);
+
+        self.areas.protect(
+            start,
+            size,
+            |old| Some(flags | (old & MappingFlags::JIT)),
+            &mut self.pgtbl,
+        )?;
 
         Ok(())
     }
 
     /// Removes all mappings in the address space.
+    ///
+    /// Large address spaces are torn down in batches, rescheduling in
+    /// between, so that one with many areas does not stall the CPU.
     pub fn clear(&mut self) {
-        self.areas.clear(&mut self.pgtbl).unwrap();
+        while self
+            .areas
+            .clear_batch(CLEAR_BATCH, &mut self.pgtbl)
+            .unwrap()
+            > 0
+        {
+            ktask::yield_now();
+        }
+        self.lazy_free.clear();
+    }
+
+    /// Returns the number of memory areas.
+    pub fn map_count(&self) -> usize {
+        self.areas.len()
+    }
+
+    /// Applies the current [`max_map_count`] to the memory areas.
+    fn update_map_limit(&mut self) {
+        self.areas.set_max_areas(max_map_count());
     }
 
     /// Checks whether an access to the specified memory region is valid.
@@ -296,10 +485,7 @@ impl AddrSpace {
         let Some(mut range) = VirtAddrRange::try_from_start_size(start, size) else {
             return false;
         };
-        for area in self.areas.iter() {
-            if area.end() <= range.start {
-                continue;
-            }
+        for area in self.areas.iter_range(range) {
             if area.start() > range.start {
                 return false;
             }
@@ -329,39 +515,336 @@ impl AddrSpace {
         vaddr: VirtAddr,
         access_flags: PageFaultFlags,
     ) -> bool {
+        self.handle_page_fault(vaddr, access_flags).is_ok()
+    }
+
+    /// Handles a page fault at the given address, telling why it could not if
+    /// it is a real fault.
+    pub fn handle_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        access_flags: PageFaultFlags,
+    ) -> Result<(), PageFaultError> {
         if !self.range.contains(vaddr) {
-            return false;
+            return Err(PageFaultError::Invalid);
         }
-        if let Some(area) = self.areas.find(vaddr) {
-            let flags = area.flags();
-            if flags.contains(access_flags) {
-                let page_size = area.backend().page_size();
-                let populate_result = area.backend().populate(
-                    VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _),
-                    flags,
-                    access_flags,
-                    &mut self.pgtbl.modify(),
-                );
-                return match populate_result {
-                    Ok((n, callback)) => {
-                        if let Some(cb) = callback {
-                            cb(self);
-                        }
-                        if n == 0 {
-                            warn!("No pages populated for {vaddr:?} ({flags:?})");
-                            false
-                        } else {
-                            true
-                        }
+        let Some(area) = self.areas.find(vaddr) else {
+            return Err(PageFaultError::Invalid);
+        };
+        let flags = area.flags();
+        if !flags.contains(access_flags) {
+            return Err(PageFaultError::Invalid);
+        }
+        // Where the hardware does not set accessed bits, an access to a page
+        // marked idle faults, and only needs the bit set.
+        if self.idle_tracked
+            && let Ok((false, _)) = self.pgtbl.modify().set_accessed(vaddr, true)
+        {
+            return Ok(());
+        }
+        let page_size = area.backend().page_size();
+        let populate_result = area.backend().populate(
+            VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _),
+            flags,
+            access_flags,
+            &mut self.pgtbl.modify(),
+        );
+        match populate_result {
+            Ok((n, callback)) => {
+                if let Some(cb) = callback {
+                    cb(self);
+                }
+                if n == 0 {
+                    warn!("No pages populated for {vaddr:?} ({flags:?})");
+                    Err(PageFaultError::Invalid)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(KError::OutOfRange) => Err(PageFaultError::BeyondEof),
+            Err(err) => {
+                warn!("Failed to populate pages for {vaddr:?} ({flags:?}): {err}");
+                Err(PageFaultError::Invalid)
+            }
+        }
+    }
+
+    /// Marks the pages mapped in `start..start + size` idle, clearing their
+    /// accessed bits.
+    ///
+    /// Huge pages partly in the range are marked whole.
+    pub fn mark_idle(&mut self, start: VirtAddr, size: usize) -> KResult {
+        self.scan_accessed(start, size, true, |_, _, _| {})
+    }
+
+    /// Returns which 4K pages of `start..start + size` are still idle, as a
+    /// bitmap with bit `i % 64` of word `i / 64` set if page `i` is mapped
+    /// and was not accessed since it was marked idle.
+    ///
+    /// The accessed bit is that of the mapping, so a page shared copy-on-write
+    /// is accessed through one process only if that process accessed it, and
+    /// the 4K pages of a huge page are all idle or not.
+    pub fn idle_pages(&mut self, start: VirtAddr, size: usize) -> KResult<Vec<u64>> {
+        let mut bitmap = vec![0u64; (size / PAGE_SIZE_4K).div_ceil(64)];
+        let end = start + size;
+        self.scan_accessed(start, size, false, |vaddr, page_size, accessed| {
+            if accessed {
+                return;
+            }
+            let first = (vaddr.max(start) - start) / PAGE_SIZE_4K;
+            let last = ((vaddr + page_size as usize).min(end) - start) / PAGE_SIZE_4K;
+            for page in first..last {
+                bitmap[page / 64] |= 1 << (page % 64);
+            }
+        })?;
+        Ok(bitmap)
+    }
+
+    /// Returns how many bytes of pages were accessed since they were marked
+    /// idle, and marks all pages idle again.
+    pub fn sample_accessed(&mut self) -> usize {
+        let mut accessed = 0;
+        self.scan_accessed(self.base(), self.size(), true, |_, page_size, hit| {
+            if hit {
+                accessed += page_size as usize;
+            }
+        })
+        .unwrap();
+        accessed
+    }
+
+    /// Calls `f` with the address, size and accessed bit of each page mapped
+    /// in `start..start + size`, clearing the bit if `clear` is set.
+    fn scan_accessed(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        clear: bool,
+        mut f: impl FnMut(VirtAddr, PageSize, bool),
+    ) -> KResult {
+        self.validate_region(start, size)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        self.idle_tracked |= clear;
+        let mut modify = self.pgtbl.modify();
+        for area in self.areas.iter_range(range) {
+            // Linear mappings are device or kernel memory, not a working set.
+            if let Backend::Linear(_) = area.backend() {
+                continue;
+            }
+            let from = area.start().max(range.start);
+            let to = area.end().min(range.end);
+            modify.scan_accessed(from, to - from, clear, &mut f);
+        }
+        if clear {
+            tlb::flush_all_cpus(&mut modify);
+        }
+        Ok(())
+    }
+
+    /// Drops the pages mapped in `start..start + size`, as `MADV_DONTNEED`
+    /// does.
+    ///
+    /// Anonymous pages read as zeroes afterwards, and pages of private file
+    /// mappings as the file holds them; shared mappings keep their data.
+    /// Addresses not mapped are skipped. Fails with [`KError::InvalidInput`]
+    /// if the range covers linear mappings, or only part of a huge page.
+    pub fn discard(&mut self, start: VirtAddr, size: usize) -> KResult {
+        self.validate_region(start, size)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        let mut modify = self.pgtbl.modify();
+        for area in self.areas.iter_range(range) {
+            let part = VirtAddrRange::new(area.start().max(start), area.end().min(range.end));
+            let page_size = area.backend().page_size();
+            if !part.start.is_aligned(page_size) || !part.end.is_aligned(page_size) {
+                k_bail!(InvalidInput, "range splits a huge page");
+            }
+            match area.backend() {
+                Backend::Linear(_) => k_bail!(InvalidInput, "linear mapping"),
+                // Their pages are fixed when they are mapped.
+                Backend::Shared(_) => {}
+                Backend::Cow(_) | Backend::File(_) => area.backend().unmap(part, &mut modify)?,
+            }
+        }
+        drop(modify);
+        self.forget_lazy_free(range);
+        Ok(())
+    }
+
+    /// Gives up the anonymous private pages mapped in `start..start + size`,
+    /// as `MADV_FREE` does.
+    ///
+    /// The pages keep their data until memory runs short, when those not
+    /// written since are freed by [`Self::reclaim_lazy_free`], and read as
+    /// zeroes afterwards. The pages are write-protected to tell whether they
+    /// are written. Fails with [`KError::InvalidInput`] if the range covers
+    /// other mappings.
+    pub(crate) fn mark_lazy_free(&mut self, start: VirtAddr, size: usize) -> KResult {
+        self.validate_region(start, size)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        let mut parts = Vec::new();
+        for area in self.areas.iter_range(range) {
+            let Backend::Cow(backend) = area.backend() else {
+                k_bail!(InvalidInput, "not an anonymous private mapping");
+            };
+            let part = VirtAddrRange::new(area.start().max(start), area.end().min(range.end));
+            let page_size = backend.page_size();
+            if !backend.is_anonymous()
+                || !part.start.is_aligned(page_size)
+                || !part.end.is_aligned(page_size)
+            {
+                k_bail!(InvalidInput, "not an anonymous private mapping");
+            }
+            if area.flags().contains(MappingFlags::WRITE) {
+                parts.push((part, page_size, area.flags() - MappingFlags::WRITE));
+            }
+        }
+
+        let mut modify = self.pgtbl.modify();
+        for &(part, page_size, flags) in &parts {
+            for vaddr in (part.start.as_usize()..part.end.as_usize()).step_by(page_size as _) {
+                match modify.protect(vaddr.into(), flags) {
+                    Ok(_) | Err(PagingError::NotMapped) => {}
+                    Err(err) => return Err(map_paging_err(err)),
+                }
+            }
+        }
+        tlb::flush_all_cpus(&mut modify);
+        drop(modify);
+        self.forget_lazy_free(range);
+        self.lazy_free
+            .extend(parts.into_iter().map(|(part, ..)| part));
+        Ok(())
+    }
+
+    /// Frees up to about `pages` 4K pages given up by `MADV_FREE` and not
+    /// written since, and returns how many it freed.
+    pub fn reclaim_lazy_free(&mut self, pages: usize) -> usize {
+        /// Frames unmapped between TLB flushes.
+        const BATCH: usize = 64;
+
+        let mut freed = 0;
+        let mut frames = [(PhysAddr::from(0), PageSize::Size4K); BATCH];
+        let mut count = 0;
+        let mut modify = self.pgtbl.modify();
+        while freed < pages
+            && let Some(range) = self.lazy_free.pop()
+        {
+            for area in self.areas.iter_range(range) {
+                let Backend::Cow(backend) = area.backend() else {
+                    continue;
+                };
+                let page_size = backend.page_size();
+                let part =
+                    VirtAddrRange::new(area.start().max(range.start), area.end().min(range.end));
+                for vaddr in (part.start.as_usize()..part.end.as_usize()).step_by(page_size as _) {
+                    // A page written since was made writable by the fault. A
+                    // page shared with another process is left to it.
+                    match modify.query(vaddr.into()) {
+                        Ok((frame, flags, _))
+                            if !flags.contains(MappingFlags::WRITE)
+                                && cow::try_is_exclusive(frame) => {}
+                        _ => continue,
                     }
-                    Err(err) => {
-                        warn!("Failed to populate pages for {vaddr:?} ({flags:?}): {err}");
-                        false
+                    let (frame, ..) = modify.unmap(vaddr.into()).unwrap();
+                    frames[count] = (frame, page_size);
+                    count += 1;
+                    freed += page_size as usize / PAGE_SIZE_4K;
+                    if count == BATCH {
+                        tlb::flush_all_cpus(&mut modify);
+                        for &(frame, size) in &frames[..count] {
+                            cow::release_frame(frame, size);
+                        }
+                        count = 0;
                     }
-                };
+                }
             }
         }
-        false
+        tlb::flush_all_cpus(&mut modify);
+        for &(frame, size) in &frames[..count] {
+            cow::release_frame(frame, size);
+        }
+        freed
+    }
+
+    /// Forgets the pages given up by `MADV_FREE` in `range`, as they are no
+    /// longer those mapped there.
+    fn forget_lazy_free(&mut self, range: VirtAddrRange) {
+        if self.lazy_free.is_empty() {
+            return;
+        }
+        let mut kept = Vec::with_capacity(self.lazy_free.len());
+        for part in self.lazy_free.drain(..) {
+            if !part.overlaps(range) {
+                kept.push(part);
+                continue;
+            }
+            if part.start < range.start {
+                kept.push(VirtAddrRange::new(part.start, range.start));
+            }
+            if part.end > range.end {
+                kept.push(VirtAddrRange::new(range.end, part.end));
+            }
+        }
+        self.lazy_free = kept;
+    }
+
+    /// Reads ahead the pages of the file mappings in `start..start + size`,
+    /// as `MADV_WILLNEED` does.
+    ///
+    /// Anonymous memory is left to be allocated on first access.
+    pub fn will_need(&mut self, start: VirtAddr, size: usize) -> KResult {
+        self.validate_region(start, size)?;
+        let range = VirtAddrRange::from_start_size(start, size);
+        let mut modify = self.pgtbl.modify();
+        for area in self.areas.iter_range(range) {
+            let file_backed = match area.backend() {
+                Backend::Cow(cow) => !cow.is_anonymous(),
+                Backend::File(_) => true,
+                _ => false,
+            };
+            if !file_backed || !area.flags().contains(MappingFlags::READ) {
+                continue;
+            }
+            let page_size = area.backend().page_size();
+            let part = VirtAddrRange::new(
+                area.start().max(start.align_down(page_size)),
+                area.end().min(range.end.align_up(page_size)),
+            );
+            match area
+                .backend()
+                .populate(part, area.flags(), MappingFlags::READ, &mut modify)
+            {
+                // The pages past the end of file are left unpopulated.
+                Ok(_) | Err(KError::OutOfRange) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the pages mapped in the address space.
+    pub fn resident_size(&mut self) -> usize {
+        let mut size = 0;
+        self.scan_accessed(self.base(), self.size(), false, |_, page_size, _| {
+            size += page_size as usize;
+        })
+        .unwrap();
+        size
+    }
+
+    /// Returns the file mappings overlapping `range`, with the part of
+    /// `range` each covers.
+    pub fn file_mappings(&self, range: VirtAddrRange) -> Vec<(FileBackend, VirtAddrRange)> {
+        self.areas
+            .iter_range(range)
+            .filter_map(|area| match area.backend() {
+                Backend::File(file) => Some((
+                    file.clone(),
+                    VirtAddrRange::new(area.start().max(range.start), area.end().min(range.end)),
+                )),
+                _ => None,
+            })
+            .collect()
     }
 
     /// Attempts to clone the current address space into a new one.
@@ -401,6 +884,66 @@ impl AddrSpace {
     pub fn areas(&self) -> impl Iterator<Item = &memset::MemoryArea<Backend>> {
         self.areas.iter()
     }
+
+    /// Returns an iterator over the memory areas starting after `addr`, or
+    /// all of them if it is `None`.
+    ///
+    /// Together with [`AddrSpace::areas_generation`], this lets
+    /// `/proc/pid/maps` be read in parts, without holding the address space
+    /// locked in between.
+    pub fn areas_after(
+        &self,
+        addr: Option<VirtAddr>,
+    ) -> impl Iterator<Item = &memset::MemoryArea<Backend>> {
+        self.areas.iter_after(addr)
+    }
+
+    /// Returns a counter bumped whenever the memory areas change.
+    pub fn areas_generation(&self) -> u64 {
+        self.areas.generation()
+    }
+}
+
+/// Moves the pages of `page_size` bytes mapped in `from..from + size` to
+/// `to`, keeping their flags.
+///
+/// If a page cannot be mapped at `to`, those moved already are moved back.
+fn move_pages(
+    pgtbl: &mut PageTableMut,
+    from: VirtAddr,
+    to: VirtAddr,
+    size: usize,
+    page_size: PageSize,
+) -> KResult {
+    let mut moved = 0;
+    let mut result = Ok(());
+    while moved < size {
+        match pgtbl.unmap(from + moved) {
+            Ok((paddr, flags, _)) => {
+                if let Err(err) = pgtbl.map(to + moved, paddr, page_size, flags) {
+                    // The page table entry just cleared is still there.
+                    pgtbl.map(from + moved, paddr, page_size, flags).unwrap();
+                    result = Err(map_paging_err(err));
+                    break;
+                }
+            }
+            Err(PagingError::NotMapped) => {}
+            Err(err) => {
+                result = Err(map_paging_err(err));
+                break;
+            }
+        }
+        moved += page_size as usize;
+    }
+    if result.is_err() {
+        // The page tables at `from` are all there still.
+        for offset in (0..moved).step_by(page_size as usize) {
+            if let Ok((paddr, flags, _)) = pgtbl.unmap(to + offset) {
+                pgtbl.map(from + offset, paddr, page_size, flags).unwrap();
+            }
+        }
+    }
+    result
 }
 
 impl fmt::Debug for AddrSpace {
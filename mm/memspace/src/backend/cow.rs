@@ -3,9 +3,10 @@
 // See LICENSES for license details.
 
 //! Copy-on-write mapping backend.
-use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
 use core::slice;
 
+use fs_ng_vfs::Location;
 use kerrno::{KError, KResult};
 use kfs::FileBackend;
 use khal::{
@@ -18,7 +19,10 @@ use memaddr::{PhysAddr, VirtAddr, VirtAddrRange};
 
 use crate::{
     aspace::AddrSpace,
-    backend::{Backend, BackendOps, alloc_frame, dealloc_frame, pages_in},
+    backend::{
+        Backend, BackendOps, alloc_frame, dealloc_frame, dealloc_frames, pages_in, shift,
+        unmap_batched,
+    },
 };
 
 struct FrameRefCnt(u8);
@@ -74,6 +78,51 @@ impl FrameTableRefCount {
 
 static FRAME_TABLE: SpinNoIrq<FrameTableRefCount> = SpinNoIrq::new(FrameTableRefCount::new());
 
+/// Drops a reference to each of `frames`, freeing together those no longer
+/// referenced.
+///
+/// Takes the frame table lock twice for the whole batch instead of once or
+/// twice per frame.
+fn drop_frames(frames: &[PhysAddr], pgsize: PageSize) -> KResult {
+    let mut table = FRAME_TABLE.lock();
+    let refs: Vec<_> = frames
+        .iter()
+        .map(|&frame| (frame, table.get_frame_ref(frame)))
+        .collect();
+    drop(table);
+
+    let mut missing = false;
+    let mut unused = Vec::new();
+    for (frame, frame_ref) in refs {
+        let Some(frame_ref) = frame_ref else {
+            missing = true;
+            continue;
+        };
+        let mut frame_ref = frame_ref.lock();
+        assert!(frame_ref.0 > 0, "dropping unreferenced frame");
+        frame_ref.0 -= 1;
+        if frame_ref.0 == 0 {
+            unused.push(frame);
+        }
+    }
+
+    if !unused.is_empty() {
+        // As in `FrameRefCnt::drop_frame`, frames leave the table before
+        // they can be allocated again.
+        let mut table = FRAME_TABLE.lock();
+        for &frame in &unused {
+            table.remove_frame(frame);
+        }
+        drop(table);
+        dealloc_frames(&unused, pgsize);
+    }
+
+    if missing {
+        return Err(KError::BadAddress);
+    }
+    Ok(())
+}
+
 /// Copy-on-write mapping backend.
 ///
 /// This corresponds to the `MAP_PRIVATE` flag.
@@ -84,7 +133,47 @@ pub struct CowBackend {
     file: Option<(FileBackend, u64, Option<u64>)>,
 }
 
+/// Returns whether `frame` is mapped only once, without waiting for a lock.
+///
+/// The frame is reported shared if a lock is contended.
+pub(crate) fn try_is_exclusive(frame: PhysAddr) -> bool {
+    let Some(frame_ref) = FRAME_TABLE
+        .try_lock()
+        .and_then(|mut table| table.get_frame_ref(frame))
+    else {
+        return false;
+    };
+    frame_ref
+        .try_lock()
+        .is_some_and(|frame_ref| frame_ref.0 == 1)
+}
+
+/// Drops the reference to `frame` of a page unmapped, freeing it if it was
+/// the last one.
+///
+/// Unlike [`drop_frames`], this allocates no memory.
+pub(crate) fn release_frame(frame: PhysAddr, pgsize: PageSize) {
+    let frame_ref = FRAME_TABLE.lock().get_frame_ref(frame);
+    match frame_ref {
+        Some(frame_ref) => frame_ref.lock().drop_frame(frame, pgsize),
+        None => warn!("releasing unreferenced frame {frame:?}"),
+    }
+}
+
 impl CowBackend {
+    /// Returns whether the mapping is anonymous, not of a file.
+    pub fn is_anonymous(&self) -> bool {
+        self.file.is_none()
+    }
+
+    /// Returns the mapped file and the offset in it of the page at `va`, or
+    /// `None` if the mapping is anonymous.
+    pub fn mapped_file(&self, va: VirtAddr) -> Option<(&Location, u64)> {
+        let (file, file_start, _) = self.file.as_ref()?;
+        let offset = va.as_usize().saturating_sub(self.start.as_usize()) as u64;
+        Some((file.location(), file_start + offset))
+    }
+
     fn alloc_new_frame(&self, zeroed: bool) -> KResult<PhysAddr> {
         let frame = alloc_frame(zeroed, self.size)?;
         FRAME_TABLE.lock().init_frame(frame);
@@ -166,20 +255,10 @@ impl BackendOps for CowBackend {
 
     fn unmap(&self, range: VirtAddrRange, pgtbl: &mut PageTableMut) -> KResult {
         debug!("Cow::unmap: {range:?}");
-        for addr in pages_in(range, self.size)? {
-            if let Ok((frame, _flags, page_size)) = pgtbl.unmap(addr) {
-                assert_eq!(page_size, self.size);
-                let frame_ref = FRAME_TABLE
-                    .lock()
-                    .get_frame_ref(frame)
-                    .ok_or(KError::BadAddress)?;
-                let mut frame_ref = frame_ref.lock();
-                frame_ref.drop_frame(frame, self.size);
-            } else {
-                // Deallocation is needn't if the page is not allocated.
-            }
-        }
-        Ok(())
+        // Pages never populated are not mapped and have nothing to free.
+        unmap_batched(range, self.size, pgtbl, |frames| {
+            drop_frames(frames, self.size)
+        })
     }
 
     fn populate(
@@ -210,10 +289,34 @@ impl BackendOps for CowBackend {
                 }
                 Err(_) => return Err(KError::BadAddress),
             }
+            ktask::cond_resched();
         }
         Ok((pages, None))
     }
 
+    fn relocate(&self, from: VirtAddr, to: VirtAddr) -> KResult<Backend> {
+        Ok(Backend::Cow(CowBackend {
+            start: shift(self.start, from, to),
+            ..self.clone()
+        }))
+    }
+
+    fn can_merge(&self, next: &Backend) -> bool {
+        let Backend::Cow(next) = next else {
+            return false;
+        };
+        // Anonymous pages are the same wherever the mapping starts; file
+        // pages are read from where it was first mapped.
+        next.size == self.size
+            && match (&self.file, &next.file) {
+                (None, None) => true,
+                (Some((_, start, end)), Some((_, next_start, next_end))) => {
+                    next.start == self.start && next_start == start && next_end == end
+                }
+                _ => false,
+            }
+    }
+
     fn clone_map(
         &self,
         range: VirtAddrRange,
@@ -10,15 +10,16 @@ use alloc::{
 };
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use fs_ng_vfs::Location;
 use kerrno::{KError, KResult};
-use kfs::{CachedFile, FileFlags};
+use kfs::{CachedFile, FileFlags, PageEvent};
 use khal::paging::{MappingFlags, PageSize, PageTableMut, PagingError};
 use ksync::Mutex;
 use memaddr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 
 use crate::{
     aspace::AddrSpace,
-    backend::{Backend, BackendOps, map_paging_err, pages_in},
+    backend::{Backend, BackendOps, map_paging_err, pages_in, shift, unmap_batched},
 };
 
 #[doc(hidden)]
@@ -27,8 +28,8 @@ pub struct FileBackendInner {
     cache: CachedFile,
     flags: FileFlags,
     offset_page: u32,
+    aspace: Weak<Mutex<AddrSpace>>,
     dispatch_irq: AtomicUsize,
-    futex_dispatch_irq: Arc<()>,
 }
 impl Drop for FileBackendInner {
     fn drop(&mut self) {
@@ -41,40 +42,55 @@ impl Drop for FileBackendInner {
     }
 }
 impl FileBackendInner {
-    pub fn register_listener(self: &Arc<Self>, aspace: &Arc<Mutex<AddrSpace>>) -> usize {
-        let aspace = Arc::downgrade(aspace);
-        self.cache.add_evict_listener({
+    pub fn register_listener(self: &Arc<Self>) -> usize {
+        let aspace = self.aspace.clone();
+        let dispatch_irq = self.cache.add_evict_listener({
             let this = Arc::downgrade(self);
-            move |pn, _page| {
+            move |pn, event| {
                 let Some(this) = this.upgrade() else {
-                    return;
+                    return true;
                 };
                 let Some(aspace) = aspace.upgrade() else {
                     // The address space has been dropped, nothing to do.
-                    return;
+                    return true;
                 };
                 let Some(mut aspace) = aspace.try_lock() else {
                     // This can happen during the populate process, when new pages
                     // are being populated and old pages are being evicted. In this
                     // case, we delegate the unmapping to the populate process.
-                    return;
+                    // A page being cleaned stays dirty instead, as it may still
+                    // be mapped writable.
+                    return event == PageEvent::Evicted;
                 };
-                this.on_evict(pn, &mut aspace);
+                match event {
+                    PageEvent::Evicted => this.on_evict(pn, &mut aspace),
+                    PageEvent::Cleaned => this.on_clean(pn, &mut aspace),
+                }
+                true
             }
-        })
+        });
+        self.dispatch_irq.store(dispatch_irq, Ordering::Release);
+        dispatch_irq
     }
 
-    fn on_evict(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
-        let Some(pn) = pn.checked_sub(self.offset_page) else {
-            return;
-        };
+    /// Returns the address page `pn` of the file is mapped at, if this
+    /// mapping still controls it.
+    fn page_vaddr(self: &Arc<Self>, pn: u32, aspace: &AddrSpace) -> Option<VirtAddr> {
+        let pn = pn.checked_sub(self.offset_page)?;
         let vaddr = self.start + pn as usize * PageSize::Size4K as usize;
-        if !aspace.find_area(vaddr).is_some_and(
-            |it| matches!(it.backend(), Backend::File(file) if Arc::ptr_eq(&file.0, self)),
-        ) {
+        aspace
+            .find_area(vaddr)
+            .is_some_and(
+                |it| matches!(it.backend(), Backend::File(file) if Arc::ptr_eq(&file.0, self)),
+            )
+            .then_some(vaddr)
+    }
+
+    fn on_evict(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
+        let Some(vaddr) = self.page_vaddr(pn, aspace) else {
             // Ignore if the page is not controlled by this file mapping.
             return;
-        }
+        };
 
         let pt = aspace.page_table_mut();
         match pt.modify().unmap(vaddr) {
@@ -84,6 +100,23 @@ impl FileBackendInner {
             }
         }
     }
+
+    /// Write-protects page `pn` before it is written back, so that the next
+    /// store to it faults and marks it dirty again.
+    fn on_clean(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
+        let Some(vaddr) = self.page_vaddr(pn, aspace) else {
+            return;
+        };
+        let pt = aspace.page_table_mut();
+        let Ok((_, flags, _)) = pt.query(vaddr) else {
+            return;
+        };
+        if flags.contains(MappingFlags::WRITE)
+            && let Err(err) = pt.modify().protect(vaddr, flags - MappingFlags::WRITE)
+        {
+            warn!("Failed to write-protect page {:?}: {:?}", vaddr, err);
+        }
+    }
 }
 
 /// File-backed mapping backend.
@@ -105,9 +138,19 @@ impl FileBackend {
         Ok(())
     }
 
-    /// Returns a weak handle used to dispatch futex-related events.
-    pub fn futex_dispatch_irq(&self) -> Weak<()> {
-        Arc::downgrade(&self.0.futex_dispatch_irq)
+    /// Writes back the dirty pages of the file mapped in `range`.
+    pub fn sync(&self, range: VirtAddrRange) -> KResult {
+        let offset = self.0.offset_page as u64 * PAGE_SIZE_4K as u64;
+        let start = offset + (range.start - self.0.start) as u64;
+        let end = offset + (range.end - self.0.start) as u64;
+        self.0.cache.sync_range(start..end)?;
+        Ok(())
+    }
+
+    /// Returns the mapped file and the offset in it of the page at `va`.
+    pub fn mapped_file(&self, va: VirtAddr) -> (&Location, u64) {
+        let offset = self.0.offset_page as u64 * PAGE_SIZE_4K as u64;
+        (self.0.cache.location(), offset + (va - self.0.start) as u64)
     }
 }
 
@@ -121,16 +164,8 @@ impl BackendOps for FileBackend {
     }
 
     fn unmap(&self, range: VirtAddrRange, pt: &mut PageTableMut) -> KResult {
-        for addr in pages_in(range, PageSize::Size4K)? {
-            match pt.unmap(addr) {
-                Ok(_) | Err(PagingError::NotMapped) => {}
-                Err(err) => {
-                    warn!("Failed to unmap page {:?}: {:?}", addr, err);
-                    return Err(map_paging_err(err));
-                }
-            }
-        }
-        Ok(())
+        // The frames belong to the page cache.
+        unmap_batched(range, PageSize::Size4K, pt, |_| Ok(()))
     }
 
     fn on_protect(
@@ -174,6 +209,16 @@ impl BackendOps for FileBackend {
                 }
                 // If the page is not mapped, try map it.
                 Err(PagingError::NotMapped) => {
+                    // Pages wholly past the end of file cannot be accessed.
+                    // The caller tells apart a fault on the first page from
+                    // a range populated up to the end of file.
+                    let len = self.0.cache.location().len()?;
+                    if pn as u64 * PAGE_SIZE_4K as u64 >= len {
+                        if pages == 0 {
+                            return Err(KError::OutOfRange);
+                        }
+                        break;
+                    }
                     let map_flags = if self.0.cache.in_memory() {
                         // For in memory files, we don't need to (and also
                         // musn't) mark them dirty, so we can use the original
@@ -211,6 +256,23 @@ impl BackendOps for FileBackend {
         ))
     }
 
+    fn relocate(&self, from: VirtAddr, to: VirtAddr) -> KResult<Backend> {
+        let inner = Arc::new(FileBackendInner {
+            start: shift(self.0.start, from, to),
+            cache: self.0.cache.clone(),
+            flags: self.0.flags,
+            offset_page: self.0.offset_page,
+            aspace: self.0.aspace.clone(),
+            dispatch_irq: AtomicUsize::new(0),
+        });
+        inner.register_listener();
+        Ok(Backend::File(FileBackend(inner)))
+    }
+
+    fn can_merge(&self, next: &Backend) -> bool {
+        matches!(next, Backend::File(next) if Arc::ptr_eq(&next.0, &self.0))
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
@@ -224,10 +286,10 @@ impl BackendOps for FileBackend {
             cache: self.0.cache.clone(),
             flags: self.0.flags,
             offset_page: self.0.offset_page,
+            aspace: Arc::downgrade(new_aspace),
             dispatch_irq: AtomicUsize::new(0),
-            futex_dispatch_irq: self.0.futex_dispatch_irq.clone(),
         });
-        inner.register_listener(new_aspace);
+        inner.register_listener();
         Ok(Backend::File(FileBackend(inner)))
     }
 }
@@ -246,10 +308,10 @@ impl Backend {
             cache,
             flags,
             offset_page,
+            aspace: Arc::downgrade(aspace),
             dispatch_irq: AtomicUsize::new(0),
-            futex_dispatch_irq: Arc::new(()),
         });
-        inner.register_listener(aspace);
+        inner.register_listener();
         Self::File(FileBackend(inner))
     }
 }
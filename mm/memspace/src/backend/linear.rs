@@ -54,6 +54,16 @@ impl BackendOps for LinearBackend {
         Ok(())
     }
 
+    fn relocate(&self, from: VirtAddr, to: VirtAddr) -> KResult<Backend> {
+        Ok(Backend::new_linear(
+            self.offset + to.as_usize().wrapping_sub(from.as_usize()) as isize,
+        ))
+    }
+
+    fn can_merge(&self, next: &Backend) -> bool {
+        matches!(next, Backend::Linear(next) if next.offset == self.offset)
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
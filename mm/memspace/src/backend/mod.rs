@@ -3,9 +3,10 @@
 // See LICENSES for license details.
 
 //! Memory mapping backends.
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 
 use enum_dispatch::enum_dispatch;
+use fs_ng_vfs::Location;
 use kalloc::{UsageKind, global_allocator};
 use kerrno::{KError, KResult};
 use khal::{
@@ -23,7 +24,10 @@ pub mod shared;
 
 pub use shared::SharedPages;
 
-use crate::aspace::AddrSpace;
+use crate::{aspace::AddrSpace, tlb};
+
+/// Number of pages unmapped between TLB flushes by [`unmap_batched`].
+const UNMAP_BATCH: usize = 512;
 
 fn divide_page(size: usize, pgsize: PageSize) -> usize {
     assert!(pgsize.is_aligned(size), "unaligned");
@@ -53,6 +57,53 @@ fn dealloc_frame(frame: PhysAddr, align: PageSize) {
     global_allocator().dealloc_pages(vaddr.as_usize(), num_pages, UsageKind::VirtMem);
 }
 
+/// Frees frames of `align` bytes each, in one go.
+fn dealloc_frames(frames: &[PhysAddr], align: PageSize) {
+    let vaddrs: Vec<usize> = frames.iter().map(|&frame| p2v(frame).as_usize()).collect();
+    let num_pages = align as usize / PAGE_SIZE_4K;
+    global_allocator().dealloc_pages_bulk(&vaddrs, num_pages, UsageKind::VirtMem);
+}
+
+/// Unmaps the pages of `align` bytes mapped in `range`, [`UNMAP_BATCH`] at a
+/// time.
+///
+/// After each batch, the TLB is flushed on every CPU and `release` is called
+/// with the frames unmapped, which no CPU can reach any more. Page tables
+/// that were never populated are skipped whole, and the CPU is yielded
+/// between batches so that tearing down a huge range does not monopolize it.
+fn unmap_batched(
+    range: VirtAddrRange,
+    align: PageSize,
+    pgtbl: &mut PageTableMut,
+    mut release: impl FnMut(&[PhysAddr]) -> KResult,
+) -> KResult {
+    let mut frames = Vec::with_capacity(UNMAP_BATCH);
+    let mut start = range.start;
+    while start < range.end {
+        start = pgtbl.unmap_range(start, range.end - start, UNMAP_BATCH, |_, frame, size| {
+            assert_eq!(size, align);
+            frames.push(frame);
+        });
+        tlb::flush_all_cpus(pgtbl);
+        release(&frames)?;
+        frames.clear();
+        if start < range.end {
+            ktask::cond_resched();
+        }
+    }
+    Ok(())
+}
+
+/// Returns `start` moved by the distance from `from` to `to`.
+fn shift(start: VirtAddr, from: VirtAddr, to: VirtAddr) -> VirtAddr {
+    VirtAddr::from(
+        start
+            .as_usize()
+            .wrapping_add(to.as_usize())
+            .wrapping_sub(from.as_usize()),
+    )
+}
+
 fn pages_in(range: VirtAddrRange, align: PageSize) -> KResult<DynPageIter<VirtAddr>> {
     DynPageIter::new(range.start, range.end, align as usize).ok_or(KError::InvalidInput)
 }
@@ -100,6 +151,16 @@ pub trait BackendOps {
         Ok((0, None))
     }
 
+    /// Returns the backend mapping at `to` what this one maps at `from`, for
+    /// a mapping moved or duplicated within the same address space.
+    fn relocate(&self, from: VirtAddr, to: VirtAddr) -> KResult<Backend>;
+
+    /// Returns whether a mapping by this backend may be merged with the
+    /// mapping right after it, by `next`, mapping the same pages.
+    ///
+    /// This holds for the parts of a split mapping.
+    fn can_merge(&self, next: &Backend) -> bool;
+
     /// Duplicates this mapping for use in a different page table.
     ///
     /// This differs from `clone`, which is designed for splitting a mapping
@@ -129,6 +190,24 @@ pub enum Backend {
     File(file::FileBackend),
 }
 
+impl Backend {
+    /// Returns the file mapped at `va` and the offset of `va` in it, or
+    /// `None` if the memory is not of a file.
+    pub fn mapped_file(&self, va: VirtAddr) -> Option<(&Location, u64)> {
+        match self {
+            Self::Cow(cow) => cow.mapped_file(va),
+            Self::File(file) => Some(file.mapped_file(va)),
+            Self::Linear(_) | Self::Shared(_) => None,
+        }
+    }
+
+    /// Returns whether writes to the memory are seen by other mappings of it,
+    /// as with `MAP_SHARED`.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, Self::Shared(_) | Self::File(_))
+    }
+}
+
 impl MemorySetBackend for Backend {
     type Addr = VirtAddr;
     type Flags = MappingFlags;
@@ -167,9 +246,15 @@ impl MemorySetBackend for Backend {
         new_flags: Self::Flags,
         pgtbl: &mut Self::PageTable,
     ) -> bool {
-        pgtbl
-            .modify()
-            .protect_region(start, size, new_flags)
-            .is_ok()
+        let mut modify = pgtbl.modify();
+        let result = modify.protect_region(start, size, new_flags);
+        // Other CPUs running the address space may still write through the
+        // old entries.
+        tlb::flush_all_cpus(&mut modify);
+        result.is_ok()
+    }
+
+    fn can_merge(&self, next: &Self) -> bool {
+        BackendOps::can_merge(self, next)
     }
 }
@@ -4,7 +4,7 @@
 
 //! Shared mapping backend.
 use alloc::{sync::Arc, vec::Vec};
-use core::ops::Deref;
+use core::{any::Any, ops::Deref};
 
 use kerrno::KResult;
 use khal::paging::{MappingFlags, PageSize, PageTableMut};
@@ -14,13 +14,15 @@ use memaddr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange};
 use super::{alloc_frame, dealloc_frame};
 use crate::{
     aspace::AddrSpace,
-    backend::{Backend, BackendOps, divide_page, map_paging_err, pages_in},
+    backend::{Backend, BackendOps, divide_page, map_paging_err, pages_in, shift},
 };
 
 /// Shared physical pages backing a mapping.
 pub struct SharedPages {
     pub phys_pages: Vec<PhysAddr>,
     pub size: PageSize,
+    /// What owns the pages if they were not allocated here.
+    owner: Option<Arc<dyn Any + Send + Sync>>,
 }
 impl SharedPages {
     /// Allocate a new set of shared pages.
@@ -30,9 +32,20 @@ impl SharedPages {
                 .map(|_| alloc_frame(true, pgsize))
                 .collect::<KResult<_>>()?,
             size: pgsize,
+            owner: None,
         })
     }
 
+    /// Shares 4K pages owned by `owner`, which is kept alive as long as they
+    /// are, instead of freeing them.
+    pub fn from_owned(phys_pages: Vec<PhysAddr>, owner: Arc<dyn Any + Send + Sync>) -> Self {
+        Self {
+            phys_pages,
+            size: PageSize::Size4K,
+            owner: Some(owner),
+        }
+    }
+
     /// Return the number of pages.
     pub fn len(&self) -> usize {
         self.phys_pages.len()
@@ -54,6 +67,9 @@ impl Deref for SharedPages {
 
 impl Drop for SharedPages {
     fn drop(&mut self) {
+        if self.owner.is_some() {
+            return;
+        }
         for frame in &self.phys_pages {
             dealloc_frame(*frame, self.size);
         }
@@ -104,6 +120,21 @@ impl BackendOps for SharedBackend {
         Ok(())
     }
 
+    fn relocate(&self, from: VirtAddr, to: VirtAddr) -> KResult<Backend> {
+        Ok(Backend::new_shared(
+            shift(self.start, from, to),
+            self.pages.clone(),
+        ))
+    }
+
+    fn can_merge(&self, next: &Backend) -> bool {
+        matches!(
+            next,
+            Backend::Shared(next)
+                if next.start == self.start && Arc::ptr_eq(&next.pages, &self.pages)
+        )
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Pages given up by `MADV_FREE`, freed on memory pressure.
+//!
+//! The address spaces with such pages are registered, and the page allocator
+//! calls [`reclaim`] when it runs out of memory. As it may do so with any
+//! lock held, address spaces busy are skipped.
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use kerrno::KResult;
+use kspin::SpinNoIrq;
+use ksync::Mutex;
+use memaddr::VirtAddr;
+
+use crate::AddrSpace;
+
+static LAZY_FREE: SpinNoIrq<Vec<Weak<Mutex<AddrSpace>>>> = SpinNoIrq::new(Vec::new());
+
+/// Gives up the anonymous private pages mapped in `start..start + size` of
+/// `aspace`, to be freed on memory pressure unless written again.
+///
+/// Fails with [`kerrno::KError::InvalidInput`] if the range covers other
+/// mappings.
+pub fn free_lazily(aspace: &Arc<Mutex<AddrSpace>>, start: VirtAddr, size: usize) -> KResult {
+    aspace.lock().mark_lazy_free(start, size)?;
+    let mut spaces = LAZY_FREE.lock();
+    if !spaces.iter().any(|it| it.as_ptr() == Arc::as_ptr(aspace)) {
+        spaces.push(Arc::downgrade(aspace));
+    }
+    Ok(())
+}
+
+/// Frees up to about `pages` pages given up by `MADV_FREE`, and returns how
+/// many it freed.
+pub(crate) fn reclaim(pages: usize) -> usize {
+    let Some(mut spaces) = LAZY_FREE.try_lock() else {
+        return 0;
+    };
+    let mut freed = 0;
+    spaces.retain(|aspace| {
+        let Some(aspace) = aspace.upgrade() else {
+            return false;
+        };
+        if freed < pages
+            && let Some(mut aspace) = aspace.try_lock()
+        {
+            freed += aspace.reclaim_lazy_free(pages - freed);
+        }
+        true
+    });
+    freed
+}
@@ -12,6 +12,11 @@ extern crate alloc;
 
 mod aspace;
 pub mod backend;
+mod lazyfree;
+mod tlb;
+mod wss;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use kerrno::LinuxResult;
 use khal::{
@@ -22,10 +27,33 @@ use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use memaddr::{MemoryAddr, PhysAddr, va};
 
-pub use self::aspace::AddrSpace;
+pub use self::{
+    aspace::{AddrSpace, PageFaultError},
+    lazyfree::free_lazily,
+    wss::WssEstimator,
+};
 
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
+/// Default of [`max_map_count`], the same as on Linux.
+pub const DEFAULT_MAX_MAP_COUNT: usize = 65530;
+
+static MAX_MAP_COUNT: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MAP_COUNT);
+
+/// Returns the maximum number of memory areas of a user address space.
+///
+/// Mapping, unmapping or protecting memory fails with `ENOMEM` if it would
+/// create more areas.
+pub fn max_map_count() -> usize {
+    MAX_MAP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Sets the maximum number of memory areas of a user address space, see
+/// [`max_map_count`].
+pub fn set_max_map_count(count: usize) {
+    MAX_MAP_COUNT.store(count, Ordering::Relaxed);
+}
+
 fn mem_to_mapping_flags(f: MemFlags) -> MappingFlags {
     let mut flags = MappingFlags::empty();
 
@@ -56,6 +84,12 @@ pub fn new_kernel_layout() -> LinuxResult<AddrSpace> {
         // mapped range should contain the whole region if it is not aligned.
         let start = region.paddr.align_down_4k();
         let end = (region.paddr + region.size).align_up_4k();
+        if let Err(err) = khal::kvmap::claim(p2v(start), end - start, region.name) {
+            panic!(
+                "Cannot map {} in the kernel address space: {err:?}",
+                region.name
+            );
+        }
         vmspace.map_linear(
             p2v(start),
             start,
@@ -96,6 +130,7 @@ pub fn init_memory_management() {
     let kernel_layout = new_kernel_layout().expect("failed to initialize kernel address space");
     debug!("kernel address space init OK: {:#x?}", kernel_layout);
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_layout));
+    kalloc::register_reclaimer(lazyfree::reclaim);
     #[allow(unused_mut)]
     let mut root = kernel_page_table_root();
     #[cfg(feature = "sev")]
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! TLB shootdowns.
+use khal::paging::PageTableMut;
+
+/// Flushes the TLB entries of the pages changed through `pgtbl`, on every
+/// CPU.
+///
+/// The other CPUs are interrupted once for the range covering all the
+/// changes, and this waits until they have flushed it, so the frames of
+/// pages unmapped before can be freed afterwards. AArch64 broadcasts TLB
+/// invalidations in hardware and needs no interrupt.
+pub(crate) fn flush_all_cpus(pgtbl: &mut PageTableMut) {
+    let flushed = pgtbl.finish();
+    #[cfg(all(feature = "ipi", not(target_arch = "aarch64")))]
+    if let Some((start, size)) = flushed
+        && platconfig::plat::CPU_NUM > 1
+    {
+        shootdown(start, size);
+    }
+    #[cfg(not(all(feature = "ipi", not(target_arch = "aarch64"))))]
+    let _ = flushed;
+}
+
+#[cfg(all(feature = "ipi", not(target_arch = "aarch64")))]
+fn shootdown(start: memaddr::VirtAddr, size: usize) {
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // The callback also runs on this CPU, which flushed already.
+    let pending = Arc::new(AtomicUsize::new(platconfig::plat::CPU_NUM));
+    let remaining = pending.clone();
+    if kipi::run_on_each_cpu(move || {
+        khal::paging::flush_tlb_range(start, size);
+        remaining.fetch_sub(1, Ordering::Release);
+    })
+    .is_err()
+    {
+        return;
+    }
+    while pending.load(Ordering::Acquire) > 0 {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Working set size estimation.
+//!
+//! The estimator periodically counts the pages of an address space accessed
+//! since the previous sample, with [`AddrSpace::sample_accessed`], and keeps
+//! a moving average of the counts. Nothing is sampled, and no page faults
+//! taken to track accesses, until it is started.
+use alloc::{
+    string::ToString,
+    sync::{Arc, Weak},
+};
+use core::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use ksync::Mutex;
+
+use crate::AddrSpace;
+
+/// Weight of the previous estimate against a new sample, as a power of two.
+///
+/// With 2, a sample counts for a quarter of the new estimate.
+const DECAY_SHIFT: u32 = 2;
+
+/// Estimates the working set size of an address space.
+pub struct WssEstimator {
+    aspace: Weak<Mutex<AddrSpace>>,
+    /// The sampling interval in milliseconds, 0 when stopped.
+    interval_ms: AtomicU64,
+    /// Incremented each time sampling starts, so that a sampler left from
+    /// before a stop exits.
+    epoch: AtomicU64,
+    /// The estimate in bytes, or `usize::MAX` before the first sample.
+    estimate: AtomicUsize,
+}
+
+impl WssEstimator {
+    /// Creates a stopped estimator for `aspace`.
+    pub fn new(aspace: &Arc<Mutex<AddrSpace>>) -> Arc<Self> {
+        Arc::new(Self {
+            aspace: Arc::downgrade(aspace),
+            interval_ms: AtomicU64::new(0),
+            epoch: AtomicU64::new(0),
+            estimate: AtomicUsize::new(usize::MAX),
+        })
+    }
+
+    /// Starts sampling every `interval`, or changes the interval if sampling
+    /// already.
+    ///
+    /// All pages are marked idle, and the first sample taken after
+    /// `interval`.
+    pub fn start(self: &Arc<Self>, interval: Duration) {
+        let ms = (interval.as_millis() as u64).max(1);
+        if self.interval_ms.swap(ms, Ordering::AcqRel) != 0 {
+            return;
+        }
+        let Some(aspace) = self.aspace.upgrade() else {
+            return;
+        };
+        let epoch = self.epoch.fetch_add(1, Ordering::AcqRel) + 1;
+        self.estimate.store(usize::MAX, Ordering::Relaxed);
+        aspace.lock().sample_accessed();
+
+        let this = Arc::downgrade(self);
+        ktask::spawn_with_name(
+            move || {
+                loop {
+                    let Some(ms) = this
+                        .upgrade()
+                        .map(|it| it.interval_ms.load(Ordering::Acquire))
+                    else {
+                        break;
+                    };
+                    ktask::sleep(Duration::from_millis(ms));
+                    let Some(this) = this.upgrade() else {
+                        break;
+                    };
+                    if this.epoch.load(Ordering::Acquire) != epoch
+                        || this.interval_ms.load(Ordering::Acquire) == 0
+                    {
+                        break;
+                    }
+                    let Some(aspace) = this.aspace.upgrade() else {
+                        break;
+                    };
+                    this.sample(&mut aspace.lock());
+                }
+            },
+            "wss".to_string(),
+        );
+    }
+
+    /// Stops sampling, keeping the last estimate.
+    pub fn stop(&self) {
+        self.interval_ms.store(0, Ordering::Release);
+    }
+
+    /// Returns the sampling interval, or `None` if stopped.
+    pub fn interval(&self) -> Option<Duration> {
+        match self.interval_ms.load(Ordering::Acquire) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Returns the estimated working set size in bytes, or `None` before the
+    /// first sample.
+    pub fn estimate(&self) -> Option<usize> {
+        match self.estimate.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            bytes => Some(bytes),
+        }
+    }
+
+    /// Counts the pages of `aspace` accessed since the previous sample into
+    /// the estimate.
+    pub fn sample(&self, aspace: &mut AddrSpace) {
+        let accessed = aspace.sample_accessed();
+        let estimate = match self.estimate() {
+            None => accessed,
+            Some(old) => old - (old >> DECAY_SHIFT) + (accessed >> DECAY_SHIFT),
+        };
+        self.estimate.store(estimate, Ordering::Relaxed);
+    }
+}
+
+// Pages of LoongArch have no accessed bit, and always count as accessed.
+#[cfg(all(unittest, not(target_arch = "loongarch64")))]
+mod tests_wss {
+    use alloc::vec;
+
+    use khal::paging::{MappingFlags, PageSize};
+    use memaddr::{PAGE_SIZE_4K, VirtAddr, va};
+    use unittest::def_test;
+
+    use super::*;
+    use crate::backend::Backend;
+
+    const PAGE: usize = PAGE_SIZE_4K;
+    const BASE: usize = 0x1000_0000;
+
+    /// Returns an address space with `pages` anonymous pages populated at its
+    /// start.
+    fn setup(pages: usize) -> Arc<Mutex<AddrSpace>> {
+        let mut aspace = AddrSpace::new_empty(va!(BASE), 0x100 * PAGE).unwrap();
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        aspace
+            .map(
+                va!(BASE),
+                pages * PAGE,
+                flags,
+                true,
+                Backend::new_alloc(va!(BASE), PageSize::Size4K),
+            )
+            .unwrap();
+        Arc::new(Mutex::new(aspace))
+    }
+
+    /// Touches page `page`, as the access does on the fault it takes when the
+    /// hardware does not set the accessed bit.
+    fn touch(aspace: &mut AddrSpace, page: usize) {
+        let vaddr: VirtAddr = va!(BASE + page * PAGE);
+        aspace.handle_page_fault(vaddr, MappingFlags::READ).unwrap();
+    }
+
+    #[def_test]
+    fn test_idle_pages_bitmap() {
+        let aspace = setup(70);
+        let mut aspace = aspace.lock();
+        // Pages past the area are not mapped, and never idle.
+        aspace.mark_idle(va!(BASE), 70 * PAGE).unwrap();
+        let bitmap = aspace.idle_pages(va!(BASE), 72 * PAGE).unwrap();
+        assert_eq!(bitmap, [!0, 0b11_1111]);
+
+        for page in [0, 3, 64, 69] {
+            touch(&mut aspace, page);
+        }
+        let bitmap = aspace.idle_pages(va!(BASE), 72 * PAGE).unwrap();
+        assert_eq!(bitmap, [!0b1001, 0b01_1110]);
+
+        // Marking part of the range idle leaves the rest.
+        aspace.mark_idle(va!(BASE), 64 * PAGE).unwrap();
+        let bitmap = aspace.idle_pages(va!(BASE + PAGE), 69 * PAGE).unwrap();
+        assert_eq!(bitmap, [!(1 << 63), 0b0_1111]);
+    }
+
+    #[def_test]
+    fn test_wss_estimate() {
+        let aspace = setup(32);
+        let wss = WssEstimator::new(&aspace);
+        assert_eq!(wss.estimate(), None);
+        aspace.lock().sample_accessed();
+
+        // The workload touches 6 and 10 pages in turn, 8 on average.
+        for round in 0..16 {
+            let mut aspace = aspace.lock();
+            let touched = if round % 2 == 0 { 6 } else { 10 };
+            for page in 0..touched {
+                touch(&mut aspace, (round * 3 + page) % 32);
+            }
+            wss.sample(&mut aspace);
+        }
+        let estimate = wss.estimate().unwrap() / PAGE;
+        assert!((6..=10).contains(&estimate), "estimate {estimate}");
+
+        // The estimate decays once the workload goes idle.
+        for _ in 0..16 {
+            wss.sample(&mut aspace.lock());
+        }
+        assert!(wss.estimate().unwrap() < PAGE);
+    }
+}
@@ -2,9 +2,13 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use core::{arch::asm, fmt};
+use core::{
+    arch::asm,
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
-use memaddr::{PhysAddr, VirtAddr};
+use memaddr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
 
 use crate::{
     defs::{PageTableEntry, PagingFlags, PagingMetaData},
@@ -199,6 +203,20 @@ impl PageTableEntry for A64PageEntry {
     fn clear(&mut self) {
         self.0 = 0;
     }
+
+    fn is_accessed(&self) -> bool {
+        Arm64Attr::from_bits_truncate(self.0).contains(Arm64Attr::AF)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        // TCR_EL1.HA is not set: an access with AF clear takes an access flag
+        // fault, and the kernel sets it.
+        if accessed {
+            self.0 |= Arm64Attr::AF.bits();
+        } else {
+            self.0 &= !Arm64Attr::AF.bits();
+        }
+    }
 }
 
 impl fmt::Debug for A64PageEntry {
@@ -210,6 +228,33 @@ impl fmt::Debug for A64PageEntry {
     }
 }
 
+/// VA[55:12] in the operand of a TLBI by VA.
+const TLBI_VA_MASK: usize = (1 << 44) - 1;
+/// BaseADDR (VA[48:12] for 4K pages) in the operand of a TLBI by range.
+const TLBI_RANGE_BADDR_MASK: usize = (1 << 37) - 1;
+/// TG field of a TLBI by range, selecting 4K pages.
+const TLBI_RANGE_TG_4K: usize = 1 << 46;
+/// Pages covered by TLBI by range operations with `SCALE` up to 3.
+const TLBI_RANGE_MAX_PAGES: usize = 1 << 21;
+
+/// Whether `FEAT_TLBIRANGE` (ARMv8.4) is implemented: 0 if not checked yet,
+/// 1 if not, 2 if it is.
+static TLBI_RANGE: AtomicU8 = AtomicU8::new(0);
+
+fn has_tlbi_range() -> bool {
+    match TLBI_RANGE.load(Ordering::Relaxed) {
+        0 => {
+            let isar0: u64;
+            unsafe { asm!("mrs {}, ID_AA64ISAR0_EL1", out(reg) isar0) };
+            // ID_AA64ISAR0_EL1.TLB is 0b0010 when the range forms are there.
+            let supported = (isar0 >> 56) & 0xf >= 2;
+            TLBI_RANGE.store(if supported { 2 } else { 1 }, Ordering::Relaxed);
+            supported
+        }
+        state => state == 2,
+    }
+}
+
 pub struct A64PagingMetaData;
 
 impl PagingMetaData for A64PagingMetaData {
@@ -217,6 +262,8 @@ impl PagingMetaData for A64PagingMetaData {
 
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 48;
+    // Broadcast invalidations are expensive, but so is refilling the TLB.
+    const TLB_FLUSH_CEILING: usize = 512;
     const VA_MAX_BITS: usize = 48;
 
     fn vaddr_is_valid(vaddr: usize) -> bool {
@@ -228,13 +275,50 @@ impl PagingMetaData for A64PagingMetaData {
     fn flush_tlb(vaddr: Option<VirtAddr>) {
         unsafe {
             if let Some(vaddr) = vaddr {
-                const VA_MASK: usize = (1 << 44) - 1;
-                asm!("dsb ishst; tlbi vaae1is, {}; dsb ish; isb", in(reg) ((vaddr.as_usize() >> 12) & VA_MASK))
+                asm!("dsb ishst; tlbi vaae1is, {}; dsb ish; isb", in(reg) ((vaddr.as_usize() >> 12) & TLBI_VA_MASK))
             } else {
                 asm!("dsb ishst; tlbi vmalle1is; dsb ish; isb")
             }
         }
     }
+
+    /// Invalidates the range with TLBI by range operations when the CPU has
+    /// them, and page by page otherwise, with a single barrier either way.
+    fn flush_tlb_range(start: VirtAddr, size: usize) {
+        let range_ops = has_tlbi_range();
+        let mut pages = size.div_ceil(PAGE_SIZE_4K);
+        if pages >= TLBI_RANGE_MAX_PAGES || (!range_ops && pages > Self::TLB_FLUSH_CEILING) {
+            Self::flush_tlb(None);
+            return;
+        }
+
+        let mut page = start.as_usize() >> 12;
+        let mut scale = 0;
+        unsafe { asm!("dsb ishst") };
+        while pages > 0 {
+            if !range_ops || pages % 2 == 1 {
+                unsafe { asm!("tlbi vaae1is, {}", in(reg) page & TLBI_VA_MASK) };
+                page += 1;
+                pages -= 1;
+                continue;
+            }
+            // A range operation covers (NUM + 1) << (5 * SCALE + 1) pages.
+            let num = (pages >> (5 * scale + 1)) & 0x1f;
+            if num > 0 {
+                let operand = (page & TLBI_RANGE_BADDR_MASK)
+                    | TLBI_RANGE_TG_4K
+                    | (scale << 44)
+                    | ((num - 1) << 39);
+                // TLBI RVAAE1IS, spelled as SYS so that no ARMv8.4 target
+                // feature is needed to assemble it.
+                unsafe { asm!("sys #0, c8, c2, #3, {}", in(reg) operand) };
+                page += num << (5 * scale + 1);
+                pages -= num << (5 * scale + 1);
+            }
+            scale += 1;
+        }
+        unsafe { asm!("dsb ish; isb") };
+    }
 }
 
 pub type A64PageTable<H> = PageTable64<A64PagingMetaData, A64PageEntry, H>;
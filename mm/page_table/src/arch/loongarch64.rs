@@ -151,6 +151,13 @@ impl PageTableEntry for La64PageEntry {
     fn clear(&mut self) {
         self.0 = 0;
     }
+
+    fn is_accessed(&self) -> bool {
+        // There is no accessed bit.
+        true
+    }
+
+    fn set_accessed(&mut self, _accessed: bool) {}
 }
 
 impl fmt::Debug for La64PageEntry {
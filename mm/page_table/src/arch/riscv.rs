@@ -128,6 +128,20 @@ impl PageTableEntry for Rv64PageEntry {
     fn clear(&mut self) {
         self.0 = 0;
     }
+
+    fn is_accessed(&self) -> bool {
+        RvFlags::from_bits_truncate(self.0 as usize).contains(RvFlags::A)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        // With Svadu the hart sets A itself, otherwise (Svade) an access with
+        // A clear raises a page fault and the kernel sets it.
+        if accessed {
+            self.0 |= RvFlags::A.bits() as u64;
+        } else {
+            self.0 &= !(RvFlags::A.bits() as u64);
+        }
+    }
 }
 
 impl fmt::Debug for Rv64PageEntry {
@@ -165,6 +165,18 @@ impl PageTableEntry for X64PageEntry {
     fn clear(&mut self) {
         self.0 = 0;
     }
+
+    fn is_accessed(&self) -> bool {
+        PTF::from_bits_truncate(self.0).contains(PTF::ACCESSED)
+    }
+
+    fn set_accessed(&mut self, accessed: bool) {
+        if accessed {
+            self.0 |= PTF::ACCESSED.bits();
+        } else {
+            self.0 &= !PTF::ACCESSED.bits();
+        }
+    }
 }
 
 impl fmt::Debug for X64PageEntry {
@@ -183,6 +195,8 @@ impl PagingMetaData for X64PagingMetaData {
 
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 52;
+    // Past this many `invlpg`, reloading CR3 is cheaper.
+    const TLB_FLUSH_CEILING: usize = 33;
     const VA_MAX_BITS: usize = 48;
 
     #[inline]
@@ -5,7 +5,7 @@
 //! Page table definitions and traits.
 use core::fmt;
 
-use memaddr::{MemoryAddr, PhysAddr, VirtAddr};
+use memaddr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
 
 bitflags::bitflags! {
     /// Page table entry permission and attribute flags.
@@ -18,6 +18,9 @@ bitflags::bitflags! {
         const DEVICE        = 1 << 4;
         const UNCACHED      = 1 << 5;
         const SHARED        = 1 << 6;
+        /// Not an attribute of the entries: the mapping may be writable and
+        /// executable at once, even where that is not allowed otherwise.
+        const JIT           = 1 << 7;
     }
 }
 
@@ -40,6 +43,12 @@ pub trait PageTableEntry: fmt::Debug + Clone + Copy + Sync + Send + Sized {
     fn is_present(&self) -> bool;
     fn is_huge(&self) -> bool;
     fn clear(&mut self);
+    /// Whether the page was accessed since the accessed bit was cleared.
+    ///
+    /// Entries of architectures without an accessed bit always report
+    /// accessed.
+    fn is_accessed(&self) -> bool;
+    fn set_accessed(&mut self, accessed: bool);
 }
 
 /// Page table operation errors.
@@ -71,6 +80,9 @@ pub trait PagingMetaData: Sync + Send {
     const PA_MAX_BITS: usize;
     const VA_MAX_BITS: usize;
     const PA_MAX_ADDR: usize = (1 << Self::PA_MAX_BITS) - 1;
+    /// Largest number of pages [`PagingMetaData::flush_tlb_range`]
+    /// invalidates one by one, above which it flushes the whole TLB.
+    const TLB_FLUSH_CEILING: usize = 32;
     type VirtAddr: MemoryAddr;
 
     fn paddr_is_valid(paddr: usize) -> bool {
@@ -83,6 +95,18 @@ pub trait PagingMetaData: Sync + Send {
     }
 
     fn flush_tlb(vaddr: Option<Self::VirtAddr>);
+
+    /// Invalidates the TLB entries of the `size` bytes from `start`.
+    fn flush_tlb_range(start: Self::VirtAddr, size: usize) {
+        let pages = size.div_ceil(PAGE_SIZE_4K);
+        if pages > Self::TLB_FLUSH_CEILING {
+            Self::flush_tlb(None);
+            return;
+        }
+        for i in 0..pages {
+            Self::flush_tlb(Some(start.add(i * PAGE_SIZE_4K)));
+        }
+    }
 }
 
 /// Hooks for allocating and mapping page table frames.
@@ -123,6 +147,8 @@ impl From<PageSize> for usize {
 
 #[cfg(unittest)]
 mod tests_page_table_defs {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
     use memaddr::VirtAddr;
     use unittest::def_test;
 
@@ -155,6 +181,39 @@ mod tests_page_table_defs {
         assert!(PageSize::Size2M.is_huge());
     }
 
+    struct CountingMeta;
+
+    static FLUSHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+    static FLUSHED_ALL: AtomicUsize = AtomicUsize::new(0);
+
+    impl PagingMetaData for CountingMeta {
+        type VirtAddr = VirtAddr;
+
+        const LEVELS: usize = 4;
+        const PA_MAX_BITS: usize = 36;
+        const TLB_FLUSH_CEILING: usize = 4;
+        const VA_MAX_BITS: usize = 39;
+
+        fn flush_tlb(vaddr: Option<Self::VirtAddr>) {
+            match vaddr {
+                Some(_) => FLUSHED_PAGES.fetch_add(1, Ordering::Relaxed),
+                None => FLUSHED_ALL.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    #[def_test]
+    fn test_flush_tlb_range_ceiling() {
+        CountingMeta::flush_tlb_range(VirtAddr::from(0x1000), 0x4000);
+        assert_eq!(FLUSHED_PAGES.load(Ordering::Relaxed), 4);
+        assert_eq!(FLUSHED_ALL.load(Ordering::Relaxed), 0);
+
+        // A partial page is flushed too, which goes over the ceiling.
+        CountingMeta::flush_tlb_range(VirtAddr::from(0x1000), 0x4001);
+        assert_eq!(FLUSHED_PAGES.load(Ordering::Relaxed), 4);
+        assert_eq!(FLUSHED_ALL.load(Ordering::Relaxed), 1);
+    }
+
     #[def_test]
     fn test_paging_metadata_bounds() {
         assert!(DummyMeta::paddr_is_valid(0));
@@ -3,7 +3,10 @@
 // See LICENSES for license details.
 
 //! Generic 64-bit multi-level page table implementation.
-use core::{marker::PhantomData, ops::Deref};
+use core::{
+    marker::PhantomData,
+    ops::{Deref, Range},
+};
 
 use arrayvec::ArrayVec;
 use memaddr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr};
@@ -157,8 +160,10 @@ const FLUSH_THRESHOLD: usize = 16;
 
 enum ToFlush<M: PagingMetaData> {
     None,
-    Addresses(ArrayVec<M::VirtAddr, FLUSH_THRESHOLD>),
-    Full,
+    /// A few pages flushed one by one, and the range covering them.
+    Addresses(ArrayVec<M::VirtAddr, FLUSH_THRESHOLD>, Range<usize>),
+    /// Too many pages to track, flushed as the range covering them.
+    Range(Range<usize>),
 }
 
 /// Mutable page table access with deferred TLB flushes.
@@ -185,19 +190,27 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
         }
     }
 
-    fn flush(&mut self, vaddr: M::VirtAddr) {
-        match self.flush {
+    fn flush(&mut self, vaddr: M::VirtAddr, size: PageSize) {
+        let start: usize = vaddr.into();
+        let start = start - size.align_offset(start);
+        let page = start..start + size as usize;
+        let cover = |covered: &mut Range<usize>| {
+            covered.start = covered.start.min(page.start);
+            covered.end = covered.end.max(page.end);
+        };
+        match &mut self.flush {
             ToFlush::None => {
                 let mut addresses = ArrayVec::new();
                 addresses.push(vaddr);
-                self.flush = ToFlush::Addresses(addresses);
+                self.flush = ToFlush::Addresses(addresses, page.clone());
             }
-            ToFlush::Addresses(ref mut addrs) => {
+            ToFlush::Addresses(addrs, covered) => {
+                cover(covered);
                 if addrs.try_push(vaddr).is_err() {
-                    self.flush = ToFlush::Full;
+                    self.flush = ToFlush::Range(covered.clone());
                 }
             }
-            ToFlush::Full => {}
+            ToFlush::Range(covered) => cover(covered),
         }
     }
 
@@ -296,7 +309,7 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
             return Err(PtError::AlreadyMapped);
         }
         *entry = PageTableEntry::new_page(target.align_down(page_size), flags, page_size.is_huge());
-        self.flush(vaddr);
+        self.flush(vaddr, page_size);
         Ok(())
     }
 
@@ -309,7 +322,7 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
         let (entry, size) = self.get_entry_mut(vaddr)?;
         entry.set_paddr(paddr);
         entry.set_flags(flags, size.is_huge());
-        self.flush(vaddr);
+        self.flush(vaddr, size);
         Ok(size)
     }
 
@@ -319,10 +332,29 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
             return Err(PtError::NotMapped);
         }
         entry.set_flags(flags, size.is_huge());
-        self.flush(vaddr);
+        self.flush(vaddr, size);
         Ok(size)
     }
 
+    /// Sets or clears the accessed bit of the page mapped at `vaddr`, and
+    /// returns its previous state and the size of the page.
+    pub fn set_accessed(
+        &mut self,
+        vaddr: M::VirtAddr,
+        accessed: bool,
+    ) -> PtResult<(bool, PageSize)> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PtError::NotMapped);
+        }
+        let old = entry.is_accessed();
+        if old != accessed {
+            entry.set_accessed(accessed);
+            self.flush(vaddr, size);
+        }
+        Ok((old, size))
+    }
+
     pub fn unmap(&mut self, vaddr: M::VirtAddr) -> PtResult<(PhysAddr, PagingFlags, PageSize)> {
         let (entry, size) = self.get_entry_mut(vaddr)?;
         if !entry.is_present() {
@@ -332,10 +364,148 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
         let paddr = entry.paddr();
         let flags = entry.flags();
         entry.clear();
-        self.flush(vaddr);
+        self.flush(vaddr, size);
         Ok((paddr, flags, size))
     }
 
+    /// Unmaps the pages mapped in the `size` bytes from `vaddr`, calling `f`
+    /// with the address, frame and size of each, and stopping after `max`
+    /// pages.
+    ///
+    /// Missing page tables are skipped whole, so sparsely populated ranges
+    /// are cheap to walk. Returns the address the walk stopped at, which is
+    /// the end of the range once all of it is unmapped.
+    pub fn unmap_range(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        max: usize,
+        mut f: impl FnMut(M::VirtAddr, PhysAddr, PageSize),
+    ) -> M::VirtAddr {
+        let start: usize = vaddr.into();
+        let root = self.table_of_mut(self.root_paddr());
+        let mut budget = max;
+        self.unmap_table(root, 0, start, start + size, &mut budget, &mut f)
+            .into()
+    }
+
+    /// Walks `table` at `level` (0 being the root) from `vaddr` to `end` for
+    /// [`Self::unmap_range`], returning the address it stopped at.
+    fn unmap_table(
+        &mut self,
+        table: &'a mut [PTE],
+        level: usize,
+        mut vaddr: usize,
+        end: usize,
+        budget: &mut usize,
+        f: &mut impl FnMut(M::VirtAddr, PhysAddr, PageSize),
+    ) -> usize {
+        let shift = 12 + 9 * (M::LEVELS - 1 - level);
+        while vaddr < end && *budget > 0 {
+            // Start of the next entry, which is 0 past the top of memory.
+            let boundary = (vaddr | ((1 << shift) - 1)).wrapping_add(1);
+            let next = if boundary == 0 {
+                end
+            } else {
+                boundary.min(end)
+            };
+            let entry = &mut table[(vaddr >> shift) & (ENTRY_COUNT - 1)];
+            if entry.is_unused() {
+                vaddr = next;
+                continue;
+            }
+
+            let leaf_size = match M::LEVELS - 1 - level {
+                0 => Some(PageSize::Size4K),
+                1 if entry.is_huge() => Some(PageSize::Size2M),
+                2 if entry.is_huge() => Some(PageSize::Size1G),
+                _ => None,
+            };
+            if let Some(page_size) = leaf_size {
+                if entry.is_present() {
+                    let paddr = entry.paddr();
+                    let page = (vaddr & !((1 << shift) - 1)).into();
+                    entry.clear();
+                    self.flush(page, page_size);
+                    f(page, paddr, page_size);
+                    *budget -= 1;
+                } else {
+                    entry.clear();
+                }
+                vaddr = next;
+            } else if let Ok(next_table) = self.next_table_mut(entry) {
+                vaddr = self.unmap_table(next_table, level + 1, vaddr, next, budget, f);
+            } else {
+                vaddr = next;
+            }
+        }
+        vaddr
+    }
+
+    /// Calls `f` with the address, size and accessed bit of each page mapped
+    /// in the `size` bytes from `vaddr`, clearing the bit if `clear` is set.
+    ///
+    /// Missing page tables are skipped whole, as by [`Self::unmap_range`].
+    pub fn scan_accessed(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        clear: bool,
+        mut f: impl FnMut(M::VirtAddr, PageSize, bool),
+    ) {
+        let start: usize = vaddr.into();
+        let root = self.table_of_mut(self.root_paddr());
+        self.scan_table(root, 0, start, start + size, clear, &mut f);
+    }
+
+    /// Walks `table` at `level` from `vaddr` to `end` for
+    /// [`Self::scan_accessed`].
+    fn scan_table(
+        &mut self,
+        table: &'a mut [PTE],
+        level: usize,
+        mut vaddr: usize,
+        end: usize,
+        clear: bool,
+        f: &mut impl FnMut(M::VirtAddr, PageSize, bool),
+    ) {
+        let shift = 12 + 9 * (M::LEVELS - 1 - level);
+        while vaddr < end {
+            let boundary = (vaddr | ((1 << shift) - 1)).wrapping_add(1);
+            let next = if boundary == 0 {
+                end
+            } else {
+                boundary.min(end)
+            };
+            let entry = &mut table[(vaddr >> shift) & (ENTRY_COUNT - 1)];
+            if entry.is_unused() {
+                vaddr = next;
+                continue;
+            }
+
+            let leaf_size = match M::LEVELS - 1 - level {
+                0 => Some(PageSize::Size4K),
+                1 if entry.is_huge() => Some(PageSize::Size2M),
+                2 if entry.is_huge() => Some(PageSize::Size1G),
+                _ => None,
+            };
+            if let Some(page_size) = leaf_size {
+                if entry.is_present() {
+                    let page = (vaddr & !((1 << shift) - 1)).into();
+                    let accessed = entry.is_accessed();
+                    if clear && accessed {
+                        entry.set_accessed(false);
+                        self.flush(page, page_size);
+                    }
+                    f(page, page_size, accessed);
+                }
+            } else if let Ok(next_table) = self.next_table_mut(entry) {
+                self.scan_table(next_table, level + 1, vaddr, next, clear, f);
+            }
+            vaddr = next;
+        }
+    }
+
     pub fn map_region(
         &mut self,
         vaddr: M::VirtAddr,
@@ -435,20 +605,28 @@ impl<'a, M: PagingMetaData, PTE: PageTableEntry, H: PagingHandler> PageTableMut<
         }
     }
 
-    pub fn finish(&mut self) {
-        #[cfg(not(docsrs))]
-        match &self.flush {
-            ToFlush::None => {}
-            ToFlush::Addresses(addrs) => {
+    /// Flushes the TLB entries of the pages changed so far.
+    ///
+    /// Returns the start and size of the range covering them, if any, for
+    /// the caller to flush on other CPUs.
+    pub fn finish(&mut self) -> Option<(M::VirtAddr, usize)> {
+        let covered = match &self.flush {
+            ToFlush::None => None,
+            ToFlush::Addresses(addrs, covered) => {
+                #[cfg(not(docsrs))]
                 for vaddr in addrs.iter() {
                     M::flush_tlb(Some(*vaddr));
                 }
+                Some(covered.clone())
             }
-            ToFlush::Full => {
-                M::flush_tlb(None);
+            ToFlush::Range(covered) => {
+                #[cfg(not(docsrs))]
+                M::flush_tlb_range(covered.start.into(), covered.len());
+                Some(covered.clone())
             }
-        }
+        };
         self.flush = ToFlush::None;
+        covered.map(|covered| (covered.start.into(), covered.len()))
     }
 }
 
@@ -17,11 +17,15 @@ pub const GATEWAY: &str = env_or_default!("K_GW");
 pub const IP_PREFIX: u8 = 24;
 
 pub const STANDARD_MTU: usize = 1500;
+/// Smallest MTU an IPv4 link may have.
+pub const MIN_MTU: usize = 68;
 
 pub const TCP_RX_BUF_LEN: usize = 64 * 1024;
 pub const TCP_TX_BUF_LEN: usize = 64 * 1024;
 pub const UDP_RX_BUF_LEN: usize = 64 * 1024;
 pub const UDP_TX_BUF_LEN: usize = 64 * 1024;
+/// Smallest `SO_SNDBUF`/`SO_RCVBUF` accepted.
+pub const SOCK_MIN_BUF: usize = 4 * 1024;
 pub const LISTEN_QUEUE_SIZE: usize = 512;
 
 pub const SOCKET_BUFFER_SIZE: usize = 64;
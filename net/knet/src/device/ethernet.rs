@@ -3,50 +3,185 @@
 // See LICENSES for license details.
 
 //! Ethernet device adapter for the smoltcp stack.
-use alloc::{string::String, vec};
+use alloc::{string::String, vec, vec::Vec};
 use core::task::Waker;
 
 use hashbrown::HashMap;
 use kdriver::prelude::{
-    DriverError, DriverOps, NetBufHandle, NetDevice as DriverNetDevice, NetDriverOps,
+    DriverError, DriverOps, MacAddress, NetBufHandle, NetCapabilities,
+    NetDevice as DriverNetDevice, NetDriverOps, TxOffload,
 };
+use kerrno::{KError, KResult, LinuxError};
 use ktask::future::register_irq_waker;
 use smoltcp::{
     storage::{PacketBuffer, PacketMetadata},
     time::{Duration, Instant},
     wire::{
         ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
-        EthernetRepr, IpAddress, Ipv4Cidr,
+        EthernetRepr, IpAddress, IpProtocol, Ipv4Address, Ipv4Cidr, Ipv4Packet,
     },
 };
 
 use crate::{
-    consts::{ETHERNET_MAX_PENDING_PACKETS, STANDARD_MTU},
+    consts::{ETHERNET_MAX_PENDING_PACKETS, MIN_MTU, STANDARD_MTU},
     device::NetDevice as NetDeviceOps,
+    moderation::{Moderation, ModerationConfig},
 };
 
 const EMPTY_MAC: EthernetAddress = EthernetAddress([0; 6]);
 
+/// Software receive filter.
+///
+/// Applied to every received frame, it stands in for NICs that cannot filter
+/// in hardware, and removes the false positives of those that filter
+/// multicast by hash.
+#[derive(Default)]
+pub(crate) struct RxFilter {
+    promiscuous: bool,
+    /// Joined multicast addresses with their join counts.
+    multicast: HashMap<EthernetAddress, usize>,
+}
+
+impl RxFilter {
+    /// Returns whether a frame sent to `dst` is for the host `own`.
+    pub fn accepts(&self, own: EthernetAddress, dst: EthernetAddress) -> bool {
+        self.promiscuous
+            || dst == own
+            || dst.is_broadcast()
+            || dst == EMPTY_MAC
+            || (dst.is_multicast() && self.multicast.contains_key(&dst))
+    }
+
+    pub fn set_promiscuous(&mut self, enable: bool) {
+        self.promiscuous = enable;
+    }
+
+    /// Counts a join of `addr`, returning whether it is the first one.
+    pub fn join(&mut self, addr: EthernetAddress) -> bool {
+        let count = self.multicast.entry(addr).or_default();
+        *count += 1;
+        *count == 1
+    }
+
+    /// Drops a join of `addr`, returning whether it was the last one, or
+    /// `None` if `addr` was not joined.
+    pub fn leave(&mut self, addr: EthernetAddress) -> Option<bool> {
+        let count = self.multicast.get_mut(&addr)?;
+        *count -= 1;
+        if *count > 0 {
+            return Some(false);
+        }
+        self.multicast.remove(&addr);
+        Some(true)
+    }
+}
+
+/// Returns the Ethernet address that IP multicast `group` is sent to.
+pub(crate) fn multicast_mac(group: IpAddress) -> Option<EthernetAddress> {
+    match group {
+        IpAddress::Ipv4(addr) if addr.is_multicast() => {
+            let [_, b1, b2, b3] = addr.octets();
+            Some(EthernetAddress([0x01, 0x00, 0x5e, b1 & 0x7f, b2, b3]))
+        }
+        IpAddress::Ipv6(addr) if addr.is_multicast() => {
+            let [.., b12, b13, b14, b15] = addr.octets();
+            Some(EthernetAddress([0x33, 0x33, b12, b13, b14, b15]))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the folded (but not complemented) sum of the IPv4 pseudo-header.
+fn pseudo_header_sum(src: Ipv4Address, dst: Ipv4Address, protocol: IpProtocol, len: u16) -> u16 {
+    let mut sum = u8::from(protocol) as u32 + len as u32;
+    for pair in src.octets().chunks(2).chain(dst.octets().chunks(2)) {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Prepares the checksums left zero by the stack in the IPv4 `packet` for
+/// the NIC to fill in, and returns the offloads to request.
+///
+/// L4 checksum fields are seeded with the pseudo-header sum, as
+/// [`TxOffload`] expects.
+pub(crate) fn tx_offload(caps: NetCapabilities, packet: &mut [u8]) -> TxOffload {
+    let mut offload = TxOffload::default();
+    let Ok(mut ip) = Ipv4Packet::new_checked(packet) else {
+        return offload;
+    };
+    if caps.contains(NetCapabilities::TX_CSUM_IPV4) && ip.checksum() == 0 {
+        offload.ipv4_csum = true;
+    }
+    if ip.more_frags() || ip.frag_offset() != 0 {
+        return offload;
+    }
+    let (csum_cap, csum_offset) = match ip.next_header() {
+        IpProtocol::Tcp => (NetCapabilities::TX_CSUM_TCP, 16),
+        IpProtocol::Udp => (NetCapabilities::TX_CSUM_UDP, 6),
+        _ => return offload,
+    };
+    let (src, dst, protocol) = (ip.src_addr(), ip.dst_addr(), ip.next_header());
+    let header_len = ip.header_len() as usize;
+    let payload = ip.payload_mut();
+    let Some(csum) = payload.get(csum_offset..csum_offset + 2) else {
+        return offload;
+    };
+    if !caps.contains(csum_cap) || csum != [0, 0] {
+        return offload;
+    }
+    let len = payload.len() as u16;
+    payload[csum_offset..csum_offset + 2]
+        .copy_from_slice(&pseudo_header_sum(src, dst, protocol, len).to_be_bytes());
+    offload.csum_start = (EthernetFrame::<&[u8]>::header_len() + header_len) as u16;
+    offload.csum_offset = csum_offset as u16;
+    offload
+}
+
+/// The NIC under an [`EthernetDevice`]: the driver-provided one, or in unit
+/// tests one that packets are injected into.
+pub trait Nic: Send + Sync + 'static {
+    fn ops(&self) -> &dyn NetDriverOps;
+    fn ops_mut(&mut self) -> &mut dyn NetDriverOps;
+}
+
+impl Nic for DriverNetDevice {
+    fn ops(&self) -> &dyn NetDriverOps {
+        self
+    }
+
+    fn ops_mut(&mut self) -> &mut dyn NetDriverOps {
+        self
+    }
+}
+
 struct ArpNeighbor {
     hardware_address: EthernetAddress,
     expires_at: Instant,
 }
 
 /// Ethernet device backed by a driver-provided NIC.
-pub struct EthernetDevice {
+pub struct EthernetDevice<N: Nic = DriverNetDevice> {
     #[allow(dead_code)]
     name: String,
-    inner: DriverNetDevice,
+    inner: N,
     neighbors: HashMap<IpAddress, Option<ArpNeighbor>>,
-    ip: Ipv4Cidr,
+    /// Addresses assigned to the device, answered in ARP.
+    addrs: Vec<Ipv4Cidr>,
+    mtu: usize,
+    filter: RxFilter,
+    moderation: Moderation,
 
     pending_tx: PacketBuffer<'static, IpAddress>,
 }
-impl EthernetDevice {
+impl<N: Nic> EthernetDevice<N> {
     const NEIGHBOR_TTL: Duration = Duration::from_secs(60);
 
-    /// Create a new Ethernet device wrapper.
-    pub fn new(name: String, inner: DriverNetDevice, ip: Ipv4Cidr) -> Self {
+    /// Create a new Ethernet device wrapper, without any address yet.
+    pub fn new(name: String, mut inner: N) -> Self {
         let pending_tx = PacketBuffer::new(
             vec![PacketMetadata::EMPTY; ETHERNET_MAX_PENDING_PACKETS],
             vec![
@@ -55,18 +190,40 @@ impl EthernetDevice {
                     * ETHERNET_MAX_PENDING_PACKETS
             ],
         );
+        let moderation = Moderation::new(inner.ops_mut());
         Self {
             name,
             inner,
             neighbors: HashMap::new(),
-            ip,
+            addrs: Vec::new(),
+            mtu: STANDARD_MTU,
+            filter: RxFilter::default(),
+            moderation,
             pending_tx,
         }
     }
 
     #[inline]
     fn mac_addr(&self) -> EthernetAddress {
-        EthernetAddress(self.inner.mac().0)
+        EthernetAddress(self.inner.ops().mac().0)
+    }
+
+    /// Returns the address to send ARP requests for `target` from: the one
+    /// on the subnet of `target`, or else the first one.
+    fn source_addr_for(&self, target: Ipv4Address) -> Option<Ipv4Address> {
+        self.addrs
+            .iter()
+            .find(|it| it.contains_addr(&target))
+            .or(self.addrs.first())
+            .map(|it| it.address())
+    }
+
+    fn is_broadcast(&self, addr: IpAddress) -> bool {
+        addr.is_broadcast()
+            || self
+                .addrs
+                .iter()
+                .any(|it| it.broadcast().map(IpAddress::Ipv4) == Some(addr))
     }
 
     fn send_to<F>(
@@ -99,6 +256,11 @@ impl EthernetDevice {
         let mut frame = EthernetFrame::new_unchecked(tx_buf.data_mut());
         repr.emit(&mut frame);
         f(frame.payload_mut());
+        let caps = inner.capabilities();
+        if proto == EthernetProtocol::Ipv4 && !caps.is_empty() {
+            let offload = tx_offload(caps, frame.payload_mut());
+            tx_buf.set_offload(offload);
+        }
         trace!("SEND {} bytes: {:02X?}", tx_buf.len(), tx_buf.data());
         if let Err(err) = inner.send(tx_buf) {
             warn!("send failed: {:?}", err);
@@ -117,10 +279,7 @@ impl EthernetDevice {
             return false;
         };
 
-        if !repr.dst_addr.is_broadcast()
-            && repr.dst_addr != EMPTY_MAC
-            && repr.dst_addr != self.mac_addr()
-        {
+        if !self.filter.accepts(self.mac_addr(), repr.dst_addr) {
             return false;
         }
 
@@ -144,18 +303,22 @@ impl EthernetDevice {
             warn!("IPv6 address ARP is not supported: {}", target_ip);
             return;
         };
+        let Some(source_ipv4) = self.source_addr_for(target_ipv4) else {
+            debug!("No address to request ARP for {} from", target_ipv4);
+            return;
+        };
         debug!("Requesting ARP for {}", target_ipv4);
 
         let arp_repr = ArpRepr::EthernetIpv4 {
             operation: ArpOperation::Request,
             source_hardware_addr: self.mac_addr(),
-            source_protocol_addr: self.ip.address(),
+            source_protocol_addr: source_ipv4,
             target_hardware_addr: EthernetAddress::BROADCAST,
             target_protocol_addr: target_ipv4,
         };
 
         Self::send_to(
-            &mut self.inner,
+            self.inner.ops_mut(),
             EthernetAddress::BROADCAST,
             arp_repr.buffer_len(),
             |buf| arp_repr.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -198,7 +361,11 @@ impl EthernetDevice {
             {
                 return;
             }
-            if self.ip.address() != target_protocol_addr {
+            if !self
+                .addrs
+                .iter()
+                .any(|it| it.address() == target_protocol_addr)
+            {
                 return;
             }
 
@@ -215,13 +382,13 @@ impl EthernetDevice {
                 let response = ArpRepr::EthernetIpv4 {
                     operation: ArpOperation::Reply,
                     source_hardware_addr: self.mac_addr(),
-                    source_protocol_addr: self.ip.address(),
+                    source_protocol_addr: target_protocol_addr,
                     target_hardware_addr: source_hardware_addr,
                     target_protocol_addr: source_protocol_addr,
                 };
 
                 Self::send_to(
-                    &mut self.inner,
+                    self.inner.ops_mut(),
                     source_hardware_addr,
                     response.buffer_len(),
                     |buf| response.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -248,7 +415,7 @@ impl EthernetDevice {
                     }
 
                     Self::send_to(
-                        &mut self.inner,
+                        self.inner.ops_mut(),
                         neighbor.hardware_address,
                         buf.len(),
                         |b| b.copy_from_slice(buf),
@@ -261,27 +428,33 @@ impl EthernetDevice {
     }
 }
 
-impl NetDeviceOps for EthernetDevice {
+impl<N: Nic> NetDeviceOps for EthernetDevice<N> {
     fn name(&self) -> &str {
         &self.name
     }
 
     fn poll_rx(&mut self, buffer: &mut PacketBuffer<()>, timestamp: Instant) -> bool {
+        let mut packets = 0;
         loop {
-            let rx_buf: NetBufHandle = match self.inner.recv() {
+            let rx_buf: NetBufHandle = match self.inner.ops_mut().recv() {
                 Ok(buf) => buf,
                 Err(err) => {
                     if !matches!(err, DriverError::WouldBlock) {
                         warn!("recv failed: {:?}", err);
                     }
+                    self.moderation
+                        .polled(self.inner.ops_mut(), packets, timestamp);
                     return false;
                 }
             };
             trace!("RECV {} bytes: {:02X?}", rx_buf.len(), rx_buf.data());
+            packets += 1;
 
             let result = self.handle_rx_frame(rx_buf.data(), buffer, timestamp);
-            self.inner.recycle_rx(rx_buf).unwrap();
+            self.inner.ops_mut().recycle_rx(rx_buf).unwrap();
             if result {
+                self.moderation
+                    .polled(self.inner.ops_mut(), packets, timestamp);
                 return true;
             }
         }
@@ -293,9 +466,9 @@ impl NetDeviceOps for EthernetDevice {
         ip_packet: &[u8],
         timestamp: Instant,
     ) -> bool {
-        if next_hop.is_broadcast() || self.ip.broadcast().map(IpAddress::Ipv4) == Some(next_hop) {
+        if self.is_broadcast(next_hop) {
             Self::send_to(
-                &mut self.inner,
+                self.inner.ops_mut(),
                 EthernetAddress::BROADCAST,
                 ip_packet.len(),
                 |buf| buf.copy_from_slice(ip_packet),
@@ -308,7 +481,7 @@ impl NetDeviceOps for EthernetDevice {
             Some(Some(neighbor)) => {
                 if neighbor.expires_at > timestamp {
                     Self::send_to(
-                        &mut self.inner,
+                        self.inner.ops_mut(),
                         neighbor.hardware_address,
                         ip_packet.len(),
                         |buf| buf.copy_from_slice(ip_packet),
@@ -339,9 +512,92 @@ impl NetDeviceOps for EthernetDevice {
         false
     }
 
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Accepts MTUs up to the standard one, which the transmit buffers are
+    /// sized for.
+    fn set_mtu(&mut self, mtu: usize) -> KResult {
+        if !(MIN_MTU..=STANDARD_MTU).contains(&mtu) {
+            return Err(KError::InvalidInput);
+        }
+        self.mtu = mtu;
+        Ok(())
+    }
+
+    fn add_ipv4_addr(&mut self, addr: Ipv4Cidr) -> KResult {
+        if self.addrs.iter().any(|it| it.address() == addr.address()) {
+            return Err(KError::AlreadyExists);
+        }
+        self.addrs.push(addr);
+        Ok(())
+    }
+
     fn register_rx_waker(&self, waker: &Waker) {
-        if let Some(irq) = self.inner.irq() {
+        // No interrupt comes while the device is polled, so the waiter polls
+        // again right away.
+        if self.moderation.polling() {
+            waker.wake_by_ref();
+        } else if let Some(irq) = self.inner.ops().irq() {
             register_irq_waker(irq, waker);
         }
     }
+
+    fn capabilities(&self) -> NetCapabilities {
+        self.inner.ops().capabilities()
+    }
+
+    fn moderation(&self) -> Option<ModerationConfig> {
+        Some(self.moderation.config())
+    }
+
+    fn set_moderation(&mut self, config: ModerationConfig) -> KResult {
+        self.moderation.set_config(self.inner.ops_mut(), config)
+    }
+
+    fn set_promiscuous(&mut self, enable: bool) -> KResult {
+        match self.inner.ops_mut().set_promiscuous(enable) {
+            // Without hardware support, the NIC delivers what it delivers
+            // and the software filter does the rest.
+            Ok(()) | Err(DriverError::Unsupported) => {}
+            Err(err) => {
+                warn!("set_promiscuous failed: {:?}", err);
+                return Err(KError::Io);
+            }
+        }
+        self.filter.set_promiscuous(enable);
+        Ok(())
+    }
+
+    fn join_multicast(&mut self, group: IpAddress) -> KResult {
+        let addr = multicast_mac(group).ok_or(KError::InvalidInput)?;
+        if self.filter.join(addr) {
+            match self.inner.ops_mut().add_multicast(MacAddress(addr.0)) {
+                Ok(()) | Err(DriverError::Unsupported) => {}
+                Err(err) => {
+                    warn!("add_multicast {} failed: {:?}", addr, err);
+                    self.filter.leave(addr);
+                    return Err(KError::Io);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn leave_multicast(&mut self, group: IpAddress) -> KResult {
+        let addr = multicast_mac(group).ok_or(KError::InvalidInput)?;
+        match self.filter.leave(addr) {
+            None => Err(KError::from(LinuxError::EADDRNOTAVAIL)),
+            Some(false) => Ok(()),
+            Some(true) => {
+                if let Err(err) = self.inner.ops_mut().remove_multicast(MacAddress(addr.0))
+                    && !matches!(err, DriverError::Unsupported)
+                {
+                    warn!("remove_multicast {} failed: {:?}", addr, err);
+                }
+                Ok(())
+            }
+        }
+    }
 }
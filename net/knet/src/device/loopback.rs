@@ -6,11 +6,12 @@
 use alloc::vec;
 use core::task::Waker;
 
+use kdriver::prelude::NetCapabilities;
 use kpoll::PollSet;
 use smoltcp::{
     storage::{PacketBuffer, PacketMetadata},
     time::Instant,
-    wire::IpAddress,
+    wire::{IpAddress, IpProtocol, IpVersion, Ipv4Packet, Ipv6Packet, TcpPacket, UdpPacket},
 };
 
 use crate::{
@@ -37,6 +38,67 @@ impl LoopbackDevice {
     }
 }
 
+/// Fills in the checksums left zero by the stack in `packet`.
+///
+/// Filling in a correct checksum leaves it unchanged, so a TCP checksum that
+/// is legitimately zero is not a problem.
+pub(crate) fn fill_checksums(packet: &mut [u8]) {
+    let (src, dst, protocol, payload) = match IpVersion::of_packet(packet) {
+        Ok(IpVersion::Ipv4) => {
+            let Ok(mut ip) = Ipv4Packet::new_checked(packet) else {
+                return;
+            };
+            if ip.checksum() == 0 {
+                ip.fill_checksum();
+            }
+            if ip.more_frags() || ip.frag_offset() != 0 {
+                return;
+            }
+            let (src, dst, protocol) = (ip.src_addr(), ip.dst_addr(), ip.next_header());
+            let header_len = ip.header_len() as usize;
+            let total_len = ip.total_len() as usize;
+            (
+                IpAddress::Ipv4(src),
+                IpAddress::Ipv4(dst),
+                protocol,
+                &mut ip.into_inner()[header_len..total_len],
+            )
+        }
+        Ok(IpVersion::Ipv6) => {
+            let Ok(ip) = Ipv6Packet::new_checked(packet) else {
+                return;
+            };
+            let (src, dst, protocol) = (ip.src_addr(), ip.dst_addr(), ip.next_header());
+            let payload_len = ip.payload_len() as usize;
+            let header_len = ip.header_len();
+            (
+                IpAddress::Ipv6(src),
+                IpAddress::Ipv6(dst),
+                protocol,
+                &mut ip.into_inner()[header_len..header_len + payload_len],
+            )
+        }
+        _ => return,
+    };
+    match protocol {
+        IpProtocol::Tcp => {
+            if let Ok(mut tcp) = TcpPacket::new_checked(payload)
+                && tcp.checksum() == 0
+            {
+                tcp.fill_checksum(&src, &dst);
+            }
+        }
+        IpProtocol::Udp => {
+            if let Ok(mut udp) = UdpPacket::new_checked(payload)
+                && udp.checksum() == 0
+            {
+                udp.fill_checksum(&src, &dst);
+            }
+        }
+        _ => {}
+    }
+}
+
 impl NetDevice for LoopbackDevice {
     fn name(&self) -> &str {
         "lo"
@@ -61,6 +123,8 @@ impl NetDevice for LoopbackDevice {
         match self.queue.enqueue(ip_packet.len(), ()) {
             Ok(tx_buf) => {
                 tx_buf.copy_from_slice(ip_packet);
+                // The router may leave checksums to the other devices.
+                fill_checksums(tx_buf);
                 self.wakers.wake();
                 true
             }
@@ -77,4 +141,13 @@ impl NetDevice for LoopbackDevice {
     fn register_rx_waker(&self, waker: &Waker) {
         self.wakers.register(waker);
     }
+
+    fn capabilities(&self) -> NetCapabilities {
+        // Checksums are filled in on transmit and packets cannot be corrupted
+        // in memory, so there is nothing to verify on receive.
+        NetCapabilities::TX_CSUM_IPV4
+            | NetCapabilities::TX_CSUM_TCP
+            | NetCapabilities::TX_CSUM_UDP
+            | NetCapabilities::RX_CSUM
+    }
 }
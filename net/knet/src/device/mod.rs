@@ -5,7 +5,15 @@
 //! Network device abstractions.
 use core::task::Waker;
 
-use smoltcp::{storage::PacketBuffer, time::Instant, wire::IpAddress};
+use kdriver::prelude::{NetBufCpuIf, NetCapabilities};
+use kerrno::{KError, KResult};
+use smoltcp::{
+    storage::PacketBuffer,
+    time::Instant,
+    wire::{IpAddress, Ipv4Cidr},
+};
+
+use crate::{consts::STANDARD_MTU, moderation::ModerationConfig};
 
 mod ethernet;
 mod loopback;
@@ -17,6 +25,23 @@ pub use loopback::*;
 #[cfg(feature = "vsock")]
 pub use vsock::*;
 
+struct NetBufCpuImpl;
+
+#[crate_interface::impl_interface]
+impl NetBufCpuIf for NetBufCpuImpl {
+    fn this_cpu_id() -> usize {
+        khal::percpu::this_cpu_id()
+    }
+
+    fn cpu_num() -> usize {
+        platconfig::plat::CPU_NUM
+    }
+
+    fn cond_resched() {
+        ktask::cond_resched();
+    }
+}
+
 /// Trait implemented by network device backends.
 pub trait NetDevice: Send + Sync {
     fn name(&self) -> &str;
@@ -31,6 +56,61 @@ pub trait NetDevice: Send + Sync {
     fn send_ip_packet(&mut self, next_hop: IpAddress, ip_packet: &[u8], timestamp: Instant)
     -> bool;
 
+    /// Largest IP packet the device carries.
+    ///
+    /// The router fragments larger packets before handing them over.
+    fn mtu(&self) -> usize {
+        STANDARD_MTU
+    }
+
+    /// Changes the largest IP packet the device carries.
+    fn set_mtu(&mut self, _mtu: usize) -> KResult {
+        Err(KError::OperationNotSupported)
+    }
+
+    /// Assigns the IPv4 address `addr` to the device, for the link layer to
+    /// answer address resolution for it.
+    fn add_ipv4_addr(&mut self, _addr: Ipv4Cidr) -> KResult {
+        Ok(())
+    }
+
     /// Register a waker for receive readiness.
     fn register_rx_waker(&self, waker: &Waker);
+
+    /// Checksum offloads of the device.
+    ///
+    /// A device advertising a `TX_CSUM_*` bit accepts packets whose checksum
+    /// field of that kind is left zero, and fills it in before delivery.
+    fn capabilities(&self) -> NetCapabilities {
+        NetCapabilities::empty()
+    }
+
+    /// Enables or disables reception of frames addressed to other hosts.
+    fn set_promiscuous(&mut self, _enable: bool) -> KResult {
+        Ok(())
+    }
+
+    /// Starts receiving packets sent to the multicast `group`.
+    ///
+    /// Joins are counted: the device keeps receiving until every join has
+    /// been matched by a [`leave_multicast`](Self::leave_multicast).
+    fn join_multicast(&mut self, _group: IpAddress) -> KResult {
+        Ok(())
+    }
+
+    /// Drops one join of the multicast `group`.
+    fn leave_multicast(&mut self, _group: IpAddress) -> KResult {
+        Ok(())
+    }
+
+    /// Interrupt moderation settings of the device, or `None` if it raises
+    /// no interrupts.
+    fn moderation(&self) -> Option<ModerationConfig> {
+        None
+    }
+
+    /// Changes the interrupt moderation settings of the device.
+    fn set_moderation(&mut self, _config: ModerationConfig) -> KResult {
+        Err(KError::OperationNotSupported)
+    }
 }
@@ -228,32 +228,22 @@ pub fn vsock_listen(addr: VsockAddr) -> KResult<()> {
     Ok(())
 }
 
-fn map_dev_err(e: DriverError) -> KError {
-    match e {
-        DriverError::AlreadyExists => KError::AlreadyExists,
-        DriverError::WouldBlock => KError::WouldBlock,
-        DriverError::InvalidInput => KError::InvalidInput,
-        DriverError::Io => KError::Io,
-        _ => KError::BadState,
-    }
-}
-
 pub fn vsock_connect(conn_id: VsockConnId) -> KResult<()> {
     let mut guard = VSOCK_DEV.lock();
     let dev = guard.as_mut().ok_or(KError::NotFound)?;
-    dev.connect(conn_id).map_err(map_dev_err)
+    dev.connect(conn_id).map_err(KError::from)
 }
 
 pub fn vsock_send(conn_id: VsockConnId, buf: &[u8]) -> KResult<usize> {
     let mut guard = VSOCK_DEV.lock();
     let dev = guard.as_mut().ok_or(KError::NotFound)?;
-    dev.send(conn_id, buf).map_err(map_dev_err)
+    dev.send(conn_id, buf).map_err(KError::from)
 }
 
 pub fn vsock_disconnect(conn_id: VsockConnId) -> KResult<()> {
     let mut guard = VSOCK_DEV.lock();
     let dev = guard.as_mut().ok_or(KError::NotFound)?;
-    dev.disconnect(conn_id).map_err(map_dev_err)
+    dev.disconnect(conn_id).map_err(KError::from)
 }
 
 pub fn vsock_guest_cid() -> KResult<u64> {
@@ -0,0 +1,583 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! IP fragmentation and reassembly.
+//!
+//! Datagrams larger than the MTU of their egress device are split by
+//! [`fragment_ipv4`]. On input, the fragments of IPv4 and IPv6 datagrams go
+//! through the same reassembler, which queues them per source,
+//! destination, identification and protocol until the datagram is complete.
+//!
+//! As RFC 5722 requires, a fragment overlapping another one discards the
+//! whole datagram, since overlaps can only come from a broken or hostile
+//! sender. Exact duplicates are dropped on their own. A queue lives at most
+//! [`IPFRAG_TIMEOUT`], after which an ICMP time exceeded error is sent back
+//! if its first fragment arrived, and all queues together are charged
+//! against [`IPFRAG_MEM_LIMIT`], beyond which new fragments are dropped.
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use hashbrown::HashMap;
+use smoltcp::{
+    phy::ChecksumCapabilities,
+    time::{Duration, Instant},
+    wire::{IpAddress, IpProtocol, IpVersion, Ipv4Packet, Ipv4Repr, Ipv6Packet, Ipv6Repr},
+};
+
+use crate::SERVICE;
+
+/// Largest IP datagram, headers included.
+pub const IP_MAX_DATAGRAM: usize = 65535;
+/// Memory all reassembly queues together may take, in bytes.
+pub const IPFRAG_MEM_LIMIT: usize = 4 * 1024 * 1024;
+/// How long the fragments of a datagram may take to arrive.
+pub const IPFRAG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Memory charged for each queued fragment on top of its payload.
+const FRAGMENT_OVERHEAD: usize = 64;
+/// Memory charged for each reassembly queue on top of its fragments.
+const QUEUE_OVERHEAD: usize = 256;
+
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_FRAG_HEADER_LEN: usize = 8;
+/// Most bytes of an offending datagram an ICMPv6 error quotes, so that the
+/// error fits the IPv6 minimum MTU.
+const ICMPV6_QUOTE_LEN: usize = 1280 - IPV6_HEADER_LEN - 8;
+
+/// Reassembly counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FragStats {
+    /// Datagrams reassembled.
+    pub reassembled: u64,
+    /// Datagrams abandoned because not all of their fragments arrived in
+    /// time.
+    pub timeouts: u64,
+    /// Datagrams discarded because two of their fragments overlapped.
+    pub overlaps: u64,
+    /// Datagrams discarded because their fragments were inconsistent, or
+    /// added up to more than [`IP_MAX_DATAGRAM`].
+    pub invalid: u64,
+    /// Fragments dropped to stay within [`IPFRAG_MEM_LIMIT`].
+    pub mem_drops: u64,
+}
+
+/// Returns the reassembly counters of the network stack.
+pub fn frag_stats() -> FragStats {
+    SERVICE.lock().frag_stats()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FragKey {
+    src: IpAddress,
+    dst: IpAddress,
+    id: u32,
+    protocol: u8,
+}
+
+/// A fragment of an IPv4 or IPv6 datagram.
+struct Fragment<'a> {
+    key: FragKey,
+    /// Unfragmentable part: the IP header and, for IPv6, the extension
+    /// headers before the fragment header.
+    header: &'a [u8],
+    /// Offset in `header` of the field naming the protocol of the payload.
+    next_header_at: usize,
+    /// Protocol of the payload.
+    next_header: u8,
+    /// Offset of `payload` in the datagram, in bytes.
+    offset: usize,
+    /// Whether more fragments follow.
+    more: bool,
+    payload: &'a [u8],
+    /// Largest datagram payload that fits in the length field with `header`.
+    max_len: usize,
+    /// Leading bytes of the packet, quoted by ICMP errors.
+    quote: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        match IpVersion::of_packet(packet).ok()? {
+            IpVersion::Ipv4 => Self::parse_ipv4(packet),
+            IpVersion::Ipv6 => Self::parse_ipv6(packet),
+        }
+    }
+
+    fn parse_ipv4(packet: &'a [u8]) -> Option<Self> {
+        let ip = Ipv4Packet::new_checked(packet).ok()?;
+        if (!ip.more_frags() && ip.frag_offset() == 0) || !ip.verify_checksum() {
+            return None;
+        }
+        let header_len = ip.header_len() as usize;
+        let total_len = ip.total_len() as usize;
+        let protocol = u8::from(ip.next_header());
+        Some(Self {
+            key: FragKey {
+                src: IpAddress::Ipv4(ip.src_addr()),
+                dst: IpAddress::Ipv4(ip.dst_addr()),
+                id: ip.ident() as u32,
+                protocol,
+            },
+            header: &packet[..header_len],
+            next_header_at: 9,
+            next_header: protocol,
+            offset: ip.frag_offset() as usize,
+            more: ip.more_frags(),
+            payload: &packet[header_len..total_len],
+            max_len: IP_MAX_DATAGRAM - header_len,
+            quote: &packet[..total_len.min(header_len + 8)],
+        })
+    }
+
+    fn parse_ipv6(packet: &'a [u8]) -> Option<Self> {
+        let ip = Ipv6Packet::new_checked(packet).ok()?;
+        let packet = &packet[..IPV6_HEADER_LEN + ip.payload_len() as usize];
+
+        // Skip the extension headers that precede the fragment header.
+        let mut next_header_at = 6;
+        let mut at = IPV6_HEADER_LEN;
+        loop {
+            match IpProtocol::from(packet[next_header_at]) {
+                IpProtocol::HopByHop | IpProtocol::Ipv6Route | IpProtocol::Ipv6Opts => {
+                    let len = (*packet.get(at + 1)? as usize + 1) * 8;
+                    next_header_at = at;
+                    at += len;
+                }
+                IpProtocol::Ipv6Frag => break,
+                _ => return None,
+            }
+        }
+        let frag = packet.get(at..at + IPV6_FRAG_HEADER_LEN)?;
+        let offset = u16::from_be_bytes([frag[2], frag[3]]);
+        Some(Self {
+            key: FragKey {
+                src: IpAddress::Ipv6(ip.src_addr()),
+                dst: IpAddress::Ipv6(ip.dst_addr()),
+                id: u32::from_be_bytes([frag[4], frag[5], frag[6], frag[7]]),
+                // Only the first fragment names the payload protocol.
+                protocol: IpProtocol::Ipv6Frag.into(),
+            },
+            header: &packet[..at],
+            next_header_at,
+            next_header: frag[0],
+            offset: (offset & !7) as usize,
+            more: offset & 1 != 0,
+            payload: &packet[at + IPV6_FRAG_HEADER_LEN..],
+            max_len: (IP_MAX_DATAGRAM + IPV6_HEADER_LEN).saturating_sub(at),
+            quote: &packet[..packet.len().min(ICMPV6_QUOTE_LEN)],
+        })
+    }
+
+    fn end(&self) -> usize {
+        self.offset + self.payload.len()
+    }
+}
+
+/// Returns whether `packet` is a fragment to hand to a [`Reassembler`].
+pub(crate) fn is_fragment(packet: &[u8]) -> bool {
+    Fragment::parse(packet).is_some()
+}
+
+enum Insert {
+    Queued,
+    Duplicate,
+    Overlap,
+    Invalid,
+}
+
+/// Fragments of a datagram.
+struct FragQueue {
+    /// Payloads by offset.
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// Unfragmentable part of the first fragment.
+    header: Vec<u8>,
+    next_header_at: usize,
+    next_header: u8,
+    /// Leading bytes of the first fragment.
+    quote: Vec<u8>,
+    /// Length of the datagram payload, once its last fragment arrived.
+    len: Option<usize>,
+    /// Payload bytes received.
+    received: usize,
+    /// Memory charged for the queue.
+    mem: usize,
+    expires_at: Instant,
+}
+
+impl FragQueue {
+    fn new(expires_at: Instant) -> Self {
+        Self {
+            fragments: BTreeMap::new(),
+            header: Vec::new(),
+            next_header_at: 0,
+            next_header: 0,
+            quote: Vec::new(),
+            len: None,
+            received: 0,
+            mem: 0,
+            expires_at,
+        }
+    }
+
+    fn has_first(&self) -> bool {
+        self.fragments.contains_key(&0)
+    }
+
+    fn insert(&mut self, frag: &Fragment) -> Insert {
+        let (start, end) = (frag.offset, frag.end());
+        if let Some((&prev, data)) = self.fragments.range(..=start).next_back() {
+            if prev == start && data.len() == frag.payload.len() {
+                return Insert::Duplicate;
+            }
+            if prev + data.len() > start {
+                return Insert::Overlap;
+            }
+        }
+        if let Some((&next, _)) = self.fragments.range(start + 1..).next()
+            && next < end
+        {
+            return Insert::Overlap;
+        }
+
+        if frag.more {
+            if self.len.is_some_and(|len| end > len) {
+                return Insert::Invalid;
+            }
+        } else {
+            let last_end = self
+                .fragments
+                .last_key_value()
+                .map_or(0, |(offset, data)| offset + data.len());
+            if self.len.is_some_and(|len| len != end) || last_end > end {
+                return Insert::Invalid;
+            }
+            self.len = Some(end);
+        }
+
+        if start == 0 {
+            self.header = frag.header.to_vec();
+            self.next_header_at = frag.next_header_at;
+            self.next_header = frag.next_header;
+            self.quote = frag.quote.to_vec();
+        }
+        self.fragments.insert(start, frag.payload.to_vec());
+        self.received += frag.payload.len();
+        Insert::Queued
+    }
+
+    fn is_complete(&self) -> bool {
+        // Fragments never overlap, so they cover the datagram once their
+        // lengths add up.
+        self.has_first() && self.len == Some(self.received)
+    }
+
+    fn assemble(self) -> Option<Vec<u8>> {
+        let mut packet = Vec::with_capacity(self.header.len() + self.len?);
+        packet.extend_from_slice(&self.header);
+        packet[self.next_header_at] = self.next_header;
+        for data in self.fragments.values() {
+            packet.extend_from_slice(data);
+        }
+        match IpVersion::of_packet(&packet).ok()? {
+            IpVersion::Ipv4 => {
+                let total_len = u16::try_from(packet.len()).ok()?;
+                let mut ip = Ipv4Packet::new_unchecked(&mut packet[..]);
+                ip.set_total_len(total_len);
+                ip.set_more_frags(false);
+                ip.set_frag_offset(0);
+                ip.fill_checksum();
+            }
+            IpVersion::Ipv6 => {
+                let payload_len = u16::try_from(packet.len() - IPV6_HEADER_LEN).ok()?;
+                Ipv6Packet::new_unchecked(&mut packet[..]).set_payload_len(payload_len);
+            }
+        }
+        Some(packet)
+    }
+}
+
+/// Reassembles fragmented IPv4 and IPv6 datagrams.
+pub(crate) struct Reassembler {
+    queues: HashMap<FragKey, FragQueue>,
+    /// Memory charged for all queues.
+    mem: usize,
+    mem_limit: usize,
+    timeout: Duration,
+    stats: FragStats,
+}
+
+impl Reassembler {
+    pub fn new(mem_limit: usize, timeout: Duration) -> Self {
+        Self {
+            queues: HashMap::new(),
+            mem: 0,
+            mem_limit,
+            timeout,
+            stats: FragStats::default(),
+        }
+    }
+
+    /// Returns the memory charged for the queued fragments, in bytes.
+    pub fn mem_usage(&self) -> usize {
+        self.mem
+    }
+
+    pub fn stats(&self) -> FragStats {
+        self.stats
+    }
+
+    /// Queues the fragment `packet`, returning the datagram it completes.
+    ///
+    /// Packets that are not fragments are ignored.
+    pub fn process(&mut self, packet: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let frag = Fragment::parse(packet)?;
+        let key = frag.key;
+        if frag.end() > frag.max_len || (frag.more && !frag.payload.len().is_multiple_of(8)) {
+            self.stats.invalid += 1;
+            self.drop_queue(&key);
+            return None;
+        }
+
+        let mut cost = frag.payload.len() + FRAGMENT_OVERHEAD;
+        if frag.offset == 0 {
+            cost += frag.header.len() + frag.quote.len();
+        }
+        if !self.queues.contains_key(&key) {
+            cost += QUEUE_OVERHEAD;
+        }
+        if self.mem + cost > self.mem_limit {
+            self.stats.mem_drops += 1;
+            return None;
+        }
+
+        let expires_at = now + self.timeout;
+        let queue = self
+            .queues
+            .entry(key)
+            .or_insert_with(|| FragQueue::new(expires_at));
+        match queue.insert(&frag) {
+            Insert::Queued => {}
+            Insert::Duplicate => return None,
+            Insert::Overlap => {
+                self.stats.overlaps += 1;
+                self.drop_queue(&key);
+                return None;
+            }
+            Insert::Invalid => {
+                self.stats.invalid += 1;
+                self.drop_queue(&key);
+                return None;
+            }
+        }
+        queue.mem += cost;
+        self.mem += cost;
+        if !queue.is_complete() {
+            return None;
+        }
+
+        let queue = self.queues.remove(&key).unwrap();
+        self.mem -= queue.mem;
+        let packet = queue.assemble();
+        if packet.is_some() {
+            self.stats.reassembled += 1;
+        } else {
+            self.stats.invalid += 1;
+        }
+        packet
+    }
+
+    /// Drops the queues that timed out by `now`.
+    ///
+    /// For each such queue whose first fragment arrived, `f` is called with
+    /// the source of the datagram and the leading bytes of that fragment, to
+    /// send an ICMP time exceeded error. Datagrams sent to a broadcast or
+    /// multicast address are not answered.
+    pub fn expire(&mut self, now: Instant, mut f: impl FnMut(IpAddress, &[u8])) {
+        self.queues.retain(|key, queue| {
+            if queue.expires_at > now {
+                return true;
+            }
+            self.mem -= queue.mem;
+            self.stats.timeouts += 1;
+            if queue.has_first() && !key.dst.is_broadcast() && !key.dst.is_multicast() {
+                f(key.src, &queue.quote);
+            }
+            false
+        });
+    }
+
+    fn drop_queue(&mut self, key: &FragKey) {
+        if let Some(queue) = self.queues.remove(key) {
+            self.mem -= queue.mem;
+        }
+    }
+}
+
+/// Builds a copy of the IPv4 `header` keeping only the options flagged to be
+/// copied into every fragment.
+fn copied_options(header: &[u8]) -> Vec<u8> {
+    let mut copied = header[..20].to_vec();
+    let mut options = &header[20..];
+    while let Some(&kind) = options.first() {
+        let len = match kind {
+            // End of option list.
+            0 => break,
+            // No operation.
+            1 => 1,
+            _ => options
+                .get(1)
+                .map_or(options.len(), |&len| (len as usize).clamp(2, options.len())),
+        };
+        if kind & 0x80 != 0 {
+            copied.extend_from_slice(&options[..len]);
+        }
+        options = &options[len..];
+    }
+    copied.resize(copied.len().next_multiple_of(4), 0);
+    copied
+}
+
+/// Splits the IPv4 `packet` into fragments of at most `mtu` bytes, passing
+/// each one to `f`.
+///
+/// The fragments carry the identification `ident` and have their DF flag
+/// cleared. `packet` may itself be a fragment.
+pub(crate) fn fragment_ipv4(packet: &[u8], mtu: usize, ident: u16, mut f: impl FnMut(&[u8])) {
+    let ip = Ipv4Packet::new_unchecked(packet);
+    let header_len = ip.header_len() as usize;
+    let payload = &packet[header_len..ip.total_len() as usize];
+    let (base, more) = (ip.frag_offset() as usize, ip.more_frags());
+    let later_header = copied_options(&packet[..header_len]);
+    assert!(mtu >= header_len + 8, "MTU too small to fragment");
+
+    let mut buf = Vec::with_capacity(mtu);
+    let mut offset = 0;
+    while offset < payload.len() {
+        let header = if offset == 0 {
+            &packet[..header_len]
+        } else {
+            &later_header[..]
+        };
+        let len = ((mtu - header.len()) & !7).min(payload.len() - offset);
+        let last = offset + len == payload.len();
+        buf.clear();
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(&payload[offset..offset + len]);
+
+        let total_len = buf.len() as u16;
+        let mut frag = Ipv4Packet::new_unchecked(&mut buf[..]);
+        frag.set_header_len(header.len() as u8);
+        frag.set_total_len(total_len);
+        frag.set_ident(ident);
+        frag.set_dont_frag(false);
+        frag.set_more_frags(more || !last);
+        frag.set_frag_offset((base + offset) as u16);
+        frag.fill_checksum();
+        f(&buf);
+        offset += len;
+    }
+}
+
+/// ICMP errors about datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IcmpError {
+    /// The fragments of a datagram did not all arrive in time.
+    ReassemblyTimeout,
+    /// A datagram does not fit the MTU of the next hop, and may not be
+    /// fragmented.
+    PacketTooBig(usize),
+}
+
+/// Computes the Internet checksum of the concatenation of `parts`, each of
+/// them but the last having an even length.
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = parts
+        .iter()
+        .flat_map(|part| part.chunks(2))
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds an ICMP `error` from `src` about the datagram starting with
+/// `quote`, addressed to its source.
+pub(crate) fn icmp_error(src: IpAddress, quote: &[u8], error: IcmpError) -> Option<Vec<u8>> {
+    match (src, IpVersion::of_packet(quote).ok()?) {
+        (IpAddress::Ipv4(src_addr), IpVersion::Ipv4) => {
+            if quote.len() < 20 {
+                return None;
+            }
+            let ip = Ipv4Packet::new_unchecked(quote);
+            let quote = &quote[..quote.len().min(ip.header_len() as usize + 8)];
+            let (msg_type, code, rest) = match error {
+                IcmpError::ReassemblyTimeout => (11, 1, [0; 4]),
+                IcmpError::PacketTooBig(mtu) => {
+                    let [hi, lo] = (mtu.min(u16::MAX as usize) as u16).to_be_bytes();
+                    (3, 4, [0, 0, hi, lo])
+                }
+            };
+            let repr = Ipv4Repr {
+                src_addr,
+                dst_addr: ip.src_addr(),
+                next_header: IpProtocol::Icmp,
+                payload_len: 8 + quote.len(),
+                hop_limit: 64,
+            };
+            let mut packet = vec![0; repr.buffer_len() + repr.payload_len];
+            repr.emit(
+                &mut Ipv4Packet::new_unchecked(&mut packet[..]),
+                &ChecksumCapabilities::default(),
+            );
+            let icmp = &mut packet[repr.buffer_len()..];
+            icmp[0] = msg_type;
+            icmp[1] = code;
+            icmp[4..8].copy_from_slice(&rest);
+            icmp[8..].copy_from_slice(quote);
+            let checksum = internet_checksum(&[&*icmp]);
+            icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+            Some(packet)
+        }
+        (IpAddress::Ipv6(src_addr), IpVersion::Ipv6) => {
+            if quote.len() < IPV6_HEADER_LEN {
+                return None;
+            }
+            let ip = Ipv6Packet::new_unchecked(quote);
+            let quote = &quote[..quote.len().min(ICMPV6_QUOTE_LEN)];
+            let (msg_type, code, rest) = match error {
+                IcmpError::ReassemblyTimeout => (3, 1, [0; 4]),
+                IcmpError::PacketTooBig(mtu) => (2, 0, (mtu as u32).to_be_bytes()),
+            };
+            let repr = Ipv6Repr {
+                src_addr,
+                dst_addr: ip.src_addr(),
+                next_header: IpProtocol::Icmpv6,
+                payload_len: 8 + quote.len(),
+                hop_limit: 64,
+            };
+            let mut packet = vec![0; repr.buffer_len() + repr.payload_len];
+            repr.emit(&mut Ipv6Packet::new_unchecked(&mut packet[..]));
+            let icmp = &mut packet[repr.buffer_len()..];
+            icmp[0] = msg_type;
+            icmp[1] = code;
+            icmp[4..8].copy_from_slice(&rest);
+            icmp[8..].copy_from_slice(quote);
+            // The checksum covers a pseudo-header with the addresses, the
+            // length and the protocol.
+            let mut pseudo_header = [0; 8];
+            pseudo_header[..4].copy_from_slice(&(icmp.len() as u32).to_be_bytes());
+            pseudo_header[7] = IpProtocol::Icmpv6.into();
+            let checksum = internet_checksum(&[
+                &src_addr.octets()[..],
+                &repr.dst_addr.octets()[..],
+                &pseudo_header[..],
+                &*icmp,
+            ]);
+            icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+            Some(packet)
+        }
+        _ => None,
+    }
+}
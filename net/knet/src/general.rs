@@ -9,7 +9,7 @@ use core::{
     time::Duration,
 };
 
-use kerrno::KResult;
+use kerrno::{KError, KResult};
 use kpoll::{IoEvents, Pollable};
 use ktask::future::{block_on, poll_io, timeout};
 
@@ -25,6 +25,7 @@ pub(crate) struct GeneralOptions {
     /// Whether the socket should reuse the address.
     reuse_address: AtomicBool,
 
+    /// `SO_SNDTIMEO` and `SO_RCVTIMEO` in nanoseconds, 0 for none.
     send_timeout_nanos: AtomicU64,
     recv_timeout_nanos: AtomicU64,
 
@@ -87,29 +88,51 @@ impl GeneralOptions {
     }
 
     /// Poll for send readiness and run the provided operation.
+    ///
+    /// Fails with `EAGAIN` once the send timeout expires.
     pub fn send_poller<P: Pollable, F: FnMut() -> KResult<T>, T>(
         &self,
         pollable: &P,
         f: F,
     ) -> KResult<T> {
-        block_on(timeout(
+        block_on_timeout(
             self.send_timeout(),
             poll_io(pollable, IoEvents::OUT, self.nonblocking(), f),
-        ))?
+        )
     }
 
     /// Poll for receive readiness and run the provided operation.
+    ///
+    /// Fails with `EAGAIN` once the receive timeout expires.
     pub fn recv_poller<P: Pollable, F: FnMut() -> KResult<T>, T>(
         &self,
         pollable: &P,
         f: F,
     ) -> KResult<T> {
-        block_on(timeout(
+        block_on_timeout(
             self.recv_timeout(),
             poll_io(pollable, IoEvents::IN, self.nonblocking(), f),
-        ))?
+        )
     }
 }
+
+/// Blocks on `f` for at most `duration`, then fails with `EAGAIN` as Linux
+/// does for a socket timeout.
+///
+/// `f` is polled before the timer, so a signal that arrives by the deadline
+/// still fails it with `EINTR`.
+fn block_on_timeout<T>(
+    duration: Option<Duration>,
+    f: impl Future<Output = KResult<T>>,
+) -> KResult<T> {
+    block_on(timeout(duration, f)).unwrap_or(Err(KError::WouldBlock))
+}
+
+/// Returns a timeout in nanoseconds as stored, saturating those too long to
+/// ever expire.
+fn timeout_nanos(timeout: &Duration) -> u64 {
+    u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX)
+}
 impl Configurable for GeneralOptions {
     fn get_option_inner(&self, option: &mut GetSocketOption) -> KResult<bool> {
         use GetSocketOption as O;
@@ -147,11 +170,11 @@ impl Configurable for GeneralOptions {
             }
             O::SendTimeout(timeout) => {
                 self.send_timeout_nanos
-                    .store(timeout.as_nanos() as u64, Ordering::Relaxed);
+                    .store(timeout_nanos(timeout), Ordering::Relaxed);
             }
             O::ReceiveTimeout(timeout) => {
                 self.recv_timeout_nanos
-                    .store(timeout.as_nanos() as u64, Ordering::Relaxed);
+                    .store(timeout_nanos(timeout), Ordering::Relaxed);
             }
             O::SendBuffer(_) | O::ReceiveBuffer(_) => {
                 // TODO(mivik): implement buffer size options
@@ -11,6 +11,7 @@
 //! - [`TcpSocket`]: A TCP socket that provides POSIX-like APIs.
 //! - [`UdpSocket`]: A UDP socket that provides POSIX-like APIs.
 //! - [`dns_query`]: Function for DNS query.
+//! - [`netconfig`]: Boot-time configuration of network interfaces.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
@@ -24,10 +25,16 @@ extern crate alloc;
 
 mod consts;
 mod device;
+pub mod frag;
 mod general;
 mod listen_table;
+pub mod mem;
+pub mod moderation;
+pub mod netconfig;
 pub mod options;
 mod router;
+#[cfg(unittest)]
+pub mod selftest;
 mod service;
 mod socket;
 pub(crate) mod state;
@@ -38,21 +45,35 @@ pub mod unix;
 pub mod vsock;
 mod wrapper;
 
+mod test_checksum;
+mod test_frag;
+mod test_ip_conformance;
+mod test_mem;
+mod test_moderation;
+mod test_netconfig;
 mod test_options;
 mod test_state;
-
-use alloc::{borrow::ToOwned, boxed::Box};
+mod test_tcp_conformance;
+mod test_timeout;
+mod test_unix;
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use core::{
+    net::{IpAddr, Ipv4Addr},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use kdriver::{DeviceContainer, prelude::*};
+use kerrno::{KError, KResult};
 use ksync::Mutex;
 use lazyinit::LazyInit;
 use smoltcp::wire::{EthernetAddress, Ipv4Address, Ipv4Cidr};
 pub use socket::*;
 
 use crate::{
-    consts::{GATEWAY, IP, IP_PREFIX},
-    device::{EthernetDevice, LoopbackDevice},
+    device::{EthernetDevice, LoopbackDevice, NetDevice as _},
     listen_table::ListenTable,
+    moderation::ModerationConfig,
     router::{Router, Rule},
     service::Service,
     wrapper::SocketSetWrapper,
@@ -63,10 +84,23 @@ static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 
 static SERVICE: LazyInit<Mutex<Service>> = LazyInit::new();
 
+/// Number of the next Ethernet interface, as in `eth0`.
+static NEXT_ETH: AtomicUsize = AtomicUsize::new(0);
+
+fn ethernet_device(dev: NetDevice) -> EthernetDevice {
+    let name = format!("eth{}", NEXT_ETH.fetch_add(1, Ordering::Relaxed));
+    info!("  use NIC {:?} as {name}", dev.name());
+    info!("  mac:  {}", EthernetAddress(dev.mac().0));
+    EthernetDevice::new(name, dev)
+}
+
 /// Initializes the network subsystem by NIC devices.
 pub fn init_network(mut net_devs: DeviceContainer<NetDevice>) {
     info!("Initialize network subsystem...");
 
+    let bootargs = khal::dtb::get_chosen_bootargs().unwrap_or("");
+    mem::apply_boot_args(bootargs);
+
     let mut router = Router::new();
     let lo_dev = router.add_device(Box::new(LoopbackDevice::new()));
 
@@ -78,34 +112,12 @@ pub fn init_network(mut net_devs: DeviceContainer<NetDevice>) {
         lo_ip.address().into(),
     ));
 
-    let eth0_ip = if let Some(dev) = net_devs.take_one() {
-        info!("  use NIC 0: {:?}", dev.name());
-
-        let eth0_address = EthernetAddress(dev.mac().0);
-        let eth0_ip = Ipv4Cidr::new(IP.parse().expect("Invalid IPv4 address"), IP_PREFIX);
-
-        let eth0_dev = router.add_device(Box::new(EthernetDevice::new(
-            "eth0".to_owned(),
-            dev,
-            eth0_ip,
-        )));
-
-        router.add_rule(Rule::new(
-            Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).into(),
-            Some(GATEWAY.parse().expect("Invalid gateway address")),
-            eth0_dev,
-            eth0_ip.address().into(),
-        ));
-
-        info!("eth0:");
-        info!("  mac:  {}", eth0_address);
-        info!("  ip:   {}", eth0_ip);
-
-        Some(eth0_ip)
-    } else {
+    while let Some(dev) = net_devs.take_one() {
+        router.add_device(Box::new(ethernet_device(dev)));
+    }
+    if router.devices.len() == 1 {
         warn!("  No network device found!");
-        None
-    };
+    }
 
     for dev in &router.devices {
         info!("Device: {}", dev.name());
@@ -114,14 +126,58 @@ pub fn init_network(mut net_devs: DeviceContainer<NetDevice>) {
     let mut service = Service::new(router);
     service.iface.update_ip_addrs(|ip_addrs| {
         ip_addrs.push(lo_ip.into()).unwrap();
-        if let Some(eth0_ip) = eth0_ip {
-            ip_addrs.push(eth0_ip.into()).unwrap();
-        }
     });
     SERVICE.init_once(Mutex::new(service));
 
     SOCKET_SET.init_once(SocketSetWrapper::new());
     LISTEN_TABLE.init_once(ListenTable::new());
+
+    netconfig::init(bootargs);
+}
+
+/// Adds the NIC `dev`, which appeared after [`init_network`], such as a USB
+/// NIC, and returns the name of its interface.
+///
+/// The boot network configuration of the interface is then applied.
+pub fn add_interface(dev: NetDevice) -> String {
+    let dev = ethernet_device(dev);
+    let name = dev.name().into();
+    SERVICE.lock().add_device(Box::new(dev));
+    netconfig::interface_added();
+    name
+}
+
+/// Returns the names of the network interfaces.
+pub fn interfaces() -> Vec<String> {
+    SERVICE.lock().device_names()
+}
+
+/// Changes the MTU of the network interface `name`.
+pub fn set_mtu(name: &str, mtu: usize) -> KResult {
+    SERVICE.lock().set_mtu(name, mtu)
+}
+
+/// Assigns the address `addr`, on a subnet of `prefix_len` bits, to the
+/// network interface `name`, and routes the subnet through it.
+pub fn add_ipv4_addr(name: &str, addr: Ipv4Addr, prefix_len: u8) -> KResult {
+    if prefix_len > 32 {
+        return Err(KError::InvalidInput);
+    }
+    SERVICE
+        .lock()
+        .add_ipv4_addr(name, Ipv4Cidr::new(addr, prefix_len))
+}
+
+/// Routes the subnet `dest` of `prefix_len` bits through the gateway `via`.
+///
+/// The gateway must be on the subnet of an address of the interface `dev`,
+/// or of any interface if `None`; otherwise this fails with `ENETUNREACH`.
+pub fn add_ipv4_route(dest: Ipv4Addr, prefix_len: u8, via: Ipv4Addr, dev: Option<&str>) -> KResult {
+    if prefix_len > 32 {
+        return Err(KError::InvalidInput);
+    }
+    let dest = Ipv4Cidr::new(dest, prefix_len).network();
+    SERVICE.lock().add_route(dest.into(), via.into(), dev)
 }
 
 /// Init vsock subsystem by vsock devices.
@@ -139,6 +195,35 @@ pub fn init_vsock(mut vsock_devs: DeviceContainer<VsockDevice>) {
     }
 }
 
+/// Joins the multicast `group` on all network devices.
+///
+/// Joins are counted; the group is left once every join has been matched by
+/// [`leave_multicast_group`].
+pub fn join_multicast_group(group: IpAddr) -> KResult {
+    SERVICE.lock().join_multicast(group.into())
+}
+
+/// Drops one join of the multicast `group`.
+pub fn leave_multicast_group(group: IpAddr) -> KResult {
+    SERVICE.lock().leave_multicast(group.into())
+}
+
+/// Switches all network devices in or out of promiscuous mode.
+pub fn set_promiscuous(enable: bool) -> KResult {
+    SERVICE.lock().set_promiscuous(enable)
+}
+
+/// Returns the interrupt moderation settings of the network device `name`.
+pub fn moderation(name: &str) -> KResult<ModerationConfig> {
+    SERVICE.lock().moderation(name)
+}
+
+/// Changes the interrupt moderation settings of the network device `name`.
+pub fn set_moderation(name: &str, config: ModerationConfig) -> KResult {
+    SERVICE.lock().set_moderation(name, config)
+}
+
 pub fn poll_interfaces() {
     while SERVICE.lock().poll(&mut SOCKET_SET.inner.lock()) {}
+    SOCKET_SET.account();
 }
@@ -0,0 +1,403 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Socket memory accounting.
+//!
+//! Data queued in the receive and send buffers of TCP and UDP sockets is
+//! charged to its protocol's budget and to a global one. As with Linux'
+//! `tcp_mem`, every budget has three thresholds:
+//!
+//! - below `min`, usage is not regulated;
+//! - above `pressure`, the budget is under memory pressure until usage falls
+//!   back below `min`;
+//! - `max` is a hard cap.
+//!
+//! Under pressure, a socket already holding more than
+//! [`PRESSURE_SOCKET_QUOTA`] may not queue more: datagrams beyond the quota
+//! are dropped from UDP receive queues, and sends on either protocol wait
+//! until memory is released. A TCP receive queue is never trimmed, since its
+//! data has already been acknowledged; it is bounded by the advertised window.
+//!
+//! The limits can be tuned at boot with `net.mem=`, `net.tcp_mem=` and
+//! `net.udp_mem=`, each taking `min,pressure,max` in bytes.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use kerrno::{KError, KResult};
+
+use crate::consts::{SOCK_MIN_BUF, TCP_RX_BUF_LEN, TCP_TX_BUF_LEN, UDP_RX_BUF_LEN, UDP_TX_BUF_LEN};
+
+/// Queued bytes a socket may keep while its budget is under pressure.
+pub const PRESSURE_SOCKET_QUOTA: usize = 16 * 1024;
+
+/// Thresholds of a memory budget, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemLimits {
+    /// Usage below which a pressure state ends.
+    pub min: usize,
+    /// Usage above which a pressure state begins.
+    pub pressure: usize,
+    /// Hard cap.
+    pub max: usize,
+}
+
+impl MemLimits {
+    fn is_valid(&self) -> bool {
+        self.min <= self.pressure && self.pressure <= self.max
+    }
+}
+
+/// A memory budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemScope {
+    /// All TCP and UDP sockets together.
+    Global,
+    /// TCP sockets.
+    Tcp,
+    /// UDP sockets.
+    Udp,
+}
+
+/// Usage statistics of a memory budget.
+#[derive(Debug, Clone, Copy)]
+pub struct MemUsage {
+    /// Bytes currently charged.
+    pub usage: usize,
+    /// Current thresholds.
+    pub limits: MemLimits,
+    /// Whether the budget is under pressure.
+    pub under_pressure: bool,
+    /// Number of times the budget entered the pressure state.
+    pub pressure_events: u64,
+    /// Number of times a socket was refused more memory.
+    pub declined: u64,
+    /// Number of received datagrams dropped to stay within the budget.
+    pub dropped: u64,
+}
+
+/// Usage statistics of all socket memory budgets.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketMemStats {
+    /// All sockets together.
+    pub global: MemUsage,
+    /// TCP sockets.
+    pub tcp: MemUsage,
+    /// UDP sockets.
+    pub udp: MemUsage,
+}
+
+struct Budget {
+    min: AtomicUsize,
+    pressure: AtomicUsize,
+    max: AtomicUsize,
+    usage: AtomicUsize,
+    under_pressure: AtomicBool,
+    pressure_events: AtomicU64,
+    declined: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Budget {
+    const fn new(limits: MemLimits) -> Self {
+        Self {
+            min: AtomicUsize::new(limits.min),
+            pressure: AtomicUsize::new(limits.pressure),
+            max: AtomicUsize::new(limits.max),
+            usage: AtomicUsize::new(0),
+            under_pressure: AtomicBool::new(false),
+            pressure_events: AtomicU64::new(0),
+            declined: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn limits(&self) -> MemLimits {
+        MemLimits {
+            min: self.min.load(Ordering::Relaxed),
+            pressure: self.pressure.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_limits(&self, limits: MemLimits) {
+        self.min.store(limits.min, Ordering::Relaxed);
+        self.pressure.store(limits.pressure, Ordering::Relaxed);
+        self.max.store(limits.max, Ordering::Relaxed);
+        self.update_pressure(self.usage.load(Ordering::Relaxed));
+    }
+
+    fn charge(&self, bytes: usize) {
+        let usage = self.usage.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.update_pressure(usage);
+    }
+
+    fn uncharge(&self, bytes: usize) {
+        let usage = self.usage.fetch_sub(bytes, Ordering::Relaxed) - bytes;
+        self.update_pressure(usage);
+    }
+
+    fn update_pressure(&self, usage: usize) {
+        let limits = self.limits();
+        if usage > limits.pressure {
+            if !self.under_pressure.swap(true, Ordering::Relaxed) {
+                self.pressure_events.fetch_add(1, Ordering::Relaxed);
+            }
+        } else if usage < limits.min {
+            self.under_pressure.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn under_pressure(&self) -> bool {
+        self.under_pressure.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many more bytes fit under the hard cap.
+    fn room(&self) -> usize {
+        self.max
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.usage.load(Ordering::Relaxed))
+    }
+
+    fn stats(&self) -> MemUsage {
+        MemUsage {
+            usage: self.usage.load(Ordering::Relaxed),
+            limits: self.limits(),
+            under_pressure: self.under_pressure(),
+            pressure_events: self.pressure_events.load(Ordering::Relaxed),
+            declined: self.declined.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+const MIB: usize = 1024 * 1024;
+
+static GLOBAL: Budget = Budget::new(MemLimits {
+    min: 8 * MIB,
+    pressure: 12 * MIB,
+    max: 16 * MIB,
+});
+static TCP: Budget = Budget::new(MemLimits {
+    min: 4 * MIB,
+    pressure: 6 * MIB,
+    max: 8 * MIB,
+});
+static UDP: Budget = Budget::new(MemLimits {
+    min: 4 * MIB,
+    pressure: 6 * MIB,
+    max: 8 * MIB,
+});
+
+fn budget(scope: MemScope) -> &'static Budget {
+    match scope {
+        MemScope::Global => &GLOBAL,
+        MemScope::Tcp => &TCP,
+        MemScope::Udp => &UDP,
+    }
+}
+
+/// Sets the thresholds of a budget.
+///
+/// Fails with [`KError::InvalidInput`] unless `min <= pressure <= max`.
+pub fn set_mem_limits(scope: MemScope, limits: MemLimits) -> KResult {
+    if !limits.is_valid() {
+        return Err(KError::InvalidInput);
+    }
+    budget(scope).set_limits(limits);
+    Ok(())
+}
+
+/// Returns the thresholds of a budget.
+pub fn mem_limits(scope: MemScope) -> MemLimits {
+    budget(scope).limits()
+}
+
+/// Returns the usage statistics of all budgets.
+pub fn socket_mem_stats() -> SocketMemStats {
+    SocketMemStats {
+        global: GLOBAL.stats(),
+        tcp: TCP.stats(),
+        udp: UDP.stats(),
+    }
+}
+
+/// Applies the `net.mem=`, `net.tcp_mem=` and `net.udp_mem=` boot arguments
+/// found in `args`. Malformed values are ignored.
+pub(crate) fn apply_boot_args(args: &str) {
+    for arg in args.split_ascii_whitespace() {
+        let Some((key, value)) = arg.split_once('=') else {
+            continue;
+        };
+        let scope = match key {
+            "net.mem" => MemScope::Global,
+            "net.tcp_mem" => MemScope::Tcp,
+            "net.udp_mem" => MemScope::Udp,
+            _ => continue,
+        };
+        match parse_limits(value).map(|limits| set_mem_limits(scope, limits)) {
+            Some(Ok(())) => info!("{key}: {value}"),
+            _ => warn!("ignoring invalid {key}={value}"),
+        }
+    }
+}
+
+fn parse_limits(value: &str) -> Option<MemLimits> {
+    let mut parts = value.split(',').map(|it| it.trim().parse::<usize>().ok());
+    let limits = MemLimits {
+        min: parts.next()??,
+        pressure: parts.next()??,
+        max: parts.next()??,
+    };
+    parts.next().is_none().then_some(limits)
+}
+
+/// Transport protocol of an accounted socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Proto {
+    Tcp,
+    Udp,
+}
+
+/// Memory charged by a single socket, with its `SO_SNDBUF` and `SO_RCVBUF`
+/// limits.
+pub(crate) struct SocketMem {
+    proto: &'static Budget,
+    charged: AtomicUsize,
+    sndbuf: AtomicUsize,
+    rcvbuf: AtomicUsize,
+    /// Capacity of the underlying send and receive buffers.
+    capacity: (usize, usize),
+}
+
+impl SocketMem {
+    pub fn new(proto: Proto) -> Self {
+        let (budget, capacity) = match proto {
+            Proto::Tcp => (&TCP, (TCP_TX_BUF_LEN, TCP_RX_BUF_LEN)),
+            Proto::Udp => (&UDP, (UDP_TX_BUF_LEN, UDP_RX_BUF_LEN)),
+        };
+        Self {
+            proto: budget,
+            charged: AtomicUsize::new(0),
+            sndbuf: AtomicUsize::new(capacity.0),
+            rcvbuf: AtomicUsize::new(capacity.1),
+            capacity,
+        }
+    }
+
+    /// Sets the charge to the `queued` bytes now held by the socket.
+    pub fn sync(&self, queued: usize) {
+        let old = self.charged.swap(queued, Ordering::Relaxed);
+        if queued > old {
+            self.proto.charge(queued - old);
+            GLOBAL.charge(queued - old);
+        } else if old > queued {
+            self.proto.uncharge(old - queued);
+            GLOBAL.uncharge(old - queued);
+        }
+    }
+
+    fn under_pressure(&self) -> bool {
+        self.proto.under_pressure() || GLOBAL.under_pressure()
+    }
+
+    /// Returns how many of `want` more bytes the socket may queue.
+    pub fn allowance(&self, want: usize) -> usize {
+        let mut allowed = want.min(self.proto.room()).min(GLOBAL.room());
+        if self.under_pressure() {
+            let charged = self.charged.load(Ordering::Relaxed);
+            allowed = allowed.min(PRESSURE_SOCKET_QUOTA.saturating_sub(charged));
+        }
+        if allowed < want {
+            self.proto.declined.fetch_add(1, Ordering::Relaxed);
+            GLOBAL.declined.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Returns how many bytes the receive queue may hold.
+    pub fn rx_limit(&self) -> usize {
+        let limit = self.rcvbuf.load(Ordering::Relaxed);
+        if self.under_pressure() {
+            limit.min(PRESSURE_SOCKET_QUOTA)
+        } else {
+            limit
+        }
+    }
+
+    /// Records a received datagram dropped to stay within the budget.
+    pub fn count_drop(&self) {
+        self.proto.dropped.fetch_add(1, Ordering::Relaxed);
+        GLOBAL.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sndbuf(&self) -> usize {
+        self.sndbuf.load(Ordering::Relaxed)
+    }
+
+    pub fn rcvbuf(&self) -> usize {
+        self.rcvbuf.load(Ordering::Relaxed)
+    }
+
+    /// Sets `SO_SNDBUF`, clamped to what the send buffer can hold.
+    pub fn set_sndbuf(&self, size: usize) {
+        let size = size.clamp(SOCK_MIN_BUF, self.capacity.0);
+        self.sndbuf.store(size, Ordering::Relaxed);
+    }
+
+    /// Sets `SO_RCVBUF`, clamped to what the receive buffer can hold.
+    pub fn set_rcvbuf(&self, size: usize) {
+        let size = size.clamp(SOCK_MIN_BUF, self.capacity.1);
+        self.rcvbuf.store(size, Ordering::Relaxed);
+    }
+}
+
+impl Drop for SocketMem {
+    fn drop(&mut self) {
+        self.sync(0);
+    }
+}
+
+#[cfg(unittest)]
+mod tests_mem {
+    use unittest::def_test;
+
+    use super::{Budget, MemLimits, parse_limits};
+
+    fn limits(min: usize, pressure: usize, max: usize) -> MemLimits {
+        MemLimits { min, pressure, max }
+    }
+
+    #[def_test]
+    fn test_parse_limits() {
+        assert_eq!(parse_limits("1,2,3"), Some(limits(1, 2, 3)));
+        assert_eq!(parse_limits("1, 2, 3"), Some(limits(1, 2, 3)));
+        assert_eq!(parse_limits("1,2"), None);
+        assert_eq!(parse_limits("1,2,3,4"), None);
+        assert_eq!(parse_limits("1,x,3"), None);
+    }
+
+    #[def_test]
+    fn test_pressure_hysteresis() {
+        let budget = Budget::new(limits(100, 200, 300));
+        budget.charge(150);
+        assert!(!budget.under_pressure());
+        budget.charge(100);
+        assert!(budget.under_pressure());
+        // Still above `min`: the pressure state holds.
+        budget.uncharge(100);
+        assert!(budget.under_pressure());
+        budget.uncharge(100);
+        assert!(!budget.under_pressure());
+        budget.charge(250);
+        let stats = budget.stats();
+        assert_eq!(stats.pressure_events, 2);
+        assert_eq!(budget.room(), 50);
+    }
+
+    #[def_test]
+    fn test_limits_validation() {
+        assert!(limits(1, 2, 3).is_valid());
+        assert!(!limits(3, 2, 1).is_valid());
+    }
+}
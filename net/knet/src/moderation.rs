@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Interrupt moderation of network devices.
+//!
+//! A device raises an interrupt when packets arrive. While it is busy, the
+//! stack polls it instead, as NAPI does: the first packet after an interrupt
+//! masks the receive interrupt, and the device is polled until it was found
+//! empty more than [`Profile::idle_polls`] times in a row, after which the
+//! interrupt is unmasked again. Devices that coalesce in hardware are also
+//! programmed to delay their interrupts by [`Profile::rx_usecs`].
+//!
+//! With adaptive moderation, the profile follows the packet rate measured
+//! over [`SAMPLE_INTERVAL`]: the lowest latency one while the device is
+//! idle, larger batches under load. A profile is left once
+//! [`HYSTERESIS_SAMPLES`] samples in a row called for it, and a lower one is
+//! only taken below half the rate that led up from it, so that a rate at a
+//! boundary does not flip between two profiles.
+use core::cmp::Ordering;
+
+pub use kdriver::prelude::Coalesce;
+use kdriver::prelude::{DriverError, NetDriverOps};
+use kerrno::{KError, KResult};
+use smoltcp::time::{Duration, Instant};
+
+/// Length of the samples the packet rate is measured over.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+/// Samples in a row that must call for a profile before it is taken.
+pub const HYSTERESIS_SAMPLES: u32 = 2;
+/// A pause in polling after which the device is taken as idle at once.
+pub const IDLE_GAP: Duration = Duration::from_millis(100);
+
+/// A moderation setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// Delay of receive interrupts, on devices that coalesce in hardware.
+    pub rx_usecs: u32,
+    /// Polls finding the device empty before its interrupt is unmasked.
+    pub idle_polls: u32,
+    /// Packets per second above which the next profile is taken.
+    pub max_rate: u64,
+}
+
+/// Moderation profiles, from the lowest latency to the largest batches.
+pub const PROFILES: [Profile; 4] = [
+    Profile {
+        rx_usecs: 0,
+        idle_polls: 0,
+        max_rate: 10_000,
+    },
+    Profile {
+        rx_usecs: 16,
+        idle_polls: 2,
+        max_rate: 50_000,
+    },
+    Profile {
+        rx_usecs: 64,
+        idle_polls: 8,
+        max_rate: 200_000,
+    },
+    Profile {
+        rx_usecs: 128,
+        idle_polls: 16,
+        max_rate: u64::MAX,
+    },
+];
+
+/// Returns the profile a static `rx_usecs` setting polls like: the one with
+/// the longest delay not above it.
+pub fn static_profile(rx_usecs: u32) -> &'static Profile {
+    PROFILES
+        .iter()
+        .rev()
+        .find(|profile| profile.rx_usecs <= rx_usecs)
+        .unwrap_or(&PROFILES[0])
+}
+
+/// Interrupt moderation settings of a network interface, as in Linux's
+/// `struct ethtool_coalesce`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModerationConfig {
+    /// Settings used while adaptive moderation is off.
+    ///
+    /// Devices without coalescing in hardware take `rx_usecs` for the
+    /// polling of the matching profile, see [`static_profile`].
+    pub coalesce: Coalesce,
+    /// Whether receive moderation follows the packet rate.
+    pub adaptive_rx: bool,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            coalesce: Coalesce::default(),
+            adaptive_rx: true,
+        }
+    }
+}
+
+/// Picks a profile from the packet rate of a device.
+#[derive(Debug, Default)]
+pub struct Adaptive {
+    level: usize,
+    /// Start of the current sample.
+    start: Option<Instant>,
+    packets: u64,
+    /// What the last samples called for, and how many in a row.
+    trend: Option<Ordering>,
+    streak: u32,
+}
+
+impl Adaptive {
+    /// The current profile.
+    pub fn profile(&self) -> &'static Profile {
+        &PROFILES[self.level]
+    }
+
+    /// Accounts a poll at `now` that received `packets`, and returns the new
+    /// profile if it changed.
+    pub fn update(&mut self, packets: u64, now: Instant) -> Option<&'static Profile> {
+        let start = *self.start.get_or_insert(now);
+        self.packets += packets;
+        let elapsed = now - start;
+        if elapsed < SAMPLE_INTERVAL {
+            return None;
+        }
+        let rate = self.packets * 1_000_000 / elapsed.total_micros().max(1);
+        self.start = Some(now);
+        self.packets = 0;
+
+        let level = self.level;
+        if elapsed >= IDLE_GAP {
+            // Nothing was polled for a while: the device went idle, and the
+            // next packets are served at the lowest latency.
+            self.level = 0;
+            self.trend = None;
+            self.streak = 0;
+        } else {
+            let trend = if rate > PROFILES[level].max_rate {
+                Ordering::Greater
+            } else if level > 0 && rate < PROFILES[level - 1].max_rate / 2 {
+                Ordering::Less
+            } else {
+                Ordering::Equal
+            };
+            if trend == Ordering::Equal || self.trend != Some(trend) {
+                self.streak = 0;
+            }
+            self.trend = Some(trend);
+            if trend != Ordering::Equal {
+                self.streak += 1;
+            }
+            if self.streak >= HYSTERESIS_SAMPLES {
+                self.level = match trend {
+                    Ordering::Greater => level + 1,
+                    _ => level - 1,
+                };
+                self.streak = 0;
+            }
+        }
+        (self.level != level).then(|| self.profile())
+    }
+}
+
+/// Polling state of a device.
+#[derive(Debug, Default)]
+pub struct Napi {
+    polling: bool,
+    /// Polls in a row that found the device empty.
+    idle: u32,
+}
+
+impl Napi {
+    /// Whether the device is polled, with its receive interrupt masked.
+    pub fn polling(&self) -> bool {
+        self.polling
+    }
+
+    /// Notes that packets were received, and returns whether polling starts
+    /// and the receive interrupt is to be masked.
+    pub fn received(&mut self) -> bool {
+        self.idle = 0;
+        !core::mem::replace(&mut self.polling, true)
+    }
+
+    /// Notes a poll that found the device empty, and returns whether polling
+    /// stops and the receive interrupt is to be unmasked.
+    pub fn empty(&mut self, idle_polls: u32) -> bool {
+        if !self.polling {
+            return false;
+        }
+        self.idle += 1;
+        if self.idle <= idle_polls {
+            return false;
+        }
+        self.polling = false;
+        self.idle = 0;
+        true
+    }
+}
+
+fn driver_error(err: DriverError) -> KError {
+    match err {
+        DriverError::InvalidInput => KError::InvalidInput,
+        DriverError::Unsupported => KError::OperationNotSupported,
+        _ => KError::Io,
+    }
+}
+
+/// Interrupt moderation of a device.
+pub(crate) struct Moderation {
+    config: ModerationConfig,
+    adaptive: Adaptive,
+    napi: Napi,
+    /// Whether the device coalesces in hardware.
+    hw: bool,
+}
+
+impl Moderation {
+    pub fn new(dev: &mut dyn NetDriverOps) -> Self {
+        let mut moderation = Self {
+            config: ModerationConfig::default(),
+            adaptive: Adaptive::default(),
+            napi: Napi::default(),
+            hw: dev.coalesce().is_some(),
+        };
+        if let Err(err) = moderation.program(dev) {
+            warn!("set_coalesce failed: {:?}", err);
+        }
+        moderation
+    }
+
+    pub fn config(&self) -> ModerationConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, dev: &mut dyn NetDriverOps, config: ModerationConfig) -> KResult {
+        let old = core::mem::replace(&mut self.config, config);
+        self.adaptive = Adaptive::default();
+        if let Err(err) = self.program(dev) {
+            self.config = old;
+            let _ = self.program(dev);
+            return Err(driver_error(err));
+        }
+        if self.hw
+            && !config.adaptive_rx
+            && let Some(coalesce) = dev.coalesce()
+        {
+            // Keep the values as rounded by the device.
+            self.config.coalesce = coalesce;
+        }
+        Ok(())
+    }
+
+    /// Whether the device is polled, with its receive interrupt masked.
+    pub fn polling(&self) -> bool {
+        self.napi.polling()
+    }
+
+    fn profile(&self) -> &'static Profile {
+        if self.config.adaptive_rx {
+            self.adaptive.profile()
+        } else {
+            static_profile(self.config.coalesce.rx_usecs)
+        }
+    }
+
+    /// Programs the coalescing of the device for the current profile.
+    fn program(&self, dev: &mut dyn NetDriverOps) -> Result<(), DriverError> {
+        if !self.hw {
+            return Ok(());
+        }
+        let mut coalesce = self.config.coalesce;
+        if self.config.adaptive_rx {
+            coalesce.rx_usecs = self.profile().rx_usecs;
+        }
+        dev.set_coalesce(coalesce)
+    }
+
+    /// Accounts a poll of `dev` at `now` that received `packets`.
+    pub fn polled(&mut self, dev: &mut dyn NetDriverOps, packets: u64, now: Instant) {
+        if self.config.adaptive_rx
+            && self.adaptive.update(packets, now).is_some()
+            && let Err(err) = self.program(dev)
+        {
+            warn!("set_coalesce failed: {:?}", err);
+        }
+        if packets > 0 {
+            if self.napi.received() {
+                let _ = dev.set_rx_interrupt(false);
+            }
+        } else if self.napi.empty(self.profile().idle_polls) {
+            let _ = dev.set_rx_interrupt(true);
+            // A packet that came in before the interrupt was unmasked did not
+            // raise it.
+            if dev.can_rx() && self.napi.received() {
+                let _ = dev.set_rx_interrupt(false);
+            }
+        }
+    }
+}
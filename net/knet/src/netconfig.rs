@@ -0,0 +1,650 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Boot-time configuration of network interfaces.
+//!
+//! The configuration is a list of entries, taken from the
+//! `x-kernel,netconfig` string property of the device tree `/chosen` node,
+//! then from the kernel command line:
+//!
+//! - `ip=<addr>[/<len>]:<server>:<gateway>:<netmask>:<host>:<ifname>[:<autoconf>]`
+//!   assigns an address, like `ip=` of Linux. Trailing fields may be left
+//!   out, the server and host names are ignored, and only static
+//!   configuration (`off`, `none` or `static`) is supported. The interface
+//!   defaults to `eth0` and the prefix length to 24. Repeated, it assigns
+//!   several addresses, to one interface or several. `ip=off` leaves the
+//!   interfaces unconfigured.
+//! - `netif=<ifname>[,mtu=<mtu>][,down]` sets the MTU of an interface, or
+//!   leaves it unconfigured.
+//! - `netroute=<dest>/<len>,via=<gateway>[,dev=<ifname>]` routes a subnet,
+//!   or everything with `default`, through a gateway.
+//!
+//! The whole configuration is rejected if an entry is malformed, two subnets
+//! overlap or a gateway is not on a configured subnet. Without any entry,
+//! `eth0` gets the address the kernel was built with.
+//!
+//! Interfaces present at boot are configured right away. The `netinit` task
+//! waits for the others, such as USB NICs, retrying with growing pauses and
+//! whenever an interface is [added](crate::add_interface). [`report`] shows
+//! the effective configuration, as `/proc/net/netconfig` does.
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    fmt::{self, Write},
+    time::Duration,
+};
+
+use ksync::Mutex;
+use ktask::WaitQueue;
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+use crate::consts::{GATEWAY, IP, IP_PREFIX, MIN_MTU, STANDARD_MTU};
+
+/// Device tree property of the `/chosen` node holding entries.
+const DT_PROPERTY: &str = "x-kernel,netconfig";
+/// Interface of `ip=` entries that name none.
+const DEFAULT_INTERFACE: &str = "eth0";
+/// Longest interface name, as `IFNAMSIZ` allows.
+const MAX_NAME_LEN: usize = 15;
+/// Pause before the first retry for missing interfaces.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest pause between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// Retries before missing interfaces are given up on.
+const MAX_RETRIES: usize = 12;
+
+/// An entry that cannot be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConfigError {
+    /// The entry, as given.
+    pub token: String,
+    pub reason: &'static str,
+}
+
+impl ConfigError {
+    fn new(token: &str, reason: &'static str) -> Self {
+        Self {
+            token: token.into(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.token, self.reason)
+    }
+}
+
+/// Configuration of an interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InterfaceConfig {
+    pub name: String,
+    pub addrs: Vec<Ipv4Cidr>,
+    /// Gateway of the default route.
+    pub gateway: Option<Ipv4Address>,
+    pub mtu: Option<usize>,
+    /// Whether the interface is configured at all.
+    pub up: bool,
+}
+
+impl InterfaceConfig {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            addrs: Vec::new(),
+            gateway: None,
+            mtu: None,
+            up: true,
+        }
+    }
+}
+
+impl fmt::Display for InterfaceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.up {
+            return f.write_str(" down");
+        }
+        for addr in &self.addrs {
+            write!(f, " addr={addr}")?;
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " gateway={gateway}")?;
+        }
+        if let Some(mtu) = self.mtu {
+            write!(f, " mtu={mtu}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A route through a gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RouteConfig {
+    pub dest: Ipv4Cidr,
+    pub via: Ipv4Address,
+    /// The interface, which is the one with the gateway on its subnet unless
+    /// given.
+    pub dev: String,
+    token: String,
+}
+
+impl fmt::Display for RouteConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} via {} dev {}", self.dest, self.via, self.dev)
+    }
+}
+
+/// A validated network configuration.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct NetConfig {
+    pub interfaces: Vec<InterfaceConfig>,
+    pub routes: Vec<RouteConfig>,
+    /// Whether `ip=off` was given.
+    pub off: bool,
+}
+
+impl NetConfig {
+    /// Parses and validates the entries of the device tree property `blob`
+    /// and of the command line `cmdline`, where other arguments are skipped.
+    pub fn parse(blob: &str, cmdline: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for token in blob.split_ascii_whitespace() {
+            if !config.parse_entry(token)? {
+                return Err(ConfigError::new(token, "unknown entry"));
+            }
+        }
+        for token in cmdline.split_ascii_whitespace() {
+            config.parse_entry(token)?;
+        }
+        config.resolve_routes()?;
+        Ok(config)
+    }
+
+    /// Returns whether nothing was configured.
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_empty() && self.routes.is_empty() && !self.off
+    }
+
+    /// Parses `token`, returning whether it is an entry.
+    fn parse_entry(&mut self, token: &str) -> Result<bool, ConfigError> {
+        let Some((key, value)) = token.split_once('=') else {
+            return Ok(false);
+        };
+        match key {
+            "ip" => self.parse_ip(token, value)?,
+            "netif" => self.parse_netif(token, value)?,
+            "netroute" => self.parse_route(token, value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn interface_mut(&mut self, name: &str) -> &mut InterfaceConfig {
+        let index = match self.interfaces.iter().position(|it| it.name == name) {
+            Some(index) => index,
+            None => {
+                self.interfaces.push(InterfaceConfig::new(name));
+                self.interfaces.len() - 1
+            }
+        };
+        &mut self.interfaces[index]
+    }
+
+    fn parse_ip(&mut self, token: &str, value: &str) -> Result<(), ConfigError> {
+        match value {
+            "off" | "none" => {
+                self.off = true;
+                return Ok(());
+            }
+            "on" | "any" | "dhcp" | "bootp" | "rarp" | "both" => {
+                return Err(ConfigError::new(
+                    token,
+                    "only static configuration is supported",
+                ));
+            }
+            _ => {}
+        }
+        let mut fields = value.split(':');
+        let addr = fields.next().unwrap_or_default();
+        let _server = fields.next();
+        let gateway = fields.next().unwrap_or_default();
+        let netmask = fields.next().unwrap_or_default();
+        let _host = fields.next();
+        let name = fields.next().unwrap_or_default();
+        match fields.next() {
+            None | Some("" | "off" | "none" | "static") => {}
+            Some(_) => {
+                return Err(ConfigError::new(
+                    token,
+                    "only static configuration is supported",
+                ));
+            }
+        }
+        if fields.next().is_some() {
+            return Err(ConfigError::new(token, "too many fields"));
+        }
+
+        let addr = parse_host_addr(token, addr, netmask)?;
+        let name = if name.is_empty() {
+            DEFAULT_INTERFACE
+        } else {
+            check_name(token, name)?
+        };
+        let gateway = if gateway.is_empty() {
+            None
+        } else {
+            let gateway = parse_addr(token, gateway)?;
+            if !addr.contains_addr(&gateway) || gateway == addr.address() {
+                return Err(ConfigError::new(token, "gateway is not on the subnet"));
+            }
+            Some(gateway)
+        };
+        if self
+            .interfaces
+            .iter()
+            .flat_map(|it| &it.addrs)
+            .chain(&[loopback()])
+            .any(|it| overlaps(it, &addr))
+        {
+            return Err(ConfigError::new(token, "subnet overlaps an earlier subnet"));
+        }
+        if let Some(gateway) = gateway
+            && self
+                .interfaces
+                .iter()
+                .any(|it| it.gateway.is_some_and(|it| it != gateway))
+        {
+            return Err(ConfigError::new(
+                token,
+                "conflicts with an earlier default gateway",
+            ));
+        }
+
+        let interface = self.interface_mut(name);
+        interface.addrs.push(addr);
+        interface.gateway = interface.gateway.or(gateway);
+        Ok(())
+    }
+
+    fn parse_netif(&mut self, token: &str, value: &str) -> Result<(), ConfigError> {
+        let mut options = value.split(',');
+        let name = check_name(token, options.next().unwrap_or_default())?;
+        let (mut mtu, mut up) = (None, true);
+        for option in options {
+            if option == "down" {
+                up = false;
+            } else if let Some(value) = option.strip_prefix("mtu=") {
+                let value = value
+                    .parse()
+                    .map_err(|_| ConfigError::new(token, "malformed MTU"))?;
+                if !(MIN_MTU..=STANDARD_MTU).contains(&value) {
+                    return Err(ConfigError::new(token, "MTU out of range"));
+                }
+                mtu = Some(value);
+            } else {
+                return Err(ConfigError::new(token, "unknown option"));
+            }
+        }
+        let interface = self.interface_mut(name);
+        interface.mtu = mtu.or(interface.mtu);
+        interface.up &= up;
+        Ok(())
+    }
+
+    fn parse_route(&mut self, token: &str, value: &str) -> Result<(), ConfigError> {
+        let mut options = value.split(',');
+        let dest = match options.next().unwrap_or_default() {
+            "default" => Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0),
+            dest => {
+                let (addr, len) = dest
+                    .split_once('/')
+                    .ok_or_else(|| ConfigError::new(token, "missing prefix length"))?;
+                let dest = Ipv4Cidr::new(parse_addr(token, addr)?, parse_prefix(token, len)?);
+                if dest.network() != dest {
+                    return Err(ConfigError::new(token, "destination has host bits set"));
+                }
+                dest
+            }
+        };
+        let (mut via, mut dev) = (None, String::new());
+        for option in options {
+            if let Some(addr) = option.strip_prefix("via=") {
+                via = Some(parse_addr(token, addr)?);
+            } else if let Some(name) = option.strip_prefix("dev=") {
+                dev = check_name(token, name)?.into();
+            } else {
+                return Err(ConfigError::new(token, "unknown option"));
+            }
+        }
+        let via = via.ok_or_else(|| ConfigError::new(token, "missing gateway"))?;
+        if self.routes.iter().any(|it| it.dest == dest) {
+            return Err(ConfigError::new(token, "duplicate route"));
+        }
+        self.routes.push(RouteConfig {
+            dest,
+            via,
+            dev,
+            token: token.into(),
+        });
+        Ok(())
+    }
+
+    /// Checks that the gateway of each route is on a subnet of an interface,
+    /// and picks that interface for routes that name none.
+    fn resolve_routes(&mut self) -> Result<(), ConfigError> {
+        let has_default = self.interfaces.iter().any(|it| it.gateway.is_some());
+        for route in &mut self.routes {
+            if has_default && route.dest.prefix_len() == 0 {
+                return Err(ConfigError::new(
+                    &route.token,
+                    "conflicts with the default gateway of `ip=`",
+                ));
+            }
+            let interface = self.interfaces.iter().find(|it| {
+                (route.dev.is_empty() || it.name == route.dev)
+                    && it.up
+                    && it.addrs.iter().any(|addr| addr.contains_addr(&route.via))
+            });
+            let Some(interface) = interface else {
+                return Err(ConfigError::new(
+                    &route.token,
+                    "gateway is not on a configured subnet",
+                ));
+            };
+            route.dev = interface.name.clone();
+        }
+        Ok(())
+    }
+}
+
+fn loopback() -> Ipv4Cidr {
+    Ipv4Cidr::new(Ipv4Address::new(127, 0, 0, 0), 8)
+}
+
+/// Returns whether two subnets share addresses.
+pub(crate) fn overlaps(a: &Ipv4Cidr, b: &Ipv4Cidr) -> bool {
+    a.contains_addr(&b.network().address()) || b.contains_addr(&a.network().address())
+}
+
+fn parse_addr(token: &str, addr: &str) -> Result<Ipv4Address, ConfigError> {
+    addr.parse()
+        .map_err(|_| ConfigError::new(token, "malformed IPv4 address"))
+}
+
+fn parse_prefix(token: &str, len: &str) -> Result<u8, ConfigError> {
+    len.parse()
+        .ok()
+        .filter(|len| *len <= 32)
+        .ok_or_else(|| ConfigError::new(token, "malformed prefix length"))
+}
+
+/// Parses the address `addr` of an `ip=` entry, with an optional prefix
+/// length, and the netmask `netmask`, which may be empty.
+fn parse_host_addr(token: &str, addr: &str, netmask: &str) -> Result<Ipv4Cidr, ConfigError> {
+    let (addr, len) = match addr.split_once('/') {
+        Some((addr, len)) => (addr, Some(parse_prefix(token, len)?)),
+        None => (addr, None),
+    };
+    let addr = parse_addr(token, addr)?;
+    let mask_len = if netmask.is_empty() {
+        None
+    } else {
+        let cidr = Ipv4Cidr::from_netmask(addr, parse_addr(token, netmask)?)
+            .map_err(|_| ConfigError::new(token, "malformed netmask"))?;
+        Some(cidr.prefix_len())
+    };
+    let len = match (len, mask_len) {
+        (Some(len), Some(mask_len)) if len != mask_len => {
+            return Err(ConfigError::new(
+                token,
+                "netmask contradicts the prefix length",
+            ));
+        }
+        (len, mask_len) => len.or(mask_len).unwrap_or(IP_PREFIX),
+    };
+    let cidr = Ipv4Cidr::new(addr, len);
+    let is_host = !(addr.is_unspecified()
+        || addr.is_broadcast()
+        || addr.is_multicast()
+        || addr.is_loopback()
+        || (len < 31 && (cidr.network().address() == addr || cidr.broadcast() == Some(addr))));
+    if !is_host {
+        return Err(ConfigError::new(token, "not a host address"));
+    }
+    Ok(cidr)
+}
+
+fn check_name<'a>(token: &str, name: &'a str) -> Result<&'a str, ConfigError> {
+    let valid = (1..=MAX_NAME_LEN).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if !valid {
+        return Err(ConfigError::new(token, "malformed interface name"));
+    }
+    if name == "lo" {
+        return Err(ConfigError::new(token, "lo cannot be configured"));
+    }
+    Ok(name)
+}
+
+/// Returns the configuration the kernel was built with: its address on
+/// `eth0`, if any.
+fn built_in() -> NetConfig {
+    let mut config = NetConfig::default();
+    let Ok(addr) = IP.parse() else {
+        return config;
+    };
+    let mut interface = InterfaceConfig::new(DEFAULT_INTERFACE);
+    interface.addrs.push(Ipv4Cidr::new(addr, IP_PREFIX));
+    interface.gateway = GATEWAY.parse().ok();
+    config.interfaces.push(interface);
+    config
+}
+
+/// Progress of an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// Not present yet.
+    Waiting,
+    Configured,
+    /// Left unconfigured, as asked.
+    Down,
+    Failed,
+    /// Given up on.
+    Missing,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Waiting => "waiting",
+            Status::Configured => "configured",
+            Status::Down => "down",
+            Status::Failed => "failed",
+            Status::Missing => "missing",
+        })
+    }
+}
+
+/// The effective configuration.
+struct State {
+    source: &'static str,
+    config: NetConfig,
+    /// Progress of each interface of `config`.
+    status: Vec<Status>,
+    /// Rejected configuration or failures to apply it.
+    errors: Vec<String>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Woken when an interface is added.
+static INTERFACE_ADDED: WaitQueue = WaitQueue::new();
+
+/// Applies `interface` and the routes through it.
+fn apply(interface: &InterfaceConfig, routes: &[RouteConfig]) -> Result<(), String> {
+    let name = interface.name.as_str();
+    if let Some(mtu) = interface.mtu {
+        crate::set_mtu(name, mtu).map_err(|err| format!("{name}: mtu {mtu}: {err}"))?;
+    }
+    for addr in &interface.addrs {
+        crate::add_ipv4_addr(name, addr.address(), addr.prefix_len())
+            .map_err(|err| format!("{name}: address {addr}: {err}"))?;
+    }
+    if let Some(gateway) = interface.gateway {
+        crate::add_ipv4_route(Ipv4Address::UNSPECIFIED, 0, gateway, Some(name))
+            .map_err(|err| format!("{name}: default route via {gateway}: {err}"))?;
+    }
+    for route in routes.iter().filter(|it| it.dev == name) {
+        crate::add_ipv4_route(
+            route.dest.address(),
+            route.dest.prefix_len(),
+            route.via,
+            Some(name),
+        )
+        .map_err(|err| format!("route {route}: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Configures the interfaces waited for that are present, and returns
+/// whether some are still missing.
+fn configure_present() -> bool {
+    let present = crate::interfaces();
+    let mut state = STATE.lock();
+    let Some(state) = state.as_mut() else {
+        return false;
+    };
+    for (interface, status) in state.config.interfaces.iter().zip(&mut state.status) {
+        if *status != Status::Waiting || !present.contains(&interface.name) {
+            continue;
+        }
+        match apply(interface, &state.config.routes) {
+            Ok(()) => {
+                info!("  {interface}");
+                for route in state
+                    .config
+                    .routes
+                    .iter()
+                    .filter(|it| it.dev == interface.name)
+                {
+                    info!("  route {route}");
+                }
+                *status = Status::Configured;
+            }
+            Err(err) => {
+                error!("  {err}");
+                state.errors.push(err);
+                *status = Status::Failed;
+            }
+        }
+    }
+    state.status.contains(&Status::Waiting)
+}
+
+/// Gives up on the interfaces still missing.
+fn give_up() {
+    let mut state = STATE.lock();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    for (interface, status) in state.config.interfaces.iter().zip(&mut state.status) {
+        if *status == Status::Waiting {
+            warn!("Network interface {} did not appear", interface.name);
+            *status = Status::Missing;
+        }
+    }
+}
+
+/// Reads the configuration from the device tree and the command line
+/// `cmdline`, configures the interfaces present and starts waiting for the
+/// others.
+pub(crate) fn init(cmdline: &str) {
+    let blob = khal::dtb::get_chosen_str(DT_PROPERTY).unwrap_or("");
+    let (source, config, errors) = match NetConfig::parse(blob, cmdline) {
+        Ok(config) if config.is_empty() => ("built-in", built_in(), Vec::new()),
+        Ok(config) if blob.is_empty() => ("command line", config, Vec::new()),
+        Ok(config) => ("device tree", config, Vec::new()),
+        Err(err) => {
+            error!("Network configuration rejected: {err}");
+            ("rejected", NetConfig::default(), vec![err.to_string()])
+        }
+    };
+    info!("Network configuration ({source}):");
+    let status = config
+        .interfaces
+        .iter()
+        .map(|it| {
+            if !it.up {
+                info!("  {it}");
+                Status::Down
+            } else {
+                Status::Waiting
+            }
+        })
+        .collect();
+    let built_in = source == "built-in";
+    *STATE.lock() = Some(State {
+        source,
+        config,
+        status,
+        errors,
+    });
+
+    // The built-in address is for an interface that is there, if any.
+    if !configure_present() {
+        return;
+    }
+    if built_in {
+        give_up();
+        return;
+    }
+    ktask::spawn_with_name(
+        || {
+            let mut backoff = MIN_BACKOFF;
+            for _ in 0..MAX_RETRIES {
+                INTERFACE_ADDED.wait_timeout(backoff);
+                if !configure_present() {
+                    return;
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            give_up();
+        },
+        "netinit".into(),
+    );
+}
+
+/// Lets the `netinit` task configure an interface that was just added.
+pub(crate) fn interface_added() {
+    INTERFACE_ADDED.notify_all(false);
+}
+
+/// Returns the effective boot network configuration, one interface or
+/// route a line after the source, followed by errors.
+pub fn report() -> String {
+    let state = STATE.lock();
+    let Some(state) = state.as_ref() else {
+        return String::new();
+    };
+    let mut out = String::new();
+    let _ = writeln!(out, "source: {}", state.source);
+    for (interface, status) in state.config.interfaces.iter().zip(&state.status) {
+        let _ = writeln!(out, "{interface} status={status}");
+    }
+    for route in &state.config.routes {
+        let _ = writeln!(out, "route {route}");
+    }
+    for err in &state.errors {
+        let _ = writeln!(out, "error: {err}");
+    }
+    out
+}
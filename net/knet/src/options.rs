@@ -4,7 +4,7 @@
 
 //! Socket option types and configuration helpers.
 use alloc::boxed::Box;
-use core::time::Duration;
+use core::{net::Ipv4Addr, time::Duration};
 
 use enum_dispatch::enum_dispatch;
 use kerrno::{KError, KResult, LinuxError};
@@ -33,8 +33,11 @@ macro_rules! define_options {
 }
 
 /// Corresponds to `struct ucred` in Linux.
+///
+/// Process IDs are global: there are no PID namespaces to translate them
+/// between.
 #[repr(C)]
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UnixCredentials {
     pub pid: u32,
     pub uid: u32,
@@ -50,6 +53,24 @@ impl UnixCredentials {
     }
 }
 
+/// Corresponds to `struct ip_mreq` in Linux.
+#[derive(Debug, Clone, Copy)]
+pub struct IpMembership {
+    /// The multicast group.
+    pub multiaddr: Ipv4Addr,
+    /// Address of the local interface, or unspecified for any.
+    pub interface: Ipv4Addr,
+}
+
+impl Default for IpMembership {
+    fn default() -> Self {
+        Self {
+            multiaddr: Ipv4Addr::UNSPECIFIED,
+            interface: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
 define_options! {
     // ---- Socket level options (SO_*) ----
     ReuseAddress(bool),
@@ -71,6 +92,8 @@ define_options! {
 
     // ---- IP level options (IP_*) ----
     Ttl(u8),
+    AddMembership(IpMembership),
+    DropMembership(IpMembership),
 
     // ---- Extra options ----
     NonBlocking(bool),
@@ -3,11 +3,14 @@
 // See LICENSES for license details.
 
 //! Routing table and route selection.
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+use core::mem;
 
+use kdriver::prelude::NetCapabilities;
+use kerrno::{KError, KResult};
 use smoltcp::{
     iface::SocketSet,
-    phy::{DeviceCapabilities, Medium},
+    phy::{Checksum, ChecksumCapabilities, DeviceCapabilities, Medium},
     storage::PacketMetadata,
     time::Instant,
     wire::{IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Packet, Ipv6Packet, TcpPacket},
@@ -16,7 +19,11 @@ use smoltcp::{
 use crate::{
     LISTEN_TABLE,
     consts::{SOCKET_BUFFER_SIZE, STANDARD_MTU},
-    device::NetDevice,
+    device::{NetDevice, fill_checksums},
+    frag::{
+        FragStats, IP_MAX_DATAGRAM, IPFRAG_MEM_LIMIT, IPFRAG_TIMEOUT, IcmpError, Reassembler,
+        fragment_ipv4, icmp_error, is_fragment,
+    },
 };
 
 #[derive(Debug)]
@@ -40,6 +47,99 @@ impl Rule {
 
 type PacketBuffer = smoltcp::storage::PacketBuffer<'static, ()>;
 
+/// Returns whether `buffer` can take a packet as large as the stack emits.
+///
+/// Enqueueing may have to pad the payload ring up to its end first, so twice
+/// the packet size must be free.
+fn has_room(buffer: &PacketBuffer) -> bool {
+    !buffer.is_full()
+        && buffer.payload_capacity() - buffer.payload_bytes_count() >= 2 * IP_MAX_DATAGRAM
+}
+
+/// Returns the source and destination addresses of `packet`.
+fn packet_addrs(packet: &[u8]) -> (IpAddress, IpAddress) {
+    match IpVersion::of_packet(packet).expect("got invalid IP packet") {
+        IpVersion::Ipv4 => {
+            let ip = Ipv4Packet::new_checked(packet).expect("got invalid IPv4 packet");
+            (ip.src_addr().into(), ip.dst_addr().into())
+        }
+        IpVersion::Ipv6 => {
+            let ip = Ipv6Packet::new_checked(packet).expect("got invalid IPv6 packet");
+            (ip.src_addr().into(), ip.dst_addr().into())
+        }
+    }
+}
+
+/// Lowers the MSS option of the TCP SYN `packet` to what fits `mtu`.
+///
+/// The stack sees an MTU of [`IP_MAX_DATAGRAM`] so that it hands datagrams
+/// of any size over for fragmentation, which would also make TCP advertise
+/// segments no device can carry.
+fn clamp_mss(packet: &mut [u8], mtu: usize, fill_checksum: bool) {
+    let (header_len, protocol) = match IpVersion::of_packet(packet) {
+        Ok(IpVersion::Ipv4) => {
+            let ip = Ipv4Packet::new_unchecked(&*packet);
+            (ip.header_len() as usize, ip.next_header())
+        }
+        Ok(IpVersion::Ipv6) => {
+            let ip = Ipv6Packet::new_unchecked(&*packet);
+            (ip.header_len(), ip.next_header())
+        }
+        Err(_) => return,
+    };
+    if protocol != IpProtocol::Tcp {
+        return;
+    }
+    let (src, dst) = packet_addrs(packet);
+    let Ok(mut tcp) = TcpPacket::new_checked(&mut packet[header_len..]) else {
+        return;
+    };
+    if !tcp.syn() {
+        return;
+    }
+    let max = mtu.saturating_sub(header_len + 20).min(u16::MAX as usize) as u16;
+    let options = tcp.options_mut();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => return,
+            1 => i += 1,
+            2 if options.get(i + 1) == Some(&4) && i + 4 <= options.len() => {
+                if u16::from_be_bytes([options[i + 2], options[i + 3]]) <= max {
+                    return;
+                }
+                options[i + 2..i + 4].copy_from_slice(&max.to_be_bytes());
+                break;
+            }
+            _ => {
+                i += options
+                    .get(i + 1)
+                    .map_or(options.len(), |&len| (len as usize).max(2))
+            }
+        }
+    }
+    if i < options.len() && fill_checksum {
+        tcp.fill_checksum(&src, &dst);
+    }
+}
+
+/// Returns which checksums the stack has to compute itself, given the
+/// offloads common to all devices.
+pub(crate) fn checksum_caps(caps: NetCapabilities) -> ChecksumCapabilities {
+    let rx = caps.contains(NetCapabilities::RX_CSUM);
+    let checksum = |tx_cap| match (caps.contains(tx_cap), rx) {
+        (true, true) => Checksum::None,
+        (true, false) => Checksum::Rx,
+        (false, true) => Checksum::Tx,
+        (false, false) => Checksum::Both,
+    };
+    let mut checksum_caps = ChecksumCapabilities::default();
+    checksum_caps.ipv4 = checksum(NetCapabilities::TX_CSUM_IPV4);
+    checksum_caps.tcp = checksum(NetCapabilities::TX_CSUM_TCP);
+    checksum_caps.udp = checksum(NetCapabilities::TX_CSUM_UDP);
+    checksum_caps
+}
+
 // TODO(mivik): optimize
 pub struct RouteTable {
     rules: Vec<Rule>,
@@ -62,13 +162,32 @@ impl RouteTable {
             .iter()
             .find(|rule| rule.filter.contains_addr(dst))
     }
+
+    /// Returns the rule of the subnet `addr` is on, among those of the
+    /// device `dev` if given.
+    pub fn link_for(&self, addr: &IpAddress, dev: Option<usize>) -> Option<&Rule> {
+        self.rules.iter().find(|rule| {
+            rule.via.is_none()
+                && rule.filter.contains_addr(addr)
+                && dev.is_none_or(|dev| dev == rule.dev)
+        })
+    }
 }
 
 pub struct Router {
     rx_buffer: PacketBuffer,
     tx_buffer: PacketBuffer,
+    /// Reassembled datagrams and ICMP errors for the stack.
+    rx_pending: VecDeque<Vec<u8>>,
+    /// Packet from `rx_pending` being received.
+    rx_current: Vec<u8>,
+    reassembler: Reassembler,
+    /// Identification of the next fragmented IPv4 datagram.
+    next_ident: u16,
     pub(crate) devices: Vec<Box<dyn NetDevice>>,
     pub(crate) table: RouteTable,
+    /// Offloads supported by every device.
+    caps: NetCapabilities,
 }
 impl Router {
     pub fn new() -> Self {
@@ -76,15 +195,21 @@ impl Router {
             vec![PacketMetadata::EMPTY; SOCKET_BUFFER_SIZE],
             vec![0u8; STANDARD_MTU * SOCKET_BUFFER_SIZE],
         );
+        // Leaves room for the largest datagram, which is fragmented later.
         let tx_buffer = PacketBuffer::new(
             vec![PacketMetadata::EMPTY; SOCKET_BUFFER_SIZE],
-            vec![0u8; STANDARD_MTU * SOCKET_BUFFER_SIZE],
+            vec![0u8; STANDARD_MTU * SOCKET_BUFFER_SIZE + 2 * IP_MAX_DATAGRAM],
         );
         Self {
             rx_buffer,
             tx_buffer,
+            rx_pending: VecDeque::new(),
+            rx_current: Vec::new(),
+            reassembler: Reassembler::new(IPFRAG_MEM_LIMIT, IPFRAG_TIMEOUT),
+            next_ident: 0,
             devices: Vec::new(),
             table: RouteTable::new(),
+            caps: NetCapabilities::all(),
         }
     }
 
@@ -93,66 +218,160 @@ impl Router {
     }
 
     pub fn add_device(&mut self, device: Box<dyn NetDevice>) -> usize {
+        // smoltcp has one set of checksum capabilities for all devices.
+        self.caps &= device.capabilities();
         self.devices.push(device);
         self.devices.len() - 1
     }
 
+    /// Joins the multicast `group` on every device.
+    pub fn join_multicast(&mut self, group: IpAddress) -> KResult {
+        for i in 0..self.devices.len() {
+            if let Err(err) = self.devices[i].join_multicast(group) {
+                for dev in &mut self.devices[..i] {
+                    let _ = dev.leave_multicast(group);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the multicast `group` on every device.
+    pub fn leave_multicast(&mut self, group: IpAddress) -> KResult {
+        let mut result = Ok(());
+        for dev in &mut self.devices {
+            result = result.and(dev.leave_multicast(group));
+        }
+        result
+    }
+
+    /// Switches promiscuous mode on every device.
+    pub fn set_promiscuous(&mut self, enable: bool) -> KResult {
+        for dev in &mut self.devices {
+            dev.set_promiscuous(enable)?;
+        }
+        Ok(())
+    }
+
+    pub fn frag_stats(&self) -> FragStats {
+        self.reassembler.stats()
+    }
+
+    /// Returns the index of the device called `name`.
+    pub fn device_index(&self, name: &str) -> KResult<usize> {
+        self.devices
+            .iter()
+            .position(|dev| dev.name() == name)
+            .ok_or(KError::NoSuchDevice)
+    }
+
+    /// Returns the device called `name`.
+    pub fn device_mut(&mut self, name: &str) -> KResult<&mut Box<dyn NetDevice>> {
+        self.devices
+            .iter_mut()
+            .find(|dev| dev.name() == name)
+            .ok_or(KError::NoSuchDevice)
+    }
+
     pub fn poll(&mut self, timestamp: Instant) {
         for dev in &mut self.devices {
             while !self.rx_buffer.is_full() && dev.poll_rx(&mut self.rx_buffer, timestamp) {}
         }
+
+        let mut expired = Vec::new();
+        self.reassembler.expire(timestamp, |src, quote| {
+            expired.push((src, quote.to_vec()));
+        });
+        for (src, quote) in expired {
+            let Some(rule) = self.table.lookup(&src) else {
+                continue;
+            };
+            if let Some(mut packet) = icmp_error(rule.src, &quote, IcmpError::ReassemblyTimeout) {
+                self.send_packet(&mut packet, timestamp);
+            }
+        }
     }
 
     pub fn dispatch(&mut self, timestamp: Instant) -> bool {
+        // Sending may need the whole router, so the buffer is moved out.
+        let mut tx_buffer = mem::replace(
+            &mut self.tx_buffer,
+            PacketBuffer::new(Vec::new(), Vec::new()),
+        );
         let mut poll_next = false;
-        while let Ok(((), ip_packet)) = self.tx_buffer.dequeue() {
-            match IpVersion::of_packet(ip_packet).expect("got invalid IP packet") {
-                IpVersion::Ipv4 => {
-                    let ip_packet = smoltcp::wire::Ipv4Packet::new_checked(ip_packet)
-                        .expect("got invalid IPv4 packet");
-                    let dst_addr = IpAddress::Ipv4(ip_packet.dst_addr());
-                    if ip_packet.dst_addr().is_broadcast() {
-                        let buf = ip_packet.into_inner();
-                        for dev in &mut self.devices {
-                            poll_next |= dev.send_ip_packet(dst_addr, buf, timestamp);
-                        }
-                    } else {
-                        let Some(rule) = self.table.lookup(&dst_addr) else {
-                            warn!("No route found for destination: {}", dst_addr);
-                            continue;
-                        };
-                        assert_eq!(rule.src, IpAddress::Ipv4(ip_packet.src_addr()));
-
-                        let next_hop = rule.via.unwrap_or(dst_addr);
-                        let dev = &mut self.devices[rule.dev];
-                        poll_next |=
-                            dev.send_ip_packet(next_hop, ip_packet.into_inner(), timestamp);
-                    }
-                }
-                IpVersion::Ipv6 => {
-                    let ip_packet = smoltcp::wire::Ipv6Packet::new_checked(ip_packet)
-                        .expect("got invalid IPv6 packet");
-                    let dst_addr = IpAddress::Ipv6(ip_packet.dst_addr());
-                    if ip_packet.dst_addr().is_multicast() {
-                        let buf = ip_packet.into_inner();
-                        for dev in &mut self.devices {
-                            poll_next |= dev.send_ip_packet(dst_addr, buf, timestamp);
-                        }
-                    } else {
-                        let Some(rule) = self.table.lookup(&dst_addr) else {
-                            warn!("No route found for destination: {}", dst_addr);
-                            continue;
-                        };
-                        assert_eq!(rule.src, IpAddress::Ipv6(ip_packet.src_addr()));
-
-                        let next_hop = rule.via.unwrap_or(dst_addr);
-                        let dev = &mut self.devices[rule.dev];
-                        poll_next |=
-                            dev.send_ip_packet(next_hop, ip_packet.into_inner(), timestamp);
-                    }
-                }
+        while let Ok(((), ip_packet)) = tx_buffer.dequeue() {
+            poll_next |= self.send_packet(ip_packet, timestamp);
+        }
+        self.tx_buffer = tx_buffer;
+        poll_next
+    }
+
+    /// Sends `packet` to the next hop towards its destination.
+    fn send_packet(&mut self, packet: &mut [u8], timestamp: Instant) -> bool {
+        let (_, dst_addr) = packet_addrs(packet);
+        let to_all = match dst_addr {
+            IpAddress::Ipv4(addr) => addr.is_broadcast(),
+            IpAddress::Ipv6(addr) => addr.is_multicast(),
+        };
+        if to_all {
+            let mut poll_next = false;
+            for dev in 0..self.devices.len() {
+                poll_next |= self.send_via(dev, dst_addr, packet, timestamp);
             }
+            return poll_next;
+        }
+
+        let Some(rule) = self.table.lookup(&dst_addr) else {
+            warn!("No route found for destination: {}", dst_addr);
+            return false;
+        };
+        let (dev, next_hop) = (rule.dev, rule.via.unwrap_or(dst_addr));
+        self.send_via(dev, next_hop, packet, timestamp)
+    }
+
+    /// Sends `packet` out of the device `dev`, fragmenting it to the device
+    /// MTU if needed.
+    ///
+    /// TCP segments keep their DF flag, since TCP sizes them to the path
+    /// MTU; other IPv4 datagrams are fragmented whatever their flag says, as
+    /// sockets have no way to ask for path MTU discovery. IPv6 datagrams are
+    /// never fragmented. A datagram that cannot be fragmented is answered
+    /// with an ICMP error for the stack.
+    fn send_via(
+        &mut self,
+        dev: usize,
+        next_hop: IpAddress,
+        packet: &mut [u8],
+        timestamp: Instant,
+    ) -> bool {
+        let mtu = self.devices[dev].mtu();
+        clamp_mss(packet, mtu, checksum_caps(self.caps).tcp.tx());
+        if packet.len() <= mtu {
+            return self.devices[dev].send_ip_packet(next_hop, packet, timestamp);
         }
+
+        let fragmentable = matches!(IpVersion::of_packet(packet), Ok(IpVersion::Ipv4)) && {
+            let ip = Ipv4Packet::new_unchecked(&*packet);
+            !ip.dont_frag() || ip.next_header() != IpProtocol::Tcp
+        };
+        if !fragmentable {
+            debug!("Datagram of {} bytes exceeds the MTU {}", packet.len(), mtu);
+            let (src_addr, _) = packet_addrs(packet);
+            let error = icmp_error(src_addr, packet, IcmpError::PacketTooBig(mtu));
+            self.rx_pending.extend(error);
+            return true;
+        }
+
+        // Devices cannot fill in the transport checksum of a fragment.
+        fill_checksums(packet);
+        let ident = self.next_ident;
+        self.next_ident = self.next_ident.wrapping_add(1);
+        let dev = &mut self.devices[dev];
+        let mut poll_next = false;
+        fragment_ipv4(packet, mtu, ident, |frag| {
+            poll_next |= dev.send_ip_packet(next_hop, frag, timestamp);
+        });
         poll_next
     }
 }
@@ -222,30 +441,45 @@ impl smoltcp::phy::Device for Router {
     type RxToken<'a> = RxToken<'a>;
     type TxToken<'a> = TxToken<'a>;
 
-    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        if self.rx_buffer.is_empty() || self.tx_buffer.is_full() {
-            None
-        } else {
-            Some((
-                RxToken(self.rx_buffer.dequeue().unwrap().1),
-                TxToken(&mut self.tx_buffer),
-            ))
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if !has_room(&self.tx_buffer) {
+            return None;
+        }
+        loop {
+            if let Some(packet) = self.rx_pending.pop_front() {
+                self.rx_current = packet;
+                return Some((RxToken(&self.rx_current), TxToken(&mut self.tx_buffer)));
+            }
+            let ((), packet) = self.rx_buffer.peek().ok()?;
+            if !is_fragment(packet) {
+                break;
+            }
+            let ((), packet) = self.rx_buffer.dequeue().unwrap();
+            if let Some(datagram) = self.reassembler.process(packet, timestamp) {
+                self.rx_pending.push_back(datagram);
+            }
         }
+        Some((
+            RxToken(self.rx_buffer.dequeue().unwrap().1),
+            TxToken(&mut self.tx_buffer),
+        ))
     }
 
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
-        if self.tx_buffer.is_full() {
-            None
-        } else {
+        if has_room(&self.tx_buffer) {
             Some(TxToken(&mut self.tx_buffer))
+        } else {
+            None
         }
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
         let mut caps = DeviceCapabilities::default();
         caps.medium = Medium::Ip;
-        caps.max_transmission_unit = STANDARD_MTU;
+        // Datagrams larger than a device MTU are fragmented on dispatch.
+        caps.max_transmission_unit = IP_MAX_DATAGRAM;
         caps.max_burst_size = Some(SOCKET_BUFFER_SIZE);
+        caps.checksum = checksum_caps(self.caps);
         caps
     }
 }
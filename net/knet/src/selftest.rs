@@ -0,0 +1,527 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Packet injection and capture for protocol conformance tests.
+//!
+//! A [`Bench`] is a network stack of its own, with one Ethernet interface on
+//! a [`TapNic`]. Frames injected into the tap go up the receive path as if
+//! they came off the wire, and every frame the stack transmits is captured
+//! with the time it left. The stack runs on a virtual clock that tests
+//! advance, so timers are checked without waiting for them.
+//!
+//! This is only built for unit tests.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::ptr::NonNull;
+
+use kdriver::prelude::{
+    DeviceKind, DriverError, DriverOps, DriverResult, MacAddress, NetBufHandle, NetDriverOps,
+};
+use ksync::Mutex;
+use smoltcp::{
+    iface::{SocketHandle, SocketSet},
+    phy::ChecksumCapabilities,
+    socket::{AnySocket, tcp},
+    time::{Duration, Instant},
+    wire::{
+        ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+        EthernetRepr, IpProtocol, Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr, TcpPacket,
+        TcpSeqNumber, UdpPacket, UdpRepr,
+    },
+};
+
+use crate::{
+    device::{EthernetDevice, Nic},
+    router::Router,
+    service::{Service, now},
+};
+
+/// Hardware address of the bench interface.
+pub const HOST_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x0f]);
+/// Hardware address of the simulated peer.
+pub const PEER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x02]);
+/// Address of the bench interface.
+pub const HOST_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+/// Address of the simulated peer, on the subnet of the bench interface.
+pub const PEER_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+/// Prefix length of the subnet of the bench interface.
+pub const PREFIX_LEN: u8 = 24;
+/// Step by which [`Bench::advance`] moves the virtual clock.
+pub const TICK: Duration = Duration::from_millis(10);
+
+/// A frame transmitted by the stack.
+#[derive(Debug, Clone)]
+pub struct Captured {
+    /// Virtual time the frame was sent at.
+    pub at: Instant,
+    /// The Ethernet frame.
+    pub frame: Vec<u8>,
+}
+
+impl Captured {
+    /// Returns the destination hardware address of the frame.
+    pub fn dst_mac(&self) -> EthernetAddress {
+        EthernetFrame::new_unchecked(&self.frame[..]).dst_addr()
+    }
+
+    /// Returns the protocol of the frame payload.
+    pub fn ethertype(&self) -> EthernetProtocol {
+        EthernetFrame::new_unchecked(&self.frame[..]).ethertype()
+    }
+
+    /// Returns the payload of the frame.
+    pub fn payload(&self) -> &[u8] {
+        &self.frame[EthernetFrame::<&[u8]>::header_len()..]
+    }
+
+    /// Returns the ARP packet in the frame, if it carries one.
+    pub fn arp(&self) -> Option<ArpRepr> {
+        if self.ethertype() != EthernetProtocol::Arp {
+            return None;
+        }
+        ArpPacket::new_checked(self.payload())
+            .and_then(|packet| ArpRepr::parse(&packet))
+            .ok()
+    }
+
+    /// Returns the IPv4 packet in the frame, if it carries one.
+    pub fn ipv4(&self) -> Option<Ipv4Packet<&[u8]>> {
+        if self.ethertype() != EthernetProtocol::Ipv4 {
+            return None;
+        }
+        Ipv4Packet::new_checked(self.payload()).ok()
+    }
+
+    /// Returns the payload of the IPv4 packet in the frame, if it is of
+    /// `protocol`.
+    fn ipv4_payload(&self, protocol: IpProtocol) -> Option<&[u8]> {
+        let ip = self.ipv4()?;
+        if ip.next_header() != protocol {
+            return None;
+        }
+        let (start, end) = (ip.header_len() as usize, ip.total_len() as usize);
+        self.payload().get(start..end)
+    }
+
+    /// Returns the TCP segment in the frame, if it carries one.
+    pub fn tcp(&self) -> Option<TcpPacket<&[u8]>> {
+        TcpPacket::new_checked(self.ipv4_payload(IpProtocol::Tcp)?).ok()
+    }
+
+    /// Returns the UDP datagram in the frame, if it carries one.
+    pub fn udp(&self) -> Option<UdpPacket<&[u8]>> {
+        UdpPacket::new_checked(self.ipv4_payload(IpProtocol::Udp)?).ok()
+    }
+
+    /// Returns the ICMPv4 message in the frame, if it carries one.
+    pub fn icmp(&self) -> Option<&[u8]> {
+        self.ipv4_payload(IpProtocol::Icmp)
+            .filter(|icmp| icmp.len() >= 8)
+    }
+}
+
+struct TapState {
+    /// Injected frames, not received by the stack yet.
+    rx: VecDeque<Vec<u8>>,
+    /// Frames sent by the stack.
+    tx: Vec<Captured>,
+    /// Current virtual time.
+    now: Instant,
+}
+
+/// Wraps `buf` into a handle, to be freed by [`free_handle`].
+fn into_handle(buf: Vec<u8>) -> NetBufHandle {
+    let mut buf = Box::new(buf);
+    let data = NonNull::new(buf.as_mut_ptr()).unwrap();
+    let len = buf.len();
+    NetBufHandle::new(NonNull::from(Box::leak(buf)).cast(), data, len)
+}
+
+fn free_handle(buf: NetBufHandle) {
+    // SAFETY: all handles of the tap come from `into_handle`.
+    drop(unsafe { Box::from_raw(buf.owner_ptr::<Vec<u8>>()) });
+}
+
+/// A NIC whose wire is the test, see the [module-level documentation](self).
+pub struct TapNic {
+    mac: EthernetAddress,
+    state: Arc<Mutex<TapState>>,
+}
+
+impl DriverOps for TapNic {
+    fn name(&self) -> &str {
+        "tap"
+    }
+
+    fn device_kind(&self) -> DeviceKind {
+        DeviceKind::Net
+    }
+}
+
+impl NetDriverOps for TapNic {
+    fn mac(&self) -> MacAddress {
+        MacAddress(self.mac.0)
+    }
+
+    fn can_tx(&self) -> bool {
+        true
+    }
+
+    fn can_rx(&self) -> bool {
+        !self.state.lock().rx.is_empty()
+    }
+
+    fn rx_queue_len(&self) -> usize {
+        64
+    }
+
+    fn tx_queue_len(&self) -> usize {
+        64
+    }
+
+    fn recycle_rx(&mut self, rx_buf: NetBufHandle) -> DriverResult {
+        free_handle(rx_buf);
+        Ok(())
+    }
+
+    fn recycle_tx(&mut self) -> DriverResult {
+        Ok(())
+    }
+
+    fn send(&mut self, tx_buf: NetBufHandle) -> DriverResult {
+        let mut state = self.state.lock();
+        let at = state.now;
+        state.tx.push(Captured {
+            at,
+            frame: tx_buf.data().to_vec(),
+        });
+        free_handle(tx_buf);
+        Ok(())
+    }
+
+    fn recv(&mut self) -> DriverResult<NetBufHandle> {
+        let frame = self.state.lock().rx.pop_front();
+        frame.map(into_handle).ok_or(DriverError::WouldBlock)
+    }
+
+    fn alloc_tx_buf(&mut self, size: usize) -> DriverResult<NetBufHandle> {
+        Ok(into_handle(vec![0; size]))
+    }
+}
+
+impl Nic for TapNic {
+    fn ops(&self) -> &dyn NetDriverOps {
+        self
+    }
+
+    fn ops_mut(&mut self) -> &mut dyn NetDriverOps {
+        self
+    }
+}
+
+/// A network stack on a [`TapNic`], driven by a virtual clock.
+///
+/// The interface `tap0` has the address [`HOST_IP`], and already knows the
+/// hardware address of [`PEER_IP`].
+pub struct Bench {
+    service: Service,
+    sockets: SocketSet<'static>,
+    state: Arc<Mutex<TapState>>,
+    now: Instant,
+}
+
+impl Bench {
+    /// Creates a bench, with nothing captured yet.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(TapState {
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+            now: Instant::from_millis(0),
+        }));
+        let nic = TapNic {
+            mac: HOST_MAC,
+            state: state.clone(),
+        };
+        let mut router = Router::new();
+        router.add_device(Box::new(EthernetDevice::new("tap0".into(), nic)));
+        let mut service = Service::new(router);
+        service
+            .add_ipv4_addr("tap0", Ipv4Cidr::new(HOST_IP, PREFIX_LEN))
+            .unwrap();
+
+        // The interface was created at the current time, which the virtual
+        // clock must not go back from.
+        let mut bench = Self {
+            service,
+            sockets: SocketSet::new(Vec::new()),
+            state,
+            now: now(),
+        };
+        bench.inject(ethernet(
+            PEER_MAC,
+            EthernetAddress::BROADCAST,
+            EthernetProtocol::Arp,
+            &arp(ArpOperation::Request, PEER_MAC, PEER_IP, HOST_IP),
+        ));
+        bench.take();
+        bench
+    }
+
+    /// Returns the virtual time.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Returns the stack, e.g. to configure the interface.
+    pub fn service(&mut self) -> &mut Service {
+        &mut self.service
+    }
+
+    /// Lets the stack process what is pending at the current virtual time.
+    pub fn poll(&mut self) {
+        self.state.lock().now = self.now;
+        for _ in 0..64 {
+            let poll_next = self.service.poll_at(&mut self.sockets, self.now);
+            if !poll_next && self.state.lock().rx.is_empty() {
+                break;
+            }
+        }
+    }
+
+    /// Moves the virtual clock forward by `dur`, polling at every [`TICK`].
+    pub fn advance(&mut self, dur: Duration) {
+        let end = self.now + dur;
+        while self.now < end {
+            self.now = (self.now + TICK).min(end);
+            self.poll();
+        }
+    }
+
+    /// Hands `frame` to the stack as if it was received, and polls.
+    pub fn inject(&mut self, frame: Vec<u8>) {
+        self.state.lock().rx.push_back(frame);
+        self.poll();
+    }
+
+    /// Injects the IPv4 `packet` as sent by the peer.
+    pub fn inject_ipv4(&mut self, packet: &[u8]) {
+        self.inject(ethernet(PEER_MAC, HOST_MAC, EthernetProtocol::Ipv4, packet));
+    }
+
+    /// Takes the frames captured so far.
+    pub fn take(&mut self) -> Vec<Captured> {
+        core::mem::take(&mut self.state.lock().tx)
+    }
+
+    /// Adds `socket` to the stack.
+    pub fn add<T: AnySocket<'static>>(&mut self, socket: T) -> SocketHandle {
+        self.sockets.add(socket)
+    }
+
+    /// Returns the socket `handle`.
+    pub fn socket<T: AnySocket<'static>>(&mut self, handle: SocketHandle) -> &mut T {
+        self.sockets.get_mut(handle)
+    }
+
+    /// Connects a new TCP socket from `local_port` to `remote_port` of the
+    /// peer, and polls, so that its SYN is captured.
+    pub fn tcp_connect(&mut self, local_port: u16, remote_port: u16) -> SocketHandle {
+        let mut socket = tcp_socket();
+        socket
+            .connect(
+                self.service.iface.context(),
+                (PEER_IP, remote_port),
+                local_port,
+            )
+            .unwrap();
+        let handle = self.add(socket);
+        self.poll();
+        handle
+    }
+}
+
+/// Creates a TCP socket with small buffers.
+pub fn tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; 4096]),
+        tcp::SocketBuffer::new(vec![0; 4096]),
+    )
+}
+
+/// Builds an Ethernet frame.
+pub fn ethernet(
+    src: EthernetAddress,
+    dst: EthernetAddress,
+    ethertype: EthernetProtocol,
+    payload: &[u8],
+) -> Vec<u8> {
+    let repr = EthernetRepr {
+        src_addr: src,
+        dst_addr: dst,
+        ethertype,
+    };
+    let mut buf = vec![0; repr.buffer_len() + payload.len()];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+    repr.emit(&mut frame);
+    frame.payload_mut().copy_from_slice(payload);
+    buf
+}
+
+/// Builds an ARP packet for the host, asking for the hardware address of
+/// `target_ip` or answering a request of the host.
+pub fn arp(
+    operation: ArpOperation,
+    sender_mac: EthernetAddress,
+    sender_ip: Ipv4Address,
+    target_ip: Ipv4Address,
+) -> Vec<u8> {
+    let target_mac = match operation {
+        ArpOperation::Reply => HOST_MAC,
+        _ => EthernetAddress([0; 6]),
+    };
+    let repr = ArpRepr::EthernetIpv4 {
+        operation,
+        source_hardware_addr: sender_mac,
+        source_protocol_addr: sender_ip,
+        target_hardware_addr: target_mac,
+        target_protocol_addr: target_ip,
+    };
+    let mut buf = vec![0; repr.buffer_len()];
+    repr.emit(&mut ArpPacket::new_unchecked(&mut buf[..]));
+    buf
+}
+
+/// Builds an IPv4 packet, with its header checksum.
+pub fn ipv4(src: Ipv4Address, dst: Ipv4Address, protocol: IpProtocol, payload: &[u8]) -> Vec<u8> {
+    let repr = Ipv4Repr {
+        src_addr: src,
+        dst_addr: dst,
+        next_header: protocol,
+        payload_len: payload.len(),
+        hop_limit: 64,
+    };
+    let mut buf = vec![0; repr.buffer_len() + payload.len()];
+    repr.emit(
+        &mut Ipv4Packet::new_unchecked(&mut buf[..]),
+        &ChecksumCapabilities::default(),
+    );
+    buf[repr.buffer_len()..].copy_from_slice(payload);
+    buf
+}
+
+/// Builds an IPv4 UDP datagram, with its checksums.
+pub fn udp(src: (Ipv4Address, u16), dst: (Ipv4Address, u16), payload: &[u8]) -> Vec<u8> {
+    let repr = UdpRepr {
+        src_port: src.1,
+        dst_port: dst.1,
+    };
+    let mut buf = vec![0; repr.header_len() + payload.len()];
+    repr.emit(
+        &mut UdpPacket::new_unchecked(&mut buf[..]),
+        &src.0.into(),
+        &dst.0.into(),
+        payload.len(),
+        |buf| buf.copy_from_slice(payload),
+        &ChecksumCapabilities::default(),
+    );
+    ipv4(src.0, dst.0, IpProtocol::Udp, &buf)
+}
+
+/// A TCP segment sent by the peer to the host.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: Option<u32>,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub window: u16,
+    /// Value of the MSS option, sent with SYNs.
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// Creates a segment with no flags set, and a window of 64 KiB - 1.
+    pub fn new(src_port: u16, dst_port: u16, seq: u32) -> Self {
+        Self {
+            src_port,
+            dst_port,
+            seq,
+            ack: None,
+            syn: false,
+            fin: false,
+            rst: false,
+            window: u16::MAX,
+            mss: None,
+            payload: &[],
+        }
+    }
+
+    /// Sets the SYN flag, with an MSS option of 1460.
+    pub fn syn(self) -> Self {
+        Self {
+            syn: true,
+            mss: Some(1460),
+            ..self
+        }
+    }
+
+    /// Sets the ACK flag, acknowledging `ack`.
+    pub fn ack(self, ack: u32) -> Self {
+        Self {
+            ack: Some(ack),
+            ..self
+        }
+    }
+
+    pub fn seq(self, seq: u32) -> Self {
+        Self { seq, ..self }
+    }
+
+    pub fn rst(self) -> Self {
+        Self { rst: true, ..self }
+    }
+
+    pub fn fin(self) -> Self {
+        Self { fin: true, ..self }
+    }
+
+    pub fn window(self, window: u16) -> Self {
+        Self { window, ..self }
+    }
+
+    pub fn payload(self, payload: &'a [u8]) -> Self {
+        Self { payload, ..self }
+    }
+
+    /// Builds the IPv4 packet of the segment, from the peer to the host.
+    pub fn to_ipv4(&self) -> Vec<u8> {
+        let header_len = if self.mss.is_some() { 24 } else { 20 };
+        let mut buf = vec![0; header_len + self.payload.len()];
+        let mut tcp = TcpPacket::new_unchecked(&mut buf[..]);
+        tcp.set_src_port(self.src_port);
+        tcp.set_dst_port(self.dst_port);
+        tcp.set_seq_number(TcpSeqNumber(self.seq as i32));
+        tcp.set_ack_number(TcpSeqNumber(self.ack.unwrap_or(0) as i32));
+        tcp.set_header_len(header_len as u8);
+        tcp.clear_flags();
+        tcp.set_syn(self.syn);
+        tcp.set_fin(self.fin);
+        tcp.set_rst(self.rst);
+        tcp.set_ack(self.ack.is_some());
+        tcp.set_window_len(self.window);
+        tcp.set_urgent_at(0);
+        if let Some(mss) = self.mss {
+            let [hi, lo] = mss.to_be_bytes();
+            tcp.options_mut().copy_from_slice(&[2, 4, hi, lo]);
+        }
+        tcp.payload_mut().copy_from_slice(self.payload);
+        tcp.fill_checksum(&PEER_IP.into(), &HOST_IP.into());
+        ipv4(PEER_IP, HOST_IP, IpProtocol::Tcp, &buf)
+    }
+}
@@ -3,23 +3,31 @@
 // See LICENSES for license details.
 
 //! Network service wrapper around smoltcp interface.
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String, vec::Vec};
 use core::{
     pin::Pin,
     task::{Context, Waker},
 };
 
+use hashbrown::HashMap;
+use kerrno::{KError, KResult, LinuxError};
 use khal::time::{NANOS_PER_MICROS, TimeValue, wall_time_nanos};
 use ktask::future::sleep_until;
 use smoltcp::{
     iface::{Interface, SocketSet},
     time::Instant,
-    wire::{HardwareAddress, IpAddress, IpListenEndpoint},
+    wire::{HardwareAddress, IpAddress, IpCidr, IpListenEndpoint, Ipv4Cidr},
 };
 
-use crate::{SOCKET_SET, router::Router};
+use crate::{
+    SOCKET_SET,
+    device::NetDevice,
+    frag::FragStats,
+    moderation::ModerationConfig,
+    router::{Router, Rule},
+};
 
-fn now() -> Instant {
+pub(crate) fn now() -> Instant {
     Instant::from_micros_const((wall_time_nanos() / NANOS_PER_MICROS) as i64)
 }
 
@@ -27,6 +35,8 @@ pub struct Service {
     pub iface: Interface,
     router: Router,
     timeout: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// Joined multicast groups with their join counts.
+    multicast: HashMap<IpAddress, usize>,
 }
 impl Service {
     pub fn new(mut router: Router) -> Self {
@@ -37,12 +47,119 @@ impl Service {
             iface,
             router,
             timeout: None,
+            multicast: HashMap::new(),
+        }
+    }
+
+    /// Joins the multicast `group` on all devices.
+    pub fn join_multicast(&mut self, group: IpAddress) -> KResult {
+        if !group.is_multicast() {
+            return Err(KError::InvalidInput);
+        }
+        self.router.join_multicast(group)?;
+        if !self.multicast.contains_key(&group)
+            && let Err(err) = self.iface.join_multicast_group(group)
+        {
+            warn!("join_multicast_group {group} failed: {err:?}");
+            let _ = self.router.leave_multicast(group);
+            return Err(KError::NoMemory);
+        }
+        *self.multicast.entry(group).or_default() += 1;
+        Ok(())
+    }
+
+    /// Drops one join of the multicast `group`.
+    pub fn leave_multicast(&mut self, group: IpAddress) -> KResult {
+        let Some(count) = self.multicast.get_mut(&group) else {
+            return Err(KError::from(LinuxError::EADDRNOTAVAIL));
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.multicast.remove(&group);
+            let _ = self.iface.leave_multicast_group(group);
+        }
+        self.router.leave_multicast(group)
+    }
+
+    pub fn set_promiscuous(&mut self, enable: bool) -> KResult {
+        self.router.set_promiscuous(enable)
+    }
+
+    pub fn frag_stats(&self) -> FragStats {
+        self.router.frag_stats()
+    }
+
+    pub fn moderation(&mut self, name: &str) -> KResult<ModerationConfig> {
+        self.router
+            .device_mut(name)?
+            .moderation()
+            .ok_or(KError::OperationNotSupported)
+    }
+
+    pub fn set_moderation(&mut self, name: &str, config: ModerationConfig) -> KResult {
+        self.router.device_mut(name)?.set_moderation(config)
+    }
+
+    /// Returns the names of the devices.
+    pub fn device_names(&self) -> Vec<String> {
+        self.router
+            .devices
+            .iter()
+            .map(|dev| dev.name().into())
+            .collect()
+    }
+
+    /// Adds a device that appeared after the stack was set up.
+    pub fn add_device(&mut self, device: Box<dyn NetDevice>) {
+        self.router.add_device(device);
+    }
+
+    pub fn set_mtu(&mut self, name: &str, mtu: usize) -> KResult {
+        self.router.device_mut(name)?.set_mtu(mtu)
+    }
+
+    /// Assigns `addr` to the device `name`, and routes its subnet there.
+    pub fn add_ipv4_addr(&mut self, name: &str, addr: Ipv4Cidr) -> KResult {
+        let dev = self.router.device_index(name)?;
+        let ip = IpAddress::from(addr.address());
+        if self.iface.ip_addrs().iter().any(|it| it.address() == ip) {
+            return Err(KError::AlreadyExists);
+        }
+        let mut full = false;
+        self.iface
+            .update_ip_addrs(|addrs| full = addrs.push(addr.into()).is_err());
+        if full {
+            return Err(KError::NoMemory);
+        }
+        if let Err(err) = self.router.devices[dev].add_ipv4_addr(addr) {
+            self.iface
+                .update_ip_addrs(|addrs| addrs.retain(|it| it.address() != ip));
+            return Err(err);
         }
+        self.router
+            .add_rule(Rule::new(addr.network().into(), None, dev, ip));
+        Ok(())
+    }
+
+    /// Routes `dest` through the gateway `via`, which must be on the subnet
+    /// of an address of the device `dev`, or of any device if `None`.
+    pub fn add_route(&mut self, dest: IpCidr, via: IpAddress, dev: Option<&str>) -> KResult {
+        let dev = dev.map(|name| self.router.device_index(name)).transpose()?;
+        let Some(link) = self.router.table.link_for(&via, dev) else {
+            return Err(KError::from(LinuxError::ENETUNREACH));
+        };
+        let rule = Rule::new(dest, Some(via), link.dev, link.src);
+        self.router.add_rule(rule);
+        Ok(())
     }
 
     pub fn poll(&mut self, sockets: &mut SocketSet) -> bool {
-        let timestamp = now();
+        self.poll_at(sockets, now())
+    }
 
+    /// Polls as if the time was `timestamp`, for tests driving the stack by
+    /// a virtual clock.
+    pub fn poll_at(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> bool {
         self.router.poll(timestamp);
         self.iface.poll(timestamp, &mut self.router, sockets);
         self.router.dispatch(timestamp)
@@ -22,7 +22,7 @@ use kpoll::{IoEvents, Pollable};
 #[cfg(feature = "vsock")]
 use crate::vsock::VsockSocket;
 use crate::{
-    options::{Configurable, GetSocketOption, SetSocketOption},
+    options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
     tcp::TcpSocket,
     udp::UdpSocket,
     unix::{UnixAddr, UnixDomainSocket},
@@ -99,6 +99,9 @@ pub struct SendOptions {
     pub to: Option<SocketAddrEx>,
     pub flags: SendFlags,
     pub cmsg: Vec<CMsgData>,
+    /// Credentials the data is sent with on Unix domain sockets, those of
+    /// the sending socket if `None`.
+    pub credentials: Option<UnixCredentials>,
 }
 
 /// Options for receiving data from a socket.
@@ -26,14 +26,19 @@ use crate::{
     RecvFlags, RecvOptions, SERVICE, SendOptions, Shutdown, Socket, SocketAddrEx, SocketOps,
     consts::{TCP_RX_BUF_LEN, TCP_TX_BUF_LEN},
     general::GeneralOptions,
+    mem::{Proto, SocketMem},
     options::{Configurable, GetSocketOption, SetSocketOption},
     poll_interfaces,
     state::*,
 };
 
 pub(crate) fn new_tcp_socket() -> smol::Socket<'static> {
+    new_tcp_socket_sized(TCP_RX_BUF_LEN)
+}
+
+fn new_tcp_socket_sized(rx_len: usize) -> smol::Socket<'static> {
     smol::Socket::new(
-        smol::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]),
+        smol::SocketBuffer::new(vec![0; rx_len]),
         smol::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]),
     )
 }
@@ -44,6 +49,7 @@ pub struct TcpSocket {
     dispatch_irq: SocketHandle,
 
     general: GeneralOptions,
+    mem: Arc<SocketMem>,
     rx_closed: AtomicBool,
     poll_rx_closed: Arc<PollSet>,
 }
@@ -53,11 +59,13 @@ unsafe impl Sync for TcpSocket {}
 impl TcpSocket {
     /// Creates a new TCP socket.
     pub fn new() -> Self {
+        let dispatch_irq = SOCKET_SET.add(new_tcp_socket());
         Self {
             state: StateLock::new(State::Idle),
-            dispatch_irq: SOCKET_SET.add(new_tcp_socket()),
+            dispatch_irq,
 
             general: GeneralOptions::new(),
+            mem: SOCKET_SET.socket_mem(dispatch_irq, Proto::Tcp),
             rx_closed: AtomicBool::new(false),
             poll_rx_closed: Arc::new(PollSet::new()),
         }
@@ -70,6 +78,7 @@ impl TcpSocket {
             dispatch_irq,
 
             general: GeneralOptions::new(),
+            mem: SOCKET_SET.socket_mem(dispatch_irq, Proto::Tcp),
             rx_closed: AtomicBool::new(false),
             poll_rx_closed: Arc::new(PollSet::new()),
         };
@@ -146,6 +155,22 @@ impl TcpSocket {
         events
     }
 
+    /// Replaces the receive buffer of a socket that is not connected yet, so
+    /// that the advertised window follows `SO_RCVBUF`.
+    fn resize_rx_buffer(&self, rx_len: usize) {
+        if self.state() != State::Idle {
+            return;
+        }
+        self.with_smol_socket(|socket| {
+            let mut resized = new_tcp_socket_sized(rx_len);
+            resized.set_nagle_enabled(socket.nagle_enabled());
+            resized.set_keep_alive(socket.keep_alive());
+            resized.set_hop_limit(socket.hop_limit());
+            resized.set_bound_endpoint(socket.get_bound_endpoint());
+            *socket = resized;
+        });
+    }
+
     fn poll_listener(&self) -> IoEvents {
         let mut events = IoEvents::empty();
         events.set(
@@ -178,10 +203,10 @@ impl Configurable for TcpSocket {
                 **max_segment = 1460;
             }
             O::SendBuffer(size) => {
-                **size = TCP_TX_BUF_LEN;
+                **size = self.mem.sndbuf();
             }
             O::ReceiveBuffer(size) => {
-                **size = TCP_RX_BUF_LEN;
+                **size = self.mem.rcvbuf();
             }
             O::TcpInfo(_) => {
                 // TODO(mivik): implement TCP_INFO
@@ -194,6 +219,18 @@ impl Configurable for TcpSocket {
     fn set_option_inner(&self, option: SetSocketOption) -> KResult<bool> {
         use SetSocketOption as O;
 
+        match option {
+            O::SendBuffer(size) => {
+                self.mem.set_sndbuf(*size);
+                return Ok(true);
+            }
+            O::ReceiveBuffer(size) => {
+                self.mem.set_rcvbuf(*size);
+                self.resize_rx_buffer(self.mem.rcvbuf());
+                return Ok(true);
+            }
+            _ => {}
+        }
         if self.general.set_option_inner(option)? {
             return Ok(true);
         }
@@ -368,13 +405,20 @@ impl SocketOps for TcpSocket {
                     Err(KError::WouldBlock)
                 } else {
                     // connected, and the tx buffer is not full
+                    let room = self.mem.sndbuf().saturating_sub(socket.send_queue());
+                    let room = self.mem.allowance(room);
+                    if room == 0 {
+                        return Err(KError::WouldBlock);
+                    }
                     let len = socket
                         .send(|buffer| {
-                            let result = src.read(buffer);
+                            let limit = buffer.len().min(room);
+                            let result = src.read(&mut buffer[..limit]);
                             let len = result.unwrap_or(0);
                             (len, result)
                         })
                         .map_err(|_| k_err_type!(NotConnected, "not connected?"))??;
+                    self.mem.sync(socket.recv_queue() + socket.send_queue());
                     Ok(len)
                 }
             })
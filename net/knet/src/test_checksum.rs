@@ -0,0 +1,114 @@
+//! Unit tests for checksum offload.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+use alloc::{vec, vec::Vec};
+
+use kdriver::prelude::NetCapabilities;
+use smoltcp::{
+    phy::{Checksum, ChecksumCapabilities},
+    wire::{IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr},
+};
+use unittest::def_test;
+
+use crate::{
+    device::{fill_checksums, tx_offload},
+    router::checksum_caps,
+};
+
+const SRC: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+const DST: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+/// Builds an IPv4 UDP packet with all checksums left zero.
+fn udp_packet(payload: &[u8]) -> Vec<u8> {
+    let udp = UdpRepr {
+        src_port: 5000,
+        dst_port: 53,
+    };
+    let ip = Ipv4Repr {
+        src_addr: SRC,
+        dst_addr: DST,
+        next_header: IpProtocol::Udp,
+        payload_len: udp.header_len() + payload.len(),
+        hop_limit: 64,
+    };
+    let mut buf = vec![0u8; ip.buffer_len() + ip.payload_len];
+    let caps = ChecksumCapabilities::ignored();
+    ip.emit(&mut Ipv4Packet::new_unchecked(&mut buf), &caps);
+    udp.emit(
+        &mut UdpPacket::new_unchecked(&mut buf[ip.buffer_len()..]),
+        &SRC.into(),
+        &DST.into(),
+        payload.len(),
+        |buf| buf.copy_from_slice(payload),
+        &caps,
+    );
+    buf
+}
+
+/// Computes the Internet checksum of `data`, like a NIC would.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[def_test]
+fn test_checksum_caps() {
+    let caps = checksum_caps(NetCapabilities::empty());
+    assert!(matches!(caps.ipv4, Checksum::Both));
+    assert!(matches!(caps.tcp, Checksum::Both));
+
+    let caps = checksum_caps(NetCapabilities::TX_CSUM_TCP | NetCapabilities::TX_CSUM_UDP);
+    assert!(matches!(caps.ipv4, Checksum::Both));
+    assert!(matches!(caps.tcp, Checksum::Rx));
+    assert!(matches!(caps.udp, Checksum::Rx));
+
+    let caps = checksum_caps(NetCapabilities::all());
+    assert!(matches!(caps.ipv4, Checksum::None));
+    assert!(matches!(caps.udp, Checksum::None));
+}
+
+#[def_test]
+fn test_loopback_fills_checksums() {
+    let mut buf = udp_packet(b"ping");
+    fill_checksums(&mut buf);
+    let ip = Ipv4Packet::new_checked(&buf).unwrap();
+    assert!(ip.verify_checksum());
+    let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+    assert!(udp.verify_checksum(&SRC.into(), &DST.into()));
+}
+
+#[def_test]
+fn test_tx_offload_seeds_pseudo_header() {
+    let mut buf = udp_packet(b"hello");
+    let offload = tx_offload(
+        NetCapabilities::TX_CSUM_IPV4 | NetCapabilities::TX_CSUM_UDP,
+        &mut buf,
+    );
+    assert!(offload.ipv4_csum);
+    // Offsets are relative to the Ethernet frame.
+    assert_eq!(offload.csum_start, 14 + 20);
+    assert_eq!(offload.csum_offset, 6);
+
+    // Do what the NIC does with the seeded packet.
+    let start = offload.csum_start as usize - 14;
+    let csum = internet_checksum(&buf[start..]);
+    let field = start + offload.csum_offset as usize;
+    buf[field..field + 2].copy_from_slice(&csum.to_be_bytes());
+    let ip = Ipv4Packet::new_checked(&buf).unwrap();
+    let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+    assert!(udp.verify_checksum(&SRC.into(), &DST.into()));
+
+    // Without the capability, the packet is left alone.
+    let mut buf = udp_packet(b"hello");
+    let offload = tx_offload(NetCapabilities::TX_CSUM_TCP, &mut buf);
+    assert!(offload.is_empty());
+    assert_eq!(buf, udp_packet(b"hello"));
+}
@@ -0,0 +1,262 @@
+//! Unit tests for IP fragmentation and reassembly.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+use alloc::{vec, vec::Vec};
+
+use smoltcp::{
+    phy::ChecksumCapabilities,
+    time::{Duration, Instant},
+    wire::{IpAddress, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, Ipv6Address, Ipv6Packet},
+};
+use unittest::def_test;
+
+use crate::frag::{
+    IP_MAX_DATAGRAM, IPFRAG_TIMEOUT, IcmpError, Reassembler, fragment_ipv4, icmp_error,
+};
+
+const SRC: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+const DST: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+const MTU: usize = 1500;
+
+fn start() -> Instant {
+    Instant::from_secs(0)
+}
+
+fn reassembler() -> Reassembler {
+    Reassembler::new(1024 * 1024, IPFRAG_TIMEOUT)
+}
+
+/// Builds an IPv4 datagram with `len` bytes of patterned payload.
+fn datagram(len: usize) -> Vec<u8> {
+    let repr = Ipv4Repr {
+        src_addr: SRC,
+        dst_addr: DST,
+        next_header: IpProtocol::Udp,
+        payload_len: len,
+        hop_limit: 64,
+    };
+    let mut buf = vec![0u8; repr.buffer_len() + len];
+    repr.emit(
+        &mut Ipv4Packet::new_unchecked(&mut buf[..]),
+        &ChecksumCapabilities::default(),
+    );
+    for (i, byte) in buf[repr.buffer_len()..].iter_mut().enumerate() {
+        *byte = (i ^ (i >> 8)) as u8;
+    }
+    buf
+}
+
+fn fragments(packet: &[u8], ident: u16) -> Vec<Vec<u8>> {
+    let mut frags = Vec::new();
+    fragment_ipv4(packet, MTU, ident, |frag| frags.push(frag.to_vec()));
+    frags
+}
+
+/// Moves the fragment `frag` to `offset` bytes into its datagram.
+fn move_fragment(frag: &[u8], offset: u16) -> Vec<u8> {
+    let mut frag = frag.to_vec();
+    let mut ip = Ipv4Packet::new_unchecked(&mut frag[..]);
+    ip.set_frag_offset(offset);
+    ip.fill_checksum();
+    frag
+}
+
+/// Checks that `reassembled` carries the same datagram as `original`.
+fn assert_same_datagram(reassembled: &[u8], original: &[u8]) {
+    let ip = Ipv4Packet::new_checked(reassembled).unwrap();
+    let orig = Ipv4Packet::new_checked(original).unwrap();
+    assert!(ip.verify_checksum());
+    assert!(!ip.more_frags());
+    assert_eq!(ip.frag_offset(), 0);
+    assert_eq!(ip.total_len(), orig.total_len());
+    assert_eq!(ip.next_header(), orig.next_header());
+    assert_eq!(ip.src_addr(), orig.src_addr());
+    assert!(ip.payload() == orig.payload());
+}
+
+#[def_test]
+fn test_frag_fragment_ipv4() {
+    let packet = datagram(4000);
+    let frags = fragments(&packet, 7);
+    assert_eq!(frags.len(), 3);
+    for (i, frag) in frags.iter().enumerate() {
+        let ip = Ipv4Packet::new_checked(&frag[..]).unwrap();
+        assert!(frag.len() <= MTU);
+        assert!(ip.verify_checksum());
+        assert!(!ip.dont_frag());
+        assert_eq!(ip.ident(), 7);
+        assert_eq!(ip.frag_offset() as usize, i * 1480);
+        assert_eq!(ip.more_frags(), i < 2);
+    }
+    assert_eq!(frags[2].len(), 20 + 4000 - 2 * 1480);
+}
+
+#[def_test]
+fn test_frag_reassemble_reordered() {
+    let packet = datagram(6000);
+    let frags = fragments(&packet, 1);
+    let mut reasm = reassembler();
+    for &i in &[2, 4, 0, 3] {
+        assert!(reasm.process(&frags[i], start()).is_none());
+    }
+    let reassembled = reasm.process(&frags[1], start()).unwrap();
+    assert_same_datagram(&reassembled, &packet);
+    assert_eq!(reasm.stats().reassembled, 1);
+    assert_eq!(reasm.mem_usage(), 0);
+}
+
+#[def_test]
+fn test_frag_duplicates_and_overlaps() {
+    let packet = datagram(4000);
+    let mut reasm = reassembler();
+
+    // Exact duplicates are dropped on their own.
+    let frags = fragments(&packet, 1);
+    assert!(reasm.process(&frags[1], start()).is_none());
+    assert!(reasm.process(&frags[1], start()).is_none());
+    assert!(reasm.process(&frags[0], start()).is_none());
+    assert!(reasm.process(&frags[0], start()).is_none());
+    let reassembled = reasm.process(&frags[2], start()).unwrap();
+    assert_same_datagram(&reassembled, &packet);
+
+    // An overlap discards the fragments received so far.
+    let frags = fragments(&packet, 2);
+    assert!(reasm.process(&frags[0], start()).is_none());
+    let overlapping = move_fragment(&frags[1], 1480 - 8);
+    assert!(reasm.process(&overlapping, start()).is_none());
+    assert_eq!(reasm.stats().overlaps, 1);
+    assert_eq!(reasm.mem_usage(), 0);
+    assert!(reasm.process(&frags[1], start()).is_none());
+    assert!(reasm.process(&frags[2], start()).is_none());
+
+    // So does a fragment that would end the datagram early.
+    let frags = fragments(&packet, 3);
+    assert!(reasm.process(&frags[2], start()).is_none());
+    let short_last = {
+        let mut frag = frags[1].clone();
+        let mut ip = Ipv4Packet::new_unchecked(&mut frag[..]);
+        ip.set_more_frags(false);
+        ip.fill_checksum();
+        frag
+    };
+    assert!(reasm.process(&short_last, start()).is_none());
+    assert_eq!(reasm.stats().invalid, 1);
+    assert_eq!(reasm.stats().reassembled, 1);
+}
+
+#[def_test]
+fn test_frag_max_datagram() {
+    let packet = datagram(IP_MAX_DATAGRAM - 20);
+    let frags = fragments(&packet, 9);
+    assert_eq!(frags.len(), 45);
+    let mut reasm = reassembler();
+    for frag in frags.iter().rev().skip(1) {
+        assert!(reasm.process(frag, start()).is_none());
+    }
+    let reassembled = reasm.process(&frags[0], start()).unwrap();
+    assert_eq!(reassembled.len(), IP_MAX_DATAGRAM);
+    assert_same_datagram(&reassembled, &packet);
+
+    // A fragment reaching past the largest datagram is refused.
+    let last = frags.last().unwrap();
+    let offset = Ipv4Packet::new_unchecked(&last[..]).frag_offset();
+    assert!(
+        reasm
+            .process(&move_fragment(last, offset + 8), start())
+            .is_none()
+    );
+    assert_eq!(reasm.stats().invalid, 1);
+    assert_eq!(reasm.mem_usage(), 0);
+}
+
+#[def_test]
+fn test_frag_ipv6() {
+    const SRC6: Ipv6Address = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    const DST6: Ipv6Address = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+    let payload = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+    let fragment = |offset: usize, len: usize, more: bool| {
+        let mut buf = vec![0u8; 40 + 8 + len];
+        let mut ip = Ipv6Packet::new_unchecked(&mut buf[..]);
+        ip.set_version(6);
+        ip.set_payload_len((8 + len) as u16);
+        ip.set_next_header(IpProtocol::Ipv6Frag);
+        ip.set_hop_limit(64);
+        ip.set_src_addr(SRC6);
+        ip.set_dst_addr(DST6);
+        let frag = &mut buf[40..48];
+        frag[0] = IpProtocol::Udp.into();
+        frag[2..4].copy_from_slice(&(offset as u16 | more as u16).to_be_bytes());
+        frag[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        buf[48..].copy_from_slice(&payload[offset..offset + len]);
+        buf
+    };
+
+    let mut reasm = reassembler();
+    assert!(
+        reasm
+            .process(&fragment(1448, 1552, false), start())
+            .is_none()
+    );
+    let reassembled = reasm.process(&fragment(0, 1448, true), start()).unwrap();
+    let ip = Ipv6Packet::new_checked(&reassembled[..]).unwrap();
+    assert_eq!(ip.next_header(), IpProtocol::Udp);
+    assert_eq!(ip.payload_len(), 3000);
+    assert!(ip.payload() == &payload[..]);
+
+    // Overlaps are handled as for IPv4.
+    assert!(reasm.process(&fragment(0, 1448, true), start()).is_none());
+    assert!(
+        reasm
+            .process(&fragment(1440, 1560, false), start())
+            .is_none()
+    );
+    assert_eq!(reasm.stats().overlaps, 1);
+}
+
+#[def_test]
+fn test_frag_flood_is_capped() {
+    const LIMIT: usize = 64 * 1024;
+    let packet = datagram(4000);
+    let mut reasm = Reassembler::new(LIMIT, IPFRAG_TIMEOUT);
+
+    // First fragments of datagrams that never complete.
+    for ident in 0..1000 {
+        let first = &fragments(&packet, ident)[0];
+        assert!(reasm.process(first, start()).is_none());
+        assert!(reasm.mem_usage() <= LIMIT);
+    }
+    let dropped = reasm.stats().mem_drops;
+    assert!(dropped > 0);
+
+    // Expired queues are answered and their memory released.
+    let mut early = 0;
+    reasm.expire(start() + IPFRAG_TIMEOUT - Duration::from_secs(1), |_, _| {
+        early += 1
+    });
+    assert_eq!(early, 0);
+    let mut quotes = Vec::new();
+    reasm.expire(start() + IPFRAG_TIMEOUT, |src, quote| {
+        assert_eq!(src, IpAddress::Ipv4(SRC));
+        quotes.push(quote.to_vec());
+    });
+    assert_eq!(quotes.len() as u64, 1000 - dropped);
+    assert_eq!(reasm.stats().timeouts, 1000 - dropped);
+    assert_eq!(reasm.mem_usage(), 0);
+
+    // The error quotes the IP header and 8 bytes of the first fragment.
+    let error = icmp_error(DST.into(), &quotes[0], IcmpError::ReassemblyTimeout).unwrap();
+    let ip = Ipv4Packet::new_checked(&error[..]).unwrap();
+    assert_eq!(ip.dst_addr(), SRC);
+    assert_eq!(ip.next_header(), IpProtocol::Icmp);
+    assert_eq!(ip.payload()[..2], [11, 1]);
+    assert_eq!(ip.payload().len(), 8 + 20 + 8);
+
+    // Datagrams get through again.
+    let later = start() + IPFRAG_TIMEOUT;
+    let frags = fragments(&packet, 1000);
+    assert!(reasm.process(&frags[0], later).is_none());
+    assert!(reasm.process(&frags[1], later).is_none());
+    assert!(reasm.process(&frags[2], later).is_some());
+}
@@ -0,0 +1,295 @@
+//! IPv4, UDP, ICMP and ARP conformance scenarios, run against a [`Bench`].
+//!
+//! IPv6 neighbour discovery is not covered: the Ethernet adapter only
+//! carries IPv4.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+use alloc::{vec, vec::Vec};
+
+use smoltcp::{
+    iface::SocketHandle,
+    socket::udp,
+    time::Duration,
+    wire::{ArpOperation, ArpRepr, EthernetAddress, EthernetProtocol, IpProtocol, Ipv4Address},
+};
+use unittest::def_test;
+
+use crate::{
+    frag::{IPFRAG_TIMEOUT, fragment_ipv4},
+    selftest::{
+        Bench, Captured, HOST_IP, HOST_MAC, PEER_IP, PEER_MAC, TICK, arp, ethernet, ipv4, udp,
+    },
+};
+
+/// Port of the host sockets.
+const PORT: u16 = 19000;
+/// Port of the peer.
+const PEER_PORT: u16 = 40000;
+/// Another host on the link, not known to the host yet.
+const OTHER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x03]);
+const OTHER_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 3);
+
+fn udp_bind(bench: &mut Bench) -> SocketHandle {
+    let mut socket = udp::Socket::new(
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; 8], vec![0; 4096]),
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; 8], vec![0; 4096]),
+    );
+    socket.bind(PORT).unwrap();
+    bench.add(socket)
+}
+
+fn recv(bench: &mut Bench, handle: SocketHandle) -> Option<Vec<u8>> {
+    let socket = bench.socket::<udp::Socket>(handle);
+    socket.recv().ok().map(|(data, _)| data.to_vec())
+}
+
+fn icmp(captured: &[Captured]) -> Vec<&[u8]> {
+    captured.iter().filter_map(Captured::icmp).collect()
+}
+
+/// Builds an ICMP message with its checksum.
+fn icmp_message(ty: u8, code: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![ty, code, 0, 0, 0, 0, 0, 0];
+    buf.extend_from_slice(body);
+    let mut sum = buf
+        .chunks(2)
+        .map(|it| u16::from_be_bytes([it[0], it.get(1).copied().unwrap_or(0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    buf[2..4].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+    buf
+}
+
+/// Injects an ARP packet from `sender_mac`, broadcast for a request.
+fn inject_arp(
+    bench: &mut Bench,
+    operation: ArpOperation,
+    sender_mac: EthernetAddress,
+    sender_ip: Ipv4Address,
+    target_ip: Ipv4Address,
+) {
+    let dst = match operation {
+        ArpOperation::Reply => HOST_MAC,
+        _ => EthernetAddress::BROADCAST,
+    };
+    let packet = arp(operation, sender_mac, sender_ip, target_ip);
+    bench.inject(ethernet(sender_mac, dst, EthernetProtocol::Arp, &packet));
+}
+
+#[def_test]
+fn test_udp_zero_checksum() {
+    let mut bench = Bench::new();
+    let handle = udp_bind(&mut bench);
+
+    // Over IPv4, a zero checksum means none was computed.
+    let mut packet = udp((PEER_IP, PEER_PORT), (HOST_IP, PORT), b"no checksum");
+    packet[26..28].fill(0);
+    bench.inject_ipv4(&packet);
+    assert_eq!(
+        recv(&mut bench, handle).as_deref(),
+        Some(&b"no checksum"[..])
+    );
+}
+
+#[def_test]
+fn test_udp_bad_checksum() {
+    let mut bench = Bench::new();
+    let handle = udp_bind(&mut bench);
+    let mut packet = udp((PEER_IP, PEER_PORT), (HOST_IP, PORT), b"corrupted");
+    packet[26] ^= 0xff;
+    bench.inject_ipv4(&packet);
+    assert_eq!(recv(&mut bench, handle), None);
+    // A corrupted datagram cannot be trusted to name its sender.
+    assert!(icmp(&bench.take()).is_empty());
+}
+
+#[def_test]
+fn test_icmp_port_unreachable() {
+    let mut bench = Bench::new();
+    let packet = udp((PEER_IP, PEER_PORT), (HOST_IP, PORT), b"anyone there?");
+    bench.inject_ipv4(&packet);
+    let captured = bench.take();
+    assert_eq!(captured.len(), 1);
+    let ip = captured[0].ipv4().unwrap();
+    assert_eq!(ip.src_addr(), HOST_IP);
+    assert_eq!(ip.dst_addr(), PEER_IP);
+    let icmp = captured[0].icmp().unwrap();
+    assert_eq!(&icmp[..2], &[3, 3]);
+    // The quote starts with the offending IP header and its first 8 bytes.
+    assert_eq!(&icmp[8..8 + 28], &packet[..28]);
+}
+
+#[def_test]
+fn test_icmp_not_sent_for_broadcast() {
+    let mut bench = Bench::new();
+    for dst in [Ipv4Address::new(10, 0, 2, 255), Ipv4Address::BROADCAST] {
+        let packet = udp((PEER_IP, PEER_PORT), (dst, PORT), b"hello all");
+        bench.inject(ethernet(
+            PEER_MAC,
+            EthernetAddress::BROADCAST,
+            EthernetProtocol::Ipv4,
+            &packet,
+        ));
+    }
+    assert!(icmp(&bench.take()).is_empty());
+}
+
+#[def_test]
+fn test_icmp_not_sent_for_icmp_error() {
+    let mut bench = Bench::new();
+    // A port unreachable error for a datagram the host never sent, quoting a
+    // closed port of the host.
+    let quoted = udp((HOST_IP, PORT), (PEER_IP, PEER_PORT), b"");
+    let error = icmp_message(3, 3, &quoted);
+    bench.inject_ipv4(&ipv4(PEER_IP, HOST_IP, IpProtocol::Icmp, &error));
+    assert!(icmp(&bench.take()).is_empty());
+}
+
+/// Returns the fragments of a UDP datagram too large for the link.
+fn fragments() -> Vec<Vec<u8>> {
+    let packet = udp((PEER_IP, PEER_PORT), (HOST_IP, PORT), &[0x5a; 3000]);
+    let mut fragments = Vec::new();
+    fragment_ipv4(&packet, 1500, 0x1234, |it| fragments.push(it.to_vec()));
+    assert!(fragments.len() >= 3);
+    fragments
+}
+
+#[def_test]
+fn test_reassembly_timeout() {
+    let mut bench = Bench::new();
+    let fragments = fragments();
+    let start = bench.now();
+    bench.inject_ipv4(&fragments[0]);
+
+    bench.advance(IPFRAG_TIMEOUT - TICK * 2);
+    assert!(icmp(&bench.take()).is_empty());
+    bench.advance(TICK * 4);
+    let captured = bench.take();
+    let errors: Vec<_> = captured.iter().filter(|it| it.icmp().is_some()).collect();
+    assert_eq!(errors.len(), 1);
+    let icmp = errors[0].icmp().unwrap();
+    assert_eq!(&icmp[..2], &[11, 1]);
+    assert!(errors[0].at >= start + IPFRAG_TIMEOUT - TICK * 2);
+    assert_eq!(errors[0].ipv4().unwrap().dst_addr(), PEER_IP);
+    assert_eq!(&icmp[8..8 + 20], &fragments[0][..20]);
+}
+
+#[def_test]
+fn test_reassembly_timeout_without_first_fragment() {
+    let mut bench = Bench::new();
+    let fragments = fragments();
+    bench.inject_ipv4(&fragments[1]);
+    bench.advance(IPFRAG_TIMEOUT + Duration::from_secs(1));
+    // Without the first fragment there is nothing to quote.
+    assert!(icmp(&bench.take()).is_empty());
+}
+
+#[def_test]
+fn test_arp_reply() {
+    let mut bench = Bench::new();
+    inject_arp(
+        &mut bench,
+        ArpOperation::Request,
+        OTHER_MAC,
+        OTHER_IP,
+        HOST_IP,
+    );
+    let captured = bench.take();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].dst_mac(), OTHER_MAC);
+    assert_eq!(
+        captured[0].arp(),
+        Some(ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Reply,
+            source_hardware_addr: HOST_MAC,
+            source_protocol_addr: HOST_IP,
+            target_hardware_addr: OTHER_MAC,
+            target_protocol_addr: OTHER_IP,
+        })
+    );
+}
+
+#[def_test]
+fn test_arp_ignored() {
+    let mut bench = Bench::new();
+    // Not for the host.
+    inject_arp(
+        &mut bench,
+        ArpOperation::Request,
+        OTHER_MAC,
+        OTHER_IP,
+        Ipv4Address::new(10, 0, 2, 4),
+    );
+    // From a sender that cannot be answered.
+    inject_arp(
+        &mut bench,
+        ArpOperation::Request,
+        EthernetAddress::BROADCAST,
+        OTHER_IP,
+        HOST_IP,
+    );
+    assert!(bench.take().is_empty());
+}
+
+#[def_test]
+fn test_arp_resolution() {
+    let mut bench = Bench::new();
+    let handle = udp_bind(&mut bench);
+    let socket = bench.socket::<udp::Socket>(handle);
+    socket
+        .send_slice(b"first", (OTHER_IP, PEER_PORT).into())
+        .unwrap();
+    socket
+        .send_slice(b"second", (OTHER_IP, PEER_PORT).into())
+        .unwrap();
+    bench.poll();
+
+    // Both datagrams wait on a single request.
+    let captured = bench.take();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].dst_mac(), EthernetAddress::BROADCAST);
+    let Some(ArpRepr::EthernetIpv4 {
+        operation,
+        target_protocol_addr,
+        ..
+    }) = captured[0].arp()
+    else {
+        panic!("expected an ARP request, got {captured:?}");
+    };
+    assert_eq!(operation, ArpOperation::Request);
+    assert_eq!(target_protocol_addr, OTHER_IP);
+
+    inject_arp(
+        &mut bench,
+        ArpOperation::Reply,
+        OTHER_MAC,
+        OTHER_IP,
+        HOST_IP,
+    );
+    let captured = bench.take();
+    let payloads: Vec<_> = captured
+        .iter()
+        .filter(|it| it.dst_mac() == OTHER_MAC)
+        .filter_map(|it| it.udp().map(|udp| udp.payload().to_vec()))
+        .collect();
+    assert_eq!(payloads, [b"first".to_vec(), b"second".to_vec()]);
+}
+
+#[def_test]
+fn test_rx_filter() {
+    let mut bench = Bench::new();
+    let handle = udp_bind(&mut bench);
+    let packet = udp((PEER_IP, PEER_PORT), (HOST_IP, PORT), b"overheard");
+    let frame = ethernet(PEER_MAC, OTHER_MAC, EthernetProtocol::Ipv4, &packet);
+
+    bench.inject(frame.clone());
+    assert_eq!(recv(&mut bench, handle), None);
+
+    bench.service().set_promiscuous(true).unwrap();
+    bench.inject(frame);
+    assert_eq!(recv(&mut bench, handle).as_deref(), Some(&b"overheard"[..]));
+}
@@ -0,0 +1,116 @@
+//! Loopback tests for socket memory accounting.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::vec;
+use core::net::{Ipv4Addr, SocketAddr};
+
+use unittest::def_test;
+
+use crate::{
+    RecvOptions, SendOptions, SocketAddrEx, SocketOps,
+    mem::{MemLimits, MemScope, mem_limits, set_mem_limits, socket_mem_stats},
+    options::{Configurable, SetSocketOption},
+    poll_interfaces,
+    tcp::TcpSocket,
+    udp::UdpSocket,
+};
+
+const KIB: usize = 1024;
+
+fn loopback(port: u16) -> SocketAddrEx {
+    SocketAddrEx::Ip(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port))
+}
+
+/// Runs `f` with the budget of `scope` set to `limits`, then restores it.
+fn with_limits(scope: MemScope, limits: MemLimits, f: impl FnOnce()) {
+    let saved = mem_limits(scope);
+    set_mem_limits(scope, limits).unwrap();
+    f();
+    set_mem_limits(scope, saved).unwrap();
+}
+
+#[def_test]
+fn test_udp_flood_plateaus_at_cap() {
+    let limits = MemLimits {
+        min: 16 * KIB,
+        pressure: 32 * KIB,
+        max: 48 * KIB,
+    };
+    with_limits(MemScope::Udp, limits, || {
+        let rx = UdpSocket::new();
+        rx.bind(loopback(17001)).unwrap();
+        let tx = UdpSocket::new();
+        tx.set_option(SetSocketOption::NonBlocking(&true)).unwrap();
+
+        let payload = [0x5au8; 1024];
+        let mut peak = 0;
+        for _ in 0..1024 {
+            let _ = tx.send(
+                &payload[..],
+                SendOptions {
+                    to: Some(loopback(17001)),
+                    ..Default::default()
+                },
+            );
+            poll_interfaces();
+            peak = peak.max(socket_mem_stats().udp.usage);
+        }
+
+        let stats = socket_mem_stats().udp;
+        assert!(peak <= limits.max, "UDP usage peaked at {peak}");
+        assert!(stats.pressure_events > 0);
+        assert!(stats.dropped > 0);
+
+        // Unrelated allocations still succeed.
+        let unrelated = vec![0u8; 256 * KIB];
+        assert_eq!(unrelated.len(), 256 * KIB);
+    });
+}
+
+#[def_test]
+fn test_tcp_stalled_receiver_plateaus_at_cap() {
+    let limits = MemLimits {
+        min: 32 * KIB,
+        pressure: 64 * KIB,
+        max: 96 * KIB,
+    };
+    with_limits(MemScope::Tcp, limits, || {
+        let listener = TcpSocket::new();
+        listener.bind(loopback(17002)).unwrap();
+        listener.listen().unwrap();
+
+        let tx = TcpSocket::new();
+        tx.connect(loopback(17002)).unwrap();
+        // The accepted socket is never read from.
+        let rx = listener.accept().unwrap();
+        tx.set_option(SetSocketOption::NonBlocking(&true)).unwrap();
+
+        let chunk = [0xa5u8; 4096];
+        let mut peak = 0;
+        for _ in 0..256 {
+            let _ = tx.send(&chunk[..], SendOptions::default());
+            poll_interfaces();
+            peak = peak.max(socket_mem_stats().tcp.usage);
+        }
+
+        let stats = socket_mem_stats().tcp;
+        assert!(peak <= limits.max, "TCP usage peaked at {peak}");
+        assert!(stats.declined > 0);
+
+        let unrelated = vec![0u8; 256 * KIB];
+        assert_eq!(unrelated.len(), 256 * KIB);
+
+        // Draining the receiver releases the memory.
+        let mut sink = [0u8; 4096];
+        rx.set_option(SetSocketOption::NonBlocking(&true)).unwrap();
+        while rx
+            .recv(&mut sink[..], RecvOptions::default())
+            .is_ok_and(|n| n > 0)
+        {}
+        poll_interfaces();
+        assert!(socket_mem_stats().tcp.usage < peak);
+    });
+}
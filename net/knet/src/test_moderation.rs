@@ -0,0 +1,94 @@
+//! Unit tests for interrupt moderation.
+
+#![cfg(unittest)]
+
+use smoltcp::time::Instant;
+use unittest::def_test;
+
+use crate::moderation::{Adaptive, IDLE_GAP, Napi, PROFILES, static_profile};
+
+fn at(ms: i64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+#[def_test]
+fn test_static_profile() {
+    assert_eq!(static_profile(0), &PROFILES[0]);
+    assert_eq!(static_profile(15), &PROFILES[0]);
+    assert_eq!(static_profile(16), &PROFILES[1]);
+    assert_eq!(static_profile(100), &PROFILES[2]);
+    assert_eq!(static_profile(u32::MAX), &PROFILES[3]);
+}
+
+#[def_test]
+fn test_adaptive_follows_rate() {
+    let mut adaptive = Adaptive::default();
+    assert_eq!(adaptive.update(0, at(0)), None);
+    // 100k packets per second takes two samples to move up a profile.
+    assert_eq!(adaptive.update(1000, at(10)), None);
+    assert_eq!(adaptive.update(1000, at(20)), Some(&PROFILES[1]));
+    assert_eq!(adaptive.update(1000, at(30)), None);
+    assert_eq!(adaptive.update(1000, at(40)), Some(&PROFILES[2]));
+    assert_eq!(adaptive.update(1000, at(50)), None);
+    assert_eq!(adaptive.profile(), &PROFILES[2]);
+
+    // 30k is below what led up from the profile below, but not below half
+    // of it.
+    assert_eq!(adaptive.update(300, at(60)), None);
+    assert_eq!(adaptive.update(300, at(70)), None);
+    assert_eq!(adaptive.profile(), &PROFILES[2]);
+    assert_eq!(adaptive.update(100, at(80)), None);
+    assert_eq!(adaptive.update(100, at(90)), Some(&PROFILES[1]));
+
+    // Polls within a sample only add up.
+    assert_eq!(adaptive.update(100, at(95)), None);
+    assert_eq!(adaptive.profile(), &PROFILES[1]);
+}
+
+#[def_test]
+fn test_adaptive_hysteresis() {
+    let mut adaptive = Adaptive::default();
+    adaptive.update(0, at(0));
+    // A burst between quiet samples does not move the profile.
+    for i in 0..8 {
+        let packets = if i % 2 == 0 { 1000 } else { 10 };
+        assert_eq!(adaptive.update(packets, at(10 * (i + 1))), None);
+    }
+    assert_eq!(adaptive.profile(), &PROFILES[0]);
+}
+
+#[def_test]
+fn test_adaptive_idle_gap() {
+    let mut adaptive = Adaptive::default();
+    adaptive.update(0, at(0));
+    for i in 1..=6 {
+        adaptive.update(5000, at(10 * i));
+    }
+    assert_eq!(adaptive.profile(), &PROFILES[3]);
+    // The first packet after a pause is served at the lowest latency.
+    let later = at(60) + IDLE_GAP;
+    assert_eq!(adaptive.update(1, later), Some(&PROFILES[0]));
+}
+
+#[def_test]
+fn test_napi() {
+    let mut napi = Napi::default();
+    assert!(!napi.empty(2));
+    assert!(napi.received());
+    assert!(!napi.received());
+    assert!(napi.polling());
+
+    // Polling stops after more than `idle_polls` empty polls in a row.
+    assert!(!napi.empty(2));
+    assert!(!napi.empty(2));
+    // A packet starts the count over.
+    assert!(!napi.received());
+    assert!(!napi.empty(2));
+    assert!(!napi.empty(2));
+    assert!(napi.empty(2));
+    assert!(!napi.polling());
+
+    // Without idle polls, the first empty poll stops it.
+    assert!(napi.received());
+    assert!(napi.empty(0));
+}
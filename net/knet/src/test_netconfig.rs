@@ -0,0 +1,189 @@
+//! Unit tests for the boot network configuration.
+
+#![cfg(unittest)]
+
+use alloc::{format, string::String, vec::Vec};
+
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use unittest::def_test;
+
+use crate::netconfig::{NetConfig, overlaps};
+
+fn cidr(a: u8, b: u8, c: u8, d: u8, len: u8) -> Ipv4Cidr {
+    Ipv4Cidr::new(Ipv4Address::new(a, b, c, d), len)
+}
+
+/// Returns the token and the reason of the error of `cmdline`.
+fn rejected(cmdline: &str) -> (String, &'static str) {
+    let err = NetConfig::parse("", cmdline).unwrap_err();
+    (err.token, err.reason)
+}
+
+#[def_test]
+fn test_linux_ip_syntax() {
+    let config = NetConfig::parse(
+        "",
+        "console=ttyS0 ip=10.0.2.15::10.0.2.2:255.255.255.0::eth0:off",
+    )
+    .unwrap();
+    assert_eq!(config.interfaces.len(), 1);
+    let eth0 = &config.interfaces[0];
+    assert_eq!(eth0.name, "eth0");
+    assert_eq!(eth0.addrs, [cidr(10, 0, 2, 15, 24)]);
+    assert_eq!(eth0.gateway, Some(Ipv4Address::new(10, 0, 2, 2)));
+    assert!(eth0.up);
+}
+
+#[def_test]
+fn test_defaults_and_prefix() {
+    let config = NetConfig::parse("", "ip=192.168.1.10 ip=10.1.0.1/16:::::eth1").unwrap();
+    assert_eq!(config.interfaces[0].name, "eth0");
+    assert_eq!(config.interfaces[0].addrs, [cidr(192, 168, 1, 10, 24)]);
+    assert_eq!(config.interfaces[1].name, "eth1");
+    assert_eq!(config.interfaces[1].addrs, [cidr(10, 1, 0, 1, 16)]);
+    assert_eq!(config.interfaces[1].gateway, None);
+}
+
+#[def_test]
+fn test_multiple_interfaces_and_routes() {
+    let config = NetConfig::parse(
+        "ip=10.0.0.2/24::10.0.0.1:::eth0\nnetif=eth1,mtu=1400",
+        "ip=10.0.1.2/24:::::eth1 ip=10.0.2.2/24:::::eth1 netroute=172.16.0.0/12,via=10.0.2.1 \
+         netif=eth2,down",
+    )
+    .unwrap();
+    let names: Vec<_> = config.interfaces.iter().map(|it| &*it.name).collect();
+    assert_eq!(names, ["eth0", "eth1", "eth2"]);
+    assert_eq!(config.interfaces[1].mtu, Some(1400));
+    assert_eq!(config.interfaces[1].addrs.len(), 2);
+    assert!(!config.interfaces[2].up);
+    assert_eq!(config.routes.len(), 1);
+    assert_eq!(config.routes[0].dest, cidr(172, 16, 0, 0, 12));
+    // Picked by the subnet of the gateway.
+    assert_eq!(config.routes[0].dev, "eth1");
+}
+
+#[def_test]
+fn test_off_and_empty() {
+    assert!(
+        NetConfig::parse("", "console=ttyS0 root=/dev/vda1")
+            .unwrap()
+            .is_empty()
+    );
+    let config = NetConfig::parse("", "ip=off").unwrap();
+    assert!(config.off);
+    assert!(!config.is_empty());
+}
+
+#[def_test]
+fn test_malformed_entries_name_the_token() {
+    assert_eq!(
+        rejected("quiet ip=10.0.2.300:::::eth0"),
+        ("ip=10.0.2.300:::::eth0".into(), "malformed IPv4 address")
+    );
+    assert_eq!(
+        rejected("ip=dhcp").1,
+        "only static configuration is supported"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15:::::eth0:dhcp").1,
+        "only static configuration is supported"
+    );
+    assert_eq!(rejected("ip=10.0.2.15/33").1, "malformed prefix length");
+    assert_eq!(
+        rejected("ip=10.0.2.15:::::eth0:off:8.8.8.8").1,
+        "too many fields"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15:::255.0.255.0").1,
+        "malformed netmask"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15/16:::255.255.255.0").1,
+        "netmask contradicts the prefix length"
+    );
+    assert_eq!(rejected("ip=10.0.2.0/24").1, "not a host address");
+    assert_eq!(rejected("ip=127.0.0.2/32").1, "not a host address");
+    assert_eq!(
+        rejected("ip=10.0.2.15::10.0.3.1").1,
+        "gateway is not on the subnet"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15:::::eth0/x").1,
+        "malformed interface name"
+    );
+    assert_eq!(rejected("netif=lo,mtu=1400").1, "lo cannot be configured");
+    assert_eq!(rejected("netif=eth0,mtu=9000").1, "MTU out of range");
+    assert_eq!(rejected("netif=eth0,mtu=big").1, "malformed MTU");
+    assert_eq!(rejected("netif=eth0,fast").1, "unknown option");
+    assert_eq!(
+        rejected("netroute=10.0.0.0,via=10.0.2.2").1,
+        "missing prefix length"
+    );
+    assert_eq!(
+        rejected("netroute=10.0.0.1/8,via=10.0.2.2").1,
+        "destination has host bits set"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15 netroute=10.0.0.0/8").1,
+        "missing gateway"
+    );
+}
+
+#[def_test]
+fn test_overlapping_subnets_rejected() {
+    assert!(overlaps(&cidr(10, 0, 0, 0, 8), &cidr(10, 1, 2, 0, 24)));
+    assert!(overlaps(&cidr(10, 1, 2, 0, 24), &cidr(10, 0, 0, 0, 8)));
+    assert!(!overlaps(&cidr(10, 0, 1, 0, 24), &cidr(10, 0, 2, 0, 24)));
+
+    assert_eq!(
+        rejected("ip=10.0.2.15/24:::::eth0 ip=10.0.0.1/16:::::eth1"),
+        (
+            "ip=10.0.0.1/16:::::eth1".into(),
+            "subnet overlaps an earlier subnet"
+        )
+    );
+    // On the same interface too.
+    assert_eq!(
+        rejected("ip=10.0.2.15/24 ip=10.0.2.16/24").1,
+        "subnet overlaps an earlier subnet"
+    );
+}
+
+#[def_test]
+fn test_routes_validated() {
+    assert_eq!(
+        rejected("ip=10.0.2.15 netroute=10.9.0.0/16,via=10.0.3.1"),
+        (
+            "netroute=10.9.0.0/16,via=10.0.3.1".into(),
+            "gateway is not on a configured subnet"
+        )
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15 ip=10.0.3.15:::::eth1 netroute=10.9.0.0/16,via=10.0.3.1,dev=eth0").1,
+        "gateway is not on a configured subnet"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15::10.0.2.2 netroute=default,via=10.0.2.3").1,
+        "conflicts with the default gateway of `ip=`"
+    );
+    assert_eq!(
+        rejected("ip=10.0.2.15::10.0.2.2 ip=10.0.3.15::10.0.3.2:::eth1").1,
+        "conflicts with an earlier default gateway"
+    );
+    assert_eq!(
+        rejected(
+            "ip=10.0.2.15 netroute=10.9.0.0/16,via=10.0.2.1 netroute=10.9.0.0/16,via=10.0.2.2"
+        )
+        .1,
+        "duplicate route"
+    );
+}
+
+#[def_test]
+fn test_unknown_entry_in_blob() {
+    let err = NetConfig::parse("ip=10.0.2.15 hostname=box", "").unwrap_err();
+    assert_eq!(err.token, "hostname=box");
+    assert_eq!(err.reason, "unknown entry");
+    assert_eq!(format!("{err}"), "`hostname=box`: unknown entry");
+}
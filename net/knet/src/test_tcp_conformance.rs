@@ -0,0 +1,326 @@
+//! TCP conformance scenarios, run against a [`Bench`].
+
+#![cfg(unittest)]
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use smoltcp::{
+    iface::SocketHandle,
+    socket::tcp::{Socket, State},
+    time::Duration,
+    wire::TcpPacket,
+};
+use unittest::def_test;
+
+use crate::selftest::{Bench, Captured, PEER_IP, PEER_MAC, Segment, TICK, tcp_socket};
+
+/// Port of the host sockets.
+const PORT: u16 = 18000;
+/// Port of the peer.
+const PEER_PORT: u16 = 40000;
+/// Initial sequence number of the peer.
+const PEER_ISN: u32 = 1000;
+/// Round-trip time the peer answers with.
+const RTT: Duration = Duration::from_millis(50);
+
+fn seq(tcp: &TcpPacket<&[u8]>) -> u32 {
+    tcp.seq_number().0 as u32
+}
+
+fn ack(tcp: &TcpPacket<&[u8]>) -> u32 {
+    tcp.ack_number().0 as u32
+}
+
+/// Returns the value of the MSS option of `tcp`.
+fn mss(tcp: &TcpPacket<&[u8]>) -> Option<u16> {
+    let options = tcp.options();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => return None,
+            1 => i += 1,
+            2 if options.get(i + 1) == Some(&4) && i + 4 <= options.len() => {
+                return Some(u16::from_be_bytes([options[i + 2], options[i + 3]]));
+            }
+            _ => {
+                i += options
+                    .get(i + 1)
+                    .map_or(options.len(), |&len| (len as usize).max(2))
+            }
+        }
+    }
+    None
+}
+
+/// Returns the only TCP segment among `captured`.
+fn only_segment(captured: &[Captured]) -> &Captured {
+    let segments: Vec<_> = captured.iter().filter(|it| it.tcp().is_some()).collect();
+    assert_eq!(segments.len(), 1, "expected one segment, got {captured:?}");
+    segments[0]
+}
+
+/// Returns the segments among `captured` that carry data.
+fn data_segments(captured: &[Captured]) -> Vec<&Captured> {
+    captured
+        .iter()
+        .filter(|it| it.tcp().is_some_and(|tcp| !tcp.payload().is_empty()))
+        .collect()
+}
+
+fn state(bench: &mut Bench, handle: SocketHandle) -> State {
+    bench.socket::<Socket>(handle).state()
+}
+
+fn listen(bench: &mut Bench) -> SocketHandle {
+    let mut socket = tcp_socket();
+    socket.listen(PORT).unwrap();
+    bench.add(socket)
+}
+
+fn peer() -> Segment<'static> {
+    Segment::new(PEER_PORT, PORT, PEER_ISN)
+}
+
+/// Completes a handshake with a listening socket, and returns the initial
+/// sequence number of the host.
+fn accept(bench: &mut Bench, handle: SocketHandle) -> u32 {
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    let captured = bench.take();
+    let isn = seq(&only_segment(&captured).tcp().unwrap());
+    bench.advance(RTT);
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1).ack(isn + 1).to_ipv4());
+    assert_eq!(state(bench, handle), State::Established);
+    isn
+}
+
+#[def_test]
+fn test_tcp_handshake() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    let captured = bench.take();
+    let synack = only_segment(&captured);
+    assert_eq!(synack.dst_mac(), PEER_MAC);
+    assert_eq!(synack.ipv4().unwrap().dst_addr(), PEER_IP);
+    let tcp = synack.tcp().unwrap();
+    assert!(tcp.syn() && tcp.ack() && !tcp.rst());
+    assert_eq!(tcp.dst_port(), PEER_PORT);
+    assert_eq!(ack(&tcp), PEER_ISN + 1);
+    assert!(mss(&tcp).is_some_and(|mss| mss <= 1460));
+    assert_eq!(state(&mut bench, handle), State::SynReceived);
+
+    let isn = seq(&tcp);
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1).ack(isn + 1).to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Established);
+    assert!(bench.take().is_empty());
+}
+
+#[def_test]
+fn test_tcp_mss_follows_mtu() {
+    let mut bench = Bench::new();
+    bench.service().set_mtu("tap0", 1280).unwrap();
+    listen(&mut bench);
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    let captured = bench.take();
+    let tcp = only_segment(&captured).tcp().unwrap();
+    assert!(mss(&tcp).is_some_and(|mss| mss <= 1280 - 40));
+
+    // So does the MSS of an active open.
+    let handle = bench.tcp_connect(PORT + 1, PEER_PORT);
+    let captured = bench.take();
+    let tcp = only_segment(&captured).tcp().unwrap();
+    assert!(tcp.syn() && !tcp.ack());
+    assert!(mss(&tcp).is_some_and(|mss| mss <= 1280 - 40));
+    assert_eq!(state(&mut bench, handle), State::SynSent);
+}
+
+#[def_test]
+fn test_tcp_simultaneous_open() {
+    let mut bench = Bench::new();
+    let handle = bench.tcp_connect(PORT, PEER_PORT);
+    let captured = bench.take();
+    let isn = seq(&only_segment(&captured).tcp().unwrap());
+
+    // The SYNs cross: the host answers the one of the peer with a SYN-ACK of
+    // its own sequence number.
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    let captured = bench.take();
+    let tcp = only_segment(&captured).tcp().unwrap();
+    assert!(tcp.syn() && tcp.ack());
+    assert_eq!(seq(&tcp), isn);
+    assert_eq!(ack(&tcp), PEER_ISN + 1);
+    assert_eq!(state(&mut bench, handle), State::SynReceived);
+
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1).ack(isn + 1).to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Established);
+}
+
+#[def_test]
+fn test_tcp_rst_in_listen() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    bench.inject_ipv4(&peer().rst().to_ipv4());
+    bench.inject_ipv4(&peer().rst().ack(1).to_ipv4());
+    assert!(bench.take().is_empty());
+    assert_eq!(state(&mut bench, handle), State::Listen);
+
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    assert!(only_segment(&bench.take()).tcp().unwrap().syn());
+}
+
+#[def_test]
+fn test_tcp_rst_in_syn_sent() {
+    let mut bench = Bench::new();
+    let handle = bench.tcp_connect(PORT, PEER_PORT);
+    let captured = bench.take();
+    let isn = seq(&only_segment(&captured).tcp().unwrap());
+
+    // Only a RST acknowledging the SYN is acceptable.
+    bench.inject_ipv4(&peer().seq(0).rst().to_ipv4());
+    bench.inject_ipv4(&peer().seq(0).rst().ack(isn + 100).to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::SynSent);
+    bench.inject_ipv4(&peer().seq(0).rst().ack(isn + 1).to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Closed);
+    assert!(
+        bench
+            .take()
+            .iter()
+            .all(|it| it.tcp().is_none_or(|tcp| !tcp.rst()))
+    );
+}
+
+#[def_test]
+fn test_tcp_rst_in_syn_received() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    bench.take();
+    assert_eq!(state(&mut bench, handle), State::SynReceived);
+
+    // A passive open goes back to listening.
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1).rst().to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Listen);
+    bench.inject_ipv4(&peer().seq(5000).syn().to_ipv4());
+    let captured = bench.take();
+    assert_eq!(ack(&only_segment(&captured).tcp().unwrap()), 5001);
+}
+
+#[def_test]
+fn test_tcp_rst_in_established() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    accept(&mut bench, handle);
+
+    // A RST outside the receive window is not believed.
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1 + 1_000_000).rst().to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Established);
+    bench.take();
+
+    bench.inject_ipv4(&peer().seq(PEER_ISN + 1).rst().to_ipv4());
+    assert_eq!(state(&mut bench, handle), State::Closed);
+    // Never answer a RST with a RST.
+    assert!(
+        bench
+            .take()
+            .iter()
+            .all(|it| it.tcp().is_none_or(|tcp| !tcp.rst()))
+    );
+}
+
+#[def_test]
+fn test_tcp_rst_for_closed_port() {
+    let mut bench = Bench::new();
+    bench.inject_ipv4(&peer().syn().to_ipv4());
+    let captured = bench.take();
+    let tcp = only_segment(&captured).tcp().unwrap();
+    assert!(tcp.rst() && tcp.ack() && !tcp.syn());
+    assert_eq!(seq(&tcp), 0);
+    assert_eq!(ack(&tcp), PEER_ISN + 1);
+
+    // A segment with an ACK is reset from the sequence number it acknowledges.
+    bench.inject_ipv4(&peer().ack(777).payload(b"hello").to_ipv4());
+    let captured = bench.take();
+    let tcp = only_segment(&captured).tcp().unwrap();
+    assert!(tcp.rst());
+    assert_eq!(seq(&tcp), 777);
+
+    bench.inject_ipv4(&peer().rst().to_ipv4());
+    assert!(bench.take().is_empty());
+}
+
+#[def_test]
+fn test_tcp_zero_window_probe() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    let isn = accept(&mut bench, handle);
+    let data = peer().seq(PEER_ISN + 1).ack(isn + 1);
+    bench.inject_ipv4(&data.window(0).to_ipv4());
+
+    let sent = bench
+        .socket::<Socket>(handle)
+        .send_slice(&[0x55; 100])
+        .unwrap();
+    assert_eq!(sent, 100);
+    let start = bench.now();
+    bench.poll();
+    assert!(data_segments(&bench.take()).is_empty());
+
+    // The window is probed with at most a byte, and not before a
+    // retransmission timeout could have passed.
+    bench.advance(Duration::from_secs(5));
+    let captured = bench.take();
+    let probes = data_segments(&captured);
+    assert!(!probes.is_empty());
+    assert!(probes[0].at >= start + RTT);
+    for probe in &probes {
+        let tcp = probe.tcp().unwrap();
+        assert!(tcp.payload().len() <= 1);
+        assert_eq!(seq(&tcp), isn + 1);
+    }
+
+    bench.inject_ipv4(&data.window(4096).to_ipv4());
+    bench.advance(Duration::from_millis(100));
+    let captured = bench.take();
+    let end = data_segments(&captured)
+        .iter()
+        .map(|it| {
+            let tcp = it.tcp().unwrap();
+            seq(&tcp).wrapping_sub(isn + 1) as usize + tcp.payload().len()
+        })
+        .max();
+    assert_eq!(end, Some(100));
+}
+
+#[def_test]
+fn test_tcp_retransmission_backoff() {
+    let mut bench = Bench::new();
+    let handle = listen(&mut bench);
+    let isn = accept(&mut bench, handle);
+
+    bench.socket::<Socket>(handle).send_slice(b"ping").unwrap();
+    bench.poll();
+    bench.advance(Duration::from_secs(40));
+    let captured = bench.take();
+    let times: Vec<_> = data_segments(&captured)
+        .iter()
+        .filter(|it| seq(&it.tcp().unwrap()) == isn + 1)
+        .map(|it| it.at)
+        .collect();
+    assert!(times.len() >= 4, "only {} transmissions", times.len());
+
+    let intervals: Vec<_> = times.windows(2).map(|it| it[1] - it[0]).collect();
+    // No sooner than the measured round trip, and no later than the
+    // initial RTO of RFC 6298.
+    assert!(intervals[0] >= RTT && intervals[0] <= Duration::from_secs(3) + TICK);
+    for pair in intervals.windows(2) {
+        assert!(pair[1] + TICK >= pair[0], "backoff shrank: {intervals:?}");
+    }
+    assert!(
+        intervals
+            .iter()
+            .all(|&it| it <= Duration::from_secs(60) + TICK)
+    );
+    let last = *intervals.last().unwrap();
+    assert!(last + TICK >= intervals[0] * 2, "no backoff: {intervals:?}");
+}
@@ -0,0 +1,82 @@
+//! Unit tests for the send and receive timeouts of sockets.
+
+#![cfg(unittest)]
+
+use core::time::Duration;
+
+use kerrno::KError;
+use khal::time::wall_time;
+use unittest::def_test;
+
+use crate::{
+    RecvOptions, SendOptions, SocketOps,
+    options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
+    unix::{StreamTransport, UnixDomainSocket},
+};
+
+fn pair() -> (UnixDomainSocket, UnixDomainSocket) {
+    let (a, b) = StreamTransport::new_pair(UnixCredentials::default());
+    (UnixDomainSocket::new(a), UnixDomainSocket::new(b))
+}
+
+fn set_recv_timeout(sock: &impl Configurable, timeout: Duration) {
+    sock.set_option(SetSocketOption::ReceiveTimeout(&timeout))
+        .unwrap();
+}
+
+fn recv_timeout(sock: &impl Configurable) -> Duration {
+    let mut timeout = Duration::MAX;
+    sock.get_option(GetSocketOption::ReceiveTimeout(&mut timeout))
+        .unwrap();
+    timeout
+}
+
+#[def_test]
+fn test_timeout_options() {
+    let (a, _b) = pair();
+    // None by default, which blocks forever.
+    assert_eq!(recv_timeout(&a), Duration::ZERO);
+    set_recv_timeout(&a, Duration::from_micros(1_500_001));
+    assert_eq!(recv_timeout(&a), Duration::from_micros(1_500_001));
+    set_recv_timeout(&a, Duration::ZERO);
+    assert_eq!(recv_timeout(&a), Duration::ZERO);
+    // Too long to be stored, but still a timeout.
+    set_recv_timeout(&a, Duration::MAX);
+    assert_eq!(recv_timeout(&a), Duration::from_nanos(u64::MAX));
+
+    let timeout = Duration::from_secs(3);
+    a.set_option(SetSocketOption::SendTimeout(&timeout))
+        .unwrap();
+    let mut current = Duration::ZERO;
+    a.get_option(GetSocketOption::SendTimeout(&mut current))
+        .unwrap();
+    assert_eq!(current, timeout);
+}
+
+#[def_test]
+fn test_recv_times_out() {
+    let (_a, b) = pair();
+    let timeout = Duration::from_millis(50);
+    set_recv_timeout(&b, timeout);
+    let start = wall_time();
+    let mut buf = [0u8; 8];
+    assert_eq!(
+        b.recv(&mut buf[..], RecvOptions::default()),
+        Err(KError::WouldBlock)
+    );
+    assert!(wall_time() - start >= timeout);
+}
+
+#[def_test]
+fn test_recv_completes_before_deadline() {
+    let (a, b) = pair();
+    set_recv_timeout(&b, Duration::from_millis(500));
+    let sender = ktask::spawn(move || {
+        ktask::sleep(Duration::from_millis(400));
+        a.send(&b"pong"[..], SendOptions::default()).unwrap();
+    });
+    let mut buf = [0u8; 8];
+    let count = b.recv(&mut buf[..], RecvOptions::default()).unwrap();
+    assert_eq!(&buf[..count], b"pong");
+    sender.join();
+}
@@ -0,0 +1,268 @@
+//! Unit tests for the credentials and the ancillary data of Unix domain
+//! sockets.
+
+#![cfg(unittest)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+use kerrno::{KError, KResult, LinuxError};
+use unittest::def_test;
+
+use crate::{
+    CMsgData, RecvOptions, SendOptions, Socket, SocketAddrEx, SocketOps,
+    options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
+    unix::{
+        DgramTransport, PassedSockets, StreamTransport, UnixAddr, UnixDomainSocket,
+        stream::SenderRuns,
+    },
+};
+
+fn cred(pid: u32) -> UnixCredentials {
+    UnixCredentials {
+        pid,
+        uid: 1000 + pid,
+        gid: 2000 + pid,
+    }
+}
+
+fn peer_cred(sock: &impl Configurable) -> UnixCredentials {
+    let mut cred = UnixCredentials::default();
+    sock.get_option(GetSocketOption::PeerCredentials(&mut cred))
+        .unwrap();
+    cred
+}
+
+fn set_pass_cred(sock: &impl Configurable, pass: bool) {
+    sock.set_option(SetSocketOption::PassCredentials(&pass))
+        .unwrap();
+    let mut current = !pass;
+    sock.get_option(GetSocketOption::PassCredentials(&mut current))
+        .unwrap();
+    assert_eq!(current, pass);
+}
+
+fn send(sock: &impl SocketOps, data: &[u8], credentials: Option<UnixCredentials>) {
+    let options = SendOptions {
+        credentials,
+        ..Default::default()
+    };
+    assert_eq!(sock.send(data, options).unwrap(), data.len());
+}
+
+/// Receives up to `len` bytes, with the credentials passed along.
+fn recv(sock: &impl SocketOps, len: usize) -> (Vec<u8>, Vec<UnixCredentials>) {
+    let mut buf = vec![0; len];
+    let mut cmsg = Vec::new();
+    let options = RecvOptions {
+        cmsg: Some(&mut cmsg),
+        ..Default::default()
+    };
+    let count = sock.recv(&mut buf[..], options).unwrap();
+    buf.truncate(count);
+    let creds = cmsg
+        .into_iter()
+        .filter_map(|cmsg| cmsg.downcast::<UnixCredentials>().ok())
+        .map(|cred| *cred)
+        .collect();
+    (buf, creds)
+}
+
+#[def_test]
+fn test_socketpair_credentials() {
+    let (a, b) = StreamTransport::new_pair(cred(7));
+    let (a, b) = (UnixDomainSocket::new(a), UnixDomainSocket::new(b));
+    assert_eq!(peer_cred(&a), cred(7));
+    assert_eq!(peer_cred(&b), cred(7));
+
+    let (a, b) = DgramTransport::new_pair(cred(8));
+    let (a, b) = (UnixDomainSocket::new(a), UnixDomainSocket::new(b));
+    assert_eq!(peer_cred(&a), cred(8));
+    set_pass_cred(&b, true);
+    send(&a, b"ping", None);
+    assert_eq!(recv(&b, 16), (b"ping".to_vec(), vec![cred(8)]));
+}
+
+#[def_test]
+fn test_connect_credentials() {
+    let addr = SocketAddrEx::Unix(UnixAddr::Abstract(Arc::from(&b"test_unix_cred"[..])));
+    let server = UnixDomainSocket::new(StreamTransport::new(cred(10)));
+    server.bind(addr.clone()).unwrap();
+    server.listen().unwrap();
+    let client = UnixDomainSocket::new(StreamTransport::new(cred(11)));
+    client.connect(addr).unwrap();
+    let Socket::Unix(conn) = server.accept().unwrap() else {
+        panic!("accepted a socket of another domain");
+    };
+
+    // Each end sees the other as it was when the connection was made.
+    assert_eq!(peer_cred(&client), cred(10));
+    assert_eq!(peer_cred(&*conn), cred(11));
+    // Data sent through the connection later carries its sender's.
+    set_pass_cred(&*conn, true);
+    send(&client, b"hi", Some(cred(12)));
+    assert_eq!(recv(&*conn, 16), (b"hi".to_vec(), vec![cred(12)]));
+    assert_eq!(peer_cred(&*conn), cred(11));
+}
+
+#[def_test]
+fn test_pass_credentials_mid_stream() {
+    let (a, b) = StreamTransport::new_pair(cred(7));
+    let (a, b) = (UnixDomainSocket::new(a), UnixDomainSocket::new(b));
+
+    send(&a, b"one", None);
+    assert_eq!(recv(&b, 16), (b"one".to_vec(), vec![]));
+
+    // Turned on mid-stream: from now on, reads stop where the sender changes.
+    set_pass_cred(&b, true);
+    send(&a, b"two", Some(cred(9)));
+    send(&a, b"three", None);
+    send(&a, b"four", None);
+    assert_eq!(recv(&b, 16), (b"two".to_vec(), vec![cred(9)]));
+    assert_eq!(recv(&b, 4), (b"thre".to_vec(), vec![cred(7)]));
+    assert_eq!(recv(&b, 16), (b"efour".to_vec(), vec![cred(7)]));
+
+    // And off again: reads span senders and carry no credentials.
+    set_pass_cred(&b, false);
+    send(&a, b"five", Some(cred(9)));
+    send(&a, b"six", None);
+    assert_eq!(recv(&b, 16), (b"fivesix".to_vec(), vec![]));
+}
+
+#[def_test]
+fn test_sender_runs() {
+    let mut runs = SenderRuns::default();
+    assert_eq!(runs.front(), None);
+    runs.push(3, cred(1));
+    runs.push(0, cred(2));
+    runs.push(2, cred(1));
+    runs.push(4, cred(2));
+    assert_eq!(runs.front(), Some((5, cred(1))));
+
+    runs.consume(4);
+    assert_eq!(runs.front(), Some((1, cred(1))));
+    runs.consume(2);
+    assert_eq!(runs.front(), Some((3, cred(2))));
+    runs.consume(3);
+    assert_eq!(runs.front(), None);
+}
+
+/// Stands in for a file descriptor in flight.
+type Token = Arc<()>;
+
+fn send_cmsg(sock: &impl SocketOps, data: &[u8], cmsg: Vec<CMsgData>) -> KResult<usize> {
+    let options = SendOptions {
+        cmsg,
+        ..Default::default()
+    };
+    sock.send(data, options)
+}
+
+/// Receives up to `len` bytes, with the tokens passed along.
+fn recv_tokens(sock: &impl SocketOps, len: usize) -> (Vec<u8>, Vec<Token>) {
+    let mut buf = vec![0; len];
+    let mut cmsg = Vec::new();
+    let options = RecvOptions {
+        cmsg: Some(&mut cmsg),
+        ..Default::default()
+    };
+    let count = sock.recv(&mut buf[..], options).unwrap();
+    buf.truncate(count);
+    let tokens = cmsg
+        .into_iter()
+        .filter_map(|cmsg| cmsg.downcast::<Token>().ok())
+        .map(|token| *token)
+        .collect();
+    (buf, tokens)
+}
+
+fn stream_pair() -> (UnixDomainSocket, UnixDomainSocket) {
+    let (a, b) = StreamTransport::new_pair(cred(7));
+    (UnixDomainSocket::new(a), UnixDomainSocket::new(b))
+}
+
+fn dgram_pair() -> (UnixDomainSocket, UnixDomainSocket) {
+    let (a, b) = DgramTransport::new_pair(cred(7));
+    (UnixDomainSocket::new(a), UnixDomainSocket::new(b))
+}
+
+#[def_test]
+fn test_sender_runs_cmsg() {
+    let mut runs = SenderRuns::default();
+    runs.push(2, cred(1));
+    runs.push_with_cmsg(3, cred(1), vec![Box::new(()) as CMsgData]);
+    runs.push(4, cred(1));
+    // A read stops before the bytes sent with control messages, and may go
+    // on after them.
+    assert_eq!(runs.readable(true), 2);
+    assert!(runs.take_cmsg().is_empty());
+    runs.consume(2);
+    assert_eq!(runs.readable(true), 7);
+    assert_eq!(runs.readable(false), 7);
+    assert_eq!(runs.take_cmsg().len(), 1);
+    assert!(runs.take_cmsg().is_empty());
+}
+
+#[def_test]
+fn test_stream_passes_cmsg_with_first_byte() {
+    let (a, b) = stream_pair();
+    let token = Token::default();
+    send(&a, b"ab", None);
+    send_cmsg(&a, b"cd", vec![Box::new(token.clone()) as CMsgData]).unwrap();
+    send(&a, b"ef", None);
+    assert_eq!(Arc::strong_count(&token), 2);
+
+    assert_eq!(recv_tokens(&b, 16), (b"ab".to_vec(), vec![]));
+    let (data, tokens) = recv_tokens(&b, 3);
+    assert_eq!(data, b"cde");
+    assert!(tokens.len() == 1 && Arc::ptr_eq(&tokens[0], &token));
+    drop(tokens);
+    assert_eq!(recv_tokens(&b, 16), (b"f".to_vec(), vec![]));
+    assert_eq!(Arc::strong_count(&token), 1);
+}
+
+#[def_test]
+fn test_inflight_survives_sender_and_dies_with_receiver() {
+    for (a, b) in [stream_pair(), dgram_pair()] {
+        let token = Token::default();
+        send_cmsg(&a, b"x", vec![Box::new(token.clone()) as CMsgData]).unwrap();
+        drop(a);
+        let (data, tokens) = recv_tokens(&b, 16);
+        assert_eq!(data, b"x");
+        assert!(tokens.len() == 1 && Arc::ptr_eq(&tokens[0], &token));
+        drop(tokens);
+
+        let (c, d) = stream_pair();
+        send_cmsg(&c, b"y", vec![Box::new(token.clone()) as CMsgData]).unwrap();
+        assert_eq!(Arc::strong_count(&token), 2);
+        // Closing the receiver closes what was in flight to it.
+        drop(d);
+        assert_eq!(Arc::strong_count(&token), 1);
+        drop(c);
+    }
+}
+
+#[def_test]
+fn test_passing_sockets_cannot_make_cycles() {
+    let too_many_refs = Err(KError::from(LinuxError::ETOOMANYREFS));
+    let passing = |sock: &UnixDomainSocket| {
+        vec![
+            Box::new(PassedSockets(vec![sock.rx_queue().unwrap()])) as CMsgData,
+            Box::new(Token::default()) as CMsgData,
+        ]
+    };
+    for (a, b) in [stream_pair(), dgram_pair()] {
+        // Over itself: `b` would keep itself open.
+        assert_eq!(send_cmsg(&a, b"x", passing(&b)), too_many_refs);
+        // `a` is kept open by `b`, which it does not keep open.
+        assert_eq!(send_cmsg(&a, b"x", passing(&a)), Ok(1));
+
+        // Now that `b` keeps `a` open, it may not be passed until read.
+        let (c, d) = stream_pair();
+        assert_eq!(send_cmsg(&c, b"y", passing(&b)), too_many_refs);
+        assert_eq!(recv_tokens(&b, 16).0, b"x");
+        assert_eq!(send_cmsg(&c, b"y", passing(&b)), Ok(1));
+        drop((c, d));
+    }
+}
@@ -3,13 +3,13 @@
 // See LICENSES for license details.
 
 //! UDP socket implementation.
-use alloc::vec;
+use alloc::{sync::Arc, vec, vec::Vec};
 use core::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     task::Context,
 };
 
-use kerrno::{KError, KResult, k_bail, k_err_type};
+use kerrno::{KError, KResult, LinuxError, k_bail, k_err_type};
 use kio::prelude::*;
 use kpoll::{IoEvents, Pollable};
 use ksync::{Mutex, RwLock};
@@ -25,6 +25,7 @@ use crate::{
     RecvFlags, RecvOptions, SERVICE, SOCKET_SET, SendOptions, Shutdown, SocketAddrEx, SocketOps,
     consts::{UDP_RX_BUF_LEN, UDP_TX_BUF_LEN},
     general::GeneralOptions,
+    mem::{Proto, SocketMem},
     options::{Configurable, GetSocketOption, SetSocketOption},
     poll_interfaces,
 };
@@ -44,6 +45,9 @@ pub struct UdpSocket {
     peer_addr: RwLock<Option<(IpEndpoint, IpAddress)>>,
 
     general: GeneralOptions,
+    mem: Arc<SocketMem>,
+    /// Multicast groups joined through `IP_ADD_MEMBERSHIP`.
+    memberships: Mutex<Vec<Ipv4Addr>>,
 }
 
 impl UdpSocket {
@@ -59,6 +63,8 @@ impl UdpSocket {
             peer_addr: RwLock::new(None),
 
             general: GeneralOptions::new(),
+            mem: SOCKET_SET.socket_mem(dispatch_irq, Proto::Udp),
+            memberships: Mutex::new(Vec::new()),
         }
     }
 
@@ -66,6 +72,16 @@ impl UdpSocket {
         SOCKET_SET.with_socket_mut::<smol::Socket, _, _>(self.dispatch_irq, f)
     }
 
+    /// Returns whether a datagram of `len` bytes may join the `queued` bytes
+    /// of the send buffer.
+    fn may_queue(&self, queued: usize, len: usize) -> bool {
+        // A datagram larger than `SO_SNDBUF` still goes out on its own.
+        if queued > 0 && queued + len > self.mem.sndbuf() {
+            return false;
+        }
+        self.mem.allowance(len) == len
+    }
+
     fn remote_endpoint(&self) -> KResult<(IpEndpoint, IpAddress)> {
         match self.peer_addr.try_read() {
             Some(addr) => addr.ok_or(KError::NotConnected),
@@ -88,10 +104,10 @@ impl Configurable for UdpSocket {
                 });
             }
             O::SendBuffer(size) => {
-                **size = UDP_TX_BUF_LEN;
+                **size = self.mem.sndbuf();
             }
             O::ReceiveBuffer(size) => {
-                **size = UDP_RX_BUF_LEN;
+                **size = self.mem.rcvbuf();
             }
             _ => return Ok(false),
         }
@@ -101,6 +117,17 @@ impl Configurable for UdpSocket {
     fn set_option_inner(&self, option: SetSocketOption) -> KResult<bool> {
         use SetSocketOption as O;
 
+        match option {
+            O::SendBuffer(size) => {
+                self.mem.set_sndbuf(*size);
+                return Ok(true);
+            }
+            O::ReceiveBuffer(size) => {
+                self.mem.set_rcvbuf(*size);
+                return Ok(true);
+            }
+            _ => {}
+        }
         if self.general.set_option_inner(option)? {
             return Ok(true);
         }
@@ -110,6 +137,25 @@ impl Configurable for UdpSocket {
                     socket.set_hop_limit(Some(*ttl));
                 });
             }
+            O::AddMembership(mreq) => {
+                if !mreq.multiaddr.is_multicast() {
+                    k_bail!(InvalidInput, "not a multicast group");
+                }
+                let mut memberships = self.memberships.lock();
+                if memberships.contains(&mreq.multiaddr) {
+                    return Err(KError::AddrInUse);
+                }
+                crate::join_multicast_group(mreq.multiaddr.into())?;
+                memberships.push(mreq.multiaddr);
+            }
+            O::DropMembership(mreq) => {
+                let mut memberships = self.memberships.lock();
+                let Some(pos) = memberships.iter().position(|g| *g == mreq.multiaddr) else {
+                    return Err(KError::from(LinuxError::EADDRNOTAVAIL));
+                };
+                memberships.swap_remove(pos);
+                crate::leave_multicast_group(mreq.multiaddr.into())?;
+            }
             _ => return Ok(false),
         }
         Ok(true)
@@ -199,6 +245,8 @@ impl SocketOps for UdpSocket {
                     Err(k_err_type!(NotConnected))
                 } else if !socket.can_send() {
                     Err(KError::WouldBlock)
+                } else if !self.may_queue(socket.send_queue(), src.remaining()) {
+                    Err(KError::WouldBlock)
                 } else {
                     let buf = socket
                         .send(
@@ -217,6 +265,7 @@ impl SocketOps for UdpSocket {
                         })?;
                     let read = src.read(buf)?;
                     assert_eq!(read, buf.len());
+                    self.mem.sync(socket.recv_queue() + socket.send_queue());
                     Ok(read)
                 }
             })
@@ -342,6 +391,9 @@ impl Pollable for UdpSocket {
 impl Drop for UdpSocket {
     fn drop(&mut self) {
         self.shutdown(Shutdown::Both).ok();
+        for group in self.memberships.get_mut().drain(..) {
+            crate::leave_multicast_group(group.into()).ok();
+        }
         SOCKET_SET.remove(self.dispatch_irq);
     }
 }
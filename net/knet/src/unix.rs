@@ -6,14 +6,17 @@
 pub(crate) mod dgram;
 pub(crate) mod stream;
 
-use alloc::{boxed::Box, sync::Arc};
-use core::task::Context;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Context,
+};
 
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
 use fs_ng_vfs::NodeType;
 use hashbrown::HashMap;
-use kerrno::{KError, KResult};
+use kerrno::{KError, KResult, LinuxError};
 use kfs::{FS_CONTEXT, OpenOptions};
 use kio::{IoBuf, Read, Write};
 use kpoll::{IoEvents, Pollable};
@@ -23,7 +26,7 @@ use lazy_static::lazy_static;
 
 pub use self::{dgram::DgramTransport, stream::StreamTransport};
 use crate::{
-    RecvOptions, SendOptions, Shutdown, Socket, SocketAddrEx, SocketOps,
+    CMsgData, RecvOptions, SendOptions, Shutdown, Socket, SocketAddrEx, SocketOps,
     options::{Configurable, GetSocketOption, SetSocketOption},
 };
 
@@ -35,6 +38,69 @@ pub enum UnixAddr {
     Path(Arc<str>),
 }
 
+/// The most file descriptors a message may carry, `SCM_MAX_FD` of Linux.
+pub const MAX_PASSED_FDS: usize = 253;
+
+/// A receive queue of unix sockets, counting the messages in it that carry
+/// file descriptors.
+///
+/// File descriptors in flight are kept open by the queue they wait in, so a
+/// socket sent to a queue it can reach through what is in flight to itself
+/// would never be closed. Rather than collecting such cycles, passing a
+/// socket is refused while file descriptors are in flight to it, which for a
+/// listener means while connections wait to be accepted, and when the
+/// message goes to its own queue. This keeps in-flight references acyclic:
+/// whatever a socket keeps open is closed along with it.
+#[derive(Default)]
+pub struct RxQueue {
+    inflight: AtomicUsize,
+}
+
+impl RxQueue {
+    /// Counts a message with file descriptors queued.
+    pub(crate) fn hold(&self) {
+        self.inflight.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Counts a message with file descriptors received or dropped.
+    pub(crate) fn release(&self) {
+        self.inflight.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Returns `true` if file descriptors are in flight to the queue.
+    pub fn has_inflight(&self) -> bool {
+        self.inflight.load(Ordering::Acquire) > 0
+    }
+}
+
+/// The receive queues of the unix sockets among the file descriptors of a
+/// message.
+///
+/// Sent in [`SendOptions::cmsg`] along with the file descriptors, it is taken
+/// out by the transport, which checks it against the queue the message goes
+/// to, see [`RxQueue`].
+pub struct PassedSockets(pub Vec<Arc<RxQueue>>);
+
+/// Takes the [`PassedSockets`] out of `cmsg`, failing with `ETOOMANYREFS` if
+/// queueing them on `dest` could make a reference cycle.
+pub(crate) fn check_passed(cmsg: &mut Vec<CMsgData>, dest: &Arc<RxQueue>) -> KResult {
+    let mut result = Ok(());
+    cmsg.retain(|msg| {
+        let Some(passed) = msg.downcast_ref::<PassedSockets>() else {
+            return true;
+        };
+        if passed
+            .0
+            .iter()
+            .any(|queue| Arc::ptr_eq(queue, dest) || queue.has_inflight())
+        {
+            result = Err(KError::from(LinuxError::ETOOMANYREFS));
+        }
+        false
+    });
+    result
+}
+
 /// Transport interface for Unix-domain sockets.
 #[async_trait]
 #[enum_dispatch]
@@ -50,6 +116,10 @@ pub trait UnixTransportOps: Configurable + Pollable + Send + Sync {
     fn shutdown(&self, _how: Shutdown) -> KResult {
         Ok(())
     }
+
+    /// Returns the queue that messages to the socket wait in, or that
+    /// connections to it wait in for a listener.
+    fn rx_queue(&self) -> Option<Arc<RxQueue>>;
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -162,6 +232,14 @@ impl Configurable for UnixDomainSocket {
         self.transport.set_option_inner(opt)
     }
 }
+impl UnixDomainSocket {
+    /// Returns the receive queue of the socket, to pass it in
+    /// [`PassedSockets`].
+    pub fn rx_queue(&self) -> Option<Arc<RxQueue>> {
+        self.transport.rx_queue()
+    }
+}
+
 impl SocketOps for UnixDomainSocket {
     fn bind(&self, local_endpoint: SocketAddrEx) -> KResult {
         let local_endpoint = local_endpoint.into_unix()?;
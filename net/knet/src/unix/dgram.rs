@@ -4,7 +4,10 @@
 
 //! Unix datagram socket transport.
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
-use core::task::Context;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
 
 use async_channel::TryRecvError;
 use async_trait::async_trait;
@@ -17,23 +20,45 @@ use crate::{
     CMsgData, RecvFlags, RecvOptions, SendOptions, SocketAddrEx,
     general::GeneralOptions,
     options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
-    unix::{UnixAddr, UnixTransport, UnixTransportOps, lookup_bind_entry},
+    unix::{RxQueue, UnixAddr, UnixTransport, UnixTransportOps, check_passed, lookup_bind_entry},
 };
 
 struct Datagram {
     data: Vec<u8>,
     cmsg: Vec<CMsgData>,
     sender: UnixAddr,
+    cred: UnixCredentials,
 }
 
 struct Channel {
     tx: async_channel::Sender<Datagram>,
     poll: Arc<PollSet>,
+    queue: Arc<RxQueue>,
+}
+
+impl Channel {
+    /// Queues `packet`, checking the sockets passed along first.
+    fn send(&self, mut packet: Datagram) -> KResult {
+        check_passed(&mut packet.cmsg, &self.queue)?;
+        let holds = !packet.cmsg.is_empty();
+        if holds {
+            self.queue.hold();
+        }
+        if self.tx.try_send(packet).is_err() {
+            if holds {
+                self.queue.release();
+            }
+            return Err(KError::BrokenPipe);
+        }
+        self.poll.wake();
+        Ok(())
+    }
 }
 
 pub struct Bind {
     tx: async_channel::Sender<Datagram>,
     poll: Arc<PollSet>,
+    queue: Arc<RxQueue>,
 }
 impl Bind {
     fn connect(&self) -> Channel {
@@ -41,65 +66,84 @@ impl Bind {
         Channel {
             tx,
             poll: self.poll.clone(),
+            queue: self.queue.clone(),
         }
     }
 }
 
 pub struct DgramTransport {
     rx: Mutex<Option<(async_channel::Receiver<Datagram>, Arc<PollSet>)>>,
+    rx_queue: Arc<RxQueue>,
     peer: RwLock<Option<Channel>>,
     local_addr: RwLock<UnixAddr>,
     poll_state: Arc<PollSet>,
     options: GeneralOptions,
-    pid: u32,
+    /// Credentials of the process that created the socket.
+    cred: UnixCredentials,
+    /// Credentials of the peer of a socket pair.
+    peer_cred: Option<UnixCredentials>,
+    pass_cred: AtomicBool,
 }
 impl DgramTransport {
-    pub fn new(pid: u32) -> Self {
+    pub fn new(cred: UnixCredentials) -> Self {
         DgramTransport {
             rx: Mutex::new(None),
+            rx_queue: Arc::default(),
             peer: RwLock::new(None),
             local_addr: RwLock::new(UnixAddr::Unbound),
             poll_state: Arc::default(),
             options: GeneralOptions::default(),
-            pid,
+            cred,
+            peer_cred: None,
+            pass_cred: AtomicBool::new(false),
         }
     }
 
     fn new_connected(
         rx: (async_channel::Receiver<Datagram>, Arc<PollSet>),
+        rx_queue: Arc<RxQueue>,
         peer: Channel,
-        pid: u32,
+        cred: UnixCredentials,
     ) -> Self {
         DgramTransport {
             rx: Mutex::new(Some(rx)),
+            rx_queue,
             peer: RwLock::new(Some(peer)),
             local_addr: RwLock::new(UnixAddr::Unbound),
             poll_state: Arc::default(),
             options: GeneralOptions::default(),
-            pid,
+            cred,
+            peer_cred: Some(cred),
+            pass_cred: AtomicBool::new(false),
         }
     }
 
-    pub fn new_pair(pid: u32) -> (Self, Self) {
+    pub fn new_pair(cred: UnixCredentials) -> (Self, Self) {
         let (tx1, rx1) = async_channel::unbounded();
         let (tx2, rx2) = async_channel::unbounded();
         let poll1 = Arc::new(PollSet::new());
         let poll2 = Arc::new(PollSet::new());
+        let queue1 = Arc::new(RxQueue::default());
+        let queue2 = Arc::new(RxQueue::default());
         let transport1 = DgramTransport::new_connected(
             (rx1, poll1.clone()),
+            queue1.clone(),
             Channel {
                 tx: tx2,
                 poll: poll2.clone(),
+                queue: queue2.clone(),
             },
-            pid,
+            cred,
         );
         let transport2 = DgramTransport::new_connected(
-            (rx2, poll2.clone()),
+            (rx2, poll2),
+            queue2,
             Channel {
                 tx: tx1,
-                poll: poll1.clone(),
+                poll: poll1,
+                queue: queue1,
             },
-            pid,
+            cred,
         );
         (transport1, transport2)
     }
@@ -114,12 +158,14 @@ impl Configurable for DgramTransport {
         }
 
         match opt {
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                **pass = self.pass_cred.load(Ordering::Acquire);
+            }
             O::PeerCredentials(cred) => {
-                // Datagram sockets are stateless and do not have a peer, so we
-                // return the credentials of the process that created the
-                // socket.
-                **cred = UnixCredentials::new(self.pid);
+                // Datagram sockets other than socket pairs are stateless and do
+                // not have a peer, so we return the credentials of the process
+                // that created the socket.
+                **cred = self.peer_cred.unwrap_or(self.cred);
             }
             _ => return Ok(false),
         }
@@ -134,7 +180,9 @@ impl Configurable for DgramTransport {
         }
 
         match opt {
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                self.pass_cred.store(*pass, Ordering::Release);
+            }
             _ => return Ok(false),
         }
         Ok(true)
@@ -156,6 +204,7 @@ impl UnixTransportOps for DgramTransport {
         *slot = Some(Bind {
             tx,
             poll: poll.clone(),
+            queue: self.rx_queue.clone(),
         });
         *guard = Some((rx, poll));
         self.local_addr.write().clone_from(local_addr);
@@ -191,23 +240,18 @@ impl UnixTransportOps for DgramTransport {
             data: message,
             cmsg: options.cmsg,
             sender: self.local_addr.read().clone(),
+            cred: options.credentials.unwrap_or(self.cred),
         };
 
         let connected = self.peer.read();
         if let Some(addr) = options.to {
             let addr = addr.into_unix()?;
-            lookup_bind_entry(&addr, |slot| {
-                if let Some(bind) = slot.dgram.lock().as_ref() {
-                    bind.tx.try_send(packet).map_err(|_| KError::BrokenPipe)?;
-                    bind.poll.wake();
-                    Ok(())
-                } else {
-                    Err(KError::NotConnected)
-                }
+            lookup_bind_entry(&addr, |slot| match slot.dgram.lock().as_ref() {
+                Some(bind) => bind.connect().send(packet),
+                None => Err(KError::NotConnected),
             })?;
         } else if let Some(chan) = connected.as_ref() {
-            chan.tx.try_send(packet).map_err(|_| KError::BrokenPipe)?;
-            chan.poll.wake();
+            chan.send(packet)?;
         } else {
             return Err(KError::NotConnected);
         }
@@ -221,7 +265,12 @@ impl UnixTransportOps for DgramTransport {
                 return Err(KError::NotConnected);
             };
 
-            let Datagram { data, cmsg, sender } = match rx.try_recv() {
+            let Datagram {
+                data,
+                cmsg,
+                sender,
+                cred,
+            } = match rx.try_recv() {
                 Ok(packet) => packet,
                 Err(TryRecvError::Empty) => {
                     return Err(KError::WouldBlock);
@@ -230,6 +279,9 @@ impl UnixTransportOps for DgramTransport {
                     return Ok(0);
                 }
             };
+            if !cmsg.is_empty() {
+                self.rx_queue.release();
+            }
             let count = dst.write(&data)?;
             if count < data.len() {
                 warn!("UDP message truncated: {} -> {} bytes", data.len(), count);
@@ -239,6 +291,9 @@ impl UnixTransportOps for DgramTransport {
                 **from = SocketAddrEx::Unix(sender);
             }
             if let Some(dst) = options.cmsg.as_mut() {
+                if self.pass_cred.load(Ordering::Acquire) {
+                    dst.push(Box::new(cred) as CMsgData);
+                }
                 dst.extend(cmsg);
             }
 
@@ -249,6 +304,10 @@ impl UnixTransportOps for DgramTransport {
             })
         })
     }
+
+    fn rx_queue(&self) -> Option<Arc<RxQueue>> {
+        self.rx.lock().is_some().then(|| self.rx_queue.clone())
+    }
 }
 
 impl Pollable for DgramTransport {
@@ -274,5 +333,10 @@ impl Drop for DgramTransport {
         if let Some(chan) = self.peer.write().take() {
             chan.poll.wake();
         }
+        // Datagrams not received are dropped with what was sent along.
+        if let Some((rx, _)) = self.rx.lock().take() {
+            rx.close();
+            while rx.try_recv().is_ok() {}
+        }
     }
 }
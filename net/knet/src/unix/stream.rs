@@ -3,8 +3,9 @@
 // See LICENSES for license details.
 
 //! Unix stream socket transport.
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use core::{
+    mem,
     sync::atomic::{AtomicBool, Ordering},
     task::Context,
 };
@@ -14,16 +15,17 @@ use kerrno::{KError, KResult};
 use kio::{IoBuf, Read, Write};
 use kpoll::{IoEvents, PollSet, Pollable};
 use ksync::Mutex;
+use ktask::future::timeout;
 use ringbuf::{
     HeapCons, HeapProd, HeapRb,
     traits::{Consumer, Observer, Producer, Split},
 };
 
 use crate::{
-    RecvOptions, SendOptions, Shutdown,
+    CMsgData, RecvOptions, SendOptions, Shutdown,
     general::GeneralOptions,
     options::{Configurable, GetSocketOption, SetSocketOption, UnixCredentials},
-    unix::{UnixAddr, UnixTransport, UnixTransportOps},
+    unix::{RxQueue, UnixAddr, UnixTransport, UnixTransportOps, check_passed},
 };
 
 const STREAM_BUF_BYTES: usize = 64 * 1024;
@@ -32,52 +34,172 @@ fn new_ring_pair() -> (HeapProd<u8>, HeapCons<u8>) {
     let rb = HeapRb::new(STREAM_BUF_BYTES);
     rb.split()
 }
-fn new_duplex_channel(pid: u32) -> (Channel, Channel) {
+fn new_duplex_channel(
+    client_peer: UnixCredentials,
+    server_peer: UnixCredentials,
+) -> (Channel, Channel) {
     let (client_tx, server_rx) = new_ring_pair();
     let (server_tx, client_rx) = new_ring_pair();
+    let client_runs = Arc::new(Mutex::new(SenderRuns::default()));
+    let server_runs = Arc::new(Mutex::new(SenderRuns::default()));
+    let client_queue = Arc::new(RxQueue::default());
+    let server_queue = Arc::new(RxQueue::default());
     let poll = Arc::new(PollSet::new());
     (
         Channel {
             tx: client_tx,
             rx: client_rx,
+            tx_runs: client_runs.clone(),
+            rx_runs: server_runs.clone(),
+            tx_queue: server_queue.clone(),
+            rx_queue: client_queue.clone(),
             poll: poll.clone(),
-            peer_pid: pid,
+            peer_cred: client_peer,
         },
         Channel {
             tx: server_tx,
             rx: server_rx,
+            tx_runs: server_runs,
+            rx_runs: client_runs,
+            tx_queue: client_queue,
+            rx_queue: server_queue,
             poll,
-            peer_pid: pid,
+            peer_cred: server_peer,
         },
     )
 }
 
+/// A run of bytes of a stream sent with the same credentials.
+struct Run {
+    len: usize,
+    cred: UnixCredentials,
+    /// Control messages sent with the first byte of the run, until it is
+    /// read.
+    cmsg: Vec<CMsgData>,
+}
+
+/// Senders of the bytes queued in one direction of a stream, as runs of
+/// bytes sent with the same credentials, oldest first.
+///
+/// A run is pushed under the lock before its bytes are made visible to the
+/// receiver, and the receiver holds the lock while it reads them, so the
+/// runs always cover the bytes in the ring.
+#[derive(Default)]
+pub(crate) struct SenderRuns(VecDeque<Run>);
+
+impl SenderRuns {
+    /// Records `len` more bytes sent with `cred`.
+    pub(crate) fn push(&mut self, len: usize, cred: UnixCredentials) {
+        self.push_with_cmsg(len, cred, Vec::new());
+    }
+
+    /// Records `len` more bytes sent with `cred`, the first of them carrying
+    /// `cmsg`.
+    pub(crate) fn push_with_cmsg(
+        &mut self,
+        len: usize,
+        cred: UnixCredentials,
+        cmsg: Vec<CMsgData>,
+    ) {
+        if len == 0 {
+            return;
+        }
+        match self.0.back_mut() {
+            Some(run) if run.cred == cred && cmsg.is_empty() => run.len += len,
+            _ => self.0.push_back(Run { len, cred, cmsg }),
+        }
+    }
+
+    /// Returns the length and the credentials of the oldest run.
+    pub(crate) fn front(&self) -> Option<(usize, UnixCredentials)> {
+        self.0.front().map(|run| (run.len, run.cred))
+    }
+
+    /// Returns how many of the oldest bytes a read may take: the oldest run,
+    /// and with `join` the runs after it up to one carrying control
+    /// messages, which are for the read starting there.
+    pub(crate) fn readable(&self, join: bool) -> usize {
+        let mut runs = self.0.iter();
+        let Some(first) = runs.next() else {
+            return 0;
+        };
+        let rest = runs.take_while(|run| join && run.cmsg.is_empty());
+        first.len + rest.map(|run| run.len).sum::<usize>()
+    }
+
+    /// Takes the control messages of the oldest run.
+    pub(crate) fn take_cmsg(&mut self) -> Vec<CMsgData> {
+        self.0
+            .front_mut()
+            .map_or_else(Vec::new, |run| mem::take(&mut run.cmsg))
+    }
+
+    /// Takes the control messages of every run, one vector per run carrying
+    /// any.
+    fn take_all_cmsg(&mut self) -> Vec<Vec<CMsgData>> {
+        self.0
+            .iter_mut()
+            .map(|run| mem::take(&mut run.cmsg))
+            .filter(|cmsg| !cmsg.is_empty())
+            .collect()
+    }
+
+    /// Forgets the oldest `len` bytes.
+    pub(crate) fn consume(&mut self, mut len: usize) {
+        while len > 0
+            && let Some(run) = self.0.front_mut()
+        {
+            if run.len > len {
+                run.len -= len;
+                return;
+            }
+            len -= run.len;
+            self.0.pop_front();
+        }
+    }
+}
+
 struct Channel {
     tx: HeapProd<u8>,
     rx: HeapCons<u8>,
+    tx_runs: Arc<Mutex<SenderRuns>>,
+    rx_runs: Arc<Mutex<SenderRuns>>,
+    tx_queue: Arc<RxQueue>,
+    rx_queue: Arc<RxQueue>,
     // TODO: granularity
     poll: Arc<PollSet>,
-    peer_pid: u32,
+    /// Credentials of the peer when the connection was made.
+    peer_cred: UnixCredentials,
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // What is queued for us will not be read: close the file descriptors
+        // in flight once the lock is released.
+        let cmsg = self.rx_runs.lock().take_all_cmsg();
+        for _ in &cmsg {
+            self.rx_queue.release();
+        }
+    }
 }
 
 pub struct Bind {
     /// New connections are sent to this channel.
     accept_tx: async_channel::Sender<ConnRequest>,
     accept_poll: Arc<PollSet>,
-    pid: u32,
+    accept_queue: Arc<RxQueue>,
+    cred: UnixCredentials,
 }
 impl Bind {
-    fn connect(&self, local_addr: UnixAddr, pid: u32) -> KResult<Channel> {
-        let (mut client_chan, mut server_chan) = new_duplex_channel(0);
-        client_chan.peer_pid = self.pid;
-        server_chan.peer_pid = pid;
+    fn connect(&self, local_addr: UnixAddr, cred: UnixCredentials) -> KResult<Channel> {
+        let (client_chan, server_chan) = new_duplex_channel(self.cred, cred);
         self.accept_tx
             .try_send(ConnRequest {
                 channel: server_chan,
                 addr: local_addr,
-                pid,
             })
             .map_err(|_| KError::ConnectionRefused)?;
+        self.accept_queue.hold();
         self.accept_poll.wake();
         Ok(client_chan)
     }
@@ -86,39 +208,44 @@ impl Bind {
 struct ConnRequest {
     channel: Channel,
     addr: UnixAddr,
-    pid: u32,
 }
 
 pub struct StreamTransport {
     channel: Mutex<Option<Channel>>,
     accept_rx: Mutex<Option<(async_channel::Receiver<ConnRequest>, Arc<PollSet>)>>,
+    /// Counts the connections waiting to be accepted.
+    accept_queue: Arc<RxQueue>,
     poll_state: PollSet,
     options: GeneralOptions,
-    pid: u32,
+    /// Credentials of the process that created the socket.
+    cred: UnixCredentials,
+    pass_cred: AtomicBool,
     rx_closed: AtomicBool,
     tx_closed: AtomicBool,
 }
 impl StreamTransport {
-    pub fn new(pid: u32) -> Self {
-        StreamTransport::new_channel(None, pid)
+    pub fn new(cred: UnixCredentials) -> Self {
+        StreamTransport::new_channel(None, cred)
     }
 
-    fn new_channel(channel: Option<Channel>, pid: u32) -> Self {
+    fn new_channel(channel: Option<Channel>, cred: UnixCredentials) -> Self {
         StreamTransport {
             channel: Mutex::new(channel),
             accept_rx: Mutex::new(None),
+            accept_queue: Arc::default(),
             poll_state: PollSet::new(),
             options: GeneralOptions::default(),
-            pid,
+            cred,
+            pass_cred: AtomicBool::new(false),
             rx_closed: AtomicBool::new(false),
             tx_closed: AtomicBool::new(false),
         }
     }
 
-    pub fn new_pair(pid: u32) -> (Self, Self) {
-        let (chan1, chan2) = new_duplex_channel(pid);
-        let transport1 = StreamTransport::new_channel(Some(chan1), pid);
-        let transport2 = StreamTransport::new_channel(Some(chan2), pid);
+    pub fn new_pair(cred: UnixCredentials) -> (Self, Self) {
+        let (chan1, chan2) = new_duplex_channel(cred, cred);
+        let transport1 = StreamTransport::new_channel(Some(chan1), cred);
+        let transport2 = StreamTransport::new_channel(Some(chan2), cred);
         (transport1, transport2)
     }
 }
@@ -135,14 +262,15 @@ impl Configurable for StreamTransport {
             O::SendBuffer(size) => {
                 **size = STREAM_BUF_BYTES;
             }
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                **pass = self.pass_cred.load(Ordering::Acquire);
+            }
             O::PeerCredentials(cred) => {
-                let peer_pid = self
+                **cred = self
                     .channel
                     .lock()
                     .as_ref()
-                    .map_or(self.pid, |chan| chan.peer_pid);
-                **cred = UnixCredentials::new(peer_pid);
+                    .map_or(self.cred, |chan| chan.peer_cred);
             }
             _ => return Ok(false),
         }
@@ -157,7 +285,9 @@ impl Configurable for StreamTransport {
         }
 
         match opt {
-            O::PassCredentials(_) => {}
+            O::PassCredentials(pass) => {
+                self.pass_cred.store(*pass, Ordering::Release);
+            }
             _ => return Ok(false),
         }
         Ok(true)
@@ -179,7 +309,8 @@ impl UnixTransportOps for StreamTransport {
         *slot = Some(Bind {
             accept_tx: tx,
             accept_poll: poll.clone(),
-            pid: self.pid,
+            accept_queue: self.accept_queue.clone(),
+            cred: self.cred,
         });
         *guard = Some((rx, poll));
         self.poll_state.wake();
@@ -196,7 +327,7 @@ impl UnixTransportOps for StreamTransport {
                 .lock()
                 .as_ref()
                 .ok_or(KError::NotConnected)?
-                .connect(local_addr.clone(), self.pid)?,
+                .connect(local_addr.clone(), self.cred)?,
         );
         self.poll_state.wake();
         Ok(())
@@ -213,10 +344,13 @@ impl UnixTransportOps for StreamTransport {
         let ConnRequest {
             channel,
             addr: peer_addr,
-            pid,
-        } = rx.recv().await.map_err(|_| KError::ConnectionReset)?;
+        } = timeout(self.options.recv_timeout(), rx.recv())
+            .await
+            .map_err(|_| KError::WouldBlock)?
+            .map_err(|_| KError::ConnectionReset)?;
+        self.accept_queue.release();
         Ok((
-            UnixTransport::Stream(StreamTransport::new_channel(Some(channel), pid)),
+            UnixTransport::Stream(StreamTransport::new_channel(Some(channel), self.cred)),
             peer_addr,
         ))
     }
@@ -226,6 +360,8 @@ impl UnixTransportOps for StreamTransport {
             return Err(KError::InvalidInput);
         }
         let size = src.remaining();
+        let cred = options.credentials.unwrap_or(self.cred);
+        let mut cmsg = options.cmsg;
         let mut total = 0;
         let non_blocking = self.options.nonblocking();
         self.options.send_poller(self, || {
@@ -236,6 +372,7 @@ impl UnixTransportOps for StreamTransport {
             if !chan.tx.read_is_held() {
                 return Err(KError::BrokenPipe);
             }
+            check_passed(&mut cmsg, &chan.tx_queue)?;
 
             let count = {
                 let (left, right) = chan.tx.vacant_slices_mut();
@@ -243,6 +380,14 @@ impl UnixTransportOps for StreamTransport {
                 if count >= left.len() {
                     count += src.read(unsafe { right.assume_init_mut() })?;
                 }
+                let mut runs = chan.tx_runs.lock();
+                // Control messages go with the first byte sent.
+                if count > 0 && !cmsg.is_empty() {
+                    chan.tx_queue.hold();
+                    runs.push_with_cmsg(count, cred, mem::take(&mut cmsg));
+                } else {
+                    runs.push(count, cred);
+                }
                 unsafe { chan.tx.advance_write_index(count) };
                 count
             };
@@ -259,7 +404,7 @@ impl UnixTransportOps for StreamTransport {
         })
     }
 
-    fn recv(&self, mut dst: impl Write, _options: RecvOptions) -> KResult<usize> {
+    fn recv(&self, mut dst: impl Write, mut options: RecvOptions) -> KResult<usize> {
         self.options.recv_poller(self, || {
             let mut guard = self.channel.lock();
             let Some(chan) = guard.as_mut() else {
@@ -267,12 +412,38 @@ impl UnixTransportOps for StreamTransport {
             };
 
             let count = {
-                let (left, right) = chan.rx.as_slices();
+                let mut runs = chan.rx_runs.lock();
+                // With credentials passed, a read does not span data of
+                // different senders. Nor does it span into data sent with
+                // control messages.
+                let pass_cred = self.pass_cred.load(Ordering::Acquire);
+                let sender = runs.front().filter(|_| pass_cred);
+                let readable = runs.readable(!pass_cred);
+                let (mut left, mut right) = chan.rx.as_slices();
+                left = &left[..readable.min(left.len())];
+                right = &right[..(readable - left.len()).min(right.len())];
                 let mut count = dst.write(left)?;
                 if count >= left.len() {
                     count += dst.write(right)?;
                 }
+                let received = if count > 0 {
+                    runs.take_cmsg()
+                } else {
+                    Vec::new()
+                };
+                runs.consume(count);
                 unsafe { chan.rx.advance_read_index(count) };
+                if !received.is_empty() {
+                    chan.rx_queue.release();
+                }
+                if let Some(cmsg) = options.cmsg.as_mut() {
+                    if count > 0
+                        && let Some((_, cred)) = sender
+                    {
+                        cmsg.push(Box::new(cred) as CMsgData);
+                    }
+                    cmsg.extend(received);
+                }
                 count
             };
             if count > 0 {
@@ -303,6 +474,16 @@ impl UnixTransportOps for StreamTransport {
         }
         Ok(())
     }
+
+    fn rx_queue(&self) -> Option<Arc<RxQueue>> {
+        if let Some(chan) = self.channel.lock().as_ref() {
+            return Some(chan.rx_queue.clone());
+        }
+        self.accept_rx
+            .lock()
+            .is_some()
+            .then(|| self.accept_queue.clone())
+    }
 }
 
 impl Pollable for StreamTransport {
@@ -343,5 +524,10 @@ impl Drop for StreamTransport {
         if let Some(chan) = self.channel.lock().as_ref() {
             chan.poll.wake();
         }
+        // Connections not accepted are dropped with what was sent over them.
+        if let Some((rx, _)) = self.accept_rx.lock().take() {
+            rx.close();
+            while rx.try_recv().is_ok() {}
+        }
     }
 }
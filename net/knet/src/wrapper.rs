@@ -4,9 +4,10 @@
 
 //! Socket set wrapper utilities.
 
-use alloc::vec;
+use alloc::{sync::Arc, vec};
 
 use event_listener::Event;
+use hashbrown::HashMap;
 use kerrno::{KError, KResult};
 use ksync::Mutex;
 use smoltcp::{
@@ -15,9 +16,13 @@ use smoltcp::{
     wire::IpAddress,
 };
 
+use crate::mem::{Proto, SocketMem};
+
 pub(crate) struct SocketSetWrapper<'a> {
     pub inner: Mutex<SocketSet<'a>>,
     pub new_socket: Event,
+    /// Memory accounting of the TCP and UDP sockets in the set.
+    mem: Mutex<HashMap<SocketHandle, Arc<SocketMem>>>,
 }
 
 impl<'a> SocketSetWrapper<'a> {
@@ -25,6 +30,44 @@ impl<'a> SocketSetWrapper<'a> {
         Self {
             inner: Mutex::new(SocketSet::new(vec![])),
             new_socket: Event::new(),
+            mem: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the memory accounting of a socket.
+    pub fn socket_mem(&self, dispatch_irq: SocketHandle, proto: Proto) -> Arc<SocketMem> {
+        self.mem
+            .lock()
+            .entry(dispatch_irq)
+            .or_insert_with(|| Arc::new(SocketMem::new(proto)))
+            .clone()
+    }
+
+    /// Charges the data queued in every socket to its budget, trimming UDP
+    /// receive queues that exceed their limit.
+    pub fn account(&self) {
+        let mut sockets = self.inner.lock();
+        let mut mem = self.mem.lock();
+        for (dispatch_irq, socket) in sockets.iter_mut() {
+            match socket {
+                Socket::Tcp(s) => {
+                    let queued = s.recv_queue() + s.send_queue();
+                    mem.entry(dispatch_irq)
+                        .or_insert_with(|| Arc::new(SocketMem::new(Proto::Tcp)))
+                        .sync(queued);
+                }
+                Socket::Udp(s) => {
+                    let acct = mem
+                        .entry(dispatch_irq)
+                        .or_insert_with(|| Arc::new(SocketMem::new(Proto::Udp)));
+                    let limit = acct.rx_limit();
+                    while s.recv_queue() > limit && s.recv().is_ok() {
+                        acct.count_drop();
+                    }
+                    acct.sync(s.recv_queue() + s.send_queue());
+                }
+                _ => continue,
+            }
         }
     }
 
@@ -81,6 +124,9 @@ impl<'a> SocketSetWrapper<'a> {
 
     pub fn remove(&self, dispatch_irq: SocketHandle) {
         self.inner.lock().remove(dispatch_irq);
+        if let Some(mem) = self.mem.lock().remove(&dispatch_irq) {
+            mem.sync(0);
+        }
         debug!("socket {}: destroyed", dispatch_irq);
     }
 }
@@ -3,33 +3,27 @@
 // See LICENSES for license details.
 
 //! Physical memory layout and address translation helpers.
-use core::sync::atomic::{AtomicUsize, Ordering};
-
-use kplat::memory::{HwMemory, MemRange, PhysAddr, VirtAddr, pa, va};
+use kplat::{
+    memblock::{EarlyTag, early_reserve},
+    memory::{HwMemory, MemRange, PhysAddr, VirtAddr, pa, va},
+};
 use rs_fdtree::LinuxFdt;
-use spin::Once;
 
 use crate::config::{
     devices::MMIO_RANGES,
     plat::{PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE, PHYS_VIRT_OFFSET},
 };
-const FDT_MEM_SIZE: usize = 0x20_0000;
-static FDT_MEM_BASE: AtomicUsize = AtomicUsize::new(0);
-static FDT_MEM: Once<[MemRange; 2]> = Once::new();
-static DICE_MEM_BASE: AtomicUsize = AtomicUsize::new(0);
-static DICE_MEM_SIZE: AtomicUsize = AtomicUsize::new(0);
-/// Capture FDT/DICE memory ranges before the allocator is initialized.
+/// Reserves the DICE handover memory before the allocator is initialized.
+///
+/// The FDT itself is reserved by the generic early memory code.
 pub(crate) fn early_init(fdt_paddr: usize) {
-    FDT_MEM_BASE.store(fdt_paddr, Ordering::SeqCst);
     let fdt = unsafe { LinuxFdt::from_ptr(fdt_paddr as *const u8).expect("Failed to parse FDT") };
-    fdt.dice().map(|dice_node| {
-        let dice = dice_node;
-        for reg in dice.regions().expect("DICE regions") {
-            DICE_MEM_BASE.store(reg.starting_address as usize, Ordering::SeqCst);
-            DICE_MEM_SIZE.store(reg.size as usize, Ordering::SeqCst);
-            break;
-        }
-    });
+    if let Some(dice) = fdt.dice()
+        && let Some(reg) = dice.regions().expect("DICE regions").into_iter().next()
+    {
+        let range = (reg.starting_address as usize, reg.size as usize);
+        early_reserve(range, EarlyTag::permanent("dice")).expect("DICE memory overlaps");
+    }
 }
 /// Platform-specific memory description for the kernel.
 struct HwMemoryImpl;
@@ -44,17 +38,7 @@ impl HwMemory for HwMemoryImpl {
     /// Reserved memory can be contained in [`ram_regions`], they are not
     /// allocatable but should be mapped to kernel's address space.
     fn rsvd_regions() -> &'static [MemRange] {
-        FDT_MEM
-            .call_once(|| {
-                [
-                    (FDT_MEM_BASE.load(Ordering::Relaxed), FDT_MEM_SIZE),
-                    (
-                        DICE_MEM_BASE.load(Ordering::Relaxed),
-                        DICE_MEM_SIZE.load(Ordering::Relaxed),
-                    ),
-                ]
-            })
-            .as_ref()
+        &[]
     }
 
     /// Returns all device memory (MMIO) ranges on the platform.
@@ -3,11 +3,16 @@
 // See LICENSES for license details.
 
 //! PL031 RTC helper for epoch offset calculation.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use arm_pl031::Rtc;
 use kplat::memory::VirtAddr;
+use selftest::{Outcome, TestContext};
 
 use crate::generic_timer::{now_ticks, t2ns};
 static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
+/// Virtual base address of the RTC, or 0 if there is none.
+static RTC_BASE: AtomicUsize = AtomicUsize::new(0);
 /// Return the cached epoch offset in nanoseconds.
 #[inline]
 pub fn offset_ns() -> u64 {
@@ -18,9 +23,19 @@ pub fn early_init(rtc_base: VirtAddr) {
     if rtc_base.as_usize() == 0 {
         return;
     }
+    RTC_BASE.store(rtc_base.as_usize(), Ordering::Relaxed);
     let rtc = unsafe { Rtc::new(rtc_base.as_mut_ptr() as _) };
     let epoch_time_nanos = rtc.get_unix_timestamp() as u64 * 1_000_000_000;
     unsafe {
         RTC_EPOCHOFFSET_NANOS = epoch_time_nanos - t2ns(now_ticks());
     }
 }
+fn check_rtc(ctx: &mut TestContext) -> Outcome {
+    let base = RTC_BASE.load(Ordering::Relaxed);
+    if base == 0 {
+        return Outcome::Skip("no RTC".into());
+    }
+    let rtc = unsafe { Rtc::new(base as _) };
+    selftest::clock::check_rtc(ctx, &|| rtc.get_unix_timestamp() as u64)
+}
+selftest::register_selftest!(Boot, "rtc", check_rtc);
@@ -2,7 +2,10 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-use aarch64_pmuv3::pmuv3::{PmuCounter, PmuEvent};
+use aarch64_pmuv3::pmuv3::{
+    PmuCounter, PmuEvent, event_counters, event_implemented, read_counting, start_counting,
+    stop_counting,
+};
 use kplat::perf::PerfCb;
 use lazyinit::LazyInit;
 const MAX_PMU_COUNTERS: usize = 32;
@@ -124,6 +127,55 @@ pub fn set_threshold(index: u32, threshold: u64) {
         with_counter_mut(index, |c| c.set_threshold(threshold));
     }
 }
+/// Returns the event counter behind task counter `slot`: the event counters
+/// not used by the manager, in order.
+fn task_counter_index(slot: usize) -> Option<u32> {
+    let pmu = unsafe { ensure_pmu_inited() };
+    (0..event_counters())
+        .filter(|&idx| pmu.counters[idx as usize].is_none())
+        .nth(slot)
+}
+/// Maps a generic hardware event, `PERF_COUNT_HW_*` of Linux, to the
+/// PMUv3 event counting it.
+fn task_counter_event(event: u32) -> Option<PmuEvent> {
+    Some(match event {
+        0 => PmuEvent::CpuCycles,
+        1 => PmuEvent::InstRetired,
+        2 => PmuEvent::L1dCache,
+        3 => PmuEvent::L1dCacheRefill,
+        5 => PmuEvent::BrMisPred,
+        _ => return None,
+    })
+}
+pub fn task_counters() -> usize {
+    let pmu = unsafe { ensure_pmu_inited() };
+    (0..event_counters())
+        .filter(|&idx| pmu.counters[idx as usize].is_none())
+        .count()
+}
+pub fn task_counter_start(
+    slot: usize,
+    event: u32,
+    exclude_user: bool,
+    exclude_kernel: bool,
+) -> bool {
+    let (Some(index), Some(event)) = (task_counter_index(slot), task_counter_event(event)) else {
+        return false;
+    };
+    if !event_implemented(event) {
+        return false;
+    }
+    start_counting(index, event, exclude_user, exclude_kernel);
+    true
+}
+pub fn task_counter_read(slot: usize) -> u64 {
+    task_counter_index(slot).map_or(0, |index| read_counting(index) as u64)
+}
+pub fn task_counter_stop(slot: usize) {
+    if let Some(index) = task_counter_index(slot) {
+        stop_counting(index);
+    }
+}
 #[macro_export]
 macro_rules! pmu_if_impl {
     ($name:ident) => {
@@ -138,6 +190,27 @@ macro_rules! pmu_if_impl {
             fn reg_cb(index: u32, handler: PerfCb) -> bool {
                 $crate::pmu::reg_handler_overflow_handler(index, handler)
             }
+
+            fn task_counters() -> usize {
+                $crate::pmu::task_counters()
+            }
+
+            fn task_counter_start(
+                slot: usize,
+                event: u32,
+                exclude_user: bool,
+                exclude_kernel: bool,
+            ) -> bool {
+                $crate::pmu::task_counter_start(slot, event, exclude_user, exclude_kernel)
+            }
+
+            fn task_counter_read(slot: usize) -> u64 {
+                $crate::pmu::task_counter_read(slot)
+            }
+
+            fn task_counter_stop(slot: usize) {
+                $crate::pmu::task_counter_stop(slot)
+            }
         }
     };
 }
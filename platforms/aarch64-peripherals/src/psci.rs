@@ -102,6 +102,15 @@ pub fn shutdown() -> ! {
         kcpu::instrs::stop_cpu();
     }
 }
+/// Reset the system via PSCI.
+///
+/// Does not log, so it is safe to call from a failing panic path.
+pub fn reset() -> ! {
+    psci_call(PSCI_0_2_FN_SYSTEM_RESET, 0, 0, 0).ok();
+    loop {
+        kcpu::instrs::stop_cpu();
+    }
+}
 /// Power on a target CPU with the given entry point and argument.
 pub fn cpu_on(target_cpu: usize, entry_point: usize, arg: usize) {
     info!("Starting CPU {target_cpu:x} ON ...");
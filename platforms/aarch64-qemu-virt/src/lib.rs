@@ -14,7 +14,9 @@ mod power;
 pub mod config {
     platconfig_macros::include_configs!(
         path_env = "PLAT_CONFIG_PATH",
-        fallback = "platconfig.toml"
+        fallback = "platconfig.toml",
+        schema = "platconfig.schema.toml",
+        deny_unknown_keys = true,
     );
     // assert_eq!(
     // PACKAGE,
@@ -18,4 +18,8 @@ impl SysCtrl for PowerImpl {
     fn shutdown() -> ! {
         aarch64_peripherals::psci::shutdown()
     }
+
+    fn reset() -> ! {
+        aarch64_peripherals::psci::reset()
+    }
 }
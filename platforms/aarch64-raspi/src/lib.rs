@@ -13,7 +13,12 @@ mod power;
 #[cfg(feature = "smp")]
 mod mp;
 pub mod config {
-    platconfig_macros::include_configs!(path_env = "PLAT_CONFIG_PATH", fallback = "platconfig.toml");
+    platconfig_macros::include_configs!(
+        path_env = "PLAT_CONFIG_PATH",
+        fallback = "platconfig.toml",
+        schema = "platconfig.schema.toml",
+        deny_unknown_keys = true,
+    );
     assert_str_eq!(
         PACKAGE,
         env!("CARGO_PKG_NAME"),
@@ -11,10 +11,31 @@ impl SysCtrl for PowerImpl {
     fn boot_ap(cpu_id: usize, stack_top_paddr: usize) {
         crate::mp::start_secondary_cpu(cpu_id, kplat::memory::pa!(stack_top_paddr));
     }
+
     fn shutdown() -> ! {
         log::info!("Shutting down...");
         loop {
             kcpu::instrs::stop_cpu();
         }
     }
+
+    fn reset() -> ! {
+        use kplat::memory::{p2v, pa};
+
+        const PM_RSTC: usize = 0x1c;
+        const PM_WDOG: usize = 0x24;
+        const PM_PASSWORD: u32 = 0x5a00_0000;
+        const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
+        // Arm the PM watchdog with the shortest timeout and request a full
+        // reset when it expires.
+        let base = p2v(pa!(crate::config::devices::PM_PADDR)).as_usize();
+        unsafe {
+            ((base + PM_WDOG) as *mut u32).write_volatile(PM_PASSWORD | 10);
+            ((base + PM_RSTC) as *mut u32).write_volatile(PM_PASSWORD | PM_RSTC_WRCFG_FULL_RESET);
+        }
+        loop {
+            kcpu::instrs::stop_cpu();
+        }
+    }
 }
@@ -13,6 +13,7 @@ pub mod boot;
 pub mod cpu;
 pub mod interrupts;
 pub mod io;
+pub mod memblock;
 pub mod memory;
 #[cfg(feature = "nmi")]
 pub mod nm_irq;
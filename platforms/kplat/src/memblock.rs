@@ -0,0 +1,456 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Early boot memory bookkeeping.
+//!
+//! Until the page allocator is initialized, memory is handed out from the
+//! available ranges (RAM) minus the reserved ones, which carry a tag saying
+//! what they hold. When the page allocator takes over, temporary reservations
+//! are released to it and permanent ones stay reserved.
+
+use kspin::SpinNoIrq;
+use memaddr::{PAGE_SIZE_4K, PhysAddr, align_down, align_down_4k, align_up_4k, pa};
+
+use crate::memory::MemRange;
+
+/// Maximum number of available ranges, and of reservations.
+pub const MAX_MEMBLOCK_RANGES: usize = 64;
+
+/// What an early reservation holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyTag {
+    /// Name shown in the boot report.
+    pub name: &'static str,
+    /// Whether the reservation outlives the handoff to the page allocator.
+    pub permanent: bool,
+}
+
+impl EarlyTag {
+    /// Platform DMA memory.
+    pub const DMA: Self = Self::permanent("dma");
+    /// The device tree blob passed by the bootloader.
+    pub const DTB: Self = Self::permanent("dtb");
+    /// The kernel image.
+    pub const KERNEL: Self = Self::permanent("kernel image");
+
+    /// A reservation kept after the handoff.
+    pub const fn permanent(name: &'static str) -> Self {
+        Self {
+            name,
+            permanent: true,
+        }
+    }
+
+    /// A reservation released to the page allocator on handoff.
+    pub const fn temporary(name: &'static str) -> Self {
+        Self {
+            name,
+            permanent: false,
+        }
+    }
+}
+
+/// A reserved physical memory range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    /// Physical start address.
+    pub start: usize,
+    /// Size in bytes.
+    pub size: usize,
+    /// What the range holds.
+    pub tag: EarlyTag,
+}
+
+impl Reservation {
+    const EMPTY: Self = Self {
+        start: 0,
+        size: 0,
+        tag: EarlyTag::temporary(""),
+    };
+
+    /// Returns the end of the range.
+    pub const fn end(&self) -> usize {
+        self.start + self.size
+    }
+}
+
+/// Errors of the early allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemBlockError {
+    /// The range overlaps an existing reservation.
+    Overlap(Reservation),
+    /// There is no room left for another range.
+    Full,
+    /// No free range is large enough.
+    NoMemory,
+    /// The page allocator has taken over.
+    HandedOff,
+}
+
+/// Available and reserved physical memory ranges, kept sorted.
+pub struct MemBlock {
+    available: [MemRange; MAX_MEMBLOCK_RANGES],
+    nr_available: usize,
+    reserved: [Reservation; MAX_MEMBLOCK_RANGES],
+    nr_reserved: usize,
+    handed_off: bool,
+}
+
+impl MemBlock {
+    /// Creates an empty instance.
+    pub const fn new() -> Self {
+        Self {
+            available: [(0, 0); MAX_MEMBLOCK_RANGES],
+            nr_available: 0,
+            reserved: [Reservation::EMPTY; MAX_MEMBLOCK_RANGES],
+            nr_reserved: 0,
+            handed_off: false,
+        }
+    }
+
+    /// Returns the available ranges.
+    pub fn available(&self) -> &[MemRange] {
+        &self.available[..self.nr_available]
+    }
+
+    /// Returns the reservations, sorted by address.
+    pub fn reservations(&self) -> &[Reservation] {
+        &self.reserved[..self.nr_reserved]
+    }
+
+    /// Adds a range of usable memory.
+    ///
+    /// It is merged with the available ranges it overlaps or touches.
+    pub fn add_available(&mut self, start: usize, size: usize) -> Result<(), MemBlockError> {
+        if size == 0 {
+            return Ok(());
+        }
+        let (mut start, mut end) = (start, start + size);
+        // Ranges merged with the new one, which all lie in `first..last`.
+        let first = self.available().partition_point(|&(s, n)| s + n < start);
+        let mut last = first;
+        while let Some(&(s, n)) = self.available().get(last)
+            && s <= end
+        {
+            start = start.min(s);
+            end = end.max(s + n);
+            last += 1;
+        }
+        if first == last && self.nr_available == MAX_MEMBLOCK_RANGES {
+            return Err(MemBlockError::Full);
+        }
+        // Keep one slot at `first` and drop the others that were merged.
+        let removed = (last - first).saturating_sub(1);
+        if last == first {
+            self.available
+                .copy_within(first..self.nr_available, first + 1);
+            self.nr_available += 1;
+        } else {
+            self.available
+                .copy_within(last..self.nr_available, first + 1);
+            self.nr_available -= removed;
+        }
+        self.available[first] = (start, end - start);
+        Ok(())
+    }
+
+    /// Reserves `start..start + size` for `tag`.
+    ///
+    /// The range need not be available memory, but must not overlap another
+    /// reservation.
+    pub fn reserve(
+        &mut self,
+        start: usize,
+        size: usize,
+        tag: EarlyTag,
+    ) -> Result<(), MemBlockError> {
+        if self.handed_off {
+            return Err(MemBlockError::HandedOff);
+        }
+        if size == 0 {
+            return Ok(());
+        }
+        let end = start + size;
+        let idx = self.reservations().partition_point(|r| r.start < start);
+        if idx > 0 && self.reserved[idx - 1].end() > start {
+            return Err(MemBlockError::Overlap(self.reserved[idx - 1]));
+        }
+        if idx < self.nr_reserved && self.reserved[idx].start < end {
+            return Err(MemBlockError::Overlap(self.reserved[idx]));
+        }
+        if self.nr_reserved == MAX_MEMBLOCK_RANGES {
+            return Err(MemBlockError::Full);
+        }
+        self.reserved.copy_within(idx..self.nr_reserved, idx + 1);
+        self.reserved[idx] = Reservation { start, size, tag };
+        self.nr_reserved += 1;
+        Ok(())
+    }
+
+    /// Allocates `size` bytes aligned to `align` for `tag`, returning the
+    /// start address.
+    ///
+    /// Memory is taken from the top of the highest free range that fits, to
+    /// stay clear of the low memory that firmware tends to use.
+    pub fn alloc(
+        &mut self,
+        size: usize,
+        align: usize,
+        tag: EarlyTag,
+    ) -> Result<usize, MemBlockError> {
+        assert!(align.is_power_of_two());
+        if self.handed_off {
+            return Err(MemBlockError::HandedOff);
+        }
+        let mut found = None;
+        self.for_each_free(|(start, len)| {
+            if len >= size {
+                let addr = align_down(start + len - size, align);
+                if addr >= start {
+                    found = Some(addr);
+                }
+            }
+        });
+        let addr = found.ok_or(MemBlockError::NoMemory)?;
+        self.reserve(addr, size.max(1), tag)?;
+        Ok(addr)
+    }
+
+    /// Calls `f` with every available range not reserved, in address order.
+    pub fn for_each_free(&self, mut f: impl FnMut(MemRange)) {
+        for &(start, size) in self.available() {
+            let end = start + size;
+            let mut cursor = start;
+            for r in self.reservations() {
+                if r.end() <= cursor {
+                    continue;
+                }
+                if r.start >= end {
+                    break;
+                }
+                if r.start > cursor {
+                    f((cursor, r.start - cursor));
+                }
+                cursor = r.end();
+            }
+            if cursor < end {
+                f((cursor, end - cursor));
+            }
+        }
+    }
+
+    /// Drops the temporary reservations, calling `f` with the available
+    /// memory they held, and refuses any further reservation.
+    pub fn handoff(&mut self, mut f: impl FnMut(MemRange)) {
+        let mut kept = 0;
+        for i in 0..self.nr_reserved {
+            let r = self.reserved[i];
+            if r.tag.permanent {
+                self.reserved[kept] = r;
+                kept += 1;
+                continue;
+            }
+            for &(start, size) in &self.available[..self.nr_available] {
+                let (s, e) = (r.start.max(start), r.end().min(start + size));
+                if s < e {
+                    f((s, e - s));
+                }
+            }
+        }
+        self.nr_reserved = kept;
+        self.handed_off = true;
+    }
+}
+
+impl Default for MemBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static MEMBLOCK: SpinNoIrq<MemBlock> = SpinNoIrq::new(MemBlock::new());
+
+/// Adds a range of RAM to the early allocator.
+pub fn early_add_memory(range: MemRange) -> Result<(), MemBlockError> {
+    MEMBLOCK.lock().add_available(range.0, range.1)
+}
+
+/// Reserves a physical memory range before the page allocator is up.
+///
+/// The range is widened to whole pages.
+pub fn early_reserve(range: MemRange, tag: EarlyTag) -> Result<(), MemBlockError> {
+    let start = align_down_4k(range.0);
+    let end = align_up_4k(range.0 + range.1);
+    MEMBLOCK.lock().reserve(start, end - start, tag)
+}
+
+/// Allocates physical memory before the page allocator is up.
+///
+/// Whole pages are allocated, and the memory is not zeroed. It fails until
+/// some RAM has been added with [`early_add_memory`].
+pub fn early_alloc(size: usize, align: usize, tag: EarlyTag) -> Result<PhysAddr, MemBlockError> {
+    MEMBLOCK
+        .lock()
+        .alloc(align_up_4k(size), align.max(PAGE_SIZE_4K), tag)
+        .map(|addr| pa!(addr))
+}
+
+/// Calls `f` with every early reservation, in address order.
+pub fn early_reservations(f: impl FnMut(&Reservation)) {
+    MEMBLOCK.lock().reservations().iter().for_each(f);
+}
+
+/// Calls `f` with every range of RAM not reserved, in address order.
+pub fn early_free_ranges(f: impl FnMut(MemRange)) {
+    MEMBLOCK.lock().for_each_free(f)
+}
+
+/// Hands the early memory over to the page allocator.
+///
+/// `f` is called with the memory held by temporary reservations, which is
+/// now free. Permanent reservations are kept, and no more memory may be
+/// reserved or allocated early.
+pub fn early_handoff(f: impl FnMut(MemRange)) {
+    MEMBLOCK.lock().handoff(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn free(mb: &MemBlock) -> Vec<MemRange> {
+        let mut ranges = Vec::new();
+        mb.for_each_free(|r| ranges.push(r));
+        ranges
+    }
+
+    #[test]
+    fn test_add_available_merges() {
+        let mut mb = MemBlock::new();
+        mb.add_available(0x3000, 0x1000).unwrap();
+        mb.add_available(0x1000, 0x1000).unwrap();
+        assert_eq!(mb.available(), [(0x1000, 0x1000), (0x3000, 0x1000)]);
+        mb.add_available(0x2000, 0x1000).unwrap();
+        assert_eq!(mb.available(), [(0x1000, 0x3000)]);
+        mb.add_available(0x8000, 0x1000).unwrap();
+        mb.add_available(0x1800, 0x8000).unwrap();
+        assert_eq!(mb.available(), [(0x1000, 0x8800)]);
+    }
+
+    #[test]
+    fn test_reserve_overlap() {
+        let mut mb = MemBlock::new();
+        let dtb = EarlyTag::DTB;
+        mb.reserve(0x2000, 0x1000, dtb).unwrap();
+        mb.reserve(0x4000, 0x1000, EarlyTag::KERNEL).unwrap();
+        // Touching ranges do not overlap.
+        mb.reserve(0x3000, 0x1000, EarlyTag::temporary("a"))
+            .unwrap();
+
+        let err = mb.reserve(0x1800, 0x1000, EarlyTag::temporary("b"));
+        assert_eq!(
+            err,
+            Err(MemBlockError::Overlap(Reservation {
+                start: 0x2000,
+                size: 0x1000,
+                tag: dtb,
+            }))
+        );
+        assert!(mb.reserve(0x4800, 0x10, EarlyTag::DMA).is_err());
+        assert!(mb.reserve(0, 0x10000, EarlyTag::DMA).is_err());
+        assert_eq!(mb.reservations().len(), 3);
+        assert!(mb.reservations().is_sorted_by_key(|r| r.start));
+    }
+
+    #[test]
+    fn test_free_ranges() {
+        let mut mb = MemBlock::new();
+        mb.add_available(0x1000, 0x9000).unwrap();
+        mb.add_available(0x20000, 0x1000).unwrap();
+        mb.reserve(0, 0x2000, EarlyTag::permanent("low")).unwrap();
+        mb.reserve(0x4000, 0x1000, EarlyTag::KERNEL).unwrap();
+        mb.reserve(0x9000, 0x10000, EarlyTag::DMA).unwrap();
+        assert_eq!(
+            free(&mb),
+            [(0x2000, 0x2000), (0x5000, 0x4000), (0x20000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn test_alloc_top_down() {
+        let mut mb = MemBlock::new();
+        assert_eq!(
+            mb.alloc(0x1000, 0x1000, EarlyTag::temporary("a")),
+            Err(MemBlockError::NoMemory)
+        );
+        mb.add_available(0x10000, 0x10000).unwrap();
+        mb.reserve(0x1f000, 0x1000, EarlyTag::DTB).unwrap();
+
+        let a = mb.alloc(0x100, 0x1000, EarlyTag::temporary("a")).unwrap();
+        assert_eq!(a, 0x1e000);
+        let b = mb.alloc(0x800, 0x10, EarlyTag::permanent("b")).unwrap();
+        assert_eq!(b, 0x1e800);
+        // Too large for the gap left between `a` and `b`, so it goes below.
+        let c = mb.alloc(0x1000, 0x1000, EarlyTag::permanent("c")).unwrap();
+        assert_eq!(c, 0x1d000);
+        assert_eq!(
+            mb.alloc(0x10000, 0x1000, EarlyTag::temporary("d")),
+            Err(MemBlockError::NoMemory)
+        );
+        assert_eq!(free(&mb), [(0x10000, 0xd000), (0x1e100, 0x700)]);
+    }
+
+    #[test]
+    fn test_handoff() {
+        let mut mb = MemBlock::new();
+        mb.add_available(0x10000, 0x10000).unwrap();
+        mb.reserve(0x8000, 0x10000, EarlyTag::temporary("firmware"))
+            .unwrap();
+        mb.reserve(0x1f000, 0x1000, EarlyTag::DTB).unwrap();
+        let buf = mb
+            .alloc(0x2000, 0x1000, EarlyTag::temporary("log"))
+            .unwrap();
+
+        let mut released = Vec::new();
+        mb.handoff(|r| released.push(r));
+        // Only the part of a reservation in available memory is released.
+        assert_eq!(released, [(0x10000, 0x8000), (buf, 0x2000)]);
+        assert_eq!(mb.reservations().len(), 1);
+        assert_eq!(mb.reservations()[0].tag, EarlyTag::DTB);
+        assert_eq!(free(&mb), [(0x10000, 0xf000)]);
+
+        assert_eq!(
+            mb.reserve(0, 0x1000, EarlyTag::DTB),
+            Err(MemBlockError::HandedOff)
+        );
+        assert_eq!(
+            mb.alloc(0x1000, 0x1000, EarlyTag::DTB),
+            Err(MemBlockError::HandedOff)
+        );
+    }
+
+    #[test]
+    fn test_full() {
+        let mut mb = MemBlock::new();
+        mb.add_available(0, MAX_MEMBLOCK_RANGES * 0x2000).unwrap();
+        for i in 0..MAX_MEMBLOCK_RANGES {
+            mb.reserve(i * 0x2000, 0x1000, EarlyTag::temporary("a"))
+                .unwrap();
+        }
+        assert_eq!(
+            mb.reserve(
+                MAX_MEMBLOCK_RANGES * 0x2000,
+                0x1000,
+                EarlyTag::temporary("a")
+            ),
+            Err(MemBlockError::Full)
+        );
+        assert_eq!(
+            mb.alloc(0x1000, 0x1000, EarlyTag::temporary("a")),
+            Err(MemBlockError::Full)
+        );
+    }
+}
@@ -15,4 +15,19 @@ pub trait PerfMgr {
     fn on_overflow() -> bool;
     /// Registers a callback for a counter index.
     fn reg_cb(idx: u32, cb: PerfCb) -> bool;
+    /// Returns how many counters are free to count events of tasks.
+    fn task_counters() -> usize;
+    /// Starts task counter `slot` counting `event`, a generic hardware event
+    /// numbered as Linux `PERF_COUNT_HW_*`, from zero. Returns `false` if
+    /// the PMU cannot count it.
+    fn task_counter_start(
+        slot: usize,
+        event: u32,
+        exclude_user: bool,
+        exclude_kernel: bool,
+    ) -> bool;
+    /// Returns the count of task counter `slot`.
+    fn task_counter_read(slot: usize) -> u64;
+    /// Stops task counter `slot`.
+    fn task_counter_stop(slot: usize);
 }
@@ -14,4 +14,10 @@ pub trait SysCtrl {
 
     /// Shuts down the system.
     fn shutdown() -> !;
+
+    /// Resets the system immediately.
+    ///
+    /// Called from failure paths after the heap or locks may have been
+    /// corrupted: implementations must not log, allocate or take locks.
+    fn reset() -> !;
 }
@@ -3,8 +3,15 @@
 // See LICENSES for license details.
 
 //! Platform timer interface and helpers.
+//!
+//! The raw count of the global timer is the kernel reference clock: logs,
+//! trace events, boot stage marks and crash reports are all stamped with it,
+//! so that their timestamps can be compared with each other and with
+//! `CLOCK_MONOTONIC`. Its epoch is the monotonic epoch, so a
+//! [`ReferenceTime`] converts to monotonic time exactly with [`t2ns`], the
+//! very conversion that produces monotonic time.
 
-use core::time::Duration;
+use core::{fmt, time::Duration};
 
 use kplat_macros::device_interface;
 
@@ -73,3 +80,79 @@ pub fn spin_until(dl: ClockTime) {
         core::hint::spin_loop();
     }
 }
+
+/// A reading of the kernel reference clock, in timer ticks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReferenceTime(u64);
+
+impl ReferenceTime {
+    /// Creates a reference time from a raw timer count.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the raw timer count.
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the monotonic time of this reading, in nanoseconds.
+    pub fn monotonic_ns(self) -> u64 {
+        t2ns(self.0)
+    }
+
+    /// Returns the monotonic time of this reading.
+    pub fn monotonic(self) -> ClockTime {
+        ClockTime::from_nanos(self.monotonic_ns())
+    }
+
+    /// Returns the reference time of the monotonic time `ns`, rounded down
+    /// to a whole tick.
+    pub fn from_monotonic_ns(ns: u64) -> Self {
+        Self(ns2t(ns))
+    }
+}
+
+/// Displays the monotonic time as `secs.micros`, as log lines do.
+impl fmt::Display for ReferenceTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let now = self.monotonic();
+        write!(f, "{}.{:06}", now.as_secs(), now.subsec_micros())
+    }
+}
+
+/// Reads the kernel reference clock.
+///
+/// Only reads the timer count, so it is usable from any context, including
+/// before the timer is calibrated; converting the reading needs calibration.
+#[inline]
+pub fn reference_now() -> ReferenceTime {
+    ReferenceTime(now_ticks())
+}
+
+/// Parameters converting between the reference clock and `CLOCK_MONOTONIC`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceParams {
+    /// Frequency of the reference clock, in Hz.
+    pub freq: u64,
+    /// A reading of the reference clock.
+    pub ticks: u64,
+    /// The monotonic time of `ticks`, in nanoseconds.
+    pub monotonic_ns: u64,
+}
+
+impl ReferenceParams {
+    /// Samples the parameters now.
+    ///
+    /// Both times come from the same timer read, so the offset between the
+    /// clocks, `monotonic_ns - ticks * NS_SEC / freq`, is exact up to the
+    /// rounding of the conversion.
+    pub fn sample() -> Self {
+        let now = reference_now();
+        Self {
+            freq: freq(),
+            ticks: now.ticks(),
+            monotonic_ns: now.monotonic_ns(),
+        }
+    }
+}
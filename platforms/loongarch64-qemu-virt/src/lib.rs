@@ -11,7 +11,9 @@ extern crate kplat;
 pub mod config {
     platconfig_macros::include_configs!(
         path_env = "PLAT_CONFIG_PATH",
-        fallback = "platconfig.toml"
+        fallback = "platconfig.toml",
+        schema = "platconfig.schema.toml",
+        deny_unknown_keys = true,
     );
     assert_str_eq!(
         PACKAGE,
@@ -26,4 +26,13 @@ impl SysCtrl for PowerImpl {
             kcpu::instrs::stop_cpu();
         }
     }
+
+    fn reset() -> ! {
+        // The GED reset register follows the sleep control/status bytes.
+        let reset_addr: *mut u8 = p2v(pa!(GED_PADDR + 2)).as_mut_ptr();
+        unsafe { reset_addr.write_volatile(0x42) };
+        loop {
+            kcpu::instrs::stop_cpu();
+        }
+    }
 }
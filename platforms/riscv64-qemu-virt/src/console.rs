@@ -32,6 +32,21 @@ impl ConsoleIf for ConsoleImpl {
         }
     }
 
+    fn write_data_atomic(bytes: &[u8]) {
+        // A fresh handle on the same registers, so a CPU that died holding
+        // the lock cannot block failure reports.
+        let mut uart = unsafe { MmioSerialPort::new(UART_PADDR + PHYS_VIRT_OFFSET) };
+        for &c in bytes {
+            match c {
+                b'\n' => {
+                    uart.send_raw(b'\r');
+                    uart.send_raw(b'\n');
+                }
+                c => uart.send_raw(c),
+            }
+        }
+    }
+
     fn read_data(bytes: &mut [u8]) -> usize {
         let mut uart = UART.lock();
         for (i, byte) in bytes.iter_mut().enumerate() {
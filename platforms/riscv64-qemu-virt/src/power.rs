@@ -25,4 +25,11 @@ impl SysCtrl for PowerImpl {
             kcpu::instrs::stop_cpu();
         }
     }
+
+    fn reset() -> ! {
+        sbi_rt::system_reset(sbi_rt::ColdReboot, sbi_rt::SystemFailure);
+        loop {
+            kcpu::instrs::stop_cpu();
+        }
+    }
 }
@@ -54,3 +54,14 @@ impl GlobalTimer for GlobalTimerImpl {
         sbi_rt::set_timer(Self::ns2t(deadline_ns));
     }
 }
+#[cfg(feature = "rtc")]
+fn check_rtc(ctx: &mut selftest::TestContext) -> selftest::Outcome {
+    use crate::config::{devices::RTC_PADDR, plat::PHYS_VIRT_OFFSET};
+    if RTC_PADDR == 0 {
+        return selftest::Outcome::Skip("no RTC".into());
+    }
+    let rtc = riscv_goldfish::Rtc::new(RTC_PADDR + PHYS_VIRT_OFFSET);
+    selftest::clock::check_rtc(ctx, &|| rtc.get_unix_timestamp())
+}
+#[cfg(feature = "rtc")]
+selftest::register_selftest!(Boot, "rtc", check_rtc);
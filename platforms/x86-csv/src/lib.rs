@@ -21,7 +21,9 @@ mod time;
 pub mod config {
     platconfig_macros::include_configs!(
         path_env = "PLAT_CONFIG_PATH",
-        fallback = "platconfig.toml"
+        fallback = "platconfig.toml",
+        schema = "platconfig.schema.toml",
+        deny_unknown_keys = true,
     );
     check_str_eq!(
         PACKAGE,
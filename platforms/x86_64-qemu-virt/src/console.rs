@@ -29,6 +29,15 @@ impl ConsoleIf for ConsoleImpl {
         }
     }
 
+    fn write_data_atomic(bytes: &[u8]) {
+        // A fresh handle on the same port, so a CPU that died holding the
+        // lock cannot block failure reports.
+        let mut port = unsafe { SerialPort::new(0x3f8) };
+        for c in bytes {
+            port.send(*c);
+        }
+    }
+
     fn read_data(bytes: &mut [u8]) -> usize {
         let mut read_len = 0;
         while read_len < bytes.len() {
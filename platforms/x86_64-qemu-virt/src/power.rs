@@ -31,4 +31,16 @@ impl SysCtrl for PowerImpl {
             kcpu::instrs::stop_cpu();
         }
     }
+
+    fn reset() -> ! {
+        // Pulse the reset line through the keyboard controller, then fall
+        // back to the PCI reset control register.
+        unsafe {
+            PortWriteOnly::new(0x64).write(0xfeu8);
+            PortWriteOnly::new(0xcf9).write(0x0eu8);
+        }
+        loop {
+            kcpu::instrs::stop_cpu();
+        }
+    }
 }
@@ -94,3 +94,10 @@ impl GlobalTimer for GlobalTimerImpl {
         }
     }
 }
+#[cfg(feature = "rtc")]
+fn check_rtc(ctx: &mut selftest::TestContext) -> selftest::Outcome {
+    let rtc = x86_rtc::Rtc::new();
+    selftest::clock::check_rtc(ctx, &|| rtc.get_unix_timestamp())
+}
+#[cfg(feature = "rtc")]
+selftest::register_selftest!(Boot, "rtc", check_rtc);
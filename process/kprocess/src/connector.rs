@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Process event connector: process lifecycle events streamed to listeners.
+//!
+//! Events are queued synchronously at the point they happen, under a single
+//! lock, so every listener sees them in the order they happened: the fork of
+//! a process always comes before anything it does. A listener may be
+//! restricted to a subtree of processes, which it tracks through the fork
+//! and exit events it sees, so processes reparented to init stay in it.
+use alloc::{
+    collections::{btree_set::BTreeSet, vec_deque::VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::task::Waker;
+
+use bitflags::bitflags;
+use kspin::SpinNoIrq;
+
+use crate::{
+    Pid, Process, ProcessEvent,
+    event::{Waiters, wake_all},
+};
+
+/// The length of a process name, including the NUL terminator.
+pub const COMM_LEN: usize = 16;
+
+bitflags! {
+    /// Kinds of [`ConnectorEvent`], with the values of Linux `proc_event`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ConnectorEvents: u32 {
+        /// [`ConnectorEvent::Fork`].
+        const FORK = 0x0000_0001;
+        /// [`ConnectorEvent::Exec`].
+        const EXEC = 0x0000_0002;
+        /// [`ConnectorEvent::Uid`].
+        const UID = 0x0000_0004;
+        /// [`ConnectorEvent::Gid`].
+        const GID = 0x0000_0040;
+        /// [`ConnectorEvent::Comm`].
+        const COMM = 0x0000_0200;
+        /// [`ConnectorEvent::Exit`].
+        const EXIT = 0x8000_0000;
+    }
+}
+
+/// A process event, as delivered to a [`ConnectorListener`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectorEvent {
+    /// `parent` forked `child`.
+    Fork {
+        /// The parent process.
+        parent: Pid,
+        /// The new process.
+        child: Pid,
+    },
+    /// `pid` executed a new program.
+    Exec {
+        /// The process.
+        pid: Pid,
+        /// The name of the new program, NUL-padded.
+        comm: [u8; COMM_LEN],
+    },
+    /// `pid` terminated.
+    Exit {
+        /// The process.
+        pid: Pid,
+        /// How it terminated.
+        status: ProcessEvent,
+    },
+    /// `pid` changed its user IDs.
+    Uid {
+        /// The process.
+        pid: Pid,
+        /// The new real user ID.
+        ruid: u32,
+        /// The new effective user ID.
+        euid: u32,
+    },
+    /// `pid` changed its group IDs.
+    Gid {
+        /// The process.
+        pid: Pid,
+        /// The new real group ID.
+        rgid: u32,
+        /// The new effective group ID.
+        egid: u32,
+    },
+    /// `pid` changed its name.
+    Comm {
+        /// The process.
+        pid: Pid,
+        /// The new name, NUL-padded.
+        comm: [u8; COMM_LEN],
+    },
+    /// The queue of the listener was full and `lost` events were dropped
+    /// here.
+    Overflow {
+        /// How many events were dropped.
+        lost: u64,
+    },
+}
+
+impl ConnectorEvent {
+    /// Returns the kind of the event, empty for [`ConnectorEvent::Overflow`].
+    pub fn kind(&self) -> ConnectorEvents {
+        match self {
+            Self::Fork { .. } => ConnectorEvents::FORK,
+            Self::Exec { .. } => ConnectorEvents::EXEC,
+            Self::Exit { .. } => ConnectorEvents::EXIT,
+            Self::Uid { .. } => ConnectorEvents::UID,
+            Self::Gid { .. } => ConnectorEvents::GID,
+            Self::Comm { .. } => ConnectorEvents::COMM,
+            Self::Overflow { .. } => ConnectorEvents::empty(),
+        }
+    }
+
+    /// Returns the process the event is about, the parent for a fork.
+    fn pid(&self) -> Option<Pid> {
+        match *self {
+            Self::Fork { parent, .. } => Some(parent),
+            Self::Exec { pid, .. }
+            | Self::Exit { pid, .. }
+            | Self::Uid { pid, .. }
+            | Self::Gid { pid, .. }
+            | Self::Comm { pid, .. } => Some(pid),
+            Self::Overflow { .. } => None,
+        }
+    }
+}
+
+/// Queue and filter of a listener.
+struct ListenerState {
+    kinds: ConnectorEvents,
+    /// The processes of the subtree listened to, `None` for all of them.
+    subtree: Option<BTreeSet<Pid>>,
+    queue: VecDeque<ConnectorEvent>,
+    capacity: usize,
+    waiters: Waiters,
+}
+
+impl ListenerState {
+    /// Queues `event` if the listener is interested in it, returning whether
+    /// it was.
+    fn offer(&mut self, event: &ConnectorEvent) -> bool {
+        if let Some(subtree) = &mut self.subtree {
+            let Some(pid) = event.pid() else {
+                return false;
+            };
+            if !subtree.contains(&pid) {
+                return false;
+            }
+            match *event {
+                ConnectorEvent::Fork { child, .. } => {
+                    subtree.insert(child);
+                }
+                ConnectorEvent::Exit { pid, .. } => {
+                    subtree.remove(&pid);
+                }
+                _ => {}
+            }
+        }
+        if !self.kinds.intersects(event.kind()) {
+            return false;
+        }
+        self.push(*event);
+        true
+    }
+
+    /// Queues `event`, or counts it in an overflow marker taking the last
+    /// slot of the queue.
+    fn push(&mut self, event: ConnectorEvent) {
+        if self.queue.len() + 1 < self.capacity {
+            self.queue.push_back(event);
+            return;
+        }
+        match self.queue.back_mut() {
+            Some(ConnectorEvent::Overflow { lost }) => *lost += 1,
+            _ => self.queue.push_back(ConnectorEvent::Overflow { lost: 1 }),
+        }
+    }
+}
+
+static LISTENERS: SpinNoIrq<Vec<Arc<SpinNoIrq<ListenerState>>>> = SpinNoIrq::new(Vec::new());
+
+/// Delivers `event` to the listeners interested in it.
+///
+/// Fork and exit events are sent by [`Process`] itself; the others are up to
+/// the code that changes the state.
+pub fn notify(event: ConnectorEvent) {
+    let mut wakers = Vec::new();
+    {
+        let listeners = LISTENERS.lock();
+        for listener in listeners.iter() {
+            let mut state = listener.lock();
+            if state.offer(&event) {
+                wakers.append(&mut state.waiters.take());
+            }
+        }
+    }
+    wake_all(wakers);
+}
+
+/// Returns how many listeners are subscribed.
+pub fn listener_count() -> usize {
+    LISTENERS.lock().len()
+}
+
+/// A subscription to process events, removed when dropped.
+pub struct ConnectorListener {
+    state: Arc<SpinNoIrq<ListenerState>>,
+}
+
+impl ConnectorListener {
+    /// The smallest queue a listener may have, room for an event and an
+    /// overflow marker.
+    pub const MIN_CAPACITY: usize = 2;
+
+    /// Subscribes to events of the given `kinds`, queueing up to `capacity`
+    /// of them.
+    ///
+    /// With `root`, only events of it and of its descendants are delivered,
+    /// including those that outlive it or a parent of theirs.
+    pub fn subscribe(kinds: ConnectorEvents, root: Option<&Arc<Process>>, capacity: usize) -> Self {
+        let mut listeners = LISTENERS.lock();
+        // Holding the lock, no fork can be missed between the walk of the
+        // subtree and the subscription.
+        let subtree = root.map(|root| {
+            let mut subtree = BTreeSet::new();
+            let mut stack = alloc::vec![root.clone()];
+            while let Some(process) = stack.pop() {
+                if !process.is_zombie() {
+                    subtree.insert(process.pid());
+                }
+                stack.extend(process.children());
+            }
+            subtree
+        });
+        let state = Arc::new(SpinNoIrq::new(ListenerState {
+            kinds,
+            subtree,
+            queue: VecDeque::new(),
+            capacity: capacity.max(Self::MIN_CAPACITY),
+            waiters: Waiters::default(),
+        }));
+        listeners.push(state.clone());
+        Self { state }
+    }
+
+    /// Takes the oldest queued event.
+    pub fn pop(&self) -> Option<ConnectorEvent> {
+        self.state.lock().queue.pop_front()
+    }
+
+    /// Returns `true` if an event is queued.
+    pub fn has_events(&self) -> bool {
+        !self.state.lock().queue.is_empty()
+    }
+
+    /// Registers `waker` to be woken when an event is queued.
+    pub fn register_waker(&self, waker: &Waker) {
+        self.state.lock().waiters.register(waker);
+    }
+}
+
+impl Drop for ConnectorListener {
+    fn drop(&mut self) {
+        LISTENERS
+            .lock()
+            .retain(|state| !Arc::ptr_eq(state, &self.state));
+    }
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Process state changes and their waiters.
+use alloc::vec::Vec;
+use core::task::Waker;
+
+/// A change of state of a [`Process`], as reported to its waiters.
+///
+/// [`Process`]: crate::Process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessEvent {
+    /// The process exited with the given code.
+    Exited(i32),
+    /// The process was terminated by the given signal.
+    Signaled(u32),
+    /// The process was terminated by the given signal and dumped core.
+    Dumped(u32),
+    /// The process was stopped by the given signal.
+    Stopped(u32),
+    /// The process was continued by `SIGCONT`.
+    Continued,
+}
+
+impl ProcessEvent {
+    /// Decodes the raw exit status kept by a thread group, `code << 8` for an
+    /// exit and the signal number, with bit 7 set for a core dump, for a
+    /// termination by a signal.
+    pub fn from_exit_status(status: i32) -> Self {
+        match status & 0x7f {
+            0 => Self::Exited((status >> 8) & 0xff),
+            signo if status & 0x80 != 0 => Self::Dumped(signo as u32),
+            signo => Self::Signaled(signo as u32),
+        }
+    }
+
+    /// Returns the status reported by `wait4` for this event.
+    pub fn wait_status(&self) -> i32 {
+        match *self {
+            Self::Exited(code) => (code & 0xff) << 8,
+            Self::Signaled(signo) => signo as i32 & 0x7f,
+            Self::Dumped(signo) => (signo as i32 & 0x7f) | 0x80,
+            Self::Stopped(signo) => ((signo as i32 & 0xff) << 8) | 0x7f,
+            Self::Continued => 0xffff,
+        }
+    }
+}
+
+/// Wakers to wake on the next state change.
+#[derive(Default)]
+pub(crate) struct Waiters(Vec<Waker>);
+
+impl Waiters {
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        if !self.0.iter().any(|w| w.will_wake(waker)) {
+            self.0.push(waker.clone());
+        }
+    }
+
+    /// Takes the wakers, to wake once the lock protecting them is released.
+    pub(crate) fn take(&mut self) -> Vec<Waker> {
+        core::mem::take(&mut self.0)
+    }
+}
+
+/// Wakes every waker in `wakers`.
+pub(crate) fn wake_all(wakers: Vec<Waker>) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+/// State change bookkeeping of a process.
+#[derive(Default)]
+pub(crate) struct Notifier {
+    /// Woken when this process changes state.
+    pub(crate) waiters: Waiters,
+    /// Woken when a child of this process changes state.
+    pub(crate) child_waiters: Waiters,
+    /// Whether the process is stopped by job control.
+    pub(crate) stopped: bool,
+    /// The last stop or continue not yet reported to the parent.
+    pub(crate) job_event: Option<ProcessEvent>,
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Exit hooks: reaping of the long-lived state a process or thread leaves
+//! behind.
+//!
+//! State that may outlive the code that created it, such as an armed timer
+//! or a waiter parked in a shared object, registers a hook with its owner
+//! when it is created. The hooks run once the owner exits, past the point of
+//! no return: for a thread as it exits, for a process before it becomes a
+//! zombie, so before its pid can be reaped and reused. State torn down
+//! before that drops its [`ExitRegistration`], which removes the hook in
+//! constant time.
+//!
+//! A hook decides what happens to its state, see [`Reaped`]. In debug
+//! builds, state a hook says it cancelled must be gone when the hook
+//! returns.
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use kspin::SpinNoIrq;
+
+/// What an exit hook did with the state it was registered for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reaped {
+    /// The state was torn down, nothing may refer to it any more.
+    Cancelled,
+    /// The state lives on without the owner, e.g. as it is shared with
+    /// other processes.
+    Orphaned,
+}
+
+/// A hook, returning `true` if it leaked state it cancelled.
+type HookFn = Box<dyn FnOnce() -> bool + Send>;
+
+struct Slot {
+    generation: u32,
+    hook: Option<(&'static str, HookFn)>,
+}
+
+#[derive(Default)]
+struct HookList {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    len: usize,
+    /// Set once the hooks ran: the owner is gone.
+    done: bool,
+}
+
+/// The exit hooks of a process or thread.
+#[derive(Default)]
+pub struct ExitHooks(Arc<SpinNoIrq<HookList>>);
+
+impl ExitHooks {
+    /// Creates an empty list of hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `hook` to run when the owner exits.
+    ///
+    /// If the owner already exited, `hook` runs right away.
+    pub fn register(
+        &self,
+        subsystem: &'static str,
+        hook: impl FnOnce() + Send + 'static,
+    ) -> ExitRegistration {
+        self.insert(
+            subsystem,
+            Box::new(move || {
+                hook();
+                false
+            }),
+        )
+    }
+
+    /// Registers `hook` to run on `object` when the owner exits, unless the
+    /// object is gone by then.
+    ///
+    /// If the owner already exited, `hook` runs right away.
+    pub fn register_object<T: Send + Sync + 'static>(
+        &self,
+        subsystem: &'static str,
+        object: &Arc<T>,
+        hook: impl FnOnce(Arc<T>) -> Reaped + Send + 'static,
+    ) -> ExitRegistration {
+        let object = Arc::downgrade(object);
+        self.insert(
+            subsystem,
+            Box::new(move || {
+                let Some(strong) = object.upgrade() else {
+                    return false;
+                };
+                hook(strong) == Reaped::Cancelled && object.strong_count() > 0
+            }),
+        )
+    }
+
+    fn insert(&self, subsystem: &'static str, hook: HookFn) -> ExitRegistration {
+        let mut list = self.0.lock();
+        if list.done {
+            drop(list);
+            run_hook(subsystem, hook);
+            return ExitRegistration::default();
+        }
+        let index = match list.free.pop() {
+            Some(index) => index,
+            None => {
+                list.slots.push(Slot {
+                    generation: 0,
+                    hook: None,
+                });
+                list.slots.len() - 1
+            }
+        };
+        let slot = &mut list.slots[index];
+        slot.hook = Some((subsystem, hook));
+        let generation = slot.generation;
+        list.len += 1;
+        ExitRegistration {
+            list: Arc::downgrade(&self.0),
+            index,
+            generation,
+        }
+    }
+
+    /// Returns how many hooks are registered.
+    pub fn len(&self) -> usize {
+        self.0.lock().len
+    }
+
+    /// Returns `true` if no hook is registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs the hooks, once; hooks registered later run as they are
+    /// registered.
+    pub fn run(&self) {
+        let hooks: Vec<_> = {
+            let mut list = self.0.lock();
+            list.done = true;
+            list.len = 0;
+            list.free.clear();
+            core::mem::take(&mut list.slots)
+                .into_iter()
+                .filter_map(|slot| slot.hook)
+                .collect()
+        };
+        for (subsystem, hook) in hooks {
+            run_hook(subsystem, hook);
+        }
+        debug_assert!(self.is_empty(), "exit hooks left after the exit");
+    }
+}
+
+fn run_hook(subsystem: &'static str, hook: HookFn) {
+    let leaked = hook();
+    debug_assert!(!leaked, "{subsystem}: cancelled state outlived its owner");
+}
+
+/// A registered exit hook, removed when dropped.
+#[must_use = "dropping the registration removes the hook"]
+#[derive(Default)]
+pub struct ExitRegistration {
+    list: Weak<SpinNoIrq<HookList>>,
+    index: usize,
+    generation: u32,
+}
+
+impl ExitRegistration {
+    /// Returns `true` if the hook is still to run.
+    pub fn is_pending(&self) -> bool {
+        self.list.upgrade().is_some_and(|list| {
+            list.lock()
+                .slots
+                .get(self.index)
+                .is_some_and(|slot| slot.generation == self.generation && slot.hook.is_some())
+        })
+    }
+
+    /// Keeps the hook registered for as long as the owner lives.
+    pub fn detach(mut self) {
+        self.list = Weak::new();
+    }
+}
+
+impl Drop for ExitRegistration {
+    fn drop(&mut self) {
+        let Some(list) = self.list.upgrade() else {
+            return;
+        };
+        let mut list = list.lock();
+        let Some(slot) = list.slots.get_mut(self.index) else {
+            return;
+        };
+        if slot.generation != self.generation {
+            return;
+        }
+        let Some(hook) = slot.hook.take() else {
+            return;
+        };
+        slot.generation = slot.generation.wrapping_add(1);
+        list.free.push(self.index);
+        list.len -= 1;
+        drop(list);
+        drop(hook);
+    }
+}
@@ -12,13 +12,21 @@ extern crate alloc;
 
 mod tests;
 
+pub mod connector;
+mod event;
+mod exit_hooks;
 mod process;
 mod process_group;
 mod session;
+mod usage;
 
 /// A process ID, also used as session ID, process group ID, and thread ID.
 pub type Pid = u32;
 
+pub use connector::{ConnectorEvent, ConnectorEvents, ConnectorListener};
+pub use event::ProcessEvent;
+pub use exit_hooks::{ExitHooks, ExitRegistration, Reaped};
 pub use process::{Process, init_proc};
 pub use process_group::ProcessGroup;
 pub use session::Session;
+pub use usage::ResourceUsage;
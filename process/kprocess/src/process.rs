@@ -11,13 +11,20 @@ use alloc::{
 use core::{
     fmt,
     sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
 };
 
-use kspin::SpinNoIrq;
+use kspin::{SpinNoIrq, SpinRwNoIrq};
 use lazyinit::LazyInit;
 use weak_map::StrongMap;
 
-use crate::{Pid, ProcessGroup, Session};
+use crate::{
+    Pid, ProcessEvent, ProcessGroup, ResourceUsage, Session,
+    connector::{self, ConnectorEvent},
+    event::{Notifier, wake_all},
+    exit_hooks::ExitHooks,
+    usage::Accounting,
+};
 
 /// Thread group state tracked per process.
 #[derive(Default)]
@@ -31,10 +38,15 @@ pub(crate) struct ThreadGroup {
 pub struct Process {
     pid: Pid,
     is_zombie: AtomicBool,
+    /// Set once the exit status has been handed out.
+    is_reaped: AtomicBool,
     pub(crate) tg: SpinNoIrq<ThreadGroup>,
+    notifier: SpinNoIrq<Notifier>,
+    accounting: SpinNoIrq<Accounting>,
+    exit_hooks: ExitHooks,
 
     // TODO: child subreaper9
-    children: SpinNoIrq<StrongMap<Pid, Arc<Process>>>,
+    children: SpinRwNoIrq<StrongMap<Pid, Arc<Process>>>,
     parent: SpinNoIrq<Weak<Process>>,
 
     group: SpinNoIrq<Arc<ProcessGroup>>,
@@ -65,7 +77,18 @@ impl Process {
 
     /// The child [`Process`]es.
     pub fn children(&self) -> Vec<Arc<Process>> {
-        self.children.lock().values().cloned().collect()
+        self.children.read().values().cloned().collect()
+    }
+
+    /// The child [`Process`]es in the [`ProcessGroup`] `pgid`, as waited for
+    /// by `waitpid(-pgid)`.
+    pub fn children_in_group(&self, pgid: Pid) -> Vec<Arc<Process>> {
+        self.children
+            .read()
+            .values()
+            .filter(|child| child.group().pgid() == pgid)
+            .cloned()
+            .collect()
     }
 }
 
@@ -79,9 +102,9 @@ impl Process {
     fn set_group(self: &Arc<Self>, group: &Arc<ProcessGroup>) {
         let mut self_group = self.group.lock();
 
-        self_group.processes.lock().remove(&self.pid);
+        self_group.processes.write().remove(&self.pid);
 
-        group.processes.lock().insert(self.pid, self);
+        group.processes.write().insert(self.pid, self);
 
         *self_group = group.clone();
     }
@@ -200,13 +223,26 @@ impl Process {
         self.is_zombie.load(Ordering::Acquire)
     }
 
+    /// The hooks reaping the state the [`Process`] leaves behind, run by
+    /// [`Process::exit`].
+    pub fn exit_hooks(&self) -> &ExitHooks {
+        &self.exit_hooks
+    }
+
     /// Terminates the [`Process`], marking it as a zombie process.
     ///
+    /// The exit hooks of the [`Process`] run first, while it cannot be
+    /// reaped yet, so none of them sees its pid reused.
+    ///
     /// Child processes are inherited by the init process or by the nearest
-    /// subreaper process.
+    /// subreaper process. The waiters of the [`Process`] and the child
+    /// waiters of its parent are woken, as are those of the init process if
+    /// it inherited zombies.
     ///
     /// This method panics if the [`Process`] is the init process.
     pub fn exit(self: &Arc<Self>) {
+        self.exit_hooks.run();
+
         // TODO: child subreaper
         let reaper = INIT_PROC.get().unwrap();
 
@@ -214,15 +250,28 @@ impl Process {
             return;
         }
 
-        let mut children = self.children.lock(); // Acquire the lock first
-        self.is_zombie.store(true, Ordering::Release);
+        let mut inherited_zombie = false;
+        {
+            let mut children = self.children.write(); // Acquire the lock first
+            self.is_zombie.store(true, Ordering::Release);
 
-        let mut reaper_children = reaper.children.lock();
-        let reaper = Arc::downgrade(reaper);
+            let mut reaper_children = reaper.children.write();
+            let reaper = Arc::downgrade(reaper);
 
-        for (pid, child) in core::mem::take(&mut *children) {
-            *child.parent.lock() = reaper.clone();
-            reaper_children.insert(pid, child);
+            for (pid, child) in core::mem::take(&mut *children) {
+                *child.parent.lock() = reaper.clone();
+                inherited_zombie |= child.is_zombie();
+                reaper_children.insert(pid, child);
+            }
+        }
+
+        connector::notify(ConnectorEvent::Exit {
+            pid: self.pid,
+            status: ProcessEvent::from_exit_status(self.exit_code()),
+        });
+        self.notify_change();
+        if inherited_zombie {
+            wake_all(reaper.notifier.lock().child_waiters.take());
         }
     }
 
@@ -231,10 +280,149 @@ impl Process {
     /// This method panics if the [`Process`] is not a zombie.
     pub fn free(&self) {
         assert!(self.is_zombie(), "only zombie process can be freed");
+        self.is_reaped.store(true, Ordering::Release);
+
+        if let Some(parent) = self.parent() {
+            parent.children.write().remove(&self.pid);
+        }
+    }
+
+    /// Returns how the [`Process`] terminated, if it is a zombie not reaped
+    /// yet, without reaping it.
+    pub fn exit_event(&self) -> Option<ProcessEvent> {
+        (self.is_zombie() && !self.is_reaped.load(Ordering::Acquire))
+            .then(|| ProcessEvent::from_exit_status(self.exit_code()))
+    }
+
+    /// Reaps a zombie [`Process`], returning how it terminated and removing
+    /// it from its parent, which is the init process if the original parent
+    /// exited first.
+    ///
+    /// Returns `None` if the [`Process`] is not a zombie. The status is
+    /// handed out exactly once: when several waiters race for the same
+    /// zombie, all but one get `None`. The usage of the zombie, see
+    /// [`Process::total_usage`], is added to the children usage of the
+    /// parent.
+    pub fn reap(&self) -> Option<ProcessEvent> {
+        if !self.is_zombie() || self.is_reaped.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        if let Some(parent) = self.parent() {
+            parent.children.write().remove(&self.pid);
+            let usage = self.total_usage();
+            let mut accounting = parent.accounting.lock();
+            accounting.children = accounting.children.merge(usage);
+        }
+        Some(ProcessEvent::from_exit_status(self.exit_code()))
+    }
+}
+
+/// Resource usage
+impl Process {
+    /// Accounts the usage of an exiting thread to the [`Process`].
+    ///
+    /// Must be called before the last thread exits the [`Process`], so that
+    /// waiters woken by its exit find the complete usage.
+    pub fn account_exited(&self, usage: ResourceUsage) {
+        let mut accounting = self.accounting.lock();
+        accounting.exited = accounting.exited.merge(usage);
+    }
 
+    /// The usage of the threads that exited the [`Process`].
+    pub fn exited_usage(&self) -> ResourceUsage {
+        self.accounting.lock().exited
+    }
+
+    /// The usage of the reaped children of the [`Process`], including what
+    /// they had themselves reaped.
+    pub fn children_usage(&self) -> ResourceUsage {
+        self.accounting.lock().children
+    }
+
+    /// The usage of the exited threads of the [`Process`] and of its reaped
+    /// children, as reported for a zombie to its parent.
+    pub fn total_usage(&self) -> ResourceUsage {
+        let accounting = self.accounting.lock();
+        accounting.exited.merge(accounting.children)
+    }
+}
+
+/// State change notification
+impl Process {
+    /// Registers `waker` to be woken on the next state change of the
+    /// [`Process`]: its exit, stop or continue.
+    ///
+    /// Wakers are woken once; pollers register again each time they find no
+    /// change. To not miss a change, register before checking the state.
+    pub fn register_exit_waiter(&self, waker: &Waker) {
+        self.notifier.lock().waiters.register(waker);
+    }
+
+    /// Registers `waker` to be woken on the next state change of any child
+    /// of the [`Process`], as [`Process::register_exit_waiter`] does for the
+    /// [`Process`] itself.
+    pub fn register_child_waiter(&self, waker: &Waker) {
+        self.notifier.lock().child_waiters.register(waker);
+    }
+
+    /// Wakes the waiters of the [`Process`] and the child waiters of its
+    /// parent.
+    fn notify_change(&self) {
+        wake_all(self.notifier.lock().waiters.take());
         if let Some(parent) = self.parent() {
-            parent.children.lock().remove(&self.pid);
+            wake_all(parent.notifier.lock().child_waiters.take());
+        }
+    }
+
+    /// Records that the [`Process`] was stopped by `signo`.
+    ///
+    /// Returns `false`, and does nothing, if it was already stopped, so that
+    /// a stop is reported once however many threads take it.
+    pub fn stop(&self, signo: u32) -> bool {
+        {
+            let mut notifier = self.notifier.lock();
+            if notifier.stopped {
+                return false;
+            }
+            notifier.stopped = true;
+            notifier.job_event = Some(ProcessEvent::Stopped(signo));
+        }
+        self.notify_change();
+        true
+    }
+
+    /// Records that the [`Process`] was continued. Does nothing if it was not
+    /// stopped.
+    pub fn cont(&self) {
+        {
+            let mut notifier = self.notifier.lock();
+            if !notifier.stopped {
+                return;
+            }
+            notifier.stopped = false;
+            notifier.job_event = Some(ProcessEvent::Continued);
         }
+        self.notify_change();
+    }
+
+    /// Returns `true` if the [`Process`] is stopped by job control.
+    pub fn is_stopped(&self) -> bool {
+        self.notifier.lock().stopped
+    }
+
+    /// Returns the last stop or continue of the [`Process`] not reported yet
+    /// if `accept` takes it, consuming it if `consume` is set.
+    pub fn take_job_event(
+        &self,
+        consume: bool,
+        accept: impl FnOnce(&ProcessEvent) -> bool,
+    ) -> Option<ProcessEvent> {
+        let mut notifier = self.notifier.lock();
+        let event = notifier.job_event.filter(accept)?;
+        if consume {
+            notifier.job_event = None;
+        }
+        Some(event)
     }
 }
 
@@ -273,16 +461,20 @@ impl Process {
         let process = Arc::new(Process {
             pid,
             is_zombie: AtomicBool::new(false),
+            is_reaped: AtomicBool::new(false),
             tg: SpinNoIrq::new(ThreadGroup::default()),
-            children: SpinNoIrq::new(StrongMap::new()),
+            notifier: SpinNoIrq::new(Notifier::default()),
+            accounting: SpinNoIrq::new(Accounting::default()),
+            exit_hooks: ExitHooks::new(),
+            children: SpinRwNoIrq::new(StrongMap::new()),
             parent: SpinNoIrq::new(parent.as_ref().map(Arc::downgrade).unwrap_or_default()),
             group: SpinNoIrq::new(group.clone()),
         });
 
-        group.processes.lock().insert(pid, &process);
+        group.processes.write().insert(pid, &process);
 
         if let Some(parent) = parent {
-            parent.children.lock().insert(pid, process.clone());
+            parent.children.write().insert(pid, process.clone());
         } else if INIT_PROC.get().is_none() {
             INIT_PROC.init_once(process.clone());
         }
@@ -299,8 +491,16 @@ impl Process {
     }
 
     /// Creates a child [`Process`].
+    ///
+    /// Listeners of process events get the fork before anything else about
+    /// the child.
     pub fn fork(self: &Arc<Process>, pid: Pid) -> Arc<Process> {
-        Self::new(pid, Some(self.clone()))
+        let child = Self::new(pid, Some(self.clone()));
+        connector::notify(ConnectorEvent::Fork {
+            parent: self.pid,
+            child: pid,
+        });
+        child
     }
 }
 
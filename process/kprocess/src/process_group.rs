@@ -9,7 +9,7 @@ use alloc::{
 };
 use core::fmt;
 
-use kspin::SpinNoIrq;
+use kspin::SpinRwNoIrq;
 use weak_map::WeakMap;
 
 use crate::{Pid, Process, Session};
@@ -18,7 +18,7 @@ use crate::{Pid, Process, Session};
 pub struct ProcessGroup {
     pgid: Pid,
     pub(crate) session: Arc<Session>,
-    pub(crate) processes: SpinNoIrq<WeakMap<Pid, Weak<Process>>>,
+    pub(crate) processes: SpinRwNoIrq<WeakMap<Pid, Weak<Process>>>,
 }
 
 impl ProcessGroup {
@@ -27,9 +27,9 @@ impl ProcessGroup {
         let group = Arc::new(Self {
             pgid,
             session: session.clone(),
-            processes: SpinNoIrq::new(WeakMap::new()),
+            processes: SpinRwNoIrq::new(WeakMap::new()),
         });
-        session.process_groups.lock().insert(pgid, &group);
+        session.process_groups.write().insert(pgid, &group);
         group
     }
 }
@@ -47,7 +47,7 @@ impl ProcessGroup {
 
     /// The [`Process`]es that belong to this [`ProcessGroup`].
     pub fn processes(&self) -> Vec<Arc<Process>> {
-        self.processes.lock().values().collect()
+        self.processes.read().values().collect()
     }
 }
 
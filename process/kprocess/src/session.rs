@@ -9,7 +9,7 @@ use alloc::{
 };
 use core::{any::Any, fmt};
 
-use kspin::SpinNoIrq;
+use kspin::{SpinNoIrq, SpinRwNoIrq};
 use weak_map::WeakMap;
 
 use crate::{Pid, ProcessGroup};
@@ -17,7 +17,7 @@ use crate::{Pid, ProcessGroup};
 /// A [`Session`] is a collection of [`ProcessGroup`]s.
 pub struct Session {
     sid: Pid,
-    pub(crate) process_groups: SpinNoIrq<WeakMap<Pid, Weak<ProcessGroup>>>,
+    pub(crate) process_groups: SpinRwNoIrq<WeakMap<Pid, Weak<ProcessGroup>>>,
     terminal: SpinNoIrq<Option<Arc<dyn Any + Send + Sync>>>,
 }
 
@@ -26,7 +26,7 @@ impl Session {
     pub(crate) fn new(sid: Pid) -> Arc<Self> {
         Arc::new(Self {
             sid,
-            process_groups: SpinNoIrq::new(WeakMap::new()),
+            process_groups: SpinRwNoIrq::new(WeakMap::new()),
             terminal: SpinNoIrq::new(None),
         })
     }
@@ -40,7 +40,7 @@ impl Session {
 
     /// The [`ProcessGroup`]s that belong to this [`Session`].
     pub fn process_groups(&self) -> Vec<Arc<ProcessGroup>> {
-        self.process_groups.lock().values().collect()
+        self.process_groups.read().values().collect()
     }
 
     /// Sets the terminal for this session.
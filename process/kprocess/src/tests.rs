@@ -2,11 +2,27 @@
 
 #![cfg(unittest)]
 
-use alloc::sync::Arc;
+use alloc::{
+    collections::btree_set::BTreeSet,
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+    time::Duration,
+};
 
+use kspin::SpinNoIrq;
 use unittest::{assert, assert_eq, def_test};
 
-use crate::{Process, process::INIT_PROC};
+use crate::{
+    ConnectorEvent, ConnectorEvents, ConnectorListener, ExitRegistration, Process, ProcessEvent,
+    Reaped, ResourceUsage,
+    connector::{self, COMM_LEN},
+    process::INIT_PROC,
+};
 
 fn ensure_init() -> Arc<Process> {
     if let Some(p) = INIT_PROC.get() {
@@ -123,3 +139,508 @@ fn test_process_group_session() {
     p1_child.exit();
     p1_child.free();
 }
+
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    (counter.clone(), Waker::from(counter))
+}
+
+fn woken(counter: &CountingWaker) -> usize {
+    counter.0.load(Ordering::SeqCst)
+}
+
+/// Runs `process` to its exit with `status`.
+fn exit_with(process: &Arc<Process>, status: i32) {
+    process.add_thread(process.pid());
+    assert!(process.exit_thread(process.pid(), status));
+    process.exit();
+}
+
+#[def_test]
+fn test_exit_status_decoding() {
+    assert_eq!(
+        ProcessEvent::from_exit_status(3 << 8),
+        ProcessEvent::Exited(3)
+    );
+    assert_eq!(ProcessEvent::from_exit_status(9), ProcessEvent::Signaled(9));
+    assert_eq!(ProcessEvent::Exited(3).wait_status(), 3 << 8);
+    assert_eq!(ProcessEvent::Signaled(9).wait_status(), 9);
+    assert_eq!(
+        ProcessEvent::from_exit_status(128 + 11),
+        ProcessEvent::Dumped(11)
+    );
+    assert_eq!(ProcessEvent::Dumped(11).wait_status(), 0x80 | 11);
+    assert_eq!(ProcessEvent::Stopped(19).wait_status(), (19 << 8) | 0x7f);
+    assert_eq!(ProcessEvent::Continued.wait_status(), 0xffff);
+}
+
+#[def_test]
+fn test_exit_wakes_waiters() {
+    let init = ensure_init();
+    let parent = init.fork(300);
+    let child = parent.fork(301);
+
+    // Waiters register before checking, then the child exits.
+    let (pidfd, pidfd_waker) = counting_waker();
+    let (waitpid, waitpid_waker) = counting_waker();
+    child.register_exit_waiter(&pidfd_waker);
+    child.register_exit_waiter(&pidfd_waker);
+    parent.register_child_waiter(&waitpid_waker);
+    assert_eq!(child.exit_event(), None);
+
+    exit_with(&child, 7 << 8);
+    assert_eq!(woken(&pidfd), 1);
+    assert_eq!(woken(&waitpid), 1);
+
+    // Peeking leaves the status in place; reaping hands it out once.
+    assert_eq!(child.exit_event(), Some(ProcessEvent::Exited(7)));
+    assert_eq!(child.reap(), Some(ProcessEvent::Exited(7)));
+    assert_eq!(child.reap(), None);
+    assert_eq!(child.exit_event(), None);
+    assert!(parent.children().is_empty());
+
+    exit_with(&parent, 0);
+    assert_eq!(parent.reap(), Some(ProcessEvent::Exited(0)));
+}
+
+#[def_test]
+fn test_reap_races() {
+    let init = ensure_init();
+    let parent = init.fork(310);
+    let child = parent.fork(311);
+    assert_eq!(child.reap(), None);
+
+    exit_with(&child, 9);
+    let results = [child.reap(), child.reap()];
+    assert_eq!(results.iter().filter(|r| r.is_some()).count(), 1);
+    assert!(results.contains(&Some(ProcessEvent::Signaled(9))));
+
+    exit_with(&parent, 0);
+    parent.reap();
+}
+
+#[def_test]
+fn test_reparented_zombie_wakes_init() {
+    let init = ensure_init();
+    let parent = init.fork(320);
+    let child = parent.fork(321);
+    exit_with(&child, 1 << 8);
+
+    let (counter, waker) = counting_waker();
+    init.register_child_waiter(&waker);
+    exit_with(&parent, 0);
+    assert!(woken(&counter) >= 1);
+
+    // The zombie is now reaped by init.
+    assert!(init.children().iter().any(|c| c.pid() == 321));
+    assert_eq!(child.reap(), Some(ProcessEvent::Exited(1)));
+    assert!(!init.children().iter().any(|c| c.pid() == 321));
+    parent.reap();
+}
+
+#[def_test]
+fn test_children_in_group() {
+    let init = ensure_init();
+    let parent = init.fork(330);
+    let a = parent.fork(331);
+    let b = parent.fork(332);
+    let group = b.create_group().expect("Failed to create group");
+
+    let in_group = parent.children_in_group(group.pgid());
+    assert_eq!(in_group.len(), 1);
+    assert_eq!(in_group[0].pid(), 332);
+    let in_parent_group = parent.children_in_group(parent.group().pgid());
+    assert_eq!(in_parent_group.len(), 1);
+    assert_eq!(in_parent_group[0].pid(), a.pid());
+
+    for p in [&a, &b, &parent] {
+        exit_with(p, 0);
+        p.reap();
+    }
+}
+
+#[def_test]
+fn test_stop_and_continue() {
+    let init = ensure_init();
+    let parent = init.fork(340);
+    let child = parent.fork(341);
+    let any = |_: &ProcessEvent| true;
+
+    // Continuing a process that is not stopped reports nothing.
+    child.cont();
+    assert!(!child.is_stopped());
+    assert_eq!(child.take_job_event(true, any), None);
+
+    let (counter, waker) = counting_waker();
+    parent.register_child_waiter(&waker);
+    assert!(child.stop(19));
+    assert!(child.is_stopped());
+    assert_eq!(woken(&counter), 1);
+    // Another thread taking the stop reports nothing new.
+    assert!(!child.stop(20));
+    assert_eq!(woken(&counter), 1);
+
+    // Filtered out events stay, peeking leaves them, taking consumes them.
+    let continued = |e: &ProcessEvent| matches!(e, ProcessEvent::Continued);
+    assert_eq!(child.take_job_event(true, continued), None);
+    assert_eq!(
+        child.take_job_event(false, any),
+        Some(ProcessEvent::Stopped(19))
+    );
+    assert_eq!(
+        child.take_job_event(true, any),
+        Some(ProcessEvent::Stopped(19))
+    );
+    assert_eq!(child.take_job_event(true, any), None);
+
+    parent.register_child_waiter(&waker);
+    child.cont();
+    assert!(!child.is_stopped());
+    assert_eq!(woken(&counter), 2);
+    assert_eq!(
+        child.take_job_event(true, continued),
+        Some(ProcessEvent::Continued)
+    );
+
+    for p in [&child, &parent] {
+        exit_with(p, 0);
+        p.reap();
+    }
+}
+
+#[def_test]
+fn test_usage_of_reaped_children() {
+    let init = ensure_init();
+    let parent = init.fork(350);
+    let child = parent.fork(351);
+    let grandchild = child.fork(352);
+    let usage = |ms: u64, maxrss: u64| ResourceUsage {
+        utime: Duration::from_millis(ms),
+        stime: Duration::from_millis(ms / 2),
+        maxrss,
+    };
+
+    grandchild.account_exited(usage(10, 300));
+    exit_with(&grandchild, 0);
+    grandchild.reap();
+    assert_eq!(child.children_usage(), usage(10, 300));
+
+    // Each thread adds its times; the total includes reaped descendants.
+    child.account_exited(usage(20, 100));
+    child.account_exited(usage(40, 200));
+    assert_eq!(child.exited_usage(), usage(60, 200));
+    exit_with(&child, 0);
+    assert_eq!(child.total_usage(), usage(70, 300));
+
+    // Unreaped children are not accounted yet.
+    assert_eq!(parent.children_usage(), ResourceUsage::default());
+    child.reap();
+    assert_eq!(parent.children_usage(), usage(70, 300));
+    assert_eq!(parent.exited_usage(), ResourceUsage::default());
+
+    exit_with(&parent, 0);
+    parent.reap();
+}
+
+fn drain(listener: &ConnectorListener) -> Vec<ConnectorEvent> {
+    core::iter::from_fn(|| listener.pop()).collect()
+}
+
+#[def_test]
+fn test_connector_order_and_subtree() {
+    let init = ensure_init();
+    let watched = init.fork(360);
+    let listener = ConnectorListener::subscribe(ConnectorEvents::all(), Some(&watched), 64);
+    let other = init.fork(361);
+
+    let child = watched.fork(362);
+    let grandchild = child.fork(363);
+    // The grandchild stays in the subtree once reparented to init.
+    exit_with(&child, 1 << 8);
+    let comm = [b'x'; COMM_LEN];
+    connector::notify(ConnectorEvent::Exec { pid: 363, comm });
+    connector::notify(ConnectorEvent::Comm { pid: 361, comm });
+    exit_with(&grandchild, 9);
+    exit_with(&other, 0);
+
+    assert_eq!(
+        drain(&listener),
+        [
+            ConnectorEvent::Fork {
+                parent: 360,
+                child: 362
+            },
+            ConnectorEvent::Fork {
+                parent: 362,
+                child: 363
+            },
+            ConnectorEvent::Exit {
+                pid: 362,
+                status: ProcessEvent::Exited(1)
+            },
+            ConnectorEvent::Exec { pid: 363, comm },
+            ConnectorEvent::Exit {
+                pid: 363,
+                status: ProcessEvent::Signaled(9)
+            },
+        ]
+    );
+
+    for p in [&child, &grandchild, &other] {
+        p.reap();
+    }
+    exit_with(&watched, 0);
+    watched.reap();
+}
+
+#[def_test]
+fn test_connector_filter_overflow_and_drop() {
+    let init = ensure_init();
+    let listeners = connector::listener_count();
+    let root = init.fork(370);
+    let listener = ConnectorListener::subscribe(ConnectorEvents::EXIT, Some(&root), 3);
+    assert_eq!(connector::listener_count(), listeners + 1);
+    let (counter, waker) = counting_waker();
+    listener.register_waker(&waker);
+
+    let children: Vec<_> = (371..376).map(|pid| root.fork(pid)).collect();
+    assert!(!listener.has_events());
+    for child in &children {
+        exit_with(child, 0);
+    }
+    assert_eq!(woken(&counter), 1);
+
+    // The last slot counts what did not fit; later events follow it.
+    let late = root.fork(376);
+    for pid in [371, 372] {
+        assert_eq!(
+            listener.pop(),
+            Some(ConnectorEvent::Exit {
+                pid,
+                status: ProcessEvent::Exited(0)
+            })
+        );
+    }
+    exit_with(&late, 0);
+    assert_eq!(
+        drain(&listener),
+        [
+            ConnectorEvent::Overflow { lost: 3 },
+            ConnectorEvent::Exit {
+                pid: 376,
+                status: ProcessEvent::Exited(0)
+            },
+        ]
+    );
+
+    drop(listener);
+    assert_eq!(connector::listener_count(), listeners);
+    for child in children.iter().chain([&late]) {
+        child.reap();
+    }
+    exit_with(&root, 0);
+    root.reap();
+}
+
+#[def_test]
+fn test_connector_no_missed_exits() {
+    const ROUNDS: u32 = 2048;
+    let init = ensure_init();
+    let root = init.fork(20_000);
+    let listener = ConnectorListener::subscribe(
+        ConnectorEvents::FORK | ConnectorEvents::EXIT,
+        Some(&root),
+        256,
+    );
+
+    let mut forked = BTreeSet::new();
+    let mut exited = 0;
+    let mut check = |listener: &ConnectorListener| {
+        for event in drain(listener) {
+            match event {
+                ConnectorEvent::Fork { child, .. } => assert!(forked.insert(child)),
+                // Never before the fork.
+                ConnectorEvent::Exit { pid, .. } => {
+                    assert!(forked.remove(&pid));
+                    exited += 1;
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+    };
+    for round in 0..ROUNDS {
+        let pid = 20_001 + round * 2;
+        let child = root.fork(pid);
+        let grandchild = child.fork(pid + 1);
+        // Unrelated churn is filtered out.
+        let noise = init.fork(60_001 + round);
+        exit_with(&child, 0);
+        exit_with(&noise, 0);
+        exit_with(&grandchild, 0);
+        for p in [&child, &noise, &grandchild] {
+            p.reap();
+        }
+        if round % 16 == 15 {
+            check(&listener);
+        }
+    }
+    check(&listener);
+    assert_eq!(exited, ROUNDS * 2);
+    assert!(forked.is_empty());
+
+    drop(listener);
+    exit_with(&root, 0);
+    root.reap();
+}
+
+fn counting_hook(counter: &Arc<AtomicUsize>) -> impl FnOnce() + Send + 'static {
+    let counter = counter.clone();
+    move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[def_test]
+fn test_exit_hooks_run_once() {
+    let init = ensure_init();
+    let process = init.fork(30_000);
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    let kept = process.exit_hooks().register("test", counting_hook(&ran));
+    let dropped = process.exit_hooks().register("test", counting_hook(&ran));
+    process
+        .exit_hooks()
+        .register("test", counting_hook(&ran))
+        .detach();
+    assert_eq!(process.exit_hooks().len(), 3);
+    // Torn down before the exit: removed, never run.
+    drop(dropped);
+    assert_eq!(process.exit_hooks().len(), 2);
+    assert!(kept.is_pending());
+
+    exit_with(&process, 0);
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+    assert!(process.exit_hooks().is_empty());
+    assert!(!kept.is_pending());
+
+    // Too late: runs right away.
+    let late = process.exit_hooks().register("test", counting_hook(&ran));
+    assert_eq!(ran.load(Ordering::SeqCst), 3);
+    assert!(!late.is_pending());
+    drop(kept);
+    process.reap();
+}
+
+/// A subsystem whose objects must not outlive the process that made them.
+#[derive(Default)]
+struct Armed(SpinNoIrq<Vec<Arc<AtomicUsize>>>);
+
+impl Armed {
+    fn arm(self: &Arc<Self>, process: &Process) -> (Weak<AtomicUsize>, ExitRegistration) {
+        let object = Arc::new(AtomicUsize::new(0));
+        self.0.lock().push(object.clone());
+        let armed = self.clone();
+        let registration = process
+            .exit_hooks()
+            .register_object("armed", &object, move |object| {
+                armed.disarm(&Arc::downgrade(&object));
+                Reaped::Cancelled
+            });
+        (Arc::downgrade(&object), registration)
+    }
+
+    fn disarm(&self, object: &Weak<AtomicUsize>) {
+        self.0
+            .lock()
+            .retain(|it| Arc::as_ptr(it) != object.as_ptr());
+    }
+}
+
+#[def_test]
+fn test_exit_hooks_stress() {
+    const ROUNDS: u32 = 512;
+    let init = ensure_init();
+    let armed = Arc::new(Armed::default());
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move |bound: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % bound
+    };
+
+    for round in 0..ROUNDS {
+        let process = init.fork(30_001 + round);
+        let ran = Arc::new(AtomicUsize::new(0));
+        let shared = Arc::new(AtomicUsize::new(0));
+        let mut timers = Vec::new();
+        let mut watches = Vec::new();
+        let mut expected = 0;
+        let exit_at = random(33);
+        for op in 0..32 {
+            if op == exit_at {
+                exit_with(&process, 0);
+                assert!(process.exit_hooks().is_empty());
+                assert!(armed.0.lock().is_empty());
+                assert_eq!(ran.load(Ordering::SeqCst), expected);
+            }
+            let exited = op >= exit_at;
+            match random(4) {
+                // A timer, disarmed at random before the exit.
+                0 => {
+                    let (object, registration) = armed.arm(&process);
+                    timers.push((object, registration));
+                }
+                1 if !timers.is_empty() => {
+                    let (object, registration) =
+                        timers.swap_remove(random(timers.len() as u64) as _);
+                    armed.disarm(&object);
+                    drop(registration);
+                }
+                // A watch, some of them removed before the exit.
+                2 => {
+                    watches.push(process.exit_hooks().register("watch", counting_hook(&ran)));
+                    if exited {
+                        assert!(ran.load(Ordering::SeqCst) > expected);
+                    }
+                    expected += 1;
+                    if random(2) == 0 {
+                        let watch = watches.swap_remove(random(watches.len() as u64) as _);
+                        if watch.is_pending() {
+                            expected -= 1;
+                        }
+                    }
+                }
+                // Shared state, orphaned and still alive after the exit.
+                _ => {
+                    process
+                        .exit_hooks()
+                        .register_object("shared", &shared, |shared| {
+                            shared.fetch_add(1, Ordering::SeqCst);
+                            Reaped::Orphaned
+                        })
+                        .detach();
+                }
+            }
+            if exited {
+                assert!(armed.0.lock().is_empty());
+            }
+        }
+        if exit_at >= 32 {
+            exit_with(&process, 0);
+        }
+        assert!(armed.0.lock().is_empty());
+        assert!(timers.iter().all(|(object, _)| object.strong_count() == 0));
+        assert!(process.exit_hooks().is_empty());
+        assert_eq!(ran.load(Ordering::SeqCst), expected);
+        process.reap();
+    }
+}
@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Resource usage accounting.
+use core::time::Duration;
+
+/// Resource usage of a process, as reported by `getrusage` and `wait4`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Time spent in user mode.
+    pub utime: Duration,
+    /// Time spent in kernel mode.
+    pub stime: Duration,
+    /// Largest resident set size, in KiB.
+    pub maxrss: u64,
+}
+
+impl ResourceUsage {
+    /// Adds the usage `other` to this one: times add up, the largest resident
+    /// set size is kept.
+    pub fn merge(self, other: ResourceUsage) -> Self {
+        Self {
+            utime: self.utime + other.utime,
+            stime: self.stime + other.stime,
+            maxrss: self.maxrss.max(other.maxrss),
+        }
+    }
+}
+
+/// Usage accounted to a process.
+#[derive(Default)]
+pub(crate) struct Accounting {
+    /// Usage of the threads that exited.
+    pub(crate) exited: ResourceUsage,
+    /// Usage of the reaped children and their own reaped descendants.
+    pub(crate) children: ResourceUsage,
+}
@@ -10,14 +10,15 @@ use alloc::{
 use core::{
     array,
     ops::{Index, IndexMut},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 use kspin::SpinNoIrq;
 
 use crate::{
-    DefaultSignalAction, MAX_SIGNALS, PendingSignals, SignalAction, SignalActionFlags,
-    SignalDisposition, SignalInfo, SignalSet, Signo, api::ThreadSignalManager,
+    DEFAULT_SIGPENDING_LIMIT, DefaultSignalAction, MAX_SIGNALS, PendingSignals, SignalAction,
+    SignalActionFlags, SignalDisposition, SignalInfo, SignalQueueFull, SignalSet, Signo,
+    api::ThreadSignalManager,
 };
 
 /// Container for signal actions across all supported signals.
@@ -66,6 +67,11 @@ pub struct ProcessSignalManager {
 
     /// Fast path indicator for pending signals
     pub(crate) has_pending: AtomicBool,
+
+    /// Real-time signal instances queued to the process and its threads
+    queued: AtomicUsize,
+    /// Limit on `queued` for signals queued with [`Self::queue_signal`]
+    queue_limit: AtomicUsize,
 }
 
 impl ProcessSignalManager {
@@ -81,7 +87,49 @@ impl ProcessSignalManager {
             default_restorer,
             children: SpinNoIrq::new(Vec::new()),
             has_pending: AtomicBool::new(false),
+            queued: AtomicUsize::new(0),
+            queue_limit: AtomicUsize::new(DEFAULT_SIGPENDING_LIMIT),
+        }
+    }
+
+    /// Returns the number of real-time signal instances queued to the process
+    /// and its threads.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Sets the number of real-time signal instances that may be queued,
+    /// mirroring `RLIMIT_SIGPENDING`.
+    pub fn set_queue_limit(&self, limit: usize) {
+        self.queue_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Accounts for `sig` about to be queued. Only real-time signals are
+    /// counted, and the limit is ignored unless `limited`.
+    pub(crate) fn reserve_queued(
+        &self,
+        sig: &SignalInfo,
+        limited: bool,
+    ) -> Result<(), SignalQueueFull> {
+        if !sig.signo().is_realtime() {
+            return Ok(());
+        }
+        if !limited {
+            self.queued.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
         }
+        let limit = self.queue_limit.load(Ordering::Relaxed);
+        self.queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < limit).then_some(n + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| SignalQueueFull)
+    }
+
+    /// Accounts for `count` real-time signal instances leaving the queues.
+    pub(crate) fn release_queued(&self, count: usize) {
+        self.queued.fetch_sub(count, Ordering::Relaxed);
     }
 
     /// Dequeues the next pending signal that matches the given mask.
@@ -94,6 +142,9 @@ impl ProcessSignalManager {
     pub(crate) fn dequeue_signal(&self, mask: &SignalSet) -> Option<SignalInfo> {
         let mut pending_guard = self.pending.lock();
         let signal = pending_guard.dequeue_signal(mask);
+        if signal.as_ref().is_some_and(|sig| sig.signo().is_realtime()) {
+            self.release_queued(1);
+        }
 
         // Update fast path indicator
         if pending_guard.set.is_empty() {
@@ -133,38 +184,95 @@ impl ProcessSignalManager {
     /// `Some(tid)` if a specific thread should handle the signal, `None` otherwise
     #[must_use]
     pub fn send_signal(&self, sig: SignalInfo) -> Option<u32> {
+        self.put_signal(sig, false).unwrap_or(None)
+    }
+
+    /// Queues a signal to the process, as `rt_sigqueueinfo` does.
+    ///
+    /// Unlike [`Self::send_signal`], fails if `sig` is a real-time signal and
+    /// the process already has as many queued as the limit set with
+    /// [`Self::set_queue_limit`] allows.
+    pub fn queue_signal(&self, sig: SignalInfo) -> Result<Option<u32>, SignalQueueFull> {
+        self.put_signal(sig, true)
+    }
+
+    fn put_signal(&self, sig: SignalInfo, limited: bool) -> Result<Option<u32>, SignalQueueFull> {
         let signo = sig.signo();
+        self.prepare_signal(signo);
 
         // Check if signal should be ignored
         if self.signal_ignored(signo) {
-            return None;
+            return Ok(None);
         }
 
         // Add to pending signals
+        self.reserve_queued(&sig, limited)?;
         if self.pending.lock().put_signal(sig) {
             self.has_pending.store(true, Ordering::Release);
         }
 
         // Find a thread that can handle this signal
-        self.find_target_thread(signo)
+        Ok(self.find_target_thread(signo))
     }
 
-    /// Finds a suitable thread to handle the given signal.
-    fn find_target_thread(&self, signo: Signo) -> Option<u32> {
-        let mut target_tid = None;
-
-        self.children.lock().retain(|(tid, thread_weak)| {
-            if let Some(thread) = thread_weak.upgrade() {
-                if target_tid.is_none() && !thread.signal_blocked(signo) {
-                    target_tid = Some(*tid);
-                }
-                true // Keep this thread reference
-            } else {
-                false // Remove dead thread reference
+    /// Applies the side effects `signo` has on the whole thread group as
+    /// soon as it is sent, even if it is ignored or blocked.
+    ///
+    /// `SIGCONT` discards the pending stop signals, and a stop signal
+    /// discards a pending `SIGCONT`.
+    pub(crate) fn prepare_signal(&self, signo: Signo) {
+        let discard = |signo: Signo| {
+            let discarded = self.pending.lock().discard(signo);
+            self.release_queued(discarded);
+            for (_, thread) in self.live_threads() {
+                thread.discard_pending(signo);
+            }
+        };
+        if signo == Signo::SIGCONT {
+            for stop in [
+                Signo::SIGSTOP,
+                Signo::SIGTSTP,
+                Signo::SIGTTIN,
+                Signo::SIGTTOU,
+            ] {
+                discard(stop);
             }
+        } else if signo.is_stop() {
+            discard(Signo::SIGCONT);
+        }
+    }
+
+    /// Returns the signal managers of the threads still alive, dropping the
+    /// references to the others.
+    fn live_threads(&self) -> Vec<(u32, Arc<ThreadSignalManager>)> {
+        let mut threads = Vec::new();
+        self.children.lock().retain(|(tid, thread)| {
+            let Some(thread) = thread.upgrade() else {
+                return false;
+            };
+            threads.push((*tid, thread));
+            true
         });
+        threads
+    }
 
-        target_tid
+    /// Returns the threads of the process with their blocked signals, in the
+    /// order they were created.
+    pub fn thread_masks(&self) -> Vec<(u32, SignalSet)> {
+        self.live_threads()
+            .into_iter()
+            .map(|(tid, thread)| (tid, thread.blocked()))
+            .collect()
+    }
+
+    /// Finds a thread to deliver a process-directed `signo`: the first one
+    /// not blocking it, or none if all do, leaving the signal pending until
+    /// a thread unblocks it.
+    fn find_target_thread(&self, signo: Signo) -> Option<u32> {
+        self.thread_masks()
+            .into_iter()
+            .find(|(_, blocked)| !blocked.has(signo))
+            .map(|(tid, _)| tid)
     }
 
     /// Gets currently pending signals.
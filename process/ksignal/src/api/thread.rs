@@ -12,12 +12,14 @@ use core::{
 
 use kcpu::userspace::UserContext;
 use kspin::SpinNoIrq;
+use linux_raw_sys::general::SS_AUTODISARM;
 use osvm::VirtMutPtr;
 
 use super::ProcessSignalManager;
 use crate::{
     DefaultSignalAction, PendingSignals, SignalAction, SignalActionFlags, SignalDisposition,
-    SignalInfo, SignalOSAction, SignalSet, SignalStack, Signo, arch::UContext,
+    SignalInfo, SignalOSAction, SignalQueueFull, SignalSet, SignalStack, SignalStackError, Signo,
+    arch::UContext,
 };
 
 struct SignalFrame {
@@ -26,6 +28,30 @@ struct SignalFrame {
     uctx: UserContext,
 }
 
+/// A syscall interrupted by a signal, to run again once the signal is dealt
+/// with.
+///
+/// It is dropped instead if a handler without `SA_RESTART` runs for the
+/// signal, leaving the syscall failed with `EINTR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallRestart {
+    /// Address of the syscall instruction.
+    pub ip: usize,
+    /// The syscall number.
+    pub sysno: usize,
+    /// The first argument, which the return value may have overwritten.
+    pub arg0: usize,
+}
+
+impl SyscallRestart {
+    /// Rewinds `uctx` to issue the syscall again.
+    pub fn apply(&self, uctx: &mut UserContext) {
+        uctx.set_ip(self.ip);
+        uctx.set_sysno(self.sysno);
+        uctx.set_arg0(self.arg0);
+    }
+}
+
 /// Thread-level signal manager.
 pub struct ThreadSignalManager {
     /// The process-level signal manager
@@ -37,6 +63,8 @@ pub struct ThreadSignalManager {
     blocked: SpinNoIrq<SignalSet>,
     /// The stack used by signal handlers
     stack: SpinNoIrq<SignalStack>,
+    /// The interrupted syscall to restart, if any
+    restart: SpinNoIrq<Option<SyscallRestart>>,
 
     possibly_has_signal: AtomicBool,
 }
@@ -50,6 +78,7 @@ impl ThreadSignalManager {
             pending: SpinNoIrq::new(PendingSignals::default()),
             blocked: SpinNoIrq::new(SignalSet::default()),
             stack: SpinNoIrq::new(SignalStack::default()),
+            restart: SpinNoIrq::new(None),
 
             possibly_has_signal: AtomicBool::new(false),
         });
@@ -60,12 +89,19 @@ impl ThreadSignalManager {
     /// Dequeues a signal from the thread's pending signals.
     #[must_use]
     pub fn dequeue_signal(&self, mask: &SignalSet) -> Option<SignalInfo> {
-        self.pending
-            .lock()
-            .dequeue_signal(mask)
+        self.dequeue_thread_signal(mask)
             .or_else(|| self.proc.dequeue_signal(mask))
     }
 
+    /// Dequeues a signal sent to this thread only.
+    fn dequeue_thread_signal(&self, mask: &SignalSet) -> Option<SignalInfo> {
+        let sig = self.pending.lock().dequeue_signal(mask)?;
+        if sig.signo().is_realtime() {
+            self.proc.release_queued(1);
+        }
+        Some(sig)
+    }
+
     /// Returns the owning process signal manager.
     pub fn process(&self) -> &Arc<ProcessSignalManager> {
         &self.proc
@@ -91,21 +127,56 @@ impl ThreadSignalManager {
             },
             SignalDisposition::Ignore => None,
             SignalDisposition::Handler(handler) => {
+                // The frame saves the context the handler returns to.
+                if let Some(restart) = self.restart.lock().take()
+                    && action.flags.contains(SignalActionFlags::RESTART)
+                {
+                    restart.apply(uctx);
+                }
+
                 let layout = Layout::new::<SignalFrame>();
-                let stack = self.stack.lock();
-                let sp = if stack.disabled() || !action.flags.contains(SignalActionFlags::ONSTACK) {
-                    uctx.sp()
-                } else {
-                    stack.sp + stack.size
-                };
+                let mut stack = self.stack.lock();
+                let sp =
+                    stack.handler_sp(uctx.sp(), action.flags.contains(SignalActionFlags::ONSTACK));
+                let stack_status = stack.status(uctx.sp());
+                if stack.flags & SS_AUTODISARM != 0 {
+                    // Disarmed until `sigreturn` restores it from the frame,
+                    // so a handler can switch away from the stack safely.
+                    *stack = SignalStack::default();
+                }
                 drop(stack);
 
+                #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+                if uctx.is_aarch32() {
+                    let restorer = action.restorer.map_or(
+                        self.proc.default_restorer + crate::arch::COMPAT_TRAMPOLINE_OFFSET,
+                        |f| f as _,
+                    );
+                    if crate::arch::push_compat_frame(
+                        uctx,
+                        sp,
+                        stack_status,
+                        restore_blocked,
+                        sig,
+                        handler as usize,
+                        restorer,
+                    )
+                    .is_err()
+                    {
+                        return Some(SignalOSAction::CoreDump);
+                    }
+                    self.enter_handler(signo, action);
+                    return Some(SignalOSAction::Handler);
+                }
+
+                let mut ucontext = UContext::new(uctx, restore_blocked);
+                ucontext.stack = stack_status;
                 let aligned_sp = (sp - layout.size()) & !(layout.align() - 1);
 
                 let frame_ptr = aligned_sp as *mut SignalFrame;
                 if frame_ptr
                     .write_vm(SignalFrame {
-                        ucontext: UContext::new(uctx, restore_blocked),
+                        ucontext,
                         siginfo: sig.clone(),
                         uctx: *uctx,
                     })
@@ -134,20 +205,25 @@ impl ThreadSignalManager {
                 #[cfg(not(target_arch = "x86_64"))]
                 uctx.set_ra(restorer);
 
-                let mut add_blocked = action.mask;
-                if !action.flags.contains(SignalActionFlags::NODEFER) {
-                    add_blocked.add(signo);
-                }
-
-                if action.flags.contains(SignalActionFlags::RESETHAND) {
-                    self.proc.actions.lock()[signo] = SignalAction::default();
-                }
-                *self.blocked.lock() |= add_blocked;
+                self.enter_handler(signo, action);
                 Some(SignalOSAction::Handler)
             }
         }
     }
 
+    /// Blocks the signals `action` asks to while its handler of `signo` runs.
+    fn enter_handler(&self, signo: Signo, action: &SignalAction) {
+        let mut add_blocked = action.mask;
+        if !action.flags.contains(SignalActionFlags::NODEFER) {
+            add_blocked.add(signo);
+        }
+
+        if action.flags.contains(SignalActionFlags::RESETHAND) {
+            self.proc.actions.lock()[signo] = SignalAction::default();
+        }
+        *self.blocked.lock() |= add_blocked;
+    }
+
     #[cold]
     fn check_signals_slow(
         &self,
@@ -160,7 +236,7 @@ impl ThreadSignalManager {
         drop(blocked);
 
         loop {
-            let sig = match self.pending.lock().dequeue_signal(&mask) {
+            let sig = match self.dequeue_thread_signal(&mask) {
                 Some(sig) => Some(sig),
                 None => {
                     self.possibly_has_signal.store(false, Ordering::Release);
@@ -197,14 +273,34 @@ impl ThreadSignalManager {
     /// Restores the signal frame. Called by `sigreturn`.
     /// Restore user context from the signal frame during `sigreturn`.
     pub fn restore(&self, uctx: &mut UserContext) {
+        #[cfg(all(target_arch = "aarch64", feature = "compat"))]
+        if uctx.is_aarch32() {
+            match crate::arch::restore_compat_frame(uctx) {
+                Ok((stack, sigmask)) => self.restore_state(uctx, &stack, sigmask),
+                Err(_) => {
+                    // Like Linux, a bad frame kills the thread.
+                    let _ = self.send_signal(SignalInfo::new_kernel(Signo::SIGSEGV));
+                }
+            }
+            return;
+        }
+
         let frame_ptr = uctx.sp() as *const SignalFrame;
         // SAFETY: pointer is valid
         let frame = unsafe { &*frame_ptr };
 
         *uctx = frame.uctx;
         frame.ucontext.mcontext.restore(uctx);
+        self.restore_state(uctx, &frame.ucontext.stack, frame.ucontext.sigmask);
+    }
+
+    fn restore_state(&self, uctx: &UserContext, stack: &SignalStack, sigmask: SignalSet) {
+        // Like Linux, the signal stack saved in the frame is set again, which
+        // re-arms an `SS_AUTODISARM` stack. This fails harmlessly when
+        // returning to a handler still running on the current stack.
+        let _ = self.stack.lock().replace(stack, uctx.sp());
 
-        *self.blocked.lock() = frame.ucontext.sigmask;
+        *self.blocked.lock() = sigmask;
         self.possibly_has_signal.store(true, Ordering::Release);
     }
 
@@ -216,15 +312,36 @@ impl ThreadSignalManager {
     /// See [`ProcessSignalManager::send_signal`] for the process-level version.
     #[must_use]
     pub fn send_signal(&self, sig: SignalInfo) -> bool {
+        self.put_signal(sig, false).unwrap_or(false)
+    }
+
+    /// Queues a signal to the thread, as `rt_tgsigqueueinfo` does.
+    ///
+    /// Fails if `sig` is a real-time signal and the process already has as
+    /// many queued as its limit allows. See
+    /// [`ProcessSignalManager::queue_signal`].
+    pub fn queue_signal(&self, sig: SignalInfo) -> Result<bool, SignalQueueFull> {
+        self.put_signal(sig, true)
+    }
+
+    fn put_signal(&self, sig: SignalInfo, limited: bool) -> Result<bool, SignalQueueFull> {
         let signo = sig.signo();
+        self.proc.prepare_signal(signo);
         if self.proc.signal_ignored(signo) {
-            return false;
+            return Ok(false);
         }
 
+        self.proc.reserve_queued(&sig, limited)?;
         if self.pending.lock().put_signal(sig) {
             self.possibly_has_signal.store(true, Ordering::Release);
         }
-        !self.signal_blocked(signo)
+        Ok(!self.signal_blocked(signo))
+    }
+
+    /// Discards the instances of `signo` pending for this thread only.
+    pub(crate) fn discard_pending(&self, signo: Signo) {
+        let discarded = self.pending.lock().discard(signo);
+        self.proc.release_queued(discarded);
     }
 
     /// Gets the blocked signals.
@@ -252,15 +369,32 @@ impl ThreadSignalManager {
     }
 
     /// Gets the signal stack.
-    /// Returns the signal handler stack configuration.
-    pub fn stack(&self) -> SignalStack {
-        self.stack.lock().clone()
+    /// Returns the signal handler stack configuration, as seen by the thread
+    /// running at `sp`.
+    pub fn stack(&self, sp: usize) -> SignalStack {
+        self.stack.lock().status(sp)
     }
 
     /// Sets the signal stack.
-    /// Sets the signal handler stack configuration.
-    pub fn set_stack(&self, stack: SignalStack) {
-        *self.stack.lock() = stack;
+    /// Sets the signal handler stack configuration for the thread running at
+    /// `sp`, refusing to change it while the thread runs on it.
+    pub fn set_stack(&self, stack: &SignalStack, sp: usize) -> Result<(), SignalStackError> {
+        self.stack.lock().replace(stack, sp)
+    }
+
+    /// Records that the syscall just run was interrupted and may be
+    /// restarted.
+    ///
+    /// A handler run for a signal with `SA_RESTART` restarts it once the
+    /// handler returns, one without drops it. If no handler runs, the caller
+    /// takes it back with [`Self::take_syscall_restart`] and restarts it.
+    pub fn set_syscall_restart(&self, restart: SyscallRestart) {
+        *self.restart.lock() = Some(restart);
+    }
+
+    /// Takes the syscall to restart left by [`Self::set_syscall_restart`].
+    pub fn take_syscall_restart(&self) -> Option<SyscallRestart> {
+        self.restart.lock().take()
     }
 
     /// Gets current pending signals.
@@ -269,3 +403,10 @@ impl ThreadSignalManager {
         self.pending.lock().set | self.proc.pending()
     }
 }
+
+impl Drop for ThreadSignalManager {
+    fn drop(&mut self) {
+        // Signals left to a thread that is gone are never delivered.
+        self.proc.release_queued(self.pending.get_mut().queued());
+    }
+}
@@ -16,6 +16,12 @@ signal_trampoline:
     mov x8, #139
     svc #0
 
+// AArch32 `rt_sigreturn`, at `COMPAT_TRAMPOLINE_OFFSET`:
+// `mov r7, #173; svc #0` in ARM state.
+.fill 0x800 - (. - signal_trampoline), 1, 0
+    .word 0xe3a070ad
+    .word 0xef000000
+
 .fill 4096 - (. - signal_trampoline), 1, 0
 "
 );
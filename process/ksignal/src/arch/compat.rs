@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Signal frames of 32-bit ARM (AArch32) user space.
+//!
+//! The layout is the one of the ARM EABI `struct rt_sigframe`: a 32-bit
+//! `siginfo_t` followed by a 32-bit `ucontext_t`. The VFP registers are not
+//! saved in `uc_regspace`, as the native frame does not save the FP/SIMD
+//! registers either.
+use core::mem::{offset_of, size_of};
+
+use kcpu::userspace::UserContext;
+use linux_raw_sys::general::{
+    SI_KERNEL, SI_USER, SIGBUS, SIGCHLD, SIGFPE, SIGILL, SIGPOLL, SIGSEGV, SIGSYS, SIGTRAP,
+};
+use osvm::{MemResult, VirtMutPtr, VirtPtr};
+
+use crate::{SignalInfo, SignalSet, SignalStack};
+
+/// Offset of the AArch32 `rt_sigreturn` code in the trampoline page.
+pub const COMPAT_TRAMPOLINE_OFFSET: usize = 0x800;
+
+/// The `sigaltstack` of AArch32 user space.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompatSignalStack {
+    pub sp: u32,
+    pub flags: u32,
+    pub size: u32,
+}
+
+impl From<CompatSignalStack> for SignalStack {
+    fn from(value: CompatSignalStack) -> Self {
+        SignalStack {
+            sp: value.sp as usize,
+            flags: value.flags,
+            size: value.size as usize,
+        }
+    }
+}
+
+impl From<SignalStack> for CompatSignalStack {
+    fn from(value: SignalStack) -> Self {
+        CompatSignalStack {
+            sp: value.sp as u32,
+            flags: value.flags,
+            size: value.size as u32,
+        }
+    }
+}
+
+/// The 32-bit `siginfo_t`.
+///
+/// The members of the `_sifields` union are packed to 32-bit words, so
+/// which member is valid has to be known to convert it. Like Linux, this is
+/// told by the signal code and number.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CompatSignalInfo([u32; 32]);
+
+/// Byte offset of `_sifields` in the native `siginfo_t`.
+const NATIVE_FIELDS: usize = 16;
+/// Word index of `_sifields` in the 32-bit `siginfo_t`.
+const COMPAT_FIELDS: usize = 3;
+
+impl From<&SignalInfo> for CompatSignalInfo {
+    fn from(sig: &SignalInfo) -> Self {
+        // SAFETY: `siginfo_t` is 128 bytes of plain data.
+        let raw = unsafe { core::mem::transmute_copy::<_, [u8; 128]>(&sig.0) };
+        let word = |off: usize| u32::from_ne_bytes(raw[off..off + 4].try_into().unwrap());
+        let field = |off: usize| word(NATIVE_FIELDS + off);
+
+        let mut info = [0u32; 32];
+        let signo = sig.signo() as u32;
+        let code = sig.code();
+        info[0] = signo;
+        info[1] = word(4);
+        info[2] = code as u32;
+        let fields = &mut info[COMPAT_FIELDS..];
+        if code < 0 {
+            // `_rt` and `_timer` both end with the `sigval` at word 2.
+            fields[..3].copy_from_slice(&[field(0), field(4), field(8)]);
+        } else if code == SI_USER as i32 || code == SI_KERNEL as i32 {
+            fields[..2].copy_from_slice(&[field(0), field(4)]);
+        } else {
+            match signo {
+                SIGCHLD => {
+                    // The clock counts are `long`s, and truncated.
+                    fields[..5].copy_from_slice(&[
+                        field(0),
+                        field(4),
+                        field(8),
+                        field(16),
+                        field(24),
+                    ]);
+                }
+                SIGILL | SIGFPE | SIGSEGV | SIGBUS | SIGTRAP => fields[0] = field(0),
+                SIGPOLL => fields[..2].copy_from_slice(&[field(0), field(8)]),
+                SIGSYS => fields[..3].copy_from_slice(&[field(0), field(8), field(12)]),
+                _ => fields[..2].copy_from_slice(&[field(0), field(4)]),
+            }
+        }
+        CompatSignalInfo(info)
+    }
+}
+
+/// The AArch32 `struct sigcontext`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompatMContext {
+    trap_no: u32,
+    error_code: u32,
+    oldmask: u32,
+    /// R0-R15, with R13 the stack pointer and R15 the program counter.
+    regs: [u32; 16],
+    cpsr: u32,
+    fault_address: u32,
+}
+
+/// The AArch32 `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompatUContext {
+    flags: u32,
+    link: u32,
+    stack: CompatSignalStack,
+    mcontext: CompatMContext,
+    sigmask: SignalSet,
+    __unused: [u32; 30],
+    regspace: [u64; 64],
+}
+
+/// The AArch32 `struct rt_sigframe`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CompatSignalFrame {
+    info: CompatSignalInfo,
+    ucontext: CompatUContext,
+}
+
+const _: () = {
+    assert!(size_of::<CompatSignalInfo>() == 128);
+    assert!(size_of::<CompatMContext>() == 84);
+    assert!(offset_of!(CompatUContext, sigmask) == 104);
+    assert!(offset_of!(CompatUContext, regspace) == 232);
+    assert!(offset_of!(CompatSignalFrame, ucontext) == 128);
+};
+
+/// Pushes the frame of a handler of `sig` on the stack below `sp`, and sets
+/// up `uctx` to run `handler`, returning to `restorer`.
+///
+/// `stack` is the signal stack saved in the frame, and `sigmask` the
+/// blocked signals restored by `rt_sigreturn`.
+pub fn push_compat_frame(
+    uctx: &mut UserContext,
+    sp: usize,
+    stack: SignalStack,
+    sigmask: SignalSet,
+    sig: &SignalInfo,
+    handler: usize,
+    restorer: usize,
+) -> MemResult {
+    let mut regs = [0u32; 16];
+    for (reg, x) in regs.iter_mut().zip(&uctx.x[..15]) {
+        *reg = *x as u32;
+    }
+    regs[15] = uctx.elr as u32;
+    let frame = CompatSignalFrame {
+        info: sig.into(),
+        ucontext: CompatUContext {
+            flags: 0,
+            link: 0,
+            stack: stack.into(),
+            mcontext: CompatMContext {
+                trap_no: 0,
+                error_code: 0,
+                oldmask: 0,
+                regs,
+                cpsr: uctx.spsr as u32,
+                fault_address: 0,
+            },
+            sigmask,
+            __unused: [0; 30],
+            regspace: [0; 64],
+        },
+    };
+
+    let frame_sp = (sp - size_of::<CompatSignalFrame>()) & !7;
+    (frame_sp as *mut CompatSignalFrame).write_vm(frame)?;
+
+    uctx.x[0] = sig.signo() as u64;
+    uctx.x[1] = (frame_sp + offset_of!(CompatSignalFrame, info)) as u64;
+    uctx.x[2] = (frame_sp + offset_of!(CompatSignalFrame, ucontext)) as u64;
+    uctx.set_sp(frame_sp);
+    uctx.set_ra(restorer);
+    // The handler may be Thumb code, telling so by the low bit.
+    uctx.set_compat_ip(handler);
+    Ok(())
+}
+
+/// Restores `uctx` from the frame `rt_sigreturn` finds at the stack
+/// pointer, and returns the signal stack and blocked signals saved in it.
+pub fn restore_compat_frame(uctx: &mut UserContext) -> MemResult<(SignalStack, SignalSet)> {
+    // SAFETY: the frame is plain data.
+    let frame = unsafe {
+        (uctx.sp() as *const CompatSignalFrame)
+            .read_uninit()?
+            .assume_init()
+    };
+    let mcontext = &frame.ucontext.mcontext;
+    for (x, reg) in uctx.x[..15].iter_mut().zip(&mcontext.regs) {
+        *x = *reg as u64;
+    }
+    uctx.elr = mcontext.regs[15] as u64;
+    // Only the flags user space may change are taken from the frame.
+    uctx.set_compat_cpsr(mcontext.cpsr);
+    Ok((frame.ucontext.stack.into(), frame.ucontext.sigmask))
+}
@@ -13,6 +13,10 @@ cfg_if::cfg_if! {
     } else if #[cfg(target_arch = "aarch64")]{
         mod aarch64;
         pub use self::aarch64::*;
+        #[cfg(feature = "compat")]
+        mod compat;
+        #[cfg(feature = "compat")]
+        pub use self::compat::*;
     } else if #[cfg(target_arch = "loongarch64")] {
         mod loongarch64;
         pub use self::loongarch64::*;
@@ -6,7 +6,16 @@
 use alloc::{boxed::Box, collections::vec_deque::VecDeque};
 use core::array;
 
-use crate::{MAX_SIGNALS, SignalInfo, SignalSet};
+use crate::{MAX_SIGNALS, SignalInfo, SignalSet, Signo};
+
+/// Default number of real-time signals a process may have queued, for
+/// `RLIMIT_SIGPENDING`.
+pub const DEFAULT_SIGPENDING_LIMIT: usize = 4096;
+
+/// Error returned when queueing a real-time signal to a process that already
+/// has as many queued as its limit allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalQueueFull;
 
 /// Queue for managing pending signals awaiting delivery.
 ///
@@ -64,6 +73,28 @@ impl PendingSignals {
         true
     }
 
+    /// Discards every pending instance of `signo`, returning how many
+    /// real-time instances were queued.
+    pub fn discard(&mut self, signo: Signo) -> usize {
+        if !self.set.remove(signo) {
+            return 0;
+        }
+        if signo.is_realtime() {
+            let queue = &mut self.info_rt[signo as usize - 32];
+            let count = queue.len();
+            queue.clear();
+            count
+        } else {
+            self.info_std[signo as usize] = None;
+            0
+        }
+    }
+
+    /// Returns the number of queued real-time signal instances.
+    pub fn queued(&self) -> usize {
+        self.info_rt.iter().map(VecDeque::len).sum()
+    }
+
     /// Dequeues the next pending signal contained in `mask`, if any.
     pub fn dequeue_signal(&mut self, mask: &SignalSet) -> Option<SignalInfo> {
         self.set.dequeue(mask).and_then(|signo| {
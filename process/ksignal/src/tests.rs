@@ -2,9 +2,17 @@
 
 #![cfg(unittest)]
 
+use alloc::{sync::Arc, vec::Vec};
+
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::{MINSIGSTKSZ, SS_AUTODISARM, SS_DISABLE, SS_ONSTACK};
 use unittest::{assert, assert_eq, def_test};
 
-use crate::{DefaultSignalAction, PendingSignals, SignalInfo, SignalSet, Signo};
+use crate::{
+    DefaultSignalAction, PendingSignals, SignalInfo, SignalQueueFull, SignalSet, SignalStack,
+    SignalStackError, Signo,
+    api::{ProcessSignalManager, SignalActions, ThreadSignalManager},
+};
 
 #[def_test]
 fn test_signo_properties() {
@@ -127,3 +135,254 @@ fn test_pending_signals_rt() {
     let d3 = pending.dequeue_signal(&mask);
     assert!(d3.is_none());
 }
+
+fn queued_info(signo: Signo, value: usize) -> SignalInfo {
+    let mut sig = SignalInfo::new_user(signo, -1, 100);
+    sig.set_value(value);
+    sig
+}
+
+fn signal_managers() -> (Arc<ProcessSignalManager>, Arc<ThreadSignalManager>) {
+    let proc = Arc::new(ProcessSignalManager::new(
+        Arc::new(SpinNoIrq::new(SignalActions::default())),
+        0,
+    ));
+    let thread = ThreadSignalManager::new(1, proc.clone());
+    (proc, thread)
+}
+
+#[def_test]
+fn test_rt_signal_queue_order() {
+    let (proc, thread) = signal_managers();
+    let mut rt = SignalSet::default();
+    rt.add(Signo::SIGRTMIN);
+    thread.set_blocked(rt);
+
+    // Instances alternate between the process and the thread queues.
+    for value in 1..=4 {
+        let sig = queued_info(Signo::SIGRTMIN, value);
+        if value % 2 == 1 {
+            assert_eq!(proc.queue_signal(sig), Ok(None));
+        } else {
+            assert_eq!(thread.queue_signal(sig), Ok(false));
+        }
+    }
+    assert_eq!(proc.queued(), 4);
+    assert!(thread.pending().has(Signo::SIGRTMIN));
+
+    thread.set_blocked(SignalSet::default());
+    let mut values = Vec::new();
+    while let Some(sig) = thread.dequeue_signal(&rt) {
+        assert_eq!(sig.signo(), Signo::SIGRTMIN);
+        values.push(sig.value());
+    }
+    // Thread-directed instances come first, each queue in FIFO order.
+    assert_eq!(values, [2, 4, 1, 3]);
+    assert_eq!(proc.queued(), 0);
+    assert!(!thread.pending().has(Signo::SIGRTMIN));
+}
+
+#[def_test]
+fn test_rt_signal_queue_limit() {
+    let (proc, thread) = signal_managers();
+    proc.set_queue_limit(2);
+
+    assert!(proc.queue_signal(queued_info(Signo::SIGRTMIN, 1)).is_ok());
+    assert!(thread.queue_signal(queued_info(Signo::SIGRT1, 2)).is_ok());
+    assert_eq!(
+        proc.queue_signal(queued_info(Signo::SIGRTMIN, 3)),
+        Err(SignalQueueFull)
+    );
+    assert_eq!(
+        thread.queue_signal(queued_info(Signo::SIGRT1, 3)),
+        Err(SignalQueueFull)
+    );
+
+    // Standard signals are not counted, and kernel signals override the limit.
+    assert!(
+        proc.queue_signal(SignalInfo::new_kernel(Signo::SIGUSR1))
+            .is_ok()
+    );
+    let _ = proc.send_signal(SignalInfo::new_kernel(Signo::SIGRTMIN));
+    assert_eq!(proc.queued(), 3);
+
+    // Dropping a thread releases the instances queued to it.
+    drop(thread);
+    assert_eq!(proc.queued(), 2);
+}
+
+fn blocking(signo: Signo) -> SignalSet {
+    let mut set = SignalSet::default();
+    set.add(signo);
+    set
+}
+
+#[def_test]
+fn test_group_signal_targeting() {
+    let (proc, first) = signal_managers();
+    let second = ThreadSignalManager::new(2, proc.clone());
+    first.set_blocked(blocking(Signo::SIGUSR1));
+    second.set_blocked(blocking(Signo::SIGUSR2));
+    assert_eq!(
+        proc.thread_masks(),
+        [(1, blocking(Signo::SIGUSR1)), (2, blocking(Signo::SIGUSR2))]
+    );
+
+    // A process-directed signal wakes a thread not blocking it, and stays
+    // shared until one of them dequeues it.
+    assert_eq!(
+        proc.send_signal(SignalInfo::new_kernel(Signo::SIGUSR1)),
+        Some(2)
+    );
+    assert_eq!(
+        proc.send_signal(SignalInfo::new_kernel(Signo::SIGUSR2)),
+        Some(1)
+    );
+    assert!(proc.pending().has(Signo::SIGUSR1));
+    assert!(
+        second
+            .dequeue_signal(&!SignalSet::default())
+            .is_some_and(|sig| sig.signo() == Signo::SIGUSR1)
+    );
+    assert!(!proc.pending().has(Signo::SIGUSR1));
+
+    // A thread-directed signal is pending for that thread only.
+    assert!(!first.send_signal(SignalInfo::new_kernel(Signo::SIGUSR1)));
+    assert!(first.pending().has(Signo::SIGUSR1));
+    assert!(!second.pending().has(Signo::SIGUSR1));
+
+    // Once every thread blocks a signal it is left pending for the process.
+    second.set_blocked(blocking(Signo::SIGUSR1));
+    assert_eq!(
+        proc.send_signal(SignalInfo::new_kernel(Signo::SIGUSR1)),
+        None
+    );
+    assert!(proc.pending().has(Signo::SIGUSR1));
+
+    drop(second);
+    assert_eq!(proc.thread_masks(), [(1, blocking(Signo::SIGUSR1))]);
+}
+
+#[def_test]
+fn test_sigcont_discards_stop_signals() {
+    let (proc, first) = signal_managers();
+    let second = ThreadSignalManager::new(2, proc.clone());
+    first.set_blocked(blocking(Signo::SIGTSTP));
+    second.set_blocked(blocking(Signo::SIGTSTP));
+
+    let _ = proc.send_signal(SignalInfo::new_kernel(Signo::SIGTSTP));
+    let _ = second.send_signal(SignalInfo::new_kernel(Signo::SIGTTIN));
+    assert!(proc.pending().has(Signo::SIGTSTP));
+    assert!(second.pending().has(Signo::SIGTTIN));
+
+    let _ = first.send_signal(SignalInfo::new_kernel(Signo::SIGCONT));
+    assert!(!proc.pending().has(Signo::SIGTSTP));
+    assert!(!second.pending().has(Signo::SIGTTIN));
+    assert!(first.pending().has(Signo::SIGCONT));
+
+    // And a stop signal discards a pending SIGCONT.
+    let _ = proc.send_signal(SignalInfo::new_kernel(Signo::SIGSTOP));
+    assert!(!first.pending().has(Signo::SIGCONT));
+}
+
+const ALT_BASE: usize = 0x1000_0000;
+const ALT_SIZE: usize = 0x4000;
+const USER_SP: usize = 0x7fff_f000;
+
+fn alt_stack(flags: u32) -> SignalStack {
+    let mut stack = SignalStack::default();
+    let new = SignalStack {
+        sp: ALT_BASE,
+        flags,
+        size: ALT_SIZE,
+    };
+    assert_eq!(stack.replace(&new, USER_SP), Ok(()));
+    stack
+}
+
+#[def_test]
+fn test_signal_stack_nested_frames() {
+    let stack = alt_stack(0);
+    assert!(!stack.on_stack(USER_SP));
+    assert_eq!(stack.status(USER_SP).flags, 0);
+
+    // Without SA_ONSTACK the frame stays on the current stack.
+    assert_eq!(stack.handler_sp(USER_SP, false), USER_SP);
+
+    // The first signal switches to the top of the alternate stack.
+    let outer_sp = stack.handler_sp(USER_SP, true);
+    assert_eq!(outer_sp, ALT_BASE + ALT_SIZE);
+
+    // A signal nested in the handler, whose frame is below `outer_sp`, goes
+    // below it instead of overwriting it from the top again.
+    let handler_sp = outer_sp - 0x400;
+    assert!(stack.on_stack(handler_sp));
+    assert_eq!(stack.status(handler_sp).flags, SS_ONSTACK);
+    assert_eq!(stack.handler_sp(handler_sp, true), handler_sp);
+    assert_eq!(stack.handler_sp(handler_sp, false), handler_sp);
+
+    // The base is outside, the top inside.
+    assert!(!stack.on_stack(ALT_BASE));
+    assert!(stack.on_stack(ALT_BASE + ALT_SIZE));
+}
+
+#[def_test]
+fn test_signal_stack_replace() {
+    let mut stack = alt_stack(0);
+    let other = SignalStack {
+        sp: 0x2000_0000,
+        flags: 0,
+        size: ALT_SIZE,
+    };
+
+    // Refused while running on it.
+    assert_eq!(
+        stack.replace(&other, ALT_BASE + 0x100),
+        Err(SignalStackError::OnStack)
+    );
+    assert_eq!(stack.sp, ALT_BASE);
+
+    let small = SignalStack {
+        size: MINSIGSTKSZ as usize - 1,
+        ..other.clone()
+    };
+    assert_eq!(
+        stack.replace(&small, USER_SP),
+        Err(SignalStackError::TooSmall)
+    );
+    let bad_flags = SignalStack {
+        flags: 0x40,
+        ..other.clone()
+    };
+    assert_eq!(
+        stack.replace(&bad_flags, USER_SP),
+        Err(SignalStackError::InvalidFlags)
+    );
+
+    // `SS_ONSTACK` as returned by a query is accepted as 0.
+    let onstack = SignalStack {
+        flags: SS_ONSTACK,
+        ..other.clone()
+    };
+    assert_eq!(stack.replace(&onstack, USER_SP), Ok(()));
+    assert_eq!(stack, other);
+
+    let disable = SignalStack {
+        flags: SS_DISABLE,
+        ..other
+    };
+    assert_eq!(stack.replace(&disable, USER_SP), Ok(()));
+    assert!(stack.disabled());
+    assert_eq!(stack.status(USER_SP).flags, SS_DISABLE);
+    assert_eq!(stack.handler_sp(USER_SP, true), USER_SP);
+}
+
+#[def_test]
+fn test_signal_stack_autodisarm() {
+    let stack = alt_stack(SS_AUTODISARM);
+    assert_eq!(stack.handler_sp(USER_SP, true), ALT_BASE + ALT_SIZE);
+    // The stack is disarmed while a handler runs on it, so it is not in use
+    // and can be replaced from the handler.
+    assert!(!stack.on_stack(ALT_BASE + 0x100));
+    assert_eq!(stack.status(ALT_BASE + 0x100).flags, SS_AUTODISARM);
+}
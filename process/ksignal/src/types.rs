@@ -6,7 +6,9 @@
 use core::{fmt, mem};
 
 use derive_more::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
-use linux_raw_sys::general::{SI_KERNEL, SS_DISABLE, kernel_sigset_t, siginfo_t};
+use linux_raw_sys::general::{
+    MINSIGSTKSZ, SI_KERNEL, SS_AUTODISARM, SS_DISABLE, SS_ONSTACK, kernel_sigset_t, siginfo_t,
+};
 use strum::{EnumIter, FromRepr, IntoEnumIterator};
 
 use crate::DefaultSignalAction;
@@ -90,6 +92,14 @@ impl Signo {
         *self >= Signo::SIGRTMIN
     }
 
+    /// Returns `true` for the signals whose default action stops the process.
+    pub fn is_stop(&self) -> bool {
+        matches!(
+            self,
+            Signo::SIGSTOP | Signo::SIGTSTP | Signo::SIGTTIN | Signo::SIGTTOU
+        )
+    }
+
     /// Returns the default action for this signal.
     pub fn default_action(&self) -> DefaultSignalAction {
         match self {
@@ -130,7 +140,7 @@ impl Signo {
 }
 
 /// Signal set. Compatible with `struct sigset_t` in libc.
-#[derive(Default, Clone, Copy, Not, BitOr, BitOrAssign, BitAnd, BitAndAssign)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Not, BitOr, BitOrAssign, BitAnd, BitAndAssign)]
 #[repr(transparent)]
 pub struct SignalSet(u64);
 
@@ -239,6 +249,21 @@ impl SignalInfo {
         result
     }
 
+    /// Construct the `SIGCHLD` reporting a change of state of the child
+    /// `pid`: `code` is one of the `CLD_*` codes and `status` the exit code
+    /// or the signal number it describes.
+    pub fn new_child(code: i32, pid: u32, status: i32) -> Self {
+        let mut result = Self::new_user(Signo::SIGCHLD, code, pid);
+        result
+            .0
+            .__bindgen_anon_1
+            .__bindgen_anon_1
+            ._sifields
+            ._sigchld
+            ._status = status;
+        result
+    }
+
     /// Returns the signal number.
     pub fn signo(&self) -> Signo {
         unsafe { Signo::from_repr(self.0.__bindgen_anon_1.__bindgen_anon_1.si_signo as _).unwrap() }
@@ -259,6 +284,32 @@ impl SignalInfo {
         self.0.__bindgen_anon_1.__bindgen_anon_1.si_code = code;
     }
 
+    /// Returns the `si_value` payload given to `sigqueue`.
+    pub fn value(&self) -> usize {
+        // SAFETY: Both members of `sigval` are plain data, and the pointer one
+        // covers the integer one.
+        unsafe {
+            self.0
+                .__bindgen_anon_1
+                .__bindgen_anon_1
+                ._sifields
+                ._rt
+                ._sigval
+                .sival_ptr as usize
+        }
+    }
+
+    /// Updates the `si_value` payload.
+    pub fn set_value(&mut self, value: usize) {
+        self.0
+            .__bindgen_anon_1
+            .__bindgen_anon_1
+            ._sifields
+            ._rt
+            ._sigval
+            .sival_ptr = value as _;
+    }
+
     /// Returns the stored errno value.
     pub fn errno(&self) -> i32 {
         // SAFETY: The union layout matches Linux's siginfo_t definition. bindgen keeps this layout,
@@ -281,7 +332,7 @@ impl fmt::Debug for SignalInfo {
 
 /// Signal stack. Compatible with `struct sigaltstack` in libc.
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SignalStack {
     pub sp: usize,
     pub flags: u32,
@@ -298,9 +349,86 @@ impl Default for SignalStack {
     }
 }
 
+/// Why `sigaltstack` refused a new signal stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalStackError {
+    /// The thread is running on the current signal stack (`EPERM`).
+    OnStack,
+    /// The flags are not valid (`EINVAL`).
+    InvalidFlags,
+    /// The stack is smaller than `MINSIGSTKSZ` (`ENOMEM`).
+    TooSmall,
+}
+
 impl SignalStack {
     /// Checks if signal stack is disabled.
     pub fn disabled(&self) -> bool {
-        self.flags == SS_DISABLE
+        self.flags & SS_DISABLE != 0
+    }
+
+    /// Checks if `sp` points into the signal stack.
+    ///
+    /// A stack with `SS_AUTODISARM` is disabled as soon as a handler starts
+    /// on it, so it never counts as in use.
+    pub fn on_stack(&self, sp: usize) -> bool {
+        !self.disabled()
+            && self.flags & SS_AUTODISARM == 0
+            && sp > self.sp
+            && sp - self.sp <= self.size
+    }
+
+    /// Returns the signal stack as reported to a thread running at `sp`,
+    /// with `SS_ONSTACK` set if it is in use.
+    pub fn status(&self, sp: usize) -> SignalStack {
+        let mode = if self.on_stack(sp) {
+            SS_ONSTACK
+        } else if self.disabled() {
+            SS_DISABLE
+        } else {
+            0
+        };
+        SignalStack {
+            sp: self.sp,
+            flags: mode | (self.flags & SS_AUTODISARM),
+            size: self.size,
+        }
+    }
+
+    /// Returns the stack pointer below which the frame of a signal delivered
+    /// at `sp` goes.
+    ///
+    /// This is the top of the signal stack if the action asked for it with
+    /// `SA_ONSTACK` and the thread is not already running on it, and `sp`
+    /// otherwise, so nested signals stack up below each other.
+    pub fn handler_sp(&self, sp: usize, onstack: bool) -> usize {
+        if onstack && !self.disabled() && !self.on_stack(sp) {
+            self.sp + self.size
+        } else {
+            sp
+        }
+    }
+
+    /// Replaces the signal stack with `new` for a thread running at `sp`,
+    /// with the checks of `sigaltstack`.
+    pub fn replace(&mut self, new: &SignalStack, sp: usize) -> Result<(), SignalStackError> {
+        if self.on_stack(sp) {
+            return Err(SignalStackError::OnStack);
+        }
+        *self = match new.flags & !SS_AUTODISARM {
+            SS_DISABLE => SignalStack::default(),
+            // `SS_ONSTACK` is accepted for compatibility and means 0.
+            0 | SS_ONSTACK => {
+                if new.size < MINSIGSTKSZ as usize {
+                    return Err(SignalStackError::TooSmall);
+                }
+                SignalStack {
+                    sp: new.sp,
+                    flags: new.flags & SS_AUTODISARM,
+                    size: new.size,
+                }
+            }
+            _ => return Err(SignalStackError::InvalidFlags),
+        };
+        Ok(())
     }
 }
@@ -4,7 +4,8 @@
 
 //! Allocation helpers for loading user memory into heap buffers.
 extern crate alloc;
-use alloc::vec::Vec;
+use alloc::{ffi::CString, vec::Vec};
+use core::mem::MaybeUninit;
 
 use bytemuck::{AnyBitPattern, Pod, bytes_of, zeroed};
 
@@ -67,3 +68,109 @@ pub fn load_vec_until_null<T: Pod>(p: *const T) -> MemResult<Vec<T>> {
     }
     Ok(res)
 }
+
+/// Strings loaded from a null-terminated array of string pointers.
+#[derive(Debug, Default)]
+pub struct StrArray {
+    /// The strings, in array order.
+    pub strings: Vec<CString>,
+    /// The bytes taken by the strings, NUL terminators included.
+    pub total_bytes: usize,
+}
+
+/// Load a null-terminated array of pointers to NUL-terminated strings, such
+/// as the `argv` and `envp` of `execve`.
+///
+/// Fails with [`MemError::NameTooLong`] if the array holds more than
+/// `max_entries` strings, if a string is longer than the per-string limit, or
+/// if the strings take more than `max_total_bytes` in all.
+pub fn load_str_array(
+    p: *const *const u8,
+    max_entries: usize,
+    max_total_bytes: usize,
+) -> MemResult<StrArray> {
+    load_str_array_with(&mut MemImpl::new(), p, max_entries, max_total_bytes)
+}
+
+pub(crate) fn load_str_array_with(
+    io: &mut impl VirtMemIo,
+    p: *const *const u8,
+    max_entries: usize,
+    max_total_bytes: usize,
+) -> MemResult<StrArray> {
+    if !p.is_aligned() {
+        return Err(MemError::InvalidAddr);
+    }
+
+    let mut res = StrArray::default();
+    loop {
+        let addr = res
+            .strings
+            .len()
+            .checked_mul(size_of::<*const u8>())
+            .and_then(|offset| p.addr().checked_add(offset))
+            .ok_or(MemError::InvalidAddr)?;
+        let mut ptr = MaybeUninit::<*const u8>::uninit();
+        io.read_mem(addr, ptr.as_bytes_mut())?;
+        // SAFETY: Any bit pattern is a valid pointer.
+        let ptr = unsafe { ptr.assume_init() };
+        if ptr.is_null() {
+            break;
+        }
+        if res.strings.len() == max_entries {
+            return Err(MemError::NameTooLong);
+        }
+
+        let s = load_cstr_with(io, ptr, LIMIT.min(max_total_bytes - res.total_bytes))?;
+        res.total_bytes += s.as_bytes_with_nul().len();
+        res.strings.push(s);
+    }
+    Ok(res)
+}
+
+/// Load a NUL-terminated string taking at most `max_bytes`, terminator
+/// included, such as a path.
+///
+/// Fails with [`MemError::NameTooLong`] if there is no terminator within
+/// `max_bytes`. Being a [`CString`], what is loaded cannot hold a NUL.
+pub fn load_cstr(p: *const u8, max_bytes: usize) -> MemResult<CString> {
+    load_cstr_with(&mut MemImpl::new(), p, max_bytes)
+}
+
+/// Load a NUL-terminated string taking at most `max_bytes`, terminator
+/// included.
+pub(crate) fn load_cstr_with(
+    io: &mut impl VirtMemIo,
+    p: *const u8,
+    max_bytes: usize,
+) -> MemResult<CString> {
+    let mut res = Vec::new();
+    loop {
+        const BATCH: usize = 32;
+
+        if res.len() == max_bytes {
+            return Err(MemError::NameTooLong);
+        }
+        // Batches are aligned so that none crosses a page boundary: a string
+        // ending just before an unmapped page is loaded fine.
+        let base = p
+            .addr()
+            .checked_add(res.len())
+            .ok_or(MemError::InvalidAddr)?;
+        let n = ((base + 1).next_multiple_of(BATCH) - base).min(max_bytes - res.len());
+
+        res.reserve(n);
+        let dst = &mut res.spare_capacity_mut()[..n];
+        io.read_mem(base, dst)?;
+
+        let slc = unsafe { dst.assume_init_ref() };
+        let idx = slc.iter().position(|&b| b == 0);
+
+        unsafe { res.set_len(res.len() + idx.map_or(n, |idx| idx + 1)) };
+        if idx.is_some() {
+            break;
+        }
+    }
+    // SAFETY: The only NUL in `res` is the one ending it.
+    Ok(unsafe { CString::from_vec_with_nul_unchecked(res) })
+}
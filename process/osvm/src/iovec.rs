@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Scatter-gather buffers described by user `iovec` arrays.
+extern crate alloc;
+use alloc::vec::Vec;
+use core::{mem::MaybeUninit, ops::Range};
+
+use bytemuck::AnyBitPattern;
+
+use crate::{MemError, MemImpl, MemResult, VirtMemIo, read_partial_with, write_partial_with};
+
+/// Maximum number of segments in an `iovec` array.
+pub const IOV_MAX: usize = 1024;
+
+/// A user `struct iovec`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AnyBitPattern)]
+pub struct IoVec {
+    /// Base address of the segment.
+    pub iov_base: *mut u8,
+    /// Length of the segment in bytes.
+    pub iov_len: isize,
+}
+
+/// A user buffer made of the segments of an `iovec` array.
+///
+/// The array is loaded and validated once, so later changes to it by user
+/// space have no effect. [`IoVecBuffer::read_to`] and
+/// [`IoVecBuffer::write_from`] go through the segments in order, each call
+/// picking up where the previous one stopped.
+pub struct IoVecBuffer {
+    /// The non-empty segments.
+    segs: Vec<(*mut u8, usize)>,
+    total_len: usize,
+    /// Position of the next transfer: segment index and offset in it.
+    seg: usize,
+    offset: usize,
+    remaining: usize,
+}
+
+impl IoVecBuffer {
+    /// Loads the `iovcnt` segments at `iov`.
+    ///
+    /// Fails with [`MemError::InvalidInput`] if there are more than
+    /// [`IOV_MAX`] segments, if a length is negative or if the lengths add up
+    /// to more than `isize::MAX`, and with [`MemError::InvalidAddr`] if a
+    /// segment wraps around the address space.
+    pub fn from_user(iov: *const IoVec, iovcnt: usize) -> MemResult<Self> {
+        Self::from_user_with(&mut MemImpl::new(), iov, iovcnt)
+    }
+
+    pub(crate) fn from_user_with(
+        io: &mut impl VirtMemIo,
+        iov: *const IoVec,
+        iovcnt: usize,
+    ) -> MemResult<Self> {
+        if iovcnt > IOV_MAX {
+            return Err(MemError::InvalidInput);
+        }
+        let mut iovs = Vec::with_capacity(iovcnt);
+        if iovcnt > 0 {
+            let dst = &mut iovs.spare_capacity_mut()[..iovcnt];
+            if read_partial_with(io, iov, dst)? < size_of_val(dst) {
+                return Err(MemError::NoAccess);
+            }
+            // SAFETY: We have just initialized `iovcnt` elements.
+            unsafe { iovs.set_len(iovcnt) };
+        }
+
+        let mut segs = Vec::with_capacity(iovcnt);
+        let mut total_len = 0usize;
+        for iov in iovs {
+            let len = usize::try_from(iov.iov_len).map_err(|_| MemError::InvalidInput)?;
+            if len == 0 {
+                continue;
+            }
+            if iov.iov_base.addr().checked_add(len).is_none() {
+                return Err(MemError::InvalidAddr);
+            }
+            total_len = total_len
+                .checked_add(len)
+                .filter(|&total| total <= isize::MAX as usize)
+                .ok_or(MemError::InvalidInput)?;
+            segs.push((iov.iov_base, len));
+        }
+        Ok(Self {
+            segs,
+            total_len,
+            seg: 0,
+            offset: 0,
+            remaining: total_len,
+        })
+    }
+
+    /// Returns the length of all segments together.
+    pub fn total_len(&self) -> usize {
+        self.total_len
+    }
+
+    /// Returns the number of bytes left to transfer.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Returns the non-empty segments, as base and length.
+    pub fn iter(&self) -> impl Iterator<Item = (*mut u8, usize)> + '_ {
+        self.segs.iter().copied()
+    }
+
+    /// Copies the next bytes of the segments to `buf`.
+    ///
+    /// Returns the number of bytes copied, which is short at the end of the
+    /// segments or at a fault after some progress. Fails if a fault prevents
+    /// any progress.
+    pub fn read_to(&mut self, buf: &mut [u8]) -> MemResult<usize> {
+        self.read_to_with(&mut MemImpl::new(), buf)
+    }
+
+    /// Copies `buf` to the next bytes of the segments.
+    ///
+    /// Returns the number of bytes copied, as [`IoVecBuffer::read_to`].
+    pub fn write_from(&mut self, buf: &[u8]) -> MemResult<usize> {
+        self.write_from_with(&mut MemImpl::new(), buf)
+    }
+
+    pub(crate) fn read_to_with(
+        &mut self,
+        io: &mut impl VirtMemIo,
+        buf: &mut [u8],
+    ) -> MemResult<usize> {
+        // SAFETY: Initialized bytes are valid `MaybeUninit<u8>`s, and only
+        // initialized bytes are written back.
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        self.transfer(buf.len(), |p, range| {
+            read_partial_with(io, p.cast_const(), &mut buf[range])
+        })
+    }
+
+    pub(crate) fn write_from_with(
+        &mut self,
+        io: &mut impl VirtMemIo,
+        buf: &[u8],
+    ) -> MemResult<usize> {
+        self.transfer(buf.len(), |p, range| write_partial_with(io, p, &buf[range]))
+    }
+
+    /// Transfers up to `len` bytes, calling `f` with the user address and the
+    /// range of the kernel buffer for each piece.
+    fn transfer(
+        &mut self,
+        len: usize,
+        mut f: impl FnMut(*mut u8, Range<usize>) -> MemResult<usize>,
+    ) -> MemResult<usize> {
+        let mut count = 0;
+        while count < len && self.seg < self.segs.len() {
+            let (base, seg_len) = self.segs[self.seg];
+            let n = (seg_len - self.offset).min(len - count);
+            let done = match f(base.wrapping_add(self.offset), count..count + n) {
+                Ok(done) => done,
+                // Report what was transferred before the fault.
+                Err(_) if count > 0 => break,
+                Err(err) => return Err(err),
+            };
+            count += done;
+            self.remaining -= done;
+            self.offset += done;
+            if self.offset == seg_len {
+                self.seg += 1;
+                self.offset = 0;
+            }
+            if done < n {
+                break;
+            }
+        }
+        Ok(count)
+    }
+}
@@ -20,6 +20,8 @@ pub enum MemError {
     NoAccess,
     #[cfg(feature = "alloc")]
     NameTooLong,
+    #[cfg(feature = "alloc")]
+    InvalidInput,
 }
 
 impl From<MemError> for KError {
@@ -28,6 +30,8 @@ impl From<MemError> for KError {
             MemError::InvalidAddr | MemError::NoAccess => KError::BadAddress,
             #[cfg(feature = "alloc")]
             MemError::NameTooLong => KError::NameTooLong,
+            #[cfg(feature = "alloc")]
+            MemError::InvalidInput => KError::InvalidInput,
         }
     }
 }
@@ -41,23 +45,110 @@ pub unsafe trait VirtMemIo: 'static {
     fn new() -> Self;
     fn read_mem(&mut self, addr: usize, out: &mut [MaybeUninit<u8>]) -> MemResult;
     fn write_mem(&mut self, addr: usize, src: &[u8]) -> MemResult;
+
+    /// Reads as much of `out` as possible, stopping at the first fault.
+    ///
+    /// Returns the number of bytes read, and fails only if `out` is not
+    /// empty and no byte could be read. The default implementation is all or
+    /// nothing.
+    fn read_mem_partial(&mut self, addr: usize, out: &mut [MaybeUninit<u8>]) -> MemResult<usize> {
+        self.read_mem(addr, out).map(|()| out.len())
+    }
+
+    /// Writes as much of `src` as possible, stopping at the first fault.
+    ///
+    /// Returns the number of bytes written, and fails only if `src` is not
+    /// empty and no byte could be written. The default implementation is all
+    /// or nothing.
+    fn write_mem_partial(&mut self, addr: usize, src: &[u8]) -> MemResult<usize> {
+        self.write_mem(addr, src).map(|()| src.len())
+    }
 }
 
-/// Read virtual memory into an uninitialized buffer.
-pub fn read_vm_mem<T>(p: *const T, out: &mut [MaybeUninit<T>]) -> MemResult {
+/// Rounds the `bytes` transferred for a slice of `T` down to whole elements,
+/// failing if not a single element of the `len` bytes requested made it.
+fn whole_elements<T>(bytes: usize, len: usize) -> MemResult<usize> {
+    let bytes = bytes - bytes % size_of::<T>().max(1);
+    if bytes == 0 && len != 0 {
+        Err(MemError::NoAccess)
+    } else {
+        Ok(bytes)
+    }
+}
+
+fn read_partial_with<T>(
+    io: &mut impl VirtMemIo,
+    p: *const T,
+    out: &mut [MaybeUninit<T>],
+) -> MemResult<usize> {
     if !p.is_aligned() {
         return Err(MemError::InvalidAddr);
     }
-    MemImpl::new().read_mem(p.addr(), out.as_bytes_mut())
+    let out = out.as_bytes_mut();
+    whole_elements::<T>(io.read_mem_partial(p.addr(), out)?, out.len())
 }
 
-/// Write a typed slice to virtual memory.
-pub fn write_vm_mem<T>(p: *mut T, src: &[T]) -> MemResult {
+fn write_partial_with<T>(io: &mut impl VirtMemIo, p: *mut T, src: &[T]) -> MemResult<usize> {
     if !p.is_aligned() {
         return Err(MemError::InvalidAddr);
     }
     let bytes = unsafe { slice::from_raw_parts(src.as_ptr().cast::<u8>(), size_of_val(src)) };
-    MemImpl::new().write_mem(p.addr(), bytes)
+    whole_elements::<T>(io.write_mem_partial(p.addr(), bytes)?, bytes.len())
+}
+
+/// Read virtual memory into an uninitialized buffer.
+pub fn read_vm_mem<T>(p: *const T, out: &mut [MaybeUninit<T>]) -> MemResult {
+    let len = size_of_val(out);
+    if read_vm_mem_partial(p, out)? < len {
+        return Err(MemError::NoAccess);
+    }
+    Ok(())
+}
+
+/// Read virtual memory into an uninitialized buffer, up to the first fault.
+///
+/// Returns the number of bytes read, which is a whole number of elements:
+/// only those are initialized. Fails if not a single element could be read.
+pub fn read_vm_mem_partial<T>(p: *const T, out: &mut [MaybeUninit<T>]) -> MemResult<usize> {
+    read_partial_with(&mut MemImpl::new(), p, out)
+}
+
+fn read_cstr_with(io: &mut impl VirtMemIo, p: *const u8, buf: &mut [u8]) -> MemResult<usize> {
+    let out = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), buf.len()) };
+    let read = read_partial_with(io, p, out)?;
+    match buf[..read].iter().position(|&b| b == 0) {
+        Some(len) => Ok(len),
+        None if read == buf.len() => Ok(read),
+        // Faulted before the end of the string.
+        None => Err(MemError::NoAccess),
+    }
+}
+
+/// Read a NUL-terminated string from virtual memory into `buf`, truncating
+/// it to the length of `buf`, as `strncpy_from_user` does.
+///
+/// Returns the length of the string read, without the terminator. Memory
+/// past the terminator or past `buf.len()` bytes is never required to be
+/// accessible.
+pub fn read_vm_cstr(p: *const u8, buf: &mut [u8]) -> MemResult<usize> {
+    read_cstr_with(&mut MemImpl::new(), p, buf)
+}
+
+/// Write a typed slice to virtual memory.
+pub fn write_vm_mem<T>(p: *mut T, src: &[T]) -> MemResult {
+    if write_vm_mem_partial(p, src)? < size_of_val(src) {
+        return Err(MemError::NoAccess);
+    }
+    Ok(())
+}
+
+/// Write a typed slice to virtual memory, up to the first fault.
+///
+/// Returns the number of bytes written, rounded down to a whole number of
+/// elements; the bytes of a partially written element may still have been
+/// stored. Fails if not a single element could be written.
+pub fn write_vm_mem_partial<T>(p: *mut T, src: &[T]) -> MemResult<usize> {
+    write_partial_with(&mut MemImpl::new(), p, src)
 }
 
 mod ptrs;
@@ -66,8 +157,21 @@ pub use ptrs::{VirtMutPtr, VirtPtr};
 #[cfg(feature = "alloc")]
 mod heap;
 #[cfg(feature = "alloc")]
-pub use heap::{load_vec, load_vec_unsafe, load_vec_until_null};
+pub use heap::{
+    StrArray, load_cstr, load_str_array, load_vec, load_vec_unsafe, load_vec_until_null,
+};
+
+#[cfg(feature = "alloc")]
+mod iovec;
+#[cfg(feature = "alloc")]
+pub use iovec::{IOV_MAX, IoVec, IoVecBuffer};
 
 // Cannot test in kernel mode
 // #[cfg(unittest)]
 // mod tests;
+
+#[cfg(feature = "alloc")]
+mod test_iovec;
+mod test_partial;
+#[cfg(feature = "alloc")]
+mod test_str_array;
@@ -0,0 +1,107 @@
+//! Unit tests for iovec buffers.
+
+#![cfg(unittest)]
+
+use core::ptr;
+
+use unittest::{assert, assert_eq, def_test};
+
+use crate::{IOV_MAX, IoVec, IoVecBuffer, MemError, test_partial::FaultyMem};
+
+/// An address past every buffer of the tests, where the mock faults.
+const BAD: usize = usize::MAX & !0xfff;
+
+fn mem() -> FaultyMem {
+    FaultyMem { fault_at: BAD }
+}
+
+fn iov(base: *mut u8, len: usize) -> IoVec {
+    IoVec {
+        iov_base: base,
+        iov_len: len as isize,
+    }
+}
+
+fn load(iovs: &[IoVec]) -> Result<IoVecBuffer, MemError> {
+    IoVecBuffer::from_user_with(&mut mem(), iovs.as_ptr(), iovs.len())
+}
+
+#[def_test]
+fn test_iovec_validation() {
+    let buf = load(&[]).unwrap();
+    assert_eq!(buf.total_len(), 0);
+
+    // Empty segments are skipped, whatever their base.
+    let mut data = [0u8; 8];
+    let iovs = [
+        iov(BAD as *mut u8, 0),
+        iov(data.as_mut_ptr(), 8),
+        iov(ptr::null_mut(), 0),
+    ];
+    let buf = load(&iovs).unwrap();
+    assert_eq!(buf.total_len(), 8);
+    assert_eq!(buf.iter().count(), 1);
+
+    let iovs = [iov(data.as_mut_ptr(), 1); IOV_MAX + 1];
+    assert_eq!(load(&iovs).err(), Some(MemError::InvalidInput));
+    assert!(load(&iovs[..IOV_MAX]).is_ok());
+
+    let iovs = [
+        iov(data.as_mut_ptr(), 8),
+        iov(data.as_mut_ptr(), usize::MAX),
+    ];
+    assert_eq!(load(&iovs).err(), Some(MemError::InvalidInput));
+
+    // Lengths adding up past isize::MAX.
+    let half = isize::MAX as usize / 2 + 1;
+    let iovs = [iov(0x1000 as *mut u8, half), iov(0x1000 as *mut u8, half)];
+    assert_eq!(load(&iovs).err(), Some(MemError::InvalidInput));
+    assert!(load(&iovs[..1]).is_ok());
+
+    // A segment wrapping around the address space.
+    let iovs = [iov((usize::MAX - 4) as *mut u8, 8)];
+    assert_eq!(load(&iovs).err(), Some(MemError::InvalidAddr));
+
+    // The array itself is unreadable.
+    let iovs = [iov(data.as_mut_ptr(), 8); 2];
+    let mut faulty = FaultyMem::at(iovs.as_ptr(), size_of::<IoVec>());
+    assert_eq!(
+        IoVecBuffer::from_user_with(&mut faulty, iovs.as_ptr(), 2).err(),
+        Some(MemError::NoAccess)
+    );
+}
+
+#[def_test]
+fn test_iovec_transfer() {
+    let mut data = [0u8; 8];
+    let base = data.as_mut_ptr();
+    let iovs = [
+        iov(base, 3),
+        iov(ptr::null_mut(), 0),
+        iov(base.wrapping_add(3), 5),
+    ];
+
+    // Writes continue where the previous one stopped.
+    let mut buf = load(&iovs).unwrap();
+    assert_eq!(buf.write_from_with(&mut mem(), b"12"), Ok(2));
+    assert_eq!(buf.write_from_with(&mut mem(), b"3456"), Ok(4));
+    assert_eq!(buf.remaining(), 2);
+    assert_eq!(buf.write_from_with(&mut mem(), b"789"), Ok(2));
+    assert_eq!(buf.write_from_with(&mut mem(), b"0"), Ok(0));
+    assert_eq!(&data, b"12345678");
+
+    let mut buf = load(&iovs).unwrap();
+    let mut out = [0u8; 16];
+    assert_eq!(buf.read_to_with(&mut mem(), &mut out), Ok(8));
+    assert_eq!(&out[..8], b"12345678");
+
+    // A fault in the second segment stops the transfer there.
+    let mut faulty = FaultyMem::at(base, 5);
+    let mut buf = load(&iovs).unwrap();
+    assert_eq!(buf.read_to_with(&mut faulty, &mut out), Ok(5));
+    assert_eq!(buf.remaining(), 3);
+    assert_eq!(
+        buf.read_to_with(&mut faulty, &mut out),
+        Err(MemError::NoAccess)
+    );
+}
@@ -0,0 +1,161 @@
+//! Unit tests for partial transfers, against a mock memory that faults at a
+//! chosen address.
+
+#![cfg(unittest)]
+
+use core::{mem::MaybeUninit, ptr};
+
+use unittest::{assert, assert_eq, def_test};
+
+use crate::{
+    MemError, MemResult, VirtMemIo, read_cstr_with, read_partial_with, write_partial_with,
+};
+
+/// Local memory in which every address from `fault_at` on faults.
+pub(crate) struct FaultyMem {
+    pub(crate) fault_at: usize,
+}
+
+impl FaultyMem {
+    pub(crate) fn at<T>(p: *const T, offset: usize) -> Self {
+        Self {
+            fault_at: p.addr() + offset,
+        }
+    }
+
+    fn accessible(&self, addr: usize, len: usize) -> MemResult<usize> {
+        let len = len.min(self.fault_at.saturating_sub(addr));
+        if len == 0 {
+            Err(MemError::NoAccess)
+        } else {
+            Ok(len)
+        }
+    }
+}
+
+unsafe impl VirtMemIo for FaultyMem {
+    fn new() -> Self {
+        Self {
+            fault_at: usize::MAX,
+        }
+    }
+
+    fn read_mem(&mut self, addr: usize, out: &mut [MaybeUninit<u8>]) -> MemResult {
+        match self.read_mem_partial(addr, out)? {
+            n if n == out.len() => Ok(()),
+            _ => Err(MemError::NoAccess),
+        }
+    }
+
+    fn write_mem(&mut self, addr: usize, src: &[u8]) -> MemResult {
+        match self.write_mem_partial(addr, src)? {
+            n if n == src.len() => Ok(()),
+            _ => Err(MemError::NoAccess),
+        }
+    }
+
+    fn read_mem_partial(&mut self, addr: usize, out: &mut [MaybeUninit<u8>]) -> MemResult<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        let len = self.accessible(addr, out.len())?;
+        unsafe { ptr::copy_nonoverlapping(addr as *const u8, out.as_mut_ptr().cast(), len) };
+        Ok(len)
+    }
+
+    fn write_mem_partial(&mut self, addr: usize, src: &[u8]) -> MemResult<usize> {
+        if src.is_empty() {
+            return Ok(0);
+        }
+        let len = self.accessible(addr, src.len())?;
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), addr as *mut u8, len) };
+        Ok(len)
+    }
+}
+
+#[def_test]
+fn test_partial_read_bytes() {
+    let data: [u8; 16] = core::array::from_fn(|i| i as u8);
+    let mut out = [MaybeUninit::<u8>::uninit(); 16];
+
+    let mut mem = FaultyMem::at(data.as_ptr(), 10);
+    assert_eq!(read_partial_with(&mut mem, data.as_ptr(), &mut out), Ok(10));
+    let read = unsafe { out[..10].assume_init_ref() };
+    assert_eq!(read, &data[..10]);
+
+    // Nothing transferred is an error.
+    let mut mem = FaultyMem::at(data.as_ptr(), 0);
+    assert_eq!(
+        read_partial_with(&mut mem, data.as_ptr(), &mut out),
+        Err(MemError::NoAccess)
+    );
+    assert_eq!(read_partial_with(&mut mem, data.as_ptr(), &mut []), Ok(0));
+}
+
+#[def_test]
+fn test_partial_read_whole_elements() {
+    let data: [u32; 4] = [0x1111_1111, 0x2222_2222, 0x3333_3333, 0x4444_4444];
+    let mut out = [MaybeUninit::<u32>::uninit(); 4];
+
+    // A fault inside the second element only reports the first one.
+    let mut mem = FaultyMem::at(data.as_ptr(), 6);
+    assert_eq!(read_partial_with(&mut mem, data.as_ptr(), &mut out), Ok(4));
+    assert_eq!(unsafe { out[0].assume_init() }, 0x1111_1111);
+
+    let mut mem = FaultyMem::at(data.as_ptr(), 3);
+    assert_eq!(
+        read_partial_with(&mut mem, data.as_ptr(), &mut out),
+        Err(MemError::NoAccess)
+    );
+
+    let mut mem = FaultyMem::at(data.as_ptr(), 16);
+    assert_eq!(read_partial_with(&mut mem, data.as_ptr(), &mut out), Ok(16));
+
+    // Misaligned pointers are refused before any access.
+    let unaligned = data.as_ptr().cast::<u8>().wrapping_add(1).cast::<u32>();
+    assert_eq!(
+        read_partial_with(&mut mem, unaligned, &mut out),
+        Err(MemError::InvalidAddr)
+    );
+}
+
+#[def_test]
+fn test_partial_write_whole_elements() {
+    let mut data = [0u64; 3];
+    let base = data.as_mut_ptr();
+
+    let mut mem = FaultyMem::at(base, 20);
+    assert_eq!(write_partial_with(&mut mem, base, &[1, 2, 3]), Ok(16));
+    assert_eq!(data[..2], [1, 2]);
+
+    let mut mem = FaultyMem::at(base, 4);
+    assert_eq!(
+        write_partial_with(&mut mem, base, &[5, 6, 7]),
+        Err(MemError::NoAccess)
+    );
+    assert!(data[1] == 2);
+}
+
+#[def_test]
+fn test_read_cstr_truncates() {
+    let data = *b"worker\0garbage";
+    let mut buf = [0u8; 15];
+
+    // Memory past the terminator need not be readable.
+    let mut mem = FaultyMem::at(data.as_ptr(), 7);
+    assert_eq!(read_cstr_with(&mut mem, data.as_ptr(), &mut buf), Ok(6));
+    assert_eq!(&buf[..6], b"worker");
+
+    // A longer string is cut at the buffer size, without a fault past it.
+    let long = *b"a-very-long-thread-name\0";
+    let mut mem = FaultyMem::at(long.as_ptr(), 15);
+    assert_eq!(read_cstr_with(&mut mem, long.as_ptr(), &mut buf), Ok(15));
+    assert_eq!(&buf, b"a-very-long-thr");
+
+    // A fault before the terminator is an error.
+    let mut mem = FaultyMem::at(long.as_ptr(), 4);
+    assert_eq!(
+        read_cstr_with(&mut mem, long.as_ptr(), &mut buf),
+        Err(MemError::NoAccess)
+    );
+}
@@ -0,0 +1,121 @@
+//! Unit tests for loading strings and string pointer arrays.
+
+#![cfg(unittest)]
+
+use core::ptr;
+
+use unittest::{assert, assert_eq, def_test};
+
+use crate::{
+    MemError,
+    heap::{load_cstr_with, load_str_array_with},
+    test_partial::FaultyMem,
+};
+
+/// An address past every buffer of the tests, where the mock faults.
+const BAD: usize = usize::MAX & !0xfff;
+
+fn mem() -> FaultyMem {
+    FaultyMem { fault_at: BAD }
+}
+
+#[def_test]
+fn test_str_array_empty() {
+    let argv = [ptr::null::<u8>()];
+    let res = load_str_array_with(&mut mem(), argv.as_ptr(), 0, 0).unwrap();
+    assert!(res.strings.is_empty());
+    assert_eq!(res.total_bytes, 0);
+}
+
+#[def_test]
+fn test_str_array_limits() {
+    let argv = [
+        c"ab".as_ptr().cast::<u8>(),
+        c"".as_ptr().cast(),
+        c"xyz".as_ptr().cast(),
+        ptr::null(),
+    ];
+    let res = load_str_array_with(&mut mem(), argv.as_ptr(), 3, 8).unwrap();
+    assert_eq!(res.strings.len(), 3);
+    assert_eq!(res.strings[0].as_c_str(), c"ab");
+    assert_eq!(res.strings[1].as_c_str(), c"");
+    assert_eq!(res.strings[2].as_c_str(), c"xyz");
+    assert_eq!(res.total_bytes, 8);
+
+    assert_eq!(
+        load_str_array_with(&mut mem(), argv.as_ptr(), 2, 8).unwrap_err(),
+        MemError::NameTooLong
+    );
+    assert_eq!(
+        load_str_array_with(&mut mem(), argv.as_ptr(), 3, 7).unwrap_err(),
+        MemError::NameTooLong
+    );
+
+    let unaligned = argv.as_ptr().cast::<u8>().wrapping_add(1).cast();
+    assert_eq!(
+        load_str_array_with(&mut mem(), unaligned, 3, 8).unwrap_err(),
+        MemError::InvalidAddr
+    );
+}
+
+#[def_test]
+fn test_str_array_bad_pointer() {
+    let argv = [
+        c"ab".as_ptr().cast::<u8>(),
+        BAD as *const u8,
+        c"xyz".as_ptr().cast(),
+        ptr::null(),
+    ];
+    assert_eq!(
+        load_str_array_with(&mut mem(), argv.as_ptr(), 8, 4096).unwrap_err(),
+        MemError::NoAccess
+    );
+}
+
+#[def_test]
+fn test_str_array_page_boundary() {
+    /// A buffer starting on a batch boundary, followed by a fault.
+    #[repr(align(64))]
+    struct Page([u8; 64]);
+
+    let mut page = Page([b'a'; 64]);
+    let mut mem = FaultyMem::at(page.0.as_ptr(), 64);
+
+    // A string ending right before the fault loads fine.
+    page.0[63] = 0;
+    let argv = [page.0.as_ptr(), ptr::null()];
+    let res = load_str_array_with(&mut mem, argv.as_ptr(), 8, 4096).unwrap();
+    assert_eq!(res.strings[0].as_bytes().len(), 63);
+    assert_eq!(res.total_bytes, 64);
+
+    // One running into it fails.
+    page.0[63] = b'a';
+    assert_eq!(
+        load_str_array_with(&mut mem, argv.as_ptr(), 8, 4096).unwrap_err(),
+        MemError::NoAccess
+    );
+
+    // So does an array missing its terminator.
+    let argv = [c"ab".as_ptr().cast::<u8>(); 2];
+    let mut mem = FaultyMem::at(argv.as_ptr(), size_of_val(&argv));
+    assert_eq!(
+        load_str_array_with(&mut mem, argv.as_ptr(), 8, 4096).unwrap_err(),
+        MemError::NoAccess
+    );
+}
+
+#[def_test]
+fn test_cstr_limit() {
+    let path = c"/usr/bin".as_ptr().cast::<u8>();
+    // The terminator counts.
+    let res = load_cstr_with(&mut mem(), path, 9).unwrap();
+    assert_eq!(res.as_c_str(), c"/usr/bin");
+    assert_eq!(
+        load_cstr_with(&mut mem(), path, 8).unwrap_err(),
+        MemError::NameTooLong
+    );
+    assert_eq!(
+        load_cstr_with(&mut mem(), path, 0).unwrap_err(),
+        MemError::NameTooLong
+    );
+}
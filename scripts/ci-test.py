@@ -73,7 +73,7 @@ try:
         buffer += b
 
         if PROMPT in buffer and not sent:
-            s.sendall(b"exit\r\n")
+            s.sendall(b"cat /proc/net/netconfig\r\nexit\r\n")
             sent = True
 
         if datetime.datetime.now() - start > datetime.timedelta(seconds=10):
@@ -84,6 +84,14 @@ try:
 
     print()
     print("\x1b[32m✔ Boot into BusyBox shell\x1b[0m")
+
+    # The address comes from `ip=` on the command line, except on x86_64,
+    # which has no device tree to pass it in and uses the built-in one.
+    source = "built-in" if arch == "x86_64" else "command line"
+    for marker in [f"source: {source}", "eth0 addr=10.0.2.15/24", "status=configured"]:
+        if marker not in buffer:
+            raise Exception(f"Network not configured at boot: missing {marker!r}")
+    print("\x1b[32m✔ Network configured at boot\x1b[0m")
 except Exception:
     print("\x1b[31m❌ Boot failed or timed out\x1b[0m")
     raise
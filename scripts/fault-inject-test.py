@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+
+import argparse
+import subprocess
+import sys
+
+parser = argparse.ArgumentParser()
+parser.add_argument("arch")
+parser.add_argument(
+    "cases",
+    nargs="*",
+    default=["heap", "nested"],
+    help="fault injection cases to run (heap, nested)",
+)
+
+args = parser.parse_args()
+
+# Markers that must appear on the console for each case. The crash record is
+# echoed right before the machine stops, so its markers are checked too.
+EXPECTED = {
+    "heap": [
+        "---[ KERNEL PANIC on CPU",
+        "fault injection: heap corrupted",
+        "Raw backtrace",
+        "---[ end of panic report ]---",
+        "---[ crash record ]---",
+        "---[ end of crash record ]---",
+    ],
+    "nested": [
+        "---[ KERNEL PANIC on CPU",
+        "---[ RECURSIVE PANIC on CPU",
+        "---[ resetting ]---",
+        "---[ crash record ]---",
+        "---[ end of crash record ]---",
+    ],
+}
+
+
+def run_case(case):
+    make_cmd = [
+        "make",
+        "ARCH=" + args.arch,
+        "ACCEL=n",
+        "APP_FEATURES=fault-inject-" + case,
+        "justrun",
+        # A reset must end the run instead of booting again.
+        "QEMU_ARGS=-no-reboot",
+    ]
+    try:
+        p = subprocess.run(
+            make_cmd,
+            stdout=subprocess.PIPE,
+            stdin=subprocess.DEVNULL,
+            text=True,
+            errors="ignore",
+            timeout=120,
+        )
+        output = p.stdout
+    except subprocess.TimeoutExpired as e:
+        output = e.stdout or ""
+        if isinstance(output, bytes):
+            output = output.decode("utf-8", errors="ignore")
+        print(output)
+        raise Exception(f"{case}: timeout waiting for the machine to stop")
+
+    print(output)
+    missing = [m for m in EXPECTED[case] if m not in output]
+    if missing:
+        raise Exception(f"{case}: missing {missing}")
+
+    # The record must hold the report itself, not just the echo markers.
+    record = output.split("---[ crash record ]---", 1)[1]
+    if EXPECTED[case][0] not in record:
+        raise Exception(f"{case}: crash record does not contain the report")
+
+
+failed = False
+for case in args.cases:
+    try:
+        run_case(case)
+        print(f"\x1b[32m✔ Fault injection: {case}\x1b[0m")
+    except Exception as e:
+        print(f"\x1b[31m❌ Fault injection: {e}\x1b[0m")
+        failed = True
+
+sys.exit(1 if failed else 0)
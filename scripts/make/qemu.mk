@@ -92,6 +92,15 @@ endif
 
 qemu_args-$(ICOUNT) += -icount shift=1
 
+# Kernel command line, with the boot network configuration of the user
+# network of QEMU
+kernel_args-y := $(BOOTARGS)
+kernel_args-$(NET) += ip=$(IP)::$(GW):255.255.255.0::eth0
+
+ifneq ($(strip $(kernel_args-y)),)
+  qemu_args-y += -append "$(strip $(kernel_args-y))"
+endif
+
 qemu_args-y += $(QEMU_ARGS)
 
 qemu_args-debug := $(qemu_args-y) -s -S
@@ -0,0 +1,79 @@
+#!/usr/bin/env python3
+
+import argparse
+import subprocess
+import sys
+
+parser = argparse.ArgumentParser()
+parser.add_argument("arch")
+parser.add_argument(
+    "cases",
+    nargs="*",
+    default=["pass", "missing-blk"],
+    help="self-test cases to run (pass, missing-blk)",
+)
+
+args = parser.parse_args()
+
+# Extra make variables and the markers that must appear in the report.
+CASES = {
+    "pass": ([], ["level=manufacturing", "verdict=pass exit_code=0"]),
+    "missing-blk": (
+        ["BLK=n"],
+        [
+            "test=devices result=fail",
+            "missing block:virtio-blk",
+            "verdict=fail exit_code=1",
+            "failures=devices",
+        ],
+    ),
+}
+
+
+def run_case(case):
+    extra, expected = CASES[case]
+    make_cmd = [
+        "make",
+        "ARCH=" + args.arch,
+        "ACCEL=n",
+        "justrun",
+        # The kernel powers off or resets after the report.
+        "QEMU_ARGS=-no-reboot",
+        "BOOTARGS=selftest=manufacturing",
+    ] + extra
+    try:
+        p = subprocess.run(
+            make_cmd,
+            stdout=subprocess.PIPE,
+            stdin=subprocess.DEVNULL,
+            text=True,
+            errors="ignore",
+            timeout=120,
+        )
+        output = p.stdout
+    except subprocess.TimeoutExpired as e:
+        output = e.stdout or ""
+        if isinstance(output, bytes):
+            output = output.decode("utf-8", errors="ignore")
+        print(output)
+        raise Exception(f"{case}: timeout waiting for the machine to stop")
+
+    print(output)
+    if "---[ end of selftest report ]---" not in output:
+        raise Exception(f"{case}: no self-test report")
+    report = output.split("---[ selftest report ]---", 1)[1]
+    missing = [m for m in expected if m not in report]
+    if missing:
+        raise Exception(f"{case}: missing {missing}")
+
+
+failed = False
+for case in args.cases:
+    try:
+        run_case(case)
+        print(f"\x1b[32m✔ Self-test: {case}\x1b[0m")
+    except Exception as e:
+        print(f"\x1b[31m❌ Self-test: {e}\x1b[0m")
+        failed = True
+
+sys.exit(1 if failed else 0)
@@ -0,0 +1,117 @@
+#!/usr/bin/env python3
+
+# Checks that a program survives syscall faults injected through
+# /proc/sys/debug/syscall_faults. Fault injection only exists in debug builds.
+
+import argparse
+import datetime
+import re
+import socket
+import subprocess
+import sys
+import threading
+
+parser = argparse.ArgumentParser()
+parser.add_argument("arch")
+
+args = parser.parse_args()
+
+make_cmd = [
+    "make",
+    "ARCH=" + args.arch,
+    "ACCEL=n",
+    "MODE=debug",
+    "justrun",
+    "QEMU_ARGS=-monitor none -serial tcp::4444,server=on",
+]
+
+RULES = "/proc/sys/debug/syscall_faults"
+PAYLOAD = "survived-eintr"
+
+# BusyBox retries reads interrupted by EINTR, so `cat` must print the whole
+# file and exit successfully although its first reads all fail.
+COMMANDS = [
+    f"echo {PAYLOAD} > /tmp/fault-target",
+    f"echo 'sys=read comm=cat fail=EINTR max=5' > {RULES}",
+    f"echo 'sys=sendfile comm=cat fail=EINTR max=5' > {RULES}",
+    'cat /tmp/fault-target; echo "cat-status=$?"',
+    f"cat {RULES}",
+    f"echo clear > {RULES}",
+    "exit",
+]
+
+p = subprocess.Popen(
+    make_cmd,
+    stderr=subprocess.PIPE,
+    text=True,
+)
+
+ready = threading.Event()
+
+
+def worker():
+    for line in p.stderr:
+        print(line, file=sys.stderr, end="")
+        if "QEMU waiting for connection" in line:
+            ready.set()
+    ready.set()
+
+
+thread = threading.Thread(target=worker)
+thread.daemon = True
+thread.start()
+
+try:
+    if not ready.wait(timeout=5):
+        raise Exception("QEMU did not start in time")
+    if p.poll() is not None:
+        raise Exception("QEMU exited prematurely")
+
+    PROMPT = "kylin-x:~#"
+
+    s = socket.create_connection(("localhost", 4444), timeout=5)
+    buffer = ""
+    sent = 0
+    start = datetime.datetime.now()
+
+    while True:
+        try:
+            b = s.recv(1024).decode("utf-8", errors="ignore")
+        except ConnectionError as e:
+            print(e)
+            break
+        if not b:
+            break
+
+        print(b, end="")
+        buffer += b
+
+        # Send the next command each time the prompt comes back.
+        if buffer.count(PROMPT) > sent and sent < len(COMMANDS):
+            s.sendall(COMMANDS[sent].encode() + b"\r\n")
+            sent += 1
+
+        if datetime.datetime.now() - start > datetime.timedelta(seconds=60):
+            raise Exception("Timeout waiting for exit")
+
+    if sent < len(COMMANDS):
+        raise Exception("Did not run all commands")
+    if "cat-status=0" not in buffer:
+        raise Exception("cat did not survive the injected EINTR")
+    if not re.search(rf"^{PAYLOAD}\r?$", buffer, re.MULTILINE):
+        raise Exception("cat did not print the whole file")
+    hits = [int(h) for h in re.findall(r"comm=cat fail=EINTR .* hits=(\d+)", buffer)]
+    if not any(hits):
+        raise Exception("no fault was injected")
+
+    print()
+    print("\x1b[32m✔ Program survives injected EINTR\x1b[0m")
+except Exception:
+    print("\x1b[31m❌ Syscall fault injection test failed\x1b[0m")
+    raise
+finally:
+    try:
+        p.wait(1)
+    except subprocess.TimeoutExpired:
+        p.terminate()
+        p.wait()
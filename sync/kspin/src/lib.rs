@@ -22,7 +22,8 @@
 //! ## Locks (`lock` module)
 //!
 //! Generic spinlock implementation [`SpinLock<G, T>`] parameterized
-//! by guard type.
+//! by guard type, and its reader-writer counterpart [`SpinRwLock<G, T>`]
+//! (`rwlock` module).
 //!
 //! ## Type Aliases
 //!
@@ -30,6 +31,7 @@
 //! - [`SpinRaw`]: Lock with no guards
 //! - [`SpinNoPreempt`]: Lock with preemption disabled
 //! - [`SpinNoIrq`]: Lock with IRQs and preemption disabled
+//! - [`SpinRwNoIrq`]: Reader-writer lock with IRQs and preemption disabled
 //!
 //! # Feature Flags
 //!
@@ -97,10 +99,12 @@ extern crate alloc;
 
 mod guard;
 mod lock;
+mod rwlock;
 mod tests;
 
 pub use guard::{BaseGuard, IrqSave, KernelGuardIf, NoOp, NoPreempt, NoPreemptIrqSave};
 pub use lock::{SpinLock, SpinLockGuard};
+pub use rwlock::{SpinRwLock, SpinRwLockReadGuard, SpinRwLockWriteGuard};
 
 /// Raw spinlock with no guards.
 ///
@@ -128,3 +132,14 @@ pub type SpinNoIrq<T> = SpinLock<NoPreemptIrqSave, T>;
 
 /// Guard for [`SpinNoIrq`].
 pub type SpinNoIrqGuard<'a, T> = SpinLockGuard<'a, NoPreemptIrqSave, T>;
+
+/// Reader-writer spinlock that disables IRQs and preemption.
+///
+/// Can be used from any context including interrupt handlers.
+pub type SpinRwNoIrq<T> = SpinRwLock<NoPreemptIrqSave, T>;
+
+/// Read guard for [`SpinRwNoIrq`].
+pub type SpinRwNoIrqReadGuard<'a, T> = SpinRwLockReadGuard<'a, NoPreemptIrqSave, T>;
+
+/// Write guard for [`SpinRwNoIrq`].
+pub type SpinRwNoIrqWriteGuard<'a, T> = SpinRwLockWriteGuard<'a, NoPreemptIrqSave, T>;
@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Reader-writer spinlock implementation with configurable guards.
+//!
+//! Like [`crate::SpinLock`], the guard type `G` determines whether
+//! preemption and IRQs are disabled while the lock is held, by readers and
+//! writers alike.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::guard::BaseGuard;
+
+const WRITE_LOCKED: u32 = 1 << 31;
+const WRITER_WAITING: u32 = 1 << 30;
+const READERS_MASK: u32 = WRITER_WAITING - 1;
+
+/// A reader-writer spinlock with configurable guard behavior.
+///
+/// Allows multiple readers or a single writer. Writers are preferred: once
+/// one spins for the lock, new readers spin until it got and released it,
+/// so a stream of readers cannot starve it. A reader must therefore not
+/// take the read lock it already holds again.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use kspin::SpinRwNoIrq;
+///
+/// let lock = SpinRwNoIrq::new(42);
+/// {
+///     let a = lock.read();
+///     let b = lock.read();
+///     assert_eq!(*a + *b, 84);
+/// }
+/// *lock.write() += 1;
+/// ```
+pub struct SpinRwLock<G: BaseGuard, T: ?Sized> {
+    _phantom: PhantomData<G>,
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+/// RAII guard for shared access to a [`SpinRwLock`].
+pub struct SpinRwLockReadGuard<'a, G: BaseGuard, T: ?Sized + 'a> {
+    _phantom: &'a PhantomData<G>,
+    guard_state: G::State,
+    data: *const T,
+    state: &'a AtomicU32,
+}
+
+/// RAII guard for exclusive access to a [`SpinRwLock`].
+pub struct SpinRwLockWriteGuard<'a, G: BaseGuard, T: ?Sized + 'a> {
+    _phantom: &'a PhantomData<G>,
+    guard_state: G::State,
+    data: *mut T,
+    state: &'a AtomicU32,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<G: BaseGuard, T: ?Sized + Send + Sync> Sync for SpinRwLock<G, T> {}
+unsafe impl<G: BaseGuard, T: ?Sized + Send> Send for SpinRwLock<G, T> {}
+
+impl<G: BaseGuard, T> SpinRwLock<G, T> {
+    /// Create a new reader-writer spinlock.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            _phantom: PhantomData,
+            state: AtomicU32::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consume the lock and return the inner value.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> SpinRwLock<G, T> {
+    #[inline(always)]
+    fn try_add_reader(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITE_LOCKED | WRITER_WAITING) != 0 {
+            return false;
+        }
+        assert!(state < READERS_MASK, "too many readers");
+        self.state
+            .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    fn try_add_writer(&self) -> bool {
+        // Acquiring clears the waiting bit, other waiting writers set it
+        // again as they keep spinning.
+        let state = self.state.load(Ordering::Relaxed);
+        state & !WRITER_WAITING == 0
+            && self
+                .state
+                .compare_exchange(state, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    #[inline(always)]
+    fn read_guard(&self, guard_state: G::State) -> SpinRwLockReadGuard<'_, G, T> {
+        SpinRwLockReadGuard {
+            _phantom: &PhantomData,
+            guard_state,
+            data: self.data.get(),
+            state: &self.state,
+        }
+    }
+
+    #[inline(always)]
+    fn write_guard(&self, guard_state: G::State) -> SpinRwLockWriteGuard<'_, G, T> {
+        SpinRwLockWriteGuard {
+            _phantom: &PhantomData,
+            guard_state,
+            data: self.data.get(),
+            state: &self.state,
+        }
+    }
+
+    /// Acquire shared access, spinning while a writer holds or waits for
+    /// the lock.
+    #[inline(always)]
+    pub fn read(&self) -> SpinRwLockReadGuard<'_, G, T> {
+        let guard_state = G::acquire();
+        while !self.try_add_reader() {
+            core::hint::spin_loop();
+        }
+        self.read_guard(guard_state)
+    }
+
+    /// Try to acquire shared access without spinning.
+    ///
+    /// Returns `None` if a writer holds or waits for the lock.
+    #[inline(always)]
+    pub fn try_read(&self) -> Option<SpinRwLockReadGuard<'_, G, T>> {
+        let guard_state = G::acquire();
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & (WRITE_LOCKED | WRITER_WAITING) == 0 {
+            assert!(state < READERS_MASK, "too many readers");
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(self.read_guard(guard_state)),
+                Err(x) => state = x,
+            }
+        }
+        G::release(guard_state);
+        None
+    }
+
+    /// Acquire exclusive access, spinning until readers and writers left.
+    #[inline(always)]
+    pub fn write(&self) -> SpinRwLockWriteGuard<'_, G, T> {
+        let guard_state = G::acquire();
+        while !self.try_add_writer() {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            core::hint::spin_loop();
+        }
+        self.write_guard(guard_state)
+    }
+
+    /// Try to acquire exclusive access without spinning.
+    ///
+    /// Returns `None` if the lock is held.
+    #[inline(always)]
+    pub fn try_write(&self) -> Option<SpinRwLockWriteGuard<'_, G, T>> {
+        let guard_state = G::acquire();
+        if self.try_add_writer() {
+            Some(self.write_guard(guard_state))
+        } else {
+            G::release(guard_state);
+            None
+        }
+    }
+
+    /// Check if a writer holds the lock (heuristic only).
+    ///
+    /// # Warning
+    ///
+    /// This provides no synchronization guarantees. The result
+    /// may be stale immediately. Do not use for synchronization.
+    #[inline(always)]
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITE_LOCKED != 0
+    }
+
+    /// Get mutable reference (zero-cost).
+    ///
+    /// Since this requires a mutable reference to the lock itself,
+    /// no actual locking is needed.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<G: BaseGuard, T: Default> Default for SpinRwLock<G, T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for SpinRwLock<G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f
+                .debug_struct("SpinRwLock")
+                .field("data", &&*guard)
+                .finish(),
+            None => f
+                .debug_struct("SpinRwLock")
+                .field("data", &"<locked>")
+                .finish(),
+        }
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Deref for SpinRwLockReadGuard<'_, G, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for SpinRwLockReadGuard<'_, G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Drop for SpinRwLockReadGuard<'_, G, T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.state.fetch_sub(1, Ordering::Release);
+        G::release(self.guard_state);
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Deref for SpinRwLockWriteGuard<'_, G, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> DerefMut for SpinRwLockWriteGuard<'_, G, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for SpinRwLockWriteGuard<'_, G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Drop for SpinRwLockWriteGuard<'_, G, T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        // Keep the waiting bit of writers spinning meanwhile.
+        self.state.fetch_and(!WRITE_LOCKED, Ordering::Release);
+        G::release(self.guard_state);
+    }
+}
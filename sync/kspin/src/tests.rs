@@ -155,3 +155,49 @@ fn debug_output() {
     let debug_str = format!("{:?}", lock);
     assert!(debug_str.contains("42") || debug_str.contains("SpinLock"));
 }
+
+type TestRwIrq<T> = SpinRwLock<TestGuardIrq, T>;
+
+#[def_test]
+fn rwlock_readers_share() {
+    let lock = TestRwIrq::new(42);
+    let a = lock.read();
+    let b = lock.try_read();
+    assert_eq!(*a, 42);
+    assert_eq!(b.as_deref().copied(), Some(42));
+    assert!(lock.try_write().is_none());
+    assert_eq!(unsafe { IRQ_CNT }, 2);
+
+    drop(a);
+    drop(b);
+    assert_eq!(unsafe { IRQ_CNT }, 0);
+    *lock.write() += 1;
+    assert_eq!(*lock.read(), 43);
+}
+
+#[def_test]
+fn rwlock_writer_excludes() {
+    let lock = TestRwIrq::new(0);
+    let mut w = lock.write();
+    *w = 7;
+    assert!(lock.is_write_locked());
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    assert_eq!(unsafe { IRQ_CNT }, 1);
+
+    drop(w);
+    assert_eq!(unsafe { IRQ_CNT }, 0);
+    assert!(!lock.is_write_locked());
+    assert_eq!(lock.try_read().as_deref().copied(), Some(7));
+}
+
+#[def_test]
+fn rwlock_unsized_and_into_inner() {
+    let lock: &TestRwIrq<[i32]> = &TestRwIrq::new([1, 2, 3]);
+    lock.write()[1] = 5;
+    let expected: &[i32] = &[1, 5, 3];
+    assert_eq!(&*lock.read(), expected);
+
+    let lock = TestRwIrq::new(NonCopy(10));
+    assert_eq!(lock.into_inner(), NonCopy(10));
+}
@@ -105,6 +105,18 @@ pub fn unwind_stack(fp: usize) -> Vec<Frame> {
     }
 }
 
+/// Walk the stack from `fp` without allocating or symbolizing.
+///
+/// Intended for failure paths (panics, faults) where the heap may be
+/// unusable. `visit` is called for each raw frame until it returns `false`.
+/// Returns the number of frames visited, or 0 if not initialized.
+pub fn walk_stack(fp: usize, visit: &mut dyn FnMut(&Frame) -> bool) -> usize {
+    let Some(config) = CONFIG.get() else {
+        return 0;
+    };
+    Unwinder::new(config).walk(fp, visit).unwrap_or(0)
+}
+
 /// State of a captured backtrace.
 #[allow(dead_code)]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -24,7 +24,31 @@ impl<'a> Unwinder<'a> {
     }
 
     /// Unwind the stack from the given frame pointer.
-    pub fn unwind(&self, mut fp: usize) -> Result<Vec<Frame>> {
+    pub fn unwind(&self, fp: usize) -> Result<Vec<Frame>> {
+        let mut frames = Vec::with_capacity(self.config.max_depth);
+        let mut push = |frame: &Frame| {
+            frames.push(*frame);
+            true
+        };
+        self.walk_inner(fp, &mut push, true)?;
+        Ok(frames)
+    }
+
+    /// Walk the stack from the given frame pointer without allocating.
+    ///
+    /// `visit` is called for each frame until it returns `false`. Returns the
+    /// number of frames visited. Nothing is logged, so this can run while the
+    /// logger's locks are held.
+    pub fn walk(&self, fp: usize, visit: &mut dyn FnMut(&Frame) -> bool) -> Result<usize> {
+        self.walk_inner(fp, visit, false)
+    }
+
+    fn walk_inner(
+        &self,
+        mut fp: usize,
+        visit: &mut dyn FnMut(&Frame) -> bool,
+        verbose: bool,
+    ) -> Result<usize> {
         // Validate initial frame pointer
         if !self.config.validate_fp(fp) {
             return Err(BacktraceError::OutOfRange {
@@ -33,7 +57,6 @@ impl<'a> Unwinder<'a> {
             });
         }
 
-        let mut frames = Vec::with_capacity(self.config.max_depth);
         let mut depth = 0;
         let mut prev_fp = 0;
 
@@ -51,12 +74,16 @@ impl<'a> Unwinder<'a> {
 
             // Check for cycles
             if frame.fp == prev_fp {
-                log::warn!("Detected frame pointer cycle at {:#x}", fp);
+                if verbose {
+                    log::warn!("Detected frame pointer cycle at {:#x}", fp);
+                }
                 break;
             }
 
             if frame.fp <= fp {
-                log::warn!("Frame pointer not increasing: {:#x} -> {:#x}", fp, frame.fp);
+                if verbose {
+                    log::warn!("Frame pointer not increasing: {:#x} -> {:#x}", fp, frame.fp);
+                }
                 break;
             }
 
@@ -70,16 +97,17 @@ impl<'a> Unwinder<'a> {
                 });
             }
 
-            // Add frame
-            frames.push(frame);
+            depth += 1;
+            if !visit(&frame) {
+                break;
+            }
 
             // Move to next frame
             prev_fp = fp;
             fp = frame.fp;
-            depth += 1;
         }
 
-        Ok(frames)
+        Ok(depth)
     }
 }
 
@@ -95,4 +123,18 @@ mod tests {
         let result = unwinder.unwind(0x2000);
         assert!(matches!(result, Err(BacktraceError::OutOfRange { .. })));
     }
+
+    #[test]
+    fn test_walk_out_of_range_visits_nothing() {
+        let config = BacktraceConfig::new(0..0x1000, 0..0x1000);
+        let unwinder = Unwinder::new(&config);
+
+        let mut visited = 0;
+        let result = unwinder.walk(0x2000, &mut |_| {
+            visited += 1;
+            true
+        });
+        assert!(matches!(result, Err(BacktraceError::OutOfRange { .. })));
+        assert_eq!(visited, 0);
+    }
 }
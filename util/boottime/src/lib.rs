@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Boot stage timing.
+//!
+//! [`boot_mark`] records the start of a boot stage as a reading of the kernel
+//! reference clock, the raw timer count, so it can be called from the first Rust code on, before the timer is calibrated
+//! and before the heap exists. A stage lasts until the next mark. Driver
+//! probes are timed separately with [`time_probe`], summed per driver, and a
+//! probe taking longer than the budget is logged as a warning.
+//!
+//! Counts are converted to time only when the report is built. It is printed
+//! when the stage given by the `boottime=` boot argument starts (`init` by
+//! default, `off` to never print it), and [`report`] builds it again at any
+//! time. It is a block of `key=value` lines between `---[ boot timing ]---`
+//! and `---[ end of boot timing ]---`: one `stage=` line per stage with its
+//! start on the monotonic clock, its duration, the time since the first mark
+//! and its raw reference clock start, to match it with log lines and crash
+//! reports, one `probe=` line per driver, and a final `total_us=` line.
+#![no_std]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate log;
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    ptr, slice,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+};
+
+use kplat::timer::{NS_MS, NS_US, reference_now, t2ns};
+use kspin::SpinNoIrq;
+
+/// Maximum number of recorded stages. Later marks are dropped.
+pub const MAX_MARKS: usize = 32;
+/// Maximum number of drivers whose probes are timed.
+pub const MAX_PROBES: usize = 32;
+/// Default time a single driver probe may take before a warning.
+pub const DEFAULT_PROBE_BUDGET_MS: u64 = 100;
+
+/// Stage printing the report when no `boottime=` argument is given.
+const DEFAULT_PRINT_AT: &str = "init";
+
+#[derive(Clone, Copy)]
+struct Mark {
+    stage: &'static str,
+    ticks: u64,
+}
+
+/// A slot of the mark array, written without locks so that marks work
+/// before per-CPU data and preemption control are set up.
+struct MarkSlot {
+    /// Published last: a null pointer is a slot not written yet.
+    stage: AtomicPtr<u8>,
+    len: AtomicUsize,
+    ticks: AtomicU64,
+}
+
+impl MarkSlot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        stage: AtomicPtr::new(ptr::null_mut()),
+        len: AtomicUsize::new(0),
+        ticks: AtomicU64::new(0),
+    };
+
+    fn get(&self) -> Option<Mark> {
+        let stage = self.stage.load(Ordering::Acquire);
+        if stage.is_null() {
+            return None;
+        }
+        let len = self.len.load(Ordering::Relaxed);
+        // SAFETY: `stage` and `len` come from a `&'static str`.
+        let stage = unsafe { str::from_utf8_unchecked(slice::from_raw_parts(stage, len)) };
+        Some(Mark {
+            stage,
+            ticks: self.ticks.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Probe {
+    driver: &'static str,
+    ticks: u64,
+    calls: u32,
+}
+
+struct Probes {
+    probes: [Probe; MAX_PROBES],
+    len: usize,
+    /// Drivers that did not fit.
+    dropped: usize,
+}
+
+impl Probes {
+    const fn new() -> Self {
+        Self {
+            probes: [Probe {
+                driver: "",
+                ticks: 0,
+                calls: 0,
+            }; MAX_PROBES],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    fn add(&mut self, driver: &'static str, ticks: u64) {
+        if let Some(probe) = self.probes[..self.len]
+            .iter_mut()
+            .find(|p| p.driver == driver)
+        {
+            probe.ticks += ticks;
+            probe.calls += 1;
+        } else if self.len < MAX_PROBES {
+            self.probes[self.len] = Probe {
+                driver,
+                ticks,
+                calls: 1,
+            };
+            self.len += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    fn as_slice(&self) -> &[Probe] {
+        &self.probes[..self.len]
+    }
+}
+
+/// Writes the report as of `now`, converting counts with `t2ns`.
+fn write_report(
+    f: &mut impl Write,
+    marks: &[Mark],
+    probes: &[Probe],
+    dropped: usize,
+    now: u64,
+    t2ns: fn(u64) -> u64,
+) -> fmt::Result {
+    let us = |ticks: u64| t2ns(ticks) / NS_US;
+    let first = marks.first().map_or(now, |m| m.ticks);
+
+    writeln!(f, "---[ boot timing ]---")?;
+    for (i, mark) in marks.iter().enumerate() {
+        let end = marks.get(i + 1).map_or(now, |next| next.ticks);
+        writeln!(
+            f,
+            "stage={} start_us={} duration_us={} cumulative_us={} start_ref={}",
+            mark.stage,
+            us(mark.ticks),
+            us(end.saturating_sub(mark.ticks)),
+            us(end.saturating_sub(first)),
+            mark.ticks,
+        )?;
+    }
+    for probe in probes {
+        writeln!(
+            f,
+            "probe={} calls={} duration_us={}",
+            probe.driver,
+            probe.calls,
+            us(probe.ticks)
+        )?;
+    }
+    write!(f, "total_us={}", us(now.saturating_sub(first)))?;
+    if dropped > 0 {
+        write!(f, " dropped={dropped}")?;
+    }
+    writeln!(f)?;
+    writeln!(f, "---[ end of boot timing ]---")
+}
+
+static MARKS: [MarkSlot; MAX_MARKS] = [MarkSlot::EMPTY; MAX_MARKS];
+/// Number of marks taken, including the dropped ones.
+static NR_MARKS: AtomicUsize = AtomicUsize::new(0);
+static PROBES: SpinNoIrq<Probes> = SpinNoIrq::new(Probes::new());
+/// Set by [`init`], which makes [`PRINT_AT`] safe to lock from [`boot_mark`].
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+/// Stage at which the report is printed.
+static PRINT_AT: SpinNoIrq<Option<&'static str>> = SpinNoIrq::new(None);
+static PROBE_BUDGET_NS: AtomicU64 = AtomicU64::new(DEFAULT_PROBE_BUDGET_MS * NS_MS);
+
+/// Marks the start of the boot stage `stage`, ending the previous one.
+///
+/// Only reads the timer count and takes no lock, so it is usable before the
+/// timer is calibrated and before per-CPU data is set up.
+pub fn boot_mark(stage: &'static str) {
+    let ticks = reference_now().ticks();
+    let idx = NR_MARKS.fetch_add(1, Ordering::Relaxed);
+    if let Some(slot) = MARKS.get(idx) {
+        slot.ticks.store(ticks, Ordering::Relaxed);
+        slot.len.store(stage.len(), Ordering::Relaxed);
+        slot.stage
+            .store(stage.as_ptr().cast_mut(), Ordering::Release);
+    }
+    if CONFIGURED.load(Ordering::Acquire) && *PRINT_AT.lock() == Some(stage) {
+        print_report();
+    }
+}
+
+/// Runs the probe `f` of `driver`, adding its duration to the driver's.
+///
+/// Must not be called before the timer is calibrated.
+pub fn time_probe<T>(driver: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = reference_now().ticks();
+    let ret = f();
+    let ticks = reference_now().ticks() - start;
+    PROBES.lock().add(driver, ticks);
+
+    let ns = t2ns(ticks);
+    let budget = PROBE_BUDGET_NS.load(Ordering::Relaxed);
+    if ns > budget {
+        warn!(
+            "probing {driver} took {} ms, over the {} ms budget",
+            ns / NS_MS,
+            budget / NS_MS
+        );
+    }
+    ret
+}
+
+/// Reads the options in the kernel command line, enabling the report.
+///
+/// `boottime=<stage>` prints the report when `stage` starts, and
+/// `boottime=off` disables printing. `boottime.probe_budget_ms=<ms>` sets
+/// the probe duration above which a warning is logged.
+pub fn init(cmdline: &'static str) {
+    let mut print_at = Some(DEFAULT_PRINT_AT);
+    for arg in cmdline.split_ascii_whitespace() {
+        if let Some(stage) = arg.strip_prefix("boottime=") {
+            print_at = (stage != "off").then_some(stage);
+        } else if let Some(ms) = arg.strip_prefix("boottime.probe_budget_ms=") {
+            match ms.parse::<u64>() {
+                Ok(ms) => PROBE_BUDGET_NS.store(ms * NS_MS, Ordering::Relaxed),
+                Err(_) => warn!("invalid boot time probe budget {ms:?}"),
+            }
+        }
+    }
+    *PRINT_AT.lock() = print_at;
+    CONFIGURED.store(true, Ordering::Release);
+}
+
+/// Builds the report, with the last stage lasting until now.
+pub fn report() -> String {
+    let now = reference_now().ticks();
+    let nr_marks = NR_MARKS.load(Ordering::Relaxed);
+    let marks: Vec<Mark> = MARKS.iter().filter_map(MarkSlot::get).collect();
+    let probes = PROBES.lock();
+    let dropped = nr_marks.saturating_sub(MAX_MARKS) + probes.dropped;
+
+    let mut out = String::new();
+    let _ = write_report(&mut out, &marks, probes.as_slice(), dropped, now, t2ns);
+    out
+}
+
+/// Prints the report to the console.
+pub fn print_report() {
+    kplat::io::write_data_atomic(report().as_bytes());
+}
+
+#[cfg(unittest)]
+mod tests_boottime {
+    use unittest::def_test;
+
+    use super::*;
+
+    /// One tick per microsecond.
+    fn t2ns(ticks: u64) -> u64 {
+        ticks * NS_US
+    }
+
+    fn report_of(marks: &[Mark], probes: &Probes, dropped: usize, now: u64) -> String {
+        let mut out = String::new();
+        write_report(&mut out, marks, probes.as_slice(), dropped, now, t2ns).unwrap();
+        out
+    }
+
+    fn mark(stage: &'static str, ticks: u64) -> Mark {
+        Mark { stage, ticks }
+    }
+
+    #[def_test]
+    fn test_report_stages() {
+        let marks = [
+            mark("early", 100),
+            mark("memory", 150),
+            mark("drivers", 400),
+        ];
+        let mut probes = Probes::new();
+        probes.add("virtio-blk", 30);
+        probes.add("virtio-net", 20);
+        probes.add("virtio-blk", 12);
+
+        let report = report_of(&marks, &probes, 0, 1000);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "---[ boot timing ]---",
+                "stage=early start_us=100 duration_us=50 cumulative_us=50 start_ref=100",
+                "stage=memory start_us=150 duration_us=250 cumulative_us=300 start_ref=150",
+                "stage=drivers start_us=400 duration_us=600 cumulative_us=900 start_ref=400",
+                "probe=virtio-blk calls=2 duration_us=42",
+                "probe=virtio-net calls=1 duration_us=20",
+                "total_us=900",
+                "---[ end of boot timing ]---",
+            ]
+        );
+    }
+
+    #[def_test]
+    fn test_report_overflow() {
+        let mut probes = Probes::new();
+        for _ in 0..2 {
+            for i in 0..MAX_PROBES + 1 {
+                probes.add(["a", "b"][i % 2], 1);
+            }
+        }
+        assert_eq!(probes.len, 2);
+        assert_eq!(probes.dropped, 0);
+
+        let report = report_of(&[mark("early", 2)], &probes, 3, 10);
+        assert!(report.contains("total_us=8 dropped=3\n"));
+
+        // No mark at all still gives a well-formed report.
+        let report = report_of(&[], &Probes::new(), 0, 5);
+        assert_eq!(
+            report,
+            "---[ boot timing ]---\ntotal_us=0\n---[ end of boot timing ]---\n"
+        );
+    }
+}
@@ -0,0 +1,544 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! The format of trace files.
+//!
+//! A trace file is a header followed by records of [`RECORD_SIZE`] bytes,
+//! all little endian. The header describes the rest of the file: the fields
+//! of every kind of record, the clocks the timestamps relate to and the
+//! names of the syscalls, so that a host tool decodes the files of any
+//! kernel without knowing its sources.
+//!
+//! The header starts with:
+//!
+//! | Offset | Size | Content                                             |
+//! |--------|------|-----------------------------------------------------|
+//! | 0      | 8    | [`MAGIC`]                                           |
+//! | 8      | 2    | [`FORMAT_VERSION`]                                  |
+//! | 10     | 2    | size of a record                                    |
+//! | 12     | 4    | size of the header: where the records start         |
+//! | 16     | 4    | sequence number of the file, to order rotated files |
+//! | 20     | 4    | number of record schemas                            |
+//! | 24     | 8    | resolution of the clock, in nanoseconds             |
+//! | 32     | 8    | monotonic time the file was started at              |
+//! | 40     | 8    | real time since the epoch at the same point         |
+//!
+//! Then come the schemas, each a kind (`u16`), the length of its name
+//! (`u8`), the number of its fields (`u8`) and its name, then for every
+//! field its [`FieldType`] (`u8`), its size (`u8`), the length of its name
+//! (`u8`) and its name. Last come the syscall names: their number (`u32`),
+//! then for each the syscall number (`u32`), the length of its name (`u8`)
+//! and its name.
+//!
+//! A record starts with its kind (`u16`), two reserved bytes, the CPU it was
+//! recorded on (`u32`), its monotonic timestamp in nanoseconds (`u64`) and
+//! the task it was recorded by (`u64`). Its fields follow in schema order;
+//! the rest of the record is zero.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use kerrno::{KError, KResult};
+
+/// Magic number starting a trace file.
+pub const MAGIC: [u8; 8] = *b"XKFLTREC";
+
+/// Version of the format, changed whenever decoders of older versions
+/// would misread files.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Size of a record in bytes.
+pub const RECORD_SIZE: usize = 80;
+
+/// Size of the header common to all records.
+const RECORD_HEADER_SIZE: usize = 24;
+
+/// Size of the header of a file before the schemas.
+const FILE_HEADER_SIZE: usize = 48;
+
+/// Largest length of the text of a marker.
+pub const MARKER_TEXT_LEN: usize = 48;
+
+/// Kind of a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum EventKind {
+    SyscallEnter = 1,
+    SyscallExit  = 2,
+    SchedSwitch  = 3,
+    SchedWakeup  = 4,
+    Signal       = 5,
+    Marker       = 6,
+    Dropped      = 7,
+}
+
+impl EventKind {
+    fn from_raw(raw: u16) -> Option<Self> {
+        SCHEMAS
+            .iter()
+            .map(|schema| schema.kind)
+            .find(|kind| *kind as u16 == raw)
+    }
+}
+
+/// How a field of a record is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FieldType {
+    /// An unsigned integer.
+    U64   = 0,
+    /// A signed integer.
+    I64   = 1,
+    /// Bytes, NUL-padded.
+    Bytes = 2,
+}
+
+impl FieldType {
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::U64),
+            1 => Some(Self::I64),
+            2 => Some(Self::Bytes),
+            _ => None,
+        }
+    }
+}
+
+/// A field of a record.
+pub struct Field {
+    pub name: &'static str,
+    pub ty: FieldType,
+    pub size: u8,
+}
+
+const fn field(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty, size: 8 }
+}
+
+/// The layout of a kind of record, as written to the file headers.
+pub struct Schema {
+    pub kind: EventKind,
+    pub name: &'static str,
+    pub fields: &'static [Field],
+}
+
+/// The schemas of all kinds of records.
+pub static SCHEMAS: &[Schema] = &[
+    Schema {
+        kind: EventKind::SyscallEnter,
+        name: "sys_enter",
+        fields: &[
+            field("nr", FieldType::U64),
+            field("arg0", FieldType::U64),
+            field("arg1", FieldType::U64),
+            field("arg2", FieldType::U64),
+            field("arg3", FieldType::U64),
+            field("arg4", FieldType::U64),
+            field("arg5", FieldType::U64),
+        ],
+    },
+    Schema {
+        kind: EventKind::SyscallExit,
+        name: "sys_exit",
+        fields: &[field("nr", FieldType::U64), field("ret", FieldType::I64)],
+    },
+    Schema {
+        kind: EventKind::SchedSwitch,
+        name: "sched_switch",
+        fields: &[
+            field("prev_tid", FieldType::U64),
+            field("next_tid", FieldType::U64),
+            field("prev_state", FieldType::U64),
+        ],
+    },
+    Schema {
+        kind: EventKind::SchedWakeup,
+        name: "sched_wakeup",
+        fields: &[field("tid", FieldType::U64), field("cpu", FieldType::U64)],
+    },
+    Schema {
+        kind: EventKind::Signal,
+        name: "signal_deliver",
+        fields: &[
+            field("signo", FieldType::U64),
+            field("action", FieldType::U64),
+        ],
+    },
+    Schema {
+        kind: EventKind::Marker,
+        name: "marker",
+        fields: &[
+            field("value", FieldType::U64),
+            Field {
+                name: "text",
+                ty: FieldType::Bytes,
+                size: MARKER_TEXT_LEN as u8,
+            },
+        ],
+    },
+    Schema {
+        kind: EventKind::Dropped,
+        name: "dropped",
+        fields: &[field("count", FieldType::U64)],
+    },
+];
+
+/// What a record reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A syscall was entered.
+    SyscallEnter { nr: u32, args: [u64; 6] },
+    /// A syscall returned `ret`, negative for an errno.
+    SyscallExit { nr: u32, ret: i64 },
+    /// The CPU switched from task `prev` to task `next`, leaving `prev` in
+    /// state `prev_state`.
+    SchedSwitch {
+        prev: u64,
+        next: u64,
+        prev_state: u64,
+    },
+    /// Task `tid` was made runnable on `cpu`.
+    SchedWakeup { tid: u64, cpu: u32 },
+    /// Signal `signo` was delivered, taking `action`: 0 to run a handler,
+    /// 1 to terminate, 2 to dump core, 3 to stop and 4 to continue.
+    Signal { signo: u32, action: u32 },
+    /// A correlation point set by user space.
+    Marker {
+        value: u64,
+        text: [u8; MARKER_TEXT_LEN],
+    },
+    /// `count` records of the CPU were dropped since the last such record.
+    Dropped { count: u64 },
+}
+
+impl Event {
+    /// Returns a marker, with `text` cut to [`MARKER_TEXT_LEN`] bytes.
+    pub fn marker(value: u64, text: &[u8]) -> Self {
+        let mut buf = [0; MARKER_TEXT_LEN];
+        let len = text.len().min(MARKER_TEXT_LEN);
+        buf[..len].copy_from_slice(&text[..len]);
+        Self::Marker { value, text: buf }
+    }
+
+    /// Returns the kind of records of this event.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::SyscallEnter { .. } => EventKind::SyscallEnter,
+            Self::SyscallExit { .. } => EventKind::SyscallExit,
+            Self::SchedSwitch { .. } => EventKind::SchedSwitch,
+            Self::SchedWakeup { .. } => EventKind::SchedWakeup,
+            Self::Signal { .. } => EventKind::Signal,
+            Self::Marker { .. } => EventKind::Marker,
+            Self::Dropped { .. } => EventKind::Dropped,
+        }
+    }
+}
+
+/// A recorded event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// Monotonic time of the event, in nanoseconds.
+    pub timestamp: u64,
+    /// The CPU the event was recorded on.
+    pub cpu: u32,
+    /// The task the event was recorded by.
+    pub tid: u64,
+    pub event: Event,
+}
+
+impl Record {
+    pub(crate) const EMPTY: Self = Self {
+        timestamp: 0,
+        cpu: 0,
+        tid: 0,
+        event: Event::Dropped { count: 0 },
+    };
+
+    /// Encodes the record into `out`.
+    pub fn encode(&self, out: &mut [u8; RECORD_SIZE]) {
+        out.fill(0);
+        out[0..2].copy_from_slice(&(self.event.kind() as u16).to_le_bytes());
+        out[4..8].copy_from_slice(&self.cpu.to_le_bytes());
+        out[8..16].copy_from_slice(&self.timestamp.to_le_bytes());
+        out[16..24].copy_from_slice(&self.tid.to_le_bytes());
+
+        let mut fields = out[RECORD_HEADER_SIZE..].chunks_exact_mut(8);
+        let mut put = |value: u64| {
+            if let Some(slot) = fields.next() {
+                slot.copy_from_slice(&value.to_le_bytes());
+            }
+        };
+        match self.event {
+            Event::SyscallEnter { nr, args } => {
+                put(nr as u64);
+                args.into_iter().for_each(put);
+            }
+            Event::SyscallExit { nr, ret } => {
+                put(nr as u64);
+                put(ret as u64);
+            }
+            Event::SchedSwitch {
+                prev,
+                next,
+                prev_state,
+            } => {
+                put(prev);
+                put(next);
+                put(prev_state);
+            }
+            Event::SchedWakeup { tid, cpu } => {
+                put(tid);
+                put(cpu as u64);
+            }
+            Event::Signal { signo, action } => {
+                put(signo as u64);
+                put(action as u64);
+            }
+            Event::Marker { value, text } => {
+                put(value);
+                out[RECORD_HEADER_SIZE + 8..][..MARKER_TEXT_LEN].copy_from_slice(&text);
+            }
+            Event::Dropped { count } => put(count),
+        }
+    }
+
+    /// Decodes a record, failing with [`KError::InvalidData`] if its kind is
+    /// unknown.
+    pub fn decode(buf: &[u8; RECORD_SIZE]) -> KResult<Self> {
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
+        let field = |index: usize| u64_at(RECORD_HEADER_SIZE + 8 * index);
+
+        let kind = u16::from_le_bytes([buf[0], buf[1]]);
+        let event = match EventKind::from_raw(kind).ok_or(KError::InvalidData)? {
+            EventKind::SyscallEnter => Event::SyscallEnter {
+                nr: field(0) as u32,
+                args: core::array::from_fn(|i| field(i + 1)),
+            },
+            EventKind::SyscallExit => Event::SyscallExit {
+                nr: field(0) as u32,
+                ret: field(1) as i64,
+            },
+            EventKind::SchedSwitch => Event::SchedSwitch {
+                prev: field(0),
+                next: field(1),
+                prev_state: field(2),
+            },
+            EventKind::SchedWakeup => Event::SchedWakeup {
+                tid: field(0),
+                cpu: field(1) as u32,
+            },
+            EventKind::Signal => Event::Signal {
+                signo: field(0) as u32,
+                action: field(1) as u32,
+            },
+            EventKind::Marker => Event::Marker {
+                value: field(0),
+                text: buf[RECORD_HEADER_SIZE + 8..][..MARKER_TEXT_LEN]
+                    .try_into()
+                    .unwrap(),
+            },
+            EventKind::Dropped => Event::Dropped { count: field(0) },
+        };
+        Ok(Self {
+            timestamp: u64_at(8),
+            cpu: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            tid: u64_at(16),
+            event,
+        })
+    }
+}
+
+/// The clocks the timestamps of a file relate to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockParams {
+    /// Resolution of the clock, in nanoseconds.
+    pub resolution_ns: u64,
+    /// Monotonic time, the clock of the timestamps.
+    pub monotonic_ns: u64,
+    /// Real time since the epoch at the same point.
+    pub realtime_ns: u64,
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+    out.extend_from_slice(name);
+}
+
+fn name_len(name: &str) -> u8 {
+    name.len().min(u8::MAX as usize) as u8
+}
+
+/// Appends the header of file `sequence` to `out`, naming `syscalls`.
+pub fn encode_header(
+    out: &mut Vec<u8>,
+    sequence: u32,
+    clock: &ClockParams,
+    syscalls: &[(u32, &str)],
+) {
+    let start = out.len();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(RECORD_SIZE as u16).to_le_bytes());
+    // The size of the header, filled in last.
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&sequence.to_le_bytes());
+    out.extend_from_slice(&(SCHEMAS.len() as u32).to_le_bytes());
+    out.extend_from_slice(&clock.resolution_ns.to_le_bytes());
+    out.extend_from_slice(&clock.monotonic_ns.to_le_bytes());
+    out.extend_from_slice(&clock.realtime_ns.to_le_bytes());
+
+    for schema in SCHEMAS {
+        out.extend_from_slice(&(schema.kind as u16).to_le_bytes());
+        out.push(name_len(schema.name));
+        out.push(schema.fields.len() as u8);
+        put_name(out, schema.name);
+        for field in schema.fields {
+            out.extend_from_slice(&[field.ty as u8, field.size, name_len(field.name)]);
+            put_name(out, field.name);
+        }
+    }
+
+    out.extend_from_slice(&(syscalls.len() as u32).to_le_bytes());
+    for (nr, name) in syscalls {
+        out.extend_from_slice(&nr.to_le_bytes());
+        out.push(name_len(name));
+        put_name(out, name);
+    }
+
+    let size = (out.len() - start) as u32;
+    out[start + 12..start + 16].copy_from_slice(&size.to_le_bytes());
+}
+
+/// A schema read back from a file header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedSchema {
+    pub kind: u16,
+    pub name: String,
+    /// The name, type and size of every field.
+    pub fields: Vec<(String, FieldType, u8)>,
+}
+
+/// A file header read back, see [`TraceHeader::parse`].
+#[derive(Debug, Clone)]
+pub struct TraceHeader {
+    pub version: u16,
+    pub record_size: usize,
+    /// Where the records start.
+    pub header_size: usize,
+    pub sequence: u32,
+    pub clock: ClockParams,
+    pub schemas: Vec<DecodedSchema>,
+    pub syscalls: BTreeMap<u32, String>,
+}
+
+/// Reads the fields of a header in order.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> KResult<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(KError::InvalidData)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> KResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> KResult<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> KResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> KResult<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self, len: u8) -> KResult<String> {
+        let bytes = self.bytes(len as usize)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| KError::InvalidData)
+    }
+}
+
+impl TraceHeader {
+    /// Reads the header of the trace file `data`.
+    ///
+    /// Fails with [`KError::InvalidData`] if `data` is not a trace file or
+    /// is cut short, and with [`KError::Unsupported`] if it has another
+    /// version of the format.
+    pub fn parse(data: &[u8]) -> KResult<Self> {
+        let mut cursor = Cursor { data, pos: 0 };
+        if cursor.bytes(MAGIC.len())? != MAGIC {
+            return Err(KError::InvalidData);
+        }
+        let version = cursor.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(KError::Unsupported);
+        }
+        let record_size = cursor.u16()? as usize;
+        let header_size = cursor.u32()? as usize;
+        let sequence = cursor.u32()?;
+        let schema_count = cursor.u32()?;
+        let clock = ClockParams {
+            resolution_ns: cursor.u64()?,
+            monotonic_ns: cursor.u64()?,
+            realtime_ns: cursor.u64()?,
+        };
+        debug_assert_eq!(cursor.pos, FILE_HEADER_SIZE);
+
+        let mut schemas = Vec::new();
+        for _ in 0..schema_count {
+            let kind = cursor.u16()?;
+            let name_len = cursor.u8()?;
+            let field_count = cursor.u8()?;
+            let name = cursor.name(name_len)?;
+            let mut fields = Vec::new();
+            for _ in 0..field_count {
+                let ty = FieldType::from_raw(cursor.u8()?).ok_or(KError::InvalidData)?;
+                let size = cursor.u8()?;
+                let name_len = cursor.u8()?;
+                fields.push((cursor.name(name_len)?, ty, size));
+            }
+            schemas.push(DecodedSchema { kind, name, fields });
+        }
+
+        let mut syscalls = BTreeMap::new();
+        for _ in 0..cursor.u32()? {
+            let nr = cursor.u32()?;
+            let name_len = cursor.u8()?;
+            syscalls.insert(nr, cursor.name(name_len)?);
+        }
+        if cursor.pos != header_size || record_size != RECORD_SIZE {
+            return Err(KError::InvalidData);
+        }
+
+        Ok(Self {
+            version,
+            record_size,
+            header_size,
+            sequence,
+            clock,
+            schemas,
+            syscalls,
+        })
+    }
+
+    /// Decodes the records of the trace file `data` this header was read
+    /// from. A record cut short by the end of the file is left out.
+    pub fn records<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = KResult<Record>> + 'a {
+        data.get(self.header_size..)
+            .unwrap_or_default()
+            .chunks_exact(RECORD_SIZE)
+            .map(|chunk| Record::decode(chunk.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,480 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Flight recorder: a persistent trace of syscalls, scheduling and signals.
+//!
+//! While recording, syscall entries and exits, context switches, wakeups,
+//! signal deliveries and user space markers are put as fixed-size records in
+//! a lock-free queue by the tasks they happen on, see [`record`]. A writer
+//! task drains the queue with [`drain`] and appends the records to trace
+//! files through a [`TraceWriter`], which rotates them by size. The files
+//! describe their own layout (see [`format`]), so that they are decoded
+//! offline, long after the events left any in-memory buffer.
+//!
+//! Recording never blocks the traced tasks: when the writer falls behind
+//! and the queue is full, records are dropped and counted per CPU, and the
+//! count shows up in the stream as an [`Event::Dropped`] record. While not
+//! recording, every recording site costs a single `NOP` when static keys
+//! are patched.
+#![no_std]
+
+extern crate alloc;
+
+pub mod format;
+mod queue;
+mod writer;
+
+use core::time::Duration;
+
+use static_keys::{StaticKey, static_branch_unlikely};
+
+pub use self::{
+    format::{ClockParams, Event, EventKind, MARKER_TEXT_LEN, RECORD_SIZE, Record, TraceHeader},
+    queue::{MAX_TRACE_CPUS, RecordQueue, RecordSlot},
+    writer::{TraceSink, TraceWriter},
+};
+
+/// Number of records the queue holds.
+pub const RECORD_QUEUE_SLOTS: usize = 4096;
+
+/// Size of the trace files used unless `flightrec=` says otherwise.
+pub const DEFAULT_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Number of trace files kept unless `flightrec=` says otherwise.
+pub const DEFAULT_FILE_COUNT: u32 = 4;
+
+/// How long records may wait in the queue for the writer.
+pub const WRITER_PERIOD: Duration = Duration::from_millis(20);
+
+/// What the recorder needs from the rest of the kernel.
+#[crate_interface::def_interface]
+pub trait FlightRecorderAdapter {
+    /// Returns the monotonic time, in nanoseconds.
+    fn now_ns() -> u64;
+    /// Returns the ID of the current CPU.
+    fn cpu_id() -> usize;
+}
+
+/// Whether events are being recorded.
+static RECORDING: StaticKey = StaticKey::new(false);
+
+static SLOTS: [RecordSlot; RECORD_QUEUE_SLOTS] = [const { RecordSlot::new() }; RECORD_QUEUE_SLOTS];
+static QUEUE: RecordQueue = RecordQueue::new(&SLOTS);
+
+/// Records `event` of task `tid`, if recording.
+#[inline]
+pub fn record(tid: u64, event: Event) {
+    if static_branch_unlikely!(RECORDING) {
+        record_slow(tid, event);
+    }
+}
+
+#[cold]
+fn record_slow(tid: u64, event: Event) {
+    QUEUE.submit(Record {
+        timestamp: crate_interface::call_interface!(FlightRecorderAdapter::now_ns),
+        cpu: crate_interface::call_interface!(FlightRecorderAdapter::cpu_id) as u32,
+        tid,
+        event,
+    });
+}
+
+/// Returns whether events are being recorded.
+pub fn is_recording() -> bool {
+    RECORDING.is_enabled()
+}
+
+/// Starts or stops recording events.
+///
+/// Only to be started once a task drains the queue periodically.
+pub fn set_recording(enabled: bool) {
+    RECORDING.set(enabled);
+}
+
+/// Passes the recorded events to `emit`, oldest first, unless another CPU
+/// is doing so. Returns the number of records passed.
+pub fn drain(emit: &mut dyn FnMut(&Record)) -> usize {
+    QUEUE.drain(
+        crate_interface::call_interface!(FlightRecorderAdapter::now_ns),
+        emit,
+    )
+}
+
+/// Returns the number of records dropped on `cpu` because the writer fell
+/// behind. Records of CPUs from [`MAX_TRACE_CPUS`] on count on the last one.
+pub fn dropped(cpu: usize) -> u64 {
+    QUEUE.dropped(cpu)
+}
+
+/// Where and how to record, from the kernel command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config<'a> {
+    /// Path of the trace files, suffixed with `.0`, `.1`, ...
+    pub path: &'a str,
+    /// Size of a trace file in bytes.
+    pub file_size: u64,
+    /// Number of trace files kept.
+    pub file_count: u32,
+}
+
+impl<'a> Config<'a> {
+    /// Picks the configuration from a kernel command line.
+    ///
+    /// `flightrec=<path>[,size=<bytes>][,files=<count>]` records to files
+    /// at `path`; the size takes a `K`, `M` or `G` suffix. Without the
+    /// argument, nothing is recorded.
+    pub fn from_cmdline(cmdline: &'a str) -> Option<Self> {
+        let value = cmdline
+            .split_ascii_whitespace()
+            .filter_map(|arg| arg.strip_prefix("flightrec="))
+            .next_back()?;
+        let mut options = value.split(',');
+        let path = options.next().filter(|path| !path.is_empty())?;
+        let mut config = Self {
+            path,
+            file_size: DEFAULT_FILE_SIZE,
+            file_count: DEFAULT_FILE_COUNT,
+        };
+        for option in options {
+            if let Some(size) = option.strip_prefix("size=").and_then(parse_size) {
+                config.file_size = size;
+            } else if let Some(count) = option.strip_prefix("files=").and_then(|n| n.parse().ok())
+                && count > 0
+            {
+                config.file_count = count;
+            }
+        }
+        Some(config)
+    }
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'K' | b'k' => (&size[..size.len() - 1], 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(unittest)]
+mod tests_flightrec {
+    use alloc::{vec, vec::Vec};
+
+    use kerrno::KResult;
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    /// Files kept in memory.
+    struct MemorySink {
+        files: Vec<Vec<u8>>,
+        current: usize,
+    }
+
+    impl TraceSink for MemorySink {
+        fn start_file(&mut self, slot: u32) -> KResult<()> {
+            let slot = slot as usize;
+            if self.files.len() <= slot {
+                self.files.resize(slot + 1, Vec::new());
+            }
+            self.files[slot].clear();
+            self.current = slot;
+            Ok(())
+        }
+
+        fn write(&mut self, data: &[u8]) -> KResult<()> {
+            self.files[self.current].extend_from_slice(data);
+            Ok(())
+        }
+
+        fn clock(&self) -> ClockParams {
+            ClockParams {
+                resolution_ns: 1,
+                monotonic_ns: 1000,
+                realtime_ns: 1_700_000_000_000_000_000,
+            }
+        }
+    }
+
+    /// Decodes the files of `sink`, in sequence order.
+    fn decode(sink: &MemorySink) -> (Vec<TraceHeader>, Vec<Record>) {
+        let mut files = sink
+            .files
+            .iter()
+            .map(|data| (TraceHeader::parse(data).unwrap(), data))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(header, _)| header.sequence);
+        let records = files
+            .iter()
+            .flat_map(|(header, data)| header.records(data))
+            .map(Result::unwrap)
+            .collect();
+        (
+            files.into_iter().map(|(header, _)| header).collect(),
+            records,
+        )
+    }
+
+    fn rec(timestamp: u64, cpu: u32, tid: u64, event: Event) -> Record {
+        Record {
+            timestamp,
+            cpu,
+            tid,
+            event,
+        }
+    }
+
+    /// Two processes on two CPUs: 10 writes to a pipe and wakes 20 blocked
+    /// reading it, which then gets a signal; both mark their progress.
+    fn scenario() -> Vec<Record> {
+        let (write, read) = (1, 0);
+        vec![
+            rec(
+                10,
+                0,
+                20,
+                Event::SyscallEnter {
+                    nr: read,
+                    args: [3, 0x1000, 64, 0, 0, 0],
+                },
+            ),
+            rec(
+                11,
+                0,
+                20,
+                Event::SchedSwitch {
+                    prev: 20,
+                    next: 1,
+                    prev_state: 3,
+                },
+            ),
+            rec(12, 1, 10, Event::marker(1, b"before write")),
+            rec(
+                13,
+                1,
+                10,
+                Event::SyscallEnter {
+                    nr: write,
+                    args: [4, 0x2000, 5, 0, 0, 0],
+                },
+            ),
+            rec(14, 1, 10, Event::SchedWakeup { tid: 20, cpu: 0 }),
+            rec(15, 1, 10, Event::SyscallExit { nr: write, ret: 5 }),
+            rec(
+                16,
+                0,
+                1,
+                Event::SchedSwitch {
+                    prev: 1,
+                    next: 20,
+                    prev_state: 1,
+                },
+            ),
+            rec(17, 0, 20, Event::SyscallExit { nr: read, ret: 5 }),
+            rec(
+                18,
+                1,
+                10,
+                Event::SyscallEnter {
+                    nr: 62,
+                    args: [20, 10, 0, 0, 0, 0],
+                },
+            ),
+            rec(19, 1, 10, Event::SyscallExit { nr: 62, ret: 0 }),
+            rec(
+                20,
+                0,
+                20,
+                Event::Signal {
+                    signo: 10,
+                    action: 0,
+                },
+            ),
+            rec(21, 0, 20, Event::marker(2, b"handled")),
+        ]
+    }
+
+    #[def_test]
+    fn test_record_layout() {
+        let record = rec(
+            5,
+            3,
+            42,
+            Event::SyscallEnter {
+                nr: 7,
+                args: [1, 2, 3, 4, 5, u64::MAX],
+            },
+        );
+        let mut buf = [0xff; RECORD_SIZE];
+        record.encode(&mut buf);
+        assert_eq!(&buf[0..2], &1u16.to_le_bytes());
+        assert_eq!(&buf[4..8], &3u32.to_le_bytes());
+        assert_eq!(&buf[8..16], &5u64.to_le_bytes());
+        assert_eq!(&buf[16..24], &42u64.to_le_bytes());
+        assert_eq!(&buf[24..32], &7u64.to_le_bytes());
+        assert_eq!(Record::decode(&buf), Ok(record));
+
+        let marker = rec(1, 0, 1, Event::marker(9, &[b'x'; 100]));
+        marker.encode(&mut buf);
+        assert_eq!(&buf[32..], &[b'x'; MARKER_TEXT_LEN]);
+        assert_eq!(Record::decode(&buf), Ok(marker));
+
+        buf[0] = 0xee;
+        assert!(Record::decode(&buf).is_err());
+    }
+
+    #[def_test]
+    fn test_header_describes_records() {
+        let mut data = Vec::new();
+        let clock = ClockParams {
+            resolution_ns: 8,
+            monotonic_ns: 2,
+            realtime_ns: 3,
+        };
+        format::encode_header(&mut data, 5, &clock, &[(0, "read"), (1, "write")]);
+        let header = TraceHeader::parse(&data).unwrap();
+        assert_eq!(header.header_size, data.len());
+        assert_eq!(header.record_size, RECORD_SIZE);
+        assert_eq!(header.sequence, 5);
+        assert_eq!(header.clock, clock);
+        assert_eq!(
+            header.syscalls.get(&1).map(|name| name.as_str()),
+            Some("write")
+        );
+
+        assert_eq!(header.schemas.len(), format::SCHEMAS.len());
+        for (decoded, schema) in header.schemas.iter().zip(format::SCHEMAS) {
+            assert_eq!(decoded.kind, schema.kind as u16);
+            assert_eq!(decoded.name, schema.name);
+            assert_eq!(decoded.fields.len(), schema.fields.len());
+            // The fields fit a record after its common header.
+            let size: usize = decoded.fields.iter().map(|f| f.2 as usize).sum();
+            assert!(24 + size <= RECORD_SIZE);
+        }
+
+        assert!(TraceHeader::parse(&data[..data.len() - 1]).is_err());
+        data[0] = b'Y';
+        assert!(TraceHeader::parse(&data).is_err());
+    }
+
+    #[def_test]
+    fn test_scripted_scenario() {
+        static SLOTS: [RecordSlot; 64] = [const { RecordSlot::new() }; 64];
+        let queue = RecordQueue::new(&SLOTS);
+        let expected = scenario();
+        for record in &expected {
+            assert!(queue.submit(*record));
+        }
+
+        let sink = MemorySink {
+            files: Vec::new(),
+            current: 0,
+        };
+        let mut writer =
+            TraceWriter::new(sink, 1024, 8, vec![(0, "read"), (1, "write"), (62, "kill")]);
+        let mut batch = Vec::new();
+        queue.drain(100, &mut |rec| batch.push(*rec));
+        writer.write(&batch).unwrap();
+
+        let (headers, records) = decode(writer.sink());
+        assert_eq!(records, expected);
+        // Small files: the header, then as many records as fit.
+        assert!(headers.len() > 1);
+        for (seq, header) in headers.iter().enumerate() {
+            assert_eq!(header.sequence, seq as u32);
+            assert_eq!(
+                header.syscalls.get(&62).map(|name| name.as_str()),
+                Some("kill")
+            );
+        }
+        for data in &writer.sink().files {
+            assert!(data.len() <= 1024);
+        }
+    }
+
+    #[def_test]
+    fn test_rotation_keeps_newest_files() {
+        let sink = MemorySink {
+            files: Vec::new(),
+            current: 0,
+        };
+        // Too small for any record: each file still takes one.
+        let mut writer = TraceWriter::new(sink, 1, 3, Vec::new());
+        let records = (0..10)
+            .map(|i| {
+                rec(
+                    i,
+                    0,
+                    1,
+                    Event::SyscallExit {
+                        nr: 0,
+                        ret: i as i64,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        writer.write(&records[..4]).unwrap();
+        writer.write(&records[4..]).unwrap();
+
+        let (headers, decoded) = decode(writer.sink());
+        assert_eq!(writer.sink().files.len(), 3);
+        assert_eq!(
+            headers.iter().map(|h| h.sequence).collect::<Vec<_>>(),
+            [7, 8, 9]
+        );
+        assert_eq!(decoded, records[7..]);
+    }
+
+    #[def_test]
+    fn test_overflow_is_reported_in_stream() {
+        static SLOTS: [RecordSlot; 4] = [const { RecordSlot::new() }; 4];
+        let queue = RecordQueue::new(&SLOTS);
+        let script = scenario();
+        let queued = script.iter().filter(|rec| queue.submit(**rec)).count();
+        assert_eq!(queued, 4);
+        assert_eq!(queue.dropped(0) + queue.dropped(1), 8);
+
+        let mut stream = Vec::new();
+        queue.drain(50, &mut |rec| stream.push(*rec));
+        let dropped = stream
+            .iter()
+            .filter_map(|rec| match rec.event {
+                Event::Dropped { count } => Some(count),
+                _ => None,
+            })
+            .sum::<u64>();
+        assert_eq!(dropped, 8);
+        assert_eq!(stream[stream.len() - 4..], script[..4]);
+    }
+
+    #[def_test]
+    fn test_config_from_cmdline() {
+        assert_eq!(Config::from_cmdline("console=ttyS0"), None);
+        assert_eq!(Config::from_cmdline("flightrec="), None);
+        assert_eq!(
+            Config::from_cmdline("flightrec=/var/trace"),
+            Some(Config {
+                path: "/var/trace",
+                file_size: DEFAULT_FILE_SIZE,
+                file_count: DEFAULT_FILE_COUNT,
+            })
+        );
+        assert_eq!(
+            Config::from_cmdline("quiet flightrec=/t,size=2M,files=8"),
+            Some(Config {
+                path: "/t",
+                file_size: 2 << 20,
+                file_count: 8,
+            })
+        );
+        // Bad options keep their defaults.
+        let config = Config::from_cmdline("flightrec=/t,size=lots,files=0").unwrap();
+        assert_eq!(
+            (config.file_size, config.file_count),
+            (DEFAULT_FILE_SIZE, DEFAULT_FILE_COUNT)
+        );
+    }
+}
@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Lock-free queue of records between the traced tasks and the writer.
+//!
+//! A traced task reserves a slot by advancing an atomic cursor, stores its
+//! record and commits it; it never takes a lock nor waits for the writer.
+//! When the queue is full the record is dropped and counted against the CPU
+//! that recorded it. The writer drains the queue in reservation order, and
+//! reports the drops in the stream as [`Event::Dropped`] records.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use kspin::SpinNoIrq;
+
+use crate::format::{Event, Record};
+
+/// Number of CPUs with a dropped-records counter of their own; records of
+/// CPUs past it are counted with the last one.
+pub const MAX_TRACE_CPUS: usize = 64;
+
+/// A slot of a [`RecordQueue`], holding one record.
+pub struct RecordSlot {
+    /// `2 * lap` while free for the record at position `lap * len + index`
+    /// of the queue, one more once that record is committed.
+    state: AtomicUsize,
+    record: UnsafeCell<Record>,
+}
+
+// SAFETY: the record of a slot is only accessed by the producer that
+// reserved it until it is committed, then by the drainer until it is freed.
+unsafe impl Sync for RecordSlot {}
+
+impl RecordSlot {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            record: UnsafeCell::new(Record::EMPTY),
+        }
+    }
+}
+
+impl Default for RecordSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bounded queue of records with per-CPU drop counters.
+pub struct RecordQueue {
+    slots: &'static [RecordSlot],
+    /// Position of the next record to reserve.
+    tail: AtomicUsize,
+    /// Position of the next record to drain; only advanced by the holder of
+    /// the drain lock.
+    head: AtomicUsize,
+    dropped: [AtomicU64; MAX_TRACE_CPUS],
+    /// Serializes draining; holds the drops of every CPU reported so far.
+    drain: SpinNoIrq<[u64; MAX_TRACE_CPUS]>,
+}
+
+impl RecordQueue {
+    /// Creates a queue holding up to `slots.len()` records.
+    pub const fn new(slots: &'static [RecordSlot]) -> Self {
+        Self {
+            slots,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            dropped: [const { AtomicU64::new(0) }; MAX_TRACE_CPUS],
+            drain: SpinNoIrq::new([0; MAX_TRACE_CPUS]),
+        }
+    }
+
+    /// Returns the slot of position `pos`, and its state while free for it.
+    fn slot(&self, pos: usize) -> (&RecordSlot, usize) {
+        let len = self.slots.len();
+        (&self.slots[pos % len], 2 * (pos / len))
+    }
+
+    /// Reserves the next position, or returns `None` if the queue is full.
+    fn reserve(&self) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let (slot, free) = self.slot(pos);
+            let state = slot.state.load(Ordering::Acquire);
+            if state == free {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Some(pos),
+                    Err(tail) => pos = tail,
+                }
+            } else if state < free {
+                // Still holds the record of the previous lap.
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == pos {
+                    return None;
+                }
+                pos = tail;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Queues `record`, or counts it as dropped if the queue is full.
+    /// Returns whether it was queued.
+    pub fn submit(&self, record: Record) -> bool {
+        let Some(pos) = self.reserve() else {
+            let cpu = (record.cpu as usize).min(MAX_TRACE_CPUS - 1);
+            self.dropped[cpu].fetch_add(1, Ordering::Relaxed);
+            return false;
+        };
+        let (slot, free) = self.slot(pos);
+        // SAFETY: the slot is reserved for this record until committed.
+        unsafe { *slot.record.get() = record };
+        slot.state.store(free + 1, Ordering::Release);
+        true
+    }
+
+    /// Passes the oldest record to `f` and frees its slot. Returns `false`,
+    /// without calling `f`, if that record is not committed yet.
+    ///
+    /// Must be called by the holder of the drain lock.
+    fn pop(&self, f: &mut dyn FnMut(&Record)) -> bool {
+        if self.slots.is_empty() {
+            return false;
+        }
+        let pos = self.head.load(Ordering::Relaxed);
+        let (slot, free) = self.slot(pos);
+        if slot.state.load(Ordering::Acquire) != free + 1 {
+            return false;
+        }
+        // SAFETY: the record is committed and only the drainer reads it.
+        unsafe { f(&*slot.record.get()) };
+        self.head.store(pos + 1, Ordering::Relaxed);
+        slot.state.store(free + 2, Ordering::Release);
+        true
+    }
+
+    /// Passes the committed records to `emit`, oldest first, unless another
+    /// CPU is doing so. Returns the number of records passed.
+    ///
+    /// Records dropped since the last call are reported first, with an
+    /// [`Event::Dropped`] record per CPU stamped `now`.
+    pub fn drain(&self, now: u64, emit: &mut dyn FnMut(&Record)) -> usize {
+        let Some(mut reported) = self.drain.try_lock() else {
+            return 0;
+        };
+        let mut count = 0;
+        for (cpu, dropped) in self.dropped.iter().enumerate() {
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped != reported[cpu] {
+                emit(&Record {
+                    timestamp: now,
+                    cpu: cpu as u32,
+                    tid: 0,
+                    event: Event::Dropped {
+                        count: dropped - reported[cpu],
+                    },
+                });
+                reported[cpu] = dropped;
+                count += 1;
+            }
+        }
+        while self.pop(emit) {
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the number of records dropped on `cpu` because the queue was
+    /// full. Records of CPUs from [`MAX_TRACE_CPUS`] on count on the last one.
+    pub fn dropped(&self, cpu: usize) -> u64 {
+        self.dropped
+            .get(cpu)
+            .map_or(0, |dropped| dropped.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(unittest)]
+mod tests_queue {
+    use unittest::{assert, assert_eq, def_test};
+
+    use super::*;
+
+    fn record(cpu: u32, tid: u64) -> Record {
+        Record {
+            timestamp: tid,
+            cpu,
+            tid,
+            event: Event::SyscallExit { nr: 0, ret: 0 },
+        }
+    }
+
+    #[def_test]
+    fn test_queue_drops_when_full() {
+        static SLOTS: [RecordSlot; 2] = [const { RecordSlot::new() }; 2];
+        let queue = RecordQueue::new(&SLOTS);
+        assert!(queue.submit(record(0, 1)));
+        assert!(queue.submit(record(1, 2)));
+        // Full: dropped rather than waited for.
+        assert!(!queue.submit(record(1, 3)));
+        assert!(!queue.submit(record(1000, 4)));
+        assert_eq!(queue.dropped(0), 0);
+        assert_eq!(queue.dropped(1), 1);
+        assert_eq!(queue.dropped(MAX_TRACE_CPUS - 1), 1);
+
+        let mut out = alloc::vec::Vec::new();
+        assert_eq!(queue.drain(7, &mut |rec| out.push(*rec)), 4);
+        assert_eq!(out[0].event, Event::Dropped { count: 1 });
+        assert_eq!((out[0].cpu, out[0].timestamp), (1, 7));
+        assert_eq!(out[1].cpu as usize, MAX_TRACE_CPUS - 1);
+        assert_eq!((out[2].tid, out[3].tid), (1, 2));
+
+        // Drops are reported once.
+        assert!(queue.submit(record(0, 5)));
+        out.clear();
+        assert_eq!(queue.drain(8, &mut |rec| out.push(*rec)), 1);
+        assert_eq!(out[0].tid, 5);
+    }
+
+    #[def_test]
+    fn test_queue_waits_for_commit() {
+        static SLOTS: [RecordSlot; 4] = [const { RecordSlot::new() }; 4];
+        let queue = RecordQueue::new(&SLOTS);
+        // A producer that reserved its slot but has not committed yet holds
+        // back the records reserved after it.
+        let pos = queue.reserve().unwrap();
+        assert!(queue.submit(record(0, 2)));
+        assert_eq!(queue.drain(0, &mut |_| {}), 0);
+
+        let (slot, free) = queue.slot(pos);
+        slot.state.store(free + 1, Ordering::Release);
+        assert_eq!(queue.drain(0, &mut |_| {}), 2);
+    }
+}
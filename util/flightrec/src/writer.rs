@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Writing records out to a rotating set of trace files.
+
+use alloc::vec::Vec;
+
+use kerrno::KResult;
+
+use crate::format::{ClockParams, RECORD_SIZE, Record, encode_header};
+
+/// Where trace files are written, e.g. files of a filesystem.
+pub trait TraceSink {
+    /// Starts over the file of slot `slot`, emptying it.
+    fn start_file(&mut self, slot: u32) -> KResult<()>;
+
+    /// Appends `data` to the current file.
+    fn write(&mut self, data: &[u8]) -> KResult<()>;
+
+    /// Returns the current state of the clocks of the timestamps.
+    fn clock(&self) -> ClockParams;
+}
+
+/// Writes records to the files of a [`TraceSink`], starting a new file with
+/// its own header once the current one is full.
+///
+/// Files are written to slots `0..max_files` in turn, the oldest being
+/// overwritten; the sequence number in their headers tells their order.
+pub struct TraceWriter<S> {
+    sink: S,
+    max_file_size: u64,
+    max_files: u32,
+    syscalls: Vec<(u32, &'static str)>,
+    /// Sequence number of the current file, if any.
+    sequence: Option<u32>,
+    /// Bytes written to the current file.
+    written: u64,
+    /// Records written to the current file.
+    file_records: u64,
+    buf: Vec<u8>,
+}
+
+impl<S: TraceSink> TraceWriter<S> {
+    /// Creates a writer of files of at most `max_file_size` bytes, keeping
+    /// the last `max_files`, whose headers name `syscalls`.
+    ///
+    /// A file always holds at least one record, whatever `max_file_size`.
+    pub fn new(
+        sink: S,
+        max_file_size: u64,
+        max_files: u32,
+        syscalls: Vec<(u32, &'static str)>,
+    ) -> Self {
+        Self {
+            sink,
+            max_file_size,
+            max_files: max_files.max(1),
+            syscalls,
+            sequence: None,
+            written: 0,
+            file_records: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Returns the sink written to.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Starts the next file and writes its header.
+    fn rotate(&mut self) -> KResult<()> {
+        let sequence = self.sequence.map_or(0, |seq| seq.wrapping_add(1));
+        self.sink.start_file(sequence % self.max_files)?;
+        self.sequence = Some(sequence);
+
+        self.buf.clear();
+        encode_header(&mut self.buf, sequence, &self.sink.clock(), &self.syscalls);
+        self.sink.write(&self.buf)?;
+        self.written = self.buf.len() as u64;
+        self.file_records = 0;
+        Ok(())
+    }
+
+    /// Writes `records` out, rotating files as they fill up.
+    pub fn write(&mut self, records: &[Record]) -> KResult<()> {
+        let mut records = records;
+        while !records.is_empty() {
+            let room = match self.sequence {
+                Some(_) => self.max_file_size.saturating_sub(self.written) / RECORD_SIZE as u64,
+                None => 0,
+            };
+            // A file with nothing but its header takes a record anyway.
+            if room == 0 && (self.sequence.is_none() || self.file_records > 0) {
+                self.rotate()?;
+                continue;
+            }
+            let count = (room.max(1) as usize).min(records.len());
+            let (batch, rest) = records.split_at(count);
+
+            self.buf.clear();
+            self.buf.resize(batch.len() * RECORD_SIZE, 0);
+            for (record, chunk) in batch.iter().zip(self.buf.chunks_exact_mut(RECORD_SIZE)) {
+                record.encode(chunk.try_into().unwrap());
+            }
+            self.sink.write(&self.buf)?;
+            self.written += self.buf.len() as u64;
+            self.file_records += count as u64;
+            records = rest;
+        }
+        Ok(())
+    }
+}
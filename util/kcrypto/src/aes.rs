@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! AES-256 block cipher (FIPS-197).
+//!
+//! A byte-oriented software implementation. The S-box lookups depend on the
+//! data, so it is not hardened against cache-timing attacks.
+
+use crate::zeroize;
+
+/// Size of an AES block in bytes.
+pub const BLOCK_SIZE: usize = 16;
+
+/// Size of an AES-256 key in bytes.
+pub const KEY_SIZE: usize = 32;
+
+const ROUNDS: usize = 14;
+
+const fn xtime(x: u8) -> u8 {
+    (x << 1) ^ if x & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+const fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            p ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    p
+}
+
+const fn build_sbox() -> [u8; 256] {
+    let mut sbox = [0u8; 256];
+    // Walk the multiplicative group with generator 3, keeping `q` as the
+    // inverse of `p`, and apply the affine transformation.
+    let (mut p, mut q) = (1u8, 1u8);
+    loop {
+        p = p ^ xtime(p);
+        q ^= q << 1;
+        q ^= q << 2;
+        q ^= q << 4;
+        if q & 0x80 != 0 {
+            q ^= 0x09;
+        }
+        let x = q ^ q.rotate_left(1) ^ q.rotate_left(2) ^ q.rotate_left(3) ^ q.rotate_left(4);
+        sbox[p as usize] = x ^ 0x63;
+        if p == 1 {
+            break;
+        }
+    }
+    sbox[0] = 0x63;
+    sbox
+}
+
+const fn invert(table: &[u8; 256]) -> [u8; 256] {
+    let mut inv = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv[table[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv
+}
+
+const SBOX: [u8; 256] = build_sbox();
+const INV_SBOX: [u8; 256] = invert(&SBOX);
+
+type Block = [u8; BLOCK_SIZE];
+
+fn add_round_key(state: &mut Block, key: &Block) {
+    for (s, k) in state.iter_mut().zip(key) {
+        *s ^= k;
+    }
+}
+
+fn sub_bytes(state: &mut Block, table: &[u8; 256]) {
+    for s in state.iter_mut() {
+        *s = table[*s as usize];
+    }
+}
+
+/// The state is column-major: byte `4 * c + r` is row `r` of column `c`.
+fn shift_rows(state: &mut Block) {
+    let old = *state;
+    for c in 0..4 {
+        for r in 0..4 {
+            state[4 * c + r] = old[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+fn inv_shift_rows(state: &mut Block) {
+    let old = *state;
+    for c in 0..4 {
+        for r in 0..4 {
+            state[4 * ((c + r) % 4) + r] = old[4 * c + r];
+        }
+    }
+}
+
+fn mix_columns(state: &mut Block) {
+    for col in state.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [col[0], col[1], col[2], col[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        col[0] ^= all ^ xtime(a0 ^ a1);
+        col[1] ^= all ^ xtime(a1 ^ a2);
+        col[2] ^= all ^ xtime(a2 ^ a3);
+        col[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+fn inv_mix_columns(state: &mut Block) {
+    for col in state.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [col[0], col[1], col[2], col[3]];
+        col[0] = gmul(a0, 14) ^ gmul(a1, 11) ^ gmul(a2, 13) ^ gmul(a3, 9);
+        col[1] = gmul(a0, 9) ^ gmul(a1, 14) ^ gmul(a2, 11) ^ gmul(a3, 13);
+        col[2] = gmul(a0, 13) ^ gmul(a1, 9) ^ gmul(a2, 14) ^ gmul(a3, 11);
+        col[3] = gmul(a0, 11) ^ gmul(a1, 13) ^ gmul(a2, 9) ^ gmul(a3, 14);
+    }
+}
+
+/// An expanded AES-256 key.
+///
+/// The round keys are wiped when the value is dropped.
+#[derive(Clone)]
+pub struct Aes256 {
+    round_keys: [Block; ROUNDS + 1],
+}
+
+impl Aes256 {
+    /// Expands `key`.
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        let mut words = [[0u8; 4]; 4 * (ROUNDS + 1)];
+        for (word, chunk) in words.iter_mut().zip(key.chunks_exact(4)) {
+            word.copy_from_slice(chunk);
+        }
+        let mut rcon = 1u8;
+        for i in 8..words.len() {
+            let mut temp = words[i - 1];
+            if i % 8 == 0 {
+                temp.rotate_left(1);
+                temp = temp.map(|b| SBOX[b as usize]);
+                temp[0] ^= rcon;
+                rcon = xtime(rcon);
+            } else if i % 8 == 4 {
+                temp = temp.map(|b| SBOX[b as usize]);
+            }
+            for (j, t) in temp.iter().enumerate() {
+                words[i][j] = words[i - 8][j] ^ t;
+            }
+        }
+
+        let mut round_keys = [[0u8; BLOCK_SIZE]; ROUNDS + 1];
+        for (round_key, chunk) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+            for (dst, word) in round_key.chunks_exact_mut(4).zip(chunk) {
+                dst.copy_from_slice(word);
+            }
+        }
+        zeroize(words.as_flattened_mut());
+        Self { round_keys }
+    }
+
+    /// Encrypts `block` in place.
+    pub fn encrypt_block(&self, block: &mut Block) {
+        add_round_key(block, &self.round_keys[0]);
+        for round_key in &self.round_keys[1..ROUNDS] {
+            sub_bytes(block, &SBOX);
+            shift_rows(block);
+            mix_columns(block);
+            add_round_key(block, round_key);
+        }
+        sub_bytes(block, &SBOX);
+        shift_rows(block);
+        add_round_key(block, &self.round_keys[ROUNDS]);
+    }
+
+    /// Decrypts `block` in place.
+    pub fn decrypt_block(&self, block: &mut Block) {
+        add_round_key(block, &self.round_keys[ROUNDS]);
+        for round_key in self.round_keys[1..ROUNDS].iter().rev() {
+            inv_shift_rows(block);
+            sub_bytes(block, &INV_SBOX);
+            add_round_key(block, round_key);
+            inv_mix_columns(block);
+        }
+        inv_shift_rows(block);
+        sub_bytes(block, &INV_SBOX);
+        add_round_key(block, &self.round_keys[0]);
+    }
+}
+
+impl Drop for Aes256 {
+    fn drop(&mut self) {
+        zeroize(self.round_keys.as_flattened_mut());
+    }
+}
+
+#[cfg(unittest)]
+pub mod tests_aes {
+    use unittest::def_test;
+
+    use super::*;
+    use crate::tests_util::hex;
+
+    #[def_test]
+    fn test_aes256_fips197() {
+        // FIPS-197, appendix C.3.
+        let key: [u8; 32] = hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let aes = Aes256::new(&key);
+        let mut block: [u8; 16] = hex("00112233445566778899aabbccddeeff");
+        aes.encrypt_block(&mut block);
+        assert_eq!(block, hex::<16>("8ea2b7ca516745bfeafc49904b496089"));
+        aes.decrypt_block(&mut block);
+        assert_eq!(block, hex::<16>("00112233445566778899aabbccddeeff"));
+    }
+
+    #[def_test]
+    fn test_sbox() {
+        assert_eq!(SBOX[0x00], 0x63);
+        assert_eq!(SBOX[0x53], 0xed);
+        assert_eq!(INV_SBOX[0xed], 0x53);
+    }
+}
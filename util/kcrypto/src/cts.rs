@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! AES-256-CBC with ciphertext stealing.
+//!
+//! Uses the CS3 variant, as Linux `cts(cbc(aes))` does: the last two blocks
+//! are always swapped, and the ciphertext is as long as the plaintext.
+
+use crate::aes::{Aes256, BLOCK_SIZE, KEY_SIZE};
+
+type Block = [u8; BLOCK_SIZE];
+
+fn xor(dst: &mut [u8], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
+
+/// An AES-256-CBC-CTS key.
+pub struct Aes256Cts {
+    aes: Aes256,
+}
+
+impl Aes256Cts {
+    /// Size of the key in bytes.
+    pub const KEY_SIZE: usize = KEY_SIZE;
+
+    /// Creates the cipher from `key`.
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            aes: Aes256::new(key),
+        }
+    }
+
+    /// Encrypts `data` in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than one block.
+    pub fn encrypt(&self, iv: &Block, data: &mut [u8]) {
+        assert!(data.len() >= BLOCK_SIZE, "CTS message too short");
+        let blocks = data.len().div_ceil(BLOCK_SIZE);
+        if blocks == 1 {
+            let block: &mut Block = data.try_into().unwrap();
+            xor(block, iv);
+            self.aes.encrypt_block(block);
+            return;
+        }
+
+        let mut prev = *iv;
+        for block in data[..(blocks - 1) * BLOCK_SIZE].chunks_exact_mut(BLOCK_SIZE) {
+            let block: &mut Block = block.try_into().unwrap();
+            xor(block, &prev);
+            self.aes.encrypt_block(block);
+            prev = *block;
+        }
+
+        // `prev` is the ciphertext of the second to last block. The last
+        // block is padded with zeroes and chained to it, then the two are
+        // swapped and the (now last) copy of `prev` is truncated.
+        let tail_start = (blocks - 1) * BLOCK_SIZE;
+        let tail_len = data.len() - tail_start;
+        let mut last = [0u8; BLOCK_SIZE];
+        last[..tail_len].copy_from_slice(&data[tail_start..]);
+        xor(&mut last, &prev);
+        self.aes.encrypt_block(&mut last);
+        data[tail_start - BLOCK_SIZE..tail_start].copy_from_slice(&last);
+        data[tail_start..].copy_from_slice(&prev[..tail_len]);
+    }
+
+    /// Decrypts `data` in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than one block.
+    pub fn decrypt(&self, iv: &Block, data: &mut [u8]) {
+        assert!(data.len() >= BLOCK_SIZE, "CTS message too short");
+        let blocks = data.len().div_ceil(BLOCK_SIZE);
+        if blocks == 1 {
+            let block: &mut Block = data.try_into().unwrap();
+            self.aes.decrypt_block(block);
+            xor(block, iv);
+            return;
+        }
+
+        let mut prev = *iv;
+        for block in data[..(blocks - 2) * BLOCK_SIZE].chunks_exact_mut(BLOCK_SIZE) {
+            let block: &mut Block = block.try_into().unwrap();
+            let cipher = *block;
+            self.aes.decrypt_block(block);
+            xor(block, &prev);
+            prev = cipher;
+        }
+
+        let tail_start = (blocks - 1) * BLOCK_SIZE;
+        let tail_len = data.len() - tail_start;
+        let mut last: Block = data[tail_start - BLOCK_SIZE..tail_start].try_into().unwrap();
+        self.aes.decrypt_block(&mut last);
+        // `last` is now the padded plaintext XORed with the stolen block,
+        // whose missing bytes are therefore the ones past the tail.
+        let mut stolen = last;
+        stolen[..tail_len].copy_from_slice(&data[tail_start..]);
+        xor(&mut last[..tail_len], &stolen[..tail_len]);
+
+        let penultimate = &mut data[tail_start - BLOCK_SIZE..tail_start];
+        penultimate.copy_from_slice(&stolen);
+        let penultimate: &mut Block = penultimate.try_into().unwrap();
+        self.aes.decrypt_block(penultimate);
+        xor(penultimate, &prev);
+        data[tail_start..].copy_from_slice(&last[..tail_len]);
+    }
+}
+
+#[cfg(unittest)]
+pub mod tests_cts {
+    use alloc::vec::Vec;
+
+    use unittest::def_test;
+
+    use super::*;
+    use crate::tests_util::{hex, hex_vec};
+
+    #[def_test]
+    fn test_cts_lengths() {
+        let cts = Aes256Cts::new(&hex(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        ));
+        let iv = [0u8; 16];
+        for (len, expected) in [(16, CTS_16), (17, CTS_17), (31, CTS_31), (32, CTS_32), (48, CTS_48)] {
+            let plain = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let mut data = plain.clone();
+            cts.encrypt(&iv, &mut data);
+            assert_eq!(data, hex_vec(expected), "length {len}");
+            cts.decrypt(&iv, &mut data);
+            assert_eq!(data, plain, "length {len}");
+        }
+    }
+
+    const CTS_16: &str = "b7bf3a5df43989dd97f0fa97ebce2f4a";
+    const CTS_17: &str = "b9a591514ed599327f36848aaf93f0c6b7";
+    const CTS_31: &str = "2fd8839572ce5e0d5096eb0399704d5ab7bf3a5df43989dd97f0fa97ebce2f";
+    const CTS_32: &str = "c8eb32a55129827e1ada307db13f67e1b7bf3a5df43989dd97f0fa97ebce2f4a";
+    const CTS_48: &str = "b7bf3a5df43989dd97f0fa97ebce2f4acd52afda53188aa9d29aba596be4b7acc8eb32a55129827e1ada307db13f67e1";
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! HMAC-SHA512 (RFC 2104) and HKDF-SHA512 (RFC 5869).
+
+use crate::{
+    sha512::{BLOCK_SIZE, DIGEST_SIZE, Sha512},
+    zeroize,
+};
+
+/// Incremental HMAC-SHA512.
+#[derive(Clone)]
+pub struct HmacSha512 {
+    inner: Sha512,
+    outer: Sha512,
+}
+
+impl HmacSha512 {
+    /// Creates an HMAC keyed with `key`.
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            block[..DIGEST_SIZE].copy_from_slice(&Sha512::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut pad = block.map(|b| b ^ 0x36);
+        let mut inner = Sha512::new();
+        inner.update(&pad);
+        pad = block.map(|b| b ^ 0x5c);
+        let mut outer = Sha512::new();
+        outer.update(&pad);
+
+        zeroize(&mut block);
+        zeroize(&mut pad);
+        Self { inner, outer }
+    }
+
+    /// Feeds `data` into the MAC.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Finishes the MAC and returns the tag.
+    pub fn finalize(self) -> [u8; DIGEST_SIZE] {
+        let Self { inner, mut outer } = self;
+        outer.update(&inner.finalize());
+        outer.finalize()
+    }
+
+    /// Returns the tag of `data` under `key`.
+    pub fn mac(key: &[u8], data: &[u8]) -> [u8; DIGEST_SIZE] {
+        let mut hmac = Self::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+}
+
+/// HKDF-SHA512 with an extracted pseudorandom key.
+pub struct Hkdf {
+    prk: [u8; DIGEST_SIZE],
+}
+
+impl Hkdf {
+    /// Runs HKDF-Extract on `ikm`. An empty `salt` stands for the default
+    /// salt of all zeroes.
+    pub fn extract(salt: &[u8], ikm: &[u8]) -> Self {
+        let salt = if salt.is_empty() {
+            &[0u8; DIGEST_SIZE][..]
+        } else {
+            salt
+        };
+        Self {
+            prk: HmacSha512::mac(salt, ikm),
+        }
+    }
+
+    /// Runs HKDF-Expand, filling `okm` with key material bound to `info`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `okm` is longer than 255 digests.
+    pub fn expand(&self, info: &[&[u8]], okm: &mut [u8]) {
+        assert!(okm.len() <= 255 * DIGEST_SIZE, "HKDF output too long");
+        let mut previous = [0u8; DIGEST_SIZE];
+        for (i, chunk) in okm.chunks_mut(DIGEST_SIZE).enumerate() {
+            let mut hmac = HmacSha512::new(&self.prk);
+            if i > 0 {
+                hmac.update(&previous);
+            }
+            for part in info {
+                hmac.update(part);
+            }
+            hmac.update(&[i as u8 + 1]);
+            previous = hmac.finalize();
+            chunk.copy_from_slice(&previous[..chunk.len()]);
+        }
+        zeroize(&mut previous);
+    }
+}
+
+impl Drop for Hkdf {
+    fn drop(&mut self) {
+        zeroize(&mut self.prk);
+    }
+}
+
+#[cfg(unittest)]
+pub mod tests_hkdf {
+    use unittest::def_test;
+
+    use super::*;
+    use crate::tests_util::hex;
+
+    #[def_test]
+    fn test_hmac_sha512_rfc4231() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            HmacSha512::mac(b"Jefe", b"what do ya want for nothing?"),
+            hex::<64>(
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                 9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+            )
+        );
+        // Test case 6: the key is longer than a block.
+        assert_eq!(
+            HmacSha512::mac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            hex::<64>(
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352\
+                 6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+            )
+        );
+    }
+
+    #[def_test]
+    fn test_hkdf_sha512() {
+        let hkdf = Hkdf::extract(
+            &hex::<13>("000102030405060708090a0b0c"),
+            &[0x0b; 22],
+        );
+        let mut okm = [0u8; 42];
+        hkdf.expand(&[&hex::<10>("f0f1f2f3f4f5f6f7f8f9")], &mut okm);
+        assert_eq!(
+            okm,
+            hex::<42>(
+                "832390086cda71fb47625bb5ceb168e4c8e26a1a16ed34d9fc7fe92c14815793\
+                 38da362cb8d9f925d7cb"
+            )
+        );
+    }
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Software cryptographic primitives for kernel subsystems.
+//!
+//! Provides the algorithms needed by filesystem encryption: the AES-256 block
+//! cipher in XTS and CBC-CTS modes, SHA-512, HMAC-SHA512 and HKDF-SHA512,
+//! and SHA-256 for checking the integrity of archive members.
+//! Everything is portable Rust without hardware acceleration.
+#![no_std]
+
+#[cfg(unittest)]
+extern crate alloc;
+
+pub mod aes;
+pub mod cts;
+pub mod hkdf;
+pub mod sha256;
+pub mod sha512;
+pub mod xts;
+
+pub use aes::Aes256;
+pub use cts::Aes256Cts;
+pub use hkdf::{Hkdf, HmacSha512};
+pub use sha256::Sha256;
+pub use sha512::Sha512;
+pub use xts::Aes256Xts;
+
+/// Overwrites `buf` with zeroes in a way the compiler will not elide.
+pub fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference.
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(unittest)]
+mod tests_util {
+    use alloc::vec::Vec;
+
+    /// Decodes the hex string `s`.
+    pub fn hex_vec(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Decodes the hex string `s` into an array.
+    pub fn hex<const N: usize>(s: &str) -> [u8; N] {
+        hex_vec(s).try_into().unwrap()
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! AES-256-XTS (IEEE 1619) with ciphertext stealing.
+
+use crate::aes::{Aes256, BLOCK_SIZE, KEY_SIZE};
+
+type Block = [u8; BLOCK_SIZE];
+
+fn xor(dst: &mut Block, src: &Block) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= s;
+    }
+}
+
+/// Multiplies the tweak by the primitive element of GF(2^128).
+fn mul_alpha(tweak: &mut Block) {
+    let mut carry = 0;
+    for byte in tweak.iter_mut() {
+        let next = *byte >> 7;
+        *byte = (*byte << 1) | carry;
+        carry = next;
+    }
+    if carry != 0 {
+        tweak[0] ^= 0x87;
+    }
+}
+
+/// An AES-256-XTS key, which is two AES-256 keys.
+pub struct Aes256Xts {
+    data: Aes256,
+    tweak: Aes256,
+}
+
+impl Aes256Xts {
+    /// Size of the XTS key in bytes.
+    pub const KEY_SIZE: usize = 2 * KEY_SIZE;
+
+    /// Creates the cipher from `key`, the data key followed by the tweak key.
+    pub fn new(key: &[u8; Self::KEY_SIZE]) -> Self {
+        let (data, tweak) = key.split_at(KEY_SIZE);
+        Self {
+            data: Aes256::new(data.try_into().unwrap()),
+            tweak: Aes256::new(tweak.try_into().unwrap()),
+        }
+    }
+
+    fn crypt_block(&self, block: &mut [u8], tweak: &Block, encrypt: bool) {
+        let block: &mut Block = block.try_into().unwrap();
+        xor(block, tweak);
+        if encrypt {
+            self.data.encrypt_block(block);
+        } else {
+            self.data.decrypt_block(block);
+        }
+        xor(block, tweak);
+    }
+
+    /// Encrypts the data unit `data` in place; `iv` selects the unit.
+    ///
+    /// A length that is not a multiple of the block size is handled with
+    /// ciphertext stealing, so the ciphertext is as long as the plaintext.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than one block.
+    pub fn encrypt(&self, iv: &Block, data: &mut [u8]) {
+        self.crypt(iv, data, true);
+    }
+
+    /// Decrypts the data unit `data` in place; `iv` selects the unit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than one block.
+    pub fn decrypt(&self, iv: &Block, data: &mut [u8]) {
+        self.crypt(iv, data, false);
+    }
+
+    fn crypt(&self, iv: &Block, data: &mut [u8], encrypt: bool) {
+        assert!(data.len() >= BLOCK_SIZE, "XTS data unit too short");
+        let mut tweak = *iv;
+        self.tweak.encrypt_block(&mut tweak);
+
+        let tail = data.len() % BLOCK_SIZE;
+        // With stealing, the last full block is processed together with the
+        // partial one.
+        let full = data.len() / BLOCK_SIZE - (tail != 0) as usize;
+        for block in data[..full * BLOCK_SIZE].chunks_exact_mut(BLOCK_SIZE) {
+            self.crypt_block(block, &tweak, encrypt);
+            mul_alpha(&mut tweak);
+        }
+        if tail == 0 {
+            return;
+        }
+
+        let (last, partial) = data[full * BLOCK_SIZE..].split_at_mut(BLOCK_SIZE);
+        let mut next_tweak = tweak;
+        mul_alpha(&mut next_tweak);
+        // Encryption uses the tweaks in order, decryption swaps them.
+        let (first, second) = if encrypt {
+            (&tweak, &next_tweak)
+        } else {
+            (&next_tweak, &tweak)
+        };
+        self.crypt_block(last, first, encrypt);
+        for (l, p) in last.iter_mut().zip(partial.iter_mut()) {
+            core::mem::swap(l, p);
+        }
+        self.crypt_block(last, second, encrypt);
+    }
+}
+
+#[cfg(unittest)]
+pub mod tests_xts {
+    use alloc::vec::Vec;
+
+    use unittest::def_test;
+
+    use super::*;
+    use crate::tests_util::{hex, hex_vec};
+
+    fn key() -> [u8; 64] {
+        hex(
+            "2718281828459045235360287471352662497757247093699959574966967627\
+             3141592653589793238462643383279502884197169399375105820974944592",
+        )
+    }
+
+    #[def_test]
+    fn test_xts_full_blocks() {
+        let xts = Aes256Xts::new(&key());
+        let iv = hex::<16>("ff000000000000000000000000000000");
+        let plain = (0..64).map(|i| i as u8).collect::<Vec<_>>();
+        let mut data = plain.clone();
+        xts.encrypt(&iv, &mut data);
+        assert_eq!(data, hex_vec(XTS_FULL));
+        xts.decrypt(&iv, &mut data);
+        assert_eq!(data, plain);
+    }
+
+    #[def_test]
+    fn test_xts_ciphertext_stealing() {
+        let xts = Aes256Xts::new(&key());
+        let iv = hex::<16>("9a785634120000000000000000000000");
+        let plain = (0..37).map(|i| i as u8).collect::<Vec<_>>();
+        let mut data = plain.clone();
+        xts.encrypt(&iv, &mut data);
+        assert_eq!(data, hex_vec(XTS_STEALING));
+        xts.decrypt(&iv, &mut data);
+        assert_eq!(data, plain);
+    }
+
+    const XTS_FULL: &str = "1c3b3a102f770386e4836c99e370cf9bea00803f5e482357a4ae12d414a3e63b5d31e276f8fe4a8d66b317f9ac683f44680a86ac35adfc3345befecb4bb188fd";
+    const XTS_STEALING: &str = "50ea7b0e72da7912892bcd0c7496baa4070b9ffc97605ee5298d380fb10a7d38b346523120";
+}
@@ -0,0 +1,529 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! DEFLATE decompression (RFC 1951), with the zlib (RFC 1950) and gzip
+//! (RFC 1952) wrappers.
+//!
+//! Huffman codes are decoded a bit at a time from their canonical form,
+//! which needs no tables beyond the code lengths. Back-references are copied
+//! from the output buffer, so it must hold the whole stream.
+
+use crate::{Error, Result};
+
+/// Largest number of literal/length codes.
+const MAX_LIT_CODES: usize = 288;
+/// Largest number of distance codes.
+const MAX_DIST_CODES: usize = 30;
+/// Longest Huffman code, in bits.
+const MAX_CODE_BITS: usize = 15;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths are stored in.
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads the input a bit at a time, least significant bit first.
+struct BitReader<'a> {
+    src: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Self {
+            src,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    /// Reads `n` bits, at most 16.
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.src.get(self.pos).ok_or(Error::Truncated)?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left of the current byte, and returns the next `n`
+    /// bytes.
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        self.buf = 0;
+        self.count = 0;
+        let bytes = self
+            .src
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code.
+struct Huffman<const N: usize> {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_BITS + 1],
+    /// Symbols ordered by code.
+    symbols: [u16; N],
+}
+
+impl<const N: usize> Huffman<N> {
+    /// Builds the code of the symbols with code lengths `lengths`.
+    ///
+    /// Incomplete codes are accepted, as a single distance code is; decoding
+    /// a missing code fails.
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; MAX_CODE_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Error::Corrupted);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_BITS + 1];
+        for len in 1..MAX_CODE_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = [0u16; N];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Corrupted)
+    }
+}
+
+/// The output of a stream, into which back-references point.
+struct Output<'a> {
+    dst: &'a mut [u8],
+    len: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, byte: u8) -> Result<()> {
+        *self.dst.get_mut(self.len).ok_or(Error::OutputTooSmall)? = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> Result<()> {
+        self.dst
+            .get_mut(self.len..self.len + bytes.len())
+            .ok_or(Error::OutputTooSmall)?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    /// Copies `len` bytes from `dist` bytes back, which may overlap what is
+    /// copied.
+    fn copy_back(&mut self, dist: usize, len: usize) -> Result<()> {
+        if dist > self.len {
+            return Err(Error::Corrupted);
+        }
+        if self.len + len > self.dst.len() {
+            return Err(Error::OutputTooSmall);
+        }
+        for i in self.len..self.len + len {
+            self.dst[i] = self.dst[i - dist];
+        }
+        self.len += len;
+        Ok(())
+    }
+}
+
+fn inflate_stored(bits: &mut BitReader, out: &mut Output) -> Result<()> {
+    let header = bits.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(Error::Corrupted);
+    }
+    out.extend(bits.bytes(len as usize)?)
+}
+
+fn inflate_codes(
+    bits: &mut BitReader,
+    out: &mut Output,
+    lit: &Huffman<MAX_LIT_CODES>,
+    dist: &Huffman<MAX_DIST_CODES>,
+) -> Result<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        match symbol {
+            0..256 => out.push(symbol as u8)?,
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LEN_BASE.len() {
+                    return Err(Error::Corrupted);
+                }
+                let len = LEN_BASE[symbol] as usize + bits.bits(LEN_EXTRA[symbol] as u32)? as usize;
+                let symbol = dist.decode(bits)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(Error::Corrupted);
+                }
+                let dist =
+                    DIST_BASE[symbol] as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                out.copy_back(dist, len)?;
+            }
+        }
+    }
+}
+
+fn inflate_fixed(bits: &mut BitReader, out: &mut Output) -> Result<()> {
+    let mut lengths = [0u8; MAX_LIT_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let lit = Huffman::new(&lengths)?;
+    let dist = Huffman::new(&[5; MAX_DIST_CODES])?;
+    inflate_codes(bits, out, &lit, &dist)
+}
+
+fn inflate_dynamic(bits: &mut BitReader, out: &mut Output) -> Result<()> {
+    let nlit = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let nclen = bits.bits(4)? as usize + 4;
+    if nlit > 286 || ndist > MAX_DIST_CODES {
+        return Err(Error::Corrupted);
+    }
+
+    let mut clens = [0u8; 19];
+    for &index in &CLEN_ORDER[..nclen] {
+        clens[index] = bits.bits(3)? as u8;
+    }
+    let clen = Huffman::<19>::new(&clens)?;
+
+    let mut lengths = [0u8; MAX_LIT_CODES + MAX_DIST_CODES];
+    let mut i = 0;
+    while i < nlit + ndist {
+        let symbol = clen.decode(bits)?;
+        let (len, repeat) = match symbol {
+            0..16 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths[..i].last().ok_or(Error::Corrupted)?;
+                (prev, 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if i + repeat > nlit + ndist {
+            return Err(Error::Corrupted);
+        }
+        lengths[i..i + repeat].fill(len);
+        i += repeat;
+    }
+    // There must be a code to end the block with.
+    if lengths[256] == 0 {
+        return Err(Error::Corrupted);
+    }
+
+    let lit = Huffman::new(&lengths[..nlit])?;
+    let dist = Huffman::new(&lengths[nlit..nlit + ndist])?;
+    inflate_codes(bits, out, &lit, &dist)
+}
+
+/// Decompresses the raw DEFLATE stream at the start of `src` into `dst`.
+///
+/// Returns the number of bytes of `src` the stream takes up, and of `dst`
+/// written.
+pub fn inflate(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize)> {
+    let mut bits = BitReader::new(src);
+    let mut out = Output { dst, len: 0 };
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => inflate_stored(&mut bits, &mut out)?,
+            1 => inflate_fixed(&mut bits, &mut out)?,
+            2 => inflate_dynamic(&mut bits, &mut out)?,
+            _ => return Err(Error::Corrupted),
+        }
+        if last {
+            return Ok((bits.pos, out.len));
+        }
+    }
+}
+
+/// Returns the Adler-32 checksum of `data`.
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // The sums cannot overflow over this many bytes.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses the zlib stream `src` into `dst`, and returns the number of
+/// bytes written.
+pub fn zlib_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let [cmf, flg, ..] = *src else {
+        return Err(Error::Truncated);
+    };
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || (cmf as u16 * 256 + flg as u16) % 31 != 0 {
+        return Err(Error::Corrupted);
+    }
+    // A preset dictionary.
+    if flg & 0x20 != 0 {
+        return Err(Error::Unsupported);
+    }
+    let (used, len) = inflate(&src[2..], dst)?;
+    let trailer = src.get(2 + used..2 + used + 4).ok_or(Error::Truncated)?;
+    if u32::from_be_bytes(trailer.try_into().unwrap()) != adler32(&dst[..len]) {
+        return Err(Error::Checksum);
+    }
+    Ok(len)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Returns the CRC-32 of `data`, as gzip and zip compute it.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Returns the CRC-32 of the input that had the CRC-32 `crc` followed by
+/// `data`, for checksumming input that comes in pieces.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Decompresses the gzip member at the start of `src` into `dst`.
+///
+/// Returns the number of bytes of `src` the member takes up, and of `dst`
+/// written. Archives of several members are decompressed one member at a
+/// time.
+pub fn gzip_decompress(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize)> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let header = src.get(..10).ok_or(Error::Truncated)?;
+    if header[..3] != [0x1f, 0x8b, 8] {
+        return Err(Error::Corrupted);
+    }
+    let flags = header[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = src.get(pos..pos + 2).ok_or(Error::Truncated)?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let len = src
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or(Error::Truncated)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let (used, len) = inflate(src.get(pos..).ok_or(Error::Truncated)?, dst)?;
+    pos += used;
+    let trailer = src.get(pos..pos + 8).ok_or(Error::Truncated)?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    if crc != crc32(&dst[..len]) || size != len as u32 {
+        return Err(Error::Checksum);
+    }
+    Ok((pos + 8, len))
+}
+
+#[cfg(unittest)]
+mod tests_inflate {
+    use unittest::def_test;
+
+    use super::*;
+
+    /// `Rust is cool! ` three times and a newline, with fixed codes.
+    const FIXED: &[u8] = &[
+        0x78, 0xda, 0x0b, 0x2a, 0x2d, 0x2e, 0x51, 0xc8, 0x2c, 0x56, 0x48, 0xce, 0xcf, 0xcf, 0x51,
+        0x54, 0x08, 0xc2, 0xcd, 0xe3, 0x02, 0x00, 0x38, 0x92, 0x0e, 0x13,
+    ];
+
+    /// The lines of [`lines`], with dynamic codes.
+    const DYNAMIC: &[u8] = &[
+        0x78, 0xda, 0x7d, 0xd4, 0xcb, 0x4d, 0x03, 0x40, 0x0c, 0x40, 0xc1, 0x3b, 0x55, 0x6c, 0x09,
+        0xf8, 0xb7, 0x09, 0x94, 0x03, 0x0a, 0x22, 0x22, 0x4a, 0x04, 0x02, 0x41, 0xf9, 0x88, 0x02,
+        0x98, 0xf3, 0x3b, 0x79, 0x64, 0xfb, 0x72, 0xbe, 0x9e, 0xd6, 0xfd, 0xe3, 0xfa, 0x7c, 0x3d,
+        0xad, 0xf7, 0xaf, 0xf3, 0xf3, 0xdb, 0x7a, 0xfa, 0xb8, 0x7d, 0x5f, 0xd7, 0xcb, 0xed, 0xe7,
+        0xee, 0xf2, 0xd7, 0x02, 0x2d, 0xd1, 0x0a, 0xad, 0xd1, 0x06, 0x6d, 0xa3, 0x1d, 0xd0, 0x8e,
+        0x68, 0x0f, 0x9a, 0x9d, 0x30, 0x92, 0x09, 0xd1, 0x84, 0x6c, 0x42, 0x38, 0x21, 0x9d, 0x10,
+        0x4f, 0xc8, 0x27, 0x04, 0x14, 0x12, 0x4a, 0x09, 0x25, 0x77, 0x47, 0x42, 0x29, 0xa1, 0x94,
+        0x50, 0x4a, 0x28, 0x25, 0x94, 0x12, 0x4a, 0x09, 0xa5, 0x84, 0x4a, 0x42, 0x25, 0xa1, 0xe2,
+        0x79, 0x49, 0xa8, 0x24, 0x54, 0x12, 0x2a, 0x09, 0x95, 0x84, 0x4a, 0x42, 0x25, 0xa1, 0x96,
+        0x50, 0x4b, 0xa8, 0x25, 0xd4, 0xfc, 0x40, 0x12, 0x6a, 0x09, 0xb5, 0x84, 0x5a, 0x42, 0x2d,
+        0xa1, 0x96, 0xd0, 0x48, 0x68, 0x24, 0x34, 0x12, 0x1a, 0x09, 0x0d, 0x9f, 0xb4, 0x84, 0x46,
+        0x42, 0x23, 0xa1, 0x91, 0xd0, 0x48, 0x68, 0x4b, 0x68, 0x4b, 0x68, 0x4b, 0x68, 0xff, 0x23,
+        0xf4, 0x0b, 0x53, 0xaf, 0x6f, 0xc1,
+    ];
+
+    /// `abc` in a stored block.
+    const STORED: &[u8] = &[
+        0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27,
+    ];
+
+    /// Writes `line {i}: the quick brown fox` for the first 64 `i` to `buf`,
+    /// and returns the length.
+    fn lines(buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for i in 0..64 {
+            let mut push = |bytes: &[u8]| {
+                buf[len..len + bytes.len()].copy_from_slice(bytes);
+                len += bytes.len();
+            };
+            push(b"line ");
+            if i >= 10 {
+                push(&[b'0' + i / 10]);
+            }
+            push(&[b'0' + i % 10]);
+            push(b": the quick brown fox\n");
+        }
+        len
+    }
+
+    #[def_test]
+    fn test_zlib_fixed() {
+        let mut buf = [0; 64];
+        let len = zlib_decompress(FIXED, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"Rust is cool! Rust is cool! Rust is cool!\n");
+    }
+
+    #[def_test]
+    fn test_zlib_dynamic() {
+        let mut expected = [0; 2048];
+        let len = lines(&mut expected);
+        let expected = &expected[..len];
+        let mut buf = [0; 2048];
+        let len = zlib_decompress(DYNAMIC, &mut buf).unwrap();
+        assert_eq!(&buf[..len], expected);
+
+        assert_eq!(
+            zlib_decompress(DYNAMIC, &mut buf[..len - 1]),
+            Err(Error::OutputTooSmall)
+        );
+    }
+
+    #[def_test]
+    fn test_zlib_stored() {
+        let mut buf = [0; 3];
+        assert_eq!(zlib_decompress(STORED, &mut buf), Ok(3));
+        assert_eq!(&buf, b"abc");
+    }
+
+    #[def_test]
+    fn test_zlib_errors() {
+        let mut buf = [0; 64];
+        assert_eq!(
+            zlib_decompress(&FIXED[..FIXED.len() - 6], &mut buf),
+            Err(Error::Truncated)
+        );
+        let mut corrupted = [0; FIXED.len()];
+        corrupted.copy_from_slice(FIXED);
+        corrupted[FIXED.len() - 1] ^= 1;
+        assert_eq!(zlib_decompress(&corrupted, &mut buf), Err(Error::Checksum));
+        assert_eq!(
+            zlib_decompress(&[0x78, 0x00], &mut buf),
+            Err(Error::Corrupted)
+        );
+    }
+
+    #[def_test]
+    fn test_gzip() {
+        // The stream of `STORED` in a member named `a`.
+        let mut member = [0; 10 + 2 + 8 + 8];
+        member[..10].copy_from_slice(&[0x1f, 0x8b, 8, 0x08, 0, 0, 0, 0, 0, 3]);
+        member[10..12].copy_from_slice(b"a\0");
+        member[12..20].copy_from_slice(&STORED[2..10]);
+        member[20..24].copy_from_slice(&crc32(b"abc").to_le_bytes());
+        member[24..].copy_from_slice(&3u32.to_le_bytes());
+
+        let mut buf = [0; 3];
+        assert_eq!(gzip_decompress(&member, &mut buf), Ok((member.len(), 3)));
+        assert_eq!(&buf, b"abc");
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Software decompressors for kernel subsystems.
+//!
+//! Provides the formats needed to read compressed images: DEFLATE streams,
+//! raw or wrapped in zlib (as squashfs stores them) or gzip (as initramfs
+//! images are), and LZ4 blocks, along with the CRC-32 that zip and gzip
+//! check their contents with. Decompression is one-shot into a buffer the
+//! caller sizes, and allocates nothing.
+#![no_std]
+
+pub mod inflate;
+pub mod lz4;
+
+pub use inflate::{crc32, crc32_update, gzip_decompress, inflate, zlib_decompress};
+pub use lz4::lz4_decompress;
+
+/// Errors of the decompressors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The input ended before the end of the stream.
+    Truncated,
+    /// The input is not a valid stream.
+    Corrupted,
+    /// The stream uses a feature that is not supported, such as a preset
+    /// dictionary.
+    Unsupported,
+    /// The output does not fit in the buffer.
+    OutputTooSmall,
+    /// The checksum of the output does not match the stream.
+    Checksum,
+}
+
+/// Result type of the decompressors.
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! LZ4 block decompression.
+//!
+//! Only the block format is supported, not the frame format around it: the
+//! size of a block is known from the container, as it is for squashfs.
+
+use crate::{Error, Result};
+
+/// Reads a length continued by bytes of 255, starting from `base`.
+fn read_len(src: &[u8], pos: &mut usize, base: usize) -> Result<usize> {
+    let mut len = base;
+    if base == 15 {
+        loop {
+            let byte = *src.get(*pos).ok_or(Error::Truncated)?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Decompresses the LZ4 block `src` into `dst`, and returns the number of
+/// bytes written.
+pub fn lz4_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let mut pos = 0;
+    let mut len = 0;
+    loop {
+        let token = *src.get(pos).ok_or(Error::Truncated)?;
+        pos += 1;
+
+        let literals = read_len(src, &mut pos, (token >> 4) as usize)?;
+        let literals = src.get(pos..pos + literals).ok_or(Error::Truncated)?;
+        dst.get_mut(len..len + literals.len())
+            .ok_or(Error::OutputTooSmall)?
+            .copy_from_slice(literals);
+        pos += literals.len();
+        len += literals.len();
+        // The last sequence has only literals.
+        if pos == src.len() {
+            return Ok(len);
+        }
+
+        let offset = src.get(pos..pos + 2).ok_or(Error::Truncated)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        pos += 2;
+        if offset == 0 || offset > len {
+            return Err(Error::Corrupted);
+        }
+        let matched = read_len(src, &mut pos, (token & 0x0f) as usize)? + 4;
+        if len + matched > dst.len() {
+            return Err(Error::OutputTooSmall);
+        }
+        // The match may overlap what it copies.
+        for i in len..len + matched {
+            dst[i] = dst[i - offset];
+        }
+        len += matched;
+    }
+}
+
+#[cfg(unittest)]
+mod tests_lz4 {
+    use unittest::def_test;
+
+    use super::*;
+
+    #[def_test]
+    fn test_lz4_literals() {
+        let mut buf = [0; 5];
+        assert_eq!(lz4_decompress(b"\x50hello", &mut buf), Ok(5));
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[def_test]
+    fn test_lz4_overlapping_match() {
+        // `abc`, 9 bytes from 3 back, then `x`.
+        let mut buf = [0; 13];
+        assert_eq!(lz4_decompress(b"\x35abc\x03\x00\x10x", &mut buf), Ok(13));
+        assert_eq!(&buf, b"abcabcabcabcx");
+    }
+
+    #[def_test]
+    fn test_lz4_long_lengths() {
+        // 15 + 5 literals, a match of 15 + 5 + 4 bytes, and a last literal.
+        let mut block = [0u8; 27];
+        block[..2].copy_from_slice(&[0xff, 5]);
+        block[2..22].fill(b'a');
+        block[22..].copy_from_slice(&[1, 0, 5, 0x10, b'b']);
+        let mut buf = [0; 64];
+        assert_eq!(lz4_decompress(&block, &mut buf), Ok(45));
+        assert!(buf[..44].iter().all(|&b| b == b'a'));
+        assert_eq!(buf[44], b'b');
+    }
+
+    #[def_test]
+    fn test_lz4_errors() {
+        let mut buf = [0; 16];
+        assert_eq!(
+            lz4_decompress(b"\x35abc\x04\x00\x10x", &mut buf),
+            Err(Error::Corrupted)
+        );
+        assert_eq!(lz4_decompress(b"\x50hel", &mut buf), Err(Error::Truncated));
+        assert_eq!(
+            lz4_decompress(b"\x35abc\x03\x00\x10x", &mut buf[..12]),
+            Err(Error::OutputTooSmall)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Logging storm: several threads log as fast as they can to a slow console,
+//! here stdout, and the latency of each logging call is measured.
+//!
+//! Queued records drained by a writer thread are compared against writing
+//! each line out under one global lock, as klogger used to. The results go
+//! to stderr: run with stdout on a terminal to see the effect of a slow
+//! console, e.g. `cargo bench -p klogger --features std`.
+
+use std::{
+    hint::black_box,
+    io::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use klogger::{LogQueue, LogSlot};
+
+const THREADS: usize = 4;
+const RECORDS: usize = 2000;
+const QUEUE_SLOTS: usize = 1024;
+
+/// Runs `log` `RECORDS` times on each of `THREADS` threads at once, returning
+/// the duration of every call.
+fn storm(log: impl Fn(usize, usize) + Sync) -> Vec<Duration> {
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let log = &log;
+                s.spawn(move || {
+                    (0..RECORDS)
+                        .map(|i| {
+                            let start = Instant::now();
+                            log(t, black_box(i));
+                            start.elapsed()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    eprintln!(
+        "{name:>8}: p50 {:>10?}  p99 {:>10?}  max {:>10?}",
+        percentile(50),
+        percentile(99),
+        latencies[latencies.len() - 1],
+    );
+}
+
+fn main() {
+    static SLOTS: [LogSlot; QUEUE_SLOTS] = [const { LogSlot::new() }; QUEUE_SLOTS];
+    static QUEUE: LogQueue = LogQueue::new(&SLOTS);
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    klogger::init_klogger();
+    klogger::set_log_level("warn");
+    klogger::set_log_queue(&QUEUE);
+
+    report(
+        "locked",
+        storm(|t, i| {
+            let _guard = LOCK.lock().unwrap();
+            let mut out = std::io::stdout().lock();
+            let _ = writeln!(out, "[storm] thread {t} record {i}");
+            let _ = out.flush();
+        }),
+    );
+
+    let stop = AtomicBool::new(false);
+    let queued = thread::scope(|s| {
+        s.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                klogger::drain_log_queue();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        klogger::set_async_logging(true);
+        let latencies = storm(|t, i| klogger::warn!("[storm] thread {t} record {i}"));
+        stop.store(true, Ordering::Relaxed);
+        latencies
+    });
+    klogger::set_async_logging(false);
+    report("queued", queued);
+    eprintln!("{:>8}: {} records", "dropped", klogger::log_dropped(0));
+}
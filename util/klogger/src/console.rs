@@ -0,0 +1,434 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Consoles records are written to besides the one of the platform.
+//!
+//! Each console declares itself fast, such as a UART at a high baud rate or
+//! a memory ring, or slow, such as a framebuffer or a netconsole. Records are
+//! written to fast consoles as they leave the submission queue, so those at
+//! or above the level set by [`set_sync_log_level`](crate::set_sync_log_level)
+//! reach them before the logging call returns. A slow console gets a backlog
+//! of its own instead, which the log writer task works through a few records
+//! at a time, see [`drain_log_queue`](crate::drain_log_queue). Nothing waits
+//! for a slow console, except before the writer task runs and once the
+//! system panics.
+//!
+//! When the backlog of a slow console is full, records are dropped for that
+//! console alone and counted, see [`log_consoles`].
+
+use core::{
+    fmt::{self, Write},
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use kspin::SpinNoIrq;
+use log::Level;
+
+use crate::{queue::RecordMeta, ring::RecordBuf};
+
+/// Number of consoles that can be registered.
+pub const MAX_LOG_CONSOLES: usize = 4;
+
+/// Bytes of records the backlog of a slow console holds.
+pub const LOG_BACKLOG_SIZE: usize = 8 * 1024;
+
+/// Records written to a slow console at a time by the log writer task.
+pub(crate) const BACKLOG_BATCH: usize = 8;
+
+/// Attempts at taking a slow console once the system panics, before leaving
+/// its backlog to the CPU writing to it.
+const PANIC_CLAIM_SPINS: usize = 1 << 16;
+
+/// Level, start of the colored message and length of a backlog record.
+const HEADER_LEN: usize = 5;
+
+/// No colored message in a backlog record header.
+const NO_COLOR: u16 = u16::MAX;
+
+/// A console records are written to, see [`register_log_console`].
+pub trait LogConsole: Sync {
+    /// Returns the name of the console, such as `fbcon`.
+    fn name(&self) -> &str;
+
+    /// Returns whether the console takes output quickly, rather than
+    /// rendering it or sending it over the network.
+    fn is_fast(&self) -> bool;
+
+    /// Writes `s` out.
+    fn write_str(&self, s: &str);
+}
+
+/// Statistics of a registered console.
+#[derive(Debug, Clone, Copy)]
+pub struct LogConsoleStats {
+    pub name: &'static str,
+    pub fast: bool,
+    /// Records written out.
+    pub written: usize,
+    /// Bytes of records waiting in the backlog.
+    pub queued: usize,
+    /// Most bytes of records that have waited in the backlog at once.
+    pub high_water: usize,
+    /// Records dropped because the backlog was full.
+    pub dropped: usize,
+}
+
+/// Records waiting for a slow console, each stored as a header followed by
+/// the record text.
+struct Backlog {
+    data: [u8; LOG_BACKLOG_SIZE],
+    /// Offset of the oldest record.
+    head: usize,
+    /// Bytes in use, headers included.
+    used: usize,
+}
+
+impl Backlog {
+    const fn new() -> Self {
+        Self {
+            data: [0; LOG_BACKLOG_SIZE],
+            head: 0,
+            used: 0,
+        }
+    }
+
+    fn copy_in(&mut self, pos: usize, data: &[u8]) {
+        let first = data.len().min(LOG_BACKLOG_SIZE - pos);
+        self.data[pos..pos + first].copy_from_slice(&data[..first]);
+        self.data[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    fn copy_out(&self, pos: usize, out: &mut [u8]) {
+        let first = out.len().min(LOG_BACKLOG_SIZE - pos);
+        out[..first].copy_from_slice(&self.data[pos..pos + first]);
+        let rest = out.len() - first;
+        out[first..].copy_from_slice(&self.data[..rest]);
+    }
+
+    /// Appends a record, returning `false` if it does not fit.
+    fn push(&mut self, meta: &RecordMeta, text: &[u8]) -> bool {
+        let need = HEADER_LEN + text.len();
+        if LOG_BACKLOG_SIZE - self.used < need {
+            return false;
+        }
+        let color_from = meta.color_from.map_or(NO_COLOR, |it| it as u16);
+        let mut header = [0; HEADER_LEN];
+        header[0] = meta.level.map_or(0, |level| level as u8);
+        header[1..3].copy_from_slice(&color_from.to_le_bytes());
+        header[3..].copy_from_slice(&(text.len() as u16).to_le_bytes());
+        let tail = (self.head + self.used) % LOG_BACKLOG_SIZE;
+        self.copy_in(tail, &header);
+        self.copy_in((tail + HEADER_LEN) % LOG_BACKLOG_SIZE, text);
+        self.used += need;
+        true
+    }
+
+    /// Moves the oldest record into `text`, returning how to write it out.
+    fn pop(&mut self, text: &mut RecordBuf) -> Option<RecordMeta> {
+        if self.used == 0 {
+            return None;
+        }
+        let mut header = [0; HEADER_LEN];
+        self.copy_out(self.head, &mut header);
+        let level = match header[0] {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        };
+        let color_from = u16::from_le_bytes([header[1], header[2]]);
+        let len = u16::from_le_bytes([header[3], header[4]]) as usize;
+        let data = (self.head + HEADER_LEN) % LOG_BACKLOG_SIZE;
+        text.fill(len, |buf| self.copy_out(data, buf));
+        self.head = (data + len) % LOG_BACKLOG_SIZE;
+        self.used -= HEADER_LEN + len;
+        Some(RecordMeta {
+            level,
+            color_from: (color_from != NO_COLOR).then_some(color_from as usize),
+        })
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.used = 0;
+    }
+}
+
+/// A registered console and the state of its output.
+struct ConsoleSlot {
+    console: SpinNoIrq<Option<&'static dyn LogConsole>>,
+    backlog: SpinNoIrq<Backlog>,
+    /// Set while a CPU writes to the slow console, which one does at a time.
+    busy: AtomicBool,
+    written: AtomicUsize,
+    high_water: AtomicUsize,
+    dropped: AtomicUsize,
+    /// Dropped records reported to the console so far.
+    reported: AtomicUsize,
+}
+
+impl ConsoleSlot {
+    const fn new() -> Self {
+        Self {
+            console: SpinNoIrq::new(None),
+            backlog: SpinNoIrq::new(Backlog::new()),
+            busy: AtomicBool::new(false),
+            written: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            reported: AtomicUsize::new(0),
+        }
+    }
+
+    fn console(&self) -> Option<&'static dyn LogConsole> {
+        *self.console.lock()
+    }
+
+    fn write(&self, console: &'static dyn LogConsole, meta: &RecordMeta, text: &RecordBuf) {
+        crate::write_colored(&mut ConsoleWriter(console), meta, text);
+        self.written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn queue(&self, meta: &RecordMeta, text: &RecordBuf) {
+        let mut backlog = self.backlog.lock();
+        if backlog.push(meta, text.as_bytes()) {
+            self.high_water.fetch_max(backlog.used, Ordering::Relaxed);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the right to write to the slow console, trying `spins` times.
+    fn claim(&self, spins: usize) -> bool {
+        (0..spins).any(|_| {
+            let claimed = !self.busy.swap(true, Ordering::Acquire);
+            if !claimed {
+                spin_loop();
+            }
+            claimed
+        })
+    }
+
+    fn release(&self) {
+        self.busy.store(false, Ordering::Release);
+    }
+
+    /// Writes up to `batch` records of the backlog to `console`, which must
+    /// be claimed, and returns how many.
+    fn write_backlog(&self, console: &'static dyn LogConsole, batch: usize) -> usize {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        let reported = self.reported.swap(dropped, Ordering::Relaxed);
+        if dropped != reported {
+            let _ = writeln!(
+                ConsoleWriter(console),
+                "[klogger: {} records dropped]",
+                dropped - reported
+            );
+        }
+        let mut text = RecordBuf::new();
+        let mut written = 0;
+        while written < batch {
+            let Some(meta) = self.backlog.lock().pop(&mut text) else {
+                break;
+            };
+            self.write(console, &meta, &text);
+            written += 1;
+        }
+        written
+    }
+
+    fn stats(&self) -> Option<LogConsoleStats> {
+        let console = self.console()?;
+        Some(LogConsoleStats {
+            name: console.name(),
+            fast: console.is_fast(),
+            written: self.written.load(Ordering::Relaxed),
+            queued: self.backlog.lock().used,
+            high_water: self.high_water.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Adapts a console to [`fmt::Write`].
+struct ConsoleWriter(&'static dyn LogConsole);
+
+impl Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+static CONSOLES: [ConsoleSlot; MAX_LOG_CONSOLES] = [const { ConsoleSlot::new() }; MAX_LOG_CONSOLES];
+
+/// Writes a record leaving the submission queue to the registered consoles:
+/// to the fast ones, and with `sync` to the slow ones too, which otherwise
+/// get it queued.
+pub(crate) fn emit(meta: &RecordMeta, text: &RecordBuf, sync: bool) {
+    for slot in &CONSOLES {
+        let Some(console) = slot.console() else {
+            continue;
+        };
+        if console.is_fast() {
+            slot.write(console, meta, text);
+        } else if sync && slot.claim(PANIC_CLAIM_SPINS) {
+            // Records queued before come first.
+            slot.write_backlog(console, usize::MAX);
+            slot.write(console, meta, text);
+            slot.release();
+        } else {
+            slot.queue(meta, text);
+        }
+    }
+}
+
+/// Writes up to `batch` queued records to each slow console no other CPU is
+/// writing to. Returns the number of records written.
+pub(crate) fn drain_backlogs(batch: usize) -> usize {
+    let mut written = 0;
+    for slot in &CONSOLES {
+        let Some(console) = slot.console() else {
+            continue;
+        };
+        if slot.claim(1) {
+            written += slot.write_backlog(console, batch);
+            slot.release();
+        }
+    }
+    written
+}
+
+/// Writes all queued records to the slow consoles once the system panics.
+///
+/// A console is only waited for briefly: if the CPU writing to it never
+/// lets go, its records are left queued.
+pub(crate) fn flush_backlogs_for_panic() {
+    for slot in &CONSOLES {
+        let Some(console) = slot.console() else {
+            continue;
+        };
+        if slot.claim(PANIC_CLAIM_SPINS) {
+            slot.write_backlog(console, usize::MAX);
+            slot.release();
+        }
+    }
+}
+
+/// Returns whether records are waiting for a slow console.
+pub fn log_backlog_pending() -> bool {
+    CONSOLES.iter().any(|slot| slot.backlog.lock().used > 0)
+}
+
+/// Registers `console` to be written every record logged from now on.
+///
+/// Returns `false` if [`MAX_LOG_CONSOLES`] consoles are registered already.
+pub fn register_log_console(console: &'static dyn LogConsole) -> bool {
+    for slot in &CONSOLES {
+        let mut current = slot.console.lock();
+        if current.is_none() {
+            slot.backlog.lock().clear();
+            for counter in [
+                &slot.written,
+                &slot.high_water,
+                &slot.dropped,
+                &slot.reported,
+            ] {
+                counter.store(0, Ordering::Relaxed);
+            }
+            *current = Some(console);
+            return true;
+        }
+    }
+    false
+}
+
+/// Unregisters the console named `name`, discarding the records queued for
+/// it. Returns `false` if there is none.
+///
+/// Once this returns, no CPU writes to the console any more.
+pub fn unregister_log_console(name: &str) -> bool {
+    for slot in &CONSOLES {
+        let mut current = slot.console.lock();
+        if current.is_some_and(|console| console.name() == name) {
+            *current = None;
+            drop(current);
+            slot.backlog.lock().clear();
+            while slot.busy.load(Ordering::Acquire) {
+                spin_loop();
+            }
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the statistics of the registered consoles.
+pub fn log_consoles() -> impl Iterator<Item = LogConsoleStats> {
+    CONSOLES.iter().filter_map(ConsoleSlot::stats)
+}
+
+#[cfg(unittest)]
+mod tests_console {
+    use core::fmt::Write;
+
+    use log::Level;
+    use unittest::def_test;
+
+    use super::{Backlog, HEADER_LEN, LOG_BACKLOG_SIZE};
+    use crate::{queue::RecordMeta, ring::RecordBuf};
+
+    fn meta(level: Option<Level>, color_from: Option<usize>) -> RecordMeta {
+        RecordMeta { level, color_from }
+    }
+
+    #[def_test]
+    fn test_backlog_round_trip() {
+        let mut backlog = Backlog::new();
+        assert!(backlog.push(&meta(Some(Level::Warn), Some(3)), b"[x] warned\n"));
+        assert!(backlog.push(&meta(None, None), b"raw"));
+
+        let mut text = RecordBuf::new();
+        let first = backlog.pop(&mut text).unwrap();
+        assert_eq!(first.level, Some(Level::Warn));
+        assert_eq!(first.color_from, Some(3));
+        assert_eq!(text.as_str(), "[x] warned\n");
+        let second = backlog.pop(&mut text).unwrap();
+        assert_eq!(second.level, None);
+        assert_eq!(second.color_from, None);
+        assert_eq!(text.as_str(), "raw");
+        assert!(backlog.pop(&mut text).is_none());
+    }
+
+    #[def_test]
+    fn test_backlog_full_and_wrap() {
+        let mut backlog = Backlog::new();
+        let mut record = RecordBuf::new();
+        let _ = write!(record, "{:1000}", "record");
+        let per_record = HEADER_LEN + record.as_bytes().len();
+        let fits = LOG_BACKLOG_SIZE / per_record;
+        for _ in 0..fits {
+            assert!(backlog.push(&meta(Some(Level::Info), None), record.as_bytes()));
+        }
+        // Full: the record is dropped rather than one queued before.
+        assert!(!backlog.push(&meta(Some(Level::Info), None), record.as_bytes()));
+
+        // Records stored across the end of the storage read back whole.
+        let mut text = RecordBuf::new();
+        for _ in 0..3 {
+            assert!(backlog.pop(&mut text).is_some());
+            assert!(backlog.push(&meta(Some(Level::Info), None), record.as_bytes()));
+        }
+        let mut popped = 0;
+        while backlog.pop(&mut text).is_some() {
+            assert_eq!(text.as_bytes(), record.as_bytes());
+            popped += 1;
+        }
+        assert_eq!(popped, fits);
+        assert_eq!(backlog.used, 0);
+    }
+}
@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Selectable layouts of a log line.
+
+use core::{
+    fmt::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+use log::Level;
+
+/// Layout of the log lines written to the console and the ring buffer.
+#[repr(u8)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[secs.micros cpu:name-tid path:line] msg`, colored by level.
+    #[default]
+    Human   = 0,
+    /// `[secs.micros cpu:name-tid L path:line] msg`, where `L` is the first
+    /// letter of the level. Never colored.
+    Compact = 1,
+    /// One JSON object per line with the fields `ts`, `ref`, `level`, `cpu`,
+    /// `tid`, `comm`, `target`, `line` and `msg`, where `ref` is the raw
+    /// reference clock count `ts` was taken from. Never colored.
+    Json    = 2,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+static LOG_FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Human as u8);
+
+/// Selects the layout of subsequent log lines.
+pub fn set_log_format(format: LogFormat) {
+    LOG_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Returns the current layout of log lines.
+pub fn log_format() -> LogFormat {
+    match LOG_FORMAT.load(Ordering::Relaxed) {
+        1 => LogFormat::Compact,
+        2 => LogFormat::Json,
+        _ => LogFormat::Human,
+    }
+}
+
+/// The time a record was logged at: a reading of the reference clock and its
+/// monotonic time.
+#[derive(Clone, Copy)]
+pub(crate) struct Timestamp {
+    pub ticks: u64,
+    pub time: Duration,
+}
+
+/// The CPU and task a record was logged from, printed as ` cpu:name-tid`,
+/// or ` cpu:tid` if the task has no name.
+pub(crate) struct RecordContext {
+    pub cpu_id: Option<usize>,
+    pub task_id: Option<u64>,
+    /// The NUL-padded task name.
+    pub task_name: Option<[u8; 16]>,
+}
+
+impl RecordContext {
+    /// Returns the task name, up to its first NUL byte or invalid UTF-8
+    /// sequence, if it is not empty.
+    fn task_name(&self) -> Option<&str> {
+        let name = self.task_name.as_ref()?;
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let name = match core::str::from_utf8(&name[..len]) {
+            Ok(name) => name,
+            // Safety: the bytes up to `valid_up_to` are valid UTF-8.
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&name[..e.valid_up_to()]) },
+        };
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+impl fmt::Display for RecordContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.cpu_id, self.task_id, self.task_name()) {
+            (Some(c), Some(t), Some(name)) => write!(f, " {c}:{name}-{t}"),
+            (Some(c), Some(t), None) => write!(f, " {c}:{t}"),
+            (Some(c), None, _) => write!(f, " {c}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A complete, uncolored log line in the [`LogFormat::Compact`] or
+/// [`LogFormat::Json`] layout, newline included.
+pub(crate) struct PlainLine<'a> {
+    pub format: LogFormat,
+    pub now: Timestamp,
+    pub ctx: RecordContext,
+    pub level: Level,
+    pub target: &'a str,
+    pub line: u32,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for PlainLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (secs, micros) = (self.now.time.as_secs(), self.now.time.subsec_micros());
+        if self.format != LogFormat::Json {
+            let level = &self.level.as_str()[..1];
+            return writeln!(
+                f,
+                "[{secs:>3}.{micros:06}{ctx} {level} {target}:{line}] {args}",
+                ctx = self.ctx,
+                target = self.target,
+                line = self.line,
+                args = self.args,
+            );
+        }
+
+        write!(
+            f,
+            "{{\"ts\":{secs}.{micros:06},\"ref\":{},\"level\":\"{}\",\"cpu\":",
+            self.now.ticks, self.level
+        )?;
+        match self.ctx.cpu_id {
+            Some(cpu) => write!(f, "{cpu}")?,
+            None => f.write_str("null")?,
+        }
+        f.write_str(",\"tid\":")?;
+        match self.ctx.task_id {
+            Some(tid) => write!(f, "{tid}")?,
+            None => f.write_str("null")?,
+        }
+        f.write_str(",\"comm\":")?;
+        match self.ctx.task_name() {
+            Some(name) => write!(f, "\"{}\"", JsonStr(name))?,
+            None => f.write_str("null")?,
+        }
+        writeln!(
+            f,
+            ",\"target\":\"{}\",\"line\":{},\"msg\":\"{}\"}}",
+            JsonStr(self.target),
+            self.line,
+            JsonStr(self.args),
+        )
+    }
+}
+
+/// Displays its content escaped for use inside a JSON string.
+struct JsonStr<T>(T);
+
+impl<T: fmt::Display> fmt::Display for JsonStr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(JsonEscape(f), "{}", self.0)
+    }
+}
+
+struct JsonEscape<W>(W);
+
+impl<W: Write> Write for JsonEscape<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Copy unescaped runs in one go.
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let escaped = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                c if (c as u32) < 0x20 => "",
+                _ => continue,
+            };
+            self.0.write_str(&s[start..i])?;
+            if escaped.is_empty() {
+                write!(self.0, "\\u{:04x}", c as u32)?;
+            } else {
+                self.0.write_str(escaped)?;
+            }
+            start = i + c.len_utf8();
+        }
+        self.0.write_str(&s[start..])
+    }
+}
+
+#[cfg(unittest)]
+mod tests_format {
+    use core::{fmt::Write, time::Duration};
+
+    use log::Level;
+    use unittest::def_test;
+
+    use super::{LogFormat, PlainLine, RecordContext, Timestamp};
+    use crate::ring::RecordBuf;
+
+    fn render(format: LogFormat, ctx: RecordContext, msg: &str) -> RecordBuf {
+        let mut buf = RecordBuf::new();
+        let _ = write!(
+            buf,
+            "{}",
+            PlainLine {
+                format,
+                now: Timestamp {
+                    ticks: 75_001_050,
+                    time: Duration::from_micros(3_000_042),
+                },
+                ctx,
+                level: Level::Warn,
+                target: "kapi::fs",
+                line: 7,
+                args: format_args!("{msg}"),
+            }
+        );
+        buf
+    }
+
+    #[def_test]
+    fn test_json_line() {
+        let ctx = RecordContext {
+            cpu_id: Some(1),
+            task_id: Some(5),
+            task_name: Some(*b"sh\"\0\0\0\0\0\0\0\0\0\0\0\0\0"),
+        };
+        let buf = render(LogFormat::Json, ctx, "open \"a\\b\"\n\x01");
+        assert_eq!(
+            buf.as_bytes(),
+            b"{\"ts\":3.000042,\"ref\":75001050,\"level\":\"WARN\",\"cpu\":1,\"tid\":5,\"comm\":\"sh\\\"\",\"target\":\"kapi::fs\",\
+              \"line\":7,\"msg\":\"open \\\"a\\\\b\\\"\\n\\u0001\"}\n"
+        );
+    }
+
+    #[def_test]
+    fn test_json_line_without_context() {
+        let ctx = RecordContext {
+            cpu_id: None,
+            task_id: None,
+            task_name: None,
+        };
+        let buf = render(LogFormat::Json, ctx, "x");
+        let line = core::str::from_utf8(buf.as_bytes()).unwrap();
+        assert!(line.contains("\"cpu\":null,\"tid\":null,\"comm\":null,"));
+    }
+
+    #[def_test]
+    fn test_compact_line() {
+        let ctx = RecordContext {
+            cpu_id: Some(0),
+            task_id: None,
+            task_name: None,
+        };
+        let buf = render(LogFormat::Compact, ctx, "hello");
+        assert_eq!(buf.as_bytes(), b"[  3.000042 0 W kapi::fs:7] hello\n");
+    }
+
+    #[def_test]
+    fn test_compact_line_with_task_name() {
+        let mut name = [0; 16];
+        name[..6].copy_from_slice(b"init\xff!");
+        let ctx = RecordContext {
+            cpu_id: Some(2),
+            task_id: Some(9),
+            task_name: Some(name),
+        };
+        let buf = render(LogFormat::Compact, ctx, "hello");
+        assert_eq!(
+            buf.as_bytes(),
+            b"[  3.000042 2:init-9 W kapi::fs:7] hello\n"
+        );
+
+        let ctx = RecordContext {
+            cpu_id: Some(2),
+            task_id: Some(9),
+            task_name: Some([0; 16]),
+        };
+        let buf = render(LogFormat::Compact, ctx, "hello");
+        assert_eq!(buf.as_bytes(), b"[  3.000042 2:9 W kapi::fs:7] hello\n");
+    }
+
+    #[def_test]
+    fn test_parse_format() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("compact".parse(), Ok(LogFormat::Compact));
+        assert_eq!("human".parse(), Ok(LogFormat::Human));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
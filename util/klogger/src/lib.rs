@@ -3,19 +3,71 @@
 // See LICENSES for license details.
 
 //! Kernel logging utilities and macros.
+//!
+//! Besides the global level set by [`set_log_level`], individual modules can
+//! be given their own level with [`set_module_log_level`]. The per-module
+//! lookup sits behind a static key, so it costs nothing until the first
+//! override is installed.
+//!
+//! Every record is also kept, without colors, in an in-memory ring buffer
+//! that can be read back with [`log_buffer_read`], e.g. to implement `dmesg`.
+//!
+//! Lines are laid out for people by default; [`set_log_format`] switches to
+//! an uncolored compact layout or to one JSON object per line for log
+//! collectors.
+//!
+//! Logging never waits for the console: records are queued without taking a
+//! lock and written out later by the log writer task, see
+//! [`set_async_logging`]. Records at or above the level set by
+//! [`set_sync_log_level`], `Error` by default, and those logged while
+//! panicking are still written out before the logging call returns.
+//!
+//! Besides the console of the platform, records go to the consoles added
+//! with [`register_log_console`]. Those that declare themselves slow are
+//! only written to by the log writer task, in bounded batches, so that a
+//! framebuffer console never holds up a logging call.
 #![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate log;
 
+mod console;
+mod format;
+mod queue;
+mod ring;
+
 use core::{
     fmt::{self, Write},
     str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 #[cfg(not(feature = "std"))]
 use crate_interface::call_interface;
-use log::{Level, LevelFilter, Log, Metadata, Record};
-pub use log::{debug, error, info, trace, warn};
+use kspin::SpinNoIrq;
+pub use log::{Level, debug, error, info, trace, warn};
+use log::{LevelFilter, Log, Metadata, Record};
+use static_keys::{StaticKey, static_branch_unlikely};
+
+pub use self::{
+    console::{
+        LOG_BACKLOG_SIZE, LogConsole, LogConsoleStats, MAX_LOG_CONSOLES, log_backlog_pending,
+        log_consoles, register_log_console, unregister_log_console,
+    },
+    format::{LogFormat, log_format, set_log_format},
+    queue::{
+        DEFAULT_LOG_QUEUE_SLOTS, LogQueue, LogSlot, MAX_LOG_CPUS, drain_log_queue,
+        flush_log_queue_for_panic, log_dropped, set_async_logging, set_log_queue,
+        set_sync_log_level, sync_log_level,
+    },
+    ring::{
+        DEFAULT_LOG_BUFFER_SIZE, log_buffer_clear, log_buffer_len, log_buffer_read, set_log_buffer,
+    },
+};
+use self::{
+    format::{PlainLine, RecordContext, Timestamp},
+    queue::RecordMeta,
+    ring::RecordBuf,
+};
 
 #[macro_export]
 macro_rules! kprint {
@@ -62,9 +114,13 @@ enum AnsiColor {
 #[crate_interface::def_interface]
 pub trait LoggerAdapter {
     fn write_str(s: &str);
-    fn now() -> core::time::Duration;
+    /// Reads the kernel reference clock, returning its raw count and the
+    /// monotonic time of that count.
+    fn reference_now() -> (u64, core::time::Duration);
     fn cpu_id() -> Option<usize>;
     fn task_id() -> Option<u64>;
+    /// Returns the name of the current task, NUL-padded.
+    fn task_name() -> Option<[u8; 16]>;
 }
 
 struct KernelLogger;
@@ -84,7 +140,10 @@ impl Write for KernelLogger {
 
 impl Log for KernelLogger {
     #[inline]
-    fn enabled(&self, _metadata: &Metadata) -> bool {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if static_branch_unlikely!(MODULE_LEVELS_ACTIVE) {
+            return metadata.level() <= module_level(metadata.target());
+        }
         true
     }
 
@@ -96,78 +155,146 @@ impl Log for KernelLogger {
         let level = record.level();
         let line = record.line().unwrap_or(0);
         let path = record.target();
-        let color = match level {
-            Level::Error => AnsiColor::Red,
-            Level::Warn => AnsiColor::Yellow,
-            Level::Info => AnsiColor::Green,
-            Level::Debug => AnsiColor::Cyan,
-            Level::Trace => AnsiColor::BrightBlack,
-        };
+        let now = now();
+        let ctx = record_context();
+        let cpu_id = ctx.cpu_id;
 
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "std")] {
-                let _ = print_fmt(color_fmt!(
-                    AnsiColor::White,
-                    "[{time} {path}:{line}] {args}\n",
-                    time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.6f"),
-                    path = path,
-                    line = line,
-                    args = color_fmt!(color, "{}", record.args()),
-                ));
-            } else {
-                let cpu_id = call_interface!(LoggerAdapter::cpu_id);
-                let tid = call_interface!(LoggerAdapter::task_id);
-                let now = call_interface!(LoggerAdapter::now);
-
-                let _ = match (cpu_id, tid) {
-                   (Some(c), Some(t)) => print_fmt(color_fmt!(
-                        AnsiColor::White,
-                        "[{:>3}.{:06} {c}:{t} {path}:{line}] {args}\n",
-                        now.as_secs(),
-                        now.subsec_micros(),
-                        c = c,
-                        t = t,
-                        path = path,
-                        line = line,
-                        args = color_fmt!(color, "{}", record.args()),
-                    )),
-                    (Some(c), None) => print_fmt(color_fmt!(
-                        AnsiColor::White,
-                        "[{:>3}.{:06} {c} {path}:{line}] {args}\n",
-                        now.as_secs(),
-                        now.subsec_micros(),
-                        c = c,
-                        path = path,
-                        line = line,
-                        args = color_fmt!(color, "{}", record.args()),
-                    )),
-                    _ => print_fmt(color_fmt!(
-                        AnsiColor::White,
-                        "[{:>3}.{:06} {path}:{line}] {args}\n",
-                        now.as_secs(),
-                        now.subsec_micros(),
-                        path = path,
-                        line = line,
-                        args = color_fmt!(color, "{}", record.args()),
-                    )),
-                };
+        let format = log_format();
+        if format != LogFormat::Human {
+            let line = PlainLine {
+                format,
+                now,
+                ctx,
+                level,
+                target: path,
+                line,
+                args: *record.args(),
+            };
+            queue::submit(Some(level), cpu_id, |buf| {
+                let _ = write!(buf, "{line}");
+                None
+            });
+            return;
+        }
+
+        queue::submit(Some(level), cpu_id, |buf| {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "std")] {
+                    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.6f");
+                    let _ = write!(buf, "[{time} {path}:{line}] ");
+                } else {
+                    let now = now.time;
+                    let (secs, micros) = (now.as_secs(), now.subsec_micros());
+                    let _ = write!(buf, "[{secs:>3}.{micros:06}{ctx} {path}:{line}] ");
+                }
+            }
+            let color_from = buf.as_bytes().len();
+            let _ = writeln!(buf, "{}", record.args());
+            Some(color_from)
+        });
+    }
+
+    fn flush(&self) {
+        queue::flush();
+    }
+}
+
+/// Reads the reference clock, or the time since the Unix epoch on `std`.
+fn now() -> Timestamp {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "std")] {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            Timestamp {
+                ticks: time.as_nanos() as u64,
+                time,
             }
+        } else {
+            let (ticks, time) = call_interface!(LoggerAdapter::reference_now);
+            Timestamp { ticks, time }
+        }
+    }
+}
+
+fn cpu_id() -> Option<usize> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "std")] {
+            None
+        } else {
+            call_interface!(LoggerAdapter::cpu_id)
         }
     }
+}
 
-    fn flush(&self) {}
+fn record_context() -> RecordContext {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "std")] {
+            RecordContext {
+                cpu_id: None,
+                task_id: None,
+                task_name: None,
+            }
+        } else {
+            RecordContext {
+                cpu_id: cpu_id(),
+                task_id: call_interface!(LoggerAdapter::task_id),
+                task_name: call_interface!(LoggerAdapter::task_name),
+            }
+        }
+    }
 }
 
 pub fn print_fmt(args: fmt::Arguments) -> fmt::Result {
-    use kspin::SpinNoIrq;
-    static LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+    queue::submit(None, cpu_id(), |buf| {
+        let _ = buf.write_fmt(args);
+        None
+    });
+    Ok(())
+}
+
+/// Writes a dequeued record to the consoles, and keeps it in the ring buffer
+/// unless it is raw console output.
+fn write_record(meta: &RecordMeta, text: &RecordBuf) {
+    if meta.level.is_some() {
+        ring::push_bytes(text.as_bytes());
+    }
+    write_colored(&mut KernelLogger, meta, text);
+    console::emit(meta, text, queue::sync_consoles());
+}
 
-    let _guard = LOCK.lock();
-    KernelLogger.write_fmt(args)
+/// Writes a record to `out`, colored if so submitted.
+fn write_colored(out: &mut dyn Write, meta: &RecordMeta, text: &RecordBuf) {
+    let text = text.as_str();
+    let (Some(level), Some(color_from)) = (meta.level, meta.color_from) else {
+        let _ = out.write_str(text);
+        return;
+    };
+    let color = match level {
+        Level::Error => AnsiColor::Red,
+        Level::Warn => AnsiColor::Yellow,
+        Level::Info => AnsiColor::Green,
+        Level::Debug => AnsiColor::Cyan,
+        Level::Trace => AnsiColor::BrightBlack,
+    };
+    let (prefix, msg) = text.split_at(color_from.min(text.len()));
+    let msg = msg.strip_suffix('\n').unwrap_or(msg);
+    let _ = out.write_fmt(color_fmt!(
+        AnsiColor::White,
+        "{prefix}{}\n",
+        color_fmt!(color, "{msg}")
+    ));
 }
 
 pub fn init_klogger() {
+    init_klogger_with(LogFormat::default());
+}
+
+/// Installs the kernel logger with lines laid out as `format`.
+pub fn init_klogger_with(format: LogFormat) {
+    set_log_format(format);
     log::set_logger(&KernelLogger).unwrap();
+    GLOBAL_LEVEL.store(LevelFilter::Warn as usize, Ordering::Relaxed);
     log::set_max_level(LevelFilter::Warn);
 }
 
@@ -175,5 +302,131 @@ pub fn set_log_level(level: &str) {
     let lf = LevelFilter::from_str(level)
         .ok()
         .unwrap_or(LevelFilter::Off);
-    log::set_max_level(lf);
+    GLOBAL_LEVEL.store(lf as usize, Ordering::Relaxed);
+    update_max_level(&MODULE_LEVELS.lock());
+}
+
+const MAX_MODULE_LEVELS: usize = 16;
+const MAX_MODULE_NAME_LEN: usize = 64;
+
+/// A per-module level override.
+#[derive(Clone, Copy)]
+struct ModuleLevel {
+    name: [u8; MAX_MODULE_NAME_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl ModuleLevel {
+    fn name(&self) -> &str {
+        // Only ever built from a `&str` cut at a char boundary.
+        core::str::from_utf8(&self.name[..self.len]).unwrap_or("")
+    }
+
+    /// Returns whether `target` is this module or one of its submodules.
+    fn matches(&self, target: &str) -> bool {
+        let name = self.name();
+        target
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+type ModuleLevels = [Option<ModuleLevel>; MAX_MODULE_LEVELS];
+
+static GLOBAL_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Warn as usize);
+static MODULE_LEVELS: SpinNoIrq<ModuleLevels> = SpinNoIrq::new([None; MAX_MODULE_LEVELS]);
+/// Enabled while at least one per-module override is installed.
+static MODULE_LEVELS_ACTIVE: StaticKey = StaticKey::new(false);
+
+fn global_level() -> LevelFilter {
+    match GLOBAL_LEVEL.load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Returns the effective level of `target`, preferring the longest matching
+/// module override.
+fn module_level(target: &str) -> LevelFilter {
+    MODULE_LEVELS
+        .lock()
+        .iter()
+        .flatten()
+        .filter(|m| m.matches(target))
+        .max_by_key(|m| m.len)
+        .map_or_else(global_level, |m| m.level)
+}
+
+/// Raises the `log` crate's static ceiling to the most verbose level in use.
+fn update_max_level(levels: &ModuleLevels) {
+    let max = levels
+        .iter()
+        .flatten()
+        .map(|m| m.level)
+        .fold(global_level(), LevelFilter::max);
+    log::set_max_level(max);
+}
+
+/// Sets the log level of `module` (a target path such as `kapi::syscall`) and
+/// its submodules, overriding the global level.
+///
+/// Returns `false` if `level` is invalid, the name is too long, or the
+/// override table is full.
+pub fn set_module_log_level(module: &str, level: &str) -> bool {
+    let Ok(level) = LevelFilter::from_str(level) else {
+        return false;
+    };
+    if module.is_empty() || module.len() > MAX_MODULE_NAME_LEN {
+        return false;
+    }
+
+    let mut levels = MODULE_LEVELS.lock();
+    let slot = match levels
+        .iter()
+        .position(|m| m.is_some_and(|m| m.name() == module))
+    {
+        Some(idx) => idx,
+        None => match levels.iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => return false,
+        },
+    };
+    let mut name = [0; MAX_MODULE_NAME_LEN];
+    name[..module.len()].copy_from_slice(module.as_bytes());
+    levels[slot] = Some(ModuleLevel {
+        name,
+        len: module.len(),
+        level,
+    });
+    update_max_level(&levels);
+    drop(levels);
+
+    // Patch the gate outside the table lock: patching may log.
+    MODULE_LEVELS_ACTIVE.enable();
+    true
+}
+
+/// Removes the level override of `module`. Returns `false` if none was set.
+pub fn clear_module_log_level(module: &str) -> bool {
+    let mut levels = MODULE_LEVELS.lock();
+    let Some(slot) = levels
+        .iter_mut()
+        .find(|m| m.is_some_and(|m| m.name() == module))
+    else {
+        return false;
+    };
+    *slot = None;
+    let any_left = levels.iter().any(Option::is_some);
+    update_max_level(&levels);
+    drop(levels);
+
+    if !any_left {
+        MODULE_LEVELS_ACTIVE.disable();
+    }
+    true
 }
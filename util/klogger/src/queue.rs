@@ -0,0 +1,469 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Lock-free submission queue of log records.
+//!
+//! A producer reserves a slot by advancing an atomic cursor, formats its
+//! record straight into the slot and commits it; it never takes a lock or
+//! waits for the console, whose speed only decides when lines appear. The
+//! queue is drained in reservation order, to the consoles and the ring
+//! buffer, by the log writer task through [`drain_log_queue`], or by the
+//! producer itself while logging is synchronous, for records at or above the
+//! level set by [`set_sync_log_level`] and once the system panics.
+//!
+//! Records are formatted when submitted, so their timestamp, CPU and task
+//! are those of the producer. When the queue is full, records are dropped
+//! and counted against the CPU that submitted them, see [`log_dropped`].
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use kspin::SpinNoIrq;
+use log::Level;
+
+use crate::{
+    console,
+    ring::{self, RecordBuf},
+};
+
+/// Number of slots of the queue used until [`set_log_queue`] is called.
+pub const DEFAULT_LOG_QUEUE_SLOTS: usize = 64;
+
+/// Number of CPUs with a dropped-records counter of their own; records of
+/// CPUs past it are counted with the last one.
+pub const MAX_LOG_CPUS: usize = 64;
+
+/// Attempts at taking the drain lock once the system panics, before leaving
+/// the records to the CPU holding it.
+const PANIC_DRAIN_SPINS: usize = 1 << 16;
+
+/// Owner of the drain lock when none is known.
+const NO_OWNER: usize = usize::MAX;
+
+/// How a queued record is written out.
+#[derive(Clone, Copy)]
+pub(crate) struct RecordMeta {
+    /// The level of the record; `None` for raw console output, which is not
+    /// kept in the ring buffer.
+    pub level: Option<Level>,
+    /// Where the message starts in the text of a record colored by level.
+    pub color_from: Option<usize>,
+}
+
+/// A slot of a [`LogQueue`], holding one record.
+pub struct LogSlot {
+    /// `2 * lap` while free for the record at position `lap * len + index`
+    /// of the queue, one more once that record is committed.
+    state: AtomicUsize,
+    meta: UnsafeCell<RecordMeta>,
+    text: UnsafeCell<RecordBuf>,
+}
+
+// SAFETY: the record of a slot is only accessed by the producer that
+// reserved it until it is committed, then by the drainer until it is freed.
+unsafe impl Sync for LogSlot {}
+
+impl LogSlot {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            meta: UnsafeCell::new(RecordMeta {
+                level: None,
+                color_from: None,
+            }),
+            text: UnsafeCell::new(RecordBuf::new()),
+        }
+    }
+}
+
+impl Default for LogSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bounded queue of log records, see [`set_log_queue`].
+pub struct LogQueue {
+    slots: &'static [LogSlot],
+    /// Position of the next record to reserve.
+    tail: AtomicUsize,
+    /// Position of the next record to write out; only advanced by the
+    /// holder of the drain lock.
+    head: AtomicUsize,
+}
+
+impl LogQueue {
+    /// Creates a queue holding up to `slots.len()` records.
+    pub const fn new(slots: &'static [LogSlot]) -> Self {
+        Self {
+            slots,
+            tail: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the slot of position `pos`, and its state while free for it.
+    fn slot(&self, pos: usize) -> (&LogSlot, usize) {
+        let len = self.slots.len();
+        (&self.slots[pos % len], 2 * (pos / len))
+    }
+
+    /// Reserves the next position, or returns `None` if the queue is full.
+    fn reserve(&self) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let (slot, free) = self.slot(pos);
+            let state = slot.state.load(Ordering::Acquire);
+            if state == free {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Some(pos),
+                    Err(tail) => pos = tail,
+                }
+            } else if state < free {
+                // Still holds the record of the previous lap.
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == pos {
+                    return None;
+                }
+                pos = tail;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Queues a record `write` formats, returning where its message starts
+    /// if it is to be colored. Returns `false` if the queue is full.
+    fn submit(
+        &self,
+        level: Option<Level>,
+        write: impl FnOnce(&mut RecordBuf) -> Option<usize>,
+    ) -> bool {
+        let Some(pos) = self.reserve() else {
+            return false;
+        };
+        let (slot, free) = self.slot(pos);
+        // SAFETY: the slot is reserved for this record until committed.
+        let (meta, text) = unsafe { (&mut *slot.meta.get(), &mut *slot.text.get()) };
+        text.clear();
+        let color_from = write(text);
+        *meta = RecordMeta { level, color_from };
+        slot.state.store(free + 1, Ordering::Release);
+        true
+    }
+
+    /// Passes the oldest record to `f` and frees its slot. Returns `false`,
+    /// without calling `f`, if that record is not committed yet.
+    ///
+    /// Must be called by the holder of the drain lock.
+    fn pop(&self, f: impl FnOnce(&RecordMeta, &RecordBuf)) -> bool {
+        if self.slots.is_empty() {
+            return false;
+        }
+        let pos = self.head.load(Ordering::Relaxed);
+        let (slot, free) = self.slot(pos);
+        if slot.state.load(Ordering::Acquire) != free + 1 {
+            return false;
+        }
+        // SAFETY: the record is committed and only the drainer reads it.
+        unsafe { f(&*slot.meta.get(), &*slot.text.get()) };
+        self.head.store(pos + 1, Ordering::Relaxed);
+        slot.state.store(free + 2, Ordering::Release);
+        true
+    }
+
+    /// Returns whether the oldest record is committed.
+    fn has_committed(&self) -> bool {
+        if self.slots.is_empty() {
+            return false;
+        }
+        let (slot, free) = self.slot(self.head.load(Ordering::Relaxed));
+        slot.state.load(Ordering::Acquire) == free + 1
+    }
+}
+
+static DEFAULT_SLOTS: [LogSlot; DEFAULT_LOG_QUEUE_SLOTS] =
+    [const { LogSlot::new() }; DEFAULT_LOG_QUEUE_SLOTS];
+static DEFAULT_QUEUE: LogQueue = LogQueue::new(&DEFAULT_SLOTS);
+/// The queue in use; null for [`DEFAULT_QUEUE`].
+static QUEUE: AtomicPtr<LogQueue> = AtomicPtr::new(ptr::null_mut());
+
+static DROPPED: [AtomicUsize; MAX_LOG_CPUS] = [const { AtomicUsize::new(0) }; MAX_LOG_CPUS];
+static ASYNC: AtomicBool = AtomicBool::new(false);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// The least severe level written out before the logging call returns.
+static SYNC_LEVEL: AtomicUsize = AtomicUsize::new(Level::Error as usize);
+
+/// Serializes draining; holds the number of dropped records reported so far.
+static DRAIN: SpinNoIrq<usize> = SpinNoIrq::new(0);
+/// The CPU holding the drain lock, to tell when the console it writes to
+/// logs in turn.
+static DRAIN_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+fn queue() -> &'static LogQueue {
+    let queue = QUEUE.load(Ordering::Acquire);
+    if queue.is_null() {
+        &DEFAULT_QUEUE
+    } else {
+        // SAFETY: only ever set from a `&'static LogQueue`.
+        unsafe { &*queue }
+    }
+}
+
+/// How hard a CPU tries to write the queue out itself.
+#[derive(Clone, Copy)]
+enum DrainMode {
+    /// Unless another CPU is doing so.
+    Try,
+    /// Waiting for another CPU doing so, to have its own record out.
+    Wait,
+    /// Waiting briefly, the CPU doing so may never finish.
+    Panic,
+}
+
+/// Queues a record `write` formats, then writes the queue out if the record
+/// must not wait for the log writer.
+pub(crate) fn submit(
+    level: Option<Level>,
+    cpu_id: Option<usize>,
+    write: impl FnOnce(&mut RecordBuf) -> Option<usize>,
+) {
+    if !queue().submit(level, write) {
+        let cpu = cpu_id.unwrap_or(0).min(MAX_LOG_CPUS - 1);
+        DROPPED[cpu].fetch_add(1, Ordering::Relaxed);
+    }
+    let mode = if PANICKING.load(Ordering::Relaxed) {
+        DrainMode::Panic
+    } else if level.is_some_and(|level| level as usize <= SYNC_LEVEL.load(Ordering::Relaxed)) {
+        DrainMode::Wait
+    } else if !ASYNC.load(Ordering::Relaxed) {
+        DrainMode::Try
+    } else {
+        return;
+    };
+    drain(mode, cpu_id, &mut |meta, text| {
+        crate::write_record(meta, text)
+    });
+}
+
+/// Writes the committed records out through `emit`, returning how many.
+fn drain(
+    mode: DrainMode,
+    cpu_id: Option<usize>,
+    emit: &mut dyn FnMut(&RecordMeta, &RecordBuf),
+) -> usize {
+    let mut written = 0;
+    loop {
+        let owner = cpu_id.unwrap_or(NO_OWNER);
+        let reentered = cpu_id.is_some() && DRAIN_OWNER.load(Ordering::Relaxed) == owner;
+        let guard = match mode {
+            // The records are left to the drain this CPU is already in.
+            _ if reentered => None,
+            DrainMode::Wait if cpu_id.is_some() => Some(DRAIN.lock()),
+            DrainMode::Try | DrainMode::Wait => DRAIN.try_lock(),
+            DrainMode::Panic => (0..PANIC_DRAIN_SPINS).find_map(|_| {
+                core::hint::spin_loop();
+                DRAIN.try_lock()
+            }),
+        };
+        let Some(mut reported) = guard else {
+            return written;
+        };
+        DRAIN_OWNER.store(owner, Ordering::Relaxed);
+
+        let queue = queue();
+        let dropped = DROPPED.iter().map(|n| n.load(Ordering::Relaxed)).sum();
+        if dropped != *reported {
+            let mut notice = RecordBuf::new();
+            let _ = writeln!(notice, "[klogger: {} records dropped]", dropped - *reported);
+            let meta = RecordMeta {
+                level: Some(Level::Warn),
+                color_from: None,
+            };
+            emit(&meta, &notice);
+            *reported = dropped;
+        }
+        while queue.pop(|meta, text| emit(meta, text)) {
+            written += 1;
+        }
+
+        DRAIN_OWNER.store(NO_OWNER, Ordering::Relaxed);
+        drop(reported);
+        // A record committed after the last pop but before the unlock may
+        // have seen the lock taken and left it to this CPU.
+        if !queue.has_committed() {
+            return written;
+        }
+    }
+}
+
+/// Writes the queued records out to the fast consoles and the ring buffer,
+/// and a batch of those waiting for each slow console, unless another CPU is
+/// doing so. Returns the number of records written.
+///
+/// The log writer task calls this periodically once logging is asynchronous,
+/// and again soon while [`log_backlog_pending`](crate::log_backlog_pending).
+pub fn drain_log_queue() -> usize {
+    let written = drain(DrainMode::Try, crate::cpu_id(), &mut |meta, text| {
+        crate::write_record(meta, text)
+    });
+    written + console::drain_backlogs(console::BACKLOG_BATCH)
+}
+
+/// Returns whether slow consoles get records as they leave the queue rather
+/// than from their backlog: while no log writer task runs, and once the
+/// system panics.
+pub(crate) fn sync_consoles() -> bool {
+    PANICKING.load(Ordering::Relaxed) || !ASYNC.load(Ordering::Relaxed)
+}
+
+/// Writes the queued records out, waiting for another CPU doing so.
+pub(crate) fn flush() {
+    drain(DrainMode::Wait, crate::cpu_id(), &mut |meta, text| {
+        crate::write_record(meta, text)
+    });
+}
+
+/// Makes records below the level set by [`set_sync_log_level`] wait in the
+/// queue for [`drain_log_queue`], rather than being written out by the CPU
+/// that logs them, and records for slow consoles wait in their backlog.
+///
+/// Only to be enabled once a task drains the queue periodically.
+pub fn set_async_logging(enabled: bool) {
+    ASYNC.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        drain_log_queue();
+        console::drain_backlogs(usize::MAX);
+    }
+}
+
+/// Makes records at or above `level` reach the fast consoles before the
+/// logging call returns, even while logging is asynchronous. Defaults to
+/// `Error`.
+pub fn set_sync_log_level(level: Level) {
+    SYNC_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Returns the level set by [`set_sync_log_level`].
+pub fn sync_log_level() -> Level {
+    match SYNC_LEVEL.load(Ordering::Relaxed) {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Writes the queued records to `out`, uncolored, and to the registered
+/// consoles, slow ones included, and makes every further record be written
+/// out everywhere as soon as it is logged. For the panic path.
+///
+/// The drain lock is only waited for briefly: if the CPU holding it never
+/// lets go, the records are left queued.
+pub fn flush_log_queue_for_panic(out: &mut dyn Write) {
+    PANICKING.store(true, Ordering::Relaxed);
+    console::flush_backlogs_for_panic();
+    drain(DrainMode::Panic, crate::cpu_id(), &mut |meta, text| {
+        if meta.level.is_some() {
+            ring::push_bytes(text.as_bytes());
+        }
+        let _ = out.write_str(text.as_str());
+        console::emit(meta, text, true);
+    });
+}
+
+/// Returns the number of records dropped on `cpu` because the queue was
+/// full. Records of CPUs from [`MAX_LOG_CPUS`] on count on the last one.
+pub fn log_dropped(cpu: usize) -> usize {
+    DROPPED
+        .get(cpu)
+        .map_or(0, |dropped| dropped.load(Ordering::Relaxed))
+}
+
+/// Replaces the queue with `queue`, e.g. to make room for more records.
+///
+/// The records queued so far are written out first. Records submitted on
+/// other CPUs while switching may be lost, so this is best done during
+/// early boot.
+pub fn set_log_queue(queue: &'static LogQueue) {
+    flush();
+    QUEUE.store(ptr::from_ref(queue).cast_mut(), Ordering::Release);
+}
+
+#[cfg(unittest)]
+mod tests_queue {
+    use core::{fmt::Write, sync::atomic::Ordering};
+
+    use log::Level;
+    use unittest::def_test;
+
+    use super::{LogQueue, LogSlot};
+
+    fn push(queue: &LogQueue, text: &str) -> bool {
+        queue.submit(Some(Level::Info), |buf| {
+            let _ = buf.write_str(text);
+            None
+        })
+    }
+
+    /// Pops the oldest record, returning whether it was `expected`.
+    fn pop_is(queue: &LogQueue, expected: &str) -> bool {
+        let mut matched = false;
+        queue.pop(|_, text| matched = text.as_str() == expected) && matched
+    }
+
+    #[def_test]
+    fn test_queue_fifo_and_full() {
+        static SLOTS: [LogSlot; 2] = [const { LogSlot::new() }; 2];
+        let queue = LogQueue::new(&SLOTS);
+        assert!(push(&queue, "a"));
+        assert!(push(&queue, "b"));
+        // Full: dropped rather than waited for.
+        assert!(!push(&queue, "c"));
+        assert!(pop_is(&queue, "a"));
+        assert!(push(&queue, "d"));
+        assert!(pop_is(&queue, "b"));
+        assert!(pop_is(&queue, "d"));
+        assert!(!queue.pop(|_, _| {}));
+        assert!(!queue.has_committed());
+    }
+
+    #[def_test]
+    fn test_queue_waits_for_commit() {
+        static SLOTS: [LogSlot; 4] = [const { LogSlot::new() }; 4];
+        let queue = LogQueue::new(&SLOTS);
+        // A producer that reserved its slot but has not committed yet holds
+        // back the records reserved after it.
+        let pos = queue.reserve().unwrap();
+        assert!(push(&queue, "later"));
+        assert!(!queue.has_committed());
+        assert!(!queue.pop(|_, _| {}));
+
+        let (slot, free) = queue.slot(pos);
+        slot.state.store(free + 1, Ordering::Release);
+        assert!(queue.pop(|_, _| {}));
+        assert!(pop_is(&queue, "later"));
+    }
+
+    #[def_test]
+    fn test_queue_without_slots() {
+        static SLOTS: [LogSlot; 0] = [];
+        let queue = LogQueue::new(&SLOTS);
+        assert!(!push(&queue, "lost"));
+        assert!(!queue.pop(|_, _| {}));
+    }
+}
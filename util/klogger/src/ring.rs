@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! In-memory ring buffer of formatted log records.
+//!
+//! Records are stored as a little-endian `u32` length header followed by the
+//! record text. When the buffer is full, whole records are dropped from the
+//! oldest end, so readers never observe a partial record.
+
+use core::{
+    fmt::{self, Write},
+    ptr::addr_of_mut,
+};
+
+use kspin::SpinNoIrq;
+
+/// Size of the ring buffer used until [`set_log_buffer`] is called.
+pub const DEFAULT_LOG_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Longest record kept; longer records are truncated.
+const MAX_RECORD_LEN: usize = 1024;
+
+const HEADER_LEN: usize = size_of::<u32>();
+
+static mut DEFAULT_BUFFER: [u8; DEFAULT_LOG_BUFFER_SIZE] = [0; DEFAULT_LOG_BUFFER_SIZE];
+
+struct LogRing {
+    /// Backing storage; null until the first use.
+    buf: *mut u8,
+    cap: usize,
+    /// Offset of the oldest record.
+    head: usize,
+    /// Bytes in use, headers included.
+    used: usize,
+}
+
+// SAFETY: the storage is only accessed under the `LOG_RING` lock.
+unsafe impl Send for LogRing {}
+
+static LOG_RING: SpinNoIrq<LogRing> = SpinNoIrq::new(LogRing {
+    buf: core::ptr::null_mut(),
+    cap: 0,
+    head: 0,
+    used: 0,
+});
+
+impl LogRing {
+    fn storage(&mut self) -> &mut [u8] {
+        if self.buf.is_null() {
+            self.buf = addr_of_mut!(DEFAULT_BUFFER).cast();
+            self.cap = DEFAULT_LOG_BUFFER_SIZE;
+        }
+        // SAFETY: `buf` points to `cap` bytes owned by the ring.
+        unsafe { core::slice::from_raw_parts_mut(self.buf, self.cap) }
+    }
+
+    fn copy_in(&mut self, pos: usize, data: &[u8]) {
+        let cap = self.cap;
+        let storage = self.storage();
+        let first = data.len().min(cap - pos);
+        storage[pos..pos + first].copy_from_slice(&data[..first]);
+        storage[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    fn copy_out(&mut self, pos: usize, out: &mut [u8]) {
+        let cap = self.cap;
+        let storage = self.storage();
+        let first = out.len().min(cap - pos);
+        out[..first].copy_from_slice(&storage[pos..pos + first]);
+        let rest = out.len() - first;
+        out[first..].copy_from_slice(&storage[..rest]);
+    }
+
+    fn record_len(&mut self, pos: usize) -> usize {
+        let mut header = [0; HEADER_LEN];
+        self.copy_out(pos, &mut header);
+        u32::from_le_bytes(header) as usize
+    }
+
+    fn drop_oldest(&mut self) {
+        let len = HEADER_LEN + self.record_len(self.head);
+        self.head = (self.head + len) % self.cap;
+        self.used -= len;
+    }
+
+    fn push(&mut self, record: &[u8]) {
+        self.storage();
+        if self.cap <= HEADER_LEN {
+            return;
+        }
+        let record = &record[..record.len().min(self.cap - HEADER_LEN)];
+        let need = HEADER_LEN + record.len();
+        while self.cap - self.used < need {
+            self.drop_oldest();
+        }
+        let tail = (self.head + self.used) % self.cap;
+        self.copy_in(tail, &(record.len() as u32).to_le_bytes());
+        self.copy_in((tail + HEADER_LEN) % self.cap, record);
+        self.used += need;
+    }
+
+    /// Copies record text starting at byte `offset` of the concatenated
+    /// records into `out`.
+    fn read(&mut self, out: &mut [u8], mut offset: usize) -> usize {
+        self.storage();
+        let mut pos = self.head;
+        let mut remaining = self.used;
+        let mut copied = 0;
+        while remaining > 0 && copied < out.len() {
+            let len = self.record_len(pos);
+            let data = (pos + HEADER_LEN) % self.cap;
+            if offset < len {
+                let n = (len - offset).min(out.len() - copied);
+                self.copy_out((data + offset) % self.cap, &mut out[copied..copied + n]);
+                copied += n;
+                offset = 0;
+            } else {
+                offset -= len;
+            }
+            pos = (data + len) % self.cap;
+            remaining -= HEADER_LEN + len;
+        }
+        copied
+    }
+
+    /// Returns the total length of the record text.
+    fn text_len(&mut self) -> usize {
+        self.storage();
+        let mut pos = self.head;
+        let mut remaining = self.used;
+        let mut total = 0;
+        while remaining > 0 {
+            let len = self.record_len(pos);
+            total += len;
+            pos = (pos + HEADER_LEN + len) % self.cap;
+            remaining -= HEADER_LEN + len;
+        }
+        total
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.used = 0;
+    }
+}
+
+/// A record under construction, truncated at [`MAX_RECORD_LEN`].
+pub(crate) struct RecordBuf {
+    data: [u8; MAX_RECORD_LEN],
+    len: usize,
+}
+
+impl RecordBuf {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; MAX_RECORD_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from `&str`s cut at a char boundary.
+        core::str::from_utf8(self.as_bytes()).unwrap_or("")
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Replaces the contents with the `len` bytes `fill` writes, which must
+    /// be whole characters.
+    pub fn fill(&mut self, len: usize, fill: impl FnOnce(&mut [u8])) {
+        self.len = len.min(MAX_RECORD_LEN);
+        fill(&mut self.data[..self.len]);
+    }
+}
+
+impl Write for RecordBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut n = s.len().min(MAX_RECORD_LEN - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.data[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Appends a record to the ring buffer.
+pub(crate) fn push_bytes(record: &[u8]) {
+    LOG_RING.lock().push(record);
+}
+
+/// Replaces the ring buffer storage with `buf`, keeping as many of the most
+/// recent records as fit.
+pub fn set_log_buffer(buf: &'static mut [u8]) {
+    let mut ring = LOG_RING.lock();
+    ring.storage();
+    let mut new = LogRing {
+        buf: buf.as_mut_ptr(),
+        cap: buf.len(),
+        head: 0,
+        used: 0,
+    };
+    // Drop the records that cannot fit, then move the rest in order.
+    while ring.used > 0 && ring.used > new.cap {
+        ring.drop_oldest();
+    }
+    let mut record = [0; MAX_RECORD_LEN];
+    while ring.used > 0 {
+        let len = ring.record_len(ring.head).min(MAX_RECORD_LEN);
+        let data = (ring.head + HEADER_LEN) % ring.cap;
+        ring.copy_out(data, &mut record[..len]);
+        new.push(&record[..len]);
+        ring.drop_oldest();
+    }
+    *ring = new;
+}
+
+/// Reads buffered log text starting at byte `offset` into `out`.
+///
+/// Returns the number of bytes copied, 0 once `offset` reaches the end.
+/// Offsets refer to the records currently retained, so they shift when old
+/// records are dropped.
+pub fn log_buffer_read(out: &mut [u8], offset: usize) -> usize {
+    LOG_RING.lock().read(out, offset)
+}
+
+/// Returns the number of bytes of buffered log text.
+pub fn log_buffer_len() -> usize {
+    LOG_RING.lock().text_len()
+}
+
+/// Discards all buffered records.
+pub fn log_buffer_clear() {
+    LOG_RING.lock().clear();
+}
+
+#[cfg(unittest)]
+mod tests_ring {
+    use unittest::def_test;
+
+    use super::LogRing;
+
+    fn ring(storage: &mut [u8]) -> LogRing {
+        LogRing {
+            buf: storage.as_mut_ptr(),
+            cap: storage.len(),
+            head: 0,
+            used: 0,
+        }
+    }
+
+    #[def_test]
+    fn test_ring_read_across_records() {
+        let mut storage = [0u8; 64];
+        let mut ring = ring(&mut storage);
+        ring.push(b"first\n");
+        ring.push(b"second\n");
+        let mut out = [0u8; 32];
+        let n = ring.read(&mut out, 0);
+        assert_eq!(&out[..n], b"first\nsecond\n");
+        let n = ring.read(&mut out, 3);
+        assert_eq!(&out[..n], b"st\nsecond\n");
+        assert_eq!(ring.text_len(), 13);
+    }
+
+    #[def_test]
+    fn test_ring_drops_whole_records() {
+        let mut storage = [0u8; 24];
+        let mut ring = ring(&mut storage);
+        ring.push(b"aaaaaaa\n");
+        ring.push(b"bbbbbbb\n");
+        // Needs 12 bytes: the first record must go entirely.
+        ring.push(b"ccccccc\n");
+        let mut out = [0u8; 32];
+        let n = ring.read(&mut out, 0);
+        assert_eq!(&out[..n], b"bbbbbbb\nccccccc\n");
+    }
+
+    #[def_test]
+    fn test_ring_clear() {
+        let mut storage = [0u8; 32];
+        let mut ring = ring(&mut storage);
+        ring.push(b"record\n");
+        ring.clear();
+        assert_eq!(ring.text_len(), 0);
+    }
+}
@@ -6,20 +6,57 @@
 //!
 //! This crate provides procedural macros for converting TOML format configurations
 //! to equivalent Rust constant definitions for the X-Kernel project.
+//!
+//! Included config files are checked against the schema in `schema.toml`.
 #![cfg_attr(feature = "nightly", feature(proc_macro_expand))]
-use kconfig_gen::{Config, OutputFormat};
+use kconfig_gen::{Config, OutputFormat, Schema};
 use proc_macro::{LexError, TokenStream};
 use quote::{ToTokens, quote};
 use syn::{
-    Error, Ident, LitStr, Result, Token,
+    Error, Ident, LitBool, LitStr, Result, Token,
     parse::{Parse, ParseStream},
     parse_macro_input,
 };
 
+#[cfg(test)]
+mod tests;
+
+/// The schema all platform configs are checked against.
+const BASE_SCHEMA: &str = include_str!("../schema.toml");
+
 fn compiler_error<T: ToTokens>(tokens: T, msg: String) -> TokenStream {
     Error::new_spanned(tokens, msg).to_compile_error().into()
 }
 
+/// Parses the config read from `path` and checks it against the base schema,
+/// extended by the `(path, toml)` of a platform schema if given.
+///
+/// On failure, returns all errors found, each naming the file it is about.
+fn check_config(
+    path: &str,
+    config_toml: &str,
+    extension: Option<(&str, &str)>,
+    deny_unknown_keys: bool,
+) -> std::result::Result<(Config, Schema), Vec<String>> {
+    let mut schema = Schema::from_toml(BASE_SCHEMA)
+        .map_err(|e| vec![format!("platconfig-macros/schema.toml: {}", e)])?;
+    if let Some((schema_path, schema_toml)) = extension {
+        schema
+            .extend_toml(schema_toml)
+            .map_err(|e| vec![format!("{}: {}", schema_path, e)])?;
+    }
+    let config = Config::from_toml(config_toml).map_err(|e| vec![format!("{}: {}", path, e)])?;
+    let violations = schema.validate(&config, deny_unknown_keys);
+    if violations.is_empty() {
+        Ok((config, schema))
+    } else {
+        Err(violations
+            .iter()
+            .map(|v| format!("{}: {}", path, v))
+            .collect())
+    }
+}
+
 /// Parses TOML config content and expands it into Rust code.
 ///
 /// # Example
@@ -60,70 +97,173 @@ pub fn parse_configs(config_toml: TokenStream) -> TokenStream {
 /// include_configs!(path_env = "PLAT_CONFIG_PATH", fallback = "path/to/defconfig.toml");
 /// ```
 ///
+/// The config is checked against the schema of this crate, extended by the
+/// platform schema given by `schema`. With `deny_unknown_keys`, keys that
+/// neither schema knows are errors too, which catches typos:
+///
+/// ```rust,ignore
+/// include_configs!(
+///     path_env = "PLAT_CONFIG_PATH",
+///     fallback = "platconfig.toml",
+///     schema = "platconfig.schema.toml",
+///     deny_unknown_keys = true,
+/// );
+/// ```
+///
+/// The generated constants are documented by the schema.
+///
 /// See the [crate-level documentation][crate] for more details.
 #[proc_macro]
 pub fn include_configs(args: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as IncludeConfigsArgs);
-    let path = match args {
-        IncludeConfigsArgs::Path(p) => p.value(),
-        IncludeConfigsArgs::PathEnv(env) => {
+    let (path, span) = match args.path {
+        ConfigPath::Path(p) => (p.value(), p),
+        ConfigPath::PathEnv(env) => {
             let Ok(path) = std::env::var(env.value()) else {
                 return compiler_error(
                     &env,
                     format!("environment variable `{}` not set", env.value()),
                 );
             };
-            path
-        }
-        IncludeConfigsArgs::PathEnvFallback(env, fallback) => {
-            std::env::var(env.value()).unwrap_or_else(|_| fallback.value())
+            (path, env)
         }
+        ConfigPath::PathEnvFallback(env, fallback) => match std::env::var(env.value()) {
+            Ok(path) => (path, env),
+            Err(_) => (fallback.value(), fallback),
+        },
     };
 
     let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".into());
     let cfg_path = std::path::Path::new(&root).join(&path);
 
     let Ok(config_toml) = std::fs::read_to_string(&cfg_path) else {
-        return compiler_error(path, format!("failed to read config file: {:?}", cfg_path));
+        return compiler_error(span, format!("failed to read config file: {:?}", cfg_path));
     };
 
-    quote! {
-        ::platconfig_macros::parse_configs!(#config_toml);
+    let extension = match &args.schema {
+        Some(schema) => {
+            let schema_path = std::path::Path::new(&root).join(schema.value());
+            let Ok(schema_toml) = std::fs::read_to_string(&schema_path) else {
+                return compiler_error(
+                    schema,
+                    format!("failed to read schema file: {:?}", schema_path),
+                );
+            };
+            Some((schema_path.display().to_string(), schema_toml))
+        }
+        None => None,
+    };
+
+    let checked = check_config(
+        &cfg_path.display().to_string(),
+        &config_toml,
+        extension.as_ref().map(|(p, s)| (p.as_str(), s.as_str())),
+        args.deny_unknown_keys,
+    );
+    let (mut config, schema) = match checked {
+        Ok(checked) => checked,
+        Err(errors) => {
+            return errors
+                .into_iter()
+                .map(|e| Error::new_spanned(&span, e))
+                .reduce(|mut all, e| {
+                    all.combine(e);
+                    all
+                })
+                .unwrap()
+                .to_compile_error()
+                .into();
+        }
+    };
+    schema.apply_docs(&mut config);
+    match config.dump(OutputFormat::Rust) {
+        Ok(code) => code
+            .parse()
+            .unwrap_or_else(|e: LexError| compiler_error(span, e.to_string())),
+        Err(e) => compiler_error(span, e.to_string()),
     }
-    .into()
 }
 
-enum IncludeConfigsArgs {
+/// Expands to a Markdown table documenting all keys of the platform configs,
+/// generated from the schema of this crate.
+///
+/// ```rust,ignore
+/// #![doc = platconfig_macros::schema_docs!()]
+/// ```
+#[proc_macro]
+pub fn schema_docs(args: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(
+            proc_macro2::Span::call_site(),
+            "`schema_docs!` takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    match Schema::from_toml(BASE_SCHEMA) {
+        Ok(schema) => {
+            let docs = schema.dump_markdown();
+            quote!(#docs).into()
+        }
+        Err(e) => Error::new(
+            proc_macro2::Span::call_site(),
+            format!("platconfig-macros/schema.toml: {}", e),
+        )
+        .to_compile_error()
+        .into(),
+    }
+}
+
+enum ConfigPath {
     Path(LitStr),
     PathEnv(LitStr),
     PathEnvFallback(LitStr, LitStr),
 }
 
+struct IncludeConfigsArgs {
+    path: ConfigPath,
+    schema: Option<LitStr>,
+    deny_unknown_keys: bool,
+}
+
 impl Parse for IncludeConfigsArgs {
     fn parse(input: ParseStream) -> Result<Self> {
+        let mut path = None;
         if input.peek(LitStr) {
-            return Ok(IncludeConfigsArgs::Path(input.parse()?));
+            path = Some(input.parse()?);
+            if input.peek(Token![,]) {
+                input.parse::<Token![,]>()?;
+            }
         }
 
         let mut env = None;
         let mut fallback = None;
+        let mut schema = None;
+        let mut deny_unknown_keys = None;
         while !input.is_empty() {
             let ident: Ident = input.parse()?;
             input.parse::<Token![=]>()?;
-            let str: LitStr = input.parse()?;
+            let duplicate = || Error::new(ident.span(), format!("duplicate parameter `{}`", ident));
 
             match ident.to_string().as_str() {
-                "path_env" => {
-                    if env.is_some() {
-                        return Err(Error::new(ident.span(), "duplicate parameter `path_env`"));
+                "path_env" | "fallback" | "schema" => {
+                    let str: LitStr = input.parse()?;
+                    let param = match ident.to_string().as_str() {
+                        "path_env" => &mut env,
+                        "fallback" => &mut fallback,
+                        _ => &mut schema,
+                    };
+                    if param.is_some() {
+                        return Err(duplicate());
                     }
-                    env = Some(str);
+                    *param = Some(str);
                 }
-                "fallback" => {
-                    if fallback.is_some() {
-                        return Err(Error::new(ident.span(), "duplicate parameter `fallback`"));
+                "deny_unknown_keys" => {
+                    let bool: LitBool = input.parse()?;
+                    if deny_unknown_keys.is_some() {
+                        return Err(duplicate());
                     }
-                    fallback = Some(str);
+                    deny_unknown_keys = Some(bool.value);
                 }
                 _ => {
                     return Err(Error::new(
@@ -138,13 +278,27 @@ impl Parse for IncludeConfigsArgs {
             }
         }
 
-        match (env, fallback) {
-            (Some(env), None) => Ok(IncludeConfigsArgs::PathEnv(env)),
-            (Some(env), Some(fallback)) => Ok(IncludeConfigsArgs::PathEnvFallback(env, fallback)),
-            _ => Err(Error::new(
-                input.span(),
-                "missing required parameter `path_env`",
-            )),
-        }
+        let path = match (path, env, fallback) {
+            (Some(path), None, None) => ConfigPath::Path(path),
+            (Some(_), ..) => {
+                return Err(Error::new(
+                    input.span(),
+                    "`path_env` and `fallback` cannot be used with a path",
+                ));
+            }
+            (None, Some(env), None) => ConfigPath::PathEnv(env),
+            (None, Some(env), Some(fallback)) => ConfigPath::PathEnvFallback(env, fallback),
+            (None, None, _) => {
+                return Err(Error::new(
+                    input.span(),
+                    "missing required parameter `path_env`",
+                ));
+            }
+        };
+        Ok(Self {
+            path,
+            schema,
+            deny_unknown_keys: deny_unknown_keys.unwrap_or(false),
+        })
     }
 }
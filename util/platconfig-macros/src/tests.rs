@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use super::check_config;
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+fn check_fixture(name: &str, extension: Option<&str>) -> Vec<String> {
+    let path = format!("fixtures/{}.toml", name);
+    let toml = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(&path)).unwrap();
+    match check_config(&path, &toml, extension.map(|s| ("ext.toml", s)), true) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors,
+    }
+}
+
+#[test]
+fn test_configs_pass() {
+    let mut paths = Vec::new();
+    for dir in ["configs", "configs/custom"] {
+        for entry in std::fs::read_dir(root().join(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            // Merged into every config, see `test_platform_configs_pass`.
+            if path.ends_with("defconfig.toml") {
+                continue;
+            }
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                paths.push(path);
+            }
+        }
+    }
+    assert!(!paths.is_empty());
+    for path in paths {
+        let toml = std::fs::read_to_string(&path).unwrap();
+        let path = path.display().to_string();
+        if let Err(errors) = check_config(&path, &toml, None, false) {
+            panic!("{}", errors.join("\n"));
+        }
+    }
+}
+
+#[test]
+fn test_platform_configs_pass() {
+    let defconfig = std::fs::read_to_string(root().join("configs/defconfig.toml")).unwrap();
+    let mut count = 0;
+    for entry in std::fs::read_dir(root().join("platforms")).unwrap() {
+        let dir = entry.unwrap().path();
+        let Ok(toml) = std::fs::read_to_string(dir.join("platconfig.toml")) else {
+            continue;
+        };
+        let schema_path = dir.join("platconfig.schema.toml");
+        let schema = std::fs::read_to_string(&schema_path).unwrap();
+        let schema_path = schema_path.display().to_string();
+        let path = dir.join("platconfig.toml").display().to_string();
+        // Both on its own as the fallback, and merged as by `make defconfig`.
+        for toml in [toml.clone(), format!("{}\n{}", defconfig, toml)] {
+            if let Err(errors) = check_config(&path, &toml, Some((&schema_path, &schema)), true) {
+                panic!("{}", errors.join("\n"));
+            }
+        }
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn test_valid() {
+    assert_eq!(check_fixture("valid", None), Vec::<String>::new());
+}
+
+#[test]
+fn test_truncated() {
+    let errors = check_fixture("truncated", None);
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].starts_with("fixtures/truncated.toml: TOML parse error at line "),
+        "{}",
+        errors[0]
+    );
+}
+
+#[test]
+fn test_typo_key() {
+    assert_eq!(
+        check_fixture("typo_key", None),
+        [
+            "fixtures/typo_key.toml: missing required key `plat.kernel-base-vaddr`",
+            "fixtures/typo_key.toml: unknown key `plat.kernel_base_vadrr`, did you mean \
+             `plat.kernel-base-vaddr`?",
+        ]
+    );
+}
+
+#[test]
+fn test_missing_key() {
+    assert_eq!(
+        check_fixture("missing_key", None),
+        ["fixtures/missing_key.toml: missing required key `devices.timer-irq`"]
+    );
+}
+
+#[test]
+fn test_wrong_type() {
+    assert_eq!(
+        check_fixture("wrong_type", None),
+        [r#"fixtures/wrong_type.toml: `plat.cpu-num` = "four" violates `type = "uint"`"#]
+    );
+}
+
+#[test]
+fn test_irq_out_of_range() {
+    assert_eq!(
+        check_fixture("irq_out_of_range", None),
+        [
+            "fixtures/irq_out_of_range.toml: `devices.uart-irq` = 1100 violates \
+             `max-for-arch.aarch64 = 1019`"
+        ]
+    );
+}
+
+#[test]
+fn test_user_half_vaddr() {
+    assert_eq!(
+        check_fixture("user_half_vaddr", None),
+        [
+            r#"fixtures/user_half_vaddr.toml: `plat.kernel-base-vaddr` = "0x0000_0000_4020_0000" violates `min-for-arch.aarch64 = 0xffff000000000000`"#,
+            r#"fixtures/user_half_vaddr.toml: `plat.kernel-base-vaddr` = "0x0000_0000_4020_0000" violates `min-key = plat.kernel-aspace-base (0xffff000000000000)`"#,
+        ]
+    );
+}
+
+#[test]
+fn test_cpu_num_above_max() {
+    assert_eq!(
+        check_fixture("cpu_num_above_max", None),
+        [
+            "fixtures/cpu_num_above_max.toml: `plat.cpu-num` = 8 violates `max-key = \
+             plat.max-cpu-num (4)`"
+        ]
+    );
+}
+
+#[test]
+fn test_bad_psci_method() {
+    assert_eq!(
+        check_fixture("bad_psci_method", None),
+        [
+            r#"fixtures/bad_psci_method.toml: `plat.psci-method` = "svc" violates `one-of = ["hvc", "smc"]`"#
+        ]
+    );
+}
+
+#[test]
+fn test_extension() {
+    // Requires a key of the base schema, and adds one with a tighter bound.
+    let extension = r#"
+        [devices]
+        gicc-paddr = { required = true }
+        uart-irq = { max = 32 }
+        board-id = { type = "uint", required = true }
+    "#;
+    assert_eq!(
+        check_fixture("valid", Some(extension)),
+        [
+            "fixtures/valid.toml: missing required key `devices.board-id`",
+            "fixtures/valid.toml: missing required key `devices.gicc-paddr`",
+            "fixtures/valid.toml: `devices.uart-irq` = 33 violates `max = 32`",
+        ]
+    );
+
+    let errors = check_fixture("valid", Some("[devices.board-id]\nrequired = true\n"));
+    assert_eq!(
+        errors,
+        ["ext.toml: schema of `devices.board-id`: `type` is missing"]
+    );
+}
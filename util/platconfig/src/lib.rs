@@ -3,12 +3,18 @@
 // See LICENSES for license details.
 
 //! Platform-specific constants and parameters for X-Kernel.
-// NOTE: keep config docs in sync with configs (e.g., optional AHCI/PMU PADDR/IRQ).
 //! Currently supported platform configs can be found in the [configs] directory of
 //! the [X-Kernel] root.
 //!
 //! [X-Kernel]: https://github.com/kylin-x-kernel/x-kernel
 //! [configs]: https://github.com/kylin-x-kernel/x-kernel/tree/main/configs
+//!
+//! # Config keys
+//!
+//! The keys a platform config may contain, generated from the schema it is
+//! checked against. Platforms may require more of them.
+//!
+#![doc = platconfig_macros::schema_docs!()]
 #![no_std]
 
 platconfig_macros::include_configs!(
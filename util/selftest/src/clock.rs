@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Checks of the RTC and of the timer against it.
+//!
+//! Platforms register a test that calls [`check_rtc`] with a function reading
+//! their RTC in seconds since the epoch.
+
+use alloc::format;
+
+use kplat::timer::{NS_SEC, now_ns};
+
+use crate::{Level, Outcome, TestContext};
+
+/// Earliest plausible RTC time: 2024-01-01T00:00:00Z.
+const MIN_EPOCH_SECS: u64 = 1_704_067_200;
+/// Largest accepted disagreement between the timer and the RTC.
+const MAX_DRIFT_PPM: i64 = 1000;
+/// RTC seconds the timer is compared over in manufacturing runs.
+const COMPARE_SECS: u64 = 2;
+
+/// Spins until `read` returns a value other than `from`, for at most
+/// `timeout_ns`. Returns the new value and the timer time it was seen at.
+fn wait_for_change(read: &dyn Fn() -> u64, from: u64, timeout_ns: u64) -> Option<(u64, u64)> {
+    let deadline = now_ns() + timeout_ns;
+    loop {
+        let now = now_ns();
+        let value = read();
+        if value != from {
+            return Some((value, now));
+        }
+        if now > deadline {
+            return None;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Checks that the RTC read by `read_secs` holds a plausible time and, in
+/// manufacturing runs, that the timer runs at the rate of the RTC.
+///
+/// The rate check times [`COMPARE_SECS`] RTC seconds, starting and ending on
+/// a seconds edge, so it takes up to three seconds.
+pub fn check_rtc(ctx: &mut TestContext, read_secs: &dyn Fn() -> u64) -> Outcome {
+    let secs = read_secs();
+    ctx.measure("rtc_s", secs as i64);
+    if secs < MIN_EPOCH_SECS {
+        return Outcome::Fail(format!("RTC time {secs} is before 2024"));
+    }
+    if ctx.level() < Level::Manufacturing {
+        return Outcome::Pass;
+    }
+
+    let Some((start_secs, start_ns)) = wait_for_change(read_secs, secs, 2 * NS_SEC) else {
+        return Outcome::Fail("RTC is not ticking".into());
+    };
+    let mut secs = start_secs;
+    let mut end_ns = start_ns;
+    while secs < start_secs + COMPARE_SECS {
+        match wait_for_change(read_secs, secs, 2 * NS_SEC) {
+            Some((s, ns)) => (secs, end_ns) = (s, ns),
+            None => return Outcome::Fail("RTC stopped ticking".into()),
+        }
+    }
+
+    let expected_ns = ((secs - start_secs) * NS_SEC) as i64;
+    let timer_ns = (end_ns - start_ns) as i64;
+    let drift_ppm = (timer_ns - expected_ns) * 1_000_000 / expected_ns;
+    ctx.measure("timer_ns", timer_ns);
+    ctx.measure("timer_hz", kplat::timer::freq() as i64);
+    ctx.measure("drift_ppm", drift_ppm);
+    if drift_ppm.abs() > MAX_DRIFT_PPM {
+        return Outcome::Fail(format!(
+            "timer drifts {drift_ppm} ppm from the RTC (limit {MAX_DRIFT_PPM})"
+        ));
+    }
+    Outcome::Pass
+}
@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Inventory of probed devices, checked against the platform manifest.
+//!
+//! The manifest is the `expected-devices` list in the `[devices]` section of
+//! the platform config, as `(kind, name)` pairs such as
+//! `("block", "virtio-blk")`. A pair listed twice expects two such devices.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use kspin::SpinNoIrq;
+
+use crate::{Outcome, TestContext};
+
+static DEVICES: SpinNoIrq<Vec<(String, String)>> = SpinNoIrq::new(Vec::new());
+
+/// Records that a device of `kind` named `name` has been probed.
+pub fn add_device(kind: &str, name: &str) {
+    DEVICES.lock().push((kind.to_string(), name.to_string()));
+}
+
+/// Returns the `(kind, name)` pairs of all probed devices.
+pub fn devices() -> Vec<(String, String)> {
+    DEVICES.lock().clone()
+}
+
+/// Returns the entries of `expected` without a matching probed device, as
+/// `kind:name`.
+pub fn missing_devices(expected: &[(&str, &str)]) -> Vec<String> {
+    let mut found = devices();
+    let mut missing = Vec::new();
+    for &(kind, name) in expected {
+        match found.iter().position(|(k, n)| k == kind && n == name) {
+            Some(i) => {
+                found.swap_remove(i);
+            }
+            None => missing.push(format!("{kind}:{name}")),
+        }
+    }
+    missing
+}
+
+fn check_manifest(ctx: &mut TestContext) -> Outcome {
+    let expected = platconfig::devices::EXPECTED_DEVICES;
+    ctx.measure("expected", expected.len() as i64);
+    ctx.measure("found", DEVICES.lock().len() as i64);
+    let missing = missing_devices(expected);
+    if missing.is_empty() {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("missing {}", missing.join(",")))
+    }
+}
+
+crate::register_selftest!(Boot, "devices", check_manifest);
+
+#[cfg(unittest)]
+mod tests_inventory {
+    use unittest::def_test;
+
+    use super::{DEVICES, add_device, missing_devices};
+
+    #[def_test]
+    fn test_missing_devices() {
+        let saved = core::mem::take(&mut *DEVICES.lock());
+        add_device("block", "virtio-blk");
+        add_device("net", "virtio-net");
+
+        assert!(missing_devices(&[("net", "virtio-net"), ("block", "virtio-blk")]).is_empty());
+        assert_eq!(
+            missing_devices(&[
+                ("block", "virtio-blk"),
+                ("block", "virtio-blk"),
+                ("display", "virtio-gpu"),
+            ]),
+            ["block:virtio-blk", "display:virtio-gpu"]
+        );
+
+        *DEVICES.lock() = saved;
+    }
+}
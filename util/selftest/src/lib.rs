@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Boot-time and manufacturing self-tests.
+//!
+//! Unlike `unittest`, which checks kernel logic, self-tests check the board:
+//! that every expected device probed, that the clocks agree and that devices
+//! can move data. Subsystems register tests with [`register_selftest!`]; [`run`]
+//! executes the ones of the requested [`Level`] and returns a [`Report`] with
+//! one [`Record`] per test and an aggregate verdict.
+//!
+//! The report is printed as a block of `key=value` lines between
+//! `---[ selftest report ]---` and `---[ end of selftest report ]---`, one
+//! `test=` line per test and a final `verdict=` line.
+#![no_std]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate log;
+
+pub mod clock;
+mod inventory;
+mod report;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use kspin::SpinNoIrq;
+#[doc(hidden)]
+pub use linkme;
+
+pub use self::{
+    inventory::{add_device, devices, missing_devices},
+    report::{Measurement, Outcome, Record, Report, Verdict},
+};
+
+/// Which set of self-tests to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Quick checks run on every boot.
+    Boot,
+    /// The extended set run on the production line, including tests that
+    /// take seconds.
+    Manufacturing,
+}
+
+impl Level {
+    /// Returns the name used on the command line and in reports.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Level::Boot => "boot",
+            Level::Manufacturing => "manufacturing",
+        }
+    }
+
+    /// Picks the level from a kernel command line.
+    ///
+    /// `selftest=boot` (the default) and `selftest=manufacturing` select a
+    /// level, `selftest=off` disables self-tests.
+    pub fn from_cmdline(cmdline: &str) -> Option<Level> {
+        let value = cmdline
+            .split_ascii_whitespace()
+            .filter_map(|arg| arg.strip_prefix("selftest="))
+            .last();
+        match value {
+            None | Some("boot") => Some(Level::Boot),
+            Some("manufacturing") => Some(Level::Manufacturing),
+            Some("off") => None,
+            Some(other) => {
+                warn!("unknown selftest level {other:?}, using boot");
+                Some(Level::Boot)
+            }
+        }
+    }
+}
+
+/// State passed to a running self-test.
+pub struct TestContext {
+    level: Level,
+    measurements: Vec<Measurement>,
+}
+
+impl TestContext {
+    /// Returns the level the tests are run at.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Records a measurement, reported as `name=value`.
+    ///
+    /// By convention `name` ends with the unit, e.g. `drift_ppm`.
+    pub fn measure(&mut self, name: impl Into<String>, value: i64) {
+        self.measurements.push(Measurement {
+            name: name.into(),
+            value,
+        });
+    }
+}
+
+/// A registered self-test.
+pub struct SelfTest {
+    name: &'static str,
+    level: Level,
+    func: fn(&mut TestContext) -> Outcome,
+}
+
+impl SelfTest {
+    #[doc(hidden)]
+    pub const fn new(
+        name: &'static str,
+        level: Level,
+        func: fn(&mut TestContext) -> Outcome,
+    ) -> Self {
+        Self { name, level, func }
+    }
+
+    /// Returns the name of the test.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the lowest level the test runs at.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+}
+
+/// All self-tests linked into the kernel.
+#[linkme::distributed_slice]
+pub static SELFTESTS: [SelfTest];
+
+/// Registers `$func` as the self-test `$name`, run at `$level` and above.
+///
+/// `$func` has the signature `fn(&mut TestContext) -> Outcome`.
+///
+/// ```ignore
+/// selftest::register_selftest!(Boot, "rtc", check_rtc);
+/// ```
+#[macro_export]
+macro_rules! register_selftest {
+    ($level:ident, $name:literal, $func:path) => {
+        const _: () = {
+            #[$crate::linkme::distributed_slice($crate::SELFTESTS)]
+            #[linkme(crate = $crate::linkme)]
+            static SELFTEST: $crate::SelfTest =
+                $crate::SelfTest::new($name, $crate::Level::$level, $func);
+        };
+    };
+}
+
+/// The report of the last run, as printed.
+static LAST_REPORT: SpinNoIrq<Option<String>> = SpinNoIrq::new(None);
+
+/// Runs all self-tests registered for `level`, in name order.
+///
+/// The report is logged, printed to the console and kept for
+/// [`last_report`].
+pub fn run(level: Level) -> Report {
+    let mut tests: Vec<&SelfTest> = SELFTESTS.iter().filter(|t| t.level <= level).collect();
+    tests.sort_by_key(|t| t.name);
+    info!("Running {} {} self-tests...", tests.len(), level.as_str());
+
+    let mut records = Vec::with_capacity(tests.len());
+    for test in tests {
+        let mut ctx = TestContext {
+            level,
+            measurements: Vec::new(),
+        };
+        let start = kplat::timer::now_ns();
+        let outcome = (test.func)(&mut ctx);
+        let duration_us = (kplat::timer::now_ns() - start) / kplat::timer::NS_US;
+        match &outcome {
+            Outcome::Pass => info!("  selftest {}: pass", test.name),
+            Outcome::Skip(reason) => info!("  selftest {}: skip ({reason})", test.name),
+            Outcome::Fail(reason) => error!("  selftest {}: FAIL ({reason})", test.name),
+        }
+        records.push(Record {
+            name: test.name,
+            outcome,
+            duration_us,
+            measurements: ctx.measurements,
+        });
+    }
+
+    let report = Report { level, records };
+    let text = report.to_string();
+    kplat::io::write_data_atomic(text.as_bytes());
+    *LAST_REPORT.lock() = Some(text);
+    report
+}
+
+/// Returns the printed report of the last run, if any.
+pub fn last_report() -> Option<String> {
+    LAST_REPORT.lock().clone()
+}
+
+#[cfg(unittest)]
+mod tests_selftest {
+    use unittest::def_test;
+
+    use super::Level;
+
+    #[def_test]
+    fn test_level_from_cmdline() {
+        assert_eq!(Level::from_cmdline(""), Some(Level::Boot));
+        assert_eq!(
+            Level::from_cmdline("console=ttyS0 selftest=manufacturing"),
+            Some(Level::Manufacturing)
+        );
+        assert_eq!(Level::from_cmdline("selftest=boot selftest=off"), None);
+        assert_eq!(Level::from_cmdline("selftest=bogus"), Some(Level::Boot));
+    }
+}
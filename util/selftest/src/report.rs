@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Self-test results and their machine-parseable form.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::Level;
+
+/// The result of one self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The hardware behaved as expected.
+    Pass,
+    /// The hardware misbehaved, for the given reason.
+    Fail(String),
+    /// The test could not run here, for the given reason.
+    Skip(String),
+}
+
+impl Outcome {
+    /// Returns the name used in reports.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail(_) => "fail",
+            Outcome::Skip(_) => "skip",
+        }
+    }
+}
+
+/// A named value measured by a self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    /// Name of the value, ending with its unit.
+    pub name: String,
+    /// The measured value.
+    pub value: i64,
+}
+
+/// The record of one self-test run.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Name of the test.
+    pub name: &'static str,
+    /// Result of the test.
+    pub outcome: Outcome,
+    /// Time the test took, in microseconds.
+    pub duration_us: u64,
+    /// Values measured by the test.
+    pub measurements: Vec<Measurement>,
+}
+
+/// The aggregate verdict of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// No test failed.
+    Pass,
+    /// At least one test failed.
+    Fail,
+}
+
+impl Verdict {
+    /// Returns the exit code that goes with the verdict.
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Verdict::Pass => 0,
+            Verdict::Fail => 1,
+        }
+    }
+}
+
+/// The results of a self-test run.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Level the tests were run at.
+    pub level: Level,
+    /// One record per test, in the order they ran.
+    pub records: Vec<Record>,
+}
+
+impl Report {
+    /// Returns the records of the failed tests.
+    pub fn failures(&self) -> impl Iterator<Item = &Record> {
+        self.records
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Fail(_)))
+    }
+
+    /// Returns the aggregate verdict.
+    pub fn verdict(&self) -> Verdict {
+        if self.failures().next().is_some() {
+            Verdict::Fail
+        } else {
+            Verdict::Pass
+        }
+    }
+}
+
+/// Writes `reason` as a quoted value that stays on one line.
+fn write_reason(f: &mut fmt::Formatter<'_>, reason: &str) -> fmt::Result {
+    f.write_str(" reason=\"")?;
+    for c in reason.chars() {
+        match c {
+            '"' => f.write_str("'")?,
+            '\n' | '\r' => f.write_str(" ")?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "---[ selftest report ]---")?;
+        writeln!(f, "level={}", self.level.as_str())?;
+        for record in &self.records {
+            write!(
+                f,
+                "test={} result={} duration_us={}",
+                record.name,
+                record.outcome.as_str(),
+                record.duration_us
+            )?;
+            if let Outcome::Fail(reason) | Outcome::Skip(reason) = &record.outcome {
+                write_reason(f, reason)?;
+            }
+            for m in &record.measurements {
+                write!(f, " {}={}", m.name, m.value)?;
+            }
+            writeln!(f)?;
+        }
+
+        let verdict = self.verdict();
+        write!(
+            f,
+            "verdict={} exit_code={} total={} failed={}",
+            match verdict {
+                Verdict::Pass => "pass",
+                Verdict::Fail => "fail",
+            },
+            verdict.exit_code(),
+            self.records.len(),
+            self.failures().count()
+        )?;
+        for (i, record) in self.failures().enumerate() {
+            f.write_str(if i == 0 { " failures=" } else { "," })?;
+            f.write_str(record.name)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "---[ end of selftest report ]---")
+    }
+}
+
+#[cfg(unittest)]
+mod tests_report {
+    use alloc::{string::ToString, vec};
+
+    use unittest::def_test;
+
+    use super::*;
+
+    fn record(name: &'static str, outcome: Outcome) -> Record {
+        Record {
+            name,
+            outcome,
+            duration_us: 5,
+            measurements: vec![],
+        }
+    }
+
+    #[def_test]
+    fn test_report_format() {
+        let mut ok = record("rtc", Outcome::Pass);
+        ok.measurements.push(Measurement {
+            name: "drift_ppm".into(),
+            value: -3,
+        });
+        let report = Report {
+            level: Level::Manufacturing,
+            records: vec![
+                ok,
+                record(
+                    "devices",
+                    Outcome::Fail("missing \"block:virtio-blk\"".into()),
+                ),
+                record("net-link", Outcome::Skip("no cable".into())),
+            ],
+        };
+        assert_eq!(report.verdict(), Verdict::Fail);
+        assert_eq!(
+            report.to_string(),
+            concat!(
+                "---[ selftest report ]---\n",
+                "level=manufacturing\n",
+                "test=rtc result=pass duration_us=5 drift_ppm=-3\n",
+                "test=devices result=fail duration_us=5 reason=\"missing 'block:virtio-blk'\"\n",
+                "test=net-link result=skip duration_us=5 reason=\"no cable\"\n",
+                "verdict=fail exit_code=1 total=3 failed=1 failures=devices\n",
+                "---[ end of selftest report ]---\n",
+            )
+        );
+    }
+
+    #[def_test]
+    fn test_report_pass() {
+        let report = Report {
+            level: Level::Boot,
+            records: vec![record("rtc", Outcome::Skip("no RTC".into()))],
+        };
+        assert_eq!(report.verdict(), Verdict::Pass);
+        assert!(
+            report
+                .to_string()
+                .contains("verdict=pass exit_code=0 total=1 failed=0\n")
+        );
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Architecture-specific site emission, instruction encoding and I-cache
+//! maintenance.
+//!
+//! Every supported architecture has fixed 32-bit instructions, and a naturally
+//! aligned 32-bit store of a `NOP` or an unconditional `B`/`J` is observed
+//! atomically by other CPUs, so sites can be flipped without stopping them.
+
+/// Emits a patchable site for the current architecture.
+///
+/// Expands to a block of type `bool` that evaluates to `$taken` when the site
+/// branches and `!$taken` when it falls through.
+#[cfg(target_arch = "aarch64")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __static_branch_site {
+    ($key:path, $flags:literal, $taken:literal) => {
+        'site: {
+            unsafe {
+                ::core::arch::asm!(
+                    "1: nop",
+                    ".pushsection __static_keys, \"aw\"",
+                    ".balign 8",
+                    ".quad {key} + {flags}, 1b, {target}",
+                    ".popsection",
+                    target = label { break 'site $taken; },
+                    key = sym $key,
+                    flags = const $flags,
+                );
+            }
+            !$taken
+        }
+    };
+}
+
+#[cfg(target_arch = "riscv64")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __static_branch_site {
+    ($key:path, $flags:literal, $taken:literal) => {
+        'site: {
+            unsafe {
+                ::core::arch::asm!(
+                    ".balign 4",
+                    ".option push",
+                    ".option norvc",
+                    "1: nop",
+                    ".option pop",
+                    ".pushsection __static_keys, \"aw\"",
+                    ".balign 8",
+                    ".dword {key} + {flags}, 1b, {target}",
+                    ".popsection",
+                    target = label { break 'site $taken; },
+                    key = sym $key,
+                    flags = const $flags,
+                );
+            }
+            !$taken
+        }
+    };
+}
+
+#[cfg(target_arch = "loongarch64")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __static_branch_site {
+    ($key:path, $flags:literal, $taken:literal) => {
+        'site: {
+            unsafe {
+                ::core::arch::asm!(
+                    "1: nop",
+                    ".pushsection __static_keys, \"aw\"",
+                    ".balign 8",
+                    ".dword {key} + {flags}, 1b, {target}",
+                    ".popsection",
+                    target = label { break 'site $taken; },
+                    key = sym $key,
+                    flags = const $flags,
+                );
+            }
+            !$taken
+        }
+    };
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "aarch64")] {
+        /// Encoding of the architectural no-op.
+        pub(crate) const NOP: u32 = 0xd503_201f;
+
+        /// Encodes `b target` placed at `pc`.
+        pub(crate) fn branch(pc: usize, target: usize) -> Option<u32> {
+            let offset = (target as isize).wrapping_sub(pc as isize);
+            if offset & 0b11 != 0 || !(-(1 << 27)..(1 << 27)).contains(&offset) {
+                return None;
+            }
+            Some(0x1400_0000 | ((offset >> 2) as u32 & 0x03ff_ffff))
+        }
+
+        /// Makes a patched instruction visible to the instruction fetch of
+        /// all CPUs in the inner shareable domain.
+        pub(crate) fn sync_icache(addr: usize) {
+            unsafe {
+                core::arch::asm!(
+                    "dc cvau, {0}",
+                    "dsb ish",
+                    "ic ivau, {0}",
+                    "dsb ish",
+                    "isb",
+                    in(reg) addr,
+                );
+            }
+        }
+    } else if #[cfg(target_arch = "riscv64")] {
+        pub(crate) const NOP: u32 = 0x0000_0013;
+
+        /// Encodes `jal zero, target` placed at `pc`.
+        pub(crate) fn branch(pc: usize, target: usize) -> Option<u32> {
+            let offset = (target as isize).wrapping_sub(pc as isize);
+            if offset & 0b1 != 0 || !(-(1 << 20)..(1 << 20)).contains(&offset) {
+                return None;
+            }
+            let imm = offset as u32;
+            Some(
+                ((imm >> 20) & 0x1) << 31
+                    | ((imm >> 1) & 0x3ff) << 21
+                    | ((imm >> 11) & 0x1) << 20
+                    | ((imm >> 12) & 0xff) << 12
+                    | 0x6f,
+            )
+        }
+
+        /// `fence.i` only orders the local hart; remote harts are handled by
+        /// [`TextPokeIf::sync_remote_icache`](crate::TextPokeIf).
+        pub(crate) fn sync_icache(_addr: usize) {
+            unsafe { core::arch::asm!("fence.i") };
+        }
+    } else if #[cfg(target_arch = "loongarch64")] {
+        pub(crate) const NOP: u32 = 0x0340_0000;
+
+        /// Encodes `b target` placed at `pc`.
+        pub(crate) fn branch(pc: usize, target: usize) -> Option<u32> {
+            let offset = (target as isize).wrapping_sub(pc as isize);
+            if offset & 0b11 != 0 || !(-(1 << 27)..(1 << 27)).contains(&offset) {
+                return None;
+            }
+            let offs = (offset >> 2) as u32;
+            Some(0x5000_0000 | (offs & 0xffff) << 10 | (offs >> 16) & 0x3ff)
+        }
+
+        pub(crate) fn sync_icache(_addr: usize) {
+            unsafe { core::arch::asm!("ibar 0") };
+        }
+    } else {
+        // Patching is not implemented; sites are plain branches.
+        #[allow(dead_code)]
+        pub(crate) const NOP: u32 = 0;
+
+        #[allow(dead_code)]
+        pub(crate) fn branch(_pc: usize, _target: usize) -> Option<u32> {
+            None
+        }
+
+        #[allow(dead_code)]
+        pub(crate) fn sync_icache(_addr: usize) {}
+    }
+}
+
+/// Synchronizes the instruction stream of the calling CPU with prior text
+/// modifications.
+///
+/// Intended to be run on every CPU (e.g. via IPI) after patching on
+/// architectures whose I-cache maintenance is not broadcast.
+pub fn local_icache_sync() {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "aarch64")] {
+            unsafe { core::arch::asm!("isb") };
+        } else if #[cfg(target_arch = "riscv64")] {
+            unsafe { core::arch::asm!("fence.i") };
+        } else if #[cfg(target_arch = "loongarch64")] {
+            unsafe { core::arch::asm!("ibar 0") };
+        }
+    }
+}
@@ -0,0 +1,395 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Static keys: branches that cost nothing while disabled.
+//!
+//! A [`StaticKey`] is tested with [`static_branch_unlikely!`] or
+//! [`static_branch_likely!`]. With the `patch` feature on aarch64, riscv64 and
+//! loongarch64 each test compiles to a single `NOP` that is live-patched into
+//! an unconditional branch when the key changes state. Every site is recorded
+//! in the `__static_keys` link section, which [`init`] sorts by key.
+//!
+//! On other architectures, or without the `patch` feature, a test is a plain
+//! load and branch on the key's flag, so callers never need to care which
+//! implementation is active.
+//!
+//! Text is made writable through [`TextPokeIf`], which the runtime implements
+//! on top of the kernel address space.
+#![no_std]
+
+#[macro_use]
+extern crate log;
+
+mod arch;
+pub mod tracepoint;
+
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kspin::SpinNoIrq;
+
+pub use self::arch::local_icache_sync;
+
+/// Whether sites are live-patched on this build.
+pub const PATCHING: bool = cfg!(all(
+    feature = "patch",
+    any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )
+));
+
+/// Interface for making kernel text writable while sites are patched.
+#[crate_interface::def_interface]
+pub trait TextPokeIf {
+    /// Grants (`true`) or revokes (`false`) write permission on `range`.
+    fn set_text_writable(range: Range<usize>, writable: bool) -> bool;
+
+    /// Makes the patched text visible to the instruction fetch of other CPUs.
+    fn sync_remote_icache();
+}
+
+/// A boolean switch that can be tested without a load on the fast path.
+///
+/// Keys must be `static` items: their address identifies their sites.
+#[repr(C, align(8))]
+pub struct StaticKey {
+    enabled: AtomicBool,
+}
+
+impl StaticKey {
+    /// Creates a key with the given initial state.
+    pub const fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    /// Returns the current state of the key.
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Enables the key and patches all of its sites.
+    pub fn enable(&'static self) {
+        static_key_enable(self);
+    }
+
+    /// Disables the key and patches all of its sites.
+    pub fn disable(&'static self) {
+        static_key_disable(self);
+    }
+
+    /// Sets the key to `enabled`.
+    pub fn set(&'static self, enabled: bool) {
+        if enabled {
+            self.enable()
+        } else {
+            self.disable()
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        feature = "patch",
+        any(
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        )
+    ))] {
+        /// Tests a key that is expected to be disabled.
+        ///
+        /// The disabled path falls through; the enabled path is a taken branch.
+        #[macro_export]
+        macro_rules! static_branch_unlikely {
+            ($key:path) => {
+                $crate::__static_branch_site!($key, 0, true)
+            };
+        }
+
+        /// Tests a key that is expected to be enabled.
+        ///
+        /// The enabled path falls through; the disabled path is a taken branch.
+        #[macro_export]
+        macro_rules! static_branch_likely {
+            ($key:path) => {
+                $crate::__static_branch_site!($key, 1, false)
+            };
+        }
+    } else {
+        /// Tests a key that is expected to be disabled.
+        #[macro_export]
+        macro_rules! static_branch_unlikely {
+            ($key:path) => {
+                $crate::StaticKey::is_enabled(&$key)
+            };
+        }
+
+        /// Tests a key that is expected to be enabled.
+        #[macro_export]
+        macro_rules! static_branch_likely {
+            ($key:path) => {
+                $crate::StaticKey::is_enabled(&$key)
+            };
+        }
+    }
+}
+
+/// A recorded site, as emitted by the site macros.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct JumpEntry {
+    /// Address of the key, with [`JumpEntry::LIKELY`] in the low bit.
+    key: usize,
+    code: usize,
+    target: usize,
+}
+
+impl JumpEntry {
+    /// The site falls through while the key is enabled.
+    const LIKELY: usize = 1;
+
+    fn key_addr(&self) -> usize {
+        self.key & !Self::LIKELY
+    }
+
+    fn is_likely(&self) -> bool {
+        self.key & Self::LIKELY != 0
+    }
+
+    /// Returns the instruction the site must hold for the given key state.
+    fn insn_for(&self, enabled: bool) -> Option<u32> {
+        if enabled != self.is_likely() {
+            arch::branch(self.code, self.target)
+        } else {
+            Some(arch::NOP)
+        }
+    }
+}
+
+const MAX_UNPATCHABLE: usize = 8;
+
+struct PatchState {
+    /// Whether [`init`] has run; before that only key flags are updated.
+    ready: bool,
+    unpatchable: [Option<Range<usize>>; MAX_UNPATCHABLE],
+}
+
+static PATCH_STATE: SpinNoIrq<PatchState> = SpinNoIrq::new(PatchState {
+    ready: false,
+    unpatchable: [const { None }; MAX_UNPATCHABLE],
+});
+
+fn jump_entries() -> &'static mut [JumpEntry] {
+    if !PATCHING {
+        return &mut [];
+    }
+    unsafe extern "C" {
+        static _static_keys_start: [JumpEntry; 0];
+        static _static_keys_end: [JumpEntry; 0];
+    }
+    unsafe {
+        core::slice::from_raw_parts_mut(
+            _static_keys_start.as_ptr().cast_mut(),
+            _static_keys_end
+                .as_ptr()
+                .offset_from_unsigned(_static_keys_start.as_ptr()),
+        )
+    }
+}
+
+fn entries_of(key: &StaticKey) -> &'static [JumpEntry] {
+    let entries = jump_entries();
+    let addr = key as *const StaticKey as usize;
+    let start = entries.partition_point(|e| e.key_addr() < addr);
+    let end = start + entries[start..].partition_point(|e| e.key_addr() == addr);
+    &entries[start..end]
+}
+
+impl PatchState {
+    fn is_unpatchable(&self, addr: usize) -> bool {
+        self.unpatchable
+            .iter()
+            .flatten()
+            .any(|range| range.contains(&addr))
+    }
+
+    /// Rewrites `entries` for their keys' current state.
+    ///
+    /// Returns the number of sites that were left untouched.
+    fn patch(&self, entries: &[JumpEntry]) -> usize {
+        let Some(text) = text_range(entries) else {
+            return 0;
+        };
+        if !set_text_writable(text.clone(), true) {
+            warn!("static keys: cannot make text {text:#x?} writable");
+            return entries.len();
+        }
+
+        let mut skipped = 0;
+        for entry in entries {
+            let key = unsafe { &*(entry.key_addr() as *const StaticKey) };
+            let insn = match entry.insn_for(key.is_enabled()) {
+                Some(insn) if !self.is_unpatchable(entry.code) => insn,
+                Some(_) => {
+                    skipped += 1;
+                    continue;
+                }
+                None => {
+                    warn!("static keys: site {:#x} out of branch range", entry.code);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let site = entry.code as *mut u32;
+            if unsafe { site.read_volatile() } != insn {
+                unsafe { site.write_volatile(insn) };
+                arch::sync_icache(entry.code);
+            }
+        }
+
+        set_text_writable(text, false);
+        sync_remote_icache();
+        skipped
+    }
+}
+
+// `TextPokeIf` is only required to be implemented when patching is built in.
+#[cfg(feature = "patch")]
+fn set_text_writable(range: Range<usize>, writable: bool) -> bool {
+    crate_interface::call_interface!(TextPokeIf::set_text_writable(range, writable))
+}
+
+#[cfg(not(feature = "patch"))]
+fn set_text_writable(_range: Range<usize>, _writable: bool) -> bool {
+    false
+}
+
+#[cfg(feature = "patch")]
+fn sync_remote_icache() {
+    crate_interface::call_interface!(TextPokeIf::sync_remote_icache());
+}
+
+#[cfg(not(feature = "patch"))]
+fn sync_remote_icache() {}
+
+/// Returns the page-aligned range covering the code of `entries`.
+fn text_range(entries: &[JumpEntry]) -> Option<Range<usize>> {
+    const PAGE_SIZE: usize = 0x1000;
+    let start = entries.iter().map(|e| e.code).min()?;
+    let end = entries.iter().map(|e| e.code).max()? + 4;
+    Some((start & !(PAGE_SIZE - 1))..((end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)))
+}
+
+fn update(key: &'static StaticKey, enabled: bool) {
+    let state = PATCH_STATE.lock();
+    if key.enabled.swap(enabled, Ordering::Relaxed) == enabled || !state.ready {
+        return;
+    }
+    let skipped = state.patch(entries_of(key));
+    if skipped > 0 {
+        warn!(
+            "static keys: {skipped} site(s) of key {:p} left unpatched",
+            key as *const StaticKey
+        );
+    }
+}
+
+/// Enables `key`, turning all of its sites into the enabled path.
+pub fn static_key_enable(key: &'static StaticKey) {
+    update(key, true);
+}
+
+/// Disables `key`, turning all of its sites into the disabled path.
+pub fn static_key_disable(key: &'static StaticKey) {
+    update(key, false);
+}
+
+/// Excludes `range` from patching.
+///
+/// Sites inside an excluded range keep their current instruction and observe
+/// later key changes only through [`StaticKey::is_enabled`]. Returns `false`
+/// if the exclusion table is full.
+pub fn mark_unpatchable(range: Range<usize>) -> bool {
+    let mut state = PATCH_STATE.lock();
+    match state.unpatchable.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(range);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sorts the site table and brings every site in line with its key.
+///
+/// Keys may be toggled before this is called; only their flags change until
+/// then.
+pub fn init() {
+    let mut state = PATCH_STATE.lock();
+    let entries = jump_entries();
+    entries.sort_unstable();
+    let skipped = state.patch(entries);
+    state.ready = true;
+    info!(
+        "static keys: {} site(s), patching {}",
+        entries.len(),
+        if PATCHING { "enabled" } else { "disabled" }
+    );
+    if skipped > 0 {
+        warn!("static keys: {skipped} site(s) left unpatched");
+    }
+}
+
+#[cfg(unittest)]
+mod tests_static_keys {
+    use unittest::def_test;
+
+    use super::{JumpEntry, StaticKey, text_range};
+
+    #[def_test]
+    fn test_jump_entry_flags() {
+        let entry = JumpEntry {
+            key: 0x8000 | JumpEntry::LIKELY,
+            code: 0x1000,
+            target: 0x1040,
+        };
+        assert_eq!(entry.key_addr(), 0x8000);
+        assert!(entry.is_likely());
+    }
+
+    #[def_test]
+    fn test_text_range_page_aligned() {
+        let entries = [
+            JumpEntry {
+                key: 0x8000,
+                code: 0x1ffc,
+                target: 0x2010,
+            },
+            JumpEntry {
+                key: 0x8000,
+                code: 0x1100,
+                target: 0x1120,
+            },
+        ];
+        assert_eq!(text_range(&entries), Some(0x1000..0x2000));
+        assert_eq!(text_range(&[]), None);
+    }
+
+    #[def_test]
+    fn test_key_flag_before_init() {
+        static KEY: StaticKey = StaticKey::new(false);
+        assert!(!KEY.is_enabled());
+        KEY.enable();
+        assert!(KEY.is_enabled());
+        KEY.disable();
+        assert!(!KEY.is_enabled());
+    }
+}
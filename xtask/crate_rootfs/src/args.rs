@@ -42,6 +42,17 @@ fn parse_copy_spec(input: &str) -> Result<CopySpec, String> {
     })
 }
 
+fn parse_populate_spec(input: &str) -> Result<CopySpec, String> {
+    let (src, dest) = input.split_once(':').unwrap_or((input, "/"));
+    if src.trim().is_empty() || dest.trim().is_empty() {
+        return Err("populate spec must be in SRC[:DEST] format".to_string());
+    }
+    Ok(CopySpec {
+        src: PathBuf::from(src),
+        dest: dest.to_string(),
+    })
+}
+
 /// Command line arguments for rootfs creation.
 #[derive(Debug, Parser)]
 #[command(author, version, about = "Create an ext4 rootfs image")]
@@ -57,6 +68,20 @@ pub struct Args {
     /// Multiple copy specs in SRC:DEST format. Can be provided multiple times.
     #[arg(long = "copy", value_parser = parse_copy_spec)]
     pub copies: Vec<CopySpec>,
+
+    /// Host directory trees to copy, in SRC[:DEST] format with DEST
+    /// defaulting to the image root. Can be provided multiple times.
+    #[arg(long = "populate", value_parser = parse_populate_spec)]
+    pub populates: Vec<CopySpec>,
+
+    /// Check the image with e2fsck and read the populated trees back.
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Also mount the image on the host and compare the populated trees
+    /// (implies --verify, needs root).
+    #[arg(long)]
+    pub mount_check: bool,
 }
 
 /// Parse CLI args into a structured Args value.
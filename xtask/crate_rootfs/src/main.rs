@@ -1,7 +1,10 @@
 mod args;
 mod blockdev;
+mod populate;
 mod rootfs;
+mod tree;
 mod util;
+mod verify;
 
 use args::parse_args;
 use rootfs::build_rootfs;
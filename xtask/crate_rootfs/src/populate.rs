@@ -0,0 +1,110 @@
+use rsext4::{
+    Jbd2Dev,
+    dir::{get_inode_with_num, mkdir_with_ino},
+    disknode::Ext4Inode,
+    ext4::Ext4FileSystem,
+    file::{mkfile_with_ino, read_file, read_symlink_target, symlink},
+};
+
+use crate::{
+    blockdev::FileBlockDev,
+    tree::{Node, Tree, compare_node},
+};
+
+/// Path in the image of the tree entry `rel` copied under `dest`.
+pub fn image_path(dest: &str, rel: &str) -> String {
+    let dest = dest.trim_end_matches('/');
+    if rel.is_empty() {
+        if dest.is_empty() {
+            "/".to_string()
+        } else {
+            dest.to_string()
+        }
+    } else {
+        format!("{dest}/{rel}")
+    }
+}
+
+/// Copy `tree` into the image under the directory `dest`, which is created
+/// if needed.
+///
+/// Everything goes through the same directory, file and symlink creation
+/// paths as writes from the kernel. Parents sort before their children, so
+/// walking the tree in order always finds the parent directory in place.
+pub fn populate(
+    fs: &mut Ext4FileSystem,
+    jbd: &mut Jbd2Dev<FileBlockDev>,
+    tree: &Tree,
+    dest: &str,
+) -> Result<(), String> {
+    for (rel, node) in tree {
+        let path = image_path(dest, rel);
+        match node {
+            Node::Dir { mode } => {
+                let (ino, _) = mkdir_with_ino(jbd, fs, &path)
+                    .ok_or_else(|| format!("failed to create directory {path}"))?;
+                set_mode(fs, jbd, ino, Ext4Inode::S_IFDIR, *mode, &path)?;
+            }
+            Node::File { mode, data } => {
+                let data = (!data.is_empty()).then_some(data.as_slice());
+                let (ino, _) = mkfile_with_ino(jbd, fs, &path, data, None)
+                    .ok_or_else(|| format!("failed to create file {path}"))?;
+                set_mode(fs, jbd, ino, Ext4Inode::S_IFREG, *mode, &path)?;
+            }
+            Node::Symlink { target } => {
+                symlink(jbd, fs, target, &path)
+                    .map_err(|e| format!("failed to create symlink {path}: {e}"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_mode(
+    fs: &mut Ext4FileSystem,
+    jbd: &mut Jbd2Dev<FileBlockDev>,
+    ino: u32,
+    kind: u16,
+    mode: u32,
+    path: &str,
+) -> Result<(), String> {
+    fs.modify_inode(jbd, ino, |inode| {
+        inode.i_mode = kind | (mode & 0o7777) as u16;
+    })
+    .map_err(|e| format!("chmod {path} failed: {e}"))
+}
+
+/// Check that the image holds `tree` under `dest`, reading it back through
+/// the filesystem code.
+pub fn check(
+    fs: &mut Ext4FileSystem,
+    jbd: &mut Jbd2Dev<FileBlockDev>,
+    tree: &Tree,
+    dest: &str,
+) -> Result<(), String> {
+    for (rel, want) in tree {
+        let path = image_path(dest, rel);
+        let (_, mut inode) = get_inode_with_num(fs, jbd, &path)
+            .map_err(|e| format!("lookup {path} failed: {e}"))?
+            .ok_or_else(|| format!("{path}: missing"))?;
+        let mode = (inode.i_mode & 0o7777) as u32;
+        let got = if inode.is_dir() {
+            Node::Dir { mode }
+        } else if inode.is_symlink() {
+            let target = read_symlink_target(jbd, fs, &mut inode)
+                .map_err(|e| format!("readlink {path} failed: {e}"))?;
+            Node::Symlink {
+                target: String::from_utf8_lossy(&target).into_owned(),
+            }
+        } else {
+            let data = read_file(jbd, fs, &path)
+                .map_err(|e| format!("read {path} failed: {e}"))?
+                .unwrap_or_default();
+            Node::File { mode, data }
+        };
+        if let Some(diff) = compare_node(want, &got) {
+            return Err(format!("{path}: {diff}"));
+        }
+    }
+    Ok(())
+}
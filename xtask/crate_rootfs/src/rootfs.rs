@@ -15,10 +15,13 @@ use rsext4::{
 use crate::{
     args::Args,
     blockdev::FileBlockDev,
+    populate::{check, populate},
+    tree::{Tree, snapshot},
     util::{align_up, ensure_parent},
+    verify::{fsck, mount_check},
 };
 
-/// Build an ext4 image with a single hello binary.
+/// Build an ext4 image holding the requested files and directory trees.
 pub fn build_rootfs(args: Args) -> Result<(), String> {
     ensure_parent(&args.image)?;
 
@@ -69,6 +72,15 @@ pub fn build_rootfs(args: Args) -> Result<(), String> {
         created_paths.push(dest);
     }
 
+    let mut trees = Vec::new();
+    for item in &args.populates {
+        let tree = snapshot(&item.src)?;
+        let dest = normalize_dest(&item.dest);
+        populate(&mut fs, &mut jbd, &tree, &dest)
+            .map_err(|e| format!("populating {dest} from {}: {e}", item.src.display()))?;
+        trees.push((tree, dest));
+    }
+
     for path in &created_paths {
         verify_present(&mut fs, &mut jbd, path)?;
     }
@@ -80,7 +92,16 @@ pub fn build_rootfs(args: Args) -> Result<(), String> {
 
     drop(jbd);
 
-    verify_persisted(&args, total_blocks, &created_paths)?;
+    verify_persisted(&args, total_blocks, &created_paths, &trees)?;
+
+    if args.verify || args.mount_check {
+        fsck(&args.image)?;
+    }
+    if args.mount_check {
+        for (tree, dest) in &trees {
+            mount_check(&args.image, tree, dest)?;
+        }
+    }
 
     Ok(())
 }
@@ -99,7 +120,12 @@ fn verify_present(
     Ok(())
 }
 
-fn verify_persisted(args: &Args, total_blocks: u64, paths: &[String]) -> Result<(), String> {
+fn verify_persisted(
+    args: &Args,
+    total_blocks: u64,
+    paths: &[String],
+    trees: &[(Tree, String)],
+) -> Result<(), String> {
     let verify_file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -118,6 +144,12 @@ fn verify_persisted(args: &Args, total_blocks: u64, paths: &[String]) -> Result<
             return Err(format!("verify after umount {path} failed: not found"));
         }
     }
+    if args.verify || args.mount_check {
+        for (tree, dest) in trees {
+            check(&mut verify_fs, &mut verify_jbd, tree, dest)
+                .map_err(|e| format!("verify after umount failed: {e}"))?;
+        }
+    }
 
     verify_fs
         .umount(&mut verify_jbd)
@@ -0,0 +1,152 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+};
+
+/// A node of a directory tree, as compared after populating an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Dir { mode: u32 },
+    File { mode: u32, data: Vec<u8> },
+    Symlink { target: String },
+}
+
+impl Node {
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Dir { .. } => "directory",
+            Node::File { .. } => "file",
+            Node::Symlink { .. } => "symlink",
+        }
+    }
+}
+
+/// A directory tree keyed by path relative to its root, without a leading
+/// `/`. The root itself is the empty path.
+pub type Tree = BTreeMap<String, Node>;
+
+/// Read the tree rooted at the host directory `root`.
+///
+/// Files other than directories, regular files and symlinks are skipped with
+/// a warning, as the image cannot hold them.
+pub fn snapshot(root: &Path) -> Result<Tree, String> {
+    let mut tree = Tree::new();
+    let meta = fs::metadata(root).map_err(|e| format!("failed to stat {}: {e}", root.display()))?;
+    if !meta.is_dir() {
+        return Err(format!("{} is not a directory", root.display()));
+    }
+    tree.insert(
+        String::new(),
+        Node::Dir {
+            mode: meta.permissions().mode() & 0o7777,
+        },
+    );
+    walk(root, "", &mut tree)?;
+    Ok(tree)
+}
+
+fn walk(dir: &Path, prefix: &str, tree: &mut Tree) -> Result<(), String> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| format!("failed to read {}: {e}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to read {}: {e}", dir.display()))?;
+    // Sorted, so that the same tree always gives the same image.
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| format!("non UTF-8 file name {name:?} in {}", dir.display()))?;
+        let rel = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        let meta = fs::symlink_metadata(&path)
+            .map_err(|e| format!("failed to stat {}: {e}", path.display()))?;
+        let mode = meta.permissions().mode() & 0o7777;
+        let file_type = meta.file_type();
+
+        if file_type.is_dir() {
+            tree.insert(rel.clone(), Node::Dir { mode });
+            walk(&path, &rel, tree)?;
+        } else if file_type.is_file() {
+            let data =
+                fs::read(&path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+            tree.insert(rel, Node::File { mode, data });
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&path)
+                .map_err(|e| format!("failed to read link {}: {e}", path.display()))?;
+            let target = target
+                .into_os_string()
+                .into_string()
+                .map_err(|t| format!("non UTF-8 link target {t:?} of {}", path.display()))?;
+            tree.insert(rel, Node::Symlink { target });
+        } else {
+            let kind = if file_type.is_fifo() {
+                "fifo"
+            } else if file_type.is_socket() {
+                "socket"
+            } else {
+                "device"
+            };
+            eprintln!("skipping {kind} {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Compare `actual` with `expected`, reporting every difference.
+pub fn compare(expected: &Tree, actual: &Tree) -> Result<(), String> {
+    let mut diffs = Vec::new();
+    for (path, want) in expected {
+        match actual.get(path) {
+            None => diffs.push(format!("/{path}: missing")),
+            Some(got) => {
+                if let Some(diff) = compare_node(want, got) {
+                    diffs.push(format!("/{path}: {diff}"));
+                }
+            }
+        }
+    }
+    for path in actual.keys().filter(|path| !expected.contains_key(*path)) {
+        diffs.push(format!("/{path}: unexpected"));
+    }
+
+    if diffs.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("trees differ:\n  {}", diffs.join("\n  ")))
+    }
+}
+
+/// Compare two nodes, returning the difference if any.
+pub fn compare_node(want: &Node, got: &Node) -> Option<String> {
+    match (want, got) {
+        (Node::Dir { mode: a }, Node::Dir { mode: b }) if a != b => {
+            Some(format!("mode {b:o}, expected {a:o}"))
+        }
+        (Node::File { mode: a, data: x }, Node::File { mode: b, data: y }) => {
+            if a != b {
+                Some(format!("mode {b:o}, expected {a:o}"))
+            } else if x != y {
+                Some(format!(
+                    "content differs ({} bytes, expected {})",
+                    y.len(),
+                    x.len()
+                ))
+            } else {
+                None
+            }
+        }
+        (Node::Symlink { target: a }, Node::Symlink { target: b }) if a != b => {
+            Some(format!("target {b:?}, expected {a:?}"))
+        }
+        _ if want.kind() != got.kind() => Some(format!("{}, expected {}", got.kind(), want.kind())),
+        _ => None,
+    }
+}
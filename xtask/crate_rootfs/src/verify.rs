@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::tree::{Tree, compare, snapshot};
+
+/// Run `e2fsck` on the image without changing it, failing on any problem.
+pub fn fsck(image: &Path) -> Result<(), String> {
+    let output = Command::new("e2fsck")
+        .arg("-fn")
+        .arg(image)
+        .output()
+        .map_err(|e| format!("failed to run e2fsck: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "e2fsck found errors ({}):\n{}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Mount the image read-only on the host and compare the tree under `dest`
+/// with `expected`.
+///
+/// Needs the permission to mount loop devices, usually root.
+pub fn mount_check(image: &Path, expected: &Tree, dest: &str) -> Result<(), String> {
+    let mountpoint = std::env::temp_dir().join(format!("crate_rootfs-{}", std::process::id()));
+    fs::create_dir_all(&mountpoint)
+        .map_err(|e| format!("failed to create {}: {e}", mountpoint.display()))?;
+    let mounted = MountGuard::mount(image, &mountpoint)?;
+
+    let root = mounted.path().join(dest.trim_start_matches('/'));
+    let mut actual = snapshot(&root)?;
+    if dest.trim_matches('/').is_empty() {
+        // Created by mkfs rather than copied.
+        actual.remove("lost+found");
+    }
+    compare(expected, &actual)
+}
+
+/// A host mount, unmounted and removed when dropped.
+struct MountGuard {
+    path: PathBuf,
+}
+
+impl MountGuard {
+    fn mount(image: &Path, path: &Path) -> Result<Self, String> {
+        let status = Command::new("mount")
+            .args(["-t", "ext4", "-o", "loop,ro"])
+            .arg(image)
+            .arg(path)
+            .status()
+            .map_err(|e| format!("failed to run mount: {e}"))?;
+        if !status.success() {
+            let _ = fs::remove_dir(path);
+            return Err(format!("mounting {} failed ({status})", image.display()));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MountGuard {
+    fn drop(&mut self) {
+        match Command::new("umount").arg(&self.path).status() {
+            Ok(status) if status.success() => {
+                let _ = fs::remove_dir(&self.path);
+            }
+            _ => eprintln!("failed to unmount {}", self.path.display()),
+        }
+    }
+}
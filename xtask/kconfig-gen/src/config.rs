@@ -89,6 +89,11 @@ impl ConfigItem {
     pub fn value_mut(&mut self) -> &mut ConfigValue {
         &mut self.value
     }
+
+    /// Replaces the comments of the config item, each line starting with `#`.
+    pub(crate) fn set_comments(&mut self, comments: String) {
+        self.comments = comments;
+    }
 }
 
 /// A structure storing all config items.
@@ -1,5 +1,6 @@
 mod config;
 mod output;
+mod schema;
 mod ty;
 mod value;
 
@@ -11,6 +12,7 @@ use toml_edit::TomlError;
 pub use self::{
     config::{Config, ConfigItem},
     output::OutputFormat,
+    schema::{KeySchema, Schema, Violation},
     ty::ConfigType,
     value::ConfigValue,
 };
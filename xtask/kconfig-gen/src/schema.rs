@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::{Config, ConfigErr, ConfigItem, ConfigResult, ConfigType, value::parse_num};
+
+/// The constraints on a single config item.
+#[derive(Debug, Clone)]
+pub struct KeySchema {
+    ty: ConfigType,
+    required: bool,
+    doc: String,
+    min: Option<i128>,
+    max: Option<i128>,
+    min_for_arch: BTreeMap<String, i128>,
+    max_for_arch: BTreeMap<String, i128>,
+    one_of: Vec<String>,
+    min_key: Option<String>,
+    max_key: Option<String>,
+}
+
+impl KeySchema {
+    /// Parses the schema of the key `name` from `table`, whose fields
+    /// override those of `base`.
+    fn from_table(name: &str, table: &Table, base: Option<Self>) -> ConfigResult<Self> {
+        let err = |field: &str, what: &str| {
+            ConfigErr::Other(format!("schema of `{}`: `{}` {}", name, field, what))
+        };
+        let ty = match table.get("type") {
+            Some(ty) => {
+                let ty = ty
+                    .as_str()
+                    .ok_or_else(|| err("type", "must be a type string"))?;
+                Some(ConfigType::new(ty)?)
+            }
+            None => None,
+        };
+        let mut schema = match (base, ty) {
+            (Some(base), None) => base,
+            (Some(base), Some(ty)) => Self { ty, ..base },
+            (None, Some(ty)) => Self {
+                ty,
+                required: false,
+                doc: String::new(),
+                min: None,
+                max: None,
+                min_for_arch: BTreeMap::new(),
+                max_for_arch: BTreeMap::new(),
+                one_of: Vec::new(),
+                min_key: None,
+                max_key: None,
+            },
+            (None, None) => return Err(err("type", "is missing")),
+        };
+        for (field, item) in table.iter() {
+            match field {
+                "type" => {}
+                "required" => {
+                    schema.required = item
+                        .as_bool()
+                        .ok_or_else(|| err(field, "must be a boolean"))?;
+                }
+                "doc" => {
+                    schema.doc = item
+                        .as_str()
+                        .ok_or_else(|| err(field, "must be a string"))?
+                        .trim()
+                        .into();
+                }
+                "min" | "max" => {
+                    let bound = item
+                        .as_value()
+                        .and_then(value_num)
+                        .ok_or_else(|| err(field, "must be a number"))?;
+                    if field == "min" {
+                        schema.min = Some(bound);
+                    } else {
+                        schema.max = Some(bound);
+                    }
+                }
+                "min-for-arch" | "max-for-arch" => {
+                    let bounds = item
+                        .as_table_like()
+                        .ok_or_else(|| err(field, "must be a table of arch names to numbers"))?;
+                    let map = if field == "min-for-arch" {
+                        &mut schema.min_for_arch
+                    } else {
+                        &mut schema.max_for_arch
+                    };
+                    for (arch, bound) in bounds.iter() {
+                        let bound = bound.as_value().and_then(value_num).ok_or_else(|| {
+                            err(field, "must be a table of arch names to numbers")
+                        })?;
+                        map.insert(arch.into(), bound);
+                    }
+                }
+                "one-of" => {
+                    let values = item
+                        .as_array()
+                        .ok_or_else(|| err(field, "must be an array of strings"))?;
+                    schema.one_of.clear();
+                    for value in values.iter() {
+                        let value = value
+                            .as_str()
+                            .ok_or_else(|| err(field, "must be an array of strings"))?;
+                        schema.one_of.push(value.into());
+                    }
+                }
+                "min-key" | "max-key" => {
+                    let key = item
+                        .as_str()
+                        .ok_or_else(|| err(field, "must be a key name"))?
+                        .to_string();
+                    if field == "min-key" {
+                        schema.min_key = Some(key);
+                    } else {
+                        schema.max_key = Some(key);
+                    }
+                }
+                _ => return Err(err(field, "is not a schema field")),
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Returns the type the value must have.
+    pub fn ty(&self) -> &ConfigType {
+        &self.ty
+    }
+
+    /// Returns whether the config must contain the item.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Returns the description of the item.
+    pub fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    /// Returns the constraints on the value, one string each in the syntax of
+    /// the schema, e.g. `min = 1` or `max-for-arch.aarch64 = 1019`.
+    pub fn constraints(&self) -> Vec<String> {
+        let mut constraints = Vec::new();
+        if let Some(min) = self.min {
+            constraints.push(format!("min = {}", fmt_num(min)));
+        }
+        if let Some(max) = self.max {
+            constraints.push(format!("max = {}", fmt_num(max)));
+        }
+        for (arch, min) in &self.min_for_arch {
+            constraints.push(format!("min-for-arch.{} = {}", arch, fmt_num(*min)));
+        }
+        for (arch, max) in &self.max_for_arch {
+            constraints.push(format!("max-for-arch.{} = {}", arch, fmt_num(*max)));
+        }
+        if !self.one_of.is_empty() {
+            constraints.push(format!("one-of = {}", fmt_one_of(&self.one_of)));
+        }
+        if let Some(key) = &self.min_key {
+            constraints.push(format!("min-key = {}", key));
+        }
+        if let Some(key) = &self.max_key {
+            constraints.push(format!("max-key = {}", key));
+        }
+        constraints
+    }
+}
+
+/// A config item violating the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the item, in the format of [`ConfigItem::item_name`].
+    pub key: String,
+    /// What is wrong with the item.
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+/// A schema describing which config items a config may contain, and which
+/// values they may have.
+///
+/// It is written in TOML, mirroring the config it describes: each key is a
+/// table holding the `type` of the value and optional constraints.
+///
+/// ```toml
+/// [arch]
+/// type = "str"
+/// required = true
+/// one-of = ["aarch64", "riscv64"]
+///
+/// [plat.cpu-num]
+/// type = "uint"
+/// required = true
+/// doc = "Number of CPUs."
+/// min = 1
+/// max-key = "plat.max-cpu-num"
+/// ```
+///
+/// The supported constraints are:
+///
+/// - `required`: the config must contain the key.
+/// - `min` / `max`: inclusive bounds of a number.
+/// - `min-for-arch` / `max-for-arch`: bounds that apply only if the global
+///   `arch` of the config is one of the table keys.
+/// - `one-of`: the allowed values of a string.
+/// - `min-key` / `max-key`: bounds given by another key of the config, only
+///   checked if the config contains it.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    keys: BTreeMap<String, KeySchema>,
+}
+
+impl Schema {
+    /// Parse a TOML string into a schema.
+    pub fn from_toml(toml: &str) -> ConfigResult<Self> {
+        let mut schema = Self::default();
+        schema.extend_toml(toml)?;
+        Ok(schema)
+    }
+
+    /// Extends the schema with another one in TOML format, e.g. with the keys
+    /// of a specific platform.
+    ///
+    /// Keys the schema does not contain yet are added, they must have a
+    /// `type`. For the others, the fields given replace those of the schema,
+    /// and the `min-for-arch` and `max-for-arch` tables are merged, so a key
+    /// can be made required or constrained further without repeating its
+    /// type and doc.
+    pub fn extend_toml(&mut self, toml: &str) -> ConfigResult<()> {
+        let doc = toml.parse::<DocumentMut>()?;
+        let keys = &mut self.keys;
+        let mut add = |name: &str, item: &Item| {
+            let base = keys.remove(name);
+            let schema = KeySchema::from_table(name, &to_table(item), base)?;
+            keys.insert(name.into(), schema);
+            ConfigResult::Ok(())
+        };
+        for (key, item) in doc.as_table().iter() {
+            let table = item
+                .as_table_like()
+                .ok_or_else(|| ConfigErr::Other(format!("schema of `{}` must be a table", key)))?;
+            // Tables of keys hold only tables, those of a key some values.
+            if table.iter().any(|(_, item)| !item.is_table_like()) {
+                add(key, item)?;
+                continue;
+            }
+            for (sub_key, item) in table.iter() {
+                let name = format!("{}.{}", key, sub_key);
+                if item.as_table_like().is_none() {
+                    return Err(ConfigErr::Other(format!(
+                        "schema of `{}` must be a table",
+                        name
+                    )));
+                }
+                add(&name, item)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the schema of the key with the specified name, in the format of
+    /// [`ConfigItem::item_name`].
+    pub fn key_at(&self, name: &str) -> Option<&KeySchema> {
+        self.keys.get(name)
+    }
+
+    /// Returns the iterator of all keys and their schemas.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &KeySchema)> {
+        self.keys
+            .iter()
+            .map(|(name, schema)| (name.as_str(), schema))
+    }
+
+    /// Checks `config` against the schema, returning all violations.
+    ///
+    /// With `deny_unknown_keys`, keys of the config that the schema does not
+    /// contain are violations too.
+    pub fn validate(&self, config: &Config, deny_unknown_keys: bool) -> Vec<Violation> {
+        let arch = config
+            .config_at(Config::GLOBAL_TABLE_NAME, "arch")
+            .and_then(|item| item.value().as_str());
+        let mut violations = Vec::new();
+        for (name, schema) in &self.keys {
+            match item_at(config, name) {
+                Some(item) => self.check_item(config, arch, item, schema, &mut violations),
+                None if schema.required => violations.push(Violation {
+                    key: name.clone(),
+                    message: format!("missing required key `{}`", name),
+                }),
+                None => {}
+            }
+        }
+        if deny_unknown_keys {
+            for item in config.iter() {
+                let name = item.item_name();
+                if self.keys.contains_key(&name) {
+                    continue;
+                }
+                let message = match self.closest_key(&name) {
+                    Some(closest) => {
+                        format!("unknown key `{}`, did you mean `{}`?", name, closest)
+                    }
+                    None => format!("unknown key `{}`", name),
+                };
+                violations.push(Violation { key: name, message });
+            }
+        }
+        violations
+    }
+
+    fn check_item(
+        &self,
+        config: &Config,
+        arch: Option<&str>,
+        item: &ConfigItem,
+        schema: &KeySchema,
+        violations: &mut Vec<Violation>,
+    ) {
+        let name = item.item_name();
+        let value = item.value();
+        let shown = value.to_toml_value();
+        let mut violate = |constraint: String| {
+            violations.push(Violation {
+                key: name.clone(),
+                message: format!("`{}` = {} violates `{}`", name, shown, constraint),
+            })
+        };
+
+        let type_ok = match value.ty() {
+            Some(ty) => *ty == schema.ty,
+            None => value.type_matches(&schema.ty),
+        };
+        if !type_ok {
+            violate(format!("type = \"{}\"", schema.ty));
+            return;
+        }
+
+        if let Some(n) = value.as_int() {
+            if let Some(min) = schema.min.filter(|min| n < *min) {
+                violate(format!("min = {}", fmt_num(min)));
+            }
+            if let Some(max) = schema.max.filter(|max| n > *max) {
+                violate(format!("max = {}", fmt_num(max)));
+            }
+            if let Some(arch) = arch {
+                if let Some(min) = schema.min_for_arch.get(arch).filter(|min| n < **min) {
+                    violate(format!("min-for-arch.{} = {}", arch, fmt_num(*min)));
+                }
+                if let Some(max) = schema.max_for_arch.get(arch).filter(|max| n > **max) {
+                    violate(format!("max-for-arch.{} = {}", arch, fmt_num(*max)));
+                }
+            }
+            let other = |key: &Option<String>| {
+                let key = key.as_ref()?;
+                let other = item_at(config, key)?.value().as_int()?;
+                Some((key.clone(), other))
+            };
+            if let Some((key, min)) = other(&schema.min_key).filter(|(_, min)| n < *min) {
+                violate(format!("min-key = {} ({})", key, fmt_num(min)));
+            }
+            if let Some((key, max)) = other(&schema.max_key).filter(|(_, max)| n > *max) {
+                violate(format!("max-key = {} ({})", key, fmt_num(max)));
+            }
+        }
+
+        if let Some(s) = value.as_str()
+            && !schema.one_of.is_empty()
+            && !schema.one_of.iter().any(|v| v == s)
+        {
+            violate(format!("one-of = {}", fmt_one_of(&schema.one_of)));
+        }
+    }
+
+    /// Returns the known key closest to `name`, if it is close enough to be a
+    /// likely typo. Underscores count as dashes.
+    fn closest_key(&self, name: &str) -> Option<&str> {
+        let name = name.replace('_', "-");
+        self.keys
+            .keys()
+            .map(|key| (edit_distance(&name, key), key))
+            .filter(|(distance, key)| *distance <= (key.len() / 4).clamp(1, 3))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, key)| key.as_str())
+    }
+
+    /// Replaces the comments of the items in `config` with the docs and
+    /// constraints of their schemas.
+    ///
+    /// Items without a documented schema keep their comments.
+    pub fn apply_docs(&self, config: &mut Config) {
+        let names = config
+            .iter()
+            .map(|item| (item.table_name().to_string(), item.key().to_string()))
+            .collect::<Vec<_>>();
+        for (table, key) in names {
+            let item = config.config_at_mut(&table, &key).unwrap();
+            let Some(schema) = self.keys.get(&item.item_name()) else {
+                continue;
+            };
+            if schema.doc.is_empty() {
+                continue;
+            }
+            let mut comments = String::new();
+            for line in schema.doc.lines() {
+                comments += &format!("# {}\n", line.trim_end());
+            }
+            let constraints = schema.constraints();
+            if !constraints.is_empty() {
+                comments += "#\n";
+                comments += &format!("# Constraints: `{}`.\n", constraints.join("`, `"));
+            }
+            item.set_comments(comments);
+        }
+    }
+
+    /// Dump the schema into a Markdown table documenting all keys.
+    pub fn dump_markdown(&self) -> String {
+        let mut result = String::new();
+        result += "| Key | Type | Required | Constraints | Description |\n";
+        result += "| --- | --- | --- | --- | --- |\n";
+        for (name, schema) in &self.keys {
+            let constraints = schema
+                .constraints()
+                .iter()
+                .map(|c| format!("`{}`", c))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            result += &format!(
+                "| `{}` | `{}` | {} | {} | {} |\n",
+                name,
+                schema.ty,
+                if schema.required { "yes" } else { "no" },
+                constraints,
+                schema
+                    .doc
+                    .lines()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .replace('|', "\\|"),
+            );
+        }
+        result
+    }
+}
+
+fn to_table(item: &Item) -> Table {
+    match item {
+        Item::Table(table) => table.clone(),
+        Item::Value(Value::InlineTable(table)) => table.clone().into_table(),
+        _ => unreachable!(),
+    }
+}
+
+fn item_at<'a>(config: &'a Config, name: &str) -> Option<&'a ConfigItem> {
+    match name.split_once('.') {
+        Some((table, key)) => config.config_at(table, key),
+        None => config.config_at(Config::GLOBAL_TABLE_NAME, name),
+    }
+}
+
+fn value_num(value: &Value) -> Option<i128> {
+    match value {
+        Value::Integer(i) => Some(*i.value() as i128),
+        Value::String(s) => parse_num(s.value()),
+        _ => None,
+    }
+}
+
+fn fmt_num(n: i128) -> String {
+    if n > 0xffff {
+        format!("{:#x}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+fn fmt_one_of(values: &[String]) -> String {
+    let values = values
+        .iter()
+        .map(|v| format!("\"{}\"", v))
+        .collect::<Vec<_>>();
+    format!("[{}]", values.join(", "))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
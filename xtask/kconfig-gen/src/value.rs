@@ -85,6 +85,23 @@ impl ConfigValue {
         value_type_matches(&self.value, ty)
     }
 
+    /// Returns the value of an integer, or of a string holding one.
+    pub fn as_int(&self) -> Option<i128> {
+        match &self.value {
+            Value::Integer(i) => Some(*i.value() as i128),
+            Value::String(s) => parse_num(s.value()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::String(s) => Some(s.value()),
+            _ => None,
+        }
+    }
+
     /// Returns the TOML-formatted string of the config value.
     pub fn to_toml_value(&self) -> String {
         to_toml(&self.value)
@@ -120,6 +137,24 @@ fn is_num(s: &str) -> bool {
     }
 }
 
+/// Parses a number in the formats accepted by [`is_num`].
+pub(crate) fn parse_num(s: &str) -> Option<i128> {
+    if !is_num(s) {
+        return None;
+    }
+    let s = s.to_lowercase().replace('_', "");
+    let (digits, radix) = if let Some(s) = s.strip_prefix("0x") {
+        (s.to_string(), 16)
+    } else if let Some(s) = s.strip_prefix("0b") {
+        (s.to_string(), 2)
+    } else if let Some(s) = s.strip_prefix("0o") {
+        (s.to_string(), 8)
+    } else {
+        (s, 10)
+    };
+    i128::from_str_radix(&digits, radix).ok()
+}
+
 fn value_is_valid(value: &Value) -> bool {
     match value {
         Value::Boolean(_) | Value::Integer(_) | Value::String(_) => true,